@@ -55,6 +55,7 @@ pub fn attach_default_module_init_params(
                     finality_delay,
                     client_default_bitcoin_rpc: default_esplora_server(network),
                     fee_consensus: Default::default(),
+                    peg_out_batch_window: 0,
                 },
             },
         );
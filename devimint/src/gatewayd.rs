@@ -230,12 +230,15 @@ impl Gatewayd {
                 let short_channel_id = channel["short_channel_id"]
                     .as_u64()
                     .context("short_channel_id must be a u64")?;
+                let channel_type =
+                    serde_json::from_value(channel["channel_type"].clone()).unwrap_or_default();
                 Ok(ChannelInfo {
                     remote_pubkey,
                     channel_size_sats,
                     outbound_liquidity_sats,
                     inbound_liquidity_sats,
                     short_channel_id,
+                    channel_type,
                 })
             })
             .collect::<Result<Vec<ChannelInfo>>>()?;
@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+
+use fedimint_core::admin_client::{
+    AdminRole, ApiTokenInfo, IssueApiTokenRequest, ModuleAdditionRequest, ModuleAdditionsStatus,
+    ModuleMaintenanceRequest, ScheduleUpgradeRequest, SessionRetention, UpgradeStatus,
+};
+use fedimint_core::config::ClientConfig;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::module::audit::AuditSummary;
+use fedimint_core::module::{ApiAuth, ApiRequestErased};
+use fedimint_core::task::MaybeSend;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::PeerId;
+
+use crate::api::{
+    ConsensusHealth, DynGlobalApi, FederationApiExt as _, FederationResult, GuardianConfigBackup,
+    GuardianConsensusCheckpoint, GuardianDisasterRecoveryBundle, PeerConnectionStats,
+    StatusResponse,
+};
+
+/// Admin client for a single guardian that carries the guardian's [`ApiAuth`]
+/// so tooling does not have to thread credentials through every call.
+///
+/// This is a thin wrapper over [`DynGlobalApi`], which must have its self
+/// peer id set (see [`DynGlobalApi::from_config_admin`] and
+/// [`DynGlobalApi::from_pre_peer_id_admin_endpoint`]).
+#[derive(Clone)]
+pub struct GuardianAdminApi {
+    api: DynGlobalApi,
+    auth: ApiAuth,
+}
+
+impl Debug for GuardianAdminApi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // never print the password
+        f.debug_struct("GuardianAdminApi")
+            .field("peer_id", &self.api.self_peer())
+            .finish_non_exhaustive()
+    }
+}
+
+impl GuardianAdminApi {
+    pub fn new(api: DynGlobalApi, auth: ApiAuth) -> Self {
+        Self { api, auth }
+    }
+
+    /// Admin client for guardian `peer_id` of a federation we have the client
+    /// config of
+    pub fn from_config(
+        config: &ClientConfig,
+        api_secret: &Option<String>,
+        peer_id: PeerId,
+        auth: ApiAuth,
+    ) -> Self {
+        Self::new(
+            DynGlobalApi::from_config_admin(config, api_secret, peer_id),
+            auth,
+        )
+    }
+
+    /// Admin client for a guardian that is not yet part of a federation, e.g.
+    /// during config generation
+    pub fn from_url(url: SafeUrl, api_secret: &Option<String>, auth: ApiAuth) -> Self {
+        Self::new(
+            DynGlobalApi::from_pre_peer_id_admin_endpoint(url, api_secret),
+            auth,
+        )
+    }
+
+    /// The underlying api, e.g. for calling unauthenticated endpoints
+    pub fn api(&self) -> &DynGlobalApi {
+        &self.api
+    }
+
+    /// Check that the credentials are accepted by the guardian
+    pub async fn auth(&self) -> FederationResult<()> {
+        self.api.auth(self.auth.clone()).await
+    }
+
+    pub async fn status(&self) -> FederationResult<StatusResponse> {
+        self.api.status().await
+    }
+
+    pub async fn audit(&self) -> FederationResult<AuditSummary> {
+        self.api.audit(self.auth.clone()).await
+    }
+
+    pub async fn consensus_health(&self) -> FederationResult<ConsensusHealth> {
+        self.api.consensus_health(self.auth.clone()).await
+    }
+
+    pub async fn peer_connection_stats(
+        &self,
+    ) -> FederationResult<BTreeMap<PeerId, PeerConnectionStats>> {
+        self.api.peer_connection_stats(self.auth.clone()).await
+    }
+
+    pub async fn guardian_config_backup(&self) -> FederationResult<GuardianConfigBackup> {
+        self.api.guardian_config_backup(self.auth.clone()).await
+    }
+
+    pub async fn guardian_disaster_recovery_bundle(
+        &self,
+    ) -> FederationResult<GuardianDisasterRecoveryBundle> {
+        self.api
+            .guardian_disaster_recovery_bundle(self.auth.clone())
+            .await
+    }
+
+    pub async fn consensus_checkpoint(&self) -> FederationResult<GuardianConsensusCheckpoint> {
+        self.api.consensus_checkpoint(self.auth.clone()).await
+    }
+
+    pub async fn shutdown(&self, session: Option<u64>) -> FederationResult<()> {
+        self.api.shutdown(session, self.auth.clone()).await
+    }
+
+    pub async fn set_module_maintenance(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        enabled: bool,
+    ) -> FederationResult<()> {
+        let request = ModuleMaintenanceRequest {
+            module_instance_id,
+            enabled,
+        };
+        self.api
+            .set_module_maintenance(request, self.auth.clone())
+            .await
+    }
+
+    pub async fn modules_in_maintenance(&self) -> FederationResult<BTreeSet<ModuleInstanceId>> {
+        self.api.modules_in_maintenance().await
+    }
+
+    pub async fn set_session_retention(
+        &self,
+        retained_sessions: Option<u64>,
+    ) -> FederationResult<()> {
+        self.api
+            .set_session_retention(SessionRetention { retained_sessions }, self.auth.clone())
+            .await
+    }
+
+    pub async fn session_retention(&self) -> FederationResult<SessionRetention> {
+        self.api.session_retention().await
+    }
+
+    pub async fn propose_module_addition(
+        &self,
+        request: ModuleAdditionRequest,
+    ) -> FederationResult<()> {
+        self.api
+            .propose_module_addition(request, self.auth.clone())
+            .await
+    }
+
+    pub async fn module_additions(&self) -> FederationResult<ModuleAdditionsStatus> {
+        self.api.module_additions().await
+    }
+
+    pub async fn schedule_upgrade(
+        &self,
+        version: String,
+        halt_session: u64,
+    ) -> FederationResult<()> {
+        self.api
+            .schedule_upgrade(
+                ScheduleUpgradeRequest {
+                    version,
+                    halt_session,
+                },
+                self.auth.clone(),
+            )
+            .await
+    }
+
+    pub async fn upgrade_status(&self) -> FederationResult<UpgradeStatus> {
+        self.api.upgrade_status().await
+    }
+
+    /// Issue an API token with `role` for the admin `name`, the client has to
+    /// be authenticated with the guardian password
+    pub async fn issue_api_token(
+        &self,
+        name: String,
+        role: AdminRole,
+    ) -> FederationResult<ApiAuth> {
+        self.api
+            .issue_api_token(IssueApiTokenRequest { name, role }, self.auth.clone())
+            .await
+    }
+
+    pub async fn revoke_api_token(&self, name: String) -> FederationResult<()> {
+        self.api.revoke_api_token(name, self.auth.clone()).await
+    }
+
+    pub async fn list_api_tokens(&self) -> FederationResult<Vec<ApiTokenInfo>> {
+        self.api.list_api_tokens(self.auth.clone()).await
+    }
+
+    pub async fn restart_federation_setup(&self) -> FederationResult<()> {
+        self.api.restart_federation_setup(self.auth.clone()).await
+    }
+
+    /// Call an authenticated endpoint of the module instance `module_id`
+    pub async fn request_module_admin<Ret>(
+        &self,
+        module_id: ModuleInstanceId,
+        method: &str,
+        params: ApiRequestErased,
+    ) -> FederationResult<Ret>
+    where
+        Ret: serde::de::DeserializeOwned + Eq + Debug + Clone + MaybeSend,
+    {
+        self.api
+            .with_module(module_id)
+            .request_admin(method, params, self.auth.clone())
+            .await
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{self, Debug, Display};
+use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -11,35 +12,53 @@ use base64::Engine as _;
 use bitcoin::hashes::sha256;
 use bitcoin::secp256k1;
 use fedimint_core::admin_client::{
-    ConfigGenConnectionsRequest, ConfigGenParamsRequest, ConfigGenParamsResponse, PeerServerParams,
-    ServerStatus,
+    ApiTokenInfo, ConfigGenConnectionsRequest, ConfigGenParamsRequest, ConfigGenParamsResponse,
+    IssueApiTokenRequest, ModuleAdditionRequest, ModuleAdditionsStatus, ModuleMaintenanceRequest,
+    PeerServerParams, ScheduleUpgradeRequest, ServerStatus, SessionRetention, UpgradeStatus,
 };
 use fedimint_core::backup::ClientBackupSnapshot;
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::backup::SignedBackupRequest;
-use fedimint_core::core::{Decoder, DynOutputOutcome, ModuleInstanceId, OutputOutcome};
+use fedimint_core::core::{Decoder, DynOutputOutcome, ModuleInstanceId, ModuleKind, OutputOutcome};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::endpoint_constants::{
     ADD_CONFIG_GEN_PEER_ENDPOINT, AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT,
     AWAIT_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
-    CONFIG_GEN_PEERS_ENDPOINT, CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
-    DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, GUARDIAN_CONFIG_BACKUP_ENDPOINT, RECOVER_ENDPOINT,
-    RESTART_FEDERATION_SETUP_ENDPOINT, RUN_DKG_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
-    SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
-    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
-    STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
+    CONFIG_GEN_PEERS_ENDPOINT, CONSENSUS_CHECKPOINT_ENDPOINT, CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT,
+    CONSENSUS_HEALTH_ENDPOINT, DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT, FEE_SCHEDULE_ENDPOINT,
+    GUARDIAN_CONFIG_BACKUP_ENDPOINT, GUARDIAN_DISASTER_RECOVERY_BUNDLE_ENDPOINT,
+    ISSUE_API_TOKEN_ENDPOINT, LIST_API_TOKENS_ENDPOINT, MODULES_IN_MAINTENANCE_ENDPOINT,
+    MODULE_ADDITIONS_ENDPOINT, MODULE_CONSENSUS_FEATURES_ENDPOINT, PEER_CONNECTION_STATS_ENDPOINT,
+    PROPOSE_MODULE_ADDITION_ENDPOINT, RECOVER_ENDPOINT, RESTART_FEDERATION_SETUP_ENDPOINT,
+    REVOKE_API_TOKEN_ENDPOINT, RUN_DKG_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT,
+    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_RETENTION_ENDPOINT,
+    SESSION_STATUS_ENDPOINT, SESSION_TRANSACTIONS_ENDPOINT, SET_CONFIG_GEN_CONNECTIONS_ENDPOINT,
+    SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_MODULE_MAINTENANCE_ENDPOINT, SET_PASSWORD_ENDPOINT,
+    SET_SESSION_RETENTION_ENDPOINT, SHUTDOWN_ENDPOINT, START_CONSENSUS_ENDPOINT, STATUS_ENDPOINT,
+    SUBMIT_TRANSACTIONS_BATCH_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, TRANSACTION_ERROR_ENDPOINT,
+    TRANSPORT_OPTIONS_ENDPOINT, UPGRADE_STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT,
     VERIFY_CONFIG_HASH_ENDPOINT,
 };
 use fedimint_core::fmt_utils::{AbbreviateDebug, AbbreviateJson};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::audit::AuditSummary;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::module::{ApiAuth, ApiRequestErased, ApiVersion, SerdeModuleEncoding};
+use fedimint_core::module::{
+    ApiAuth, ApiRequestErased, ApiVersion, FeeSchedule, ModuleConsensusFeature, ModuleHealth,
+    ModuleInMaintenanceError, SerdeModuleEncoding, MODULE_IN_MAINTENANCE_ERROR_CODE,
+};
+use fedimint_core::net::api_transport::{
+    ApiCompression, ApiRequestTransport, ApiTransportOptions, MAX_API_REQUEST_SIZE,
+    MAX_API_RESPONSE_SIZE,
+};
 use fedimint_core::session_outcome::{AcceptedItem, SessionOutcome, SessionStatus};
 use fedimint_core::task::jit::JitTryAnyhow;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
-use fedimint_core::transaction::{SerdeTransaction, Transaction, TransactionSubmissionOutcome};
+use fedimint_core::transaction::{
+    SerdeTransaction, Transaction, TransactionRejection, TransactionSubmissionOutcome,
+    MAX_TRANSACTIONS_PER_BATCH,
+};
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, runtime, NumPeers, NumPeersExt, OutPoint,
@@ -63,6 +82,10 @@ use thiserror::Error;
 use tokio::sync::{Mutex, OnceCell, RwLock};
 use tracing::{debug, error, instrument, trace, warn};
 
+use crate::health::PeerHealthTracker;
+use crate::interceptor::{
+    ApiRequest, ApiRequestNext, DynApiRequestInterceptor, IApiRequestTransport,
+};
 use crate::query::{FilterMapThreshold, QueryStep, QueryStrategy, ThresholdConsensus};
 
 pub type PeerResult<T> = Result<T, PeerError>;
@@ -84,6 +107,22 @@ pub enum PeerError {
 }
 
 impl PeerError {
+    /// Returns the module instance that caused the guardian to reject a
+    /// transaction because the module is in maintenance mode
+    pub fn module_in_maintenance(&self) -> Option<ModuleInstanceId> {
+        let PeerError::Rpc(JsonRpcClientError::Call(error)) = self else {
+            return None;
+        };
+
+        if error.code() != MODULE_IN_MAINTENANCE_ERROR_CODE {
+            return None;
+        }
+
+        serde_json::from_str::<ModuleInMaintenanceError>(error.data()?.get())
+            .ok()
+            .map(|data| data.module_instance_id)
+    }
+
     /// Report errors that are worth reporting
     ///
     /// The goal here is to avoid spamming logs with errors that happen commonly
@@ -94,6 +133,8 @@ impl PeerError {
             PeerError::ResponseDeserialization(_)
             | PeerError::InvalidPeerId { .. }
             | PeerError::InvalidResponse(_) => true,
+            // modules are put into maintenance mode deliberately by the guardians
+            PeerError::Rpc(_) if self.module_in_maintenance().is_some() => false,
             PeerError::Rpc(rpc_e) => match rpc_e {
                 // TODO: Does this cover all retryable cases?
                 JsonRpcClientError::Transport(_) | JsonRpcClientError::RequestTimeout => false,
@@ -269,6 +310,10 @@ pub trait IRawFederationApi: Debug + MaybeSend + MaybeSync {
 
     fn with_module(&self, id: ModuleInstanceId) -> DynModuleApi;
 
+    /// Latency and error statistics of the requests sent to each peer, used
+    /// to prefer healthy peers for requests that don't need all of them
+    fn peer_health(&self) -> &PeerHealthTracker;
+
     /// Make request to a specific federation peer by `peer_id`
     async fn request_raw(
         &self,
@@ -276,6 +321,11 @@ pub trait IRawFederationApi: Debug + MaybeSend + MaybeSync {
         method: &str,
         params: &[Value],
     ) -> result::Result<Value, JsonRpcClientError>;
+
+    /// Connects to the peers in `peer_urls` at their new URL, e.g. after a
+    /// guardian moved to a different host. Peers whose URL didn't change keep
+    /// their connection, unknown peers are ignored.
+    async fn update_peer_urls(&self, peer_urls: &BTreeMap<PeerId, SafeUrl>);
 }
 
 /// Set of api versions for each component (core + modules)
@@ -367,18 +417,20 @@ pub trait FederationApiExt: IRawFederationApi {
         #[cfg(target_family = "wasm")]
         let mut futures = FuturesUnordered::<Pin<Box<dyn Future<Output = _>>>>::new();
 
-        let peers = self.all_peers();
-
-        for peer_id in peers {
-            futures.push(Box::pin(async {
+        // Healthy peers are queried first, so that strategies which only need
+        // some of the responses are less likely to wait on a flaky peer
+        for peer_id in self.peer_health().sorted_by_health(self.all_peers()) {
+            let method = &method;
+            let params = &params;
+            futures.push(Box::pin(async move {
                 let request = async {
-                    self.request_raw(*peer_id, &method, &[params.to_json()])
+                    self.request_raw(peer_id, method, &[params.to_json()])
                         .await
                         .map(AbbreviateDebug)
                 };
 
                 PeerResponse {
-                    peer: *peer_id,
+                    peer: peer_id,
                     result: request.await,
                 }
             }));
@@ -457,6 +509,56 @@ pub trait FederationApiExt: IRawFederationApi {
         }
     }
 
+    /// Make a request that any single peer can answer, trying one peer after
+    /// another, healthiest first, until one of them responds within
+    /// `timeout_per_peer`.
+    ///
+    /// Unlike [`Self::request_current_consensus`] the response is not checked
+    /// against other peers, so this must only be used for data that is either
+    /// verified by the caller or not security critical.
+    async fn request_with_failover<Ret>(
+        &self,
+        method: String,
+        params: ApiRequestErased,
+        timeout_per_peer: Duration,
+    ) -> FederationResult<Ret>
+    where
+        Ret: serde::de::DeserializeOwned + MaybeSend,
+    {
+        let mut peer_errors = BTreeMap::new();
+
+        for peer_id in self.peer_health().sorted_by_health(self.all_peers()) {
+            match self
+                .request_single_peer_typed(
+                    Some(timeout_per_peer),
+                    method.clone(),
+                    params.clone(),
+                    peer_id,
+                )
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(error) => {
+                    debug!(
+                        target: LOG_CLIENT_NET_API,
+                        %peer_id,
+                        method,
+                        %error,
+                        "Peer failed to respond, failing over to next peer"
+                    );
+                    peer_errors.insert(peer_id, error);
+                }
+            }
+        }
+
+        Err(FederationError {
+            method,
+            params: params.params,
+            general: None,
+            peers: peer_errors,
+        })
+    }
+
     async fn request_current_consensus<Ret>(
         &self,
         method: String,
@@ -543,11 +645,22 @@ impl AsRef<dyn IGlobalFederationApi + 'static> for DynGlobalApi {
 
 impl DynGlobalApi {
     pub fn from_pre_peer_id_admin_endpoint(url: SafeUrl, api_secret: &Option<String>) -> Self {
+        Self::from_pre_peer_id_admin_endpoint_with_proxy(url, api_secret, None)
+    }
+
+    /// Like [`Self::from_pre_peer_id_admin_endpoint`], but connects through the
+    /// SOCKS5 proxy `socks5_proxy` if set
+    pub fn from_pre_peer_id_admin_endpoint_with_proxy(
+        url: SafeUrl,
+        api_secret: &Option<String>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
         // PeerIds are used only for informational purposes, but just in case, make a
         // big number so it stands out
         let peer_id = PeerId::from(1024);
         GlobalFederationApiWithCache::new(
-            WsFederationApi::new(vec![(peer_id, url)], api_secret).with_self_peer_id(peer_id),
+            WsFederationApi::new_with_proxy(vec![(peer_id, url)], api_secret, socks5_proxy)
+                .with_self_peer_id(peer_id),
         )
         .into()
     }
@@ -565,6 +678,21 @@ impl DynGlobalApi {
         GlobalFederationApiWithCache::new(WsFederationApi::from_config(config, api_secret)).into()
     }
 
+    /// Like [`Self::from_config`], but connects through the SOCKS5 proxy
+    /// `socks5_proxy` if set
+    pub fn from_config_with_proxy(
+        config: &ClientConfig,
+        api_secret: &Option<String>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
+        GlobalFederationApiWithCache::new(WsFederationApi::from_config_with_proxy(
+            config,
+            api_secret,
+            socks5_proxy,
+        ))
+        .into()
+    }
+
     pub fn from_config_admin(
         config: &ClientConfig,
         api_secret: &Option<String>,
@@ -576,6 +704,22 @@ impl DynGlobalApi {
         .into()
     }
 
+    /// Like [`Self::from_config`] and [`Self::from_config_admin`], but every
+    /// request is passed through the `interceptors` before it is sent
+    pub fn from_config_with_interceptors(
+        config: &ClientConfig,
+        api_secret: &Option<String>,
+        self_peer_id: Option<PeerId>,
+        interceptors: Vec<DynApiRequestInterceptor>,
+    ) -> Self {
+        let api = WsFederationApi::from_config(config, api_secret).with_interceptors(interceptors);
+        let api = match self_peer_id {
+            Some(self_peer_id) => api.with_self_peer_id(self_peer_id),
+            None => api,
+        };
+        GlobalFederationApiWithCache::new(api).into()
+    }
+
     pub fn from_invite_code(invite_code: &InviteCode) -> Self {
         GlobalFederationApiWithCache::new(WsFederationApi::new(
             invite_code.peers().into_iter().collect_vec(),
@@ -618,6 +762,14 @@ pub trait IGlobalFederationApi: IRawFederationApi {
         tx: Transaction,
     ) -> FederationResult<SerdeModuleEncoding<TransactionSubmissionOutcome>>;
 
+    /// Submits multiple transactions with as few requests as possible, returns
+    /// the outcomes in the order of `txs`. Batches larger than
+    /// [`MAX_TRANSACTIONS_PER_BATCH`] are split into multiple requests.
+    async fn submit_transactions_batch(
+        &self,
+        txs: Vec<Transaction>,
+    ) -> FederationResult<Vec<SerdeModuleEncoding<TransactionSubmissionOutcome>>>;
+
     async fn await_block(
         &self,
         block_index: u64,
@@ -630,10 +782,35 @@ pub trait IGlobalFederationApi: IRawFederationApi {
         decoders: &ModuleDecoderRegistry,
     ) -> anyhow::Result<SessionStatus>;
 
+    /// Like [`Self::get_session_status`], but the outcome of a completed
+    /// session only contains its transactions, which guardians keep even once
+    /// the session was pruned. Requires core API version 0.5.
+    async fn get_session_transactions(
+        &self,
+        block_index: u64,
+        decoders: &ModuleDecoderRegistry,
+    ) -> anyhow::Result<SessionStatus>;
+
     async fn session_count(&self) -> FederationResult<u64>;
 
+    /// Returns the consensus features that are active for each module instance
+    async fn module_consensus_features(
+        &self,
+    ) -> FederationResult<BTreeMap<ModuleInstanceId, BTreeSet<ModuleConsensusFeature>>>;
+
+    /// Returns the current fee schedule of each module instance
+    async fn fee_schedule(&self) -> FederationResult<BTreeMap<ModuleInstanceId, FeeSchedule>>;
+
     async fn await_transaction(&self, txid: TransactionId) -> FederationResult<TransactionId>;
 
+    /// Fetches why consensus rejected the transaction, `None` if it was not
+    /// rejected (yet). Requires core API version 0.4, a rejection is not final
+    /// as the transaction can still be accepted in a later session.
+    async fn transaction_error(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<Option<TransactionRejection>>;
+
     /// Fetches the server consensus hash if enough peers agree on it
     async fn server_config_consensus_hash(&self) -> FederationResult<sha256::Hash>;
 
@@ -727,14 +904,104 @@ pub trait IGlobalFederationApi: IRawFederationApi {
     /// Show an audit across all modules
     async fn audit(&self, auth: ApiAuth) -> FederationResult<AuditSummary>;
 
+    /// Returns the consensus health of the server
+    async fn consensus_health(&self, auth: ApiAuth) -> FederationResult<ConsensusHealth>;
+
+    /// Returns statistics about the P2P connections of the server to its peers
+    async fn peer_connection_stats(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<BTreeMap<PeerId, PeerConnectionStats>>;
+
     /// Download the guardian config to back it up
     async fn guardian_config_backup(&self, auth: ApiAuth)
         -> FederationResult<GuardianConfigBackup>;
 
+    /// Download an encrypted bundle to restore the guardian from with
+    /// `fedimintd restore --bundle`
+    async fn guardian_disaster_recovery_bundle(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDisasterRecoveryBundle>;
+
+    /// Download a snapshot of the guardian's consensus state that a new or
+    /// rebuilt guardian can be started from with `fedimintd
+    /// --import-checkpoint`
+    async fn consensus_checkpoint(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianConsensusCheckpoint>;
+
     /// Check auth credentials
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()>;
 
     async fn restart_federation_setup(&self, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Ask the guardian to shut down after the given session index, or
+    /// immediately if `None`
+    async fn shutdown(&self, session: Option<u64>, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Put a module instance into or take it out of maintenance mode, which
+    /// makes the guardian reject new transactions using the module
+    async fn set_module_maintenance(
+        &self,
+        request: ModuleMaintenanceRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// Returns the module instances the guardian put into maintenance mode
+    async fn modules_in_maintenance(&self) -> FederationResult<BTreeSet<ModuleInstanceId>>;
+
+    /// Set how many finished sessions the guardian keeps, older sessions are
+    /// pruned from its database
+    async fn set_session_retention(
+        &self,
+        retention: SessionRetention,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// Returns how many finished sessions the guardian keeps
+    async fn session_retention(&self) -> FederationResult<SessionRetention>;
+
+    /// Vote for adding a module instance to the running federation, which is
+    /// activated once a threshold of guardians proposed the same addition
+    async fn propose_module_addition(
+        &self,
+        request: ModuleAdditionRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// Returns the pending votes and approved module additions known to the
+    /// guardian
+    async fn module_additions(&self) -> FederationResult<ModuleAdditionsStatus>;
+
+    /// Vote for a coordinated upgrade, consensus halts at the halt session
+    /// until the guardians run the new version once a threshold of guardians
+    /// scheduled the same upgrade
+    async fn schedule_upgrade(
+        &self,
+        request: ScheduleUpgradeRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()>;
+
+    /// Returns the pending votes and the scheduled upgrade known to the
+    /// guardian
+    async fn upgrade_status(&self) -> FederationResult<UpgradeStatus>;
+
+    /// Issue an API token for another admin of the guardian, requires the
+    /// guardian password
+    async fn issue_api_token(
+        &self,
+        request: IssueApiTokenRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<ApiAuth>;
+
+    /// Revoke the API token named `name`, requires the guardian password
+    async fn revoke_api_token(&self, name: String, auth: ApiAuth) -> FederationResult<()>;
+
+    /// Returns the API tokens issued by the guardian, requires the guardian
+    /// password
+    async fn list_api_tokens(&self, auth: ApiAuth) -> FederationResult<Vec<ApiTokenInfo>>;
 }
 
 pub fn deserialize_outcome<R>(
@@ -784,6 +1051,12 @@ struct GlobalFederationApiWithCache<T> {
     #[allow(clippy::type_complexity)]
     get_session_status_lru:
         Arc<tokio::sync::Mutex<lru::LruCache<u64, Arc<OnceCell<SessionOutcome>>>>>,
+
+    /// Like [`Self::get_session_status_lru`], but for
+    /// [`IGlobalFederationApi::get_session_transactions`]
+    #[allow(clippy::type_complexity)]
+    get_session_transactions_lru:
+        Arc<tokio::sync::Mutex<lru::LruCache<u64, Arc<OnceCell<SessionOutcome>>>>>,
 }
 
 impl<T> GlobalFederationApiWithCache<T> {
@@ -796,6 +1069,9 @@ impl<T> GlobalFederationApiWithCache<T> {
             get_session_status_lru: Arc::new(tokio::sync::Mutex::new(lru::LruCache::new(
                 NonZeroUsize::new(32).expect("is non-zero"),
             ))),
+            get_session_transactions_lru: Arc::new(tokio::sync::Mutex::new(lru::LruCache::new(
+                NonZeroUsize::new(32).expect("is non-zero"),
+            ))),
         }
     }
 }
@@ -821,18 +1097,66 @@ where
 
     async fn get_session_status_raw(
         &self,
+        method: &str,
         block_index: u64,
         decoders: &ModuleDecoderRegistry,
     ) -> anyhow::Result<SessionStatus> {
         debug!(block_index, "Fetching block's outcome from Federation");
         self.request_current_consensus::<SerdeModuleEncoding<SessionStatus>>(
-            SESSION_STATUS_ENDPOINT.to_string(),
+            method.to_string(),
             ApiRequestErased::new(block_index),
         )
         .await?
         .try_into_inner(&decoders.clone().with_fallback())
         .map_err(|e| anyhow!(e))
     }
+
+    /// Fetches the status of a session via `method`, caching it in `lru` once
+    /// the session is complete
+    #[allow(clippy::type_complexity)]
+    async fn get_session_status_cached(
+        &self,
+        lru: &tokio::sync::Mutex<lru::LruCache<u64, Arc<OnceCell<SessionOutcome>>>>,
+        method: &str,
+        session_idx: u64,
+        decoders: &ModuleDecoderRegistry,
+    ) -> anyhow::Result<SessionStatus> {
+        let mut lru_lock = lru.lock().await;
+
+        let entry_arc = lru_lock
+            .get_or_insert(session_idx, || Arc::new(OnceCell::new()))
+            .clone();
+
+        // we drop the lru lock so requests for other `session_idx` can work in parallel
+        drop(lru_lock);
+
+        enum NoCacheErr {
+            Initial,
+            Pending(Vec<AcceptedItem>),
+            Err(anyhow::Error),
+        }
+        match entry_arc
+            .get_or_try_init(|| async {
+                match self
+                    .get_session_status_raw(method, session_idx, decoders)
+                    .await
+                {
+                    Err(e) => Err(NoCacheErr::Err(e)),
+                    Ok(SessionStatus::Initial) => Err(NoCacheErr::Initial),
+                    Ok(SessionStatus::Pending(s)) => Err(NoCacheErr::Pending(s)),
+                    // only status we can cache (hance outer Ok)
+                    Ok(SessionStatus::Complete(s)) => Ok(s),
+                }
+            })
+            .await
+            .cloned()
+        {
+            Ok(s) => Ok(SessionStatus::Complete(s)),
+            Err(NoCacheErr::Initial) => Ok(SessionStatus::Initial),
+            Err(NoCacheErr::Pending(s)) => Ok(SessionStatus::Pending(s)),
+            Err(NoCacheErr::Err(e)) => Err(e),
+        }
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -852,6 +1176,10 @@ where
         self.inner.with_module(id)
     }
 
+    fn peer_health(&self) -> &PeerHealthTracker {
+        self.inner.peer_health()
+    }
+
     /// Make request to a specific federation peer by `peer_id`
     async fn request_raw(
         &self,
@@ -861,6 +1189,10 @@ where
     ) -> result::Result<Value, JsonRpcClientError> {
         self.inner.request_raw(peer_id, method, params).await
     }
+
+    async fn update_peer_urls(&self, peer_urls: &BTreeMap<PeerId, SafeUrl>) {
+        self.inner.update_peer_urls(peer_urls).await;
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -893,38 +1225,27 @@ where
         session_idx: u64,
         decoders: &ModuleDecoderRegistry,
     ) -> anyhow::Result<SessionStatus> {
-        let mut lru_lock = self.get_session_status_lru.lock().await;
-
-        let entry_arc = lru_lock
-            .get_or_insert(session_idx, || Arc::new(OnceCell::new()))
-            .clone();
-
-        // we drop the lru lock so requests for other `session_idx` can work in parallel
-        drop(lru_lock);
+        self.get_session_status_cached(
+            &self.get_session_status_lru,
+            SESSION_STATUS_ENDPOINT,
+            session_idx,
+            decoders,
+        )
+        .await
+    }
 
-        enum NoCacheErr {
-            Initial,
-            Pending(Vec<AcceptedItem>),
-            Err(anyhow::Error),
-        }
-        match entry_arc
-            .get_or_try_init(|| async {
-                match self.get_session_status_raw(session_idx, decoders).await {
-                    Err(e) => Err(NoCacheErr::Err(e)),
-                    Ok(SessionStatus::Initial) => Err(NoCacheErr::Initial),
-                    Ok(SessionStatus::Pending(s)) => Err(NoCacheErr::Pending(s)),
-                    // only status we can cache (hance outer Ok)
-                    Ok(SessionStatus::Complete(s)) => Ok(s),
-                }
-            })
-            .await
-            .cloned()
-        {
-            Ok(s) => Ok(SessionStatus::Complete(s)),
-            Err(NoCacheErr::Initial) => Ok(SessionStatus::Initial),
-            Err(NoCacheErr::Pending(s)) => Ok(SessionStatus::Pending(s)),
-            Err(NoCacheErr::Err(e)) => Err(e),
-        }
+    async fn get_session_transactions(
+        &self,
+        session_idx: u64,
+        decoders: &ModuleDecoderRegistry,
+    ) -> anyhow::Result<SessionStatus> {
+        self.get_session_status_cached(
+            &self.get_session_transactions_lru,
+            SESSION_TRANSACTIONS_ENDPOINT,
+            session_idx,
+            decoders,
+        )
+        .await
     }
 
     /// Submit a transaction for inclusion
@@ -939,6 +1260,28 @@ where
         .await
     }
 
+    async fn submit_transactions_batch(
+        &self,
+        txs: Vec<Transaction>,
+    ) -> FederationResult<Vec<SerdeModuleEncoding<TransactionSubmissionOutcome>>> {
+        let mut outcomes = Vec::with_capacity(txs.len());
+
+        for chunk in txs.chunks(MAX_TRANSACTIONS_PER_BATCH) {
+            let chunk = chunk.iter().map(SerdeTransaction::from).collect::<Vec<_>>();
+
+            let chunk_outcomes: Vec<SerdeModuleEncoding<TransactionSubmissionOutcome>> = self
+                .request_current_consensus(
+                    SUBMIT_TRANSACTIONS_BATCH_ENDPOINT.to_owned(),
+                    ApiRequestErased::new(&chunk),
+                )
+                .await?;
+
+            outcomes.extend(chunk_outcomes);
+        }
+
+        Ok(outcomes)
+    }
+
     async fn session_count(&self) -> FederationResult<u64> {
         self.request_current_consensus(
             SESSION_COUNT_ENDPOINT.to_owned(),
@@ -947,6 +1290,24 @@ where
         .await
     }
 
+    async fn module_consensus_features(
+        &self,
+    ) -> FederationResult<BTreeMap<ModuleInstanceId, BTreeSet<ModuleConsensusFeature>>> {
+        self.request_current_consensus(
+            MODULE_CONSENSUS_FEATURES_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
+    async fn fee_schedule(&self) -> FederationResult<BTreeMap<ModuleInstanceId, FeeSchedule>> {
+        self.request_current_consensus(
+            FEE_SCHEDULE_ENDPOINT.to_owned(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
+
     async fn await_transaction(&self, txid: TransactionId) -> FederationResult<TransactionId> {
         self.request_current_consensus(
             AWAIT_TRANSACTION_ENDPOINT.to_owned(),
@@ -955,6 +1316,17 @@ where
         .await
     }
 
+    async fn transaction_error(
+        &self,
+        txid: TransactionId,
+    ) -> FederationResult<Option<TransactionRejection>> {
+        self.request_current_consensus(
+            TRANSACTION_ERROR_ENDPOINT.to_owned(),
+            ApiRequestErased::new(txid),
+        )
+        .await
+    }
+
     async fn server_config_consensus_hash(&self) -> FederationResult<sha256::Hash> {
         self.request_current_consensus(
             SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT.to_owned(),
@@ -1084,6 +1456,23 @@ where
             .await
     }
 
+    async fn consensus_health(&self, auth: ApiAuth) -> FederationResult<ConsensusHealth> {
+        self.request_admin(CONSENSUS_HEALTH_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
+
+    async fn peer_connection_stats(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<BTreeMap<PeerId, PeerConnectionStats>> {
+        self.request_admin(
+            PEER_CONNECTION_STATS_ENDPOINT,
+            ApiRequestErased::default(),
+            auth,
+        )
+        .await
+    }
+
     async fn guardian_config_backup(
         &self,
         auth: ApiAuth,
@@ -1096,6 +1485,30 @@ where
         .await
     }
 
+    async fn guardian_disaster_recovery_bundle(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianDisasterRecoveryBundle> {
+        self.request_admin(
+            GUARDIAN_DISASTER_RECOVERY_BUNDLE_ENDPOINT,
+            ApiRequestErased::default(),
+            auth,
+        )
+        .await
+    }
+
+    async fn consensus_checkpoint(
+        &self,
+        auth: ApiAuth,
+    ) -> FederationResult<GuardianConsensusCheckpoint> {
+        self.request_admin(
+            CONSENSUS_CHECKPOINT_ENDPOINT,
+            ApiRequestErased::default(),
+            auth,
+        )
+        .await
+    }
+
     async fn auth(&self, auth: ApiAuth) -> FederationResult<()> {
         self.request_admin(AUTH_ENDPOINT, ApiRequestErased::default(), auth)
             .await
@@ -1109,6 +1522,106 @@ where
         )
         .await
     }
+
+    async fn shutdown(&self, session: Option<u64>, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(SHUTDOWN_ENDPOINT, ApiRequestErased::new(session), auth)
+            .await
+    }
+
+    async fn set_module_maintenance(
+        &self,
+        request: ModuleMaintenanceRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            SET_MODULE_MAINTENANCE_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn modules_in_maintenance(&self) -> FederationResult<BTreeSet<ModuleInstanceId>> {
+        self.request_admin_no_auth(MODULES_IN_MAINTENANCE_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn set_session_retention(
+        &self,
+        retention: SessionRetention,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            SET_SESSION_RETENTION_ENDPOINT,
+            ApiRequestErased::new(retention),
+            auth,
+        )
+        .await
+    }
+
+    async fn session_retention(&self) -> FederationResult<SessionRetention> {
+        self.request_admin_no_auth(SESSION_RETENTION_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn propose_module_addition(
+        &self,
+        request: ModuleAdditionRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            PROPOSE_MODULE_ADDITION_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn module_additions(&self) -> FederationResult<ModuleAdditionsStatus> {
+        self.request_admin_no_auth(MODULE_ADDITIONS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn schedule_upgrade(
+        &self,
+        request: ScheduleUpgradeRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<()> {
+        self.request_admin(
+            SCHEDULE_UPGRADE_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn upgrade_status(&self) -> FederationResult<UpgradeStatus> {
+        self.request_admin_no_auth(UPGRADE_STATUS_ENDPOINT, ApiRequestErased::default())
+            .await
+    }
+
+    async fn issue_api_token(
+        &self,
+        request: IssueApiTokenRequest,
+        auth: ApiAuth,
+    ) -> FederationResult<ApiAuth> {
+        self.request_admin(
+            ISSUE_API_TOKEN_ENDPOINT,
+            ApiRequestErased::new(request),
+            auth,
+        )
+        .await
+    }
+
+    async fn revoke_api_token(&self, name: String, auth: ApiAuth) -> FederationResult<()> {
+        self.request_admin(REVOKE_API_TOKEN_ENDPOINT, ApiRequestErased::new(name), auth)
+            .await
+    }
+
+    async fn list_api_tokens(&self, auth: ApiAuth) -> FederationResult<Vec<ApiTokenInfo>> {
+        self.request_admin(LIST_API_TOKENS_ENDPOINT, ApiRequestErased::default(), auth)
+            .await
+    }
 }
 
 /// Mint API client that will try to run queries against all `peers` expecting
@@ -1120,6 +1633,8 @@ pub struct WsFederationApi<C = WsClient> {
     self_peer_id: Option<PeerId>,
     peers: Arc<Vec<FederationPeer<C>>>,
     module_id: Option<ModuleInstanceId>,
+    interceptors: Vec<DynApiRequestInterceptor>,
+    health: Arc<PeerHealthTracker>,
 }
 
 /// Some data shared/preserved between [`FederationPeerClient`] and
@@ -1172,7 +1687,11 @@ impl FederationPeerClientShared {
 /// starting background Jit task
 #[derive(Debug)]
 struct FederationPeerClient<C> {
-    client: JitTryAnyhow<C>,
+    client: JitTryAnyhow<PeerConnection<C>>,
+    /// URL the peer is currently connected to, changes if the guardian moved
+    url: SafeUrl,
+    /// SOCKS5 proxy connections are made through, required for onion URLs
+    socks5_proxy: Option<SocketAddr>,
     shared: Arc<tokio::sync::Mutex<FederationPeerClientShared>>,
 }
 
@@ -1180,11 +1699,24 @@ impl<C> FederationPeerClient<C>
 where
     C: JsonRpcClient + 'static,
 {
-    pub fn new(peer_id: PeerId, url: SafeUrl, api_secret: Option<String>) -> Self {
+    pub fn new(
+        peer_id: PeerId,
+        url: SafeUrl,
+        api_secret: Option<String>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
         let shared: Arc<_> = tokio::sync::Mutex::new(FederationPeerClientShared::new()).into();
 
         Self {
-            client: Self::new_jit_client(peer_id, url, api_secret, shared.clone()),
+            client: Self::new_jit_client(
+                peer_id,
+                url.clone(),
+                api_secret,
+                socks5_proxy,
+                shared.clone(),
+            ),
+            url,
+            socks5_proxy,
             shared,
         }
     }
@@ -1193,8 +1725,9 @@ where
         peer_id: PeerId,
         url: SafeUrl,
         api_secret: Option<String>,
+        socks5_proxy: Option<SocketAddr>,
         shared: Arc<Mutex<FederationPeerClientShared>>,
-    ) -> JitTryAnyhow<C> {
+    ) -> JitTryAnyhow<PeerConnection<C>> {
         JitTryAnyhow::new_try(move || async move {
             shared.lock().await.wait_and_inc_reconnect().await;
 
@@ -1203,7 +1736,10 @@ where
                 peer_id = %peer_id,
                 url = %url,
                 "Connecting to peer");
-            let res = C::connect(&url, api_secret).await;
+            let res = match C::connect(&url, api_secret, socks5_proxy).await {
+                Ok(client) => Ok(PeerConnection::negotiate(peer_id, client).await),
+                Err(err) => Err(err),
+            };
 
             match &res {
                 Ok(_) => {
@@ -1226,14 +1762,95 @@ where
         })
     }
 
-    pub fn reconnect(&mut self, peer_id: PeerId, url: SafeUrl, api_secret: Option<String>) {
-        self.client = Self::new_jit_client(peer_id, url, api_secret, self.shared.clone());
+    pub fn reconnect(&mut self, peer_id: PeerId, api_secret: Option<String>) {
+        self.client = Self::new_jit_client(
+            peer_id,
+            self.url.clone(),
+            api_secret,
+            self.socks5_proxy,
+            self.shared.clone(),
+        );
+    }
+
+    /// Drops the current connection and connects to `url` instead
+    pub fn set_url(&mut self, peer_id: PeerId, url: SafeUrl, api_secret: Option<String>) {
+        self.url = url;
+        self.reconnect(peer_id, api_secret);
+    }
+}
+
+/// Connection to a peer together with the transport parameters negotiated for
+/// it, see [`fedimint_core::net::api_transport`]
+#[derive(Debug)]
+struct PeerConnection<C> {
+    client: C,
+    transport: ApiRequestTransport,
+    /// Largest request the server of the peer accepts
+    max_request_size: u32,
+}
+
+impl<C> PeerConnection<C>
+where
+    C: JsonRpcClient,
+{
+    /// Asks the server of the peer which compressions and message sizes it
+    /// supports. Servers that don't know the endpoint yet are sent requests
+    /// without transport parameters.
+    async fn negotiate(peer_id: PeerId, client: C) -> Self {
+        let params = [ApiRequestErased::default().to_json()];
+        match client
+            .request::<ApiTransportOptions, _>(TRANSPORT_OPTIONS_ENDPOINT, &params[..])
+            .await
+        {
+            Ok(options) => PeerConnection {
+                transport: options.negotiate(&[ApiCompression::Deflate], MAX_API_RESPONSE_SIZE),
+                max_request_size: options.max_request_size,
+                client,
+            },
+            Err(err) => {
+                debug!(
+                    target: LOG_CLIENT_NET_API,
+                    peer_id = %peer_id,
+                    %err, "Peer doesn't support transport negotiation");
+                PeerConnection {
+                    client,
+                    transport: ApiRequestTransport::default(),
+                    max_request_size: MAX_API_REQUEST_SIZE,
+                }
+            }
+        }
+    }
+
+    async fn request(&self, method: &str, params: &[Value]) -> JsonRpcResult<Value> {
+        let mut params = params.to_vec();
+        if !self.transport.is_default() {
+            if let Some(Value::Object(request)) = params.first_mut() {
+                request.insert(
+                    "transport".to_string(),
+                    serde_json::to_value(self.transport).expect("Serializing can't fail"),
+                );
+            }
+        }
+
+        // Fail right away instead of having the server drop the connection
+        let size = serde_json::to_vec(&params).map_or(0, |params| params.len());
+        if size > self.max_request_size as usize {
+            return Err(JsonRpcClientError::Custom(format!(
+                "Request of {size} bytes exceeds the maximum of {} bytes",
+                self.max_request_size
+            )));
+        }
+
+        let response = self.client.request::<Value, _>(method, &params[..]).await?;
+
+        self.transport
+            .decode_response(response)
+            .map_err(|e| JsonRpcClientError::Custom(format!("Failed to decode response: {e}")))
     }
 }
 
 #[derive(Debug)]
 struct FederationPeer<C> {
-    url: SafeUrl,
     peer_id: PeerId,
     api_secret: Option<String>,
     client: RwLock<FederationPeerClient<C>>,
@@ -1259,10 +1876,16 @@ impl<C: JsonRpcClient + Debug + 'static> IRawFederationApi for WsFederationApi<C
             peers: self.peers.clone(),
             module_id: Some(id),
             self_peer_id: self.self_peer_id,
+            interceptors: self.interceptors.clone(),
+            health: self.health.clone(),
         }
         .into()
     }
 
+    fn peer_health(&self) -> &PeerHealthTracker {
+        &self.health
+    }
+
     async fn request_raw(
         &self,
         peer_id: PeerId,
@@ -1279,15 +1902,53 @@ impl<C: JsonRpcClient + Debug + 'static> IRawFederationApi for WsFederationApi<C
             None => method.to_string(),
             Some(id) => format!("module_{id}_{method}"),
         };
-        peer.request(&method, params).await
+        let request = ApiRequest {
+            peer_id,
+            method,
+            params: params.to_vec(),
+        };
+        let start = now();
+        let result = ApiRequestNext::new(&self.interceptors, peer)
+            .run(request)
+            .await;
+        match &result {
+            Ok(_) => self
+                .health
+                .record_success(peer_id, now().duration_since(start).unwrap_or_default()),
+            Err(e) => self.health.record_error(peer_id, e.to_string()),
+        }
+        result
+    }
+
+    async fn update_peer_urls(&self, peer_urls: &BTreeMap<PeerId, SafeUrl>) {
+        for peer in self.peers.iter() {
+            let Some(url) = peer_urls.get(&peer.peer_id) else {
+                continue;
+            };
+
+            let mut client = peer.client.write().await;
+            if client.url == *url {
+                continue;
+            }
+
+            debug!(
+                target: LOG_CLIENT_NET_API,
+                peer_id = %peer.peer_id,
+                old_url = %client.url,
+                new_url = %url,
+                "Peer moved to a new URL, reconnecting");
+            client.set_url(peer.peer_id, url.clone(), peer.api_secret.clone());
+        }
     }
 }
 
 #[apply(async_trait_maybe_send!)]
 pub trait JsonRpcClient: ClientT + Sized + MaybeSend + MaybeSync {
+    /// Connects to `url`, through the SOCKS5 proxy `socks5_proxy` if set
     async fn connect(
         url: &SafeUrl,
         api_secret: Option<String>,
+        socks5_proxy: Option<SocketAddr>,
     ) -> result::Result<Self, JsonRpcClientError>;
     fn is_connected(&self) -> bool;
 }
@@ -1297,11 +1958,20 @@ impl JsonRpcClient for WsClient {
     async fn connect(
         url: &SafeUrl,
         api_secret: Option<String>,
+        socks5_proxy: Option<SocketAddr>,
     ) -> result::Result<Self, JsonRpcClientError> {
+        if socks5_proxy.is_none() && url.is_onion() {
+            return Err(JsonRpcClientError::Transport(anyhow::format_err!(
+                "Connecting to onion URL {url} requires a SOCKS5 proxy"
+            )));
+        }
+
         #[cfg(not(target_family = "wasm"))]
         let mut client = WsClientBuilder::default()
             .use_webpki_rustls()
-            .max_concurrent_requests(u16::MAX as usize);
+            .max_concurrent_requests(u16::MAX as usize)
+            .max_request_size(MAX_API_REQUEST_SIZE)
+            .max_response_size(MAX_API_RESPONSE_SIZE);
 
         #[cfg(target_family = "wasm")]
         let client = WsClientBuilder::default().max_concurrent_requests(u16::MAX as usize);
@@ -1337,6 +2007,19 @@ impl JsonRpcClient for WsClient {
                 return client.build(url.as_str()).await;
             }
         }
+
+        #[cfg(not(target_family = "wasm"))]
+        if let Some(socks5_proxy) = socks5_proxy {
+            return crate::socks5::build_client(client, url, socks5_proxy).await;
+        }
+
+        #[cfg(target_family = "wasm")]
+        if socks5_proxy.is_some() {
+            return Err(JsonRpcClientError::Transport(anyhow::format_err!(
+                "SOCKS5 proxies are not supported on wasm"
+            )));
+        }
+
         client.build(url.as_str()).await
     }
 
@@ -1348,12 +2031,32 @@ impl JsonRpcClient for WsClient {
 impl WsFederationApi<WsClient> {
     /// Creates a new API client
     pub fn new(peers: Vec<(PeerId, SafeUrl)>, api_secret: &Option<String>) -> Self {
-        Self::new_with_client(peers, None, api_secret)
+        Self::new_with_client(peers, None, api_secret, None)
+    }
+
+    /// Creates a new API client connecting through the SOCKS5 proxy
+    /// `socks5_proxy` if set
+    pub fn new_with_proxy(
+        peers: Vec<(PeerId, SafeUrl)>,
+        api_secret: &Option<String>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
+        Self::new_with_client(peers, None, api_secret, socks5_proxy)
     }
 
     /// Creates a new API client from a client config
     pub fn from_config(config: &ClientConfig, api_secret: &Option<String>) -> Self {
-        Self::new(
+        Self::from_config_with_proxy(config, api_secret, None)
+    }
+
+    /// Creates a new API client from a client config connecting through the
+    /// SOCKS5 proxy `socks5_proxy` if set
+    pub fn from_config_with_proxy(
+        config: &ClientConfig,
+        api_secret: &Option<String>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
+        Self::new_with_proxy(
             config
                 .global
                 .api_endpoints
@@ -1361,6 +2064,7 @@ impl WsFederationApi<WsClient> {
                 .map(|(id, peer)| (*id, peer.url.clone()))
                 .collect(),
             api_secret,
+            socks5_proxy,
         )
     }
 
@@ -1385,6 +2089,7 @@ where
         peers: Vec<(PeerId, SafeUrl)>,
         self_peer_id: Option<PeerId>,
         api_secret: &Option<String>,
+        socks5_proxy: Option<SocketAddr>,
     ) -> Self {
         WsFederationApi {
             peer_ids: peers.iter().map(|m| m.0).collect(),
@@ -1403,16 +2108,27 @@ where
                             peer_id,
                             client: RwLock::new(FederationPeerClient::new(
                                 peer_id,
-                                url.clone(),
+                                url,
                                 api_secret.clone(),
+                                socks5_proxy,
                             )),
-                            url,
                             api_secret: api_secret.clone(),
                         }
                     })
                     .collect(),
             ),
             module_id: None,
+            interceptors: vec![],
+            health: Arc::new(PeerHealthTracker::default()),
+        }
+    }
+
+    /// Passes every request through the `interceptors` before sending it, see
+    /// [`crate::interceptor::IApiRequestInterceptor`]
+    pub fn with_interceptors(self, interceptors: Vec<DynApiRequestInterceptor>) -> Self {
+        Self {
+            interceptors,
+            ..self
         }
     }
 }
@@ -1433,8 +2149,8 @@ where
             debug_assert!(attempts <= 1);
             let rclient = self.client.read().await;
             match rclient.client.get_try().await {
-                Ok(client) if client.is_connected() => {
-                    return client.request::<_, _>(method, params).await;
+                Ok(connection) if connection.client.is_connected() => {
+                    return connection.request(method, params).await;
                 }
                 Err(e) => {
                     // Strategies using timeouts often depend on failing requests returning quickly,
@@ -1457,12 +2173,12 @@ where
             drop(rclient);
             let mut wclient = self.client.write().await;
             match wclient.client.get_try().await {
-                Ok(client) if client.is_connected() => {
+                Ok(connection) if connection.client.is_connected() => {
                     // someone else connected, just loop again
                     trace!(target: LOG_CLIENT_NET_API, "Some other request reconnected client, retrying");
                 }
                 _ => {
-                    wclient.reconnect(self.peer_id, self.url.clone(), self.api_secret.clone());
+                    wclient.reconnect(self.peer_id, self.api_secret.clone());
                 }
             }
         }
@@ -1471,6 +2187,16 @@ where
     }
 }
 
+#[apply(async_trait_maybe_send!)]
+impl<C> IApiRequestTransport for FederationPeer<C>
+where
+    C: JsonRpcClient + 'static,
+{
+    async fn send(&self, request: &ApiRequest) -> JsonRpcResult<Value> {
+        self.request(&request.method, &request.params).await
+    }
+}
+
 impl<C: JsonRpcClient> WsFederationApi<C> {}
 
 /// The status of a server, including how it views its peers
@@ -1483,6 +2209,11 @@ pub struct FederationStatus {
     /// This should always be 0 if everything is okay, so a monitoring tool
     /// should generate an alert if this is not the case.
     pub peers_flagged: u64,
+    /// Hex encoded bitmap of the peers this server considers alive, bit `i`
+    /// of byte `i / 8` is set for peer `i`. Comparing it across servers
+    /// reveals asymmetric connectivity between peers.
+    #[serde(default)]
+    pub liveness_bitmap: String,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -1502,10 +2233,75 @@ pub enum PeerConnectionStatus {
     Connected,
 }
 
+/// Statistics about the P2P connection to a peer since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerConnectionStats {
+    /// Number of times the connection was established again after it was lost
+    pub reconnect_count: u64,
+    pub disconnect_count: u64,
+    /// Number of messages sent to the peer, including pings
+    pub messages_sent: u64,
+    /// Number of messages received from the peer, including pings
+    pub messages_received: u64,
+    /// Size of the serialized messages sent to the peer, excluding framing
+    pub bytes_sent: u64,
+    /// Size of the serialized messages received from the peer, excluding
+    /// framing
+    pub bytes_received: u64,
+    /// How long sending and flushing the last message to the peer took
+    pub last_send_latency_ms: Option<u64>,
+    /// Unix timestamp (seconds) of the last message received from the peer
+    pub last_seen: Option<u64>,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct StatusResponse {
     pub server: ServerStatus,
     pub federation: Option<FederationStatus>,
+    /// Health of the modules that check their health, only reported once
+    /// consensus is running
+    #[serde(default)]
+    pub modules: BTreeMap<ModuleInstanceId, ModuleHealthStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ModuleHealthStatus {
+    pub kind: ModuleKind,
+    pub health: ModuleHealth,
+}
+
+/// Aggregated consensus health of a guardian, meant to be polled by
+/// monitoring dashboards
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConsensusHealth {
+    pub session_count: u64,
+    /// Unix timestamp (seconds) at which we last processed a consensus item of
+    /// each peer, `None` if we haven't seen one since the last restart
+    pub last_item_timestamp_by_peer: BTreeMap<PeerId, Option<u64>>,
+    pub peers_offline: BTreeSet<PeerId>,
+    pub peers_flagged: BTreeSet<PeerId>,
+    /// Number of items waiting to be proposed to consensus
+    pub pending_submissions: u64,
+    /// Empty if everything is okay, a monitoring tool should generate an alert
+    /// otherwise
+    pub alerts: Vec<ConsensusHealthAlert>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsensusHealthAlert {
+    /// Some peers are not connected to us
+    PeersOffline { count: u64 },
+    /// Some peers have not contributed to consensus in a long time
+    PeersFlagged { count: u64 },
+    /// A peer has not contributed a consensus item for longer than the
+    /// threshold
+    PeerStalled {
+        peer: PeerId,
+        seconds_since_last_item: u64,
+    },
+    /// More items are waiting to be proposed than the threshold
+    SubmissionBacklog { pending: u64 },
 }
 
 /// Archive of all the guardian config files that can be used to recover a lost
@@ -1516,6 +2312,28 @@ pub struct GuardianConfigBackup {
     pub tar_archive_bytes: Vec<u8>,
 }
 
+/// Guardian config, including the private key shares of all modules, and a
+/// reference to the last finished session, encrypted as a whole with the
+/// guardian password. Unlike [`GuardianConfigBackup`] it can be passed to
+/// `fedimintd restore --bundle` to recreate the data dir of a lost guardian.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianDisasterRecoveryBundle {
+    /// Salt used to derive the encryption key from the guardian password
+    pub salt: String,
+    #[serde(with = "fedimint_core::hex::serde")]
+    pub encrypted_bundle: Vec<u8>,
+}
+
+/// Snapshot of a guardian's consensus state after `session_count` sessions,
+/// including the threshold signed outcome of the last one
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GuardianConsensusCheckpoint {
+    pub session_count: u64,
+    /// Consensus encoded database entries
+    #[serde(with = "fedimint_core::hex::serde")]
+    pub snapshot: Vec<u8>,
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr as _;
@@ -1547,7 +2365,11 @@ mod tests {
             self.0.is_connected()
         }
 
-        async fn connect(_url: &SafeUrl, _api_secret: Option<String>) -> Result<Self> {
+        async fn connect(
+            _url: &SafeUrl,
+            _api_secret: Option<String>,
+            _socks5_proxy: Option<SocketAddr>,
+        ) -> Result<Self> {
             Ok(Self(C::connect().await?))
         }
     }
@@ -1619,4 +2441,31 @@ mod tests {
             peer_to_url_map.into_iter().take(max_size).collect();
         assert_eq!(expected_map, code.peers());
     }
+
+    #[test]
+    fn parses_module_in_maintenance_error() {
+        // the error as it is sent by the guardian, see `attach_endpoints`
+        let api_error = fedimint_core::module::ApiError::module_in_maintenance(3);
+        let error = PeerError::Rpc(JsonRpcClientError::Call(
+            serde_json::from_value(serde_json::json!({
+                "code": api_error.code,
+                "message": api_error.message,
+                "data": api_error.data,
+            }))
+            .unwrap(),
+        ));
+        assert_eq!(error.module_in_maintenance(), Some(3));
+
+        let error = PeerError::Rpc(JsonRpcClientError::Call(
+            serde_json::from_value(serde_json::json!({
+                "code": MODULE_IN_MAINTENANCE_ERROR_CODE,
+                "message": "Service unavailable",
+            }))
+            .unwrap(),
+        ));
+        assert_eq!(error.module_in_maintenance(), None);
+
+        let error = PeerError::Rpc(JsonRpcClientError::RequestTimeout);
+        assert_eq!(error.module_in_maintenance(), None);
+    }
 }
@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use fedimint_core::module::ApiVersion;
+use fedimint_core::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Number of consecutive failed requests after which a peer is considered
+/// unhealthy and only queried after all healthy peers
+pub const UNHEALTHY_AFTER_CONSECUTIVE_ERRORS: u64 = 3;
+
+/// Weight of the latest request in the moving average of a peer's latency, in
+/// percent
+const LATENCY_SMOOTHING_PERCENT: u64 = 20;
+
+/// Statistics about the requests sent to a single guardian
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerHealth {
+    pub requests: u64,
+    pub errors: u64,
+    /// Number of failed requests since the last successful one
+    pub consecutive_errors: u64,
+    /// Exponential moving average of the latency of successful requests
+    pub avg_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    /// Latest core api version the peer reported to support
+    pub core_api_version: Option<ApiVersion>,
+}
+
+impl PeerHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_errors < UNHEALTHY_AFTER_CONSECUTIVE_ERRORS
+    }
+
+    /// Share of failed requests in percent, `None` if no request was sent yet
+    pub fn error_rate_percent(&self) -> Option<u64> {
+        (self.requests != 0).then(|| self.errors * 100 / self.requests)
+    }
+}
+
+/// Health of all guardians as observed by the api client, see
+/// [`PeerHealthTracker`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiHealthReport {
+    pub peers: BTreeMap<PeerId, PeerHealth>,
+}
+
+/// Tracks the latency and errors of requests to every guardian, so that
+/// requests which don't need a response from every guardian can go to the
+/// healthiest ones first.
+///
+/// Statistics are kept in memory only and shared by all clones of an api
+/// client, including the module apis derived from it.
+#[derive(Debug, Default)]
+pub struct PeerHealthTracker {
+    peers: Mutex<BTreeMap<PeerId, PeerHealth>>,
+}
+
+impl PeerHealthTracker {
+    pub fn record_success(&self, peer: PeerId, latency: Duration) {
+        let mut peers = self.peers.lock().expect("poisoned");
+        let health = peers.entry(peer).or_default();
+
+        let latency_ms = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        health.requests += 1;
+        health.consecutive_errors = 0;
+        health.avg_latency_ms = Some(match health.avg_latency_ms {
+            Some(avg) => {
+                (avg * (100 - LATENCY_SMOOTHING_PERCENT)
+                    + latency_ms.saturating_mul(LATENCY_SMOOTHING_PERCENT))
+                    / 100
+            }
+            None => latency_ms,
+        });
+    }
+
+    pub fn record_error(&self, peer: PeerId, error: String) {
+        let mut peers = self.peers.lock().expect("poisoned");
+        let health = peers.entry(peer).or_default();
+
+        health.requests += 1;
+        health.errors += 1;
+        health.consecutive_errors += 1;
+        health.last_error = Some(error);
+    }
+
+    pub fn record_core_api_version(&self, peer: PeerId, version: ApiVersion) {
+        let mut peers = self.peers.lock().expect("poisoned");
+        peers.entry(peer).or_default().core_api_version = Some(version);
+    }
+
+    /// Orders `peers` from the most to the least preferable to send a request
+    /// to: healthy peers before unhealthy ones, then by fewest consecutive
+    /// errors and lowest latency. Peers without statistics yet are treated
+    /// as healthy but slower than any peer with known latency.
+    pub fn sorted_by_health(&self, peers: &BTreeSet<PeerId>) -> Vec<PeerId> {
+        let health = self.peers.lock().expect("poisoned");
+
+        let mut sorted = peers.iter().copied().collect::<Vec<_>>();
+        sorted.sort_by_key(|peer| {
+            let peer_health = health.get(peer).cloned().unwrap_or_default();
+            (
+                !peer_health.is_healthy(),
+                peer_health.consecutive_errors,
+                peer_health.avg_latency_ms.unwrap_or(u64::MAX),
+            )
+        });
+        sorted
+    }
+
+    pub fn report(&self) -> ApiHealthReport {
+        ApiHealthReport {
+            peers: self.peers.lock().expect("poisoned").clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use fedimint_core::PeerId;
+
+    use super::{PeerHealthTracker, UNHEALTHY_AFTER_CONSECUTIVE_ERRORS};
+
+    #[test]
+    fn prefers_healthy_and_fast_peers() {
+        let tracker = PeerHealthTracker::default();
+        let peers = (0..4).map(PeerId::from).collect::<BTreeSet<_>>();
+
+        tracker.record_success(PeerId::from(0), Duration::from_millis(300));
+        tracker.record_success(PeerId::from(1), Duration::from_millis(50));
+        for _ in 0..UNHEALTHY_AFTER_CONSECUTIVE_ERRORS {
+            tracker.record_error(PeerId::from(2), "connection refused".to_string());
+        }
+
+        assert_eq!(
+            tracker.sorted_by_health(&peers),
+            vec![
+                PeerId::from(1),
+                PeerId::from(0),
+                PeerId::from(3),
+                PeerId::from(2)
+            ]
+        );
+
+        // a single success makes a peer healthy again
+        tracker.record_success(PeerId::from(2), Duration::from_millis(10));
+        assert_eq!(tracker.sorted_by_health(&peers)[0], PeerId::from(2));
+    }
+
+    #[test]
+    fn reports_error_rate_and_latency() {
+        let tracker = PeerHealthTracker::default();
+        let peer = PeerId::from(0);
+
+        tracker.record_success(peer, Duration::from_millis(100));
+        tracker.record_success(peer, Duration::from_millis(200));
+        tracker.record_error(peer, "timeout".to_string());
+
+        let health = tracker.report().peers[&peer].clone();
+        assert_eq!(health.requests, 3);
+        assert_eq!(health.errors, 1);
+        assert_eq!(health.consecutive_errors, 1);
+        assert_eq!(health.avg_latency_ms, Some(120));
+        assert_eq!(health.error_rate_percent(), Some(33));
+        assert_eq!(health.last_error.as_deref(), Some("timeout"));
+        assert!(health.is_healthy());
+    }
+}
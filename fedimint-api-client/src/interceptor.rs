@@ -0,0 +1,178 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send, dyn_newtype_define, PeerId};
+use serde_json::Value;
+
+use crate::api::JsonRpcResult;
+
+/// A request to a single federation peer, as seen by the interceptors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiRequest {
+    pub peer_id: PeerId,
+    /// Full name of the endpoint, including the `module_{id}_` prefix of
+    /// module endpoints
+    pub method: String,
+    pub params: Vec<Value>,
+}
+
+/// Hook wrapping every request the api client sends to a federation peer,
+/// e.g. to collect metrics, sign requests or stop calling an unresponsive
+/// peer.
+///
+/// Interceptors are called in the order they were registered. Each one
+/// decides whether to pass the (possibly modified) request on to the next one
+/// via [`ApiRequestNext::run`] or to answer it itself, and can inspect or
+/// replace the response on the way back.
+#[apply(async_trait_maybe_send!)]
+pub trait IApiRequestInterceptor: Debug + MaybeSend + MaybeSync {
+    async fn intercept(
+        &self,
+        request: ApiRequest,
+        next: ApiRequestNext<'_>,
+    ) -> JsonRpcResult<Value>;
+}
+
+dyn_newtype_define! {
+    #[derive(Clone)]
+    pub DynApiRequestInterceptor(Arc<IApiRequestInterceptor>)
+}
+
+/// Sends a request to the peer once all interceptors passed it on
+#[apply(async_trait_maybe_send!)]
+pub(crate) trait IApiRequestTransport: MaybeSend + MaybeSync {
+    async fn send(&self, request: &ApiRequest) -> JsonRpcResult<Value>;
+}
+
+/// The interceptors a request has not passed yet, followed by the connection
+/// to the peer
+pub struct ApiRequestNext<'a> {
+    interceptors: &'a [DynApiRequestInterceptor],
+    transport: &'a dyn IApiRequestTransport,
+}
+
+impl<'a> ApiRequestNext<'a> {
+    pub(crate) fn new(
+        interceptors: &'a [DynApiRequestInterceptor],
+        transport: &'a dyn IApiRequestTransport,
+    ) -> Self {
+        Self {
+            interceptors,
+            transport,
+        }
+    }
+
+    /// Passes the request on to the next interceptor, or sends it to the peer
+    /// if there is none left
+    pub async fn run(self, request: ApiRequest) -> JsonRpcResult<Value> {
+        match self.interceptors.split_first() {
+            Some((interceptor, rest)) => {
+                interceptor
+                    .intercept(request, ApiRequestNext::new(rest, self.transport))
+                    .await
+            }
+            None => self.transport.send(&request).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use fedimint_core::{apply, async_trait_maybe_send, PeerId};
+    use jsonrpsee_core::client::Error as JsonRpcClientError;
+    use serde_json::Value;
+
+    use super::{
+        ApiRequest, ApiRequestNext, DynApiRequestInterceptor, IApiRequestInterceptor,
+        IApiRequestTransport,
+    };
+    use crate::api::JsonRpcResult;
+
+    /// Answers every request with the method it was sent to
+    struct EchoTransport;
+
+    #[apply(async_trait_maybe_send!)]
+    impl IApiRequestTransport for EchoTransport {
+        async fn send(&self, request: &ApiRequest) -> JsonRpcResult<Value> {
+            Ok(Value::String(request.method.clone()))
+        }
+    }
+
+    /// Records the requests it sees and renames them
+    #[derive(Debug, Default)]
+    struct Rename {
+        seen: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IApiRequestInterceptor for Rename {
+        async fn intercept(
+            &self,
+            mut request: ApiRequest,
+            next: ApiRequestNext<'_>,
+        ) -> JsonRpcResult<Value> {
+            self.seen.lock().unwrap().push(request.method.clone());
+            request.method.push_str("_renamed");
+            next.run(request).await
+        }
+    }
+
+    /// Rejects every request without sending it
+    #[derive(Debug)]
+    struct CircuitOpen;
+
+    #[apply(async_trait_maybe_send!)]
+    impl IApiRequestInterceptor for CircuitOpen {
+        async fn intercept(
+            &self,
+            _request: ApiRequest,
+            _next: ApiRequestNext<'_>,
+        ) -> JsonRpcResult<Value> {
+            Err(JsonRpcClientError::Custom("circuit open".to_string()))
+        }
+    }
+
+    fn request() -> ApiRequest {
+        ApiRequest {
+            peer_id: PeerId::from(0),
+            method: "status".to_string(),
+            params: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_run_in_registration_order() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let interceptors = vec![
+            DynApiRequestInterceptor::from(Rename { seen: seen.clone() }),
+            DynApiRequestInterceptor::from(Rename::default()),
+        ];
+
+        let response = ApiRequestNext::new(&interceptors, &EchoTransport)
+            .run(request())
+            .await
+            .expect("request is passed on");
+
+        assert_eq!(response, Value::from("status_renamed_renamed"));
+        assert_eq!(*seen.lock().unwrap(), vec!["status".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn interceptor_can_answer_without_sending() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let interceptors = vec![
+            DynApiRequestInterceptor::from(CircuitOpen),
+            DynApiRequestInterceptor::from(Rename { seen: seen.clone() }),
+        ];
+
+        let result = ApiRequestNext::new(&interceptors, &EchoTransport)
+            .run(request())
+            .await;
+
+        assert!(result.is_err());
+        assert!(seen.lock().unwrap().is_empty());
+    }
+}
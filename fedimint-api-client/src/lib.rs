@@ -18,9 +18,20 @@ use fedimint_core::NumPeers;
 use query::FilterMap;
 use tracing::debug;
 
+/// Typed client for the guardian admin endpoints
+pub mod admin;
 pub mod api;
+/// Per-guardian request statistics used to route requests to healthy peers
+pub mod health;
+/// Hooks wrapping the requests the api client sends to federation peers
+pub mod interceptor;
+/// Typed wrappers over the API endpoints of server modules
+pub mod module_api;
 /// Client query system
 pub mod query;
+/// Websocket connections through a SOCKS5 proxy like Tor
+#[cfg(not(target_family = "wasm"))]
+mod socks5;
 
 /// Tries to download the client config from the federation,
 /// attempts to retry teb times before giving up.
@@ -0,0 +1,95 @@
+//! Typed wrappers over the API endpoints of server modules
+//!
+//! Most client modules define an extension trait of
+//! [`crate::api::IModuleFederationApi`] with one method per endpoint of their
+//! server module, implemented for every module API by sending the arguments
+//! of the method as the request and deserializing the response the guardians
+//! agree on. [`module_api_ext!`](crate::module_api_ext) generates such a trait
+//! from the signatures of its methods and the endpoints they call, so only
+//! endpoints that need a different query strategy or retries have to be
+//! implemented by hand.
+
+/// Defines an extension trait of [`crate::api::IModuleFederationApi`], and so
+/// of [`crate::api::DynModuleApi`], with a method per module API endpoint
+///
+/// Every method sends its arguments to the endpoint and returns the response
+/// a threshold of guardians agrees on, see
+/// [`crate::api::FederationApiExt::request_current_consensus`]. A method
+/// without arguments sends no parameters, a method with a single argument
+/// sends the argument and a method with multiple arguments sends them as a
+/// tuple, which is what the server side `api_endpoint!` handler has to expect.
+///
+/// ```ignore
+/// module_api_ext! {
+///     pub trait MintFederationApi {
+///         /// Returns for every nonce whether the note it belongs to has
+///         /// already been spent
+///         async fn check_spent_nonces(nonces: Vec<Nonce>) -> Vec<bool>
+///             = CHECK_SPENT_NONCES_ENDPOINT;
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! module_api_ext {
+    (
+        $(#[$trait_attr:meta])*
+        $vis:vis trait $trait_name:ident {
+            $(
+                $(#[$method_attr:meta])*
+                async fn $method:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $ret:ty
+                    = $endpoint:expr;
+            )*
+        }
+    ) => {
+        $(#[$trait_attr])*
+        #[::fedimint_core::apply(::fedimint_core::async_trait_maybe_send!)]
+        $vis trait $trait_name {
+            $(
+                $(#[$method_attr])*
+                async fn $method(
+                    &self,
+                    $($arg: $arg_ty),*
+                ) -> $crate::api::FederationResult<$ret>;
+            )*
+        }
+
+        #[::fedimint_core::apply(::fedimint_core::async_trait_maybe_send!)]
+        impl<T: ?Sized> $trait_name for T
+        where
+            T: $crate::api::IModuleFederationApi
+                + ::fedimint_core::task::MaybeSend
+                + ::fedimint_core::task::MaybeSync
+                + 'static,
+        {
+            $(
+                async fn $method(
+                    &self,
+                    $($arg: $arg_ty),*
+                ) -> $crate::api::FederationResult<$ret> {
+                    $crate::api::FederationApiExt::request_current_consensus(
+                        self,
+                        ::std::string::ToString::to_string(&$endpoint),
+                        $crate::__module_api_request!($($arg),*),
+                    )
+                    .await
+                }
+            )*
+        }
+    };
+}
+
+/// Builds the request of a method generated by
+/// [`module_api_ext!`](crate::module_api_ext) from its arguments
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __module_api_request {
+    () => {
+        ::fedimint_core::module::ApiRequestErased::default()
+    };
+    ($arg:ident) => {
+        ::fedimint_core::module::ApiRequestErased::new($arg)
+    };
+    ($($arg:ident),+) => {
+        ::fedimint_core::module::ApiRequestErased::new(($($arg),+))
+    };
+}
@@ -0,0 +1,76 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use fedimint_core::util::SafeUrl;
+use jsonrpsee_core::client::Error as JsonRpcClientError;
+use jsonrpsee_ws_client::{WsClient, WsClientBuilder};
+use tokio_rustls::rustls::{ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tokio_socks::tcp::Socks5Stream;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+/// Opens the websocket connection to `url` through the SOCKS5 proxy
+/// `socks5_proxy`, which resolves the host of `url` itself, so onion URLs can
+/// be reached through Tor
+pub async fn build_client(
+    client: WsClientBuilder,
+    url: &SafeUrl,
+    socks5_proxy: SocketAddr,
+) -> Result<WsClient, JsonRpcClientError> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| transport_error(format!("URL {url} has no host")))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| transport_error(format!("URL {url} has no port")))?;
+
+    let stream = Socks5Stream::connect(socks5_proxy, (host, port))
+        .await
+        .map_err(|e| transport_error(format!("Failed to connect through SOCKS5 proxy: {e}")))?
+        .into_inner();
+
+    match url.scheme() {
+        "ws" => {
+            client
+                .build_with_stream(url.as_str(), stream.compat())
+                .await
+        }
+        "wss" => {
+            let server_name = ServerName::try_from(host)
+                .map_err(|e| transport_error(format!("Invalid server name {host}: {e}")))?;
+            let stream = tls_connector()
+                .connect(server_name, stream)
+                .await
+                .map_err(|e| transport_error(format!("TLS handshake failed: {e}")))?;
+
+            client
+                .build_with_stream(url.as_str(), stream.compat())
+                .await
+        }
+        scheme => Err(transport_error(format!(
+            "Unsupported websocket scheme {scheme}"
+        ))),
+    }
+}
+
+fn tls_connector() -> TlsConnector {
+    let mut root_store = RootCertStore::empty();
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    TlsConnector::from(Arc::new(config))
+}
+
+fn transport_error(msg: String) -> JsonRpcClientError {
+    JsonRpcClientError::Transport(anyhow::format_err!(msg))
+}
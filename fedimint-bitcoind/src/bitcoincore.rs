@@ -100,6 +100,12 @@ impl IBitcoindRpc for BitcoinClient {
         Ok(height.map(|h| h as u64))
     }
 
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let tx = block_in_place(|| self.0.get_raw_transaction(txid, None))
+            .map_err(|error| info!(?error, "Unable to get raw transaction"));
+        Ok(tx.ok())
+    }
+
     async fn is_tx_in_block(
         &self,
         txid: &Txid,
@@ -109,6 +109,12 @@ impl IBitcoindRpc for ElectrumClient {
         }
     }
 
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        let tx = block_in_place(|| self.0.transaction_get(txid))
+            .map_err(|error| info!(?error, "Unable to get raw transaction"));
+        Ok(tx.ok())
+    }
+
     async fn is_tx_in_block(
         &self,
         txid: &Txid,
@@ -101,6 +101,10 @@ impl IBitcoindRpc for EsploraClient {
             .map(u64::from))
     }
 
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        Ok(self.0.get_tx(txid).await?)
+    }
+
     async fn is_tx_in_block(
         &self,
         txid: &Txid,
@@ -151,6 +151,12 @@ pub trait IBitcoindRpc: Debug {
     /// `watch_script_history` or run bitcoind with txindex enabled.
     async fn get_tx_block_height(&self, txid: &Txid) -> Result<Option<u64>>;
 
+    /// Returns a transaction from the mempool or the blockchain if it is known
+    /// to the backend.
+    /// Note: calling this method with bitcoind as a backend only works for
+    /// wallet transactions unless bitcoind runs with txindex enabled.
+    async fn get_transaction(&self, txid: &Txid) -> Result<Option<Transaction>>;
+
     /// Check if a transaction is included in a block
     async fn is_tx_in_block(
         &self,
@@ -259,6 +265,11 @@ where
             .await
     }
 
+    async fn get_transaction(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        self.retry_call(|| async { self.inner.get_transaction(txid).await })
+            .await
+    }
+
     async fn is_tx_in_block(
         &self,
         txid: &Txid,
@@ -93,9 +93,14 @@ pub enum ClientCmd {
         #[clap(long)]
         include_invite: bool,
     },
-    /// Verifies the signatures of e-cash notes, but *not* if they have been
-    /// spent already
-    Validate { oob_notes: OOBNotes },
+    /// Verifies the signatures of e-cash notes and, if requested, asks the
+    /// federation whether they have been spent already
+    Validate {
+        oob_notes: OOBNotes,
+        /// Also check that none of the notes have been spent already
+        #[clap(long)]
+        check_spent: bool,
+    },
     /// Splits a string containing multiple e-cash notes (e.g. from the `spend`
     /// command) into ones that contain exactly one.
     Split { oob_notes: OOBNotes },
@@ -176,12 +181,17 @@ pub enum ClientCmd {
     #[clap(hide = true)]
     DiscoverVersion,
     /// Restore the previously created backup of mint notes (with `backup`
-    /// command)
+    /// command). Without a backup the funds are recovered by scanning the
+    /// federation history.
     Restore {
         #[clap(long)]
         mnemonic: String,
         #[clap(long)]
         invite_code: String,
+        /// Only scan this many of the most recent sessions if no backup is
+        /// found, all sessions are scanned by default
+        #[clap(long)]
+        scan_depth: Option<u64>,
     },
     /// Print the secret key of the client
     PrintSecret,
@@ -200,6 +210,22 @@ pub enum ClientCmd {
     },
     /// Returns the client config
     Config,
+    /// Inspect and manage the transactions that were queued while the
+    /// federation was unreachable, see `--offline-mode`
+    #[clap(subcommand)]
+    Outbox(OutboxCmd),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum OutboxCmd {
+    /// List the queued transactions
+    List,
+    /// Submit all queued transactions, even if the federation seems
+    /// unreachable
+    Flush,
+    /// Remove the transaction of an operation from the outbox without ever
+    /// submitting it, which releases the funds of its inputs
+    Cancel { operation_id: OperationId },
 }
 
 pub async fn handle_command(
@@ -279,10 +305,16 @@ pub async fn handle_command(
                 "notes": notes,
             }))
         }
-        ClientCmd::Validate { oob_notes } => {
-            let amount = client
-                .get_first_module::<MintClientModule>()
-                .validate_notes(&oob_notes)?;
+        ClientCmd::Validate {
+            oob_notes,
+            check_spent,
+        } => {
+            let mint = client.get_first_module::<MintClientModule>();
+            let amount = if check_spent {
+                mint.check_notes_spendable(&oob_notes).await?
+            } else {
+                mint.validate_notes(&oob_notes)?
+            };
 
             Ok(json!({
                 "amount_msat": amount,
@@ -404,9 +436,13 @@ pub async fn handle_command(
                 fedimint_ln_client::get_invoice(&payment_info, amount, lnurl_comment).await?;
             info!("Paying invoice: {bolt11}");
             let lightning_module = client.get_first_module::<LightningClientModule>();
-            let ln_gateway = lightning_module
-                .get_gateway(gateway_id, force_internal)
-                .await?;
+            let ln_gateway = if lightning_module.is_internal_invoice(&bolt11).await? {
+                None
+            } else {
+                lightning_module
+                    .get_gateway(gateway_id, force_internal)
+                    .await?
+            };
 
             let lightning_module = client.get_first_module::<LightningClientModule>();
             let OutgoingLightningPayment {
@@ -556,7 +592,7 @@ pub async fn handle_command(
                 // the amount we are withdrawing
                 BitcoinAmountOrAll::All => {
                     let balance =
-                        bitcoin::Amount::from_sat(client.get_balance().await.msats / 1000);
+                        bitcoin::Amount::from_sat(client.get_balance().await.sats_round_down());
                     let fees = wallet_module
                         .get_withdraw_fees(address.clone(), balance)
                         .await?;
@@ -584,6 +620,9 @@ pub async fn handle_command(
                 .await?
                 .into_stream();
 
+            // Only known if the federation paid the withdrawal in a batch
+            let mut batch_vout = None;
+
             while let Some(update) = updates.next().await {
                 debug!(target: LOG_CLIENT, ?update, "Withdraw state update");
 
@@ -592,8 +631,12 @@ pub async fn handle_command(
                         return Ok(json!({
                             "txid": txid.consensus_encode_to_hex(),
                             "fees_sat": absolute_fees.to_sat(),
+                            "batch_vout": batch_vout,
                         }));
                     }
+                    WithdrawState::Batched(outpoint) => {
+                        batch_vout = Some(outpoint.vout);
+                    }
                     WithdrawState::Failed(e) => {
                         bail!("Withdraw failed: {e}");
                     }
@@ -644,6 +687,16 @@ pub async fn handle_command(
             let config = client.get_config_json();
             Ok(serde_json::to_value(config).expect("Client config is serializable"))
         }
+        ClientCmd::Outbox(OutboxCmd::List) => Ok(json!({
+            "outbox": client.outbox().await,
+        })),
+        ClientCmd::Outbox(OutboxCmd::Flush) => Ok(json!({
+            "submitted": client.flush_outbox().await?,
+        })),
+        ClientCmd::Outbox(OutboxCmd::Cancel { operation_id }) => {
+            client.cancel_outbox_transaction(operation_id).await?;
+            Ok(serde_json::Value::Null)
+        }
     }
 }
 
@@ -29,19 +29,23 @@ use clap::{Args, CommandFactory, Parser, Subcommand};
 use db_locked::LockedBuilder;
 use envs::FM_API_SECRET_ENV;
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
+use fedimint_api_client::admin::GuardianAdminApi;
 use fedimint_api_client::api::{
-    DynGlobalApi, FederationApiExt, FederationError, IRawFederationApi, WsFederationApi,
+    DynGlobalApi, FederationApiExt, FederationError, IRawFederationApi,
 };
 use fedimint_bip39::Bip39RootSecretStrategy;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitRegistry};
 use fedimint_client::module::ClientModule as _;
 use fedimint_client::secret::{get_default_client_secret, RootSecretStrategy};
 use fedimint_client::{AdminCreds, Client, ClientBuilder, ClientHandleArc};
-use fedimint_core::admin_client::{ConfigGenConnectionsRequest, ConfigGenParamsRequest};
+use fedimint_core::admin_client::{
+    AdminRole, ConfigGenConnectionsRequest, ConfigGenParamsRequest, ModuleAdditionRequest,
+};
 use fedimint_core::config::{
-    ClientConfig, FederationId, FederationIdPrefix, ServerModuleConfigGenParamsRegistry,
+    ClientConfig, ConfigGenModuleParams, FederationId, FederationIdPrefix,
+    ServerModuleConfigGenParamsRegistry,
 };
-use fedimint_core::core::{ModuleInstanceId, OperationId};
+use fedimint_core::core::{ModuleInstanceId, ModuleKind, OperationId};
 use fedimint_core::db::{Database, DatabaseValue};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
@@ -55,6 +59,7 @@ use fedimint_server::config::io::SALT_FILE;
 use fedimint_wallet_client::api::WalletFederationApi;
 use fedimint_wallet_client::{WalletClientInit, WalletClientModule};
 use futures::future::pending;
+use futures::StreamExt;
 use itertools::Itertools;
 use rand::thread_rng;
 use serde::{Deserialize, Serialize};
@@ -206,7 +211,7 @@ struct Opts {
     #[arg(env = FM_OUR_ID_ENV, long, value_parser = parse_peer_id)]
     our_id: Option<PeerId>,
 
-    /// Guardian password for authentication
+    /// Guardian password or API token for authentication
     #[arg(long, env = FM_PASSWORD_ENV)]
     password: Option<String>,
 
@@ -215,6 +220,11 @@ struct Opts {
     #[arg(short = 'v', long)]
     verbose: bool,
 
+    /// Queue transactions in the outbox of the client instead of submitting
+    /// them if the federation is unreachable
+    #[arg(long)]
+    offline_mode: bool,
+
     #[clap(subcommand)]
     command: Command,
 }
@@ -244,6 +254,20 @@ impl Opts {
         Ok(DynGlobalApi::from_config_admin(cfg, api_secret, our_id))
     }
 
+    fn guardian_admin_client(
+        &self,
+        cfg: &ClientConfig,
+        api_secret: &Option<String>,
+    ) -> CliResult<GuardianAdminApi> {
+        let our_id = self.our_id.ok_or_cli_msg("Admin client needs our-id set")?;
+        Ok(GuardianAdminApi::from_config(
+            cfg,
+            api_secret,
+            our_id,
+            self.auth()?,
+        ))
+    }
+
     fn auth(&self) -> CliResult<ApiAuth> {
         let password = self
             .password
@@ -322,9 +346,98 @@ enum AdminCmd {
     /// Download guardian config to back it up
     GuardianConfigBackup,
 
+    /// Download an encrypted bundle to restore a lost guardian from with
+    /// `fedimintd restore --bundle`
+    GuardianDisasterRecoveryBundle,
+
+    /// Download a snapshot of the guardian's consensus state to start a new or
+    /// rebuilt guardian from with `fedimintd --import-checkpoint`
+    ConsensusCheckpoint,
+
+    /// Shut down the guardian after the given session, or immediately if
+    /// none is given
+    Shutdown {
+        #[arg(long)]
+        session: Option<u64>,
+    },
+
+    /// Put a module instance into maintenance mode, rejecting new transactions
+    /// that use it, or take it out of maintenance mode again
+    ModuleMaintenance {
+        module_instance_id: ModuleInstanceId,
+        #[arg(long)]
+        disable: bool,
+    },
+
+    /// Show or set how many finished sessions the guardian keeps, older
+    /// sessions are pruned from its database
+    SessionRetention {
+        /// Keep this many of the most recent sessions
+        #[arg(long)]
+        retained_sessions: Option<u64>,
+        /// Keep all sessions, disabling pruning
+        #[arg(long, conflicts_with = "retained_sessions")]
+        keep_all: bool,
+    },
+
+    /// Vote for adding a module instance to the running federation. Every
+    /// guardian has to propose the same kind, consensus params and activation
+    /// session for the module to be added.
+    ProposeModuleAddition {
+        module_instance_id: ModuleInstanceId,
+        kind: String,
+        /// First session in which the module is active
+        #[arg(long)]
+        activation_session: u64,
+        /// Consensus config gen params of the module as JSON
+        #[arg(long, default_value = "null")]
+        consensus_params: Value,
+        /// Local config gen params of the module as JSON
+        #[arg(long, default_value = "null")]
+        local_params: Value,
+    },
+
+    /// Show the votes and approved proposals for adding module instances
+    ModuleAdditions,
+
+    /// Vote for halting consensus at a session until the guardians run a new
+    /// version of fedimintd. Every guardian has to schedule the same version
+    /// and halt session for the upgrade to be scheduled.
+    ScheduleUpgrade {
+        /// Code version of the fedimintd that runs the halt session onwards
+        version: String,
+        /// First session that is only run by the new version
+        #[arg(long)]
+        halt_session: u64,
+    },
+
+    /// Show the votes for and the scheduled coordinated upgrade
+    UpgradeStatus,
+
+    /// Manage the API tokens of other admins of the guardian, requires the
+    /// guardian password
+    #[clap(subcommand)]
+    ApiToken(ApiTokenCmd),
+
     Dkg(DkgAdminArgs),
 }
 
+#[derive(Debug, Clone, Subcommand)]
+enum ApiTokenCmd {
+    /// Issue a token, which can be used instead of the password for
+    /// endpoints permitted by its role
+    Issue {
+        name: String,
+        /// One of `read_only`, `config_change` or `dangerous`
+        #[arg(long)]
+        role: AdminRole,
+    },
+    /// Revoke the token named `name`
+    Revoke { name: String },
+    /// List the issued tokens
+    List,
+}
+
 #[derive(Debug, Clone, Args)]
 struct DkgAdminArgs {
     #[arg(long, env = "FM_WS_URL")]
@@ -465,6 +578,10 @@ Examples:
     /// Gets the current fedimint AlephBFT block count
     SessionCount,
 
+    /// Show latency, error and version statistics of the guardians as observed
+    /// by the client
+    ApiHealth,
+
     ConfigDecrypt {
         /// Encrypted config file
         #[arg(long = "in-file")]
@@ -575,6 +692,9 @@ impl FedimintCli {
         let mut client_builder = Client::builder(db);
         client_builder.with_module_inits(self.module_inits.clone());
         client_builder.with_primary_module(1);
+        if cli.offline_mode {
+            client_builder.with_offline_mode();
+        }
 
         Ok(client_builder)
     }
@@ -642,6 +762,7 @@ impl FedimintCli {
         cli: &Opts,
         mnemonic: Mnemonic,
         invite_code: InviteCode,
+        scan_depth: Option<u64>,
     ) -> CliResult<ClientHandleArc> {
         let builder = self.make_client_builder(cli).await?;
 
@@ -676,16 +797,31 @@ impl FedimintCli {
             .download_backup_from_federation(&root_secret, &client_config, invite_code.api_secret())
             .await
             .map_err_cli()?;
-        builder
-            .recover(
-                root_secret,
-                client_config.clone(),
-                invite_code.api_secret(),
-                backup,
-            )
-            .await
-            .map(Arc::new)
-            .map_err_cli()
+        match backup {
+            Some(backup) => {
+                builder
+                    .recover(
+                        root_secret,
+                        client_config.clone(),
+                        invite_code.api_secret(),
+                        Some(backup),
+                    )
+                    .await
+            }
+            None => {
+                info!("No backup found, scanning the federation history");
+                builder
+                    .recover_without_backup(
+                        root_secret,
+                        client_config.clone(),
+                        invite_code.api_secret(),
+                        scan_depth,
+                    )
+                    .await
+            }
+        }
+        .map(Arc::new)
+        .map_err_cli()
     }
 
     async fn handle_command(&mut self, cli: Opts) -> CliOutputResult {
@@ -722,19 +858,35 @@ impl FedimintCli {
             Command::Client(ClientCmd::Restore {
                 mnemonic,
                 invite_code,
+                scan_depth,
             }) => {
                 let invite_code: InviteCode =
                     InviteCode::from_str(&invite_code).map_err_cli_msg("invalid invite code")?;
                 let mnemonic = Mnemonic::from_str(&mnemonic).map_err_cli()?;
-                let client = self.client_recover(&cli, mnemonic, invite_code).await?;
+                let client = self
+                    .client_recover(&cli, mnemonic, invite_code, scan_depth)
+                    .await?;
+
+                let mut progress = client.subscribe_to_recovery_progress();
+                let log_progress = async {
+                    while let Some((module_instance_id, progress)) = progress.next().await {
+                        info!(
+                            module_instance_id,
+                            complete = progress.complete,
+                            total = progress.total,
+                            "Recovery progress"
+                        );
+                    }
+                    pending::<()>().await;
+                };
 
                 // TODO: until we implement recovery for other modules we can't really wait
                 // for more than this one
                 debug!("Waiting for mint module recovery to finish");
-                client
-                    .wait_for_module_kind_recovery(MintClientModule::kind())
-                    .await
-                    .map_err_cli()?;
+                tokio::select! {
+                    res = client.wait_for_module_kind_recovery(MintClientModule::kind()) => res.map_err_cli()?,
+                    () = log_progress => {}
+                }
 
                 debug!("Recovery complete");
 
@@ -782,6 +934,139 @@ impl FedimintCli {
                         .map_err_cli_msg("invalid response")?,
                 ))
             }
+            Command::Admin(AdminCmd::GuardianDisasterRecoveryBundle) => {
+                let client = self.client_open(&cli).await?;
+
+                let bundle = cli
+                    .guardian_admin_client(client.get_config(), client.api_secret())?
+                    .guardian_disaster_recovery_bundle()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(bundle).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::ConsensusCheckpoint) => {
+                let client = self.client_open(&cli).await?;
+
+                let checkpoint = cli
+                    .guardian_admin_client(client.get_config(), client.api_secret())?
+                    .consensus_checkpoint()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(checkpoint).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::Shutdown { session }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.guardian_admin_client(client.get_config(), client.api_secret())?
+                    .shutdown(session)
+                    .await?;
+                Ok(CliOutput::Raw(Value::Null))
+            }
+            Command::Admin(AdminCmd::ModuleMaintenance {
+                module_instance_id,
+                disable,
+            }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.guardian_admin_client(client.get_config(), client.api_secret())?
+                    .set_module_maintenance(module_instance_id, !disable)
+                    .await?;
+                Ok(CliOutput::Raw(Value::Null))
+            }
+            Command::Admin(AdminCmd::SessionRetention {
+                retained_sessions,
+                keep_all,
+            }) => {
+                let client = self.client_open(&cli).await?;
+                let admin_client =
+                    cli.guardian_admin_client(client.get_config(), client.api_secret())?;
+
+                if retained_sessions.is_some() || keep_all {
+                    admin_client
+                        .set_session_retention(retained_sessions)
+                        .await?;
+                }
+
+                let retention = admin_client.session_retention().await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(retention).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::ProposeModuleAddition {
+                module_instance_id,
+                kind,
+                activation_session,
+                consensus_params,
+                local_params,
+            }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.guardian_admin_client(client.get_config(), client.api_secret())?
+                    .propose_module_addition(ModuleAdditionRequest {
+                        module_instance_id,
+                        kind: ModuleKind::clone_from_str(&kind),
+                        params: ConfigGenModuleParams::new(local_params, consensus_params),
+                        activation_session,
+                    })
+                    .await?;
+                Ok(CliOutput::Raw(Value::Null))
+            }
+            Command::Admin(AdminCmd::ModuleAdditions) => {
+                let client = self.client_open(&cli).await?;
+
+                let status = cli
+                    .guardian_admin_client(client.get_config(), client.api_secret())?
+                    .module_additions()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(status).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::ScheduleUpgrade {
+                version,
+                halt_session,
+            }) => {
+                let client = self.client_open(&cli).await?;
+
+                cli.guardian_admin_client(client.get_config(), client.api_secret())?
+                    .schedule_upgrade(version, halt_session)
+                    .await?;
+                Ok(CliOutput::Raw(Value::Null))
+            }
+            Command::Admin(AdminCmd::UpgradeStatus) => {
+                let client = self.client_open(&cli).await?;
+
+                let status = cli
+                    .guardian_admin_client(client.get_config(), client.api_secret())?
+                    .upgrade_status()
+                    .await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(status).map_err_cli_msg("invalid response")?,
+                ))
+            }
+            Command::Admin(AdminCmd::ApiToken(api_token_cmd)) => {
+                let client = self.client_open(&cli).await?;
+                let admin_client =
+                    cli.guardian_admin_client(client.get_config(), client.api_secret())?;
+
+                let output = match api_token_cmd {
+                    ApiTokenCmd::Issue { name, role } => {
+                        let token = admin_client.issue_api_token(name, role).await?;
+                        json!({ "token": token.0 })
+                    }
+                    ApiTokenCmd::Revoke { name } => {
+                        admin_client.revoke_api_token(name).await?;
+                        Value::Null
+                    }
+                    ApiTokenCmd::List => {
+                        serde_json::to_value(admin_client.list_api_tokens().await?)
+                            .map_err_cli_msg("invalid response")?
+                    }
+                };
+                Ok(CliOutput::Raw(output))
+            }
             Command::Admin(AdminCmd::Dkg(dkg_args)) => {
                 self.handle_admin_dkg_command(cli, dkg_args).await
             }
@@ -808,14 +1093,14 @@ impl FedimintCli {
                 }
                 let client = self.client_open(&cli).await?;
 
-                let ws_api: Arc<_> =
-                    WsFederationApi::from_config(client.get_config(), client.api_secret()).into();
+                // Use the api of the client, so the request passes its interceptors
+                let api = client.api();
                 let response: Value = match peer_id {
-                    Some(peer_id) => ws_api
+                    Some(peer_id) => api
                         .request_raw(peer_id.into(), &method, &[params.to_json()])
                         .await
                         .map_err_cli()?,
-                    None => ws_api
+                    None => api
                         .request_current_consensus(method, params)
                         .await
                         .map_err_cli()?,
@@ -914,6 +1199,12 @@ impl FedimintCli {
                 let count = client.api().session_count().await?;
                 Ok(CliOutput::EpochCount { count })
             }
+            Command::Dev(DevCmd::ApiHealth) => {
+                let client = self.client_open(&cli).await?;
+                Ok(CliOutput::Raw(
+                    serde_json::to_value(client.api_health()).map_err_cli()?,
+                ))
+            }
             Command::Dev(DevCmd::ConfigDecrypt {
                 in_file,
                 out_file,
@@ -0,0 +1,157 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fedimint_client::sm::{
+    Context, DynContext, DynState, Executor, Notifier, State, StateTransition,
+};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, ModuleKind, OperationId};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::TaskGroup;
+use futures::future::join_all;
+
+const MODULE_INSTANCE: ModuleInstanceId = 0;
+const STEPS: u8 = 5;
+/// Time a transition spends waiting, like a gateway's transitions waiting for
+/// the database or the lightning node
+const TRANSITION_LATENCY: Duration = Duration::from_millis(1);
+
+/// Counts down to zero, one transition per step
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+struct CountdownStateMachine {
+    operation_id: OperationId,
+    remaining: u8,
+}
+
+impl State for CountdownStateMachine {
+    type ModuleContext = BenchContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        _global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        if self.remaining == 0 {
+            return vec![];
+        }
+
+        let next = CountdownStateMachine {
+            operation_id: self.operation_id,
+            remaining: self.remaining - 1,
+        };
+        vec![StateTransition::new(async {}, move |_dbtx, (), _state| {
+            let next = next.clone();
+            Box::pin(async move {
+                tokio::time::sleep(TRANSITION_LATENCY).await;
+                next
+            })
+        })]
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.operation_id
+    }
+}
+
+impl IntoDynInstance for CountdownStateMachine {
+    type DynType = DynState;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynState::from_typed(instance_id, self)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct BenchContext;
+
+impl Context for BenchContext {}
+
+impl IntoDynInstance for BenchContext {
+    type DynType = DynContext;
+
+    fn into_dyn(self, instance_id: ModuleInstanceId) -> Self::DynType {
+        DynContext::from_typed(instance_id, self)
+    }
+}
+
+/// Runs `operations` state machines with [`STEPS`] transitions each to
+/// completion on a fresh executor
+async fn run_state_machines(operations: usize) {
+    let mut decoder_builder = Decoder::builder();
+    decoder_builder.with_decodable_type::<CountdownStateMachine>();
+    let decoders = ModuleDecoderRegistry::new(vec![(
+        MODULE_INSTANCE,
+        ModuleKind::from_static_str("bench"),
+        decoder_builder.build(),
+    )]);
+    let db = Database::new(MemDatabase::new(), decoders);
+    let task_group = TaskGroup::new();
+
+    let mut executor_builder = Executor::builder();
+    executor_builder.with_module(MODULE_INSTANCE, BenchContext);
+    let executor = executor_builder.build(db.clone(), Notifier::new(db), task_group.clone());
+    executor
+        .start_executor(Arc::new(|_, _| DynGlobalClientContext::new_fake()))
+        .await;
+
+    let operation_ids = (0..operations)
+        .map(|_| OperationId::new_random())
+        .collect::<Vec<_>>();
+    executor
+        .add_state_machines(
+            operation_ids
+                .iter()
+                .map(|operation_id| {
+                    DynState::from_typed(
+                        MODULE_INSTANCE,
+                        CountdownStateMachine {
+                            operation_id: *operation_id,
+                            remaining: STEPS,
+                        },
+                    )
+                })
+                .collect(),
+        )
+        .await
+        .expect("Failed to add state machines");
+
+    join_all(operation_ids.into_iter().map(|operation_id| {
+        executor.await_inactive_state(DynState::from_typed(
+            MODULE_INSTANCE,
+            CountdownStateMachine {
+                operation_id,
+                remaining: 0,
+            },
+        ))
+    }))
+    .await;
+
+    task_group
+        .shutdown_join_all(Duration::from_secs(10))
+        .await
+        .expect("Failed to shut down executor");
+}
+
+/// Measures how long the executor takes to drive hundreds of active state
+/// machines to completion, like a gateway handling many payments at once
+fn bench_executor(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to build runtime");
+
+    let mut group = c.benchmark_group("State machine executor");
+    group.sample_size(10);
+
+    for operations in [10, 100, 500] {
+        group.bench_with_input(
+            BenchmarkId::new("operations", operations),
+            &operations,
+            |b, operations| b.iter(|| runtime.block_on(run_state_machines(*operations))),
+        );
+    }
+}
+
+criterion_group!(benches, bench_executor);
+criterion_main!(benches);
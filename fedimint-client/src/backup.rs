@@ -1,24 +1,28 @@
 use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 use std::io::{Cursor, Error, Read, Write};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Context, Result};
 use fedimint_api_client::api::DynGlobalApi;
 use fedimint_core::core::backup::{
     BackupRequest, SignedBackupRequest, BACKUP_REQUEST_MAX_PAYLOAD_SIZE_BYTES,
 };
-use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::core::{ModuleInstanceId, OperationId};
 use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::sleep;
+use fedimint_core::Amount;
 use fedimint_derive_secret::DerivableSecret;
 use fedimint_logging::{LOG_CLIENT, LOG_CLIENT_BACKUP, LOG_CLIENT_RECOVERY};
+use futures::StreamExt;
 use secp256k1_zkp::{KeyPair, Secp256k1};
 use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use super::Client;
-use crate::db::LastBackupKey;
+use crate::db::{LastBackupKey, LastBackupUploadKey, OperationLabelKeyPrefix};
 use crate::get_decoded_client_secret;
 use crate::module::recovery::DynModuleBackup;
 use crate::secret::DeriveableSecretClientExt;
@@ -82,6 +86,8 @@ pub struct ClientBackup {
     // TODO: remove redundant ModuleInstanceId
     /// Module specific-backup (if supported)
     pub modules: BTreeMap<ModuleInstanceId, DynModuleBackup>,
+    /// User-visible labels of operations, see [`Client::set_operation_label`]
+    pub labels: BTreeMap<OperationId, String>,
 }
 
 impl ClientBackup {
@@ -161,6 +167,7 @@ impl ClientBackup {
             session_count: self.session_count,
             metadata: self.metadata,
             modules,
+            labels: self.labels,
         }
     }
 }
@@ -172,15 +179,26 @@ impl Encodable for ClientBackup {
         len += self.metadata.consensus_encode(writer)?;
         len += self.modules.consensus_encode(writer)?;
 
+        // Labels are appended after the padding and omitted if there are none, so
+        // backups created before labels existed still decode
+        let labels = if self.labels.is_empty() {
+            vec![]
+        } else {
+            self.labels.consensus_encode_to_vec()
+        };
+
         // FIXME: this still leaks some information about the backup size if the padding
         // is so short that its length is encoded as 1 byte instead of 3.
-        let estimated_len = len + 3;
+        let estimated_len = len + labels.len() + 3;
 
         // Hide small changes in backup size for privacy
         let alignment_size = Self::get_alignment_size(estimated_len); // +3 for most likely padding len len
         let padding = vec![0u8; alignment_size - estimated_len];
         len += padding.consensus_encode(writer)?;
 
+        writer.write_all(&labels)?;
+        len += labels.len();
+
         Ok(len)
     }
 }
@@ -197,10 +215,21 @@ impl Decodable for ClientBackup {
                 .context("module_backups")?;
         let _padding = Vec::<u8>::consensus_decode(r, modules).context("padding")?;
 
+        let mut remaining = vec![];
+        r.read_to_end(&mut remaining)
+            .map_err(|e| DecodeError::new_custom(e.into()))?;
+        let labels = if remaining.is_empty() {
+            BTreeMap::new()
+        } else {
+            BTreeMap::<OperationId, String>::consensus_decode(&mut Cursor::new(remaining), modules)
+                .context("labels")?
+        };
+
         Ok(Self {
             session_count,
             metadata,
             modules: module_backups,
+            labels,
         })
     }
 }
@@ -242,6 +271,53 @@ impl EncryptedClientBackup {
     }
 }
 
+/// Settings of the automatic backups enabled with
+/// [`crate::ClientBuilder::with_auto_backup`]
+///
+/// A backup is made once the balance increased by
+/// [`Self::balance_increase_threshold`] since the last backup or a module
+/// requested one with [`crate::module::ClientContext::request_backup`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoBackupConfig {
+    /// Back up once the balance increased by at least this amount, e.g.
+    /// because new notes were received
+    pub balance_increase_threshold: Amount,
+    /// Time to wait after a change before backing up, further changes restart
+    /// the wait so that a burst of changes results in a single backup
+    pub debounce: Duration,
+    /// Minimum time between two backups
+    pub min_interval: Duration,
+}
+
+impl Default for AutoBackupConfig {
+    fn default() -> Self {
+        Self {
+            balance_increase_threshold: Amount::from_sats(1000),
+            debounce: Duration::from_secs(10),
+            min_interval: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Backup that was last uploaded to the federation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct LastBackupUpload {
+    pub time: SystemTime,
+    /// Session count of the federation when the backup was created
+    pub session_count: u64,
+    /// Size of the encrypted backup in bytes
+    pub size: u64,
+}
+
+/// See [`Client::backup_status`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupStatus {
+    /// `None` if no backup was uploaded yet
+    pub last_upload: Option<LastBackupUpload>,
+    /// `None` if automatic backups are disabled
+    pub auto_backup: Option<AutoBackupConfig>,
+}
+
 impl Client {
     /// Create a backup, include provided `metadata`
     pub async fn create_backup(&self, metadata: Metadata) -> anyhow::Result<ClientBackup> {
@@ -259,10 +335,21 @@ impl Client {
             }
         }
 
+        let labels = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&OperationLabelKeyPrefix)
+            .await
+            .map(|(key, label)| (key.operation_id, label))
+            .collect()
+            .await;
+
         Ok(ClientBackup {
             session_count,
             metadata,
             modules,
+            labels,
         })
     }
 
@@ -279,6 +366,9 @@ impl Client {
 
     /// Prepare an encrypted backup and send it to federation for storing
     pub async fn backup_to_federation(&self, metadata: Metadata) -> Result<()> {
+        // The backup would be encrypted to a key derived from the throwaway secret
+        self.ensure_not_watch_only()?;
+
         let last_backup = self.load_previous_backup().await;
         let new_backup = self.create_backup(metadata).await?;
 
@@ -292,9 +382,112 @@ impl Client {
 
         self.upload_backup(&encrypted).await?;
 
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &LastBackupUploadKey,
+            &LastBackupUpload {
+                time: fedimint_core::time::now(),
+                session_count: new_backup.session_count,
+                size: encrypted.len() as u64,
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
         Ok(())
     }
 
+    /// Returns when the last backup was uploaded and whether automatic
+    /// backups are enabled
+    pub async fn backup_status(&self) -> BackupStatus {
+        BackupStatus {
+            last_upload: self
+                .db
+                .begin_transaction_nc()
+                .await
+                .get_value(&LastBackupUploadKey)
+                .await,
+            auto_backup: self.auto_backup.clone(),
+        }
+    }
+
+    /// Asks for an automatic backup, which is debounced and rate limited like
+    /// backups triggered by balance changes. Does nothing if automatic backups
+    /// are disabled.
+    pub fn request_backup(&self) {
+        if self.auto_backup.is_some() {
+            self.auto_backup_notify.notify_one();
+        }
+    }
+
+    /// Backs up the client whenever [`AutoBackupConfig`] says so, keeping the
+    /// metadata of the previous backup
+    pub(crate) async fn auto_backup_continuously(&self, config: AutoBackupConfig) {
+        if self.is_watch_only() {
+            return;
+        }
+
+        let mut balance_changes = self.subscribe_balance_changes().await;
+        let Some(mut prev_balance) = balance_changes.next().await else {
+            return;
+        };
+        let mut received = Amount::ZERO;
+        let mut due = false;
+
+        loop {
+            while !due {
+                tokio::select! {
+                    balance = balance_changes.next() => {
+                        let Some(balance) = balance else {
+                            return;
+                        };
+                        received += balance.saturating_sub(prev_balance);
+                        prev_balance = balance;
+                        due = config.balance_increase_threshold <= received;
+                    }
+                    () = self.auto_backup_notify.notified() => due = true,
+                }
+            }
+
+            loop {
+                tokio::select! {
+                    balance = balance_changes.next() => {
+                        let Some(balance) = balance else {
+                            return;
+                        };
+                        prev_balance = balance;
+                    }
+                    () = self.auto_backup_notify.notified() => {}
+                    () = sleep(config.debounce) => break,
+                }
+            }
+
+            if let Some(last_upload) = self.backup_status().await.last_upload {
+                let elapsed = fedimint_core::time::now()
+                    .duration_since(last_upload.time)
+                    .unwrap_or_default();
+                sleep(config.min_interval.saturating_sub(elapsed)).await;
+            }
+
+            let metadata = self
+                .load_previous_backup()
+                .await
+                .map_or_else(Metadata::empty, |backup| backup.metadata);
+
+            match self.backup_to_federation(metadata).await {
+                Ok(()) => {
+                    info!(target: LOG_CLIENT_BACKUP, "Automatic backup uploaded");
+                    received = Amount::ZERO;
+                    due = false;
+                }
+                Err(e) => {
+                    warn!(target: LOG_CLIENT_BACKUP, %e, "Automatic backup failed, retrying");
+                    sleep(config.min_interval).await;
+                }
+            }
+        }
+    }
+
     /// Validate backup before sending it to federation
     pub fn validate_backup(&self, backup: &EncryptedClientBackup) -> Result<()> {
         if BACKUP_REQUEST_MAX_PAYLOAD_SIZE_BYTES < backup.len() {
@@ -1,6 +1,7 @@
 use std::io::Cursor;
 
 use anyhow::Result;
+use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_derive_secret::DerivableSecret;
 
@@ -29,6 +30,7 @@ fn sanity_ecash_backup_decode_encode() -> Result<()> {
         session_count: 0,
         metadata: Metadata::from_raw(vec![1, 2, 3]),
         modules: Default::default(),
+        labels: Default::default(),
     };
 
     let encoded = orig.consensus_encode_to_vec();
@@ -47,6 +49,7 @@ fn sanity_ecash_backup_encrypt_decrypt() -> Result<()> {
         modules: Default::default(),
         session_count: 1,
         metadata: Metadata::from_raw(vec![1, 2, 3]),
+        labels: Default::default(),
     };
 
     let secret = DerivableSecret::new_root(&[1; 32], &[1, 32]);
@@ -60,3 +63,22 @@ fn sanity_ecash_backup_encrypt_decrypt() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn sanity_ecash_backup_labels_decode_encode() -> Result<()> {
+    let orig = ClientBackup {
+        session_count: 0,
+        metadata: Metadata::from_raw(vec![1, 2, 3]),
+        modules: Default::default(),
+        labels: [(OperationId([1; 32]), "rent".to_string())].into(),
+    };
+
+    let encoded = orig.consensus_encode_to_vec();
+    assert_eq!(encoded.len(), ClientBackup::PADDING_ALIGNMENT);
+    assert_eq!(
+        orig,
+        ClientBackup::consensus_decode(&mut Cursor::new(encoded), &Default::default())?
+    );
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::secp256k1::{schnorr, KeyPair, Message, PublicKey, SECP256K1};
+use fedimint_core::{NumPeersExt, PeerId};
+use thiserror::Error;
+
+/// Checks a [`ClientConfig`] that was distributed out-of-band before joining
+/// with it, see [`crate::ClientBuilder::join_with_config`]
+///
+/// Without any checks the config is trusted on first use: the application
+/// should remember the federation id of the client it joined and pin it with
+/// [`Self::with_pinned_federation_id`] whenever it joins with a config again,
+/// e.g. after losing the client database.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigVerification {
+    pinned_federation_id: Option<FederationId>,
+    guardian_signatures: Option<GuardianConfigSignatures>,
+}
+
+/// Signatures of guardians over a client config, checked against keys of the
+/// guardians the application knows independently of the config
+#[derive(Debug, Clone)]
+struct GuardianConfigSignatures {
+    guardian_keys: BTreeMap<PeerId, PublicKey>,
+    signatures: BTreeMap<PeerId, schnorr::Signature>,
+}
+
+/// Reason a config was rejected by [`ConfigVerification::verify`]
+#[derive(Debug, Clone, Error)]
+pub enum ConfigVerificationError {
+    #[error("Config belongs to federation {actual} instead of the pinned federation {pinned}")]
+    FederationIdMismatch {
+        pinned: FederationId,
+        actual: FederationId,
+    },
+    #[error("Guardians of the config don't match the known guardian keys")]
+    GuardianMismatch,
+    #[error(
+        "Only {valid} of the {threshold} required guardian signatures of the config are valid"
+    )]
+    InsufficientSignatures { valid: usize, threshold: usize },
+}
+
+impl ConfigVerification {
+    /// Accepts any config, see [`ConfigVerification`]
+    pub fn trust_on_first_use() -> Self {
+        Self::default()
+    }
+
+    /// Only accepts the config of the federation with id `federation_id`
+    pub fn with_pinned_federation_id(mut self, federation_id: FederationId) -> Self {
+        self.pinned_federation_id = Some(federation_id);
+        self
+    }
+
+    /// Only accepts a config signed by a threshold of the guardians with the
+    /// given keys, see [`sign_client_config`]. The config has to list exactly
+    /// these guardians.
+    pub fn with_guardian_signatures(
+        mut self,
+        guardian_keys: BTreeMap<PeerId, PublicKey>,
+        signatures: BTreeMap<PeerId, schnorr::Signature>,
+    ) -> Self {
+        self.guardian_signatures = Some(GuardianConfigSignatures {
+            guardian_keys,
+            signatures,
+        });
+        self
+    }
+
+    pub fn verify(&self, config: &ClientConfig) -> Result<(), ConfigVerificationError> {
+        if let Some(pinned) = self.pinned_federation_id {
+            let actual = config.calculate_federation_id();
+
+            if actual != pinned {
+                return Err(ConfigVerificationError::FederationIdMismatch { pinned, actual });
+            }
+        }
+
+        if let Some(guardian_signatures) = &self.guardian_signatures {
+            guardian_signatures.verify(config)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GuardianConfigSignatures {
+    fn verify(&self, config: &ClientConfig) -> Result<(), ConfigVerificationError> {
+        let config_guardians = config
+            .global
+            .api_endpoints
+            .keys()
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        if self.guardian_keys.keys().copied().collect::<BTreeSet<_>>() != config_guardians {
+            return Err(ConfigVerificationError::GuardianMismatch);
+        }
+
+        let message = config_signing_message(config);
+
+        let valid = self
+            .signatures
+            .iter()
+            .filter(|(peer, signature)| {
+                self.guardian_keys.get(peer).is_some_and(|key| {
+                    SECP256K1
+                        .verify_schnorr(signature, &message, &key.x_only_public_key().0)
+                        .is_ok()
+                })
+            })
+            .count();
+
+        let threshold = config_guardians.threshold();
+
+        if valid < threshold {
+            return Err(ConfigVerificationError::InsufficientSignatures { valid, threshold });
+        }
+
+        Ok(())
+    }
+}
+
+/// Signs `config` with the key of a guardian, so clients that know the keys of
+/// the guardians can join with a config distributed by an untrusted party
+pub fn sign_client_config(config: &ClientConfig, keypair: &KeyPair) -> schnorr::Signature {
+    SECP256K1.sign_schnorr(&config_signing_message(config), keypair)
+}
+
+fn config_signing_message(config: &ClientConfig) -> Message {
+    Message::from_slice(config.consensus_hash().as_ref()).expect("Hashes are 32 bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::config::{ClientConfig, GlobalClientConfig, PeerUrl};
+    use fedimint_core::module::CoreConsensusVersion;
+    use fedimint_core::secp256k1::{KeyPair, SECP256K1};
+    use fedimint_core::PeerId;
+    use rand::rngs::OsRng;
+
+    use super::{sign_client_config, ConfigVerification, ConfigVerificationError};
+
+    fn client_config(num_peers: u16) -> ClientConfig {
+        ClientConfig {
+            global: GlobalClientConfig {
+                api_endpoints: (0..num_peers)
+                    .map(|peer| {
+                        (
+                            PeerId::from(peer),
+                            PeerUrl {
+                                url: format!("wss://guardian-{peer}.example.com")
+                                    .parse()
+                                    .expect("valid url"),
+                                name: format!("guardian-{peer}"),
+                            },
+                        )
+                    })
+                    .collect(),
+                consensus_version: CoreConsensusVersion::new(2, 0),
+                meta: BTreeMap::new(),
+            },
+            modules: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn pinned_federation_id_is_enforced() {
+        let config = client_config(4);
+        let other_config = client_config(1);
+
+        ConfigVerification::trust_on_first_use()
+            .verify(&config)
+            .expect("Any config is trusted on first use");
+
+        let pinned = ConfigVerification::trust_on_first_use()
+            .with_pinned_federation_id(config.calculate_federation_id());
+
+        pinned.verify(&config).expect("Pinned federation");
+        assert!(matches!(
+            pinned.verify(&other_config),
+            Err(ConfigVerificationError::FederationIdMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn threshold_of_guardian_signatures_is_required() {
+        let config = client_config(4);
+        let keys = (0..4)
+            .map(|peer| (PeerId::from(peer), KeyPair::new(SECP256K1, &mut OsRng)))
+            .collect::<BTreeMap<_, _>>();
+        let guardian_keys = keys
+            .iter()
+            .map(|(peer, keypair)| (*peer, keypair.public_key()))
+            .collect::<BTreeMap<_, _>>();
+
+        let signatures = |signers: u16| {
+            keys.iter()
+                .take(signers.into())
+                .map(|(peer, keypair)| (*peer, sign_client_config(&config, keypair)))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        ConfigVerification::trust_on_first_use()
+            .with_guardian_signatures(guardian_keys.clone(), signatures(3))
+            .verify(&config)
+            .expect("Threshold of guardians signed");
+
+        assert!(matches!(
+            ConfigVerification::trust_on_first_use()
+                .with_guardian_signatures(guardian_keys.clone(), signatures(2))
+                .verify(&config),
+            Err(ConfigVerificationError::InsufficientSignatures {
+                valid: 2,
+                threshold: 3
+            })
+        ));
+
+        assert!(matches!(
+            ConfigVerification::trust_on_first_use()
+                .with_guardian_signatures(guardian_keys, signatures(4))
+                .verify(&client_config(3)),
+            Err(ConfigVerificationError::GuardianMismatch)
+        ));
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::io::Cursor;
 use std::time::SystemTime;
 
+use anyhow::ensure;
 use fedimint_api_client::api::ApiVersionSet;
 use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::core::{ModuleInstanceId, OperationId};
@@ -13,22 +14,25 @@ use fedimint_core::db::{
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::SupportedApiVersionsSummary;
-use fedimint_core::util::BoxFuture;
-use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use fedimint_core::transaction::Transaction;
+use fedimint_core::util::{BoxFuture, SafeUrl};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, OutPoint, PeerId};
 use fedimint_logging::LOG_CLIENT_DB;
 use futures::StreamExt;
 use serde::Serialize;
 use strum_macros::EnumIter;
 use tracing::{debug, info, trace, warn};
 
-use crate::backup::{ClientBackup, Metadata};
+use crate::backup::{ClientBackup, LastBackupUpload, Metadata};
 use crate::module::recovery::RecoveryProgress;
 use crate::oplog::OperationLogEntry;
+use crate::secret::SecretPurpose;
 use crate::sm::executor::{
     ActiveStateKeyBytes, ActiveStateKeyPrefixBytes, InactiveStateKeyBytes,
     InactiveStateKeyPrefixBytes,
 };
 use crate::sm::{ActiveStateMeta, InactiveStateMeta};
+use crate::DynState;
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -47,6 +51,15 @@ pub enum DbKeyPrefix {
     ClientMetaServiceInfo = 0x35,
     ApiSecret = 0x36,
     PeerLastApiVersionsSummaryCache = 0x37,
+    DerivedSecret = 0x38,
+    ClientMigrationBackup = 0x39,
+    OperationLabel = 0x3a,
+    OperationsByLabel = 0x3b,
+    FundsReservations = 0x3c,
+    GuardianApiUrls = 0x3d,
+    TransactionOutbox = 0x3e,
+    ClientLastBackupUpload = 0x3f,
+    OutputOutcomeCache = 0x40,
 
     /// Arbitrary data of the applications integrating Fedimint client and
     /// wanting to store some Federation-specific data in Fedimint client
@@ -208,6 +221,10 @@ pub enum InitMode {
     /// Should be used with root secrets provided by the user to recover a
     /// (even if just possibly) already used secret.
     Recover { snapshot: Option<ClientBackup> },
+    /// Should be used with root secrets provided by the user if no backup of
+    /// the client exists. Modules recover by scanning the federation history
+    /// only, limited to the `scan_depth` most recent sessions if set.
+    BlindScan { scan_depth: Option<u64> },
 }
 
 /// Like `InitMode`, but without no longer required data.
@@ -236,7 +253,7 @@ impl InitState {
         match self {
             InitState::Pending(p) => InitState::Complete(match p {
                 InitMode::Fresh => InitModeComplete::Fresh,
-                InitMode::Recover { .. } => InitModeComplete::Recover,
+                InitMode::Recover { .. } | InitMode::BlindScan { .. } => InitModeComplete::Recover,
             }),
             InitState::Complete(t) => InitState::Complete(t),
         }
@@ -247,11 +264,21 @@ impl InitState {
             InitState::Pending(p) => match p {
                 InitMode::Fresh => None,
                 InitMode::Recover { snapshot } => Some(snapshot.clone()),
+                InitMode::BlindScan { .. } => Some(None),
             },
             InitState::Complete(_) => None,
         }
     }
 
+    /// Number of most recent sessions module recovery scans, `None` if the
+    /// whole history since the backup, if any, is scanned
+    pub fn recovery_scan_depth(&self) -> Option<u64> {
+        match self {
+            InitState::Pending(InitMode::BlindScan { scan_depth }) => *scan_depth,
+            _ => None,
+        }
+    }
+
     pub fn is_pending(&self) -> bool {
         match self {
             InitState::Pending(_) => true,
@@ -331,6 +358,17 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::ClientLastBackup
 );
 
+/// When the last backup was uploaded to the federation, see
+/// [`crate::Client::backup_status`]
+#[derive(Debug, Encodable, Decodable)]
+pub struct LastBackupUploadKey;
+
+impl_db_record!(
+    key = LastBackupUploadKey,
+    value = LastBackupUpload,
+    db_prefix = DbKeyPrefix::ClientLastBackupUpload
+);
+
 #[derive(Encodable, Decodable, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct MetaFieldKey(pub String);
 
@@ -363,6 +401,248 @@ impl_db_record!(
 
 impl_db_lookup!(key = MetaFieldKey, query_prefix = MetaFieldPrefix);
 
+/// Secret derived by a module via
+/// [`crate::module::ClientContext::derive_secret`]
+#[derive(Encodable, Decodable, Debug, Clone, Copy, Serialize)]
+pub struct DerivedSecretKey {
+    pub module_instance_id: ModuleInstanceId,
+    pub purpose: SecretPurpose,
+    pub index: u64,
+}
+
+#[derive(Encodable, Decodable, Debug)]
+pub struct DerivedSecretKeyPrefix;
+
+#[derive(Encodable, Decodable, Debug)]
+pub struct DerivedSecretModulePrefix {
+    pub module_instance_id: ModuleInstanceId,
+}
+
+/// Record of the first derivation of a [`DerivedSecretKey`], which allows
+/// auditing which secrets of the client root secret are in use
+#[derive(Encodable, Decodable, Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DerivedSecretRecord {
+    /// Derivation path relative to the client root secret, see
+    /// [`crate::secret::module_purpose_secret_path`]
+    pub path: Vec<u64>,
+    pub first_derived: SystemTime,
+}
+
+impl_db_record!(
+    key = DerivedSecretKey,
+    value = DerivedSecretRecord,
+    db_prefix = DbKeyPrefix::DerivedSecret
+);
+
+impl_db_lookup!(
+    key = DerivedSecretKey,
+    query_prefix = DerivedSecretKeyPrefix,
+    query_prefix = DerivedSecretModulePrefix
+);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ClientMigrationBackupKey {
+    pub module_instance_id: ModuleInstanceId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ClientMigrationBackupKeyPrefix;
+
+/// Copy of all data of a client module taken right before its database was
+/// migrated, so a migration that leaves the module unable to start can be
+/// undone with [`restore_client_migration_backups`]
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct ClientMigrationBackup {
+    /// Database version of the module before the migration
+    pub version: DatabaseVersion,
+    /// Raw entries of the module's isolated database, with full keys
+    pub module_entries: Vec<(Vec<u8>, Vec<u8>)>,
+    pub active_states: Vec<(Vec<u8>, OperationId)>,
+    pub inactive_states: Vec<(Vec<u8>, OperationId)>,
+}
+
+impl_db_record!(
+    key = ClientMigrationBackupKey,
+    value = ClientMigrationBackup,
+    db_prefix = DbKeyPrefix::ClientMigrationBackup
+);
+
+impl_db_lookup!(
+    key = ClientMigrationBackupKey,
+    query_prefix = ClientMigrationBackupKeyPrefix
+);
+
+/// User-visible label of an operation, e.g. a memo set by the wallet user
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationLabelKey {
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationLabelKeyPrefix;
+
+impl_db_record!(
+    key = OperationLabelKey,
+    value = String,
+    db_prefix = DbKeyPrefix::OperationLabel
+);
+
+impl_db_lookup!(
+    key = OperationLabelKey,
+    query_prefix = OperationLabelKeyPrefix
+);
+
+/// Index of operations by their label, see [`OperationLabelKey`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationsByLabelKey {
+    pub label: String,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationsByLabelPrefix {
+    pub label: String,
+}
+
+impl_db_record!(
+    key = OperationsByLabelKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationsByLabel
+);
+
+impl_db_lookup!(
+    key = OperationsByLabelKey,
+    query_prefix = OperationsByLabelPrefix
+);
+
+/// Funds of the primary module reserved by operations that haven't submitted
+/// their transaction yet, see [`crate::Client::reserve_funds`]. All
+/// reservations are kept in a single record so that concurrent reservations
+/// and spends conflict and are retried.
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct FundsReservationsKey;
+
+impl_db_record!(
+    key = FundsReservationsKey,
+    value = BTreeMap<OperationId, Amount>,
+    db_prefix = DbKeyPrefix::FundsReservations
+);
+
+/// API URLs of the guardians that moved to a different host since the client
+/// joined the federation. The endpoints in the stored [`ClientConfig`] are
+/// left unchanged, since the federation id is derived from them.
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct GuardianApiUrlsKey;
+
+impl_db_record!(
+    key = GuardianApiUrlsKey,
+    value = BTreeMap<PeerId, SafeUrl>,
+    db_prefix = DbKeyPrefix::GuardianApiUrls
+);
+
+/// Transactions that were finalized while the federation was unreachable,
+/// see [`crate::ClientBuilder::with_offline_mode`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct TransactionOutboxKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TransactionOutboxKeyPrefix;
+
+/// A fully signed transaction waiting in the outbox along with the state
+/// machines of its inputs and outputs, which are only started once the
+/// transaction is submitted
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct OutboxTransaction {
+    pub transaction: Transaction,
+    pub states: Vec<DynState>,
+    pub queued_at: SystemTime,
+}
+
+impl_db_record!(
+    key = TransactionOutboxKey,
+    value = OutboxTransaction,
+    db_prefix = DbKeyPrefix::TransactionOutbox
+);
+impl_db_lookup!(
+    key = TransactionOutboxKey,
+    query_prefix = TransactionOutboxKeyPrefix
+);
+
+/// Output outcomes already resolved by the federation, see
+/// [`crate::Client::await_output_outcome`]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OutputOutcomeCacheKey(pub OutPoint);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct OutputOutcomeCacheKeyPrefix;
+
+/// A consensus encoded output outcome. Outcomes that are not terminal yet,
+/// e.g. a contract still waiting for its preimage to be decrypted, are only
+/// served from the cache until they are older than
+/// [`crate::NON_TERMINAL_OUTPUT_OUTCOME_TTL`].
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct CachedOutputOutcome {
+    pub outcome: Vec<u8>,
+    pub terminal: bool,
+    pub cached_at: SystemTime,
+}
+
+impl_db_record!(
+    key = OutputOutcomeCacheKey,
+    value = CachedOutputOutcome,
+    db_prefix = DbKeyPrefix::OutputOutcomeCache
+);
+impl_db_lookup!(
+    key = OutputOutcomeCacheKey,
+    query_prefix = OutputOutcomeCacheKeyPrefix
+);
+
+/// Sets the label of an operation, replacing any previous one, or removes it
+/// if `label` is `None`. Keeps [`OperationsByLabelKey`] in sync.
+pub async fn set_operation_label_dbtx(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    label: Option<String>,
+) {
+    let key = OperationLabelKey { operation_id };
+    if let Some(previous) = dbtx.remove_entry(&key).await {
+        dbtx.remove_entry(&OperationsByLabelKey {
+            label: previous,
+            operation_id,
+        })
+        .await;
+    }
+
+    if let Some(label) = label {
+        dbtx.insert_entry(&key, &label).await;
+        dbtx.insert_entry(
+            &OperationsByLabelKey {
+                label,
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+    }
+}
+
+/// What the pending migrations of a client module would change, see
+/// [`check_migrations_client`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientMigrationReport {
+    pub module_instance_id: ModuleInstanceId,
+    pub kind: String,
+    pub current_version: DatabaseVersion,
+    pub target_version: DatabaseVersion,
+    /// Number of entries of the module's isolated database the migrations
+    /// would add, modify and remove
+    pub added_entries: usize,
+    pub modified_entries: usize,
+    pub removed_entries: usize,
+    /// Whether the migrations would rewrite the module's state machines
+    pub migrates_states: bool,
+}
+
 /// `ClientMigrationFn` is a function that modules can implement to "migrate"
 /// the database to the next database version.
 pub type ClientMigrationFn = for<'r, 'tx> fn(
@@ -415,7 +695,7 @@ pub async fn apply_migrations_client(
         .get_value(&DatabaseVersionKey(module_instance_id))
         .await;
 
-    let db_version = if let Some(mut current_version) = current_version {
+    let db_version = if let Some(current_version) = current_version {
         if current_version == target_version {
             trace!(
                 target: LOG_CLIENT_DB,
@@ -445,66 +725,28 @@ pub async fn apply_migrations_client(
             kind,
             "Migrating client module database"
         );
-        let mut active_states =
-            get_active_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
-        let mut inactive_states =
-            get_inactive_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
 
-        while current_version < target_version {
-            let new_states = if let Some(migration) = migrations.get(&current_version) {
-                debug!(
-                     target: LOG_CLIENT_DB,
-                     module_instance_id,
-                     %kind,
-                     %current_version,
-                     %target_version,
-                     "Running module db migration");
-
-                migration(
-                    &mut global_dbtx
-                        .to_ref_with_prefix_module_id(module_instance_id)
-                        .into_nc(),
-                    active_states.clone(),
-                    inactive_states.clone(),
-                )
-                .await?
-            } else {
-                warn!(
-                    target: LOG_CLIENT_DB,
-                    ?current_version, "Missing client db migration");
-                None
-            };
-
-            // If the client migration returned new states, a state machine migration has
-            // occurred, and the new states need to be persisted to the database.
-            if let Some((new_active_states, new_inactive_states)) = new_states {
-                remove_old_and_persist_new_active_states(
-                    &mut global_dbtx.to_ref_nc(),
-                    new_active_states.clone(),
-                    active_states.clone(),
-                    module_instance_id,
-                )
-                .await;
-                remove_old_and_persist_new_inactive_states(
-                    &mut global_dbtx.to_ref_nc(),
-                    new_inactive_states.clone(),
-                    inactive_states.clone(),
-                    module_instance_id,
-                )
-                .await;
-
-                // the new states become the old states for the next migration
-                active_states = new_active_states;
-                inactive_states = new_inactive_states;
-            }
-
-            current_version.increment();
-            global_dbtx
-                .insert_entry(&DatabaseVersionKey(module_instance_id), &current_version)
-                .await;
-        }
+        let backup = create_client_migration_backup(
+            &mut global_dbtx.to_ref_nc(),
+            module_instance_id,
+            current_version,
+        )
+        .await?;
+        global_dbtx
+            .insert_entry(&ClientMigrationBackupKey { module_instance_id }, &backup)
+            .await;
+
+        run_client_migrations(
+            &mut global_dbtx.to_ref_nc(),
+            &kind,
+            current_version,
+            target_version,
+            &migrations,
+            module_instance_id,
+        )
+        .await?;
 
-        current_version
+        target_version
     } else {
         target_version
     };
@@ -516,6 +758,270 @@ pub async fn apply_migrations_client(
     Ok(())
 }
 
+/// Runs the migrations of a client module from `current_version` up to
+/// `target_version` inside `global_dbtx` without committing. Returns whether
+/// any of them migrated the module's state machines.
+async fn run_client_migrations(
+    global_dbtx: &mut DatabaseTransaction<'_>,
+    kind: &str,
+    mut current_version: DatabaseVersion,
+    target_version: DatabaseVersion,
+    migrations: &BTreeMap<DatabaseVersion, ClientMigrationFn>,
+    module_instance_id: ModuleInstanceId,
+) -> anyhow::Result<bool> {
+    let mut active_states =
+        get_active_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
+    let mut inactive_states =
+        get_inactive_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
+    let mut migrated_states = false;
+
+    while current_version < target_version {
+        let new_states = if let Some(migration) = migrations.get(&current_version) {
+            debug!(
+                 target: LOG_CLIENT_DB,
+                 module_instance_id,
+                 %kind,
+                 %current_version,
+                 %target_version,
+                 "Running module db migration");
+
+            migration(
+                &mut global_dbtx
+                    .to_ref_with_prefix_module_id(module_instance_id)
+                    .into_nc(),
+                active_states.clone(),
+                inactive_states.clone(),
+            )
+            .await?
+        } else {
+            warn!(
+                target: LOG_CLIENT_DB,
+                ?current_version, "Missing client db migration");
+            None
+        };
+
+        // If the client migration returned new states, a state machine migration has
+        // occurred, and the new states need to be persisted to the database.
+        if let Some((new_active_states, new_inactive_states)) = new_states {
+            remove_old_and_persist_new_active_states(
+                &mut global_dbtx.to_ref_nc(),
+                new_active_states.clone(),
+                active_states.clone(),
+                module_instance_id,
+            )
+            .await;
+            remove_old_and_persist_new_inactive_states(
+                &mut global_dbtx.to_ref_nc(),
+                new_inactive_states.clone(),
+                inactive_states.clone(),
+                module_instance_id,
+            )
+            .await;
+
+            // the new states become the old states for the next migration
+            active_states = new_active_states;
+            inactive_states = new_inactive_states;
+            migrated_states = true;
+        }
+
+        current_version.increment();
+        global_dbtx
+            .insert_entry(&DatabaseVersionKey(module_instance_id), &current_version)
+            .await;
+    }
+
+    Ok(migrated_states)
+}
+
+/// Runs all pending migrations of a client module like
+/// [`apply_migrations_client`], but discards the transaction instead of
+/// committing it. Returns what the migrations would change, or `None` if the
+/// module is up to date or has no database version recorded yet.
+pub async fn check_migrations_client(
+    db: &Database,
+    kind: String,
+    target_version: DatabaseVersion,
+    migrations: BTreeMap<DatabaseVersion, ClientMigrationFn>,
+    module_instance_id: ModuleInstanceId,
+) -> anyhow::Result<Option<ClientMigrationReport>> {
+    let mut global_dbtx = db.begin_transaction().await;
+    global_dbtx.ignore_uncommitted();
+
+    let Some(current_version) = global_dbtx
+        .get_value(&DatabaseVersionKey(module_instance_id))
+        .await
+    else {
+        return Ok(None);
+    };
+
+    if current_version == target_version {
+        return Ok(None);
+    }
+
+    ensure!(
+        current_version < target_version,
+        "On disk database version for module {kind} was higher ({current_version}) than the target database version ({target_version})."
+    );
+
+    let before = create_client_migration_backup(
+        &mut global_dbtx.to_ref_nc(),
+        module_instance_id,
+        current_version,
+    )
+    .await?;
+
+    let migrates_states = run_client_migrations(
+        &mut global_dbtx.to_ref_nc(),
+        &kind,
+        current_version,
+        target_version,
+        &migrations,
+        module_instance_id,
+    )
+    .await?;
+
+    let after = create_client_migration_backup(
+        &mut global_dbtx.to_ref_nc(),
+        module_instance_id,
+        target_version,
+    )
+    .await?;
+
+    let before_entries = before
+        .module_entries
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    let after_entries = after.module_entries.into_iter().collect::<BTreeMap<_, _>>();
+
+    Ok(Some(ClientMigrationReport {
+        module_instance_id,
+        kind,
+        current_version,
+        target_version,
+        added_entries: after_entries
+            .keys()
+            .filter(|key| !before_entries.contains_key(*key))
+            .count(),
+        modified_entries: after_entries
+            .iter()
+            .filter(|(key, value)| {
+                before_entries
+                    .get(*key)
+                    .is_some_and(|before_value| before_value != *value)
+            })
+            .count(),
+        removed_entries: before_entries
+            .keys()
+            .filter(|key| !after_entries.contains_key(*key))
+            .count(),
+        migrates_states,
+    }))
+}
+
+/// Reads all data of a client module at database `version` that a migration
+/// can touch
+async fn create_client_migration_backup(
+    global_dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+    version: DatabaseVersion,
+) -> anyhow::Result<ClientMigrationBackup> {
+    let module_entries = global_dbtx
+        .raw_find_by_prefix(&module_db_prefix(module_instance_id))
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(ClientMigrationBackup {
+        version,
+        module_entries,
+        active_states: get_active_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await,
+        inactive_states: get_inactive_states(&mut global_dbtx.to_ref_nc(), module_instance_id)
+            .await,
+    })
+}
+
+/// Undoes the last migration of every client module that has a backup,
+/// restoring the module's data and database version from before the
+/// migration. Returns the ids of the restored modules.
+pub async fn restore_client_migration_backups(
+    db: &Database,
+) -> anyhow::Result<Vec<ModuleInstanceId>> {
+    let mut global_dbtx = db.begin_transaction().await;
+
+    let backups = global_dbtx
+        .find_by_prefix(&ClientMigrationBackupKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut restored = Vec::with_capacity(backups.len());
+    for (key, backup) in backups {
+        let module_instance_id = key.module_instance_id;
+
+        global_dbtx
+            .raw_remove_by_prefix(&module_db_prefix(module_instance_id))
+            .await?;
+        for (entry_key, entry_value) in &backup.module_entries {
+            global_dbtx.raw_insert_bytes(entry_key, entry_value).await?;
+        }
+
+        let active_states =
+            get_active_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
+        remove_old_and_persist_new_active_states(
+            &mut global_dbtx.to_ref_nc(),
+            backup.active_states,
+            active_states,
+            module_instance_id,
+        )
+        .await;
+        let inactive_states =
+            get_inactive_states(&mut global_dbtx.to_ref_nc(), module_instance_id).await;
+        remove_old_and_persist_new_inactive_states(
+            &mut global_dbtx.to_ref_nc(),
+            backup.inactive_states,
+            inactive_states,
+            module_instance_id,
+        )
+        .await;
+
+        global_dbtx
+            .insert_entry(&DatabaseVersionKey(module_instance_id), &backup.version)
+            .await;
+        global_dbtx.remove_entry(&key).await;
+
+        info!(
+            target: LOG_CLIENT_DB,
+            module_instance_id,
+            version = %backup.version,
+            "Restored client module database from migration backup"
+        );
+        restored.push(module_instance_id);
+    }
+
+    global_dbtx.commit_tx_result().await?;
+
+    Ok(restored)
+}
+
+/// Removes all migration backups, once the migrated modules were initialized
+/// successfully and the backups are no longer needed
+pub async fn remove_client_migration_backups(db: &Database) {
+    let mut global_dbtx = db.begin_transaction().await;
+    global_dbtx
+        .remove_by_prefix(&ClientMigrationBackupKeyPrefix)
+        .await;
+    global_dbtx.commit_tx().await;
+}
+
+/// Prefix of the isolated database of a client module
+fn module_db_prefix(module_instance_id: ModuleInstanceId) -> Vec<u8> {
+    let mut prefix = vec![MODULE_GLOBAL_PREFIX];
+    module_instance_id
+        .consensus_encode(&mut prefix)
+        .expect("Error encoding module instance id as prefix");
+    prefix
+}
+
 /// Reads all active states from the database and returns `Vec<DynState>`.
 /// TODO: It is unfortunate that we can't read states by the module's instance
 /// id so we are forced to return all active states. Once we do a db migration
@@ -0,0 +1,182 @@
+use fedimint_api_client::api::{FederationError, OutputOutcomeError};
+use fedimint_core::core::OperationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config_verification::ConfigVerificationError;
+use crate::WatchOnly;
+
+/// Error returned by the public client APIs that submit transactions, await
+/// their outputs or join and recover federations.
+///
+/// Applications can branch on the variant or on its [`ClientErrorCode`]
+/// instead of matching on error messages. Modules can return these variants
+/// wrapped in an [`anyhow::Error`], they are recovered by the
+/// `From<anyhow::Error>` implementation.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Insufficient funds: requested {requested} but only {available} available")]
+    InsufficientFunds {
+        requested: Amount,
+        available: Amount,
+    },
+    #[error("Federation unreachable: {0}")]
+    FederationUnreachable(anyhow::Error),
+    #[error("Transaction rejected by the federation: {reason}")]
+    TxRejected { reason: String },
+    #[error("There already exists an operation with id {0:?}")]
+    OperationAlreadyExists(OperationId),
+    #[error(transparent)]
+    WatchOnly(#[from] WatchOnly),
+    #[error("Invalid client config: {0}")]
+    InvalidConfig(#[from] ConfigVerificationError),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// Machine-readable code of a [`ClientError`], e.g. for passing it over FFI or
+/// RPC boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientErrorCode {
+    InsufficientFunds,
+    FederationUnreachable,
+    TxRejected,
+    OperationAlreadyExists,
+    WatchOnly,
+    InvalidConfig,
+    Other,
+}
+
+impl ClientError {
+    pub fn code(&self) -> ClientErrorCode {
+        match self {
+            ClientError::InsufficientFunds { .. } => ClientErrorCode::InsufficientFunds,
+            ClientError::FederationUnreachable(_) => ClientErrorCode::FederationUnreachable,
+            ClientError::TxRejected { .. } => ClientErrorCode::TxRejected,
+            ClientError::OperationAlreadyExists(_) => ClientErrorCode::OperationAlreadyExists,
+            ClientError::WatchOnly(_) => ClientErrorCode::WatchOnly,
+            ClientError::InvalidConfig(_) => ClientErrorCode::InvalidConfig,
+            ClientError::Other(_) => ClientErrorCode::Other,
+        }
+    }
+
+    /// Recreates variants that don't wrap another error, so they can be
+    /// recovered from a reference into an error chain
+    fn clone_classified(&self) -> Option<ClientError> {
+        match self {
+            ClientError::InsufficientFunds {
+                requested,
+                available,
+            } => Some(ClientError::InsufficientFunds {
+                requested: *requested,
+                available: *available,
+            }),
+            ClientError::TxRejected { reason } => Some(ClientError::TxRejected {
+                reason: reason.clone(),
+            }),
+            ClientError::OperationAlreadyExists(operation_id) => {
+                Some(ClientError::OperationAlreadyExists(*operation_id))
+            }
+            ClientError::WatchOnly(watch_only) => Some(ClientError::WatchOnly(*watch_only)),
+            ClientError::InvalidConfig(error) => Some(ClientError::InvalidConfig(error.clone())),
+            ClientError::FederationUnreachable(_) | ClientError::Other(_) => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for ClientError {
+    /// Classifies the error by the first known error type in its chain. Context
+    /// added on top of an [`ClientError::InsufficientFunds`],
+    /// [`ClientError::TxRejected`], [`ClientError::OperationAlreadyExists`] or
+    /// [`WatchOnly`] error is dropped.
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<ClientError>() {
+            Ok(client_error) => return client_error,
+            Err(error) => error,
+        };
+
+        for cause in error.chain() {
+            if let Some(classified) = cause
+                .downcast_ref::<ClientError>()
+                .and_then(ClientError::clone_classified)
+            {
+                return classified;
+            }
+
+            if let Some(watch_only) = cause.downcast_ref::<WatchOnly>() {
+                return ClientError::WatchOnly(*watch_only);
+            }
+
+            if let Some(OutputOutcomeError::Rejected(reason)) =
+                cause.downcast_ref::<OutputOutcomeError>()
+            {
+                return ClientError::TxRejected {
+                    reason: reason.clone(),
+                };
+            }
+        }
+
+        if error.chain().any(|cause| cause.is::<FederationError>()) {
+            return ClientError::FederationUnreachable(error);
+        }
+
+        ClientError::Other(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use fedimint_api_client::api::FederationError;
+    use fedimint_core::Amount;
+
+    use super::{ClientError, ClientErrorCode};
+    use crate::WatchOnly;
+
+    #[test]
+    fn classifies_anyhow_errors() {
+        let insufficient_funds = anyhow::Error::from(ClientError::InsufficientFunds {
+            requested: Amount::from_sats(10),
+            available: Amount::from_sats(5),
+        })
+        .context("Failed to select notes");
+        assert!(matches!(
+            ClientError::from(insufficient_funds),
+            ClientError::InsufficientFunds { requested, available }
+                if requested == Amount::from_sats(10) && available == Amount::from_sats(5)
+        ));
+
+        let watch_only = Err::<(), _>(WatchOnly)
+            .context("Failed to spend")
+            .unwrap_err();
+        assert_eq!(
+            ClientError::from(watch_only).code(),
+            ClientErrorCode::WatchOnly
+        );
+
+        let unreachable = anyhow::Error::from(FederationError::general(
+            "session_count",
+            (),
+            anyhow!("Connection refused"),
+        ));
+        assert_eq!(
+            ClientError::from(unreachable).code(),
+            ClientErrorCode::FederationUnreachable
+        );
+
+        assert_eq!(
+            ClientError::from(anyhow!("Something else")).code(),
+            ClientErrorCode::Other
+        );
+    }
+
+    #[test]
+    fn codes_serialize_as_snake_case() {
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::TxRejected).expect("serializable"),
+            "\"tx_rejected\""
+        );
+    }
+}
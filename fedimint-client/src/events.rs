@@ -0,0 +1,25 @@
+use fedimint_core::core::{ModuleInstanceId, OperationId};
+use serde::{Deserialize, Serialize};
+
+/// Event emitted by the client, see [`crate::Client::subscribe_events`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// The balance held by `module` changed by `delta` msats
+    BalanceChanged {
+        module: ModuleInstanceId,
+        delta: i64,
+    },
+    /// A state machine belonging to `op_id` transitioned into a new state
+    OperationStateChanged {
+        op_id: OperationId,
+        /// Module kind of the operation as recorded in the operation log,
+        /// empty if the operation isn't logged (yet)
+        kind: String,
+        /// Debug representation of the new state, meant for display and
+        /// logging only as its format isn't stable
+        state_json: serde_json::Value,
+    },
+    /// All module recoveries that were running when subscribing finished
+    RecoveryDone,
+}
@@ -1,5 +1,6 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::cast_possible_truncation)]
+#![allow(clippy::cast_possible_wrap)]
 #![allow(clippy::default_trait_access)]
 #![allow(clippy::doc_markdown)]
 #![allow(clippy::explicit_deref_methods)]
@@ -82,41 +83,48 @@
 //!
 //! For a hacky instantiation of a complete client see the [`ng` subcommand of `fedimint-cli`](https://github.com/fedimint/fedimint/blob/55f9d88e17d914b92a7018de677d16e57ed42bf6/fedimint-cli/src/ng.rs#L56-L73).
 
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::ops::{self, Range};
 use std::pin::Pin;
-use std::sync::{Arc, Weak};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, ensure, Context};
 use async_stream::stream;
-use backup::ClientBackup;
+use backup::{AutoBackupConfig, ClientBackup};
 use db::{
-    apply_migrations_client, ApiSecretKey, CachedApiVersionSet, CachedApiVersionSetKey,
-    ClientConfigKey, ClientConfigKeyPrefix, ClientInitStateKey, ClientModuleRecovery,
-    EncodedClientSecretKey, InitMode, PeerLastApiVersionsSummary, PeerLastApiVersionsSummaryKey,
+    apply_migrations_client, check_migrations_client, remove_client_migration_backups,
+    restore_client_migration_backups, ApiSecretKey, CachedApiVersionSet, CachedApiVersionSetKey,
+    ClientConfigKey, ClientConfigKeyPrefix, ClientInitStateKey, ClientMigrationReport,
+    ClientModuleRecovery, EncodedClientSecretKey, InitMode, PeerLastApiVersionsSummary,
+    PeerLastApiVersionsSummaryKey,
 };
 use fedimint_api_client::api::{
     ApiVersionSet, DynGlobalApi, DynModuleApi, FederationApiExt, IGlobalFederationApi,
+    IRawFederationApi, OutputOutcomeError,
 };
+use fedimint_api_client::health::ApiHealthReport;
+use fedimint_api_client::interceptor::DynApiRequestInterceptor;
 use fedimint_core::config::{ClientConfig, FederationId, JsonClientConfig, ModuleInitRegistry};
 use fedimint_core::core::{
-    DynInput, DynOutput, IInput, IOutput, ModuleInstanceId, ModuleKind, OperationId,
+    Decoder, DynInput, DynOutput, IInput, IOutput, ModuleInstanceId, ModuleKind, OperationId,
+    OutputOutcome,
 };
 use fedimint_core::db::{
     AutocommitError, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::endpoint_constants::VERSION_ENDPOINT;
+use fedimint_core::endpoint_constants::{CLIENT_CONFIG_ENDPOINT, VERSION_ENDPOINT};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
-    ApiAuth, ApiRequestErased, ApiVersion, MultiApiVersion, SupportedApiVersionsSummary,
-    SupportedCoreApiVersions, SupportedModuleApiVersions,
+    ApiAuth, ApiRequestErased, ApiVersion, FeeSchedule, MultiApiVersion,
+    SupportedApiVersionsSummary, SupportedCoreApiVersions, SupportedModuleApiVersions,
 };
 use fedimint_core::task::{Elapsed, MaybeSend, MaybeSync, TaskGroup};
 use fedimint_core::transaction::Transaction;
-use fedimint_core::util::{BoxStream, NextOrPending};
+use fedimint_core::util::{BoxStream, NextOrPending, SafeUrl};
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, fedimint_build_code_version_env,
     maybe_add_send, maybe_add_send_sync, runtime, Amount, NumPeers, NumPeersExt, OutPoint, PeerId,
@@ -132,21 +140,32 @@ use module::recovery::RecoveryProgress;
 use module::{DynClientModule, FinalClient};
 use rand::thread_rng;
 use secp256k1_zkp::{PublicKey, Secp256k1};
-use secret::{DeriveableSecretClientExt, PlainRootSecretStrategy, RootSecretStrategy as _};
+use secret::{
+    DeriveableSecretClientExt, PlainRootSecretStrategy, RootSecretStrategy as _, StableAccountId,
+};
 use thiserror::Error;
 #[cfg(not(target_family = "wasm"))]
 use tokio::runtime::{Handle as RuntimeHandle, RuntimeFlavor};
-use tokio::sync::watch;
+use tokio::sync::{watch, Notify};
 use tokio_stream::wrappers::WatchStream;
 use tracing::{debug, error, info, warn};
 
 use crate::api_version_discovery::discover_common_api_versions_set;
 use crate::backup::Metadata;
-use crate::db::{ClientMetadataKey, ClientModuleRecoveryState, InitState, OperationLogKey};
+use crate::config_verification::ConfigVerification;
+use crate::db::{
+    set_operation_label_dbtx, CachedOutputOutcome, ClientMetadataKey, ClientModuleRecoveryState,
+    FundsReservationsKey, GuardianApiUrlsKey, InitState, OperationLabelKey, OperationLogKey,
+    OperationsByLabelPrefix, OutboxTransaction, OutputOutcomeCacheKey, TransactionOutboxKey,
+    TransactionOutboxKeyPrefix,
+};
+use crate::error::ClientError;
+use crate::events::ClientEvent;
 use crate::module::init::{
     ClientModuleInit, ClientModuleInitRegistry, DynClientModuleInit, IClientModuleInit,
 };
 use crate::module::{ClientModule, ClientModuleRegistry, IClientModule, StateGenerator};
+use crate::oplock::OperationLocks;
 use crate::oplog::OperationLog;
 use crate::sm::executor::{
     ActiveOperationStateKeyPrefix, ContextGen, InactiveOperationStateKeyPrefix,
@@ -154,6 +173,7 @@ use crate::sm::executor::{
 use crate::sm::{
     ClientSMDatabaseTransaction, DynState, Executor, IState, Notifier, OperationState, State,
 };
+use crate::sync::{SyncBackoff, SyncConfig};
 use crate::transaction::{
     tx_submission_sm_decoder, ClientInput, ClientOutput, TransactionBuilder, TxSubmissionContext,
     TxSubmissionStates, TRANSACTION_SUBMISSION_MODULE_INSTANCE,
@@ -161,18 +181,31 @@ use crate::transaction::{
 
 /// Client backup
 pub mod backup;
+/// Verification of client configs distributed without an invite code
+pub mod config_verification;
 /// Database keys used by the client
 pub mod db;
 /// Environment variables
 pub mod envs;
+/// Typed errors of the public client API
+pub mod error;
+/// Typed events emitted by the client
+pub mod events;
 /// Module client interface definitions
 pub mod module;
+/// Operation-scoped locks on logical resources of client modules
+pub mod oplock;
 /// Operation log subsystem of the client
 pub mod oplog;
 /// Secret handling & derivation
 pub mod secret;
 /// Client state machine interfaces and executor implementation
 pub mod sm;
+/// Adaptive polling of the federation by background tasks
+pub mod sync;
+/// In-memory federation to unit test client modules against their server
+/// side
+pub mod test_utils;
 /// Structs and interfaces to construct Fedimint transactions
 pub mod transaction;
 
@@ -201,6 +234,30 @@ pub enum AddStateMachinesError {
 
 pub type AddStateMachinesResult = Result<(), AddStateMachinesError>;
 
+/// Returned by every operation that would need the client's secret, e.g.
+/// spending, when it was opened with [`ClientBuilder::open_watch_only`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("The client is watch-only and cannot start operations that need its secret")]
+pub struct WatchOnly;
+
+/// Balance of the client's primary module, see [`Client::get_balance_details`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ClientBalance {
+    /// Funds that are not reserved by any operation
+    pub spendable: Amount,
+    /// Funds reserved by operations that haven't submitted their transaction
+    /// yet
+    pub reserved: Amount,
+}
+
+/// A transaction waiting in the outbox, see [`Client::outbox`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OutboxEntry {
+    pub operation_id: OperationId,
+    pub txid: TransactionId,
+    pub queued_at: SystemTime,
+}
+
 #[apply(async_trait_maybe_send!)]
 pub trait IGlobalClientContext: Debug + MaybeSend + MaybeSync + 'static {
     /// Returned a reference client's module API client, so that module-specific
@@ -218,6 +275,9 @@ pub trait IGlobalClientContext: Debug + MaybeSend + MaybeSync + 'static {
 
     fn decoders(&self) -> &ModuleDecoderRegistry;
 
+    /// Core API version negotiated with the federation
+    fn core_api_version(&self) -> ApiVersion;
+
     /// This function is mostly meant for internal use, you are probably looking
     /// for [`DynGlobalClientContext::claim_input`].
     /// Returns transaction id of the funding transaction and an optional
@@ -266,6 +326,10 @@ impl IGlobalClientContext for () {
         unimplemented!("fake implementation, only for tests");
     }
 
+    fn core_api_version(&self) -> ApiVersion {
+        unimplemented!("fake implementation, only for tests");
+    }
+
     async fn claim_input_dyn(
         &self,
         _dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
@@ -453,6 +517,10 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
         self.client.decoders()
     }
 
+    fn core_api_version(&self) -> ApiVersion {
+        self.client.api_versions().core
+    }
+
     fn client_config(&self) -> &ClientConfig {
         self.client.config()
     }
@@ -568,6 +636,47 @@ impl ClientHandle {
         self.shutdown_inner().await;
     }
 
+    /// Shutdown the client after letting in-flight state transitions finish
+    ///
+    /// New operations are rejected right away. The state machine executor stops
+    /// applying new transitions and waits up to `timeout` for the ones that
+    /// are currently being applied to be committed to the database, before
+    /// the client is shut down like with [`ClientHandle::shutdown`].
+    ///
+    /// Operations that still have active state machines are reported, they are
+    /// resumed the next time the client is started.
+    pub async fn shutdown_graceful(mut self, timeout: Duration) -> ShutdownReport {
+        let (quiesced, pending_operations) = {
+            let inner = self.as_inner();
+            inner.shutting_down.store(true, Ordering::SeqCst);
+
+            debug!(target: LOG_CLIENT, "Waiting for in-flight state transitions to finish");
+            let quiesced = runtime::timeout(timeout, inner.executor.quiesce())
+                .await
+                .is_ok();
+            if !quiesced {
+                warn!(target: LOG_CLIENT, ?timeout, "Timed out waiting for in-flight state transitions to finish");
+            }
+
+            let pending_operations = inner
+                .executor
+                .get_active_states()
+                .await
+                .into_iter()
+                .map(|(state, _)| state.operation_id())
+                .collect::<BTreeSet<_>>();
+
+            (quiesced, pending_operations)
+        };
+
+        self.shutdown_inner().await;
+
+        ShutdownReport {
+            quiesced,
+            pending_operations,
+        }
+    }
+
     async fn shutdown_inner(&mut self) {
         let Some(inner) = self.inner.take() else {
             error!("ClientHandleShared::shutdown called twice");
@@ -625,6 +734,109 @@ impl ClientHandle {
 
         builder.build(root_secret, config, api_secret, false).await
     }
+
+    /// Download the current client config from the federation and attach any
+    /// module instances the federation added since the client was created.
+    ///
+    /// Module instances already known to the client must be unchanged in the
+    /// new config, only additions are supported. If there are new modules the
+    /// client is rebuilt in-process on the same [`Database`] (like
+    /// [`ClientHandle::restart`]), which initializes the new modules,
+    /// registers their decoders and starts their state machine executors. The
+    /// updated config is only persisted once the rebuilt client is running.
+    /// If the rebuild fails, the previous database state is restored and the
+    /// client is rebuilt with its previous config, so the error is returned
+    /// only if that fails as well. Otherwise the handle is returned unchanged.
+    ///
+    /// The client is rebuilt instead of extended in place since its module
+    /// instances are fixed once it is built: the decoders of the [`Database`]
+    /// handle, the module contexts of the state machine executor and the
+    /// [`module::ClientContext`] of every module are all captured on
+    /// construction and shared with running operations. Before rebuilding, the
+    /// in-flight state transitions are given some time to be committed, like
+    /// with [`ClientHandle::shutdown_graceful`], and operations that are still
+    /// active are resumed by the rebuilt client.
+    pub async fn reload_config(self) -> anyhow::Result<ClientHandle> {
+        let (builder, fallback_builder, config, new_config, api_secret, root_secret) = {
+            let client = self
+                .inner
+                .as_ref()
+                .ok_or_else(|| anyhow::format_err!("Already stopped"))?;
+
+            let new_config = client
+                .api
+                .request_current_consensus::<ClientConfig>(
+                    CLIENT_CONFIG_ENDPOINT.to_owned(),
+                    ApiRequestErased::default(),
+                )
+                .await?;
+
+            let new_modules = Client::new_module_instances(&client.config, &new_config)?;
+            if new_modules.is_empty() {
+                debug!(target: LOG_CLIENT, "No new module instances in client config");
+                return Ok(self);
+            }
+
+            info!(target: LOG_CLIENT, ?new_modules, "Federation added new module instances, reloading client");
+
+            (
+                ClientBuilder::from_existing(client),
+                ClientBuilder::from_existing(client),
+                client.config.clone(),
+                new_config,
+                client.api_secret.clone(),
+                client.root_secret.clone(),
+            )
+        };
+        let db = fallback_builder.db_no_decoders.clone();
+        let federation_id = config.calculate_federation_id();
+
+        let report = self.shutdown_graceful(RELOAD_CONFIG_SHUTDOWN_TIMEOUT).await;
+        debug!(
+            target: LOG_CLIENT,
+            quiesced = report.quiesced,
+            pending_operations = report.pending_operations.len(),
+            "Shut down client to attach new module instances"
+        );
+
+        // The cached api versions do not cover the new modules, force a new
+        // negotiation when rebuilding the client
+        let mut dbtx = db.begin_transaction().await;
+        let cached_api_versions = dbtx.remove_entry(&CachedApiVersionSetKey).await;
+        dbtx.commit_tx_result().await?;
+
+        let error = match builder
+            .build(
+                root_secret.clone(),
+                new_config.clone(),
+                api_secret.clone(),
+                false,
+            )
+            .await
+        {
+            Ok(client) => {
+                let mut dbtx = db.begin_transaction().await;
+                dbtx.insert_entry(&ClientConfigKey { id: federation_id }, &new_config)
+                    .await;
+                dbtx.commit_tx_result().await?;
+                return Ok(client);
+            }
+            Err(error) => error,
+        };
+
+        warn!(target: LOG_CLIENT, %error, "Failed to attach new module instances, restoring client");
+        if let Some(cached_api_versions) = cached_api_versions {
+            let mut dbtx = db.begin_transaction().await;
+            dbtx.insert_entry(&CachedApiVersionSetKey, &cached_api_versions)
+                .await;
+            dbtx.commit_tx_result().await?;
+        }
+
+        fallback_builder
+            .build(root_secret, config, api_secret, false)
+            .await
+            .context(error)
+    }
 }
 
 impl ops::Deref for ClientHandle {
@@ -643,6 +855,16 @@ impl ClientHandle {
     }
 }
 
+/// Outcome of [`ClientHandle::shutdown_graceful`]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ShutdownReport {
+    /// Whether all in-flight state transitions were committed before the
+    /// timeout
+    pub quiesced: bool,
+    /// Operations with state machines that are still active
+    pub pending_operations: BTreeSet<OperationId>,
+}
+
 /// Internal self-reference to [`Client`]
 #[derive(Debug, Clone)]
 pub(crate) struct ClientStrong {
@@ -707,6 +929,26 @@ impl Drop for ClientHandle {
     }
 }
 
+/// Interval in which the client config is re-fetched from the federation to
+/// pick up guardians that moved to a different API URL
+const CONFIG_REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long [`ClientHandle::reload_config`] waits for in-flight state
+/// transitions to be committed before it rebuilds the client
+const RELOAD_CONFIG_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the client waits for the federation to respond before it considers
+/// it unreachable and queues transactions in the outbox
+const FEDERATION_REACHABILITY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a cached output outcome that can still change is served without
+/// asking the federation again, see [`Client::await_output_outcome`]
+pub const NON_TERMINAL_OUTPUT_OUTCOME_TTL: Duration = Duration::from_secs(30);
+
+/// Minimum interval in which the fee schedules of the modules are re-fetched
+/// from the federation, see [`FeeSchedule`]
+const FEE_SCHEDULE_REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 /// List of core api versions supported by the implementation.
 /// Notably `major` version is the one being supported, and corresponding
 /// `minor` version is the one required (for given `major` version).
@@ -762,8 +1004,30 @@ pub struct Client {
     api: DynGlobalApi,
     root_secret: DerivableSecret,
     operation_log: OperationLog,
+    operation_locks: OperationLocks,
+    /// Interceptors of `api`, kept to build the api of a restarted client
+    api_interceptors: Vec<DynApiRequestInterceptor>,
     secp_ctx: Secp256k1<secp256k1_zkp::All>,
     meta_service: Arc<MetaService>,
+    /// Set once a graceful shutdown started, no new operations are accepted
+    /// afterwards, see [`ClientHandle::shutdown_graceful`]
+    shutting_down: AtomicBool,
+    /// API versions negotiated with the federation when the client was built
+    api_versions: ApiVersionSet,
+    /// Opened without the root secret, see [`ClientBuilder::open_watch_only`]
+    watch_only: bool,
+    /// Queue transactions if the federation is unreachable, see
+    /// [`ClientBuilder::with_offline_mode`]
+    offline_mode: bool,
+    /// See [`ClientBuilder::with_auto_backup`]
+    auto_backup: Option<AutoBackupConfig>,
+    /// Wakes up the automatic backup task, see [`Client::request_backup`]
+    auto_backup_notify: Notify,
+    /// See [`ClientBuilder::with_sync_config`]
+    sync_config: SyncConfig,
+    /// Last fee schedules fetched from the federation, modules without a
+    /// schedule don't charge dynamic fees
+    fee_schedules: RwLock<BTreeMap<ModuleInstanceId, FeeSchedule>>,
 
     task_group: TaskGroup,
 
@@ -783,10 +1047,23 @@ impl Client {
         self.api.as_ref()
     }
 
+    /// Core and module API versions negotiated with the federation when the
+    /// client was started
+    pub fn api_versions(&self) -> &ApiVersionSet {
+        &self.api_versions
+    }
+
     pub fn api_clone(&self) -> DynGlobalApi {
         self.api.clone()
     }
 
+    /// Latency, error and version statistics of the guardians as observed by
+    /// this client's api, see
+    /// [`fedimint_api_client::health::PeerHealthTracker`]
+    pub fn api_health(&self) -> ApiHealthReport {
+        self.api.peer_health().report()
+    }
+
     /// Get the [`TaskGroup`] that is tied to Client's lifetime.
     pub fn task_group(&self) -> &TaskGroup {
         &self.task_group
@@ -875,17 +1152,42 @@ impl Client {
     }
 
     pub async fn start_executor(self: &Arc<Self>) {
+        // State machines claim and spend funds, which is up to the client holding the
+        // secret
+        if self.watch_only {
+            debug!(target: LOG_CLIENT, "Not starting the executor of a watch-only client");
+            return;
+        }
+
         debug!(
             "Starting fedimint client executor (version: {})",
             fedimint_build_code_version_env!()
         );
         self.executor.start_executor(self.context_gen()).await;
+
+        // Submitting the outbox starts state machines, so it requires a running
+        // executor
+        self.task_group
+            .spawn_cancellable("flush transaction outbox", {
+                let client_inner = self.clone();
+                async move {
+                    client_inner.flush_outbox_continuously().await;
+                }
+            });
     }
 
     pub fn federation_id(&self) -> FederationId {
         self.federation_id
     }
 
+    /// Returns an identifier of this client that is stable across restarts
+    /// and recoveries with the same root secret, but differs between
+    /// federations. It is not secret and can be shared with third parties to
+    /// deduplicate registrations of the same user, see [`StableAccountId`].
+    pub fn stable_account_id(&self) -> StableAccountId {
+        StableAccountId::derive(&self.root_secret, self.federation_id)
+    }
+
     fn context_gen(self: &Arc<Self>) -> ModuleGlobalContextGen {
         let client_inner = Arc::downgrade(self);
         Arc::new(move |module_instance, operation| {
@@ -946,7 +1248,9 @@ impl Client {
 
             let item_fee = module.input_fee(&input.input).expect(
                 "We only build transactions with input versions that are supported by the module",
-            );
+            ) + self
+                .fee_schedule(input.input.module_instance_id())
+                .input_fee(input.input.consensus_encode_to_vec().len());
 
             in_amount += input.amount;
             fee_amount += item_fee;
@@ -957,7 +1261,9 @@ impl Client {
 
             let item_fee = module.output_fee(&output.output).expect(
                 "We only build transactions with output versions that are supported by the module",
-            );
+            ) + self
+                .fee_schedule(output.output.module_instance_id())
+                .output_fee(output.output.consensus_encode_to_vec().len());
 
             out_amount += output.amount;
             fee_amount += item_fee;
@@ -970,6 +1276,28 @@ impl Client {
         Ok((self.federation_id().to_fake_ln_pub_key(&self.secp_ctx)?, 0))
     }
 
+    /// Fails if the client is shutting down and doesn't start new operations
+    /// anymore, see [`ClientHandle::shutdown_graceful`]
+    pub(crate) fn ensure_accepting_operations(&self) -> anyhow::Result<()> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            bail!("Client is shutting down, not accepting new operations");
+        }
+        self.ensure_not_watch_only()?;
+        Ok(())
+    }
+
+    /// Whether the client was opened with [`ClientBuilder::open_watch_only`]
+    pub fn is_watch_only(&self) -> bool {
+        self.watch_only
+    }
+
+    pub(crate) fn ensure_not_watch_only(&self) -> Result<(), WatchOnly> {
+        if self.watch_only {
+            return Err(WatchOnly);
+        }
+        Ok(())
+    }
+
     pub fn get_meta(&self, key: &str) -> Option<String> {
         self.federation_meta.get(key).cloned()
     }
@@ -983,6 +1311,7 @@ impl Client {
         dbtx: &mut DatabaseTransaction<'_>,
         states: Vec<DynState>,
     ) -> AddStateMachinesResult {
+        self.ensure_not_watch_only().map_err(anyhow::Error::from)?;
         self.executor.add_state_machines_dbtx(dbtx, states).await
     }
 
@@ -1008,6 +1337,75 @@ impl Client {
         &self.operation_log
     }
 
+    /// Attaches a user-visible label, e.g. a memo or tag, to an operation,
+    /// replacing any previous one. `None` removes the label. Labels are
+    /// included in backups.
+    pub async fn set_operation_label(&self, operation_id: OperationId, label: Option<String>) {
+        let mut dbtx = self.db().begin_transaction().await;
+        set_operation_label_dbtx(&mut dbtx.to_ref_nc(), operation_id, label).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Returns the label of an operation, see [`Self::set_operation_label`]
+    pub async fn get_operation_label(&self, operation_id: OperationId) -> Option<String> {
+        self.db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&OperationLabelKey { operation_id })
+            .await
+    }
+
+    /// Returns all operations with the given label
+    pub async fn get_operations_by_label(&self, label: &str) -> Vec<OperationId> {
+        self.db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&OperationsByLabelPrefix {
+                label: label.to_owned(),
+            })
+            .await
+            .map(|(key, ())| key.operation_id)
+            .collect()
+            .await
+    }
+
+    /// Requests cooperative cancellation of a running operation from the
+    /// module that started it, see [`ClientModule::cancel`]. Returns an error
+    /// if the operation already finished or its module can't cancel it in its
+    /// current state.
+    pub async fn cancel_operation(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        let operation = self
+            .operation_log
+            .get_operation(operation_id)
+            .await
+            .ok_or_else(|| anyhow!("Unknown operation {}", operation_id.fmt_short()))?;
+        ensure!(
+            operation.outcome::<serde_json::Value>().is_none(),
+            "Operation has already finished"
+        );
+
+        let (active_states, _) = self.executor.get_operation_states(operation_id).await;
+        let module_instance = active_states
+            .iter()
+            .map(|(state, _)| state.module_instance_id())
+            .find(|&instance| instance != TRANSACTION_SUBMISSION_MODULE_INSTANCE)
+            .context("Operation has no running state machines that could be cancelled")?;
+
+        info!(
+            target: LOG_CLIENT,
+            operation_id = %operation_id.fmt_short(),
+            module_instance,
+            "Cancelling operation"
+        );
+        self.get_module_client_dyn(module_instance)?
+            .cancel(operation_id)
+            .await
+    }
+
+    pub fn operation_locks(&self) -> &OperationLocks {
+        &self.operation_locks
+    }
+
     /// Get the meta manager to read meta fields.
     pub fn meta_service(&self) -> &Arc<MetaService> {
         &self.meta_service
@@ -1030,6 +1428,7 @@ impl Client {
                 operation_id,
                 input_amount,
                 output_amount,
+                self.fee_schedule(self.primary_module_instance),
             )
             .await?;
 
@@ -1046,9 +1445,13 @@ impl Client {
 
         let (input_amount, output_amount) = self.transaction_builder_balance(&partial_transaction);
 
-        assert_eq!(input_amount, output_amount, "Transaction is not balanced");
+        // Transactions paying schedule fees may overpay, see `FeeSchedule`
+        assert!(
+            input_amount >= output_amount,
+            "Transaction is not sufficiently funded"
+        );
 
-        let (tx, states) = partial_transaction.build(&self.secp_ctx, thread_rng());
+        let (tx, states) = partial_transaction.build(thread_rng()).await?;
 
         Ok((tx, states, change_range))
     }
@@ -1056,9 +1459,16 @@ impl Client {
     /// Add funding and/or change to the transaction builder as needed, finalize
     /// the transaction and submit it to the federation.
     ///
+    /// If the client was built [`ClientBuilder::with_offline_mode`] and the
+    /// federation is unreachable, the transaction is queued in the outbox
+    /// instead. Its state machines are only started once it is submitted, see
+    /// [`Self::outbox`].
+    ///
     /// ## Errors
-    /// The function will return an error if the operation with given ID already
-    /// exists.
+    /// The function will return [`ClientError::OperationAlreadyExists`] if the
+    /// operation with given ID already exists and
+    /// [`ClientError::InsufficientFunds`] if the primary module can't fund the
+    /// transaction.
     ///
     /// ## Panics
     /// The function will panic if the database transaction collides with
@@ -1070,12 +1480,15 @@ impl Client {
         operation_type: &str,
         operation_meta: F,
         tx_builder: TransactionBuilder,
-    ) -> anyhow::Result<(TransactionId, Vec<OutPoint>)>
+    ) -> Result<(TransactionId, Vec<OutPoint>), ClientError>
     where
         F: Fn(TransactionId, Vec<OutPoint>) -> M + Clone + MaybeSend + MaybeSync,
         M: serde::Serialize + MaybeSend,
     {
+        self.ensure_accepting_operations()?;
+
         let operation_type = operation_type.to_owned();
+        let queue = self.offline_mode && !self.is_federation_reachable().await;
 
         let autocommit_res = self
             .db
@@ -1086,11 +1499,16 @@ impl Client {
                     let operation_meta = operation_meta.clone();
                     Box::pin(async move {
                         if Client::operation_exists_dbtx(dbtx, operation_id).await {
-                            bail!("There already exists an operation with id {operation_id:?}")
+                            return Err(ClientError::OperationAlreadyExists(operation_id).into());
                         }
 
                         let (txid, change) = self
-                            .finalize_and_submit_transaction_inner(dbtx, operation_id, tx_builder)
+                            .finalize_and_submit_transaction_inner(
+                                dbtx,
+                                operation_id,
+                                tx_builder,
+                                queue,
+                            )
                             .await?;
 
                         self.operation_log()
@@ -1111,7 +1529,7 @@ impl Client {
 
         match autocommit_res {
             Ok(txid) => Ok(txid),
-            Err(AutocommitError::ClosureError { error, .. }) => Err(error),
+            Err(AutocommitError::ClosureError { error, .. }) => Err(error.into()),
             Err(AutocommitError::CommitFailed {
                 attempts,
                 last_error,
@@ -1126,11 +1544,22 @@ impl Client {
         dbtx: &mut DatabaseTransaction<'_>,
         operation_id: OperationId,
         tx_builder: TransactionBuilder,
+        queue: bool,
     ) -> anyhow::Result<(TransactionId, Vec<OutPoint>)> {
+        self.ensure_not_watch_only()?;
+
+        let balance_before = self
+            .primary_module()
+            .get_balance(self.primary_module_instance, &mut dbtx.to_ref_nc())
+            .await;
+
         let (transaction, mut states, change_range) = self
             .finalize_transaction(&mut dbtx.to_ref_nc(), operation_id, tx_builder)
             .await?;
 
+        self.consume_funds_reservation(dbtx, operation_id, balance_before)
+            .await?;
+
         ensure!(
             transaction.consensus_encode_to_vec().len() <= Transaction::MAX_TX_SIZE,
             "The generated transaction would be rejected by the federation for being too large."
@@ -1138,13 +1567,28 @@ impl Client {
 
         let txid = transaction.tx_hash();
 
-        debug!(target: LOG_CLIENT_NET_API, %txid, ?transaction,  "Finalized and submitting transaction");
-
         let change_outpoints = change_range
             .into_iter()
             .map(|out_idx| OutPoint { txid, out_idx })
             .collect();
 
+        if queue {
+            info!(target: LOG_CLIENT, %txid, %operation_id, "Federation is unreachable, queueing transaction in the outbox");
+            dbtx.insert_new_entry(
+                &TransactionOutboxKey(operation_id),
+                &OutboxTransaction {
+                    transaction,
+                    states,
+                    queued_at: fedimint_core::time::now(),
+                },
+            )
+            .await;
+
+            return Ok((txid, change_outpoints));
+        }
+
+        debug!(target: LOG_CLIENT_NET_API, %txid, ?transaction,  "Finalized and submitting transaction");
+
         let tx_submission_sm = DynState::from_typed(
             TRANSACTION_SUBMISSION_MODULE_INSTANCE,
             OperationState {
@@ -1196,7 +1640,12 @@ impl Client {
             .await
             .is_some();
 
-        active_state_exists || inactive_state_exists
+        let is_queued = dbtx
+            .get_value(&TransactionOutboxKey(operation_id))
+            .await
+            .is_some();
+
+        active_state_exists || inactive_state_exists || is_queued
     }
 
     pub async fn has_active_states(&self, operation_id: OperationId) -> bool {
@@ -1216,10 +1665,11 @@ impl Client {
         &self,
         operation_id: OperationId,
         out_point: OutPoint,
-    ) -> anyhow::Result<Amount> {
-        self.primary_module()
+    ) -> Result<Amount, ClientError> {
+        Ok(self
+            .primary_module()
             .await_primary_module_output(operation_id, out_point)
-            .await
+            .await?)
     }
 
     /// Returns a reference to a typed module client instance by kind
@@ -1292,7 +1742,7 @@ impl Client {
         &self,
         operation_id: OperationId,
         outputs: Vec<OutPoint>,
-    ) -> anyhow::Result<Amount> {
+    ) -> Result<Amount, ClientError> {
         let mut amount = Amount::ZERO;
 
         for out_point in outputs {
@@ -1305,10 +1755,386 @@ impl Client {
     }
 
     /// Returns the config with which the client was initialized.
+    ///
+    /// Guardians that moved to a different API URL since are not reflected in
+    /// the config, see [`Self::refresh_config`].
     pub fn get_config(&self) -> &ClientConfig {
         &self.config
     }
 
+    /// Re-fetches the client config from the federation and connects to the
+    /// guardians that moved to a different API URL. The new URLs are persisted,
+    /// so they are used after a restart as well. Returns the guardians whose
+    /// URL changed and their new URL.
+    ///
+    /// The client calls this periodically in the background, so guardians can
+    /// change their hosting without the client having to re-join, as long as
+    /// a threshold of guardians is reachable.
+    pub async fn refresh_config(&self) -> anyhow::Result<BTreeMap<PeerId, SafeUrl>> {
+        let new_config = self
+            .api
+            .request_current_consensus::<ClientConfig>(
+                CLIENT_CONFIG_ENDPOINT.to_owned(),
+                ApiRequestErased::default(),
+            )
+            .await?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut guardian_api_urls = dbtx
+            .get_value(&GuardianApiUrlsKey)
+            .await
+            .unwrap_or_default();
+
+        let rotated_urls =
+            Self::rotated_guardian_urls(&self.config, &guardian_api_urls, &new_config)?;
+        if rotated_urls.is_empty() {
+            debug!(target: LOG_CLIENT, "Guardian API URLs are unchanged");
+            return Ok(rotated_urls);
+        }
+
+        info!(target: LOG_CLIENT, ?rotated_urls, "Guardians moved to new API URLs");
+
+        for (peer_id, url) in &rotated_urls {
+            if self.config.global.api_endpoints[peer_id].url == *url {
+                guardian_api_urls.remove(peer_id);
+            } else {
+                guardian_api_urls.insert(*peer_id, url.clone());
+            }
+        }
+        dbtx.insert_entry(&GuardianApiUrlsKey, &guardian_api_urls)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        self.api.update_peer_urls(&rotated_urls).await;
+
+        Ok(rotated_urls)
+    }
+
+    /// Calls [`Self::refresh_config`] every [`CONFIG_REFRESH_INTERVAL`]
+    async fn refresh_config_continuously(&self) {
+        loop {
+            runtime::sleep(CONFIG_REFRESH_INTERVAL).await;
+
+            if let Err(err) = self.refresh_config().await {
+                warn!(target: LOG_CLIENT, %err, "Failed to refresh client config");
+            }
+        }
+    }
+
+    /// Like [`DynGlobalApi::await_output_outcome`], but answers repeated
+    /// queries for the same `outpoint`, e.g. when an operation is
+    /// re-opened, from a cache in the client database instead of the
+    /// federation. Outcomes for which `is_terminal` returns `false` are
+    /// re-fetched once they are older
+    /// than [`NON_TERMINAL_OUTPUT_OUTCOME_TTL`].
+    pub async fn await_output_outcome<R>(
+        &self,
+        outpoint: OutPoint,
+        timeout: Duration,
+        module_decoder: &Decoder,
+        is_terminal: impl Fn(&R) -> bool,
+    ) -> Result<R, OutputOutcomeError>
+    where
+        R: OutputOutcome + MaybeSend,
+    {
+        if let Some(outcome) = self.cached_output_outcome(outpoint).await {
+            return Ok(outcome);
+        }
+
+        let outcome = self
+            .api
+            .await_output_outcome::<R>(outpoint, timeout, module_decoder)
+            .await?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &OutputOutcomeCacheKey(outpoint),
+            &CachedOutputOutcome {
+                outcome: outcome.consensus_encode_to_vec(),
+                terminal: is_terminal(&outcome),
+                cached_at: fedimint_core::time::now(),
+            },
+        )
+        .await;
+        if let Err(e) = dbtx.commit_tx_result().await {
+            warn!(target: LOG_CLIENT, %outpoint, "Failed to cache output outcome: {e}");
+        }
+
+        Ok(outcome)
+    }
+
+    async fn cached_output_outcome<R>(&self, outpoint: OutPoint) -> Option<R>
+    where
+        R: OutputOutcome,
+    {
+        let cached = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&OutputOutcomeCacheKey(outpoint))
+            .await?;
+
+        let expired = fedimint_core::time::now()
+            .duration_since(cached.cached_at)
+            .map_or(true, |age| age > NON_TERMINAL_OUTPUT_OUTCOME_TTL);
+        if !cached.terminal && expired {
+            return None;
+        }
+
+        // Outcomes of a module don't contain dynamic types of other modules
+        R::consensus_decode_vec(cached.outcome, &ModuleDecoderRegistry::default())
+            .inspect_err(|e| {
+                warn!(target: LOG_CLIENT, %outpoint, "Failed to decode cached output outcome: {e}");
+            })
+            .ok()
+    }
+
+    /// Removes the cached outcome of `outpoint`, so that the next
+    /// [`Self::await_output_outcome`] asks the federation again
+    pub async fn invalidate_output_outcome(&self, outpoint: OutPoint) {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.remove_entry(&OutputOutcomeCacheKey(outpoint)).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Fetches the current fee schedules of all modules from the federation,
+    /// which are added to the fees of inputs and outputs when funding
+    /// transactions
+    pub async fn refresh_fee_schedules(&self) -> anyhow::Result<()> {
+        let fee_schedules = self.api.fee_schedule().await?;
+
+        *self
+            .fee_schedules
+            .write()
+            .expect("fee schedules lock poisoned") = fee_schedules;
+
+        Ok(())
+    }
+
+    /// Current fee schedule of a module as last fetched from the federation
+    pub fn fee_schedule(&self, module_instance_id: ModuleInstanceId) -> FeeSchedule {
+        self.fee_schedules
+            .read()
+            .expect("fee schedules lock poisoned")
+            .get(&module_instance_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Calls [`Self::refresh_fee_schedules`] right away and then at the
+    /// interval of the [`SyncConfig`], but at most every
+    /// [`FEE_SCHEDULE_REFRESH_INTERVAL`]. Federations that don't serve fee
+    /// schedules yet don't charge dynamic fees, so errors are only logged.
+    async fn refresh_fee_schedules_continuously(&self) {
+        let mut backoff = SyncBackoff::default();
+        loop {
+            if let Err(err) = self.refresh_fee_schedules().await {
+                debug!(target: LOG_CLIENT, %err, "Failed to refresh fee schedules");
+            }
+
+            self.wait_for_next_sync(&mut backoff, FEE_SCHEDULE_REFRESH_INTERVAL)
+                .await;
+        }
+    }
+
+    /// Whether a threshold of guardians responds within
+    /// [`FEDERATION_REACHABILITY_TIMEOUT`]
+    async fn is_federation_reachable(&self) -> bool {
+        runtime::timeout(FEDERATION_REACHABILITY_TIMEOUT, self.api.session_count())
+            .await
+            .is_ok_and(|res| res.is_ok())
+    }
+
+    /// Transactions that were queued while the federation was unreachable and
+    /// haven't been submitted yet, see [`ClientBuilder::with_offline_mode`]
+    ///
+    /// The outbox is submitted automatically once the federation is reachable
+    /// again, or manually with [`Self::flush_outbox`].
+    pub async fn outbox(&self) -> Vec<OutboxEntry> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&TransactionOutboxKeyPrefix)
+            .await
+            .map(|(key, entry)| OutboxEntry {
+                operation_id: key.0,
+                txid: entry.transaction.tx_hash(),
+                queued_at: entry.queued_at,
+            })
+            .collect()
+            .await
+    }
+
+    /// Submits all transactions in the outbox and starts the state machines of
+    /// their operations, regardless of whether the federation is reachable.
+    /// Returns the operations whose transaction was submitted.
+    pub async fn flush_outbox(&self) -> anyhow::Result<Vec<OperationId>> {
+        self.ensure_not_watch_only()?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+
+        let entries = dbtx
+            .find_by_prefix(&TransactionOutboxKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut operation_ids = Vec::with_capacity(entries.len());
+        for (key, entry) in entries {
+            let operation_id = key.0;
+            dbtx.remove_entry(&key).await;
+
+            let mut states = entry.states;
+            states.push(DynState::from_typed(
+                TRANSACTION_SUBMISSION_MODULE_INSTANCE,
+                OperationState {
+                    operation_id,
+                    state: TxSubmissionStates::Created(entry.transaction),
+                },
+            ));
+            self.executor
+                .add_state_machines_dbtx(&mut dbtx.to_ref_nc(), states)
+                .await?;
+
+            operation_ids.push(operation_id);
+        }
+
+        dbtx.commit_tx_result().await?;
+
+        if !operation_ids.is_empty() {
+            info!(target: LOG_CLIENT, ?operation_ids, "Submitted transactions from the outbox");
+        }
+
+        Ok(operation_ids)
+    }
+
+    /// Removes the transaction of `operation_id` from the outbox without ever
+    /// submitting it. The state machines of the operation are started with the
+    /// transaction being rejected, so that the funds of its inputs are
+    /// recovered.
+    pub async fn cancel_outbox_transaction(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        self.ensure_not_watch_only()?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+
+        let entry = dbtx
+            .remove_entry(&TransactionOutboxKey(operation_id))
+            .await
+            .context("No transaction of this operation is queued in the outbox")?;
+
+        let mut states = entry.states;
+        states.push(DynState::from_typed(
+            TRANSACTION_SUBMISSION_MODULE_INSTANCE,
+            OperationState {
+                operation_id,
+                state: TxSubmissionStates::Cancelled(entry.transaction.tx_hash()),
+            },
+        ));
+        self.executor
+            .add_state_machines_dbtx(&mut dbtx.to_ref_nc(), states)
+            .await?;
+
+        dbtx.commit_tx_result().await?;
+
+        info!(target: LOG_CLIENT, %operation_id, "Cancelled transaction in the outbox");
+
+        Ok(())
+    }
+
+    /// Calls [`Self::flush_outbox`] at the interval of the [`SyncConfig`] while
+    /// the outbox is not empty and the federation is reachable. A non-empty
+    /// outbox counts as activity, so it is retried at the active interval.
+    async fn flush_outbox_continuously(&self) {
+        let mut backoff = SyncBackoff::default();
+        loop {
+            if !self.outbox().await.is_empty() {
+                backoff.reset();
+
+                if self.is_federation_reachable().await {
+                    if let Err(err) = self.flush_outbox().await {
+                        warn!(target: LOG_CLIENT, %err, "Failed to submit the transaction outbox");
+                    }
+                }
+            }
+
+            self.wait_for_next_sync(&mut backoff, Duration::ZERO).await;
+        }
+    }
+
+    /// Returns the guardians whose API URL in `new_config` differs from the
+    /// URL the client currently uses, failing if `new_config` has different
+    /// guardians or changes any existing module instance.
+    fn rotated_guardian_urls(
+        current_config: &ClientConfig,
+        guardian_api_urls: &BTreeMap<PeerId, SafeUrl>,
+        new_config: &ClientConfig,
+    ) -> anyhow::Result<BTreeMap<PeerId, SafeUrl>> {
+        ensure!(
+            current_config
+                .global
+                .api_endpoints
+                .keys()
+                .eq(new_config.global.api_endpoints.keys()),
+            "New client config has different guardians"
+        );
+        Self::ensure_module_configs_unchanged(current_config, new_config)?;
+
+        Ok(new_config
+            .global
+            .api_endpoints
+            .iter()
+            .filter(|(peer_id, peer_url)| {
+                let current_url = guardian_api_urls
+                    .get(peer_id)
+                    .unwrap_or(&current_config.global.api_endpoints[peer_id].url);
+                *current_url != peer_url.url
+            })
+            .map(|(peer_id, peer_url)| (*peer_id, peer_url.url.clone()))
+            .collect())
+    }
+
+    /// Fails if a module instance of `current_config` is missing or different
+    /// in `new_config`
+    fn ensure_module_configs_unchanged(
+        current_config: &ClientConfig,
+        new_config: &ClientConfig,
+    ) -> anyhow::Result<()> {
+        for (module_instance_id, module_config) in &current_config.modules {
+            let Some(new_module_config) = new_config.modules.get(module_instance_id) else {
+                bail!("Module instance {module_instance_id} was removed from the client config");
+            };
+            // The configs may differ in being decoded or raw, so compare encodings
+            ensure!(
+                module_config.consensus_encode_to_vec()
+                    == new_module_config.consensus_encode_to_vec(),
+                "Config of module instance {module_instance_id} changed"
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the module instances present in `new_config` but not in
+    /// `current_config`, failing if `new_config` belongs to a different
+    /// federation or changes any existing module instance.
+    fn new_module_instances(
+        current_config: &ClientConfig,
+        new_config: &ClientConfig,
+    ) -> anyhow::Result<Vec<ModuleInstanceId>> {
+        ensure!(
+            current_config.calculate_federation_id() == new_config.calculate_federation_id(),
+            "New client config belongs to a different federation"
+        );
+        Self::ensure_module_configs_unchanged(current_config, new_config)?;
+
+        Ok(new_config
+            .modules
+            .keys()
+            .filter(|module_instance_id| !current_config.modules.contains_key(module_instance_id))
+            .copied()
+            .collect())
+    }
+
     /// Returns the config of the client in JSON format.
     ///
     /// Compared to the consensus module format where module configs are binary
@@ -1325,7 +2151,157 @@ impl Client {
             .expect("primary module must be present")
     }
 
-    /// Balance available to the client for spending
+    /// Reserves `amount` of the primary module's funds for `operation_id`, so
+    /// that other operations can't spend them before the operation submits its
+    /// transaction. Replaces a previous reservation of the operation.
+    ///
+    /// The reservation is released once the operation's transaction is
+    /// submitted via [`Self::finalize_and_submit_transaction`]. Callers that
+    /// abort the operation before that have to release it with
+    /// [`Self::release_funds_reservation`].
+    ///
+    /// ## Errors
+    /// Returns [`ClientError::InsufficientFunds`] if the funds that are not
+    /// reserved by other operations don't cover `amount`.
+    pub async fn reserve_funds(
+        &self,
+        operation_id: OperationId,
+        amount: Amount,
+    ) -> Result<(), ClientError> {
+        let autocommit_res = self
+            .db
+            .autocommit(
+                |dbtx, _| {
+                    Box::pin(async move {
+                        let mut reservations = dbtx
+                            .get_value(&FundsReservationsKey)
+                            .await
+                            .unwrap_or_default();
+                        reservations.remove(&operation_id);
+
+                        let balance = self
+                            .primary_module()
+                            .get_balance(self.primary_module_instance, &mut dbtx.to_ref_nc())
+                            .await;
+                        let available =
+                            balance.saturating_sub(reservations.values().copied().sum());
+
+                        if available < amount {
+                            return Err(anyhow::Error::from(ClientError::InsufficientFunds {
+                                requested: amount,
+                                available,
+                            }));
+                        }
+
+                        reservations.insert(operation_id, amount);
+                        dbtx.insert_entry(&FundsReservationsKey, &reservations)
+                            .await;
+
+                        Ok(())
+                    })
+                },
+                Some(100),
+            )
+            .await;
+
+        match autocommit_res {
+            Ok(()) => Ok(()),
+            Err(AutocommitError::ClosureError { error, .. }) => Err(error.into()),
+            Err(AutocommitError::CommitFailed {
+                attempts,
+                last_error,
+            }) => {
+                panic!("Failed to commit funds reservation after {attempts} attempts: {last_error}")
+            }
+        }
+    }
+
+    /// Releases the funds reserved for `operation_id` with
+    /// [`Self::reserve_funds`], if any
+    pub async fn release_funds_reservation(&self, operation_id: OperationId) {
+        self.db
+            .autocommit(
+                |dbtx, _| {
+                    Box::pin(async move {
+                        let mut reservations = dbtx
+                            .get_value(&FundsReservationsKey)
+                            .await
+                            .unwrap_or_default();
+
+                        if reservations.remove(&operation_id).is_some() {
+                            dbtx.insert_entry(&FundsReservationsKey, &reservations)
+                                .await;
+                        }
+
+                        Ok::<_, anyhow::Error>(())
+                    })
+                },
+                None,
+            )
+            .await
+            .expect("Releasing a funds reservation can't fail");
+    }
+
+    /// Releases the reservation of `operation_id` as part of submitting its
+    /// transaction and ensures the transaction didn't spend funds reserved by
+    /// other operations
+    async fn consume_funds_reservation(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        balance_before: Amount,
+    ) -> Result<(), ClientError> {
+        let Some(mut reservations) = dbtx.get_value(&FundsReservationsKey).await else {
+            return Ok(());
+        };
+        reservations.remove(&operation_id);
+
+        let reserved_by_others = reservations.values().copied().sum::<Amount>();
+        let balance_after = self
+            .primary_module()
+            .get_balance(self.primary_module_instance, &mut dbtx.to_ref_nc())
+            .await;
+
+        if balance_after < reserved_by_others {
+            return Err(ClientError::InsufficientFunds {
+                requested: balance_before.saturating_sub(balance_after),
+                available: balance_before.saturating_sub(reserved_by_others),
+            });
+        }
+
+        // Written even if unchanged, so that concurrent reservations conflict with
+        // this transaction and re-check the balance
+        dbtx.insert_entry(&FundsReservationsKey, &reservations)
+            .await;
+
+        Ok(())
+    }
+
+    /// Balance of the client split into the funds reserved by operations with
+    /// [`Self::reserve_funds`] and the funds that are spendable by others
+    pub async fn get_balance_details(&self) -> ClientBalance {
+        let mut dbtx = self.db().begin_transaction_nc().await;
+
+        let total = self
+            .primary_module()
+            .get_balance(self.primary_module_instance, &mut dbtx)
+            .await;
+        let reserved = dbtx
+            .get_value(&FundsReservationsKey)
+            .await
+            .unwrap_or_default()
+            .into_values()
+            .sum::<Amount>()
+            .min(total);
+
+        ClientBalance {
+            spendable: total - reserved,
+            reserved,
+        }
+    }
+
+    /// Balance available to the client for spending, including funds reserved
+    /// by operations, see [`Self::get_balance_details`]
     pub async fn get_balance(&self) -> Amount {
         self.primary_module()
             .get_balance(
@@ -1362,6 +2338,65 @@ impl Client {
         })
     }
 
+    /// Returns a single stream of all [`ClientEvent`]s, so integrators don't
+    /// have to subscribe to the balance, every operation and the recovery
+    /// progress separately.
+    ///
+    /// Only events happening after subscribing are emitted.
+    pub async fn subscribe_events(&self) -> BoxStream<'static, ClientEvent> {
+        let primary_module_instance = self.primary_module_instance;
+        let mut balance_changes = self.subscribe_balance_changes().await;
+        let balance_events = stream! {
+            let Some(mut prev_balance) = balance_changes.next().await else {
+                return;
+            };
+            while let Some(balance) = balance_changes.next().await {
+                yield ClientEvent::BalanceChanged {
+                    module: primary_module_instance,
+                    delta: balance.msats as i64 - prev_balance.msats as i64,
+                };
+                prev_balance = balance;
+            }
+        };
+
+        let operation_log = self.operation_log.clone();
+        let mut transitions = self.executor.notifier().subscribe_all_modules();
+        let operation_events = stream! {
+            while let Some(state) = transitions.next().await {
+                let op_id = state.operation_id();
+                let kind = operation_log
+                    .get_operation(op_id)
+                    .await
+                    .map(|op| op.operation_module_kind().to_owned())
+                    .unwrap_or_default();
+                yield ClientEvent::OperationStateChanged {
+                    op_id,
+                    kind,
+                    state_json: serde_json::Value::String(format!("{state:?}")),
+                };
+            }
+        };
+
+        let mut recovery_receiver = self.client_recovery_progress_receiver.clone();
+        let recovery_pending = self.has_pending_recoveries();
+        let recovery_events = stream! {
+            if recovery_pending
+                && recovery_receiver
+                    .wait_for(|progress| progress.values().all(|p| p.is_done()))
+                    .await
+                    .is_ok()
+            {
+                yield ClientEvent::RecoveryDone;
+            }
+        };
+
+        Box::pin(futures::stream::select_all([
+            Box::pin(balance_events) as BoxStream<'static, ClientEvent>,
+            Box::pin(operation_events),
+            Box::pin(recovery_events),
+        ]))
+    }
+
     /// Query the federation for API version support and then calculate
     /// the best API version to use (supported by most guardians).
     pub async fn refresh_peers_api_versions(
@@ -1420,6 +2455,16 @@ impl Client {
                     }
                 }
                 Ok(o) => {
+                    if let Some(core_api_version) = o
+                        .core
+                        .api
+                        .into_iter()
+                        .max_by_key(|version| (version.major, version.minor))
+                    {
+                        api.peer_health()
+                            .record_core_api_version(peer_id, core_api_version);
+                    }
+
                     // Save the response to the database right away, just to
                     // not lose it
                     let mut dbtx = db.begin_transaction().await;
@@ -1827,6 +2872,9 @@ pub struct TransactionUpdates {
 impl TransactionUpdates {
     /// Waits for the transaction to be accepted or rejected as part of the
     /// operation to which the `TransactionUpdates` object is subscribed.
+    ///
+    /// If the transaction was rejected the error describes why, including the
+    /// input or output that failed validation if it was rejected by consensus.
     pub async fn await_tx_accepted(self, await_txid: TransactionId) -> Result<(), String> {
         self.update_stream
             .filter_map(|tx_update| {
@@ -1858,7 +2906,12 @@ pub struct ClientBuilder {
     admin_creds: Option<AdminCreds>,
     db_no_decoders: Database,
     meta_service: Arc<MetaService>,
+    api_interceptors: Vec<DynApiRequestInterceptor>,
     stopped: bool,
+    watch_only: bool,
+    offline_mode: bool,
+    auto_backup: Option<AutoBackupConfig>,
+    sync_config: SyncConfig,
 }
 
 impl ClientBuilder {
@@ -1870,7 +2923,12 @@ impl ClientBuilder {
             admin_creds: None,
             db_no_decoders: db,
             stopped: false,
+            watch_only: false,
+            offline_mode: false,
+            auto_backup: None,
+            sync_config: SyncConfig::default(),
             meta_service,
+            api_interceptors: vec![],
         }
     }
 
@@ -1881,8 +2939,13 @@ impl ClientBuilder {
             admin_creds: None,
             db_no_decoders: client.db.with_decoders(Default::default()),
             stopped: false,
+            watch_only: client.watch_only,
+            offline_mode: client.offline_mode,
+            auto_backup: client.auto_backup.clone(),
+            sync_config: client.sync_config.clone(),
             // non unique
             meta_service: client.meta_service.clone(),
+            api_interceptors: client.api_interceptors.clone(),
         }
     }
 
@@ -1920,6 +2983,32 @@ impl ClientBuilder {
         self.meta_service = meta_service;
     }
 
+    /// Passes every request the client sends to the federation through
+    /// `interceptor`. Interceptors are called in the order they were added.
+    pub fn with_api_interceptor(&mut self, interceptor: impl Into<DynApiRequestInterceptor>) {
+        self.api_interceptors.push(interceptor.into());
+    }
+
+    /// Queue transactions in a persistent outbox instead of submitting them if
+    /// the federation is unreachable, so that operations can be prepared while
+    /// offline. Queued transactions are submitted once the federation is
+    /// reachable again, see [`Client::outbox`].
+    pub fn with_offline_mode(&mut self) {
+        self.offline_mode = true;
+    }
+
+    /// Back up the client to the federation automatically after significant
+    /// changes, see [`AutoBackupConfig`] and [`Client::backup_status`]
+    pub fn with_auto_backup(&mut self, config: AutoBackupConfig) {
+        self.auto_backup = Some(config);
+    }
+
+    /// Configures how often background tasks poll the federation depending on
+    /// whether the client has active operations, see [`SyncConfig`]
+    pub fn with_sync_config(&mut self, config: SyncConfig) {
+        self.sync_config = config;
+    }
+
     async fn migrate_database(&self, db: &Database) -> anyhow::Result<()> {
         // Only apply the client database migrations if the database has been
         // initialized.
@@ -1945,6 +3034,46 @@ impl ClientBuilder {
         Ok(())
     }
 
+    /// Runs all pending client module database migrations without committing
+    /// them and reports what they would change, so applications can check
+    /// them before opening the client
+    pub async fn check_migrations(&self) -> anyhow::Result<Vec<ClientMigrationReport>> {
+        let client_config = self.load_existing_config().await?;
+        let db = self
+            .db_no_decoders
+            .with_decoders(self.decoders(&client_config));
+
+        let mut reports = vec![];
+        for (module_id, module_cfg) in client_config.modules {
+            let kind = module_cfg.kind.clone();
+            let Some(init) = self.module_inits.get(&kind) else {
+                continue;
+            };
+
+            if let Some(report) = check_migrations_client(
+                &db,
+                kind.to_string(),
+                init.database_version(),
+                init.get_database_migrations(),
+                module_id,
+            )
+            .await?
+            {
+                reports.push(report);
+            }
+        }
+
+        Ok(reports)
+    }
+
+    /// Undoes the last database migration of every client module that failed
+    /// to initialize after being migrated. Returns the ids of the restored
+    /// modules. The client can only be opened again by a version that expects
+    /// the restored database versions.
+    pub async fn rollback_migrations(&self) -> anyhow::Result<Vec<ModuleInstanceId>> {
+        restore_client_migration_backups(&self.db_no_decoders).await
+    }
+
     pub fn db_no_decoders(&self) -> &Database {
         &self.db_no_decoders
     }
@@ -1993,13 +3122,19 @@ impl ClientBuilder {
             let init_state = InitState::Pending(init_mode);
             dbtx.insert_entry(&ClientInitStateKey, &init_state).await;
 
-            let metadata = init_state
+            let (metadata, labels) = init_state
                 .does_require_recovery()
                 .flatten()
-                .map_or(Metadata::empty(), |s| s.metadata);
+                .map_or((Metadata::empty(), BTreeMap::new()), |s| {
+                    (s.metadata, s.labels)
+                });
 
             dbtx.insert_new_entry(&ClientMetadataKey, &metadata).await;
 
+            for (operation_id, label) in labels {
+                set_operation_label_dbtx(&mut dbtx.to_ref_nc(), operation_id, Some(label)).await;
+            }
+
             dbtx.commit_tx_result().await?;
         }
 
@@ -2041,7 +3176,8 @@ impl ClientBuilder {
     /// // https://github.com/fedimint/fedimint/blob/master/docs/secret_derivation.md
     /// // let root_secret = …;
     ///
-    /// // Get invite code from user
+    /// // Get invite code from user, both the v1 (`fed11…`) and the compact v2
+    /// // (`fed21…`, see `InviteCode::encode_v2`) encodings are accepted
     /// let invite_code = InviteCode::from_str("fed11qgqpw9thwvaz7te3xgmjuvpwxqhrzw3jxumrvvf0qqqjpetvlg8glnpvzcufhffgzhv8m75f7y34ryk7suamh8x7zetly8h0v9v0rm")
     ///     .expect("Invalid invite code");
     /// let config = fedimint_api_client::download_from_invite_code(&invite_code).await
@@ -2075,7 +3211,7 @@ impl ClientBuilder {
     ///     // .with_module(LightningClientInit)
     ///     // .with_module(MintClientInit)
     ///     // .with_module(WalletClientInit::default())
-    ///     .join(root_secret, config, None)
+    ///     .join(root_secret, config, invite_code.api_secret())
     ///     .await
     ///     .expect("Error joining federation");
     /// # }
@@ -2085,9 +3221,28 @@ impl ClientBuilder {
         root_secret: DerivableSecret,
         config: ClientConfig,
         api_secret: Option<String>,
-    ) -> anyhow::Result<ClientHandle> {
-        self.init(root_secret, config, api_secret, InitMode::Fresh)
-            .await
+    ) -> Result<ClientHandle, ClientError> {
+        Ok(self
+            .init(root_secret, config, api_secret, InitMode::Fresh)
+            .await?)
+    }
+
+    /// Join a new Federation with a config that was distributed out-of-band,
+    /// e.g. as a file shipped with the application, instead of downloading it
+    /// from a guardian with an invite code
+    ///
+    /// The config is checked according to `verification` before the client
+    /// database is initialized, no guardian is contacted for that. The same
+    /// warnings as for [`Self::join`] apply to the `root_secret`.
+    pub async fn join_with_config(
+        self,
+        root_secret: DerivableSecret,
+        config: ClientConfig,
+        verification: ConfigVerification,
+    ) -> Result<ClientHandle, ClientError> {
+        verification.verify(&config)?;
+
+        self.join(root_secret, config, None).await
     }
 
     /// Download most recent valid backup found from the Federation
@@ -2096,14 +3251,19 @@ impl ClientBuilder {
         root_secret: &DerivableSecret,
         config: &ClientConfig,
         api_secret: Option<String>,
-    ) -> anyhow::Result<Option<ClientBackup>> {
-        let api = DynGlobalApi::from_config(config, &api_secret);
-        Client::download_backup_from_federation_static(
+    ) -> Result<Option<ClientBackup>, ClientError> {
+        let api = DynGlobalApi::from_config_with_interceptors(
+            config,
+            &api_secret,
+            None,
+            self.api_interceptors.clone(),
+        );
+        Ok(Client::download_backup_from_federation_static(
             &api,
             &Self::federation_root_secret(root_secret, config),
             &self.decoders(config),
         )
-        .await
+        .await?)
     }
 
     /// Join a (possibly) previous joined Federation
@@ -2122,7 +3282,7 @@ impl ClientBuilder {
         config: ClientConfig,
         api_secret: Option<String>,
         backup: Option<ClientBackup>,
-    ) -> anyhow::Result<ClientHandle> {
+    ) -> Result<ClientHandle, ClientError> {
         let client = self
             .init(
                 root_secret,
@@ -2137,6 +3297,38 @@ impl ClientBuilder {
         Ok(client)
     }
 
+    /// Join a previously joined Federation without a backup of the client,
+    /// e.g. if the user never created one
+    ///
+    /// Like [`Self::recover`] with no backup, every client module that supports
+    /// recovery reconstructs its state by scanning the federation history for
+    /// outputs derived from `root_secret`. `scan_depth` limits the scan to the
+    /// most recent sessions, which is faster on long-running federations, but
+    /// misses funds received before. Since ecash issued later may have been
+    /// derived from indices past the scanned window, the mint module first
+    /// asks the federation which of our note nonces were already spent and
+    /// resumes issuance after them; recovery fails if the federation does not
+    /// support that. Unclaimed lnv2 contracts paying to or funded by our
+    /// static key are recovered and claimed or refunded, while lnv1 contracts
+    /// use random keys and cannot be recovered. Progress is reported via
+    /// [`Client::subscribe_to_recovery_progress`].
+    pub async fn recover_without_backup(
+        self,
+        root_secret: DerivableSecret,
+        config: ClientConfig,
+        api_secret: Option<String>,
+        scan_depth: Option<u64>,
+    ) -> Result<ClientHandle, ClientError> {
+        Ok(self
+            .init(
+                root_secret,
+                config,
+                api_secret,
+                InitMode::BlindScan { scan_depth },
+            )
+            .await?)
+    }
+
     pub async fn open(self, root_secret: DerivableSecret) -> anyhow::Result<ClientHandle> {
         let Some(config) = Client::get_config_from_db(&self.db_no_decoders).await else {
             bail!("Client database not initialized")
@@ -2152,6 +3344,23 @@ impl ClientBuilder {
         Ok(client)
     }
 
+    /// Open an existing client without its root secret, e.g. for audit
+    /// dashboards or on a device that should never spend.
+    ///
+    /// Balances, the operation log and subscriptions to operation updates work
+    /// as usual, but the state machine executor is not started and every
+    /// operation that would need the secret, like spending or creating new
+    /// receive operations, fails with [`WatchOnly`]. State machines keep being
+    /// driven by the client holding the secret.
+    pub async fn open_watch_only(mut self) -> anyhow::Result<ClientHandle> {
+        self.watch_only = true;
+        // Modules require a secret to be initialized, since no operations can be
+        // started none of the keys derived from it are ever used
+        let throwaway_secret =
+            DerivableSecret::new_root(&rand::random::<[u8; 32]>(), b"watch-only");
+        self.open(throwaway_secret).await
+    }
+
     /// Build a [`Client`] but do not start the executor
     async fn build(
         self,
@@ -2179,17 +3388,27 @@ impl ClientBuilder {
         let config = Self::config_decoded(config, &decoders)?;
         let fed_id = config.calculate_federation_id();
         let db = self.db_no_decoders.with_decoders(decoders.clone());
-        let api = if let Some(admin_creds) = self.admin_creds.as_ref() {
-            DynGlobalApi::from_config_admin(&config, &api_secret, admin_creds.peer_id)
-        } else {
-            DynGlobalApi::from_config(&config, &api_secret)
-        };
+        let api = DynGlobalApi::from_config_with_interceptors(
+            &config,
+            &api_secret,
+            self.admin_creds.as_ref().map(|creds| creds.peer_id),
+            self.api_interceptors.clone(),
+        );
         let task_group = TaskGroup::new();
 
         // Migrate the database before interacting with it in case any on-disk data
         // structures have changed.
         self.migrate_database(&db).await?;
 
+        if let Some(guardian_api_urls) = db
+            .begin_transaction_nc()
+            .await
+            .get_value(&GuardianApiUrlsKey)
+            .await
+        {
+            api.update_peer_urls(&guardian_api_urls).await;
+        }
+
         let init_state = Self::load_init_state(&db).await;
 
         let primary_module_instance = self
@@ -2257,6 +3476,7 @@ impl ClientBuilder {
                         let api = api.clone();
                         let root_secret = root_secret.clone();
                         let admin_auth = self.admin_creds.as_ref().map(|creds| creds.auth.clone());
+                        let scan_depth = init_state.recovery_scan_depth();
                         let final_client = final_client.clone();
                         let (progress_tx, progress_rx) = tokio::sync::watch::channel(progress);
                         let module_init = module_init.clone();
@@ -2278,6 +3498,7 @@ impl ClientBuilder {
                                         admin_auth,
                                             snapshot.as_ref().and_then(|s| s.modules.get(&module_instance_id)),
                                             progress_tx,
+                                            scan_depth,
                                         )
                                         .await
                                         .map_err(|err| {
@@ -2366,6 +3587,10 @@ impl ClientBuilder {
             modules
         };
 
+        // All migrated modules could be initialized, so the pre-migration backups are
+        // no longer needed
+        remove_client_migration_backups(&db).await;
+
         if init_state.is_pending() && module_recoveries.is_empty() {
             let mut dbtx = db.begin_transaction().await;
             dbtx.insert_entry(&ClientInitStateKey, &init_state.into_complete())
@@ -2412,8 +3637,18 @@ impl ClientBuilder {
             root_secret,
             task_group,
             operation_log: OperationLog::new(db),
+            operation_locks: OperationLocks::default(),
+            api_interceptors: self.api_interceptors,
             client_recovery_progress_receiver,
             meta_service: self.meta_service,
+            shutting_down: AtomicBool::new(false),
+            api_versions: common_api_versions,
+            watch_only: self.watch_only,
+            offline_mode: self.offline_mode,
+            auto_backup: self.auto_backup,
+            auto_backup_notify: Notify::new(),
+            sync_config: self.sync_config,
+            fee_schedules: RwLock::new(BTreeMap::new()),
         });
         client_inner
             .task_group
@@ -2426,6 +3661,30 @@ impl ClientBuilder {
                         .await;
                 }
             });
+        client_inner
+            .task_group
+            .spawn_cancellable("refresh client config", {
+                let client_inner = client_inner.clone();
+                async move {
+                    client_inner.refresh_config_continuously().await;
+                }
+            });
+        client_inner
+            .task_group
+            .spawn_cancellable("refresh fee schedules", {
+                let client_inner = client_inner.clone();
+                async move {
+                    client_inner.refresh_fee_schedules_continuously().await;
+                }
+            });
+        if let Some(config) = client_inner.auto_backup.clone() {
+            client_inner.task_group.spawn_cancellable("auto backup", {
+                let client_inner = client_inner.clone();
+                async move {
+                    client_inner.auto_backup_continuously(config).await;
+                }
+            });
+        }
 
         let client_arc = ClientHandle::new(client_inner);
 
@@ -2528,3 +3787,93 @@ pub fn client_decoders<'a>(
     }
     ModuleDecoderRegistry::from(modules)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::config::{ClientConfig, ClientModuleConfig, GlobalClientConfig, PeerUrl};
+    use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+    use fedimint_core::encoding::DynRawFallback;
+    use fedimint_core::module::{CoreConsensusVersion, ModuleConsensusVersion};
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::PeerId;
+
+    use super::Client;
+
+    fn module_config(module_instance_id: ModuleInstanceId, raw: u8) -> ClientModuleConfig {
+        ClientModuleConfig {
+            kind: ModuleKind::from_static_str("dummy"),
+            version: ModuleConsensusVersion::new(0, 0),
+            config: DynRawFallback::Raw {
+                module_instance_id,
+                raw: vec![raw],
+            },
+        }
+    }
+
+    fn client_config(
+        url: &str,
+        modules: impl IntoIterator<Item = (ModuleInstanceId, u8)>,
+    ) -> ClientConfig {
+        ClientConfig {
+            global: GlobalClientConfig {
+                api_endpoints: BTreeMap::from([(
+                    PeerId::from(0),
+                    PeerUrl {
+                        url: SafeUrl::parse(url).expect("valid url"),
+                        name: "guardian".to_string(),
+                    },
+                )]),
+                consensus_version: CoreConsensusVersion::new(0, 0),
+                meta: BTreeMap::new(),
+            },
+            modules: modules
+                .into_iter()
+                .map(|(id, raw)| (id, module_config(id, raw)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn new_module_instances_are_the_added_ones() {
+        let current = client_config("ws://guardian:5000", [(0, 0), (1, 1)]);
+
+        assert_eq!(
+            Client::new_module_instances(&current, &current).unwrap(),
+            Vec::<ModuleInstanceId>::new()
+        );
+        assert_eq!(
+            Client::new_module_instances(
+                &current,
+                &client_config("ws://guardian:5000", [(0, 0), (1, 1), (3, 3), (2, 2)])
+            )
+            .unwrap(),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn new_module_instances_require_unchanged_modules_of_the_same_federation() {
+        let current = client_config("ws://guardian:5000", [(0, 0), (1, 1)]);
+
+        // Removed and changed module instances
+        assert!(Client::new_module_instances(
+            &current,
+            &client_config("ws://guardian:5000", [(0, 0), (2, 2)])
+        )
+        .is_err());
+        assert!(Client::new_module_instances(
+            &current,
+            &client_config("ws://guardian:5000", [(0, 0), (1, 7)])
+        )
+        .is_err());
+
+        // Config of another federation
+        assert!(Client::new_module_instances(
+            &current,
+            &client_config("ws://other:5000", [(0, 0), (1, 1), (2, 2)])
+        )
+        .is_err());
+    }
+}
@@ -69,6 +69,8 @@ where
         &self.core_api_version
     }
 
+    /// API version of this module negotiated with the federation, use
+    /// [`ApiVersion::supports`] to adapt to older federations
     pub fn module_api_version(&self) -> &ApiVersion {
         &self.module_api_version
     }
@@ -134,6 +136,7 @@ where
     module_api: DynModuleApi,
     context: ClientContext<<C as ClientModuleInit>::Module>,
     progress_tx: tokio::sync::watch::Sender<RecoveryProgress>,
+    scan_depth: Option<u64>,
 }
 
 impl<C> ClientModuleRecoverArgs<C>
@@ -191,6 +194,13 @@ where
         &self.module_api
     }
 
+    /// Number of most recent sessions to scan when recovering from the
+    /// federation history, `None` to scan every session since the backup or
+    /// since the federation was created if there is no backup
+    pub fn scan_depth(&self) -> Option<u64> {
+        self.scan_depth
+    }
+
     /// Get the [`ClientContext`]
     ///
     /// Notably `ClientContext`, unlike [`ClientModuleInitArgs::context`],
@@ -278,6 +288,7 @@ pub trait IClientModuleInit: IDynCommonModuleInit + Debug + MaybeSend + MaybeSyn
         admin_auth: Option<ApiAuth>,
         snapshot: Option<&DynModuleBackup>,
         progress_tx: watch::Sender<RecoveryProgress>,
+        scan_depth: Option<u64>,
     ) -> anyhow::Result<()>;
 
     #[allow(clippy::too_many_arguments)]
@@ -339,6 +350,7 @@ where
         admin_auth: Option<ApiAuth>,
         snapshot: Option<&DynModuleBackup>,
         progress_tx: watch::Sender<RecoveryProgress>,
+        scan_depth: Option<u64>,
     ) -> anyhow::Result<()> {
         let typed_cfg: &<<T as fedimint_core::module::ModuleInit>::Common as CommonModuleInit>::ClientConfig = cfg.cast()?;
         let snapshot: Option<&<<Self as ClientModuleInit>::Module as ClientModule>::Backup> =
@@ -369,6 +381,7 @@ where
                         _marker: marker::PhantomData,
                     },
                     progress_tx,
+                    scan_depth,
                 },
                 snapshot,
             )
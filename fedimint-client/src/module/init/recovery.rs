@@ -241,6 +241,10 @@ where
             const PARALLISM_LEVEL: usize = 64;
             const VERSION_THAT_INTRODUCED_GET_SESSION_STATUS: ApiVersion =
                 ApiVersion { major: 0, minor: 1 };
+            // Guardians agree on the transactions of pruned sessions, but not
+            // on their full outcome
+            const VERSION_THAT_INTRODUCED_GET_SESSION_TRANSACTIONS: ApiVersion =
+                ApiVersion { major: 0, minor: 5 };
 
             futures::stream::iter(epoch_range.clone())
                 .map(move |session_idx| {
@@ -256,7 +260,12 @@ where
                             let items_res = if core_api_version < VERSION_THAT_INTRODUCED_GET_SESSION_STATUS {
                                 api.await_block(session_idx, &decoders).await.map(|s| s.items)
                             } else {
-                                api.get_session_status(session_idx, &decoders).await.map(|s| match s {
+                                let status_res = if core_api_version < VERSION_THAT_INTRODUCED_GET_SESSION_TRANSACTIONS {
+                                    api.get_session_status(session_idx, &decoders).await
+                                } else {
+                                    api.get_session_transactions(session_idx, &decoders).await
+                                };
+                                status_res.map(|s| match s {
                                     SessionStatus::Initial => panic!("Federation missing session that existed when we started recovery"),
                                     SessionStatus::Pending(items) => items,
                                     SessionStatus::Complete(s) => s.items,
@@ -380,6 +389,14 @@ where
             } else {
                 let (state, start_session) = Recovery::new(self, snapshot).await?;
 
+                // A limited scan depth trades finding older funds for a faster blind scan
+                let start_session = match self.scan_depth() {
+                    Some(scan_depth) => {
+                        cmp::max(start_session, current_session_count.saturating_sub(scan_depth))
+                    }
+                    None => start_session,
+                };
+
                 debug!(target: LOG_CLIENT_RECOVERY, start_session, "Recovery start session");
                 (state,
                 RecoveryFromHistoryCommon {
@@ -3,28 +3,42 @@ use std::any::Any;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{ffi, marker, ops};
 
 use anyhow::{anyhow, bail};
-use fedimint_api_client::api::DynGlobalApi;
+use fedimint_api_client::api::{DynGlobalApi, OutputOutcomeError};
 use fedimint_core::config::ClientConfig;
 use fedimint_core::core::{
     Decoder, DynInput, DynOutput, IntoDynInstance, ModuleInstanceId, ModuleKind, OperationId,
 };
-use fedimint_core::db::{AutocommitError, Database, DatabaseTransaction, PhantomBound};
+use fedimint_core::db::{
+    AutocommitError, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped, PhantomBound,
+};
+use fedimint_core::encoding::Encodable;
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::registry::{ModuleDecoderRegistry, ModuleRegistry};
-use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleInit};
+use fedimint_core::module::{
+    ApiVersion, CommonModuleInit, FeeSchedule, ModuleCommon, ModuleConsensusFeature, ModuleInit,
+};
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::util::{BoxFuture, BoxStream};
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, maybe_add_send_sync, Amount, OutPoint,
     TransactionId,
 };
+use fedimint_derive_secret::DerivableSecret;
+use futures::StreamExt;
 use secp256k1_zkp::PublicKey;
 
 use self::init::ClientModuleInit;
+use crate::db::{DerivedSecretKey, DerivedSecretModulePrefix, DerivedSecretRecord};
+use crate::error::ClientError;
 use crate::module::recovery::{DynModuleBackup, ModuleBackup};
+use crate::oplock::{OperationInProgressError, OperationLockGuard, OperationLockKey};
+use crate::secret::{
+    module_purpose_secret_path, DeriveableSecretClientExt, SecretPurpose, StableAccountId,
+};
 use crate::sm::{self, ActiveStateMeta, Context, DynContext, DynState, State};
 use crate::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use crate::{oplog, AddStateMachinesResult, Client, ClientStrong, ClientWeak, TransactionUpdates};
@@ -34,6 +48,10 @@ pub mod recovery;
 
 pub type ClientModuleRegistry = ModuleRegistry<DynClientModule>;
 
+/// Core API version in which the federation started to publish the active
+/// consensus features of its modules
+const MODULE_CONSENSUS_FEATURES_API_VERSION: ApiVersion = ApiVersion::new(0, 3);
+
 /// A final, fully initialized [`crate::Client`]
 ///
 /// Client modules need to be able to access a `Client` they are a part
@@ -164,6 +182,23 @@ where
             .await;
     }
 
+    /// See [`ClientContext::update_operation_meta`]
+    pub async fn update_operation_meta<Meta>(
+        &mut self,
+        operation_id: OperationId,
+        f: impl FnOnce(Meta) -> Meta,
+    ) -> anyhow::Result<()>
+    where
+        Meta: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        self.client
+            .client
+            .get()
+            .operation_log()
+            .update_operation_meta(self.dbtx, operation_id, M::kind().as_str(), f)
+            .await
+    }
+
     pub async fn add_state_machines_dbtx(
         &mut self,
         states: Vec<DynState>,
@@ -171,8 +206,7 @@ where
         self.client
             .client
             .get()
-            .executor
-            .add_state_machines_dbtx(self.dbtx, states)
+            .add_state_machines(self.dbtx, states)
             .await
     }
 }
@@ -200,10 +234,72 @@ where
         }
     }
 
+    /// Locks `resource` of this module for `operation_id` until the returned
+    /// guard is dropped, see [`crate::oplock::OperationLocks`]
+    ///
+    /// Fails if another operation is currently using the same resource.
+    pub fn try_lock_resource(
+        &self,
+        resource: &impl Encodable,
+        operation_id: OperationId,
+    ) -> Result<OperationLockGuard, OperationInProgressError> {
+        self.client.get().operation_locks().try_lock(
+            OperationLockKey::new(self.module_instance_id, resource),
+            operation_id,
+        )
+    }
+
+    /// Locks `resource` of this module for `operation_id` until the returned
+    /// guard is dropped, waiting for other operations using the same resource
+    /// to release it first, see [`crate::oplock::OperationLocks`]
+    pub async fn lock_resource(
+        &self,
+        resource: &impl Encodable,
+        operation_id: OperationId,
+    ) -> OperationLockGuard {
+        let locks = self.client.get().operation_locks().clone();
+        locks
+            .lock(
+                OperationLockKey::new(self.module_instance_id, resource),
+                operation_id,
+            )
+            .await
+    }
+
+    /// Asks for an automatic backup of the client after a change the module
+    /// considers significant, see [`crate::Client::request_backup`]
+    pub fn request_backup(&self) {
+        self.client.get().request_backup();
+    }
+
     /// Get a reference to a global Api handle
     pub fn global_api(&self) -> DynGlobalApi {
         self.client.get().api_clone()
     }
+
+    /// Whether the federation activated `feature` of this module instance, so
+    /// that inputs and outputs requiring it are accepted. Federations that
+    /// predate consensus features have none active.
+    pub async fn is_consensus_feature_active(
+        &self,
+        feature: &ModuleConsensusFeature,
+    ) -> anyhow::Result<bool> {
+        let client = self.client.get();
+        if !client
+            .api_versions()
+            .core
+            .supports(MODULE_CONSENSUS_FEATURES_API_VERSION)
+        {
+            return Ok(false);
+        }
+
+        let features = client.api().module_consensus_features().await?;
+
+        Ok(features
+            .get(&self.module_instance_id)
+            .is_some_and(|features| features.contains(feature)))
+    }
+
     pub fn decoders(&self) -> ModuleDecoderRegistry {
         self.client.get().decoders().clone()
     }
@@ -345,7 +441,7 @@ where
         operation_type: &str,
         operation_meta: F,
         tx_builder: TransactionBuilder,
-    ) -> anyhow::Result<(TransactionId, Vec<OutPoint>)>
+    ) -> Result<(TransactionId, Vec<OutPoint>), ClientError>
     where
         F: Fn(TransactionId, Vec<OutPoint>) -> Meta + Clone + MaybeSend + MaybeSync,
         Meta: serde::Serialize + MaybeSend,
@@ -361,6 +457,23 @@ where
             .await
     }
 
+    /// See [`crate::Client::reserve_funds`]
+    pub async fn reserve_funds(
+        &self,
+        operation_id: OperationId,
+        amount: Amount,
+    ) -> Result<(), ClientError> {
+        self.client.get().reserve_funds(operation_id, amount).await
+    }
+
+    /// See [`crate::Client::release_funds_reservation`]
+    pub async fn release_funds_reservation(&self, operation_id: OperationId) {
+        self.client
+            .get()
+            .release_funds_reservation(operation_id)
+            .await;
+    }
+
     /// See [`crate::Client::transaction_updates`]
     pub async fn transaction_updates(&self, operation_id: OperationId) -> TransactionUpdates {
         self.client.get().transaction_updates(operation_id).await
@@ -371,13 +484,32 @@ where
         &self,
         operation_id: OperationId,
         outputs: Vec<OutPoint>,
-    ) -> anyhow::Result<Amount> {
+    ) -> Result<Amount, ClientError> {
         self.client
             .get()
             .await_primary_module_outputs(operation_id, outputs)
             .await
     }
 
+    /// Waits for the outcome of one of this module's outputs, see
+    /// [`crate::Client::await_output_outcome`] for how outcomes are cached
+    pub async fn await_output_outcome(
+        &self,
+        outpoint: OutPoint,
+        timeout: Duration,
+        is_terminal: impl Fn(&<M::Common as ModuleCommon>::OutputOutcome) -> bool,
+    ) -> Result<<M::Common as ModuleCommon>::OutputOutcome, OutputOutcomeError> {
+        self.client
+            .get()
+            .await_output_outcome(outpoint, timeout, &M::decoder(), is_terminal)
+            .await
+    }
+
+    /// See [`crate::Client::invalidate_output_outcome`]
+    pub async fn invalidate_output_outcome(&self, outpoint: OutPoint) {
+        self.client.get().invalidate_output_outcome(outpoint).await;
+    }
+
     // TODO: unify with `Self::get_operation`
     pub async fn get_operation(
         &self,
@@ -398,6 +530,25 @@ where
         Ok(operation)
     }
 
+    /// Atomically replaces the meta data of one of this module's operations
+    /// with the result of applying `f` to it, e.g. to attach details that are
+    /// only known once the operation completed
+    pub async fn update_operation_meta<Meta>(
+        &self,
+        operation_id: OperationId,
+        f: impl Fn(Meta) -> Meta + MaybeSync,
+    ) -> anyhow::Result<()>
+    where
+        Meta: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let f = &f;
+        self.module_autocommit_2(
+            |dbtx, _| Box::pin(async move { dbtx.update_operation_meta(operation_id, f).await }),
+            Some(100),
+        )
+        .await
+    }
+
     pub fn global_db(&self) -> fedimint_core::db::Database {
         self.client.get().db().clone()
     }
@@ -438,6 +589,23 @@ where
         self.client.get().get_config().clone()
     }
 
+    /// Core API version negotiated with the federation
+    pub fn core_api_version(&self) -> ApiVersion {
+        self.client.get().api_versions().core
+    }
+
+    /// API version of this module negotiated with the federation, use
+    /// [`ApiVersion::supports`] to only call endpoints the federation offers
+    pub fn module_api_version(&self) -> ApiVersion {
+        *self
+            .client
+            .get()
+            .api_versions()
+            .modules
+            .get(&self.module_instance_id)
+            .expect("Modules are only initialized with a negotiated API version")
+    }
+
     /// Returns an invite code for the federation that points to an arbitrary
     /// guardian server for fetching the config
     pub fn get_invite_code(&self) -> InviteCode {
@@ -459,6 +627,75 @@ where
         self.client.get().get_internal_payment_markers()
     }
 
+    /// Derives the child secret `index` for `purpose` of this module instance
+    ///
+    /// This is the stable way for modules to derive additional secrets
+    /// deterministically from the client root secret, so they can be
+    /// re-derived on recovery. The derivation path (see
+    /// [`crate::secret::module_purpose_secret_path`]) only consists of
+    /// hardened steps, so leaking a derived secret doesn't reveal any of its
+    /// siblings or parents. Each derived path is recorded in the client
+    /// database the first time it is used, see
+    /// [`ClientContext::derived_secrets`].
+    pub async fn derive_secret(&self, purpose: SecretPurpose, index: u64) -> DerivableSecret {
+        let key = DerivedSecretKey {
+            module_instance_id: self.module_instance_id,
+            purpose,
+            index,
+        };
+        let path = module_purpose_secret_path(self.module_instance_id, purpose, index)
+            .into_iter()
+            .map(|child_id| child_id.0)
+            .collect::<Vec<_>>();
+
+        self.global_db()
+            .autocommit::<_, _, anyhow::Error>(
+                |dbtx, _| {
+                    let path = path.clone();
+                    Box::pin(async move {
+                        if dbtx.get_value(&key).await.is_none() {
+                            dbtx.insert_new_entry(
+                                &key,
+                                &DerivedSecretRecord {
+                                    path,
+                                    first_derived: fedimint_core::time::now(),
+                                },
+                            )
+                            .await;
+                        }
+                        Ok(())
+                    })
+                },
+                None,
+            )
+            .await
+            .expect("Failed to record derived secret");
+
+        self.client
+            .get()
+            .root_secret()
+            .derive_module_purpose_secret(self.module_instance_id, purpose, index)
+    }
+
+    /// Returns the stable account id of the client, see
+    /// [`crate::Client::stable_account_id`]
+    pub fn stable_account_id(&self) -> StableAccountId {
+        self.client.get().stable_account_id()
+    }
+
+    /// Returns all secrets this module instance derived via
+    /// [`ClientContext::derive_secret`] so far
+    pub async fn derived_secrets(&self) -> Vec<(DerivedSecretKey, DerivedSecretRecord)> {
+        let db = self.global_db();
+        let mut dbtx = db.begin_transaction_nc().await;
+        dbtx.find_by_prefix(&DerivedSecretModulePrefix {
+            module_instance_id: self.module_instance_id,
+        })
+        .await
+        .collect()
+        .await
+    }
+
     /// This method starts n state machines with given operation id without a
     /// corresponding transaction
     pub async fn manual_operation_start(
@@ -468,7 +705,9 @@ where
         operation_meta: impl serde::Serialize + Debug,
         sms: Vec<DynState>,
     ) -> anyhow::Result<()> {
-        let db = self.client.get().db().clone();
+        let client = self.client.get();
+        client.ensure_accepting_operations()?;
+        let db = client.db().clone();
         let mut dbtx = db.begin_transaction().await;
 
         if Client::operation_exists_dbtx(&mut dbtx.to_ref_nc(), operation_id).await {
@@ -601,6 +840,10 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     ///   takes the transaction id of the transaction in which the output was
     ///   used and the output index as input since these cannot be known at time
     ///   of calling `create_change_output` and have to be injected later.
+    ///
+    /// Besides their static fees, the created inputs and outputs have to pay
+    /// the fees of the module's current `fee_schedule`. If the change can't
+    /// match these exactly the transaction may overpay, see [`FeeSchedule`].
 
     async fn create_final_inputs_and_outputs(
         &self,
@@ -608,6 +851,7 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         _operation_id: OperationId,
         _input_amount: Amount,
         _output_amount: Amount,
+        _fee_schedule: FeeSchedule,
     ) -> anyhow::Result<(
         Vec<ClientInput<<Self::Common as ModuleCommon>::Input, Self::States>>,
         Vec<ClientOutput<<Self::Common as ModuleCommon>::Output, Self::States>>,
@@ -697,6 +941,18 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     async fn leave(&self, _dbtx: &mut DatabaseTransaction<'_>) -> anyhow::Result<()> {
         bail!("Unable to determine if safe to leave the federation: Not implemented")
     }
+
+    /// Requests cooperative cancellation of an operation of this module, see
+    /// [`crate::Client::cancel_operation`].
+    ///
+    /// Implementations should only abort state machines that are still in a
+    /// state where cancelling can't lose funds (e.g. a payment that wasn't
+    /// funded yet) and return an error otherwise. On success the final state
+    /// of the operation's update stream should reflect the cancellation, so
+    /// it gets cached as the outcome of the operation.
+    async fn cancel(&self, _operation_id: OperationId) -> anyhow::Result<()> {
+        bail!("Operations of this module can't be cancelled")
+    }
 }
 
 /// Type-erased version of [`ClientModule`]
@@ -729,6 +985,7 @@ pub trait IClientModule: Debug {
         operation_id: OperationId,
         input_amount: Amount,
         output_amount: Amount,
+        fee_schedule: FeeSchedule,
     ) -> anyhow::Result<(Vec<ClientInput>, Vec<ClientOutput>)>;
 
     async fn await_primary_module_output(
@@ -744,6 +1001,8 @@ pub trait IClientModule: Debug {
     ) -> Amount;
 
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()>;
+
+    async fn cancel(&self, operation_id: OperationId) -> anyhow::Result<()>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -815,6 +1074,7 @@ where
         operation_id: OperationId,
         input_amount: Amount,
         output_amount: Amount,
+        fee_schedule: FeeSchedule,
     ) -> anyhow::Result<(Vec<ClientInput>, Vec<ClientOutput>)> {
         let (inputs, outputs) = <T as ClientModule>::create_final_inputs_and_outputs(
             self,
@@ -822,6 +1082,7 @@ where
             operation_id,
             input_amount,
             output_amount,
+            fee_schedule,
         )
         .await?;
 
@@ -861,6 +1122,10 @@ where
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()> {
         <T as ClientModule>::subscribe_balance_changes(self).await
     }
+
+    async fn cancel(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        <T as ClientModule>::cancel(self, operation_id).await
+    }
 }
 
 dyn_newtype_define!(
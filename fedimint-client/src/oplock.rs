@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use fedimint_core::core::{ModuleInstanceId, OperationId};
+use fedimint_core::encoding::Encodable;
+use thiserror::Error;
+use tokio::sync::Notify;
+
+/// Identifies a logical resource of a client module, e.g. a contract or a
+/// deposit address, that must only be used by one operation at a time
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OperationLockKey {
+    module_instance_id: ModuleInstanceId,
+    resource: Vec<u8>,
+}
+
+impl OperationLockKey {
+    pub fn new(module_instance_id: ModuleInstanceId, resource: &impl Encodable) -> Self {
+        Self {
+            module_instance_id,
+            resource: resource.consensus_encode_to_vec(),
+        }
+    }
+}
+
+/// Another operation is currently using the resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Operation {} is already in progress for this resource", .operation_id.fmt_short())]
+pub struct OperationInProgressError {
+    pub operation_id: OperationId,
+}
+
+/// In-memory locks that serialize concurrent calls on the same resource
+///
+/// Client modules take a lock while they check whether a resource is free and
+/// create the state machines using it. A concurrent call on the same resource
+/// either fails with an [`OperationInProgressError`], see [`Self::try_lock`],
+/// or waits until the resource is released, see [`Self::lock`], instead of
+/// racing and creating conflicting state machines. The locks are not
+/// persisted, anything that outlives the call has to be checked against the
+/// database.
+#[derive(Debug, Clone, Default)]
+pub struct OperationLocks {
+    held: Arc<Mutex<BTreeMap<OperationLockKey, OperationId>>>,
+    /// Notified whenever a lock is released
+    released: Arc<Notify>,
+}
+
+impl OperationLocks {
+    /// Locks `key` for `operation_id` until the returned guard is dropped
+    pub fn try_lock(
+        &self,
+        key: OperationLockKey,
+        operation_id: OperationId,
+    ) -> Result<OperationLockGuard, OperationInProgressError> {
+        let mut held = self.held.lock().expect("poisoned");
+
+        if let Some(holder) = held.get(&key) {
+            return Err(OperationInProgressError {
+                operation_id: *holder,
+            });
+        }
+
+        held.insert(key.clone(), operation_id);
+
+        Ok(OperationLockGuard {
+            held: self.held.clone(),
+            released: self.released.clone(),
+            key,
+            operation_id,
+        })
+    }
+
+    /// Locks `key` for `operation_id` until the returned guard is dropped,
+    /// waiting for other operations to release it first
+    pub async fn lock(
+        &self,
+        key: OperationLockKey,
+        operation_id: OperationId,
+    ) -> OperationLockGuard {
+        loop {
+            // Created before checking the lock, so a release in between is not missed
+            let released = self.released.notified();
+
+            if let Ok(guard) = self.try_lock(key.clone(), operation_id) {
+                return guard;
+            }
+
+            released.await;
+        }
+    }
+
+    /// Returns the operation currently holding the lock on `key`, if any
+    pub fn holder(&self, key: &OperationLockKey) -> Option<OperationId> {
+        self.held.lock().expect("poisoned").get(key).copied()
+    }
+}
+
+/// Releases the lock on its resource when dropped
+#[derive(Debug)]
+pub struct OperationLockGuard {
+    held: Arc<Mutex<BTreeMap<OperationLockKey, OperationId>>>,
+    released: Arc<Notify>,
+    key: OperationLockKey,
+    operation_id: OperationId,
+}
+
+impl OperationLockGuard {
+    pub fn operation_id(&self) -> OperationId {
+        self.operation_id
+    }
+}
+
+impl Drop for OperationLockGuard {
+    fn drop(&mut self) {
+        self.held.lock().expect("poisoned").remove(&self.key);
+        self.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fedimint_core::core::OperationId;
+    use fedimint_core::runtime;
+
+    use super::{OperationInProgressError, OperationLockKey, OperationLocks};
+
+    #[test]
+    fn concurrent_lock_on_same_resource_fails() {
+        let locks = OperationLocks::default();
+        let key = OperationLockKey::new(0, &[42u8; 32]);
+        let first = OperationId::new_random();
+        let second = OperationId::new_random();
+
+        let guard = locks
+            .try_lock(key.clone(), first)
+            .expect("resource is free");
+        assert_eq!(
+            locks.try_lock(key.clone(), second).unwrap_err(),
+            OperationInProgressError {
+                operation_id: first
+            }
+        );
+
+        // Other resources and the same resource of other modules are independent
+        let other_module = OperationLockKey::new(1, &[42u8; 32]);
+        assert!(locks.try_lock(other_module, second).is_ok());
+
+        drop(guard);
+        assert_eq!(locks.holder(&key), None);
+        assert!(locks.try_lock(key, second).is_ok());
+    }
+
+    #[tokio::test]
+    async fn lock_waits_for_release() {
+        let locks = OperationLocks::default();
+        let key = OperationLockKey::new(0, &[42u8; 32]);
+        let first = OperationId::new_random();
+        let second = OperationId::new_random();
+
+        let guard = locks.lock(key.clone(), first).await;
+        assert!(
+            runtime::timeout(Duration::from_millis(100), locks.lock(key.clone(), second))
+                .await
+                .is_err(),
+            "resource is still locked"
+        );
+
+        let waiting = locks.lock(key.clone(), second);
+        drop(guard);
+        let guard = runtime::timeout(Duration::from_secs(1), waiting)
+            .await
+            .expect("resource was released");
+        assert_eq!(guard.operation_id(), second);
+        assert_eq!(locks.holder(&key), Some(second));
+    }
+}
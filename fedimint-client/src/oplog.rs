@@ -2,8 +2,9 @@ use std::fmt::Debug;
 use std::future;
 use std::io::{Read, Write};
 
+use anyhow::{bail, ensure, Context};
 use async_stream::stream;
-use fedimint_core::core::OperationId;
+use fedimint_core::core::{ModuleKind, OperationId};
 use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
@@ -36,16 +37,50 @@ impl OperationLog {
         operation_type: &str,
         operation_meta: impl serde::Serialize,
     ) {
-        dbtx.insert_new_entry(
-            &OperationLogKey { operation_id },
-            &OperationLogEntry {
+        Self::insert_operation_log_entry(
+            dbtx,
+            operation_id,
+            OperationLogEntry {
                 operation_module_kind: operation_type.to_string(),
                 meta: serde_json::to_value(operation_meta)
                     .expect("Can only fail if meta is not serializable"),
+                meta_version: 0,
+                outcome: None,
+            },
+        )
+        .await;
+    }
+
+    /// Like [`OperationLog::add_operation_log_entry`], but stores the meta
+    /// together with its [`OperationMeta::VERSION`], so it can be migrated by
+    /// [`OperationLogEntry::meta_typed`] once the format changes
+    pub async fn add_typed_operation_log_entry<M: OperationMeta>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation_meta: &M,
+    ) {
+        Self::insert_operation_log_entry(
+            dbtx,
+            operation_id,
+            OperationLogEntry {
+                operation_module_kind: M::KIND.to_string(),
+                meta: serde_json::to_value(operation_meta)
+                    .expect("Can only fail if meta is not serializable"),
+                meta_version: M::VERSION,
                 outcome: None,
             },
         )
         .await;
+    }
+
+    async fn insert_operation_log_entry(
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        entry: OperationLogEntry,
+    ) {
+        dbtx.insert_new_entry(&OperationLogKey { operation_id }, &entry)
+            .await;
         dbtx.insert_new_entry(
             &ChronologicalOperationLogKey {
                 creation_time: now(),
@@ -56,6 +91,36 @@ impl OperationLog {
         .await;
     }
 
+    /// Replaces the meta data of an operation with the result of applying `f`
+    /// to it. Fails if the operation doesn't exist, was created by a module
+    /// other than `operation_type` or its meta can't be deserialized as `M`.
+    pub async fn update_operation_meta<M>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation_type: &str,
+        f: impl FnOnce(M) -> M,
+    ) -> anyhow::Result<()>
+    where
+        M: Serialize + DeserializeOwned,
+    {
+        let mut operation = Self::get_operation_inner(dbtx, operation_id)
+            .await
+            .context("Operation not found")?;
+        ensure!(
+            operation.operation_module_kind == operation_type,
+            "Operation was created by a different module"
+        );
+
+        let meta = serde_json::from_value(operation.meta).context("Failed to deserialize meta")?;
+        operation.meta =
+            serde_json::to_value(f(meta)).expect("Can only fail if meta is not serializable");
+        dbtx.insert_entry(&OperationLogKey { operation_id }, &operation)
+            .await;
+
+        Ok(())
+    }
+
     /// Returns the last `limit` operations. To fetch the next page, pass the
     /// last operation's [`ChronologicalOperationLogKey`] as `start_after`.
     pub async fn list_operations(
@@ -155,6 +220,28 @@ impl OperationLog {
     }
 }
 
+/// Meta data a module attaches to its operations, read with
+/// [`OperationLogEntry::meta_typed`].
+///
+/// The meta is stored as JSON together with its [`OperationMeta::VERSION`].
+/// When the format of the meta changes the version has to be increased and
+/// [`OperationMeta::migrate`] has to convert meta of the previous version, so
+/// operations created by older clients can still be read. Meta stored before
+/// the module implemented this trait, or with
+/// [`OperationLog::add_operation_log_entry`], has version 0.
+pub trait OperationMeta: Serialize + DeserializeOwned {
+    /// Kind of the module that creates operations with this meta
+    const KIND: ModuleKind;
+
+    /// Current version of the meta format
+    const VERSION: u16;
+
+    /// Converts meta of format `version` to format `version + 1`
+    fn migrate(version: u16, _meta: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        bail!("No migration from operation meta version {version}")
+    }
+}
+
 /// Represents an operation triggered by a user, typically related to sending or
 /// receiving money.
 ///
@@ -164,7 +251,9 @@ impl OperationLog {
 ///   2. The [`OperationLogEntry::meta`] function returns static meta data that
 ///      was associated with the operation when it was created. Modules define
 ///      their own meta structures, so the module kind has to be used to
-///      determine the structure of the meta data.
+///      determine the structure of the meta data. Meta structures implementing
+///      [`OperationMeta`] can be read with [`OperationLogEntry::meta_typed`]
+///      instead.
 ///   3. To find out the current state of the operation there is a two-step
 ///      process:
 ///     * First, the [`OperationLogEntry::outcome`] function returns the outcome
@@ -178,6 +267,9 @@ impl OperationLog {
 pub struct OperationLogEntry {
     operation_module_kind: String,
     meta: serde_json::Value,
+    /// Version of the format of `meta`, see [`OperationMeta::VERSION`]
+    #[serde(default)]
+    meta_version: u16,
     // TODO: probably change all that JSON to Dyn-types
     pub(crate) outcome: Option<serde_json::Value>,
 }
@@ -197,6 +289,35 @@ impl OperationLogEntry {
         serde_json::from_value(self.meta.clone()).expect("JSON deserialization should not fail")
     }
 
+    /// Returns the meta data of the operation as the meta type of the module
+    /// that created it, migrating meta that was stored in an older format.
+    /// Fails if the operation was created by a different module kind.
+    pub fn meta_typed<M: OperationMeta>(&self) -> anyhow::Result<M> {
+        ensure!(
+            self.operation_module_kind == M::KIND.as_str(),
+            "Operation was created by module kind {}, not {}",
+            self.operation_module_kind,
+            M::KIND
+        );
+
+        if M::VERSION < self.meta_version {
+            bail!(
+                "Operation meta has version {}, but only version {} is supported",
+                self.meta_version,
+                M::VERSION
+            );
+        }
+
+        let mut meta = self.meta.clone();
+        for version in self.meta_version..M::VERSION {
+            meta = M::migrate(version, meta).with_context(|| {
+                format!("Failed to migrate operation meta of version {version}")
+            })?;
+        }
+
+        serde_json::from_value(meta).context("Failed to deserialize operation meta")
+    }
+
     /// Returns the last state update of the operation, if any was cached yet.
     /// If this hasn't been the case yet and `None` is returned subscribe to the
     /// appropriate update stream.
@@ -259,6 +380,7 @@ impl Encodable for OperationLogEntry {
                 serde_json::to_string(outcome).expect("JSON serialization should not fail")
             })
             .consensus_encode(writer)?;
+        len += self.meta_version.consensus_encode(writer)?;
 
         Ok(len)
     }
@@ -279,9 +401,20 @@ impl Decodable for OperationLogEntry {
             .map(|outcome_str| serde_json::from_str(&outcome_str).map_err(DecodeError::from_err))
             .transpose()?;
 
+        // Entries written before meta was versioned end after the outcome
+        let mut meta_version_bytes = Vec::new();
+        r.read_to_end(&mut meta_version_bytes)
+            .map_err(DecodeError::from_err)?;
+        let meta_version = if meta_version_bytes.is_empty() {
+            0
+        } else {
+            u16::consensus_decode_vec(meta_version_bytes, modules)?
+        };
+
         Ok(OperationLogEntry {
             operation_module_kind: operation_type,
             meta,
+            meta_version,
             outcome,
         })
     }
@@ -341,21 +474,23 @@ where
 
 #[cfg(test)]
 mod tests {
-    use fedimint_core::core::OperationId;
+    use fedimint_core::core::{ModuleKind, OperationId};
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::{Database, IRawDatabaseExt};
+    use fedimint_core::encoding::{Decodable, Encodable};
     use futures::stream::StreamExt;
     use serde::{Deserialize, Serialize};
 
     use super::UpdateStreamOrOutcome;
     use crate::db::ChronologicalOperationLogKey;
-    use crate::oplog::{OperationLog, OperationLogEntry};
+    use crate::oplog::{OperationLog, OperationLogEntry, OperationMeta};
 
     #[test]
     fn test_operation_log_entry_serde() {
         let op_log = OperationLogEntry {
             operation_module_kind: "test".to_string(),
             meta: serde_json::to_value(()).unwrap(),
+            meta_version: 0,
             outcome: None,
         };
 
@@ -378,12 +513,97 @@ mod tests {
         let op_log = OperationLogEntry {
             operation_module_kind: "test".to_string(),
             meta: serde_json::to_value(meta.clone()).unwrap(),
+            meta_version: 0,
             outcome: None,
         };
 
         assert_eq!(op_log.meta::<Meta>(), meta);
     }
 
+    #[test]
+    fn test_operation_log_entry_meta_typed_migration() {
+        #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+        struct Meta {
+            amount_msat: u64,
+        }
+
+        impl OperationMeta for Meta {
+            const KIND: ModuleKind = ModuleKind::from_static_str("test");
+            const VERSION: u16 = 1;
+
+            fn migrate(version: u16, meta: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+                assert_eq!(version, 0);
+                Ok(
+                    serde_json::json!({ "amount_msat": meta["amount_sat"].as_u64().unwrap() * 1000 }),
+                )
+            }
+        }
+
+        let legacy = OperationLogEntry {
+            operation_module_kind: "test".to_string(),
+            meta: serde_json::json!({ "amount_sat": 2 }),
+            meta_version: 0,
+            outcome: None,
+        };
+        assert_eq!(
+            legacy.meta_typed::<Meta>().unwrap(),
+            Meta { amount_msat: 2000 }
+        );
+
+        let current = OperationLogEntry {
+            operation_module_kind: "test".to_string(),
+            meta: serde_json::json!({ "amount_msat": 3 }),
+            meta_version: 1,
+            outcome: None,
+        };
+        assert_eq!(
+            current.meta_typed::<Meta>().unwrap(),
+            Meta { amount_msat: 3 }
+        );
+
+        let newer = OperationLogEntry {
+            meta_version: 2,
+            ..current
+        };
+        assert!(newer.meta_typed::<Meta>().is_err());
+
+        let other_kind = OperationLogEntry {
+            operation_module_kind: "other".to_string(),
+            ..legacy
+        };
+        assert!(other_kind.meta_typed::<Meta>().is_err());
+    }
+
+    #[test]
+    fn test_operation_log_entry_decode_unversioned() {
+        let mut legacy_bytes = Vec::new();
+        "test"
+            .to_string()
+            .consensus_encode(&mut legacy_bytes)
+            .unwrap();
+        "\"meta\""
+            .to_string()
+            .consensus_encode(&mut legacy_bytes)
+            .unwrap();
+        None::<String>.consensus_encode(&mut legacy_bytes).unwrap();
+
+        let entry = OperationLogEntry::consensus_decode_vec(legacy_bytes, &Default::default())
+            .expect("Legacy entry decodes");
+        assert_eq!(entry.meta_version, 0);
+        assert_eq!(entry.meta::<String>(), "meta");
+
+        let versioned = OperationLogEntry {
+            meta_version: 3,
+            ..entry
+        };
+        let decoded = OperationLogEntry::consensus_decode_vec(
+            versioned.consensus_encode_to_vec(),
+            &Default::default(),
+        )
+        .expect("Versioned entry decodes");
+        assert_eq!(decoded.meta_version, 3);
+    }
+
     #[tokio::test]
     async fn test_operation_log_update() {
         let op_id = OperationId([0x32; 32]);
@@ -422,6 +642,40 @@ mod tests {
         assert_eq!(updates, vec!["baz"]);
     }
 
+    #[tokio::test]
+    async fn test_operation_meta_update() {
+        let op_id = OperationId([0x32; 32]);
+
+        let db = MemDatabase::new().into_database();
+        let op_log = OperationLog::new(db.clone());
+
+        let mut dbtx = db.begin_transaction().await;
+        op_log
+            .add_operation_log_entry(&mut dbtx.to_ref_nc(), op_id, "foo", "bar")
+            .await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+        op_log
+            .update_operation_meta(&mut dbtx.to_ref_nc(), op_id, "foo", |meta: String| {
+                meta + "baz"
+            })
+            .await
+            .unwrap();
+        assert!(op_log
+            .update_operation_meta(&mut dbtx.to_ref_nc(), op_id, "other", |meta: String| meta)
+            .await
+            .is_err());
+        assert!(op_log
+            .update_operation_meta(&mut dbtx.to_ref_nc(), op_id, "foo", |meta: u64| meta)
+            .await
+            .is_err());
+        dbtx.commit_tx().await;
+
+        let op = op_log.get_operation(op_id).await.expect("op exists");
+        assert_eq!(op.meta::<String>(), "barbaz");
+    }
+
     #[tokio::test]
     async fn test_operation_log_update_from_stream() {
         let op_id = OperationId([0x32; 32]);
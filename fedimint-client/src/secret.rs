@@ -1,18 +1,102 @@
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
+use bitcoin::hashes::{sha256, Hash, HashEngine};
 use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
 use rand::{CryptoRng, Rng, RngCore};
+use serde::{Deserialize, Serialize};
 
 const TYPE_MODULE: ChildId = ChildId(0);
 const TYPE_BACKUP: ChildId = ChildId(1);
+const TYPE_MODULE_PURPOSE: ChildId = ChildId(2);
+const TYPE_ACCOUNT_ID: ChildId = ChildId(3);
+
+/// Identifies what a secret derived by a module through
+/// [`crate::module::ClientContext::derive_secret`] is used for.
+///
+/// Purposes are scoped to the module instance, so different modules can use
+/// the same values without deriving the same secrets.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Encodable,
+    Decodable,
+    Serialize,
+    Deserialize,
+)]
+pub struct SecretPurpose(pub u64);
+
+/// Identifier of a client in a federation that stays the same for the
+/// lifetime of its root secret, e.g. across recoveries, see
+/// [`crate::Client::stable_account_id`]
+///
+/// It is derived from a dedicated branch of the client root secret, so sharing
+/// it with third parties reveals nothing about the keys the client spends
+/// with.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Encodable,
+    Decodable,
+    Serialize,
+    Deserialize,
+)]
+pub struct StableAccountId(pub sha256::Hash);
+
+impl StableAccountId {
+    /// Derives the account id from the client root secret of the federation
+    /// `federation_id`
+    pub fn derive(client_root_secret: &DerivableSecret, federation_id: FederationId) -> Self {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&federation_id.0.to_byte_array());
+        engine.input(
+            &client_root_secret
+                .derive_account_id_secret()
+                .to_random_bytes::<32>(),
+        );
+        StableAccountId(sha256::Hash::from_engine(engine))
+    }
+}
+
+impl std::fmt::Display for StableAccountId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl std::str::FromStr for StableAccountId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(StableAccountId(s.parse::<sha256::Hash>()?))
+    }
+}
 
 pub trait DeriveableSecretClientExt {
     fn derive_module_secret(&self, module_instance_id: ModuleInstanceId) -> DerivableSecret;
     fn derive_backup_secret(&self) -> DerivableSecret;
+    fn derive_account_id_secret(&self) -> DerivableSecret;
+    fn derive_module_purpose_secret(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        purpose: SecretPurpose,
+        index: u64,
+    ) -> DerivableSecret;
 }
 
 impl DeriveableSecretClientExt for DerivableSecret {
@@ -26,6 +110,44 @@ impl DeriveableSecretClientExt for DerivableSecret {
         assert_eq!(self.level(), 0);
         self.child_key(TYPE_BACKUP)
     }
+
+    fn derive_account_id_secret(&self) -> DerivableSecret {
+        assert_eq!(self.level(), 0);
+        self.child_key(TYPE_ACCOUNT_ID)
+    }
+
+    fn derive_module_purpose_secret(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        purpose: SecretPurpose,
+        index: u64,
+    ) -> DerivableSecret {
+        assert_eq!(self.level(), 0);
+        module_purpose_secret_path(module_instance_id, purpose, index)
+            .into_iter()
+            .fold(self.clone(), |secret, child_id| secret.child_key(child_id))
+    }
+}
+
+/// Derivation path, relative to the client root secret, of the secret `index`
+/// derived for `purpose` by the module instance `module_instance_id`:
+///
+/// `client_root_secret/<key-type=module-purpose=2>/<module-instance-id>/
+/// <purpose>/<index>`
+///
+/// The path is disjoint from the module root secret handed to modules on
+/// initialization, so it can't collide with secrets modules derived from it.
+pub fn module_purpose_secret_path(
+    module_instance_id: ModuleInstanceId,
+    purpose: SecretPurpose,
+    index: u64,
+) -> Vec<ChildId> {
+    vec![
+        TYPE_MODULE_PURPOSE,
+        ChildId(u64::from(module_instance_id)),
+        ChildId(purpose.0),
+        ChildId(index),
+    ]
 }
 
 /// Trait defining a way to generate, serialize and deserialize a root secret.
@@ -109,3 +231,62 @@ pub fn get_default_client_secret(
     let federation_wallet_root_secret = federation_root_secret.child_key(ChildId(0)); // wallet-number=0
     federation_wallet_root_secret.child_key(ChildId(0)) // key-type=fedimint-client=0
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+    use fedimint_derive_secret::{ChildId, DerivableSecret};
+
+    use super::{DeriveableSecretClientExt, SecretPurpose, StableAccountId};
+
+    #[test]
+    fn module_purpose_secrets_are_isolated() {
+        let root = DerivableSecret::new_root(&[42; 64], b"test");
+
+        let secret = root.derive_module_purpose_secret(1, SecretPurpose(3), 7);
+        let expected = root
+            .child_key(ChildId(2))
+            .child_key(ChildId(1))
+            .child_key(ChildId(3))
+            .child_key(ChildId(7));
+        assert_eq!(
+            secret.to_random_bytes::<32>(),
+            expected.to_random_bytes::<32>()
+        );
+
+        let other_module = root.derive_module_purpose_secret(2, SecretPurpose(3), 7);
+        let other_index = root.derive_module_purpose_secret(1, SecretPurpose(3), 8);
+        let module_root = root.derive_module_secret(1);
+        for other in [other_module, other_index, module_root] {
+            assert_ne!(
+                secret.to_random_bytes::<32>(),
+                other.to_random_bytes::<32>()
+            );
+        }
+    }
+
+    #[test]
+    fn stable_account_id_is_deterministic_per_federation() {
+        let root = DerivableSecret::new_root(&[42; 64], b"test");
+        let federation_id = FederationId::dummy();
+        let other_federation_id: FederationId = "ab".repeat(32).parse().unwrap();
+
+        let account_id =
+            StableAccountId::derive(&root.federation_key(&federation_id), federation_id);
+        assert_eq!(
+            account_id,
+            StableAccountId::derive(&root.federation_key(&federation_id), federation_id)
+        );
+        assert_ne!(
+            account_id,
+            StableAccountId::derive(
+                &root.federation_key(&other_federation_id),
+                other_federation_id
+            )
+        );
+        assert_eq!(
+            account_id.to_string().parse::<StableAccountId>().unwrap(),
+            account_id
+        );
+    }
+}
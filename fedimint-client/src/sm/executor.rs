@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
 use std::fmt::{Debug, Formatter};
 use std::io::{Error, Read, Write};
@@ -21,7 +21,7 @@ use fedimint_logging::LOG_CLIENT_REACTOR;
 use futures::future::{self, select_all};
 use futures::stream::{FuturesUnordered, StreamExt};
 use tokio::select;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, Mutex, Semaphore};
 use tracing::{debug, error, info, trace, warn, Instrument};
 
 use super::state::StateTransitionFunction;
@@ -33,6 +33,10 @@ use crate::{AddStateMachinesError, AddStateMachinesResult, DynGlobalClientContex
 /// After how many attempts a DB transaction is aborted with an error
 const MAX_DB_ATTEMPTS: Option<usize> = Some(100);
 
+/// Maximum number of state transitions the executor applies at the same time,
+/// see the `executor` benchmark
+const MAX_PARALLEL_TRANSITIONS: usize = 32;
+
 pub type ContextGen =
     Arc<maybe_add_send_sync!(dyn Fn(ModuleInstanceId, OperationId) -> DynGlobalClientContext)>;
 
@@ -51,6 +55,10 @@ enum ExecutorDbPrefixes {
 /// The executor is aware of the concept of Fedimint modules and can give state
 /// machines a different [execution context](super::state::Context) depending on
 /// the owning module, making it very flexible.
+///
+/// Transitions of different operations are applied in parallel by up to
+/// [`MAX_PARALLEL_TRANSITIONS`] workers, while the transitions of a single
+/// operation are applied one at a time in the order they were triggered.
 #[derive(Clone, Debug)]
 pub struct Executor {
     inner: Arc<ExecutorInner>,
@@ -67,6 +75,9 @@ struct ExecutorInner {
     /// was created), it's must be sent through this channel for it to notice.
     sm_update_tx: mpsc::UnboundedSender<DynState>,
     sm_update_rx: Mutex<Option<mpsc::UnboundedReceiver<DynState>>>,
+    /// Requests to stop applying new transitions, see [`Executor::quiesce`]
+    quiesce_tx: mpsc::UnboundedSender<oneshot::Sender<()>>,
+    quiesce_rx: Mutex<Option<mpsc::UnboundedReceiver<oneshot::Sender<()>>>>,
     client_task_group: TaskGroup,
 }
 
@@ -288,6 +299,13 @@ impl Executor {
             .await
             .take()
             .expect("start_executor was called previously: no sm_update_rx available");
+        let quiesce_rx = self
+            .inner
+            .quiesce_rx
+            .lock()
+            .await
+            .take()
+            .expect("start_executor was called previously: no quiesce_rx available");
 
         let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
 
@@ -305,7 +323,7 @@ impl Executor {
 
         let task_runner_inner = self.inner.clone();
         let _handle = self.inner.client_task_group.spawn("sm-executor", |task_handle| async move {
-            let executor_runner = task_runner_inner.run(context_gen, sm_update_rx);
+            let executor_runner = task_runner_inner.run(context_gen, sm_update_rx, quiesce_rx);
             let task_group_shutdown_rx = task_handle.make_shutdown_rx().await;
             select! {
                 _ = task_group_shutdown_rx => {
@@ -345,6 +363,28 @@ impl Executor {
         self.inner.stop_executor()
     }
 
+    /// Stops applying new state transitions and waits until all transitions
+    /// that are currently being applied have been committed.
+    ///
+    /// Triggers that fire afterwards are ignored, their states stay active in
+    /// the database and are picked up again when the executor is restarted.
+    /// The executor can't be resumed, it's meant to be followed by
+    /// [`Executor::stop_executor`]. Returns immediately if the executor isn't
+    /// running.
+    pub async fn quiesce(&self) {
+        if self.inner.context.lock().await.is_none() {
+            return;
+        }
+
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.inner.quiesce_tx.send(done_tx).is_err() {
+            return;
+        }
+        // If the executor stopped or stops in the meantime, the request is dropped
+        // and there is nothing left to wait for
+        let _ = done_rx.await;
+    }
+
     /// Returns a reference to the [`Notifier`] that can be used to subscribe to
     /// state transitions
     pub fn notifier(&self) -> &Notifier {
@@ -358,6 +398,31 @@ impl Drop for ExecutorInner {
     }
 }
 
+/// All futures in the executor resolve to this type, so the handling
+/// code can tell them apart.
+enum ExecutorLoopEvent {
+    /// Notification about `DynState` arrived and should be handled,
+    /// usually added to the list of pending futures.
+    New { state: DynState },
+    /// One of trigger futures of a state machine finished and
+    /// returned transition function to run
+    Triggered(TransitionForActiveState),
+    /// Transition function and all the accounting around it are done
+    Completed {
+        state: DynState,
+        outcome: ActiveOrInactiveState,
+    },
+    /// Transition function panicked, so its database transaction was never
+    /// committed and the state machine stays in `state` until the client is
+    /// restarted
+    Failed { state: DynState },
+    /// Stop applying new transitions and signal `done` once the running ones
+    /// were committed
+    Quiesce { done: oneshot::Sender<()> },
+    /// New job receiver disconnected, that can only mean termination
+    Disconnected,
+}
+
 struct TransitionForActiveState {
     outcome: serde_json::Value,
     state: DynState,
@@ -365,15 +430,44 @@ struct TransitionForActiveState {
     transition_fn: StateTransitionFunction<DynState>,
 }
 
+/// Takes the next transition of `operation_id` that was triggered while one of
+/// its transitions was applied, or marks the operation as no longer running if
+/// there is none or the executor is quiescing
+fn next_queued_transition(
+    operation_id: OperationId,
+    quiescing: bool,
+    queued_transitions: &mut HashMap<OperationId, VecDeque<TransitionForActiveState>>,
+    running_operations: &mut HashSet<OperationId>,
+) -> Option<TransitionForActiveState> {
+    let next = queued_transitions
+        .get_mut(&operation_id)
+        .and_then(VecDeque::pop_front);
+    if quiescing
+        || queued_transitions
+            .get(&operation_id)
+            .is_some_and(VecDeque::is_empty)
+    {
+        queued_transitions.remove(&operation_id);
+    }
+    match next {
+        Some(transition) if !quiescing => Some(transition),
+        _ => {
+            running_operations.remove(&operation_id);
+            None
+        }
+    }
+}
+
 impl ExecutorInner {
     async fn run(
         &self,
         global_context_gen: ContextGen,
         sm_update_rx: tokio::sync::mpsc::UnboundedReceiver<DynState>,
+        quiesce_rx: mpsc::UnboundedReceiver<oneshot::Sender<()>>,
     ) {
         debug!(target: LOG_CLIENT_REACTOR, "Starting state machine executor task");
         if let Err(err) = self
-            .run_state_machines_executor_inner(global_context_gen, sm_update_rx)
+            .run_state_machines_executor_inner(global_context_gen, sm_update_rx, quiesce_rx)
             .await
         {
             warn!(
@@ -447,6 +541,7 @@ impl ExecutorInner {
         &self,
         global_context_gen: ContextGen,
         mut sm_update_rx: tokio::sync::mpsc::UnboundedReceiver<DynState>,
+        mut quiesce_rx: mpsc::UnboundedReceiver<oneshot::Sender<()>>,
     ) -> anyhow::Result<()> {
         let active_states = self.get_active_states().await;
         trace!(target: LOG_CLIENT_REACTOR, "Starting active states: {:?}", active_states);
@@ -456,27 +551,19 @@ impl ExecutorInner {
                 .expect("Must be able to send state machine to own opened channel");
         }
 
-        /// All futures in the executor resolve to this type, so the handling
-        /// code can tell them apart.
-        enum ExecutorLoopEvent {
-            /// Notification about `DynState` arrived and should be handled,
-            /// usually added to the list of pending futures.
-            New { state: DynState },
-            /// One of trigger futures of a state machine finished and
-            /// returned transition function to run
-            Triggered(TransitionForActiveState),
-            /// Transition function and all the accounting around it are done
-            Completed {
-                state: DynState,
-                outcome: ActiveOrInactiveState,
-            },
-            /// New job receiver disconnected, that can only mean termination
-            Disconnected,
-        }
-
         // Keeps track of things already running, so we can deduplicate, just
         // in case.
         let mut currently_running_sms = HashSet::<DynState>::new();
+        // Operations with a transition being applied right now, and the
+        // transitions of these operations that were triggered in the meantime
+        let mut running_operations = HashSet::<OperationId>::new();
+        let mut queued_transitions =
+            HashMap::<OperationId, VecDeque<TransitionForActiveState>>::new();
+        let transition_workers = Arc::new(Semaphore::new(MAX_PARALLEL_TRANSITIONS));
+        // Once quiescing no new transitions are started, the waiters are signalled
+        // when the last running transition completed
+        let mut quiescing = false;
+        let mut quiesce_waiters = Vec::<oneshot::Sender<()>>::new();
         // All things happening in parallel go into here
         let mut futures: FuturesUnordered<BoxFuture<'_, ExecutorLoopEvent>> =
             FuturesUnordered::new();
@@ -493,12 +580,17 @@ impl ExecutorInner {
                     }
                 },
 
+                Some(done) = quiesce_rx.recv() => ExecutorLoopEvent::Quiesce { done },
+
                 event = futures.next(), if !futures.is_empty() => event.expect("we only .next() if there are pending futures"),
             };
 
             // main reactor loop: wait for next thing that completed, react (possibly adding
             // more things to `futures`)
             match event {
+                ExecutorLoopEvent::New { .. } | ExecutorLoopEvent::Triggered(_) if quiescing => {
+                    trace!(target: LOG_CLIENT_REACTOR, "Executor is quiescing, ignoring state machine update");
+                }
                 ExecutorLoopEvent::New { state } => {
                     if currently_running_sms.contains(&state) {
                         warn!(target: LOG_CLIENT_REACTOR, operation_id = %state.operation_id().fmt_short(), "Received a state machine that is already running. Ignoring");
@@ -528,133 +620,27 @@ impl ExecutorInner {
                     debug!(target: LOG_CLIENT_REACTOR, operation_id = %state.operation_id().fmt_short(), total = futures.len(), transitions_num, "New active state machine.");
                     trace!(target: LOG_CLIENT_REACTOR, state = ?state, "Started new active state machine, details.");
                 }
-                ExecutorLoopEvent::Triggered(TransitionForActiveState {
-                    outcome,
-                    state,
-                    meta,
-                    transition_fn,
-                }) => {
-                    debug!(
-                        target: LOG_CLIENT_REACTOR,
-                        operation_id = %state.operation_id().fmt_short(),
-                        "Triggered state transition",
-                    );
-                    let span = tracing::debug_span!(
-                        target: LOG_CLIENT_REACTOR,
-                        "sm_transition",
-                        operation_id = %state.operation_id().fmt_short()
-                    );
-                    // Perform the transition as another future, so transitions can happen in
-                    // parallel.
-                    // Database write conflicts might be happening quite often here,
-                    // but transaction functions are supposed to be idempotent anyway,
-                    // so it seems like a good stress-test in the worst case.
-                    futures.push({
-                        let sm_update_tx = self.sm_update_tx.clone();
-                        let db = self.db.clone();
-                        let notifier = self.notifier.clone();
-                        let module_contexts = self.module_contexts.clone();
-                        let global_context_gen = global_context_gen.clone();
-                        Box::pin(
-                            async move {
-                                debug!(
-                                    target: LOG_CLIENT_REACTOR,
-                                    "Executing state transition",
-                                );
-                                trace!(
-                                    target: LOG_CLIENT_REACTOR,
-                                    ?state,
-                                    outcome = ?AbbreviateJson(&outcome),
-                                    "Executing state transition (details)",
-                                );
-
-                                let module_contexts = &module_contexts;
-                                let global_context_gen = &global_context_gen;
-
-                                let outcome = db
-                                    .autocommit::<'_, '_, _, _, Infallible>(
-                                        |dbtx, _| {
-                                            let state = state.clone();
-                                            let transition_fn = transition_fn.clone();
-                                            let transition_outcome = outcome.clone();
-                                            Box::pin(async move {
-                                                let new_state = transition_fn(
-                                                    &mut ClientSMDatabaseTransaction::new(
-                                                        &mut dbtx.to_ref(),
-                                                        state.module_instance_id(),
-                                                    ),
-                                                    transition_outcome,
-                                                    state.clone(),
-                                                )
-                                                .await;
-                                                dbtx.remove_entry(&ActiveStateKey::from_state(
-                                                    state.clone(),
-                                                ))
-                                                .await;
-                                                dbtx.insert_entry(
-                                                    &InactiveStateKey::from_state(state.clone()),
-                                                    &meta.into_inactive(),
-                                                )
-                                                .await;
-
-                                                let context = &module_contexts
-                                                    .get(&state.module_instance_id())
-                                                    .expect("Unknown module");
-
-                                                let global_context = global_context_gen(
-                                                    state.module_instance_id(),
-                                                    state.operation_id(),
-                                                );
-                                                if new_state.is_terminal(context, &global_context) {
-                                                    let k = InactiveStateKey::from_state(
-                                                        new_state.clone(),
-                                                    );
-                                                    let v = ActiveStateMeta::default().into_inactive();
-                                                    dbtx.insert_entry(&k, &v).await;
-                                                    Ok(ActiveOrInactiveState::Inactive {
-                                                        dyn_state: new_state,
-                                                    })
-                                                } else {
-                                                    let k = ActiveStateKey::from_state(
-                                                        new_state.clone(),
-                                                    );
-                                                    let v = ActiveStateMeta::default();
-                                                    dbtx.insert_entry(&k, &v).await;
-                                                    Ok(ActiveOrInactiveState::Active {
-                                                        dyn_state: new_state,
-                                                        meta: v,
-                                                    })
-                                                }
-                                            })
-                                        },
-                                        None,
-                                    )
-                                    .await
-                                    .expect("autocommit should keep trying to commit (max_attempt: None) and body doesn't return errors");
-
-                                debug!(
-                                    target: LOG_CLIENT_REACTOR,
-                                    terminal = !outcome.is_active(),
-                                    ?outcome,
-                                    "State transition complete",
-                                );
-
-                                match &outcome {
-                                    ActiveOrInactiveState::Active { dyn_state, meta: _ } => {
-                                        sm_update_tx
-                                            .send(dyn_state.clone())
-                                            .expect("can't fail: we are the receiving end");
-                                        notifier.notify(dyn_state.clone());
-                                    }
-                                    ActiveOrInactiveState::Inactive { dyn_state } => {
-                                        notifier.notify(dyn_state.clone());
-                                    }
-                                }
-                                ExecutorLoopEvent::Completed { state, outcome }
-                            }
-                            .instrument(span),
-                        )
-                    });
+                ExecutorLoopEvent::Triggered(transition) => {
+                    let operation_id = transition.state.operation_id();
+                    // Transitions of one operation are applied in the order they were
+                    // triggered, so they never race each other's database changes
+                    if !running_operations.insert(operation_id) {
+                        debug!(
+                            target: LOG_CLIENT_REACTOR,
+                            operation_id = %operation_id.fmt_short(),
+                            "Queued state transition behind a running one of the same operation",
+                        );
+                        queued_transitions
+                            .entry(operation_id)
+                            .or_default()
+                            .push_back(transition);
+                        continue;
+                    }
+                    futures.push(self.spawn_transition(
+                        transition,
+                        &global_context_gen,
+                        &transition_workers,
+                    ));
                 }
                 ExecutorLoopEvent::Completed { state, outcome } => {
                     assert!(
@@ -674,17 +660,215 @@ impl ExecutorInner {
                         operation_id = %state.operation_id().fmt_short(), total = futures.len(),
                         "State transition complete"
                     );
+
+                    if let Some(transition) = next_queued_transition(
+                        state.operation_id(),
+                        quiescing,
+                        &mut queued_transitions,
+                        &mut running_operations,
+                    ) {
+                        futures.push(self.spawn_transition(
+                            transition,
+                            &global_context_gen,
+                            &transition_workers,
+                        ));
+                    }
+                }
+                ExecutorLoopEvent::Failed { state } => {
+                    assert!(
+                        currently_running_sms.remove(&state),
+                        "State must have been recorded"
+                    );
+                    error!(
+                        target: LOG_CLIENT_REACTOR,
+                        operation_id = %state.operation_id().fmt_short(),
+                        module_instance_id = state.module_instance_id(),
+                        ?state,
+                        "State transition panicked, the state machine will be retried after a restart. Please report this bug upstream."
+                    );
+
+                    // Other transitions of the operation are independent state machines
+                    if let Some(transition) = next_queued_transition(
+                        state.operation_id(),
+                        quiescing,
+                        &mut queued_transitions,
+                        &mut running_operations,
+                    ) {
+                        futures.push(self.spawn_transition(
+                            transition,
+                            &global_context_gen,
+                            &transition_workers,
+                        ));
+                    }
+                }
+                ExecutorLoopEvent::Quiesce { done } => {
+                    debug!(
+                        target: LOG_CLIENT_REACTOR,
+                        running = running_operations.len(),
+                        "Quiescing state machine executor"
+                    );
+                    quiescing = true;
+                    quiesce_waiters.push(done);
                 }
                 ExecutorLoopEvent::Disconnected => {
                     break;
                 }
             }
+
+            if quiescing && running_operations.is_empty() {
+                for done in quiesce_waiters.drain(..) {
+                    let _ = done.send(());
+                }
+            }
         }
 
         info!(target: LOG_CLIENT_REACTOR, "Terminated.");
         Ok(())
     }
 
+    /// Applies a triggered transition on its own task once one of the
+    /// `transition_workers` is free, so transitions of different operations
+    /// run in parallel
+    fn spawn_transition(
+        &self,
+        transition: TransitionForActiveState,
+        global_context_gen: &ContextGen,
+        transition_workers: &Arc<Semaphore>,
+    ) -> BoxFuture<'static, ExecutorLoopEvent> {
+        let TransitionForActiveState {
+            outcome,
+            state,
+            meta,
+            transition_fn,
+        } = transition;
+        debug!(
+            target: LOG_CLIENT_REACTOR,
+            operation_id = %state.operation_id().fmt_short(),
+            "Triggered state transition",
+        );
+        let span = tracing::debug_span!(
+            target: LOG_CLIENT_REACTOR,
+            "sm_transition",
+            operation_id = %state.operation_id().fmt_short()
+        );
+        // Database write conflicts might be happening quite often here,
+        // but transaction functions are supposed to be idempotent anyway,
+        // so it seems like a good stress-test in the worst case.
+        let failed_state = state.clone();
+        let sm_update_tx = self.sm_update_tx.clone();
+        let db = self.db.clone();
+        let notifier = self.notifier.clone();
+        let module_contexts = self.module_contexts.clone();
+        let global_context_gen = global_context_gen.clone();
+        let transition_workers = transition_workers.clone();
+        let transition_result = self.client_task_group.spawn_cancellable(
+            "sm-transition",
+            async move {
+                let _worker = transition_workers
+                    .acquire_owned()
+                    .await
+                    .expect("transition workers are never closed");
+                debug!(target: LOG_CLIENT_REACTOR, "Executing state transition");
+                trace!(
+                    target: LOG_CLIENT_REACTOR,
+                    ?state,
+                    outcome = ?AbbreviateJson(&outcome),
+                    "Executing state transition (details)",
+                );
+
+                let module_contexts = &module_contexts;
+                let global_context_gen = &global_context_gen;
+
+                let outcome = db
+                    .autocommit::<'_, '_, _, _, Infallible>(
+                        |dbtx, _| {
+                            let state = state.clone();
+                            let transition_fn = transition_fn.clone();
+                            let transition_outcome = outcome.clone();
+                            Box::pin(async move {
+                                let new_state = transition_fn(
+                                    &mut ClientSMDatabaseTransaction::new(
+                                        &mut dbtx.to_ref(),
+                                        state.module_instance_id(),
+                                    ),
+                                    transition_outcome,
+                                    state.clone(),
+                                )
+                                .await;
+                                dbtx.remove_entry(&ActiveStateKey::from_state(state.clone()))
+                                    .await;
+                                dbtx.insert_entry(
+                                    &InactiveStateKey::from_state(state.clone()),
+                                    &meta.into_inactive(),
+                                )
+                                .await;
+
+                                let context = &module_contexts
+                                    .get(&state.module_instance_id())
+                                    .expect("Unknown module");
+
+                                let global_context = global_context_gen(
+                                    state.module_instance_id(),
+                                    state.operation_id(),
+                                );
+                                if new_state.is_terminal(context, &global_context) {
+                                    let k = InactiveStateKey::from_state(new_state.clone());
+                                    let v = ActiveStateMeta::default().into_inactive();
+                                    dbtx.insert_entry(&k, &v).await;
+                                    Ok(ActiveOrInactiveState::Inactive {
+                                        dyn_state: new_state,
+                                    })
+                                } else {
+                                    let k = ActiveStateKey::from_state(new_state.clone());
+                                    let v = ActiveStateMeta::default();
+                                    dbtx.insert_entry(&k, &v).await;
+                                    Ok(ActiveOrInactiveState::Active {
+                                        dyn_state: new_state,
+                                        meta: v,
+                                    })
+                                }
+                            })
+                        },
+                        None,
+                    )
+                    .await
+                    .expect("autocommit should keep trying to commit (max_attempt: None) and body doesn't return errors");
+
+                debug!(
+                    target: LOG_CLIENT_REACTOR,
+                    terminal = !outcome.is_active(),
+                    ?outcome,
+                    "State transition complete",
+                );
+
+                match &outcome {
+                    ActiveOrInactiveState::Active { dyn_state, meta: _ } => {
+                        sm_update_tx
+                            .send(dyn_state.clone())
+                            .expect("can't fail: we are the receiving end");
+                        notifier.notify(dyn_state.clone());
+                    }
+                    ActiveOrInactiveState::Inactive { dyn_state } => {
+                        notifier.notify(dyn_state.clone());
+                    }
+                }
+                (state, outcome)
+            }
+            .instrument(span),
+        );
+        Box::pin(async move {
+            match transition_result.await {
+                Ok(Ok((state, outcome))) => ExecutorLoopEvent::Completed { state, outcome },
+                // The client is shutting down
+                Ok(Err(_)) => ExecutorLoopEvent::Disconnected,
+                // The task panicked and dropped the sender of its result
+                Err(_) => ExecutorLoopEvent::Failed {
+                    state: failed_state,
+                },
+            }
+        })
+    }
+
     async fn get_active_states(&self) -> Vec<(DynState, ActiveStateMeta)> {
         self.db
             .begin_transaction()
@@ -800,6 +984,7 @@ impl ExecutorBuilder {
     /// cannot be an isolated DB instance itself.
     pub fn build(self, db: Database, notifier: Notifier, client_task_group: TaskGroup) -> Executor {
         let (sm_update_tx, sm_update_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (quiesce_tx, quiesce_rx) = mpsc::unbounded_channel();
 
         let inner = Arc::new(ExecutorInner {
             db,
@@ -810,6 +995,8 @@ impl ExecutorBuilder {
             shutdown_executor: Default::default(),
             sm_update_tx,
             sm_update_rx: Mutex::new(Some(sm_update_rx)),
+            quiesce_tx,
+            quiesce_rx: Mutex::new(Some(quiesce_rx)),
             client_task_group,
         });
 
@@ -1198,6 +1385,10 @@ mod tests {
         Start,
         ReceivedNonNull(u64),
         Final,
+        /// Counts down to zero in immediate transitions
+        Countdown(OperationId, u8),
+        /// Panics in its immediate transition
+        Panic(OperationId),
     }
 
     impl State for MockStateMachine {
@@ -1257,14 +1448,30 @@ mod tests {
                         |_dbtx, (), _state| Box::pin(async move { MockStateMachine::Final }),
                     )]
                 }
-                MockStateMachine::Final => {
+                MockStateMachine::Final | MockStateMachine::Countdown(_, 0) => {
                     vec![]
                 }
+                MockStateMachine::Countdown(operation_id, remaining) => {
+                    let next = MockStateMachine::Countdown(*operation_id, remaining - 1);
+                    vec![StateTransition::new(async {}, move |_dbtx, (), _state| {
+                        let next = next.clone();
+                        Box::pin(async move { next })
+                    })]
+                }
+                MockStateMachine::Panic(_) => {
+                    vec![StateTransition::new(async {}, |_dbtx, (), _state| {
+                        Box::pin(async move { panic!("Mock state transition panicked") })
+                    })]
+                }
             }
         }
 
         fn operation_id(&self) -> OperationId {
-            OperationId([0u8; 32])
+            match self {
+                MockStateMachine::Countdown(operation_id, _)
+                | MockStateMachine::Panic(operation_id) => *operation_id,
+                _ => OperationId([0u8; 32]),
+            }
         }
     }
 
@@ -1370,4 +1577,134 @@ mod tests {
             "State was written to DB and waits for broadcast"
         );
     }
+
+    #[tokio::test]
+    async fn test_executor_runs_many_operations() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+        const OPERATIONS: usize = 300;
+        const STEPS: u8 = 5;
+
+        let (executor, _sender, _db) = get_executor().await;
+        let operation_ids = (0..OPERATIONS)
+            .map(|_| OperationId::new_random())
+            .collect::<Vec<_>>();
+        executor
+            .add_state_machines(
+                operation_ids
+                    .iter()
+                    .map(|operation_id| {
+                        DynState::from_typed(
+                            MOCK_INSTANCE,
+                            MockStateMachine::Countdown(*operation_id, STEPS),
+                        )
+                    })
+                    .collect(),
+            )
+            .await
+            .unwrap();
+
+        let start = fedimint_core::time::now();
+        for operation_id in operation_ids {
+            let mut finished = false;
+            for _ in 0..100 {
+                if executor
+                    .contains_inactive_state(
+                        MOCK_INSTANCE,
+                        MockStateMachine::Countdown(operation_id, 0),
+                    )
+                    .await
+                {
+                    finished = true;
+                    break;
+                }
+                runtime::sleep(Duration::from_millis(100)).await;
+            }
+            assert!(finished, "Every state machine reaches its final state");
+        }
+        info!(
+            elapsed = ?fedimint_core::time::now().duration_since(start),
+            "Ran {OPERATIONS} state machines with {STEPS} transitions each"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_executor_survives_panicking_transition() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let (executor, _sender, _db) = get_executor().await;
+        let panicking_operation = OperationId::new_random();
+        let operation_id = OperationId::new_random();
+        executor
+            .add_state_machines(vec![
+                DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Panic(panicking_operation)),
+                DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Countdown(operation_id, 3)),
+            ])
+            .await
+            .unwrap();
+
+        runtime::timeout(
+            Duration::from_secs(5),
+            executor.await_inactive_state(DynState::from_typed(
+                MOCK_INSTANCE,
+                MockStateMachine::Countdown(operation_id, 0),
+            )),
+        )
+        .await
+        .expect("Other state machines are not affected by the panic");
+
+        // The executor keeps running new state machines
+        let later_operation_id = OperationId::new_random();
+        executor
+            .add_state_machines(vec![DynState::from_typed(
+                MOCK_INSTANCE,
+                MockStateMachine::Countdown(later_operation_id, 3),
+            )])
+            .await
+            .unwrap();
+        runtime::timeout(
+            Duration::from_secs(5),
+            executor.await_inactive_state(DynState::from_typed(
+                MOCK_INSTANCE,
+                MockStateMachine::Countdown(later_operation_id, 0),
+            )),
+        )
+        .await
+        .expect("The executor is still running");
+
+        assert!(
+            executor
+                .contains_active_state(MOCK_INSTANCE, MockStateMachine::Panic(panicking_operation))
+                .await,
+            "The panicked transition was never committed"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_executor_quiesce() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let (executor, sender, _db) = get_executor().await;
+        executor
+            .add_state_machines(vec![DynState::from_typed(
+                MOCK_INSTANCE,
+                MockStateMachine::Start,
+            )])
+            .await
+            .unwrap();
+        runtime::sleep(Duration::from_secs(1)).await;
+
+        runtime::timeout(Duration::from_secs(5), executor.quiesce())
+            .await
+            .expect("No transitions are running");
+
+        sender.send(0).unwrap();
+        runtime::sleep(Duration::from_secs(1)).await;
+
+        assert!(
+            executor
+                .contains_active_state(MOCK_INSTANCE, MockStateMachine::Start)
+                .await,
+            "Triggered transitions are not applied after quiescing"
+        );
+    }
 }
@@ -6,7 +6,7 @@ use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::util::broadcaststream::BroadcastStream;
 use fedimint_core::util::BoxStream;
 use futures::StreamExt;
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 
 use crate::sm::executor::{
     ActiveModuleOperationStateKeyPrefix, ActiveStateKey, InactiveModuleOperationStateKeyPrefix,
@@ -63,6 +63,28 @@ impl Notifier {
         }
     }
 
+    /// Subscribe to the state transitions of all module instances.
+    ///
+    /// Unlike [`ModuleNotifier::subscribe`] no past transitions are loaded
+    /// from the database. If the subscriber falls behind, missed transitions
+    /// are skipped instead of ending the stream.
+    pub fn subscribe_all_modules(&self) -> BoxStream<'static, DynState> {
+        Box::pin(
+            BroadcastStream::new(self.broadcast.subscribe()).filter_map(|res| async move {
+                match res {
+                    Ok(state) => Some(state),
+                    Err(err) => {
+                        warn!(
+                            ?err,
+                            "Notifier subscriber lagged behind, skipping transitions"
+                        );
+                        None
+                    }
+                }
+            }),
+        )
+    }
+
     /// Create a [`NotifierSender`] handle that lets the owner trigger
     /// notifications without having to hold a full `Notifier`.
     pub fn sender(&self) -> NotifierSender {
@@ -0,0 +1,155 @@
+//! Adaptive polling of the federation by the background tasks of the client
+//!
+//! Background tasks like submitting the transaction outbox and refreshing fee
+//! schedules poll the federation periodically. While operations are active the
+//! client polls every [`SyncConfig::active_interval`], once it is idle the
+//! interval doubles with every poll up to [`SyncConfig::idle_interval`], which
+//! saves battery and bandwidth of mobile wallets that spend most of their time
+//! idle. Every interval is randomized by [`SyncConfig::jitter_percent`] so that
+//! many clients don't poll in lockstep, and any state transition of an
+//! operation wakes the tasks up again.
+
+use std::time::Duration;
+
+use fedimint_core::runtime;
+use futures::StreamExt;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::Client;
+
+/// Polling intervals of the background tasks, see
+/// [`crate::ClientBuilder::with_sync_config`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// Interval while the client has active operations
+    pub active_interval: Duration,
+    /// Longest interval the client backs off to while it is idle
+    pub idle_interval: Duration,
+    /// Every interval is randomly shortened or lengthened by up to this
+    /// percentage of it, capped at 100
+    pub jitter_percent: u8,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            active_interval: Duration::from_secs(10),
+            idle_interval: Duration::from_secs(15 * 60),
+            jitter_percent: 20,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Interval before jitter after `idle_polls` consecutive polls without
+    /// activity
+    pub fn interval(&self, idle_polls: u32) -> Duration {
+        let factor = 2u32.saturating_pow(idle_polls);
+
+        self.active_interval
+            .saturating_mul(factor)
+            .min(self.idle_interval.max(self.active_interval))
+    }
+
+    /// Randomly shortens or lengthens `interval` by up to
+    /// [`Self::jitter_percent`] of it
+    pub fn jittered(&self, interval: Duration) -> Duration {
+        let max_jitter = interval.mul_f64(f64::from(self.jitter_percent.min(100)) / 100.0);
+        if max_jitter.is_zero() {
+            return interval;
+        }
+
+        let jitter = max_jitter.mul_f64(rand::thread_rng().gen_range(0.0..=1.0));
+        if rand::thread_rng().gen_bool(0.5) {
+            interval.saturating_add(jitter)
+        } else {
+            interval.saturating_sub(jitter)
+        }
+    }
+}
+
+/// Number of consecutive polls of a background task without activity
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SyncBackoff {
+    idle_polls: u32,
+}
+
+impl SyncBackoff {
+    /// Polls at the active interval again, e.g. because the task found work
+    pub(crate) fn reset(&mut self) {
+        self.idle_polls = 0;
+    }
+}
+
+impl Client {
+    /// Waits until a background task should poll the federation again, which
+    /// is at least `min_interval` from now. Returns early if an operation
+    /// transitions to a new state.
+    pub(crate) async fn wait_for_next_sync(
+        &self,
+        backoff: &mut SyncBackoff,
+        min_interval: Duration,
+    ) {
+        if self.executor.get_active_states().await.is_empty() {
+            backoff.idle_polls = backoff.idle_polls.saturating_add(1);
+        } else {
+            backoff.reset();
+        }
+
+        let interval = self
+            .sync_config
+            .jittered(self.sync_config.interval(backoff.idle_polls))
+            .max(min_interval);
+
+        let mut transitions = self.executor.notifier().subscribe_all_modules();
+
+        tokio::select! {
+            () = runtime::sleep(interval) => {}
+            Some(_) = transitions.next() => {
+                backoff.reset();
+                runtime::sleep(min_interval).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SyncConfig;
+
+    #[test]
+    fn interval_backs_off_until_idle_interval() {
+        let config = SyncConfig {
+            active_interval: Duration::from_secs(10),
+            idle_interval: Duration::from_secs(60),
+            jitter_percent: 0,
+        };
+
+        assert_eq!(config.interval(0), Duration::from_secs(10));
+        assert_eq!(config.interval(1), Duration::from_secs(20));
+        assert_eq!(config.interval(2), Duration::from_secs(40));
+        assert_eq!(config.interval(3), Duration::from_secs(60));
+        assert_eq!(config.interval(u32::MAX), Duration::from_secs(60));
+        assert_eq!(
+            config.jittered(Duration::from_secs(40)),
+            Duration::from_secs(40)
+        );
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let config = SyncConfig {
+            jitter_percent: 20,
+            ..SyncConfig::default()
+        };
+
+        for _ in 0..100 {
+            let interval = config.jittered(Duration::from_secs(100));
+            assert!(Duration::from_secs(80) <= interval);
+            assert!(interval <= Duration::from_secs(120));
+        }
+    }
+}
@@ -0,0 +1,597 @@
+//! Test harness for client module developers
+//!
+//! [`TestFederation`] runs the server side of one or more modules in memory
+//! and answers the requests of clients created with
+//! [`TestFederation::new_client`] directly, without networking or consensus.
+//! Submitted transactions are processed and accepted immediately, so a client
+//! module can be unit tested against its server module without setting up a
+//! full federation as `fedimint-testing` does.
+//!
+//! Only the core endpoints needed to submit transactions and to await their
+//! outcomes are supported, together with the api endpoints of the modules.
+//! Consensus items are never proposed or processed.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Context};
+use fedimint_api_client::api::JsonRpcResult;
+use fedimint_api_client::interceptor::{ApiRequest, ApiRequestNext, IApiRequestInterceptor};
+use fedimint_core::config::{ClientConfig, ConfigGenModuleParams, GlobalClientConfig, PeerUrl};
+use fedimint_core::core::{ModuleInstanceId, OperationId};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{
+    Database, DatabaseKey, DatabaseLookup, DatabaseRecord, IDatabaseTransactionOpsCoreTyped,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::endpoint_constants::{
+    AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, SESSION_COUNT_ENDPOINT,
+    SUBMIT_TRANSACTIONS_BATCH_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, TRANSACTION_ERROR_ENDPOINT,
+    VERSION_ENDPOINT,
+};
+use fedimint_core::module::registry::{ModuleDecoderRegistry, ServerModuleRegistry};
+use fedimint_core::module::{
+    ApiEndpointContext, ApiRequestErased, DynServerModuleInit, ModuleInit, SerdeModuleEncoding,
+    ServerModule, ServerModuleInit, SupportedApiVersionsSummary, CORE_CONSENSUS_VERSION,
+};
+use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
+use fedimint_core::transaction::{
+    SerdeTransaction, Transaction, TransactionError, TransactionSubmissionOutcome,
+};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{
+    apply, async_trait_maybe_send, impl_db_record, Amount, NumPeers, OutPoint, PeerId,
+    TransactionId,
+};
+use futures::StreamExt;
+use jsonrpsee_core::client::Error as JsonRpcClientError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::module::init::{ClientModuleInit, ClientModuleInitRegistry};
+use crate::secret::{PlainRootSecretStrategy, RootSecretStrategy};
+use crate::sm::{DynState, State};
+use crate::{Client, ClientHandleArc};
+
+#[repr(u8)]
+#[derive(Clone, Debug)]
+enum DbKeyPrefix {
+    AcceptedTransaction = 0x02,
+}
+
+/// Module instances of the outputs of an accepted transaction
+#[derive(Debug, Encodable, Decodable)]
+struct AcceptedTransactionKey(TransactionId);
+
+impl_db_record!(
+    key = AcceptedTransactionKey,
+    value = Vec<ModuleInstanceId>,
+    db_prefix = DbKeyPrefix::AcceptedTransaction,
+    notify_on_modify = true,
+);
+
+/// Builder for a [`TestFederation`], see [`TestFederation::builder`]
+pub struct TestFederationBuilder {
+    client_inits: ClientModuleInitRegistry,
+    server_inits: Vec<(DynServerModuleInit, ConfigGenModuleParams)>,
+    primary_module: ModuleInstanceId,
+}
+
+impl TestFederationBuilder {
+    /// Adds a module to the federation. Modules get instance ids in the order
+    /// they are added, starting at `0`.
+    pub fn with_module<C, S>(
+        mut self,
+        client_init: C,
+        server_init: S,
+        params: ConfigGenModuleParams,
+    ) -> Self
+    where
+        C: ClientModuleInit,
+        S: ServerModuleInit + Sync + 'static,
+    {
+        assert_eq!(
+            <C as ModuleInit>::Common::KIND,
+            <S as ModuleInit>::Common::KIND,
+            "Client and server module have to be of the same kind"
+        );
+        self.client_inits.attach(client_init);
+        self.server_inits.push((server_init.into(), params));
+        self
+    }
+
+    /// Sets the module instance the clients use as their primary module,
+    /// defaults to the first module
+    pub fn with_primary_module(mut self, primary_module: ModuleInstanceId) -> Self {
+        self.primary_module = primary_module;
+        self
+    }
+
+    pub async fn build(self) -> anyhow::Result<TestFederation> {
+        let task_group = TaskGroup::new();
+        // The federation consists of a single guardian
+        let peer_id = PeerId::from(0);
+        let peers = [peer_id];
+
+        let decoders = ModuleDecoderRegistry::new(self.server_inits.iter().zip(0..).map(
+            |((server_init, _), module_instance_id)| {
+                (
+                    module_instance_id,
+                    server_init.module_kind(),
+                    server_init.decoder(),
+                )
+            },
+        ));
+        let db = Database::new(MemDatabase::new(), decoders.clone());
+
+        let mut modules = ServerModuleRegistry::default();
+        let mut client_module_configs = BTreeMap::new();
+        for ((server_init, params), module_instance_id) in self.server_inits.into_iter().zip(0..) {
+            let kind = server_init.module_kind();
+            let cfg = server_init
+                .trusted_dealer_gen(&peers, &params)
+                .remove(&peer_id)
+                .with_context(|| format!("Module {kind} did not generate a config"))?;
+
+            client_module_configs.insert(
+                module_instance_id,
+                server_init.get_client_config(module_instance_id, &cfg.consensus)?,
+            );
+
+            let module = server_init
+                .init(
+                    NumPeers::from(peers.len()),
+                    cfg,
+                    db.with_prefix_module_id(module_instance_id),
+                    &task_group,
+                    peer_id,
+                )
+                .await?;
+            modules.register_module(module_instance_id, kind, module);
+        }
+
+        ensure!(
+            modules.get(self.primary_module).is_some(),
+            "Primary module {} does not exist",
+            self.primary_module
+        );
+
+        let client_config = ClientConfig {
+            global: GlobalClientConfig {
+                // Requests are answered before the client connects to this url
+                api_endpoints: BTreeMap::from([(
+                    peer_id,
+                    PeerUrl {
+                        url: SafeUrl::parse("ws://127.0.0.1:1/").expect("valid url"),
+                        name: "test-guardian".to_string(),
+                    },
+                )]),
+                consensus_version: CORE_CONSENSUS_VERSION,
+                meta: BTreeMap::new(),
+            },
+            modules: client_module_configs,
+        };
+
+        let api_versions =
+            Client::supported_api_versions_summary_static(&client_config, &self.client_inits);
+
+        Ok(TestFederation {
+            server: Arc::new(TestFederationServer {
+                db,
+                decoders,
+                modules,
+                api_versions,
+            }),
+            client_config,
+            client_inits: self.client_inits,
+            primary_module: self.primary_module,
+            _task_group: task_group,
+        })
+    }
+}
+
+/// Single guardian federation running in memory, see the
+/// [module documentation](self)
+pub struct TestFederation {
+    server: Arc<TestFederationServer>,
+    client_config: ClientConfig,
+    client_inits: ClientModuleInitRegistry,
+    primary_module: ModuleInstanceId,
+    _task_group: TaskGroup,
+}
+
+impl TestFederation {
+    pub fn builder() -> TestFederationBuilder {
+        TestFederationBuilder {
+            client_inits: ClientModuleInitRegistry::new(),
+            server_inits: vec![],
+            primary_module: 0,
+        }
+    }
+
+    pub fn client_config(&self) -> &ClientConfig {
+        &self.client_config
+    }
+
+    /// Creates a new client with an in-memory database whose requests are
+    /// answered by this federation
+    pub async fn new_client(&self) -> anyhow::Result<ClientHandleArc> {
+        let mut client_builder = Client::builder(MemDatabase::new().into());
+        client_builder.with_module_inits(self.client_inits.clone());
+        client_builder.with_primary_module(self.primary_module);
+        client_builder.with_api_interceptor(TestFederationApi {
+            server: self.server.clone(),
+        });
+
+        let client_secret =
+            Client::load_or_generate_client_secret(client_builder.db_no_decoders()).await?;
+        let client = client_builder
+            .join(
+                PlainRootSecretStrategy::to_root_secret(&client_secret),
+                self.client_config.clone(),
+                None,
+            )
+            .await?;
+
+        Ok(Arc::new(client))
+    }
+
+    /// Database of the server side of a module, to inspect the state it
+    /// persisted
+    pub fn module_db(&self, module_instance_id: ModuleInstanceId) -> Database {
+        self.server.db.with_prefix_module_id(module_instance_id)
+    }
+
+    /// Returns the server side of a module
+    ///
+    /// ## Panics
+    /// If the module does not exist or is not of type `M`
+    pub fn server_module<M>(&self, module_instance_id: ModuleInstanceId) -> &M
+    where
+        M: ServerModule + 'static,
+    {
+        self.server
+            .modules
+            .get_expect(module_instance_id)
+            .as_any()
+            .downcast_ref::<M>()
+            .expect("Module has a different type")
+    }
+
+    /// Whether a transaction was accepted by the federation
+    pub async fn is_accepted(&self, txid: TransactionId) -> bool {
+        self.server
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_some()
+    }
+}
+
+/// Server side of a [`TestFederation`], shared with the api interceptors of
+/// its clients
+struct TestFederationServer {
+    db: Database,
+    decoders: ModuleDecoderRegistry,
+    modules: ServerModuleRegistry,
+    api_versions: SupportedApiVersionsSummary,
+}
+
+impl TestFederationServer {
+    async fn handle_request(
+        &self,
+        method: &str,
+        request: ApiRequestErased,
+    ) -> anyhow::Result<Value> {
+        if let Some((module_instance_id, path)) = parse_module_method(method) {
+            return self
+                .handle_module_request(module_instance_id, path, request)
+                .await;
+        }
+
+        match method {
+            VERSION_ENDPOINT => to_value(&self.api_versions),
+            SESSION_COUNT_ENDPOINT => to_value(&0u64),
+            SUBMIT_TRANSACTION_ENDPOINT => {
+                let transaction = params::<SerdeTransaction>(request)?
+                    .try_into_inner(&self.decoders)
+                    .map_err(|e| anyhow!("Invalid transaction: {e}"))?;
+                let outcome =
+                    TransactionSubmissionOutcome(self.submit_transaction(transaction).await);
+                to_value(&SerdeModuleEncoding::from(&outcome))
+            }
+            SUBMIT_TRANSACTIONS_BATCH_ENDPOINT => {
+                let mut outcomes = vec![];
+                for transaction in params::<Vec<SerdeTransaction>>(request)? {
+                    let transaction = transaction
+                        .try_into_inner(&self.decoders)
+                        .map_err(|e| anyhow!("Invalid transaction: {e}"))?;
+                    let outcome =
+                        TransactionSubmissionOutcome(self.submit_transaction(transaction).await);
+                    outcomes.push(SerdeModuleEncoding::from(&outcome));
+                }
+                to_value(&outcomes)
+            }
+            AWAIT_TRANSACTION_ENDPOINT => {
+                let txid = params::<TransactionId>(request)?;
+                self.db.wait_key_exists(&AcceptedTransactionKey(txid)).await;
+                to_value(&txid)
+            }
+            // Invalid transactions are rejected on submission already
+            TRANSACTION_ERROR_ENDPOINT => to_value(&Value::Null),
+            AWAIT_OUTPUT_OUTCOME_ENDPOINT => {
+                let out_point = params::<OutPoint>(request)?;
+                let (module_ids, mut dbtx) = self
+                    .db
+                    .wait_key_check(
+                        &AcceptedTransactionKey(out_point.txid),
+                        std::convert::identity,
+                    )
+                    .await;
+                let module_instance_id = *module_ids
+                    .get(out_point.out_idx as usize)
+                    .with_context(|| format!("Outpoint index out of bounds {out_point:?}"))?;
+                let outcome = self
+                    .modules
+                    .get_expect(module_instance_id)
+                    .output_status(
+                        &mut dbtx
+                            .to_ref_with_prefix_module_id(module_instance_id)
+                            .into_nc(),
+                        out_point,
+                        module_instance_id,
+                    )
+                    .await
+                    .with_context(|| format!("Outcome for {out_point:?} is not available yet"))?;
+                to_value(&SerdeModuleEncoding::from(&outcome))
+            }
+            _ => Err(anyhow!(
+                "Endpoint {method} is not supported by the test federation"
+            )),
+        }
+    }
+
+    async fn handle_module_request(
+        &self,
+        module_instance_id: ModuleInstanceId,
+        path: &str,
+        request: ApiRequestErased,
+    ) -> anyhow::Result<Value> {
+        let module = self
+            .modules
+            .get(module_instance_id)
+            .with_context(|| format!("Module {module_instance_id} does not exist"))?;
+        let endpoint = module
+            .api_endpoints()
+            .into_iter()
+            .find(|endpoint| endpoint.path == path)
+            .with_context(|| format!("Module {module_instance_id} has no endpoint {path}"))?;
+
+        let context = ApiEndpointContext::new(
+            self.db.with_prefix_module_id(module_instance_id),
+            self.db
+                .begin_transaction()
+                .await
+                .with_prefix_module_id(module_instance_id),
+            false,
+            request.auth.clone(),
+        );
+
+        (endpoint.handler)(module, context, request)
+            .await
+            .map_err(|e| anyhow!("{} (code {})", e.message, e.code))
+    }
+
+    /// Verifies, processes and immediately accepts a transaction like
+    /// consensus does, or leaves the state untouched if it is invalid
+    async fn submit_transaction(
+        &self,
+        transaction: Transaction,
+    ) -> Result<TransactionId, TransactionError> {
+        let txid = transaction.tx_hash();
+        let mut dbtx = self.db.begin_transaction().await;
+
+        if dbtx
+            .get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_some()
+        {
+            return Ok(txid);
+        }
+
+        let mut input_amount = Amount::ZERO;
+        let mut output_amount = Amount::ZERO;
+        let mut fee_amount = Amount::ZERO;
+        let mut public_keys = Vec::new();
+
+        for input in &transaction.inputs {
+            let module_instance_id = input.module_instance_id();
+            let module = self.modules.get_expect(module_instance_id);
+            module
+                .verify_input(input, module_instance_id)
+                .map_err(TransactionError::Input)?;
+            let meta = module
+                .process_input(
+                    &mut dbtx.to_ref_with_prefix_module_id(module_instance_id),
+                    input,
+                    module_instance_id,
+                )
+                .await
+                .map_err(TransactionError::Input)?;
+
+            input_amount += meta.amount.amount;
+            fee_amount += meta.amount.fee;
+            public_keys.push(meta.pub_key);
+        }
+
+        transaction.validate_signatures(&public_keys)?;
+
+        for (output, out_idx) in transaction.outputs.iter().zip(0u64..) {
+            let module_instance_id = output.module_instance_id();
+            let module = self.modules.get_expect(module_instance_id);
+            module
+                .verify_output(output, module_instance_id)
+                .map_err(TransactionError::Output)?;
+            let amount = module
+                .process_output(
+                    &mut dbtx.to_ref_with_prefix_module_id(module_instance_id),
+                    output,
+                    OutPoint { txid, out_idx },
+                    module_instance_id,
+                )
+                .await
+                .map_err(TransactionError::Output)?;
+
+            output_amount += amount.amount;
+            fee_amount += amount.fee;
+        }
+
+        if input_amount != output_amount + fee_amount {
+            return Err(TransactionError::UnbalancedTransaction {
+                inputs: input_amount,
+                outputs: output_amount,
+                fee: fee_amount,
+            });
+        }
+
+        let module_ids = transaction
+            .outputs
+            .iter()
+            .map(|output| output.module_instance_id())
+            .collect::<Vec<_>>();
+        dbtx.insert_entry(&AcceptedTransactionKey(txid), &module_ids)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .expect("Committing to an in-memory database can only fail on conflicts");
+
+        Ok(txid)
+    }
+}
+
+/// Answers the requests of a client instead of sending them to a guardian
+struct TestFederationApi {
+    server: Arc<TestFederationServer>,
+}
+
+impl fmt::Debug for TestFederationApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TestFederationApi")
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IApiRequestInterceptor for TestFederationApi {
+    async fn intercept(
+        &self,
+        request: ApiRequest,
+        _next: ApiRequestNext<'_>,
+    ) -> JsonRpcResult<Value> {
+        let params = request
+            .params
+            .first()
+            .map(|params| serde_json::from_value::<ApiRequestErased>(params.clone()))
+            .transpose()
+            .map_err(|e| JsonRpcClientError::Custom(e.to_string()))?
+            .unwrap_or_default();
+
+        self.server
+            .handle_request(&request.method, params)
+            .await
+            .map_err(|e| JsonRpcClientError::Custom(e.to_string()))
+    }
+}
+
+/// Splits `module_{id}_{path}` into the module instance id and the path
+fn parse_module_method(method: &str) -> Option<(ModuleInstanceId, &str)> {
+    let (module_instance_id, path) = method.strip_prefix("module_")?.split_once('_')?;
+    Some((module_instance_id.parse().ok()?, path))
+}
+
+fn params<T: DeserializeOwned>(request: ApiRequestErased) -> anyhow::Result<T> {
+    Ok(request.to_typed::<T>()?.params)
+}
+
+fn to_value<T: Serialize>(value: &T) -> anyhow::Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// Waits until all state machines of an operation reached a final state
+pub async fn await_operation_inactive(client: &Client, operation_id: OperationId) {
+    let mut transitions = client.executor().notifier().subscribe_all_modules();
+    while client.has_active_states(operation_id).await {
+        transitions.next().await;
+    }
+}
+
+/// Returns the active and inactive states of an operation that belong to the
+/// state machine `S` of the given module
+pub async fn operation_states<S>(
+    client: &Client,
+    module_instance_id: ModuleInstanceId,
+    operation_id: OperationId,
+) -> (Vec<S>, Vec<S>)
+where
+    S: State + 'static,
+{
+    let (active_states, inactive_states) =
+        client.executor().get_operation_states(operation_id).await;
+
+    let typed = |states: Vec<_>| {
+        states
+            .into_iter()
+            .filter(|(state, _): &(DynState, _)| state.module_instance_id() == module_instance_id)
+            .filter_map(|(state, _)| state.as_any().downcast_ref::<S>().cloned())
+            .collect::<Vec<S>>()
+    };
+
+    (typed(active_states), typed(inactive_states))
+}
+
+/// Reads the value stored under `key`, e.g. in the database of a client or
+/// the [`TestFederation::module_db`] of a module
+pub async fn db_value<K>(db: &Database, key: &K) -> Option<K::Value>
+where
+    K: DatabaseKey + DatabaseRecord + MaybeSend + MaybeSync,
+{
+    db.begin_transaction_nc().await.get_value(key).await
+}
+
+/// Reads all entries whose key starts with `key_prefix`
+pub async fn db_entries<KP>(
+    db: &Database,
+    key_prefix: &KP,
+) -> Vec<(
+    KP::Record,
+    <<KP as DatabaseLookup>::Record as DatabaseRecord>::Value,
+)>
+where
+    KP: DatabaseLookup + MaybeSend + MaybeSync,
+    KP::Record: DatabaseKey,
+{
+    db.begin_transaction_nc()
+        .await
+        .find_by_prefix(key_prefix)
+        .await
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_module_method;
+
+    #[test]
+    fn parses_module_methods() {
+        assert_eq!(
+            parse_module_method("module_3_block_count"),
+            Some((3, "block_count"))
+        );
+        assert_eq!(parse_module_method("module_x_block_count"), None);
+        assert_eq!(parse_module_method("submit_transaction"), None);
+    }
+}
@@ -1,20 +1,24 @@
+use std::future::Future;
 use std::sync::Arc;
 
-use bitcoin::key::KeyPair;
+use anyhow::{ensure, Context};
 use fedimint_core::core::{DynInput, DynOutput, IntoDynInstance, ModuleInstanceId};
+use fedimint_core::secp256k1::{Message, SECP256K1};
 use fedimint_core::transaction::{Transaction, TransactionSignature};
 use fedimint_core::Amount;
 use itertools::multiunzip;
 use rand::{CryptoRng, Rng, RngCore};
-use secp256k1_zkp::Secp256k1;
 
+use super::DynSigner;
 use crate::module::StateGenerator;
 use crate::sm::DynState;
 
 #[derive(Clone)]
 pub struct ClientInput<I = DynInput, S = DynState> {
     pub input: I,
-    pub keys: Vec<KeyPair>,
+    /// Signers of the keys the input has to be signed with, a `KeyPair` can be
+    /// converted into a [`DynSigner`] with `into()`
+    pub keys: Vec<DynSigner>,
     pub amount: Amount,
     pub state_machines: StateGenerator<S>,
 }
@@ -96,14 +100,22 @@ impl TransactionBuilder {
         self
     }
 
-    pub fn build<C, R: RngCore + CryptoRng>(
+    /// Signs the transaction with the signers of its inputs. The nonce is
+    /// drawn from `rng` right away, so the returned future doesn't hold on to
+    /// it while awaiting the signers.
+    pub fn build<R: RngCore + CryptoRng>(
         self,
-        secp_ctx: &Secp256k1<C>,
         mut rng: R,
-    ) -> (Transaction, Vec<DynState>)
-    where
-        C: secp256k1_zkp::Signing + secp256k1_zkp::Verification,
-    {
+    ) -> impl Future<Output = anyhow::Result<(Transaction, Vec<DynState>)>> {
+        let nonce: [u8; 8] = rng.gen();
+
+        self.build_with_nonce(nonce)
+    }
+
+    async fn build_with_nonce(
+        self,
+        nonce: [u8; 8],
+    ) -> anyhow::Result<(Transaction, Vec<DynState>)> {
         let (inputs, input_keys, input_states): (Vec<_>, Vec<_>, Vec<_>) = multiunzip(
             self.inputs
                 .into_iter()
@@ -115,16 +127,27 @@ impl TransactionBuilder {
             .map(|output| (output.output, output.state_machines))
             .unzip();
 
-        let nonce: [u8; 8] = rng.gen();
-
         let txid = Transaction::tx_hash_from_parts(&inputs, &outputs, nonce);
-        let msg = secp256k1_zkp::Message::from_slice(&txid[..]).expect("txid has right length");
-
-        let signatures = input_keys
-            .into_iter()
-            .flatten()
-            .map(|keypair| secp_ctx.sign_schnorr(&msg, &keypair))
-            .collect();
+        let msg = Message::from_slice(&txid[..]).expect("txid has right length");
+
+        let mut signatures = vec![];
+        for signer in input_keys.into_iter().flatten() {
+            let signature = signer
+                .sign_schnorr(msg)
+                .await
+                .with_context(|| format!("Signer {signer:?} failed to sign transaction {txid}"))?;
+
+            // External signers are not trusted to sign with the right key, an invalid
+            // signature would only be noticed once the federation rejects the transaction
+            ensure!(
+                SECP256K1
+                    .verify_schnorr(&signature, &msg, &signer.public_key().x_only_public_key().0)
+                    .is_ok(),
+                "Signer {signer:?} returned an invalid signature for transaction {txid}"
+            );
+
+            signatures.push(signature);
+        }
 
         let transaction = Transaction {
             inputs,
@@ -140,7 +163,7 @@ impl TransactionBuilder {
             .flat_map(|(idx, state_gen)| state_gen(txid, idx as u64))
             .collect::<Vec<_>>();
 
-        (transaction, states)
+        Ok((transaction, states))
     }
 }
 
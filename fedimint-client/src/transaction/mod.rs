@@ -1,5 +1,7 @@
 mod builder;
+mod signer;
 mod sm;
 
 pub use builder::*;
+pub use signer::*;
 pub use sm::*;
@@ -0,0 +1,56 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use fedimint_core::secp256k1::{schnorr, KeyPair, Message, PublicKey, SECP256K1};
+use fedimint_core::{apply, async_trait_maybe_send, dyn_newtype_define};
+
+/// Signs the transactions spending a [`super::ClientInput`]
+///
+/// Modules usually hold the keys of their inputs in memory and use a
+/// [`KeyPair`], which converts into a [`DynSigner`]. Implementing this trait
+/// allows delegating the signing to an external signer like an HSM, a hardware
+/// wallet or a remote signing service instead, so that the secret key never
+/// has to be known to the client.
+#[apply(async_trait_maybe_send!)]
+pub trait ISigner: Debug {
+    /// Key the federation verifies the signature of the input against
+    fn public_key(&self) -> PublicKey;
+
+    /// Creates a schnorr signature of `msg`, which is the id of the
+    /// transaction spending the input
+    async fn sign_schnorr(&self, msg: Message) -> anyhow::Result<schnorr::Signature>;
+}
+
+dyn_newtype_define! {
+    #[derive(Clone)]
+    pub DynSigner(Arc<ISigner>)
+}
+
+/// Signer holding its key in memory
+#[derive(Clone)]
+pub struct KeyPairSigner(KeyPair);
+
+impl Debug for KeyPairSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("KeyPairSigner")
+            .field(&self.0.public_key())
+            .finish()
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl ISigner for KeyPairSigner {
+    fn public_key(&self) -> PublicKey {
+        self.0.public_key()
+    }
+
+    async fn sign_schnorr(&self, msg: Message) -> anyhow::Result<schnorr::Signature> {
+        Ok(SECP256K1.sign_schnorr(&msg, &self.0))
+    }
+}
+
+impl From<KeyPair> for DynSigner {
+    fn from(keypair: KeyPair) -> Self {
+        KeyPairSigner(keypair).into()
+    }
+}
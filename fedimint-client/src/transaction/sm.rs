@@ -4,6 +4,7 @@ use std::time::Duration;
 
 use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, OperationId};
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::ApiVersion;
 use fedimint_core::runtime::sleep;
 use fedimint_core::transaction::{Transaction, TransactionSubmissionOutcome};
 use fedimint_core::TransactionId;
@@ -21,6 +22,10 @@ pub const LOG_TARGET: &str = "transaction_submission";
 
 const RETRY_INTERVAL: Duration = Duration::from_secs(5);
 
+/// Core API version in which the federation started to record why consensus
+/// rejected transactions
+const TRANSACTION_ERROR_API_VERSION: ApiVersion = ApiVersion::new(0, 4);
+
 #[derive(Debug, Clone)]
 pub struct TxSubmissionContext;
 
@@ -42,6 +47,7 @@ impl IntoDynInstance for TxSubmissionContext {
 /// flowchart LR
 ///     Created -- tx is accepted by consensus --> Accepted
 ///     Created -- tx is rejected on submission --> Rejected
+///     Cancelled -- tx was cancelled before submission --> Rejected
 /// ```
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub enum TxSubmissionStates {
@@ -52,7 +58,9 @@ pub enum TxSubmissionStates {
     ///
     /// **This state is final**
     Accepted(TransactionId),
-    /// The transaction has been rejected by a quorum on submission
+    /// The transaction has been rejected by a quorum on submission, along with
+    /// the reason. If consensus rejected the transaction before, the reason
+    /// names the input or output that failed validation.
     ///
     /// **This state is final**
     Rejected(TransactionId, String),
@@ -61,6 +69,11 @@ pub enum TxSubmissionStates {
     // but due to some rust bug/limitation it seem impossible to prevent
     // existing usages from spamming compilation output with warnings.
     NonRetryableError(String),
+    /// The transaction was removed from the outbox before it was ever
+    /// submitted, see [`crate::Client::cancel_outbox_transaction`]. It is
+    /// rejected right away, so that the state machines of its inputs and
+    /// outputs can recover their funds.
+    Cancelled(TransactionId),
 }
 
 impl State for TxSubmissionStates {
@@ -87,6 +100,20 @@ impl State for TxSubmissionStates {
                     ),
                 ]
             }
+            TxSubmissionStates::Cancelled(txid) => {
+                let txid = *txid;
+                vec![StateTransition::new(
+                    std::future::ready(()),
+                    move |_, (), _| {
+                        Box::pin(async move {
+                            TxSubmissionStates::Rejected(
+                                txid,
+                                "Transaction was cancelled before submission".to_string(),
+                            )
+                        })
+                    },
+                )]
+            }
             TxSubmissionStates::Accepted(..)
             | TxSubmissionStates::Rejected(..)
             | TxSubmissionStates::NonRetryableError(..) => {
@@ -107,7 +134,9 @@ impl TxSubmissionStates {
                 Ok(serde_outcome) => match serde_outcome.try_into_inner(context.decoders()) {
                     Ok(outcome) => {
                         if let TransactionSubmissionOutcome(Err(transaction_error)) = outcome {
-                            return transaction_error.to_string();
+                            return Self::consensus_rejection(tx.tx_hash(), &context)
+                                .await
+                                .unwrap_or_else(|| transaction_error.to_string());
                         }
                     }
                     Err(decode_error) => {
@@ -123,6 +152,30 @@ impl TxSubmissionStates {
         }
     }
 
+    /// Fetches why consensus rejected the transaction, which is more specific
+    /// than the submission error. A rejection by consensus alone is not final,
+    /// since the transaction can still be accepted in a later session, so it
+    /// is only used to explain a rejection on submission.
+    async fn consensus_rejection(
+        txid: TransactionId,
+        context: &DynGlobalClientContext,
+    ) -> Option<String> {
+        if !context
+            .core_api_version()
+            .supports(TRANSACTION_ERROR_API_VERSION)
+        {
+            return None;
+        }
+
+        match context.api().transaction_error(txid).await {
+            Ok(rejection) => rejection.map(|rejection| rejection.to_string()),
+            Err(error) => {
+                error.report_if_important();
+                None
+            }
+        }
+    }
+
     async fn trigger_created_accepted(txid: TransactionId, context: DynGlobalClientContext) {
         loop {
             match context.api().await_transaction(txid).await {
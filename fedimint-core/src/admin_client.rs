@@ -1,12 +1,16 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::time::SystemTime;
 
 use fedimint_core::util::SafeUrl;
 use serde::{Deserialize, Serialize};
 #[cfg(not(target_family = "wasm"))]
 use tokio_rustls::rustls::Certificate as RustlsCertificate;
 
-use crate::config::ServerModuleConfigGenParamsRegistry;
+use crate::config::{ConfigGenModuleParams, ServerModuleConfigGenParamsRegistry};
+use crate::core::{ModuleInstanceId, ModuleKind};
+use crate::encoding::{Decodable, Encodable};
+use crate::epoch::{ModuleAdditionProposal, UpgradeProposal};
 use crate::PeerId;
 
 /// The state of the server returned via APIs
@@ -93,6 +97,136 @@ pub struct ConfigGenParamsRequest {
     pub modules: ServerModuleConfigGenParamsRegistry,
 }
 
+/// Sent by admin user to put a module instance into maintenance mode, in which
+/// the guardian rejects newly submitted transactions spending or creating
+/// inputs or outputs of the module, or to take it out of maintenance mode
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ModuleMaintenanceRequest {
+    pub module_instance_id: ModuleInstanceId,
+    pub enabled: bool,
+}
+
+/// How many of the most recent finished sessions a guardian keeps in its
+/// database. Older sessions are pruned, clients and guardians that are further
+/// behind can't fetch them from this guardian anymore.
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq, Encodable, Decodable,
+)]
+pub struct SessionRetention {
+    /// Number of sessions to keep, `None` keeps all sessions
+    pub retained_sessions: Option<u64>,
+}
+
+/// Sent by admin user to vote for adding a module instance to the running
+/// federation. Every guardian has to send the same `kind`, consensus params and
+/// `activation_session`, while the local params may differ.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ModuleAdditionRequest {
+    pub module_instance_id: ModuleInstanceId,
+    pub kind: ModuleKind,
+    pub params: ConfigGenModuleParams,
+    pub activation_session: u64,
+}
+
+/// Sent by admin user to vote for a coordinated upgrade of `fedimintd`. Every
+/// guardian has to send the same `version` and `halt_session`.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ScheduleUpgradeRequest {
+    /// The code version of the `fedimintd` that runs `halt_session` onwards
+    pub version: String,
+    /// The first session that is only run by the new version
+    pub halt_session: u64,
+}
+
+/// Permissions of an admin of a guardian. Every role includes the permissions
+/// of the roles before it, the guardian password grants
+/// [`AdminRole::Dangerous`] and is additionally required to manage API tokens
+/// and to download backups encrypted with it.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Serialize,
+    Deserialize,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    Encodable,
+    Decodable,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// Read the status of the guardian, e.g. audits and consensus health
+    ReadOnly,
+    /// Change the configuration of the running guardian, e.g. put modules
+    /// into maintenance mode
+    ConfigChange,
+    /// Shut down the guardian or export its consensus state
+    Dangerous,
+}
+
+impl std::fmt::Display for AdminRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminRole::ReadOnly => f.write_str("read_only"),
+            AdminRole::ConfigChange => f.write_str("config_change"),
+            AdminRole::Dangerous => f.write_str("dangerous"),
+        }
+    }
+}
+
+impl std::str::FromStr for AdminRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(AdminRole::ReadOnly),
+            "config_change" => Ok(AdminRole::ConfigChange),
+            "dangerous" => Ok(AdminRole::Dangerous),
+            _ => Err(anyhow::format_err!("Unknown admin role {s}")),
+        }
+    }
+}
+
+/// Sent by the admin to issue an API token for another admin of the guardian
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct IssueApiTokenRequest {
+    /// Name identifying the admin, has to be unique among the issued tokens
+    pub name: String,
+    pub role: AdminRole,
+}
+
+/// An issued API token, the token itself is only returned once when it is
+/// issued
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ApiTokenInfo {
+    pub name: String,
+    pub role: AdminRole,
+    pub issued_at: SystemTime,
+}
+
+/// Votes and approved proposals for adding module instances to the federation
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct ModuleAdditionsStatus {
+    /// The latest vote of every guardian that has not been approved yet
+    pub votes: BTreeMap<PeerId, ModuleAdditionProposal>,
+    /// Approved proposals, including ones that are active already
+    pub approved: Vec<ModuleAdditionProposal>,
+}
+
+/// Votes for and the scheduled coordinated upgrade of `fedimintd`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct UpgradeStatus {
+    /// The code version of the `fedimintd` of the guardian
+    pub code_version: String,
+    /// The latest vote of every guardian that has not been approved yet
+    pub votes: BTreeMap<PeerId, UpgradeProposal>,
+    /// The latest approved upgrade, which remains scheduled after it happened
+    pub scheduled: Option<UpgradeProposal>,
+}
+
 mod serde_tls_cert {
     use std::borrow::Cow;
 
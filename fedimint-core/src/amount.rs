@@ -0,0 +1,304 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Amount, ParseAmountError};
+
+pub mod serde {
+    pub mod as_msat {
+        //! Serialize and deserialize [`Amount`](crate::Amount) as integers
+        //! denominated in milli-satoshi. Use with
+        //! `#[serde(with = "amount::serde::as_msat")]`.
+
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(a: &crate::Amount, s: S) -> Result<S::Ok, S::Error> {
+            u64::serialize(&a.msats, s)
+        }
+
+        pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<crate::Amount, D::Error> {
+            Ok(crate::Amount::from_msats(u64::deserialize(d)?))
+        }
+    }
+}
+
+/// Unit an [`Amount`] is parsed from or displayed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AmountUnit {
+    MilliSatoshi,
+    Satoshi,
+    Bitcoin,
+}
+
+impl AmountUnit {
+    /// Number of decimal places of the unit that can be represented with
+    /// milli-satoshi precision
+    pub const fn decimals(self) -> u32 {
+        match self {
+            AmountUnit::MilliSatoshi => 0,
+            AmountUnit::Satoshi => 3,
+            AmountUnit::Bitcoin => 11,
+        }
+    }
+
+    pub const fn msats_per_unit(self) -> u64 {
+        10u64.pow(self.decimals())
+    }
+
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            AmountUnit::MilliSatoshi => "msat",
+            AmountUnit::Satoshi => "sat",
+            AmountUnit::Bitcoin => "BTC",
+        }
+    }
+}
+
+impl fmt::Display for AmountUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+impl FromStr for AmountUnit {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "msat" | "msats" | "millisat" | "millisats" | "millisatoshi" | "millisatoshis" => {
+                Ok(AmountUnit::MilliSatoshi)
+            }
+            "sat" | "sats" | "satoshi" | "satoshis" => Ok(AmountUnit::Satoshi),
+            "btc" | "bitcoin" | "bitcoins" => Ok(AmountUnit::Bitcoin),
+            _ => Err(ParseAmountError::UnknownUnit(s.to_string())),
+        }
+    }
+}
+
+/// Parses a decimal number of `unit`s like `1.5` into an [`Amount`], failing
+/// if it is more precise than a milli-satoshi or doesn't fit into a `u64` of
+/// milli-satoshis
+pub(crate) fn parse_in(s: &str, unit: AmountUnit) -> Result<Amount, ParseAmountError> {
+    let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+
+    if fraction.len() > unit.decimals() as usize {
+        return Err(ParseAmountError::TooPrecise);
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseAmountError::InvalidNumber(s.to_string()));
+    }
+
+    let integer: u64 = if integer.is_empty() && !fraction.is_empty() {
+        0
+    } else {
+        integer.parse()?
+    };
+    let fraction_msats = if fraction.is_empty() {
+        0
+    } else {
+        let padding = unit.decimals() - fraction.len() as u32;
+        fraction.parse::<u64>()? * 10u64.pow(padding)
+    };
+
+    integer
+        .checked_mul(unit.msats_per_unit())
+        .and_then(|msats| msats.checked_add(fraction_msats))
+        .map(Amount::from_msats)
+        .ok_or(ParseAmountError::Overflow)
+}
+
+/// Displays an [`Amount`] in a given [`AmountUnit`], created with
+/// [`Amount::display_in`].
+///
+/// By default as many decimal places as needed to show the amount exactly are
+/// displayed, without grouping the integer part:
+///
+/// ```
+/// use fedimint_core::amount::AmountUnit;
+/// use fedimint_core::Amount;
+///
+/// let amount = Amount::from_msats(1_234_567_800);
+/// assert_eq!(
+///     amount.display_in(AmountUnit::Satoshi).to_string(),
+///     "1234567.8 sat"
+/// );
+/// assert_eq!(
+///     amount
+///         .display_in(AmountUnit::Satoshi)
+///         .precision(2)
+///         .separators(Some('.'), ',')
+///         .to_string(),
+///     "1.234.567,80 sat"
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountDisplay {
+    amount: Amount,
+    unit: AmountUnit,
+    precision: Option<u32>,
+    thousands_separator: Option<char>,
+    decimal_separator: char,
+}
+
+impl AmountDisplay {
+    pub(crate) fn new(amount: Amount, unit: AmountUnit) -> Self {
+        AmountDisplay {
+            amount,
+            unit,
+            precision: None,
+            thousands_separator: None,
+            decimal_separator: '.',
+        }
+    }
+
+    /// Always display `precision` decimal places. Digits that don't fit are
+    /// cut off, so the displayed amount is rounded down.
+    pub fn precision(self, precision: u32) -> Self {
+        AmountDisplay {
+            precision: Some(precision),
+            ..self
+        }
+    }
+
+    /// Sets the locale specific separators, e.g. `(Some(','), '.')` for
+    /// `1,000.5` or `(Some('.'), ',')` for `1.000,5`
+    pub fn separators(self, thousands_separator: Option<char>, decimal_separator: char) -> Self {
+        AmountDisplay {
+            thousands_separator,
+            decimal_separator,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for AmountDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = self.unit.decimals() as usize;
+        let integer = (self.amount.msats / self.unit.msats_per_unit()).to_string();
+        let fraction = self.amount.msats % self.unit.msats_per_unit();
+
+        let mut fraction = if decimals == 0 {
+            String::new()
+        } else {
+            format!("{fraction:0decimals$}")
+        };
+        match self.precision {
+            Some(precision) => {
+                let precision = precision as usize;
+                if precision < fraction.len() {
+                    fraction.truncate(precision);
+                } else {
+                    fraction.extend(std::iter::repeat('0').take(precision - fraction.len()));
+                }
+            }
+            None => fraction.truncate(fraction.trim_end_matches('0').len()),
+        }
+
+        for (idx, digit) in integer.chars().enumerate() {
+            let remaining = integer.len() - idx;
+            if idx != 0 && remaining % 3 == 0 {
+                if let Some(separator) = self.thousands_separator {
+                    write!(f, "{separator}")?;
+                }
+            }
+            write!(f, "{digit}")?;
+        }
+        if !fraction.is_empty() {
+            write!(f, "{}{fraction}", self.decimal_separator)?;
+        }
+        write!(f, " {}", self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::AmountUnit;
+    use crate::{Amount, ParseAmountError};
+
+    #[test]
+    fn parses_decimal_amounts_in_units() {
+        assert_eq!(
+            Amount::from_str("1.5btc").unwrap(),
+            Amount::from_sats(150_000_000)
+        );
+        assert_eq!(
+            Amount::from_str("2000sats").unwrap(),
+            Amount::from_sats(2000)
+        );
+        assert_eq!(
+            Amount::from_str("2000 SATS").unwrap(),
+            Amount::from_sats(2000)
+        );
+        assert_eq!(
+            Amount::from_str("1.5sat").unwrap(),
+            Amount::from_msats(1500)
+        );
+        assert_eq!(
+            Amount::from_str(".001BTC").unwrap(),
+            Amount::from_sats(100_000)
+        );
+        assert_eq!(
+            Amount::from_str("0.00000000001btc").unwrap(),
+            Amount::from_msats(1)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_amounts() {
+        assert!(matches!(
+            Amount::from_str("1.5msat"),
+            Err(ParseAmountError::TooPrecise)
+        ));
+        assert!(matches!(
+            Amount::from_str("0.000000000001btc"),
+            Err(ParseAmountError::TooPrecise)
+        ));
+        assert!(matches!(
+            Amount::from_str("1000000000btc"),
+            Err(ParseAmountError::Overflow)
+        ));
+        assert!(matches!(
+            Amount::from_str("1.-5sat"),
+            Err(ParseAmountError::InvalidNumber(_))
+        ));
+        assert!(Amount::from_str("5 apples").is_err());
+    }
+
+    #[test]
+    fn displays_amounts_in_units() {
+        let amount = Amount::from_msats(150_000_001_500);
+
+        assert_eq!(
+            amount.display_in(AmountUnit::MilliSatoshi).to_string(),
+            "150000001500 msat"
+        );
+        assert_eq!(
+            amount.display_in(AmountUnit::Satoshi).to_string(),
+            "150000001.5 sat"
+        );
+        assert_eq!(
+            amount.display_in(AmountUnit::Bitcoin).to_string(),
+            "1.500000015 BTC"
+        );
+        assert_eq!(
+            amount
+                .display_in(AmountUnit::Bitcoin)
+                .precision(3)
+                .to_string(),
+            "1.500 BTC"
+        );
+        assert_eq!(
+            amount
+                .display_in(AmountUnit::Satoshi)
+                .precision(0)
+                .separators(Some(','), '.')
+                .to_string(),
+            "150,000,001 sat"
+        );
+        assert_eq!(
+            Amount::ZERO.display_in(AmountUnit::Bitcoin).to_string(),
+            "0 BTC"
+        );
+    }
+}
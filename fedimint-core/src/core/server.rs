@@ -4,6 +4,7 @@
 //!
 //! This (Rust) module defines common interoperability types
 //! and functionality that are only used on the server side.
+use std::collections::BTreeSet;
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -18,8 +19,8 @@ use crate::db::DatabaseTransaction;
 use crate::dyn_newtype_define;
 use crate::module::registry::ModuleInstanceId;
 use crate::module::{
-    ApiEndpoint, ApiEndpointContext, ApiRequestErased, InputMeta, ModuleCommon, ServerModule,
-    TransactionItemAmount,
+    ApiEndpoint, ApiEndpointContext, ApiRequestErased, FeeSchedule, InputMeta, ModuleCommon,
+    ModuleConsensusFeature, ModuleHealth, ServerModule, TransactionItemAmount,
 };
 
 /// Backend side module interface
@@ -76,6 +77,22 @@ pub trait IServerModule: Debug {
         module_instance_id: ModuleInstanceId,
     ) -> Result<TransactionItemAmount, DynOutputError>;
 
+    /// Checks the parts of an input that do not depend on the database, see
+    /// [`ServerModule::verify_input`]
+    fn verify_input(
+        &self,
+        input: &DynInput,
+        module_instance_id: ModuleInstanceId,
+    ) -> Result<(), DynInputError>;
+
+    /// Checks the parts of an output that do not depend on the database, see
+    /// [`ServerModule::verify_output`]
+    fn verify_output(
+        &self,
+        output: &DynOutput,
+        module_instance_id: ModuleInstanceId,
+    ) -> Result<(), DynOutputError>;
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
@@ -104,6 +121,27 @@ pub trait IServerModule: Debug {
     /// should be deterministic, only dependant on their input and the
     /// current epoch.
     fn api_endpoints(&self) -> Vec<ApiEndpoint<DynServerModule>>;
+
+    /// Current fee schedule of the module
+    async fn fee_schedule(&self, dbtx: &mut DatabaseTransaction<'_>) -> FeeSchedule;
+
+    /// Consensus feature that has to be active for the fee schedule to apply
+    fn fee_schedule_consensus_feature(&self) -> Option<ModuleConsensusFeature>;
+
+    /// Consensus features the module implementation supports
+    fn supported_consensus_features(&self) -> BTreeSet<ModuleConsensusFeature>;
+
+    /// Prefixes of database entries that only belong to this guardian
+    fn local_db_prefixes(&self) -> BTreeSet<u8>;
+
+    /// Health of the module, `None` if the module doesn't check its health
+    async fn health_check(&self, dbtx: &mut DatabaseTransaction<'_>) -> Option<ModuleHealth>;
+
+    /// Consensus feature that has to be active for the input to be processed
+    fn input_consensus_feature(&self, input: &DynInput) -> Option<ModuleConsensusFeature>;
+
+    /// Consensus feature that has to be active for the output to be processed
+    fn output_consensus_feature(&self, output: &DynOutput) -> Option<ModuleConsensusFeature>;
 }
 
 dyn_newtype_define!(
@@ -210,6 +248,36 @@ where
         .map_err(|v| DynOutputError::from_typed(module_instance_id, v))
     }
 
+    fn verify_input(
+        &self,
+        input: &DynInput,
+        module_instance_id: ModuleInstanceId,
+    ) -> Result<(), DynInputError> {
+        <Self as ServerModule>::verify_input(
+            self,
+            input
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Input>()
+                .expect("incorrect input type passed to module plugin"),
+        )
+        .map_err(|v| DynInputError::from_typed(module_instance_id, v))
+    }
+
+    fn verify_output(
+        &self,
+        output: &DynOutput,
+        module_instance_id: ModuleInstanceId,
+    ) -> Result<(), DynOutputError> {
+        <Self as ServerModule>::verify_output(
+            self,
+            output
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Output>()
+                .expect("incorrect output type passed to module plugin"),
+        )
+        .map_err(|v| DynOutputError::from_typed(module_instance_id, v))
+    }
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
@@ -258,4 +326,44 @@ where
             })
             .collect()
     }
+
+    async fn fee_schedule(&self, dbtx: &mut DatabaseTransaction<'_>) -> FeeSchedule {
+        <Self as ServerModule>::fee_schedule(self, dbtx).await
+    }
+
+    fn fee_schedule_consensus_feature(&self) -> Option<ModuleConsensusFeature> {
+        <Self as ServerModule>::fee_schedule_consensus_feature(self)
+    }
+
+    fn supported_consensus_features(&self) -> BTreeSet<ModuleConsensusFeature> {
+        <Self as ServerModule>::supported_consensus_features(self)
+    }
+
+    fn local_db_prefixes(&self) -> BTreeSet<u8> {
+        <Self as ServerModule>::local_db_prefixes(self)
+    }
+
+    async fn health_check(&self, dbtx: &mut DatabaseTransaction<'_>) -> Option<ModuleHealth> {
+        <Self as ServerModule>::health_check(self, dbtx).await
+    }
+
+    fn input_consensus_feature(&self, input: &DynInput) -> Option<ModuleConsensusFeature> {
+        <Self as ServerModule>::input_consensus_feature(
+            self,
+            input
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Input>()
+                .expect("incorrect input type passed to module plugin"),
+        )
+    }
+
+    fn output_consensus_feature(&self, output: &DynOutput) -> Option<ModuleConsensusFeature> {
+        <Self as ServerModule>::output_consensus_feature(
+            self,
+            output
+                .as_any()
+                .downcast_ref::<<<Self as ServerModule>::Common as ModuleCommon>::Output>()
+                .expect("incorrect output type passed to module plugin"),
+        )
+    }
 }
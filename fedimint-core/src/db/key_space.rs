@@ -0,0 +1,241 @@
+//! Declarations of the key prefixes used in the database
+//!
+//! Every module instance reads and writes its own isolated key space, see
+//! [`super::Database::with_prefix_module_id`], in which it chooses the prefixes
+//! of its records. Nothing but convention prevents two records from sharing a
+//! prefix though, in which case their keys and values silently overwrite each
+//! other. Modules therefore declare their prefixes together with the types of
+//! the records stored under them via
+//! [`crate::module::ModuleInit::db_key_prefixes`], so that conflicting
+//! declarations are detected when the server starts and `fedimint-dbtool
+//! check` can find data under undeclared prefixes or prefixes the
+//! `dump_database` implementation of the module doesn't cover.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use thiserror::Error;
+
+use super::{DatabaseRecord, DatabaseVersionKey, DatabaseVersionKeyV0, DbKeyPrefix};
+use crate::backup::ClientBackupKey;
+use crate::core::ModuleInstanceId;
+
+/// Declaration of a key prefix and the record stored under it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbKeyPrefixDecl {
+    pub prefix: u8,
+    /// Name of the prefix, which is also the name `dump_database` filters the
+    /// prefix by
+    pub name: String,
+    pub key_type: Option<&'static str>,
+    pub value_type: Option<&'static str>,
+}
+
+impl DbKeyPrefixDecl {
+    /// Declares the prefix of the record `R`
+    pub fn of<R: DatabaseRecord>(name: impl fmt::Display) -> Self {
+        DbKeyPrefixDecl {
+            prefix: R::DB_PREFIX,
+            name: name.to_string(),
+            key_type: Some(std::any::type_name::<R::Key>()),
+            value_type: Some(std::any::type_name::<R::Value>()),
+        }
+    }
+
+    /// Declares a prefix that is not used by a single record type, e.g. the
+    /// prefix of a nested key space
+    pub fn reserved(prefix: u8, name: impl fmt::Display) -> Self {
+        DbKeyPrefixDecl {
+            prefix,
+            name: name.to_string(),
+            key_type: None,
+            value_type: None,
+        }
+    }
+}
+
+impl fmt::Display for DbKeyPrefixDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (0x{:02x})", self.name, self.prefix)?;
+        if let (Some(key_type), Some(value_type)) = (self.key_type, self.value_type) {
+            write!(f, " {key_type} -> {value_type}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Prefixes of the records `fedimint-core` stores in the global key space
+pub fn core_db_key_prefixes() -> Vec<DbKeyPrefixDecl> {
+    vec![
+        DbKeyPrefixDecl::of::<DatabaseVersionKeyV0>(DbKeyPrefix::DatabaseVersion),
+        DbKeyPrefixDecl::of::<DatabaseVersionKey>(DbKeyPrefix::DatabaseVersion),
+        DbKeyPrefixDecl::of::<ClientBackupKey>(DbKeyPrefix::ClientBackup),
+    ]
+}
+
+/// Key space prefixes are declared in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DbKeySpace {
+    Global,
+    Module(ModuleInstanceId),
+}
+
+impl fmt::Display for DbKeySpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbKeySpace::Global => write!(f, "global key space"),
+            DbKeySpace::Module(module_instance_id) => {
+                write!(f, "key space of module {module_instance_id}")
+            }
+        }
+    }
+}
+
+/// Two owners, or one owner under two names, claim the same prefix
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Prefix 0x{prefix:02x} in the {space} is declared as {first} by {first_owner} and as {second} by {second_owner}")]
+pub struct DbKeyPrefixConflict {
+    pub space: DbKeySpace,
+    pub prefix: u8,
+    pub first_owner: String,
+    pub first: String,
+    pub second_owner: String,
+    pub second: String,
+}
+
+/// Prefixes declared by the owners of each key space of a database
+#[derive(Debug, Clone, Default)]
+pub struct DbKeySpaceRegistry {
+    declarations: BTreeMap<DbKeySpace, BTreeMap<u8, Vec<(String, DbKeyPrefixDecl)>>>,
+}
+
+impl DbKeySpaceRegistry {
+    /// Records that `owner` stores data in `space` under the given prefixes
+    pub fn declare(
+        &mut self,
+        space: DbKeySpace,
+        owner: impl fmt::Display,
+        prefixes: impl IntoIterator<Item = DbKeyPrefixDecl>,
+    ) {
+        let declarations = self.declarations.entry(space).or_default();
+        for decl in prefixes {
+            declarations
+                .entry(decl.prefix)
+                .or_default()
+                .push((owner.to_string(), decl));
+        }
+    }
+
+    /// Returns whether any owner declared prefixes in `space`, the data of
+    /// key spaces without declarations can't be checked
+    pub fn has_declarations(&self, space: DbKeySpace) -> bool {
+        self.declarations.contains_key(&space)
+    }
+
+    /// Returns the declarations of `prefix` in `space`
+    pub fn get(&self, space: DbKeySpace, prefix: u8) -> Vec<&DbKeyPrefixDecl> {
+        self.declarations
+            .get(&space)
+            .and_then(|declarations| declarations.get(&prefix))
+            .into_iter()
+            .flatten()
+            .map(|(_, decl)| decl)
+            .collect()
+    }
+
+    /// Returns every prefix that is declared by different owners, or by one
+    /// owner under different names. Multiple records sharing a prefix under
+    /// the same name are not a conflict, since that is how old versions of a
+    /// record are kept around for migrations.
+    pub fn conflicts(&self) -> Vec<DbKeyPrefixConflict> {
+        let mut conflicts = Vec::new();
+
+        for (space, declarations) in &self.declarations {
+            for (prefix, decls) in declarations {
+                let (first_owner, first) = &decls[0];
+                for (second_owner, second) in &decls[1..] {
+                    if first_owner != second_owner || first.name != second.name {
+                        conflicts.push(DbKeyPrefixConflict {
+                            space: *space,
+                            prefix: *prefix,
+                            first_owner: first_owner.clone(),
+                            first: first.to_string(),
+                            second_owner: second_owner.clone(),
+                            second: second.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        conflicts
+    }
+
+    /// Fails with all conflicting declarations if there are any, see
+    /// [`Self::conflicts`]
+    pub fn ensure_no_conflicts(&self) -> anyhow::Result<()> {
+        let conflicts = self.conflicts();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+
+        let conflicts = conflicts
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        anyhow::bail!("Conflicting database key prefixes:\n{conflicts}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DbKeyPrefixDecl, DbKeySpace, DbKeySpaceRegistry};
+
+    #[test]
+    fn conflicting_declarations_are_detected() {
+        let mut registry = DbKeySpaceRegistry::default();
+        registry.declare(
+            DbKeySpace::Module(0),
+            "mint",
+            [
+                DbKeyPrefixDecl::reserved(0x10, "NoteNonce"),
+                // Old versions of a record share the prefix of the current one
+                DbKeyPrefixDecl::reserved(0x10, "NoteNonce"),
+            ],
+        );
+        // Isolated key spaces can use the same prefixes
+        registry.declare(
+            DbKeySpace::Module(1),
+            "wallet",
+            [DbKeyPrefixDecl::reserved(0x10, "Utxo")],
+        );
+        assert!(registry.conflicts().is_empty());
+
+        registry.declare(
+            DbKeySpace::Module(1),
+            "wallet",
+            [DbKeyPrefixDecl::reserved(0x10, "PegOutNonce")],
+        );
+        registry.declare(
+            DbKeySpace::Global,
+            "fedimint-server",
+            [DbKeyPrefixDecl::reserved(0x01, "AcceptedItem")],
+        );
+        registry.declare(
+            DbKeySpace::Global,
+            "third-party",
+            [DbKeyPrefixDecl::reserved(0x01, "AcceptedItem")],
+        );
+
+        let conflicts = registry.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert!(conflicts
+            .iter()
+            .any(|c| c.space == DbKeySpace::Module(1) && c.prefix == 0x10));
+        assert!(conflicts
+            .iter()
+            .any(|c| c.space == DbKeySpace::Global && c.prefix == 0x01));
+        assert!(registry.ensure_no_conflicts().is_err());
+    }
+}
@@ -61,6 +61,7 @@ use crate::fmt_utils::AbbreviateHexBytes;
 use crate::task::{MaybeSend, MaybeSync};
 use crate::{async_trait_maybe_send, maybe_add_send, timing};
 
+pub mod key_space;
 pub mod mem_impl;
 pub mod notifications;
 
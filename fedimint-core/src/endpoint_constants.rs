@@ -1,6 +1,7 @@
 pub const ADD_CONFIG_GEN_PEER_ENDPOINT: &str = "add_config_gen_peer";
 pub const AUDIT_ENDPOINT: &str = "audit";
 pub const GUARDIAN_CONFIG_BACKUP_ENDPOINT: &str = "download_guardian_backup";
+pub const GUARDIAN_DISASTER_RECOVERY_BUNDLE_ENDPOINT: &str = "download_disaster_recovery_bundle";
 pub const AUTH_ENDPOINT: &str = "auth";
 pub const AWAIT_OUTPUT_OUTCOME_ENDPOINT: &str = "await_output_outcome";
 pub const BACKUP_ENDPOINT: &str = "backup";
@@ -11,6 +12,7 @@ pub const SESSION_COUNT_ENDPOINT: &str = "session_count";
 pub const AWAIT_SESSION_OUTCOME_ENDPOINT: &str = "await_session_outcome";
 pub const AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT: &str = "await_signed_session_outcome";
 pub const SESSION_STATUS_ENDPOINT: &str = "session_status";
+pub const SESSION_TRANSACTIONS_ENDPOINT: &str = "session_transactions";
 pub const SHUTDOWN_ENDPOINT: &str = "shutdown";
 pub const CONFIG_GEN_PEERS_ENDPOINT: &str = "config_gen_peers";
 pub const CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT: &str = "consensus_config_gen_params";
@@ -19,15 +21,34 @@ pub const VERIFY_CONFIG_HASH_ENDPOINT: &str = "verify_config_hash";
 pub const RECOVER_ENDPOINT: &str = "recover";
 pub const REGISTER_GATEWAY_ENDPOINT: &str = "register_gateway";
 pub const RUN_DKG_ENDPOINT: &str = "run_dkg";
+pub const SCHEDULE_UPGRADE_ENDPOINT: &str = "schedule_upgrade";
 pub const SET_CONFIG_GEN_CONNECTIONS_ENDPOINT: &str = "set_config_gen_connections";
 pub const SET_CONFIG_GEN_PARAMS_ENDPOINT: &str = "set_config_gen_params";
 pub const SET_PASSWORD_ENDPOINT: &str = "set_password";
 pub const START_CONSENSUS_ENDPOINT: &str = "start_consensus";
 pub const STATUS_ENDPOINT: &str = "status";
 pub const SUBMIT_TRANSACTION_ENDPOINT: &str = "submit_transaction";
+pub const SUBMIT_TRANSACTIONS_BATCH_ENDPOINT: &str = "submit_transactions_batch";
 pub const VERIFIED_CONFIGS_ENDPOINT: &str = "verified_configs";
 pub const VERSION_ENDPOINT: &str = "version";
 pub const AWAIT_TRANSACTION_ENDPOINT: &str = "await_transaction";
 pub const INVITE_CODE_ENDPOINT: &str = "invite_code";
+pub const MODULES_IN_MAINTENANCE_ENDPOINT: &str = "modules_in_maintenance";
+pub const MODULE_CONSENSUS_FEATURES_ENDPOINT: &str = "module_consensus_features";
+pub const FEE_SCHEDULE_ENDPOINT: &str = "fee_schedule";
+pub const SET_MODULE_MAINTENANCE_ENDPOINT: &str = "set_module_maintenance";
+pub const MODULE_ADDITIONS_ENDPOINT: &str = "module_additions";
+pub const PROPOSE_MODULE_ADDITION_ENDPOINT: &str = "propose_module_addition";
 pub const FEDERATION_ID_ENDPOINT: &str = "federation_id";
 pub const RESTART_FEDERATION_SETUP_ENDPOINT: &str = "restart_federation_setup";
+pub const TRANSACTION_ERROR_ENDPOINT: &str = "transaction_error";
+pub const CONSENSUS_CHECKPOINT_ENDPOINT: &str = "consensus_checkpoint";
+pub const CONSENSUS_HEALTH_ENDPOINT: &str = "consensus_health";
+pub const PEER_CONNECTION_STATS_ENDPOINT: &str = "peer_connection_stats";
+pub const SESSION_RETENTION_ENDPOINT: &str = "session_retention";
+pub const SET_SESSION_RETENTION_ENDPOINT: &str = "set_session_retention";
+pub const ISSUE_API_TOKEN_ENDPOINT: &str = "issue_api_token";
+pub const REVOKE_API_TOKEN_ENDPOINT: &str = "revoke_api_token";
+pub const LIST_API_TOKENS_ENDPOINT: &str = "list_api_tokens";
+pub const TRANSPORT_OPTIONS_ENDPOINT: &str = "transport_options";
+pub const UPGRADE_STATUS_ENDPOINT: &str = "upgrade_status";
@@ -1,6 +1,11 @@
+use std::collections::BTreeSet;
+
 use fedimint_core::core::DynModuleConsensusItem as ModuleConsensusItem;
 use fedimint_core::encoding::{Decodable, Encodable};
+use serde::{Deserialize, Serialize};
 
+use crate::core::{ModuleInstanceId, ModuleKind};
+use crate::module::ModuleConsensusFeature;
 use crate::transaction::Transaction;
 
 /// All the items that may be produced during a consensus epoch
@@ -10,8 +15,48 @@ pub enum ConsensusItem {
     Transaction(Transaction),
     /// Any data that modules require consensus on
     Module(ModuleConsensusItem),
+    /// Vote of a guardian to add a module instance to the running federation
+    ModuleAddition(ModuleAdditionProposal),
+    /// The consensus features a guardian supports for a module instance
+    ModuleFeatures(ModuleFeatureSignal),
+    /// Vote of a guardian to halt consensus for a coordinated upgrade
+    Upgrade(UpgradeProposal),
     /// Allows us to add new items in the future without crashing old clients
     /// that try to interpret the session log.
     #[encodable_default]
     Default { variant: u64, bytes: Vec<u8> },
 }
+
+/// A module instance the guardians want to add to the running federation. Once
+/// a threshold of guardians voted for the same proposal it is approved, and the
+/// guardians generate the config of the module before `activation_session`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ModuleAdditionProposal {
+    pub module_instance_id: ModuleInstanceId,
+    pub kind: ModuleKind,
+    /// The consensus part of the module's config gen params as JSON, the local
+    /// part is provided to every guardian individually
+    pub consensus_params: String,
+    /// The first session in which the module is active
+    pub activation_session: u64,
+}
+
+/// An upgrade of `fedimintd` the guardians want to coordinate. Once a threshold
+/// of guardians voted for the same proposal it is scheduled: every guardian
+/// halts consensus after completing the session before `halt_session` and only
+/// continues once it runs `version`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct UpgradeProposal {
+    /// The code version of the `fedimintd` that runs `halt_session` onwards
+    pub version: String,
+    /// The first session that is only run by the new version
+    pub halt_session: u64,
+}
+
+/// The consensus features the module implementation of a guardian supports,
+/// which replaces any signal the guardian sent for the module instance before
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ModuleFeatureSignal {
+    pub module_instance_id: ModuleInstanceId,
+    pub features: BTreeSet<ModuleConsensusFeature>,
+}
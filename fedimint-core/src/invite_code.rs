@@ -146,6 +146,96 @@ impl InviteCode {
             })
             .expect("Ensured by constructor")
     }
+
+    /// Sets the api secret to use when communicating with the federation,
+    /// replacing any previous one
+    pub fn with_api_secret(mut self, api_secret: String) -> Self {
+        self.0
+            .retain(|data| !matches!(data, InviteCodeData::ApiSecret(_)));
+        self.0.push(InviteCodeData::ApiSecret(api_secret));
+        self
+    }
+
+    /// Adds a hint about the federation's meta, e.g. its name, that clients
+    /// can display before the config was downloaded
+    pub fn with_meta_hint(mut self, key: String, value: String) -> Self {
+        self.0.retain(|data| match data {
+            InviteCodeData::MetaHint { key: existing, .. } => *existing != key,
+            _ => true,
+        });
+        self.0.push(InviteCodeData::MetaHint { key, value });
+        self
+    }
+
+    /// Meta hints contained in the invite code, see
+    /// [`InviteCode::with_meta_hint`]
+    pub fn meta_hints(&self) -> BTreeMap<String, String> {
+        self.0
+            .iter()
+            .filter_map(|data| match data {
+                InviteCodeData::MetaHint { key, value } => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Encodes the invite code in the compact v2 format
+    ///
+    /// Compared to the v1 format used by [`Display`] it saves the per-entry
+    /// overhead, which makes it practical to include the URLs of all guardians
+    /// so joining doesn't depend on a single guardian being online. As bech32
+    /// is case insensitive, the upper case form of the string can be used to
+    /// encode it more efficiently in QR codes.
+    ///
+    /// Clients that don't support the v2 format can't parse it, so the v1
+    /// format should be preferred where compatibility matters.
+    pub fn encode_v2(&self) -> String {
+        let data = InviteCodeV2 {
+            federation_id: self.federation_id(),
+            peers: self.peers(),
+            api_secret: self.api_secret(),
+            meta_hints: self.meta_hints(),
+        }
+        .consensus_encode_to_vec();
+
+        bech32::encode::<Bech32m>(BECH32_HRP_V2, &data)
+            .expect("Invite code data does not exceed the bech32 length limit")
+    }
+
+    /// Parses an invite code encoded with [`InviteCode::encode_v2`]
+    pub fn decode_v2(encoded: &str) -> anyhow::Result<Self> {
+        let (hrp, data) = bech32::decode(encoded)?;
+
+        ensure!(hrp == BECH32_HRP_V2, "Invalid HRP in bech32 encoding");
+
+        Self::from_v2_bytes(&data)
+    }
+
+    fn from_v2_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let v2 = InviteCodeV2::consensus_decode(&mut Cursor::new(data), &Default::default())?;
+
+        ensure!(
+            !v2.peers.is_empty(),
+            "No API was provided in the invite code"
+        );
+
+        let mut inner = v2
+            .peers
+            .into_iter()
+            .map(|(peer, url)| InviteCodeData::Api { url, peer })
+            .collect::<Vec<_>>();
+        inner.push(InviteCodeData::FederationId(v2.federation_id));
+        if let Some(api_secret) = v2.api_secret {
+            inner.push(InviteCodeData::ApiSecret(api_secret));
+        }
+        inner.extend(
+            v2.meta_hints
+                .into_iter()
+                .map(|(key, value)| InviteCodeData::MetaHint { key, value }),
+        );
+
+        Ok(InviteCode(inner))
+    }
 }
 
 /// Data that can be encoded in the invite code. Currently we always just use
@@ -168,6 +258,9 @@ enum InviteCodeData {
     /// Api secret to use
     ApiSecret(String),
 
+    /// Hint about a meta field of the federation
+    MetaHint { key: String, value: String },
+
     /// Unknown invite code fields to be defined in the future
     #[encodable_default]
     Default { variant: u64, bytes: Vec<u8> },
@@ -182,12 +275,30 @@ enum InviteCodeData {
 /// ```
 const BECH32_HRP: Hrp = Hrp::parse_unchecked("fed1");
 
+/// HRP of the compact invite code encoding, see [`InviteCode::encode_v2`]
+const BECH32_HRP_V2: Hrp = Hrp::parse_unchecked("fed2");
+
+/// Compact invite code encoding, which avoids the per-entry overhead of the
+/// extensible v1 encoding
+#[derive(Debug, Encodable, Decodable)]
+struct InviteCodeV2 {
+    federation_id: FederationId,
+    peers: BTreeMap<PeerId, SafeUrl>,
+    api_secret: Option<String>,
+    meta_hints: BTreeMap<String, String>,
+}
+
+/// Parses both the v1 and the compact v2 encoding
 impl FromStr for InviteCode {
     type Err = anyhow::Error;
 
     fn from_str(encoded: &str) -> Result<Self, Self::Err> {
         let (hrp, data) = bech32::decode(encoded)?;
 
+        if hrp == BECH32_HRP_V2 {
+            return Self::from_v2_bytes(&data);
+        }
+
         ensure!(hrp == BECH32_HRP, "Invalid HRP in bech32 encoding");
 
         let invite = InviteCode::consensus_decode(&mut Cursor::new(data), &Default::default())?;
@@ -257,4 +368,40 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_invite_code_v2_roundtrip() {
+        let federation_id = FederationId::dummy();
+        let peers = (0..4)
+            .map(|peer| {
+                (
+                    crate::PeerId(peer),
+                    format!("wss://guardian-{peer}.example.com/")
+                        .parse()
+                        .expect("valid url"),
+                )
+            })
+            .collect();
+        let invite_code = InviteCode::new_with_essential_num_guardians(&peers, federation_id)
+            .with_api_secret("secret".to_string())
+            .with_meta_hint("federation_name".to_string(), "Test".to_string());
+
+        let encoded = invite_code.encode_v2();
+        assert!(encoded.starts_with("fed21"));
+        assert!(encoded.len() < invite_code.to_string().len());
+
+        for encoded in [encoded.clone(), encoded.to_uppercase()] {
+            for decoded in [
+                InviteCode::decode_v2(&encoded).expect("valid v2 invite code"),
+                InviteCode::from_str(&encoded).expect("valid invite code"),
+            ] {
+                assert_eq!(decoded.federation_id(), federation_id);
+                assert_eq!(decoded.peers(), invite_code.peers());
+                assert_eq!(decoded.api_secret(), Some("secret".to_string()));
+                assert_eq!(decoded.meta_hints(), invite_code.meta_hints());
+            }
+        }
+
+        assert!(InviteCode::decode_v2(&invite_code.to_string()).is_err());
+    }
 }
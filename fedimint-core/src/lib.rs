@@ -61,12 +61,15 @@ use thiserror::Error;
 pub use tiered::Tiered;
 pub use tiered_multi::*;
 
+use crate::amount::{AmountDisplay, AmountUnit};
 pub use crate::core::server;
 use crate::encoding::{Decodable, DecodeError, Encodable};
 use crate::module::registry::ModuleDecoderRegistry;
 
 /// Admin (guardian) client types
 pub mod admin_client;
+/// Parsing, display and serde helpers for [`Amount`]
+pub mod amount;
 /// Federation-stored client backups
 pub mod backup;
 /// Gradual bitcoin dependency migration helpers
@@ -234,6 +237,44 @@ impl Amount {
             msats: self.msats.checked_sub(other.msats)?,
         })
     }
+
+    pub fn checked_add(self, other: Amount) -> Option<Self> {
+        Some(Self {
+            msats: self.msats.checked_add(other.msats)?,
+        })
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Self {
+        Amount {
+            msats: self.msats.saturating_add(other.msats),
+        }
+    }
+
+    pub fn checked_mul(self, other: u64) -> Option<Self> {
+        Some(Self {
+            msats: self.msats.checked_mul(other)?,
+        })
+    }
+
+    pub fn saturating_mul(self, other: u64) -> Self {
+        Amount {
+            msats: self.msats.saturating_mul(other),
+        }
+    }
+
+    /// Divides the amount by `other`, rounding down. Returns `None` if `other`
+    /// is zero.
+    pub fn checked_div(self, other: u64) -> Option<Self> {
+        Some(Self {
+            msats: self.msats.checked_div(other)?,
+        })
+    }
+
+    /// Displays the amount in `unit` instead of milli-satoshis, see
+    /// [`AmountDisplay`] for the formatting options
+    pub fn display_in(self, unit: AmountUnit) -> AmountDisplay {
+        AmountDisplay::new(self, unit)
+    }
 }
 
 /// Shorthand for [`Amount::from_msats`]
@@ -249,26 +290,6 @@ pub fn sats(amount: u64) -> Amount {
     Amount::from_sats(amount)
 }
 
-pub mod amount {
-    pub mod serde {
-        pub mod as_msat {
-            //! Serialize and deserialize [`Amount`](crate::Amount) as integers
-            //! denominated in milli-satoshi. Use with
-            //! `#[serde(with = "amount::serde::as_msat")]`.
-
-            use serde::{Deserialize, Deserializer, Serialize, Serializer};
-
-            pub fn serialize<S: Serializer>(a: &crate::Amount, s: S) -> Result<S::Ok, S::Error> {
-                u64::serialize(&a.msats, s)
-            }
-
-            pub fn deserialize<'d, D: Deserializer<'d>>(d: D) -> Result<crate::Amount, D::Error> {
-                Ok(crate::Amount::from_msats(u64::deserialize(d)?))
-            }
-        }
-    }
-}
-
 /// Amount of bitcoin to send, or "all" to send all available funds
 #[derive(Debug, Eq, PartialEq, Copy, Hash, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -322,6 +343,14 @@ pub enum ParseAmountError {
     NotANumber(#[from] ParseIntError),
     #[error("Error parsing string as a bitcoin amount: {0}")]
     WrongBitcoinAmount(#[from] bitcoin::amount::ParseAmountError),
+    #[error("Invalid number: {0}")]
+    InvalidNumber(String),
+    #[error("Unknown unit: {0}")]
+    UnknownUnit(String),
+    #[error("Amount is more precise than a millisatoshi")]
+    TooPrecise,
+    #[error("Amount is too large")]
+    Overflow,
 }
 
 impl<T> NumPeersExt for BTreeMap<PeerId, T> {
@@ -529,11 +558,18 @@ impl FromStr for Amount {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if let Some(i) = s.find(char::is_alphabetic) {
-            let (amt, denom) = s.split_at(i);
-            Amount::from_str_in(amt.trim(), denom.trim().parse()?)
+            let (amt, unit) = s.split_at(i);
+            match AmountUnit::from_str(unit.trim()) {
+                Ok(unit) => amount::parse_in(amt.trim(), unit),
+                // less common denominations like `mBTC` are only supported by `bitcoin`
+                Err(e) => {
+                    let denom = unit.trim().parse().map_err(|_| e)?;
+                    Amount::from_str_in(amt.trim(), denom)
+                }
+            }
         } else {
             // default to millisatoshi
-            Amount::from_str_in(s.trim(), bitcoin::Denomination::MilliSatoshi)
+            amount::parse_in(s.trim(), AmountUnit::MilliSatoshi)
         }
     }
 }
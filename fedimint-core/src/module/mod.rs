@@ -14,7 +14,7 @@
 pub mod audit;
 pub mod registry;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::{self, PhantomData};
 use std::pin::Pin;
@@ -30,6 +30,7 @@ use tracing::Instrument;
 // TODO: Make this module public and remove the wildcard `pub use` below
 mod version;
 pub use self::version::*;
+use crate::admin_client::AdminRole;
 use crate::config::{
     ClientModuleConfig, ConfigGenModuleParams, DkgPeerMsg, ModuleInitParams, ServerModuleConfig,
     ServerModuleConsensusConfig,
@@ -38,6 +39,7 @@ use crate::core::{
     ClientConfig, Decoder, DecoderBuilder, Input, InputError, ModuleConsensusItem,
     ModuleInstanceId, ModuleKind, Output, OutputError, OutputOutcome,
 };
+use crate::db::key_space::DbKeyPrefixDecl;
 use crate::db::{
     Committable, Database, DatabaseKey, DatabaseKeyWithNotify, DatabaseRecord, DatabaseTransaction,
     DatabaseVersion, ServerMigrationFn,
@@ -45,6 +47,7 @@ use crate::db::{
 use crate::encoding::{Decodable, DecodeError, Encodable};
 use crate::fmt_utils::AbbreviateHexBytes;
 use crate::module::audit::Audit;
+use crate::net::api_transport::ApiRequestTransport;
 use crate::net::peers::MuxPeerConnections;
 use crate::server::DynServerModule;
 use crate::task::{MaybeSend, TaskGroup};
@@ -77,6 +80,53 @@ impl TransactionItemAmount {
     };
 }
 
+/// Fees a module charges on top of the fees of [`TransactionItemAmount`],
+/// which can change over time, e.g. with the backlog of the module
+///
+/// The fee of an input or output is its base fee plus the fee per kilobyte
+/// of its consensus encoding. Clients query the current schedules of all
+/// modules and add the fees when funding a transaction. Since the fees of the
+/// change outputs depend on how the change is split, they can't always be
+/// matched exactly, so transactions paying schedule fees may overpay, the
+/// surplus being charged as fee.
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct FeeSchedule {
+    pub input_base: Amount,
+    pub output_base: Amount,
+    pub per_kilobyte: Amount,
+}
+
+impl FeeSchedule {
+    /// Fee of an input whose consensus encoding is `size` bytes long
+    pub fn input_fee(&self, size: usize) -> Amount {
+        self.input_base + self.size_fee(size)
+    }
+
+    /// Fee of an output whose consensus encoding is `size` bytes long
+    pub fn output_fee(&self, size: usize) -> Amount {
+        self.output_base + self.size_fee(size)
+    }
+
+    fn size_fee(&self, size: usize) -> Amount {
+        Amount::from_msats(self.per_kilobyte.msats.saturating_mul(size as u64) / 1000)
+    }
+}
+
+/// Health of a server module as reported by [`ServerModule::health_check`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "reason")]
+pub enum ModuleHealth {
+    Healthy,
+    /// The module still works, but will stop working if the problem persists,
+    /// e.g. because the external service it depends on is lagging behind
+    Degraded(String),
+    /// The module can't work, e.g. because the external service it depends on
+    /// is unreachable
+    Unhealthy(String),
+}
+
 /// All requests from client to server contain these fields
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ApiRequest<T> {
@@ -84,6 +134,10 @@ pub struct ApiRequest<T> {
     pub auth: Option<ApiAuth>,
     /// Parameters required by the API
     pub params: T,
+    /// Compression and size limit of the response, see
+    /// [`crate::net::api_transport`]
+    #[serde(default, skip_serializing_if = "ApiRequestTransport::is_default")]
+    pub transport: ApiRequestTransport,
 }
 
 pub type ApiRequestErased = ApiRequest<JsonValue>;
@@ -93,6 +147,7 @@ impl Default for ApiRequestErased {
         Self {
             auth: None,
             params: JsonValue::Null,
+            transport: ApiRequestTransport::default(),
         }
     }
 }
@@ -103,6 +158,7 @@ impl ApiRequestErased {
             auth: None,
             params: serde_json::to_value(params)
                 .expect("parameter serialization error - this should not happen"),
+            transport: ApiRequestTransport::default(),
         }
     }
 
@@ -114,6 +170,7 @@ impl ApiRequestErased {
         Self {
             auth: Some(auth),
             params: self.params,
+            transport: self.transport,
         }
     }
 
@@ -123,6 +180,7 @@ impl ApiRequestErased {
         Ok(ApiRequest {
             auth: self.auth,
             params: serde_json::from_value::<T>(self.params)?,
+            transport: self.transport,
         })
     }
 }
@@ -137,15 +195,45 @@ impl Debug for ApiAuth {
     }
 }
 
+/// Error code of the [`ApiError`] returned when submitting a transaction that
+/// uses a module instance the guardian put into maintenance mode
+pub const MODULE_IN_MAINTENANCE_ERROR_CODE: i32 = 503;
+
+/// Data of the [`ApiError`] returned when submitting a transaction that uses a
+/// module instance the guardian put into maintenance mode
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ModuleInMaintenanceError {
+    pub module_instance_id: ModuleInstanceId,
+}
+
 #[derive(Debug, Clone)]
 pub struct ApiError {
     pub code: i32,
     pub message: String,
+    /// Structured details about the error for clients to act on
+    pub data: Option<JsonValue>,
 }
 
 impl ApiError {
     pub fn new(code: i32, message: String) -> Self {
-        Self { code, message }
+        Self {
+            code,
+            message,
+            data: None,
+        }
+    }
+
+    pub fn module_in_maintenance(module_instance_id: ModuleInstanceId) -> Self {
+        Self {
+            code: MODULE_IN_MAINTENANCE_ERROR_CODE,
+            message: format!(
+                "Module instance {module_instance_id} is in maintenance mode, try again later"
+            ),
+            data: Some(
+                serde_json::to_value(ModuleInMaintenanceError { module_instance_id })
+                    .expect("Can't fail"),
+            ),
+        }
     }
 
     pub fn not_found(message: String) -> Self {
@@ -170,6 +258,7 @@ pub struct ApiEndpointContext<'dbtx> {
     db: Database,
     dbtx: DatabaseTransaction<'dbtx, Committable>,
     has_auth: bool,
+    token_role: Option<AdminRole>,
     request_auth: Option<ApiAuth>,
 }
 
@@ -185,10 +274,17 @@ impl<'a> ApiEndpointContext<'a> {
             db,
             dbtx,
             has_auth,
+            token_role: None,
             request_auth,
         }
     }
 
+    /// Sets the role of the API token the request was authenticated with
+    pub fn with_token_role(mut self, token_role: Option<AdminRole>) -> Self {
+        self.token_role = token_role;
+        self
+    }
+
     /// Database tx handle, will be committed
     pub fn dbtx<'s, 'mtx>(&'s mut self) -> DatabaseTransaction<'mtx, Committable>
     where
@@ -211,6 +307,16 @@ impl<'a> ApiEndpointContext<'a> {
         self.has_auth
     }
 
+    /// Role of the admin the request was authenticated as, the guardian
+    /// password grants every role
+    pub fn auth_role(&self) -> Option<AdminRole> {
+        if self.has_auth {
+            Some(AdminRole::Dangerous)
+        } else {
+            self.token_role
+        }
+    }
+
     pub fn db(&self) -> Database {
         self.db.clone()
     }
@@ -248,10 +354,7 @@ impl<'a> ApiEndpointContext<'a> {
                 "API server error when writing to database: {:?}",
                 err
             );
-            ApiError {
-                code: 500,
-                message: "API server error when writing to database".to_string(),
-            }
+            ApiError::server_error("API server error when writing to database".to_string())
         })
     }
 }
@@ -431,6 +534,8 @@ pub trait IDynCommonModuleInit: Debug {
 
     fn database_version(&self) -> DatabaseVersion;
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl>;
+
     async fn dump_database(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -461,6 +566,13 @@ pub trait ModuleInit: Debug + Clone + Send + Sync + 'static {
             >,
         >
     );
+
+    /// Declares the key prefixes of the records the module stores in its
+    /// database, see [`crate::db::key_space`]. Every prefix `dump_database`
+    /// knows about should be declared under the name it is filtered by.
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        Vec::new()
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -484,6 +596,10 @@ where
         <Self as ModuleInit>::DATABASE_VERSION
     }
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        <Self as ModuleInit>::db_key_prefixes(self)
+    }
+
     async fn dump_database(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -520,6 +636,10 @@ pub trait IServerModuleInit: IDynCommonModuleInit {
 
     fn validate_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<()>;
 
+    /// Checks only the consensus part of the config gen params, which all
+    /// guardians agree on unlike the local part
+    fn validate_consensus_params(&self, consensus: &serde_json::Value) -> anyhow::Result<()>;
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -730,6 +850,13 @@ where
         Ok(())
     }
 
+    fn validate_consensus_params(&self, consensus: &serde_json::Value) -> anyhow::Result<()> {
+        serde_json::from_value::<
+            <<Self as ServerModuleInit>::Params as ModuleInitParams>::Consensus,
+        >(consensus.clone())?;
+        Ok(())
+    }
+
     fn trusted_dealer_gen(
         &self,
         peers: &[PeerId],
@@ -878,6 +1005,29 @@ pub trait ServerModule: Debug + Sized {
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, <Self::Common as ModuleCommon>::OutputError>;
 
+    /// Checks the parts of an input that do not depend on the database, like
+    /// the signatures of the federation on an ecash note. The consensus engine
+    /// calls this for the transactions of an ordered batch in parallel before
+    /// they are processed one after another, so expensive cryptographic
+    /// checks should be done here instead of in [`Self::process_input`], which
+    /// is only called for inputs that passed this check.
+    fn verify_input(
+        &self,
+        _input: &<Self::Common as ModuleCommon>::Input,
+    ) -> Result<(), <Self::Common as ModuleCommon>::InputError> {
+        Ok(())
+    }
+
+    /// Checks the parts of an output that do not depend on the database, see
+    /// [`Self::verify_input`]. [`Self::process_output`] is only called for
+    /// outputs that passed this check.
+    fn verify_output(
+        &self,
+        _output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Result<(), <Self::Common as ModuleCommon>::OutputError> {
+        Ok(())
+    }
+
     /// Retrieve the current status of the output. Depending on the module this
     /// might contain data needed by the client to access funds or give an
     /// estimate of when funds will be available. Returns `None` if the
@@ -905,6 +1055,63 @@ pub trait ServerModule: Debug + Sized {
     /// should be deterministic, only dependant on their input and the
     /// current epoch.
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>>;
+
+    /// Current fee schedule of the module, see [`FeeSchedule`]. The fees are
+    /// charged in addition to the fees returned by [`Self::process_input`] and
+    /// [`Self::process_output`], so modules with static fees don't have to
+    /// implement this.
+    async fn fee_schedule(&self, _dbtx: &mut DatabaseTransaction<'_>) -> FeeSchedule {
+        FeeSchedule::default()
+    }
+
+    /// Consensus feature that has to be active before the federation charges
+    /// the fees of [`Self::fee_schedule`], so guardians that don't know the
+    /// schedule yet don't disagree on which transactions are funded
+    fn fee_schedule_consensus_feature(&self) -> Option<ModuleConsensusFeature> {
+        None
+    }
+
+    /// Consensus features this implementation supports, which the guardian
+    /// signals to the federation, see [`ModuleConsensusFeature`]
+    fn supported_consensus_features(&self) -> BTreeSet<ModuleConsensusFeature> {
+        BTreeSet::new()
+    }
+
+    /// Prefixes of database entries that only belong to this guardian, like
+    /// its own signatures or decryption shares. They are left out of
+    /// consensus checkpoints, since another guardian importing them would
+    /// propose them as its own.
+    fn local_db_prefixes(&self) -> BTreeSet<u8> {
+        BTreeSet::new()
+    }
+
+    /// Checks whether the module and the external services it depends on work
+    /// as expected. The result is part of the status of the guardian, so
+    /// monitoring can detect a degraded module before it stalls consensus.
+    /// Modules without anything to check return `None`.
+    async fn health_check(&self, _dbtx: &mut DatabaseTransaction<'_>) -> Option<ModuleHealth> {
+        None
+    }
+
+    /// Consensus feature that has to be active for `input` to be processed,
+    /// inputs requiring an inactive feature are rejected before
+    /// [`Self::process_input`] is called
+    fn input_consensus_feature(
+        &self,
+        _input: &<Self::Common as ModuleCommon>::Input,
+    ) -> Option<ModuleConsensusFeature> {
+        None
+    }
+
+    /// Consensus feature that has to be active for `output` to be processed,
+    /// outputs requiring an inactive feature are rejected before
+    /// [`Self::process_output`] is called
+    fn output_consensus_feature(
+        &self,
+        _output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Option<ModuleConsensusFeature> {
+        None
+    }
 }
 
 /// Creates a struct that can be used to make our module-decodable structs
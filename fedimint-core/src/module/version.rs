@@ -107,7 +107,20 @@ pub const CORE_CONSENSUS_VERSION: CoreConsensusVersion = CoreConsensusVersion::n
 /// the same time (each of different `ModuleKind` version), allow users to
 /// slowly migrate to a new one. This avoids complex and error-prone server-side
 /// consensus-migration logic.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    Encodable,
+    Decodable,
+)]
 pub struct ModuleConsensusVersion {
     pub major: u32,
     pub minor: u32,
@@ -119,6 +132,31 @@ impl ModuleConsensusVersion {
     }
 }
 
+/// A consensus feature of a module, e.g. a new input or output variant, that
+/// can be rolled out without bumping the [`ModuleConsensusVersion`].
+///
+/// Every guardian signals the features its module implementation supports. A
+/// feature becomes active once all guardians of the federation signaled it and
+/// stays active from then on. Inputs and outputs that require a feature are
+/// rejected until it is active, so guardians can upgrade one after another
+/// without the federation forking over items only some of them understand.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct ModuleConsensusFeature(pub String);
+
+impl ModuleConsensusFeature {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+}
+
+impl std::fmt::Display for ModuleConsensusFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
 /// Api version supported by a core server or a client/server module at a given
 /// [`ModuleConsensusVersion`].
 ///
@@ -159,6 +197,23 @@ impl ApiVersion {
     pub const fn new(major: u32, minor: u32) -> Self {
         Self { major, minor }
     }
+
+    /// Whether an API of this (negotiated) version offers everything that was
+    /// introduced in `required`, used to adapt to older federations.
+    ///
+    /// Unlike `>=` this doesn't consider APIs of a higher major version to be
+    /// compatible, since they might have removed functionality.
+    ///
+    /// ```
+    /// use fedimint_core::module::ApiVersion;
+    /// assert!(ApiVersion::new(0, 3).supports(ApiVersion::new(0, 2)));
+    /// assert!(ApiVersion::new(0, 2).supports(ApiVersion::new(0, 2)));
+    /// assert!(!ApiVersion::new(0, 1).supports(ApiVersion::new(0, 2)));
+    /// assert!(!ApiVersion::new(1, 0).supports(ApiVersion::new(0, 2)));
+    /// ```
+    pub fn supports(&self, required: ApiVersion) -> bool {
+        self.major == required.major && required.minor <= self.minor
+    }
 }
 
 /// ```
@@ -0,0 +1,235 @@
+//! Per-connection compression and message size negotiation of the API
+//!
+//! After connecting to a guardian, the client fetches the
+//! [`ApiTransportOptions`] of its server via
+//! [`crate::endpoint_constants::TRANSPORT_OPTIONS_ENDPOINT`] and picks the
+//! [`ApiRequestTransport`] it sends along with every request on the
+//! connection. If it picked a compression, the server answers with an
+//! [`ApiResponseEnvelope`] that compresses responses worth compressing, e.g.
+//! client configs and session outcomes downloaded during recovery. Clients of
+//! servers that don't know the endpoint send requests without transport
+//! parameters and receive plain responses.
+
+use std::io::{Read, Write};
+
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+use crate::module::ApiError;
+
+/// Largest request the API server accepts
+pub const MAX_API_REQUEST_SIZE: u32 = 10 * 1024 * 1024;
+
+/// Largest response the API server sends
+pub const MAX_API_RESPONSE_SIZE: u32 = 10 * 1024 * 1024;
+
+/// Serialized responses smaller than this are sent uncompressed, since the
+/// base64 encoding of the compressed data would outweigh the savings
+pub const MIN_COMPRESSED_RESPONSE_SIZE: usize = 1024;
+
+/// Error code of the [`ApiError`] returned if a response exceeds the
+/// `max_response_size` of the request
+pub const RESPONSE_TOO_LARGE_ERROR_CODE: i32 = 413;
+
+/// Compression of API responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiCompression {
+    Deflate,
+}
+
+/// Compressions and message sizes the API server of a guardian supports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiTransportOptions {
+    pub compression: Vec<ApiCompression>,
+    pub max_request_size: u32,
+    pub max_response_size: u32,
+}
+
+impl ApiTransportOptions {
+    /// Options of the API server of this version
+    pub fn server() -> Self {
+        Self {
+            compression: vec![ApiCompression::Deflate],
+            max_request_size: MAX_API_REQUEST_SIZE,
+            max_response_size: MAX_API_RESPONSE_SIZE,
+        }
+    }
+
+    /// Transport parameters of the requests of a client that supports
+    /// `compression` and accepts responses of up to `max_response_size`
+    pub fn negotiate(
+        &self,
+        compression: &[ApiCompression],
+        max_response_size: u32,
+    ) -> ApiRequestTransport {
+        ApiRequestTransport {
+            compression: compression
+                .iter()
+                .find(|compression| self.compression.contains(compression))
+                .copied(),
+            max_response_size: Some(max_response_size.min(self.max_response_size)),
+        }
+    }
+}
+
+/// Transport parameters of a request, chosen by the client from the
+/// [`ApiTransportOptions`] of the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiRequestTransport {
+    /// Compression of the response, the response is wrapped in an
+    /// [`ApiResponseEnvelope`] if set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<ApiCompression>,
+    /// Largest response the client accepts, larger responses are replaced by
+    /// an error instead of making the client drop the connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_size: Option<u32>,
+}
+
+impl ApiRequestTransport {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Encodes the response of a request with these transport parameters
+    pub fn encode_response(&self, response: JsonValue) -> Result<JsonValue, ApiError> {
+        if self.is_default() {
+            return Ok(response);
+        }
+
+        let serialized = serde_json::to_vec(&response).expect("Serializing json can't fail");
+
+        let response = match self.compression {
+            Some(compression) if MIN_COMPRESSED_RESPONSE_SIZE <= serialized.len() => {
+                serde_json::to_value(ApiResponseEnvelope::compress(compression, &serialized))
+                    .expect("Serializing envelope can't fail")
+            }
+            Some(_) => serde_json::to_value(ApiResponseEnvelope::Plain(response))
+                .expect("Serializing envelope can't fail"),
+            None => response,
+        };
+
+        if let Some(max_response_size) = self.max_response_size {
+            let size = serde_json::to_vec(&response)
+                .expect("Serializing json can't fail")
+                .len();
+
+            if size > max_response_size as usize {
+                return Err(ApiError::new(
+                    RESPONSE_TOO_LARGE_ERROR_CODE,
+                    format!(
+                        "Response of {size} bytes exceeds the maximum of {max_response_size} bytes"
+                    ),
+                ));
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Decodes the response of a request with these transport parameters
+    pub fn decode_response(&self, response: JsonValue) -> anyhow::Result<JsonValue> {
+        if self.compression.is_none() {
+            return Ok(response);
+        }
+
+        serde_json::from_value::<ApiResponseEnvelope>(response)?.decompress()
+    }
+}
+
+/// Response to a request that asked for compression
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiResponseEnvelope {
+    /// Response that was too small to be worth compressing
+    Plain(JsonValue),
+    /// Base64 encoded, deflate compressed json of the response
+    Deflate(String),
+}
+
+impl ApiResponseEnvelope {
+    fn compress(compression: ApiCompression, serialized: &[u8]) -> Self {
+        match compression {
+            ApiCompression::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(serialized)
+                    .expect("Writing to a vec can't fail");
+                let compressed = encoder.finish().expect("Writing to a vec can't fail");
+
+                ApiResponseEnvelope::Deflate(
+                    base64::engine::general_purpose::STANDARD.encode(compressed),
+                )
+            }
+        }
+    }
+
+    fn decompress(self) -> anyhow::Result<JsonValue> {
+        match self {
+            ApiResponseEnvelope::Plain(response) => Ok(response),
+            ApiResponseEnvelope::Deflate(data) => {
+                let compressed = base64::engine::general_purpose::STANDARD.decode(data)?;
+                // Bound the decompressed size, so a malicious server can't make us allocate
+                // arbitrary amounts of memory
+                let mut serialized = Vec::new();
+                DeflateDecoder::new(compressed.as_slice())
+                    .take(u64::from(MAX_API_RESPONSE_SIZE) + 1)
+                    .read_to_end(&mut serialized)?;
+                anyhow::ensure!(
+                    serialized.len() <= MAX_API_RESPONSE_SIZE as usize,
+                    "Decompressed response exceeds {MAX_API_RESPONSE_SIZE} bytes"
+                );
+
+                Ok(serde_json::from_slice(&serialized)?)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{
+        ApiCompression, ApiRequestTransport, ApiTransportOptions, RESPONSE_TOO_LARGE_ERROR_CODE,
+    };
+
+    #[test]
+    fn responses_roundtrip_through_compression() {
+        let transport = ApiTransportOptions::server().negotiate(&[ApiCompression::Deflate], 4096);
+        assert_eq!(transport.compression, Some(ApiCompression::Deflate));
+
+        let small = json!({ "session_count": 42 });
+        let large = json!(vec!["session outcome"; 1000]);
+
+        for response in [small, large.clone()] {
+            let encoded = transport.encode_response(response.clone()).unwrap();
+            assert_eq!(transport.decode_response(encoded).unwrap(), response);
+        }
+
+        // Compressed the large response fits, uncompressed it doesn't
+        let uncompressed = ApiRequestTransport {
+            compression: None,
+            max_response_size: Some(4096),
+        };
+        assert_eq!(
+            uncompressed.encode_response(large).unwrap_err().code,
+            RESPONSE_TOO_LARGE_ERROR_CODE
+        );
+    }
+
+    #[test]
+    fn default_transport_leaves_responses_untouched() {
+        let transport = ApiRequestTransport::default();
+        let response = json!(vec!["session outcome"; 1000]);
+
+        assert_eq!(
+            transport.encode_response(response.clone()).unwrap(),
+            response
+        );
+    }
+}
@@ -1,3 +1,4 @@
+pub mod api_transport;
 pub mod peers;
 
 pub const STANDARD_FEDIMINT_P2P_PORT: u16 = 8173;
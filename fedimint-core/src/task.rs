@@ -63,6 +63,35 @@ impl TaskGroupInner {
         }
     }
 }
+
+/// Resolves once the process receives Ctrl+C or, on unix, `SIGTERM`
+#[cfg(not(target_family = "wasm"))]
+pub async fn wait_for_shutdown_signal() {
+    use tokio::signal;
+
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
 /// A group of task working together
 ///
 /// Using this struct it is possible to spawn one or more
@@ -125,31 +154,6 @@ impl TaskGroup {
 
     #[cfg(not(target_family = "wasm"))]
     pub fn install_kill_handler(&self) {
-        use tokio::signal;
-
-        async fn wait_for_shutdown_signal() {
-            let ctrl_c = async {
-                signal::ctrl_c()
-                    .await
-                    .expect("failed to install Ctrl+C handler");
-            };
-
-            #[cfg(unix)]
-            let terminate = async {
-                signal::unix::signal(signal::unix::SignalKind::terminate())
-                    .expect("failed to install signal handler")
-                    .recv()
-                    .await;
-            };
-
-            #[cfg(not(unix))]
-            let terminate = std::future::pending::<()>();
-
-            tokio::select! {
-                _ = ctrl_c => {},
-                _ = terminate => {},
-            }
-        }
         runtime::spawn("kill handlers", {
             let task_group = self.clone();
             async move {
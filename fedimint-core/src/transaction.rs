@@ -1,9 +1,10 @@
 use bitcoin::hashes::Hash as BitcoinHash;
-use fedimint_core::core::{DynInput, DynOutput};
+use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::module::SerdeModuleEncoding;
+use fedimint_core::module::{ModuleConsensusFeature, SerdeModuleEncoding};
 use fedimint_core::{Amount, TransactionId};
 use secp256k1_zkp::schnorr;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::config::ALEPH_BFT_UNIT_BYTE_LIMIT;
@@ -146,7 +147,54 @@ pub enum TransactionError {
     Input(DynInputError),
     #[error("The transaction had an invalid output: {}", .0)]
     Output(DynOutputError),
+    #[error("The transaction requires feature {feature} of module {module_instance_id}, which is not active yet")]
+    InactiveFeature {
+        module_instance_id: ModuleInstanceId,
+        feature: ModuleConsensusFeature,
+    },
 }
 
+/// Maximum number of transactions that can be submitted in a single call to the
+/// `submit_transactions_batch` endpoint
+pub const MAX_TRANSACTIONS_PER_BATCH: usize = 100;
+
 #[derive(Debug, Encodable, Decodable, Clone, Eq, PartialEq)]
 pub struct TransactionSubmissionOutcome(pub Result<TransactionId, TransactionError>);
+
+/// Input or output of a transaction, identified by its index
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub enum TransactionItemIndex {
+    Input(u64),
+    Output(u64),
+}
+
+impl std::fmt::Display for TransactionItemIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionItemIndex::Input(idx) => write!(f, "input {idx}"),
+            TransactionItemIndex::Output(idx) => write!(f, "output {idx}"),
+        }
+    }
+}
+
+/// Reason why consensus rejected a transaction that was submitted
+/// successfully, e.g. because a conflicting transaction spent the same input
+/// first
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct TransactionRejection {
+    /// The input or output that failed validation, `None` if the transaction
+    /// as a whole is invalid
+    pub item: Option<TransactionItemIndex>,
+    /// The validation error, including the module error for inputs and
+    /// outputs
+    pub error: String,
+}
+
+impl std::fmt::Display for TransactionRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.item {
+            Some(item) => write!(f, "Transaction rejected, {item} is invalid: {}", self.error),
+            None => write!(f, "Transaction rejected: {}", self.error),
+        }
+    }
+}
@@ -88,9 +88,22 @@ impl SafeUrl {
         if s.port_or_known_default().is_none() {
             return Err(ParseError::InvalidPort);
         }
+
+        if s.is_onion() && !is_valid_onion_host(s.host_str().expect("onion urls have a host")) {
+            return Err(ParseError::InvalidDomainCharacter);
+        }
+
         Ok(s)
     }
 
+    /// Returns true if the url points to a Tor onion service, which can only
+    /// be reached through a Tor SOCKS5 proxy
+    pub fn is_onion(&self) -> bool {
+        self.0
+            .host_str()
+            .is_some_and(|host| host.to_ascii_lowercase().ends_with(".onion"))
+    }
+
     /// Warning: This removes the safety.
     // nosemgrep: ban-raw-url
     pub fn to_unsafe(self) -> Url {
@@ -161,6 +174,28 @@ impl SafeUrl {
     }
 }
 
+/// Length of the base32 encoded public key, checksum and version of a v3 onion
+/// service address
+const ONION_V3_ADDRESS_LEN: usize = 56;
+
+/// Checks that `host` is a v3 onion service address, deprecated v2 addresses
+/// are rejected since Tor can't reach them anymore
+fn is_valid_onion_host(host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let Some(address) = host.strip_suffix(".onion") else {
+        return false;
+    };
+
+    // Subdomains of onion services are ignored by Tor
+    let address = address.rsplit('.').next().unwrap_or(address);
+
+    address.len() == ONION_V3_ADDRESS_LEN
+        && address
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c))
+        && address.ends_with('d')
+}
+
 impl Display for SafeUrl {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}://", self.0.scheme())?;
@@ -190,6 +225,10 @@ impl<'de> serde::de::Deserialize<'de> for SafeUrl {
             return Err(serde::de::Error::custom("Invalid port"));
         }
 
+        if s.is_onion() && !is_valid_onion_host(s.host_str().expect("onion urls have a host")) {
+            return Err(serde::de::Error::custom("Invalid onion address"));
+        }
+
         Ok(s)
     }
 }
@@ -216,6 +255,10 @@ impl TryFrom<Url> for SafeUrl {
             anyhow::bail!("Invalid port");
         }
 
+        if s.is_onion() && !is_valid_onion_host(s.host_str().expect("onion urls have a host")) {
+            anyhow::bail!("Invalid onion address");
+        }
+
         Ok(s)
     }
 }
@@ -508,6 +551,23 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_safe_url_onion() {
+        let onion = "ws://vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:8174/";
+        let url = SafeUrl::parse(onion).unwrap();
+        assert!(url.is_onion());
+        assert!(SafeUrl::parse(&onion.to_uppercase()).unwrap().is_onion());
+        assert!(!SafeUrl::parse("ws://1.2.3.4:8174/").unwrap().is_onion());
+
+        // v2 onion address
+        assert!(SafeUrl::parse("ws://expyuzz4wqqyqhjn.onion:8174/").is_err());
+        // wrong version byte
+        assert!(SafeUrl::parse(
+            "ws://vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyya.onion:8174/"
+        )
+        .is_err());
+    }
+
     #[tokio::test]
     async fn test_next_or_pending() {
         let mut stream = futures::stream::iter(vec![1, 2]);
@@ -0,0 +1,143 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::Context;
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::db::key_space::{DbKeySpace, DbKeySpaceRegistry};
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCore};
+use fedimint_rocksdb::RocksDbReadOnly;
+use fedimint_server::config::io::read_server_config;
+use fedimint_server::consensus::db::server_db_key_spaces;
+use futures::StreamExt;
+
+/// Checks the database of a guardian against the key prefixes declared by
+/// `fedimint-core`, `fedimint-server` and its modules. Reports
+/// - prefixes declared by multiple owners or under multiple names
+/// - data stored under prefixes nobody declared
+/// - declared prefixes with data that `dump_database` of the module doesn't
+///   dump
+///
+/// The key spaces of modules that don't declare their prefixes are skipped.
+pub async fn check_database(
+    cfg_dir: &Path,
+    data_dir: &str,
+    password: &str,
+    module_inits: &ServerModuleInitRegistry,
+) -> anyhow::Result<()> {
+    let cfg = read_server_config(password, cfg_dir).context("Failed to read server config")?;
+
+    let decoders = module_inits
+        .available_decoders(cfg.iter_module_instances())?
+        .with_fallback();
+    let db = Database::new(
+        RocksDbReadOnly::open_read_only(data_dir).context("Failed to open database")?,
+        decoders,
+    );
+
+    let key_spaces = server_db_key_spaces(&cfg, module_inits);
+
+    let mut problems = key_spaces
+        .conflicts()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+
+    let mut dbtx = db.begin_transaction().await;
+    problems.extend(undeclared_prefixes(&mut dbtx, &key_spaces, DbKeySpace::Global).await?);
+
+    for (module_instance_id, module_cfg) in &cfg.consensus.modules {
+        let space = DbKeySpace::Module(*module_instance_id);
+        let Some(module_init) = module_inits.get(&module_cfg.kind) else {
+            println!(
+                "Skipping the {space}: module {} is unknown",
+                module_cfg.kind
+            );
+            continue;
+        };
+        if !key_spaces.has_declarations(space) {
+            println!(
+                "Skipping the {space}: module {} doesn't declare its prefixes",
+                module_cfg.kind
+            );
+            continue;
+        }
+
+        let mut isolated_dbtx = dbtx.to_ref_with_prefix_module_id(*module_instance_id);
+        problems.extend(undeclared_prefixes(&mut isolated_dbtx, &key_spaces, space).await?);
+
+        for prefix in used_prefixes(&mut isolated_dbtx).await? {
+            let names = key_spaces
+                .get(space, prefix)
+                .into_iter()
+                .map(|decl| decl.name.clone())
+                .collect::<BTreeSet<_>>();
+
+            for name in names {
+                let dumped = module_init
+                    .dump_database(&mut isolated_dbtx.to_ref_nc(), vec![name.to_lowercase()])
+                    .await
+                    .any(|(_, items)| is_non_empty(&serde_json::to_value(&items)));
+
+                if !dumped {
+                    problems.push(format!(
+                        "Prefix {name} (0x{prefix:02x}) in the {space} contains data that dump_database of module {} doesn't dump",
+                        module_cfg.kind
+                    ));
+                }
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        println!("No problems found");
+        return Ok(());
+    }
+
+    for problem in &problems {
+        println!("{problem}");
+    }
+    anyhow::bail!("Found {} problems in the database", problems.len())
+}
+
+/// Returns the prefixes with at least one key in the key space of `dbtx`
+async fn used_prefixes(dbtx: &mut DatabaseTransaction<'_>) -> anyhow::Result<Vec<u8>> {
+    let mut prefixes = Vec::new();
+    for prefix in 0u8..=255 {
+        if dbtx
+            .raw_find_by_prefix(&[prefix])
+            .await?
+            .next()
+            .await
+            .is_some()
+        {
+            prefixes.push(prefix);
+        }
+    }
+    Ok(prefixes)
+}
+
+async fn undeclared_prefixes(
+    dbtx: &mut DatabaseTransaction<'_>,
+    key_spaces: &DbKeySpaceRegistry,
+    space: DbKeySpace,
+) -> anyhow::Result<Vec<String>> {
+    Ok(used_prefixes(dbtx)
+        .await?
+        .into_iter()
+        .filter(|prefix| key_spaces.get(space, *prefix).is_empty())
+        .map(|prefix| {
+            format!("Prefix 0x{prefix:02x} in the {space} contains data but is not declared")
+        })
+        .collect())
+}
+
+fn is_non_empty(value: &serde_json::Result<serde_json::Value>) -> bool {
+    match value {
+        Ok(serde_json::Value::Null) => false,
+        Ok(serde_json::Value::Array(items)) => !items.is_empty(),
+        Ok(serde_json::Value::Object(items)) => !items.is_empty(),
+        Ok(_) => true,
+        // The items exist, even though they can't be serialized
+        Err(_) => true,
+    }
+}
@@ -12,12 +12,13 @@ use fedimint_core::db::{
 };
 use fedimint_core::encoding::Encodable;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::push_db_pair_items_no_serde;
+use fedimint_core::{push_db_key_items, push_db_pair_items_no_serde};
 use fedimint_rocksdb::RocksDbReadOnly;
 use fedimint_server::config::io::read_server_config;
 use fedimint_server::config::ServerConfig;
 use fedimint_server::consensus::db as ConsensusRange;
 use futures::StreamExt;
+use ln_gateway::earnings::{dump_earnings, EarningsDbKeyPrefix};
 use ln_gateway::Gateway;
 use strum::IntoEnumIterator;
 
@@ -199,6 +200,43 @@ impl DatabaseDump {
         Ok(())
     }
 
+    /// Gateways store their fee ledger in the databases of their lightning
+    /// client modules, which is dumped alongside the module if there is one
+    async fn serialize_gateway_earnings(
+        &mut self,
+        module_id: &u16,
+        kind: &ModuleKind,
+    ) -> anyhow::Result<()> {
+        if !["ln", "lnv2"].contains(&kind.as_str())
+            || (!self.modules.is_empty() && !self.modules.contains(&kind.to_string()))
+        {
+            return Ok(());
+        }
+        let mut dbtx = self.read_only.begin_transaction().await;
+        let mut isolated_dbtx = dbtx.to_ref_with_prefix_module_id(*module_id);
+
+        let mut has_ledger = false;
+        for prefix in EarningsDbKeyPrefix::iter() {
+            has_ledger |= isolated_dbtx
+                .raw_find_by_prefix(&[prefix as u8])
+                .await?
+                .next()
+                .await
+                .is_some();
+        }
+        if !has_ledger {
+            return Ok(());
+        }
+
+        let earnings_serialized =
+            dump_earnings(&mut isolated_dbtx.to_ref_nc(), &self.prefixes).await;
+        self.serialized.insert(
+            format!("gateway-earnings-{module_id}"),
+            Box::new(earnings_serialized),
+        );
+        Ok(())
+    }
+
     /// Iterates through all the specified ranges in the database and retrieves
     /// the data for each range. Prints serialized contents at the end.
     pub async fn dump_database(&mut self) -> anyhow::Result<()> {
@@ -228,6 +266,7 @@ impl DatabaseDump {
 
                 let registry = CommonModuleInitRegistry::from(modules);
                 self.serialize_module(module_id, kind, registry).await?;
+                self.serialize_gateway_earnings(module_id, kind).await?;
             }
 
             self.print_database();
@@ -293,6 +332,128 @@ impl DatabaseDump {
                         "Aleph Units"
                     );
                 }
+                // Contains the guardian's setup secrets and is removed once setup completes
+                ConsensusRange::DbKeyPrefix::ConfigGenCheckpoint => {}
+                ConsensusRange::DbKeyPrefix::SessionRetention => {
+                    if let Some(retention) =
+                        dbtx.get_value(&ConsensusRange::SessionRetentionKey).await
+                    {
+                        consensus.insert("Session Retention".to_string(), Box::new(retention));
+                    }
+                }
+                ConsensusRange::DbKeyPrefix::ModuleMaintenance => {
+                    push_db_key_items!(
+                        dbtx,
+                        ConsensusRange::ModuleMaintenancePrefix,
+                        ConsensusRange::ModuleMaintenanceKey,
+                        consensus,
+                        "Modules In Maintenance"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::RejectedTransaction => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::RejectedTransactionKeyPrefix,
+                        ConsensusRange::RejectedTransactionKey,
+                        fedimint_core::transaction::TransactionRejection,
+                        consensus,
+                        "Rejected Transactions"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ModuleAdditionVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ModuleAdditionVotePrefix,
+                        ConsensusRange::ModuleAdditionVoteKey,
+                        fedimint_core::epoch::ModuleAdditionProposal,
+                        consensus,
+                        "Module Addition Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ModuleAddition => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ModuleAdditionPrefix,
+                        ConsensusRange::ModuleAdditionKey,
+                        fedimint_core::epoch::ModuleAdditionProposal,
+                        consensus,
+                        "Module Additions"
+                    );
+                }
+                // Contains the local config gen params, which may include credentials
+                ConsensusRange::DbKeyPrefix::PendingModuleAddition => {}
+                ConsensusRange::DbKeyPrefix::ModuleFeatureSignal => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ModuleFeatureSignalPrefix,
+                        ConsensusRange::ModuleFeatureSignalKey,
+                        std::collections::BTreeSet<fedimint_core::module::ModuleConsensusFeature>,
+                        consensus,
+                        "Module Feature Signals"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ActiveModuleFeatures => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ActiveModuleFeaturesPrefix,
+                        ConsensusRange::ActiveModuleFeaturesKey,
+                        std::collections::BTreeSet<fedimint_core::module::ModuleConsensusFeature>,
+                        consensus,
+                        "Active Module Features"
+                    );
+                }
+                // Contains the credentials of the guardian's admins
+                ConsensusRange::DbKeyPrefix::ApiToken => {}
+                ConsensusRange::DbKeyPrefix::UpgradeVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::UpgradeVotePrefix,
+                        ConsensusRange::UpgradeVoteKey,
+                        fedimint_core::epoch::UpgradeProposal,
+                        consensus,
+                        "Upgrade Votes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::ScheduledUpgrade => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::ScheduledUpgradePrefix,
+                        ConsensusRange::ScheduledUpgradeKey,
+                        fedimint_core::epoch::UpgradeProposal,
+                        consensus,
+                        "Scheduled Upgrade"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PendingUpgrade => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::PendingUpgradePrefix,
+                        ConsensusRange::PendingUpgradeKey,
+                        fedimint_core::epoch::UpgradeProposal,
+                        consensus,
+                        "Pending Upgrade"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PrunedSessionOutcome => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::PrunedSessionOutcomePrefix,
+                        ConsensusRange::PrunedSessionOutcomeKey,
+                        fedimint_core::session_outcome::SessionOutcome,
+                        consensus,
+                        "Pruned Session Outcomes"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::RestoredSessionCheckpoint => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusRange::RestoredSessionCheckpointPrefix,
+                        ConsensusRange::RestoredSessionCheckpointKey,
+                        fedimint_server::config::io::SessionCheckpoint,
+                        consensus,
+                        "Restored Session Checkpoint"
+                    );
+                }
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -31,9 +31,11 @@ use fedimint_wallet_server::WalletInit;
 use futures::StreamExt;
 use hex::ToHex;
 
+use crate::check::check_database;
 use crate::dump::DatabaseDump;
 use crate::envs::{FM_DBTOOL_CONFIG_DIR_ENV, FM_DBTOOL_DATABASE_ENV, FM_PASSWORD_ENV};
 
+mod check;
 mod dump;
 
 #[derive(Debug, Clone, Parser)]
@@ -93,6 +95,16 @@ enum DbCommand {
         #[arg(long, required = false)]
         prefixes: Option<String>,
     },
+    /// Check the database of a guardian for conflicting key prefix
+    /// declarations, data under undeclared prefixes and declared prefixes
+    /// that are not covered by `dump_database` of their module. Password is
+    /// used to decrypt the server's configuration file.
+    Check {
+        #[clap(long, env = FM_DBTOOL_CONFIG_DIR_ENV)]
+        cfg_dir: PathBuf,
+        #[arg(long, env = FM_PASSWORD_ENV)]
+        password: String,
+    },
 }
 
 fn hex_parser(hex: &str) -> Result<Bytes> {
@@ -238,6 +250,15 @@ impl FedimintDBTool {
                 .await?;
                 dbdump.dump_database().await?;
             }
+            DbCommand::Check { cfg_dir, password } => {
+                let module_inits = if options.no_modules {
+                    ServerModuleInitRegistry::new()
+                } else {
+                    self.server_module_inits.clone()
+                };
+
+                check_database(cfg_dir, &options.database, password, &module_inits).await?;
+            }
             DbCommand::DeletePrefix { prefix } => {
                 let rocksdb = fedimint_rocksdb::RocksDb::open(&options.database)
                     .unwrap()
@@ -157,6 +157,7 @@ pub async fn build_client(
             client_builder
                 .join(root_secret, client_config.clone(), invite_code.api_secret())
                 .await
+                .map_err(anyhow::Error::from)
         } else {
             bail!("Database not initialize and invite code not provided");
         }
@@ -278,6 +279,16 @@ pub async fn gateway_pay_invoice(
             LnPayState::WaitingForRefund { error_reason } => {
                 warn!("{prefix} Waiting for refund: {error_reason:?}");
             }
+            LnPayState::Redispatched {
+                failed_gateway_id,
+                gateway_id,
+                error_reason,
+            } => {
+                warn!(
+                    "{prefix} Gateway {failed_gateway_id} failed due to {error_reason:?}, \
+                     retrying with {gateway_id}"
+                );
+            }
             LnPayState::UnexpectedError { error_message } => {
                 bail!("Failed to pay invoice: {error_message:?}")
             }
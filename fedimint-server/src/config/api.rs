@@ -1,10 +1,12 @@
 use std::collections::BTreeMap;
+use std::io::{Read, Write};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
 use bitcoin_hashes::sha256;
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
 use fedimint_api_client::api::{DynGlobalApi, StatusResponse};
 use fedimint_core::admin_client::{
     ConfigGenConnectionsRequest, ConfigGenParamsConsensus, ConfigGenParamsRequest,
@@ -14,8 +16,8 @@ use fedimint_core::config::{
     ConfigGenModuleParams, ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry,
 };
 use fedimint_core::core::ModuleInstanceId;
-use fedimint_core::db::Database;
-use fedimint_core::encoding::Encodable;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::endpoint_constants::{
     ADD_CONFIG_GEN_PEER_ENDPOINT, AUTH_ENDPOINT, CONFIG_GEN_PEERS_ENDPOINT,
     CONSENSUS_CONFIG_GEN_PARAMS_ENDPOINT, DEFAULT_CONFIG_GEN_PARAMS_ENDPOINT,
@@ -23,6 +25,7 @@ use fedimint_core::endpoint_constants::{
     SET_CONFIG_GEN_PARAMS_ENDPOINT, SET_PASSWORD_ENDPOINT, START_CONSENSUS_ENDPOINT,
     STATUS_ENDPOINT, VERIFIED_CONFIGS_ENDPOINT, VERIFY_CONFIG_HASH_ENDPOINT,
 };
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
     api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, ApiVersion,
 };
@@ -36,6 +39,7 @@ use tokio_rustls::rustls;
 use tracing::{error, info};
 
 use crate::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+use crate::consensus::db::ConfigGenCheckpointKey;
 use crate::envs::FM_PEER_ID_SORT_BY_URL_ENV;
 use crate::net::api::{check_auth, ApiResult, HasApiContext};
 use crate::net::peers::DelayCalculator;
@@ -45,7 +49,7 @@ use crate::net::peers::DelayCalculator;
 pub struct ConfigGenApi {
     /// In-memory state machine
     state: Arc<Mutex<ConfigGenState>>,
-    /// DB not really used
+    /// Stores the [`EncryptedConfigGenCheckpoint`] so setup survives a restart
     db: Database,
     /// Tracks when the config is generated
     config_generated_tx: Sender<ServerConfig>,
@@ -79,8 +83,39 @@ impl ConfigGenApi {
     }
 
     // Sets the auth and decryption key derived from the password
+    //
+    // If we were restarted during config gen the password decrypts the
+    // checkpoint of the setup, which is then resumed. If DKG had already
+    // completed we resume verifying the configs, otherwise we wait for `run_dkg`
+    // to be called again with the connections and params we previously agreed
+    // on, so the other guardians only have to retry DKG instead of restarting
+    // the setup.
     pub async fn set_password(&self, auth: ApiAuth) -> ApiResult<()> {
         let mut state = self.require_status(ServerStatus::AwaitingPassword).await?;
+
+        let checkpoint = self
+            .db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ConfigGenCheckpointKey)
+            .await;
+        if let Some(checkpoint) = checkpoint {
+            let checkpoint = checkpoint.decrypt(&auth).map_err(|_| {
+                ApiError::bad_request(
+                    "Password does not match the one the interrupted setup was started with"
+                        .to_string(),
+                )
+            })?;
+            state.restore(checkpoint);
+            state.auth = Some(auth);
+            info!(
+                target: fedimint_logging::LOG_NET_PEER_DKG,
+                status = ?state.status,
+                "Restored config gen from checkpoint"
+            );
+            return Ok(());
+        }
+
         state.auth = Some(auth);
         state.status = ServerStatus::SharingConfigGenParams;
         info!(
@@ -130,10 +165,14 @@ impl ConfigGenApi {
         let local = state.local.clone();
 
         if let Some(url) = local.and_then(|local| local.leader_api_url) {
-            DynGlobalApi::from_pre_peer_id_admin_endpoint(url, &self.api_secret)
-                .add_config_gen_peer(state.our_peer_info()?)
-                .await
-                .map_err(|_| ApiError::not_found("Unable to connect to the leader".to_string()))?;
+            DynGlobalApi::from_pre_peer_id_admin_endpoint_with_proxy(
+                url,
+                &self.api_secret,
+                state.settings.socks5_proxy,
+            )
+            .add_config_gen_peer(state.our_peer_info()?)
+            .await
+            .map_err(|_| ApiError::not_found("Unable to connect to the leader".to_string()))?;
         }
         Ok(())
     }
@@ -192,9 +231,10 @@ impl ConfigGenApi {
 
         let consensus = match local.and_then(|local| local.leader_api_url) {
             Some(leader_url) => {
-                let client = DynGlobalApi::from_pre_peer_id_admin_endpoint(
+                let client = DynGlobalApi::from_pre_peer_id_admin_endpoint_with_proxy(
                     leader_url.clone(),
                     &self.api_secret,
+                    state.settings.socks5_proxy,
                 );
                 let response = client.consensus_config_gen_params().await;
                 response
@@ -221,11 +261,15 @@ impl ConfigGenApi {
     /// blocking until completion, which can be fragile due to timeouts, poor
     /// network connections, etc.
     ///
-    /// Calling a second time will return an error.
+    /// Calling a second time will return an error, unless the previous DKG
+    /// failed in which case it is retried with the same connections and params.
     pub async fn run_dkg(&self) -> ApiResult<()> {
         let leader = {
             let mut state = self
-                .require_status(ServerStatus::SharingConfigGenParams)
+                .require_any_status(&[
+                    ServerStatus::SharingConfigGenParams,
+                    ServerStatus::ConfigGenFailed,
+                ])
                 .await?;
             // Update our state
             state.status = ServerStatus::ReadyForConfigGen;
@@ -236,7 +280,11 @@ impl ConfigGenApi {
             // Create a WSClient for the leader
             state.local.clone().and_then(|local| {
                 local.leader_api_url.map(|url| {
-                    DynGlobalApi::from_pre_peer_id_admin_endpoint(url, &self.api_secret.clone())
+                    DynGlobalApi::from_pre_peer_id_admin_endpoint_with_proxy(
+                        url,
+                        &self.api_secret.clone(),
+                        state.settings.socks5_proxy,
+                    )
                 })
             })
         };
@@ -271,6 +319,9 @@ impl ConfigGenApi {
                 (params, registry)
             };
 
+            // Persist everything needed to retry DKG without restarting setup
+            self_clone.write_checkpoint().await?;
+
             // Run DKG
             let mut task_group: TaskGroup = self_clone.task_group.make_subgroup();
             let config = ServerConfig::distributed_gen(
@@ -286,6 +337,7 @@ impl ConfigGenApi {
                 .await
                 .expect("shuts down");
 
+            let dkg_succeeded = config.is_ok();
             {
                 let mut state = self_clone.state.lock().await;
                 match config {
@@ -310,12 +362,41 @@ impl ConfigGenApi {
                     }
                 }
             }
+
+            // Checkpoint the generated config so we don't need to rerun DKG with
+            // everyone if we crash while the configs are being verified
+            if dkg_succeeded {
+                self_clone.write_checkpoint().await?;
+            }
             self_clone.update_leader().await
         });
 
         Ok(())
     }
 
+    /// Persists the current setup progress as a [`ConfigGenCheckpoint`]
+    /// encrypted with our password
+    async fn write_checkpoint(&self) -> ApiResult<()> {
+        let (checkpoint, auth) = {
+            let state = self.state.lock().await;
+            (state.checkpoint()?, state.auth()?)
+        };
+        let encrypted = checkpoint
+            .encrypt(&auth)
+            .map_err(|e| ApiError::server_error(format!("Unable to encrypt checkpoint: {e}")))?;
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&ConfigGenCheckpointKey, &encrypted).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ApiError::server_error(format!("Unable to write checkpoint: {e}")))?;
+        info!(
+            target: fedimint_logging::LOG_NET_PEER_DKG,
+            dkg_completed = checkpoint.config.is_some(),
+            "Wrote config gen checkpoint"
+        );
+        Ok(())
+    }
+
     /// Returns tagged hashes of consensus config to be shared with other peers.
     /// The hashes are tagged with the peer id  such that they are unique to
     /// each peer and their manual verification by the guardians via the UI is
@@ -401,6 +482,12 @@ impl ConfigGenApi {
             ];
             let mut state = self.require_any_status(&expected_status).await?;
 
+            // Make sure we don't resume the abandoned setup after a restart
+            let mut dbtx = self.db.begin_transaction().await;
+            dbtx.remove_entry(&ConfigGenCheckpointKey).await;
+            dbtx.commit_tx_result()
+                .await
+                .map_err(|e| ApiError::server_error(format!("Unable to remove checkpoint: {e}")))?;
             state.status = ServerStatus::SetupRestarted;
             info!(
                 target: fedimint_logging::LOG_NET_PEER_DKG,
@@ -408,9 +495,13 @@ impl ConfigGenApi {
             );
             // Create a WSClient for the leader
             state.local.clone().and_then(|local| {
-                local
-                    .leader_api_url
-                    .map(|url| DynGlobalApi::from_pre_peer_id_admin_endpoint(url, &self.api_secret))
+                local.leader_api_url.map(|url| {
+                    DynGlobalApi::from_pre_peer_id_admin_endpoint_with_proxy(
+                        url,
+                        &self.api_secret,
+                        state.settings.socks5_proxy,
+                    )
+                })
             })
         };
 
@@ -492,6 +583,9 @@ pub struct ConfigGenParamsLocal {
     pub api_bind: SocketAddr,
     /// How many API connections we will accept
     pub max_connections: u32,
+    /// SOCKS5 proxy the P2P connections to the other guardians are made
+    /// through
+    pub socks5_proxy: Option<SocketAddr>,
 }
 
 /// All the info we configure prior to config gen starting
@@ -513,6 +607,9 @@ pub struct ConfigGenSettings {
     pub max_connections: u32,
     /// Registry for config gen
     pub registry: ServerModuleInitRegistry,
+    /// SOCKS5 proxy, e.g. Tor, all connections to the other guardians are made
+    /// through, required if they are only reachable at onion URLs
+    pub socks5_proxy: Option<SocketAddr>,
 }
 
 /// State held by the API after receiving a `ConfigGenConnectionsRequest`
@@ -549,6 +646,109 @@ struct ConfigGenLocalConnection {
     leader_api_url: Option<SafeUrl>,
 }
 
+/// Setup progress of a guardian, allowing it to resume after a restart during
+/// config gen. Only persisted as an [`EncryptedConfigGenCheckpoint`] since it
+/// contains the guardian's TLS key and, once DKG completed, its private config.
+#[derive(Debug, Clone)]
+pub struct ConfigGenCheckpoint {
+    local: ConfigGenLocalConnection,
+    peers: BTreeMap<SafeUrl, PeerServerParams>,
+    requested_params: ConfigGenParamsRequest,
+    /// Set once DKG completed successfully
+    config: Option<ServerConfig>,
+}
+
+impl Encodable for ConfigGenCheckpoint {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        len += self.local.tls_private.0.consensus_encode(writer)?;
+        len += self.local.tls_cert.0.consensus_encode(writer)?;
+        len += self.local.our_name.consensus_encode(writer)?;
+        len += self.local.leader_api_url.consensus_encode(writer)?;
+        len += serde_json::to_string(&self.peers)
+            .expect("JSON serialization should not fail")
+            .consensus_encode(writer)?;
+        len += serde_json::to_string(&self.requested_params)
+            .expect("JSON serialization should not fail")
+            .consensus_encode(writer)?;
+        len += self
+            .config
+            .as_ref()
+            .map(|config| {
+                serde_json::to_string(config).expect("JSON serialization should not fail")
+            })
+            .consensus_encode(writer)?;
+
+        Ok(len)
+    }
+}
+
+impl Decodable for ConfigGenCheckpoint {
+    fn consensus_decode<R: Read>(
+        r: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let local = ConfigGenLocalConnection {
+            tls_private: rustls::PrivateKey(Vec::<u8>::consensus_decode(r, modules)?),
+            tls_cert: rustls::Certificate(Vec::<u8>::consensus_decode(r, modules)?),
+            our_name: String::consensus_decode(r, modules)?,
+            leader_api_url: Option::<SafeUrl>::consensus_decode(r, modules)?,
+        };
+
+        let peers_str = String::consensus_decode(r, modules)?;
+        let peers = serde_json::from_str(&peers_str).map_err(DecodeError::from_err)?;
+
+        let params_str = String::consensus_decode(r, modules)?;
+        let requested_params = serde_json::from_str(&params_str).map_err(DecodeError::from_err)?;
+
+        let config_str = Option::<String>::consensus_decode(r, modules)?;
+        let config = config_str
+            .map(|config_str| serde_json::from_str(&config_str).map_err(DecodeError::from_err))
+            .transpose()?;
+
+        Ok(ConfigGenCheckpoint {
+            local,
+            peers,
+            requested_params,
+            config,
+        })
+    }
+}
+
+impl ConfigGenCheckpoint {
+    /// Encrypts the checkpoint with a key derived from the guardian's password
+    /// and a fresh salt, like the private config written to the data dir
+    fn encrypt(&self, auth: &ApiAuth) -> anyhow::Result<EncryptedConfigGenCheckpoint> {
+        let salt = random_salt();
+        let key = get_encryption_key(&auth.0, &salt)?;
+        let ciphertext = encrypt(self.consensus_encode_to_vec(), &key)?;
+
+        Ok(EncryptedConfigGenCheckpoint { salt, ciphertext })
+    }
+}
+
+/// A [`ConfigGenCheckpoint`] as stored in the database, only the guardian's
+/// password can decrypt it
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct EncryptedConfigGenCheckpoint {
+    salt: String,
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedConfigGenCheckpoint {
+    /// Fails if `auth` is not the password the checkpoint was encrypted with
+    fn decrypt(&self, auth: &ApiAuth) -> anyhow::Result<ConfigGenCheckpoint> {
+        let key = get_encryption_key(&auth.0, &self.salt)?;
+        let mut ciphertext = self.ciphertext.clone();
+        let plaintext = decrypt(&mut ciphertext, &key)?;
+
+        Ok(ConfigGenCheckpoint::consensus_decode_vec(
+            plaintext.to_vec(),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
 impl ConfigGenState {
     fn new(settings: ConfigGenSettings) -> Self {
         Self {
@@ -584,6 +784,29 @@ impl ConfigGenState {
         ))
     }
 
+    fn checkpoint(&self) -> ApiResult<ConfigGenCheckpoint> {
+        Ok(ConfigGenCheckpoint {
+            local: self.local_connection()?,
+            peers: self.peers.clone(),
+            requested_params: self.requested_params.clone().ok_or(ApiError::bad_request(
+                "Config params were not set on this guardian".to_string(),
+            ))?,
+            config: self.config.clone(),
+        })
+    }
+
+    fn restore(&mut self, checkpoint: ConfigGenCheckpoint) {
+        self.local = Some(checkpoint.local);
+        self.peers = checkpoint.peers;
+        self.requested_params = Some(checkpoint.requested_params);
+        self.status = if checkpoint.config.is_some() {
+            ServerStatus::VerifyingConfigs
+        } else {
+            ServerStatus::SharingConfigGenParams
+        };
+        self.config = checkpoint.config;
+    }
+
     fn auth(&self) -> ApiResult<ApiAuth> {
         self.auth
             .clone()
@@ -669,6 +892,7 @@ impl ConfigGenState {
             p2p_bind: self.settings.p2p_bind,
             api_bind: self.settings.api_bind,
             max_connections: self.settings.max_connections,
+            socks5_proxy: self.settings.socks5_proxy,
         };
 
         Ok(ConfigGenParams { local, consensus })
@@ -815,7 +1039,8 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConfigGenApi>> {
                 let server = config.server_status().await;
                 Ok(StatusResponse {
                     server,
-                    federation: None
+                    federation: None,
+                    modules: BTreeMap::new(),
                 })
             }
         },
@@ -851,14 +1076,15 @@ mod tests {
     use fedimint_core::admin_client::{ConfigGenParamsRequest, ServerStatus};
     use fedimint_core::config::{ServerModuleConfigGenParamsRegistry, ServerModuleInitRegistry};
     use fedimint_core::db::mem_impl::MemDatabase;
-    use fedimint_core::db::IRawDatabaseExt;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped, IRawDatabaseExt};
     use fedimint_core::module::ApiAuth;
     use fedimint_core::runtime::spawn;
     use fedimint_core::task::{sleep, TaskGroup};
     use fedimint_core::util::SafeUrl;
     use fedimint_core::Amount;
     use fedimint_dummy_common::config::{
-        DummyConfig, DummyGenParams, DummyGenParamsConsensus, DummyGenParamsLocal,
+        DummyConfig, DummyFailureInjection, DummyGenParams, DummyGenParamsConsensus,
+        DummyGenParamsLocal,
     };
     use fedimint_dummy_server::DummyInit;
     use fedimint_logging::TracingSetup;
@@ -868,9 +1094,10 @@ mod tests {
     use itertools::Itertools;
     use tracing::info;
 
-    use crate::config::api::{ConfigGenConnectionsRequest, ConfigGenSettings};
+    use crate::config::api::{ConfigGenApi, ConfigGenConnectionsRequest, ConfigGenSettings};
     use crate::config::io::{read_server_config, PLAINTEXT_PASSWORD};
     use crate::config::{DynServerModuleInit, ServerConfig, DEFAULT_MAX_CLIENT_CONNECTIONS};
+    use crate::consensus::db::ConfigGenCheckpointKey;
     use crate::fedimint_core::module::ServerModuleInit;
     use crate::net::api::ApiSecrets;
 
@@ -891,32 +1118,9 @@ mod tests {
             let db = MemDatabase::new().into_database();
 
             let name = format!("peer{name_suffix}");
-            let api_bind = format!("127.0.0.1:{port}").parse().expect("parses");
-            let api_url: SafeUrl = format!("ws://127.0.0.1:{port}").parse().expect("parses");
-            let p2p_bind = format!("127.0.0.1:{}", port + 1).parse().expect("parses");
-            let p2p_url = format!("fedimint://127.0.0.1:{}", port + 1)
-                .parse()
-                .expect("parses");
             let module_inits = ServerModuleInitRegistry::from_iter([DummyInit.into()]);
-            let mut modules = ServerModuleConfigGenParamsRegistry::default();
-            modules.attach_config_gen_params_by_id(0, DummyInit::kind(), DummyGenParams::default());
-
-            let default_params = ConfigGenParamsRequest {
-                meta: Default::default(),
-                modules,
-            };
-            let settings = ConfigGenSettings {
-                download_token_limit: None,
-                p2p_bind,
-                api_bind,
-                p2p_url,
-                api_url: api_url.clone(),
-                default_params,
-                max_connections: DEFAULT_MAX_CLIENT_CONNECTIONS,
-                registry: ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(
-                    DummyInit,
-                )]),
-            };
+            let settings = test_settings(port);
+            let api_url = settings.api_url.clone();
 
             let dir = data_dir.join(name_suffix.to_string());
             fs::create_dir_all(dir.clone()).expect("Unable to create test dir");
@@ -1037,6 +1241,7 @@ mod tests {
                     local: DummyGenParamsLocal,
                     consensus: DummyGenParamsConsensus {
                         tx_fee: self.amount,
+                        failure_injection: DummyFailureInjection::default(),
                     },
                 },
             );
@@ -1058,6 +1263,105 @@ mod tests {
         }
     }
 
+    /// Settings of a guardian with its API on `port` and its P2P endpoint on
+    /// the next port
+    fn test_settings(port: u16) -> ConfigGenSettings {
+        let api_bind = format!("127.0.0.1:{port}").parse().expect("parses");
+        let api_url: SafeUrl = format!("ws://127.0.0.1:{port}").parse().expect("parses");
+        let p2p_bind = format!("127.0.0.1:{}", port + 1).parse().expect("parses");
+        let p2p_url = format!("fedimint://127.0.0.1:{}", port + 1)
+            .parse()
+            .expect("parses");
+        let mut modules = ServerModuleConfigGenParamsRegistry::default();
+        modules.attach_config_gen_params_by_id(0, DummyInit::kind(), DummyGenParams::default());
+
+        let default_params = ConfigGenParamsRequest {
+            meta: Default::default(),
+            modules,
+        };
+        ConfigGenSettings {
+            download_token_limit: None,
+            p2p_bind,
+            api_bind,
+            p2p_url,
+            api_url,
+            default_params,
+            max_connections: DEFAULT_MAX_CLIENT_CONNECTIONS,
+            registry: ServerModuleInitRegistry::from(vec![DynServerModuleInit::from(DummyInit)]),
+            socks5_proxy: None,
+        }
+    }
+
+    fn config_gen_api(db: Database) -> ConfigGenApi {
+        let (config_generated_tx, _) = tokio::sync::mpsc::channel(1);
+        ConfigGenApi::new(
+            test_settings(port_alloc(2).unwrap()),
+            db,
+            config_generated_tx,
+            &mut TaskGroup::new(),
+            "dummyversionhash".to_owned(),
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn setup_resumes_from_encrypted_checkpoint() {
+        let db = MemDatabase::new().into_database();
+        let auth = ApiAuth("correct horse battery staple".to_string());
+
+        let api = config_gen_api(db.clone());
+        api.set_password(auth.clone()).await.unwrap();
+        api.set_config_gen_connections(ConfigGenConnectionsRequest {
+            our_name: "leader".to_string(),
+            leader_api_url: None,
+        })
+        .await
+        .unwrap();
+        let requested_params = api.default_config_gen_params().await.unwrap();
+        api.state.lock().await.requested_params = Some(requested_params.clone());
+        api.write_checkpoint().await.unwrap();
+
+        // Neither the password nor the TLS key are stored in plaintext
+        let tls_private = api.state.lock().await.local.clone().unwrap().tls_private;
+        let encrypted = db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ConfigGenCheckpointKey)
+            .await
+            .expect("Checkpoint was written");
+        let contains = |needle: &[u8]| {
+            encrypted
+                .ciphertext
+                .windows(needle.len())
+                .any(|window| window == needle)
+        };
+        assert!(!contains(&tls_private.0));
+        assert!(!contains(auth.0.as_bytes()));
+
+        // After a restart only the same password resumes the setup
+        let restarted = config_gen_api(db);
+        assert!(restarted
+            .set_password(ApiAuth("wrong password".to_string()))
+            .await
+            .is_err());
+        assert_eq!(
+            restarted.server_status().await,
+            ServerStatus::AwaitingPassword
+        );
+
+        restarted.set_password(auth.clone()).await.unwrap();
+        assert_eq!(
+            restarted.server_status().await,
+            ServerStatus::SharingConfigGenParams
+        );
+        let state = restarted.state.lock().await;
+        let local = state.local.clone().expect("Connection was restored");
+        assert_eq!(local.tls_private, tls_private);
+        assert_eq!(local.our_name, "leader");
+        assert_eq!(state.requested_params, Some(requested_params));
+        assert!(state.auth == Some(auth));
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_config_api() {
         const PEER_NUM: u16 = 4;
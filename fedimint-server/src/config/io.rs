@@ -3,10 +3,17 @@ use std::fs;
 use std::io::Write;
 use std::path::Path;
 
-use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key, LessSafeKey};
+use anyhow::Context;
+use bitcoin_hashes::sha256;
+use fedimint_aead::{
+    decrypt, encrypt, encrypted_read, encrypted_write, get_encryption_key, random_salt, LessSafeKey,
+};
+use fedimint_api_client::api::GuardianDisasterRecoveryBundle;
 use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::util::write_new;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::config::ServerConfig;
 
@@ -94,6 +101,48 @@ pub fn write_server_config(
     encrypted_json_write(&server.private, &key, &path.join(PRIVATE_CONFIG))
 }
 
+/// Replaces the configuration files of a running guardian, e.g. after a module
+/// instance was added. The new files are written into the
+/// [`CONFIG_STAGING_DIR`] first and then moved into place, so an interrupted
+/// write leaves the previous config intact.
+pub fn update_server_config(
+    server: &ServerConfig,
+    path: &Path,
+    password: &str,
+    module_config_gens: &ServerModuleInitRegistry,
+    api_secret: Option<String>,
+) -> anyhow::Result<()> {
+    let staging_path = path.join(CONFIG_STAGING_DIR);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path)?;
+    }
+    fs::create_dir_all(&staging_path)?;
+    fs::copy(path.join(SALT_FILE), staging_path.join(SALT_FILE))?;
+
+    write_server_config(
+        server,
+        &staging_path,
+        password,
+        module_config_gens,
+        api_secret,
+    )?;
+
+    for file in [
+        Path::new(LOCAL_CONFIG).with_extension(JSON_EXT),
+        Path::new(CONSENSUS_CONFIG).with_extension(JSON_EXT),
+        Path::new(CLIENT_CONFIG).with_extension(JSON_EXT),
+        Path::new(CLIENT_INVITE_CODE_FILE).to_path_buf(),
+        Path::new(PRIVATE_CONFIG).with_extension(ENCRYPTED_EXT),
+    ] {
+        fs::rename(staging_path.join(&file), path.join(&file))
+            .with_context(|| format!("Failed to move {} into place", file.display()))?;
+    }
+
+    fs::remove_dir_all(staging_path)?;
+
+    Ok(())
+}
+
 /// Writes struct into a plaintext json file
 fn plaintext_json_write<T: Serialize + DeserializeOwned>(
     obj: &T,
@@ -126,3 +175,223 @@ fn encrypted_json_write<T: Serialize + DeserializeOwned>(
     let bytes = serde_json::to_string(obj)?.into_bytes();
     encrypted_write(bytes, key, path.with_extension(ENCRYPTED_EXT))
 }
+
+/// Reference to the last session a guardian had finished when a
+/// [`DisasterRecoveryBundle`] was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub struct SessionCheckpoint {
+    pub session_index: u64,
+    /// Hash of the header of the session, which is what the guardians sign
+    pub header_hash: sha256::Hash,
+}
+
+/// Contents of a [`GuardianDisasterRecoveryBundle`] once decrypted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisasterRecoveryBundle {
+    pub config: ServerConfig,
+    /// `None` if no session had been finished yet
+    pub checkpoint: Option<SessionCheckpoint>,
+}
+
+impl DisasterRecoveryBundle {
+    /// Encrypts the bundle with a key derived from the guardian password and a
+    /// fresh salt
+    pub fn encrypt(&self, password: &str) -> anyhow::Result<GuardianDisasterRecoveryBundle> {
+        let salt = random_salt();
+        let key = get_encryption_key(password, &salt)?;
+        let encrypted_bundle = encrypt(serde_json::to_vec(self)?, &key)?;
+
+        Ok(GuardianDisasterRecoveryBundle {
+            salt,
+            encrypted_bundle,
+        })
+    }
+
+    /// Decrypts a bundle, failing if the password is wrong
+    pub fn decrypt(
+        bundle: &GuardianDisasterRecoveryBundle,
+        password: &str,
+    ) -> anyhow::Result<Self> {
+        let key = get_encryption_key(password, &bundle.salt)?;
+        let mut encrypted_bundle = bundle.encrypted_bundle.clone();
+        let decrypted = decrypt(&mut encrypted_bundle, &key)
+            .context("Failed to decrypt bundle, is the password correct?")?;
+        Ok(serde_json::from_slice(decrypted)?)
+    }
+}
+
+/// Recreates the config files of a guardian in an empty data dir from a
+/// [`GuardianDisasterRecoveryBundle`]. The guardian catches up on the sessions
+/// it is missing from its peers once started, the returned
+/// [`DisasterRecoveryBundle::checkpoint`] has to be recorded with
+/// [`crate::consensus::restore::record_restored_checkpoint`] to verify the
+/// bundle against them.
+pub fn restore_disaster_recovery_bundle(
+    bundle: &GuardianDisasterRecoveryBundle,
+    password: &str,
+    path: &Path,
+    module_config_gens: &ServerModuleInitRegistry,
+    api_secret: Option<String>,
+) -> anyhow::Result<DisasterRecoveryBundle> {
+    let bundle = DisasterRecoveryBundle::decrypt(bundle, password)?;
+
+    anyhow::ensure!(
+        !path
+            .join(CONSENSUS_CONFIG)
+            .with_extension(JSON_EXT)
+            .exists(),
+        "Refusing to restore into {}, it already contains a config",
+        path.display()
+    );
+
+    fs::create_dir_all(path)?;
+    write_new(path.join(SALT_FILE), random_salt())?;
+    write_server_config(
+        &bundle.config,
+        path,
+        password,
+        module_config_gens,
+        api_secret,
+    )?;
+
+    Ok(bundle)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::fs;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::admin_client::{ConfigGenParamsConsensus, PeerServerParams};
+    use fedimint_core::config::ServerModuleInitRegistry;
+    use fedimint_core::encoding::Encodable;
+    use fedimint_core::module::ApiAuth;
+    use fedimint_core::PeerId;
+
+    use super::{
+        read_server_config, restore_disaster_recovery_bundle, DisasterRecoveryBundle,
+        SessionCheckpoint, CONSENSUS_CONFIG, JSON_EXT,
+    };
+    use crate::config::api::ConfigGenParamsLocal;
+    use crate::config::{gen_cert_and_key, ConfigGenParams, ServerConfig};
+
+    const PASSWORD: &str = "pass";
+
+    /// Config of a single guardian federation without modules
+    pub(crate) fn server_config() -> ServerConfig {
+        let peer = PeerId::from(0);
+        let (cert, private_key) = gen_cert_and_key("peer-0").unwrap();
+
+        let params = ConfigGenParams {
+            local: ConfigGenParamsLocal {
+                our_id: peer,
+                our_private_key: private_key,
+                api_auth: ApiAuth(PASSWORD.to_string()),
+                p2p_bind: "127.0.0.1:10000".parse().unwrap(),
+                api_bind: "127.0.0.1:10001".parse().unwrap(),
+                max_connections: 10,
+                socks5_proxy: None,
+            },
+            consensus: ConfigGenParamsConsensus {
+                peers: BTreeMap::from([(
+                    peer,
+                    PeerServerParams {
+                        cert,
+                        p2p_url: "fedimint://127.0.0.1:10000".parse().unwrap(),
+                        api_url: "ws://127.0.0.1:10001".parse().unwrap(),
+                        name: "peer-0".to_string(),
+                        status: None,
+                    },
+                )]),
+                meta: BTreeMap::new(),
+                modules: Default::default(),
+            },
+        };
+
+        ServerConfig::trusted_dealer_gen(
+            &HashMap::from([(peer, params)]),
+            &ServerModuleInitRegistry::default(),
+            "test",
+        )
+        .remove(&peer)
+        .unwrap()
+    }
+
+    fn bundle() -> DisasterRecoveryBundle {
+        DisasterRecoveryBundle {
+            config: server_config(),
+            checkpoint: Some(SessionCheckpoint {
+                session_index: 7,
+                header_hash: sha256::Hash::hash(b"header"),
+            }),
+        }
+    }
+
+    #[test]
+    fn bundle_round_trips() {
+        let bundle = bundle();
+
+        let decrypted =
+            DisasterRecoveryBundle::decrypt(&bundle.encrypt(PASSWORD).unwrap(), PASSWORD).unwrap();
+
+        assert_eq!(decrypted.checkpoint, bundle.checkpoint);
+        assert_eq!(
+            decrypted.config.consensus.consensus_hash::<sha256::Hash>(),
+            bundle.config.consensus.consensus_hash::<sha256::Hash>()
+        );
+        assert_eq!(
+            serde_json::to_value(&decrypted.config.private).unwrap(),
+            serde_json::to_value(&bundle.config.private).unwrap()
+        );
+    }
+
+    #[test]
+    fn bundle_with_wrong_password_is_rejected() {
+        let encrypted = bundle().encrypt(PASSWORD).unwrap();
+
+        assert!(DisasterRecoveryBundle::decrypt(&encrypted, "wrong").is_err());
+    }
+
+    #[test]
+    fn bundle_is_restored_into_empty_data_dir_only() {
+        let bundle = bundle();
+        let encrypted = bundle.encrypt(PASSWORD).unwrap();
+        let data_dir = tempfile::tempdir().unwrap();
+        let registry = ServerModuleInitRegistry::default();
+
+        let restored = restore_disaster_recovery_bundle(
+            &encrypted,
+            PASSWORD,
+            data_dir.path(),
+            &registry,
+            None,
+        )
+        .unwrap();
+        assert_eq!(restored.checkpoint, bundle.checkpoint);
+
+        let config = read_server_config(PASSWORD, data_dir.path()).unwrap();
+        assert_eq!(
+            config.consensus.consensus_hash::<sha256::Hash>(),
+            bundle.config.consensus.consensus_hash::<sha256::Hash>()
+        );
+
+        let consensus_path = data_dir
+            .path()
+            .join(CONSENSUS_CONFIG)
+            .with_extension(JSON_EXT);
+        let written = fs::read(&consensus_path).unwrap();
+
+        assert!(restore_disaster_recovery_bundle(
+            &encrypted,
+            PASSWORD,
+            data_dir.path(),
+            &registry,
+            None,
+        )
+        .is_err());
+
+        // the existing config is left untouched
+        assert_eq!(fs::read(&consensus_path).unwrap(), written);
+    }
+}
@@ -6,9 +6,10 @@ use std::time::Duration;
 use anyhow::{bail, format_err};
 use fedimint_core::admin_client::ConfigGenParamsConsensus;
 pub use fedimint_core::config::{
-    serde_binary_human_readable, ClientConfig, DkgError, DkgPeerMsg, DkgResult, FederationId,
-    GlobalClientConfig, JsonWithKind, ModuleInitRegistry, PeerUrl, ServerModuleConfig,
-    ServerModuleConsensusConfig, ServerModuleInitRegistry, TypedServerModuleConfig,
+    serde_binary_human_readable, ClientConfig, ConfigGenModuleParams, DkgError, DkgPeerMsg,
+    DkgResult, FederationId, GlobalClientConfig, JsonWithKind, ModuleInitRegistry, PeerUrl,
+    ServerModuleConfig, ServerModuleConsensusConfig, ServerModuleInitRegistry,
+    TypedServerModuleConfig,
 };
 use fedimint_core::core::{ModuleInstanceId, ModuleKind, MODULE_INSTANCE_ID_GLOBAL};
 use fedimint_core::envs::is_running_in_test_env;
@@ -17,7 +18,9 @@ use fedimint_core::module::{
     ApiAuth, ApiVersion, CoreConsensusVersion, DynServerModuleInit, MultiApiVersion, PeerHandle,
     SupportedApiVersionsSummary, SupportedCoreApiVersions, CORE_CONSENSUS_VERSION,
 };
-use fedimint_core::net::peers::{IMuxPeerConnections, IPeerConnections, PeerConnections};
+use fedimint_core::net::peers::{
+    IMuxPeerConnections, IPeerConnections, MuxPeerConnections, PeerConnections,
+};
 use fedimint_core::task::{timeout, Cancelled, Elapsed, TaskGroup};
 use fedimint_core::{secp256k1, timing, PeerId};
 use fedimint_logging::{LOG_NET_PEER, LOG_NET_PEER_DKG};
@@ -210,7 +213,7 @@ impl ServerConfig {
     pub fn supported_api_versions() -> SupportedCoreApiVersions {
         SupportedCoreApiVersions {
             core_consensus: CORE_CONSENSUS_VERSION,
-            api: MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 2 }])
+            api: MultiApiVersion::try_from_iter([ApiVersion { major: 0, minor: 5 }])
                 .expect("not version conflicts"),
         }
     }
@@ -519,48 +522,7 @@ impl ServerConfig {
             return Err(DkgError::ParamsNotFound(registered_modules));
         }
 
-        info!(
-            target: LOG_NET_PEER_DKG,
-            "Sending confirmations to other peers."
-        );
-        // Note: Since our outgoing buffers are asynchronous, we don't actually know
-        // if other peers received our message, just because we received theirs.
-        // That's why we need to do a one last best effort sync.
-        let dkg_done = "DKG DONE".to_string();
-        connections
-            .send(
-                peers,
-                (MODULE_INSTANCE_ID_GLOBAL, dkg_done.clone()),
-                DkgPeerMsg::Done,
-            )
-            .await?;
-
-        info!(
-            target: LOG_NET_PEER_DKG,
-            "Waiting for confirmations from other peers."
-        );
-        if let Err(Elapsed) = timeout(Duration::from_secs(30), async {
-            let mut done_peers = BTreeSet::from([*our_id]);
-
-            while done_peers.len() < peers.len() {
-                match connections.receive((MODULE_INSTANCE_ID_GLOBAL, dkg_done.clone())).await {
-                    Ok((peer_id, DkgPeerMsg::Done)) => {
-                        info!(
-                            target: LOG_NET_PEER_DKG,
-                            pper_id = %peer_id, "Got completion confirmation");
-                        done_peers.insert(peer_id);
-                    },
-                    Ok((peer_id, msg)) => {
-                        error!(target: LOG_NET_PEER_DKG, %peer_id, ?msg, "Received incorrect message after dkg was supposed to be finished. Probably dkg multiplexing bug.");
-                    },
-                    Err(Cancelled) => {/* ignore shutdown for time being, we'll timeout soon anyway */},
-                }
-            }
-        })
-        .await
-        {
-            error!(target: LOG_NET_PEER_DKG, "Timeout waiting for dkg completion confirmation from other peers");
-        };
+        await_dkg_done(&connections, *our_id, peers).await?;
 
         let server = ServerConfig::from(
             params.clone(),
@@ -578,6 +540,105 @@ impl ServerConfig {
 
         Ok(server)
     }
+
+    /// Runs the distributed key generation of a single module with the other
+    /// guardians of the running federation, e.g. to add a module instance
+    pub async fn distributed_gen_module(
+        &self,
+        module_init: &DynServerModuleInit,
+        module_instance_id: ModuleInstanceId,
+        params: &ConfigGenModuleParams,
+        socks5_proxy: Option<SocketAddr>,
+        task_group: &mut TaskGroup,
+    ) -> DkgResult<ServerModuleConfig> {
+        let our_id = self.local.identity;
+        let peers = self
+            .consensus
+            .broadcast_public_keys
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        // in case we are running by ourselves, avoid DKG
+        if peers.len() == 1 {
+            return Ok(module_init
+                .trusted_dealer_gen(&peers, params)
+                .remove(&our_id)
+                .expect("Config of every peer is generated"));
+        }
+
+        let server_conn = connect(
+            self.network_config(socks5_proxy),
+            self.tls_config(),
+            DelayCalculator::PROD_DEFAULT,
+            task_group,
+        )
+        .await;
+        let connections = PeerConnectionMultiplexer::new(server_conn).into_dyn();
+
+        info!(
+            target: LOG_NET_PEER_DKG,
+            %module_instance_id, "Peer {} running distributed key generation of module...", our_id
+        );
+
+        let dkg = PeerHandle::new(&connections, module_instance_id, our_id, peers.clone());
+        let module_cfg = module_init.distributed_gen(&dkg, params).await?;
+
+        await_dkg_done(&connections, our_id, &peers).await?;
+
+        Ok(module_cfg)
+    }
+}
+
+/// Note: Since our outgoing buffers are asynchronous, we don't actually know
+/// if other peers received our message, just because we received theirs.
+/// That's why we need to do a one last best effort sync.
+async fn await_dkg_done(
+    connections: &MuxPeerConnections<(ModuleInstanceId, String), DkgPeerMsg>,
+    our_id: PeerId,
+    peers: &[PeerId],
+) -> DkgResult<()> {
+    info!(
+        target: LOG_NET_PEER_DKG,
+        "Sending confirmations to other peers."
+    );
+    let dkg_done = "DKG DONE".to_string();
+    connections
+        .send(
+            peers,
+            (MODULE_INSTANCE_ID_GLOBAL, dkg_done.clone()),
+            DkgPeerMsg::Done,
+        )
+        .await?;
+
+    info!(
+        target: LOG_NET_PEER_DKG,
+        "Waiting for confirmations from other peers."
+    );
+    if let Err(Elapsed) = timeout(Duration::from_secs(30), async {
+        let mut done_peers = BTreeSet::from([our_id]);
+
+        while done_peers.len() < peers.len() {
+            match connections.receive((MODULE_INSTANCE_ID_GLOBAL, dkg_done.clone())).await {
+                Ok((peer_id, DkgPeerMsg::Done)) => {
+                    info!(
+                        target: LOG_NET_PEER_DKG,
+                        pper_id = %peer_id, "Got completion confirmation");
+                    done_peers.insert(peer_id);
+                },
+                Ok((peer_id, msg)) => {
+                    error!(target: LOG_NET_PEER_DKG, %peer_id, ?msg, "Received incorrect message after dkg was supposed to be finished. Probably dkg multiplexing bug.");
+                },
+                Err(Cancelled) => {/* ignore shutdown for time being, we'll timeout soon anyway */},
+            }
+        }
+    })
+    .await
+    {
+        error!(target: LOG_NET_PEER_DKG, "Timeout waiting for dkg completion confirmation from other peers");
+    };
+
+    Ok(())
 }
 
 /// The types of keys to run distributed key generation for
@@ -589,7 +650,9 @@ pub enum KeyType {
 }
 
 impl ServerConfig {
-    pub fn network_config(&self) -> NetworkConfig {
+    /// Network config connecting to the other guardians through the SOCKS5
+    /// proxy `socks5_proxy` if set
+    pub fn network_config(&self, socks5_proxy: Option<SocketAddr>) -> NetworkConfig {
         NetworkConfig {
             identity: self.local.identity,
             bind_addr: self.local.fed_bind,
@@ -599,6 +662,7 @@ impl ServerConfig {
                 .iter()
                 .map(|(&id, endpoint)| (id, endpoint.url.clone()))
                 .collect(),
+            socks5_proxy,
         }
     }
 
@@ -634,6 +698,7 @@ impl ConfigGenParams {
                 .into_iter()
                 .map(|(id, peer)| (id, peer.url))
                 .collect(),
+            socks5_proxy: self.local.socks5_proxy,
         }
     }
 
@@ -707,7 +772,9 @@ pub async fn connect<T>(
 where
     T: std::fmt::Debug + Clone + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
-    let connector = TlsTcpConnector::new(certs, network.identity).into_dyn();
+    let connector = TlsTcpConnector::new(certs, network.identity)
+        .with_socks5_proxy(network.socks5_proxy)
+        .into_dyn();
     let (connections, _) =
         ReconnectPeerConnectionsReliable::new(network, delay_calculator, connector, task_group)
             .await;
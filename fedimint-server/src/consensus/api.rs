@@ -1,19 +1,26 @@
 //! Implements the client API through which users interact with the federation
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
-use bitcoin_hashes::sha256;
+use bitcoin_hashes::{sha256, Hash as _};
 use fedimint_aead::{encrypt, get_encryption_key, random_salt};
 use fedimint_api_client::api::{
-    FederationStatus, GuardianConfigBackup, PeerConnectionStatus, PeerStatus, StatusResponse,
+    ConsensusHealth, ConsensusHealthAlert, FederationStatus, GuardianConfigBackup,
+    GuardianConsensusCheckpoint, GuardianDisasterRecoveryBundle, ModuleHealthStatus,
+    PeerConnectionStats, PeerConnectionStatus, PeerStatus, StatusResponse,
+};
+use fedimint_core::admin_client::{
+    AdminRole, ApiTokenInfo, IssueApiTokenRequest, ModuleAdditionRequest, ModuleAdditionsStatus,
+    ModuleMaintenanceRequest, ScheduleUpgradeRequest, ServerStatus, SessionRetention,
+    UpgradeStatus,
 };
-use fedimint_core::admin_client::ServerStatus;
 use fedimint_core::backup::{ClientBackupKey, ClientBackupSnapshot};
-use fedimint_core::config::{ClientConfig, JsonClientConfig};
+use fedimint_core::config::{ClientConfig, JsonClientConfig, ServerModuleInitRegistry};
 use fedimint_core::core::backup::{SignedBackupRequest, BACKUP_REQUEST_MAX_PAYLOAD_SIZE_BYTES};
 use fedimint_core::core::{DynOutputOutcome, ModuleInstanceId};
 use fedimint_core::db::{
@@ -22,23 +29,34 @@ use fedimint_core::db::{
 use fedimint_core::endpoint_constants::{
     AUDIT_ENDPOINT, AUTH_ENDPOINT, AWAIT_OUTPUT_OUTCOME_ENDPOINT, AWAIT_SESSION_OUTCOME_ENDPOINT,
     AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT, AWAIT_TRANSACTION_ENDPOINT, BACKUP_ENDPOINT,
-    CLIENT_CONFIG_ENDPOINT, CLIENT_CONFIG_JSON_ENDPOINT, FEDERATION_ID_ENDPOINT,
-    GUARDIAN_CONFIG_BACKUP_ENDPOINT, INVITE_CODE_ENDPOINT, RECOVER_ENDPOINT,
-    SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT, SESSION_COUNT_ENDPOINT, SESSION_STATUS_ENDPOINT,
-    SHUTDOWN_ENDPOINT, STATUS_ENDPOINT, SUBMIT_TRANSACTION_ENDPOINT, VERSION_ENDPOINT,
+    CLIENT_CONFIG_ENDPOINT, CLIENT_CONFIG_JSON_ENDPOINT, CONSENSUS_CHECKPOINT_ENDPOINT,
+    CONSENSUS_HEALTH_ENDPOINT, FEDERATION_ID_ENDPOINT, FEE_SCHEDULE_ENDPOINT,
+    GUARDIAN_CONFIG_BACKUP_ENDPOINT, GUARDIAN_DISASTER_RECOVERY_BUNDLE_ENDPOINT,
+    INVITE_CODE_ENDPOINT, ISSUE_API_TOKEN_ENDPOINT, LIST_API_TOKENS_ENDPOINT,
+    MODULES_IN_MAINTENANCE_ENDPOINT, MODULE_ADDITIONS_ENDPOINT, MODULE_CONSENSUS_FEATURES_ENDPOINT,
+    PEER_CONNECTION_STATS_ENDPOINT, PROPOSE_MODULE_ADDITION_ENDPOINT, RECOVER_ENDPOINT,
+    REVOKE_API_TOKEN_ENDPOINT, SCHEDULE_UPGRADE_ENDPOINT, SERVER_CONFIG_CONSENSUS_HASH_ENDPOINT,
+    SESSION_COUNT_ENDPOINT, SESSION_RETENTION_ENDPOINT, SESSION_STATUS_ENDPOINT,
+    SESSION_TRANSACTIONS_ENDPOINT, SET_MODULE_MAINTENANCE_ENDPOINT, SET_SESSION_RETENTION_ENDPOINT,
+    SHUTDOWN_ENDPOINT, STATUS_ENDPOINT, SUBMIT_TRANSACTIONS_BATCH_ENDPOINT,
+    SUBMIT_TRANSACTION_ENDPOINT, TRANSACTION_ERROR_ENDPOINT, TRANSPORT_OPTIONS_ENDPOINT,
+    UPGRADE_STATUS_ENDPOINT, VERSION_ENDPOINT,
 };
-use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::epoch::{ConsensusItem, ModuleAdditionProposal, UpgradeProposal};
 use fedimint_core::module::audit::{Audit, AuditSummary};
 use fedimint_core::module::registry::ServerModuleRegistry;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, ApiVersion,
-    SerdeModuleEncoding, SupportedApiVersionsSummary,
+    api_endpoint, ApiAuth, ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased, ApiVersion,
+    FeeSchedule, ModuleConsensusFeature, ModuleHealth, SerdeModuleEncoding,
+    SupportedApiVersionsSummary,
 };
+use fedimint_core::net::api_transport::ApiTransportOptions;
 use fedimint_core::secp256k1::{PublicKey, SECP256K1};
 use fedimint_core::server::DynServerModule;
 use fedimint_core::session_outcome::{SessionOutcome, SessionStatus, SignedSessionOutcome};
 use fedimint_core::transaction::{
-    SerdeTransaction, Transaction, TransactionError, TransactionSubmissionOutcome,
+    SerdeTransaction, Transaction, TransactionError, TransactionRejection,
+    TransactionSubmissionOutcome, MAX_TRANSACTIONS_PER_BATCH,
 };
 use fedimint_core::{OutPoint, PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
@@ -47,15 +65,46 @@ use tokio::sync::{watch, RwLock};
 use tracing::{debug, info};
 
 use crate::config::io::{
-    CONSENSUS_CONFIG, ENCRYPTED_EXT, JSON_EXT, LOCAL_CONFIG, PRIVATE_CONFIG, SALT_FILE,
+    DisasterRecoveryBundle, SessionCheckpoint, CONSENSUS_CONFIG, ENCRYPTED_EXT, JSON_EXT,
+    LOCAL_CONFIG, PRIVATE_CONFIG, SALT_FILE,
 };
 use crate::config::ServerConfig;
-use crate::consensus::db::{AcceptedItemPrefix, AcceptedTransactionKey, SignedSessionOutcomeKey};
+use crate::consensus::api_tokens::{
+    api_token_role, issue_api_token, list_api_tokens, revoke_api_token,
+};
+use crate::consensus::checkpoint::export_checkpoint;
+use crate::consensus::db::{
+    AcceptedItemPrefix, AcceptedTransactionKey, ModuleAdditionPrefix, ModuleAdditionVotePrefix,
+    PendingModuleAddition, PendingModuleAdditionKey, PendingUpgradeKey, RejectedTransactionKey,
+    ScheduledUpgradeKey, SignedSessionOutcomeKey, UpgradeVotePrefix,
+};
 use crate::consensus::engine::get_finished_session_count_static;
-use crate::consensus::transaction::process_transaction_with_dbtx;
+use crate::consensus::features::all_active_module_features;
+use crate::consensus::maintenance::{
+    module_in_maintenance, modules_in_maintenance, set_module_maintenance,
+};
+use crate::consensus::module_addition::MIN_ACTIVATION_DELAY_SESSIONS;
+use crate::consensus::retention::{
+    first_retained_session, get_session_outcome, get_session_retention, get_session_transactions,
+    set_session_retention,
+};
+use crate::consensus::transaction::{active_fee_schedule, process_transaction_with_dbtx};
+use crate::consensus::upgrade::MIN_HALT_DELAY_SESSIONS;
 use crate::fedimint_core::encoding::Encodable;
 use crate::metrics::{BACKUP_WRITE_SIZE_BYTES, STORED_BACKUPS_COUNT};
-use crate::net::api::{check_auth, ApiResult, HasApiContext};
+use crate::net::api::{check_auth, check_role, ApiResult, HasApiContext};
+
+/// A peer that has not contributed a consensus item for this long triggers a
+/// health alert
+const HEALTH_STALLED_PEER_THRESHOLD: Duration = Duration::from_secs(10 * 60);
+
+/// Pending submissions above this count trigger a health alert, half of the
+/// submission channel's capacity
+const HEALTH_SUBMISSION_BACKLOG_THRESHOLD: u64 = 500;
+
+/// A module health check that takes longer than this reports the module as
+/// unhealthy, so the status endpoint stays responsive
+const MODULE_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct ConsensusApi {
@@ -73,8 +122,14 @@ pub struct ConsensusApi {
     pub submission_sender: async_channel::Sender<ConsensusItem>,
     pub shutdown_sender: watch::Sender<Option<u64>>,
     pub connection_status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+    pub connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
     pub last_ci_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
+    pub last_ci_time_by_peer: Arc<RwLock<BTreeMap<PeerId, SystemTime>>>,
     pub supported_api_versions: SupportedApiVersionsSummary,
+    /// Module kinds supported by this guardian, for validating module additions
+    pub module_init_registry: ServerModuleInitRegistry,
+    /// The code version of this `fedimintd`
+    pub code_version: String,
 }
 
 impl ConsensusApi {
@@ -113,7 +168,9 @@ impl ConsensusApi {
         // We ignore any writes, as we only verify if the transaction is valid here
         dbtx.ignore_uncommitted();
 
-        process_transaction_with_dbtx(self.modules.clone(), &mut dbtx, transaction.clone()).await?;
+        process_transaction_with_dbtx(self.modules.clone(), &mut dbtx, transaction.clone())
+            .await
+            .map_err(|e| e.error)?;
 
         self.submission_sender
             .send(ConsensusItem::Transaction(transaction))
@@ -123,6 +180,212 @@ impl ConsensusApi {
         Ok(txid)
     }
 
+    /// Returns the first module instance used by the transaction that this
+    /// guardian put into maintenance mode. This only filters transactions
+    /// submitted to this guardian, see [`crate::consensus::maintenance`].
+    pub async fn module_in_maintenance(
+        &self,
+        transaction: &Transaction,
+    ) -> Option<ModuleInstanceId> {
+        module_in_maintenance(transaction, &self.modules_in_maintenance().await)
+    }
+
+    /// Current fee schedules of all modules, see [`FeeSchedule`]
+    pub async fn fee_schedules(&self) -> BTreeMap<ModuleInstanceId, FeeSchedule> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+        let mut fee_schedules = BTreeMap::new();
+
+        for (module_instance_id, _, module) in self.modules.iter_modules() {
+            let fee_schedule = active_fee_schedule(&mut dbtx, module_instance_id, module).await;
+            fee_schedules.insert(module_instance_id, fee_schedule);
+        }
+
+        fee_schedules
+    }
+
+    /// Health of all modules that check their health, see
+    /// [`fedimint_core::module::ServerModule::health_check`]
+    pub async fn module_health(&self) -> BTreeMap<ModuleInstanceId, ModuleHealthStatus> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+        let mut module_health = BTreeMap::new();
+
+        for (module_instance_id, kind, module) in self.modules.iter_modules() {
+            let health = fedimint_core::runtime::timeout(
+                MODULE_HEALTH_CHECK_TIMEOUT,
+                module.health_check(&mut dbtx.to_ref_with_prefix_module_id(module_instance_id)),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                Some(ModuleHealth::Unhealthy(
+                    "Health check timed out".to_string(),
+                ))
+            });
+
+            if let Some(health) = health {
+                module_health.insert(
+                    module_instance_id,
+                    ModuleHealthStatus {
+                        kind: kind.clone(),
+                        health,
+                    },
+                );
+            }
+        }
+
+        module_health
+    }
+
+    pub async fn modules_in_maintenance(&self) -> BTreeSet<ModuleInstanceId> {
+        modules_in_maintenance(&mut self.db.begin_transaction_nc().await).await
+    }
+
+    /// Maintenance mode only affects transactions submitted to this guardian,
+    /// see [`crate::consensus::maintenance`]
+    pub async fn set_module_maintenance(&self, request: ModuleMaintenanceRequest) -> ApiResult<()> {
+        let module_instance_id = request.module_instance_id;
+        if self.modules.get(module_instance_id).is_none() {
+            return Err(ApiError::bad_request(format!(
+                "Module instance {module_instance_id} does not exist"
+            )));
+        }
+
+        let mut dbtx = self.db.begin_transaction().await;
+        set_module_maintenance(&mut dbtx.to_ref_nc(), module_instance_id, request.enabled).await;
+        if request.enabled {
+            info!(target: LOG_NET_API, %module_instance_id, "Module entered maintenance mode");
+        } else {
+            info!(target: LOG_NET_API, %module_instance_id, "Module left maintenance mode");
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
+    /// Stores our vote for adding a module instance together with its local
+    /// config gen params and submits it to consensus. Every guardian has to
+    /// propose the same module addition for it to be approved.
+    pub async fn propose_module_addition(&self, request: ModuleAdditionRequest) -> ApiResult<()> {
+        let module_instance_id = request.module_instance_id;
+
+        if self.module_init_registry.get(&request.kind).is_none() {
+            return Err(ApiError::bad_request(format!(
+                "Module kind {} is not supported by this guardian",
+                request.kind
+            )));
+        }
+
+        if self.cfg.consensus.modules.contains_key(&module_instance_id) {
+            return Err(ApiError::bad_request(format!(
+                "Module instance {module_instance_id} already exists"
+            )));
+        }
+
+        let session_count = self.session_count().await;
+        if request.activation_session < session_count + MIN_ACTIVATION_DELAY_SESSIONS {
+            return Err(ApiError::bad_request(format!(
+                "Activation session has to be at least {}",
+                session_count + MIN_ACTIVATION_DELAY_SESSIONS
+            )));
+        }
+
+        // The JSON is serialized with sorted keys, so the proposals of all guardians
+        // are equal if their consensus params are
+        let proposal = ModuleAdditionProposal {
+            module_instance_id,
+            kind: request.kind,
+            consensus_params: request.params.consensus.to_string(),
+            activation_session: request.activation_session,
+        };
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PendingModuleAdditionKey(module_instance_id),
+            &PendingModuleAddition {
+                proposal: proposal.clone(),
+                local_params: request.params.local.to_string(),
+            },
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))?;
+
+        info!(target: LOG_NET_API, %module_instance_id, kind = %proposal.kind, "Proposed module addition");
+
+        self.submission_sender
+            .send(ConsensusItem::ModuleAddition(proposal))
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
+    pub async fn module_additions(&self) -> ModuleAdditionsStatus {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        ModuleAdditionsStatus {
+            votes: dbtx
+                .find_by_prefix(&ModuleAdditionVotePrefix)
+                .await
+                .map(|(key, proposal)| (key.0, proposal))
+                .collect()
+                .await,
+            approved: dbtx
+                .find_by_prefix(&ModuleAdditionPrefix)
+                .await
+                .map(|(_, proposal)| proposal)
+                .collect()
+                .await,
+        }
+    }
+
+    /// Stores our vote for a coordinated upgrade and submits it to consensus.
+    /// Every guardian has to schedule the same upgrade for it to be approved.
+    pub async fn schedule_upgrade(&self, request: ScheduleUpgradeRequest) -> ApiResult<()> {
+        if request.version.is_empty() {
+            return Err(ApiError::bad_request("Version is empty".to_string()));
+        }
+
+        let session_count = self.session_count().await;
+        if request.halt_session < session_count + MIN_HALT_DELAY_SESSIONS {
+            return Err(ApiError::bad_request(format!(
+                "Halt session has to be at least {}",
+                session_count + MIN_HALT_DELAY_SESSIONS
+            )));
+        }
+
+        let proposal = UpgradeProposal {
+            version: request.version,
+            halt_session: request.halt_session,
+        };
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&PendingUpgradeKey, &proposal).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))?;
+
+        info!(target: LOG_NET_API, version = %proposal.version, halt_session = proposal.halt_session, "Scheduled upgrade");
+
+        self.submission_sender
+            .send(ConsensusItem::Upgrade(proposal))
+            .await
+            .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
+    pub async fn upgrade_status(&self) -> UpgradeStatus {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        UpgradeStatus {
+            code_version: self.code_version.clone(),
+            votes: dbtx
+                .find_by_prefix(&UpgradeVotePrefix)
+                .await
+                .map(|(key, proposal)| (key.0, proposal))
+                .collect()
+                .await,
+            scheduled: dbtx.get_value(&ScheduledUpgradeKey).await,
+        }
+    }
+
     pub async fn await_transaction(
         &self,
         txid: TransactionId,
@@ -132,6 +395,15 @@ impl ConsensusApi {
             .await
     }
 
+    /// Returns why consensus rejected the transaction, if it did
+    pub async fn transaction_error(&self, txid: TransactionId) -> Option<TransactionRejection> {
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&RejectedTransactionKey(txid))
+            .await
+    }
+
     pub async fn await_output_outcome(
         &self,
         outpoint: OutPoint,
@@ -152,7 +424,9 @@ impl ConsensusApi {
                 module_id,
             )
             .await
-            .expect("The transaction is accepted");
+            // Modules may defer producing an outcome for accepted outputs, e.g. when they
+            // are only processed at a later point in consensus
+            .ok_or(anyhow!("Outcome for {outpoint:?} is not available yet"))?;
 
         Ok((&outcome).into())
     }
@@ -161,14 +435,38 @@ impl ConsensusApi {
         get_finished_session_count_static(&mut self.db.begin_transaction_nc().await).await
     }
 
-    pub async fn await_signed_session_outcome(&self, index: u64) -> SignedSessionOutcome {
-        self.db
+    pub async fn await_signed_session_outcome(
+        &self,
+        index: u64,
+    ) -> ApiResult<SignedSessionOutcome> {
+        self.ensure_session_retained(index).await?;
+
+        Ok(self
+            .db
             .wait_key_check(&SignedSessionOutcomeKey(index), std::convert::identity)
             .await
-            .0
+            .0)
     }
 
+    /// The status of a completed session contains its full outcome, or only
+    /// its transactions if the session was pruned, see
+    /// [`crate::consensus::retention`]
     pub async fn session_status(&self, session_index: u64) -> SessionStatus {
+        self.session_status_with(session_index, false).await
+    }
+
+    /// Like [`Self::session_status`], but a completed session only contains its
+    /// transactions regardless of whether it was pruned, so all guardians agree
+    /// on it
+    pub async fn session_transactions(&self, session_index: u64) -> SessionStatus {
+        self.session_status_with(session_index, true).await
+    }
+
+    async fn session_status_with(
+        &self,
+        session_index: u64,
+        transactions_only: bool,
+    ) -> SessionStatus {
         let mut dbtx = self.db.begin_transaction_nc().await;
 
         match session_index.cmp(&get_finished_session_count_static(&mut dbtx).await) {
@@ -181,14 +479,31 @@ impl ConsensusApi {
                     .await,
             ),
             Ordering::Less => SessionStatus::Complete(
-                dbtx.get_value(&SignedSessionOutcomeKey(session_index))
-                    .await
-                    .expect("There are no gaps in session outcomes")
-                    .session_outcome,
+                if transactions_only {
+                    get_session_transactions(&mut dbtx, session_index).await
+                } else {
+                    get_session_outcome(&mut dbtx, session_index).await
+                }
+                .expect("There are no gaps in session outcomes"),
             ),
         }
     }
 
+    /// Fails if the signed outcome of the session was pruned, see
+    /// [`crate::consensus::retention`]
+    async fn ensure_session_retained(&self, session_index: u64) -> ApiResult<()> {
+        let first_retained =
+            first_retained_session(&mut self.db.begin_transaction_nc().await).await;
+
+        if session_index < first_retained {
+            return Err(ApiError::not_found(format!(
+                "Session {session_index} was pruned, the oldest retained session is {first_retained}"
+            )));
+        }
+
+        Ok(())
+    }
+
     pub async fn get_federation_status(&self) -> ApiResult<FederationStatus> {
         let peers_connection_status = self.connection_status_channels.read().await.clone();
         let last_ci_by_peer = self.last_ci_by_peer.read().await.clone();
@@ -225,15 +540,95 @@ impl ConsensusApi {
             .filter(|status| status.connection_status == PeerConnectionStatus::Disconnected)
             .count() as u64;
 
+        // We consider ourselves alive
+        let mut liveness_bitmap = vec![0u8; self.cfg.consensus.api_endpoints.len().div_ceil(8)];
+        let peers_alive = status_by_peer
+            .iter()
+            .filter(|(_, status)| status.connection_status == PeerConnectionStatus::Connected)
+            .map(|(peer, _)| *peer)
+            .chain(std::iter::once(self.cfg.local.identity));
+        for peer in peers_alive {
+            if let Some(byte) = liveness_bitmap.get_mut(peer.to_usize() / 8) {
+                *byte |= 1 << (peer.to_usize() % 8);
+            }
+        }
+
         Ok(FederationStatus {
             session_count,
             status_by_peer,
             peers_online,
             peers_offline,
             peers_flagged,
+            liveness_bitmap: hex::encode(liveness_bitmap),
         })
     }
 
+    pub async fn get_peer_connection_stats(&self) -> BTreeMap<PeerId, PeerConnectionStats> {
+        self.connection_stats.read().await.clone()
+    }
+
+    pub async fn get_consensus_health(&self) -> ApiResult<ConsensusHealth> {
+        let federation_status = self.get_federation_status().await?;
+        let last_ci_time_by_peer = self.last_ci_time_by_peer.read().await.clone();
+        let now = fedimint_core::time::now();
+
+        let mut health = ConsensusHealth {
+            session_count: federation_status.session_count,
+            pending_submissions: self.submission_sender.len() as u64,
+            ..ConsensusHealth::default()
+        };
+
+        for peer in self.cfg.consensus.api_endpoints.keys() {
+            let last_item_time = last_ci_time_by_peer.get(peer).copied();
+            health.last_item_timestamp_by_peer.insert(
+                *peer,
+                last_item_time.map(|time| {
+                    time.duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs()
+                }),
+            );
+
+            // We only notice peers stalling that have contributed since our restart
+            if let Some(last_item_time) = last_item_time {
+                let since_last_item = now.duration_since(last_item_time).unwrap_or_default();
+                if HEALTH_STALLED_PEER_THRESHOLD < since_last_item {
+                    health.alerts.push(ConsensusHealthAlert::PeerStalled {
+                        peer: *peer,
+                        seconds_since_last_item: since_last_item.as_secs(),
+                    });
+                }
+            }
+        }
+
+        for (peer, status) in federation_status.status_by_peer {
+            if status.connection_status == PeerConnectionStatus::Disconnected {
+                health.peers_offline.insert(peer);
+            }
+            if status.flagged {
+                health.peers_flagged.insert(peer);
+            }
+        }
+
+        if !health.peers_offline.is_empty() {
+            health.alerts.push(ConsensusHealthAlert::PeersOffline {
+                count: health.peers_offline.len() as u64,
+            });
+        }
+        if !health.peers_flagged.is_empty() {
+            health.alerts.push(ConsensusHealthAlert::PeersFlagged {
+                count: health.peers_flagged.len() as u64,
+            });
+        }
+        if HEALTH_SUBMISSION_BACKLOG_THRESHOLD < health.pending_submissions {
+            health.alerts.push(ConsensusHealthAlert::SubmissionBacklog {
+                pending: health.pending_submissions,
+            });
+        }
+
+        Ok(health)
+    }
+
     fn shutdown(&self, index: Option<u64>) {
         self.shutdown_sender.send_replace(index);
     }
@@ -317,6 +712,42 @@ impl ConsensusApi {
         GuardianConfigBackup { tar_archive_bytes }
     }
 
+    /// Bundles the in-memory config with a reference to the last finished
+    /// session and encrypts it with the guardian password
+    async fn get_disaster_recovery_bundle(
+        &self,
+        password: &str,
+    ) -> ApiResult<GuardianDisasterRecoveryBundle> {
+        let session_count = self.session_count().await;
+
+        let checkpoint = match session_count.checked_sub(1) {
+            Some(session_index) => {
+                let header = self
+                    .db
+                    .begin_transaction_nc()
+                    .await
+                    .get_value(&SignedSessionOutcomeKey(session_index))
+                    .await
+                    .expect("Finished sessions have a signed outcome")
+                    .session_outcome
+                    .header(session_index);
+
+                Some(SessionCheckpoint {
+                    session_index,
+                    header_hash: sha256::Hash::hash(&header),
+                })
+            }
+            None => None,
+        };
+
+        DisasterRecoveryBundle {
+            config: self.cfg.clone(),
+            checkpoint,
+        }
+        .encrypt(password)
+        .map_err(|e| ApiError::server_error(e.to_string()))
+    }
+
     async fn handle_backup_request<'s, 'dbtx, 'a>(
         &'s self,
         dbtx: &'dbtx mut DatabaseTransaction<'a>,
@@ -372,6 +803,14 @@ impl HasApiContext<ConsensusApi> for ConsensusApi {
         request: &ApiRequestErased,
         id: Option<ModuleInstanceId>,
     ) -> (&ConsensusApi, ApiEndpointContext<'_>) {
+        let has_auth = request.auth == Some(self.cfg.private.api_auth.clone());
+        let token_role = match &request.auth {
+            Some(token) if !has_auth => {
+                api_token_role(&mut self.db.begin_transaction_nc().await, token).await
+            }
+            _ => None,
+        };
+
         let mut db = self.db.clone();
         let mut dbtx = self.db.begin_transaction().await;
         if let Some(id) = id {
@@ -380,12 +819,8 @@ impl HasApiContext<ConsensusApi> for ConsensusApi {
         }
         (
             self,
-            ApiEndpointContext::new(
-                db,
-                dbtx,
-                request.auth == Some(self.cfg.private.api_auth.clone()),
-                request.auth.clone(),
-            ),
+            ApiEndpointContext::new(db, dbtx, has_auth, request.auth.clone())
+                .with_token_role(token_role),
         )
     }
 }
@@ -414,6 +849,13 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(fedimint.api_versions_summary().to_owned())
             }
         },
+        api_endpoint! {
+            TRANSPORT_OPTIONS_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |_fedimint: &ConsensusApi, _context, _v: ()| -> ApiTransportOptions {
+                Ok(ApiTransportOptions::server())
+            }
+        },
         api_endpoint! {
             SUBMIT_TRANSACTION_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -422,11 +864,47 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                     .try_into_inner(&fedimint.modules.decoder_registry())
                     .map_err(|e| ApiError::bad_request(e.to_string()))?;
 
+                if let Some(id) = fedimint.module_in_maintenance(&transaction).await {
+                    return Err(ApiError::module_in_maintenance(id));
+                }
+
                 // we return an inner error if and only if the submitted transaction is
                 // invalid and will be rejected if we were to submit it to consensus
                 Ok((&TransactionSubmissionOutcome(fedimint.submit_transaction(transaction).await)).into())
             }
         },
+        api_endpoint! {
+            SUBMIT_TRANSACTIONS_BATCH_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, transactions: Vec<SerdeTransaction>| -> Vec<SerdeModuleEncoding<TransactionSubmissionOutcome>> {
+                if transactions.len() > MAX_TRANSACTIONS_PER_BATCH {
+                    return Err(ApiError::bad_request(format!(
+                        "At most {MAX_TRANSACTIONS_PER_BATCH} transactions can be submitted in a batch"
+                    )));
+                }
+
+                // The whole batch is rejected if any transaction is malformed or touches a
+                // module in maintenance, so that either all or none of them are submitted
+                let transactions = transactions
+                    .into_iter()
+                    .map(|transaction| transaction.try_into_inner(&fedimint.modules.decoder_registry()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+                for transaction in &transactions {
+                    if let Some(id) = fedimint.module_in_maintenance(transaction).await {
+                        return Err(ApiError::module_in_maintenance(id));
+                    }
+                }
+
+                let mut outcomes = Vec::with_capacity(transactions.len());
+                for transaction in transactions {
+                    outcomes.push((&TransactionSubmissionOutcome(fedimint.submit_transaction(transaction).await)).into());
+                }
+
+                Ok(outcomes)
+            }
+        },
         api_endpoint! {
             AWAIT_TRANSACTION_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -440,6 +918,13 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(tx_hash)
             }
         },
+        api_endpoint! {
+            TRANSACTION_ERROR_ENDPOINT,
+            ApiVersion::new(0, 4),
+            async |fedimint: &ConsensusApi, _context, txid: TransactionId| -> Option<TransactionRejection> {
+                Ok(fedimint.transaction_error(txid).await)
+            }
+        },
         api_endpoint! {
             AWAIT_OUTPUT_OUTCOME_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -494,7 +979,8 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
             async |fedimint: &ConsensusApi, _context, _v: ()| -> StatusResponse {
                 Ok(StatusResponse {
                     server: ServerStatus::ConsensusRunning,
-                    federation: Some(fedimint.get_federation_status().await?)
+                    federation: Some(fedimint.get_federation_status().await?),
+                    modules: fedimint.module_health().await,
                 })
             }
         },
@@ -509,14 +995,14 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
             AWAIT_SESSION_OUTCOME_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SessionOutcome> {
-                Ok((&fedimint.await_signed_session_outcome(index).await.session_outcome).into())
+                Ok((&fedimint.await_signed_session_outcome(index).await?.session_outcome).into())
             }
         },
         api_endpoint! {
             AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SignedSessionOutcome> {
-                Ok((&fedimint.await_signed_session_outcome(index).await).into())
+                Ok((&fedimint.await_signed_session_outcome(index).await?).into())
             }
         },
         api_endpoint! {
@@ -526,23 +1012,122 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok((&fedimint.session_status(index).await).into())
             }
         },
+        api_endpoint! {
+            SESSION_TRANSACTIONS_ENDPOINT,
+            ApiVersion::new(0, 5),
+            async |fedimint: &ConsensusApi, _context, index: u64| -> SerdeModuleEncoding<SessionStatus> {
+                Ok((&fedimint.session_transactions(index).await).into())
+            }
+        },
         api_endpoint! {
             SHUTDOWN_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, context, index: Option<u64>| -> () {
-                check_auth(context)?;
+                check_role(context, AdminRole::Dangerous)?;
                 fedimint.shutdown(index);
                 Ok(())
             }
         },
+        api_endpoint! {
+            SET_MODULE_MAINTENANCE_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, request: ModuleMaintenanceRequest| -> () {
+                check_role(context, AdminRole::ConfigChange)?;
+                fedimint.set_module_maintenance(request).await
+            }
+        },
+        api_endpoint! {
+            MODULES_IN_MAINTENANCE_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> BTreeSet<ModuleInstanceId> {
+                Ok(fedimint.modules_in_maintenance().await)
+            }
+        },
+        api_endpoint! {
+            MODULE_CONSENSUS_FEATURES_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> BTreeMap<ModuleInstanceId, BTreeSet<ModuleConsensusFeature>> {
+                Ok(all_active_module_features(&fedimint.db).await)
+            }
+        },
+        api_endpoint! {
+            FEE_SCHEDULE_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> BTreeMap<ModuleInstanceId, FeeSchedule> {
+                Ok(fedimint.fee_schedules().await)
+            }
+        },
+        api_endpoint! {
+            SET_SESSION_RETENTION_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, retention: SessionRetention| -> () {
+                check_role(context, AdminRole::ConfigChange)?;
+                set_session_retention(&fedimint.db, retention)
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            SESSION_RETENTION_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> SessionRetention {
+                Ok(get_session_retention(&fedimint.db).await)
+            }
+        },
+        api_endpoint! {
+            PROPOSE_MODULE_ADDITION_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, request: ModuleAdditionRequest| -> () {
+                check_role(context, AdminRole::ConfigChange)?;
+                fedimint.propose_module_addition(request).await
+            }
+        },
+        api_endpoint! {
+            MODULE_ADDITIONS_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> ModuleAdditionsStatus {
+                Ok(fedimint.module_additions().await)
+            }
+        },
+        api_endpoint! {
+            SCHEDULE_UPGRADE_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, request: ScheduleUpgradeRequest| -> () {
+                check_role(context, AdminRole::Dangerous)?;
+                fedimint.schedule_upgrade(request).await
+            }
+        },
+        api_endpoint! {
+            UPGRADE_STATUS_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, _context, _v: ()| -> UpgradeStatus {
+                Ok(fedimint.upgrade_status().await)
+            }
+        },
         api_endpoint! {
             AUDIT_ENDPOINT,
             ApiVersion::new(0, 0),
             async |fedimint: &ConsensusApi, context, _v: ()| -> AuditSummary {
-                check_auth(context)?;
+                check_role(context, AdminRole::ReadOnly)?;
                 Ok(fedimint.get_federation_audit().await?)
             }
         },
+        api_endpoint! {
+            CONSENSUS_HEALTH_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> ConsensusHealth {
+                check_role(context, AdminRole::ReadOnly)?;
+                fedimint.get_consensus_health().await
+            }
+        },
+        api_endpoint! {
+            PEER_CONNECTION_STATS_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> BTreeMap<PeerId, PeerConnectionStats> {
+                check_role(context, AdminRole::ReadOnly)?;
+                Ok(fedimint.get_peer_connection_stats().await)
+            }
+        },
         api_endpoint! {
             GUARDIAN_CONFIG_BACKUP_ENDPOINT,
             ApiVersion::new(0, 2),
@@ -552,6 +1137,25 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                 Ok(fedimint.get_guardian_config_backup(&password))
             }
         },
+        api_endpoint! {
+            GUARDIAN_DISASTER_RECOVERY_BUNDLE_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> GuardianDisasterRecoveryBundle {
+                check_auth(context)?;
+                let password = context.request_auth().expect("Auth was checked before").0;
+                fedimint.get_disaster_recovery_bundle(&password).await
+            }
+        },
+        api_endpoint! {
+            CONSENSUS_CHECKPOINT_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> GuardianConsensusCheckpoint {
+                check_role(context, AdminRole::Dangerous)?;
+                export_checkpoint(&fedimint.db, &fedimint.modules)
+                    .await
+                    .map_err(|e| ApiError::server_error(e.to_string()))
+            }
+        },
         api_endpoint! {
             BACKUP_ENDPOINT,
             ApiVersion::new(0, 0),
@@ -570,11 +1174,39 @@ pub fn server_endpoints() -> Vec<ApiEndpoint<ConsensusApi>> {
                     .handle_recover_request(&mut context.dbtx().into_nc(), id).await)
             }
         },
+        api_endpoint! {
+            ISSUE_API_TOKEN_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, request: IssueApiTokenRequest| -> ApiAuth {
+                check_auth(context)?;
+                issue_api_token(&fedimint.db, request)
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            REVOKE_API_TOKEN_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, name: String| -> () {
+                check_auth(context)?;
+                revoke_api_token(&fedimint.db, &name)
+                    .await
+                    .map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            LIST_API_TOKENS_ENDPOINT,
+            ApiVersion::new(0, 3),
+            async |fedimint: &ConsensusApi, context, _v: ()| -> Vec<ApiTokenInfo> {
+                check_auth(context)?;
+                Ok(list_api_tokens(&fedimint.db).await)
+            }
+        },
         api_endpoint! {
             AUTH_ENDPOINT,
             ApiVersion::new(0, 0),
             async |_fedimint: &ConsensusApi, context, _v: ()| -> () {
-                check_auth(context)?;
+                check_role(context, AdminRole::ReadOnly)?;
                 Ok(())
             }
         },
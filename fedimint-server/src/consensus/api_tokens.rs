@@ -0,0 +1,174 @@
+//! API tokens of the admins of a guardian
+//!
+//! Besides the guardian password, which grants every [`AdminRole`], the admin
+//! holding the password can issue named API tokens scoped to a single role to
+//! other admins and revoke them again. Only the hash of a token is stored, the
+//! token itself is returned once when it is issued. Tokens are local to the
+//! guardian and are not included in consensus checkpoints.
+
+use anyhow::ensure;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::admin_client::{AdminRole, ApiTokenInfo, IssueApiTokenRequest};
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::module::ApiAuth;
+use fedimint_core::time::now;
+use fedimint_logging::LOG_NET_API;
+use futures::StreamExt;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tracing::info;
+
+use crate::consensus::db::{ApiTokenKey, ApiTokenPrefix, ApiTokenRecord};
+
+/// Maximum length of the name of an API token
+const MAX_TOKEN_NAME_LEN: usize = 64;
+
+fn token_hash(token: &ApiAuth) -> sha256::Hash {
+    sha256::Hash::hash(token.0.as_bytes())
+}
+
+/// Returns the role of the API token `token`, `None` if no such token was
+/// issued or it was revoked
+pub async fn api_token_role(
+    dbtx: &mut DatabaseTransaction<'_>,
+    token: &ApiAuth,
+) -> Option<AdminRole> {
+    dbtx.get_value(&ApiTokenKey(token_hash(token)))
+        .await
+        .map(|record| record.role)
+}
+
+/// Issues a new API token, which is only returned by this function
+pub async fn issue_api_token(
+    db: &Database,
+    request: IssueApiTokenRequest,
+) -> anyhow::Result<ApiAuth> {
+    ensure!(
+        !request.name.is_empty() && request.name.len() <= MAX_TOKEN_NAME_LEN,
+        "Token name has to be between 1 and {MAX_TOKEN_NAME_LEN} characters long"
+    );
+
+    let mut secret = [0; 32];
+    OsRng.fill_bytes(&mut secret);
+    let token = ApiAuth(hex::encode(secret));
+
+    let mut dbtx = db.begin_transaction().await;
+
+    let name_taken = dbtx
+        .find_by_prefix(&ApiTokenPrefix)
+        .await
+        .any(|(_, record)| std::future::ready(record.name == request.name))
+        .await;
+    ensure!(!name_taken, "A token named {} already exists", request.name);
+
+    dbtx.insert_new_entry(
+        &ApiTokenKey(token_hash(&token)),
+        &ApiTokenRecord {
+            name: request.name.clone(),
+            role: request.role,
+            issued_at: now(),
+        },
+    )
+    .await;
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_NET_API, name = %request.name, role = %request.role, "Issued API token");
+
+    Ok(token)
+}
+
+/// Revokes the API token named `name`
+pub async fn revoke_api_token(db: &Database, name: &str) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let keys = dbtx
+        .find_by_prefix(&ApiTokenPrefix)
+        .await
+        .filter_map(|(key, record)| std::future::ready((record.name == name).then_some(key)))
+        .collect::<Vec<_>>()
+        .await;
+    ensure!(!keys.is_empty(), "No token named {name} exists");
+
+    for key in keys {
+        dbtx.remove_entry(&key).await;
+    }
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_NET_API, %name, "Revoked API token");
+
+    Ok(())
+}
+
+pub async fn list_api_tokens(db: &Database) -> Vec<ApiTokenInfo> {
+    let mut tokens = db
+        .begin_transaction_nc()
+        .await
+        .find_by_prefix(&ApiTokenPrefix)
+        .await
+        .map(|(_, record)| ApiTokenInfo {
+            name: record.name,
+            role: record.role,
+            issued_at: record.issued_at,
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    tokens.sort_by(|a, b| a.name.cmp(&b.name));
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::admin_client::{AdminRole, IssueApiTokenRequest};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::module::ApiAuth;
+
+    use super::{api_token_role, issue_api_token, list_api_tokens, revoke_api_token};
+
+    #[tokio::test]
+    async fn issued_tokens_authenticate_until_revoked() {
+        let db = MemDatabase::new().into_database();
+
+        let token = issue_api_token(
+            &db,
+            IssueApiTokenRequest {
+                name: "monitoring".to_string(),
+                role: AdminRole::ReadOnly,
+            },
+        )
+        .await
+        .expect("Token is issued");
+
+        assert!(issue_api_token(
+            &db,
+            IssueApiTokenRequest {
+                name: "monitoring".to_string(),
+                role: AdminRole::Dangerous,
+            },
+        )
+        .await
+        .is_err());
+
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert_eq!(
+            api_token_role(&mut dbtx, &token).await,
+            Some(AdminRole::ReadOnly)
+        );
+        assert_eq!(
+            api_token_role(&mut dbtx, &ApiAuth("guess".to_string())).await,
+            None
+        );
+        drop(dbtx);
+
+        assert_eq!(list_api_tokens(&db).await.len(), 1);
+
+        revoke_api_token(&db, "monitoring")
+            .await
+            .expect("Token is revoked");
+
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert_eq!(api_token_role(&mut dbtx, &token).await, None);
+        assert!(list_api_tokens(&db).await.is_empty());
+    }
+}
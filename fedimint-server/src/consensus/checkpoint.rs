@@ -0,0 +1,286 @@
+use aleph_bft::Keychain as KeychainTrait;
+use anyhow::{bail, ensure, Context};
+use fedimint_api_client::api::GuardianConsensusCheckpoint;
+use fedimint_core::db::{
+    Database, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped, MODULE_GLOBAL_PREFIX,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::{ModuleDecoderRegistry, ServerModuleRegistry};
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::{info, warn};
+
+use crate::config::ServerConfig;
+use crate::consensus::aleph_bft::keychain::Keychain;
+use crate::consensus::aleph_bft::to_node_index;
+use crate::consensus::db::{DbKeyPrefix, SignedSessionOutcomeKey};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Prefixes of data that is local to a guardian or only relevant to the
+/// currently running session, which is left out of checkpoints
+const LOCAL_DB_PREFIXES: [u8; 10] = [
+    DbKeyPrefix::AlephUnits as u8,
+    DbKeyPrefix::ConfigGenCheckpoint as u8,
+    DbKeyPrefix::ModuleMaintenance as u8,
+    DbKeyPrefix::RejectedTransaction as u8,
+    DbKeyPrefix::PendingModuleAddition as u8,
+    DbKeyPrefix::SessionRetention as u8,
+    DbKeyPrefix::ApiToken as u8,
+    DbKeyPrefix::PendingUpgrade as u8,
+    DbKeyPrefix::RestoredSessionCheckpoint as u8,
+    fedimint_core::db::DbKeyPrefix::ClientBackup as u8,
+];
+
+/// Key prefixes of the data that only belongs to this guardian, which consist
+/// of [`LOCAL_DB_PREFIXES`] and the prefixes modules declare via
+/// [`fedimint_core::module::ServerModule::local_db_prefixes`]
+fn local_key_prefixes(modules: &ServerModuleRegistry) -> Vec<Vec<u8>> {
+    let mut prefixes = LOCAL_DB_PREFIXES
+        .iter()
+        .map(|prefix| vec![*prefix])
+        .collect::<Vec<Vec<u8>>>();
+
+    for (module_instance_id, _, module) in modules.iter_modules() {
+        for prefix in module.local_db_prefixes() {
+            let mut key_prefix = vec![MODULE_GLOBAL_PREFIX];
+            key_prefix.append(&mut module_instance_id.consensus_encode_to_vec());
+            key_prefix.push(prefix);
+            prefixes.push(key_prefix);
+        }
+    }
+
+    prefixes
+}
+
+/// Snapshots the consensus state of the guardian, so that a new or rebuilt
+/// guardian can start from it instead of processing every session since the
+/// federation was created.
+///
+/// Data that only belongs to this guardian is left out, see
+/// [`local_key_prefixes`]. Hence the importing guardian doesn't contribute its
+/// signatures or decryption shares to peg-outs and contracts that were pending
+/// at the time of the export, the other guardians still provide a threshold of
+/// them.
+///
+/// The snapshot includes the signed outcome of the last finished session, whose
+/// threshold signature is checked by [`import_checkpoint`]. The module state in
+/// the snapshot is not covered by that signature though, so a checkpoint must
+/// only be imported from a guardian that is trusted.
+pub async fn export_checkpoint(
+    db: &Database,
+    modules: &ServerModuleRegistry,
+) -> anyhow::Result<GuardianConsensusCheckpoint> {
+    let local_key_prefixes = local_key_prefixes(modules);
+
+    let mut dbtx = db.begin_transaction_nc().await;
+
+    let session_count = get_finished_session_count_static(&mut dbtx).await;
+    ensure!(session_count > 0, "No session has been completed yet");
+
+    let entries = dbtx
+        .raw_find_by_prefix(&[])
+        .await?
+        .filter(|(key, _)| {
+            std::future::ready(
+                !local_key_prefixes
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix)),
+            )
+        })
+        .collect::<Vec<(Vec<u8>, Vec<u8>)>>()
+        .await;
+
+    info!(target: LOG_CONSENSUS, session_count, entries = entries.len(), "Exported consensus checkpoint");
+
+    Ok(GuardianConsensusCheckpoint {
+        session_count,
+        snapshot: entries.consensus_encode_to_vec(),
+    })
+}
+
+/// Writes the snapshot of a checkpoint created with [`export_checkpoint`] into
+/// an empty database, after verifying that the last session it contains was
+/// signed by a threshold of guardians of our federation.
+///
+/// Nothing is imported if the database already contains finished sessions, so
+/// that the guardian can be restarted with the same checkpoint.
+pub async fn import_checkpoint(
+    db: &Database,
+    cfg: &ServerConfig,
+    decoders: &ModuleDecoderRegistry,
+    checkpoint: &GuardianConsensusCheckpoint,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let existing_session_count = get_finished_session_count_static(&mut dbtx.to_ref_nc()).await;
+    if existing_session_count != 0 {
+        warn!(
+            target: LOG_CONSENSUS,
+            existing_session_count,
+            checkpoint_session_count = checkpoint.session_count,
+            "Database already contains finished sessions, not importing checkpoint"
+        );
+        return Ok(());
+    }
+
+    if dbtx.raw_find_by_prefix(&[]).await?.next().await.is_some() {
+        bail!("Refusing to import checkpoint into a database that is not empty");
+    }
+
+    // Snapshots can exceed the size limit of `consensus_decode`, the reader is
+    // finite anyway
+    let entries = Vec::<(Vec<u8>, Vec<u8>)>::consensus_decode_from_finite_reader(
+        &mut std::io::Cursor::new(&checkpoint.snapshot),
+        decoders,
+    )
+    .context("Invalid checkpoint snapshot")?;
+
+    for (key, value) in &entries {
+        dbtx.raw_insert_bytes(key, value).await?;
+    }
+
+    let session_count = get_finished_session_count_static(&mut dbtx.to_ref_nc()).await;
+    ensure!(
+        session_count > 0 && session_count == checkpoint.session_count,
+        "Checkpoint claims to contain {} sessions, but its snapshot contains {session_count}",
+        checkpoint.session_count
+    );
+
+    let session_index = session_count - 1;
+    let signed_session_outcome = dbtx
+        .get_value(&SignedSessionOutcomeKey(session_index))
+        .await
+        .context("Checkpoint does not contain the outcome of its last session")?;
+
+    let keychain = Keychain::new(cfg);
+    let header = signed_session_outcome.session_outcome.header(session_index);
+    ensure!(
+        signed_session_outcome.signatures.len() >= keychain.threshold()
+            && signed_session_outcome
+                .signatures
+                .iter()
+                .all(|(peer_id, signature)| {
+                    keychain.verify(&header, signature, to_node_index(*peer_id))
+                }),
+        "Session {session_index} of the checkpoint is not signed by our federation"
+    );
+
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_CONSENSUS, session_count, entries = entries.len(), "Imported consensus checkpoint");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use aleph_bft::Keychain as KeychainTrait;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{
+        Database, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped,
+        MODULE_GLOBAL_PREFIX,
+    };
+    use fedimint_core::module::registry::{ModuleDecoderRegistry, ServerModuleRegistry};
+    use fedimint_core::session_outcome::{SessionOutcome, SignedSessionOutcome};
+    use futures::StreamExt;
+
+    use super::{export_checkpoint, import_checkpoint};
+    use crate::config::io::tests::server_config;
+    use crate::config::ServerConfig;
+    use crate::consensus::aleph_bft::keychain::Keychain;
+    use crate::consensus::db::{DbKeyPrefix, SignedSessionOutcomeKey};
+
+    const MODULE_KEY: [u8; 5] = [MODULE_GLOBAL_PREFIX, 0, 0, 0x10, 1];
+    const LOCAL_KEY: [u8; 2] = [DbKeyPrefix::ApiToken as u8, 1];
+
+    /// Outcome of an empty session, signed over the header of session
+    /// `signed_index`
+    fn signed_session(cfg: &ServerConfig, signed_index: u64) -> SignedSessionOutcome {
+        let session_outcome = SessionOutcome { items: vec![] };
+
+        SignedSessionOutcome {
+            signatures: BTreeMap::from([(
+                cfg.local.identity,
+                Keychain::new(cfg).sign(&session_outcome.header(signed_index)),
+            )]),
+            session_outcome,
+        }
+    }
+
+    async fn source_db(cfg: &ServerConfig, last_signed_index: u64) -> Database {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&SignedSessionOutcomeKey(0), &signed_session(cfg, 0))
+            .await;
+        dbtx.insert_entry(
+            &SignedSessionOutcomeKey(1),
+            &signed_session(cfg, last_signed_index),
+        )
+        .await;
+        dbtx.raw_insert_bytes(&MODULE_KEY, &[2]).await.unwrap();
+        dbtx.raw_insert_bytes(&LOCAL_KEY, &[3]).await.unwrap();
+        dbtx.commit_tx().await;
+
+        db
+    }
+
+    #[tokio::test]
+    async fn checkpoint_round_trips_without_local_data() {
+        let cfg = server_config();
+        let checkpoint =
+            export_checkpoint(&source_db(&cfg, 1).await, &ServerModuleRegistry::default())
+                .await
+                .unwrap();
+        assert_eq!(checkpoint.session_count, 2);
+
+        let db = MemDatabase::new().into_database();
+        import_checkpoint(&db, &cfg, &ModuleDecoderRegistry::default(), &checkpoint)
+            .await
+            .unwrap();
+
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert!(dbtx.get_value(&SignedSessionOutcomeKey(1)).await.is_some());
+        assert_eq!(
+            dbtx.raw_get_bytes(&MODULE_KEY).await.unwrap(),
+            Some(vec![2])
+        );
+        assert_eq!(dbtx.raw_get_bytes(&LOCAL_KEY).await.unwrap(), None);
+        drop(dbtx);
+
+        // importing the same checkpoint again on restart does nothing
+        import_checkpoint(&db, &cfg, &ModuleDecoderRegistry::default(), &checkpoint)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn checkpoint_with_tampered_signature_is_rejected() {
+        let cfg = server_config();
+        // the signature of the last session covers the header of another one
+        let checkpoint =
+            export_checkpoint(&source_db(&cfg, 0).await, &ServerModuleRegistry::default())
+                .await
+                .unwrap();
+
+        let db = MemDatabase::new().into_database();
+        assert!(
+            import_checkpoint(&db, &cfg, &ModuleDecoderRegistry::default(), &checkpoint)
+                .await
+                .is_err()
+        );
+
+        // nothing was imported
+        assert!(db
+            .begin_transaction_nc()
+            .await
+            .raw_find_by_prefix(&[])
+            .await
+            .unwrap()
+            .next()
+            .await
+            .is_none());
+    }
+}
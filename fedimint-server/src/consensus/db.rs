@@ -1,14 +1,28 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
+use std::time::SystemTime;
 
+use bitcoin_hashes::sha256;
+use fedimint_core::admin_client::{AdminRole, SessionRetention};
+use fedimint_core::config::ServerModuleInitRegistry;
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::{
+    core_db_key_prefixes, DbKeyPrefixDecl, DbKeySpace, DbKeySpaceRegistry,
+};
 use fedimint_core::db::{DatabaseVersion, ServerMigrationFn, MODULE_GLOBAL_PREFIX};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::session_outcome::{AcceptedItem, SignedSessionOutcome};
-use fedimint_core::{impl_db_lookup, impl_db_record, TransactionId};
+use fedimint_core::epoch::{ModuleAdditionProposal, UpgradeProposal};
+use fedimint_core::module::ModuleConsensusFeature;
+use fedimint_core::session_outcome::{AcceptedItem, SessionOutcome, SignedSessionOutcome};
+use fedimint_core::transaction::TransactionRejection;
+use fedimint_core::{impl_db_lookup, impl_db_record, PeerId, TransactionId};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::config::api::EncryptedConfigGenCheckpoint;
+use crate::config::io::SessionCheckpoint;
+use crate::config::ServerConfig;
+
 pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
 #[repr(u8)]
@@ -18,6 +32,21 @@ pub enum DbKeyPrefix {
     AcceptedTransaction = 0x02,
     SignedSessionOutcome = 0x04,
     AlephUnits = 0x05,
+    ConfigGenCheckpoint = 0x06,
+    ModuleMaintenance = 0x07,
+    RejectedTransaction = 0x08,
+    ModuleAdditionVote = 0x09,
+    ModuleAddition = 0x0a,
+    PendingModuleAddition = 0x0b,
+    SessionRetention = 0x0c,
+    ModuleFeatureSignal = 0x0d,
+    ActiveModuleFeatures = 0x0e,
+    ApiToken = 0x0f,
+    UpgradeVote = 0x10,
+    ScheduledUpgrade = 0x11,
+    PendingUpgrade = 0x12,
+    PrunedSessionOutcome = 0x13,
+    RestoredSessionCheckpoint = 0x14,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -89,10 +118,355 @@ impl_db_record!(
 );
 impl_db_lookup!(key = AlephUnitsKey, query_prefix = AlephUnitsPrefix);
 
+/// Progress of the federation setup, only present until consensus is started
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConfigGenCheckpointKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ConfigGenCheckpointPrefix;
+
+impl_db_record!(
+    key = ConfigGenCheckpointKey,
+    value = EncryptedConfigGenCheckpoint,
+    db_prefix = DbKeyPrefix::ConfigGenCheckpoint,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ConfigGenCheckpointKey,
+    query_prefix = ConfigGenCheckpointPrefix
+);
+
+/// Module instance that this guardian put into maintenance mode, new
+/// transactions using it are rejected by the API of this guardian only, see
+/// [`crate::consensus::maintenance`]
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ModuleMaintenanceKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleMaintenancePrefix;
+
+impl_db_record!(
+    key = ModuleMaintenanceKey,
+    value = (),
+    db_prefix = DbKeyPrefix::ModuleMaintenance,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ModuleMaintenanceKey,
+    query_prefix = ModuleMaintenancePrefix
+);
+
+/// Why consensus rejected a transaction, written together with the signed
+/// outcome of the session the transaction was rejected in and removed again if
+/// the transaction is accepted later on
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct RejectedTransactionKey(pub TransactionId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RejectedTransactionKeyPrefix;
+
+impl_db_record!(
+    key = RejectedTransactionKey,
+    value = TransactionRejection,
+    db_prefix = DbKeyPrefix::RejectedTransaction,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = RejectedTransactionKey,
+    query_prefix = RejectedTransactionKeyPrefix
+);
+
+/// The latest vote of a guardian for adding a module instance, removed once a
+/// proposal for the module instance is approved
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ModuleAdditionVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleAdditionVotePrefix;
+
+impl_db_record!(
+    key = ModuleAdditionVoteKey,
+    value = ModuleAdditionProposal,
+    db_prefix = DbKeyPrefix::ModuleAdditionVote,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ModuleAdditionVoteKey,
+    query_prefix = ModuleAdditionVotePrefix
+);
+
+/// Module addition approved by a threshold of guardians
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ModuleAdditionKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleAdditionPrefix;
+
+impl_db_record!(
+    key = ModuleAdditionKey,
+    value = ModuleAdditionProposal,
+    db_prefix = DbKeyPrefix::ModuleAddition,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = ModuleAdditionKey, query_prefix = ModuleAdditionPrefix);
+
+/// Module addition this guardian voted for, together with its local config gen
+/// params which are needed to generate the module's config
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct PendingModuleAddition {
+    pub proposal: ModuleAdditionProposal,
+    /// The local part of the module's config gen params as JSON
+    pub local_params: String,
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct PendingModuleAdditionKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingModuleAdditionPrefix;
+
+impl_db_record!(
+    key = PendingModuleAdditionKey,
+    value = PendingModuleAddition,
+    db_prefix = DbKeyPrefix::PendingModuleAddition,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = PendingModuleAdditionKey,
+    query_prefix = PendingModuleAdditionPrefix
+);
+
+/// Number of finished sessions this guardian keeps, see
+/// [`crate::consensus::retention`]
+#[derive(Debug, Encodable, Decodable)]
+pub struct SessionRetentionKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SessionRetentionPrefix;
+
+impl_db_record!(
+    key = SessionRetentionKey,
+    value = SessionRetention,
+    db_prefix = DbKeyPrefix::SessionRetention,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = SessionRetentionKey,
+    query_prefix = SessionRetentionPrefix
+);
+
+/// The consensus features a guardian signaled for a module instance, see
+/// [`crate::consensus::features`]
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ModuleFeatureSignalKey {
+    pub module_instance_id: ModuleInstanceId,
+    pub peer: PeerId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleFeatureSignalPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ModuleFeatureSignalModulePrefix(pub ModuleInstanceId);
+
+impl_db_record!(
+    key = ModuleFeatureSignalKey,
+    value = BTreeSet<ModuleConsensusFeature>,
+    db_prefix = DbKeyPrefix::ModuleFeatureSignal,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ModuleFeatureSignalKey,
+    query_prefix = ModuleFeatureSignalPrefix,
+    query_prefix = ModuleFeatureSignalModulePrefix
+);
+
+/// The consensus features of a module instance that all guardians signaled
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ActiveModuleFeaturesKey(pub ModuleInstanceId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ActiveModuleFeaturesPrefix;
+
+impl_db_record!(
+    key = ActiveModuleFeaturesKey,
+    value = BTreeSet<ModuleConsensusFeature>,
+    db_prefix = DbKeyPrefix::ActiveModuleFeatures,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ActiveModuleFeaturesKey,
+    query_prefix = ActiveModuleFeaturesPrefix
+);
+
+/// API token of an admin of this guardian, keyed by the hash of the token, see
+/// [`crate::consensus::api_tokens`]
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ApiTokenKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ApiTokenPrefix;
+
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct ApiTokenRecord {
+    pub name: String,
+    pub role: AdminRole,
+    pub issued_at: SystemTime,
+}
+
+impl_db_record!(
+    key = ApiTokenKey,
+    value = ApiTokenRecord,
+    db_prefix = DbKeyPrefix::ApiToken,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = ApiTokenKey, query_prefix = ApiTokenPrefix);
+
+/// The latest vote of a guardian for a coordinated upgrade, removed once an
+/// upgrade is approved
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct UpgradeVoteKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct UpgradeVotePrefix;
+
+impl_db_record!(
+    key = UpgradeVoteKey,
+    value = UpgradeProposal,
+    db_prefix = DbKeyPrefix::UpgradeVote,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = UpgradeVoteKey, query_prefix = UpgradeVotePrefix);
+
+/// The latest upgrade approved by a threshold of guardians, see
+/// [`crate::consensus::upgrade`]
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct ScheduledUpgradeKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScheduledUpgradePrefix;
+
+impl_db_record!(
+    key = ScheduledUpgradeKey,
+    value = UpgradeProposal,
+    db_prefix = DbKeyPrefix::ScheduledUpgrade,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = ScheduledUpgradeKey,
+    query_prefix = ScheduledUpgradePrefix
+);
+
+/// Upgrade this guardian voted for, which is resubmitted until it is approved
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct PendingUpgradeKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingUpgradePrefix;
+
+impl_db_record!(
+    key = PendingUpgradeKey,
+    value = UpgradeProposal,
+    db_prefix = DbKeyPrefix::PendingUpgrade,
+    notify_on_modify = false,
+);
+impl_db_lookup!(key = PendingUpgradeKey, query_prefix = PendingUpgradePrefix);
+
+/// The transactions of a session whose signed outcome was pruned, see
+/// [`crate::consensus::retention`]
+#[derive(Debug, Encodable, Decodable)]
+pub struct PrunedSessionOutcomeKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PrunedSessionOutcomePrefix;
+
+impl_db_record!(
+    key = PrunedSessionOutcomeKey,
+    value = SessionOutcome,
+    db_prefix = DbKeyPrefix::PrunedSessionOutcome,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = PrunedSessionOutcomeKey,
+    query_prefix = PrunedSessionOutcomePrefix
+);
+
+/// Checkpoint of the disaster-recovery bundle this guardian was restored from,
+/// until it was verified, see [`crate::consensus::restore`]
+#[derive(Debug, Encodable, Decodable)]
+pub struct RestoredSessionCheckpointKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RestoredSessionCheckpointPrefix;
+
+impl_db_record!(
+    key = RestoredSessionCheckpointKey,
+    value = SessionCheckpoint,
+    db_prefix = DbKeyPrefix::RestoredSessionCheckpoint,
+    notify_on_modify = false,
+);
+impl_db_lookup!(
+    key = RestoredSessionCheckpointKey,
+    query_prefix = RestoredSessionCheckpointPrefix
+);
+
 pub fn get_global_database_migrations() -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
     BTreeMap::new()
 }
 
+/// Prefixes of the records `fedimint-server` stores in the global key space
+pub fn global_db_key_prefixes() -> Vec<DbKeyPrefixDecl> {
+    vec![
+        DbKeyPrefixDecl::of::<AcceptedItemKey>(DbKeyPrefix::AcceptedItem),
+        DbKeyPrefixDecl::of::<AcceptedTransactionKey>(DbKeyPrefix::AcceptedTransaction),
+        DbKeyPrefixDecl::of::<SignedSessionOutcomeKey>(DbKeyPrefix::SignedSessionOutcome),
+        DbKeyPrefixDecl::of::<AlephUnitsKey>(DbKeyPrefix::AlephUnits),
+        DbKeyPrefixDecl::of::<ConfigGenCheckpointKey>(DbKeyPrefix::ConfigGenCheckpoint),
+        DbKeyPrefixDecl::of::<ModuleMaintenanceKey>(DbKeyPrefix::ModuleMaintenance),
+        DbKeyPrefixDecl::of::<RejectedTransactionKey>(DbKeyPrefix::RejectedTransaction),
+        DbKeyPrefixDecl::of::<ModuleAdditionVoteKey>(DbKeyPrefix::ModuleAdditionVote),
+        DbKeyPrefixDecl::of::<ModuleAdditionKey>(DbKeyPrefix::ModuleAddition),
+        DbKeyPrefixDecl::of::<PendingModuleAdditionKey>(DbKeyPrefix::PendingModuleAddition),
+        DbKeyPrefixDecl::of::<SessionRetentionKey>(DbKeyPrefix::SessionRetention),
+        DbKeyPrefixDecl::of::<ModuleFeatureSignalKey>(DbKeyPrefix::ModuleFeatureSignal),
+        DbKeyPrefixDecl::of::<ActiveModuleFeaturesKey>(DbKeyPrefix::ActiveModuleFeatures),
+        DbKeyPrefixDecl::of::<ApiTokenKey>(DbKeyPrefix::ApiToken),
+        DbKeyPrefixDecl::of::<UpgradeVoteKey>(DbKeyPrefix::UpgradeVote),
+        DbKeyPrefixDecl::of::<ScheduledUpgradeKey>(DbKeyPrefix::ScheduledUpgrade),
+        DbKeyPrefixDecl::of::<PendingUpgradeKey>(DbKeyPrefix::PendingUpgrade),
+        DbKeyPrefixDecl::of::<PrunedSessionOutcomeKey>(DbKeyPrefix::PrunedSessionOutcome),
+        DbKeyPrefixDecl::of::<RestoredSessionCheckpointKey>(DbKeyPrefix::RestoredSessionCheckpoint),
+        DbKeyPrefixDecl::reserved(MODULE_GLOBAL_PREFIX, DbKeyPrefix::Module),
+    ]
+}
+
+/// Collects the key prefixes declared by `fedimint-core`, `fedimint-server`
+/// and the module of every module instance in `cfg`
+pub fn server_db_key_spaces(
+    cfg: &ServerConfig,
+    module_init_registry: &ServerModuleInitRegistry,
+) -> DbKeySpaceRegistry {
+    let mut key_spaces = DbKeySpaceRegistry::default();
+    key_spaces.declare(DbKeySpace::Global, "fedimint-core", core_db_key_prefixes());
+    key_spaces.declare(
+        DbKeySpace::Global,
+        "fedimint-server",
+        global_db_key_prefixes(),
+    );
+
+    for (module_instance_id, module_cfg) in &cfg.consensus.modules {
+        if let Some(module_init) = module_init_registry.get(&module_cfg.kind) {
+            key_spaces.declare(
+                DbKeySpace::Module(*module_instance_id),
+                &module_cfg.kind,
+                module_init.db_key_prefixes(),
+            );
+        }
+    }
+
+    key_spaces
+}
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::collections::BTreeMap;
@@ -286,6 +660,25 @@ mod fedimint_migration_tests {
                             );
                             info!(target: LOG_DB, "Validated AlephUnits");
                         }
+                        // Only written during federation setup, which is never migrated
+                        DbKeyPrefix::ConfigGenCheckpoint => {}
+                        // Only set by a guardian temporarily while responding to an incident
+                        DbKeyPrefix::ModuleMaintenance => {}
+                        // Local setting of the guardian that is not written by older versions
+                        DbKeyPrefix::SessionRetention => {}
+                        // Not part of the v0 database snapshot
+                        DbKeyPrefix::RejectedTransaction
+                        | DbKeyPrefix::ModuleAdditionVote
+                        | DbKeyPrefix::ModuleAddition
+                        | DbKeyPrefix::PendingModuleAddition
+                        | DbKeyPrefix::ModuleFeatureSignal
+                        | DbKeyPrefix::ActiveModuleFeatures
+                        | DbKeyPrefix::ApiToken
+                        | DbKeyPrefix::UpgradeVote
+                        | DbKeyPrefix::ScheduledUpgrade
+                        | DbKeyPrefix::PendingUpgrade
+                        | DbKeyPrefix::PrunedSessionOutcome
+                        | DbKeyPrefix::RestoredSessionCheckpoint => {}
                         // Module prefix is reserved for modules, no migration testing is needed
                         DbKeyPrefix::Module => {}
                     }
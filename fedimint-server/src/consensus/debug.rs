@@ -15,6 +15,24 @@ impl<'ci> fmt::Debug for DebugConsensusItem<'ci> {
                     mci
                 ))?;
             }
+            ConsensusItem::ModuleAddition(proposal) => {
+                f.write_fmt(format_args!(
+                    "Module addition vote: module={} kind={} activation_session={}",
+                    proposal.module_instance_id, proposal.kind, proposal.activation_session
+                ))?;
+            }
+            ConsensusItem::ModuleFeatures(signal) => {
+                f.write_fmt(format_args!(
+                    "Module feature signal: module={} features={:?}",
+                    signal.module_instance_id, signal.features
+                ))?;
+            }
+            ConsensusItem::Upgrade(proposal) => {
+                f.write_fmt(format_args!(
+                    "Upgrade vote: version={} halt_session={}",
+                    proposal.version, proposal.halt_session
+                ))?;
+            }
             ConsensusItem::Transaction(tx) => {
                 f.write_fmt(format_args!(
                     "Transaction txid={}, inputs_num={}, outputs_num={}",
@@ -1,13 +1,17 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use aleph_bft::Keychain as KeychainTrait;
 use anyhow::{anyhow, bail};
 use async_channel::Receiver;
-use fedimint_api_client::api::{DynGlobalApi, FederationApiExt, PeerConnectionStatus};
+use fedimint_api_client::api::{
+    DynGlobalApi, FederationApiExt, PeerConnectionStats, PeerConnectionStatus,
+};
 use fedimint_api_client::query::FilterMap;
-use fedimint_core::core::{DynOutput, MODULE_INSTANCE_ID_GLOBAL};
+use fedimint_core::config::ServerModuleInitRegistry;
+use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId, MODULE_INSTANCE_ID_GLOBAL};
 use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::Decodable;
 use fedimint_core::endpoint_constants::AWAIT_SIGNED_SESSION_OUTCOME_ENDPOINT;
@@ -22,10 +26,11 @@ use fedimint_core::session_outcome::{
 };
 use fedimint_core::task::{sleep, TaskGroup, TaskHandle};
 use fedimint_core::timing::TimeReporter;
-use fedimint_core::{timing, NumPeers, PeerId};
+use fedimint_core::transaction::TransactionRejection;
+use fedimint_core::{timing, NumPeers, PeerId, TransactionId};
 use futures::StreamExt;
 use rand::Rng;
-use tokio::sync::{watch, RwLock};
+use tokio::sync::{watch, Notify, RwLock};
 use tracing::{debug, info, instrument, warn, Level};
 
 use crate::config::ServerConfig;
@@ -38,10 +43,18 @@ use crate::consensus::aleph_bft::spawner::Spawner;
 use crate::consensus::aleph_bft::{to_node_index, Message};
 use crate::consensus::db::{
     AcceptedItemKey, AcceptedItemPrefix, AcceptedTransactionKey, AlephUnitsPrefix,
-    SignedSessionOutcomeKey, SignedSessionOutcomePrefix,
+    RejectedTransactionKey, SignedSessionOutcomeKey, SignedSessionOutcomePrefix,
 };
 use crate::consensus::debug::DebugConsensusItem;
-use crate::consensus::transaction::process_transaction_with_dbtx;
+use crate::consensus::features::process_module_feature_signal;
+use crate::consensus::module_addition::{due_module_additions, process_module_addition_vote};
+use crate::consensus::restore::verify_restored_checkpoint;
+use crate::consensus::retention::prune_sessions;
+use crate::consensus::transaction::{
+    persist_transaction_rejections, process_transaction_with_dbtx,
+    process_verified_transaction_with_dbtx, verify_transactions,
+};
+use crate::consensus::upgrade::{due_upgrade, process_upgrade_vote};
 use crate::fedimint_core::encoding::Encodable;
 use crate::metrics::{
     CONSENSUS_ITEMS_PROCESSED_TOTAL, CONSENSUS_ITEM_PROCESSING_DURATION_SECONDS,
@@ -55,6 +68,8 @@ use crate::LOG_CONSENSUS;
 /// Runs the main server consensus loop
 pub struct ConsensusEngine {
     pub modules: ServerModuleRegistry,
+    /// Module kinds we support, module additions of other kinds are rejected
+    pub module_init_registry: ServerModuleInitRegistry,
     pub db: Database,
     pub keychain: Keychain,
     pub federation_api: DynGlobalApi,
@@ -62,11 +77,25 @@ pub struct ConsensusEngine {
     pub submission_receiver: Receiver<ConsensusItem>,
     pub shutdown_receiver: watch::Receiver<Option<u64>>,
     pub last_ci_by_peer: Arc<RwLock<BTreeMap<PeerId, u64>>>,
+    /// When we last processed a consensus item of each peer
+    pub last_ci_time_by_peer: Arc<RwLock<BTreeMap<PeerId, SystemTime>>>,
+    /// Why transactions were rejected in the current session, persisted once
+    /// the session is complete
+    pub transaction_rejections: Arc<RwLock<BTreeMap<TransactionId, TransactionRejection>>>,
+    /// Wakes up the task submitting the consensus proposal of each module, see
+    /// [`Self::wake_module_proposals`]
+    pub module_proposal_wakeups: BTreeMap<ModuleInstanceId, Arc<Notify>>,
     /// Just a string version of `cfg.local.identity` for performance
     pub self_id_str: String,
     /// Just a string version of peer ids for performance
     pub peer_id_str: Vec<String>,
     pub connection_status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+    pub connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
+    /// SOCKS5 proxy the P2P connections to the other guardians are made through
+    pub socks5_proxy: Option<SocketAddr>,
+    /// The code version of this `fedimintd`, consensus halts for scheduled
+    /// upgrades to other versions
+    pub code_version: String,
     pub task_group: TaskGroup,
 }
 
@@ -100,6 +129,7 @@ impl ConsensusEngine {
                         item_index,
                         item,
                         self.cfg.local.identity,
+                        false,
                     )
                     .await
                     .is_ok()
@@ -132,9 +162,26 @@ impl ConsensusEngine {
 
             info!(target: LOG_CONSENSUS, "Session {session_index} completed");
 
+            verify_restored_checkpoint(&self.db).await?;
+
             if Some(session_index) == self.shutdown_receiver.borrow().to_owned() {
                 break;
             }
+
+            if !due_module_additions(&self.db, &self.cfg.consensus.modules)
+                .await
+                .is_empty()
+            {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate module additions");
+
+                break;
+            }
+
+            if let Some(upgrade) = due_upgrade(&self.db, &self.code_version).await {
+                info!(target: LOG_CONSENSUS, version = %upgrade.version, "Halting consensus for the scheduled upgrade");
+
+                break;
+            }
         }
 
         info!(target: LOG_CONSENSUS, "Consensus task shut down");
@@ -150,11 +197,14 @@ impl ConsensusEngine {
 
         // Build P2P connections for the atomic broadcast
         let connections = ReconnectPeerConnections::new(
-            self.cfg.network_config(),
+            self.cfg.network_config(self.socks5_proxy),
             DelayCalculator::PROD_DEFAULT,
-            TlsTcpConnector::new(self.cfg.tls_config(), self.cfg.local.identity).into_dyn(),
+            TlsTcpConnector::new(self.cfg.tls_config(), self.cfg.local.identity)
+                .with_socks5_proxy(self.socks5_proxy)
+                .into_dyn(),
             &self.task_group,
             Arc::clone(&self.connection_status_channels),
+            Arc::clone(&self.connection_stats),
         )
         .await;
 
@@ -167,6 +217,8 @@ impl ConsensusEngine {
 
             info!(target: LOG_CONSENSUS, "Session {session_index} completed");
 
+            verify_restored_checkpoint(&self.db).await?;
+
             if Some(session_index) == self.shutdown_receiver.borrow().to_owned() {
                 info!(target: LOG_CONSENSUS, "Initiating shutdown, waiting for peers to complete the session...");
 
@@ -174,6 +226,25 @@ impl ConsensusEngine {
 
                 break;
             }
+
+            if !due_module_additions(&self.db, &self.cfg.consensus.modules)
+                .await
+                .is_empty()
+            {
+                info!(target: LOG_CONSENSUS, "Stopping consensus to activate module additions, waiting for peers to complete the session...");
+
+                sleep(Duration::from_secs(60)).await;
+
+                break;
+            }
+
+            if let Some(upgrade) = due_upgrade(&self.db, &self.code_version).await {
+                info!(target: LOG_CONSENSUS, version = %upgrade.version, "Halting consensus for the scheduled upgrade, waiting for peers to complete the session...");
+
+                sleep(Duration::from_secs(60)).await;
+
+                break;
+            }
         }
 
         info!(target: LOG_CONSENSUS, "Consensus task shut down");
@@ -326,12 +397,15 @@ impl ConsensusEngine {
                 unit_data = unit_data_receiver.recv() => {
                     if let (UnitData::Batch(bytes), peer) = unit_data? {
                         if let Ok(items) = Vec::<ConsensusItem>::consensus_decode(&mut bytes.as_slice(), &self.decoders()){
-                            for item in items {
+                            let verified = verify_transactions(&self.modules, &items).await;
+
+                            for (item, transaction_verified) in items.into_iter().zip(verified) {
                                 if self.process_consensus_item(
                                     session_index,
                                     item_index,
-                                    item.clone(),
-                                    peer
+                                    item,
+                                    peer,
+                                    transaction_verified
                                 ).await
                                 .is_ok() {
                                     item_index += 1;
@@ -352,12 +426,20 @@ impl ConsensusEngine {
 
                     assert!(processed.iter().eq(pending_accepted_items.iter()));
 
-                    for accepted_item in unprocessed {
+                    let unprocessed_items = unprocessed
+                        .iter()
+                        .map(|accepted_item| accepted_item.item.clone())
+                        .collect::<Vec<_>>();
+
+                    let verified = verify_transactions(&self.modules, &unprocessed_items).await;
+
+                    for (accepted_item, transaction_verified) in unprocessed.iter().zip(verified) {
                         if self.process_consensus_item(
                             session_index,
                             item_index,
                             accepted_item.item.clone(),
-                            accepted_item.peer
+                            accepted_item.peer,
+                            transaction_verified
                         ).await.is_err(){
                             panic!("Rejected accepted consensus item {:?}", DebugConsensusItem(&accepted_item.item));
                         }
@@ -449,11 +531,18 @@ impl ConsensusEngine {
             panic!("We tried to overwrite a signed session outcome");
         }
 
+        let rejections = std::mem::take(&mut *self.transaction_rejections.write().await);
+        persist_transaction_rejections(&mut dbtx.to_ref_nc(), rejections).await;
+
+        prune_sessions(&mut dbtx.to_ref_nc(), session_index + 1).await;
+
         dbtx.commit_tx_result()
             .await
             .expect("This is the only place where we write to this key");
     }
 
+    /// Processes an ordered consensus item, `transaction_verified` is set if
+    /// the item is a transaction that already passed [`verify_transaction`]
     #[instrument(target = "fm::consensus", skip(self, item), level = "info")]
     pub async fn process_consensus_item(
         &self,
@@ -461,6 +550,7 @@ impl ConsensusEngine {
         item_index: u64,
         item: ConsensusItem,
         peer: PeerId,
+        transaction_verified: bool,
     ) -> anyhow::Result<()> {
         let peer_id_str = &self.peer_id_str[peer.to_usize()];
         let _timing /* logs on drop */ = timing::TimeReporter::new("process_consensus_item").level(Level::TRACE);
@@ -475,6 +565,11 @@ impl ConsensusEngine {
             .await
             .insert(peer, session_index);
 
+        self.last_ci_time_by_peer
+            .write()
+            .await
+            .insert(peer, fedimint_core::time::now());
+
         CONSENSUS_PEER_CONTRIBUTION_SESSION_IDX
             .with_label_values(&[&self.self_id_str, peer_id_str])
             .set(session_index as i64);
@@ -497,13 +592,21 @@ impl ConsensusEngine {
             bail!("Item was discarded previously");
         }
 
-        self.process_consensus_item_with_db_transaction(&mut dbtx.to_ref_nc(), item.clone(), peer)
-            .await?;
+        self.process_consensus_item_with_db_transaction(
+            &mut dbtx.to_ref_nc(),
+            session_index,
+            item.clone(),
+            peer,
+            transaction_verified,
+        )
+        .await?;
 
         // After this point we have to commit the database transaction since the
         // item has been fully processed without errors
         dbtx.warn_uncommitted();
 
+        let touched_modules = touched_modules(&item);
+
         dbtx.insert_entry(&AcceptedItemKey(item_index), &AcceptedItem { item, peer })
             .await;
 
@@ -538,6 +641,8 @@ impl ConsensusEngine {
             .await
             .expect("Committing consensus epoch failed");
 
+        self.wake_module_proposals(touched_modules);
+
         CONSENSUS_ITEMS_PROCESSED_TOTAL
             .with_label_values(&[peer_id_str])
             .inc();
@@ -546,11 +651,24 @@ impl ConsensusEngine {
         Ok(())
     }
 
+    /// Makes the modules whose state was changed by a processed item submit
+    /// their consensus proposal right away, since processing the item may
+    /// have created new items for them to propose
+    fn wake_module_proposals(&self, module_ids: BTreeSet<ModuleInstanceId>) {
+        for module_id in module_ids {
+            if let Some(wakeup) = self.module_proposal_wakeups.get(&module_id) {
+                wakeup.notify_one();
+            }
+        }
+    }
+
     async fn process_consensus_item_with_db_transaction(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
+        session_index: u64,
         consensus_item: ConsensusItem,
         peer_id: PeerId,
+        transaction_verified: bool,
     ) -> anyhow::Result<()> {
         // We rely on decoding rejecting any unknown module instance ids to avoid
         // peer-triggered panic here
@@ -583,16 +701,71 @@ impl ConsensusEngine {
                     .map(DynOutput::module_instance_id)
                     .collect::<Vec<_>>();
 
-                process_transaction_with_dbtx(self.modules.clone(), dbtx, transaction)
-                    .await
-                    .map_err(|error| anyhow!(error.to_string()))?;
+                let result = if transaction_verified {
+                    process_verified_transaction_with_dbtx(self.modules.clone(), dbtx, transaction)
+                        .await
+                } else {
+                    process_transaction_with_dbtx(self.modules.clone(), dbtx, transaction).await
+                };
+
+                if let Err(error) = result {
+                    let rejection = error.into_rejection();
+                    debug!(target: LOG_CONSENSUS, %txid, %rejection, "Transaction rejected");
+                    // The consensus item is discarded, so the rejection is kept until it can be
+                    // persisted atomically with the session outcome
+                    let error = rejection.to_string();
+                    self.transaction_rejections
+                        .write()
+                        .await
+                        .insert(txid, rejection);
+                    bail!(error);
+                }
 
                 debug!(target: LOG_CONSENSUS, %txid,  "Transaction accepted");
                 dbtx.insert_entry(&AcceptedTransactionKey(txid), &modules_ids)
                     .await;
+                dbtx.remove_entry(&RejectedTransactionKey(txid)).await;
 
                 Ok(())
             }
+            ConsensusItem::ModuleAddition(proposal) => {
+                process_module_addition_vote(
+                    dbtx,
+                    &self.cfg.consensus.modules,
+                    &self.module_init_registry,
+                    self.keychain.threshold(),
+                    session_index,
+                    proposal,
+                    peer_id,
+                )
+                .await
+            }
+            ConsensusItem::ModuleFeatures(signal) => {
+                process_module_feature_signal(
+                    dbtx,
+                    &self.modules,
+                    &self
+                        .cfg
+                        .consensus
+                        .broadcast_public_keys
+                        .keys()
+                        .copied()
+                        .collect(),
+                    signal,
+                    peer_id,
+                )
+                .await
+            }
+            ConsensusItem::Upgrade(proposal) => {
+                process_upgrade_vote(
+                    dbtx,
+                    self.keychain.threshold(),
+                    session_index,
+                    proposal,
+                    peer_id,
+                )
+                .await
+            }
             ConsensusItem::Default { variant, .. } => {
                 warn!(
                     target: LOG_CONSENSUS,
@@ -673,3 +846,73 @@ pub async fn get_finished_session_count_static(dbtx: &mut DatabaseTransaction<'_
         .await
         .map_or(0, |entry| (entry.0 .0) + 1)
 }
+
+/// Module instances whose state can be changed by processing `item`
+fn touched_modules(item: &ConsensusItem) -> BTreeSet<ModuleInstanceId> {
+    match item {
+        ConsensusItem::Transaction(transaction) => transaction
+            .inputs
+            .iter()
+            .map(DynInput::module_instance_id)
+            .chain(
+                transaction
+                    .outputs
+                    .iter()
+                    .map(DynOutput::module_instance_id),
+            )
+            .collect(),
+        ConsensusItem::Module(module_item) => BTreeSet::from([module_item.module_instance_id()]),
+        _ => BTreeSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use bitcoin::key::KeyPair;
+    use fedimint_core::core::{DynInput, DynOutput};
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::secp256k1::SECP256K1;
+    use fedimint_core::transaction::{Transaction, TransactionSignature};
+    use fedimint_core::Amount;
+    use fedimint_dummy_common::{DummyInput, DummyOutput};
+    use rand::thread_rng;
+
+    use super::touched_modules;
+
+    #[test]
+    fn transactions_touch_the_modules_of_their_inputs_and_outputs() {
+        let account = KeyPair::new(SECP256K1, &mut thread_rng()).public_key();
+        let transaction = Transaction {
+            inputs: vec![DynInput::from_typed(
+                1,
+                DummyInput {
+                    amount: Amount::ZERO,
+                    account,
+                },
+            )],
+            outputs: vec![DynOutput::from_typed(
+                3,
+                DummyOutput {
+                    amount: Amount::ZERO,
+                    account,
+                },
+            )],
+            nonce: [0x42; 8],
+            signatures: TransactionSignature::NaiveMultisig(vec![]),
+        };
+
+        assert_eq!(
+            touched_modules(&ConsensusItem::Transaction(transaction)),
+            BTreeSet::from([1, 3])
+        );
+        assert_eq!(
+            touched_modules(&ConsensusItem::Default {
+                variant: 42,
+                bytes: vec![],
+            }),
+            BTreeSet::new()
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Rolling out module consensus features
+//!
+//! Every guardian broadcasts the [`ModuleConsensusFeature`]s its module
+//! implementations support as a [`ConsensusItem::ModuleFeatures`] signal. Once
+//! all guardians signaled a feature for a module instance it is activated and
+//! stays active, even if a guardian downgrades later. Inputs and outputs that
+//! require a feature which is not active yet are rejected by every guardian in
+//! the same way, so modules can introduce new variants while the guardians
+//! upgrade one after another.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use anyhow::{bail, ensure};
+use async_channel::Sender;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::envs::is_running_in_test_env;
+use fedimint_core::epoch::{ConsensusItem, ModuleFeatureSignal};
+use fedimint_core::module::registry::ServerModuleRegistry;
+use fedimint_core::module::ModuleConsensusFeature;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::consensus::db::{
+    ActiveModuleFeaturesKey, ActiveModuleFeaturesPrefix, ModuleFeatureSignalKey,
+    ModuleFeatureSignalModulePrefix,
+};
+
+/// Records the signal of `peer` and activates the features of the module
+/// instance that all `peers` signaled
+pub async fn process_module_feature_signal(
+    dbtx: &mut DatabaseTransaction<'_>,
+    modules: &ServerModuleRegistry,
+    peers: &BTreeSet<PeerId>,
+    signal: ModuleFeatureSignal,
+    peer: PeerId,
+) -> anyhow::Result<()> {
+    let module_instance_id = signal.module_instance_id;
+
+    ensure!(
+        modules.get(module_instance_id).is_some(),
+        "Module instance {module_instance_id} does not exist"
+    );
+
+    let key = ModuleFeatureSignalKey {
+        module_instance_id,
+        peer,
+    };
+
+    if dbtx.get_value(&key).await.as_ref() == Some(&signal.features) {
+        bail!("Already signaled these features");
+    }
+
+    dbtx.insert_entry(&key, &signal.features).await;
+
+    let signals = dbtx
+        .find_by_prefix(&ModuleFeatureSignalModulePrefix(module_instance_id))
+        .await
+        .map(|(key, features)| (key.peer, features))
+        .collect::<BTreeMap<PeerId, BTreeSet<ModuleConsensusFeature>>>()
+        .await;
+
+    if peers.iter().any(|peer| !signals.contains_key(peer)) {
+        return Ok(());
+    }
+
+    let mut active = active_module_features(dbtx, module_instance_id).await;
+
+    for feature in &signal.features {
+        if !active.contains(feature) && signals.values().all(|other| other.contains(feature)) {
+            info!(
+                target: LOG_CONSENSUS,
+                %module_instance_id,
+                %feature,
+                "Module consensus feature activated"
+            );
+            active.insert(feature.clone());
+        }
+    }
+
+    dbtx.insert_entry(&ActiveModuleFeaturesKey(module_instance_id), &active)
+        .await;
+
+    Ok(())
+}
+
+pub async fn active_module_features(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+) -> BTreeSet<ModuleConsensusFeature> {
+    dbtx.get_value(&ActiveModuleFeaturesKey(module_instance_id))
+        .await
+        .unwrap_or_default()
+}
+
+/// Returns the active features of all module instances that have any
+pub async fn all_active_module_features(
+    db: &Database,
+) -> BTreeMap<ModuleInstanceId, BTreeSet<ModuleConsensusFeature>> {
+    db.begin_transaction_nc()
+        .await
+        .find_by_prefix(&ActiveModuleFeaturesPrefix)
+        .await
+        .map(|(key, features)| (key.0, features))
+        .collect()
+        .await
+}
+
+/// Periodically submits the features our module implementations support until
+/// the signal is recorded, since it can be discarded if it is submitted while
+/// a session is being completed
+pub fn submit_module_feature_signals(
+    task_group: &TaskGroup,
+    db: Database,
+    our_id: PeerId,
+    modules: ServerModuleRegistry,
+    submission_sender: Sender<ConsensusItem>,
+) {
+    let mut interval = tokio::time::interval(if is_running_in_test_env() {
+        Duration::from_millis(100)
+    } else {
+        Duration::from_secs(1)
+    });
+
+    task_group.spawn(
+        "submit_module_feature_signals",
+        move |task_handle| async move {
+            while !task_handle.is_shutting_down() {
+                let mut dbtx = db.begin_transaction_nc().await;
+
+                for (module_instance_id, _, module) in modules.iter_modules() {
+                    let features = module.supported_consensus_features();
+
+                    let signaled = dbtx
+                        .get_value(&ModuleFeatureSignalKey {
+                            module_instance_id,
+                            peer: our_id,
+                        })
+                        .await;
+
+                    // A module without features doesn't need to signal anything, unless it
+                    // signaled features before
+                    if signaled.as_ref() == Some(&features)
+                        || (signaled.is_none() && features.is_empty())
+                    {
+                        continue;
+                    }
+
+                    submission_sender
+                        .send(ConsensusItem::ModuleFeatures(ModuleFeatureSignal {
+                            module_instance_id,
+                            features,
+                        }))
+                        .await
+                        .ok();
+                }
+
+                interval.tick().await;
+            }
+        },
+    );
+}
@@ -0,0 +1,168 @@
+//! Per-module maintenance mode
+//!
+//! A guardian can put module instances into maintenance mode via the admin
+//! API, for example while it upgrades the backend of the module. Maintenance
+//! mode is a local admission filter of the guardian's API and deliberately
+//! not a consensus rule: transactions submitted to this guardian that spend
+//! inputs or create outputs of a module in maintenance are rejected with
+//! [`fedimint_core::module::ApiError::module_in_maintenance`], while
+//! transactions that were already submitted, or are proposed by other
+//! guardians, are still processed by consensus as usual.
+//!
+//! Enforcing maintenance mode in consensus would make the validity of a
+//! transaction depend on local state that other guardians don't share, so
+//! guardians would disagree on the outcome of a session. A module that has to
+//! reject transactions for all guardians has to do so in its own consensus
+//! logic, e.g. based on a consensus item every guardian agreed on.
+
+use std::collections::BTreeSet;
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::transaction::Transaction;
+use futures::StreamExt;
+
+use crate::consensus::db::{ModuleMaintenanceKey, ModuleMaintenancePrefix};
+
+pub async fn modules_in_maintenance(
+    dbtx: &mut DatabaseTransaction<'_>,
+) -> BTreeSet<ModuleInstanceId> {
+    dbtx.find_by_prefix(&ModuleMaintenancePrefix)
+        .await
+        .map(|(key, ())| key.0)
+        .collect()
+        .await
+}
+
+pub async fn set_module_maintenance(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+    enabled: bool,
+) {
+    if enabled {
+        dbtx.insert_entry(&ModuleMaintenanceKey(module_instance_id), &())
+            .await;
+    } else {
+        dbtx.remove_entry(&ModuleMaintenanceKey(module_instance_id))
+            .await;
+    }
+}
+
+/// Returns the first module instance used by the inputs or outputs of the
+/// transaction that is in `modules_in_maintenance`
+pub fn module_in_maintenance(
+    transaction: &Transaction,
+    modules_in_maintenance: &BTreeSet<ModuleInstanceId>,
+) -> Option<ModuleInstanceId> {
+    transaction
+        .inputs
+        .iter()
+        .map(|input| input.module_instance_id())
+        .chain(
+            transaction
+                .outputs
+                .iter()
+                .map(|output| output.module_instance_id()),
+        )
+        .find(|module_instance_id| modules_in_maintenance.contains(module_instance_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use bitcoin::key::KeyPair;
+    use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::secp256k1::SECP256K1;
+    use fedimint_core::transaction::{Transaction, TransactionSignature};
+    use fedimint_core::Amount;
+    use fedimint_dummy_common::{DummyInput, DummyOutput};
+    use rand::thread_rng;
+
+    use super::{module_in_maintenance, modules_in_maintenance, set_module_maintenance};
+
+    fn transaction(
+        input_modules: &[ModuleInstanceId],
+        output_modules: &[ModuleInstanceId],
+    ) -> Transaction {
+        let account = KeyPair::new(SECP256K1, &mut thread_rng()).public_key();
+
+        Transaction {
+            inputs: input_modules
+                .iter()
+                .map(|module_instance_id| {
+                    DynInput::from_typed(
+                        *module_instance_id,
+                        DummyInput {
+                            amount: Amount::ZERO,
+                            account,
+                        },
+                    )
+                })
+                .collect(),
+            outputs: output_modules
+                .iter()
+                .map(|module_instance_id| {
+                    DynOutput::from_typed(
+                        *module_instance_id,
+                        DummyOutput {
+                            amount: Amount::ZERO,
+                            account,
+                        },
+                    )
+                })
+                .collect(),
+            nonce: [0x42; 8],
+            signatures: TransactionSignature::NaiveMultisig(vec![]),
+        }
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_can_be_enabled_and_disabled() {
+        let db = MemDatabase::new().into_database();
+
+        let mut dbtx = db.begin_transaction().await;
+        set_module_maintenance(&mut dbtx.to_ref_nc(), 0, true).await;
+        set_module_maintenance(&mut dbtx.to_ref_nc(), 2, true).await;
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            modules_in_maintenance(&mut db.begin_transaction_nc().await).await,
+            BTreeSet::from([0, 2])
+        );
+
+        let mut dbtx = db.begin_transaction().await;
+        set_module_maintenance(&mut dbtx.to_ref_nc(), 0, false).await;
+        // disabling maintenance mode of a module that isn't in it is a no-op
+        set_module_maintenance(&mut dbtx.to_ref_nc(), 1, false).await;
+        dbtx.commit_tx().await;
+
+        assert_eq!(
+            modules_in_maintenance(&mut db.begin_transaction_nc().await).await,
+            BTreeSet::from([2])
+        );
+    }
+
+    #[test]
+    fn transactions_using_modules_in_maintenance_are_detected() {
+        let in_maintenance = BTreeSet::from([1]);
+
+        assert_eq!(
+            module_in_maintenance(&transaction(&[1], &[0]), &in_maintenance),
+            Some(1)
+        );
+        assert_eq!(
+            module_in_maintenance(&transaction(&[0], &[0, 1]), &in_maintenance),
+            Some(1)
+        );
+        assert_eq!(
+            module_in_maintenance(&transaction(&[0, 2], &[0]), &in_maintenance),
+            None
+        );
+        assert_eq!(
+            module_in_maintenance(&transaction(&[1], &[1]), &BTreeSet::new()),
+            None
+        );
+    }
+}
@@ -2,18 +2,27 @@
 
 pub mod aleph_bft;
 pub mod api;
+pub mod api_tokens;
+pub mod checkpoint;
 pub mod db;
 pub mod debug;
 pub mod engine;
+pub mod features;
+pub mod maintenance;
+pub mod module_addition;
+pub mod restore;
+pub mod retention;
 pub mod transaction;
+pub mod upgrade;
 
 use std::collections::BTreeMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::bail;
 use async_channel::Sender;
-use db::{get_global_database_migrations, GLOBAL_DATABASE_VERSION};
+use db::{get_global_database_migrations, server_db_key_spaces, GLOBAL_DATABASE_VERSION};
 use fedimint_api_client::api::DynGlobalApi;
 use fedimint_core::config::ServerModuleInitRegistry;
 use fedimint_core::core::{ModuleInstanceId, ModuleKind};
@@ -26,7 +35,7 @@ use fedimint_core::task::TaskGroup;
 use fedimint_core::NumPeers;
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE};
 use jsonrpsee::server::ServerHandle;
-use tokio::sync::watch;
+use tokio::sync::{watch, Notify};
 use tracing::info;
 use tracing::log::warn;
 
@@ -34,6 +43,10 @@ use crate::config::{ServerConfig, ServerConfigLocal};
 use crate::consensus::aleph_bft::keychain::Keychain;
 use crate::consensus::api::ConsensusApi;
 use crate::consensus::engine::ConsensusEngine;
+use crate::consensus::features::submit_module_feature_signals;
+use crate::consensus::module_addition::submit_module_addition_votes;
+use crate::consensus::restore::verify_restored_checkpoint;
+use crate::consensus::upgrade::{ensure_no_due_upgrade, submit_upgrade_votes};
 use crate::net;
 use crate::net::api::{ApiSecrets, RpcHandlerCtx};
 
@@ -46,9 +59,26 @@ pub async fn run(
     module_init_registry: ServerModuleInitRegistry,
     task_group: &TaskGroup,
     force_api_secrets: ApiSecrets,
+    socks5_proxy: Option<SocketAddr>,
+    code_version: String,
 ) -> anyhow::Result<()> {
+    // Consensus must not continue past the halt session of a scheduled upgrade
+    // until we run its version
+    ensure_no_due_upgrade(&db, &code_version).await?;
+
+    // A restored guardian may already have the session its bundle references,
+    // e.g. if a consensus checkpoint was imported as well
+    verify_restored_checkpoint(&db).await?;
+
+    // Tasks of the modules are shut down when consensus stops, since consensus is
+    // restarted with a new config after module additions
+    let task_group = task_group.make_subgroup();
+
     cfg.validate_config(&cfg.local.identity, &module_init_registry)?;
 
+    // Records of conflicting prefixes would silently overwrite each other
+    server_db_key_spaces(&cfg, &module_init_registry).ensure_no_conflicts()?;
+
     apply_migrations_server(
         &db,
         "fedimint-server".to_string(),
@@ -78,7 +108,7 @@ pub async fn run(
                         NumPeers::from(cfg.consensus.api_endpoints.len()),
                         cfg.get_module_config(*module_id)?,
                         db.with_prefix_module_id(*module_id),
-                        task_group,
+                        &task_group,
                         cfg.local.identity,
                     )
                     .await?;
@@ -96,7 +126,9 @@ pub async fn run(
     let (submission_sender, submission_receiver) = async_channel::bounded(TRANSACTION_BUFFER);
     let (shutdown_sender, shutdown_receiver) = watch::channel(None);
     let connection_status_channels = Default::default();
+    let connection_stats = Default::default();
     let last_ci_by_peer = Default::default();
+    let last_ci_time_by_peer = Default::default();
 
     let consensus_api = ConsensusApi {
         cfg: cfg.clone(),
@@ -105,13 +137,17 @@ pub async fn run(
         client_cfg: client_cfg.clone(),
         submission_sender: submission_sender.clone(),
         shutdown_sender,
+        module_init_registry: module_init_registry.clone(),
         supported_api_versions: ServerConfig::supported_api_versions_summary(
             &cfg.consensus.modules,
             &module_init_registry,
         ),
         last_ci_by_peer: Arc::clone(&last_ci_by_peer),
+        last_ci_time_by_peer: Arc::clone(&last_ci_time_by_peer),
         connection_status_channels: Arc::clone(&connection_status_channels),
+        connection_stats: Arc::clone(&connection_stats),
         force_api_secret: force_api_secrets.get_active(),
+        code_version: code_version.clone(),
     };
 
     info!(target: LOG_CONSENSUS, "Starting Consensus Api");
@@ -121,33 +157,72 @@ pub async fn run(
 
     info!(target: LOG_CONSENSUS, "Starting Submission of Module CI proposals");
 
+    let module_proposal_wakeups = module_registry
+        .iter_modules()
+        .map(|(module_id, _, _)| (module_id, Arc::new(Notify::new())))
+        .collect::<BTreeMap<_, _>>();
+
     for (module_id, kind, module) in module_registry.iter_modules() {
         submit_module_ci_proposals(
-            task_group,
+            &task_group,
             db.clone(),
             module_id,
             kind.clone(),
             module.clone(),
             submission_sender.clone(),
+            Arc::clone(&module_proposal_wakeups[&module_id]),
         );
     }
 
+    submit_module_addition_votes(
+        &task_group,
+        db.clone(),
+        cfg.local.identity,
+        submission_sender.clone(),
+    );
+
+    submit_upgrade_votes(
+        &task_group,
+        db.clone(),
+        cfg.local.identity,
+        submission_sender.clone(),
+    );
+
+    submit_module_feature_signals(
+        &task_group,
+        db.clone(),
+        cfg.local.identity,
+        module_registry.clone(),
+        submission_sender.clone(),
+    );
+
     info!(target: LOG_CONSENSUS, "Starting Consensus Engine");
 
     ConsensusEngine {
         db,
         keychain: Keychain::new(&cfg),
-        federation_api: DynGlobalApi::from_config(&client_cfg, &force_api_secrets.get_active()),
+        federation_api: DynGlobalApi::from_config_with_proxy(
+            &client_cfg,
+            &force_api_secrets.get_active(),
+            socks5_proxy,
+        ),
         self_id_str: cfg.local.identity.to_string(),
         peer_id_str: (0..cfg.consensus.api_endpoints.len())
             .map(|x| x.to_string())
             .collect(),
         cfg: cfg.clone(),
         connection_status_channels,
+        connection_stats,
+        socks5_proxy,
+        code_version,
         submission_receiver,
         shutdown_receiver,
         last_ci_by_peer,
+        last_ci_time_by_peer,
+        transaction_rejections: Default::default(),
+        module_proposal_wakeups,
         modules: module_registry,
+        module_init_registry,
         task_group: task_group.clone(),
     }
     .run()
@@ -159,6 +234,10 @@ pub async fn run(
 
     api_handler.stopped().await;
 
+    if let Err(e) = task_group.shutdown_join_all(Duration::from_secs(10)).await {
+        warn!(target: LOG_CONSENSUS, "Failed to shut down consensus tasks: {e:?}");
+    }
+
     Ok(())
 }
 
@@ -187,6 +266,16 @@ async fn start_consensus_api(
 
 const CONSENSUS_PROPOSAL_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// Minimum time between two consensus proposals of a module, so that a busy
+/// federation waking up the proposal task with every processed item doesn't
+/// make it propose the same items over and over
+const MIN_CONSENSUS_PROPOSAL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Periodically submits the consensus proposal of a module. The proposal is
+/// also submitted right away whenever `wakeup` is notified, i.e. after an item
+/// changing the module's state was processed, so that items the module creates
+/// while processing, like the peg-out signatures of the wallet module, are
+/// shared with our peers in the next round instead of after several rounds.
 fn submit_module_ci_proposals(
     task_group: &TaskGroup,
     db: Database,
@@ -194,6 +283,7 @@ fn submit_module_ci_proposals(
     kind: ModuleKind,
     module: DynServerModule,
     submission_sender: Sender<ConsensusItem>,
+    wakeup: Arc<Notify>,
 ) {
     let mut interval = tokio::time::interval(if is_running_in_test_env() {
         Duration::from_millis(100)
@@ -235,7 +325,12 @@ fn submit_module_ci_proposals(
                     }
                 }
 
-                interval.tick().await;
+                tokio::time::sleep(MIN_CONSENSUS_PROPOSAL_INTERVAL).await;
+
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    () = wakeup.notified() => {}
+                }
             }
         },
     );
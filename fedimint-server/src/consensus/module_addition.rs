@@ -0,0 +1,674 @@
+//! Adding module instances to a running federation
+//!
+//! Every guardian submits a [`ModuleAdditionProposal`] via the admin API, which
+//! is broadcast as a [`ConsensusItem::ModuleAddition`] vote. Once a threshold
+//! of guardians voted for the same proposal it is approved. After the session
+//! before its activation session is completed the guardians stop consensus,
+//! run the distributed key generation for the new module only, write the new
+//! config and restart consensus with the module included.
+//!
+//! Guardians that didn't vote for an approved addition can still provide the
+//! local part of the module's config gen params via the admin API before the
+//! activation session. Otherwise they fall back to their default params for
+//! the module kind, the same ones used during the initial config generation.
+//!
+//! Votes for module kinds we don't support or with invalid consensus params
+//! are rejected. If the distributed key generation of an approved addition
+//! still fails or doesn't complete within [`ADDED_MODULE_DKG_TIMEOUT`], e.g.
+//! since a guardian is offline, the addition is aborted and consensus
+//! continues without the module. The key generation needs all guardians, so
+//! it fails for all of them alike.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context};
+use async_channel::Sender;
+use fedimint_core::config::{
+    ConfigGenModuleParams, ServerModuleConfig, ServerModuleConfigGenParamsRegistry,
+    ServerModuleConsensusConfig, ServerModuleInitRegistry,
+};
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::envs::is_running_in_test_env;
+use fedimint_core::epoch::{ConsensusItem, ModuleAdditionProposal};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::{error, info, warn};
+
+use crate::config::io::update_server_config;
+use crate::config::ServerConfig;
+use crate::consensus::db::{
+    ModuleAdditionKey, ModuleAdditionPrefix, ModuleAdditionVoteKey, ModuleAdditionVotePrefix,
+    PendingModuleAddition, PendingModuleAdditionKey, PendingModuleAdditionPrefix,
+};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Minimum number of sessions between the session a module addition is voted
+/// for in and its activation session, which gives the other guardians time to
+/// cast their votes
+pub const MIN_ACTIVATION_DELAY_SESSIONS: u64 = 2;
+
+/// Time the guardians wait for the distributed key generation of an added
+/// module to complete before they abort the addition
+pub const ADDED_MODULE_DKG_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Records the vote of `peer` and approves the proposal once a threshold of
+/// guardians voted for it. A new vote of a guardian replaces its previous one.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_module_addition_vote(
+    dbtx: &mut DatabaseTransaction<'_>,
+    modules: &BTreeMap<ModuleInstanceId, ServerModuleConsensusConfig>,
+    registry: &ServerModuleInitRegistry,
+    threshold: usize,
+    session_index: u64,
+    proposal: ModuleAdditionProposal,
+    peer: PeerId,
+) -> anyhow::Result<()> {
+    let module_instance_id = proposal.module_instance_id;
+
+    ensure!(
+        !modules.contains_key(&module_instance_id),
+        "Module instance {module_instance_id} already exists"
+    );
+    ensure!(
+        dbtx.get_value(&ModuleAdditionKey(module_instance_id))
+            .await
+            .is_none(),
+        "Addition of module instance {module_instance_id} is already approved"
+    );
+    ensure!(
+        session_index + MIN_ACTIVATION_DELAY_SESSIONS <= proposal.activation_session,
+        "Activation session {} is too close to the current session {session_index}",
+        proposal.activation_session
+    );
+    let consensus_params = serde_json::from_str::<serde_json::Value>(&proposal.consensus_params)
+        .context("Consensus params are not valid JSON")?;
+    registry
+        .get(&proposal.kind)
+        .with_context(|| format!("Module kind {} is not supported", proposal.kind))?
+        .validate_consensus_params(&consensus_params)
+        .with_context(|| {
+            format!(
+                "Consensus params are invalid for module kind {}",
+                proposal.kind
+            )
+        })?;
+
+    if dbtx.get_value(&ModuleAdditionVoteKey(peer)).await.as_ref() == Some(&proposal) {
+        bail!("Already voted for this module addition");
+    }
+
+    dbtx.insert_entry(&ModuleAdditionVoteKey(peer), &proposal)
+        .await;
+
+    let votes = dbtx
+        .find_by_prefix(&ModuleAdditionVotePrefix)
+        .await
+        .map(|(key, vote)| (key.0, vote))
+        .collect::<Vec<_>>()
+        .await;
+
+    if votes.iter().filter(|(_, vote)| *vote == proposal).count() < threshold {
+        return Ok(());
+    }
+
+    info!(
+        target: LOG_CONSENSUS,
+        %module_instance_id,
+        kind = %proposal.kind,
+        activation_session = proposal.activation_session,
+        "Module addition approved"
+    );
+
+    for (peer, vote) in votes {
+        if vote.module_instance_id == module_instance_id {
+            dbtx.remove_entry(&ModuleAdditionVoteKey(peer)).await;
+        }
+    }
+
+    dbtx.insert_new_entry(&ModuleAdditionKey(module_instance_id), &proposal)
+        .await;
+
+    Ok(())
+}
+
+/// Returns the approved module additions that are not part of `modules` yet,
+/// although all sessions before their activation session are completed
+pub async fn due_module_additions(
+    db: &Database,
+    modules: &BTreeMap<ModuleInstanceId, ServerModuleConsensusConfig>,
+) -> Vec<ModuleAdditionProposal> {
+    let mut dbtx = db.begin_transaction_nc().await;
+
+    let session_count = get_finished_session_count_static(&mut dbtx).await;
+
+    dbtx.find_by_prefix(&ModuleAdditionPrefix)
+        .await
+        .map(|(_, proposal)| proposal)
+        .filter(|proposal| {
+            std::future::ready(
+                proposal.activation_session <= session_count
+                    && !modules.contains_key(&proposal.module_instance_id),
+            )
+        })
+        .collect()
+        .await
+}
+
+/// Periodically submits our votes for the module additions we proposed until
+/// they are approved, since a vote can be discarded if it is submitted while
+/// a session is being completed
+pub fn submit_module_addition_votes(
+    task_group: &TaskGroup,
+    db: Database,
+    our_id: PeerId,
+    submission_sender: Sender<ConsensusItem>,
+) {
+    let mut interval = tokio::time::interval(if is_running_in_test_env() {
+        Duration::from_millis(100)
+    } else {
+        Duration::from_secs(1)
+    });
+
+    task_group.spawn(
+        "submit_module_addition_votes",
+        move |task_handle| async move {
+            while !task_handle.is_shutting_down() {
+                let mut dbtx = db.begin_transaction_nc().await;
+
+                let session_count = get_finished_session_count_static(&mut dbtx).await;
+                let our_vote = dbtx.get_value(&ModuleAdditionVoteKey(our_id)).await;
+
+                let pending = dbtx
+                    .find_by_prefix(&PendingModuleAdditionPrefix)
+                    .await
+                    .map(|(_, pending)| pending.proposal)
+                    .collect::<Vec<_>>()
+                    .await;
+
+                for proposal in pending {
+                    if our_vote.as_ref() == Some(&proposal)
+                        || proposal.activation_session
+                            < session_count + MIN_ACTIVATION_DELAY_SESSIONS
+                        || dbtx
+                            .get_value(&ModuleAdditionKey(proposal.module_instance_id))
+                            .await
+                            .is_some()
+                    {
+                        continue;
+                    }
+
+                    submission_sender
+                        .send(ConsensusItem::ModuleAddition(proposal))
+                        .await
+                        .ok();
+                }
+
+                interval.tick().await;
+            }
+        },
+    );
+}
+
+/// Returns the local part of the config gen params of an added module: the
+/// local params we provided via the admin API or, if we didn't, the local
+/// params of the first module of the same kind in `default_params`
+fn added_module_local_params(
+    module_instance_id: ModuleInstanceId,
+    kind: &ModuleKind,
+    pending: Option<PendingModuleAddition>,
+    default_params: &ServerModuleConfigGenParamsRegistry,
+) -> anyhow::Result<serde_json::Value> {
+    if let Some(pending) = pending {
+        return serde_json::from_str(&pending.local_params)
+            .context("Local params of added module are not valid JSON");
+    }
+
+    let (_, _, default) = default_params
+        .iter_modules()
+        .find(|(_, default_kind, _)| *default_kind == kind)
+        .with_context(|| {
+            format!(
+                "No local params for added module instance {module_instance_id} of kind {kind}, \
+                 we did not vote for it and have no default params for its kind"
+            )
+        })?;
+
+    warn!(
+        target: LOG_CONSENSUS,
+        %module_instance_id,
+        %kind,
+        "No local params for added module, we did not vote for it, using the default params"
+    );
+
+    Ok(default.local.clone())
+}
+
+/// Drops an approved module addition whose config could not be generated, so
+/// that it is not due anymore and consensus continues without the module
+async fn abort_module_addition(
+    db: &Database,
+    module_instance_id: ModuleInstanceId,
+) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.remove_entry(&ModuleAdditionKey(module_instance_id))
+        .await;
+    dbtx.remove_entry(&PendingModuleAdditionKey(module_instance_id))
+        .await;
+    dbtx.commit_tx_result().await
+}
+
+/// Generates the config of an added module with the other guardians
+async fn generate_added_module_config(
+    cfg: &ServerConfig,
+    db: &Database,
+    registry: &ServerModuleInitRegistry,
+    default_params: &ServerModuleConfigGenParamsRegistry,
+    socks5_proxy: Option<SocketAddr>,
+    task_group: &TaskGroup,
+    proposal: &ModuleAdditionProposal,
+) -> anyhow::Result<ServerModuleConfig> {
+    let module_instance_id = proposal.module_instance_id;
+
+    let module_init = registry.get(&proposal.kind).with_context(|| {
+        format!(
+            "Module kind {} of added module instance {module_instance_id} is not supported",
+            proposal.kind
+        )
+    })?;
+
+    let pending = db
+        .begin_transaction_nc()
+        .await
+        .get_value(&PendingModuleAdditionKey(module_instance_id))
+        .await;
+    let local_params =
+        added_module_local_params(module_instance_id, &proposal.kind, pending, default_params)?;
+
+    let params = ConfigGenModuleParams::new(
+        local_params,
+        serde_json::from_str(&proposal.consensus_params)?,
+    );
+    module_init.validate_params(&params).with_context(|| {
+        format!("Config gen params of added module instance {module_instance_id} are invalid")
+    })?;
+
+    let mut dkg_task_group = task_group.make_subgroup();
+    let module_cfg = tokio::time::timeout(
+        ADDED_MODULE_DKG_TIMEOUT,
+        cfg.distributed_gen_module(
+            module_init,
+            module_instance_id,
+            &params,
+            socks5_proxy,
+            &mut dkg_task_group,
+        ),
+    )
+    .await;
+    dkg_task_group.shutdown_join_all(None).await?;
+
+    module_cfg
+        .context("Distributed key generation of added module timed out")?
+        .context("Distributed key generation of added module failed")
+}
+
+/// Generates the configs of all due module additions and writes the updated
+/// config to `data_dir`. Returns the updated config, or `None` if no module
+/// addition is due or all due additions were aborted.
+#[allow(clippy::too_many_arguments)]
+pub async fn activate_module_additions(
+    data_dir: &Path,
+    cfg: &ServerConfig,
+    db: &Database,
+    registry: &ServerModuleInitRegistry,
+    default_params: &ServerModuleConfigGenParamsRegistry,
+    api_secret: Option<String>,
+    socks5_proxy: Option<SocketAddr>,
+    task_group: &TaskGroup,
+) -> anyhow::Result<Option<ServerConfig>> {
+    let due = due_module_additions(db, &cfg.consensus.modules).await;
+
+    if due.is_empty() {
+        return Ok(None);
+    }
+
+    let mut module_cfgs = BTreeMap::new();
+
+    for proposal in due {
+        let module_instance_id = proposal.module_instance_id;
+
+        info!(
+            target: LOG_CONSENSUS,
+            %module_instance_id,
+            kind = %proposal.kind,
+            "Generating config of added module"
+        );
+
+        match generate_added_module_config(
+            cfg,
+            db,
+            registry,
+            default_params,
+            socks5_proxy,
+            task_group,
+            &proposal,
+        )
+        .await
+        {
+            Ok(module_cfg) => {
+                module_cfgs.insert(module_instance_id, module_cfg);
+            }
+            Err(err) => {
+                error!(
+                    target: LOG_CONSENSUS,
+                    %module_instance_id,
+                    err = %format!("{err:#}"),
+                    "Aborting module addition"
+                );
+                abort_module_addition(db, module_instance_id).await?;
+            }
+        }
+    }
+
+    if module_cfgs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut cfg = cfg.clone();
+    cfg.add_modules(module_cfgs);
+    cfg.validate_config(&cfg.local.identity, registry)?;
+
+    update_server_config(
+        &cfg,
+        data_dir,
+        &cfg.private.api_auth.0,
+        registry,
+        api_secret,
+    )?;
+
+    let mut dbtx = db.begin_transaction().await;
+    for module_instance_id in cfg.consensus.modules.keys() {
+        dbtx.remove_entry(&PendingModuleAdditionKey(*module_instance_id))
+            .await;
+    }
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_CONSENSUS, "Activated module additions");
+
+    Ok(Some(cfg))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::config::{
+        ConfigGenModuleParams, ServerModuleConfigGenParamsRegistry, ServerModuleConsensusConfig,
+        ServerModuleInitRegistry,
+    };
+    use fedimint_core::core::ModuleKind;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::epoch::ModuleAdditionProposal;
+    use fedimint_core::module::ModuleConsensusVersion;
+    use fedimint_core::session_outcome::{SessionOutcome, SignedSessionOutcome};
+    use fedimint_core::PeerId;
+    use fedimint_dummy_server::DummyInit;
+    use serde_json::json;
+
+    use super::{
+        abort_module_addition, added_module_local_params, due_module_additions,
+        process_module_addition_vote, MIN_ACTIVATION_DELAY_SESSIONS,
+    };
+    use crate::consensus::db::{
+        ModuleAdditionKey, ModuleAdditionVoteKey, PendingModuleAddition, PendingModuleAdditionKey,
+        SignedSessionOutcomeKey,
+    };
+
+    const THRESHOLD: usize = 3;
+
+    fn dummy_kind() -> ModuleKind {
+        ModuleKind::from_static_str("dummy")
+    }
+
+    fn proposal(activation_session: u64) -> ModuleAdditionProposal {
+        ModuleAdditionProposal {
+            module_instance_id: 3,
+            kind: dummy_kind(),
+            consensus_params: json!({ "tx_fee": 0 }).to_string(),
+            activation_session,
+        }
+    }
+
+    async fn vote(
+        db: &Database,
+        modules: &BTreeMap<u16, ServerModuleConsensusConfig>,
+        session_index: u64,
+        proposal: ModuleAdditionProposal,
+        peer: u16,
+    ) -> anyhow::Result<()> {
+        let mut dbtx = db.begin_transaction().await;
+        let mut registry = ServerModuleInitRegistry::new();
+        registry.attach(DummyInit);
+
+        process_module_addition_vote(
+            &mut dbtx.to_ref_nc(),
+            modules,
+            &registry,
+            THRESHOLD,
+            session_index,
+            proposal,
+            PeerId::from(peer),
+        )
+        .await?;
+        dbtx.commit_tx().await;
+
+        Ok(())
+    }
+
+    async fn approved(db: &Database) -> Option<ModuleAdditionProposal> {
+        db.begin_transaction_nc()
+            .await
+            .get_value(&ModuleAdditionKey(3))
+            .await
+    }
+
+    async fn complete_sessions(db: &Database, session_count: u64) {
+        let mut dbtx = db.begin_transaction().await;
+        for session_index in 0..session_count {
+            dbtx.insert_entry(
+                &SignedSessionOutcomeKey(session_index),
+                &SignedSessionOutcome {
+                    session_outcome: SessionOutcome { items: vec![] },
+                    signatures: BTreeMap::new(),
+                },
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test]
+    async fn module_addition_is_approved_by_threshold_of_equal_votes() {
+        let db = MemDatabase::new().into_database();
+        let modules = BTreeMap::new();
+
+        vote(&db, &modules, 0, proposal(10), 0).await.unwrap();
+        vote(&db, &modules, 0, proposal(10), 1).await.unwrap();
+        // votes for a different activation session don't count towards the
+        // approval of the proposal
+        vote(&db, &modules, 0, proposal(11), 2).await.unwrap();
+        assert!(vote(&db, &modules, 0, proposal(10), 1).await.is_err());
+        assert_eq!(approved(&db).await, None);
+
+        // a guardian can change its vote
+        vote(&db, &modules, 0, proposal(10), 2).await.unwrap();
+        assert_eq!(approved(&db).await, Some(proposal(10)));
+
+        // the votes are cleared once the addition is approved
+        let mut dbtx = db.begin_transaction_nc().await;
+        for peer in 0..3 {
+            assert_eq!(
+                dbtx.get_value(&ModuleAdditionVoteKey(PeerId::from(peer)))
+                    .await,
+                None
+            );
+        }
+
+        assert!(vote(&db, &modules, 1, proposal(10), 3).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_module_addition_votes_are_rejected() {
+        let db = MemDatabase::new().into_database();
+
+        let too_early = proposal(5 + MIN_ACTIVATION_DELAY_SESSIONS - 1);
+        assert!(vote(&db, &BTreeMap::new(), 5, too_early, 0).await.is_err());
+
+        let invalid_params = ModuleAdditionProposal {
+            consensus_params: "{".to_string(),
+            ..proposal(10)
+        };
+        assert!(vote(&db, &BTreeMap::new(), 5, invalid_params, 0)
+            .await
+            .is_err());
+
+        let unknown_kind = ModuleAdditionProposal {
+            kind: ModuleKind::from_static_str("unknown"),
+            ..proposal(10)
+        };
+        assert!(vote(&db, &BTreeMap::new(), 5, unknown_kind, 0)
+            .await
+            .is_err());
+
+        let invalid_consensus_params = ModuleAdditionProposal {
+            consensus_params: json!({ "tx_fee": "free" }).to_string(),
+            ..proposal(10)
+        };
+        assert!(vote(&db, &BTreeMap::new(), 5, invalid_consensus_params, 0)
+            .await
+            .is_err());
+
+        let modules = BTreeMap::from([(
+            3,
+            ServerModuleConsensusConfig {
+                kind: dummy_kind(),
+                version: ModuleConsensusVersion::new(0, 0),
+                config: vec![],
+            },
+        )]);
+        assert!(vote(&db, &modules, 5, proposal(10), 0).await.is_err());
+
+        vote(
+            &db,
+            &BTreeMap::new(),
+            5,
+            proposal(5 + MIN_ACTIVATION_DELAY_SESSIONS),
+            0,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn module_addition_is_due_once_session_before_activation_completed() {
+        let db = MemDatabase::new().into_database();
+        let modules = BTreeMap::new();
+
+        for peer in 0..3 {
+            vote(&db, &modules, 0, proposal(4), peer).await.unwrap();
+        }
+
+        complete_sessions(&db, 3).await;
+        assert!(due_module_additions(&db, &modules).await.is_empty());
+
+        complete_sessions(&db, 4).await;
+        assert_eq!(due_module_additions(&db, &modules).await, vec![proposal(4)]);
+
+        // not due anymore once the module is part of the config
+        let modules = BTreeMap::from([(
+            3,
+            ServerModuleConsensusConfig {
+                kind: dummy_kind(),
+                version: ModuleConsensusVersion::new(0, 0),
+                config: vec![],
+            },
+        )]);
+        assert!(due_module_additions(&db, &modules).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn aborted_module_addition_is_not_due_anymore() {
+        let db = MemDatabase::new().into_database();
+        let modules = BTreeMap::new();
+
+        for peer in 0..3 {
+            vote(&db, &modules, 0, proposal(4), peer).await.unwrap();
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PendingModuleAdditionKey(3),
+            &PendingModuleAddition {
+                proposal: proposal(4),
+                local_params: json!({}).to_string(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        complete_sessions(&db, 4).await;
+        assert_eq!(due_module_additions(&db, &modules).await, vec![proposal(4)]);
+
+        abort_module_addition(&db, 3).await.unwrap();
+
+        assert!(due_module_additions(&db, &modules).await.is_empty());
+        assert_eq!(approved(&db).await, None);
+        assert!(db
+            .begin_transaction_nc()
+            .await
+            .get_value(&PendingModuleAdditionKey(3))
+            .await
+            .is_none());
+    }
+
+    #[test]
+    fn local_params_of_added_module_are_derived_deterministically() {
+        let mut default_params = ServerModuleConfigGenParamsRegistry::default();
+        default_params.register_module(
+            0,
+            dummy_kind(),
+            ConfigGenModuleParams::new(json!({ "default": true }), json!({})),
+        );
+
+        let pending = PendingModuleAddition {
+            proposal: proposal(10),
+            local_params: json!({ "default": false }).to_string(),
+        };
+        assert_eq!(
+            added_module_local_params(3, &dummy_kind(), Some(pending), &default_params).unwrap(),
+            json!({ "default": false })
+        );
+
+        // guardians that didn't vote use their default params for the kind
+        for _ in 0..2 {
+            assert_eq!(
+                added_module_local_params(3, &dummy_kind(), None, &default_params).unwrap(),
+                json!({ "default": true })
+            );
+        }
+
+        assert!(added_module_local_params(
+            3,
+            &ModuleKind::from_static_str("unknown"),
+            None,
+            &default_params
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Verification of a guardian restored from a disaster-recovery bundle
+//!
+//! The bundle references the last session the guardian had finished when it
+//! was created. A restored guardian starts with an empty database and
+//! downloads the signed outcomes of the sessions it is missing from its peers.
+//! Once it reaches the referenced session, the header of the outcome signed by
+//! the federation has to match the one recorded in the bundle, otherwise the
+//! bundle was created by a guardian of a different federation or history and
+//! consensus is stopped.
+
+use anyhow::ensure;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_logging::LOG_CONSENSUS;
+use tracing::info;
+
+use crate::config::io::SessionCheckpoint;
+use crate::consensus::db::{
+    PrunedSessionOutcomeKey, RestoredSessionCheckpointKey, SignedSessionOutcomeKey,
+};
+
+/// Records the checkpoint of a restored bundle, which is verified by
+/// [`verify_restored_checkpoint`] once the guardian caught up to it
+pub async fn record_restored_checkpoint(db: &Database, checkpoint: SessionCheckpoint) {
+    let mut dbtx = db.begin_transaction().await;
+
+    dbtx.insert_entry(&RestoredSessionCheckpointKey, &checkpoint)
+        .await;
+
+    dbtx.commit_tx().await;
+}
+
+/// Compares the recorded checkpoint with the header of the session signed by
+/// the federation and removes it once it matched. Does nothing while no
+/// checkpoint is recorded or the session has not been finished yet.
+pub async fn verify_restored_checkpoint(db: &Database) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let Some(checkpoint) = dbtx.get_value(&RestoredSessionCheckpointKey).await else {
+        return Ok(());
+    };
+
+    let session_outcome = match dbtx
+        .get_value(&SignedSessionOutcomeKey(checkpoint.session_index))
+        .await
+    {
+        Some(signed_session_outcome) => signed_session_outcome.session_outcome,
+        None => match dbtx
+            .get_value(&PrunedSessionOutcomeKey(checkpoint.session_index))
+            .await
+        {
+            Some(session_outcome) => session_outcome,
+            None => return Ok(()),
+        },
+    };
+
+    let header_hash = sha256::Hash::hash(&session_outcome.header(checkpoint.session_index));
+
+    ensure!(
+        header_hash == checkpoint.header_hash,
+        "Session {} signed by the federation does not match the restored bundle",
+        checkpoint.session_index
+    );
+
+    dbtx.remove_entry(&RestoredSessionCheckpointKey).await;
+
+    dbtx.commit_tx().await;
+
+    info!(
+        target: LOG_CONSENSUS,
+        session_index = checkpoint.session_index,
+        "Verified the restored bundle against the federation"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::session_outcome::{SessionOutcome, SignedSessionOutcome};
+
+    use super::{record_restored_checkpoint, verify_restored_checkpoint};
+    use crate::config::io::SessionCheckpoint;
+    use crate::consensus::db::{RestoredSessionCheckpointKey, SignedSessionOutcomeKey};
+
+    async fn finish_empty_session(db: &Database, session_index: u64) {
+        let session_outcome = SessionOutcome { items: vec![] };
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SignedSessionOutcomeKey(session_index),
+            &SignedSessionOutcome {
+                session_outcome,
+                signatures: BTreeMap::new(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+    }
+
+    async fn has_checkpoint(db: &Database) -> bool {
+        db.begin_transaction_nc()
+            .await
+            .get_value(&RestoredSessionCheckpointKey)
+            .await
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn checkpoint_is_verified_once_session_is_finished() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let header_hash = sha256::Hash::hash(&SessionOutcome { items: vec![] }.header(1));
+
+        record_restored_checkpoint(
+            &db,
+            SessionCheckpoint {
+                session_index: 1,
+                header_hash,
+            },
+        )
+        .await;
+
+        verify_restored_checkpoint(&db).await.unwrap();
+        assert!(has_checkpoint(&db).await, "Session 1 is not finished yet");
+
+        finish_empty_session(&db, 1).await;
+
+        verify_restored_checkpoint(&db).await.unwrap();
+        assert!(!has_checkpoint(&db).await);
+    }
+
+    #[tokio::test]
+    async fn mismatching_checkpoint_is_rejected() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+
+        record_restored_checkpoint(
+            &db,
+            SessionCheckpoint {
+                session_index: 0,
+                header_hash: sha256::Hash::hash(b"other federation"),
+            },
+        )
+        .await;
+
+        finish_empty_session(&db, 0).await;
+
+        assert!(verify_restored_checkpoint(&db).await.is_err());
+        assert!(has_checkpoint(&db).await);
+    }
+}
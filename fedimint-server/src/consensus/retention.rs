@@ -0,0 +1,353 @@
+//! Pruning of historical sessions
+//!
+//! The signed outcomes of finished sessions make up most of the consensus data
+//! of a long-running federation. Guardians can configure a
+//! [`SessionRetention`] via the admin API, after which the outcomes of sessions
+//! older than the most recent `retained_sessions` are pruned once a session
+//! is completed.
+//!
+//! Client recovery replays the transactions of every session since the
+//! client's last backup, so pruning only drops what recovery doesn't read: the
+//! signatures of the session outcome and all consensus items that are not
+//! transactions. The transactions of a pruned session are kept under
+//! [`PrunedSessionOutcomeKey`]. The session status contains the full outcome
+//! of retained sessions and only the transactions of pruned ones, so guardians
+//! with different retentions can disagree on it. Recovering clients therefore
+//! fetch the session transactions instead, which only contain transactions for
+//! every completed session. Accepted transactions, client
+//! backups and the module state, which audits are computed from, are kept as
+//! well. Lagging guardians that need the signed outcome of a pruned session
+//! have to fetch it from a guardian that still retains it or be bootstrapped
+//! from a consensus checkpoint.
+
+use anyhow::ensure;
+use fedimint_core::admin_client::SessionRetention;
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::epoch::ConsensusItem;
+use fedimint_core::session_outcome::SessionOutcome;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::consensus::db::{
+    PrunedSessionOutcomeKey, SessionRetentionKey, SignedSessionOutcomeKey,
+    SignedSessionOutcomePrefix,
+};
+
+/// Minimum number of sessions a guardian has to retain, so that lagging peers
+/// and clients that were offline for a while can still catch up
+pub const MIN_RETAINED_SESSIONS: u64 = 1000;
+
+/// Maximum number of sessions pruned after a single session, so that enabling
+/// the retention on a long-running federation doesn't stall consensus
+const MAX_PRUNED_SESSIONS_PER_SESSION: usize = 100;
+
+pub async fn get_session_retention(db: &Database) -> SessionRetention {
+    db.begin_transaction_nc()
+        .await
+        .get_value(&SessionRetentionKey)
+        .await
+        .unwrap_or_default()
+}
+
+pub async fn set_session_retention(
+    db: &Database,
+    retention: SessionRetention,
+) -> anyhow::Result<()> {
+    if let Some(retained_sessions) = retention.retained_sessions {
+        ensure!(
+            MIN_RETAINED_SESSIONS <= retained_sessions,
+            "At least {MIN_RETAINED_SESSIONS} sessions have to be retained"
+        );
+    }
+
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.insert_entry(&SessionRetentionKey, &retention).await;
+    dbtx.commit_tx_result().await?;
+
+    info!(target: LOG_CONSENSUS, ?retention.retained_sessions, "Updated session retention");
+
+    Ok(())
+}
+
+/// Returns the index of the oldest session whose signed outcome was not pruned
+pub async fn first_retained_session(dbtx: &mut DatabaseTransaction<'_>) -> u64 {
+    dbtx.find_by_prefix(&SignedSessionOutcomePrefix)
+        .await
+        .next()
+        .await
+        .map_or(0, |(key, _)| key.0)
+}
+
+/// Returns the full outcome of a retained session, or only the transactions of
+/// a pruned one
+pub async fn get_session_outcome(
+    dbtx: &mut DatabaseTransaction<'_>,
+    session_index: u64,
+) -> Option<SessionOutcome> {
+    if let Some(signed) = dbtx
+        .get_value(&SignedSessionOutcomeKey(session_index))
+        .await
+    {
+        return Some(signed.session_outcome);
+    }
+
+    dbtx.get_value(&PrunedSessionOutcomeKey(session_index))
+        .await
+}
+
+/// Returns the transactions of a completed session as they are replayed by
+/// client recovery, regardless of whether the session was pruned
+pub async fn get_session_transactions(
+    dbtx: &mut DatabaseTransaction<'_>,
+    session_index: u64,
+) -> Option<SessionOutcome> {
+    if let Some(signed) = dbtx
+        .get_value(&SignedSessionOutcomeKey(session_index))
+        .await
+    {
+        return Some(retain_transactions(signed.session_outcome));
+    }
+
+    dbtx.get_value(&PrunedSessionOutcomeKey(session_index))
+        .await
+}
+
+fn retain_transactions(mut outcome: SessionOutcome) -> SessionOutcome {
+    outcome
+        .items
+        .retain(|accepted_item| matches!(accepted_item.item, ConsensusItem::Transaction(_)));
+
+    outcome
+}
+
+/// Replaces the signed outcomes of sessions that fall out of the configured
+/// retention once `session_count` sessions are finished with their
+/// transactions
+pub async fn prune_sessions(dbtx: &mut DatabaseTransaction<'_>, session_count: u64) {
+    let Some(retained_sessions) = dbtx
+        .get_value(&SessionRetentionKey)
+        .await
+        .and_then(|retention| retention.retained_sessions)
+    else {
+        return;
+    };
+
+    let first_retained = session_count.saturating_sub(retained_sessions);
+
+    let pruned = dbtx
+        .find_by_prefix(&SignedSessionOutcomePrefix)
+        .await
+        .map(|(key, _)| key.0)
+        .take_while(|session_index| std::future::ready(*session_index < first_retained))
+        .take(MAX_PRUNED_SESSIONS_PER_SESSION)
+        .collect::<Vec<u64>>()
+        .await;
+
+    for session_index in &pruned {
+        let signed = dbtx
+            .remove_entry(&SignedSessionOutcomeKey(*session_index))
+            .await
+            .expect("Session was found above");
+
+        dbtx.insert_new_entry(
+            &PrunedSessionOutcomeKey(*session_index),
+            &retain_transactions(signed.session_outcome),
+        )
+        .await;
+    }
+
+    if let (Some(first), Some(last)) = (pruned.first(), pruned.last()) {
+        info!(target: LOG_CONSENSUS, first, last, "Pruned session outcomes");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::admin_client::SessionRetention;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::session_outcome::{AcceptedItem, SessionOutcome, SignedSessionOutcome};
+    use fedimint_core::transaction::{Transaction, TransactionSignature};
+    use fedimint_core::PeerId;
+
+    use super::{
+        first_retained_session, get_session_outcome, get_session_transactions, prune_sessions,
+    };
+    use crate::consensus::db::{
+        PrunedSessionOutcomeKey, SessionRetentionKey, SignedSessionOutcomeKey,
+    };
+
+    fn transaction(session_index: u64) -> AcceptedItem {
+        AcceptedItem {
+            item: ConsensusItem::Transaction(Transaction {
+                inputs: vec![],
+                outputs: vec![],
+                nonce: session_index.to_be_bytes(),
+                signatures: TransactionSignature::NaiveMultisig(vec![]),
+            }),
+            peer: PeerId::from(0),
+        }
+    }
+
+    async fn complete_sessions(db: &Database, session_count: u64) {
+        let mut dbtx = db.begin_transaction().await;
+        for session_index in 0..session_count {
+            let items = vec![
+                transaction(session_index),
+                AcceptedItem {
+                    item: ConsensusItem::Default {
+                        variant: 42,
+                        bytes: vec![0; 32],
+                    },
+                    peer: PeerId::from(1),
+                },
+            ];
+            dbtx.insert_entry(
+                &SignedSessionOutcomeKey(session_index),
+                &SignedSessionOutcome {
+                    session_outcome: SessionOutcome { items },
+                    signatures: BTreeMap::new(),
+                },
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    async fn prune(db: &Database, session_count: u64) {
+        let mut dbtx = db.begin_transaction().await;
+        prune_sessions(&mut dbtx.to_ref_nc(), session_count).await;
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test]
+    async fn sessions_are_pruned_beyond_the_retention() {
+        let db = MemDatabase::new().into_database();
+        complete_sessions(&db, 250).await;
+
+        // Without a retention nothing is pruned
+        prune(&db, 250).await;
+        assert_eq!(
+            first_retained_session(&mut db.begin_transaction_nc().await).await,
+            0
+        );
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SessionRetentionKey,
+            &SessionRetention {
+                retained_sessions: Some(10),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        // Pruning is spread over multiple sessions
+        prune(&db, 250).await;
+        assert_eq!(
+            first_retained_session(&mut db.begin_transaction_nc().await).await,
+            100
+        );
+        prune(&db, 250).await;
+        prune(&db, 250).await;
+        assert_eq!(
+            first_retained_session(&mut db.begin_transaction_nc().await).await,
+            240
+        );
+
+        // Only the transactions of a pruned session are kept
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert!(dbtx
+            .get_value(&SignedSessionOutcomeKey(239))
+            .await
+            .is_none());
+        assert_eq!(
+            dbtx.get_value(&PrunedSessionOutcomeKey(239)).await,
+            Some(SessionOutcome {
+                items: vec![transaction(239)]
+            })
+        );
+        assert!(dbtx
+            .get_value(&SignedSessionOutcomeKey(240))
+            .await
+            .is_some());
+        assert!(dbtx
+            .get_value(&PrunedSessionOutcomeKey(240))
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn recovery_replays_the_same_transactions_across_pruned_sessions() {
+        let db = MemDatabase::new().into_database();
+        complete_sessions(&db, 50).await;
+
+        let mut replayed = vec![];
+        for session_index in 0..50 {
+            replayed.push(
+                get_session_transactions(&mut db.begin_transaction_nc().await, session_index)
+                    .await
+                    .expect("Session is complete"),
+            );
+        }
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SessionRetentionKey,
+            &SessionRetention {
+                retained_sessions: Some(20),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+        prune(&db, 50).await;
+        assert_eq!(
+            first_retained_session(&mut db.begin_transaction_nc().await).await,
+            30
+        );
+
+        for (session_index, outcome) in (0..50).zip(replayed) {
+            assert_eq!(
+                get_session_transactions(&mut db.begin_transaction_nc().await, session_index).await,
+                Some(outcome.clone())
+            );
+            assert_eq!(outcome.items, vec![transaction(session_index)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn session_outcome_is_only_reduced_once_pruned() {
+        let db = MemDatabase::new().into_database();
+        complete_sessions(&db, 50).await;
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &SessionRetentionKey,
+            &SessionRetention {
+                retained_sessions: Some(20),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+        prune(&db, 50).await;
+
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert_eq!(
+            get_session_outcome(&mut dbtx, 29).await.unwrap().items,
+            vec![transaction(29)]
+        );
+        assert_eq!(
+            get_session_outcome(&mut dbtx, 30)
+                .await
+                .unwrap()
+                .items
+                .len(),
+            2
+        );
+        assert_eq!(get_session_outcome(&mut dbtx, 50).await, None);
+    }
+}
@@ -1,16 +1,134 @@
-use fedimint_core::db::DatabaseTransaction;
+use std::collections::BTreeMap;
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::epoch::ConsensusItem;
 use fedimint_core::module::registry::ServerModuleRegistry;
-use fedimint_core::module::TransactionItemAmount;
-use fedimint_core::transaction::{Transaction, TransactionError};
-use fedimint_core::{Amount, OutPoint};
+use fedimint_core::module::{FeeSchedule, ModuleConsensusFeature, TransactionItemAmount};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::transaction::{
+    Transaction, TransactionError, TransactionItemIndex, TransactionRejection,
+};
+use fedimint_core::{timing, Amount, OutPoint, TransactionId};
+use rayon::prelude::*;
+use tracing::Level;
 
+use crate::consensus::db::{AcceptedTransactionKey, RejectedTransactionKey};
+use crate::consensus::features::active_module_features;
 use crate::metrics::{CONSENSUS_TX_PROCESSED_INPUTS, CONSENSUS_TX_PROCESSED_OUTPUTS};
 
+/// A [`TransactionError`] together with the input or output that caused it
+#[derive(Debug)]
+pub struct ProcessTransactionError {
+    pub item: Option<TransactionItemIndex>,
+    pub error: TransactionError,
+}
+
+impl ProcessTransactionError {
+    fn new(item: Option<TransactionItemIndex>, error: TransactionError) -> Self {
+        Self { item, error }
+    }
+
+    pub fn into_rejection(self) -> TransactionRejection {
+        TransactionRejection {
+            item: self.item,
+            error: self.error.to_string(),
+        }
+    }
+}
+
 pub async fn process_transaction_with_dbtx(
     modules: ServerModuleRegistry,
     dbtx: &mut DatabaseTransaction<'_>,
     transaction: Transaction,
-) -> Result<(), TransactionError> {
+) -> Result<(), ProcessTransactionError> {
+    verify_transaction(&modules, &transaction)?;
+
+    process_verified_transaction_with_dbtx(modules, dbtx, transaction).await
+}
+
+/// Runs the checks of the inputs and outputs of a transaction that do not
+/// depend on the database, see
+/// [`fedimint_core::module::ServerModule::verify_input`]. Since this only needs
+/// shared access to the modules the transactions of an ordered batch can be
+/// verified in parallel.
+pub fn verify_transaction(
+    modules: &ServerModuleRegistry,
+    transaction: &Transaction,
+) -> Result<(), ProcessTransactionError> {
+    for (input, in_idx) in transaction.inputs.iter().zip(0u64..) {
+        modules
+            .get_expect(input.module_instance_id())
+            .verify_input(input, input.module_instance_id())
+            .map_err(|e| {
+                ProcessTransactionError::new(
+                    Some(TransactionItemIndex::Input(in_idx)),
+                    TransactionError::Input(e),
+                )
+            })?;
+    }
+
+    for (output, out_idx) in transaction.outputs.iter().zip(0u64..) {
+        modules
+            .get_expect(output.module_instance_id())
+            .verify_output(output, output.module_instance_id())
+            .map_err(|e| {
+                ProcessTransactionError::new(
+                    Some(TransactionItemIndex::Output(out_idx)),
+                    TransactionError::Output(e),
+                )
+            })?;
+    }
+
+    Ok(())
+}
+
+/// Runs [`verify_transaction`] for the transactions among `items` in
+/// parallel, returning for every item whether it is a transaction that
+/// passed. Those only have to be checked against the database when they
+/// are processed in order, which keeps the sequential part of
+/// processing a batch short.
+pub async fn verify_transactions(
+    modules: &ServerModuleRegistry,
+    items: &[ConsensusItem],
+) -> Vec<bool> {
+    let _timing /* logs on drop */ = timing::TimeReporter::new("verify_transactions").level(Level::TRACE);
+
+    let transactions = items
+        .iter()
+        .map(|item| match item {
+            ConsensusItem::Transaction(transaction) => Some(transaction.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if transactions.iter().all(Option::is_none) {
+        return vec![false; items.len()];
+    }
+
+    let modules = modules.clone();
+
+    tokio::task::spawn_blocking(move || {
+        transactions
+            .par_iter()
+            .map(|transaction| {
+                transaction
+                    .as_ref()
+                    .is_some_and(|transaction| verify_transaction(&modules, transaction).is_ok())
+            })
+            .collect()
+    })
+    .await
+    .expect("Verifying transactions panicked")
+}
+
+/// Processes a transaction that already passed [`verify_transaction`]
+pub async fn process_verified_transaction_with_dbtx(
+    modules: ServerModuleRegistry,
+    dbtx: &mut DatabaseTransaction<'_>,
+    transaction: Transaction,
+) -> Result<(), ProcessTransactionError> {
     let in_count = transaction.inputs.len();
     let out_count = transaction.outputs.len();
 
@@ -19,31 +137,64 @@ pub async fn process_transaction_with_dbtx(
         CONSENSUS_TX_PROCESSED_OUTPUTS.observe(out_count as f64);
     });
 
+    let fee_schedules = fee_schedules(&modules, dbtx, &transaction).await;
+
     let mut funding_verifier = FundingVerifier::default();
     let mut public_keys = Vec::new();
 
-    for input in &transaction.inputs {
-        let meta = modules
-            .get_expect(input.module_instance_id())
+    for (input, in_idx) in transaction.inputs.iter().zip(0u64..) {
+        let module = modules.get_expect(input.module_instance_id());
+
+        check_feature_active(
+            dbtx,
+            input.module_instance_id(),
+            module.input_consensus_feature(input),
+        )
+        .await
+        .map_err(|e| ProcessTransactionError::new(Some(TransactionItemIndex::Input(in_idx)), e))?;
+
+        let meta = module
             .process_input(
                 &mut dbtx.to_ref_with_prefix_module_id(input.module_instance_id()),
                 input,
                 input.module_instance_id(),
             )
             .await
-            .map_err(TransactionError::Input)?;
+            .map_err(|e| {
+                ProcessTransactionError::new(
+                    Some(TransactionItemIndex::Input(in_idx)),
+                    TransactionError::Input(e),
+                )
+            })?;
 
         funding_verifier.add_input(meta.amount);
+        funding_verifier.add_schedule_fee(
+            fee_schedules[&input.module_instance_id()]
+                .input_fee(input.consensus_encode_to_vec().len()),
+        );
         public_keys.push(meta.pub_key);
     }
 
-    transaction.validate_signatures(&public_keys)?;
+    transaction
+        .validate_signatures(&public_keys)
+        .map_err(|e| ProcessTransactionError::new(None, e))?;
 
     let txid = transaction.tx_hash();
 
     for (output, out_idx) in transaction.outputs.iter().zip(0u64..) {
-        let amount = modules
-            .get_expect(output.module_instance_id())
+        let module = modules.get_expect(output.module_instance_id());
+
+        check_feature_active(
+            dbtx,
+            output.module_instance_id(),
+            module.output_consensus_feature(output),
+        )
+        .await
+        .map_err(|e| {
+            ProcessTransactionError::new(Some(TransactionItemIndex::Output(out_idx)), e)
+        })?;
+
+        let amount = module
             .process_output(
                 &mut dbtx.to_ref_with_prefix_module_id(output.module_instance_id()),
                 output,
@@ -51,20 +202,136 @@ pub async fn process_transaction_with_dbtx(
                 output.module_instance_id(),
             )
             .await
-            .map_err(TransactionError::Output)?;
+            .map_err(|e| {
+                ProcessTransactionError::new(
+                    Some(TransactionItemIndex::Output(out_idx)),
+                    TransactionError::Output(e),
+                )
+            })?;
 
         funding_verifier.add_output(amount);
+        funding_verifier.add_schedule_fee(
+            fee_schedules[&output.module_instance_id()]
+                .output_fee(output.consensus_encode_to_vec().len()),
+        );
     }
 
-    funding_verifier.verify_funding()?;
+    funding_verifier
+        .verify_funding()
+        .map_err(|e| ProcessTransactionError::new(None, e))?;
 
     Ok(())
 }
 
+/// Persists why transactions were rejected during a session as part of the
+/// database transaction completing the session, so the rejections only become
+/// visible together with the session outcome. Transactions that were accepted
+/// by the end of the session are skipped.
+pub async fn persist_transaction_rejections(
+    dbtx: &mut DatabaseTransaction<'_>,
+    rejections: BTreeMap<TransactionId, TransactionRejection>,
+) {
+    for (txid, rejection) in rejections {
+        if dbtx
+            .get_value(&AcceptedTransactionKey(txid))
+            .await
+            .is_none()
+        {
+            dbtx.insert_entry(&RejectedTransactionKey(txid), &rejection)
+                .await;
+        }
+    }
+}
+
+/// Fee schedules of all modules the transaction has inputs or outputs of, read
+/// before any of them is processed so that the fees don't depend on the order
+/// of the items
+async fn fee_schedules(
+    modules: &ServerModuleRegistry,
+    dbtx: &mut DatabaseTransaction<'_>,
+    transaction: &Transaction,
+) -> BTreeMap<ModuleInstanceId, FeeSchedule> {
+    let mut fee_schedules = BTreeMap::new();
+
+    let module_instance_ids = transaction
+        .inputs
+        .iter()
+        .map(|input| input.module_instance_id())
+        .chain(
+            transaction
+                .outputs
+                .iter()
+                .map(|output| output.module_instance_id()),
+        );
+
+    for module_instance_id in module_instance_ids {
+        if fee_schedules.contains_key(&module_instance_id) {
+            continue;
+        }
+
+        let fee_schedule = active_fee_schedule(
+            dbtx,
+            module_instance_id,
+            modules.get_expect(module_instance_id),
+        )
+        .await;
+
+        fee_schedules.insert(module_instance_id, fee_schedule);
+    }
+
+    fee_schedules
+}
+
+/// Fee schedule of the module, or no fees if the consensus feature the module
+/// requires for it is not active yet
+pub async fn active_fee_schedule(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+    module: &DynServerModule,
+) -> FeeSchedule {
+    if let Some(feature) = module.fee_schedule_consensus_feature() {
+        if !active_module_features(dbtx, module_instance_id)
+            .await
+            .contains(&feature)
+        {
+            return FeeSchedule::default();
+        }
+    }
+
+    module
+        .fee_schedule(&mut dbtx.to_ref_with_prefix_module_id(module_instance_id))
+        .await
+}
+
+/// Rejects inputs and outputs that require a module consensus feature which is
+/// not active yet, see [`crate::consensus::features`]
+async fn check_feature_active(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+    feature: Option<ModuleConsensusFeature>,
+) -> Result<(), TransactionError> {
+    let Some(feature) = feature else {
+        return Ok(());
+    };
+
+    if active_module_features(dbtx, module_instance_id)
+        .await
+        .contains(&feature)
+    {
+        return Ok(());
+    }
+
+    Err(TransactionError::InactiveFeature {
+        module_instance_id,
+        feature,
+    })
+}
+
 pub struct FundingVerifier {
     input_amount: Amount,
     output_amount: Amount,
     fee_amount: Amount,
+    schedule_fee_amount: Amount,
 }
 
 impl FundingVerifier {
@@ -78,8 +345,20 @@ impl FundingVerifier {
         self.fee_amount += output_amount.fee;
     }
 
+    /// Adds the fee of a [`FeeSchedule`] for an input or output
+    pub fn add_schedule_fee(&mut self, fee: Amount) {
+        self.fee_amount += fee;
+        self.schedule_fee_amount += fee;
+    }
+
+    /// Transactions have to be balanced exactly, unless they pay schedule fees
+    /// which clients can't always match with their change, see [`FeeSchedule`]
     pub fn verify_funding(self) -> Result<(), TransactionError> {
-        if self.input_amount == (self.output_amount + self.fee_amount) {
+        let required_amount = self.output_amount + self.fee_amount;
+
+        if self.input_amount == required_amount
+            || (self.schedule_fee_amount != Amount::ZERO && self.input_amount > required_amount)
+        {
             Ok(())
         } else {
             Err(TransactionError::UnbalancedTransaction {
@@ -97,6 +376,87 @@ impl Default for FundingVerifier {
             input_amount: Amount::ZERO,
             output_amount: Amount::ZERO,
             fee_amount: Amount::ZERO,
+            schedule_fee_amount: Amount::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::Hash;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::IDatabaseTransactionOpsCoreTyped;
+    use fedimint_core::module::TransactionItemAmount;
+    use fedimint_core::transaction::{TransactionItemIndex, TransactionRejection};
+    use fedimint_core::{Amount, TransactionId};
+
+    use super::{persist_transaction_rejections, FundingVerifier};
+    use crate::consensus::db::{AcceptedTransactionKey, RejectedTransactionKey};
+
+    fn rejection(error: &str) -> TransactionRejection {
+        TransactionRejection {
+            item: Some(TransactionItemIndex::Input(0)),
+            error: error.to_string(),
         }
     }
+
+    #[tokio::test]
+    async fn rejections_of_transactions_accepted_later_are_not_persisted() {
+        let db = MemDatabase::new().into_database();
+        let rejected = TransactionId::from_byte_array([1; 32]);
+        let accepted = TransactionId::from_byte_array([2; 32]);
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&AcceptedTransactionKey(accepted), &vec![])
+            .await;
+        persist_transaction_rejections(
+            &mut dbtx.to_ref_nc(),
+            BTreeMap::from([
+                (rejected, rejection("Input already spent")),
+                (accepted, rejection("Input not yet created")),
+            ]),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction_nc().await;
+        assert_eq!(
+            dbtx.get_value(&RejectedTransactionKey(rejected)).await,
+            Some(rejection("Input already spent"))
+        );
+        assert_eq!(
+            dbtx.get_value(&RejectedTransactionKey(accepted)).await,
+            None
+        );
+    }
+
+    fn funding_verifier(input: u64, output: u64, schedule_fee: u64) -> FundingVerifier {
+        let mut funding_verifier = FundingVerifier::default();
+        funding_verifier.add_input(TransactionItemAmount {
+            amount: Amount::from_msats(input),
+            fee: Amount::from_msats(10),
+        });
+        funding_verifier.add_output(TransactionItemAmount {
+            amount: Amount::from_msats(output),
+            fee: Amount::ZERO,
+        });
+        funding_verifier.add_schedule_fee(Amount::from_msats(schedule_fee));
+        funding_verifier
+    }
+
+    #[test]
+    fn transactions_without_schedule_fees_must_balance_exactly() {
+        assert!(funding_verifier(110, 100, 0).verify_funding().is_ok());
+        assert!(funding_verifier(111, 100, 0).verify_funding().is_err());
+        assert!(funding_verifier(109, 100, 0).verify_funding().is_err());
+    }
+
+    #[test]
+    fn transactions_with_schedule_fees_may_overpay() {
+        assert!(funding_verifier(115, 100, 5).verify_funding().is_ok());
+        assert!(funding_verifier(117, 100, 5).verify_funding().is_ok());
+        assert!(funding_verifier(114, 100, 5).verify_funding().is_err());
+    }
 }
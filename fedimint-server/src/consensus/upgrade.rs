@@ -0,0 +1,292 @@
+//! Coordinated upgrades of `fedimintd`
+//!
+//! Every guardian submits an [`UpgradeProposal`] via the admin API, which is
+//! broadcast as a [`ConsensusItem::Upgrade`] vote. Once a threshold of
+//! guardians voted for the same proposal it is scheduled. After completing the
+//! session before its halt session every guardian that does not run the
+//! proposed version stops consensus and refuses to start it again, until it is
+//! restarted with the new binary. Guardians that already run the new version
+//! simply continue, so operators can upgrade at their own pace before the halt
+//! session. Once a guardian runs the new version past the halt session the
+//! upgrade is completed and removed, so it doesn't halt later releases.
+
+use std::time::Duration;
+
+use anyhow::{bail, ensure};
+use async_channel::Sender;
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::envs::is_running_in_test_env;
+use fedimint_core::epoch::{ConsensusItem, UpgradeProposal};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use tracing::info;
+
+use crate::consensus::db::{
+    PendingUpgradeKey, ScheduledUpgradeKey, UpgradeVoteKey, UpgradeVotePrefix,
+};
+use crate::consensus::engine::get_finished_session_count_static;
+
+/// Minimum number of sessions between the session an upgrade is voted for in
+/// and its halt session, which gives the other guardians time to cast their
+/// votes
+pub const MIN_HALT_DELAY_SESSIONS: u64 = 2;
+
+/// Records the vote of `peer` and schedules the upgrade once a threshold of
+/// guardians voted for it, replacing any upgrade scheduled before
+pub async fn process_upgrade_vote(
+    dbtx: &mut DatabaseTransaction<'_>,
+    threshold: usize,
+    session_index: u64,
+    proposal: UpgradeProposal,
+    peer: PeerId,
+) -> anyhow::Result<()> {
+    ensure!(!proposal.version.is_empty(), "Upgrade version is empty");
+    ensure!(
+        session_index + MIN_HALT_DELAY_SESSIONS <= proposal.halt_session,
+        "Halt session {} is too close to the current session {session_index}",
+        proposal.halt_session
+    );
+    ensure!(
+        dbtx.get_value(&ScheduledUpgradeKey).await.as_ref() != Some(&proposal),
+        "Upgrade is already scheduled"
+    );
+
+    if dbtx.get_value(&UpgradeVoteKey(peer)).await.as_ref() == Some(&proposal) {
+        bail!("Already voted for this upgrade");
+    }
+
+    dbtx.insert_entry(&UpgradeVoteKey(peer), &proposal).await;
+
+    let votes = dbtx
+        .find_by_prefix(&UpgradeVotePrefix)
+        .await
+        .map(|(key, vote)| (key.0, vote))
+        .collect::<Vec<_>>()
+        .await;
+
+    if votes.iter().filter(|(_, vote)| *vote == proposal).count() < threshold {
+        return Ok(());
+    }
+
+    info!(
+        target: LOG_CONSENSUS,
+        version = %proposal.version,
+        halt_session = proposal.halt_session,
+        "Upgrade scheduled"
+    );
+
+    for (peer, _) in votes {
+        dbtx.remove_entry(&UpgradeVoteKey(peer)).await;
+    }
+
+    dbtx.insert_entry(&ScheduledUpgradeKey, &proposal).await;
+
+    Ok(())
+}
+
+/// Returns the scheduled upgrade if all sessions before its halt session are
+/// completed, but we don't run its version yet. If we do run its version the
+/// upgrade is completed instead.
+pub async fn due_upgrade(db: &Database, code_version: &str) -> Option<UpgradeProposal> {
+    let mut dbtx = db.begin_transaction().await;
+
+    let session_count = get_finished_session_count_static(&mut dbtx.to_ref_nc()).await;
+
+    let upgrade = dbtx
+        .get_value(&ScheduledUpgradeKey)
+        .await
+        .filter(|upgrade| upgrade.halt_session <= session_count)?;
+
+    if upgrade.version != code_version {
+        return Some(upgrade);
+    }
+
+    info!(
+        target: LOG_CONSENSUS,
+        version = %upgrade.version,
+        "Upgrade completed"
+    );
+
+    dbtx.remove_entry(&ScheduledUpgradeKey).await;
+    dbtx.remove_entry(&PendingUpgradeKey).await;
+    dbtx.commit_tx().await;
+
+    None
+}
+
+/// Fails if consensus is halted for an upgrade to a version other than ours
+pub async fn ensure_no_due_upgrade(db: &Database, code_version: &str) -> anyhow::Result<()> {
+    if let Some(upgrade) = due_upgrade(db, code_version).await {
+        bail!(
+            "Consensus is halted at session {} for the upgrade to fedimintd {}, but this is fedimintd {code_version}. Restart with the new version to continue.",
+            upgrade.halt_session,
+            upgrade.version
+        );
+    }
+
+    Ok(())
+}
+
+/// Periodically submits our vote for the upgrade we proposed until it is
+/// scheduled, since a vote can be discarded if it is submitted while a session
+/// is being completed
+pub fn submit_upgrade_votes(
+    task_group: &TaskGroup,
+    db: Database,
+    our_id: PeerId,
+    submission_sender: Sender<ConsensusItem>,
+) {
+    let mut interval = tokio::time::interval(if is_running_in_test_env() {
+        Duration::from_millis(100)
+    } else {
+        Duration::from_secs(1)
+    });
+
+    task_group.spawn("submit_upgrade_votes", move |task_handle| async move {
+        while !task_handle.is_shutting_down() {
+            let mut dbtx = db.begin_transaction_nc().await;
+
+            let session_count = get_finished_session_count_static(&mut dbtx).await;
+
+            if let Some(proposal) = dbtx.get_value(&PendingUpgradeKey).await {
+                if dbtx.get_value(&UpgradeVoteKey(our_id)).await.as_ref() != Some(&proposal)
+                    && dbtx.get_value(&ScheduledUpgradeKey).await.as_ref() != Some(&proposal)
+                    && session_count + MIN_HALT_DELAY_SESSIONS <= proposal.halt_session
+                {
+                    submission_sender
+                        .send(ConsensusItem::Upgrade(proposal))
+                        .await
+                        .ok();
+                }
+            }
+
+            interval.tick().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+    use fedimint_core::epoch::UpgradeProposal;
+    use fedimint_core::session_outcome::{SessionOutcome, SignedSessionOutcome};
+    use fedimint_core::PeerId;
+
+    use super::{due_upgrade, ensure_no_due_upgrade, process_upgrade_vote};
+    use crate::consensus::db::{ScheduledUpgradeKey, SignedSessionOutcomeKey};
+
+    async fn complete_sessions(db: &Database, session_count: u64) {
+        let mut dbtx = db.begin_transaction().await;
+        for session_index in 0..session_count {
+            dbtx.insert_entry(
+                &SignedSessionOutcomeKey(session_index),
+                &SignedSessionOutcome {
+                    session_outcome: SessionOutcome { items: vec![] },
+                    signatures: BTreeMap::new(),
+                },
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
+    }
+
+    async fn vote(db: &Database, session_index: u64, proposal: &UpgradeProposal, peer: u16) {
+        let mut dbtx = db.begin_transaction().await;
+        process_upgrade_vote(
+            &mut dbtx.to_ref_nc(),
+            3,
+            session_index,
+            proposal.clone(),
+            PeerId::from(peer),
+        )
+        .await
+        .expect("Vote is valid");
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test]
+    async fn upgrade_is_scheduled_once_a_threshold_voted() {
+        let db = MemDatabase::new().into_database();
+        let proposal = UpgradeProposal {
+            version: "v0.5.0".to_string(),
+            halt_session: 10,
+        };
+
+        // The halt session has to leave the other guardians time to vote
+        let too_close = UpgradeProposal {
+            version: "v0.5.0".to_string(),
+            halt_session: 9,
+        };
+        assert!(process_upgrade_vote(
+            &mut db.begin_transaction_nc().await,
+            3,
+            8,
+            too_close,
+            PeerId::from(0)
+        )
+        .await
+        .is_err());
+
+        vote(&db, 8, &proposal, 0).await;
+        vote(&db, 8, &proposal, 1).await;
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&ScheduledUpgradeKey)
+                .await,
+            None
+        );
+
+        vote(&db, 8, &proposal, 2).await;
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&ScheduledUpgradeKey)
+                .await,
+            Some(proposal)
+        );
+    }
+
+    #[tokio::test]
+    async fn consensus_halts_until_the_upgrade_completed() {
+        let db = MemDatabase::new().into_database();
+        let proposal = UpgradeProposal {
+            version: "v0.5.0".to_string(),
+            halt_session: 10,
+        };
+
+        for peer in 0..3 {
+            vote(&db, 0, &proposal, peer).await;
+        }
+
+        complete_sessions(&db, 9).await;
+        assert_eq!(due_upgrade(&db, "v0.4.0").await, None);
+
+        complete_sessions(&db, 10).await;
+        assert_eq!(due_upgrade(&db, "v0.4.0").await, Some(proposal.clone()));
+        assert!(ensure_no_due_upgrade(&db, "v0.4.0").await.is_err());
+
+        // Restarting with the new version resumes consensus and completes the
+        // upgrade, so later releases aren't halted by it
+        ensure_no_due_upgrade(&db, "v0.5.0")
+            .await
+            .expect("Runs the upgraded version");
+        assert_eq!(
+            db.begin_transaction_nc()
+                .await
+                .get_value(&ScheduledUpgradeKey)
+                .await,
+            None
+        );
+
+        complete_sessions(&db, 20).await;
+        ensure_no_due_upgrade(&db, "v0.6.0")
+            .await
+            .expect("Later releases are not halted");
+    }
+}
@@ -22,8 +22,9 @@ use std::path::{Path, PathBuf};
 use config::io::{read_server_config, PLAINTEXT_PASSWORD};
 use config::ServerConfig;
 use fedimint_aead::random_salt;
+use fedimint_api_client::api::GuardianConsensusCheckpoint;
 use fedimint_core::config::ServerModuleInitRegistry;
-use fedimint_core::db::Database;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::epoch::ConsensusItem;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::write_new;
@@ -33,6 +34,7 @@ use tracing::info;
 
 use crate::config::api::{ConfigGenApi, ConfigGenSettings};
 use crate::config::io::{write_server_config, SALT_FILE};
+use crate::consensus::db::ConfigGenCheckpointKey;
 use crate::metrics::initialize_gauge_metrics;
 use crate::net::api::RpcHandlerCtx;
 use crate::net::connect::TlsTcpConnector;
@@ -61,15 +63,19 @@ pub async fn run(
     code_version_str: String,
     module_init_registry: &ServerModuleInitRegistry,
     task_group: TaskGroup,
+    import_checkpoint: Option<GuardianConsensusCheckpoint>,
 ) -> anyhow::Result<()> {
-    let cfg = match get_config(&data_dir)? {
+    let socks5_proxy = settings.socks5_proxy;
+    let default_params = settings.default_params.modules.clone();
+
+    let mut cfg = match get_config(&data_dir)? {
         Some(cfg) => cfg,
         None => {
             run_config_gen(
                 data_dir,
                 settings,
                 db.clone(),
-                code_version_str,
+                code_version_str.clone(),
                 task_group.make_subgroup(),
                 force_api_secrets.clone(),
             )
@@ -77,25 +83,65 @@ pub async fn run(
         }
     };
 
-    let decoders = module_init_registry.decoders_strict(
-        cfg.consensus
-            .modules
-            .iter()
-            .map(|(id, config)| (*id, &config.kind)),
-    )?;
+    let mut import_checkpoint = import_checkpoint;
 
-    let db = db.with_decoders(decoders);
+    // Consensus is restarted with an updated config whenever module instances
+    // were added to the federation
+    loop {
+        let decoders = module_init_registry.decoders_strict(
+            cfg.consensus
+                .modules
+                .iter()
+                .map(|(id, config)| (*id, &config.kind)),
+        )?;
 
-    initialize_gauge_metrics(&db).await;
+        let db = db.with_decoders(decoders.clone());
 
-    consensus::run(
-        cfg,
-        db,
-        module_init_registry.clone(),
-        &task_group,
-        force_api_secrets,
-    )
-    .await?;
+        if let Some(checkpoint) = import_checkpoint.take() {
+            consensus::checkpoint::import_checkpoint(&db, &cfg, &decoders, &checkpoint).await?;
+        }
+
+        if let Some(updated_cfg) = consensus::module_addition::activate_module_additions(
+            &data_dir,
+            &cfg,
+            &db,
+            module_init_registry,
+            &default_params,
+            force_api_secrets.get_active(),
+            socks5_proxy,
+            &task_group,
+        )
+        .await?
+        {
+            cfg = updated_cfg;
+            continue;
+        }
+
+        initialize_gauge_metrics(&db).await;
+
+        consensus::run(
+            cfg.clone(),
+            db.clone(),
+            module_init_registry.clone(),
+            &task_group,
+            force_api_secrets.clone(),
+            socks5_proxy,
+            code_version_str.clone(),
+        )
+        .await?;
+
+        // Exit with an error if consensus halted for a scheduled upgrade, so the
+        // operator notices that the binary has to be replaced
+        consensus::upgrade::ensure_no_due_upgrade(&db, &code_version_str).await?;
+
+        if task_group.make_handle().is_shutting_down()
+            || consensus::module_addition::due_module_additions(&db, &cfg.consensus.modules)
+                .await
+                .is_empty()
+        {
+            break;
+        }
+    }
 
     info!(target: LOG_CONSENSUS, "Shutting down tasks");
 
@@ -168,5 +214,10 @@ pub async fn run_config_gen(
         force_api_secrets.get_active(),
     )?;
 
+    // The config is safely on disk, so setup will never need to be resumed
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.remove_entry(&ConfigGenCheckpointKey).await;
+    dbtx.commit_tx_result().await?;
+
     Ok(cfg)
 }
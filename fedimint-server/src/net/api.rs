@@ -9,9 +9,11 @@ use std::time::Duration;
 
 use anyhow::{bail, Context};
 use async_trait::async_trait;
+use fedimint_core::admin_client::AdminRole;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{ApiEndpoint, ApiEndpointContext, ApiError, ApiRequestErased};
+use fedimint_core::net::api_transport::{MAX_API_REQUEST_SIZE, MAX_API_RESPONSE_SIZE};
 use fedimint_logging::LOG_NET_API;
 use futures::FutureExt;
 use jsonrpsee::server::{PingConfig, RpcServiceBuilder, ServerBuilder, ServerHandle};
@@ -114,6 +116,7 @@ pub trait HasApiContext<State> {
 
 pub type ApiResult<T> = Result<T, ApiError>;
 
+/// Requires the request to be authenticated with the guardian password
 pub fn check_auth(context: &mut ApiEndpointContext) -> ApiResult<()> {
     if context.has_auth() {
         Ok(())
@@ -122,6 +125,19 @@ pub fn check_auth(context: &mut ApiEndpointContext) -> ApiResult<()> {
     }
 }
 
+/// Requires the request to be authenticated with the guardian password or an
+/// API token of at least `role`
+pub fn check_role(context: &mut ApiEndpointContext, role: AdminRole) -> ApiResult<()> {
+    if context
+        .auth_role()
+        .is_some_and(|auth_role| role <= auth_role)
+    {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized())
+    }
+}
+
 pub async fn spawn<T>(
     name: &'static str,
     api_bind: &SocketAddr,
@@ -136,6 +152,8 @@ pub async fn spawn<T>(
 
     ServerBuilder::new()
         .max_connections(max_connections)
+        .max_request_body_size(MAX_API_REQUEST_SIZE)
+        .max_response_body_size(MAX_API_RESPONSE_SIZE)
         .enable_ws_ping(PingConfig::new().ping_interval(Duration::from_secs(10)))
         .set_rpc_middleware(RpcServiceBuilder::new().layer(metrics::jsonrpsee::MetricsLayer))
         .set_http_middleware(builder)
@@ -183,11 +201,12 @@ pub fn attach_endpoints<State, T>(
                 // are only reading and the few that do write anything are atomic. Lastly, this
                 // is only the last line of defense
                 AssertUnwindSafe(tokio::time::timeout(API_ENDPOINT_TIMEOUT, async {
-                    let request = serde_json::from_value(params)
+                    let request: ApiRequestErased = serde_json::from_value(params)
                         .map_err(|e| ApiError::bad_request(e.to_string()))?;
+                    let transport = request.transport;
                     let (state, context) = rpc_context.context(&request, module_instance_id).await;
 
-                    (handler)(state, context, request).await
+                    transport.encode_response((handler)(state, context, request).await?)
                 }))
                 .catch_unwind()
                 .await
@@ -204,7 +223,7 @@ pub fn attach_endpoints<State, T>(
                     // was moved to be client-side only
                     ErrorObject::owned(-32000, "Request timeout", None::<()>)
                 })?
-                .map_err(|e| ErrorObject::owned(e.code, e.message, None::<()>))
+                .map_err(|e| ErrorObject::owned(e.code, e.message, e.data))
             })
             .expect("Failed to register async method");
     }
@@ -7,7 +7,7 @@ use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 
-use anyhow::format_err;
+use anyhow::{ensure, format_err};
 use async_trait::async_trait;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::PeerId;
@@ -17,6 +17,7 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
 use tokio_rustls::rustls::RootCertStore;
 use tokio_rustls::{rustls, TlsAcceptor, TlsConnector, TlsStream};
+use tokio_socks::tcp::Socks5Stream;
 
 use crate::net::framed::{AnyFramedTransport, BidiFramed, FramedTransport};
 
@@ -65,6 +66,8 @@ pub struct TlsTcpConnector {
     /// understands
     cert_store: RootCertStore,
     peer_names: BTreeMap<PeerId, String>,
+    /// SOCKS5 proxy outgoing connections are made through, e.g. Tor
+    socks5_proxy: Option<SocketAddr>,
 }
 
 #[derive(Debug, Clone)]
@@ -94,6 +97,16 @@ impl TlsTcpConnector {
             peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
             cert_store,
             peer_names: cfg.peer_names,
+            socks5_proxy: None,
+        }
+    }
+
+    /// Makes outgoing connections through the SOCKS5 proxy `socks5_proxy` if
+    /// set, which is required to connect to onion URLs
+    pub fn with_socks5_proxy(self, socks5_proxy: Option<SocketAddr>) -> TlsTcpConnector {
+        TlsTcpConnector {
+            socks5_proxy,
+            ..self
         }
     }
 }
@@ -177,7 +190,7 @@ where
         let tls_conn = connector
             .connect(
                 fake_domain,
-                TcpStream::connect(parse_host_port(&destination)?).await?,
+                connect_tcp(&destination, self.socks5_proxy).await?,
             )
             .await?;
 
@@ -243,6 +256,29 @@ pub fn parse_host_port(url: &SafeUrl) -> anyhow::Result<String> {
     Ok(format!("{host}:{port}"))
 }
 
+/// Opens a TCP connection to `url`, through the SOCKS5 proxy `socks5_proxy` if
+/// set. The proxy resolves the host itself, so onion URLs can only be reached
+/// through it.
+pub async fn connect_tcp(
+    url: &SafeUrl,
+    socks5_proxy: Option<SocketAddr>,
+) -> anyhow::Result<TcpStream> {
+    let host_port = parse_host_port(url)?;
+
+    match socks5_proxy {
+        Some(socks5_proxy) => Ok(Socks5Stream::connect(socks5_proxy, host_port.as_str())
+            .await?
+            .into_inner()),
+        None => {
+            ensure!(
+                !url.is_onion(),
+                "Connecting to onion URL {url} requires a SOCKS5 proxy"
+            );
+            Ok(TcpStream::connect(host_port).await?)
+        }
+    }
+}
+
 /// Fake network stack used in tests
 #[allow(unused_imports)]
 pub mod mock {
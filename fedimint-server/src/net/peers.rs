@@ -10,11 +10,11 @@ use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::Context;
 use async_trait::async_trait;
-use fedimint_api_client::api::PeerConnectionStatus;
+use fedimint_api_client::api::{PeerConnectionStats, PeerConnectionStatus};
 use fedimint_core::net::peers::IPeerConnections;
 use fedimint_core::task::{sleep_until, Cancellable, Cancelled, TaskGroup, TaskHandle};
 use fedimint_core::util::SafeUrl;
@@ -75,6 +75,9 @@ pub struct NetworkConfig {
     pub bind_addr: SocketAddr,
     /// Map of all peers' connection information we want to be connected to
     pub peers: HashMap<PeerId, SafeUrl>,
+    /// SOCKS5 proxy connections to peers are made through, e.g. Tor
+    #[serde(default)]
+    pub socks5_proxy: Option<SocketAddr>,
 }
 
 /// Internal message type for [`ReconnectPeerConnections`], just public because
@@ -150,6 +153,13 @@ struct CommonPeerConnectionState<M> {
     connect: SharedAnyConnector<PeerMessage<M>>,
     incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
     status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+    /// Statistics of this connection, published to `connection_stats` after
+    /// every state transition
+    stats: PeerConnectionStats,
+    connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
+    /// Whether the connection has been established before, so further
+    /// connections count as reconnects
+    connected_before: bool,
 }
 
 struct DisconnectedPeerConnectionState {
@@ -182,6 +192,7 @@ where
         connect: PeerConnector<T>,
         task_group: &TaskGroup,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+        connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
     ) -> Self {
         let shared_connector: SharedAnyConnector<PeerMessage<T>> = connect.into();
         let mut connection_senders = HashMap::new();
@@ -200,6 +211,7 @@ where
                 shared_connector.clone(),
                 connection_receiver,
                 status_channels.clone(),
+                connection_stats.clone(),
                 task_group,
             );
 
@@ -210,6 +222,10 @@ where
                 .write()
                 .await
                 .insert(*peer, PeerConnectionStatus::Disconnected);
+            connection_stats
+                .write()
+                .await
+                .insert(*peer, PeerConnectionStats::default());
         }
 
         task_group.spawn("listen task", move |handle| {
@@ -334,7 +350,7 @@ where
 
 impl<M> PeerConnectionStateMachine<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn run(mut self, task_handle: &TaskHandle) {
         let peer = self.common.peer_id;
@@ -359,7 +375,7 @@ where
     async fn state_transition(self, task_handle: &TaskHandle) -> Option<Self> {
         let PeerConnectionStateMachine { mut common, state } = self;
 
-        match state {
+        let new_state = match state {
             PeerConnectionState::Disconnected(disconnected) => {
                 let new_state = common
                     .state_transition_disconnected(disconnected, task_handle)
@@ -390,8 +406,15 @@ where
 
                 new_state
             }
-        }
-        .map(|new_state| PeerConnectionStateMachine {
+        };
+
+        common
+            .connection_stats
+            .write()
+            .await
+            .insert(common.peer_id, common.stats);
+
+        new_state.map(|new_state| PeerConnectionStateMachine {
             common,
             state: new_state,
         })
@@ -400,7 +423,7 @@ where
 
 impl<M> CommonPeerConnectionState<M>
 where
-    M: Debug + Clone,
+    M: Debug + Clone + Serialize,
 {
     async fn state_transition_connected(
         &mut self,
@@ -430,6 +453,8 @@ where
             Some(message_res) = connected.connection.next() => {
                 match message_res {
                     Ok(peer_message) => {
+                        self.record_received(&peer_message);
+
                         if let PeerMessage::Message(msg) = peer_message {
                             PEER_MESSAGES_COUNT.with_label_values(&[&self.our_id_str, &self.peer_id_str, "incoming"]).inc();
                             if self.incoming.try_send(msg).is_err(){
@@ -463,18 +488,26 @@ where
             peer = ?self.peer_id, %disconnect_count,
             "Initializing new connection");
         match new_connection.send(PeerMessage::Ping).await {
-            Ok(()) => PeerConnectionState::Connected(ConnectedPeerConnectionState {
-                connection: new_connection,
-                next_ping: Instant::now(),
-            }),
+            Ok(()) => {
+                if self.connected_before {
+                    self.stats.reconnect_count += 1;
+                }
+                self.connected_before = true;
+
+                PeerConnectionState::Connected(ConnectedPeerConnectionState {
+                    connection: new_connection,
+                    next_ping: Instant::now(),
+                })
+            }
             Err(e) => self.disconnect_err(&e, disconnect_count),
         }
     }
 
-    fn disconnect(&self, mut disconnect_count: u64) -> PeerConnectionState<M> {
+    fn disconnect(&mut self, mut disconnect_count: u64) -> PeerConnectionState<M> {
         PEER_DISCONNECT_COUNT
             .with_label_values(&[&self.our_id_str, &self.peer_id_str])
             .inc();
+        self.stats.disconnect_count += 1;
         disconnect_count += 1;
 
         let reconnect_at = {
@@ -497,7 +530,11 @@ where
         })
     }
 
-    fn disconnect_err(&self, err: &anyhow::Error, disconnect_count: u64) -> PeerConnectionState<M> {
+    fn disconnect_err(
+        &mut self,
+        err: &anyhow::Error,
+        disconnect_count: u64,
+    ) -> PeerConnectionState<M> {
         debug!(target: LOG_NET_PEER,
             our_id = ?self.our_id,
             peer = ?self.peer_id, %err, %disconnect_count, "Peer disconnected");
@@ -514,6 +551,9 @@ where
             .with_label_values(&[&self.our_id_str, &self.peer_id_str, "outgoing"])
             .inc();
 
+        let size = message_size(&peer_message);
+        let send_start = Instant::now();
+
         if let Err(e) = connected.connection.send(peer_message).await {
             return self.disconnect_err(&e, 0);
         }
@@ -521,11 +561,34 @@ where
         connected.next_ping = Instant::now() + PING_INTERVAL;
 
         match connected.connection.flush().await {
-            Ok(()) => PeerConnectionState::Connected(connected),
+            Ok(()) => {
+                self.stats.messages_sent += 1;
+                self.stats.bytes_sent += size;
+                self.stats.last_send_latency_ms = Some(
+                    send_start
+                        .elapsed()
+                        .as_millis()
+                        .try_into()
+                        .unwrap_or(u64::MAX),
+                );
+
+                PeerConnectionState::Connected(connected)
+            }
             Err(e) => self.disconnect_err(&e, 0),
         }
     }
 
+    fn record_received(&mut self, peer_message: &PeerMessage<M>) {
+        self.stats.messages_received += 1;
+        self.stats.bytes_received += message_size(peer_message);
+        self.stats.last_seen = Some(
+            fedimint_core::time::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+    }
+
     async fn state_transition_disconnected(
         &mut self,
         disconnected: DisconnectedPeerConnectionState,
@@ -594,9 +657,14 @@ where
     }
 }
 
+/// Size of the serialized message, not including the framing
+fn message_size<M: Serialize>(peer_message: &PeerMessage<M>) -> u64 {
+    bincode::serialized_size(peer_message).unwrap_or_default()
+}
+
 impl<M> PeerConnection<M>
 where
-    M: Debug + Clone + Send + Sync + 'static,
+    M: Debug + Clone + Serialize + Send + Sync + 'static,
 {
     #[allow(clippy::too_many_arguments)]
     fn new(
@@ -607,6 +675,7 @@ where
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+        connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
         task_group: &TaskGroup,
     ) -> PeerConnection<M> {
         let (outgoing_sender, outgoing_receiver) = async_channel::bounded(1024);
@@ -625,6 +694,7 @@ where
                     connect,
                     incoming_connections,
                     status_channels,
+                    connection_stats,
                     &handle,
                 )
                 .await;
@@ -665,6 +735,7 @@ where
         connect: SharedAnyConnector<PeerMessage<M>>,
         incoming_connections: Receiver<AnyFramedTransport<PeerMessage<M>>>,
         status_channels: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStatus>>>,
+        connection_stats: Arc<RwLock<BTreeMap<PeerId, PeerConnectionStats>>>,
         task_handle: &TaskHandle,
     ) {
         let common = CommonPeerConnectionState {
@@ -679,6 +750,9 @@ where
             connect,
             incoming_connections,
             status_channels,
+            stats: PeerConnectionStats::default(),
+            connection_stats,
+            connected_before: false,
         };
         let initial_state = PeerConnectionState::Disconnected(DisconnectedPeerConnectionState {
             reconnect_at: Instant::now(),
@@ -760,35 +834,56 @@ mod tests {
                     identity: PeerId::from(id),
                     bind_addr: bind.parse().unwrap(),
                     peers: peers_ref.clone(),
+                    socks5_proxy: None,
                 };
                 let connect = net_ref
                     .connector(cfg.identity, StreamReliability::MILDLY_UNRELIABLE)
                     .into_dyn();
                 let status_channels = Default::default();
+                let connection_stats = Default::default();
                 let connection = ReconnectPeerConnections::<u64>::new(
                     cfg,
                     DelayCalculator::TEST_DEFAULT,
                     connect,
                     &task_group,
                     Arc::clone(&status_channels),
+                    Arc::clone(&connection_stats),
                 )
                 .await;
 
-                (connection, status_channels)
+                (connection, status_channels, connection_stats)
             };
 
-            let (_peers_a, peer_status_client_a) =
+            let (_peers_a, peer_status_client_a, peer_stats_a) =
                 build_peers("127.0.0.1:1000", 1, task_group.clone()).await;
-            let (_peers_b, peer_status_client_b) =
+            let (_peers_b, peer_status_client_b, _) =
                 build_peers("127.0.0.1:2000", 2, task_group.clone()).await;
 
             wait_for_connection("a", &peer_status_client_a).await;
             wait_for_connection("b", &peer_status_client_b).await;
 
-            let (_peers_c, peer_status_client_c) =
+            let (_peers_c, peer_status_client_c, _) =
                 build_peers("127.0.0.1:3000", 3, task_group.clone()).await;
 
             wait_for_connection("c", &peer_status_client_c).await;
+
+            // Peers ping each other on connect, so a has heard from b
+            retry(
+                "wait for stats of a",
+                fedimint_core::util::FibonacciBackoff::default()
+                    .with_min_delay(Duration::from_millis(200))
+                    .with_max_delay(Duration::from_secs(5))
+                    .with_max_times(10),
+                || async {
+                    let stats = peer_stats_a.read().await;
+                    let stats_b = stats.get(&PeerId::from(2)).context("missing stats")?;
+                    ensure!(0 < stats_b.messages_received);
+                    ensure!(stats_b.last_seen.is_some());
+                    Ok(())
+                },
+            )
+            .await
+            .unwrap();
         }
 
         task_group.shutdown();
@@ -770,6 +770,7 @@ mod tests {
                     identity: PeerId::from(id),
                     bind_addr: bind.parse().unwrap(),
                     peers: peers_ref.clone(),
+                    socks5_proxy: None,
                 };
                 let connect = net_ref
                     .connector(cfg.identity, StreamReliability::MILDLY_UNRELIABLE)
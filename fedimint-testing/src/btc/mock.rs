@@ -345,6 +345,21 @@ impl IBitcoindRpc for FakeBitcoinTest {
         Ok(None)
     }
 
+    async fn get_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> BitcoinRpcResult<Option<bitcoin::Transaction>> {
+        let inner = self.inner.read().unwrap();
+        let transaction = inner
+            .blocks
+            .iter()
+            .flat_map(|block| block.txdata.iter())
+            .chain(inner.pending.iter())
+            .find(|tx| tx.txid() == *txid)
+            .cloned();
+        Ok(transaction)
+    }
+
     async fn is_tx_in_block(
         &self,
         txid: &Txid,
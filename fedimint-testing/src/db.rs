@@ -3,8 +3,11 @@ use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use std::{env, fs, io};
 
-use anyhow::{bail, format_err, Context};
-use fedimint_client::db::apply_migrations_client;
+use anyhow::{bail, ensure, format_err, Context};
+use fedimint_client::db::{
+    apply_migrations_client, check_migrations_client, get_active_states, get_inactive_states,
+    restore_client_migration_backups,
+};
 use fedimint_client::module::init::DynClientModuleInit;
 use fedimint_client::module::ClientModule;
 use fedimint_client::sm::{
@@ -13,9 +16,11 @@ use fedimint_client::sm::{
 };
 use fedimint_core::core::OperationId;
 use fedimint_core::db::{
-    apply_migrations, apply_migrations_server, Database, DatabaseVersion,
-    IDatabaseTransactionOpsCoreTyped, ServerMigrationFn,
+    apply_migrations, apply_migrations_server, create_database_version, Database, DatabaseVersion,
+    DatabaseVersionKey, IDatabaseTransactionOpsCore, IDatabaseTransactionOpsCoreTyped,
+    ServerMigrationFn, MODULE_GLOBAL_PREFIX,
 };
+use fedimint_core::encoding::Encodable;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{CommonModuleInit, DynServerModuleInit};
 use fedimint_logging::LOG_TEST;
@@ -391,6 +396,114 @@ where
     Ok(())
 }
 
+/// Validates that the pending migrations of a client module can be checked
+/// with [`check_migrations_client`] without changing the database, and that
+/// [`restore_client_migration_backups`] restores the exact pre-migration data
+/// after the migrations were applied.
+pub async fn validate_migration_rollback_client<T>(
+    module: DynClientModuleInit,
+    db_prefix: &str,
+) -> anyhow::Result<()>
+where
+    T: ClientModule,
+{
+    let decoders = ModuleDecoderRegistry::from_iter([(
+        TEST_MODULE_INSTANCE_ID,
+        module.as_common().module_kind(),
+        T::decoder(),
+    )]);
+    let (db, _tmp_dir) = get_temp_database(db_prefix, &decoders)?;
+    let kind = module.as_common().module_kind().to_string();
+
+    // Snapshots might predate `DatabaseVersionKey`, which the client creates
+    // before migrating
+    create_database_version(
+        &db,
+        module.database_version(),
+        Some(TEST_MODULE_INSTANCE_ID),
+        kind.clone(),
+        false,
+    )
+    .await?;
+    let snapshot = read_client_module_data(&db).await?;
+
+    let report = check_migrations_client(
+        &db,
+        kind.clone(),
+        module.database_version(),
+        module.get_database_migrations(),
+        TEST_MODULE_INSTANCE_ID,
+    )
+    .await?
+    .context("Expected pending migrations for the snapshot")?;
+    ensure!(
+        report.target_version == module.database_version(),
+        "Migration report has the wrong target version"
+    );
+    ensure!(
+        read_client_module_data(&db).await? == snapshot,
+        "Checking migrations changed the database"
+    );
+
+    apply_migrations_client(
+        &db,
+        kind,
+        module.database_version(),
+        module.get_database_migrations(),
+        TEST_MODULE_INSTANCE_ID,
+    )
+    .await
+    .context("Error applying migrations to temp database")?;
+    ensure!(
+        read_client_module_data(&db).await? != snapshot,
+        "Applying migrations did not change the database"
+    );
+
+    let restored = restore_client_migration_backups(&db).await?;
+    ensure!(
+        restored == vec![TEST_MODULE_INSTANCE_ID],
+        "Expected a migration backup for the test module, restored {restored:?}"
+    );
+    ensure!(
+        read_client_module_data(&db).await? == snapshot,
+        "Rolling back migrations did not restore the pre-migration database"
+    );
+
+    Ok(())
+}
+
+type ClientModuleData = (
+    Option<DatabaseVersion>,
+    Vec<(Vec<u8>, Vec<u8>)>,
+    Vec<(Vec<u8>, OperationId)>,
+    Vec<(Vec<u8>, OperationId)>,
+);
+
+/// Reads the database version, raw entries and state machines of the test
+/// module
+async fn read_client_module_data(db: &Database) -> anyhow::Result<ClientModuleData> {
+    let mut dbtx = db.begin_transaction_nc().await;
+
+    let version = dbtx
+        .get_value(&DatabaseVersionKey(TEST_MODULE_INSTANCE_ID))
+        .await;
+
+    let mut module_prefix = vec![MODULE_GLOBAL_PREFIX];
+    TEST_MODULE_INSTANCE_ID.consensus_encode(&mut module_prefix)?;
+    let entries = dbtx
+        .raw_find_by_prefix(&module_prefix)
+        .await?
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut active_states = get_active_states(&mut dbtx, TEST_MODULE_INSTANCE_ID).await;
+    active_states.sort();
+    let mut inactive_states = get_inactive_states(&mut dbtx, TEST_MODULE_INSTANCE_ID).await;
+    inactive_states.sort();
+
+    Ok((version, entries, active_states, inactive_states))
+}
+
 /// Open a temporary database located at `temp_path` and copy the contents from
 /// the folder `src_dir` to the temporary database's path.
 fn open_temp_db_and_copy(
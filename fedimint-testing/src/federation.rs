@@ -2,7 +2,7 @@ use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 use std::time::Duration;
 
-use fedimint_api_client::api::{DynGlobalApi, FederationApiExt};
+use fedimint_api_client::api::{DynGlobalApi, FederationApiExt, IRawFederationApi};
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::secret::{PlainRootSecretStrategy, RootSecretStrategy};
 use fedimint_client::{AdminCreds, Client, ClientHandleArc};
@@ -18,6 +18,7 @@ use fedimint_core::endpoint_constants::SESSION_COUNT_ENDPOINT;
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::{ApiAuth, ApiRequestErased};
 use fedimint_core::task::{block_in_place, sleep_in_test, TaskGroup};
+use fedimint_core::util::SafeUrl;
 use fedimint_core::PeerId;
 use fedimint_logging::LOG_TEST;
 use fedimint_rocksdb::RocksDb;
@@ -72,6 +73,41 @@ impl FederationTest {
         .await
     }
 
+    /// Create a client connected to this fed that queues transactions in its
+    /// outbox while the federation is unreachable, see
+    /// [`fedimint_client::ClientBuilder::with_offline_mode`]
+    pub async fn new_offline_mode_client(&self) -> ClientHandleArc {
+        let client_config = self.configs[&PeerId::from(0)]
+            .consensus
+            .to_client_config(&self.server_init)
+            .unwrap();
+
+        self.new_client_with_options(client_config, MemDatabase::new().into(), None, true)
+            .await
+    }
+
+    /// Points the API connections of `client` at ports nobody listens on, or
+    /// back at the guardians, to make the federation unreachable for the
+    /// client or reachable again
+    pub async fn set_reachable(&self, client: &Client, reachable: bool) {
+        let peer_urls = client
+            .get_config()
+            .global
+            .api_endpoints
+            .iter()
+            .map(|(peer_id, peer_url)| {
+                let url = if reachable {
+                    peer_url.url.clone()
+                } else {
+                    SafeUrl::parse("ws://127.0.0.1:1").expect("Valid url")
+                };
+                (*peer_id, url)
+            })
+            .collect();
+
+        client.api().update_peer_urls(&peer_urls).await;
+    }
+
     /// Create a new admin client connected to this fed
     pub async fn new_admin_client(&self, peer_id: PeerId, auth: ApiAuth) -> ClientHandleArc {
         let client_config = self.configs[&PeerId::from(0)]
@@ -90,6 +126,17 @@ impl FederationTest {
         client_config: ClientConfig,
         db: Database,
         admin_creds: Option<AdminCreds>,
+    ) -> ClientHandleArc {
+        self.new_client_with_options(client_config, db, admin_creds, false)
+            .await
+    }
+
+    async fn new_client_with_options(
+        &self,
+        client_config: ClientConfig,
+        db: Database,
+        admin_creds: Option<AdminCreds>,
+        offline_mode: bool,
     ) -> ClientHandleArc {
         info!(target: LOG_TEST, "Setting new client with config");
         let mut client_builder = Client::builder(db);
@@ -98,6 +145,9 @@ impl FederationTest {
         if let Some(admin_creds) = admin_creds {
             client_builder.set_admin_creds(admin_creds);
         }
+        if offline_mode {
+            client_builder.with_offline_mode();
+        }
         let client_secret = Client::load_or_generate_client_secret(client_builder.db_no_decoders())
             .await
             .unwrap();
@@ -112,6 +162,50 @@ impl FederationTest {
             .expect("Failed to build client")
     }
 
+    /// Recovers the client with `client_secret` into a fresh database by
+    /// scanning the federation history, see
+    /// [`fedimint_client::ClientBuilder::recover_without_backup`]
+    pub async fn recover_client_without_backup(
+        &self,
+        client_secret: [u8; 64],
+        scan_depth: Option<u64>,
+    ) -> ClientHandleArc {
+        let client_config = self.configs[&PeerId::from(0)]
+            .consensus
+            .to_client_config(&self.server_init)
+            .unwrap();
+
+        let mut client_builder = Client::builder(MemDatabase::new().into());
+        client_builder.with_module_inits(self.client_init.clone());
+        client_builder.with_primary_module(self.primary_client);
+        Client::store_encodable_client_secret(client_builder.db_no_decoders(), client_secret)
+            .await
+            .unwrap();
+        client_builder
+            .recover_without_backup(
+                PlainRootSecretStrategy::to_root_secret(&client_secret),
+                client_config,
+                None,
+                scan_depth,
+            )
+            .await
+            .map(Arc::new)
+            .expect("Failed to recover client")
+    }
+
+    /// Opens the existing client in `db` in watch-only mode, see
+    /// [`fedimint_client::ClientBuilder::open_watch_only`]
+    pub async fn open_watch_only_client(&self, db: Database) -> ClientHandleArc {
+        let mut client_builder = Client::builder(db);
+        client_builder.with_module_inits(self.client_init.clone());
+        client_builder.with_primary_module(self.primary_client);
+        client_builder
+            .open_watch_only()
+            .await
+            .map(Arc::new)
+            .expect("Failed to open watch-only client")
+    }
+
     /// Return first invite code for gateways
     pub fn invite_code(&self) -> InviteCode {
         self.configs[&PeerId::from(0)].get_invite_code(None)
@@ -218,6 +312,8 @@ impl FederationTestBuilder {
                     module_init_registry,
                     &subgroup,
                     fedimint_server::net::api::ApiSecrets::default(),
+                    None,
+                    config.consensus.code_version.clone(),
                 )
                 .await
                 .expect("Could not initialise consensus");
@@ -310,6 +406,7 @@ pub fn local_config_gen_params(
                     p2p_bind: p2p_bind.parse().expect("Valid address"),
                     api_bind: api_bind.parse().expect("Valid address"),
                     max_connections: 10,
+                    socks5_proxy: None,
                 },
                 consensus: ConfigGenParamsConsensus {
                     peers: connections.clone(),
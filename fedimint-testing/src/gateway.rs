@@ -9,15 +9,19 @@ use async_trait::async_trait;
 use fedimint_client::module::init::ClientModuleInitRegistry;
 use fedimint_client::ClientHandleArc;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::secp256k1::PublicKey;
 use fedimint_core::task::{block_in_place, block_on, sleep_in_test, TaskGroup};
 use fedimint_core::util::SafeUrl;
+use fedimint_core::Amount;
+use fedimint_lnv2_client::CreateInvoicePayload;
 use fedimint_logging::LOG_TEST;
-use lightning_invoice::RoutingFees;
+use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use ln_gateway::client::GatewayClientBuilder;
+use ln_gateway::gateway_module_v2::GatewayClientModuleV2;
 use ln_gateway::lightning::{ILnRpcClient, LightningBuilder};
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{ConnectFedPayload, FederationInfo, V1_API_ENDPOINT};
@@ -74,6 +78,53 @@ impl GatewayTest {
         self.gateway.gateway_id
     }
 
+    /// Creates an invoice for an LNv2 receive via the fake lightning node, like
+    /// a client would through the gateway's public API
+    pub async fn create_invoice_v2(
+        &self,
+        payload: CreateInvoicePayload,
+    ) -> anyhow::Result<Bolt11Invoice> {
+        self.gateway.create_invoice_v2(payload).await
+    }
+
+    /// Simulates an HTLC for an invoice created with
+    /// [`GatewayTest::create_invoice_v2`] arriving at the fake lightning node
+    /// and waits until the gateway funded the incoming contract. Returns the
+    /// preimage the HTLC is settled with, or `None` if the receive failed and
+    /// the HTLC is cancelled.
+    pub async fn relay_incoming_htlc_v2(
+        &self,
+        incoming_chan_id: u64,
+        htlc_id: u64,
+        payload: CreateInvoicePayload,
+        amount: Amount,
+    ) -> anyhow::Result<Option<[u8; 32]>> {
+        let operation_id = OperationId::from_encodable(&payload);
+        let client = self.select_client(payload.federation_id).await;
+        let module = client.get_first_module::<GatewayClientModuleV2>();
+
+        module
+            .relay_incoming_htlc(incoming_chan_id, htlc_id, payload, amount)
+            .await?;
+
+        Ok(module.subscribe_receive(operation_id).await)
+    }
+
+    /// Funds the incoming contract of an invoice created with
+    /// [`GatewayTest::create_invoice_v2`] directly, as the gateway does when
+    /// one of its federations pays an invoice of another, and returns the
+    /// preimage
+    pub async fn relay_direct_swap_v2(
+        &self,
+        payload: CreateInvoicePayload,
+    ) -> anyhow::Result<[u8; 32]> {
+        self.select_client(payload.federation_id)
+            .await
+            .get_first_module::<GatewayClientModuleV2>()
+            .relay_direct_swap(payload)
+            .await
+    }
+
     pub(crate) async fn new(
         base_port: u16,
         cli_password: Option<String>,
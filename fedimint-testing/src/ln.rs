@@ -9,6 +9,7 @@ use bitcoin::secp256k1::{PublicKey, SecretKey};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::BoxStream;
 use fedimint_core::{secp256k1, Amount};
+use fedimint_ln_common::KeysendPayment;
 use fedimint_logging::LOG_TEST;
 use lightning_invoice::{
     Bolt11Invoice, Bolt11InvoiceDescription, Currency, Description, InvoiceBuilder, PaymentSecret,
@@ -27,6 +28,10 @@ use tracing::info;
 
 pub const INVALID_INVOICE_DESCRIPTION: &str = "INVALID";
 
+/// `FakeLightningTest` fails to send any keysend payment carrying a custom
+/// record of this type
+pub const INVALID_KEYSEND_TLV_TYPE: u64 = 65_537;
+
 #[derive(Debug)]
 pub struct FakeLightningTest {
     pub gateway_node_pub_key: secp256k1::PublicKey,
@@ -145,16 +150,43 @@ impl ILnRpcClient for FakeLightningTest {
                 &Description::new(INVALID_INVOICE_DESCRIPTION.into()).unwrap(),
             )
         {
-            return Err(LightningRpcError::FailedPayment {
+            return Err(LightningRpcError::FailedPaymentFinal {
                 failure_reason: "Description was invalid".to_string(),
             });
         }
 
         Ok(PayInvoiceResponse {
             preimage: [0; 32].to_vec(),
+            shards: vec![],
+            fee_msat: 0,
         })
     }
 
+    async fn pay_keysend(
+        &self,
+        payment: KeysendPayment,
+        _max_delay: u64,
+        _max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        *self.amount_sent.lock().unwrap() += payment.amount.msats;
+
+        if payment.tlv_records.contains_key(&INVALID_KEYSEND_TLV_TYPE) {
+            return Err(LightningRpcError::FailedPaymentFinal {
+                failure_reason: "Keysend record was invalid".to_string(),
+            });
+        }
+
+        Ok(PayInvoiceResponse {
+            preimage: payment.preimage.0.to_vec(),
+            shards: vec![],
+            fee_msat: 0,
+        })
+    }
+
+    fn supports_keysend(&self) -> bool {
+        true
+    }
+
     async fn route_htlcs<'a>(
         mut self: Box<Self>,
         task_group: &mut TaskGroup,
@@ -189,13 +221,18 @@ impl ILnRpcClient for FakeLightningTest {
 
         let payment_hash = sha256::Hash::from_slice(&create_invoice_request.payment_hash)
             .expect("Failed to lookup FederationId");
-        let invoice = InvoiceBuilder::new(Currency::Regtest)
+        let builder = InvoiceBuilder::new(Currency::Regtest);
+        let builder = if create_invoice_request.amount_msat == 0 {
+            builder
+        } else {
+            builder.amount_milli_satoshis(create_invoice_request.amount_msat)
+        };
+        let invoice = builder
             .description(String::new())
             .payment_hash(payment_hash)
             .current_timestamp()
             .min_final_cltv_expiry_delta(0)
             .payment_secret(PaymentSecret([0; 32]))
-            .amount_milli_satoshis(create_invoice_request.amount_msat)
             .expiry_time(Duration::from_secs(u64::from(
                 create_invoice_request.expiry,
             )))
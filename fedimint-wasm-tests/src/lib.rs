@@ -49,6 +49,7 @@ async fn client(invite_code: &InviteCode) -> Result<fedimint_client::ClientHandl
         )
         .await
         .map(Arc::new)
+        .map_err(Into::into)
 }
 
 mod faucet {
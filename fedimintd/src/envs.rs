@@ -31,6 +31,9 @@ pub const FM_BITCOIN_NETWORK_ENV: &str = "FM_BITCOIN_NETWORK";
 // Env variable to TODO
 pub const FM_FINALITY_DELAY_ENV: &str = "FM_FINALITY_DELAY";
 
+// Env variable to set the peg-out batching window in blocks
+pub const FM_PEG_OUT_BATCH_WINDOW_ENV: &str = "FM_PEG_OUT_BATCH_WINDOW";
+
 // Env variable to TODO
 pub const FM_BIND_METRICS_API_ENV: &str = "FM_BIND_METRICS_API";
 
@@ -42,3 +45,11 @@ pub const FM_DEFAULT_API_SECRETS_ENV: &str = "FM_DEFAULT_API_SECRETS";
 
 // Can be used to absolutely override the values stored in the db
 pub const FM_FORCE_API_SECRETS_ENV: &str = "FM_FORCE_API_SECRETS";
+
+// Path to a consensus checkpoint to start a guardian with an empty database
+// from
+pub const FM_IMPORT_CHECKPOINT_ENV: &str = "FM_IMPORT_CHECKPOINT";
+
+// SOCKS5 proxy (e.g. Tor) the connections to the other guardians are made
+// through
+pub const FM_SOCKS5_PROXY_ENV: &str = "FM_SOCKS5_PROXY";
@@ -1,4 +1,5 @@
 mod metrics;
+mod setup;
 
 use std::collections::BTreeMap;
 use std::net::SocketAddr;
@@ -27,8 +28,9 @@ use fedimint_meta_server::{MetaGenParams, MetaInit};
 use fedimint_mint_server::common::config::{MintGenParams, MintGenParamsConsensus};
 use fedimint_mint_server::MintInit;
 use fedimint_server::config::api::ConfigGenSettings;
-use fedimint_server::config::io::{DB_FILE, PLAINTEXT_PASSWORD};
+use fedimint_server::config::io::{restore_disaster_recovery_bundle, DB_FILE, PLAINTEXT_PASSWORD};
 use fedimint_server::config::ServerConfig;
+use fedimint_server::consensus::restore::record_restored_checkpoint;
 use fedimint_server::net::api::ApiSecrets;
 use fedimint_unknown_common::config::UnknownGenParams;
 use fedimint_unknown_server::UnknownInit;
@@ -43,8 +45,8 @@ use crate::default_esplora_server;
 use crate::envs::{
     FM_API_URL_ENV, FM_BIND_API_ENV, FM_BIND_METRICS_API_ENV, FM_BIND_P2P_ENV,
     FM_BITCOIN_NETWORK_ENV, FM_DATA_DIR_ENV, FM_DISABLE_META_MODULE_ENV, FM_EXTRA_DKG_META_ENV,
-    FM_FINALITY_DELAY_ENV, FM_FORCE_API_SECRETS_ENV, FM_P2P_URL_ENV, FM_PASSWORD_ENV,
-    FM_TOKIO_CONSOLE_BIND_ENV,
+    FM_FINALITY_DELAY_ENV, FM_FORCE_API_SECRETS_ENV, FM_IMPORT_CHECKPOINT_ENV, FM_P2P_URL_ENV,
+    FM_PASSWORD_ENV, FM_PEG_OUT_BATCH_WINDOW_ENV, FM_SOCKS5_PROXY_ENV, FM_TOKIO_CONSOLE_BIND_ENV,
 };
 use crate::fedimintd::metrics::APP_START_TS;
 
@@ -87,6 +89,10 @@ pub struct ServerOpts {
     /// The number of blocks the federation stays behind the blockchain tip
     #[arg(long, env = FM_FINALITY_DELAY_ENV, default_value = "10")]
     finality_delay: u32,
+    /// The number of blocks over which peg-outs are batched into a single
+    /// transaction, 0 disables batching
+    #[arg(long, env = FM_PEG_OUT_BATCH_WINDOW_ENV, default_value = "0")]
+    peg_out_batch_window: u32,
 
     #[arg(long, env = FM_BIND_METRICS_API_ENV)]
     bind_metrics_api: Option<SocketAddr>,
@@ -113,6 +119,18 @@ pub struct ServerOpts {
     #[arg(long, env = FM_FORCE_API_SECRETS_ENV, default_value = "")]
     force_api_secrets: ApiSecrets,
 
+    /// Start from a consensus checkpoint downloaded with `fedimint-cli admin
+    /// consensus-checkpoint` instead of processing all past sessions. Only
+    /// used if the database does not contain any sessions yet.
+    #[arg(long, env = FM_IMPORT_CHECKPOINT_ENV)]
+    import_checkpoint: Option<PathBuf>,
+
+    /// SOCKS5 proxy, e.g. a local Tor daemon, all connections to the other
+    /// guardians are made through. Required if they are only reachable at
+    /// onion URLs.
+    #[arg(long, env = FM_SOCKS5_PROXY_ENV)]
+    socks5_proxy: Option<SocketAddr>,
+
     #[clap(subcommand)]
     subcommand: Option<ServerSubcommand>,
 }
@@ -122,6 +140,21 @@ enum ServerSubcommand {
     /// Development-related commands
     #[clap(subcommand)]
     Dev(DevSubcommand),
+    /// Recreate the config of a lost guardian in an empty data dir from a
+    /// disaster recovery bundle, then start the guardian
+    Restore {
+        /// Path to the bundle downloaded from the guardian before it was lost
+        #[arg(long)]
+        bundle: PathBuf,
+    },
+    /// Interactively set up the federation with the other guardians via the
+    /// config gen API of a guardian that is running without a config. Can be
+    /// interrupted and restarted at any step.
+    Setup {
+        /// API URL of the guardian to set up, defaults to `--api-url`
+        #[arg(long)]
+        setup_api_url: Option<SafeUrl>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -281,6 +314,7 @@ impl Fedimintd {
 
         let bitcoind_rpc = self.bitcoind_rpc.clone();
         let finality_delay = self.opts.finality_delay;
+        let peg_out_batch_window = self.opts.peg_out_batch_window;
         let s = self
             .with_module_kind(LightningInit)
             .with_module_instance(
@@ -317,6 +351,7 @@ impl Fedimintd {
                         finality_delay,
                         client_default_bitcoin_rpc: default_esplora_server(network),
                         fee_consensus: Default::default(),
+                        peg_out_batch_window,
                     },
                 },
             );
@@ -355,6 +390,28 @@ impl Fedimintd {
                     println!("{db_versions}");
                     std::process::exit(0);
                 }
+                ServerSubcommand::Setup { setup_api_url } => {
+                    let Some(data_dir) = self.opts.data_dir.clone() else {
+                        error!("data-dir option is not present");
+                        std::process::exit(1);
+                    };
+                    let result = setup::run_setup_wizard(
+                        setup_api_url
+                            .clone()
+                            .unwrap_or_else(|| self.opts.api_url.clone()),
+                        self.opts.force_api_secrets.get_active(),
+                        data_dir,
+                        self.opts.password.clone(),
+                    )
+                    .await;
+                    if let Err(error) = result {
+                        error!(?error, "Setup failed");
+                        std::process::exit(1);
+                    }
+                    std::process::exit(0);
+                }
+                // Needs the data dir and password, so it is handled when starting up
+                ServerSubcommand::Restore { .. } => {}
             }
         }
 
@@ -464,6 +521,25 @@ async fn run(
 
     let data_dir = opts.data_dir.context("data-dir option is not present")?;
 
+    let mut restored_checkpoint = None;
+    if let Some(ServerSubcommand::Restore { bundle }) = &opts.subcommand {
+        let password = opts
+            .password
+            .as_ref()
+            .context("password option is required to restore a bundle")?;
+        let bundle = serde_json::from_slice(&std::fs::read(bundle)?)
+            .context("Failed to parse disaster recovery bundle")?;
+        let restored = restore_disaster_recovery_bundle(
+            &bundle,
+            password,
+            &data_dir,
+            &module_inits,
+            opts.force_api_secrets.get_active(),
+        )?;
+        info!("Restored guardian config, missing sessions will be fetched from peers");
+        restored_checkpoint = restored.checkpoint;
+    }
+
     // TODO: Fedimintd should use the config gen API
     // on each run we want to pass the currently passed password, so we need to
     // overwrite
@@ -484,6 +560,7 @@ async fn run(
         default_params,
         max_connections: fedimint_server::config::max_connections(),
         registry: module_inits.clone(),
+        socks5_proxy: opts.socks5_proxy,
     };
 
     let db = Database::new(
@@ -491,6 +568,23 @@ async fn run(
         Default::default(),
     );
 
+    if let Some(checkpoint) = restored_checkpoint {
+        info!(
+            session_index = checkpoint.session_index,
+            header_hash = %checkpoint.header_hash,
+            "Session checkpoint of the bundle will be verified against the federation"
+        );
+        record_restored_checkpoint(&db, checkpoint).await;
+    }
+
+    let import_checkpoint = match opts.import_checkpoint {
+        Some(path) => Some(
+            serde_json::from_slice(&std::fs::read(path)?)
+                .context("Failed to parse consensus checkpoint")?,
+        ),
+        None => None,
+    };
+
     fedimint_server::run(
         data_dir,
         opts.force_api_secrets,
@@ -499,6 +593,7 @@ async fn run(
         code_version_str,
         &module_inits,
         task_group.clone(),
+        import_checkpoint,
     )
     .await?;
 
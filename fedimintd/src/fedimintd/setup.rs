@@ -0,0 +1,381 @@
+//! Interactive federation setup for guardians, see `fedimintd setup`
+//!
+//! The wizard drives the config gen API of a guardian that was started without
+//! a config. Every step is derived from the status reported by the guardian,
+//! and the answers that can't be queried from it are stored in
+//! [`SETUP_PROGRESS_FILE`], so the wizard can be interrupted and restarted at
+//! any time.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use fedimint_api_client::api::DynGlobalApi;
+use fedimint_core::admin_client::{ConfigGenConnectionsRequest, ServerStatus};
+use fedimint_core::module::ApiAuth;
+use fedimint_core::runtime::sleep;
+use fedimint_core::util::{write_overwrite, SafeUrl};
+use serde::{Deserialize, Serialize};
+
+/// File in the data dir the progress of the wizard is stored in
+pub const SETUP_PROGRESS_FILE: &str = "setup-progress.json";
+
+/// How often the guardian is polled while waiting for a status change
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Answers of the guardian that the config gen API doesn't return
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SetupProgress {
+    /// Our guardian name and the leader's API URL, `None` if we are the leader
+    connections: Option<(String, Option<SafeUrl>)>,
+    /// Whether the config gen params were submitted
+    params_set: bool,
+}
+
+impl SetupProgress {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        serde_json::from_slice(&std::fs::read(path)?)
+            .with_context(|| format!("Failed to parse setup progress {}", path.display()))
+    }
+
+    fn store(&self, path: &Path) -> anyhow::Result<()> {
+        write_overwrite(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Guides the guardian through the federation setup of the guardian reachable
+/// at `api_url` until consensus is running
+pub async fn run_setup_wizard(
+    api_url: SafeUrl,
+    api_secret: Option<String>,
+    data_dir: PathBuf,
+    password: Option<String>,
+) -> anyhow::Result<()> {
+    let api = DynGlobalApi::from_pre_peer_id_admin_endpoint(api_url.clone(), &api_secret);
+    let progress_path = data_dir.join(SETUP_PROGRESS_FILE);
+    let mut progress = SetupProgress::load(&progress_path)?;
+    let mut auth = password.map(ApiAuth);
+
+    println!("Connecting to the guardian at {api_url}...");
+    let mut status = loop {
+        match api.status().await {
+            Ok(status) => break status.server,
+            Err(error) => {
+                println!("Guardian is not reachable yet: {error}");
+                sleep(POLL_INTERVAL * 5).await;
+            }
+        }
+    };
+
+    loop {
+        match status {
+            ServerStatus::AwaitingPassword => {
+                // The guardian forgot any previous setup progress
+                progress = SetupProgress::default();
+                progress.store(&progress_path)?;
+
+                println!("Step 1: Set the password of your guardian.");
+                println!("It is needed to decrypt the config on every start, store it safely.");
+                let password = match auth.clone() {
+                    Some(auth) => auth,
+                    None => ApiAuth(prompt_new_password()?),
+                };
+                api.set_password(password.clone()).await?;
+                auth = Some(password);
+            }
+            ServerStatus::SharingConfigGenParams => {
+                let auth = get_auth(&mut auth)?;
+
+                if progress.connections.is_none() {
+                    progress.connections = Some(set_connections(&api, &auth).await?);
+                    progress.store(&progress_path)?;
+                } else if !progress.params_set {
+                    let is_leader = progress
+                        .connections
+                        .as_ref()
+                        .is_some_and(|(_, leader_api_url)| leader_api_url.is_none());
+                    set_params(&api, &auth, is_leader).await?;
+                    progress.params_set = true;
+                    progress.store(&progress_path)?;
+                } else {
+                    await_peers(&api).await?;
+
+                    println!("Step 5: Running the distributed key generation...");
+                    api.run_dkg(auth).await?;
+                }
+            }
+            ServerStatus::ReadyForConfigGen => {
+                println!("Waiting for the other guardians to start the key generation...");
+            }
+            ServerStatus::ConfigGenFailed => {
+                println!("The distributed key generation failed.");
+                if !confirm("Retry with the same guardians and params?", true)? {
+                    bail!("Key generation failed, restart the setup of all guardians");
+                }
+                api.run_dkg(get_auth(&mut auth)?).await?;
+            }
+            ServerStatus::VerifyingConfigs | ServerStatus::VerifiedConfigs => {
+                let auth = get_auth(&mut auth)?;
+                verify_configs(&api, &auth, status == ServerStatus::VerifiedConfigs).await?;
+
+                println!("Step 7: Starting consensus...");
+                // The config gen API is shut down during the call, so an error is expected
+                api.start_consensus(auth).await.ok();
+            }
+            ServerStatus::ConsensusRunning => {
+                if progress_path.exists() {
+                    std::fs::remove_file(&progress_path)?;
+                }
+                println!("Setup is complete, consensus is running.");
+                return Ok(());
+            }
+            ServerStatus::SetupRestarted => {
+                println!("Setup was restarted, waiting for all guardians to restart it...");
+            }
+        }
+
+        status = await_status_change(&api, status).await;
+    }
+}
+
+/// Returns the password of the guardian, asking for it if we don't know it
+fn get_auth(auth: &mut Option<ApiAuth>) -> anyhow::Result<ApiAuth> {
+    if auth.is_none() {
+        *auth = Some(ApiAuth(rpassword::prompt_password(
+            "Password of your guardian: ",
+        )?));
+    }
+
+    Ok(auth.clone().expect("Was set above"))
+}
+
+/// Sets our name and leader, returns them for the setup progress
+async fn set_connections(
+    api: &DynGlobalApi,
+    auth: &ApiAuth,
+) -> anyhow::Result<(String, Option<SafeUrl>)> {
+    println!("Step 2: Set the connection info of your guardian.");
+    println!(
+        "One guardian is the leader, all other guardians send their connection info to the leader."
+    );
+
+    let our_name = prompt("Your guardian name", None)?;
+    let leader_api_url = if confirm("Are you the leader?", false)? {
+        None
+    } else {
+        Some(
+            prompt(
+                "API URL of the leader, e.g. wss://fedimint.example.com",
+                None,
+            )?
+            .parse()
+            .context("Invalid leader API URL")?,
+        )
+    };
+
+    api.set_config_gen_connections(
+        ConfigGenConnectionsRequest {
+            our_name: our_name.clone(),
+            leader_api_url: leader_api_url.clone(),
+        },
+        auth.clone(),
+    )
+    .await?;
+
+    Ok((our_name, leader_api_url))
+}
+
+/// Lets the leader choose the federation name and consensus params and every
+/// guardian choose their local params
+async fn set_params(api: &DynGlobalApi, auth: &ApiAuth, is_leader: bool) -> anyhow::Result<()> {
+    println!("Step 3: Set the federation params.");
+    if !is_leader {
+        println!("Only your local params are used, the leader sets the consensus params.");
+    }
+
+    let mut params = api.get_default_config_gen_params(auth.clone()).await?;
+
+    if is_leader {
+        let default_name = params.meta.get("federation_name").cloned();
+        let federation_name = prompt("Federation name", default_name.as_deref())?;
+        params
+            .meta
+            .insert("federation_name".to_string(), federation_name);
+    }
+
+    for (module_instance_id, kind, module_params) in params.modules.iter_modules_mut() {
+        println!("Module {module_instance_id} ({kind}):");
+        println!(
+            "  local params: {}",
+            serde_json::to_string(&module_params.local)?
+        );
+        if is_leader {
+            println!(
+                "  consensus params: {}",
+                serde_json::to_string(&module_params.consensus)?
+            );
+        }
+
+        if !confirm("  Change these params?", false)? {
+            continue;
+        }
+
+        module_params.local = prompt_json("  Local params", &module_params.local)?;
+        if is_leader {
+            module_params.consensus = prompt_json("  Consensus params", &module_params.consensus)?;
+        }
+    }
+
+    api.set_config_gen_params(params, auth.clone()).await?;
+
+    Ok(())
+}
+
+/// Shows the guardians that joined the setup until the guardian confirms that
+/// all of them joined
+async fn await_peers(api: &DynGlobalApi) -> anyhow::Result<()> {
+    println!("Step 4: Wait for all guardians to join.");
+
+    loop {
+        match api.consensus_config_gen_params().await {
+            Ok(response) => {
+                println!("Guardians that joined the setup:");
+                for (peer_id, peer) in &response.consensus.peers {
+                    let marker = if *peer_id == response.our_current_id {
+                        " (you)"
+                    } else {
+                        ""
+                    };
+                    println!("  {peer_id}: {} at {}{marker}", peer.name, peer.api_url);
+                }
+            }
+            Err(error) => println!("Params of the leader are not available yet: {error}"),
+        }
+
+        if confirm("Did all guardians join? Answer no to refresh", false)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Shows the config hashes of all guardians, which have to be compared out of
+/// band, and marks the configs as verified once the guardian confirms them
+async fn verify_configs(
+    api: &DynGlobalApi,
+    auth: &ApiAuth,
+    already_verified: bool,
+) -> anyhow::Result<()> {
+    println!("Step 6: Verify the generated config.");
+    println!("Make sure every guardian sees the same hash for every guardian:");
+    for (peer_id, hash) in api.get_verify_config_hash(auth.clone()).await? {
+        println!("  {peer_id}: {hash}");
+    }
+
+    if already_verified {
+        return Ok(());
+    }
+
+    if !confirm("Do the hashes of all guardians match?", false)? {
+        bail!("Config verification failed, restart the setup of all guardians");
+    }
+
+    api.verified_configs(auth.clone()).await?;
+
+    Ok(())
+}
+
+/// Polls the guardian until its status is different from `status`
+async fn await_status_change(api: &DynGlobalApi, status: ServerStatus) -> ServerStatus {
+    loop {
+        match api.status().await {
+            Ok(response) if response.server != status => return response.server,
+            // The guardian progressed on its own, e.g. while waiting for peers
+            Ok(_) if status == ServerStatus::SharingConfigGenParams => return status,
+            Ok(_) => {}
+            // The API is restarted when consensus is started
+            Err(_)
+                if matches!(
+                    status,
+                    ServerStatus::VerifyingConfigs | ServerStatus::VerifiedConfigs
+                ) => {}
+            Err(error) => println!("Failed to fetch the guardian status: {error}"),
+        }
+
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Reads a line from stdin after printing `question`
+fn read_answer(question: &str) -> anyhow::Result<String> {
+    print!("{question}");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        bail!("Setup aborted");
+    }
+
+    Ok(answer.trim().to_string())
+}
+
+fn prompt(question: &str, default: Option<&str>) -> anyhow::Result<String> {
+    loop {
+        let answer = match default {
+            Some(default) => read_answer(&format!("{question} [{default}]: "))?,
+            None => read_answer(&format!("{question}: "))?,
+        };
+
+        match (answer.is_empty(), default) {
+            (false, _) => return Ok(answer),
+            (true, Some(default)) => return Ok(default.to_string()),
+            (true, None) => println!("An answer is required"),
+        }
+    }
+}
+
+fn confirm(question: &str, default: bool) -> anyhow::Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+
+    loop {
+        match read_answer(&format!("{question} [{hint}]: "))?
+            .to_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer yes or no"),
+        }
+    }
+}
+
+fn prompt_json(question: &str, current: &serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    loop {
+        let answer = prompt(question, Some(&serde_json::to_string(current)?))?;
+        match serde_json::from_str(&answer) {
+            Ok(value) => return Ok(value),
+            Err(error) => println!("Invalid JSON: {error}"),
+        }
+    }
+}
+
+fn prompt_new_password() -> anyhow::Result<String> {
+    loop {
+        let password = rpassword::prompt_password("New password: ")?;
+        if password.is_empty() {
+            println!("The password must not be empty");
+            continue;
+        }
+
+        if rpassword::prompt_password("Repeat the password: ")? == password {
+            return Ok(password);
+        }
+        println!("The passwords don't match");
+    }
+}
@@ -3,21 +3,31 @@
 #![allow(clippy::missing_panics_doc)]
 #![allow(clippy::too_many_lines)]
 
-use std::time::Duration;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::bail;
 use bitcoin::address::NetworkUnchecked;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::sha256;
 use bitcoin::Address;
 use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_core::config::FederationId;
+use fedimint_core::core::ModuleKind;
 use fedimint_core::util::{retry, ConstantBackoff, SafeUrl};
-use fedimint_core::{fedimint_build_code_version_env, BitcoinAmountOrAll};
+use fedimint_core::{fedimint_build_code_version_env, secp256k1, Amount, BitcoinAmountOrAll};
 use fedimint_logging::TracingSetup;
+use ln_gateway::envs::FM_GATEWAY_CONNECTIONS_PASSWORD_ENV;
 use ln_gateway::rpc::rpc_client::GatewayRpcClient;
 use ln_gateway::rpc::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, FederationRoutingFees, GetFundingAddressPayload,
-    LeaveFedPayload, OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload,
+    BackupPayload, BalancePayload, CloseChannelPayload, CloseChannelsWithPeerPayload,
+    ConfigPayload, ConnectFedPayload, ConnectToPeerPayload, DepositAddressPayload,
+    ExportConnectionsPayload, ExportDbPayload, FederationPolicy, FederationRoutingFees,
+    GatewayConfigFile, GetChannelPayload, GetFundingAddressPayload, GetPaymentProgressPayload,
+    HtlcResolution, ImportConnectionsPayload, LeaveFedPayload, ListPaymentsPayload,
+    OpenChannelPayload, RegisterPublicReceiverPayload, ResolveHtlcPayload, RestorePayload,
+    RiskLimits, SetConfigurationPayload, SetFederationRegistrationPayload, SetL402ConfigPayload,
+    SetNostrConfigPayload, SetRouteHintPrivacyPayload, ShutdownPayload, WithdrawPayload,
     V1_API_ENDPOINT,
 };
 use serde::Serialize;
@@ -112,6 +122,228 @@ pub enum Commands {
         /// other federations not given here will keep their current fees.
         #[clap(long)]
         per_federation_routing_fees: Option<Vec<PerFederationRoutingFees>>,
+
+        /// Seconds between two checks whether the route hints of the lightning
+        /// node changed, 0 disables the checks
+        #[clap(long)]
+        route_hint_refresh_interval_secs: Option<u64>,
+
+        /// Number of consecutive checks that have to see changed route hints
+        /// before the gateway re-registers with a federation
+        #[clap(long)]
+        route_hint_refresh_hysteresis: Option<u32>,
+    },
+    /// Register a receiver that can be paid through the public, rate-limited
+    /// invoice endpoint. Prints the token identifying the receiver.
+    RegisterPublicReceiver {
+        #[clap(long)]
+        federation_id: FederationId,
+
+        /// LNv2 static public key of the receiving user
+        #[clap(long)]
+        recipient_static_pk: secp256k1::PublicKey,
+
+        #[clap(long)]
+        max_invoice_amount: Amount,
+
+        #[clap(long, default_value_t = 60)]
+        max_invoices_per_hour: u32,
+    },
+    /// List finished payments from the payment history, newest first
+    ListPayments {
+        #[clap(long)]
+        federation_id: Option<FederationId>,
+
+        /// Only list payments completed at or after this UNIX timestamp
+        #[clap(long)]
+        start_time: Option<u64>,
+
+        /// Only list payments completed at or before this UNIX timestamp
+        #[clap(long)]
+        end_time: Option<u64>,
+
+        #[clap(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// List intercepted HTLCs paying a federation that are not settled or
+    /// cancelled yet
+    ListPendingHtlcs,
+    /// Manually settle or cancel a pending HTLC, e.g. if completing it is
+    /// stuck. The resolution is recorded and can't be undone.
+    ResolveHtlc {
+        #[clap(long)]
+        incoming_chan_id: u64,
+
+        #[clap(long)]
+        htlc_id: u64,
+
+        /// Settle the HTLC with this hex encoded preimage
+        #[clap(long, required_unless_present = "cancel_reason")]
+        preimage: Option<String>,
+
+        /// Cancel the HTLC for this reason
+        #[clap(long, conflicts_with = "preimage")]
+        cancel_reason: Option<String>,
+    },
+    /// Show the HTLC attempts the lightning node made for a recent outgoing
+    /// payment, e.g. to find out why it is stuck
+    PaymentProgress {
+        #[clap(long)]
+        payment_hash: sha256::Hash,
+    },
+    /// List the SCID aliases assigned to federations the gateway has been
+    /// connected to
+    ListScidAliases,
+    /// Hide the channels of the lightning node from invoices of a federation
+    /// by routing them through a random phantom SCID. Enabling it again
+    /// assigns a new phantom SCID.
+    SetRouteHintPrivacy {
+        #[clap(long)]
+        federation_id: FederationId,
+        /// Disable route hint privacy instead
+        #[clap(long)]
+        disable: bool,
+    },
+    /// Set the lightning alias and SCID base the gateway registers with a
+    /// federation, so its invoices can be told apart from those of other
+    /// federations. Omitted values fall back to the alias of the node and the
+    /// SCID alias of the federation.
+    SetFederationRegistration {
+        #[clap(long)]
+        federation_id: FederationId,
+        #[clap(long)]
+        lightning_alias: Option<String>,
+        /// Has to encode a funding block height of at least 1 and an output
+        /// index of 0, and must not be used by a channel of the node or
+        /// another federation
+        #[clap(long)]
+        scid_base: Option<u64>,
+    },
+    /// Show the routing fees the gateway earned, per federation and direction
+    GetEarnings,
+    /// Show the forwarding and rebalancing statistics of the lightning node
+    /// together with the routing fees earned from federations
+    NodeStats,
+    /// Export the federation connections of the gateway, encrypted with a
+    /// password, so they can be imported into another gateway
+    ExportConnections {
+        /// Prompted for if not set
+        #[clap(long, env = FM_GATEWAY_CONNECTIONS_PASSWORD_ENV, hide_env_values = true)]
+        password: Option<String>,
+
+        /// File the encrypted connections are written to
+        #[clap(long)]
+        output: PathBuf,
+    },
+    /// Export the gateway database and the client databases of all
+    /// federations, encrypted with a password, so the gateway can be restored
+    /// on another machine with `gatewayd import-db`
+    ExportDb {
+        #[clap(long)]
+        password: String,
+
+        /// File the encrypted archive is written to
+        #[clap(long)]
+        output: PathBuf,
+    },
+    /// Connect the gateway to all federations of a connections export,
+    /// skipping the ones it is already connected to
+    ImportConnections {
+        /// Prompted for if not set
+        #[clap(long, env = FM_GATEWAY_CONNECTIONS_PASSWORD_ENV, hide_env_values = true)]
+        password: Option<String>,
+
+        /// File containing the encrypted connections
+        #[clap(long)]
+        input: PathBuf,
+    },
+    /// Print the current gateway settings as TOML, in the same format as the
+    /// gatewayd `--config` file
+    GetConfig {
+        /// File the settings are written to instead of stdout
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// Apply gateway settings from a TOML file, e.g. one written by
+    /// `get-config`. Settings missing from the file are left unchanged.
+    ApplyConfig {
+        /// TOML file containing the settings
+        #[clap(long)]
+        input: PathBuf,
+    },
+    /// Show the policy restricting which federations the gateway connects to
+    /// and serves
+    GetFederationPolicy,
+    /// Replace the policy restricting which federations the gateway connects
+    /// to and serves. Without any options every federation is allowed.
+    SetFederationPolicy {
+        /// Only allow these federations, can be given multiple times
+        #[clap(long = "allow")]
+        allowed_federations: Vec<FederationId>,
+        /// Never allow these federations, can be given multiple times
+        #[clap(long = "deny")]
+        denied_federations: Vec<FederationId>,
+        /// Minimum number of guardians that have to agree on consensus
+        #[clap(long)]
+        min_guardian_threshold: Option<u64>,
+        /// Module kinds a federation has to offer, can be given multiple times
+        #[clap(long = "require-module")]
+        required_modules: Vec<String>,
+    },
+    /// Show the risk limits of the gateway and their current utilization
+    GetRiskStatus,
+    /// Replace the limits on the payments the gateway makes. Limits that are
+    /// not given are not enforced.
+    SetRiskLimits {
+        /// Maximum amount of a single payment
+        #[clap(long)]
+        max_payment_amount: Option<Amount>,
+        /// Maximum amount paid out on behalf of a single federation within an
+        /// hour
+        #[clap(long)]
+        max_hourly_outgoing_volume: Option<Amount>,
+        /// Maximum amount paid out on behalf of a single federation within a
+        /// day
+        #[clap(long)]
+        max_daily_outgoing_volume: Option<Amount>,
+        /// Maximum total amount of pending payments
+        #[clap(long)]
+        max_pending_exposure: Option<Amount>,
+    },
+    /// Show the nostr public key zap receipts are signed with
+    GetNostrConfig,
+    /// Sign and publish zap receipts for public invoices paying zap requests
+    /// (NIP-57). Zap receipts are disabled if no secret key is given.
+    SetNostrConfig {
+        /// Hex encoded nostr secret key zap receipts are signed with
+        #[clap(long)]
+        secret_key: Option<secp256k1::SecretKey>,
+        /// Relay zap receipts are published to in addition to the relays
+        /// listed in the zap request, can be given multiple times
+        #[clap(long = "relay")]
+        relays: Vec<SafeUrl>,
+    },
+    /// Show the price of the L402 tokens required to create public invoices
+    GetL402Config,
+    /// Require an L402 token, paid over lightning, to create public invoices.
+    /// L402 authentication is disabled if no price is given.
+    SetL402Config {
+        /// Price of a token
+        #[clap(long)]
+        price: Option<Amount>,
+        /// Number of seconds a token remains valid, defaults to a day
+        #[clap(long)]
+        validity_secs: Option<u64>,
+        /// Generate a new root key, revoking all previously minted tokens
+        #[clap(long)]
+        rotate_root_key: bool,
+    },
+    /// Stop intercepting new HTLCs, wait for in-flight payments to settle or
+    /// refund and shut the gateway down. Prints the drain report.
+    Shutdown {
+        /// Maximum number of seconds to wait for in-flight payments
+        #[clap(long)]
+        timeout_secs: Option<u64>,
     },
     #[command(subcommand)]
     Lightning(LightningCommands),
@@ -155,6 +387,27 @@ pub enum LightningCommands {
     },
     /// List active channels
     ListActiveChannels,
+    /// List all channels, including channels that are being opened and
+    /// channels that are pending close
+    ListChannels,
+    /// Show a single channel
+    GetChannel {
+        /// The short channel id of the channel
+        #[clap(long)]
+        scid: u64,
+    },
+    /// Close a single channel, claiming the funds to the lightning node's
+    /// on-chain wallet
+    CloseChannel {
+        /// The funding outpoint of the channel, as shown by `list-channels`
+        #[clap(long)]
+        channel_id: bitcoin::OutPoint,
+
+        /// Broadcast the latest commitment transaction instead of closing the
+        /// channel cooperatively with the peer
+        #[clap(long)]
+        force: bool,
+    },
     /// Wait for the lightning node to be synced with the blockchain
     WaitForChainSync {
         /// The block height to wait for
@@ -289,6 +542,8 @@ async fn main() -> anyhow::Result<()> {
             routing_fees,
             network,
             per_federation_routing_fees,
+            route_hint_refresh_interval_secs,
+            route_hint_refresh_hysteresis,
         } => {
             let per_federation_routing_fees = per_federation_routing_fees
                 .map(|input| input.into_iter().map(Into::into).collect());
@@ -299,9 +554,241 @@ async fn main() -> anyhow::Result<()> {
                     routing_fees,
                     network,
                     per_federation_routing_fees,
+                    route_hint_refresh_interval_secs,
+                    route_hint_refresh_hysteresis,
                 })
                 .await?;
         }
+        Commands::RegisterPublicReceiver {
+            federation_id,
+            recipient_static_pk,
+            max_invoice_amount,
+            max_invoices_per_hour,
+        } => {
+            let response = client()
+                .register_public_receiver(RegisterPublicReceiverPayload {
+                    federation_id,
+                    recipient_static_pk,
+                    max_invoice_amount,
+                    max_invoices_per_hour,
+                })
+                .await?;
+
+            print_response(response);
+        }
+        Commands::ListPayments {
+            federation_id,
+            start_time,
+            end_time,
+            limit,
+        } => {
+            let to_system_time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+            let response = client()
+                .list_payments(ListPaymentsPayload {
+                    federation_id,
+                    start_time: start_time.map(to_system_time),
+                    end_time: end_time.map(to_system_time),
+                    limit,
+                    start_after: None,
+                })
+                .await?;
+
+            print_response(response);
+        }
+        Commands::ListPendingHtlcs => {
+            let response = client().list_pending_htlcs().await?;
+
+            print_response(response);
+        }
+        Commands::ResolveHtlc {
+            incoming_chan_id,
+            htlc_id,
+            preimage,
+            cancel_reason,
+        } => {
+            let resolution = match (preimage, cancel_reason) {
+                (Some(preimage), None) => HtlcResolution::Settle {
+                    preimage: Vec::from_hex(&preimage)?,
+                },
+                (None, Some(reason)) => HtlcResolution::Cancel { reason },
+                _ => bail!("Either --preimage or --cancel-reason has to be given"),
+            };
+
+            client()
+                .resolve_htlc(ResolveHtlcPayload {
+                    incoming_chan_id,
+                    htlc_id,
+                    resolution,
+                })
+                .await?;
+        }
+        Commands::ListScidAliases => {
+            let response = client().list_scid_aliases().await?;
+
+            print_response(response);
+        }
+        Commands::SetRouteHintPrivacy {
+            federation_id,
+            disable,
+        } => {
+            client()
+                .set_route_hint_privacy(SetRouteHintPrivacyPayload {
+                    federation_id,
+                    enabled: !disable,
+                })
+                .await?;
+        }
+        Commands::SetFederationRegistration {
+            federation_id,
+            lightning_alias,
+            scid_base,
+        } => {
+            client()
+                .set_federation_registration(SetFederationRegistrationPayload {
+                    federation_id,
+                    lightning_alias,
+                    scid_base,
+                })
+                .await?;
+        }
+        Commands::GetEarnings => {
+            let response = client().get_earnings().await?;
+
+            print_response(response);
+        }
+        Commands::NodeStats => {
+            let response = client().node_stats().await?;
+
+            print_response(response);
+        }
+        Commands::ExportConnections { password, output } => {
+            let password = match password {
+                Some(password) => password,
+                None => prompt_new_password()?,
+            };
+            let connections = client()
+                .export_connections(ExportConnectionsPayload { password })
+                .await?;
+
+            std::fs::write(&output, serde_json::to_string_pretty(&connections)?)?;
+        }
+        Commands::ExportDb { password, output } => {
+            let archive = client().export_db(ExportDbPayload { password }).await?;
+
+            std::fs::write(&output, serde_json::to_string_pretty(&archive)?)?;
+        }
+        Commands::ImportConnections { password, input } => {
+            let connections = serde_json::from_slice(&std::fs::read(&input)?)?;
+            let password = match password {
+                Some(password) => password,
+                None => rpassword::prompt_password("Password: ")?,
+            };
+            let response = client()
+                .import_connections(ImportConnectionsPayload {
+                    password,
+                    connections,
+                })
+                .await?;
+
+            print_response(response);
+        }
+        Commands::GetConfig { output } => {
+            let config = toml::to_string_pretty(&client().get_config().await?)?;
+
+            match output {
+                Some(output) => std::fs::write(&output, config)?,
+                None => print!("{config}"),
+            }
+        }
+        Commands::ApplyConfig { input } => {
+            let config: GatewayConfigFile = toml::from_str(&std::fs::read_to_string(&input)?)?;
+
+            client().apply_config(config).await?;
+        }
+        Commands::PaymentProgress { payment_hash } => {
+            let response = client()
+                .get_payment_progress(GetPaymentProgressPayload { payment_hash })
+                .await?;
+
+            print_response(response);
+        }
+        Commands::GetFederationPolicy => {
+            let response = client().get_federation_policy().await?;
+
+            print_response(response);
+        }
+        Commands::SetFederationPolicy {
+            allowed_federations,
+            denied_federations,
+            min_guardian_threshold,
+            required_modules,
+        } => {
+            let policy = FederationPolicy {
+                allowed_federations: (!allowed_federations.is_empty())
+                    .then(|| allowed_federations.into_iter().collect()),
+                denied_federations: denied_federations.into_iter().collect(),
+                min_guardian_threshold,
+                required_modules: required_modules
+                    .iter()
+                    .map(|kind| ModuleKind::clone_from_str(kind))
+                    .collect(),
+            };
+
+            client().set_federation_policy(policy).await?;
+        }
+        Commands::GetRiskStatus => {
+            let response = client().get_risk_status().await?;
+
+            print_response(response);
+        }
+        Commands::SetRiskLimits {
+            max_payment_amount,
+            max_hourly_outgoing_volume,
+            max_daily_outgoing_volume,
+            max_pending_exposure,
+        } => {
+            client()
+                .set_risk_limits(RiskLimits {
+                    max_payment_amount,
+                    max_hourly_outgoing_volume,
+                    max_daily_outgoing_volume,
+                    max_pending_exposure,
+                })
+                .await?;
+        }
+        Commands::GetNostrConfig => {
+            let response = client().get_nostr_config().await?;
+
+            print_response(response);
+        }
+        Commands::SetNostrConfig { secret_key, relays } => {
+            client()
+                .set_nostr_config(SetNostrConfigPayload { secret_key, relays })
+                .await?;
+        }
+        Commands::GetL402Config => {
+            let response = client().get_l402_config().await?;
+
+            print_response(response);
+        }
+        Commands::SetL402Config {
+            price,
+            validity_secs,
+            rotate_root_key,
+        } => {
+            client()
+                .set_l402_config(SetL402ConfigPayload {
+                    price,
+                    validity_secs,
+                    rotate_root_key,
+                })
+                .await?;
+        }
+        Commands::Shutdown { timeout_secs } => {
+            let response = client().shutdown(ShutdownPayload { timeout_secs }).await?;
+
+            print_response(response);
+        }
 
         Commands::Lightning(lightning_command) => match lightning_command {
             LightningCommands::ConnectToPeer { pubkey, host } => {
@@ -339,6 +826,23 @@ async fn main() -> anyhow::Result<()> {
                 let response = client().list_active_channels().await?;
                 print_response(response);
             }
+            LightningCommands::ListChannels => {
+                let response = client().list_channels().await?;
+                print_response(response);
+            }
+            LightningCommands::GetChannel { scid } => {
+                let response = client()
+                    .get_channel(GetChannelPayload {
+                        short_channel_id: scid,
+                    })
+                    .await?;
+                print_response(response);
+            }
+            LightningCommands::CloseChannel { channel_id, force } => {
+                client()
+                    .close_channel(CloseChannelPayload { channel_id, force })
+                    .await?;
+            }
             LightningCommands::WaitForChainSync {
                 block_height,
                 max_retries,
@@ -377,3 +881,19 @@ pub fn print_response<T: Serialize>(val: T) {
         serde_json::to_string_pretty(&val).expect("Cannot serialize")
     );
 }
+
+/// Prompts for a new password until it is entered the same way twice
+fn prompt_new_password() -> anyhow::Result<String> {
+    loop {
+        let password = rpassword::prompt_password("New password: ")?;
+        if password.is_empty() {
+            println!("The password must not be empty");
+            continue;
+        }
+
+        if rpassword::prompt_password("Repeat the password: ")? == password {
+            return Ok(password);
+        }
+        println!("The passwords don't match");
+    }
+}
@@ -25,14 +25,19 @@ use ln_gateway::gateway_lnrpc::gateway_lightning_server::{
     GatewayLightning, GatewayLightningServer,
 };
 use ln_gateway::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+use ln_gateway::gateway_lnrpc::htlc_attempt::Status as HtlcAttemptStatus;
 use ln_gateway::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use ln_gateway::gateway_lnrpc::list_active_channels_response::ChannelInfo;
+use ln_gateway::gateway_lnrpc::list_channels_response::{ChannelDetails, ChannelState};
+use ln_gateway::gateway_lnrpc::pay_invoice_update::Update;
 use ln_gateway::gateway_lnrpc::{
-    CloseChannelsWithPeerRequest, CloseChannelsWithPeerResponse, ConnectToPeerRequest,
-    CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
-    InterceptHtlcRequest, InterceptHtlcResponse, ListActiveChannelsResponse, OpenChannelRequest,
-    PayInvoiceRequest, PayInvoiceResponse,
+    CancelHoldInvoiceRequest, CloseChannelRequest, CloseChannelsWithPeerRequest,
+    CloseChannelsWithPeerResponse, ConnectToPeerRequest, CreateInvoiceRequest,
+    CreateInvoiceResponse, EmptyRequest, EmptyResponse, GetFundingAddressResponse,
+    GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse, HtlcAttempt,
+    InterceptHtlcRequest, InterceptHtlcResponse, ListActiveChannelsResponse, ListChannelsResponse,
+    OpenChannelRequest, PayInvoiceRequest, PayInvoiceResponse, PayInvoiceUpdate, PayKeysendRequest,
+    SettleHoldInvoiceRequest,
 };
 use rand::rngs::OsRng;
 use rand::Rng;
@@ -47,6 +52,9 @@ use tracing::{debug, error, info, warn};
 
 const MAX_HTLC_PROCESSING_DURATION: Duration = Duration::MAX;
 
+/// Interval at which the HTLC attempts of a streamed payment are polled
+const PAYMENT_ATTEMPT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Parser)]
 #[command(version)]
 struct ClnExtensionOpts {
@@ -206,6 +214,23 @@ impl ClnRpcService {
         })
     }
 
+    /// Lists the channels of the node in all states
+    async fn list_peer_channels(&self) -> Result<Vec<ListpeerchannelsChannels>, ClnExtensionError> {
+        match self
+            .rpc_client()
+            .await?
+            .call(cln_rpc::Request::ListPeerChannels(
+                model::requests::ListpeerchannelsRequest { id: None },
+            ))
+            .await?
+        {
+            cln_rpc::Response::ListPeerChannels(model::responses::ListpeerchannelsResponse {
+                channels,
+            }) => Ok(channels.unwrap_or_default()),
+            _ => Err(ClnExtensionError::RpcWrongResponse),
+        }
+    }
+
     async fn info(&self) -> Result<(PublicKey, String, String, u32, bool), ClnExtensionError> {
         self.rpc_client()
             .await?
@@ -233,6 +258,106 @@ impl ClnRpcService {
             })
             .map_err(ClnExtensionError::RpcError)?
     }
+
+    /// Pays an invoice, returning once the payment succeeded or failed.
+    async fn pay(
+        mut rpc: cln_rpc::ClnRpc,
+        request: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, Status> {
+        let PayInvoiceRequest {
+            invoice,
+            max_delay,
+            max_fee_msat,
+            payment_hash: _,
+        } = request;
+
+        rpc.call(cln_rpc::Request::Pay(model::requests::PayRequest {
+            bolt11: invoice,
+            amount_msat: None,
+            label: None,
+            riskfactor: None,
+            retry_for: None,
+            maxdelay: Some(max_delay as u16),
+            exemptfee: None,
+            localinvreqid: None,
+            exclude: None,
+            maxfee: Some(cln_rpc::primitives::Amount::from_msat(max_fee_msat)),
+            maxfeepercent: None,
+            description: None,
+        }))
+        .await
+        .map(|response| match response {
+            cln_rpc::Response::Pay(model::responses::PayResponse {
+                payment_preimage,
+                amount_msat,
+                amount_sent_msat,
+                ..
+            }) => Ok(PayInvoiceResponse {
+                preimage: payment_preimage.to_vec(),
+                shards: vec![],
+                fee_msat: amount_sent_msat.msat().saturating_sub(amount_msat.msat()),
+            }),
+            _ => Err(ClnExtensionError::RpcWrongResponse),
+        })
+        .map_err(|e| {
+            error!("cln pay rpc returned error {:?}", e);
+            Status::internal(e.to_string())
+        })?
+        .map_err(|e| Status::internal(e.to_string()))
+    }
+
+    /// Lists the HTLC attempts lightningd made so far to pay `invoice`.
+    async fn htlc_attempts(
+        rpc: &mut cln_rpc::ClnRpc,
+        invoice: &str,
+    ) -> Result<Vec<HtlcAttempt>, ClnExtensionError> {
+        rpc.call(cln_rpc::Request::ListSendPays(
+            model::requests::ListsendpaysRequest {
+                bolt11: Some(invoice.to_string()),
+                payment_hash: None,
+                status: None,
+                index: None,
+                start: None,
+                limit: None,
+            },
+        ))
+        .await
+        .map(|response| match response {
+            cln_rpc::Response::ListSendPays(model::responses::ListsendpaysResponse {
+                payments,
+            }) => Ok(payments
+                .into_iter()
+                .map(|payment| {
+                    let status = match payment.status {
+                        model::responses::ListsendpaysPaymentsStatus::PENDING => {
+                            HtlcAttemptStatus::InFlight
+                        }
+                        model::responses::ListsendpaysPaymentsStatus::COMPLETE => {
+                            HtlcAttemptStatus::Succeeded
+                        }
+                        model::responses::ListsendpaysPaymentsStatus::FAILED => {
+                            HtlcAttemptStatus::Failed
+                        }
+                    };
+                    let amount_msat = payment.amount_msat.map_or(0, |amount| amount.msat());
+                    let amount_sent_msat = payment.amount_sent_msat.msat();
+
+                    HtlcAttempt {
+                        attempt_id: payment.id,
+                        status: status.into(),
+                        amount_msat,
+                        fee_msat: amount_sent_msat.saturating_sub(amount_msat),
+                        // lightningd doesn't report the route of past attempts
+                        num_hops: 0,
+                        failure_reason: (status == HtlcAttemptStatus::Failed)
+                            .then(|| "HTLC attempt failed".to_string()),
+                    }
+                })
+                .collect()),
+            _ => Err(ClnExtensionError::RpcWrongResponse),
+        })
+        .map_err(ClnExtensionError::RpcError)?
+    }
 }
 
 #[tonic::async_trait]
@@ -381,47 +506,95 @@ impl GatewayLightning for ClnRpcService {
         &self,
         request: tonic::Request<PayInvoiceRequest>,
     ) -> Result<tonic::Response<PayInvoiceResponse>, tonic::Status> {
-        let PayInvoiceRequest {
-            invoice,
-            max_delay,
-            max_fee_msat,
-            payment_hash: _,
-        } = request.into_inner();
+        let rpc = self
+            .rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let outcome = Self::pay(rpc, request.into_inner()).await?;
+
+        Ok(tonic::Response::new(outcome))
+    }
+
+    type PayInvoiceStreamStream = ReceiverStream<Result<PayInvoiceUpdate, Status>>;
 
-        let outcome = self
+    async fn pay_invoice_stream(
+        &self,
+        request: tonic::Request<PayInvoiceRequest>,
+    ) -> Result<tonic::Response<Self::PayInvoiceStreamStream>, Status> {
+        let request = request.into_inner();
+        let invoice = request.invoice.clone();
+        let pay_rpc = self
             .rpc_client()
             .await
-            .map_err(|e| Status::internal(e.to_string()))?
-            .call(cln_rpc::Request::Pay(model::requests::PayRequest {
-                bolt11: invoice,
-                amount_msat: None,
-                label: None,
-                riskfactor: None,
-                retry_for: None,
-                maxdelay: Some(max_delay as u16),
-                exemptfee: None,
-                localinvreqid: None,
-                exclude: None,
-                maxfee: Some(cln_rpc::primitives::Amount::from_msat(max_fee_msat)),
-                maxfeepercent: None,
-                description: None,
-            }))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let mut status_rpc = self
+            .rpc_client()
             .await
-            .map(|response| match response {
-                cln_rpc::Response::Pay(model::responses::PayResponse {
-                    payment_preimage, ..
-                }) => Ok(PayInvoiceResponse {
-                    preimage: payment_preimage.to_vec(),
-                }),
-                _ => Err(ClnExtensionError::RpcWrongResponse),
-            })
-            .map_err(|e| {
-                error!("cln pay rpc returned error {:?}", e);
-                tonic::Status::internal(e.to_string())
-            })?
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            .map_err(|e| Status::internal(e.to_string()))?;
 
-        Ok(tonic::Response::new(outcome))
+        let (sender, receiver) = mpsc::channel::<Result<PayInvoiceUpdate, Status>>(100);
+
+        self.task_group
+            .spawn("pay invoice stream", move |_| async move {
+                let payment = Self::pay(pay_rpc, request);
+                tokio::pin!(payment);
+
+                // `pay` blocks until the payment is resolved, so the attempts are
+                // polled concurrently and only changes are streamed to the gateway
+                let mut reported_attempts = BTreeMap::new();
+                let outcome = loop {
+                    tokio::select! {
+                        outcome = &mut payment => break outcome,
+                        () = tokio::time::sleep(PAYMENT_ATTEMPT_POLL_INTERVAL) => {}
+                    }
+
+                    let attempts = match Self::htlc_attempts(&mut status_rpc, &invoice).await {
+                        Ok(attempts) => attempts,
+                        Err(e) => {
+                            warn!("Failed to list HTLC attempts of payment: {e:?}");
+                            continue;
+                        }
+                    };
+
+                    for attempt in attempts {
+                        if reported_attempts.insert(attempt.attempt_id, attempt.status)
+                            == Some(attempt.status)
+                        {
+                            continue;
+                        }
+
+                        let update = PayInvoiceUpdate {
+                            update: Some(Update::Attempt(attempt)),
+                        };
+                        // The gateway may stop listening, but the payment has to proceed
+                        let _ = sender.send(Ok(update)).await;
+                    }
+                };
+
+                let update = match outcome {
+                    Ok(response) => Update::Success(response),
+                    Err(status) => Update::FailureReason(status.message().to_string()),
+                };
+                let _ = sender
+                    .send(Ok(PayInvoiceUpdate {
+                        update: Some(update),
+                    }))
+                    .await;
+            });
+
+        Ok(tonic::Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn pay_keysend(
+        &self,
+        _request: tonic::Request<PayKeysendRequest>,
+    ) -> Result<tonic::Response<PayInvoiceResponse>, tonic::Status> {
+        // CLN's keysend always generates the preimage itself, but the outgoing
+        // contract is locked to the hash of the preimage chosen by the client
+        Err(Status::unimplemented(
+            "CLN cannot send keysend payments with a preimage chosen by the client",
+        ))
     }
 
     type RouteHtlcsStream = ReceiverStream<Result<InterceptHtlcRequest, Status>>;
@@ -452,7 +625,7 @@ impl GatewayLightning for ClnRpcService {
             ..
         } = intercept_response.into_inner();
 
-        if let Some(outcome) = self
+        if let Some((_, outcome)) = self
             .interceptor
             .outcomes
             .lock()
@@ -541,9 +714,16 @@ impl GatewayLightning for ClnRpcService {
         let network =
             Currency::from_str(info.2.as_str()).map_err(|e| Status::internal(e.to_string()))?;
 
+        // A zero amount creates an amountless invoice
+        let builder = InvoiceBuilder::new(network);
+        let builder = if amount_msat == 0 {
+            builder
+        } else {
+            builder.amount_milli_satoshis(amount_msat)
+        };
+
         let invoice = match description {
-            Description::Direct(description) => InvoiceBuilder::new(network)
-                .amount_milli_satoshis(amount_msat)
+            Description::Direct(description) => builder
                 .invoice_description(lightning_invoice::Bolt11InvoiceDescription::Direct(
                     &lightning_invoice::Description::new(description)
                         .expect("Description is valid"),
@@ -560,8 +740,7 @@ impl GatewayLightning for ClnRpcService {
                         .sign_ecdsa_recoverable(m, &SecretKey::new(&mut OsRng))
                 })
                 .map_err(|e| Status::internal(e.to_string()))?,
-            Description::Hash(hash) => InvoiceBuilder::new(network)
-                .amount_milli_satoshis(amount_msat)
+            Description::Hash(hash) => builder
                 .invoice_description(lightning_invoice::Bolt11InvoiceDescription::Hash(
                     &lightning_invoice::Sha256(
                         bitcoin_hashes::sha256::Hash::from_slice(&hash)
@@ -608,6 +787,48 @@ impl GatewayLightning for ClnRpcService {
         Ok(tonic::Response::new(response))
     }
 
+    async fn settle_hold_invoice(
+        &self,
+        request: tonic::Request<SettleHoldInvoiceRequest>,
+    ) -> Result<tonic::Response<EmptyResponse>, Status> {
+        let preimage: [u8; 32] = request
+            .into_inner()
+            .preimage
+            .as_slice()
+            .try_into()
+            .map_err(|e: TryFromSliceError| Status::invalid_argument(e.to_string()))?;
+
+        let resolved = self
+            .interceptor
+            .resolve_held_htlcs(
+                sha256::Hash::hash(&preimage),
+                serde_json::json!({ "result": "resolve", "payment_key": preimage.encode_hex::<String>() }),
+            )
+            .await;
+
+        if resolved == 0 {
+            return Err(Status::not_found("No held HTLCs found for this invoice"));
+        }
+
+        Ok(tonic::Response::new(EmptyResponse {}))
+    }
+
+    async fn cancel_hold_invoice(
+        &self,
+        request: tonic::Request<CancelHoldInvoiceRequest>,
+    ) -> Result<tonic::Response<EmptyResponse>, Status> {
+        let payment_hash = sha256::Hash::from_slice(&request.into_inner().payment_hash)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        // There is nothing to cancel if no HTLC paying the invoice arrived yet, the
+        // invoice is unknown to CLN so later HTLCs are intercepted as well
+        self.interceptor
+            .resolve_held_htlcs(payment_hash, htlc_processing_failure())
+            .await;
+
+        Ok(tonic::Response::new(EmptyResponse {}))
+    }
+
     async fn connect_to_peer(
         &self,
         request: tonic::Request<ConnectToPeerRequest>,
@@ -838,6 +1059,115 @@ impl GatewayLightning for ClnRpcService {
             channels,
         }))
     }
+
+    async fn list_channels(
+        &self,
+        _request: tonic::Request<EmptyRequest>,
+    ) -> Result<tonic::Response<ListChannelsResponse>, Status> {
+        let channels = self
+            .list_peer_channels()
+            .await
+            .map_err(|e| {
+                error!("cln listpeerchannels rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?
+            .into_iter()
+            .filter_map(channel_details)
+            .collect();
+
+        Ok(tonic::Response::new(ListChannelsResponse { channels }))
+    }
+
+    async fn close_channel(
+        &self,
+        request: tonic::Request<CloseChannelRequest>,
+    ) -> Result<tonic::Response<EmptyResponse>, Status> {
+        let CloseChannelRequest { channel_id, force } = request.into_inner();
+
+        let channel = self
+            .list_peer_channels()
+            .await
+            .map_err(|e| tonic::Status::internal(e.to_string()))?
+            .into_iter()
+            .find(|channel| funding_outpoint(channel).as_ref() == Some(&channel_id))
+            .ok_or_else(|| Status::not_found(format!("Channel {channel_id} not found")))?;
+
+        let cln_channel_id = channel
+            .channel_id
+            .ok_or_else(|| Status::internal(format!("Channel {channel_id} has no channel id")))?;
+
+        self.rpc_client()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .call(cln_rpc::Request::Close(model::requests::CloseRequest {
+                id: cln_channel_id.to_string(),
+                // CLN closes the channel unilaterally once the timeout passed
+                // without the peer agreeing to a cooperative close
+                unilateraltimeout: force.then_some(1),
+                destination: None,
+                fee_negotiation_step: None,
+                wrong_funding: None,
+                force_lease_closed: None,
+                feerange: None,
+            }))
+            .await
+            .map_err(|e| {
+                error!("cln close rpc returned error {:?}", e);
+                tonic::Status::internal(e.to_string())
+            })?;
+
+        Ok(tonic::Response::new(EmptyResponse {}))
+    }
+}
+
+/// Formats the funding outpoint of a channel as `<txid>:<vout>`
+fn funding_outpoint(channel: &ListpeerchannelsChannels) -> Option<String> {
+    Some(format!(
+        "{}:{}",
+        channel.funding_txid.as_ref()?,
+        channel.funding_outnum?
+    ))
+}
+
+fn channel_details(channel: ListpeerchannelsChannels) -> Option<ChannelDetails> {
+    use model::responses::ListpeerchannelsChannelsState as ClnState;
+
+    let state = match channel.state? {
+        ClnState::CHANNELD_NORMAL if channel.peer_connected == Some(true) => ChannelState::Active,
+        ClnState::CHANNELD_NORMAL => ChannelState::Inactive,
+        ClnState::OPENINGD
+        | ClnState::CHANNELD_AWAITING_LOCKIN
+        | ClnState::DUALOPEND_OPEN_INIT
+        | ClnState::DUALOPEND_AWAITING_LOCKIN => ChannelState::Opening,
+        _ => ChannelState::Closing,
+    };
+
+    // Only channels that can still route payments report their liquidity
+    let (outbound_liquidity_sats, inbound_liquidity_sats) = if state == ChannelState::Closing {
+        (0, 0)
+    } else {
+        (
+            channel
+                .spendable_msat
+                .map_or(0, |value| value.msat() / 1000),
+            channel
+                .receivable_msat
+                .map_or(0, |value| value.msat() / 1000),
+        )
+    };
+
+    Some(ChannelDetails {
+        channel_id: funding_outpoint(&channel)?,
+        short_channel_id: channel.short_channel_id.map(scid_to_u64),
+        remote_pubkey: channel.peer_id?.to_string(),
+        state: state.into(),
+        channel_size_sats: channel.total_msat.map_or(0, |value| value.msat() / 1000),
+        outbound_liquidity_sats,
+        inbound_liquidity_sats,
+        closing_txid: (state == ChannelState::Closing)
+            .then_some(channel.scratch_txid)
+            .flatten(),
+    })
 }
 
 #[derive(Debug, Error)]
@@ -877,7 +1207,8 @@ type HtlcOutcomeSender = oneshot::Sender<serde_json::Value>;
 /// Used as a CLN plugin
 #[derive(Clone)]
 struct ClnHtlcInterceptor {
-    outcomes: Arc<Mutex<BTreeMap<(u64, u64), HtlcOutcomeSender>>>,
+    /// Intercepted HTLCs awaiting their outcome with their payment hash
+    outcomes: Arc<Mutex<BTreeMap<(u64, u64), (sha256::Hash, HtlcOutcomeSender)>>>,
     sender: Arc<Mutex<Option<HtlcInterceptionSender>>>,
 }
 
@@ -948,10 +1279,10 @@ impl ClnHtlcInterceptor {
                 Ok(_) => {
                     // Open a channel to receive the outcome of the HTLC processing
                     let (sender, receiver) = oneshot::channel::<serde_json::Value>();
-                    self.outcomes
-                        .lock()
-                        .await
-                        .insert((incoming_chan_id, payload.htlc.id), sender);
+                    self.outcomes.lock().await.insert(
+                        (incoming_chan_id, payload.htlc.id),
+                        (payload.htlc.payment_hash, sender),
+                    );
 
                     // If the gateway does not respond within the HTLC expiry,
                     // Automatically respond with a failure message.
@@ -981,5 +1312,30 @@ impl ClnHtlcInterceptor {
         serde_json::json!({ "result": "continue" })
     }
 
+    /// Resolves all held HTLCs paying to `payment_hash` with `outcome` and
+    /// returns how many HTLCs were resolved
+    async fn resolve_held_htlcs(
+        &self,
+        payment_hash: sha256::Hash,
+        outcome: serde_json::Value,
+    ) -> usize {
+        let mut outcomes = self.outcomes.lock().await;
+
+        let held = outcomes
+            .iter()
+            .filter(|(_, (hash, _))| *hash == payment_hash)
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        for key in &held {
+            let (_, sender) = outcomes.remove(key).expect("Key was found above");
+            if sender.send(outcome.clone()).is_err() {
+                error!(?key, "Failed to send htlc_accepted response to interceptor");
+            }
+        }
+
+        held.len()
+    }
+
     // TODO: Add a method to remove a HTLC subscriber
 }
@@ -9,20 +9,38 @@
 //! The API also has endpoints for managing the gateway.
 
 use fedimint_core::fedimint_build_code_version_env;
-use fedimint_core::task::TaskGroup;
+use fedimint_core::task::{wait_for_shutdown_signal, TaskGroup};
 use fedimint_core::util::handle_version_hash_command;
 use fedimint_logging::TracingSetup;
-use ln_gateway::Gateway;
+use ln_gateway::db_archive::DbArchiveCommand;
+use ln_gateway::{Gateway, DEFAULT_DRAIN_TIMEOUT};
 use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     handle_version_hash_command(fedimint_build_code_version_env!());
     TracingSetup::default().init()?;
+
+    if let Some(command) = DbArchiveCommand::from_args() {
+        return command.run().await;
+    }
+
     let mut tg = TaskGroup::new();
-    tg.install_kill_handler();
     let gatewayd = Gateway::new_with_default_modules().await?;
     let shutdown_receiver = gatewayd.clone().run(&mut tg).await?;
+
+    // Drain in-flight payments before shutting down, so HTLCs are not stranded
+    tg.spawn_cancellable("drain on shutdown signal", {
+        let gatewayd = gatewayd.clone();
+        let tg = tg.clone();
+        async move {
+            wait_for_shutdown_signal().await;
+            info!("Signal received, draining in-flight payments before shutting down");
+            gatewayd.drain(DEFAULT_DRAIN_TIMEOUT).await;
+            tg.shutdown();
+        }
+    });
+
     shutdown_receiver.await;
     gatewayd.leave_all_federations().await;
     info!("Gatewayd exiting...");
@@ -101,6 +101,7 @@ impl GatewayClientBuilder {
                 // TODO: make this configurable?
                 .join(root_secret, client_config.clone(), invite_code.api_secret())
                 .await
+                .map_err(anyhow::Error::from)
         }
         .map(Arc::new)
         .map_err(GatewayError::ClientStateMachineError)
@@ -1,25 +1,33 @@
 use std::collections::BTreeMap;
+use std::time::SystemTime;
 
 use bitcoin::Network;
 use bitcoin_hashes::sha256;
 use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
 use fedimint_core::db::{
     DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped, ServerMigrationFn,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::invite_code::InviteCode;
-use fedimint_core::{impl_db_lookup, impl_db_record, secp256k1};
+use fedimint_core::{impl_db_lookup, impl_db_record, secp256k1, Amount};
 use fedimint_ln_common::serde_routing_fees;
 use fedimint_lnv2_client::CreateInvoicePayload;
-use futures::FutureExt;
+use fedimint_lnv2_common::contracts::IncomingContract;
+use fedimint_lnv2_common::ContractId;
+use futures::{FutureExt, StreamExt};
 use lightning_invoice::RoutingFees;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
 
 use crate::rpc::rpc_server::hash_password;
+use crate::rpc::{
+    FederationPolicy, FederationRegistration, FeeMode, L402Config, NodeRoutingStats, NostrConfig,
+    PaymentDirection, PaymentStatus, RiskLimits, RouteHintRefreshConfig, SwapFees,
+};
 
-pub const GATEWAYD_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+pub const GATEWAYD_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(3);
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -29,6 +37,28 @@ pub enum DbKeyPrefix {
     GatewayConfiguration = 0x07,
     PreimageAuthentication = 0x08,
     CreateInvoicePayload = 0x09,
+    PublicReceiver = 0x0a,
+    PaymentRecord = 0x0b,
+    PendingIncomingPayment = 0x0c,
+    ScidAlias = 0x0d,
+    DrainReport = 0x0e,
+    FederationPolicy = 0x0f,
+    SwapFees = 0x10,
+    RouteHintRefreshConfig = 0x11,
+    RiskLimits = 0x12,
+    HoldInvoice = 0x13,
+    ManualHtlcResolution = 0x14,
+    NostrConfig = 0x15,
+    PendingZap = 0x16,
+    FeeMode = 0x17,
+    PhantomScid = 0x18,
+    RouteHintPrivacy = 0x19,
+    L402Config = 0x1a,
+    L402Invoice = 0x1b,
+    NodeRoutingStats = 0x1c,
+    FederationRegistration = 0x1d,
+    RegisteredScidBase = 0x1e,
+    PublicInvoiceContract = 0x1f,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -131,6 +161,8 @@ impl_db_lookup!(
 pub fn get_gatewayd_database_migrations() -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
     let mut migrations: BTreeMap<DatabaseVersion, ServerMigrationFn> = BTreeMap::new();
     migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+    migrations.insert(DatabaseVersion(1), move |dbtx| migrate_to_v2(dbtx).boxed());
+    migrations.insert(DatabaseVersion(2), move |dbtx| migrate_to_v3(dbtx).boxed());
     migrations
 }
 
@@ -153,6 +185,58 @@ async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow:
     Ok(())
 }
 
+/// Persists the channel ids of the connected federations as their SCID aliases
+async fn migrate_to_v2(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let configs = dbtx
+        .find_by_prefix(&FederationIdKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (key, config) in configs {
+        dbtx.insert_new_entry(
+            &ScidAliasKey {
+                federation_id: key.id,
+            },
+            &config.mint_channel_id,
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Records the lightning network fees of the payments in the payment history,
+/// which were not known for payments made before
+async fn migrate_to_v3(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let records = dbtx
+        .find_by_prefix(&PaymentRecordKeyPrefixV0)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (key, record) in records {
+        dbtx.insert_entry(
+            &PaymentRecordKey {
+                completed_at: key.completed_at,
+                payment_hash: key.payment_hash,
+            },
+            &PaymentRecord {
+                federation_id: record.federation_id,
+                direction: record.direction,
+                status: record.status,
+                amount: record.amount,
+                fees_earned: record.fees_earned,
+                lightning_fee: Amount::ZERO,
+                started_at: record.started_at,
+            },
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct CreateInvoicePayloadKey(pub [u8; 32]);
 
@@ -162,6 +246,487 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::CreateInvoicePayload,
 );
 
+/// Key for a receiver that can be paid through the public, unauthenticated
+/// invoice endpoint. Only the hash of the receiver's token is stored.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PublicReceiverKey {
+    pub token_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PublicReceiverKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PublicReceiver {
+    pub federation_id: FederationId,
+    /// LNv2 static public key the incoming contracts are locked to
+    pub recipient_static_pk: secp256k1::PublicKey,
+    pub max_invoice_amount: Amount,
+    pub max_invoices_per_hour: u32,
+}
+
+impl_db_record!(
+    key = PublicReceiverKey,
+    value = PublicReceiver,
+    db_prefix = DbKeyPrefix::PublicReceiver,
+);
+
+impl_db_lookup!(
+    key = PublicReceiverKey,
+    query_prefix = PublicReceiverKeyPrefix
+);
+
+/// Key for an incoming contract the gateway created for a public receiver.
+/// The receiver fetches its contracts with its token to claim them, see
+/// [`crate::Gateway::handle_public_invoice_contracts_msg`].
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct PublicInvoiceContractKey {
+    pub token_hash: sha256::Hash,
+    pub contract_id: ContractId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PublicInvoiceContractKeyPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PublicInvoiceContractTokenPrefix {
+    pub token_hash: sha256::Hash,
+}
+
+impl_db_record!(
+    key = PublicInvoiceContractKey,
+    value = IncomingContract,
+    db_prefix = DbKeyPrefix::PublicInvoiceContract,
+);
+
+impl_db_lookup!(
+    key = PublicInvoiceContractKey,
+    query_prefix = PublicInvoiceContractKeyPrefix,
+    query_prefix = PublicInvoiceContractTokenPrefix
+);
+
+/// Key for a finished payment in the payment history. Payments are ordered
+/// chronologically by the time they completed or failed.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq)]
+pub struct PaymentRecordKey {
+    pub completed_at: SystemTime,
+    pub payment_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentRecordKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentRecord {
+    pub federation_id: FederationId,
+    pub direction: PaymentDirection,
+    pub status: PaymentStatus,
+    pub amount: Amount,
+    pub fees_earned: Amount,
+    /// Lightning network fees the gateway paid for an outgoing payment
+    pub lightning_fee: Amount,
+    pub started_at: SystemTime,
+}
+
+impl_db_record!(
+    key = PaymentRecordKey,
+    value = PaymentRecord,
+    db_prefix = DbKeyPrefix::PaymentRecord,
+);
+
+impl_db_lookup!(
+    key = PaymentRecordKey,
+    query_prefix = PaymentRecordKeyPrefix
+);
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq)]
+pub struct PaymentRecordKeyV0 {
+    pub completed_at: SystemTime,
+    pub payment_hash: sha256::Hash,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentRecordKeyPrefixV0;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PaymentRecordV0 {
+    pub federation_id: FederationId,
+    pub direction: PaymentDirection,
+    pub status: PaymentStatus,
+    pub amount: Amount,
+    pub fees_earned: Amount,
+    pub started_at: SystemTime,
+}
+
+impl_db_record!(
+    key = PaymentRecordKeyV0,
+    value = PaymentRecordV0,
+    db_prefix = DbKeyPrefix::PaymentRecord,
+);
+
+impl_db_lookup!(
+    key = PaymentRecordKeyV0,
+    query_prefix = PaymentRecordKeyPrefixV0
+);
+
+/// Key for an intercepted HTLC that is being paid to a federation. The entry
+/// is moved to the payment history once the HTLC is settled or cancelled.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct PendingIncomingPaymentKey {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingIncomingPaymentKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PendingIncomingPayment {
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    pub amount: Amount,
+    pub fees_earned: Amount,
+    pub started_at: SystemTime,
+}
+
+impl_db_record!(
+    key = PendingIncomingPaymentKey,
+    value = PendingIncomingPayment,
+    db_prefix = DbKeyPrefix::PendingIncomingPayment,
+);
+
+impl_db_lookup!(
+    key = PendingIncomingPaymentKey,
+    query_prefix = PendingIncomingPaymentKeyPrefix
+);
+
+/// Key for the short channel id alias assigned to a federation. Aliases are
+/// kept after leaving a federation, so the federation is assigned the same
+/// alias when the gateway reconnects and previously created invoices remain
+/// payable.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct ScidAliasKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ScidAliasKeyPrefix;
+
+impl_db_record!(
+    key = ScidAliasKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ScidAlias,
+);
+
+impl_db_lookup!(key = ScidAliasKey, query_prefix = ScidAliasKeyPrefix);
+
+/// Key for a phantom short channel id the gateway put into the route hints of
+/// a federation instead of its SCID alias. Phantom SCIDs are never removed, so
+/// invoices created before the federation was assigned a new one remain
+/// payable.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct PhantomScidKey {
+    pub scid: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PhantomScidKeyPrefix;
+
+impl_db_record!(
+    key = PhantomScidKey,
+    value = FederationId,
+    db_prefix = DbKeyPrefix::PhantomScid,
+);
+
+impl_db_lookup!(key = PhantomScidKey, query_prefix = PhantomScidKeyPrefix);
+
+/// Key for the phantom short channel id invoices of a federation currently
+/// use. Only federations with route hint privacy enabled have an entry.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct RouteHintPrivacyKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RouteHintPrivacyKeyPrefix;
+
+impl_db_record!(
+    key = RouteHintPrivacyKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::RouteHintPrivacy,
+);
+
+impl_db_lookup!(
+    key = RouteHintPrivacyKey,
+    query_prefix = RouteHintPrivacyKeyPrefix
+);
+
+/// Key for the report of the last time the gateway drained in-flight payments
+/// before shutting down
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct DrainReportKey;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct DrainReport {
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    /// Whether all in-flight payments settled or refunded before the timeout
+    pub drained: bool,
+    /// Operations of each federation that still had active state machines,
+    /// they are resumed when the gateway is started again
+    pub pending_operations: BTreeMap<FederationId, Vec<OperationId>>,
+    /// Intercepted HTLCs that were neither settled nor cancelled yet
+    pub pending_incoming_htlcs: u64,
+}
+
+impl_db_record!(
+    key = DrainReportKey,
+    value = DrainReport,
+    db_prefix = DbKeyPrefix::DrainReport,
+);
+
+/// Key for the policy restricting which federations the gateway connects to
+/// and serves
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct FederationPolicyKey;
+
+impl_db_record!(
+    key = FederationPolicyKey,
+    value = FederationPolicy,
+    db_prefix = DbKeyPrefix::FederationPolicy,
+);
+
+/// Key for the LNv2 direct swap fees of a connected federation
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct SwapFeesKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct SwapFeesKeyPrefix;
+
+impl_db_record!(
+    key = SwapFeesKey,
+    value = SwapFees,
+    db_prefix = DbKeyPrefix::SwapFees,
+);
+
+impl_db_lookup!(key = SwapFeesKey, query_prefix = SwapFeesKeyPrefix);
+
+/// Key for the [`FeeMode`] of the outgoing LNv1 payments of a connected
+/// federation
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct FeeModeKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FeeModeKeyPrefix;
+
+impl_db_record!(
+    key = FeeModeKey,
+    value = FeeMode,
+    db_prefix = DbKeyPrefix::FeeMode,
+);
+
+impl_db_lookup!(key = FeeModeKey, query_prefix = FeeModeKeyPrefix);
+
+/// Key for the settings of the task that re-registers the gateway when the
+/// route hints of its lightning node change
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct RouteHintRefreshConfigKey;
+
+impl_db_record!(
+    key = RouteHintRefreshConfigKey,
+    value = RouteHintRefreshConfig,
+    db_prefix = DbKeyPrefix::RouteHintRefreshConfig,
+);
+
+/// Key for the limits on the size and volume of payments the gateway makes
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct RiskLimitsKey;
+
+impl_db_record!(
+    key = RiskLimitsKey,
+    value = RiskLimits,
+    db_prefix = DbKeyPrefix::RiskLimits,
+);
+
+/// Key for an invoice the gateway created as a hold invoice on the lightning
+/// node. Its HTLCs are settled or cancelled through the hold invoice instead of
+/// by completing the intercepted HTLC.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct HoldInvoiceKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct HoldInvoiceKeyPrefix;
+
+impl_db_record!(
+    key = HoldInvoiceKey,
+    value = (),
+    db_prefix = DbKeyPrefix::HoldInvoice,
+);
+
+impl_db_lookup!(key = HoldInvoiceKey, query_prefix = HoldInvoiceKeyPrefix);
+
+/// Key for the audit record of an intercepted HTLC that was settled or
+/// cancelled manually by the operator. The state machine completing the HTLC
+/// stops once it finds the record.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct ManualHtlcResolutionKey {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ManualHtlcResolutionKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct ManualHtlcResolution {
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    /// Whether the HTLC was settled with the preimage or cancelled
+    pub settled: bool,
+    /// Reason given by the operator for cancelling the HTLC
+    pub reason: Option<String>,
+    pub resolved_at: SystemTime,
+}
+
+impl_db_record!(
+    key = ManualHtlcResolutionKey,
+    value = ManualHtlcResolution,
+    db_prefix = DbKeyPrefix::ManualHtlcResolution,
+);
+
+impl_db_lookup!(
+    key = ManualHtlcResolutionKey,
+    query_prefix = ManualHtlcResolutionKeyPrefix
+);
+
+/// Key for the nostr key the gateway signs zap receipts with
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct NostrConfigKey;
+
+impl_db_record!(
+    key = NostrConfigKey,
+    value = NostrConfig,
+    db_prefix = DbKeyPrefix::NostrConfig,
+);
+
+/// Key for the zap request of a public invoice that was not paid yet, by the
+/// payment hash of the invoice
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct PendingZapKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingZapKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PendingZap {
+    /// The zap request exactly as it was hashed into the invoice description
+    pub zap_request: String,
+    pub invoice: String,
+}
+
+impl_db_record!(
+    key = PendingZapKey,
+    value = PendingZap,
+    db_prefix = DbKeyPrefix::PendingZap,
+);
+
+impl_db_lookup!(key = PendingZapKey, query_prefix = PendingZapKeyPrefix);
+
+/// Key for the price and root key of L402 tokens, only present if L402
+/// authentication of public invoices is enabled
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct L402ConfigKey;
+
+impl_db_record!(
+    key = L402ConfigKey,
+    value = L402Config,
+    db_prefix = DbKeyPrefix::L402Config,
+);
+
+/// Key for the invoice of an L402 challenge, by its payment hash. The gateway
+/// settles incoming HTLCs of these invoices itself instead of forwarding them
+/// to a federation.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct L402InvoiceKey(pub sha256::Hash);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct L402InvoiceKeyPrefix;
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct L402Invoice {
+    pub preimage: [u8; 32],
+    pub amount: Amount,
+    /// Unix time after which the invoice expired and the entry can be removed
+    pub expires_at: u64,
+}
+
+impl_db_record!(
+    key = L402InvoiceKey,
+    value = L402Invoice,
+    db_prefix = DbKeyPrefix::L402Invoice,
+);
+
+impl_db_lookup!(key = L402InvoiceKey, query_prefix = L402InvoiceKeyPrefix);
+
+/// Key for the routing statistics of the lightning node, see
+/// [`crate::Gateway::sync_node_stats`]
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq)]
+pub struct NodeRoutingStatsKey;
+
+impl_db_record!(
+    key = NodeRoutingStatsKey,
+    value = NodeRoutingStats,
+    db_prefix = DbKeyPrefix::NodeRoutingStats,
+);
+
+/// Key for the lightning alias and short channel id the gateway registers with
+/// a federation, see [`crate::Gateway::handle_set_federation_registration_msg`]
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct FederationRegistrationKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct FederationRegistrationKeyPrefix;
+
+impl_db_record!(
+    key = FederationRegistrationKey,
+    value = FederationRegistration,
+    db_prefix = DbKeyPrefix::FederationRegistration,
+);
+
+impl_db_lookup!(
+    key = FederationRegistrationKey,
+    query_prefix = FederationRegistrationKeyPrefix
+);
+
+/// Key for a SCID base an operator registered with a federation, all short
+/// channel ids derived from it are paid to the federation. Like phantom SCIDs
+/// they are never removed, so invoices created before the federation was
+/// registered with a different base remain payable.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct RegisteredScidBaseKey {
+    pub scid_base: u64,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RegisteredScidBaseKeyPrefix;
+
+impl_db_record!(
+    key = RegisteredScidBaseKey,
+    value = FederationId,
+    db_prefix = DbKeyPrefix::RegisteredScidBase,
+);
+
+impl_db_lookup!(
+    key = RegisteredScidBaseKey,
+    query_prefix = RegisteredScidBaseKeyPrefix
+);
+
 #[cfg(test)]
 mod fedimint_migration_tests {
     use std::str::FromStr;
@@ -190,7 +755,7 @@ mod fedimint_migration_tests {
     };
     use crate::db::{
         get_gatewayd_database_migrations, DbKeyPrefix, FederationIdKeyPrefix,
-        PreimageAuthenticationPrefix, GATEWAYD_DATABASE_VERSION,
+        PreimageAuthenticationPrefix, ScidAliasKeyPrefix, GATEWAYD_DATABASE_VERSION,
     };
     use crate::DEFAULT_FEES;
 
@@ -290,7 +855,40 @@ mod fedimint_migration_tests {
                             ensure!(gateway_configuration.is_some(), "validate_migrations was not able to read GatewayConfiguration");
                             info!("Validated GatewayConfiguration");
                         }
-                        DbKeyPrefix::CreateInvoicePayload => {}
+                        DbKeyPrefix::ScidAlias => {
+                            let aliases = dbtx
+                                .find_by_prefix(&ScidAliasKeyPrefix)
+                                .await
+                                .collect::<Vec<_>>()
+                                .await;
+                            ensure!(
+                                !aliases.is_empty(),
+                                "validate_migrations was not able to read any ScidAliases"
+                            );
+                            info!("Validated ScidAlias");
+                        }
+                        DbKeyPrefix::CreateInvoicePayload
+                        | DbKeyPrefix::PublicReceiver
+                        | DbKeyPrefix::PaymentRecord
+                        | DbKeyPrefix::PendingIncomingPayment
+                        | DbKeyPrefix::DrainReport
+                        | DbKeyPrefix::FederationPolicy
+                        | DbKeyPrefix::SwapFees
+                        | DbKeyPrefix::RouteHintRefreshConfig
+                        | DbKeyPrefix::RiskLimits
+                        | DbKeyPrefix::HoldInvoice
+                        | DbKeyPrefix::ManualHtlcResolution
+                        | DbKeyPrefix::NostrConfig
+                        | DbKeyPrefix::PendingZap
+                        | DbKeyPrefix::FeeMode
+                        | DbKeyPrefix::PhantomScid
+                        | DbKeyPrefix::RouteHintPrivacy
+                        | DbKeyPrefix::L402Config
+                        | DbKeyPrefix::L402Invoice
+                        | DbKeyPrefix::NodeRoutingStats
+                        | DbKeyPrefix::FederationRegistration
+                        | DbKeyPrefix::RegisteredScidBase
+                        | DbKeyPrefix::PublicInvoiceContract => {}
                     }
                 }
                 Ok(())
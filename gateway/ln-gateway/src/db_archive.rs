@@ -0,0 +1,288 @@
+//! Moving a gateway to another machine
+//!
+//! A [`GatewayDbArchive`] contains the raw entries of the gateway database and
+//! of the client database of every connected federation, so the gateway keeps
+//! its ecash, pending payments and keys when it is restored elsewhere. It is
+//! exported either with `gatewayd export-db` while the gateway is stopped or
+//! through the admin API of a running gateway, and restored with
+//! `gatewayd import-db` into an empty data dir.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use clap::Parser;
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
+use fedimint_core::config::FederationId;
+use fedimint_core::db::{
+    Database, DatabaseVersion, DatabaseVersionKey, IDatabaseTransactionOpsCore,
+    IDatabaseTransactionOpsCoreTyped, MODULE_GLOBAL_PREFIX,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::fedimint_build_code_version_env;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use futures::StreamExt;
+use hex::ToHex;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::db::{FederationIdKeyPrefix, GATEWAYD_DATABASE_VERSION};
+use crate::{envs, DB_FILE};
+
+/// Version of the archive format, archives of a different format are rejected
+const DB_ARCHIVE_FORMAT_VERSION: u16 = 0;
+
+type RawDbEntries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Raw contents of the databases of a gateway
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct GatewayDbArchive {
+    pub format_version: u16,
+    /// Version of the gateway the archive was exported by
+    pub code_version: String,
+    /// `None` if the gateway database was never migrated
+    pub gateway_db_version: Option<DatabaseVersion>,
+    pub gateway_db: RawDbEntries,
+    pub client_dbs: BTreeMap<FederationId, RawDbEntries>,
+}
+
+/// [`GatewayDbArchive`] encrypted with a password, safe to copy to the machine
+/// the gateway is moved to
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EncryptedDbArchive {
+    /// Salt used to derive the encryption key from the password
+    pub salt: String,
+    /// Hex encoded ciphertext of the consensus encoded archive
+    pub ciphertext: String,
+}
+
+impl EncryptedDbArchive {
+    pub fn encrypt(archive: &GatewayDbArchive, password: &str) -> anyhow::Result<Self> {
+        let salt = random_salt();
+        let key = get_encryption_key(password, &salt)?;
+        let ciphertext = encrypt(archive.consensus_encode_to_vec(), &key)?;
+
+        Ok(Self {
+            salt,
+            ciphertext: ciphertext.encode_hex(),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> anyhow::Result<GatewayDbArchive> {
+        let key = get_encryption_key(password, &self.salt)?;
+        let mut ciphertext = hex::decode(&self.ciphertext)?;
+        let plaintext = decrypt(&mut ciphertext, &key)
+            .context("Failed to decrypt archive, is the password correct?")?;
+
+        // Archives can exceed the size limit of `consensus_decode`, the reader is
+        // finite anyway
+        Ok(GatewayDbArchive::consensus_decode_from_finite_reader(
+            &mut std::io::Cursor::new(plaintext),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
+/// Creates an archive of the gateway database and the given client databases
+pub async fn export_db_archive(
+    gateway_db: &Database,
+    client_dbs: BTreeMap<FederationId, Database>,
+) -> anyhow::Result<GatewayDbArchive> {
+    let gateway_db_version = gateway_db
+        .begin_transaction_nc()
+        .await
+        .get_value(&DatabaseVersionKey(MODULE_GLOBAL_PREFIX.into()))
+        .await;
+
+    let mut client_entries = BTreeMap::new();
+    for (federation_id, client_db) in client_dbs {
+        client_entries.insert(federation_id, read_raw_entries(&client_db).await?);
+    }
+
+    Ok(GatewayDbArchive {
+        format_version: DB_ARCHIVE_FORMAT_VERSION,
+        code_version: fedimint_build_code_version_env!().to_string(),
+        gateway_db_version,
+        gateway_db: read_raw_entries(gateway_db).await?,
+        client_dbs: client_entries,
+    })
+}
+
+/// Creates an archive of the databases in the data dir of a stopped gateway
+pub async fn export_data_dir(data_dir: &Path) -> anyhow::Result<GatewayDbArchive> {
+    ensure!(
+        data_dir.join(DB_FILE).exists(),
+        "No gateway database found in {}",
+        data_dir.display()
+    );
+
+    let gateway_db = open_db(&data_dir.join(DB_FILE))?;
+
+    let federation_ids = gateway_db
+        .begin_transaction_nc()
+        .await
+        .find_by_prefix(&FederationIdKeyPrefix)
+        .await
+        .map(|(key, _)| key.id)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut client_dbs = BTreeMap::new();
+    for federation_id in federation_ids {
+        let path = data_dir.join(format!("{federation_id}.db"));
+        if path.exists() {
+            client_dbs.insert(federation_id, open_db(&path)?);
+        }
+    }
+
+    export_db_archive(&gateway_db, client_dbs).await
+}
+
+/// Writes the databases of an archive into `data_dir`, which must not contain
+/// a gateway database yet. Database migrations are applied when the gateway is
+/// started.
+pub async fn import_db_archive(archive: GatewayDbArchive, data_dir: &Path) -> anyhow::Result<()> {
+    ensure!(
+        archive.format_version == DB_ARCHIVE_FORMAT_VERSION,
+        "Unsupported archive format version {}",
+        archive.format_version
+    );
+
+    if let Some(version) = archive.gateway_db_version {
+        ensure!(
+            version <= GATEWAYD_DATABASE_VERSION,
+            "Archive was exported by gatewayd {} with database version {version}, which is newer than the supported version {GATEWAYD_DATABASE_VERSION}",
+            archive.code_version
+        );
+    }
+
+    let gateway_db_path = data_dir.join(DB_FILE);
+    if gateway_db_path.exists() {
+        bail!(
+            "Refusing to import into {}, it already contains a gateway database",
+            data_dir.display()
+        );
+    }
+
+    std::fs::create_dir_all(data_dir)?;
+
+    write_raw_entries(&open_db(&gateway_db_path)?, &archive.gateway_db).await?;
+
+    for (federation_id, entries) in &archive.client_dbs {
+        let path = data_dir.join(format!("{federation_id}.db"));
+        ensure!(
+            !path.exists(),
+            "Refusing to overwrite existing client database {}",
+            path.display()
+        );
+        write_raw_entries(&open_db(&path)?, entries).await?;
+    }
+
+    info!(
+        code_version = %archive.code_version,
+        federations = archive.client_dbs.len(),
+        "Imported gateway database archive"
+    );
+
+    Ok(())
+}
+
+fn open_db(path: &Path) -> anyhow::Result<Database> {
+    Ok(Database::new(
+        fedimint_rocksdb::RocksDb::open(path)?,
+        ModuleDecoderRegistry::default(),
+    ))
+}
+
+async fn read_raw_entries(db: &Database) -> anyhow::Result<RawDbEntries> {
+    Ok(db
+        .begin_transaction_nc()
+        .await
+        .raw_find_by_prefix(&[])
+        .await?
+        .collect()
+        .await)
+}
+
+async fn write_raw_entries(db: &Database, entries: &RawDbEntries) -> anyhow::Result<()> {
+    let mut dbtx = db.begin_transaction().await;
+    for (key, value) in entries {
+        dbtx.raw_insert_bytes(key, value).await?;
+    }
+    dbtx.commit_tx_result().await
+}
+
+/// `gatewayd export-db` and `gatewayd import-db`, which operate on the data
+/// dir of a stopped gateway
+#[derive(Parser)]
+pub enum DbArchiveCommand {
+    /// Write an encrypted archive of the gateway database and the client
+    /// databases of all federations. The gateway must be stopped.
+    ExportDb {
+        #[arg(long = "data-dir", env = envs::FM_GATEWAY_DATA_DIR_ENV)]
+        data_dir: PathBuf,
+
+        /// Password the archive is encrypted with
+        #[arg(long = "password", env = envs::FM_GATEWAY_PASSWORD_ENV)]
+        password: String,
+
+        /// File the encrypted archive is written to
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Restore the databases of an archive into an empty data dir
+    ImportDb {
+        #[arg(long = "data-dir", env = envs::FM_GATEWAY_DATA_DIR_ENV)]
+        data_dir: PathBuf,
+
+        /// Password the archive was encrypted with
+        #[arg(long = "password", env = envs::FM_GATEWAY_PASSWORD_ENV)]
+        password: String,
+
+        /// File containing the encrypted archive
+        #[arg(long)]
+        input: PathBuf,
+    },
+}
+
+impl DbArchiveCommand {
+    /// Parses the command if `gatewayd` was started with `export-db` or
+    /// `import-db`, which take precedence over running the gateway
+    pub fn from_args() -> Option<Self> {
+        match std::env::args().nth(1).as_deref() {
+            Some("export-db" | "import-db") => Some(Self::parse()),
+            _ => None,
+        }
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        match self {
+            DbArchiveCommand::ExportDb {
+                data_dir,
+                password,
+                output,
+            } => {
+                let archive = export_data_dir(&data_dir).await?;
+                let encrypted = EncryptedDbArchive::encrypt(&archive, &password)?;
+                std::fs::write(&output, serde_json::to_string_pretty(&encrypted)?)?;
+
+                info!(
+                    federations = archive.client_dbs.len(),
+                    "Exported gateway database archive to {}",
+                    output.display()
+                );
+            }
+            DbArchiveCommand::ImportDb {
+                data_dir,
+                password,
+                input,
+            } => {
+                let encrypted: EncryptedDbArchive = serde_json::from_slice(&std::fs::read(&input)?)
+                    .context("Failed to parse database archive")?;
+                import_db_archive(encrypted.decrypt(&password)?, &data_dir).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, push_db_pair_items, Amount};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+/// Prefixes of the fee ledger records. The records are stored in the module
+/// database of the gateway client modules, so every federation has its own
+/// ledger.
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum EarningsDbKeyPrefix {
+    Earnings = 0x01,
+    PendingEarnings = 0x02,
+}
+
+impl std::fmt::Display for EarningsDbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Encodable,
+    Decodable,
+    Eq,
+    PartialEq,
+    Hash,
+    Ord,
+    PartialOrd,
+    Serialize,
+    Deserialize,
+)]
+pub enum FeeDirection {
+    /// Fees charged for paying an HTLC to a federation
+    Incoming,
+    /// Fees charged for paying an invoice on behalf of a federation client
+    Outgoing,
+}
+
+/// Key for the cumulative fees earned in one direction
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct EarningsKey(pub FeeDirection);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EarningsKeyPrefix;
+
+impl_db_record!(
+    key = EarningsKey,
+    value = Amount,
+    db_prefix = EarningsDbKeyPrefix::Earnings,
+);
+
+impl_db_lookup!(key = EarningsKey, query_prefix = EarningsKeyPrefix);
+
+/// Key for the fee of a payment that is still in flight. The fee is added to
+/// the ledger by [`settle_pending_earnings`] once the payment succeeds.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct PendingEarningsKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PendingEarningsKeyPrefix;
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PendingEarnings {
+    pub direction: FeeDirection,
+    pub fee: Amount,
+}
+
+impl_db_record!(
+    key = PendingEarningsKey,
+    value = PendingEarnings,
+    db_prefix = EarningsDbKeyPrefix::PendingEarnings,
+);
+
+impl_db_lookup!(
+    key = PendingEarningsKey,
+    query_prefix = PendingEarningsKeyPrefix
+);
+
+/// Adds `fee` to the fees earned in `direction`
+pub async fn record_earnings(
+    dbtx: &mut DatabaseTransaction<'_>,
+    direction: FeeDirection,
+    fee: Amount,
+) {
+    let earned = dbtx
+        .get_value(&EarningsKey(direction))
+        .await
+        .unwrap_or(Amount::ZERO);
+    dbtx.insert_entry(&EarningsKey(direction), &(earned + fee))
+        .await;
+}
+
+/// Returns the fee earned by paying an invoice of `payment_amount` on behalf of
+/// a client who funded a contract of `contract_amount`, which is what is left
+/// after paying the `routing_fee` of the lightning network
+pub fn outgoing_fee(
+    contract_amount: Amount,
+    payment_amount: Amount,
+    routing_fee: Amount,
+) -> Amount {
+    contract_amount.saturating_sub(payment_amount + routing_fee)
+}
+
+/// Remembers the fee the payment of `operation_id` earns if it succeeds
+pub async fn start_pending_earnings(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    direction: FeeDirection,
+    fee: Amount,
+) {
+    dbtx.insert_entry(
+        &PendingEarningsKey(operation_id),
+        &PendingEarnings { direction, fee },
+    )
+    .await;
+}
+
+/// Adds the pending fee of `operation_id` to the ledger if the payment
+/// succeeded and discards it otherwise
+pub async fn settle_pending_earnings(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+    succeeded: bool,
+) {
+    if let Some(pending) = dbtx.remove_entry(&PendingEarningsKey(operation_id)).await {
+        if succeeded {
+            record_earnings(dbtx, pending.direction, pending.fee).await;
+        }
+    }
+}
+
+/// Returns the cumulative fees earned, per direction
+pub async fn load_earnings(dbtx: &mut DatabaseTransaction<'_>) -> BTreeMap<FeeDirection, Amount> {
+    dbtx.find_by_prefix(&EarningsKeyPrefix)
+        .await
+        .map(|(EarningsKey(direction), earned)| (direction, earned))
+        .collect()
+        .await
+}
+
+/// Dumps the fee ledger for the dbtool, used by the gateway client modules
+/// whose databases contain the ledger
+pub async fn dump_earnings(
+    dbtx: &mut DatabaseTransaction<'_>,
+    prefix_names: &[String],
+) -> BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> {
+    let mut earnings_items: BTreeMap<String, Box<dyn erased_serde::Serialize + Send>> =
+        BTreeMap::new();
+    let filtered_prefixes = EarningsDbKeyPrefix::iter().filter(|f| {
+        prefix_names.is_empty() || prefix_names.contains(&f.to_string().to_lowercase())
+    });
+
+    for table in filtered_prefixes {
+        match table {
+            EarningsDbKeyPrefix::Earnings => {
+                push_db_pair_items!(
+                    dbtx,
+                    EarningsKeyPrefix,
+                    EarningsKey,
+                    Amount,
+                    earnings_items,
+                    "Earnings"
+                );
+            }
+            EarningsDbKeyPrefix::PendingEarnings => {
+                push_db_pair_items!(
+                    dbtx,
+                    PendingEarningsKeyPrefix,
+                    PendingEarningsKey,
+                    PendingEarnings,
+                    earnings_items,
+                    "Pending Earnings"
+                );
+            }
+        }
+    }
+
+    earnings_items
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::core::OperationId;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::Amount;
+
+    use super::{
+        load_earnings, outgoing_fee, settle_pending_earnings, start_pending_earnings, FeeDirection,
+    };
+
+    #[tokio::test]
+    async fn only_fees_of_successful_payments_are_earned() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let succeeded = OperationId::new_random();
+        let failed = OperationId::new_random();
+
+        for operation_id in [succeeded, failed] {
+            start_pending_earnings(
+                &mut dbtx.to_ref_nc(),
+                operation_id,
+                FeeDirection::Incoming,
+                Amount::from_msats(1_000),
+            )
+            .await;
+        }
+        settle_pending_earnings(&mut dbtx.to_ref_nc(), succeeded, true).await;
+        settle_pending_earnings(&mut dbtx.to_ref_nc(), failed, false).await;
+        // Settling twice must not count the fee again
+        settle_pending_earnings(&mut dbtx.to_ref_nc(), succeeded, true).await;
+
+        let earnings = load_earnings(&mut dbtx.to_ref_nc()).await;
+        assert_eq!(
+            earnings.get(&FeeDirection::Incoming),
+            Some(&Amount::from_msats(1_000))
+        );
+        assert_eq!(earnings.get(&FeeDirection::Outgoing), None);
+    }
+
+    #[test]
+    fn routing_fees_are_not_earned() {
+        let contract_amount = Amount::from_msats(10_300);
+        let payment_amount = Amount::from_msats(10_000);
+
+        assert_eq!(
+            outgoing_fee(contract_amount, payment_amount, Amount::ZERO),
+            Amount::from_msats(300)
+        );
+        assert_eq!(
+            outgoing_fee(contract_amount, payment_amount, Amount::from_msats(120)),
+            Amount::from_msats(180)
+        );
+        // Routing fees exceeding the fee charged lose money, which isn't an earning
+        assert_eq!(
+            outgoing_fee(contract_amount, payment_amount, Amount::from_msats(400)),
+            Amount::ZERO
+        );
+    }
+}
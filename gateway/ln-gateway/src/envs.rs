@@ -31,5 +31,38 @@ pub const FM_LND_TLS_CERT_ENV: &str = "FM_LND_TLS_CERT";
 // Env variable to TODO
 pub const FM_LND_MACAROON_ENV: &str = "FM_LND_MACAROON";
 
+// Env variable to configure the maximum number of parts LND splits outgoing
+// payments into
+pub const FM_LND_MAX_PARTS_ENV: &str = "FM_LND_MAX_PARTS";
+
+// Env variable to configure the maximum amount in millisats of a single part of
+// an outgoing LND payment
+pub const FM_LND_MAX_SHARD_MSAT_ENV: &str = "FM_LND_MAX_SHARD_MSAT";
+
+// Env variable to configure the address of CLN's built-in gRPC interface
+pub const FM_CLN_GRPC_ADDR_ENV: &str = "FM_CLN_GRPC_ADDR";
+
+// Env variable to configure the directory containing the TLS certificates of
+// CLN's built-in gRPC interface
+pub const FM_CLN_GRPC_CERT_DIR_ENV: &str = "FM_CLN_GRPC_CERT_DIR";
+
 // Env variable to TODO
 pub const FM_GATEWAY_LIGHTNING_ADDR_ENV: &str = "FM_GATEWAY_LIGHTNING_ADDR";
+
+// Env variable to configure additional lightning nodes the gateway routes
+// payments through, as a JSON list of lightning modes
+pub const FM_GATEWAY_ADDITIONAL_LIGHTNING_NODES_ENV: &str = "FM_GATEWAY_ADDITIONAL_LIGHTNING_NODES";
+
+// Env variable to configure the liquidity in sats below which the gateway
+// publishes liquidity alerts on its event stream
+pub const FM_GATEWAY_LOW_LIQUIDITY_ALERT_THRESHOLD_SATS_ENV: &str =
+    "FM_GATEWAY_LOW_LIQUIDITY_ALERT_THRESHOLD_SATS";
+
+// Env variable to pass the password federation connections are exported and
+// imported with to gateway-cli, which prompts for it otherwise
+pub const FM_GATEWAY_CONNECTIONS_PASSWORD_ENV: &str = "FM_GATEWAY_CONNECTIONS_PASSWORD";
+
+// Env variable to configure the path of a TOML file the gateway loads its
+// settings from. Settings given on the command line or through other env
+// variables take precedence over the file.
+pub const FM_GATEWAY_CONFIG_ENV: &str = "FM_GATEWAY_CONFIG";
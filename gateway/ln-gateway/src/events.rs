@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bitcoin_hashes::sha256;
+use fedimint_core::config::FederationId;
+use fedimint_core::time::duration_since_epoch;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::lightning::PaymentAttemptUpdate;
+
+/// Number of recent events kept in memory so that subscribers can resume from
+/// a cursor after reconnecting
+pub const EVENT_BUFFER_SIZE: usize = 1024;
+
+/// Interval at which the liquidity of the lightning node is checked against
+/// the configured alert threshold
+pub const LIQUIDITY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Events published by the gateway to subscribers of the event stream
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GatewayEvent {
+    OutgoingPaymentStarted {
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        amount: Option<Amount>,
+    },
+    OutgoingPaymentSucceeded {
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+    },
+    OutgoingPaymentFailed {
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        error: String,
+    },
+    /// The lightning node dispatched or resolved an HTLC attempt of an
+    /// outgoing payment
+    OutgoingPaymentAttempt {
+        payment_hash: sha256::Hash,
+        attempt: PaymentAttemptUpdate,
+    },
+    /// An intercepted HTLC is being paid to a federation. The payment is
+    /// identified by `incoming_chan_id` and `htlc_id` in subsequent events.
+    IncomingPaymentStarted {
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        amount: Amount,
+        incoming_chan_id: u64,
+        htlc_id: u64,
+    },
+    IncomingPaymentSucceeded {
+        incoming_chan_id: u64,
+        htlc_id: u64,
+    },
+    IncomingPaymentFailed {
+        incoming_chan_id: u64,
+        htlc_id: u64,
+        error: String,
+    },
+    FederationConnected {
+        federation_id: FederationId,
+    },
+    FederationDisconnected {
+        federation_id: FederationId,
+    },
+    /// Total outbound liquidity of the lightning node dropped below the
+    /// configured threshold
+    LowOutboundLiquidity {
+        outbound_liquidity_sats: u64,
+        threshold_sats: u64,
+    },
+    /// Total inbound liquidity of the lightning node dropped below the
+    /// configured threshold
+    LowInboundLiquidity {
+        inbound_liquidity_sats: u64,
+        threshold_sats: u64,
+    },
+    /// The lightning node became unreachable or fell out of sync with the
+    /// chain, new payments are rejected
+    NodeDegraded {
+        reason: String,
+    },
+    /// The lightning node recovered from being degraded
+    NodeRecovered,
+}
+
+/// A [`GatewayEvent`] together with its position in the event stream
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GatewayEventEnvelope {
+    /// Strictly increasing position of the event, subscribers pass the last
+    /// cursor they have seen to resume the stream
+    pub cursor: u64,
+    /// Seconds since the UNIX epoch at which the event was published
+    pub timestamp: u64,
+    pub event: GatewayEvent,
+}
+
+/// Error returned when subscribing with a cursor whose events are no longer
+/// buffered, either because the subscriber fell too far behind or because
+/// the gateway was restarted. The subscriber has to resync its state and
+/// subscribe without a cursor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CursorExpired;
+
+struct EventLog {
+    next_cursor: u64,
+    recent: VecDeque<GatewayEventEnvelope>,
+}
+
+/// In-memory publisher of [`GatewayEvent`]s.
+///
+/// The most recent [`EVENT_BUFFER_SIZE`] events are retained so that
+/// subscribers can resume from the last cursor they received. Events are not
+/// persisted, cursors are only valid for the lifetime of the gateway process.
+pub struct GatewayEventBus {
+    log: Mutex<EventLog>,
+    sender: broadcast::Sender<GatewayEventEnvelope>,
+}
+
+impl std::fmt::Debug for GatewayEventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GatewayEventBus")
+            .field("receivers", &self.sender.receiver_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for GatewayEventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        Self {
+            log: Mutex::new(EventLog {
+                next_cursor: 1,
+                recent: VecDeque::with_capacity(EVENT_BUFFER_SIZE),
+            }),
+            sender,
+        }
+    }
+}
+
+impl GatewayEventBus {
+    pub fn publish(&self, event: GatewayEvent) {
+        let mut log = self.log.lock().expect("poisoned");
+        let envelope = GatewayEventEnvelope {
+            cursor: log.next_cursor,
+            timestamp: duration_since_epoch().as_secs(),
+            event,
+        };
+        log.next_cursor += 1;
+
+        if log.recent.len() == EVENT_BUFFER_SIZE {
+            log.recent.pop_front();
+        }
+        log.recent.push_back(envelope.clone());
+
+        // Sending only fails if there are no subscribers, which is fine
+        let _ = self.sender.send(envelope);
+    }
+
+    /// Subscribes to the event stream. If `cursor` is given, all buffered
+    /// events after it are returned to be delivered before the live events
+    /// from the receiver.
+    pub fn subscribe(
+        &self,
+        cursor: Option<u64>,
+    ) -> Result<
+        (
+            Vec<GatewayEventEnvelope>,
+            broadcast::Receiver<GatewayEventEnvelope>,
+        ),
+        CursorExpired,
+    > {
+        // Holding the lock guarantees no event is published between reading
+        // the backlog and subscribing, so none is missed or delivered twice
+        let log = self.log.lock().expect("poisoned");
+        let receiver = self.sender.subscribe();
+
+        let Some(cursor) = cursor else {
+            return Ok((Vec::new(), receiver));
+        };
+
+        let oldest_cursor = log
+            .recent
+            .front()
+            .map_or(log.next_cursor, |envelope| envelope.cursor);
+        if cursor >= log.next_cursor || cursor + 1 < oldest_cursor {
+            return Err(CursorExpired);
+        }
+
+        let backlog = log
+            .recent
+            .iter()
+            .filter(|envelope| envelope.cursor > cursor)
+            .cloned()
+            .collect();
+        Ok((backlog, receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+
+    use super::{CursorExpired, GatewayEvent, GatewayEventBus, EVENT_BUFFER_SIZE};
+
+    fn connected() -> GatewayEvent {
+        GatewayEvent::FederationConnected {
+            federation_id: FederationId::dummy(),
+        }
+    }
+
+    #[test]
+    fn subscribers_resume_from_cursor() {
+        let bus = GatewayEventBus::default();
+        bus.publish(connected());
+        bus.publish(connected());
+
+        let (backlog, _) = bus.subscribe(Some(1)).expect("cursor is buffered");
+        assert_eq!(
+            backlog.iter().map(|e| e.cursor).collect::<Vec<_>>(),
+            vec![2]
+        );
+
+        let (backlog, mut receiver) = bus.subscribe(Some(2)).expect("cursor is buffered");
+        assert!(backlog.is_empty());
+        bus.publish(connected());
+        assert_eq!(receiver.try_recv().expect("event was sent").cursor, 3);
+
+        // cursors from the future, e.g. from before a restart, are rejected
+        assert_eq!(bus.subscribe(Some(4)).unwrap_err(), CursorExpired);
+    }
+
+    #[test]
+    fn evicted_cursors_expire() {
+        let bus = GatewayEventBus::default();
+        for _ in 0..=EVENT_BUFFER_SIZE {
+            bus.publish(connected());
+        }
+
+        // the event with cursor 1 has been evicted
+        assert_eq!(bus.subscribe(Some(0)).unwrap_err(), CursorExpired);
+        let (backlog, _) = bus.subscribe(Some(1)).expect("cursor is buffered");
+        assert_eq!(backlog.len(), EVENT_BUFFER_SIZE);
+    }
+}
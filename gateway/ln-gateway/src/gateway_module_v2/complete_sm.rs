@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use fedimint_client::sm::{State, StateTransition};
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
@@ -8,6 +8,7 @@ use fedimint_core::task::sleep;
 use futures::StreamExt;
 use tracing::warn;
 
+use crate::earnings::settle_pending_earnings;
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Settle};
 use crate::gateway_lnrpc::InterceptHtlcResponse;
 use crate::gateway_module_v2::receive_sm::ReceiveSMState;
@@ -75,7 +76,7 @@ impl State for CompleteStateMachine {
                     self.common.htlc_id,
                     result.clone(),
                 ),
-                move |_, _, old_state| Box::pin(Self::transition_completion(old_state)),
+                move |dbtx, _, old_state| Box::pin(Self::transition_completion(dbtx, old_state)),
             )],
             CompleteSMState::Completed => Vec::new(),
         }
@@ -127,7 +128,17 @@ impl CompleteStateMachine {
         htlc_id: u64,
         result: Result<[u8; 32], String>,
     ) {
-        let action = match result {
+        let payment_result = result.as_ref().map(|_| ()).map_err(Clone::clone);
+
+        // The preimage is only available once the transaction funding the incoming
+        // contract was accepted, so a hold invoice is never settled for a contract
+        // that the federation rejected
+        let hold_invoice_payment_hash = context
+            .gateway
+            .hold_invoice_payment_hash(incoming_chan_id, htlc_id)
+            .await;
+
+        let action = match result.clone() {
             Ok(preimage) => Action::Settle(Settle {
                 preimage: preimage.to_vec(),
             }),
@@ -141,14 +152,47 @@ impl CompleteStateMachine {
         };
 
         loop {
+            if context
+                .gateway
+                .is_htlc_manually_resolved(incoming_chan_id, htlc_id)
+                .await
+            {
+                warn!("HTLC was resolved manually, not completing it");
+                return;
+            }
+
             match context.gateway.get_lightning_context().await {
                 Ok(lightning_context) => {
-                    match lightning_context
-                        .lnrpc
-                        .complete_htlc(intercept_htlc_response.clone())
-                        .await
-                    {
-                        Ok(..) => return,
+                    let completion = match (hold_invoice_payment_hash, &result) {
+                        (Some(_), Ok(preimage)) => {
+                            lightning_context.lnrpc.settle_hold_invoice(*preimage).await
+                        }
+                        (Some(payment_hash), Err(..)) => {
+                            lightning_context
+                                .lnrpc
+                                .cancel_hold_invoice(payment_hash)
+                                .await
+                        }
+                        (None, _) => {
+                            lightning_context
+                                .lnrpc
+                                .complete_htlc(intercept_htlc_response.clone())
+                                .await
+                        }
+                    };
+
+                    match completion {
+                        Ok(..) => {
+                            if let Ok(preimage) = &result {
+                                context.gateway.publish_zap_receipt(*preimage).await;
+                            }
+
+                            context
+                                .gateway
+                                .finish_incoming_payment(incoming_chan_id, htlc_id, payment_result)
+                                .await;
+                            return;
+                        }
                         Err(error) => {
                             warn!("Trying to complete HTLC but got {error}, will keep retrying...");
                         }
@@ -163,7 +207,19 @@ impl CompleteStateMachine {
         }
     }
 
-    async fn transition_completion(old_state: CompleteStateMachine) -> CompleteStateMachine {
+    async fn transition_completion(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        old_state: CompleteStateMachine,
+    ) -> CompleteStateMachine {
+        // The fee is only earned if the HTLC was settled with the preimage
+        let settled = matches!(old_state.state, CompleteSMState::Completing(Ok(..)));
+        settle_pending_earnings(
+            &mut dbtx.module_tx(),
+            old_state.common.operation_id,
+            settled,
+        )
+        .await;
+
         old_state.update(CompleteSMState::Completed)
     }
 }
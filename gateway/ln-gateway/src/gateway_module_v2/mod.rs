@@ -38,6 +38,7 @@ use serde::{Deserialize, Serialize};
 use tpe::{AggregatePublicKey, PublicKeyShare};
 use tracing::warn;
 
+use crate::earnings::{dump_earnings, load_earnings, start_pending_earnings, FeeDirection};
 use crate::gateway_module_v2::complete_sm::{
     CompleteSMCommon, CompleteSMState, CompleteStateMachine,
 };
@@ -59,10 +60,10 @@ impl ModuleInit for GatewayClientInitV2 {
 
     async fn dump_database(
         &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _prefix_names: Vec<String>,
+        dbtx: &mut DatabaseTransaction<'_>,
+        prefix_names: Vec<String>,
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
-        Box::new(vec![].into_iter())
+        Box::new(dump_earnings(dbtx, &prefix_names).await.into_iter())
     }
 }
 
@@ -232,13 +233,27 @@ impl GatewayClientModuleV2 {
             .amount_milli_satoshis()
             .ok_or(anyhow!("Invoice is missing amount"))?;
 
-        let min_contract_amount = self
+        let payment_info = self
             .gateway
             .payment_info_v2(&payload.federation_id)
             .await
-            .ok_or(anyhow!("Payment Info not available"))?
-            .send_fee_minimum
-            .add_fee(invoice_msats);
+            .ok_or(anyhow!("Payment Info not available"))?;
+
+        // Invoices issued by our own lightning node are settled via a direct swap
+        // into another federation, which has its own fee schedule
+        let is_direct_swap = self
+            .gateway
+            .get_lightning_context()
+            .await
+            .is_ok_and(|context| {
+                context.lightning_public_key == payload.invoice.recover_payee_pub_key()
+            });
+
+        let min_contract_amount = if is_direct_swap {
+            payment_info.swap_send_fee.add_fee(invoice_msats)
+        } else {
+            payment_info.send_fee_minimum.add_fee(invoice_msats)
+        };
 
         // We need to check that the contract has been confirmed by the federation
         // before we start the state machine to prevent DOS attacks.
@@ -299,11 +314,21 @@ impl GatewayClientModuleV2 {
         }
     }
 
+    /// Returns the routing fees earned by payments of this federation handled
+    /// by the gateway, per direction
+    pub async fn earnings(&self) -> BTreeMap<FeeDirection, Amount> {
+        load_earnings(&mut self.client_ctx.module_db().begin_transaction_nc().await).await
+    }
+
+    /// Funds the incoming contract of `payload` for an HTLC of `amount`, which
+    /// has to pay the invoice, see
+    /// [`CreateInvoicePayload::accepts_htlc_amount`]
     pub async fn relay_incoming_htlc(
         &self,
         incoming_chan_id: u64,
         htlc_id: u64,
         payload: CreateInvoicePayload,
+        amount: Amount,
     ) -> anyhow::Result<()> {
         let operation_id = OperationId::from_encodable(&payload.clone());
 
@@ -311,6 +336,27 @@ impl GatewayClientModuleV2 {
             return Ok(());
         }
 
+        let receive_fee = self
+            .gateway
+            .payment_info_v2(&self.federation_id)
+            .await
+            .ok_or(anyhow!("Payment Info not available"))?
+            .receive_fee;
+
+        if !payload.accepts_htlc_amount(amount, &receive_fee) {
+            bail!("The HTLC amount {amount} does not pay the invoice");
+        }
+
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        start_pending_earnings(
+            &mut dbtx.to_ref_nc(),
+            operation_id,
+            FeeDirection::Incoming,
+            amount.saturating_sub(payload.contract.commitment.amount),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
         let refund_keypair = self.keypair;
 
         let client_output = ClientOutput::<LightningOutput, GatewayClientStateMachinesV2> {
@@ -367,6 +413,17 @@ impl GatewayClientModuleV2 {
                 .ok_or(anyhow!("The internal send failed"));
         }
 
+        let max_contract_amount = self
+            .gateway
+            .swap_fees(&self.federation_id)
+            .await
+            .receive_fee
+            .subtract_fee(payload.invoice_amount.msats);
+
+        if payload.contract.commitment.amount > max_contract_amount {
+            bail!("The incoming contract does not cover the swap receive fee");
+        }
+
         let refund_keypair = self.keypair;
 
         let client_output = ClientOutput::<LightningOutput, GatewayClientStateMachinesV2> {
@@ -402,7 +459,9 @@ impl GatewayClientModuleV2 {
             .ok_or(anyhow!("The internal send failed"))
     }
 
-    async fn subscribe_receive(&self, operation_id: OperationId) -> Option<[u8; 32]> {
+    /// Waits until the incoming contract of the operation was funded and
+    /// decrypted, returns its preimage or `None` if the receive failed
+    pub async fn subscribe_receive(&self, operation_id: OperationId) -> Option<[u8; 32]> {
         let mut stream = self.notifier.subscribe(operation_id).await;
 
         loop {
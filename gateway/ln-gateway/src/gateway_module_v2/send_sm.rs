@@ -15,6 +15,7 @@ use fedimint_lnv2_common::{LightningInput, LightningInputV0, OutgoingWitness};
 use lightning_invoice::Bolt11Invoice;
 use serde::{Deserialize, Serialize};
 
+use crate::earnings::{outgoing_fee, record_earnings, FeeDirection};
 use crate::gateway_lnrpc::PayInvoiceRequest;
 use crate::gateway_module_v2::{GatewayClientContextV2, GatewayClientModuleV2};
 
@@ -118,13 +119,15 @@ impl State for SendStateMachine {
 }
 
 impl SendStateMachine {
+    /// Pays the invoice and returns the preimage together with the routing fee
+    /// the lightning node paid to the network
     async fn send_payment(
         context: GatewayClientContextV2,
         max_delay: u64,
         min_contract_amount: Amount,
         invoice: Bolt11Invoice,
         contract: OutgoingContract,
-    ) -> Result<[u8; 32], Cancelled> {
+    ) -> Result<([u8; 32], Amount), Cancelled> {
         // The following three checks may fail in edge cases since they have inherent
         // timing assumptions. Therefore, they may only be checked after we have created
         // the state machine such that we can cancel the contract.
@@ -157,65 +160,96 @@ impl SendStateMachine {
                 .await
                 .map_err(|e| Cancelled::DirectSwapError(e.to_string()))?;
 
+            // Swaps between federations don't pay any lightning routing fees
             return client
                 .get_first_module::<GatewayClientModuleV2>()
                 .relay_direct_swap(payload)
                 .await
+                .map(|preimage| (preimage, Amount::ZERO))
                 .map_err(|e| Cancelled::DirectSwapError(e.to_string()));
         }
 
         let max_fee = contract.amount - min_contract_amount;
+        let payment_hash = *invoice.payment_hash();
 
-        if lightning_context.lnrpc.supports_private_payments() {
-            lightning_context
-                .lnrpc
-                .pay_private(
-                    PrunedInvoice::try_from(invoice).expect("Invoice has amount"),
-                    max_delay,
-                    max_fee,
-                )
-                .await
-        } else {
-            lightning_context
-                .lnrpc
-                .pay(PayInvoiceRequest {
-                    invoice: invoice.to_string(),
-                    max_delay,
-                    max_fee_msat: max_fee.msats,
-                    payment_hash: invoice.payment_hash().to_byte_array().to_vec(),
-                })
-                .await
-        }
-        .map(|response| {
-            response
-                .preimage
-                .as_slice()
-                .try_into()
-                .expect("Preimage is 32 bytes")
-        })
-        .map_err(|e| Cancelled::LightningRpcError(e.to_string()))
+        context
+            .gateway
+            .pay_with_progress(payment_hash, move |updates| async move {
+                if lightning_context.lnrpc.supports_private_payments() {
+                    lightning_context
+                        .lnrpc
+                        .pay_private_with_updates(
+                            PrunedInvoice::try_from(invoice).expect("Invoice has amount"),
+                            max_delay,
+                            max_fee,
+                            updates,
+                        )
+                        .await
+                } else {
+                    lightning_context
+                        .lnrpc
+                        .pay_with_updates(
+                            PayInvoiceRequest {
+                                invoice: invoice.to_string(),
+                                max_delay,
+                                max_fee_msat: max_fee.msats,
+                                payment_hash: payment_hash.to_byte_array().to_vec(),
+                            },
+                            updates,
+                        )
+                        .await
+                }
+            })
+            .await
+            .map(|response| {
+                let preimage = response
+                    .preimage
+                    .as_slice()
+                    .try_into()
+                    .expect("Preimage is 32 bytes");
+                (preimage, Amount::from_msats(response.fee_msat))
+            })
+            .map_err(|e| Cancelled::LightningRpcError(e.to_string()))
     }
 
     async fn transition_send_payment(
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         old_state: SendStateMachine,
         global_context: DynGlobalClientContext,
-        result: Result<[u8; 32], Cancelled>,
+        result: Result<([u8; 32], Amount), Cancelled>,
     ) -> SendStateMachine {
         match result {
-            Ok(preimage) => {
+            Ok((preimage, routing_fee)) => {
                 let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
                     input: LightningInput::V0(LightningInputV0::Outgoing(
                         old_state.common.contract.contract_id(),
                         OutgoingWitness::Claim(preimage),
                     )),
                     amount: old_state.common.contract.amount,
-                    keys: vec![old_state.common.claim_keypair],
+                    keys: vec![old_state.common.claim_keypair.into()],
                     state_machines: Arc::new(|_, _| vec![]),
                 };
 
                 let outpoints = global_context.claim_input(dbtx, client_input).await.1;
 
+                let invoice_amount = Amount::from_msats(
+                    old_state
+                        .common
+                        .invoice
+                        .amount_milli_satoshis()
+                        .expect("We checked this previously"),
+                );
+                record_earnings(
+                    &mut dbtx.module_tx(),
+                    FeeDirection::Outgoing,
+                    outgoing_fee(
+                        old_state.common.contract.amount,
+                        invoice_amount,
+                        routing_fee,
+                    ),
+                )
+                .await;
+
                 old_state.update(SendSMState::Claiming(Claiming {
                     preimage,
                     outpoints,
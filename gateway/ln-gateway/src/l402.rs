@@ -0,0 +1,507 @@
+//! L402 tokens for the public invoice endpoint
+//!
+//! If the operator sets a price for L402 tokens, creating public invoices
+//! requires a token. A request without a valid token is answered with `402
+//! Payment Required` and a challenge in the `WWW-Authenticate` header, which
+//! consists of a macaroon and an invoice of the lightning node of the gateway.
+//! The macaroon commits to the payment hash of the invoice, so once the client
+//! paid the invoice it can authenticate with `Authorization: L402
+//! <macaroon>:<preimage>` until the macaroon expires. Tokens are verified
+//! without any state, the preimage proves that the invoice was paid.
+//!
+//! Every challenge creates an invoice on the lightning node, so at most
+//! [`MAX_PENDING_L402_CHALLENGES`] unpaid challenges can be outstanding at any
+//! time. Multi-part payments of a challenge are held by [`L402HtlcParts`] until
+//! their parts add up to the price.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use anyhow::{bail, ensure, Context};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE};
+use base64::Engine;
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use fedimint_core::Amount;
+
+/// Location of the macaroons minted by the gateway
+const MACAROON_LOCATION: &str = "fedimint-gateway";
+
+/// Key the root key is hashed with before signing the identifier, as done by
+/// libmacaroons
+const MACAROON_KEY_GENERATOR: &[u8] = b"macaroons-key-generator";
+
+/// Version of the binary macaroon serialization
+const MACAROON_V2: u8 = 2;
+
+const FIELD_EOS: u64 = 0;
+const FIELD_LOCATION: u64 = 1;
+const FIELD_IDENTIFIER: u64 = 2;
+const FIELD_SIGNATURE: u64 = 6;
+
+/// Caveat restricting the validity of a token to before a unix timestamp
+const EXPIRES_AT_CAVEAT: &str = "expires_at=";
+
+/// Expiry of the invoices of L402 challenges
+pub const L402_INVOICE_EXPIRY_SECS: u32 = 600;
+
+/// Maximum number of unexpired L402 challenges, further unauthenticated
+/// requests are rate limited until challenges are paid or expire
+pub const MAX_PENDING_L402_CHALLENGES: usize = 1_000;
+
+/// Macaroon with first party caveats, serialized in the binary V2 format of
+/// libmacaroons
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Macaroon {
+    pub location: String,
+    pub identifier: Vec<u8>,
+    pub caveats: Vec<String>,
+    pub signature: [u8; 32],
+}
+
+impl Macaroon {
+    pub fn new(root_key: &[u8; 32], location: String, identifier: Vec<u8>) -> Macaroon {
+        let signature = hmac(&hmac(MACAROON_KEY_GENERATOR, root_key), &identifier);
+
+        Macaroon {
+            location,
+            identifier,
+            caveats: Vec::new(),
+            signature,
+        }
+    }
+
+    /// Restricts the macaroon further, the caveat is chained into the
+    /// signature so it can't be removed again
+    pub fn add_caveat(&mut self, caveat: String) {
+        self.signature = hmac(&self.signature, caveat.as_bytes());
+        self.caveats.push(caveat);
+    }
+
+    /// Checks that the macaroon was minted with `root_key` and its caveats
+    /// were not tampered with, the caveats themselves are not checked
+    pub fn verify_signature(&self, root_key: &[u8; 32]) -> bool {
+        let mut expected = Macaroon::new(root_key, self.location.clone(), self.identifier.clone());
+        for caveat in &self.caveats {
+            expected.add_caveat(caveat.clone());
+        }
+
+        // Compare all bytes, so the time taken doesn't leak the correct prefix
+        expected
+            .signature
+            .iter()
+            .zip(self.signature.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![MACAROON_V2];
+
+        write_field(&mut bytes, FIELD_LOCATION, self.location.as_bytes());
+        write_field(&mut bytes, FIELD_IDENTIFIER, &self.identifier);
+        write_varint(&mut bytes, FIELD_EOS);
+
+        for caveat in &self.caveats {
+            write_field(&mut bytes, FIELD_IDENTIFIER, caveat.as_bytes());
+            write_varint(&mut bytes, FIELD_EOS);
+        }
+        write_varint(&mut bytes, FIELD_EOS);
+
+        write_field(&mut bytes, FIELD_SIGNATURE, &self.signature);
+
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> anyhow::Result<Macaroon> {
+        let mut reader = FieldReader { bytes, position: 0 };
+
+        ensure!(
+            reader.read_byte()? == MACAROON_V2,
+            "Unsupported macaroon version"
+        );
+
+        let mut field = reader.read_field()?;
+        let location = if field.0 == FIELD_LOCATION {
+            let location = String::from_utf8(field.1.to_vec())?;
+            field = reader.read_field()?;
+            location
+        } else {
+            String::new()
+        };
+
+        ensure!(field.0 == FIELD_IDENTIFIER, "Macaroon has no identifier");
+        let identifier = field.1.to_vec();
+        ensure!(reader.read_varint()? == FIELD_EOS, "Unexpected field");
+
+        let mut caveats = Vec::new();
+        loop {
+            let field = reader.read_field()?;
+            match field.0 {
+                FIELD_EOS => break,
+                FIELD_IDENTIFIER => {
+                    caveats.push(String::from_utf8(field.1.to_vec())?);
+                    ensure!(
+                        reader.read_varint()? == FIELD_EOS,
+                        "Only first party caveats are supported"
+                    );
+                }
+                _ => bail!("Only first party caveats are supported"),
+            }
+        }
+
+        let field = reader.read_field()?;
+        ensure!(field.0 == FIELD_SIGNATURE, "Macaroon has no signature");
+        let signature = field.1.try_into().context("Signature must be 32 bytes")?;
+        ensure!(reader.is_empty(), "Trailing bytes after macaroon");
+
+        Ok(Macaroon {
+            location,
+            identifier,
+            caveats,
+            signature,
+        })
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(key);
+    engine.input(data);
+    Hmac::<sha256::Hash>::from_engine(engine).to_byte_array()
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn write_field(bytes: &mut Vec<u8>, field_type: u64, data: &[u8]) {
+    write_varint(bytes, field_type);
+    write_varint(bytes, data.len() as u64);
+    bytes.extend_from_slice(data);
+}
+
+struct FieldReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn read_byte(&mut self) -> anyhow::Result<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .context("Unexpected end of macaroon")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_varint(&mut self) -> anyhow::Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Varint is too long")
+    }
+
+    /// Reads a field, the end of a section is returned as a field of type
+    /// [`FIELD_EOS`] without data
+    fn read_field(&mut self) -> anyhow::Result<(u64, &'a [u8])> {
+        let field_type = self.read_varint()?;
+        if field_type == FIELD_EOS {
+            return Ok((FIELD_EOS, &[]));
+        }
+
+        let len = usize::try_from(self.read_varint()?)?;
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .context("Unexpected end of macaroon")?;
+        let data = &self.bytes[self.position..end];
+        self.position = end;
+
+        Ok((field_type, data))
+    }
+}
+
+/// Identifier of an L402 macaroon: a version, the payment hash of the invoice
+/// and a random token id
+fn token_identifier(payment_hash: sha256::Hash, token_id: [u8; 32]) -> Vec<u8> {
+    let mut identifier = 0u16.to_be_bytes().to_vec();
+    identifier.extend_from_slice(&payment_hash.to_byte_array());
+    identifier.extend_from_slice(&token_id);
+    identifier
+}
+
+fn token_payment_hash(identifier: &[u8]) -> anyhow::Result<sha256::Hash> {
+    ensure!(
+        identifier.len() == 66 && identifier[..2] == [0, 0],
+        "Unsupported token identifier"
+    );
+    Ok(sha256::Hash::from_slice(&identifier[2..34])?)
+}
+
+/// Mints the macaroon of a token that becomes valid once the invoice with
+/// `payment_hash` was paid and stays valid until `expires_at`
+pub fn mint_token(
+    root_key: &[u8; 32],
+    payment_hash: sha256::Hash,
+    token_id: [u8; 32],
+    expires_at: u64,
+) -> Macaroon {
+    let mut macaroon = Macaroon::new(
+        root_key,
+        MACAROON_LOCATION.to_string(),
+        token_identifier(payment_hash, token_id),
+    );
+    macaroon.add_caveat(format!("{EXPIRES_AT_CAVEAT}{expires_at}"));
+    macaroon
+}
+
+/// Value of the `WWW-Authenticate` header of an L402 challenge
+pub fn challenge_header(macaroon: &Macaroon, invoice: &str) -> String {
+    format!(
+        "L402 macaroon=\"{}\", invoice=\"{invoice}\"",
+        STANDARD.encode(macaroon.serialize())
+    )
+}
+
+/// Parses the value of an `Authorization` header of the form `L402
+/// <macaroon>:<preimage>`, the legacy `LSAT` scheme is accepted as well
+pub fn parse_authorization(header: &str) -> anyhow::Result<(Macaroon, [u8; 32])> {
+    let (scheme, credentials) = header
+        .split_once(' ')
+        .context("Missing authorization scheme")?;
+    ensure!(
+        scheme.eq_ignore_ascii_case("L402") || scheme.eq_ignore_ascii_case("LSAT"),
+        "Not an L402 authorization"
+    );
+
+    let (macaroon, preimage) = credentials
+        .trim()
+        .split_once(':')
+        .context("Missing preimage")?;
+
+    let macaroon = STANDARD
+        .decode(macaroon)
+        .or_else(|_| URL_SAFE.decode(macaroon))
+        .context("Macaroon is not base64 encoded")?;
+    let preimage = hex::decode(preimage)?
+        .try_into()
+        .map_err(|_| anyhow::format_err!("Preimage must be 32 bytes"))?;
+
+    Ok((Macaroon::deserialize(&macaroon)?, preimage))
+}
+
+/// Checks that the token was minted with `root_key`, its invoice was paid and
+/// it has not expired at `now` (unix seconds)
+pub fn verify_token(
+    root_key: &[u8; 32],
+    macaroon: &Macaroon,
+    preimage: [u8; 32],
+    now: u64,
+) -> anyhow::Result<()> {
+    ensure!(
+        macaroon.verify_signature(root_key),
+        "Invalid macaroon signature"
+    );
+
+    ensure!(
+        token_payment_hash(&macaroon.identifier)? == sha256::Hash::hash(&preimage),
+        "Preimage does not match the payment hash of the token"
+    );
+
+    for caveat in &macaroon.caveats {
+        let Some(expires_at) = caveat.strip_prefix(EXPIRES_AT_CAVEAT) else {
+            bail!("Unknown caveat {caveat}");
+        };
+        ensure!(now < expires_at.parse::<u64>()?, "Token expired");
+    }
+
+    Ok(())
+}
+
+/// An intercepted HTLC paying (part of) the invoice of an L402 challenge
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct L402HtlcPart {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+    pub amount: Amount,
+}
+
+#[derive(Debug)]
+enum L402Payment {
+    /// The parts received so far don't add up to the price yet
+    Held(Vec<L402HtlcPart>),
+    /// The preimage was revealed, further parts are settled right away
+    Settling,
+}
+
+/// In-memory accumulator of the HTLCs of multi-part payments of L402
+/// invoices, keyed by payment hash.
+///
+/// The state is intentionally not persisted, the lightning node replays
+/// unresolved HTLCs after a restart of the gateway.
+#[derive(Debug, Default)]
+pub struct L402HtlcParts {
+    payments: Mutex<BTreeMap<sha256::Hash, L402Payment>>,
+}
+
+impl L402HtlcParts {
+    /// Holds `part` of the payment of the invoice for `price` and returns the
+    /// parts that are to be settled, which is empty while the held parts don't
+    /// add up to the price.
+    pub fn add_part(
+        &self,
+        payment_hash: sha256::Hash,
+        part: L402HtlcPart,
+        price: Amount,
+    ) -> Vec<L402HtlcPart> {
+        let mut payments = self.payments.lock().expect("poisoned");
+        let payment = payments
+            .entry(payment_hash)
+            .or_insert_with(|| L402Payment::Held(Vec::new()));
+
+        let L402Payment::Held(parts) = payment else {
+            return vec![part];
+        };
+
+        if !parts.iter().any(|held| {
+            (held.incoming_chan_id, held.htlc_id) == (part.incoming_chan_id, part.htlc_id)
+        }) {
+            parts.push(part);
+        }
+
+        if parts.iter().map(|held| held.amount).sum::<Amount>() < price {
+            return Vec::new();
+        }
+
+        let parts = std::mem::take(parts);
+        *payment = L402Payment::Settling;
+        parts
+    }
+
+    /// Forgets the payment and returns the parts that are still held, e.g. to
+    /// cancel them after the invoice expired
+    pub fn remove(&self, payment_hash: sha256::Hash) -> Vec<L402HtlcPart> {
+        match self
+            .payments
+            .lock()
+            .expect("poisoned")
+            .remove(&payment_hash)
+        {
+            Some(L402Payment::Held(parts)) => parts,
+            Some(L402Payment::Settling) | None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::Amount;
+
+    use super::{
+        challenge_header, mint_token, parse_authorization, verify_token, L402HtlcPart,
+        L402HtlcParts, Macaroon,
+    };
+
+    const ROOT_KEY: [u8; 32] = [7; 32];
+    const PREIMAGE: [u8; 32] = [1; 32];
+
+    fn authorization(macaroon: &Macaroon, preimage: [u8; 32]) -> String {
+        let header = challenge_header(macaroon, "lnbc1");
+        let encoded = header
+            .split('"')
+            .nth(1)
+            .expect("challenge contains the macaroon");
+        format!("L402 {encoded}:{}", hex::encode(preimage))
+    }
+
+    #[test]
+    fn macaroon_serialization_roundtrip() {
+        let macaroon = mint_token(&ROOT_KEY, sha256::Hash::hash(&PREIMAGE), [2; 32], 1_000);
+
+        assert_eq!(
+            Macaroon::deserialize(&macaroon.serialize()).unwrap(),
+            macaroon
+        );
+    }
+
+    #[test]
+    fn paid_tokens_are_valid_until_expiry() {
+        let macaroon = mint_token(&ROOT_KEY, sha256::Hash::hash(&PREIMAGE), [2; 32], 1_000);
+        let (parsed, preimage) = parse_authorization(&authorization(&macaroon, PREIMAGE)).unwrap();
+
+        verify_token(&ROOT_KEY, &parsed, preimage, 999).unwrap();
+        assert!(verify_token(&ROOT_KEY, &parsed, preimage, 1_000).is_err());
+        assert!(verify_token(&ROOT_KEY, &parsed, [3; 32], 999).is_err());
+        assert!(verify_token(&[8; 32], &parsed, preimage, 999).is_err());
+    }
+
+    #[test]
+    fn tampered_caveats_are_rejected() {
+        let macaroon = mint_token(&ROOT_KEY, sha256::Hash::hash(&PREIMAGE), [2; 32], 1_000);
+
+        let mut extended = macaroon.clone();
+        extended.caveats[0] = "expires_at=2000".to_string();
+        assert!(verify_token(&ROOT_KEY, &extended, PREIMAGE, 1_500).is_err());
+
+        let mut stripped = macaroon;
+        stripped.caveats.clear();
+        assert!(verify_token(&ROOT_KEY, &stripped, PREIMAGE, 1_500).is_err());
+    }
+
+    fn part(htlc_id: u64, msats: u64) -> L402HtlcPart {
+        L402HtlcPart {
+            incoming_chan_id: 1,
+            htlc_id,
+            amount: Amount::from_msats(msats),
+        }
+    }
+
+    #[test]
+    fn multi_part_payments_are_held_until_complete() {
+        let parts = L402HtlcParts::default();
+        let payment_hash = sha256::Hash::hash(&PREIMAGE);
+        let price = Amount::from_msats(1_000);
+
+        assert!(parts.add_part(payment_hash, part(0, 400), price).is_empty());
+        // a replayed part is only counted once
+        assert!(parts.add_part(payment_hash, part(0, 400), price).is_empty());
+        assert_eq!(
+            parts.add_part(payment_hash, part(1, 600), price),
+            vec![part(0, 400), part(1, 600)]
+        );
+
+        // once the preimage is revealed, late parts are settled right away
+        assert_eq!(
+            parts.add_part(payment_hash, part(2, 100), price),
+            vec![part(2, 100)]
+        );
+        assert!(parts.remove(payment_hash).is_empty());
+    }
+
+    #[test]
+    fn held_parts_are_returned_on_removal() {
+        let parts = L402HtlcParts::default();
+        let payment_hash = sha256::Hash::hash(&PREIMAGE);
+
+        assert!(parts
+            .add_part(payment_hash, part(0, 400), Amount::from_msats(1_000))
+            .is_empty());
+        assert_eq!(parts.remove(payment_hash), vec![part(0, 400)]);
+        assert!(parts.remove(payment_hash).is_empty());
+    }
+}
@@ -20,10 +20,19 @@
 
 pub mod client;
 mod db;
+pub mod db_archive;
+pub mod earnings;
 pub mod envs;
+pub mod events;
 pub mod gateway_module_v2;
+pub mod l402;
 pub mod lightning;
+pub mod nostr;
+mod payment_progress;
+mod public_receiver;
+pub mod risk;
 pub mod rpc;
+mod scid;
 pub mod state_machine;
 mod types;
 
@@ -35,14 +44,16 @@ use std::borrow::Cow;
 use std::collections::BTreeMap;
 use std::env;
 use std::fmt::Display;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::ops::ControlFlow;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, Context};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use bitcoin::{Address, Network, Txid};
@@ -64,26 +75,29 @@ use fedimint_core::core::{
 use fedimint_core::db::{
     apply_migrations_server, Database, DatabaseTransaction, IDatabaseTransactionOpsCoreTyped,
 };
+use fedimint_core::encoding::Encodable;
 use fedimint_core::endpoint_constants::REGISTER_GATEWAY_ENDPOINT;
 use fedimint_core::fmt_utils::OptStacktrace;
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::CommonModuleInit;
 use fedimint_core::secp256k1::schnorr::Signature;
-use fedimint_core::secp256k1::{KeyPair, PublicKey, Secp256k1};
-use fedimint_core::task::{sleep, TaskGroup, TaskHandle, TaskShutdownToken};
+use fedimint_core::secp256k1::{KeyPair, PublicKey, Scalar, Secp256k1};
+use fedimint_core::task::{sleep, timeout, TaskGroup, TaskHandle, TaskShutdownToken};
 use fedimint_core::time::{duration_since_epoch, now};
 use fedimint_core::util::{SafeUrl, Spanned};
 use fedimint_core::{
     fedimint_build_code_version_env, push_db_pair_items, Amount, BitcoinAmountOrAll, BitcoinHash,
 };
-use fedimint_ln_client::pay::PayInvoicePayload;
-use fedimint_ln_common::config::{GatewayFee, LightningClientConfig};
+use fedimint_ln_client::pay::{PayInvoicePayload, PaymentData};
+use fedimint_ln_common::config::{FeeToAmount, GatewayFee, LightningClientConfig};
 use fedimint_ln_common::contracts::Preimage;
 use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::LightningCommonInit;
 use fedimint_lnv2_client::{
-    Bolt11InvoiceDescription, CreateInvoicePayload, PaymentFee, PaymentInfo, SendPaymentPayload,
+    Bolt11InvoiceDescription, CreateInvoicePayload, PaymentFee, PaymentInfo,
+    PublicInvoiceContractsPayload, SendPaymentPayload,
 };
+use fedimint_lnv2_common::contracts::IncomingContract;
 use fedimint_mint_client::{MintClientInit, MintCommonInit};
 use fedimint_wallet_client::{
     WalletClientInit, WalletClientModule, WalletCommonInit, WithdrawState,
@@ -92,52 +106,107 @@ use futures::stream::StreamExt;
 use gateway_lnrpc::intercept_htlc_response::Action;
 use gateway_lnrpc::{
     CloseChannelsWithPeerResponse, GetNodeInfoResponse, GetRouteHintsResponse,
-    InterceptHtlcResponse,
+    InterceptHtlcResponse, PayInvoiceResponse,
 };
 use hex::ToHex;
-use lightning::{ILnRpcClient, LightningBuilder, LightningMode, LightningRpcError};
+use lightning::{
+    routable_liquidity_sats, ILnRpcClient, LightningBuilder, LightningMode, LightningRpcError,
+    PaymentUpdateSender,
+};
 use lightning_invoice::{Bolt11Invoice, RoutingFees};
 use rand::rngs::OsRng;
 use rand::Rng;
 use rpc::{
-    CloseChannelsWithPeerPayload, ConnectToPeerPayload, FederationInfo, GatewayFedConfig,
-    GatewayInfo, LeaveFedPayload, OpenChannelPayload, SetConfigurationPayload, V1_API_ENDPOINT,
+    CloseChannelPayload, CloseChannelsWithPeerPayload, ConnectToPeerPayload, EncryptedConnections,
+    ExportConnectionsPayload, ExportDbPayload, FederationConfigOverride, FederationConnection,
+    FederationEarnings, FederationInfo, FederationPolicy, FederationRegistration,
+    FederationRiskUtilization, GatewayConfigFile, GatewayConnections, GatewayEarnings,
+    GatewayFedConfig, GatewayInfo, GetChannelPayload, HtlcResolution, ImportConnectionsPayload,
+    L402Config, L402ConfigInfo, LeaveFedPayload, ListPaymentsPayload, NodeRoutingStats, NodeStats,
+    NostrConfig, NostrConfigInfo, OpenChannelPayload, PaymentDirection, PaymentProgress,
+    PaymentStatus, PaymentSummary, PendingHtlc, ResolveHtlcPayload, RiskLimits, RiskStatus,
+    RouteHintRefreshConfig, ScidAliasInfo, SetConfigurationPayload, SetL402ConfigPayload,
+    SetNostrConfigPayload, SetSwapFeesPayload, SwapFees, V1_API_ENDPOINT,
 };
 use state_machine::pay::OutgoingPaymentError;
 use state_machine::GatewayClientModule;
 use strum::IntoEnumIterator;
 use thiserror::Error;
-use tokio::sync::{Mutex, MutexGuard, RwLock};
+use tokio::sync::{mpsc, Mutex, MutexGuard, RwLock};
 use tracing::{debug, error, info, info_span, warn, Instrument};
 
 use crate::db::{
-    get_gatewayd_database_migrations, CreateInvoicePayloadKey, FederationConfig,
-    FederationIdKeyPrefix,
+    get_gatewayd_database_migrations, CreateInvoicePayloadKey, DrainReport, DrainReportKey,
+    FederationConfig, FederationIdKeyPrefix, FederationPolicyKey, FederationRegistrationKey,
+    FederationRegistrationKeyPrefix, FeeModeKey, FeeModeKeyPrefix, HoldInvoiceKey,
+    HoldInvoiceKeyPrefix, L402ConfigKey, L402Invoice, L402InvoiceKey, L402InvoiceKeyPrefix,
+    ManualHtlcResolution, ManualHtlcResolutionKey, ManualHtlcResolutionKeyPrefix,
+    NodeRoutingStatsKey, NostrConfigKey, PaymentRecord, PaymentRecordKey, PaymentRecordKeyPrefix,
+    PendingIncomingPayment, PendingIncomingPaymentKey, PendingIncomingPaymentKeyPrefix, PendingZap,
+    PendingZapKey, PendingZapKeyPrefix, PhantomScidKey, PhantomScidKeyPrefix,
+    PublicInvoiceContractKey, PublicInvoiceContractKeyPrefix, PublicInvoiceContractTokenPrefix,
+    PublicReceiver, PublicReceiverKey, PublicReceiverKeyPrefix, RegisteredScidBaseKey,
+    RegisteredScidBaseKeyPrefix, RiskLimitsKey, RouteHintPrivacyKey, RouteHintPrivacyKeyPrefix,
+    RouteHintRefreshConfigKey, ScidAliasKey, ScidAliasKeyPrefix, SwapFeesKey, SwapFeesKeyPrefix,
 };
+use crate::db_archive::{export_db_archive, EncryptedDbArchive};
+use crate::earnings::FeeDirection;
+use crate::events::{GatewayEvent, GatewayEventBus, LIQUIDITY_CHECK_INTERVAL};
 use crate::gateway_lnrpc::create_invoice_request::Description;
-use crate::gateway_lnrpc::intercept_htlc_response::Forward;
+use crate::gateway_lnrpc::intercept_htlc_response::{Cancel, Forward, Settle};
 use crate::gateway_lnrpc::CreateInvoiceRequest;
 use crate::gateway_module_v2::GatewayClientModuleV2;
+use crate::l402::{L402HtlcPart, L402HtlcParts};
 use crate::lightning::cln::RouteHtlcStream;
 use crate::lightning::GatewayLightningBuilder;
+use crate::nostr::{
+    parse_zap_request, publish_event, zap_receipt, zap_request_relays, NostrEvent,
+    MAX_ZAP_REQUEST_LEN,
+};
+use crate::payment_progress::PaymentProgressLog;
+use crate::public_receiver::{
+    PublicInvoiceRateLimiter, MAX_PUBLIC_INVOICE_DESCRIPTION_LEN, PUBLIC_INVOICE_EXPIRY_SECS,
+};
+use crate::risk::{PendingOutgoingPayment, PendingOutgoingPayments, RiskLimitViolation};
 use crate::rpc::rpc_server::{hash_password, run_webserver};
 use crate::rpc::{
-    BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, RestorePayload,
-    WithdrawPayload,
+    BackupPayload, BalancePayload, ConnectFedPayload, CreatePublicInvoicePayload,
+    DepositAddressPayload, FeeMode, RegisterPublicReceiverPayload, RestorePayload,
+    SetFederationRegistrationPayload, SetFeeModePayload, SetRouteHintPrivacyPayload,
+    ShutdownPayload, WithdrawPayload,
 };
-use crate::state_machine::GatewayExtPayStates;
+use crate::state_machine::{GatewayExtPayStates, Htlc};
 
 /// This initial SCID is considered invalid by LND HTLC interceptor,
 /// So we should always increment the value before assigning a new SCID.
 const INITIAL_SCID: u64 = 0;
 
+/// Maximum age in blocks of the channel a phantom short channel id pretends to
+/// belong to
+const PHANTOM_SCID_MAX_AGE: u32 = 52_560;
+
+/// Maximum length in bytes of a lightning node alias
+const MAX_LIGHTNING_ALIAS_LEN: usize = 32;
+
+/// How long an L402 token stays valid after it was minted, unless the operator
+/// configures a different validity
+const DEFAULT_L402_TOKEN_VALIDITY_SECS: u64 = 24 * 60 * 60;
+
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
 
+/// How often the route hint refresh task checks whether it was enabled again
+const ROUTE_HINT_REFRESH_DISABLED_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 /// The default number of route hints that the legacy gateway provides for
 /// invoice creation.
 const DEFAULT_NUM_ROUTE_HINTS: u32 = 1;
 
+/// The default number of blocks the gateway keeps as a safety margin between
+/// the timelock of an outgoing contract and the maximum delay of the lightning
+/// payment it funds
+const DEFAULT_TIMELOCK_DELTA: u64 = 10;
+
 /// Default Bitcoin network for testing purposes.
 pub const DEFAULT_NETWORK: Network = Network::Regtest;
 
@@ -151,9 +220,31 @@ pub const DEFAULT_FEES: RoutingFees = RoutingFees {
     proportional_millionths: 10000,
 };
 
+/// How long the gateway waits for in-flight payments to settle or refund when
+/// shutting down, unless the operator requests a different timeout
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often the gateway checks for in-flight payments while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 /// LNv2 CLTV Delta in blocks
 const EXPIRATION_DELTA_MINIMUM_V2: u64 = 144;
 
+/// Longest expiry of LNv2 invoices in seconds
+const INVOICE_EXPIRY_MAXIMUM_V2: u32 = 7 * 24 * 60 * 60;
+
+/// How often the health watchdog checks whether the lightning node is
+/// reachable and synced to the chain
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the health watchdog waits for the lightning node to respond before
+/// considering it unreachable
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the forwarding and payment history of the lightning node is
+/// synced into the [`NodeRoutingStats`]
+const NODE_STATS_SYNC_INTERVAL: Duration = Duration::from_secs(600);
+
 pub type Result<T> = std::result::Result<T, GatewayError>;
 
 /// Name of the gateway's database that is used for metadata and configuration
@@ -167,24 +258,40 @@ const DEFAULT_MODULE_KINDS: [(ModuleInstanceId, &ModuleKind); 2] = [
 ];
 
 /// Command line parameters for starting the gateway. `mode`, `data_dir`,
-/// `listen`, and `api_addr` are all required.
+/// `listen`, and `api_addr` are all required, either on the command line,
+/// through the environment or in the `--config` file.
 #[derive(Parser)]
 #[command(version)]
 struct GatewayOpts {
     #[clap(subcommand)]
-    mode: LightningMode,
+    mode: Option<LightningMode>,
+
+    /// Path to a TOML file containing gateway settings, see
+    /// [`GatewayConfigFile`]. Settings given on the command line or through
+    /// the environment take precedence over the file.
+    #[arg(long = "config", env = envs::FM_GATEWAY_CONFIG_ENV)]
+    pub config: Option<PathBuf>,
+
+    /// Additional lightning nodes to route payments through, as a JSON list
+    /// of lightning modes. The node given by `mode` remains the primary node.
+    #[arg(
+        long = "additional-lightning-nodes",
+        env = envs::FM_GATEWAY_ADDITIONAL_LIGHTNING_NODES_ENV,
+        value_parser = LightningMode::parse_list,
+    )]
+    pub additional_lightning_nodes: Option<Vec<LightningMode>>,
 
     /// Path to folder containing gateway config and data files
     #[arg(long = "data-dir", env = envs::FM_GATEWAY_DATA_DIR_ENV)]
-    pub data_dir: PathBuf,
+    pub data_dir: Option<PathBuf>,
 
     /// Gateway webserver listen address
     #[arg(long = "listen", env = envs::FM_GATEWAY_LISTEN_ADDR_ENV)]
-    pub listen: SocketAddr,
+    pub listen: Option<SocketAddr>,
 
     /// Public URL from which the webserver API is reachable
     #[arg(long = "api-addr", env = envs::FM_GATEWAY_API_ADDR_ENV)]
-    pub api_addr: SafeUrl,
+    pub api_addr: Option<SafeUrl>,
 
     /// Gateway webserver authentication password
     #[arg(long = "password", env = envs::FM_GATEWAY_PASSWORD_ENV)]
@@ -199,32 +306,99 @@ struct GatewayOpts {
     #[arg(long = "fees", env = envs::FM_GATEWAY_FEES_ENV)]
     pub fees: Option<GatewayFee>,
 
-    /// Number of route hints to return in invoices
+    /// Number of route hints to return in invoices, defaults to
+    /// `DEFAULT_NUM_ROUTE_HINTS`
+    #[arg(long = "num-route-hints", env = envs::FM_NUMBER_OF_ROUTE_HINTS_ENV)]
+    pub num_route_hints: Option<u32>,
+
+    /// Publish a liquidity alert on the event stream when the total inbound or
+    /// outbound liquidity of the lightning node drops below this many sats
     #[arg(
-        long = "num-route-hints",
-        env = envs::FM_NUMBER_OF_ROUTE_HINTS_ENV,
-        default_value_t = DEFAULT_NUM_ROUTE_HINTS
+        long = "low-liquidity-alert-threshold-sats",
+        env = envs::FM_GATEWAY_LOW_LIQUIDITY_ALERT_THRESHOLD_SATS_ENV
     )]
-    pub num_route_hints: u32,
+    pub low_liquidity_alert_threshold_sats: Option<u64>,
+
+    /// Per-federation routing fees, only configurable through the `--config`
+    /// file
+    #[clap(skip)]
+    pub federations: Vec<FederationConfigOverride>,
 }
 
 impl GatewayOpts {
+    /// Reads the `--config` file, if any, and fills in all settings that were
+    /// not given on the command line or through the environment.
+    fn merge_config_file(mut self) -> anyhow::Result<Self> {
+        let Some(path) = self.config.as_ref() else {
+            return Ok(self);
+        };
+
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read gateway config file {}", path.display()))?;
+        let file: GatewayConfigFile = toml::from_str(&raw)
+            .with_context(|| format!("Failed to parse gateway config file {}", path.display()))?;
+
+        self.mode = self.mode.or(file.mode);
+        self.additional_lightning_nodes = self
+            .additional_lightning_nodes
+            .or(file.additional_lightning_nodes);
+        self.data_dir = self.data_dir.or(file.data_dir);
+        self.listen = self.listen.or(file.listen);
+        self.api_addr = self.api_addr.or(file.api_addr);
+        self.password = self.password.or(file.password);
+        self.network = self.network.or(file.network);
+        self.fees = self.fees.or(file.fees.map(|fees| GatewayFee(fees.into())));
+        self.num_route_hints = self.num_route_hints.or(file.num_route_hints);
+        self.low_liquidity_alert_threshold_sats = self
+            .low_liquidity_alert_threshold_sats
+            .or(file.low_liquidity_alert_threshold_sats);
+        self.federations = file.federations;
+
+        Ok(self)
+    }
+
+    fn mode(&self) -> anyhow::Result<&LightningMode> {
+        self.mode
+            .as_ref()
+            .context("Missing lightning mode, use the `cln` or `lnd` subcommand or set `mode`")
+    }
+
+    fn data_dir(&self) -> anyhow::Result<&PathBuf> {
+        self.data_dir.as_ref().context(
+            "Missing --data-dir, set it as an argument, env variable or in the config file",
+        )
+    }
+
     /// Converts the command line parameters into a helper struct the Gateway
     /// uses to store runtime parameters.
     fn to_gateway_parameters(&self) -> anyhow::Result<GatewayParameters> {
-        let versioned_api = self.api_addr.join(V1_API_ENDPOINT).map_err(|e| {
-            anyhow::anyhow!(
-                "Failed to version gateway API address: {api_addr:?}, error: {e:?}",
-                api_addr = self.api_addr,
-            )
+        let listen = self.listen.context(
+            "Missing --listen, set it as an argument, env variable or in the config file",
+        )?;
+        let api_addr = self.api_addr.clone().context(
+            "Missing --api-addr, set it as an argument, env variable or in the config file",
+        )?;
+        let versioned_api = api_addr.join(V1_API_ENDPOINT).map_err(|e| {
+            anyhow::anyhow!("Failed to version gateway API address: {api_addr:?}, error: {e:?}")
         })?;
         Ok(GatewayParameters {
-            listen: self.listen,
+            listen,
             versioned_api,
             password: self.password.clone(),
             network: self.network,
-            num_route_hints: self.num_route_hints,
+            num_route_hints: self.num_route_hints.unwrap_or(DEFAULT_NUM_ROUTE_HINTS),
             fees: self.fees.clone(),
+            low_liquidity_alert_threshold_sats: self.low_liquidity_alert_threshold_sats,
+            startup_config: GatewayConfigFile {
+                mode: Some(self.mode()?.clone()),
+                additional_lightning_nodes: self.additional_lightning_nodes.clone(),
+                data_dir: Some(self.data_dir()?.clone()),
+                listen: Some(listen),
+                api_addr: Some(api_addr),
+                low_liquidity_alert_threshold_sats: self.low_liquidity_alert_threshold_sats,
+                federations: self.federations.clone(),
+                ..GatewayConfigFile::default()
+            },
         })
     }
 }
@@ -243,6 +417,9 @@ pub struct GatewayParameters {
     network: Option<Network>,
     num_route_hints: u32,
     fees: Option<GatewayFee>,
+    low_liquidity_alert_threshold_sats: Option<u64>,
+    /// Settings that are only read on startup, reported by `get_config`
+    startup_config: GatewayConfigFile,
 }
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -262,7 +439,15 @@ pub enum GatewayState {
     Initializing,
     Configuring,
     Connected,
-    Running { lightning_context: LightningContext },
+    Running {
+        lightning_context: LightningContext,
+    },
+    /// The lightning node is connected but unreachable or not synced to the
+    /// chain, new payments are rejected until it recovers
+    Degraded {
+        lightning_context: LightningContext,
+        reason: String,
+    },
     Disconnected,
 }
 
@@ -273,6 +458,7 @@ impl Display for GatewayState {
             GatewayState::Configuring => write!(f, "Configuring"),
             GatewayState::Connected => write!(f, "Connected"),
             GatewayState::Running { .. } => write!(f, "Running"),
+            GatewayState::Degraded { .. } => write!(f, "Degraded"),
             GatewayState::Disconnected => write!(f, "Disconnected"),
         }
     }
@@ -329,11 +515,15 @@ pub struct Gateway {
     // handling incoming HTLCs.
     scid_to_federation: ScidToFederationMap,
 
+    // Route hints the gateway last registered with each federation, used to detect when the
+    // route hints of the lightning node changed.
+    registered_route_hints: Arc<RwLock<BTreeMap<FederationId, Vec<RouteHint>>>>,
+
     // A public key representing the identity of the gateway. Private key is not used.
     pub gateway_id: PublicKey,
 
-    // Tracker for short channel ID assignments. When connecting a new federation,
-    // this value is incremented and assigned to the federation as the `mint_channel_id`
+    // Tracker for short channel ID assignments. When connecting a federation for the first
+    // time, this value is incremented and persisted as the federation's SCID alias
     max_used_scid: Arc<Mutex<u64>>,
 
     // The Gateway's API URL.
@@ -341,6 +531,31 @@ pub struct Gateway {
 
     // The socket the gateway listens on.
     listen: SocketAddr,
+
+    // Per-token quotas for the public invoice endpoint.
+    public_invoice_rate_limiter: Arc<PublicInvoiceRateLimiter>,
+
+    // Parts of multi-part payments of L402 invoices that are held until they add up to the price.
+    l402_htlc_parts: Arc<L402HtlcParts>,
+
+    // Publishes payment, federation and liquidity events to websocket subscribers.
+    events: Arc<GatewayEventBus>,
+
+    // Threshold below which liquidity alerts are published, disabled if `None`.
+    low_liquidity_alert_threshold_sats: Option<u64>,
+
+    // HTLC attempts the lightning node reported for recent outgoing payments.
+    payment_progress: Arc<PaymentProgressLog>,
+
+    // Outgoing payments in flight, which count towards the risk limits.
+    pending_outgoing_payments: Arc<PendingOutgoingPayments>,
+
+    // Set once the gateway started draining in-flight payments before shutting down. New HTLCs
+    // are not intercepted and new payments are rejected from then on.
+    draining: Arc<AtomicBool>,
+
+    // Settings that are only read on startup and reported back by `get_config`.
+    startup_config: Arc<GatewayConfigFile>,
 }
 
 impl std::fmt::Debug for Gateway {
@@ -385,6 +600,12 @@ impl Gateway {
                 num_route_hints,
                 fees: Some(GatewayFee(fees)),
                 network,
+                low_liquidity_alert_threshold_sats: None,
+                startup_config: GatewayConfigFile {
+                    listen: Some(listen),
+                    api_addr: Some(api_addr),
+                    ..GatewayConfigFile::default()
+                },
             },
             gateway_db,
             client_builder,
@@ -395,7 +616,8 @@ impl Gateway {
     /// Default function for creating a gateway with the `Mint`, `Wallet`, and
     /// `Gateway` modules.
     pub async fn new_with_default_modules() -> anyhow::Result<Gateway> {
-        let opts = GatewayOpts::parse();
+        let opts = GatewayOpts::parse().merge_config_file()?;
+        let data_dir = opts.data_dir()?;
 
         // Gateway module will be attached when the federation clients are created
         // because the LN RPC will be injected with `GatewayClientGen`.
@@ -406,12 +628,12 @@ impl Gateway {
         let decoders = registry.available_decoders(DEFAULT_MODULE_KINDS.iter().copied())?;
 
         let gateway_db = Database::new(
-            fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?,
+            fedimint_rocksdb::RocksDb::open(data_dir.join(DB_FILE))?,
             decoders.clone(),
         );
 
         let client_builder = GatewayClientBuilder::new(
-            opts.data_dir.clone(),
+            data_dir.clone(),
             registry.clone(),
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
         );
@@ -423,7 +645,11 @@ impl Gateway {
 
         Gateway::new(
             Arc::new(GatewayLightningBuilder {
-                lightning_mode: opts.mode.clone(),
+                lightning_mode: opts.mode()?.clone(),
+                additional_lightning_modes: opts
+                    .additional_lightning_nodes
+                    .clone()
+                    .unwrap_or_default(),
             }),
             opts.to_gateway_parameters()?,
             gateway_db,
@@ -465,9 +691,19 @@ impl Gateway {
             gateway_db,
             clients: Arc::new(RwLock::new(BTreeMap::new())),
             scid_to_federation: Arc::new(RwLock::new(BTreeMap::new())),
+            registered_route_hints: Arc::new(RwLock::new(BTreeMap::new())),
             client_joining_lock: Arc::new(Mutex::new(ClientsJoinLock)),
             versioned_api: gateway_parameters.versioned_api,
             listen: gateway_parameters.listen,
+            public_invoice_rate_limiter: Arc::new(PublicInvoiceRateLimiter::default()),
+            l402_htlc_parts: Arc::new(L402HtlcParts::default()),
+            events: Arc::new(GatewayEventBus::default()),
+            low_liquidity_alert_threshold_sats: gateway_parameters
+                .low_liquidity_alert_threshold_sats,
+            payment_progress: Arc::new(PaymentProgressLog::default()),
+            pending_outgoing_payments: Arc::new(PendingOutgoingPayments::default()),
+            draining: Arc::new(AtomicBool::new(false)),
+            startup_config: Arc::new(gateway_parameters.startup_config),
         })
     }
 
@@ -518,6 +754,202 @@ impl Gateway {
                         );
                     }
                 }
+                DbKeyPrefix::PublicReceiver => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PublicReceiverKeyPrefix,
+                        PublicReceiverKey,
+                        PublicReceiver,
+                        gateway_items,
+                        "Public Receivers"
+                    );
+                }
+                DbKeyPrefix::PaymentRecord => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PaymentRecordKeyPrefix,
+                        PaymentRecordKey,
+                        PaymentRecord,
+                        gateway_items,
+                        "Payment Records"
+                    );
+                }
+                DbKeyPrefix::PendingIncomingPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PendingIncomingPaymentKeyPrefix,
+                        PendingIncomingPaymentKey,
+                        PendingIncomingPayment,
+                        gateway_items,
+                        "Pending Incoming Payments"
+                    );
+                }
+                DbKeyPrefix::ScidAlias => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ScidAliasKeyPrefix,
+                        ScidAliasKey,
+                        u64,
+                        gateway_items,
+                        "SCID Aliases"
+                    );
+                }
+                DbKeyPrefix::PhantomScid => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PhantomScidKeyPrefix,
+                        PhantomScidKey,
+                        FederationId,
+                        gateway_items,
+                        "Phantom SCIDs"
+                    );
+                }
+                DbKeyPrefix::RouteHintPrivacy => {
+                    push_db_pair_items!(
+                        dbtx,
+                        RouteHintPrivacyKeyPrefix,
+                        RouteHintPrivacyKey,
+                        u64,
+                        gateway_items,
+                        "Route Hint Privacy"
+                    );
+                }
+                DbKeyPrefix::DrainReport => {
+                    if let Some(drain_report) = dbtx.get_value(&DrainReportKey).await {
+                        gateway_items.insert("Drain Report".to_string(), Box::new(drain_report));
+                    }
+                }
+                DbKeyPrefix::FederationPolicy => {
+                    if let Some(policy) = dbtx.get_value(&FederationPolicyKey).await {
+                        gateway_items.insert("Federation Policy".to_string(), Box::new(policy));
+                    }
+                }
+                DbKeyPrefix::SwapFees => {
+                    push_db_pair_items!(
+                        dbtx,
+                        SwapFeesKeyPrefix,
+                        SwapFeesKey,
+                        SwapFees,
+                        gateway_items,
+                        "Swap Fees"
+                    );
+                }
+                DbKeyPrefix::FeeMode => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FeeModeKeyPrefix,
+                        FeeModeKey,
+                        FeeMode,
+                        gateway_items,
+                        "Fee Modes"
+                    );
+                }
+                DbKeyPrefix::RouteHintRefreshConfig => {
+                    if let Some(refresh_config) = dbtx.get_value(&RouteHintRefreshConfigKey).await {
+                        gateway_items.insert(
+                            "Route Hint Refresh Config".to_string(),
+                            Box::new(refresh_config),
+                        );
+                    }
+                }
+                DbKeyPrefix::RiskLimits => {
+                    if let Some(limits) = dbtx.get_value(&RiskLimitsKey).await {
+                        gateway_items.insert("Risk Limits".to_string(), Box::new(limits));
+                    }
+                }
+                DbKeyPrefix::HoldInvoice => {
+                    push_db_pair_items!(
+                        dbtx,
+                        HoldInvoiceKeyPrefix,
+                        HoldInvoiceKey,
+                        (),
+                        gateway_items,
+                        "Hold Invoices"
+                    );
+                }
+                DbKeyPrefix::ManualHtlcResolution => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ManualHtlcResolutionKeyPrefix,
+                        ManualHtlcResolutionKey,
+                        ManualHtlcResolution,
+                        gateway_items,
+                        "Manual HTLC Resolutions"
+                    );
+                }
+                DbKeyPrefix::NostrConfig => {
+                    // The secret key is not dumped
+                    if let Some(config) = dbtx.get_value(&NostrConfigKey).await {
+                        gateway_items.insert(
+                            "Nostr Config".to_string(),
+                            Box::new(NostrConfigInfo::from(&config)),
+                        );
+                    }
+                }
+                DbKeyPrefix::L402Config => {
+                    // The root key is not dumped
+                    if let Some(config) = dbtx.get_value(&L402ConfigKey).await {
+                        gateway_items.insert(
+                            "L402 Config".to_string(),
+                            Box::new(L402ConfigInfo::from(&config)),
+                        );
+                    }
+                }
+                DbKeyPrefix::L402Invoice => {
+                    push_db_pair_items!(
+                        dbtx,
+                        L402InvoiceKeyPrefix,
+                        L402InvoiceKey,
+                        L402Invoice,
+                        gateway_items,
+                        "L402 Invoices"
+                    );
+                }
+                DbKeyPrefix::NodeRoutingStats => {
+                    if let Some(stats) = dbtx.get_value(&NodeRoutingStatsKey).await {
+                        gateway_items.insert("Node Routing Stats".to_string(), Box::new(stats));
+                    }
+                }
+                DbKeyPrefix::FederationRegistration => {
+                    push_db_pair_items!(
+                        dbtx,
+                        FederationRegistrationKeyPrefix,
+                        FederationRegistrationKey,
+                        FederationRegistration,
+                        gateway_items,
+                        "Federation Registrations"
+                    );
+                }
+                DbKeyPrefix::PublicInvoiceContract => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PublicInvoiceContractKeyPrefix,
+                        PublicInvoiceContractKey,
+                        IncomingContract,
+                        gateway_items,
+                        "Public Invoice Contracts"
+                    );
+                }
+                DbKeyPrefix::RegisteredScidBase => {
+                    push_db_pair_items!(
+                        dbtx,
+                        RegisteredScidBaseKeyPrefix,
+                        RegisteredScidBaseKey,
+                        FederationId,
+                        gateway_items,
+                        "Registered SCID Bases"
+                    );
+                }
+                DbKeyPrefix::PendingZap => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PendingZapKeyPrefix,
+                        PendingZapKey,
+                        PendingZap,
+                        gateway_items,
+                        "Pending Zaps"
+                    );
+                }
                 DbKeyPrefix::GatewayPublicKey => {
                     if let Some(public_key) = dbtx.get_value(&GatewayPublicKey).await {
                         gateway_items
@@ -539,6 +971,10 @@ impl Gateway {
         self.register_clients_timer(tg);
         self.load_clients().await;
         self.start_gateway(tg);
+        self.start_liquidity_monitor(tg);
+        self.start_node_stats_sync(tg);
+        self.start_route_hint_refresh(tg);
+        self.start_health_watchdog(tg);
         // start webserver last to avoid handling requests before fully initialized
         run_webserver(self.clone(), tg).await?;
         let handle = tg.make_handle();
@@ -559,12 +995,11 @@ impl Gateway {
                 }
 
                 let mut htlc_task_group = tg.make_subgroup();
-                let lnrpc_route = self_copy.lightning_builder.build().await;
-
                 debug!("Will try to intercept HTLC stream...");
                 // Re-create the HTLC stream if the connection breaks
-                match lnrpc_route
-                    .route_htlcs(&mut htlc_task_group)
+                match self_copy
+                    .lightning_builder
+                    .build_and_route_htlcs(&mut htlc_task_group)
                     .await
                 {
                     Ok((stream, ln_client)) => {
@@ -595,6 +1030,8 @@ impl Gateway {
                                         num_route_hints: None,
                                         routing_fees: None,
                                         per_federation_routing_fees: None,
+                                        route_hint_refresh_interval_secs: None,
+                                        route_hint_refresh_hysteresis: None,
                                     }).await.expect("Failed to set gateway configuration");
                                     continue;
                                 }
@@ -637,6 +1074,308 @@ impl Gateway {
         });
     }
 
+    /// Periodically checks the liquidity of the lightning node and publishes
+    /// an alert on the event stream when the total inbound or outbound
+    /// liquidity drops below the configured threshold. Alerts are only
+    /// published again after the liquidity recovered above the threshold.
+    fn start_liquidity_monitor(&self, task_group: &mut TaskGroup) {
+        let Some(threshold_sats) = self.low_liquidity_alert_threshold_sats else {
+            return;
+        };
+
+        let gateway = self.clone();
+        task_group.spawn_cancellable("liquidity monitor", async move {
+            let mut outbound_alerted = false;
+            let mut inbound_alerted = false;
+            loop {
+                sleep(LIQUIDITY_CHECK_INTERVAL).await;
+
+                let Ok(channels) = gateway.handle_list_active_channels_msg().await else {
+                    continue;
+                };
+                let (outbound_liquidity_sats, inbound_liquidity_sats) =
+                    routable_liquidity_sats(&channels);
+
+                let outbound_low = outbound_liquidity_sats < threshold_sats;
+                if outbound_low && !outbound_alerted {
+                    warn!(%outbound_liquidity_sats, "Outbound liquidity is low");
+                    gateway.events.publish(GatewayEvent::LowOutboundLiquidity {
+                        outbound_liquidity_sats,
+                        threshold_sats,
+                    });
+                }
+                outbound_alerted = outbound_low;
+
+                let inbound_low = inbound_liquidity_sats < threshold_sats;
+                if inbound_low && !inbound_alerted {
+                    warn!(%inbound_liquidity_sats, "Inbound liquidity is low");
+                    gateway.events.publish(GatewayEvent::LowInboundLiquidity {
+                        inbound_liquidity_sats,
+                        threshold_sats,
+                    });
+                }
+                inbound_alerted = inbound_low;
+            }
+        });
+    }
+
+    /// Periodically adds the forwards and rebalances of the lightning node to
+    /// the [`NodeRoutingStats`], see [`Self::sync_node_stats`]
+    fn start_node_stats_sync(&self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group.spawn_cancellable("node stats sync", async move {
+            loop {
+                if let Err(e) = gateway.sync_node_stats().await {
+                    debug!("Failed to sync lightning node stats: {e:?}");
+                }
+                sleep(NODE_STATS_SYNC_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Adds the forwards and rebalances the lightning node completed since the
+    /// last sync to the persisted [`NodeRoutingStats`]. Nothing is stored if
+    /// the history of any node can't be fetched, so the next sync picks up
+    /// the same events again.
+    pub async fn sync_node_stats(&self) -> Result<NodeRoutingStats> {
+        let context = self.get_lightning_context().await?;
+
+        let mut stats = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&NodeRoutingStatsKey)
+            .await
+            .unwrap_or_default();
+
+        // Events completing while we fetch the history are counted by the
+        // next sync
+        let until_secs = duration_since_epoch().as_secs();
+        let forwards = context.lnrpc.list_forwards(stats.synced_until_secs).await?;
+        let rebalances = context
+            .lnrpc
+            .list_rebalances(stats.synced_until_secs)
+            .await?;
+
+        for forward in forwards
+            .iter()
+            .filter(|forward| forward.timestamp_secs < until_secs)
+        {
+            stats.num_forwards += 1;
+            stats.forwarded += forward.amount_out;
+            stats.forwarding_fees += forward.fee();
+        }
+
+        for rebalance in rebalances
+            .iter()
+            .filter(|rebalance| rebalance.timestamp_secs < until_secs)
+        {
+            stats.num_rebalances += 1;
+            stats.rebalanced += rebalance.amount;
+            stats.rebalancing_fees += rebalance.fee;
+        }
+
+        stats.synced_until_secs = until_secs;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&NodeRoutingStatsKey, &stats).await;
+        dbtx.commit_tx().await;
+
+        Ok(stats)
+    }
+
+    /// Spawns a task that periodically checks whether the lightning node is
+    /// reachable and synced to the chain. The gateway transitions from
+    /// `Running` to `Degraded` when it isn't, and back once it recovered.
+    /// Losing the connection entirely is handled by the HTLC stream, which
+    /// transitions the gateway to `Disconnected`.
+    fn start_health_watchdog(&self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group.spawn_cancellable("health watchdog", async move {
+            loop {
+                sleep(HEALTH_CHECK_INTERVAL).await;
+
+                let lightning_context = match gateway.state.read().await.clone() {
+                    GatewayState::Running { lightning_context }
+                    | GatewayState::Degraded {
+                        lightning_context, ..
+                    } => lightning_context,
+                    _ => continue,
+                };
+
+                let unhealthy_reason =
+                    match timeout(HEALTH_CHECK_TIMEOUT, lightning_context.lnrpc.info()).await {
+                        Ok(Ok(info)) if info.synced_to_chain => None,
+                        Ok(Ok(info)) => Some(format!(
+                            "Lightning node is not synced to the chain at block height {}",
+                            info.block_height
+                        )),
+                        Ok(Err(e)) => Some(format!("Lightning node is unreachable: {e}")),
+                        Err(_) => Some(format!(
+                            "Lightning node did not respond within {HEALTH_CHECK_TIMEOUT:?}"
+                        )),
+                    };
+
+                gateway.update_health(unhealthy_reason).await;
+            }
+        });
+    }
+
+    /// Transitions between `Running` and `Degraded` according to the result of
+    /// a health check, any other state is left unchanged as it is managed by
+    /// the HTLC stream
+    async fn update_health(&self, unhealthy_reason: Option<String>) {
+        let mut state = self.state.write().await;
+
+        match (state.clone(), unhealthy_reason) {
+            (GatewayState::Running { lightning_context }, Some(reason)) => {
+                warn!(%reason, "Gateway is degraded, rejecting new payments");
+                self.events.publish(GatewayEvent::NodeDegraded {
+                    reason: reason.clone(),
+                });
+                *state = GatewayState::Degraded {
+                    lightning_context,
+                    reason,
+                };
+            }
+            (
+                GatewayState::Degraded {
+                    lightning_context, ..
+                },
+                None,
+            ) => {
+                info!("Lightning node recovered, gateway is running again");
+                self.events.publish(GatewayEvent::NodeRecovered);
+                *state = GatewayState::Running { lightning_context };
+            }
+            (
+                GatewayState::Degraded {
+                    lightning_context, ..
+                },
+                Some(reason),
+            ) => {
+                *state = GatewayState::Degraded {
+                    lightning_context,
+                    reason,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns an error if the gateway doesn't accept new payments, because it
+    /// is shutting down or its lightning node is degraded
+    async fn ensure_accepting_payments(&self) -> Result<()> {
+        if self.is_draining() {
+            return Err(GatewayError::ShuttingDown);
+        }
+
+        if let GatewayState::Degraded { reason, .. } = &*self.state.read().await {
+            return Err(GatewayError::Degraded(reason.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Spawns a task that periodically fetches the route hints of the lightning
+    /// node and re-registers the gateway with every federation it registered
+    /// different route hints with, e.g. because a channel was opened or
+    /// closed. Changed route hints have to be seen by
+    /// [`RouteHintRefreshConfig::hysteresis_checks`] consecutive checks before
+    /// the gateway re-registers.
+    fn start_route_hint_refresh(&self, task_group: &mut TaskGroup) {
+        let gateway = self.clone();
+        task_group.spawn_cancellable("route hint refresh", async move {
+            // Changed route hints of each federation and the number of consecutive checks
+            // they were seen by
+            let mut pending_changes: BTreeMap<FederationId, (Vec<RouteHint>, u32)> =
+                BTreeMap::new();
+            loop {
+                let refresh_config = gateway.route_hint_refresh_config().await;
+                if refresh_config.interval_secs == 0 {
+                    pending_changes.clear();
+                    sleep(ROUTE_HINT_REFRESH_DISABLED_POLL_INTERVAL).await;
+                    continue;
+                }
+                sleep(Duration::from_secs(refresh_config.interval_secs)).await;
+
+                let Some(gateway_config) = gateway.gateway_config.read().await.clone() else {
+                    continue;
+                };
+                let Ok(lightning_context) = gateway.get_lightning_context().await else {
+                    continue;
+                };
+
+                let route_hints = Self::fetch_lightning_route_hints(
+                    lightning_context.lnrpc,
+                    gateway_config.num_route_hints,
+                )
+                .await;
+
+                let all_federations_configs: Vec<_> = gateway
+                    .gateway_db
+                    .begin_transaction_nc()
+                    .await
+                    .find_by_prefix(&FederationIdKeyPrefix)
+                    .await
+                    .map(|(key, config)| (key.id, config))
+                    .collect()
+                    .await;
+
+                let registered_route_hints = gateway.registered_route_hints.read().await.clone();
+                let mut outdated_federations = Vec::new();
+                for (federation_id, federation_config) in all_federations_configs {
+                    if registered_route_hints.get(&federation_id) == Some(&route_hints) {
+                        pending_changes.remove(&federation_id);
+                        continue;
+                    }
+
+                    let checks = match pending_changes.get(&federation_id) {
+                        Some((pending_route_hints, checks))
+                            if *pending_route_hints == route_hints =>
+                        {
+                            checks + 1
+                        }
+                        _ => 1,
+                    };
+
+                    if checks >= refresh_config.hysteresis_checks {
+                        pending_changes.remove(&federation_id);
+                        outdated_federations.push((federation_id, federation_config));
+                    } else {
+                        pending_changes.insert(federation_id, (route_hints.clone(), checks));
+                    }
+                }
+
+                if outdated_federations.is_empty() {
+                    continue;
+                }
+
+                info!(
+                    federations = outdated_federations.len(),
+                    "Route hints of the lightning node changed, re-registering with federations"
+                );
+                if let Err(e) = gateway
+                    .register_federations(&gateway_config, &outdated_federations)
+                    .await
+                {
+                    warn!(%e, "Failed to re-register with federations after route hints changed");
+                }
+            }
+        });
+    }
+
+    /// Returns the [`RouteHintRefreshConfig`] of the task that re-registers
+    /// the gateway when the route hints of the lightning node change
+    pub async fn route_hint_refresh_config(&self) -> RouteHintRefreshConfig {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&RouteHintRefreshConfigKey)
+            .await
+            .unwrap_or_default()
+    }
+
     /// Utility function for waiting for the task that is listening for
     /// intercepted HTLCs to shutdown.
     async fn handle_disconnect(&mut self, htlc_task_group: TaskGroup) {
@@ -651,7 +1390,11 @@ impl Gateway {
     /// HTLC depending on if the gateway is able to acquire the preimage from
     /// the federation.
     pub async fn handle_htlc_stream(&self, mut stream: RouteHtlcStream<'_>, handle: TaskHandle) {
-        let GatewayState::Running { lightning_context } = self.state.read().await.clone() else {
+        let (GatewayState::Running { lightning_context }
+        | GatewayState::Degraded {
+            lightning_context, ..
+        }) = self.state.read().await.clone()
+        else {
             panic!("Gateway isn't in a running state")
         };
         loop {
@@ -665,6 +1408,20 @@ impl Gateway {
                         break;
                     }
 
+                    // While draining we don't take on new incoming payments, so the HTLC is
+                    // forwarded like any HTLC that isn't destined to a federation
+                    if self.is_draining() {
+                        Self::forward_htlc(&lightning_context, &htlc_request).await;
+                        continue;
+                    }
+
+                    if self
+                        .settle_l402_htlc(&lightning_context, &htlc_request)
+                        .await
+                    {
+                        continue;
+                    }
+
                     // If `payment_hash` has been registered as a LNv2 payment, we try to complete
                     // the payment by getting the preimage from the federation
                     // using the LNv2 protocol. If the `payment_hash` is not registered,
@@ -681,12 +1438,53 @@ impl Gateway {
                         )
                         .await
                     {
-                        if let Err(error) = client
-                            .get_first_module::<GatewayClientModuleV2>()
+                        let htlc_amount = Amount::from_msats(htlc_request.incoming_amount_msat);
+
+                        if let Err(violation) = self.check_incoming_payment(htlc_amount).await {
+                            warn!(%violation, "Rejecting incoming HTLC");
+                            let payment_hash = payload.contract.commitment.payment_hash;
+                            if self
+                                .gateway_db
+                                .begin_transaction_nc()
+                                .await
+                                .get_value(&HoldInvoiceKey(payment_hash))
+                                .await
+                                .is_some()
+                            {
+                                if let Err(error) = lightning_context
+                                    .lnrpc
+                                    .cancel_hold_invoice(payment_hash)
+                                    .await
+                                {
+                                    error!("Error cancelling hold invoice: {error:?}");
+                                }
+                            } else {
+                                Self::cancel_htlc(&lightning_context, &htlc_request, violation)
+                                    .await;
+                            }
+                            continue;
+                        }
+
+                        self.start_incoming_payment(
+                            htlc_request.incoming_chan_id,
+                            htlc_request.htlc_id,
+                            PendingIncomingPayment {
+                                federation_id: payload.federation_id,
+                                payment_hash: payload.contract.commitment.payment_hash,
+                                amount: htlc_amount,
+                                fees_earned: htlc_amount
+                                    .saturating_sub(payload.contract.commitment.amount),
+                                started_at: now(),
+                            },
+                        )
+                        .await;
+                        if let Err(error) = client
+                            .get_first_module::<GatewayClientModuleV2>()
                             .relay_incoming_htlc(
                                 htlc_request.incoming_chan_id,
                                 htlc_request.htlc_id,
                                 payload,
+                                htlc_amount,
                             )
                             .await
                         {
@@ -699,28 +1497,58 @@ impl Gateway {
                     // Check if the HTLC corresponds to a federation supporting legacy Lightning by
                     // looking up the `Client` from the short channel id and
                     // `FederationId` (scid -> FederationId -> Client).
-                    let scid_to_feds = self.scid_to_federation.read().await;
                     if let Some(short_channel_id) = htlc_request.short_channel_id {
-                        let federation_id = scid_to_feds.get(&short_channel_id);
+                        let federation_id = self.scid_federation(short_channel_id).await;
                         // Just forward the HTLC if we do not have a federation that
                         // corresponds to the short channel id
                         if let Some(federation_id) = federation_id {
                             let clients = self.clients.read().await;
-                            let client = clients.get(federation_id);
+                            let client = clients.get(&federation_id);
                             // Just forward the HTLC if we do not have a client that
                             // corresponds to the federation id
                             if let Some(client) = client {
                                 let cf = client
                                     .borrow()
                                     .with(|client| async {
-                                        let htlc = htlc_request.clone().try_into();
+                                        let htlc = Htlc::try_from(htlc_request.clone());
                                         if let Ok(htlc) = htlc {
+                                            if let Err(violation) = self
+                                                .check_incoming_payment(htlc.incoming_amount_msat)
+                                                .await
+                                            {
+                                                warn!(%violation, "Rejecting incoming HTLC");
+                                                Self::cancel_htlc(
+                                                    &lightning_context,
+                                                    &htlc_request,
+                                                    violation,
+                                                )
+                                                .await;
+                                                return Some(ControlFlow::<(), ()>::Continue(()));
+                                            }
+
+                                            let incoming_chan_id = htlc.incoming_chan_id;
+                                            let htlc_id = htlc.htlc_id;
+                                            let pending = PendingIncomingPayment {
+                                                federation_id,
+                                                payment_hash: htlc.payment_hash,
+                                                amount: htlc.incoming_amount_msat,
+                                                fees_earned: htlc
+                                                    .incoming_amount_msat
+                                                    .saturating_sub(htlc.outgoing_amount_msat),
+                                                started_at: now(),
+                                            };
                                             match client
                                                 .get_first_module::<GatewayClientModule>()
                                                 .gateway_handle_intercepted_htlc(htlc)
                                                 .await
                                             {
                                                 Ok(_) => {
+                                                    self.start_incoming_payment(
+                                                        incoming_chan_id,
+                                                        htlc_id,
+                                                        pending,
+                                                    )
+                                                    .await;
                                                     return Some(ControlFlow::<(), ()>::Continue(()))
                                                 }
                                                 Err(e) => {
@@ -742,15 +1570,7 @@ impl Gateway {
                         }
                     }
 
-                    let outcome = InterceptHtlcResponse {
-                        action: Some(Action::Forward(Forward {})),
-                        incoming_chan_id: htlc_request.incoming_chan_id,
-                        htlc_id: htlc_request.htlc_id,
-                    };
-
-                    if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
-                        error!("Error sending HTLC response to lightning node: {error:?}");
-                    }
+                    Self::forward_htlc(&lightning_context, &htlc_request).await;
                 }
                 other => {
                     info!("Got {other:?} while handling HTLC stream, exiting from loop...");
@@ -760,6 +1580,255 @@ impl Gateway {
         }
     }
 
+    /// Hands the intercepted HTLC back to the lightning node to be forwarded
+    /// normally.
+    async fn forward_htlc(
+        lightning_context: &LightningContext,
+        htlc_request: &crate::gateway_lnrpc::InterceptHtlcRequest,
+    ) {
+        let outcome = InterceptHtlcResponse {
+            action: Some(Action::Forward(Forward {})),
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+        };
+
+        if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
+            error!("Error sending HTLC response to lightning node: {error:?}");
+        }
+    }
+
+    /// Settles the intercepted HTLC if it pays the invoice of an L402
+    /// challenge, since the gateway itself is the recipient of these payments.
+    /// The parts of a multi-part payment are held until they add up to the
+    /// price. Returns whether the HTLC was handled.
+    async fn settle_l402_htlc(
+        &self,
+        lightning_context: &LightningContext,
+        htlc_request: &crate::gateway_lnrpc::InterceptHtlcRequest,
+    ) -> bool {
+        let Ok(payment_hash) = sha256::Hash::from_slice(&htlc_request.payment_hash) else {
+            return false;
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let Some(invoice) = dbtx.get_value(&L402InvoiceKey(payment_hash)).await else {
+            return false;
+        };
+        let hold_invoice = dbtx
+            .get_value(&HoldInvoiceKey(payment_hash))
+            .await
+            .is_some();
+
+        let amount = Amount::from_msats(htlc_request.incoming_amount_msat);
+
+        // The lightning node accumulates the parts of hold invoices itself and
+        // reports the total amount paid
+        if hold_invoice {
+            if amount < invoice.amount {
+                warn!(%payment_hash, "Rejecting underpaying HTLC of L402 invoice");
+                if let Err(error) = lightning_context
+                    .lnrpc
+                    .cancel_hold_invoice(payment_hash)
+                    .await
+                {
+                    error!("Error cancelling hold invoice: {error:?}");
+                    return true;
+                }
+            } else if let Err(error) = lightning_context
+                .lnrpc
+                .settle_hold_invoice(invoice.preimage)
+                .await
+            {
+                error!("Error settling L402 invoice: {error:?}");
+                return true;
+            } else {
+                info!(%payment_hash, "Settled L402 invoice");
+            }
+
+            self.remove_l402_invoice(payment_hash).await;
+            return true;
+        }
+
+        let part = L402HtlcPart {
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+            amount,
+        };
+
+        if invoice.expires_at <= duration_since_epoch().as_secs() {
+            warn!(%payment_hash, "Rejecting HTLC of expired L402 invoice");
+            let mut parts = self.l402_htlc_parts.remove(payment_hash);
+            parts.push(part);
+            for part in parts {
+                let outcome = InterceptHtlcResponse {
+                    action: Some(Action::Cancel(Cancel {
+                        reason: "L402 invoice expired".to_string(),
+                    })),
+                    incoming_chan_id: part.incoming_chan_id,
+                    htlc_id: part.htlc_id,
+                };
+                if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
+                    error!("Error sending HTLC response to lightning node: {error:?}");
+                }
+            }
+            self.remove_l402_invoice(payment_hash).await;
+            return true;
+        }
+
+        let parts = self
+            .l402_htlc_parts
+            .add_part(payment_hash, part, invoice.amount);
+        if parts.is_empty() {
+            debug!(%payment_hash, "Holding part of L402 payment until it is complete");
+            return true;
+        }
+
+        let mut settled = true;
+        for part in parts {
+            let outcome = InterceptHtlcResponse {
+                action: Some(Action::Settle(Settle {
+                    preimage: invoice.preimage.to_vec(),
+                })),
+                incoming_chan_id: part.incoming_chan_id,
+                htlc_id: part.htlc_id,
+            };
+            if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
+                error!("Error settling L402 invoice: {error:?}");
+                settled = false;
+            }
+        }
+
+        // The invoice is only forgotten once all of its parts were settled, so
+        // parts the node replays after a failure are still settled
+        if settled {
+            info!(%payment_hash, "Settled L402 invoice");
+            self.remove_l402_invoice(payment_hash).await;
+        }
+
+        true
+    }
+
+    /// Removes the invoice of an L402 challenge once its payment was settled
+    /// or rejected
+    async fn remove_l402_invoice(&self, payment_hash: sha256::Hash) {
+        self.l402_htlc_parts.remove(payment_hash);
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.remove_entry(&L402InvoiceKey(payment_hash)).await;
+        dbtx.remove_entry(&HoldInvoiceKey(payment_hash)).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Fails the intercepted HTLC because it would exceed the risk limits of
+    /// the gateway.
+    async fn cancel_htlc(
+        lightning_context: &LightningContext,
+        htlc_request: &crate::gateway_lnrpc::InterceptHtlcRequest,
+        violation: RiskLimitViolation,
+    ) {
+        let outcome = InterceptHtlcResponse {
+            action: Some(Action::Cancel(Cancel {
+                reason: violation.to_string(),
+            })),
+            incoming_chan_id: htlc_request.incoming_chan_id,
+            htlc_id: htlc_request.htlc_id,
+        };
+
+        if let Err(error) = lightning_context.lnrpc.complete_htlc(outcome).await {
+            error!("Error sending HTLC response to lightning node: {error:?}");
+        }
+    }
+
+    /// Returns `true` once the gateway started draining in-flight payments
+    /// before shutting down
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Stops intercepting new HTLCs and accepting new payments, then waits up
+    /// to `timeout` for the state machines of in-flight payments of all
+    /// federations to settle or refund. The resulting report is persisted, so
+    /// operators can inspect it after the restart.
+    ///
+    /// Payments that did not finish in time are resumed once the gateway is
+    /// started again.
+    pub async fn drain(&self, timeout: Duration) -> DrainReport {
+        info!(?timeout, "Draining in-flight payments");
+        self.draining.store(true, Ordering::SeqCst);
+        let started_at = now();
+
+        let (pending_operations, pending_incoming_htlcs) = loop {
+            let mut pending_operations = BTreeMap::new();
+            for (federation_id, client) in self.clients.read().await.iter() {
+                let active_operations = client.value().get_active_operations().await;
+                if !active_operations.is_empty() {
+                    pending_operations
+                        .insert(*federation_id, active_operations.into_iter().collect());
+                }
+            }
+
+            let pending_incoming_htlcs = self
+                .gateway_db
+                .begin_transaction_nc()
+                .await
+                .find_by_prefix(&PendingIncomingPaymentKeyPrefix)
+                .await
+                .count()
+                .await as u64;
+
+            let drained = pending_operations.is_empty() && pending_incoming_htlcs == 0;
+            let elapsed = now().duration_since(started_at).unwrap_or_default();
+            if drained || timeout <= elapsed {
+                break (pending_operations, pending_incoming_htlcs);
+            }
+
+            debug!(
+                pending_federations = pending_operations.len(),
+                %pending_incoming_htlcs,
+                "Waiting for in-flight payments to finish"
+            );
+            sleep(DRAIN_POLL_INTERVAL).await;
+        };
+
+        let report = DrainReport {
+            started_at,
+            finished_at: now(),
+            drained: pending_operations.is_empty() && pending_incoming_htlcs == 0,
+            pending_operations,
+            pending_incoming_htlcs,
+        };
+        if report.drained {
+            info!("All in-flight payments finished");
+        } else {
+            warn!(
+                pending_federations = report.pending_operations.len(),
+                pending_incoming_htlcs = report.pending_incoming_htlcs,
+                "Timed out draining in-flight payments, they will be resumed after the restart"
+            );
+        }
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&DrainReportKey, &report).await;
+        dbtx.commit_tx().await;
+
+        report
+    }
+
+    /// Drains in-flight payments like [`Gateway::drain`] and then shuts down
+    /// all tasks of the gateway, which makes `gatewayd` exit.
+    pub async fn handle_shutdown_msg(
+        &self,
+        payload: ShutdownPayload,
+        task_group: &TaskGroup,
+    ) -> Result<DrainReport> {
+        let timeout = payload
+            .timeout_secs
+            .map_or(DEFAULT_DRAIN_TIMEOUT, Duration::from_secs);
+        let report = self.drain(timeout).await;
+        task_group.shutdown();
+        Ok(report)
+    }
+
     /// Helper function for atomically changing the Gateway's internal state.
     async fn set_gateway_state(&mut self, state: GatewayState) {
         let mut lock = self.state.write().await;
@@ -769,7 +1838,12 @@ impl Gateway {
     /// Returns information about the Gateway back to the client when requested
     /// via the webserver.
     pub async fn handle_get_info(&self) -> Result<GatewayInfo> {
-        if let GatewayState::Running { lightning_context } = self.state.read().await.clone() {
+        let gateway_state = self.state.read().await.clone();
+        if let GatewayState::Running { lightning_context }
+        | GatewayState::Degraded {
+            lightning_context, ..
+        } = gateway_state.clone()
+        {
             // `GatewayConfiguration` should always exist in the database when we are in the
             // `Running` state.
             let gateway_config = self
@@ -785,7 +1859,16 @@ impl Gateway {
                 gateway_config.num_route_hints,
             )
             .await;
-            let node_info = fetch_lightning_node_info(lightning_context.lnrpc.clone()).await?;
+            // A degraded lightning node may not respond, which must not prevent the
+            // gateway from reporting its state
+            let (block_height, synced_to_chain) =
+                match fetch_lightning_node_info(lightning_context.lnrpc.clone()).await {
+                    Ok(node_info) => (Some(node_info.3), node_info.4),
+                    Err(_) if matches!(gateway_state, GatewayState::Degraded { .. }) => {
+                        (None, false)
+                    }
+                    Err(e) => return Err(e),
+                };
             for (federation_id, client) in federation_clients {
                 federations.push(
                     client
@@ -804,10 +1887,14 @@ impl Gateway {
                 fees: Some(gateway_config.routing_fees),
                 route_hints,
                 gateway_id: self.gateway_id,
-                gateway_state: self.state.read().await.to_string(),
+                gateway_state: gateway_state.to_string(),
+                degraded_reason: match gateway_state {
+                    GatewayState::Degraded { reason, .. } => Some(reason),
+                    _ => None,
+                },
                 network: Some(gateway_config.network),
-                block_height: Some(node_info.3),
-                synced_to_chain: node_info.4,
+                block_height,
+                synced_to_chain,
             });
         }
 
@@ -820,7 +1907,8 @@ impl Gateway {
             fees: None,
             route_hints: vec![],
             gateway_id: self.gateway_id,
-            gateway_state: self.state.read().await.to_string(),
+            gateway_state: gateway_state.to_string(),
+            degraded_reason: None,
             network: None,
             block_height: None,
             synced_to_chain: false,
@@ -833,7 +1921,9 @@ impl Gateway {
         &self,
         federation_id: Option<FederationId>,
     ) -> Result<GatewayFedConfig> {
-        if let GatewayState::Running { .. } = self.state.read().await.clone() {
+        if let GatewayState::Running { .. } | GatewayState::Degraded { .. } =
+            self.state.read().await.clone()
+        {
             let mut federations = BTreeMap::new();
             if let Some(federation_id) = federation_id {
                 let client = self.select_client(federation_id).await?;
@@ -899,7 +1989,7 @@ impl Gateway {
             // the amount we are withdrawing
             BitcoinAmountOrAll::All => {
                 let balance =
-                    bitcoin::Amount::from_sat(client.value().get_balance().await.msats / 1000);
+                    bitcoin::Amount::from_sat(client.value().get_balance().await.sats_round_down());
                 let fees = wallet_module
                     .get_withdraw_fees(address.clone(), balance)
                     .await?;
@@ -937,7 +2027,7 @@ impl Gateway {
                 WithdrawState::Failed(e) => {
                     return Err(GatewayError::UnexpectedState(e));
                 }
-                WithdrawState::Created => {}
+                WithdrawState::Created | WithdrawState::Batched(_) => {}
             }
         }
 
@@ -949,10 +2039,42 @@ impl Gateway {
     /// Requests the gateway to pay an outgoing LN invoice on behalf of a
     /// Fedimint client. Returns the payment hash's preimage on success.
     async fn handle_pay_invoice_msg(&self, payload: PayInvoicePayload) -> Result<Preimage> {
+        self.ensure_accepting_payments().await?;
+
         if let GatewayState::Running { .. } = self.state.read().await.clone() {
             debug!("Handling pay invoice message: {payload:?}");
             let client = self.select_client(payload.federation_id).await?;
             let contract_id = payload.contract_id;
+            let federation_id = payload.federation_id;
+            let payment_hash = payload.payment_data.payment_hash();
+            let amount = payload.payment_data.amount();
+            let _pending_payment = self
+                .reserve_outgoing_payment(
+                    federation_id,
+                    payment_hash,
+                    amount.unwrap_or(Amount::ZERO),
+                )
+                .await?;
+            let direction = match self.swap_destination(&payload.payment_data).await {
+                Some(..) => PaymentDirection::Swap,
+                None => PaymentDirection::Outgoing,
+            };
+            let started_at = now();
+            self.events.publish(GatewayEvent::OutgoingPaymentStarted {
+                federation_id,
+                payment_hash,
+                amount,
+            });
+            let amount = amount.unwrap_or(Amount::ZERO);
+            let record = |status| PaymentRecord {
+                federation_id,
+                direction,
+                status,
+                amount,
+                fees_earned: Amount::ZERO,
+                lightning_fee: Amount::ZERO,
+                started_at,
+            };
             let gateway_module = &client.value().get_first_module::<GatewayClientModule>();
             let operation_id = gateway_module.gateway_pay_bolt11_invoice(payload).await?;
             let mut updates = gateway_module
@@ -963,6 +2085,33 @@ impl Gateway {
                 match update {
                     GatewayExtPayStates::Success { preimage, .. } => {
                         debug!("Successfully paid invoice: {contract_id}");
+                        let lightning_fee = self
+                            .payment_progress
+                            .lightning_fee(&payment_hash)
+                            .unwrap_or(Amount::ZERO);
+                        let fee_mode = self.fee_mode(&federation_id).await;
+                        let fees_earned = self
+                            .gateway_db
+                            .begin_transaction_nc()
+                            .await
+                            .get_value(&FederationIdKey { id: federation_id })
+                            .await
+                            .map_or(Amount::ZERO, |config| match fee_mode {
+                                FeeMode::Static => config.fees.to_amount(&amount),
+                                FeeMode::Passthrough { .. } => fee_mode
+                                    .routing_fees(config.fees)
+                                    .to_amount(&amount)
+                                    .saturating_sub(lightning_fee),
+                            });
+                        self.finish_outgoing_payment(
+                            payment_hash,
+                            PaymentRecord {
+                                fees_earned,
+                                lightning_fee,
+                                ..record(PaymentStatus::Succeeded)
+                            },
+                        )
+                        .await;
                         return Ok(preimage);
                     }
                     GatewayExtPayStates::Fail {
@@ -970,10 +2119,24 @@ impl Gateway {
                         error_message,
                     } => {
                         error!("{error_message} while paying invoice: {contract_id}");
+                        self.finish_outgoing_payment(
+                            payment_hash,
+                            record(PaymentStatus::Failed {
+                                error: error_message,
+                            }),
+                        )
+                        .await;
                         return Err(GatewayError::OutgoingPaymentError(Box::new(error)));
                     }
                     GatewayExtPayStates::Canceled { error } => {
                         error!("Cancelled with {error} while paying invoice: {contract_id}");
+                        self.finish_outgoing_payment(
+                            payment_hash,
+                            record(PaymentStatus::Failed {
+                                error: error.to_string(),
+                            }),
+                        )
+                        .await;
                         return Err(GatewayError::OutgoingPaymentError(Box::new(error)));
                     }
                     GatewayExtPayStates::Created => {
@@ -1001,11 +2164,24 @@ impl Gateway {
     async fn handle_connect_federation(
         &mut self,
         payload: ConnectFedPayload,
+    ) -> Result<FederationInfo> {
+        let invite_code = InviteCode::from_str(&payload.invite_code).map_err(|e| {
+            GatewayError::InvalidMetadata(format!("Invalid federation member string {e:?}"))
+        })?;
+
+        self.connect_federation(invite_code, None, DEFAULT_TIMELOCK_DELTA)
+            .await
+    }
+
+    /// Connects to the federation using the gateway's default routing fees
+    /// unless `fees` are given
+    async fn connect_federation(
+        &mut self,
+        invite_code: InviteCode,
+        fees: Option<RoutingFees>,
+        timelock_delta: u64,
     ) -> Result<FederationInfo> {
         if let GatewayState::Running { lightning_context } = self.state.read().await.clone() {
-            let invite_code = InviteCode::from_str(&payload.invite_code).map_err(|e| {
-                GatewayError::InvalidMetadata(format!("Invalid federation member string {e:?}"))
-            })?;
             let federation_id = invite_code.federation_id();
 
             let _join_federation = self.client_joining_lock.lock().await;
@@ -1015,6 +2191,11 @@ impl Gateway {
                 return Err(GatewayError::FederationAlreadyConnected);
             }
 
+            let federation_policy = self.federation_policy().await;
+            federation_policy
+                .check_federation_id(federation_id)
+                .map_err(GatewayError::FederationNotAllowed)?;
+
             // `GatewayConfiguration` should always exist in the database when we are in the
             // `Running` state.
             let gateway_config = self
@@ -1024,22 +2205,16 @@ impl Gateway {
                 .clone()
                 .expect("Gateway configuration should be set");
 
-            // The gateway deterministically assigns a channel id (u64) to each federation
-            // connected.
-            let mut max_used_scid = self.max_used_scid.lock().await;
-            let mint_channel_id =
-                max_used_scid
-                    .checked_add(1)
-                    .ok_or(GatewayError::GatewayConfigurationError(
-                        "Too many connected federations".to_string(),
-                    ))?;
-            *max_used_scid = mint_channel_id;
+            let mint_channel_id = self.scid_alias(federation_id).await?;
+            let fees = fees
+                .or_else(|| self.configured_federation_fees(federation_id))
+                .unwrap_or(gateway_config.routing_fees);
 
             let gw_client_cfg = FederationConfig {
                 invite_code,
                 mint_channel_id,
-                timelock_delta: 10,
-                fees: gateway_config.routing_fees,
+                timelock_delta,
+                fees,
             };
 
             let client = self
@@ -1054,21 +2229,29 @@ impl Gateway {
                 balance_msat: client.get_balance().await,
                 config: client.get_config().clone(),
                 channel_id: Some(mint_channel_id),
-                routing_fees: Some(gateway_config.routing_fees.into()),
+                routing_fees: Some(fees.into()),
             };
 
             Self::check_federation_network(&federation_info, gateway_config.network)?;
+            federation_policy
+                .check_config(&federation_info.config)
+                .map_err(GatewayError::FederationNotAllowed)?;
 
             client
                 .get_first_module::<GatewayClientModule>()
                 .register_with_federation(
                     // Route hints will be updated in the background
                     Vec::new(),
+                    None,
                     GW_ANNOUNCEMENT_TTL,
                     gw_client_cfg.fees,
                     lightning_context,
                 )
                 .await?;
+            self.registered_route_hints
+                .write()
+                .await
+                .insert(federation_id, Vec::new());
 
             // no need to enter span earlier, because connect-fed has a span
             self.clients.write().await.insert(
@@ -1085,11 +2268,15 @@ impl Gateway {
                 .await
                 .insert(mint_channel_id, federation_id);
 
-            let dbtx = self.gateway_db.begin_transaction().await;
+            let mut dbtx = self.gateway_db.begin_transaction().await;
+            dbtx.insert_entry(&ScidAliasKey { federation_id }, &mint_channel_id)
+                .await;
             self.client_builder
                 .save_config(gw_client_cfg.clone(), dbtx)
                 .await?;
             debug!("Federation with ID: {federation_id} connected and assigned channel id: {mint_channel_id}");
+            self.events
+                .publish(GatewayEvent::FederationConnected { federation_id });
 
             return Ok(federation_info);
         }
@@ -1097,183 +2284,1291 @@ impl Gateway {
         Err(GatewayError::Disconnected)
     }
 
-    /// Handle a request to have the Gateway leave a federation. The Gateway
-    /// will request the federation to remove the registration record and
-    /// the gateway will remove the configuration needed to construct the
-    /// federation client.
-    pub async fn handle_leave_federation(
-        &mut self,
-        payload: LeaveFedPayload,
-    ) -> Result<FederationInfo> {
-        let client_joining_lock = self.client_joining_lock.lock().await;
-        let mut dbtx = self.gateway_db.begin_transaction().await;
+    /// Returns the SCID alias of the federation. Federations the gateway has
+    /// been connected to before keep their alias, otherwise a new alias is
+    /// allocated that has never been assigned to any other federation.
+    async fn scid_alias(&self, federation_id: FederationId) -> Result<u64> {
+        if let Some(scid) = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ScidAliasKey { federation_id })
+            .await
+        {
+            return Ok(scid);
+        }
 
-        let federation_info = {
-            let client = self.select_client(payload.federation_id).await?;
-            let federation_info = self
-                .make_federation_info(client.value(), payload.federation_id)
-                .await;
+        let mut max_used_scid = self.max_used_scid.lock().await;
+        let scid = max_used_scid
+            .checked_add(1)
+            .ok_or(GatewayError::GatewayConfigurationError(
+                "Too many connected federations".to_string(),
+            ))?;
+        *max_used_scid = scid;
 
-            let keypair = dbtx
-                .get_value(&GatewayPublicKey)
-                .await
-                .expect("Gateway keypair does not exist");
-            client
-                .value()
-                .get_first_module::<GatewayClientModule>()
-                .remove_from_federation(keypair)
-                .await;
-            federation_info
-        };
+        Ok(scid)
+    }
 
-        self.remove_client(payload.federation_id, &client_joining_lock)
-            .await?;
-        dbtx.remove_entry(&FederationIdKey {
-            id: payload.federation_id,
-        })
-        .await;
-        dbtx.commit_tx_result()
-            .await
-            .map_err(GatewayError::DatabaseError)?;
-        Ok(federation_info)
+    /// Returns the federation that HTLCs sent to the short channel id `scid`
+    /// are paid to, which is either the SCID alias or a phantom SCID of the
+    /// federation, or derived from the SCID base registered with it
+    async fn scid_federation(&self, scid: u64) -> Option<FederationId> {
+        if let Some(federation_id) = self.scid_to_federation.read().await.get(&scid) {
+            return Some(*federation_id);
+        }
+
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        stored_scid_federation(&mut dbtx, scid).await
     }
 
-    /// Handles a request for the gateway to backup a connected federation's
-    /// ecash. Not currently supported.
-    pub fn handle_backup_msg(
-        &self,
-        BackupPayload { federation_id: _ }: BackupPayload,
-    ) -> Result<()> {
-        unimplemented!("Backup is not currently supported");
+    /// Returns the short channel ids of all channels of the lightning node,
+    /// including those that are not active
+    async fn channel_scids(&self) -> Result<Vec<u64>> {
+        let lnrpc = self.get_lightning_context().await?.lnrpc;
+        Ok(match lnrpc.list_channels().await {
+            Ok(channels) => channels
+                .into_iter()
+                .filter_map(|channel| channel.short_channel_id)
+                .collect(),
+            Err(LightningRpcError::FailedToListChannels { .. }) => lnrpc
+                .list_active_channels()
+                .await?
+                .into_iter()
+                .map(|channel| channel.short_channel_id)
+                .collect(),
+            Err(e) => return Err(e.into()),
+        })
     }
 
-    /// Handles a request for the gateway to restore a connected federation's
-    /// ecash. Not currently supported.
-    pub fn handle_restore_msg(
-        &self,
-        RestorePayload { federation_id: _ }: RestorePayload,
-    ) -> Result<()> {
-        unimplemented!("Restore is not currently supported");
+    /// Returns the lightning alias and SCID base the gateway registers with the
+    /// federation, see [`Self::handle_set_federation_registration_msg`]
+    async fn federation_registration(&self, federation_id: FederationId) -> FederationRegistration {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationRegistrationKey { federation_id })
+            .await
+            .unwrap_or_default()
     }
 
-    /// Handle a request to change a connected federation's configuration or
-    /// gateway metadata. If `num_route_hints` is changed, the Gateway
-    /// will re-register with all connected federations. If
-    /// `per_federation_routing_fees` is changed, the Gateway will only
-    /// re-register with the specified federation.
-    pub async fn handle_set_configuration_msg(
+    /// Sets the lightning alias and SCID base the gateway registers with a
+    /// connected federation and re-registers the gateway with it, so that
+    /// invoices of different federations can be told apart in the logs of the
+    /// node and routing policies can be applied per federation. Unset fields
+    /// fall back to the alias of the node and the SCID alias of the
+    /// federation.
+    ///
+    /// No SCID derived from the base may be used by a channel of the node or
+    /// by another federation. If route hint privacy is enabled for the
+    /// federation it is assigned a new phantom SCID derived from the base.
+    /// SCIDs of bases registered before remain payable.
+    pub async fn handle_set_federation_registration_msg(
         &self,
-        SetConfigurationPayload {
-            password,
-            network,
-            num_route_hints,
-            routing_fees,
-            per_federation_routing_fees,
-        }: SetConfigurationPayload,
+        SetFederationRegistrationPayload {
+            federation_id,
+            lightning_alias,
+            scid_base,
+        }: SetFederationRegistrationPayload,
     ) -> Result<()> {
-        let gw_state = self.state.read().await.clone();
-        let lightning_network = match gw_state {
-            GatewayState::Running { lightning_context } => {
-                if network.is_some() && network != Some(lightning_context.lightning_network) {
-                    return Err(GatewayError::GatewayConfigurationError(
-                        "Cannot change network while connected to a lightning node".to_string(),
-                    ));
-                }
-                lightning_context.lightning_network
+        self.select_client(federation_id).await?;
+
+        if let Some(lightning_alias) = &lightning_alias {
+            if lightning_alias.is_empty() || MAX_LIGHTNING_ALIAS_LEN < lightning_alias.len() {
+                return Err(GatewayError::GatewayConfigurationError(format!(
+                    "Lightning alias has to be between 1 and {MAX_LIGHTNING_ALIAS_LEN} bytes long"
+                )));
             }
-            // In the case the gateway is not yet running and not yet connected to a lightning node,
-            // we start off with a default network configuration. This default gets replaced later
-            // when the gateway connects to a lightning node, or when a user sets a different
-            // configuration
-            _ => DEFAULT_NETWORK,
+        }
+
+        let channel_scids = match scid_base {
+            Some(_) => self.channel_scids().await?,
+            None => Vec::new(),
         };
 
         let mut dbtx = self.gateway_db.begin_transaction().await;
+        if let Some(scid_base) = scid_base {
+            let federation_scids = federation_scids(&mut dbtx.to_ref_nc()).await;
+            scid::ensure_scid_base_available(
+                scid_base,
+                federation_id,
+                channel_scids,
+                &federation_scids,
+            )
+            .map_err(|e| GatewayError::GatewayConfigurationError(e.to_string()))?;
 
-        let prev_gateway_config = self.gateway_config.read().await.clone();
-        let new_gateway_config = if let Some(mut prev_config) = prev_gateway_config {
-            if let Some(password) = password.as_ref() {
-                let hashed_password = hash_password(password, prev_config.password_salt);
-                prev_config.hashed_password = hashed_password;
-            }
-
-            if let Some(network) = network {
-                if self.clients.read().await.len() > 0 {
-                    return Err(GatewayError::GatewayConfigurationError(
-                        "Cannot change network while connected to a federation".to_string(),
-                    ));
-                }
-                prev_config.network = network;
-            }
+            dbtx.insert_entry(&RegisteredScidBaseKey { scid_base }, &federation_id)
+                .await;
 
-            if let Some(num_route_hints) = num_route_hints {
-                prev_config.num_route_hints = num_route_hints;
-            }
+            let phantom_scid = dbtx.get_value(&RouteHintPrivacyKey { federation_id }).await;
+            if phantom_scid.is_some_and(|phantom_scid| scid::scid_base(phantom_scid) != scid_base) {
+                let phantom_scid = loop {
+                    let scid = scid::random_scid_from_base(scid_base);
+                    if dbtx.get_value(&PhantomScidKey { scid }).await.is_none() {
+                        break scid;
+                    }
+                };
 
-            // Using this routing fee config as a default for all federation that has none
-            // routing fees specified.
-            if let Some(fees) = routing_fees.clone() {
-                let routing_fees = GatewayFee(fees.into()).0;
-                prev_config.routing_fees = routing_fees;
+                dbtx.insert_new_entry(&PhantomScidKey { scid: phantom_scid }, &federation_id)
+                    .await;
+                dbtx.insert_entry(&RouteHintPrivacyKey { federation_id }, &phantom_scid)
+                    .await;
+                info!(%federation_id, %phantom_scid, "Assigned phantom SCID from SCID base");
             }
+        }
 
-            prev_config
+        let registration = FederationRegistration {
+            lightning_alias,
+            scid_base,
+        };
+        if registration == FederationRegistration::default() {
+            dbtx.remove_entry(&FederationRegistrationKey { federation_id })
+                .await;
         } else {
-            let password = password.ok_or(GatewayError::GatewayConfigurationError(
-                "The password field is required when initially configuring the gateway".to_string(),
+            dbtx.insert_entry(&FederationRegistrationKey { federation_id }, &registration)
+                .await;
+        }
+        info!(%federation_id, ?registration, "Set federation registration");
+
+        let federation_config = dbtx.get_value(&FederationIdKey { id: federation_id }).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        let gateway_config = self.gateway_config.read().await.clone();
+        if let (Some(gateway_config), Some(federation_config)) = (gateway_config, federation_config)
+        {
+            self.register_federations(&gateway_config, &[(federation_id, federation_config)])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the phantom SCID invoices of the federation use, if route hint
+    /// privacy is enabled for it
+    async fn phantom_scid(&self, federation_id: FederationId) -> Option<u64> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&RouteHintPrivacyKey { federation_id })
+            .await
+    }
+
+    /// Enables or disables route hint privacy for a connected federation and
+    /// re-registers the gateway with it. With route hint privacy enabled,
+    /// invoices of the federation don't include the route hints of the
+    /// lightning node and route the payment through a random phantom SCID
+    /// that looks like the id of a real channel, so payers can't learn the
+    /// channels of the node. Since payers then have to find a route to the
+    /// node itself, this requires the node to have public channels.
+    ///
+    /// Every time route hint privacy is enabled the federation is assigned a
+    /// new phantom SCID, derived from the SCID base registered with the
+    /// federation if there is one. Previous phantom SCIDs remain payable.
+    pub async fn handle_set_route_hint_privacy_msg(
+        &self,
+        SetRouteHintPrivacyPayload {
+            federation_id,
+            enabled,
+        }: SetRouteHintPrivacyPayload,
+    ) -> Result<()> {
+        self.select_client(federation_id).await?;
+
+        // Phantom SCIDs look like the ids of channels funded in recent blocks, but
+        // must not be the id of one of our channels
+        let (block_height, channel_scids) = if enabled {
+            let lightning_context = self.get_lightning_context().await?;
+            (
+                fetch_lightning_node_info(lightning_context.lnrpc).await?.3,
+                self.channel_scids().await?,
+            )
+        } else {
+            (0, Vec::new())
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        if enabled {
+            let phantom_scid = assign_phantom_scid(
+                &mut dbtx.to_ref_nc(),
+                federation_id,
+                block_height,
+                &channel_scids,
+            )
+            .await;
+            info!(%federation_id, %phantom_scid, "Enabled route hint privacy");
+        } else {
+            dbtx.remove_entry(&RouteHintPrivacyKey { federation_id })
+                .await;
+            info!(%federation_id, "Disabled route hint privacy");
+        }
+        let federation_config = dbtx.get_value(&FederationIdKey { id: federation_id }).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        let gateway_config = self.gateway_config.read().await.clone();
+        if let (Some(gateway_config), Some(federation_config)) = (gateway_config, federation_config)
+        {
+            self.register_federations(&gateway_config, &[(federation_id, federation_config)])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the routing fees earned from each connected federation, as
+    /// recorded by the state machines of the gateway client modules
+    pub async fn handle_get_earnings_msg(&self) -> Result<GatewayEarnings> {
+        let mut federations = Vec::new();
+        let federation_clients = self.clients.read().await.clone().into_iter();
+        for (federation_id, client) in federation_clients {
+            let earnings = client
+                .borrow()
+                .with(|client| async {
+                    let mut earnings = client
+                        .get_first_module::<GatewayClientModule>()
+                        .earnings()
+                        .await;
+                    for (direction, earned) in client
+                        .get_first_module::<GatewayClientModuleV2>()
+                        .earnings()
+                        .await
+                    {
+                        *earnings.entry(direction).or_insert(Amount::ZERO) += earned;
+                    }
+                    earnings
+                })
+                .await;
+
+            federations.push(FederationEarnings {
+                federation_id,
+                incoming: earnings
+                    .get(&FeeDirection::Incoming)
+                    .copied()
+                    .unwrap_or(Amount::ZERO),
+                outgoing: earnings
+                    .get(&FeeDirection::Outgoing)
+                    .copied()
+                    .unwrap_or(Amount::ZERO),
+            });
+        }
+
+        Ok(GatewayEarnings {
+            total_incoming: federations.iter().map(|f| f.incoming).sum(),
+            total_outgoing: federations.iter().map(|f| f.outgoing).sum(),
+            federations,
+        })
+    }
+
+    /// Returns the routing statistics of the lightning node as of the last
+    /// sync together with the fees earned from the connected federations
+    pub async fn handle_node_stats_msg(&self) -> Result<NodeStats> {
+        let routing = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&NodeRoutingStatsKey)
+            .await
+            .unwrap_or_default();
+
+        Ok(NodeStats {
+            routing,
+            federation_earnings: self.handle_get_earnings_msg().await?,
+        })
+    }
+
+    /// Lists the SCID aliases of all federations the gateway has ever been
+    /// connected to
+    pub async fn handle_list_scid_aliases_msg(&self) -> Result<Vec<ScidAliasInfo>> {
+        let scid_to_federation = self.scid_to_federation.read().await.clone();
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+
+        let phantom_scids = dbtx
+            .find_by_prefix(&RouteHintPrivacyKeyPrefix)
+            .await
+            .map(|(key, scid)| (key.federation_id, scid))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        let registrations = dbtx
+            .find_by_prefix(&FederationRegistrationKeyPrefix)
+            .await
+            .map(|(key, registration)| (key.federation_id, registration))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        Ok(dbtx
+            .find_by_prefix(&ScidAliasKeyPrefix)
+            .await
+            .map(|(key, scid)| ScidAliasInfo {
+                federation_id: key.federation_id,
+                scid,
+                connected: scid_to_federation.get(&scid) == Some(&key.federation_id),
+                phantom_scid: phantom_scids.get(&key.federation_id).copied(),
+                registration: registrations
+                    .get(&key.federation_id)
+                    .cloned()
+                    .unwrap_or_default(),
+            })
+            .collect()
+            .await)
+    }
+
+    /// Exports the invite codes, fees and timelock deltas of all connected
+    /// federations, encrypted with the given password, so another gateway can
+    /// import them
+    pub async fn handle_export_connections_msg(
+        &self,
+        ExportConnectionsPayload { password }: ExportConnectionsPayload,
+    ) -> Result<EncryptedConnections> {
+        let federations = self
+            .client_builder
+            .load_configs(self.gateway_db.begin_transaction_nc().await)
+            .await
+            .into_iter()
+            .map(|config| FederationConnection {
+                invite_code: config.invite_code,
+                fees: config.fees,
+                timelock_delta: config.timelock_delta,
+            })
+            .collect();
+
+        EncryptedConnections::encrypt(&GatewayConnections { federations }, &password).map_err(|e| {
+            GatewayError::GatewayConfigurationError(format!("Failed to encrypt connections: {e}"))
+        })
+    }
+
+    /// Exports the gateway database and the client databases of all connected
+    /// federations, encrypted with the given password, so the gateway can be
+    /// restored on another machine with `gatewayd import-db`.
+    ///
+    /// The databases are read one after another while the gateway keeps
+    /// running, so the gateway should be drained before it is exported.
+    pub async fn handle_export_db_msg(
+        &self,
+        ExportDbPayload { password }: ExportDbPayload,
+    ) -> Result<EncryptedDbArchive> {
+        let client_dbs = self
+            .clients
+            .read()
+            .await
+            .iter()
+            .map(|(federation_id, client)| (*federation_id, client.value().db().clone()))
+            .collect();
+
+        let archive = export_db_archive(&self.gateway_db, client_dbs)
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        EncryptedDbArchive::encrypt(&archive, &password).map_err(|e| {
+            GatewayError::GatewayConfigurationError(format!("Failed to encrypt database: {e}"))
+        })
+    }
+
+    /// Connects to all exported federations the gateway is not connected to
+    /// yet, keeping the fees and timelock delta they were exported with. The
+    /// federations get new SCID aliases of this gateway.
+    ///
+    /// The import is all or nothing: if any federation can't be connected, the
+    /// gateway leaves the federations it connected to during the import.
+    pub async fn handle_import_connections_msg(
+        &mut self,
+        ImportConnectionsPayload {
+            password,
+            connections,
+        }: ImportConnectionsPayload,
+    ) -> Result<Vec<FederationInfo>> {
+        let connections = connections.decrypt(&password).map_err(|e| {
+            GatewayError::GatewayConfigurationError(format!("Failed to decrypt connections: {e}"))
+        })?;
+
+        let federation_policy = self.federation_policy().await;
+        let mut federations = Vec::new();
+        for federation in connections.federations {
+            let federation_id = federation.invite_code.federation_id();
+            if self.clients.read().await.contains_key(&federation_id) {
+                info!("Federation {federation_id} is already connected, skipping import");
+                continue;
+            }
+
+            // Don't connect to any federation if one of them is not allowed
+            federation_policy
+                .check_federation_id(federation_id)
+                .map_err(GatewayError::FederationNotAllowed)?;
+            federations.push(federation);
+        }
+
+        let mut connected = Vec::new();
+        for federation in federations {
+            match self
+                .connect_federation(
+                    federation.invite_code,
+                    Some(federation.fees),
+                    federation.timelock_delta,
+                )
+                .await
+            {
+                Ok(federation_info) => connected.push(federation_info),
+                Err(e) => {
+                    for federation_info in connected {
+                        let federation_id = federation_info.federation_id;
+                        if let Err(e) = self
+                            .handle_leave_federation(LeaveFedPayload { federation_id })
+                            .await
+                        {
+                            warn!(%federation_id, ?e, "Failed to leave federation of failed import");
+                        }
+                    }
+
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(connected)
+    }
+
+    /// Handle a request to have the Gateway leave a federation. The Gateway
+    /// will request the federation to remove the registration record and
+    /// the gateway will remove the configuration needed to construct the
+    /// federation client.
+    pub async fn handle_leave_federation(
+        &mut self,
+        payload: LeaveFedPayload,
+    ) -> Result<FederationInfo> {
+        let client_joining_lock = self.client_joining_lock.lock().await;
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+
+        let federation_info = {
+            let client = self.select_client(payload.federation_id).await?;
+            let federation_info = self
+                .make_federation_info(client.value(), payload.federation_id)
+                .await;
+
+            let keypair = dbtx
+                .get_value(&GatewayPublicKey)
+                .await
+                .expect("Gateway keypair does not exist");
+            client
+                .value()
+                .get_first_module::<GatewayClientModule>()
+                .remove_from_federation(keypair)
+                .await;
+            federation_info
+        };
+
+        self.remove_client(payload.federation_id, &client_joining_lock)
+            .await?;
+        dbtx.remove_entry(&FederationIdKey {
+            id: payload.federation_id,
+        })
+        .await;
+        dbtx.remove_entry(&SwapFeesKey {
+            federation_id: payload.federation_id,
+        })
+        .await;
+        dbtx.remove_entry(&FeeModeKey {
+            federation_id: payload.federation_id,
+        })
+        .await;
+        dbtx.remove_entry(&RouteHintPrivacyKey {
+            federation_id: payload.federation_id,
+        })
+        .await;
+        dbtx.remove_entry(&FederationRegistrationKey {
+            federation_id: payload.federation_id,
+        })
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        self.events.publish(GatewayEvent::FederationDisconnected {
+            federation_id: payload.federation_id,
+        });
+        Ok(federation_info)
+    }
+
+    /// Handles a request for the gateway to backup a connected federation's
+    /// ecash. Not currently supported.
+    pub fn handle_backup_msg(
+        &self,
+        BackupPayload { federation_id: _ }: BackupPayload,
+    ) -> Result<()> {
+        unimplemented!("Backup is not currently supported");
+    }
+
+    /// Handles a request for the gateway to restore a connected federation's
+    /// ecash. Not currently supported.
+    pub fn handle_restore_msg(
+        &self,
+        RestorePayload { federation_id: _ }: RestorePayload,
+    ) -> Result<()> {
+        unimplemented!("Restore is not currently supported");
+    }
+
+    /// Handle a request to change a connected federation's configuration or
+    /// gateway metadata. If `num_route_hints` is changed, the Gateway
+    /// will re-register with all connected federations. If
+    /// `per_federation_routing_fees` is changed, the Gateway will only
+    /// re-register with the specified federation. The route hint refresh
+    /// settings only take effect with the next check.
+    pub async fn handle_set_configuration_msg(
+        &self,
+        SetConfigurationPayload {
+            password,
+            network,
+            num_route_hints,
+            routing_fees,
+            per_federation_routing_fees,
+            route_hint_refresh_interval_secs,
+            route_hint_refresh_hysteresis,
+        }: SetConfigurationPayload,
+    ) -> Result<()> {
+        let gw_state = self.state.read().await.clone();
+        let lightning_network = match gw_state {
+            GatewayState::Running { lightning_context }
+            | GatewayState::Degraded {
+                lightning_context, ..
+            } => {
+                if network.is_some() && network != Some(lightning_context.lightning_network) {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Cannot change network while connected to a lightning node".to_string(),
+                    ));
+                }
+                lightning_context.lightning_network
+            }
+            // In the case the gateway is not yet running and not yet connected to a lightning node,
+            // we start off with a default network configuration. This default gets replaced later
+            // when the gateway connects to a lightning node, or when a user sets a different
+            // configuration
+            _ => DEFAULT_NETWORK,
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+
+        let prev_gateway_config = self.gateway_config.read().await.clone();
+        let new_gateway_config = if let Some(mut prev_config) = prev_gateway_config {
+            if let Some(password) = password.as_ref() {
+                let hashed_password = hash_password(password, prev_config.password_salt);
+                prev_config.hashed_password = hashed_password;
+            }
+
+            if let Some(network) = network {
+                if self.clients.read().await.len() > 0 {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Cannot change network while connected to a federation".to_string(),
+                    ));
+                }
+                prev_config.network = network;
+            }
+
+            if let Some(num_route_hints) = num_route_hints {
+                prev_config.num_route_hints = num_route_hints;
+            }
+
+            // Using this routing fee config as a default for all federation that has none
+            // routing fees specified.
+            if let Some(fees) = routing_fees.clone() {
+                let routing_fees = GatewayFee(fees.into()).0;
+                prev_config.routing_fees = routing_fees;
+            }
+
+            prev_config
+        } else {
+            let password = password.ok_or(GatewayError::GatewayConfigurationError(
+                "The password field is required when initially configuring the gateway".to_string(),
             ))?;
             let password_salt: [u8; 16] = rand::thread_rng().gen();
             let hashed_password = hash_password(&password, password_salt);
 
-            GatewayConfiguration {
-                hashed_password,
-                network: lightning_network,
-                num_route_hints: DEFAULT_NUM_ROUTE_HINTS,
-                routing_fees: DEFAULT_FEES,
-                password_salt,
+            GatewayConfiguration {
+                hashed_password,
+                network: lightning_network,
+                num_route_hints: DEFAULT_NUM_ROUTE_HINTS,
+                routing_fees: DEFAULT_FEES,
+                password_salt,
+            }
+        };
+        dbtx.insert_entry(&GatewayConfigurationKey, &new_gateway_config)
+            .await;
+
+        if route_hint_refresh_interval_secs.is_some() || route_hint_refresh_hysteresis.is_some() {
+            let mut refresh_config = dbtx
+                .get_value(&RouteHintRefreshConfigKey)
+                .await
+                .unwrap_or_default();
+            if let Some(interval_secs) = route_hint_refresh_interval_secs {
+                refresh_config.interval_secs = interval_secs;
+            }
+            if let Some(hysteresis_checks) = route_hint_refresh_hysteresis {
+                if hysteresis_checks == 0 {
+                    return Err(GatewayError::GatewayConfigurationError(
+                        "Route hint refresh hysteresis has to be at least one check".to_string(),
+                    ));
+                }
+                refresh_config.hysteresis_checks = hysteresis_checks;
+            }
+            dbtx.insert_entry(&RouteHintRefreshConfigKey, &refresh_config)
+                .await;
+        }
+
+        let mut register_federations: Vec<(FederationId, FederationConfig)> = Vec::new();
+        if let Some(per_federation_routing_fees) = per_federation_routing_fees {
+            for (federation_id, routing_fees) in &per_federation_routing_fees {
+                let federation_key = FederationIdKey { id: *federation_id };
+                if let Some(mut federation_config) = dbtx.get_value(&federation_key).await {
+                    federation_config.fees = routing_fees.clone().into();
+                    dbtx.insert_entry(&federation_key, &federation_config).await;
+                    register_federations.push((*federation_id, federation_config));
+                } else {
+                    warn!("Given federation {federation_id} not found for updating routing fees");
+                }
+            }
+        }
+
+        // If 'num_route_hints' is provided, all federations must be re-registered.
+        // Otherwise, only those affected by the new fees need to be re-registered.
+        if num_route_hints.is_some() {
+            let all_federations_configs: Vec<_> = dbtx
+                .find_by_prefix(&FederationIdKeyPrefix)
+                .await
+                .map(|(key, config)| (key.id, config))
+                .collect()
+                .await;
+            self.register_federations(&new_gateway_config, &all_federations_configs)
+                .await?;
+        } else {
+            self.register_federations(&new_gateway_config, &register_federations)
+                .await?;
+        }
+
+        dbtx.commit_tx().await;
+
+        let mut curr_gateway_config = self.gateway_config.write().await;
+        *curr_gateway_config = Some(new_gateway_config.clone());
+
+        info!("Set GatewayConfiguration successfully.");
+
+        Ok(())
+    }
+
+    /// Returns the [`FederationPolicy`] restricting which federations the
+    /// gateway connects to and serves
+    pub async fn federation_policy(&self) -> FederationPolicy {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FederationPolicyKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Replaces the [`FederationPolicy`]. Federations that are already
+    /// connected stay connected, but are no longer served via LNv2 if the new
+    /// policy does not allow them.
+    pub async fn handle_set_federation_policy_msg(&self, policy: FederationPolicy) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&FederationPolicyKey, &policy).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(?policy, "Updated federation policy");
+        Ok(())
+    }
+
+    /// Returns the [`SwapFees`] charged for LNv2 direct swaps into or out of
+    /// the given federation
+    pub async fn swap_fees(&self, federation_id: &FederationId) -> SwapFees {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&SwapFeesKey {
+                federation_id: *federation_id,
+            })
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Sets the [`SwapFees`] of a connected federation. They are advertised
+    /// via `payment_info_v2` and are independent of the lightning routing
+    /// fees.
+    pub async fn handle_set_swap_fees_msg(
+        &self,
+        SetSwapFeesPayload {
+            federation_id,
+            fees,
+        }: SetSwapFeesPayload,
+    ) -> Result<()> {
+        // Only connected federations can have swap fees configured
+        self.select_client(federation_id).await?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&SwapFeesKey { federation_id }, &fees)
+            .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(%federation_id, ?fees, "Updated swap fees");
+        Ok(())
+    }
+
+    /// Returns the [`FeeMode`] outgoing payments of the given federation are
+    /// charged with
+    pub async fn fee_mode(&self, federation_id: &FederationId) -> FeeMode {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&FeeModeKey {
+                federation_id: *federation_id,
+            })
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Sets the [`FeeMode`] of a connected federation and re-registers the
+    /// gateway with it, so clients fund their contracts with the routing fees
+    /// of the new mode
+    pub async fn handle_set_fee_mode_msg(
+        &self,
+        SetFeeModePayload {
+            federation_id,
+            fee_mode,
+        }: SetFeeModePayload,
+    ) -> Result<()> {
+        self.select_client(federation_id).await?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&FeeModeKey { federation_id }, &fee_mode)
+            .await;
+        let federation_config = dbtx.get_value(&FederationIdKey { id: federation_id }).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(%federation_id, ?fee_mode, "Updated fee mode");
+
+        let gateway_config = self.gateway_config.read().await.clone();
+        if let (Some(gateway_config), Some(federation_config)) = (gateway_config, federation_config)
+        {
+            self.register_federations(&gateway_config, &[(federation_id, federation_config)])
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Pays an outgoing payment with `pay`, which is handed a channel for the
+    /// lightning node to report HTLC attempts to. The attempts are published
+    /// as events and kept for [`Gateway::payment_progress`] so that payments
+    /// which are stuck or keep failing can be diagnosed.
+    pub async fn pay_with_progress<F, Fut>(
+        &self,
+        payment_hash: sha256::Hash,
+        pay: F,
+    ) -> std::result::Result<PayInvoiceResponse, LightningRpcError>
+    where
+        F: FnOnce(PaymentUpdateSender) -> Fut,
+        Fut: Future<Output = std::result::Result<PayInvoiceResponse, LightningRpcError>>,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+
+        // The receiver yields `None` once `pay` returned and dropped the sender
+        let record_updates = async {
+            while let Some(attempt) = receiver.recv().await {
+                debug!(%payment_hash, ?attempt, "Payment attempt updated");
+                self.payment_progress.record(payment_hash, attempt.clone());
+                self.events.publish(GatewayEvent::OutgoingPaymentAttempt {
+                    payment_hash,
+                    attempt,
+                });
+            }
+        };
+
+        let (result, ()) = futures::join!(pay(sender), record_updates);
+        if let Ok(response) = &result {
+            self.payment_progress
+                .record_lightning_fee(payment_hash, Amount::from_msats(response.fee_msat));
+        }
+        result
+    }
+
+    /// Returns the HTLC attempts reported for a recent outgoing payment, or
+    /// `None` if the lightning node reported none
+    pub fn payment_progress(&self, payment_hash: &sha256::Hash) -> Option<PaymentProgress> {
+        self.payment_progress.get(payment_hash)
+    }
+
+    /// Checks a connected federation against the current [`FederationPolicy`]
+    async fn check_federation_policy(&self, federation_id: &FederationId) -> Result<()> {
+        let policy = self.federation_policy().await;
+        let clients = self.clients.read().await;
+        match clients.get(federation_id) {
+            Some(client) => policy.check_config(client.value().get_config()),
+            None => policy.check_federation_id(*federation_id),
+        }
+        .map_err(GatewayError::FederationNotAllowed)
+    }
+
+    /// Returns the [`RiskLimits`] on the payments the gateway makes
+    pub async fn risk_limits(&self) -> RiskLimits {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&RiskLimitsKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Replaces the [`RiskLimits`]. Payments that are already pending are not
+    /// affected.
+    pub async fn handle_set_risk_limits_msg(&self, limits: RiskLimits) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(&RiskLimitsKey, &limits).await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(?limits, "Updated risk limits");
+        Ok(())
+    }
+
+    /// Returns the [`NostrConfig`], `None` if zap receipts are disabled
+    pub async fn nostr_config(&self) -> Option<NostrConfig> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&NostrConfigKey)
+            .await
+    }
+
+    pub async fn handle_get_nostr_config_msg(&self) -> NostrConfigInfo {
+        self.nostr_config()
+            .await
+            .as_ref()
+            .map_or_else(NostrConfigInfo::default, NostrConfigInfo::from)
+    }
+
+    /// Sets the nostr key zap receipts are signed with, or disables zap
+    /// receipts if no key is given. Zaps that are pending when zap receipts are
+    /// disabled don't get a receipt.
+    pub async fn handle_set_nostr_config_msg(&self, payload: SetNostrConfigPayload) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        match payload.secret_key {
+            Some(secret_key) => {
+                dbtx.insert_entry(
+                    &NostrConfigKey,
+                    &NostrConfig {
+                        secret_key,
+                        relays: payload.relays,
+                    },
+                )
+                .await;
+            }
+            None => {
+                dbtx.remove_entry(&NostrConfigKey).await;
+            }
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(
+            enabled = payload.secret_key.is_some(),
+            "Updated nostr config"
+        );
+        Ok(())
+    }
+
+    /// Returns the [`L402Config`], `None` if L402 authentication of public
+    /// invoices is disabled
+    pub async fn l402_config(&self) -> Option<L402Config> {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&L402ConfigKey)
+            .await
+    }
+
+    pub async fn handle_get_l402_config_msg(&self) -> L402ConfigInfo {
+        self.l402_config()
+            .await
+            .as_ref()
+            .map_or_else(L402ConfigInfo::default, L402ConfigInfo::from)
+    }
+
+    /// Sets the price of the L402 tokens required to create public invoices,
+    /// or disables L402 authentication if no price is given. Tokens stay valid
+    /// across price changes unless the root key is rotated.
+    pub async fn handle_set_l402_config_msg(&self, payload: SetL402ConfigPayload) -> Result<()> {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        match payload.price {
+            Some(price) => {
+                let previous = dbtx.get_value(&L402ConfigKey).await;
+                let root_key = match previous {
+                    Some(previous) if !payload.rotate_root_key => previous.root_key,
+                    _ => OsRng.gen(),
+                };
+                let validity_secs = payload
+                    .validity_secs
+                    .unwrap_or(DEFAULT_L402_TOKEN_VALIDITY_SECS);
+
+                dbtx.insert_entry(
+                    &L402ConfigKey,
+                    &L402Config {
+                        price,
+                        validity_secs,
+                        root_key,
+                    },
+                )
+                .await;
+            }
+            None => {
+                dbtx.remove_entry(&L402ConfigKey).await;
+            }
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+        info!(
+            price = ?payload.price,
+            rotate_root_key = payload.rotate_root_key,
+            "Updated L402 config"
+        );
+        Ok(())
+    }
+
+    /// Creates an L402 challenge: an invoice of the lightning node for the
+    /// price of a token and a macaroon that becomes valid once the invoice was
+    /// paid. Returns the value of the `WWW-Authenticate` header.
+    pub async fn create_l402_challenge(&self, config: &L402Config) -> Result<String> {
+        let preimage: [u8; 32] = OsRng.gen();
+        let payment_hash = sha256::Hash::hash(&preimage);
+        let now = duration_since_epoch().as_secs();
+
+        // The invoice is recorded before it can be paid, so its HTLCs are
+        // settled by the gateway instead of being forwarded
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let expired = dbtx
+            .find_by_prefix(&L402InvoiceKeyPrefix)
+            .await
+            .filter_map(|(key, invoice)| async move { (invoice.expires_at <= now).then_some(key) })
+            .collect::<Vec<_>>()
+            .await;
+        for key in expired {
+            dbtx.remove_entry(&key).await;
+            dbtx.remove_entry(&HoldInvoiceKey(key.0)).await;
+        }
+
+        // Every challenge creates an invoice on the lightning node, so the number
+        // of unpaid challenges is bounded
+        if l402::MAX_PENDING_L402_CHALLENGES
+            <= dbtx
+                .find_by_prefix(&L402InvoiceKeyPrefix)
+                .await
+                .count()
+                .await
+        {
+            return Err(GatewayError::RateLimited);
+        }
+
+        dbtx.insert_entry(
+            &L402InvoiceKey(payment_hash),
+            &L402Invoice {
+                preimage,
+                amount: config.price,
+                expires_at: now + u64::from(l402::L402_INVOICE_EXPIRY_SECS),
+            },
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        let invoice = self
+            .create_invoice_via_lnrpc_v2(
+                payment_hash,
+                config.price,
+                Bolt11InvoiceDescription::Direct("L402 token".to_string()),
+                l402::L402_INVOICE_EXPIRY_SECS,
+            )
+            .await
+            .map_err(|e| {
+                GatewayError::LightningRpcError(LightningRpcError::FailedToGetInvoice {
+                    failure_reason: e,
+                })
+            })?;
+
+        let macaroon = l402::mint_token(
+            &config.root_key,
+            payment_hash,
+            OsRng.gen(),
+            now + config.validity_secs,
+        );
+
+        Ok(l402::challenge_header(&macaroon, &invoice.to_string()))
+    }
+
+    /// Publishes the zap receipt if the incoming payment settled with
+    /// `preimage` paid a zap request. The receipt is published in the
+    /// background, so that completing the payment isn't delayed by the relays.
+    pub async fn publish_zap_receipt(&self, preimage: [u8; 32]) {
+        let payment_hash = sha256::Hash::hash(&preimage);
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        let Some(pending_zap) = dbtx.remove_entry(&PendingZapKey(payment_hash)).await else {
+            return;
+        };
+        let nostr_config = dbtx.get_value(&NostrConfigKey).await;
+        dbtx.commit_tx().await;
+
+        let Some(nostr_config) = nostr_config else {
+            warn!(%payment_hash, "Zap was paid after zap receipts were disabled");
+            return;
+        };
+
+        let zap_request = match serde_json::from_str::<NostrEvent>(&pending_zap.zap_request) {
+            Ok(zap_request) => zap_request,
+            Err(e) => {
+                warn!(%payment_hash, "Failed to parse zap request: {e}");
+                return;
             }
         };
-        dbtx.insert_entry(&GatewayConfigurationKey, &new_gateway_config)
+
+        let keypair = KeyPair::from_secret_key(
+            fedimint_core::secp256k1::SECP256K1,
+            &nostr_config.secret_key,
+        );
+        let receipt = zap_receipt(
+            &keypair,
+            &zap_request,
+            &pending_zap.zap_request,
+            &pending_zap.invoice,
+            preimage,
+            duration_since_epoch().as_secs(),
+        );
+
+        let mut relays = zap_request_relays(&zap_request);
+        relays.extend(nostr_config.relays);
+        relays.sort();
+        relays.dedup();
+
+        fedimint_core::runtime::spawn("publish zap receipt", async move {
+            publish_event(&receipt, &relays).await;
+        });
+    }
+
+    /// Returns the current utilization of the [`RiskLimits`]. The outgoing
+    /// volume of a federation includes its successful outgoing payments and
+    /// swaps from the payment history as well as its pending payments.
+    pub async fn risk_status(&self) -> RiskStatus {
+        let limits = self.risk_limits().await;
+        let now = now();
+        let hour_ago = now - Duration::from_secs(60 * 60);
+        let day_ago = now - Duration::from_secs(24 * 60 * 60);
+
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let recent_payments = dbtx
+            .find_by_prefix_sorted_descending(&PaymentRecordKeyPrefix)
+            .await
+            .take_while(|(key, _)| std::future::ready(day_ago <= key.completed_at))
+            .collect::<Vec<_>>()
             .await;
 
-        let mut register_federations: Vec<(FederationId, FederationConfig)> = Vec::new();
-        if let Some(per_federation_routing_fees) = per_federation_routing_fees {
-            for (federation_id, routing_fees) in &per_federation_routing_fees {
-                let federation_key = FederationIdKey { id: *federation_id };
-                if let Some(mut federation_config) = dbtx.get_value(&federation_key).await {
-                    federation_config.fees = routing_fees.clone().into();
-                    dbtx.insert_entry(&federation_key, &federation_config).await;
-                    register_federations.push((*federation_id, federation_config));
-                } else {
-                    warn!("Given federation {federation_id} not found for updating routing fees");
-                }
+        let mut federations = BTreeMap::<FederationId, FederationRiskUtilization>::new();
+        for (key, record) in recent_payments {
+            if record.direction == PaymentDirection::Incoming
+                || record.status != PaymentStatus::Succeeded
+            {
+                continue;
+            }
+
+            let utilization = federations.entry(record.federation_id).or_default();
+            utilization.daily_outgoing_volume += record.amount;
+            if hour_ago <= key.completed_at {
+                utilization.hourly_outgoing_volume += record.amount;
             }
         }
 
-        // If 'num_route_hints' is provided, all federations must be re-registered.
-        // Otherwise, only those affected by the new fees need to be re-registered.
-        if num_route_hints.is_some() {
-            let all_federations_configs: Vec<_> = dbtx
-                .find_by_prefix(&FederationIdKeyPrefix)
-                .await
-                .map(|(key, config)| (key.id, config))
-                .collect()
-                .await;
-            self.register_federations(&new_gateway_config, &all_federations_configs)
-                .await?;
-        } else {
-            self.register_federations(&new_gateway_config, &register_federations)
-                .await?;
+        let mut pending_exposure = Amount::ZERO;
+        for (federation_id, amount) in self.pending_outgoing_payments.amounts_by_federation() {
+            let utilization = federations.entry(federation_id).or_default();
+            utilization.daily_outgoing_volume += amount;
+            utilization.hourly_outgoing_volume += amount;
+            pending_exposure += amount;
         }
 
-        dbtx.commit_tx().await;
+        pending_exposure += dbtx
+            .find_by_prefix(&PendingIncomingPaymentKeyPrefix)
+            .await
+            .map(|(_, payment)| payment.amount)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .sum::<Amount>();
 
-        let mut curr_gateway_config = self.gateway_config.write().await;
-        *curr_gateway_config = Some(new_gateway_config.clone());
+        RiskStatus {
+            limits,
+            pending_exposure,
+            federations,
+        }
+    }
 
-        info!("Set GatewayConfiguration successfully.");
+    /// Checks an outgoing payment against the [`RiskLimits`] and counts it as
+    /// pending until the returned guard is dropped. Returns `None` if the
+    /// payment is already pending, e.g. because a client retried its request.
+    async fn reserve_outgoing_payment(
+        &self,
+        federation_id: FederationId,
+        payment_hash: sha256::Hash,
+        amount: Amount,
+    ) -> Result<Option<PendingOutgoingPayment>> {
+        let _check_lock = self.pending_outgoing_payments.check_lock.lock().await;
+        if self.pending_outgoing_payments.contains(&payment_hash) {
+            return Ok(None);
+        }
 
-        Ok(())
+        if let Err(violation) = self
+            .risk_status()
+            .await
+            .check_outgoing_payment(federation_id, amount)
+        {
+            warn!(%federation_id, %payment_hash, %violation, "Rejecting outgoing payment");
+            return Err(GatewayError::RiskLimitExceeded(violation));
+        }
+
+        Ok(Some(self.pending_outgoing_payments.insert(
+            payment_hash,
+            federation_id,
+            amount,
+        )))
+    }
+
+    /// Checks an intercepted HTLC that would be paid to a federation against
+    /// the [`RiskLimits`]
+    async fn check_incoming_payment(
+        &self,
+        amount: Amount,
+    ) -> std::result::Result<(), RiskLimitViolation> {
+        let _check_lock = self.pending_outgoing_payments.check_lock.lock().await;
+        self.risk_status().await.check_payment(amount)
+    }
+
+    /// Returns the current settings of the gateway in the [`GatewayConfigFile`]
+    /// schema, so they can be fed back into `apply_config` or `--config`. The
+    /// password is never returned.
+    pub async fn handle_get_config_msg(&self) -> Result<GatewayConfigFile> {
+        let mut config = (*self.startup_config).clone();
+        if let Some(gateway_config) = self.gateway_config.read().await.clone() {
+            config.network = Some(gateway_config.network);
+            config.fees = Some(gateway_config.routing_fees.into());
+            config.num_route_hints = Some(gateway_config.num_route_hints);
+        }
+
+        let connected_federations: Vec<FederationConfigOverride> = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&FederationIdKeyPrefix)
+            .await
+            .map(|(key, federation_config)| FederationConfigOverride {
+                federation_id: key.id,
+                fees: federation_config.fees.into(),
+            })
+            .collect()
+            .await;
+
+        // Overrides for federations that have not been joined yet still apply once they
+        // are
+        config.federations.retain(|federation| {
+            !connected_federations
+                .iter()
+                .any(|connected| connected.federation_id == federation.federation_id)
+        });
+        config.federations.extend(connected_federations);
+
+        Ok(config)
+    }
+
+    /// Applies a [`GatewayConfigFile`], e.g. one returned by `get_config`.
+    /// Unset fields are left unchanged, settings that are only read on startup
+    /// have to be unset or match the running gateway.
+    pub async fn handle_apply_config_msg(&self, config: GatewayConfigFile) -> Result<()> {
+        let GatewayConfigFile {
+            mode,
+            additional_lightning_nodes,
+            data_dir,
+            listen,
+            api_addr,
+            password,
+            network,
+            fees,
+            num_route_hints,
+            low_liquidity_alert_threshold_sats,
+            federations,
+        } = config;
+
+        let startup_config = &self.startup_config;
+        ensure_startup_setting_unchanged("mode", mode.as_ref(), startup_config.mode.as_ref())?;
+        ensure_startup_setting_unchanged(
+            "additional_lightning_nodes",
+            additional_lightning_nodes.as_ref(),
+            startup_config.additional_lightning_nodes.as_ref(),
+        )?;
+        ensure_startup_setting_unchanged(
+            "data_dir",
+            data_dir.as_ref(),
+            startup_config.data_dir.as_ref(),
+        )?;
+        ensure_startup_setting_unchanged(
+            "listen",
+            listen.as_ref(),
+            startup_config.listen.as_ref(),
+        )?;
+        ensure_startup_setting_unchanged(
+            "api_addr",
+            api_addr.as_ref(),
+            startup_config.api_addr.as_ref(),
+        )?;
+        ensure_startup_setting_unchanged(
+            "low_liquidity_alert_threshold_sats",
+            low_liquidity_alert_threshold_sats.as_ref(),
+            startup_config.low_liquidity_alert_threshold_sats.as_ref(),
+        )?;
+
+        // Re-applying the current network is a no-op, but would be rejected once the
+        // gateway is connected to a federation
+        let current_network = self
+            .gateway_config
+            .read()
+            .await
+            .as_ref()
+            .map(|config| config.network);
+        let network = network.filter(|network| Some(*network) != current_network);
+
+        let per_federation_routing_fees = (!federations.is_empty()).then(|| {
+            federations
+                .into_iter()
+                .map(|federation| (federation.federation_id, federation.fees))
+                .collect()
+        });
+
+        self.handle_set_configuration_msg(SetConfigurationPayload {
+            password,
+            num_route_hints,
+            routing_fees: fees,
+            network,
+            per_federation_routing_fees,
+            route_hint_refresh_interval_secs: None,
+            route_hint_refresh_hysteresis: None,
+        })
+        .await
+    }
+
+    /// Returns the routing fees configured for `federation_id` in the
+    /// `--config` file, if any.
+    fn configured_federation_fees(&self, federation_id: FederationId) -> Option<RoutingFees> {
+        self.startup_config
+            .federations
+            .iter()
+            .find(|federation| federation.federation_id == federation_id)
+            .map(|federation| federation.fees.clone().into())
     }
 
     /// Instructs the Gateway's Lightning node to connect to a peer specified by
@@ -1334,6 +3629,42 @@ impl Gateway {
         Ok(channels)
     }
 
+    /// Returns all channels of the Gateway's Lightning node, including the
+    /// channels that are still being opened or are pending close.
+    pub async fn handle_list_channels_msg(&self) -> Result<Vec<lightning::ChannelDetails>> {
+        let context = self.get_lightning_context().await?;
+        let channels = context.lnrpc.list_channels().await?;
+        Ok(channels)
+    }
+
+    /// Returns the channel of the Gateway's Lightning node with the short
+    /// channel id `short_channel_id`.
+    pub async fn handle_get_channel_msg(
+        &self,
+        GetChannelPayload { short_channel_id }: GetChannelPayload,
+    ) -> Result<lightning::ChannelDetails> {
+        self.handle_list_channels_msg()
+            .await?
+            .into_iter()
+            .find(|channel| channel.short_channel_id == Some(short_channel_id))
+            .ok_or_else(|| {
+                GatewayError::UnexpectedState(format!("Channel {short_channel_id} not found"))
+            })
+    }
+
+    /// Instructs the Gateway's Lightning node to close a single channel. The
+    /// channel is reported as closing by [`Self::handle_list_channels_msg`]
+    /// until its funds are swept to the on-chain wallet.
+    pub async fn handle_close_channel_msg(
+        &self,
+        CloseChannelPayload { channel_id, force }: CloseChannelPayload,
+    ) -> Result<()> {
+        let context = self.get_lightning_context().await?;
+        context.lnrpc.close_channel(channel_id, force).await?;
+        info!(%channel_id, force, "Closing channel");
+        Ok(())
+    }
+
     /// Registers the gateway with each specified federation.
     async fn register_federations(
         &self,
@@ -1352,14 +3683,30 @@ impl Gateway {
 
             for (federation_id, federation_config) in federations {
                 if let Some(client) = self.clients.read().await.get(federation_id) {
+                    // Invoices of federations with route hint privacy enabled only route
+                    // through the phantom SCID, so they don't reveal the channels of the node
+                    let phantom_scid = self.phantom_scid(*federation_id).await;
+                    let federation_route_hints = if phantom_scid.is_some() {
+                        Vec::new()
+                    } else {
+                        route_hints.clone()
+                    };
+                    let registration = self.federation_registration(*federation_id).await;
+                    let mut lightning_context = lightning_context.clone();
+                    if let Some(lightning_alias) = registration.lightning_alias {
+                        lightning_context.lightning_alias = lightning_alias;
+                    }
                     if let Err(e) = async {
                         client
                             .value()
                             .get_first_module::<GatewayClientModule>()
                             .register_with_federation(
-                                route_hints.clone(),
+                                federation_route_hints,
+                                scid::registered_scid(registration.scid_base, phantom_scid),
                                 GW_ANNOUNCEMENT_TTL,
-                                federation_config.fees,
+                                self.fee_mode(federation_id)
+                                    .await
+                                    .routing_fees(federation_config.fees),
                                 lightning_context.clone(),
                             )
                             .await
@@ -1373,6 +3720,11 @@ impl Gateway {
                             anyhow::anyhow!("Error registering federation {federation_id}: {e:?}"),
                         )))?;
                     }
+
+                    self.registered_route_hints
+                        .write()
+                        .await
+                        .insert(*federation_id, route_hints.clone());
                 }
             }
         }
@@ -1447,6 +3799,11 @@ impl Gateway {
             error!("client is not unique, failed to remove client");
         }
 
+        self.registered_route_hints
+            .write()
+            .await
+            .remove(&federation_id);
+
         // Remove previously assigned scid from `scid_to_federation` map
         self.scid_to_federation
             .write()
@@ -1503,9 +3860,22 @@ impl Gateway {
             }
         }
 
-        if let Some(max_mint_channel_id) = configs.iter().map(|cfg| cfg.mint_channel_id).max() {
+        // Aliases of federations the gateway has left are never reassigned
+        let max_scid_alias = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&ScidAliasKeyPrefix)
+            .await
+            .map(|(_, scid)| scid)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .max();
+
+        if let Some(max_scid_alias) = max_scid_alias {
             let mut max_used_scid = self.max_used_scid.lock().await;
-            *max_used_scid = max_mint_channel_id;
+            *max_used_scid = max_scid_alias;
         }
     }
 
@@ -1646,7 +4016,11 @@ impl Gateway {
         &self,
     ) -> std::result::Result<LightningContext, LightningRpcError> {
         match self.state.read().await.clone() {
-            GatewayState::Running { lightning_context } => Ok(lightning_context),
+            // In-flight payments are still completed while degraded
+            GatewayState::Running { lightning_context }
+            | GatewayState::Degraded {
+                lightning_context, ..
+            } => Ok(lightning_context),
             _ => Err(LightningRpcError::FailedToConnect),
         }
     }
@@ -1669,6 +4043,111 @@ impl Gateway {
     }
 }
 
+/// Returns the SCID aliases, phantom SCIDs and registered SCID bases of all
+/// federations
+async fn federation_scids(dbtx: &mut DatabaseTransaction<'_>) -> BTreeMap<u64, FederationId> {
+    let mut federation_scids = dbtx
+        .find_by_prefix(&ScidAliasKeyPrefix)
+        .await
+        .map(|(key, scid)| (scid, key.federation_id))
+        .collect::<BTreeMap<_, _>>()
+        .await;
+
+    federation_scids.extend(
+        dbtx.find_by_prefix(&PhantomScidKeyPrefix)
+            .await
+            .map(|(key, federation_id)| (key.scid, federation_id))
+            .collect::<Vec<_>>()
+            .await,
+    );
+
+    federation_scids.extend(
+        dbtx.find_by_prefix(&RegisteredScidBaseKeyPrefix)
+            .await
+            .map(|(key, federation_id)| (key.scid_base, federation_id))
+            .collect::<Vec<_>>()
+            .await,
+    );
+
+    federation_scids
+}
+
+/// Returns the federation that HTLCs sent to `scid` are paid to if it is one of
+/// the phantom SCIDs of a federation or derived from a registered SCID base
+async fn stored_scid_federation(
+    dbtx: &mut DatabaseTransaction<'_>,
+    scid: u64,
+) -> Option<FederationId> {
+    if let Some(federation_id) = dbtx.get_value(&PhantomScidKey { scid }).await {
+        return Some(federation_id);
+    }
+
+    if scid < scid::MIN_SCID_BASE {
+        return None;
+    }
+
+    dbtx.get_value(&RegisteredScidBaseKey {
+        scid_base: scid::scid_base(scid),
+    })
+    .await
+}
+
+/// Assigns a new phantom SCID to the federation, derived from the SCID base
+/// registered with it if there is one. The phantom SCID is neither the id of
+/// one of the `channel_scids` of the lightning node nor a phantom SCID already
+/// assigned, and isn't derived from the SCID base of another federation.
+async fn assign_phantom_scid(
+    dbtx: &mut DatabaseTransaction<'_>,
+    federation_id: FederationId,
+    block_height: u32,
+    channel_scids: &[u64],
+) -> u64 {
+    let scid_base = dbtx
+        .get_value(&FederationRegistrationKey { federation_id })
+        .await
+        .and_then(|registration| registration.scid_base);
+    let phantom_scid = loop {
+        let scid = match scid_base {
+            Some(scid_base) => scid::random_scid_from_base(scid_base),
+            None => random_phantom_scid(block_height),
+        };
+        if !channel_scids.contains(&scid)
+            && dbtx.get_value(&PhantomScidKey { scid }).await.is_none()
+            && (scid_base.is_some()
+                || dbtx
+                    .get_value(&RegisteredScidBaseKey {
+                        scid_base: scid::scid_base(scid),
+                    })
+                    .await
+                    .is_none())
+        {
+            break scid;
+        }
+    };
+
+    dbtx.insert_new_entry(&PhantomScidKey { scid: phantom_scid }, &federation_id)
+        .await;
+    dbtx.insert_entry(&RouteHintPrivacyKey { federation_id }, &phantom_scid)
+        .await;
+
+    phantom_scid
+}
+
+/// Generates a random short channel id of a channel funded within the last
+/// [`PHANTOM_SCID_MAX_AGE`] blocks, so it can't be told apart from the id of a
+/// real channel
+fn random_phantom_scid(block_height: u32) -> u64 {
+    let mut rng = rand::thread_rng();
+    // Block 0 is never used, so phantom SCIDs can't collide with SCID aliases
+    let funding_height = block_height
+        .saturating_sub(rng.gen_range(0..PHANTOM_SCID_MAX_AGE))
+        .max(1);
+    let tx_index = rng.gen_range(0..2_000u64);
+    let output_index = rng.gen_range(0..4u64);
+
+    (u64::from(funding_height) << 40) | (tx_index << 16) | output_index
+}
+
 /// Retrieves the basic information about the Gateway's connected Lightning
 /// node.
 pub(crate) async fn fetch_lightning_node_info(
@@ -1708,50 +4187,318 @@ impl Gateway {
         })
     }
 
-    /// Returns payment information that LNv2 clients can use to instruct this
-    /// Gateway to pay an invoice or receive a payment.
-    pub async fn payment_info_v2(&self, federation_id: &FederationId) -> Option<PaymentInfo> {
-        Some(PaymentInfo {
-            public_key: self.public_key_v2(federation_id).await?,
-            send_fee_default: PaymentFee::one_percent(),
-            send_fee_minimum: PaymentFee::half_of_one_percent(),
-            receive_fee: PaymentFee::half_of_one_percent(),
-            expiration_delta_default: 500,
-            expiration_delta_minimum: EXPIRATION_DELTA_MINIMUM_V2,
-        })
+    /// Returns payment information that LNv2 clients can use to instruct this
+    /// Gateway to pay an invoice or receive a payment.
+    /// Returns `None` if the federation is not allowed by the
+    /// [`FederationPolicy`], so that clients do not use this gateway for it.
+    pub async fn payment_info_v2(&self, federation_id: &FederationId) -> Option<PaymentInfo> {
+        if let Err(e) = self.check_federation_policy(federation_id).await {
+            warn!("Not serving federation {federation_id}: {e}");
+            return None;
+        }
+
+        let swap_fees = self.swap_fees(federation_id).await;
+
+        Some(PaymentInfo {
+            public_key: self.public_key_v2(federation_id).await?,
+            send_fee_default: PaymentFee::one_percent(),
+            send_fee_minimum: PaymentFee::half_of_one_percent(),
+            receive_fee: PaymentFee::half_of_one_percent(),
+            swap_send_fee: swap_fees.send_fee,
+            swap_receive_fee: swap_fees.receive_fee,
+            expiration_delta_default: 500,
+            expiration_delta_minimum: EXPIRATION_DELTA_MINIMUM_V2,
+        })
+    }
+
+    /// Instructs this gateway to pay a Lightning network invoice via the LNv2
+    /// protocol.
+    async fn send_payment_v2(
+        &self,
+        payload: SendPaymentPayload,
+    ) -> anyhow::Result<std::result::Result<[u8; 32], Signature>> {
+        self.ensure_accepting_payments().await?;
+
+        let clients = self.clients.read().await;
+
+        let client = clients
+            .get(&payload.federation_id)
+            .ok_or(anyhow!("Federation client not available"))?
+            .value();
+
+        let federation_id = payload.federation_id;
+        let payment_hash = payload.contract.payment_hash;
+        let contract_amount = payload.contract.amount;
+        let invoice_amount = payload
+            .invoice
+            .amount_milli_satoshis()
+            .map_or(contract_amount, Amount::from_msats);
+        let _pending_payment = self
+            .reserve_outgoing_payment(federation_id, payment_hash, invoice_amount)
+            .await?;
+        let started_at = now();
+        self.events.publish(GatewayEvent::OutgoingPaymentStarted {
+            federation_id,
+            payment_hash,
+            amount: Some(contract_amount),
+        });
+
+        let result = client
+            .get_first_module::<GatewayClientModuleV2>()
+            .send_payment(payload)
+            .await;
+
+        let (status, fees_earned) = match &result {
+            Ok(Ok(..)) => (
+                PaymentStatus::Succeeded,
+                contract_amount.saturating_sub(invoice_amount),
+            ),
+            Ok(Err(..)) => (
+                PaymentStatus::Failed {
+                    error: "Outgoing contract was cancelled".to_string(),
+                },
+                Amount::ZERO,
+            ),
+            Err(error) => (
+                PaymentStatus::Failed {
+                    error: error.to_string(),
+                },
+                Amount::ZERO,
+            ),
+        };
+        self.finish_outgoing_payment(
+            payment_hash,
+            PaymentRecord {
+                federation_id,
+                direction: PaymentDirection::Outgoing,
+                status,
+                amount: invoice_amount,
+                fees_earned,
+                lightning_fee: self
+                    .payment_progress
+                    .lightning_fee(&payment_hash)
+                    .filter(|_| result.as_ref().is_ok_and(Result::is_ok))
+                    .unwrap_or(Amount::ZERO),
+                started_at,
+            },
+        )
+        .await;
+
+        result
+    }
+
+    /// Returns the federation an invoice can be paid to directly if the last
+    /// hop of its route hint is one of the short channel ids assigned to a
+    /// connected federation by this gateway. In this case the gateway can
+    /// avoid paying the invoice over the lightning network and instead
+    /// perform a swap between the two federations.
+    async fn swap_destination(&self, payment_data: &PaymentData) -> Option<FederationId> {
+        let route_hints = payment_data.route_hints();
+        let hop = route_hints.first().and_then(|rh| rh.0.last())?;
+
+        let GatewayState::Running { lightning_context } = self.state.read().await.clone() else {
+            return None;
+        };
+        if hop.src_node_id != lightning_context.lightning_public_key {
+            return None;
+        }
+
+        self.scid_federation(hop.short_channel_id).await
+    }
+
+    /// Records an outgoing payment or swap in the payment history and
+    /// notifies subscribers of the event stream.
+    async fn finish_outgoing_payment(&self, payment_hash: sha256::Hash, record: PaymentRecord) {
+        let event = match &record.status {
+            PaymentStatus::Succeeded => GatewayEvent::OutgoingPaymentSucceeded {
+                federation_id: record.federation_id,
+                payment_hash,
+            },
+            PaymentStatus::Failed { error } => GatewayEvent::OutgoingPaymentFailed {
+                federation_id: record.federation_id,
+                payment_hash,
+                error: error.clone(),
+            },
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PaymentRecordKey {
+                completed_at: now(),
+                payment_hash,
+            },
+            &record,
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        self.events.publish(event);
+    }
+
+    /// Stores an intercepted HTLC that is being paid to a federation until it
+    /// is settled or cancelled by [`Self::finish_incoming_payment`].
+    async fn start_incoming_payment(
+        &self,
+        incoming_chan_id: u64,
+        htlc_id: u64,
+        payment: PendingIncomingPayment,
+    ) {
+        let event = GatewayEvent::IncomingPaymentStarted {
+            federation_id: payment.federation_id,
+            payment_hash: payment.payment_hash,
+            amount: payment.amount,
+            incoming_chan_id,
+            htlc_id,
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PendingIncomingPaymentKey {
+                incoming_chan_id,
+                htlc_id,
+            },
+            &payment,
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        self.events.publish(event);
     }
 
-    /// Instructs this gateway to pay a Lightning network invoice via the LNv2
-    /// protocol.
-    async fn send_payment_v2(
+    /// Moves a settled or cancelled HTLC to the payment history and notifies
+    /// subscribers of the event stream.
+    async fn finish_incoming_payment(
         &self,
-        payload: SendPaymentPayload,
-    ) -> anyhow::Result<std::result::Result<[u8; 32], Signature>> {
-        let clients = self.clients.read().await;
+        incoming_chan_id: u64,
+        htlc_id: u64,
+        result: std::result::Result<(), String>,
+    ) {
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        // The entry is missing if the HTLC was intercepted before the payment
+        // history was introduced
+        if let Some(payment) = dbtx
+            .remove_entry(&PendingIncomingPaymentKey {
+                incoming_chan_id,
+                htlc_id,
+            })
+            .await
+        {
+            dbtx.remove_entry(&HoldInvoiceKey(payment.payment_hash))
+                .await;
 
-        let client = clients
-            .get(&payload.federation_id)
-            .ok_or(anyhow!("Federation client not available"))?
-            .value();
+            let (status, fees_earned) = match &result {
+                Ok(()) => (PaymentStatus::Succeeded, payment.fees_earned),
+                Err(error) => (
+                    PaymentStatus::Failed {
+                        error: error.clone(),
+                    },
+                    Amount::ZERO,
+                ),
+            };
+            dbtx.insert_entry(
+                &PaymentRecordKey {
+                    completed_at: now(),
+                    payment_hash: payment.payment_hash,
+                },
+                &PaymentRecord {
+                    federation_id: payment.federation_id,
+                    direction: PaymentDirection::Incoming,
+                    status,
+                    amount: payment.amount,
+                    fees_earned,
+                    lightning_fee: Amount::ZERO,
+                    started_at: payment.started_at,
+                },
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
 
-        client
-            .get_first_module::<GatewayClientModuleV2>()
-            .send_payment(payload)
+        self.events.publish(match result {
+            Ok(()) => GatewayEvent::IncomingPaymentSucceeded {
+                incoming_chan_id,
+                htlc_id,
+            },
+            Err(error) => GatewayEvent::IncomingPaymentFailed {
+                incoming_chan_id,
+                htlc_id,
+                error,
+            },
+        });
+    }
+
+    /// Returns finished payments from the payment history, newest first.
+    /// Results are paginated by passing the completion time of the last
+    /// payment of the previous page as `start_after`.
+    pub async fn handle_list_payments_msg(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> Result<Vec<PaymentSummary>> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+        let payments = dbtx
+            .find_by_prefix_sorted_descending(&PaymentRecordKeyPrefix)
             .await
+            .skip_while(|(key, _)| {
+                let skip = payload
+                    .start_after
+                    .is_some_and(|start_after| key.completed_at >= start_after)
+                    || payload
+                        .end_time
+                        .is_some_and(|end_time| key.completed_at > end_time);
+                std::future::ready(skip)
+            })
+            .take_while(|(key, _)| {
+                std::future::ready(
+                    payload
+                        .start_time
+                        .map_or(true, |start_time| key.completed_at >= start_time),
+                )
+            })
+            .filter(|(_, record)| {
+                std::future::ready(
+                    payload
+                        .federation_id
+                        .map_or(true, |federation_id| record.federation_id == federation_id),
+                )
+            })
+            .take(payload.limit)
+            .map(|(key, record)| PaymentSummary {
+                payment_hash: key.payment_hash,
+                federation_id: record.federation_id,
+                direction: record.direction,
+                status: record.status,
+                amount: record.amount,
+                fees_earned: record.fees_earned,
+                lightning_fee: record.lightning_fee,
+                started_at: record.started_at,
+                completed_at: key.completed_at,
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        Ok(payments)
     }
 
     /// For the LNv2 protocol, this will create an invoice by fetching it from
     /// the connected Lightning node, then save the payment hash so that
     /// incoming HTLCs can be matched as a receive attempt to a specific
     /// federation.
-    async fn create_invoice_v2(
+    ///
+    /// If the payload's invoice amount is zero the invoice is amountless, its
+    /// amount is fixed by the HTLC paying it, which has to cover the contract
+    /// amount plus the receive fee. The invoice never outlives the contract.
+    pub async fn create_invoice_v2(
         &self,
         payload: CreateInvoicePayload,
     ) -> anyhow::Result<Bolt11Invoice> {
+        self.ensure_accepting_payments().await?;
+
         if !payload.contract.verify() {
             bail!("The contract is invalid")
         }
 
+        self.check_federation_policy(&payload.federation_id).await?;
+
         let payment_info = self
             .payment_info_v2(&payload.federation_id)
             .await
@@ -1761,24 +4508,50 @@ impl Gateway {
             bail!("The outgoing contract keyed to another gateway");
         }
 
-        let contract_amount = payment_info
-            .receive_fee
-            .subtract_fee(payload.invoice_amount.msats);
+        if payload.is_amountless() {
+            if payload.contract.commitment.amount == Amount::ZERO {
+                bail!("The contract of an amountless invoice has no amount");
+            }
+
+            if payment_info
+                .receive_fee
+                .min_amount_before_fee(payload.contract.commitment.amount.msats)
+                .is_none()
+            {
+                bail!("The receive fee consumes any amount");
+            }
+        } else {
+            let contract_amount = payment_info
+                .receive_fee
+                .subtract_fee(payload.invoice_amount.msats);
+
+            if contract_amount != payload.contract.commitment.amount {
+                bail!("The contract amount does not pay the correct amount of fees");
+            }
+        }
 
-        if contract_amount != payload.contract.commitment.amount {
-            bail!("The contract amount does not pay the correct amount of fees");
+        if payload.expiry_time == 0 || INVOICE_EXPIRY_MAXIMUM_V2 < payload.expiry_time {
+            bail!("The invoice expiry must be between 1 and {INVOICE_EXPIRY_MAXIMUM_V2} seconds");
         }
 
-        if payload.contract.commitment.expiration <= duration_since_epoch().as_secs() {
+        let now = duration_since_epoch().as_secs();
+
+        if payload.contract.commitment.expiration <= now {
             bail!("The contract has already expired");
         }
 
+        // The contract expiration was derived from the expiry when the payload was
+        // created, so we only shorten the expiry by the time that passed since
+        let expiry_time = u32::try_from(payload.contract.commitment.expiration - now)
+            .unwrap_or(u32::MAX)
+            .min(payload.expiry_time);
+
         let invoice = self
             .create_invoice_via_lnrpc_v2(
                 payload.contract.commitment.payment_hash,
                 payload.invoice_amount,
                 payload.description.clone(),
-                payload.expiry_time,
+                expiry_time,
             )
             .await
             .map_err(|e| anyhow!(e))?;
@@ -1803,8 +4576,225 @@ impl Gateway {
         Ok(invoice)
     }
 
+    /// Registers a receiver that can be paid through the public invoice
+    /// endpoint and returns the token that identifies it. Only the hash of
+    /// the token is persisted, so it cannot be retrieved again.
+    pub async fn handle_register_public_receiver_msg(
+        &self,
+        payload: RegisterPublicReceiverPayload,
+    ) -> Result<String> {
+        if self.public_key_v2(&payload.federation_id).await.is_none() {
+            return Err(GatewayError::InvalidPublicReceiverRequest(
+                "Federation does not support LNv2 or is not connected".to_string(),
+            ));
+        }
+
+        let token: [u8; 32] = OsRng.gen();
+        let token = token.encode_hex::<String>();
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &PublicReceiverKey {
+                token_hash: sha256::Hash::hash(token.as_bytes()),
+            },
+            &PublicReceiver {
+                federation_id: payload.federation_id,
+                recipient_static_pk: payload.recipient_static_pk,
+                max_invoice_amount: payload.max_invoice_amount,
+                max_invoices_per_hour: payload.max_invoices_per_hour,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        Ok(token)
+    }
+
+    /// Creates an LNv2 invoice paying into an incoming contract for a
+    /// registered public receiver. This is served without authentication, so
+    /// the request is checked against the receiver's limits and quota before
+    /// any work is done.
+    ///
+    /// The contract can only be claimed with the receiver's static key. Since
+    /// the receiver doesn't take part in creating it, the contract is stored
+    /// for the receiver to fetch and claim, see
+    /// [`Self::handle_public_invoice_contracts_msg`]. Funded contracts remain
+    /// claimable after they expired, so the receiver doesn't have to be
+    /// online when the invoice is paid.
+    pub async fn handle_create_public_invoice_msg(
+        &self,
+        payload: CreatePublicInvoicePayload,
+    ) -> Result<Bolt11Invoice> {
+        let token_hash = sha256::Hash::hash(payload.token.as_bytes());
+        let receiver = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&PublicReceiverKey { token_hash })
+            .await
+            .ok_or(GatewayError::InvalidPublicReceiverRequest(
+                "Unknown token".to_string(),
+            ))?;
+
+        if payload.amount == Amount::ZERO || receiver.max_invoice_amount < payload.amount {
+            return Err(GatewayError::InvalidPublicReceiverRequest(
+                "Invalid invoice amount".to_string(),
+            ));
+        }
+
+        let description = payload.description.unwrap_or_default();
+        if MAX_ZAP_REQUEST_LEN < description.len() {
+            return Err(GatewayError::InvalidPublicReceiverRequest(
+                "Description too long".to_string(),
+            ));
+        }
+
+        // Zap requests are only recognized if the gateway can sign the receipt
+        let zap_request = if self.nostr_config().await.is_some() {
+            parse_zap_request(&description, payload.amount).map_err(|e| {
+                GatewayError::InvalidPublicReceiverRequest(format!("Invalid zap request: {e}"))
+            })?
+        } else {
+            None
+        };
+
+        if zap_request.is_none() && MAX_PUBLIC_INVOICE_DESCRIPTION_LEN < description.len() {
+            return Err(GatewayError::InvalidPublicReceiverRequest(
+                "Description too long".to_string(),
+            ));
+        }
+
+        if !self.public_invoice_rate_limiter.try_acquire(
+            token_hash,
+            receiver.max_invoices_per_hour,
+            now(),
+        ) {
+            return Err(GatewayError::RateLimited);
+        }
+
+        let payment_info = self.payment_info_v2(&receiver.federation_id).await.ok_or(
+            GatewayError::InvalidPublicReceiverRequest("Federation not available".to_string()),
+        )?;
+
+        let tpe_agg_pk = self
+            .select_client(receiver.federation_id)
+            .await?
+            .value()
+            .get_first_module::<GatewayClientModuleV2>()
+            .cfg
+            .tpe_agg_pk;
+
+        // Mirrors the contract construction of the LNv2 client when receiving
+        // to a static public key
+        let (ephemeral_tweak, ephemeral_pk) =
+            fedimint_lnv2_client::generate_ephemeral_tweak(receiver.recipient_static_pk);
+        let encryption_seed = ephemeral_tweak
+            .consensus_hash::<sha256::Hash>()
+            .to_byte_array();
+        let preimage = encryption_seed
+            .consensus_hash::<sha256::Hash>()
+            .to_byte_array();
+        let claim_pk = receiver
+            .recipient_static_pk
+            .mul_tweak(
+                fedimint_core::secp256k1::SECP256K1,
+                &Scalar::from_be_bytes(ephemeral_tweak).expect("Within curve order"),
+            )
+            .map_err(|e| GatewayError::InvalidPublicReceiverRequest(e.to_string()))?;
+
+        let contract = IncomingContract::new(
+            tpe_agg_pk,
+            encryption_seed,
+            preimage,
+            payment_info.receive_fee.subtract_fee(payload.amount.msats),
+            duration_since_epoch()
+                .as_secs()
+                .saturating_add(u64::from(PUBLIC_INVOICE_EXPIRY_SECS)),
+            claim_pk,
+            payment_info.public_key,
+            ephemeral_pk,
+        );
+
+        // NIP-57 requires the invoice to commit to the zap request by its hash
+        let invoice_description = if zap_request.is_some() {
+            Bolt11InvoiceDescription::Hash(sha256::Hash::hash(description.as_bytes()))
+        } else {
+            Bolt11InvoiceDescription::Direct(description.clone())
+        };
+
+        let invoice = self
+            .create_invoice_v2(CreateInvoicePayload {
+                federation_id: receiver.federation_id,
+                contract,
+                invoice_amount: payload.amount,
+                description: invoice_description,
+                expiry_time: PUBLIC_INVOICE_EXPIRY_SECS,
+            })
+            .await?;
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &PublicInvoiceContractKey {
+                token_hash,
+                contract_id: contract.contract_id(),
+            },
+            &contract,
+        )
+        .await;
+        if zap_request.is_some() {
+            dbtx.insert_entry(
+                &PendingZapKey(*invoice.payment_hash()),
+                &PendingZap {
+                    zap_request: description,
+                    invoice: invoice.to_string(),
+                },
+            )
+            .await;
+        }
+        dbtx.commit_tx_result()
+            .await
+            .map_err(GatewayError::DatabaseError)?;
+
+        Ok(invoice)
+    }
+
+    /// Returns the incoming contracts created for the public receiver with
+    /// the given token, which the receiver claims with
+    /// `fedimint_lnv2_client::LightningClientModule::claim_public_invoice_contracts`
+    pub async fn handle_public_invoice_contracts_msg(
+        &self,
+        payload: PublicInvoiceContractsPayload,
+    ) -> Result<Vec<IncomingContract>> {
+        let token_hash = sha256::Hash::hash(payload.token.as_bytes());
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+
+        if dbtx
+            .get_value(&PublicReceiverKey { token_hash })
+            .await
+            .is_none()
+        {
+            return Err(GatewayError::InvalidPublicReceiverRequest(
+                "Unknown token".to_string(),
+            ));
+        }
+
+        Ok(dbtx
+            .find_by_prefix(&PublicInvoiceContractTokenPrefix { token_hash })
+            .await
+            .map(|(_, contract)| contract)
+            .collect()
+            .await)
+    }
+
     /// Retrieves a BOLT11 invoice from the connected Lightning node with a
     /// specific `payment_hash`.
+    ///
+    /// If the lightning node supports hold invoices, the invoice is created as
+    /// a hold invoice, so its HTLCs are only settled once the transaction
+    /// funding the incoming contract was accepted by the federation and the
+    /// preimage was decrypted.
     pub async fn create_invoice_via_lnrpc_v2(
         &self,
         payment_hash: sha256::Hash,
@@ -1818,33 +4808,219 @@ impl Gateway {
             .map_err(|e| e.to_string())?
             .lnrpc;
 
-        let response = match description {
-            Bolt11InvoiceDescription::Direct(description) => lnrpc
-                .create_invoice(CreateInvoiceRequest {
-                    payment_hash: payment_hash.to_byte_array().to_vec(),
-                    amount_msat: amount.msats,
-                    expiry: expiry_time,
-                    description: Some(Description::Direct(description)),
-                })
-                .await
-                .map_err(|e| e.to_string())?,
-            Bolt11InvoiceDescription::Hash(hash) => lnrpc
-                .create_invoice(CreateInvoiceRequest {
-                    payment_hash: payment_hash.to_byte_array().to_vec(),
-                    amount_msat: amount.msats,
-                    expiry: expiry_time,
-                    description: Some(Description::Hash(hash.to_byte_array().to_vec())),
-                })
-                .await
-                .map_err(|e| e.to_string())?,
+        let description = match description {
+            Bolt11InvoiceDescription::Direct(description) => Description::Direct(description),
+            Bolt11InvoiceDescription::Hash(hash) => {
+                Description::Hash(hash.to_byte_array().to_vec())
+            }
+        };
+
+        let create_invoice_request = CreateInvoiceRequest {
+            payment_hash: payment_hash.to_byte_array().to_vec(),
+            amount_msat: amount.msats,
+            expiry: expiry_time,
+            description: Some(description),
         };
 
+        let response = if lnrpc.supports_hold_invoices() {
+            // The invoice is recorded before it can be paid, so its HTLCs are never
+            // completed as regular intercepted HTLCs
+            let mut dbtx = self.gateway_db.begin_transaction().await;
+            dbtx.insert_entry(&HoldInvoiceKey(payment_hash), &()).await;
+            dbtx.commit_tx().await;
+
+            lnrpc.create_hold_invoice(create_invoice_request).await
+        } else {
+            lnrpc.create_invoice(create_invoice_request).await
+        }
+        .map_err(|e| e.to_string())?;
+
         Bolt11Invoice::from_str(&response.invoice).map_err(|e| e.to_string())
     }
 
+    /// Returns the payment hash of the hold invoice paid by the intercepted
+    /// HTLC, or `None` if the HTLC doesn't pay a hold invoice created by the
+    /// gateway and has to be completed with `complete_htlc`.
+    pub async fn hold_invoice_payment_hash(
+        &self,
+        incoming_chan_id: u64,
+        htlc_id: u64,
+    ) -> Option<sha256::Hash> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+
+        let payment_hash = dbtx
+            .get_value(&PendingIncomingPaymentKey {
+                incoming_chan_id,
+                htlc_id,
+            })
+            .await?
+            .payment_hash;
+
+        dbtx.get_value(&HoldInvoiceKey(payment_hash))
+            .await
+            .map(|()| payment_hash)
+    }
+
+    /// Returns whether the intercepted HTLC was settled or cancelled manually
+    /// via [`Gateway::handle_resolve_htlc_msg`], in which case the state
+    /// machines must not complete it again.
+    pub async fn is_htlc_manually_resolved(&self, incoming_chan_id: u64, htlc_id: u64) -> bool {
+        self.gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&ManualHtlcResolutionKey {
+                incoming_chan_id,
+                htlc_id,
+            })
+            .await
+            .is_some()
+    }
+
+    /// Returns the intercepted HTLCs paying a federation that are not settled
+    /// or cancelled yet, oldest first.
+    pub async fn handle_list_pending_htlcs_msg(&self) -> Result<Vec<PendingHtlc>> {
+        let mut dbtx = self.gateway_db.begin_transaction_nc().await;
+
+        let pending = dbtx
+            .find_by_prefix(&PendingIncomingPaymentKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut htlcs = Vec::with_capacity(pending.len());
+        for (key, payment) in pending {
+            htlcs.push(PendingHtlc {
+                incoming_chan_id: key.incoming_chan_id,
+                htlc_id: key.htlc_id,
+                federation_id: payment.federation_id,
+                payment_hash: payment.payment_hash,
+                amount: payment.amount,
+                started_at: payment.started_at,
+                hold_invoice: dbtx
+                    .get_value(&HoldInvoiceKey(payment.payment_hash))
+                    .await
+                    .is_some(),
+            });
+        }
+        htlcs.sort_by_key(|htlc| htlc.started_at);
+
+        Ok(htlcs)
+    }
+
+    /// Settles or cancels a pending HTLC on behalf of the operator, e.g. if the
+    /// state machine completing it is wedged. The resolution is recorded for
+    /// auditing and stops the state machine from completing the HTLC.
+    pub async fn handle_resolve_htlc_msg(&self, payload: ResolveHtlcPayload) -> Result<()> {
+        let ResolveHtlcPayload {
+            incoming_chan_id,
+            htlc_id,
+            resolution,
+        } = payload;
+
+        let payment = self
+            .gateway_db
+            .begin_transaction_nc()
+            .await
+            .get_value(&PendingIncomingPaymentKey {
+                incoming_chan_id,
+                htlc_id,
+            })
+            .await
+            .ok_or_else(|| {
+                GatewayError::UnexpectedState(format!(
+                    "No pending HTLC {htlc_id} on channel {incoming_chan_id}"
+                ))
+            })?;
+
+        let hold_invoice = self
+            .hold_invoice_payment_hash(incoming_chan_id, htlc_id)
+            .await
+            .is_some();
+        let lnrpc = self.get_lightning_context().await?.lnrpc;
+
+        let (result, reason) = match resolution {
+            HtlcResolution::Settle { preimage } => {
+                let preimage: [u8; 32] = preimage.try_into().map_err(|_| {
+                    GatewayError::UnexpectedState("Preimage must be 32 bytes".to_string())
+                })?;
+                if sha256::Hash::hash(&preimage) != payment.payment_hash {
+                    return Err(GatewayError::UnexpectedState(
+                        "Preimage does not match the payment hash".to_string(),
+                    ));
+                }
+
+                if hold_invoice {
+                    lnrpc.settle_hold_invoice(preimage).await?;
+                } else {
+                    lnrpc
+                        .complete_htlc(InterceptHtlcResponse {
+                            action: Some(Action::Settle(Settle {
+                                preimage: preimage.to_vec(),
+                            })),
+                            incoming_chan_id,
+                            htlc_id,
+                        })
+                        .await?;
+                }
+
+                (Ok(()), None)
+            }
+            HtlcResolution::Cancel { reason } => {
+                if hold_invoice {
+                    lnrpc.cancel_hold_invoice(payment.payment_hash).await?;
+                } else {
+                    lnrpc
+                        .complete_htlc(InterceptHtlcResponse {
+                            action: Some(Action::Cancel(Cancel {
+                                reason: reason.clone(),
+                            })),
+                            incoming_chan_id,
+                            htlc_id,
+                        })
+                        .await?;
+                }
+
+                (Err(reason.clone()), Some(reason))
+            }
+        };
+
+        let mut dbtx = self.gateway_db.begin_transaction().await;
+        dbtx.insert_entry(
+            &ManualHtlcResolutionKey {
+                incoming_chan_id,
+                htlc_id,
+            },
+            &ManualHtlcResolution {
+                federation_id: payment.federation_id,
+                payment_hash: payment.payment_hash,
+                settled: result.is_ok(),
+                reason: reason.clone(),
+                resolved_at: now(),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        warn!(
+            incoming_chan_id,
+            htlc_id,
+            payment_hash = %payment.payment_hash,
+            federation_id = %payment.federation_id,
+            settled = result.is_ok(),
+            ?reason,
+            "HTLC was resolved manually by the operator"
+        );
+
+        self.finish_incoming_payment(incoming_chan_id, htlc_id, result)
+            .await;
+
+        Ok(())
+    }
+
     /// Retrieves the persisted `CreateInvoicePayload` from the database
     /// specified by the `payment_hash` and the `ClientHandleArc` specified
-    /// by the payload's `federation_id`.
+    /// by the payload's `federation_id`, if an HTLC of `amount_msats` pays the
+    /// invoice.
     pub async fn get_payload_and_client_v2(
         &self,
         payment_hash: [u8; 32],
@@ -1858,8 +5034,14 @@ impl Gateway {
             .await
             .ok_or(anyhow!("No corresponding decryption contract available"))?;
 
-        if payload.invoice_amount.msats != amount_msats {
-            bail!("The available decryption contract's amount is not equal the requested amount")
+        let receive_fee = self
+            .payment_info_v2(&payload.federation_id)
+            .await
+            .ok_or(anyhow!("Payment Info not available"))?
+            .receive_fee;
+
+        if !payload.accepts_htlc_amount(Amount::from_msats(amount_msats), &receive_fee) {
+            bail!("The available decryption contract does not accept the requested amount")
         }
 
         let clients = self.clients.read().await;
@@ -1874,6 +5056,23 @@ impl Gateway {
     }
 }
 
+/// Rejects changing a setting through `apply_config` that is only read on
+/// startup.
+fn ensure_startup_setting_unchanged<T: PartialEq>(
+    name: &str,
+    requested: Option<&T>,
+    current: Option<&T>,
+) -> Result<()> {
+    match requested {
+        Some(requested) if Some(requested) != current => {
+            Err(GatewayError::GatewayConfigurationError(format!(
+                "`{name}` can only be changed by restarting the gateway"
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
 /// Errors that can occur while processing incoming HTLC's, making outgoing
 /// payments, registering with connected federations, or responding to webserver
 /// requests.
@@ -1905,6 +5104,18 @@ pub enum GatewayError {
     FederationAlreadyConnected,
     #[error("Error parsing response: {}", OptStacktrace(.0))]
     LightningResponseParseError(anyhow::Error),
+    #[error("Invalid public receiver request: {}", OptStacktrace(.0))]
+    InvalidPublicReceiverRequest(String),
+    #[error("Rate limit exceeded")]
+    RateLimited,
+    #[error("The gateway is shutting down")]
+    ShuttingDown,
+    #[error("The gateway is degraded: {0}")]
+    Degraded(String),
+    #[error("Federation not allowed: {}", OptStacktrace(.0))]
+    FederationNotAllowed(String),
+    #[error("Risk limit exceeded: {0}")]
+    RiskLimitExceeded(RiskLimitViolation),
 }
 
 impl IntoResponse for GatewayError {
@@ -1922,6 +5133,42 @@ impl IntoResponse for GatewayError {
                 "The gateway is disconnected from the Lightning Node".to_string(),
                 StatusCode::NOT_FOUND,
             ),
+            GatewayError::InvalidPublicReceiverRequest(_) => (
+                "Invalid public invoice request".to_string(),
+                StatusCode::BAD_REQUEST,
+            ),
+            GatewayError::RateLimited => (
+                "Too many requests".to_string(),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            GatewayError::ShuttingDown => (
+                "The gateway is shutting down".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            GatewayError::Degraded(_) => (
+                "The gateway's lightning node is unavailable, try again later".to_string(),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            GatewayError::FederationNotAllowed(_) => (
+                "The gateway does not serve this federation".to_string(),
+                StatusCode::FORBIDDEN,
+            ),
+            // The volumes of other payments are not revealed to the client
+            GatewayError::RiskLimitExceeded(violation) => (
+                match violation {
+                    RiskLimitViolation::PaymentTooLarge { max, .. } => {
+                        format!("The payment exceeds the maximum payment amount of {max}")
+                    }
+                    RiskLimitViolation::HourlyVolumeExceeded { .. }
+                    | RiskLimitViolation::DailyVolumeExceeded { .. } => {
+                        "The outgoing volume limit of the federation is reached".to_string()
+                    }
+                    RiskLimitViolation::ExposureExceeded { .. } => {
+                        "The gateway has too many pending payments".to_string()
+                    }
+                },
+                StatusCode::FORBIDDEN,
+            ),
             _ => (
                 "An internal gateway error occurred".to_string(),
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -1952,3 +5199,117 @@ impl Display for PrettyInterceptHtlcRequest<'_> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::config::FederationId;
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+
+    use super::{
+        assign_phantom_scid, random_phantom_scid, stored_scid_federation, PHANTOM_SCID_MAX_AGE,
+    };
+    use crate::db::{FederationRegistrationKey, RegisteredScidBaseKey};
+    use crate::rpc::FederationRegistration;
+    use crate::scid::{self, MIN_SCID_BASE};
+
+    const BASE: u64 = (800_000 << 40) | (42 << 16);
+
+    #[test]
+    fn phantom_scids_look_like_recently_funded_channels() {
+        for _ in 0..1000 {
+            let scid = random_phantom_scid(800_000);
+            let funding_height = scid >> 40;
+            assert!(800_000 - u64::from(PHANTOM_SCID_MAX_AGE) < funding_height);
+            assert!(funding_height <= 800_000);
+        }
+
+        // Phantom SCIDs never encode block 0, which is used by SCID aliases
+        for _ in 0..1000 {
+            assert!(MIN_SCID_BASE <= random_phantom_scid(0));
+        }
+    }
+
+    #[tokio::test]
+    async fn phantom_scids_avoid_channels_and_scids_of_other_federations() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let federation_id = FederationId::dummy();
+        let other = FederationId(sha256::Hash::hash(b"other"));
+
+        // At a block height of 1 all phantom SCIDs are funded in block 1. Only the
+        // transaction with index 7 is not a registered SCID base of another
+        // federation, and only its output 3 is not a channel of the node.
+        for tx_index in (0..2_000u64).filter(|tx_index| *tx_index != 7) {
+            dbtx.insert_entry(
+                &RegisteredScidBaseKey {
+                    scid_base: (1 << 40) | (tx_index << 16),
+                },
+                &other,
+            )
+            .await;
+        }
+        let free_tx = (1 << 40) | (7 << 16);
+        let channel_scids = [free_tx, free_tx | 1, free_tx | 2];
+
+        let phantom_scid =
+            assign_phantom_scid(&mut dbtx.to_ref_nc(), federation_id, 1, &channel_scids).await;
+        assert_eq!(phantom_scid, free_tx | 3);
+        assert_eq!(
+            stored_scid_federation(&mut dbtx.to_ref_nc(), phantom_scid).await,
+            Some(federation_id)
+        );
+    }
+
+    #[tokio::test]
+    async fn phantom_scids_are_derived_from_the_registered_base() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let federation_id = FederationId::dummy();
+        dbtx.insert_entry(
+            &FederationRegistrationKey { federation_id },
+            &FederationRegistration {
+                lightning_alias: None,
+                scid_base: Some(BASE),
+            },
+        )
+        .await;
+        dbtx.insert_entry(&RegisteredScidBaseKey { scid_base: BASE }, &federation_id)
+            .await;
+
+        let first = assign_phantom_scid(&mut dbtx.to_ref_nc(), federation_id, 0, &[]).await;
+        let second = assign_phantom_scid(&mut dbtx.to_ref_nc(), federation_id, 0, &[]).await;
+        assert_eq!(scid::scid_base(first), BASE);
+        assert_eq!(scid::scid_base(second), BASE);
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn scids_resolve_to_their_federation() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let mut dbtx = db.begin_transaction().await;
+        let federation_id = FederationId::dummy();
+        dbtx.insert_entry(&RegisteredScidBaseKey { scid_base: BASE }, &federation_id)
+            .await;
+        let phantom_scid =
+            assign_phantom_scid(&mut dbtx.to_ref_nc(), federation_id, 800_000, &[]).await;
+
+        for scid in [phantom_scid, BASE, BASE | 5] {
+            assert_eq!(
+                stored_scid_federation(&mut dbtx.to_ref_nc(), scid).await,
+                Some(federation_id),
+                "{scid}"
+            );
+        }
+
+        // Neither an SCID alias nor derived from another SCID base
+        for scid in [3, BASE + (1 << 16)] {
+            assert_eq!(
+                stored_scid_federation(&mut dbtx.to_ref_nc(), scid).await,
+                None,
+                "{scid}"
+            );
+        }
+    }
+}
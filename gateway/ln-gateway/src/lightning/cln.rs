@@ -1,24 +1,31 @@
 use std::fmt::Debug;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::secp256k1;
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::util::SafeUrl;
 use futures::stream::BoxStream;
 use tonic::transport::{Channel, Endpoint};
-use tonic::Request;
-use tracing::info;
+use tonic::{Code, Request};
+use tracing::{debug, info};
 
-use super::{ChannelInfo, ILnRpcClient, LightningRpcError};
+use super::{
+    ChannelDetails, ChannelInfo, ChannelState, ChannelType, ILnRpcClient, LightningRpcError,
+    PaymentUpdateSender,
+};
 use crate::gateway_lnrpc::gateway_lightning_client::GatewayLightningClient;
+use crate::gateway_lnrpc::pay_invoice_update::Update;
 use crate::gateway_lnrpc::{
+    list_channels_response, CancelHoldInvoiceRequest, CloseChannelRequest,
     CloseChannelsWithPeerRequest, CloseChannelsWithPeerResponse, ConnectToPeerRequest,
     CreateInvoiceRequest, CreateInvoiceResponse, EmptyRequest, EmptyResponse,
     GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsRequest, GetRouteHintsResponse,
     InterceptHtlcRequest, InterceptHtlcResponse, OpenChannelRequest, PayInvoiceRequest,
-    PayInvoiceResponse,
+    PayInvoiceResponse, SettleHoldInvoiceRequest,
 };
 use crate::lightning::MAX_LIGHTNING_RETRIES;
 pub type HtlcResult = std::result::Result<InterceptHtlcRequest, tonic::Status>;
@@ -112,6 +119,53 @@ impl ILnRpcClient for NetworkLnRpcClient {
         Ok(res.into_inner())
     }
 
+    async fn pay_with_updates(
+        &self,
+        invoice: PayInvoiceRequest,
+        updates: PaymentUpdateSender,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let mut stream = match client
+            .pay_invoice_stream(Request::new(invoice.clone()))
+            .await
+        {
+            Ok(stream) => stream.into_inner(),
+            // Extensions built before streaming was added can still pay the invoice
+            Err(status) if status.code() == Code::Unimplemented => {
+                debug!("CLN extension does not stream payment updates, paying without them");
+                return self.pay(invoice).await;
+            }
+            Err(status) => {
+                return Err(LightningRpcError::FailedPayment {
+                    failure_reason: status.message().to_string(),
+                })
+            }
+        };
+
+        loop {
+            let update = stream
+                .message()
+                .await
+                .map_err(|status| LightningRpcError::FailedPayment {
+                    failure_reason: status.message().to_string(),
+                })?
+                .and_then(|update| update.update)
+                .ok_or_else(|| LightningRpcError::FailedPayment {
+                    failure_reason: "Payment update stream ended without an outcome".to_string(),
+                })?;
+
+            match update {
+                Update::Attempt(attempt) => {
+                    let _ = updates.send(attempt.into());
+                }
+                Update::Success(response) => return Ok(response),
+                Update::FailureReason(failure_reason) => {
+                    return Err(LightningRpcError::FailedPayment { failure_reason })
+                }
+            }
+        }
+    }
+
     async fn route_htlcs<'a>(
         self: Box<Self>,
         _task_group: &mut TaskGroup,
@@ -156,6 +210,51 @@ impl ILnRpcClient for NetworkLnRpcClient {
         Ok(res.into_inner())
     }
 
+    async fn create_hold_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        // Invoices created by the extension are unknown to CLN, so their HTLCs are
+        // always intercepted and held until gatewayd resolves them
+        self.create_invoice(create_invoice_request).await
+    }
+
+    async fn settle_hold_invoice(
+        &self,
+        preimage: [u8; 32],
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client
+            .settle_hold_invoice(SettleHoldInvoiceRequest {
+                preimage: preimage.to_vec(),
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToSettleHoldInvoice {
+                failure_reason: status.message().to_string(),
+            })?;
+        Ok(res.into_inner())
+    }
+
+    async fn cancel_hold_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client
+            .cancel_hold_invoice(CancelHoldInvoiceRequest {
+                payment_hash: payment_hash.to_byte_array().to_vec(),
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToCancelHoldInvoice {
+                failure_reason: status.message().to_string(),
+            })?;
+        Ok(res.into_inner())
+    }
+
+    fn supports_hold_invoices(&self) -> bool {
+        true
+    }
+
     async fn connect_to_peer(
         &self,
         pubkey: secp256k1::PublicKey,
@@ -239,7 +338,67 @@ impl ILnRpcClient for NetworkLnRpcClient {
                 outbound_liquidity_sats: channel.outbound_liquidity_sats,
                 inbound_liquidity_sats: channel.inbound_liquidity_sats,
                 short_channel_id: channel.short_channel_id,
+                channel_type: ChannelType::Bitcoin,
             })
             .collect())
     }
+
+    async fn list_channels(&self) -> Result<Vec<ChannelDetails>, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client
+            .list_channels(EmptyRequest {})
+            .await
+            .map_err(|status| LightningRpcError::FailedToListChannels {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        res.into_inner()
+            .channels
+            .into_iter()
+            .map(|channel| {
+                let state = match channel.state() {
+                    list_channels_response::ChannelState::Opening => ChannelState::Opening,
+                    list_channels_response::ChannelState::Active => ChannelState::Active,
+                    list_channels_response::ChannelState::Inactive => ChannelState::Inactive,
+                    list_channels_response::ChannelState::Closing => ChannelState::Closing,
+                };
+
+                Ok(ChannelDetails {
+                    channel_id: bitcoin::OutPoint::from_str(&channel.channel_id).map_err(|e| {
+                        LightningRpcError::FailedToListChannels {
+                            failure_reason: format!("Invalid channel id: {e}"),
+                        }
+                    })?,
+                    short_channel_id: channel.short_channel_id,
+                    remote_pubkey: channel.remote_pubkey,
+                    state,
+                    channel_size_sats: channel.channel_size_sats,
+                    outbound_liquidity_sats: channel.outbound_liquidity_sats,
+                    inbound_liquidity_sats: channel.inbound_liquidity_sats,
+                    closing_txid: channel
+                        .closing_txid
+                        .and_then(|txid| bitcoin::Txid::from_str(&txid).ok()),
+                    channel_type: ChannelType::Bitcoin,
+                })
+            })
+            .collect()
+    }
+
+    async fn close_channel(
+        &self,
+        channel_id: bitcoin::OutPoint,
+        force: bool,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let res = client
+            .close_channel(CloseChannelRequest {
+                channel_id: channel_id.to_string(),
+                force,
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToCloseChannel {
+                failure_reason: status.message().to_string(),
+            })?;
+        Ok(res.into_inner())
+    }
 }
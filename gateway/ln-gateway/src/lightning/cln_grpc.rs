@@ -0,0 +1,702 @@
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
+use cln_grpc::pb;
+use cln_grpc::pb::listpeerchannels_channels::ListpeerchannelsChannelsState;
+use cln_grpc::pb::node_client::NodeClient;
+use fedimint_core::secp256k1::{self, SecretKey, SECP256K1};
+use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::Amount;
+use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
+use rand::rngs::OsRng;
+use rand::Rng;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tracing::{debug, info, warn};
+
+use super::cln::RouteHtlcStream;
+use super::{
+    ChannelDetails, ChannelInfo, ChannelState, ChannelType, ForwardingEvent, ILnRpcClient,
+    LightningRpcError, RebalanceEvent, MAX_LIGHTNING_RETRIES,
+};
+use crate::gateway_lnrpc::create_invoice_request::Description;
+use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
+};
+
+/// Server name in the certificate CLN generates for its gRPC interface
+const CLN_GRPC_TLS_DOMAIN: &str = "cln";
+
+/// An `ILnRpcClient` that talks to the gRPC interface built into CLN, so no
+/// extension plugin has to be deployed alongside the node.
+///
+/// CLN's gRPC interface does not expose the `htlc_accepted` hook, so HTLCs
+/// can't be intercepted. Outgoing payments and the lightning node management
+/// work, but incoming payments to federations require the CLN extension.
+#[derive(Debug)]
+pub struct GatewayClnGrpcClient {
+    address: SafeUrl,
+    /// Directory containing the `ca.pem`, `client.pem` and `client-key.pem`
+    /// files CLN generates for its gRPC interface
+    cert_dir: PathBuf,
+}
+
+impl GatewayClnGrpcClient {
+    pub fn new(address: SafeUrl, cert_dir: PathBuf) -> Self {
+        info!(
+            "Gateway configured to connect to CLN gRPC at \n address: {},\n cert dir: {} ",
+            address,
+            cert_dir.display()
+        );
+        GatewayClnGrpcClient { address, cert_dir }
+    }
+
+    fn tls_config(&self) -> std::io::Result<ClientTlsConfig> {
+        let ca = std::fs::read(self.cert_dir.join("ca.pem"))?;
+        let cert = std::fs::read(self.cert_dir.join("client.pem"))?;
+        let key = std::fs::read(self.cert_dir.join("client-key.pem"))?;
+
+        Ok(ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(ca))
+            .identity(Identity::from_pem(cert, key))
+            .domain_name(CLN_GRPC_TLS_DOMAIN))
+    }
+
+    async fn connect(&self) -> Result<NodeClient<Channel>, LightningRpcError> {
+        let tls_config = self.tls_config().map_err(|e| {
+            warn!("Couldn't read CLN gRPC certificates: {e:?}");
+            LightningRpcError::FailedToConnect
+        })?;
+
+        let mut retries = 0;
+        let client = loop {
+            if retries >= MAX_LIGHTNING_RETRIES {
+                return Err(LightningRpcError::FailedToConnect);
+            }
+
+            retries += 1;
+
+            if let Ok(endpoint) = Endpoint::from_shared(self.address.to_string())
+                .and_then(|endpoint| endpoint.tls_config(tls_config.clone()))
+            {
+                if let Ok(channel) = endpoint.connect().await {
+                    break NodeClient::new(channel);
+                }
+            }
+
+            tracing::debug!("Couldn't connect to CLN gRPC, retrying in 1 second...");
+            sleep(Duration::from_secs(1)).await;
+        };
+
+        Ok(client)
+    }
+
+    /// Lists the channels of the node that are ready to route payments
+    async fn active_channels(
+        client: &mut NodeClient<Channel>,
+        peer_id: Option<Vec<u8>>,
+    ) -> Result<Vec<pb::ListpeerchannelsChannels>, tonic::Status> {
+        Ok(client
+            .list_peer_channels(pb::ListpeerchannelsRequest { id: peer_id })
+            .await?
+            .into_inner()
+            .channels
+            .into_iter()
+            .filter(|channel| {
+                channel.state == Some(ListpeerchannelsChannelsState::ChanneldNormal as i32)
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ILnRpcClient for GatewayClnGrpcClient {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let info = client
+            .getinfo(pb::GetinfoRequest {})
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetNodeInfo {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner();
+
+        Ok(GetNodeInfoResponse {
+            pub_key: info.id,
+            alias: info.alias.unwrap_or_default(),
+            network: info.network,
+            block_height: info.blockheight,
+            synced_to_chain: info.warning_bitcoind_sync.is_none()
+                && info.warning_lightningd_sync.is_none(),
+        })
+    }
+
+    async fn routehints(
+        &self,
+        num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        let node_id = self.info().await?.pub_key;
+        let mut client = self.connect().await?;
+
+        let mut channels = Self::active_channels(&mut client, None)
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetRouteHints {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        // Prefer the channels with the most inbound liquidity
+        channels.sort_by_key(|channel| {
+            std::cmp::Reverse(
+                channel
+                    .receivable_msat
+                    .as_ref()
+                    .map_or(0, |amount| amount.msat),
+            )
+        });
+
+        let mut route_hints = vec![];
+        for channel in channels {
+            if route_hints.len() == num_route_hints {
+                break;
+            }
+
+            let (Some(peer_id), Some(scid)) = (channel.peer_id, channel.short_channel_id) else {
+                continue;
+            };
+
+            let short_channel_id = match parse_short_channel_id(&scid) {
+                Some(short_channel_id) => short_channel_id,
+                None => {
+                    warn!(?scid, "CLN returned an invalid short channel id");
+                    continue;
+                }
+            };
+
+            let Some(channel) = client
+                .list_channels(pb::ListchannelsRequest {
+                    short_channel_id: Some(scid.clone()),
+                    source: None,
+                    destination: None,
+                })
+                .await
+                .map_err(|status| LightningRpcError::FailedToGetRouteHints {
+                    failure_reason: status.message().to_string(),
+                })?
+                .into_inner()
+                .channels
+                .into_iter()
+                .find(|channel| channel.destination == node_id)
+            else {
+                warn!(?scid, "Channel not found in graph");
+                continue;
+            };
+
+            let route_hint_hop = RouteHintHop {
+                src_node_id: peer_id,
+                short_channel_id,
+                base_msat: channel.base_fee_millisatoshi,
+                proportional_millionths: channel.fee_per_millionth,
+                cltv_expiry_delta: channel.delay,
+                htlc_minimum_msat: channel.htlc_minimum_msat.map(|amount| amount.msat),
+                htlc_maximum_msat: channel.htlc_maximum_msat.map(|amount| amount.msat),
+            };
+
+            debug!("Constructed route hint {:?}", route_hint_hop);
+            route_hints.push(RouteHint {
+                hops: vec![route_hint_hop],
+            });
+        }
+
+        Ok(GetRouteHintsResponse { route_hints })
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let response = client
+            .pay(pb::PayRequest {
+                bolt11: invoice.invoice,
+                maxdelay: Some(invoice.max_delay as u32),
+                maxfee: Some(pb::Amount {
+                    msat: invoice.max_fee_msat,
+                }),
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedPayment {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner();
+
+        Ok(PayInvoiceResponse {
+            preimage: response.payment_preimage,
+            shards: vec![],
+            fee_msat: fee_msat(response.amount_msat, response.amount_sent_msat),
+        })
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        _task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        // Fail early if the node isn't reachable, like the other backends do
+        self.info().await?;
+
+        warn!("CLN gRPC can't intercept HTLCs, incoming payments require the CLN extension");
+
+        Ok((Box::pin(futures::stream::pending()), Arc::new(*self)))
+    }
+
+    async fn complete_htlc(
+        &self,
+        _htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCompleteHtlc {
+            failure_reason: "CLN gRPC does not intercept HTLCs".to_string(),
+        })
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        let CreateInvoiceRequest {
+            payment_hash,
+            amount_msat,
+            expiry,
+            description,
+        } = create_invoice_request;
+
+        let payment_hash = sha256::Hash::from_slice(&payment_hash).map_err(|e| {
+            LightningRpcError::FailedToGetInvoice {
+                failure_reason: e.to_string(),
+            }
+        })?;
+        let description = description.ok_or(LightningRpcError::FailedToGetInvoice {
+            failure_reason: "Description or description hash was not provided".to_string(),
+        })?;
+        let network = Currency::from_str(&self.info().await?.network).map_err(|e| {
+            LightningRpcError::FailedToGetInvoice {
+                failure_reason: e.to_string(),
+            }
+        })?;
+
+        // CLN can't create an invoice for a payment hash whose preimage it doesn't
+        // know, so we build the invoice ourselves and let CLN sign it
+        let builder = InvoiceBuilder::new(network);
+        // A zero amount creates an amountless invoice
+        let builder = if amount_msat == 0 {
+            builder
+        } else {
+            builder.amount_milli_satoshis(amount_msat)
+        };
+        let builder = match description {
+            Description::Direct(description) => builder.description(description),
+            Description::Hash(hash) => {
+                builder.description_hash(sha256::Hash::from_slice(&hash).map_err(|e| {
+                    LightningRpcError::FailedToGetInvoice {
+                        failure_reason: e.to_string(),
+                    }
+                })?)
+            }
+        };
+        let invoice = builder
+            .payment_hash(payment_hash)
+            .payment_secret(PaymentSecret(OsRng.gen()))
+            .duration_since_epoch(fedimint_core::time::duration_since_epoch())
+            .min_final_cltv_expiry_delta(18)
+            .expiry_time(Duration::from_secs(expiry.into()))
+            // Temporarily sign with an ephemeral private key, CLN signs the invoice next
+            .build_signed(|m| SECP256K1.sign_ecdsa_recoverable(m, &SecretKey::new(&mut OsRng)))
+            .map_err(|e| LightningRpcError::FailedToGetInvoice {
+                failure_reason: e.to_string(),
+            })?;
+
+        let mut client = self.connect().await?;
+        let response = client
+            .sign_invoice(pb::SigninvoiceRequest {
+                invstring: invoice.to_string(),
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetInvoice {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner();
+
+        Ok(CreateInvoiceResponse {
+            invoice: response.bolt11,
+        })
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        client
+            .connect_peer(pb::ConnectRequest {
+                id: format!("{pubkey}@{host}"),
+                host: None,
+                port: None,
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToConnectToPeer {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let response = client
+            .new_addr(pb::NewaddrRequest { addresstype: None })
+            .await
+            .map_err(|status| LightningRpcError::FailedToGetFundingAddress {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner();
+
+        let address = response
+            .bech32
+            .ok_or(LightningRpcError::FailedToGetFundingAddress {
+                failure_reason: "CLN newaddr returned no address".to_string(),
+            })?;
+
+        Ok(GetFundingAddressResponse { address })
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        client
+            .fund_channel(pb::FundchannelRequest {
+                id: pubkey.serialize().to_vec(),
+                amount: Some(pb::AmountOrAll {
+                    value: Some(pb::amount_or_all::Value::Amount(pb::Amount {
+                        msat: channel_size_sats * 1000,
+                    })),
+                }),
+                push_msat: Some(pb::Amount {
+                    msat: push_amount_sats * 1000,
+                }),
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToOpenChannel {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let channels = Self::active_channels(&mut client, Some(pubkey.serialize().to_vec()))
+            .await
+            .map_err(|status| LightningRpcError::FailedToCloseChannelsWithPeer {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        let channel_ids = channels
+            .into_iter()
+            .filter_map(|channel| channel.channel_id)
+            .collect::<Vec<_>>();
+
+        for channel_id in &channel_ids {
+            client
+                .close(pb::CloseRequest {
+                    id: hex::encode(channel_id),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|status| LightningRpcError::FailedToCloseChannelsWithPeer {
+                    failure_reason: status.message().to_string(),
+                })?;
+        }
+
+        Ok(CloseChannelsWithPeerResponse {
+            num_channels_closed: channel_ids.len() as u32,
+        })
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let channels = Self::active_channels(&mut client, None)
+            .await
+            .map_err(|status| LightningRpcError::FailedToListActiveChannels {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        Ok(channels
+            .into_iter()
+            .filter_map(|channel| {
+                Some(ChannelInfo {
+                    remote_pubkey: hex::encode(channel.peer_id?),
+                    channel_size_sats: channel.total_msat.map_or(0, |amount| amount.msat / 1000),
+                    outbound_liquidity_sats: channel
+                        .spendable_msat
+                        .map_or(0, |amount| amount.msat / 1000),
+                    inbound_liquidity_sats: channel
+                        .receivable_msat
+                        .map_or(0, |amount| amount.msat / 1000),
+                    short_channel_id: parse_short_channel_id(&channel.short_channel_id?)?,
+                    channel_type: ChannelType::Bitcoin,
+                })
+            })
+            .collect())
+    }
+
+    async fn list_channels(&self) -> Result<Vec<ChannelDetails>, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let channels = client
+            .list_peer_channels(pb::ListpeerchannelsRequest { id: None })
+            .await
+            .map_err(|status| LightningRpcError::FailedToListChannels {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner()
+            .channels;
+
+        Ok(channels.into_iter().filter_map(channel_details).collect())
+    }
+
+    async fn close_channel(
+        &self,
+        channel_id: bitcoin::OutPoint,
+        force: bool,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let channel = client
+            .list_peer_channels(pb::ListpeerchannelsRequest { id: None })
+            .await
+            .map_err(|status| LightningRpcError::FailedToCloseChannel {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner()
+            .channels
+            .into_iter()
+            .find(|channel| funding_outpoint(channel) == Some(channel_id))
+            .ok_or_else(|| LightningRpcError::FailedToCloseChannel {
+                failure_reason: format!("Channel {channel_id} not found"),
+            })?;
+
+        let cln_channel_id =
+            channel
+                .channel_id
+                .ok_or_else(|| LightningRpcError::FailedToCloseChannel {
+                    failure_reason: format!("Channel {channel_id} has no channel id"),
+                })?;
+
+        client
+            .close(pb::CloseRequest {
+                id: hex::encode(cln_channel_id),
+                // CLN closes the channel unilaterally once the timeout passed
+                // without the peer agreeing to a cooperative close
+                unilateraltimeout: force.then_some(1),
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToCloseChannel {
+                failure_reason: status.message().to_string(),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn list_forwards(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<ForwardingEvent>, LightningRpcError> {
+        let mut client = self.connect().await?;
+        let forwards = client
+            .list_forwards(pb::ListforwardsRequest {
+                status: Some(pb::listforwards_request::ListforwardsStatus::Settled as i32),
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToListForwards {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner()
+            .forwards;
+
+        Ok(forwards
+            .into_iter()
+            .filter(|forward| {
+                forward.status() == pb::listforwards_forwards::ListforwardsForwardsStatus::Settled
+            })
+            .filter_map(|forward| {
+                let timestamp_secs = forward.resolved_time.unwrap_or(forward.received_time) as u64;
+                (timestamp_secs >= since_secs).then(|| ForwardingEvent {
+                    timestamp_secs,
+                    incoming_short_channel_id: parse_short_channel_id(&forward.in_channel)
+                        .unwrap_or_default(),
+                    outgoing_short_channel_id: forward
+                        .out_channel
+                        .as_deref()
+                        .and_then(parse_short_channel_id)
+                        .unwrap_or_default(),
+                    amount_in: Amount::from_msats(forward.in_msat.map_or(0, |amount| amount.msat)),
+                    amount_out: Amount::from_msats(
+                        forward.out_msat.map_or(0, |amount| amount.msat),
+                    ),
+                })
+            })
+            .collect())
+    }
+
+    async fn list_rebalances(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<RebalanceEvent>, LightningRpcError> {
+        let node_id = self.info().await?.pub_key;
+
+        let mut client = self.connect().await?;
+        let pays = client
+            .list_pays(pb::ListpaysRequest {
+                status: Some(pb::listpays_request::ListpaysStatus::Complete as i32),
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToListRebalances {
+                failure_reason: status.message().to_string(),
+            })?
+            .into_inner()
+            .pays;
+
+        // Circular payments to our own node move liquidity between our channels
+        Ok(pays
+            .into_iter()
+            .filter(|pay| {
+                pay.status() == pb::listpays_pays::ListpaysPaysStatus::Complete
+                    && pay.destination.as_ref() == Some(&node_id)
+            })
+            .filter_map(|pay| {
+                let timestamp_secs = pay.completed_at.unwrap_or(pay.created_at);
+                (timestamp_secs >= since_secs).then(|| RebalanceEvent {
+                    timestamp_secs,
+                    amount: Amount::from_msats(
+                        pay.amount_msat.as_ref().map_or(0, |amount| amount.msat),
+                    ),
+                    fee: Amount::from_msats(fee_msat(pay.amount_msat, pay.amount_sent_msat)),
+                })
+            })
+            .collect())
+    }
+}
+
+/// Lightning network fees of a payment that delivered `amount` by sending
+/// `amount_sent`
+fn fee_msat(amount: Option<pb::Amount>, amount_sent: Option<pb::Amount>) -> u64 {
+    let msat = |amount: Option<pb::Amount>| amount.map_or(0, |amount| amount.msat);
+    msat(amount_sent).saturating_sub(msat(amount))
+}
+
+fn parse_txid(bytes: &[u8]) -> Option<bitcoin::Txid> {
+    bitcoin::Txid::from_str(&hex::encode(bytes)).ok()
+}
+
+fn funding_outpoint(channel: &pb::ListpeerchannelsChannels) -> Option<bitcoin::OutPoint> {
+    Some(bitcoin::OutPoint {
+        txid: parse_txid(channel.funding_txid.as_ref()?)?,
+        vout: channel.funding_outnum?,
+    })
+}
+
+fn channel_details(channel: pb::ListpeerchannelsChannels) -> Option<ChannelDetails> {
+    let cln_state = ListpeerchannelsChannelsState::try_from(channel.state?).ok()?;
+    let state = match cln_state {
+        ListpeerchannelsChannelsState::ChanneldNormal if channel.peer_connected == Some(true) => {
+            ChannelState::Active
+        }
+        ListpeerchannelsChannelsState::ChanneldNormal => ChannelState::Inactive,
+        ListpeerchannelsChannelsState::Openingd
+        | ListpeerchannelsChannelsState::ChanneldAwaitingLockin
+        | ListpeerchannelsChannelsState::DualopendOpenInit
+        | ListpeerchannelsChannelsState::DualopendAwaitingLockin => ChannelState::Opening,
+        _ => ChannelState::Closing,
+    };
+
+    // Only channels that can still route payments report their liquidity
+    let (outbound_liquidity_sats, inbound_liquidity_sats) = if state == ChannelState::Closing {
+        (0, 0)
+    } else {
+        (
+            channel
+                .spendable_msat
+                .as_ref()
+                .map_or(0, |amount| amount.msat / 1000),
+            channel
+                .receivable_msat
+                .as_ref()
+                .map_or(0, |amount| amount.msat / 1000),
+        )
+    };
+
+    Some(ChannelDetails {
+        channel_id: funding_outpoint(&channel)?,
+        short_channel_id: channel
+            .short_channel_id
+            .as_deref()
+            .and_then(parse_short_channel_id),
+        remote_pubkey: hex::encode(channel.peer_id?),
+        state,
+        channel_size_sats: channel.total_msat.map_or(0, |amount| amount.msat / 1000),
+        outbound_liquidity_sats,
+        inbound_liquidity_sats,
+        closing_txid: (state == ChannelState::Closing)
+            .then(|| channel.scratch_txid.as_deref().and_then(parse_txid))
+            .flatten(),
+        channel_type: ChannelType::Bitcoin,
+    })
+}
+
+/// Parses a short channel id in CLN's `<block>x<tx index>x<output>` format
+/// into its integer representation
+fn parse_short_channel_id(scid: &str) -> Option<u64> {
+    let mut parts = scid.split('x').map(u64::from_str);
+    let (Some(Ok(block)), Some(Ok(tx_index)), Some(Ok(output)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    if block >= 1 << 24 || tx_index >= 1 << 24 || output >= 1 << 16 {
+        return None;
+    }
+
+    Some(block << 40 | tx_index << 16 | output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_short_channel_id;
+
+    #[test]
+    fn parses_short_channel_ids() {
+        assert_eq!(parse_short_channel_id("103x1x0"), Some(103 << 40 | 1 << 16));
+        assert_eq!(parse_short_channel_id("103x1"), None);
+        assert_eq!(parse_short_channel_id("103x1x0x1"), None);
+        assert_eq!(parse_short_channel_id("103x1x70000"), None);
+        assert_eq!(parse_short_channel_id("abc"), None);
+    }
+}
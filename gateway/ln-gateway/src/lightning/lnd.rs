@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -5,21 +6,25 @@ use std::time::Duration;
 
 use anyhow::ensure;
 use async_trait::async_trait;
-use bitcoin_hashes::Hash;
+use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::{secp256k1, Amount};
-use fedimint_ln_common::PrunedInvoice;
+use fedimint_ln_common::{KeysendPayment, PrunedInvoice};
 use hex::ToHex;
 use secp256k1::PublicKey;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Status;
-use tonic_lnd::invoicesrpc::AddHoldInvoiceRequest;
+use tonic_lnd::invoicesrpc::{AddHoldInvoiceRequest, CancelInvoiceMsg, SettleInvoiceMsg};
 use tonic_lnd::lnrpc::failure::FailureCode;
+use tonic_lnd::lnrpc::htlc_attempt::HtlcStatus;
+use tonic_lnd::lnrpc::invoice::InvoiceState;
 use tonic_lnd::lnrpc::payment::PaymentStatus;
 use tonic_lnd::lnrpc::{
-    ChanInfoRequest, ChannelPoint, CloseChannelRequest, ConnectPeerRequest, GetInfoRequest,
-    LightningAddress, ListChannelsRequest, OpenChannelRequest,
+    ChanInfoRequest, ChannelPoint, CloseChannelRequest, ConnectPeerRequest,
+    ForwardingHistoryRequest, GetInfoRequest, InvoiceHtlcState, InvoiceSubscription,
+    LightningAddress, ListChannelsRequest, ListPaymentsRequest, OpenChannelRequest,
+    PendingChannelsRequest,
 };
 use tonic_lnd::routerrpc::{
     CircuitKey, ForwardHtlcInterceptResponse, ResolveHoldForwardAction, SendPaymentRequest,
@@ -31,26 +36,41 @@ use tonic_lnd::{connect, Client as LndClient};
 use tracing::{debug, error, info, trace, warn};
 
 use super::cln::RouteHtlcStream;
-use super::{ChannelInfo, ILnRpcClient, LightningRpcError, MAX_LIGHTNING_RETRIES};
+use super::{
+    ChannelDetails, ChannelInfo, ChannelState, ChannelType, ForwardingEvent, ILnRpcClient,
+    LightningRpcError, PaymentAttemptStatus, PaymentAttemptUpdate, PaymentUpdateSender,
+    RebalanceEvent, MAX_LIGHTNING_RETRIES,
+};
 use crate::gateway_lnrpc::create_invoice_request::Description;
 use crate::gateway_lnrpc::get_route_hints_response::{RouteHint, RouteHintHop};
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Forward, Settle};
 use crate::gateway_lnrpc::{
     CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcRequest,
-    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, HtlcAttempt,
+    InterceptHtlcRequest, InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 
 type HtlcSubscriptionSender = mpsc::Sender<Result<InterceptHtlcRequest, Status>>;
 
 const LND_PAYMENT_TIMEOUT_SECONDS: i32 = 180;
 
+/// Number of forwards or payments fetched from LND per request
+const LND_LIST_PAGE_SIZE: u32 = 1000;
+
+/// Custom record type LND uses to carry the preimage of a keysend payment
+const KEYSEND_PREIMAGE_TLV_TYPE: u64 = 5_482_373_484;
+
 pub struct GatewayLndClient {
     /// LND client
     address: String,
     tls_cert: String,
     macaroon: String,
     lnd_sender: Option<mpsc::Sender<ForwardHtlcInterceptResponse>>,
+    /// Maximum number of parts payments are split into, `None` uses LND's
+    /// default
+    max_parts: Option<u32>,
+    /// Maximum amount of a single part of a payment, `None` doesn't limit it
+    max_shard_amount: Option<Amount>,
 }
 
 impl GatewayLndClient {
@@ -69,6 +89,18 @@ impl GatewayLndClient {
             tls_cert,
             macaroon,
             lnd_sender,
+            max_parts: None,
+            max_shard_amount: None,
+        }
+    }
+
+    /// Limits how LND splits outgoing payments into multiple parts, so that
+    /// large payments can use the liquidity of several channels
+    pub fn with_mpp_limits(self, max_parts: Option<u32>, max_shard_amount: Option<Amount>) -> Self {
+        GatewayLndClient {
+            max_parts,
+            max_shard_amount,
+            ..self
         }
     }
 
@@ -202,6 +234,81 @@ impl GatewayLndClient {
         Ok(())
     }
 
+    /// LND doesn't pass HTLCs paying our own hold invoices to the HTLC
+    /// interceptor, so we subscribe to the invoices of the node and send the
+    /// HTLCs of accepted hold invoices to gatewayd instead. They are held until
+    /// gatewayd settles or cancels the invoice.
+    async fn spawn_hold_invoice_subscription(
+        &self,
+        task_group: &mut TaskGroup,
+        gateway_sender: HtlcSubscriptionSender,
+    ) -> Result<(), LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let mut invoice_stream = client
+            .lightning()
+            .subscribe_invoices(InvoiceSubscription {
+                add_index: 0,
+                settle_index: 0,
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedToRouteHtlcs {
+                failure_reason: format!("Failed to subscribe to LND invoices {status:?}"),
+            })?
+            .into_inner();
+
+        task_group.spawn("LND Hold Invoice Subscription", move |handle| async move {
+            while let Some(invoice) = tokio::select! {
+                _ = handle.make_shutdown_rx().await => {
+                    info!("LND Hold Invoice Subscription task received shutdown signal");
+                    None
+                }
+                invoice_message = invoice_stream.message() => {
+                    match invoice_message {
+                        Ok(invoice) => invoice,
+                        Err(e) => {
+                            error!("Error received over invoice stream: {:?}", e);
+                            None
+                        }
+                    }
+                }
+            } {
+                if invoice.state != InvoiceState::Accepted as i32 {
+                    continue;
+                }
+
+                // The HTLCs of a multi-part payment are settled or cancelled together,
+                // so the first HTLC identifies the payment
+                let Some(htlc) = invoice
+                    .htlcs
+                    .iter()
+                    .find(|htlc| htlc.state == InvoiceHtlcState::Accepted as i32)
+                else {
+                    continue;
+                };
+
+                trace!("LND Hold Invoice Subscription: handling accepted invoice {invoice:?}");
+
+                let intercept = InterceptHtlcRequest {
+                    payment_hash: invoice.r_hash.clone(),
+                    incoming_amount_msat: invoice.amt_paid_msat as u64,
+                    outgoing_amount_msat: invoice.value_msat as u64,
+                    incoming_expiry: htlc.expiry_height as u32,
+                    // The HTLC terminates at the gateway node
+                    short_channel_id: None,
+                    incoming_chan_id: htlc.chan_id,
+                    htlc_id: htlc.htlc_index,
+                };
+
+                if let Err(e) = gateway_sender.send(Ok(intercept)).await {
+                    error!("Failed to send hold invoice HTLC to gatewayd for processing: {e:?}");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
     async fn cancel_htlc(
         key: CircuitKey,
         lnd_sender: mpsc::Sender<ForwardHtlcInterceptResponse>,
@@ -262,7 +369,7 @@ impl GatewayLndClient {
                         }
 
                         let failure_reason = payment.failure_reason();
-                        return Err(LightningRpcError::FailedPayment {
+                        return Err(LightningRpcError::FailedPaymentFinal {
                             failure_reason: format!("{failure_reason:?}"),
                         });
                     }
@@ -410,6 +517,17 @@ impl ILnRpcClient for GatewayLndClient {
         invoice: PrunedInvoice,
         max_delay: u64,
         max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.pay_private_with_updates(invoice, max_delay, max_fee, mpsc::unbounded_channel().0)
+            .await
+    }
+
+    async fn pay_private_with_updates(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee: Amount,
+        updates: PaymentUpdateSender,
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
         info!("LND Paying invoice {invoice:?}");
         let mut client = self.connect().await?;
@@ -417,16 +535,17 @@ impl ILnRpcClient for GatewayLndClient {
         debug!("LND got client to pay invoice {invoice:?}, will check if payment already exists");
 
         // If the payment exists, that means we've already tried to pay the invoice
-        let preimage: Vec<u8> = if let Some(preimage) = self
-            .lookup_payment(invoice.payment_hash.to_byte_array().to_vec(), &mut client)
-            .await?
+        let (preimage, shards, fee_msat): (Vec<u8>, Vec<HtlcAttempt>, u64) = if let Some(preimage) =
+            self.lookup_payment(invoice.payment_hash.to_byte_array().to_vec(), &mut client)
+                .await?
         {
             info!("LND payment already exists for invoice {invoice:?}");
-            hex::FromHex::from_hex(preimage.as_str()).map_err(|error| {
+            let preimage = hex::FromHex::from_hex(preimage.as_str()).map_err(|error| {
                 LightningRpcError::FailedPayment {
                     failure_reason: format!("Failed to convert preimage {error:?}"),
                 }
-            })?
+            })?;
+            (preimage, vec![], 0)
         } else {
             // LND API allows fee limits in the `i64` range, but we use `u64` for
             // max_fee_msat. This means we can only set an enforceable fee limit
@@ -463,7 +582,11 @@ impl ILnRpcClient for GatewayLndClient {
                     failure_reason: e.to_string(),
                 })?;
 
-            debug!("LND payment does not exist for invoice {invoice:?}, will attempt to pay");
+            debug!(
+                max_parts = ?self.max_parts,
+                max_shard_amount = ?self.max_shard_amount,
+                "LND payment does not exist for invoice {invoice:?}, will attempt to pay"
+            );
             let payments = client
                 .router()
                 .send_payment_v2(SendPaymentRequest {
@@ -478,6 +601,9 @@ impl ILnRpcClient for GatewayLndClient {
                     no_inflight_updates: false,
                     timeout_seconds: LND_PAYMENT_TIMEOUT_SECONDS,
                     fee_limit_msat,
+                    // Zero makes LND use its defaults
+                    max_parts: self.max_parts.unwrap_or_default(),
+                    max_shard_size_msat: self.max_shard_amount.map_or(0, |amount| amount.msats),
                     ..Default::default()
                 })
                 .await
@@ -492,20 +618,39 @@ impl ILnRpcClient for GatewayLndClient {
                 "LND payment request sent for invoice {invoice:?}, waiting for payment status..."
             );
             let mut messages = payments.into_inner();
+            let mut reported_attempts = BTreeMap::new();
             loop {
-                match messages
-                    .message()
-                    .await
-                    .map_err(|error| LightningRpcError::FailedPayment {
-                        failure_reason: format!("Failed to get payment status {error:?}"),
-                    }) {
+                let message =
+                    messages
+                        .message()
+                        .await
+                        .map_err(|error| LightningRpcError::FailedPayment {
+                            failure_reason: format!("Failed to get payment status {error:?}"),
+                        });
+
+                if let Ok(Some(payment)) = &message {
+                    report_htlc_attempts(payment, &mut reported_attempts, &updates);
+                }
+
+                match message {
                     Ok(Some(payment)) if payment.status() == PaymentStatus::Succeeded => {
-                        info!("LND payment succeeded for invoice {invoice:?}");
-                        break hex::FromHex::from_hex(payment.payment_preimage.as_str()).map_err(
-                            |error| LightningRpcError::FailedPayment {
+                        info!(
+                            shards = payment.htlcs.len(),
+                            "LND payment succeeded for invoice {invoice:?}"
+                        );
+                        let preimage = hex::FromHex::from_hex(payment.payment_preimage.as_str())
+                            .map_err(|error| LightningRpcError::FailedPayment {
                                 failure_reason: format!("Failed to convert preimage {error:?}"),
-                            },
-                        )?;
+                            })?;
+                        break (
+                            preimage,
+                            payment
+                                .htlcs
+                                .iter()
+                                .map(|htlc| htlc_attempt_update(htlc).into())
+                                .collect(),
+                            payment.fee_msat.try_into().unwrap_or_default(),
+                        );
                     }
                     Ok(Some(payment)) if payment.status() == PaymentStatus::InFlight => {
                         debug!("LND payment is inflight");
@@ -514,7 +659,7 @@ impl ILnRpcClient for GatewayLndClient {
                     Ok(Some(payment)) => {
                         info!("LND payment failed for invoice {invoice:?} with {payment:?}");
                         let failure_reason = payment.failure_reason();
-                        return Err(LightningRpcError::FailedPayment {
+                        return Err(LightningRpcError::FailedPaymentFinal {
                             failure_reason: format!("{failure_reason:?}"),
                         });
                     }
@@ -534,7 +679,11 @@ impl ILnRpcClient for GatewayLndClient {
                 }
             }
         };
-        Ok(PayInvoiceResponse { preimage })
+        Ok(PayInvoiceResponse {
+            preimage,
+            shards,
+            fee_msat,
+        })
     }
 
     /// Returns true if the lightning backend supports payments without full
@@ -543,6 +692,128 @@ impl ILnRpcClient for GatewayLndClient {
         true
     }
 
+    async fn pay_keysend(
+        &self,
+        payment: KeysendPayment,
+        max_delay: u64,
+        max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let payment_hash = payment.payment_hash().to_byte_array().to_vec();
+        info!("LND sending keysend payment to {}", payment.destination);
+        let mut client = self.connect().await?;
+
+        // The preimage is chosen by the federation client, so a retried keysend
+        // maps to the same LND payment
+        if let Some(preimage) = self
+            .lookup_payment(payment_hash.clone(), &mut client)
+            .await?
+        {
+            info!(
+                "LND keysend payment already exists to {}",
+                payment.destination
+            );
+            let preimage = hex::FromHex::from_hex(preimage.as_str()).map_err(|error| {
+                LightningRpcError::FailedPayment {
+                    failure_reason: format!("Failed to convert preimage {error:?}"),
+                }
+            })?;
+            return Ok(PayInvoiceResponse {
+                preimage,
+                shards: vec![],
+                fee_msat: 0,
+            });
+        }
+
+        let fee_limit_msat: i64 =
+            max_fee
+                .msats
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedPayment {
+                    failure_reason: format!(
+                        "max_fee_msat exceeds valid LND fee limit ranges {error:?}"
+                    ),
+                })?;
+        let amt_msat =
+            payment
+                .amount
+                .msats
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedPayment {
+                    failure_reason: format!("amount exceeds valid LND amount ranges {error:?}"),
+                })?;
+        let cltv_limit =
+            max_delay
+                .try_into()
+                .map_err(|error| LightningRpcError::FailedPayment {
+                    failure_reason: format!("max delay exceeds valid LND range {error:?}"),
+                })?;
+
+        let mut dest_custom_records: HashMap<u64, Vec<u8>> =
+            payment.tlv_records.clone().into_iter().collect();
+        dest_custom_records.insert(KEYSEND_PREIMAGE_TLV_TYPE, payment.preimage.0.to_vec());
+
+        let payments = client
+            .router()
+            .send_payment_v2(SendPaymentRequest {
+                amt_msat,
+                dest: payment.destination.serialize().to_vec(),
+                payment_hash: payment_hash.clone(),
+                dest_custom_records,
+                cltv_limit,
+                no_inflight_updates: false,
+                timeout_seconds: LND_PAYMENT_TIMEOUT_SECONDS,
+                fee_limit_msat,
+                ..Default::default()
+            })
+            .await
+            .map_err(|status| LightningRpcError::FailedPayment {
+                failure_reason: format!("Failed to make outgoing keysend payment {status:?}"),
+            })?;
+
+        let mut messages = payments.into_inner();
+        loop {
+            let payment_status =
+                messages
+                    .message()
+                    .await
+                    .map_err(|error| LightningRpcError::FailedPayment {
+                        failure_reason: format!("Failed to get payment status {error:?}"),
+                    })?;
+            match payment_status {
+                Some(status) if status.status() == PaymentStatus::Succeeded => {
+                    info!("LND keysend payment to {} succeeded", payment.destination);
+                    return Ok(PayInvoiceResponse {
+                        preimage: payment.preimage.0.to_vec(),
+                        shards: vec![],
+                        fee_msat: status.fee_msat.try_into().unwrap_or_default(),
+                    });
+                }
+                Some(status) if status.status() == PaymentStatus::InFlight => {
+                    debug!("LND keysend payment is inflight");
+                }
+                Some(status) => {
+                    info!("LND keysend payment failed with {status:?}");
+                    let failure_reason = status.failure_reason();
+                    return Err(LightningRpcError::FailedPaymentFinal {
+                        failure_reason: format!("{failure_reason:?}"),
+                    });
+                }
+                None => {
+                    return Err(LightningRpcError::FailedPayment {
+                        failure_reason: format!(
+                            "Failed to get payment status for keysend payment hash {}",
+                            payment.payment_hash()
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    fn supports_keysend(&self) -> bool {
+        true
+    }
+
     async fn route_htlcs<'a>(
         self: Box<Self>,
         task_group: &mut TaskGroup,
@@ -562,6 +833,8 @@ impl ILnRpcClient for GatewayLndClient {
             gateway_sender.clone(),
         )
         .await?;
+        self.spawn_hold_invoice_subscription(task_group, gateway_sender.clone())
+            .await?;
         let new_client = Arc::new(Self::new(
             self.address.clone(),
             self.tls_cert.clone(),
@@ -618,6 +891,15 @@ impl ILnRpcClient for GatewayLndClient {
     async fn create_invoice(
         &self,
         create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        // Invoices are created as hold invoices, since the gateway doesn't know the
+        // preimage before the federation decrypted it
+        self.create_hold_invoice(create_invoice_request).await
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
     ) -> Result<CreateInvoiceResponse, LightningRpcError> {
         let mut client = self.connect().await?;
         let description =
@@ -656,6 +938,48 @@ impl ILnRpcClient for GatewayLndClient {
         Ok(CreateInvoiceResponse { invoice })
     }
 
+    async fn settle_hold_invoice(
+        &self,
+        preimage: [u8; 32],
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        client
+            .invoices()
+            .settle_invoice(SettleInvoiceMsg {
+                preimage: preimage.to_vec(),
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToSettleHoldInvoice {
+                failure_reason: e.to_string(),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn cancel_hold_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        client
+            .invoices()
+            .cancel_invoice(CancelInvoiceMsg {
+                payment_hash: payment_hash.to_byte_array().to_vec(),
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToCancelHoldInvoice {
+                failure_reason: e.to_string(),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    fn supports_hold_invoices(&self) -> bool {
+        true
+    }
+
     async fn connect_to_peer(
         &self,
         pubkey: PublicKey,
@@ -761,16 +1085,7 @@ impl ILnRpcClient for GatewayLndClient {
             client
                 .lightning()
                 .close_channel(CloseChannelRequest {
-                    channel_point: Some(ChannelPoint {
-                        funding_txid: Some(
-                            tonic_lnd::lnrpc::channel_point::FundingTxid::FundingTxidBytes(
-                                <bitcoin::Txid as AsRef<[u8]>>::as_ref(&channel_point.txid)
-                                    .as_ref()
-                                    .to_vec(),
-                            ),
-                        ),
-                        output_index: channel_point.vout,
-                    }),
+                    channel_point: Some(lnd_channel_point(channel_point)),
                     ..Default::default()
                 })
                 .await
@@ -803,48 +1118,16 @@ impl ILnRpcClient for GatewayLndClient {
                 .channels
                 .into_iter()
                 .map(|channel| {
-                    let channel_size_sats = channel.capacity.try_into().expect("i64 -> u64");
-
-                    let local_balance_sats: u64 =
-                        channel.local_balance.try_into().expect("i64 -> u64");
-                    let local_channel_reserve_sats: u64 = match channel.local_constraints {
-                        Some(constraints) => constraints.chan_reserve_sat,
-                        None => 0,
-                    };
-
-                    let outbound_liquidity_sats =
-                        if local_balance_sats >= local_channel_reserve_sats {
-                            // We must only perform this subtraction if the local balance is
-                            // greater than or equal to the channel reserve, otherwise we would
-                            // underflow and panic.
-                            local_balance_sats - local_channel_reserve_sats
-                        } else {
-                            0
-                        };
-
-                    let remote_balance_sats: u64 =
-                        channel.remote_balance.try_into().expect("i64 -> u64");
-                    let remote_channel_reserve_sats: u64 = match channel.remote_constraints {
-                        Some(constraints) => constraints.chan_reserve_sat,
-                        None => 0,
-                    };
-
-                    let inbound_liquidity_sats =
-                        if remote_balance_sats >= remote_channel_reserve_sats {
-                            // We must only perform this subtraction if the remote balance is
-                            // greater than or equal to the channel reserve, otherwise we would
-                            // underflow and panic.
-                            remote_balance_sats - remote_channel_reserve_sats
-                        } else {
-                            0
-                        };
+                    let (outbound_liquidity_sats, inbound_liquidity_sats) =
+                        channel_liquidity_sats(&channel);
 
                     ChannelInfo {
                         remote_pubkey: channel.remote_pubkey,
-                        channel_size_sats,
+                        channel_size_sats: channel.capacity.try_into().expect("i64 -> u64"),
                         outbound_liquidity_sats,
                         inbound_liquidity_sats,
                         short_channel_id: channel.chan_id,
+                        channel_type: channel_type(channel.commitment_type),
                     }
                 })
                 .collect()),
@@ -853,6 +1136,286 @@ impl ILnRpcClient for GatewayLndClient {
             }),
         }
     }
+
+    async fn list_channels(&self) -> Result<Vec<ChannelDetails>, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let open_channels = client
+            .lightning()
+            .list_channels(ListChannelsRequest::default())
+            .await
+            .map_err(|e| LightningRpcError::FailedToListChannels {
+                failure_reason: format!("Failed to list channels {e:?}"),
+            })?
+            .into_inner()
+            .channels;
+
+        let pending_channels = client
+            .lightning()
+            .pending_channels(PendingChannelsRequest::default())
+            .await
+            .map_err(|e| LightningRpcError::FailedToListChannels {
+                failure_reason: format!("Failed to list pending channels {e:?}"),
+            })?
+            .into_inner();
+
+        let mut channels = Vec::new();
+
+        for channel in open_channels {
+            let (outbound_liquidity_sats, inbound_liquidity_sats) =
+                channel_liquidity_sats(&channel);
+
+            channels.push(ChannelDetails {
+                channel_id: parse_channel_point(&channel.channel_point)?,
+                short_channel_id: Some(channel.chan_id),
+                remote_pubkey: channel.remote_pubkey,
+                state: if channel.active {
+                    ChannelState::Active
+                } else {
+                    ChannelState::Inactive
+                },
+                channel_size_sats: channel.capacity.try_into().expect("i64 -> u64"),
+                outbound_liquidity_sats,
+                inbound_liquidity_sats,
+                closing_txid: None,
+                channel_type: channel_type(channel.commitment_type),
+            });
+        }
+
+        let pending_opens = pending_channels
+            .pending_open_channels
+            .into_iter()
+            .map(|pending| (pending.channel, ChannelState::Opening, String::new()));
+        let waiting_closes = pending_channels
+            .waiting_close_channels
+            .into_iter()
+            .map(|pending| (pending.channel, ChannelState::Closing, pending.closing_txid));
+        let force_closes = pending_channels
+            .pending_force_closing_channels
+            .into_iter()
+            .map(|pending| (pending.channel, ChannelState::Closing, pending.closing_txid));
+
+        for (channel, state, closing_txid) in
+            pending_opens.chain(waiting_closes).chain(force_closes)
+        {
+            let Some(channel) = channel else {
+                continue;
+            };
+
+            channels.push(ChannelDetails {
+                channel_id: parse_channel_point(&channel.channel_point)?,
+                short_channel_id: None,
+                remote_pubkey: channel.remote_node_pub,
+                state,
+                channel_size_sats: channel.capacity.try_into().expect("i64 -> u64"),
+                // Pending channels can't route payments
+                outbound_liquidity_sats: 0,
+                inbound_liquidity_sats: 0,
+                closing_txid: bitcoin::Txid::from_str(&closing_txid).ok(),
+                channel_type: channel_type(channel.commitment_type),
+            });
+        }
+
+        Ok(channels)
+    }
+
+    async fn close_channel(
+        &self,
+        channel_id: bitcoin::OutPoint,
+        force: bool,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        client
+            .lightning()
+            .close_channel(CloseChannelRequest {
+                channel_point: Some(lnd_channel_point(channel_id)),
+                force,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| LightningRpcError::FailedToCloseChannel {
+                failure_reason: format!("Failed to close channel {channel_id} {e:?}"),
+            })?;
+
+        Ok(EmptyResponse {})
+    }
+
+    async fn list_forwards(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<ForwardingEvent>, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let mut forwards = Vec::new();
+        let mut index_offset = 0;
+        loop {
+            let response = client
+                .lightning()
+                .forwarding_history(ForwardingHistoryRequest {
+                    start_time: since_secs,
+                    index_offset,
+                    num_max_events: LND_LIST_PAGE_SIZE,
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| LightningRpcError::FailedToListForwards {
+                    failure_reason: format!("Failed to fetch forwarding history {e:?}"),
+                })?
+                .into_inner();
+
+            if response.forwarding_events.is_empty() {
+                return Ok(forwards);
+            }
+
+            forwards.extend(
+                response
+                    .forwarding_events
+                    .into_iter()
+                    .map(|event| ForwardingEvent {
+                        timestamp_secs: event.timestamp_ns / 1_000_000_000,
+                        incoming_short_channel_id: event.chan_id_in,
+                        outgoing_short_channel_id: event.chan_id_out,
+                        amount_in: Amount::from_msats(event.amt_in_msat),
+                        amount_out: Amount::from_msats(event.amt_out_msat),
+                    }),
+            );
+            index_offset = response.last_offset_index;
+        }
+    }
+
+    async fn list_rebalances(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<RebalanceEvent>, LightningRpcError> {
+        let mut client = self.connect().await?;
+
+        let own_pubkey = client
+            .lightning()
+            .get_info(GetInfoRequest {})
+            .await
+            .map_err(|e| LightningRpcError::FailedToListRebalances {
+                failure_reason: format!("Failed to get node info {e:?}"),
+            })?
+            .into_inner()
+            .identity_pubkey;
+
+        let mut rebalances = Vec::new();
+        let mut index_offset = 0;
+        loop {
+            let response = client
+                .lightning()
+                .list_payments(ListPaymentsRequest {
+                    include_incomplete: false,
+                    index_offset,
+                    max_payments: u64::from(LND_LIST_PAGE_SIZE),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| LightningRpcError::FailedToListRebalances {
+                    failure_reason: format!("Failed to list payments {e:?}"),
+                })?
+                .into_inner();
+
+            if response.payments.is_empty() {
+                return Ok(rebalances);
+            }
+
+            for payment in response.payments {
+                if payment.status() != PaymentStatus::Succeeded {
+                    continue;
+                }
+
+                // A payment to ourselves whose successful attempts all ended at our node
+                let succeeded = payment
+                    .htlcs
+                    .iter()
+                    .filter(|htlc| htlc.status() == HtlcStatus::Succeeded)
+                    .collect::<Vec<_>>();
+                let is_rebalance = !succeeded.is_empty()
+                    && succeeded.iter().all(|htlc| {
+                        htlc.route
+                            .as_ref()
+                            .and_then(|route| route.hops.last())
+                            .is_some_and(|hop| hop.pub_key == own_pubkey)
+                    });
+                if !is_rebalance {
+                    continue;
+                }
+
+                let completed_at_ns = succeeded
+                    .iter()
+                    .map(|htlc| htlc.resolve_time_ns)
+                    .max()
+                    .unwrap_or(payment.creation_time_ns);
+                let timestamp_secs = u64::try_from(completed_at_ns).unwrap_or(0) / 1_000_000_000;
+                if timestamp_secs < since_secs {
+                    continue;
+                }
+
+                rebalances.push(RebalanceEvent {
+                    timestamp_secs,
+                    amount: Amount::from_msats(payment.value_msat.try_into().unwrap_or(0)),
+                    fee: Amount::from_msats(payment.fee_msat.try_into().unwrap_or(0)),
+                });
+            }
+            index_offset = response.last_index_offset;
+        }
+    }
+}
+
+/// Returns the outbound and inbound liquidity of a channel, excluding the
+/// channel reserves
+/// Commitment type of LND's taproot asset channels. `tonic_lnd` predates it, so
+/// it is not part of its `CommitmentType`.
+const SIMPLE_TAPROOT_OVERLAY_COMMITMENT_TYPE: i32 = 6;
+
+fn channel_type(commitment_type: i32) -> ChannelType {
+    if commitment_type == SIMPLE_TAPROOT_OVERLAY_COMMITMENT_TYPE {
+        ChannelType::TaprootOverlay
+    } else {
+        ChannelType::Bitcoin
+    }
+}
+
+fn channel_liquidity_sats(channel: &tonic_lnd::lnrpc::Channel) -> (u64, u64) {
+    let local_balance_sats: u64 = channel.local_balance.try_into().expect("i64 -> u64");
+    let local_channel_reserve_sats = channel
+        .local_constraints
+        .as_ref()
+        .map_or(0, |constraints| constraints.chan_reserve_sat);
+
+    let remote_balance_sats: u64 = channel.remote_balance.try_into().expect("i64 -> u64");
+    let remote_channel_reserve_sats = channel
+        .remote_constraints
+        .as_ref()
+        .map_or(0, |constraints| constraints.chan_reserve_sat);
+
+    (
+        local_balance_sats.saturating_sub(local_channel_reserve_sats),
+        remote_balance_sats.saturating_sub(remote_channel_reserve_sats),
+    )
+}
+
+fn parse_channel_point(channel_point: &str) -> Result<bitcoin::OutPoint, LightningRpcError> {
+    bitcoin::OutPoint::from_str(channel_point).map_err(|e| {
+        LightningRpcError::FailedToListChannels {
+            failure_reason: format!("Failed to parse channel point {e:?}"),
+        }
+    })
+}
+
+fn lnd_channel_point(outpoint: bitcoin::OutPoint) -> ChannelPoint {
+    ChannelPoint {
+        funding_txid: Some(
+            tonic_lnd::lnrpc::channel_point::FundingTxid::FundingTxidBytes(
+                <bitcoin::Txid as AsRef<[u8]>>::as_ref(&outpoint.txid)
+                    .as_ref()
+                    .to_vec(),
+            ),
+        ),
+        output_index: outpoint.vout,
+    }
 }
 
 fn route_hints_to_lnd(
@@ -876,6 +1439,54 @@ fn route_hints_to_lnd(
         .collect()
 }
 
+/// Sends the HTLC attempts of `payment` whose status changed since the last
+/// payment update to `updates`.
+fn report_htlc_attempts(
+    payment: &tonic_lnd::lnrpc::Payment,
+    reported_attempts: &mut BTreeMap<u64, HtlcStatus>,
+    updates: &PaymentUpdateSender,
+) {
+    for htlc in &payment.htlcs {
+        let status = htlc.status();
+        if reported_attempts.insert(htlc.attempt_id, status) == Some(status) {
+            continue;
+        }
+
+        let _ = updates.send(htlc_attempt_update(htlc));
+    }
+}
+
+fn htlc_attempt_update(htlc: &tonic_lnd::lnrpc::HtlcAttempt) -> PaymentAttemptUpdate {
+    let (total_amt_msat, total_fees_msat, num_hops) =
+        htlc.route.as_ref().map_or((0, 0, 0), |route| {
+            (
+                route.total_amt_msat,
+                route.total_fees_msat,
+                route.hops.len(),
+            )
+        });
+    let fee_msat = u64::try_from(total_fees_msat).unwrap_or_default();
+    let amount_msat = u64::try_from(total_amt_msat)
+        .unwrap_or_default()
+        .saturating_sub(fee_msat);
+
+    PaymentAttemptUpdate {
+        attempt_id: htlc.attempt_id,
+        status: match htlc.status() {
+            HtlcStatus::InFlight => PaymentAttemptStatus::InFlight,
+            HtlcStatus::Succeeded => PaymentAttemptStatus::Succeeded,
+            HtlcStatus::Failed => PaymentAttemptStatus::Failed,
+        },
+        amount: Amount::from_msats(amount_msat),
+        fee: Amount::from_msats(fee_msat),
+        num_hops: num_hops as u32,
+        failure_reason: htlc
+            .failure
+            .as_ref()
+            .map(|failure| format!("{:?}", failure.code())),
+    }
+}
+
 fn wire_features_to_lnd_feature_vec(features_wire_encoded: &[u8]) -> anyhow::Result<Vec<i32>> {
     ensure!(
         features_wire_encoded.len() <= 1_000,
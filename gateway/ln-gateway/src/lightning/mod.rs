@@ -1,32 +1,105 @@
 pub mod cln;
+pub mod cln_grpc;
 pub mod lnd;
+pub mod router;
 
 use std::fmt::Debug;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use bitcoin_hashes::sha256;
 use clap::Subcommand;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{secp256k1, Amount};
-use fedimint_ln_common::PrunedInvoice;
+use fedimint_ln_common::{KeysendPayment, PrunedInvoice};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tokio::sync::mpsc;
 
 use self::cln::{NetworkLnRpcClient, RouteHtlcStream};
+use self::cln_grpc::GatewayClnGrpcClient;
 use self::lnd::GatewayLndClient;
+use self::router::LightningRouter;
 use crate::envs::{
-    FM_GATEWAY_LIGHTNING_ADDR_ENV, FM_LND_MACAROON_ENV, FM_LND_RPC_ADDR_ENV, FM_LND_TLS_CERT_ENV,
+    FM_CLN_GRPC_ADDR_ENV, FM_CLN_GRPC_CERT_DIR_ENV, FM_GATEWAY_LIGHTNING_ADDR_ENV,
+    FM_LND_MACAROON_ENV, FM_LND_MAX_PARTS_ENV, FM_LND_MAX_SHARD_MSAT_ENV, FM_LND_RPC_ADDR_ENV,
+    FM_LND_TLS_CERT_ENV,
 };
+use crate::gateway_lnrpc::htlc_attempt::Status as HtlcAttemptStatus;
 use crate::gateway_lnrpc::{
     CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
-    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
-    PayInvoiceRequest, PayInvoiceResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, HtlcAttempt,
+    InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
 };
 
 pub const MAX_LIGHTNING_RETRIES: u32 = 10;
 
+/// Channel the lightning node reports HTLC attempts of an outgoing payment to
+/// while the payment is in flight. Sending fails once the receiver is dropped,
+/// which backends ignore since the updates are purely informational.
+pub type PaymentUpdateSender = mpsc::UnboundedSender<PaymentAttemptUpdate>;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentAttemptStatus {
+    InFlight,
+    Succeeded,
+    Failed,
+}
+
+/// State of a single HTLC attempt the lightning node made while paying an
+/// invoice. Updates for the same attempt share the `attempt_id`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PaymentAttemptUpdate {
+    pub attempt_id: u64,
+    pub status: PaymentAttemptStatus,
+    pub amount: Amount,
+    pub fee: Amount,
+    pub num_hops: u32,
+    pub failure_reason: Option<String>,
+}
+
+impl From<HtlcAttempt> for PaymentAttemptUpdate {
+    fn from(attempt: HtlcAttempt) -> Self {
+        let status = match attempt.status() {
+            HtlcAttemptStatus::InFlight => PaymentAttemptStatus::InFlight,
+            HtlcAttemptStatus::Succeeded => PaymentAttemptStatus::Succeeded,
+            HtlcAttemptStatus::Failed => PaymentAttemptStatus::Failed,
+        };
+
+        PaymentAttemptUpdate {
+            attempt_id: attempt.attempt_id,
+            status,
+            amount: Amount::from_msats(attempt.amount_msat),
+            fee: Amount::from_msats(attempt.fee_msat),
+            num_hops: attempt.num_hops,
+            failure_reason: attempt.failure_reason,
+        }
+    }
+}
+
+impl From<PaymentAttemptUpdate> for HtlcAttempt {
+    fn from(update: PaymentAttemptUpdate) -> Self {
+        let status = match update.status {
+            PaymentAttemptStatus::InFlight => HtlcAttemptStatus::InFlight,
+            PaymentAttemptStatus::Succeeded => HtlcAttemptStatus::Succeeded,
+            PaymentAttemptStatus::Failed => HtlcAttemptStatus::Failed,
+        };
+
+        HtlcAttempt {
+            attempt_id: update.attempt_id,
+            status: status.into(),
+            amount_msat: update.amount.msats,
+            fee_msat: update.fee.msats,
+            num_hops: update.num_hops,
+            failure_reason: update.failure_reason,
+        }
+    }
+}
+
 #[derive(
     Error, Debug, Serialize, Deserialize, Encodable, Decodable, Clone, Eq, PartialEq, Hash,
 )]
@@ -39,6 +112,11 @@ pub enum LightningRpcError {
     FailedToGetRouteHints { failure_reason: String },
     #[error("Payment failed: {failure_reason}")]
     FailedPayment { failure_reason: String },
+    /// The lightning node reported that the payment failed and that none of
+    /// its HTLCs are in flight anymore, unlike [`Self::FailedPayment`] which
+    /// doesn't rule out that the payment still succeeds
+    #[error("Payment failed: {failure_reason}")]
+    FailedPaymentFinal { failure_reason: String },
     #[error("Failed to route HTLCs: {failure_reason}")]
     FailedToRouteHtlcs { failure_reason: String },
     #[error("Failed to complete HTLC: {failure_reason}")]
@@ -55,8 +133,49 @@ pub enum LightningRpcError {
     FailedToConnectToPeer { failure_reason: String },
     #[error("Failed to list active channels: {failure_reason}")]
     FailedToListActiveChannels { failure_reason: String },
+    #[error("Failed to list channels: {failure_reason}")]
+    FailedToListChannels { failure_reason: String },
+    #[error("Failed to close channel: {failure_reason}")]
+    FailedToCloseChannel { failure_reason: String },
     #[error("Failed to wait for chain sync: {failure_reason}")]
     FailedToWaitForChainSync { failure_reason: String },
+    #[error("Failed to settle hold invoice: {failure_reason}")]
+    FailedToSettleHoldInvoice { failure_reason: String },
+    #[error("Failed to cancel hold invoice: {failure_reason}")]
+    FailedToCancelHoldInvoice { failure_reason: String },
+    #[error("Failed to list forwards: {failure_reason}")]
+    FailedToListForwards { failure_reason: String },
+    #[error("Failed to list rebalances: {failure_reason}")]
+    FailedToListRebalances { failure_reason: String },
+}
+
+/// A payment the lightning node forwarded from one of its channels to another
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForwardingEvent {
+    /// Unix time the forward was settled at
+    pub timestamp_secs: u64,
+    pub incoming_short_channel_id: u64,
+    pub outgoing_short_channel_id: u64,
+    pub amount_in: Amount,
+    pub amount_out: Amount,
+}
+
+impl ForwardingEvent {
+    /// Fee the lightning node earned for the forward
+    pub fn fee(&self) -> Amount {
+        self.amount_in.saturating_sub(self.amount_out)
+    }
+}
+
+/// A payment the lightning node made to itself to move liquidity between its
+/// channels
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RebalanceEvent {
+    /// Unix time the rebalance completed at
+    pub timestamp_secs: u64,
+    pub amount: Amount,
+    /// Routing fees the lightning node paid for the rebalance
+    pub fee: Amount,
 }
 
 /// A trait that the gateway uses to interact with a lightning node. This allows
@@ -79,6 +198,17 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         invoice: PayInvoiceRequest,
     ) -> Result<PayInvoiceResponse, LightningRpcError>;
 
+    /// Like [`ILnRpcClient::pay`], but reports the HTLC attempts made by the
+    /// lightning node to `updates` while the payment is in flight. Backends
+    /// that can't observe individual attempts just pay the invoice.
+    async fn pay_with_updates(
+        &self,
+        invoice: PayInvoiceRequest,
+        _updates: PaymentUpdateSender,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.pay(invoice).await
+    }
+
     /// Attempt to pay an invoice using the lightning node using a
     /// [`PrunedInvoice`], increasing the user's privacy by not sending the
     /// invoice description to the gateway.
@@ -88,11 +218,23 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         _max_delay: u64,
         _max_fee: Amount,
     ) -> Result<PayInvoiceResponse, LightningRpcError> {
-        Err(LightningRpcError::FailedPayment {
+        Err(LightningRpcError::FailedPaymentFinal {
             failure_reason: "Private payments not supported".to_string(),
         })
     }
 
+    /// Like [`ILnRpcClient::pay_private`], but reports the HTLC attempts made
+    /// by the lightning node to `updates` while the payment is in flight.
+    async fn pay_private_with_updates(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee: Amount,
+        _updates: PaymentUpdateSender,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.pay_private(invoice, max_delay, max_fee).await
+    }
+
     /// Returns true if the lightning backend supports payments without full
     /// invoices. If this returns true, then [`ILnRpcClient::pay_private`] has
     /// to be implemented.
@@ -100,6 +242,28 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         false
     }
 
+    /// Attempt to send a spontaneous payment to `payment.destination` without
+    /// an invoice. The payment has to be locked to the hash of
+    /// `payment.preimage`, backends that always choose the preimage themselves
+    /// must not support keysend payments.
+    async fn pay_keysend(
+        &self,
+        _payment: KeysendPayment,
+        _max_delay: u64,
+        _max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedPaymentFinal {
+            failure_reason: "Keysend payments not supported".to_string(),
+        })
+    }
+
+    /// Returns true if the lightning backend supports keysend payments. If
+    /// this returns true, then [`ILnRpcClient::pay_keysend`] has to be
+    /// implemented.
+    fn supports_keysend(&self) -> bool {
+        false
+    }
+
     /// Consumes the current client and returns a stream of intercepted HTLCs
     /// and a new client. `complete_htlc` must be called for all successfully
     /// intercepted HTLCs sent to the returned stream.
@@ -128,6 +292,50 @@ pub trait ILnRpcClient: Debug + Send + Sync {
         create_invoice_request: CreateInvoiceRequest,
     ) -> Result<CreateInvoiceResponse, LightningRpcError>;
 
+    /// Create a hold invoice for `create_invoice_request.payment_hash`. The
+    /// HTLCs paying the invoice are sent to the stream returned by
+    /// `route_htlcs` and held by the lightning node until
+    /// [`ILnRpcClient::settle_hold_invoice`] or
+    /// [`ILnRpcClient::cancel_hold_invoice`] is called for the invoice.
+    async fn create_hold_invoice(
+        &self,
+        _create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToGetInvoice {
+            failure_reason: "Hold invoices not supported".to_string(),
+        })
+    }
+
+    /// Settle the HTLCs held for the hold invoice paying to the hash of
+    /// `preimage`
+    async fn settle_hold_invoice(
+        &self,
+        _preimage: [u8; 32],
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToSettleHoldInvoice {
+            failure_reason: "Hold invoices not supported".to_string(),
+        })
+    }
+
+    /// Fail the HTLCs held for the hold invoice with `payment_hash`, the
+    /// invoice can't be paid afterwards
+    async fn cancel_hold_invoice(
+        &self,
+        _payment_hash: sha256::Hash,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCancelHoldInvoice {
+            failure_reason: "Hold invoices not supported".to_string(),
+        })
+    }
+
+    /// Returns true if the lightning backend supports hold invoices. If this
+    /// returns true, then [`ILnRpcClient::create_hold_invoice`],
+    /// [`ILnRpcClient::settle_hold_invoice`] and
+    /// [`ILnRpcClient::cancel_hold_invoice`] have to be implemented.
+    fn supports_hold_invoices(&self) -> bool {
+        false
+    }
+
     /// Connect to a peer lightning node from the gateway's lightning node.
     async fn connect_to_peer(
         &self,
@@ -156,6 +364,50 @@ pub trait ILnRpcClient: Debug + Send + Sync {
     ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError>;
 
     async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError>;
+
+    /// List all channels of the gateway's lightning node, including channels
+    /// that are still being opened and channels that are being closed but whose
+    /// funds are not swept yet.
+    async fn list_channels(&self) -> Result<Vec<ChannelDetails>, LightningRpcError> {
+        Err(LightningRpcError::FailedToListChannels {
+            failure_reason: "Listing channels not supported".to_string(),
+        })
+    }
+
+    /// Close the channel funded by the outpoint `channel_id`. The channel is
+    /// closed cooperatively unless `force` is set, in which case the latest
+    /// commitment transaction is broadcast.
+    async fn close_channel(
+        &self,
+        _channel_id: bitcoin::OutPoint,
+        _force: bool,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        Err(LightningRpcError::FailedToCloseChannel {
+            failure_reason: "Closing individual channels not supported".to_string(),
+        })
+    }
+
+    /// List the forwards the lightning node settled at or after the unix time
+    /// `since_secs`
+    async fn list_forwards(
+        &self,
+        _since_secs: u64,
+    ) -> Result<Vec<ForwardingEvent>, LightningRpcError> {
+        Err(LightningRpcError::FailedToListForwards {
+            failure_reason: "Listing forwards not supported".to_string(),
+        })
+    }
+
+    /// List the payments the lightning node made to itself that completed at
+    /// or after the unix time `since_secs`
+    async fn list_rebalances(
+        &self,
+        _since_secs: u64,
+    ) -> Result<Vec<RebalanceEvent>, LightningRpcError> {
+        Err(LightningRpcError::FailedToListRebalances {
+            failure_reason: "Listing rebalances not supported".to_string(),
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -165,9 +417,77 @@ pub struct ChannelInfo {
     pub outbound_liquidity_sats: u64,
     pub inbound_liquidity_sats: u64,
     pub short_channel_id: u64,
+    #[serde(default)]
+    pub channel_type: ChannelType,
+}
+
+/// What the liquidity of a channel is denominated in
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelType {
+    /// Plain bitcoin channel
+    #[default]
+    Bitcoin,
+    /// Channel carrying taproot assets on top of a taproot channel (LND's
+    /// simple taproot overlay). Its liquidity is not bitcoin, so it can't route
+    /// the payments of the gateway.
+    TaprootOverlay,
+}
+
+impl ChannelType {
+    /// Whether the liquidity of the channel can route bitcoin payments
+    pub fn is_routable(self) -> bool {
+        self == ChannelType::Bitcoin
+    }
+}
+
+/// Sums the outbound and inbound liquidity of the channels that can route
+/// bitcoin payments, in sats
+pub fn routable_liquidity_sats(channels: &[ChannelInfo]) -> (u64, u64) {
+    channels
+        .iter()
+        .filter(|channel| channel.channel_type.is_routable())
+        .fold((0, 0), |(outbound, inbound), channel| {
+            (
+                outbound + channel.outbound_liquidity_sats,
+                inbound + channel.inbound_liquidity_sats,
+            )
+        })
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelState {
+    /// The funding transaction is not confirmed yet
+    Opening,
+    /// The channel can send and receive payments
+    Active,
+    /// The channel is open, but the peer is offline
+    Inactive,
+    /// The channel is being closed, its funds are not swept to the on-chain
+    /// wallet yet
+    Closing,
+}
+
+/// A channel of the gateway's lightning node in any [`ChannelState`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelDetails {
+    /// Funding outpoint of the channel, which identifies it when closing it
+    pub channel_id: bitcoin::OutPoint,
+    /// `None` while the funding transaction is not confirmed
+    pub short_channel_id: Option<u64>,
+    pub remote_pubkey: String,
+    pub state: ChannelState,
+    pub channel_size_sats: u64,
+    pub outbound_liquidity_sats: u64,
+    pub inbound_liquidity_sats: u64,
+    /// Transaction closing the channel, once it is known to the node
+    pub closing_txid: Option<bitcoin::Txid>,
+    #[serde(default)]
+    pub channel_type: ChannelType,
 }
 
-#[derive(Debug, Clone, Subcommand, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand, Serialize, Deserialize)]
 pub enum LightningMode {
     #[clap(name = "lnd")]
     Lnd {
@@ -182,41 +502,116 @@ pub enum LightningMode {
         /// LND macaroon file path
         #[arg(long = "lnd-macaroon", env = FM_LND_MACAROON_ENV)]
         lnd_macaroon: String,
+
+        /// Maximum number of parts outgoing payments are split into, LND's
+        /// default of 16 is used if not set
+        #[arg(long = "lnd-max-parts", env = FM_LND_MAX_PARTS_ENV)]
+        #[serde(default)]
+        lnd_max_parts: Option<u32>,
+
+        /// Maximum amount of a single part of an outgoing payment in
+        /// millisats, parts are not limited if not set
+        #[arg(long = "lnd-max-shard-msat", env = FM_LND_MAX_SHARD_MSAT_ENV)]
+        #[serde(default)]
+        lnd_max_shard_msat: Option<u64>,
     },
     #[clap(name = "cln")]
     Cln {
         #[arg(long = "cln-extension-addr", env = FM_GATEWAY_LIGHTNING_ADDR_ENV)]
         cln_extension_addr: SafeUrl,
     },
+    /// Talks to the gRPC interface built into CLN instead of the CLN
+    /// extension. HTLCs can't be intercepted this way, so the gateway can only
+    /// pay invoices on behalf of federation clients.
+    #[clap(name = "cln-grpc")]
+    ClnGrpc {
+        /// CLN gRPC address
+        #[arg(long = "cln-grpc-addr", env = FM_CLN_GRPC_ADDR_ENV)]
+        cln_grpc_addr: SafeUrl,
+
+        /// Directory containing the `ca.pem`, `client.pem` and
+        /// `client-key.pem` files of CLN's gRPC interface, usually the
+        /// network directory of the node
+        #[arg(long = "cln-grpc-cert-dir", env = FM_CLN_GRPC_CERT_DIR_ENV)]
+        cln_grpc_cert_dir: PathBuf,
+    },
+}
+
+impl LightningMode {
+    /// Parses a JSON list of additional lightning nodes, e.g.
+    /// `[{"Cln":{"cln_extension_addr":"http://127.0.0.1:10009"}}]`
+    pub fn parse_list(s: &str) -> anyhow::Result<Vec<LightningMode>> {
+        Ok(serde_json::from_str(s)?)
+    }
 }
 
 #[async_trait]
 pub trait LightningBuilder {
     async fn build(&self) -> Box<dyn ILnRpcClient>;
+
+    /// Builds the lightning client and starts intercepting HTLCs, see
+    /// [`ILnRpcClient::route_htlcs`].
+    async fn build_and_route_htlcs<'a>(
+        &self,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        self.build().await.route_htlcs(task_group).await
+    }
 }
 
 #[derive(Clone)]
 pub struct GatewayLightningBuilder {
     pub lightning_mode: LightningMode,
+    /// Further lightning nodes the gateway routes payments through. If not
+    /// empty, the gateway uses a [`LightningRouter`] with `lightning_mode` as
+    /// the primary node.
+    pub additional_lightning_modes: Vec<LightningMode>,
 }
 
-#[async_trait]
-impl LightningBuilder for GatewayLightningBuilder {
-    async fn build(&self) -> Box<dyn ILnRpcClient> {
-        match self.lightning_mode.clone() {
+impl GatewayLightningBuilder {
+    fn build_mode(lightning_mode: LightningMode) -> Box<dyn ILnRpcClient> {
+        match lightning_mode {
             LightningMode::Cln { cln_extension_addr } => {
                 Box::new(NetworkLnRpcClient::new(cln_extension_addr))
             }
+            LightningMode::ClnGrpc {
+                cln_grpc_addr,
+                cln_grpc_cert_dir,
+            } => Box::new(GatewayClnGrpcClient::new(cln_grpc_addr, cln_grpc_cert_dir)),
             LightningMode::Lnd {
                 lnd_rpc_addr,
                 lnd_tls_cert,
                 lnd_macaroon,
-            } => Box::new(GatewayLndClient::new(
-                lnd_rpc_addr,
-                lnd_tls_cert,
-                lnd_macaroon,
-                None,
-            )),
+                lnd_max_parts,
+                lnd_max_shard_msat,
+            } => Box::new(
+                GatewayLndClient::new(lnd_rpc_addr, lnd_tls_cert, lnd_macaroon, None)
+                    .with_mpp_limits(lnd_max_parts, lnd_max_shard_msat.map(Amount::from_msats)),
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl LightningBuilder for GatewayLightningBuilder {
+    /// Builds a client for the primary lightning node
+    async fn build(&self) -> Box<dyn ILnRpcClient> {
+        Self::build_mode(self.lightning_mode.clone())
+    }
+
+    async fn build_and_route_htlcs<'a>(
+        &self,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        if self.additional_lightning_modes.is_empty() {
+            return self.build().await.route_htlcs(task_group).await;
         }
+
+        let nodes = std::iter::once(&self.lightning_mode)
+            .chain(self.additional_lightning_modes.iter())
+            .cloned()
+            .map(Self::build_mode)
+            .collect();
+        LightningRouter::route_htlcs_all(nodes, task_group).await
     }
 }
@@ -0,0 +1,844 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bitcoin_hashes::sha256;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::{secp256k1, Amount};
+use fedimint_ln_common::{KeysendPayment, PrunedInvoice};
+use futures::StreamExt;
+use lightning_invoice::Bolt11Invoice;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use super::cln::RouteHtlcStream;
+use super::{
+    routable_liquidity_sats, ChannelDetails, ChannelInfo, ForwardingEvent, ILnRpcClient,
+    LightningRpcError, PaymentUpdateSender, RebalanceEvent,
+};
+use crate::gateway_lnrpc::{
+    CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+    GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse, InterceptHtlcResponse,
+    PayInvoiceRequest, PayInvoiceResponse,
+};
+
+/// Identifies an intercepted HTLC by its incoming channel and HTLC index, which
+/// is what `complete_htlc` uses to address it.
+type HtlcId = (u64, u64);
+
+/// A lightning backend managed by the [`LightningRouter`] together with simple
+/// health statistics used for node selection.
+#[derive(Debug)]
+struct RoutedNode {
+    lnrpc: Arc<dyn ILnRpcClient>,
+    /// Number of RPC failures since the last successful call. Nodes with fewer
+    /// recent failures are preferred when paying.
+    consecutive_failures: AtomicU64,
+}
+
+impl RoutedNode {
+    fn record<T>(&self, result: &Result<T, LightningRpcError>) {
+        if result.is_ok() {
+            self.consecutive_failures.store(0, Ordering::Relaxed);
+        } else {
+            self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// An `ILnRpcClient` that multiplexes several lightning backends behind a
+/// single gateway.
+///
+/// The first node is the *primary* node: its public key is the gateway's
+/// lightning identity, so node info, route hints, invoice creation and channel
+/// management are served by it. Outgoing payments are routed through whichever
+/// node currently has the most outbound liquidity, preferring nodes that
+/// recently responded successfully. Intercepted HTLCs from all nodes are merged
+/// into a single stream and `complete_htlc` is sent back to the node the HTLC
+/// was intercepted on.
+#[derive(Debug)]
+pub struct LightningRouter {
+    nodes: Vec<RoutedNode>,
+    /// Maps intercepted HTLCs to the index of the node that intercepted them
+    htlc_origins: Arc<Mutex<HashMap<HtlcId, usize>>>,
+}
+
+impl LightningRouter {
+    fn new(
+        nodes: Vec<Arc<dyn ILnRpcClient>>,
+        htlc_origins: Arc<Mutex<HashMap<HtlcId, usize>>>,
+    ) -> Self {
+        assert!(!nodes.is_empty(), "LightningRouter needs at least one node");
+        Self {
+            nodes: nodes
+                .into_iter()
+                .map(|lnrpc| RoutedNode {
+                    lnrpc,
+                    consecutive_failures: AtomicU64::new(0),
+                })
+                .collect(),
+            htlc_origins,
+        }
+    }
+
+    fn primary(&self) -> &RoutedNode {
+        &self.nodes[0]
+    }
+
+    /// Returns the indices of the nodes in the order they should be tried for
+    /// a payment of `amount_msat`: healthy nodes with enough outbound
+    /// liquidity first, ordered by recent failures and then by liquidity.
+    /// Nodes that don't `support` the kind of payment are skipped, so they
+    /// can't abort the payment before a supporting node is tried.
+    async fn payment_candidates(
+        &self,
+        amount_msat: u64,
+        supports: impl Fn(&dyn ILnRpcClient) -> bool,
+    ) -> Vec<usize> {
+        let mut candidates = Vec::with_capacity(self.nodes.len());
+        for (idx, node) in self.nodes.iter().enumerate() {
+            if !supports(node.lnrpc.as_ref()) {
+                continue;
+            }
+
+            let channels = node.lnrpc.list_active_channels().await;
+            node.record(&channels);
+            let Ok(channels) = channels else {
+                debug!(
+                    node = idx,
+                    "Skipping unreachable lightning node for payment"
+                );
+                continue;
+            };
+
+            // Asset channels can't route the payment, so they don't count
+            let outbound_msat = routable_liquidity_sats(&channels).0 * 1000;
+            if outbound_msat < amount_msat {
+                continue;
+            }
+
+            let failures = node.consecutive_failures.load(Ordering::Relaxed);
+            candidates.push((failures, std::cmp::Reverse(outbound_msat), idx));
+        }
+
+        candidates.sort_unstable();
+        candidates.into_iter().map(|(_, _, idx)| idx).collect()
+    }
+
+    /// Pays through the best candidate node among those that `support` the
+    /// payment, falling back to the next one only if the payment definitely
+    /// failed, see [`try_nodes_in_order`].
+    async fn pay_with_failover<F, Fut>(
+        &self,
+        amount_msat: u64,
+        supports: impl Fn(&dyn ILnRpcClient) -> bool,
+        pay: F,
+    ) -> Result<PayInvoiceResponse, LightningRpcError>
+    where
+        F: Fn(Arc<dyn ILnRpcClient>) -> Fut,
+        Fut: std::future::Future<Output = Result<PayInvoiceResponse, LightningRpcError>>,
+    {
+        let candidates = self.payment_candidates(amount_msat, supports).await;
+        if candidates.is_empty() {
+            return Err(LightningRpcError::FailedPaymentFinal {
+                failure_reason:
+                    "No lightning node supporting the payment has enough outbound liquidity"
+                        .to_string(),
+            });
+        }
+
+        try_nodes_in_order(candidates, |idx| {
+            let node = &self.nodes[idx];
+            let payment = pay(node.lnrpc.clone());
+            async move {
+                let result = payment.await;
+                node.record(&result);
+                result
+            }
+        })
+        .await
+    }
+}
+
+/// Attempts a payment through the nodes in the order of `candidates` until it
+/// succeeds. The next node is only tried if the lightning node reported that
+/// the payment failed without any HTLCs in flight. Any other error, like a
+/// timeout or a lost connection, is returned right away, since the payment may
+/// still succeed and paying it through another node could pay it twice.
+async fn try_nodes_in_order<T, F, Fut>(
+    candidates: Vec<usize>,
+    attempt: F,
+) -> Result<T, LightningRpcError>
+where
+    F: Fn(usize) -> Fut,
+    Fut: std::future::Future<Output = Result<T, LightningRpcError>>,
+{
+    let mut last_error = None;
+    for idx in candidates {
+        match attempt(idx).await {
+            Ok(response) => return Ok(response),
+            Err(error @ LightningRpcError::FailedPaymentFinal { .. }) => {
+                warn!(
+                    node = idx,
+                    ?error,
+                    "Payment failed, trying next lightning node"
+                );
+                last_error = Some(error);
+            }
+            Err(error) => {
+                warn!(
+                    node = idx,
+                    ?error,
+                    "Payment outcome is unknown, not trying other lightning nodes"
+                );
+                return Err(error);
+            }
+        }
+    }
+
+    Err(last_error.expect("At least one candidate was tried"))
+}
+
+#[async_trait]
+impl ILnRpcClient for LightningRouter {
+    async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+        let result = self.primary().lnrpc.info().await;
+        self.primary().record(&result);
+        result
+    }
+
+    async fn routehints(
+        &self,
+        num_route_hints: usize,
+    ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+        self.primary().lnrpc.routehints(num_route_hints).await
+    }
+
+    async fn pay(
+        &self,
+        invoice: PayInvoiceRequest,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.pay_with_updates(invoice, mpsc::unbounded_channel().0)
+            .await
+    }
+
+    async fn pay_with_updates(
+        &self,
+        invoice: PayInvoiceRequest,
+        updates: PaymentUpdateSender,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let amount_msat = Bolt11Invoice::from_str(&invoice.invoice)
+            .ok()
+            .and_then(|invoice| invoice.amount_milli_satoshis())
+            .unwrap_or_default();
+
+        self.pay_with_failover(
+            amount_msat,
+            |_| true,
+            |lnrpc| {
+                let invoice = invoice.clone();
+                let updates = updates.clone();
+                async move { lnrpc.pay_with_updates(invoice, updates).await }
+            },
+        )
+        .await
+    }
+
+    async fn pay_private(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        self.pay_private_with_updates(invoice, max_delay, max_fee, mpsc::unbounded_channel().0)
+            .await
+    }
+
+    async fn pay_private_with_updates(
+        &self,
+        invoice: PrunedInvoice,
+        max_delay: u64,
+        max_fee: Amount,
+        updates: PaymentUpdateSender,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let amount_msat = invoice.amount.msats;
+        self.pay_with_failover(
+            amount_msat,
+            |lnrpc| lnrpc.supports_private_payments(),
+            |lnrpc| {
+                let invoice = invoice.clone();
+                let updates = updates.clone();
+                async move {
+                    lnrpc
+                        .pay_private_with_updates(invoice, max_delay, max_fee, updates)
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    fn supports_private_payments(&self) -> bool {
+        self.nodes
+            .iter()
+            .any(|node| node.lnrpc.supports_private_payments())
+    }
+
+    async fn pay_keysend(
+        &self,
+        payment: KeysendPayment,
+        max_delay: u64,
+        max_fee: Amount,
+    ) -> Result<PayInvoiceResponse, LightningRpcError> {
+        let amount_msat = payment.amount.msats;
+        self.pay_with_failover(
+            amount_msat,
+            |lnrpc| lnrpc.supports_keysend(),
+            |lnrpc| {
+                let payment = payment.clone();
+                async move { lnrpc.pay_keysend(payment, max_delay, max_fee).await }
+            },
+        )
+        .await
+    }
+
+    fn supports_keysend(&self) -> bool {
+        self.nodes.iter().any(|node| node.lnrpc.supports_keysend())
+    }
+
+    async fn route_htlcs<'a>(
+        self: Box<Self>,
+        _task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        // The router is only ever constructed from clients that are already
+        // routing HTLCs, see `LightningRouter::route_htlcs_all`.
+        Err(LightningRpcError::FailedToRouteHtlcs {
+            failure_reason: "LightningRouter is already routing HTLCs".to_string(),
+        })
+    }
+
+    async fn complete_htlc(
+        &self,
+        htlc: InterceptHtlcResponse,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let htlc_key = (htlc.incoming_chan_id, htlc.htlc_id);
+        let origin = self
+            .htlc_origins
+            .lock()
+            .expect("poisoned")
+            .get(&htlc_key)
+            .copied();
+
+        let Some(idx) = origin else {
+            return Err(LightningRpcError::FailedToCompleteHtlc {
+                failure_reason: format!(
+                    "Unknown HTLC {} on channel {}",
+                    htlc.htlc_id, htlc.incoming_chan_id
+                ),
+            });
+        };
+
+        let node = &self.nodes[idx];
+        let result = node.lnrpc.complete_htlc(htlc).await;
+        node.record(&result);
+
+        // The completion is retried after transient errors, which requires the origin
+        if result.is_ok() {
+            self.htlc_origins
+                .lock()
+                .expect("poisoned")
+                .remove(&htlc_key);
+        }
+
+        result
+    }
+
+    async fn create_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        self.primary()
+            .lnrpc
+            .create_invoice(create_invoice_request)
+            .await
+    }
+
+    async fn create_hold_invoice(
+        &self,
+        create_invoice_request: CreateInvoiceRequest,
+    ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+        self.primary()
+            .lnrpc
+            .create_hold_invoice(create_invoice_request)
+            .await
+    }
+
+    async fn settle_hold_invoice(
+        &self,
+        preimage: [u8; 32],
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        // Hold invoices are always created on the primary node
+        let node = self.primary();
+        let result = node.lnrpc.settle_hold_invoice(preimage).await;
+        node.record(&result);
+        result
+    }
+
+    async fn cancel_hold_invoice(
+        &self,
+        payment_hash: sha256::Hash,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        let node = self.primary();
+        let result = node.lnrpc.cancel_hold_invoice(payment_hash).await;
+        node.record(&result);
+        result
+    }
+
+    fn supports_hold_invoices(&self) -> bool {
+        self.primary().lnrpc.supports_hold_invoices()
+    }
+
+    async fn connect_to_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        host: String,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.primary().lnrpc.connect_to_peer(pubkey, host).await
+    }
+
+    async fn get_funding_address(&self) -> Result<GetFundingAddressResponse, LightningRpcError> {
+        self.primary().lnrpc.get_funding_address().await
+    }
+
+    async fn open_channel(
+        &self,
+        pubkey: secp256k1::PublicKey,
+        channel_size_sats: u64,
+        push_amount_sats: u64,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        self.primary()
+            .lnrpc
+            .open_channel(pubkey, channel_size_sats, push_amount_sats)
+            .await
+    }
+
+    async fn close_channels_with_peer(
+        &self,
+        pubkey: secp256k1::PublicKey,
+    ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+        self.primary().lnrpc.close_channels_with_peer(pubkey).await
+    }
+
+    async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+        let mut channels = Vec::new();
+        for node in &self.nodes {
+            let result = node.lnrpc.list_active_channels().await;
+            node.record(&result);
+            match result {
+                Ok(node_channels) => channels.extend(node_channels),
+                Err(error) => warn!(?error, "Failed to list channels of lightning node"),
+            }
+        }
+        Ok(channels)
+    }
+
+    async fn list_channels(&self) -> Result<Vec<ChannelDetails>, LightningRpcError> {
+        let mut channels = Vec::new();
+        for node in &self.nodes {
+            let result = node.lnrpc.list_channels().await;
+            node.record(&result);
+            match result {
+                Ok(node_channels) => channels.extend(node_channels),
+                Err(error) => warn!(?error, "Failed to list channels of lightning node"),
+            }
+        }
+        Ok(channels)
+    }
+
+    /// Closes the channel on the node it belongs to
+    async fn close_channel(
+        &self,
+        channel_id: bitcoin::OutPoint,
+        force: bool,
+    ) -> Result<EmptyResponse, LightningRpcError> {
+        for node in &self.nodes {
+            let Ok(channels) = node.lnrpc.list_channels().await else {
+                continue;
+            };
+
+            if channels
+                .iter()
+                .any(|channel| channel.channel_id == channel_id)
+            {
+                return node.lnrpc.close_channel(channel_id, force).await;
+            }
+        }
+
+        Err(LightningRpcError::FailedToCloseChannel {
+            failure_reason: format!("Channel {channel_id} not found on any lightning node"),
+        })
+    }
+
+    /// Fails if any node fails, so the events of that node are not skipped
+    /// when the caller only asks for newer events afterwards
+    async fn list_forwards(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<ForwardingEvent>, LightningRpcError> {
+        let mut forwards = Vec::new();
+        for node in &self.nodes {
+            let result = node.lnrpc.list_forwards(since_secs).await;
+            node.record(&result);
+            forwards.extend(result?);
+        }
+        Ok(forwards)
+    }
+
+    /// Fails if any node fails, see [`Self::list_forwards`]
+    async fn list_rebalances(
+        &self,
+        since_secs: u64,
+    ) -> Result<Vec<RebalanceEvent>, LightningRpcError> {
+        let mut rebalances = Vec::new();
+        for node in &self.nodes {
+            let result = node.lnrpc.list_rebalances(since_secs).await;
+            node.record(&result);
+            rebalances.extend(result?);
+        }
+        Ok(rebalances)
+    }
+}
+
+impl LightningRouter {
+    /// Starts intercepting HTLCs on all `nodes` and returns a single stream
+    /// merging all of them, together with a router that sends HTLC
+    /// completions back to the intercepting node.
+    ///
+    /// Fails if any of the nodes fails to route HTLCs, so the gateway only
+    /// reaches the `Running` state once all configured backends are
+    /// connected.
+    pub async fn route_htlcs_all<'a>(
+        nodes: Vec<Box<dyn ILnRpcClient>>,
+        task_group: &mut TaskGroup,
+    ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+        let htlc_origins = Arc::new(Mutex::new(HashMap::new()));
+        let mut streams = Vec::with_capacity(nodes.len());
+        let mut routing_nodes = Vec::with_capacity(nodes.len());
+
+        for (idx, node) in nodes.into_iter().enumerate() {
+            let (stream, lnrpc) = node.route_htlcs(task_group).await?;
+            let htlc_origins = htlc_origins.clone();
+            streams.push(
+                stream
+                    .inspect(move |htlc| {
+                        if let Ok(htlc) = htlc {
+                            htlc_origins
+                                .lock()
+                                .expect("poisoned")
+                                .insert((htlc.incoming_chan_id, htlc.htlc_id), idx);
+                        }
+                    })
+                    .boxed(),
+            );
+            routing_nodes.push(lnrpc);
+        }
+
+        info!(
+            num_nodes = routing_nodes.len(),
+            "Routing HTLCs from all lightning nodes"
+        );
+
+        let router = LightningRouter::new(routing_nodes, htlc_origins);
+        Ok((
+            futures::stream::select_all(streams).boxed(),
+            Arc::new(router),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use fedimint_core::task::TaskGroup;
+    use fedimint_core::{secp256k1, Amount};
+    use fedimint_ln_common::contracts::Preimage;
+    use fedimint_ln_common::KeysendPayment;
+
+    use super::{try_nodes_in_order, LightningRouter};
+    use crate::gateway_lnrpc::{
+        CloseChannelsWithPeerResponse, CreateInvoiceRequest, CreateInvoiceResponse, EmptyResponse,
+        GetFundingAddressResponse, GetNodeInfoResponse, GetRouteHintsResponse,
+        InterceptHtlcResponse, PayInvoiceRequest, PayInvoiceResponse,
+    };
+    use crate::lightning::cln::RouteHtlcStream;
+    use crate::lightning::{ChannelInfo, ChannelType, ILnRpcClient, LightningRpcError};
+
+    /// Lightning node with a single channel of `outbound_liquidity_sats`
+    #[derive(Debug, Default)]
+    struct FakeNode {
+        keysend: bool,
+        outbound_liquidity_sats: u64,
+        /// Number of payments the node was asked to make
+        payments: AtomicU64,
+        /// Number of HTLC completions that fail before one succeeds
+        failing_completions: AtomicU64,
+    }
+
+    #[async_trait]
+    impl ILnRpcClient for FakeNode {
+        async fn info(&self) -> Result<GetNodeInfoResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn routehints(
+            &self,
+            _num_route_hints: usize,
+        ) -> Result<GetRouteHintsResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn pay(
+            &self,
+            _invoice: PayInvoiceRequest,
+        ) -> Result<PayInvoiceResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn pay_keysend(
+            &self,
+            _payment: KeysendPayment,
+            _max_delay: u64,
+            _max_fee: Amount,
+        ) -> Result<PayInvoiceResponse, LightningRpcError> {
+            self.payments.fetch_add(1, Ordering::Relaxed);
+            Ok(PayInvoiceResponse::default())
+        }
+
+        fn supports_keysend(&self) -> bool {
+            self.keysend
+        }
+
+        async fn route_htlcs<'a>(
+            self: Box<Self>,
+            _task_group: &mut TaskGroup,
+        ) -> Result<(RouteHtlcStream<'a>, Arc<dyn ILnRpcClient>), LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn complete_htlc(
+            &self,
+            _htlc: InterceptHtlcResponse,
+        ) -> Result<EmptyResponse, LightningRpcError> {
+            let failing = self.failing_completions.load(Ordering::Relaxed);
+            if 0 < failing {
+                self.failing_completions
+                    .store(failing - 1, Ordering::Relaxed);
+                return Err(LightningRpcError::FailedToCompleteHtlc {
+                    failure_reason: "Connection lost".to_string(),
+                });
+            }
+            Ok(EmptyResponse::default())
+        }
+
+        async fn create_invoice(
+            &self,
+            _create_invoice_request: CreateInvoiceRequest,
+        ) -> Result<CreateInvoiceResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn connect_to_peer(
+            &self,
+            _pubkey: secp256k1::PublicKey,
+            _host: String,
+        ) -> Result<EmptyResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn get_funding_address(
+            &self,
+        ) -> Result<GetFundingAddressResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn open_channel(
+            &self,
+            _pubkey: secp256k1::PublicKey,
+            _channel_size_sats: u64,
+            _push_amount_sats: u64,
+        ) -> Result<EmptyResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn close_channels_with_peer(
+            &self,
+            _pubkey: secp256k1::PublicKey,
+        ) -> Result<CloseChannelsWithPeerResponse, LightningRpcError> {
+            unimplemented!()
+        }
+
+        async fn list_active_channels(&self) -> Result<Vec<ChannelInfo>, LightningRpcError> {
+            Ok(vec![ChannelInfo {
+                remote_pubkey: String::new(),
+                channel_size_sats: self.outbound_liquidity_sats,
+                outbound_liquidity_sats: self.outbound_liquidity_sats,
+                inbound_liquidity_sats: 0,
+                short_channel_id: 0,
+                channel_type: ChannelType::Bitcoin,
+            }])
+        }
+    }
+
+    fn keysend_payment() -> KeysendPayment {
+        KeysendPayment {
+            destination: secp256k1::PublicKey::from_secret_key(
+                &secp256k1::Secp256k1::new(),
+                &secp256k1::SecretKey::from_slice(&[1; 32]).expect("valid key"),
+            ),
+            amount: Amount::from_sats(1_000),
+            preimage: Preimage([0; 32]),
+            tlv_records: BTreeMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn skips_nodes_not_supporting_the_payment() {
+        // The node without keysend support would be tried first by its liquidity
+        let unsupported = Arc::new(FakeNode {
+            keysend: false,
+            outbound_liquidity_sats: 1_000_000,
+            ..FakeNode::default()
+        });
+        let supported = Arc::new(FakeNode {
+            keysend: true,
+            outbound_liquidity_sats: 10_000,
+            ..FakeNode::default()
+        });
+        let router = LightningRouter::new(
+            vec![
+                unsupported.clone() as Arc<dyn ILnRpcClient>,
+                supported.clone(),
+            ],
+            Arc::new(Mutex::new(HashMap::new())),
+        );
+
+        router
+            .pay_keysend(keysend_payment(), 100, Amount::ZERO)
+            .await
+            .expect("Paid through the node supporting keysend");
+        assert_eq!(unsupported.payments.load(Ordering::Relaxed), 0);
+        assert_eq!(supported.payments.load(Ordering::Relaxed), 1);
+
+        let router = LightningRouter::new(
+            vec![unsupported as Arc<dyn ILnRpcClient>],
+            Arc::new(Mutex::new(HashMap::new())),
+        );
+        assert!(matches!(
+            router
+                .pay_keysend(keysend_payment(), 100, Amount::ZERO)
+                .await,
+            Err(LightningRpcError::FailedPaymentFinal { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn keeps_htlc_origin_until_completion_succeeds() {
+        let node = Arc::new(FakeNode {
+            failing_completions: AtomicU64::new(1),
+            ..FakeNode::default()
+        });
+        let htlc_origins = Arc::new(Mutex::new(HashMap::from([((1, 2), 0)])));
+        let router =
+            LightningRouter::new(vec![node as Arc<dyn ILnRpcClient>], htlc_origins.clone());
+        let htlc = InterceptHtlcResponse {
+            incoming_chan_id: 1,
+            htlc_id: 2,
+            ..InterceptHtlcResponse::default()
+        };
+
+        assert!(router.complete_htlc(htlc.clone()).await.is_err());
+        assert!(htlc_origins.lock().unwrap().contains_key(&(1, 2)));
+
+        router
+            .complete_htlc(htlc.clone())
+            .await
+            .expect("Completion is retried on the intercepting node");
+        assert!(htlc_origins.lock().unwrap().is_empty());
+
+        assert!(matches!(
+            router.complete_htlc(htlc).await,
+            Err(LightningRpcError::FailedToCompleteHtlc { .. })
+        ));
+    }
+
+    fn final_failure() -> LightningRpcError {
+        LightningRpcError::FailedPaymentFinal {
+            failure_reason: "NO_ROUTE".to_string(),
+        }
+    }
+
+    fn ambiguous_failure() -> LightningRpcError {
+        LightningRpcError::FailedPayment {
+            failure_reason: "Failed to get payment status".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_over_after_final_failures() {
+        let attempts = Mutex::new(Vec::new());
+        let result = try_nodes_in_order(vec![2, 0, 1], |idx| {
+            attempts.lock().unwrap().push(idx);
+            async move {
+                if idx == 1 {
+                    Ok(idx)
+                } else {
+                    Err(final_failure())
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(*attempts.lock().unwrap(), vec![2, 0, 1]);
+    }
+
+    #[tokio::test]
+    async fn does_not_fail_over_when_outcome_is_unknown() {
+        let attempts = Mutex::new(Vec::new());
+        let result = try_nodes_in_order(vec![0, 1], |idx| {
+            attempts.lock().unwrap().push(idx);
+            async move {
+                if idx == 0 {
+                    Err(ambiguous_failure())
+                } else {
+                    Ok(idx)
+                }
+            }
+        })
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(LightningRpcError::FailedPayment { .. })
+        ));
+        assert_eq!(*attempts.lock().unwrap(), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn returns_last_final_failure_if_all_nodes_fail() {
+        let result =
+            try_nodes_in_order::<(), _, _>(vec![0, 1], |_| async { Err(final_failure()) }).await;
+
+        assert!(matches!(
+            result,
+            Err(LightningRpcError::FailedPaymentFinal { .. })
+        ));
+    }
+}
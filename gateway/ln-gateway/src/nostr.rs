@@ -0,0 +1,283 @@
+//! Nostr zaps (NIP-57)
+//!
+//! A nostr client zaps a user by requesting an invoice whose description is a
+//! signed zap request event. If the gateway is configured with a nostr key,
+//! public invoices created for a zap request commit to the hash of the zap
+//! request, and once the incoming payment was settled the gateway publishes a
+//! zap receipt signed with its key to the relays listed in the zap request.
+
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context};
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::secp256k1::schnorr::Signature;
+use fedimint_core::secp256k1::{KeyPair, Message, XOnlyPublicKey, SECP256K1};
+use fedimint_core::util::SafeUrl;
+use fedimint_core::Amount;
+use futures::SinkExt;
+use hex::ToHex;
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tracing::{debug, warn};
+
+/// Kind of the event a nostr client puts into the invoice description
+pub const ZAP_REQUEST_KIND: u64 = 9734;
+
+/// Kind of the event the gateway publishes once a zap was paid
+pub const ZAP_RECEIPT_KIND: u64 = 9735;
+
+/// Maximum length of a zap request used as the description of a public
+/// invoice, zap requests are larger than regular descriptions since they list
+/// the relays the receipt is published to
+pub const MAX_ZAP_REQUEST_LEN: usize = 8192;
+
+/// Time after which publishing an event to a relay is given up
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Nostr event as defined by NIP-01
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NostrEvent {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u64,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl NostrEvent {
+    /// Creates an event signed by `keypair`
+    pub fn sign(
+        keypair: &KeyPair,
+        created_at: u64,
+        kind: u64,
+        tags: Vec<Vec<String>>,
+        content: String,
+    ) -> NostrEvent {
+        let pubkey = keypair
+            .x_only_public_key()
+            .0
+            .serialize()
+            .encode_hex::<String>();
+        let id = event_id(&pubkey, created_at, kind, &tags, &content);
+        let sig = SECP256K1.sign_schnorr(&event_message(id), keypair);
+
+        NostrEvent {
+            id: id.to_string(),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content,
+            sig: sig.as_ref().encode_hex(),
+        }
+    }
+
+    /// Checks that the id commits to the contents of the event and that the
+    /// event is signed by its pubkey
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        ensure!(
+            id.to_string() == self.id,
+            "Event id does not match contents"
+        );
+
+        let pubkey = XOnlyPublicKey::from_slice(&hex::decode(&self.pubkey)?)?;
+        let sig = Signature::from_slice(&hex::decode(&self.sig)?)?;
+        SECP256K1
+            .verify_schnorr(&sig, &event_message(id), &pubkey)
+            .context("Invalid event signature")?;
+
+        Ok(())
+    }
+
+    /// Tags named `name`
+    fn tags<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Vec<String>> {
+        self.tags
+            .iter()
+            .filter(move |tag| tag.first().is_some_and(|tag_name| tag_name == name))
+    }
+
+    /// First value of the first tag named `name`
+    fn tag_value(&self, name: &str) -> Option<&str> {
+        self.tags(name)
+            .next()
+            .and_then(|tag| tag.get(1))
+            .map(String::as_str)
+    }
+}
+
+/// Hash of the serialized event as defined by NIP-01
+fn event_id(
+    pubkey: &str,
+    created_at: u64,
+    kind: u64,
+    tags: &[Vec<String>],
+    content: &str,
+) -> sha256::Hash {
+    let serialized = serde_json::to_string(&(0, pubkey, created_at, kind, tags, content))
+        .expect("Serializing strings and integers can't fail");
+    sha256::Hash::hash(serialized.as_bytes())
+}
+
+fn event_message(id: sha256::Hash) -> Message {
+    Message::from_slice(&id.to_byte_array()).expect("Event ids are 32 bytes")
+}
+
+/// Parses the description of an invoice as a zap request. Returns `None` if
+/// the description is not a zap request and an error if it is an invalid one.
+pub fn parse_zap_request(description: &str, amount: Amount) -> anyhow::Result<Option<NostrEvent>> {
+    let Ok(event) = serde_json::from_str::<NostrEvent>(description) else {
+        return Ok(None);
+    };
+
+    if event.kind != ZAP_REQUEST_KIND {
+        return Ok(None);
+    }
+
+    event.verify()?;
+
+    ensure!(
+        event.tags("p").count() == 1,
+        "Zap request has to tag exactly one recipient"
+    );
+
+    if let Some(zap_amount) = event.tag_value("amount") {
+        ensure!(
+            zap_amount.parse::<u64>()? == amount.msats,
+            "Zap request amount does not match invoice amount"
+        );
+    }
+
+    if zap_request_relays(&event).is_empty() {
+        bail!("Zap request does not list any relays");
+    }
+
+    Ok(Some(event))
+}
+
+/// Relays a zap request asks the zap receipt to be published to
+pub fn zap_request_relays(zap_request: &NostrEvent) -> Vec<SafeUrl> {
+    zap_request
+        .tags("relays")
+        .flat_map(|tag| tag.iter().skip(1))
+        .filter_map(|relay| relay.parse().ok())
+        .collect()
+}
+
+/// Creates the zap receipt of a paid zap request, `zap_request_json` is the
+/// description the invoice commits to
+pub fn zap_receipt(
+    keypair: &KeyPair,
+    zap_request: &NostrEvent,
+    zap_request_json: &str,
+    invoice: &str,
+    preimage: [u8; 32],
+    paid_at: u64,
+) -> NostrEvent {
+    let mut tags = Vec::new();
+
+    for name in ["p", "e", "a"] {
+        if let Some(tag) = zap_request.tags(name).next() {
+            tags.push(tag.clone());
+        }
+    }
+
+    tags.push(vec!["P".to_string(), zap_request.pubkey.clone()]);
+    tags.push(vec!["bolt11".to_string(), invoice.to_string()]);
+    tags.push(vec![
+        "description".to_string(),
+        zap_request_json.to_string(),
+    ]);
+    tags.push(vec!["preimage".to_string(), preimage.encode_hex()]);
+
+    NostrEvent::sign(keypair, paid_at, ZAP_RECEIPT_KIND, tags, String::new())
+}
+
+/// Publishes `event` to every relay in `relays`, failures are only logged
+pub async fn publish_event(event: &NostrEvent, relays: &[SafeUrl]) {
+    let message = serde_json::to_string(&("EVENT", event)).expect("Serializing event can't fail");
+
+    for relay in relays {
+        match fedimint_core::runtime::timeout(RELAY_TIMEOUT, send_to_relay(relay, &message)).await {
+            Ok(Ok(())) => debug!(%relay, id = %event.id, "Published nostr event"),
+            Ok(Err(e)) => warn!(%relay, id = %event.id, "Failed to publish nostr event: {e:?}"),
+            Err(_) => warn!(%relay, id = %event.id, "Timed out publishing nostr event"),
+        }
+    }
+}
+
+async fn send_to_relay(relay: &SafeUrl, message: &str) -> anyhow::Result<()> {
+    let (mut stream, _) = tokio_tungstenite::connect_async(relay.as_str()).await?;
+    stream.send(WsMessage::Text(message.to_string())).await?;
+    stream.close(None).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::secp256k1::{KeyPair, SECP256K1};
+    use fedimint_core::Amount;
+    use rand::rngs::OsRng;
+
+    use super::{parse_zap_request, zap_receipt, NostrEvent, ZAP_RECEIPT_KIND, ZAP_REQUEST_KIND};
+
+    fn zap_request(keypair: &KeyPair, amount_msats: u64) -> NostrEvent {
+        NostrEvent::sign(
+            keypair,
+            1_700_000_000,
+            ZAP_REQUEST_KIND,
+            vec![
+                vec!["relays".to_string(), "wss://relay.example.com".to_string()],
+                vec!["amount".to_string(), amount_msats.to_string()],
+                vec!["p".to_string(), "ab".repeat(32)],
+            ],
+            "Great post".to_string(),
+        )
+    }
+
+    #[test]
+    fn zap_requests_are_validated() {
+        let sender = KeyPair::new(SECP256K1, &mut OsRng);
+        let request = zap_request(&sender, 21_000);
+        let json = serde_json::to_string(&request).unwrap();
+
+        assert_eq!(
+            parse_zap_request(&json, Amount::from_msats(21_000)).unwrap(),
+            Some(request.clone())
+        );
+        assert!(parse_zap_request(&json, Amount::from_msats(1_000)).is_err());
+        assert_eq!(
+            parse_zap_request("Coffee", Amount::from_msats(21_000)).unwrap(),
+            None
+        );
+
+        let mut tampered = request;
+        tampered.content = "Bad post".to_string();
+        let tampered = serde_json::to_string(&tampered).unwrap();
+        assert!(parse_zap_request(&tampered, Amount::from_msats(21_000)).is_err());
+    }
+
+    #[test]
+    fn zap_receipt_is_signed_and_references_request() {
+        let sender = KeyPair::new(SECP256K1, &mut OsRng);
+        let gateway = KeyPair::new(SECP256K1, &mut OsRng);
+        let request = zap_request(&sender, 21_000);
+        let json = serde_json::to_string(&request).unwrap();
+
+        let receipt = zap_receipt(&gateway, &request, &json, "lnbc1", [1; 32], 1_700_000_100);
+
+        receipt.verify().unwrap();
+        assert_eq!(receipt.kind, ZAP_RECEIPT_KIND);
+        assert_eq!(receipt.tag_value("p"), request.tag_value("p"));
+        assert_eq!(receipt.tag_value("P"), Some(request.pubkey.as_str()));
+        assert_eq!(receipt.tag_value("description"), Some(json.as_str()));
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+
+use bitcoin_hashes::sha256;
+use fedimint_core::Amount;
+
+use crate::lightning::PaymentAttemptUpdate;
+use crate::rpc::PaymentProgress;
+
+/// Number of recent outgoing payments whose HTLC attempts are kept in memory
+pub const MAX_TRACKED_PAYMENTS: usize = 1024;
+
+#[derive(Debug, Default)]
+struct ProgressLog {
+    /// Payment hashes in the order they were first seen, used for eviction
+    order: VecDeque<sha256::Hash>,
+    attempts: BTreeMap<sha256::Hash, Vec<PaymentAttemptUpdate>>,
+    /// Fees the lightning node reported for completed payments
+    lightning_fees: BTreeMap<sha256::Hash, Amount>,
+}
+
+impl ProgressLog {
+    /// Starts tracking a payment, evicting the oldest one if necessary
+    fn track(&mut self, payment_hash: sha256::Hash) {
+        if self.attempts.contains_key(&payment_hash)
+            || self.lightning_fees.contains_key(&payment_hash)
+        {
+            return;
+        }
+
+        if self.order.len() == MAX_TRACKED_PAYMENTS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.attempts.remove(&oldest);
+                self.lightning_fees.remove(&oldest);
+            }
+        }
+        self.order.push_back(payment_hash);
+    }
+}
+
+/// In-memory record of the HTLC attempts the lightning node reported for
+/// recent outgoing payments, used to diagnose payments that are stuck or keep
+/// failing. Once [`MAX_TRACKED_PAYMENTS`] payments are tracked the oldest one
+/// is evicted. Progress is not persisted across restarts.
+#[derive(Debug, Default)]
+pub struct PaymentProgressLog {
+    log: Mutex<ProgressLog>,
+}
+
+impl PaymentProgressLog {
+    /// Records an update of an HTLC attempt, replacing earlier updates of the
+    /// same attempt
+    pub fn record(&self, payment_hash: sha256::Hash, update: PaymentAttemptUpdate) {
+        let mut log = self.log.lock().expect("poisoned");
+        log.track(payment_hash);
+
+        let attempts = log.attempts.entry(payment_hash).or_default();
+        match attempts
+            .iter_mut()
+            .find(|attempt| attempt.attempt_id == update.attempt_id)
+        {
+            Some(attempt) => *attempt = update,
+            None => attempts.push(update),
+        }
+    }
+
+    /// Records the lightning fee of a completed payment
+    pub fn record_lightning_fee(&self, payment_hash: sha256::Hash, fee: Amount) {
+        let mut log = self.log.lock().expect("poisoned");
+        log.track(payment_hash);
+        log.lightning_fees.insert(payment_hash, fee);
+    }
+
+    /// Returns the lightning fee of a completed payment if it is still tracked
+    pub fn lightning_fee(&self, payment_hash: &sha256::Hash) -> Option<Amount> {
+        let log = self.log.lock().expect("poisoned");
+        log.lightning_fees.get(payment_hash).copied()
+    }
+
+    pub fn get(&self, payment_hash: &sha256::Hash) -> Option<PaymentProgress> {
+        let log = self.log.lock().expect("poisoned");
+        let lightning_fee = log.lightning_fees.get(payment_hash).copied();
+        let attempts = log.attempts.get(payment_hash);
+
+        if attempts.is_none() && lightning_fee.is_none() {
+            return None;
+        }
+
+        Some(PaymentProgress {
+            payment_hash: *payment_hash,
+            attempts: attempts.cloned().unwrap_or_default(),
+            lightning_fee,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::Amount;
+
+    use super::{PaymentProgressLog, MAX_TRACKED_PAYMENTS};
+    use crate::lightning::{PaymentAttemptStatus, PaymentAttemptUpdate};
+
+    fn attempt(attempt_id: u64, status: PaymentAttemptStatus) -> PaymentAttemptUpdate {
+        PaymentAttemptUpdate {
+            attempt_id,
+            status,
+            amount: Amount::from_sats(1000),
+            fee: Amount::from_msats(1000),
+            num_hops: 2,
+            failure_reason: None,
+        }
+    }
+
+    fn payment_hash(i: usize) -> sha256::Hash {
+        sha256::Hash::hash(&i.to_be_bytes())
+    }
+
+    #[test]
+    fn updates_replace_earlier_updates_of_the_same_attempt() {
+        let log = PaymentProgressLog::default();
+        log.record(payment_hash(0), attempt(1, PaymentAttemptStatus::InFlight));
+        log.record(payment_hash(0), attempt(2, PaymentAttemptStatus::InFlight));
+        log.record(payment_hash(0), attempt(1, PaymentAttemptStatus::Failed));
+
+        let progress = log.get(&payment_hash(0)).expect("payment is tracked");
+        assert_eq!(
+            progress.attempts,
+            vec![
+                attempt(1, PaymentAttemptStatus::Failed),
+                attempt(2, PaymentAttemptStatus::InFlight)
+            ]
+        );
+        assert!(log.get(&payment_hash(1)).is_none());
+    }
+
+    #[test]
+    fn oldest_payment_is_evicted() {
+        let log = PaymentProgressLog::default();
+        for i in 0..=MAX_TRACKED_PAYMENTS {
+            log.record(payment_hash(i), attempt(1, PaymentAttemptStatus::InFlight));
+        }
+
+        assert!(log.get(&payment_hash(0)).is_none());
+        assert!(log.get(&payment_hash(1)).is_some());
+        assert!(log.get(&payment_hash(MAX_TRACKED_PAYMENTS)).is_some());
+    }
+
+    #[test]
+    fn lightning_fee_is_tracked_with_attempts() {
+        let log = PaymentProgressLog::default();
+        log.record(payment_hash(0), attempt(1, PaymentAttemptStatus::Succeeded));
+        log.record_lightning_fee(payment_hash(0), Amount::from_msats(1000));
+        log.record_lightning_fee(payment_hash(1), Amount::from_msats(2000));
+
+        let progress = log.get(&payment_hash(0)).expect("payment is tracked");
+        assert_eq!(progress.attempts.len(), 1);
+        assert_eq!(progress.lightning_fee, Some(Amount::from_msats(1000)));
+        assert_eq!(
+            log.lightning_fee(&payment_hash(1)),
+            Some(Amount::from_msats(2000))
+        );
+        assert!(log
+            .get(&payment_hash(1))
+            .expect("fee is tracked")
+            .attempts
+            .is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use bitcoin_hashes::sha256;
+
+/// Window over which `PublicReceiver::max_invoices_per_hour` is enforced
+pub const PUBLIC_INVOICE_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum length of a description that can be attached to a public invoice
+pub const MAX_PUBLIC_INVOICE_DESCRIPTION_LEN: usize = 640;
+
+/// Expiry of invoices created through the public endpoint
+pub const PUBLIC_INVOICE_EXPIRY_SECS: u32 = 3600;
+
+/// In-memory sliding window rate limiter for the public invoice endpoint,
+/// keyed by the hash of the receiver's token.
+///
+/// The state is intentionally not persisted, a restart of the gateway resets
+/// all quotas.
+#[derive(Debug, Default)]
+pub struct PublicInvoiceRateLimiter {
+    requests: Mutex<BTreeMap<sha256::Hash, VecDeque<SystemTime>>>,
+}
+
+impl PublicInvoiceRateLimiter {
+    /// Records a request for `token_hash` at `now` and returns true if it is
+    /// within `limit` requests per [`PUBLIC_INVOICE_RATE_LIMIT_WINDOW`].
+    /// Rejected requests are not recorded.
+    pub fn try_acquire(&self, token_hash: sha256::Hash, limit: u32, now: SystemTime) -> bool {
+        let mut requests = self.requests.lock().expect("poisoned");
+        let window = requests.entry(token_hash).or_default();
+
+        while window.front().is_some_and(|oldest| {
+            now.duration_since(*oldest).unwrap_or_default() >= PUBLIC_INVOICE_RATE_LIMIT_WINDOW
+        }) {
+            window.pop_front();
+        }
+
+        if window.len() >= limit as usize {
+            return false;
+        }
+
+        window.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use bitcoin_hashes::{sha256, Hash};
+
+    use super::{PublicInvoiceRateLimiter, PUBLIC_INVOICE_RATE_LIMIT_WINDOW};
+
+    #[test]
+    fn rate_limiter_enforces_quota_per_token() {
+        let limiter = PublicInvoiceRateLimiter::default();
+        let token_a = sha256::Hash::hash(b"a");
+        let token_b = sha256::Hash::hash(b"b");
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert!(limiter.try_acquire(token_a, 2, now));
+        assert!(limiter.try_acquire(token_a, 2, now));
+        assert!(!limiter.try_acquire(token_a, 2, now));
+
+        // other tokens have their own quota
+        assert!(limiter.try_acquire(token_b, 2, now));
+
+        // the quota is replenished once the window has passed
+        let later = now + PUBLIC_INVOICE_RATE_LIMIT_WINDOW;
+        assert!(limiter.try_acquire(token_a, 2, later));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use bitcoin_hashes::sha256;
+use fedimint_core::config::FederationId;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::rpc::RiskStatus;
+
+/// Reason a payment was rejected by the [`crate::rpc::RiskLimits`] of the
+/// gateway
+#[derive(Debug, Clone, Eq, PartialEq, Error, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "limit")]
+pub enum RiskLimitViolation {
+    #[error("Payment of {amount} exceeds the maximum payment amount of {max}")]
+    PaymentTooLarge { amount: Amount, max: Amount },
+    #[error(
+        "Payment would raise the outgoing volume of federation {federation_id} within the last \
+         hour to {volume}, the limit is {max}"
+    )]
+    HourlyVolumeExceeded {
+        federation_id: FederationId,
+        volume: Amount,
+        max: Amount,
+    },
+    #[error(
+        "Payment would raise the outgoing volume of federation {federation_id} within the last \
+         day to {volume}, the limit is {max}"
+    )]
+    DailyVolumeExceeded {
+        federation_id: FederationId,
+        volume: Amount,
+        max: Amount,
+    },
+    #[error("Payment would raise the pending exposure to {exposure}, the limit is {max}")]
+    ExposureExceeded { exposure: Amount, max: Amount },
+}
+
+impl RiskStatus {
+    /// Checks whether the gateway may take on a payment of `amount`,
+    /// regardless of its direction
+    pub fn check_payment(&self, amount: Amount) -> Result<(), RiskLimitViolation> {
+        if let Some(max) = self.limits.max_payment_amount {
+            if max < amount {
+                return Err(RiskLimitViolation::PaymentTooLarge { amount, max });
+            }
+        }
+
+        if let Some(max) = self.limits.max_pending_exposure {
+            let exposure = self.pending_exposure + amount;
+            if max < exposure {
+                return Err(RiskLimitViolation::ExposureExceeded { exposure, max });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the gateway may pay an invoice of `amount` on behalf of
+    /// a client of the federation
+    pub fn check_outgoing_payment(
+        &self,
+        federation_id: FederationId,
+        amount: Amount,
+    ) -> Result<(), RiskLimitViolation> {
+        self.check_payment(amount)?;
+
+        let utilization = self
+            .federations
+            .get(&federation_id)
+            .copied()
+            .unwrap_or_default();
+
+        if let Some(max) = self.limits.max_hourly_outgoing_volume {
+            let volume = utilization.hourly_outgoing_volume + amount;
+            if max < volume {
+                return Err(RiskLimitViolation::HourlyVolumeExceeded {
+                    federation_id,
+                    volume,
+                    max,
+                });
+            }
+        }
+
+        if let Some(max) = self.limits.max_daily_outgoing_volume {
+            let volume = utilization.daily_outgoing_volume + amount;
+            if max < volume {
+                return Err(RiskLimitViolation::DailyVolumeExceeded {
+                    federation_id,
+                    volume,
+                    max,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Outgoing payments the gateway is currently paying. They count towards the
+/// pending exposure and the outgoing volume of their federation until they
+/// are recorded in the payment history. Payments are kept in memory only, so
+/// payments that are resumed after a restart are not accounted for.
+#[derive(Debug, Default)]
+pub struct PendingOutgoingPayments {
+    payments: Mutex<BTreeMap<sha256::Hash, (FederationId, Amount)>>,
+    /// Held while checking the risk limits of a new payment and adding it, so
+    /// that concurrent payments can't exceed the limits together
+    pub check_lock: tokio::sync::Mutex<()>,
+}
+
+impl PendingOutgoingPayments {
+    pub fn contains(&self, payment_hash: &sha256::Hash) -> bool {
+        self.payments
+            .lock()
+            .expect("poisoned")
+            .contains_key(payment_hash)
+    }
+
+    /// Tracks the payment until the returned guard is dropped
+    pub fn insert(
+        self: &Arc<Self>,
+        payment_hash: sha256::Hash,
+        federation_id: FederationId,
+        amount: Amount,
+    ) -> PendingOutgoingPayment {
+        self.payments
+            .lock()
+            .expect("poisoned")
+            .insert(payment_hash, (federation_id, amount));
+
+        PendingOutgoingPayment {
+            payments: self.clone(),
+            payment_hash,
+        }
+    }
+
+    /// Returns the total amount of pending payments per federation
+    pub fn amounts_by_federation(&self) -> BTreeMap<FederationId, Amount> {
+        let mut amounts = BTreeMap::<FederationId, Amount>::new();
+        for (federation_id, amount) in self.payments.lock().expect("poisoned").values() {
+            *amounts.entry(*federation_id).or_insert(Amount::ZERO) += *amount;
+        }
+        amounts
+    }
+}
+
+/// Guard of a payment tracked by [`PendingOutgoingPayments`], which stops
+/// tracking it when dropped
+#[derive(Debug)]
+pub struct PendingOutgoingPayment {
+    payments: Arc<PendingOutgoingPayments>,
+    payment_hash: sha256::Hash,
+}
+
+impl Drop for PendingOutgoingPayment {
+    fn drop(&mut self) {
+        self.payments
+            .payments
+            .lock()
+            .expect("poisoned")
+            .remove(&self.payment_hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::config::FederationId;
+    use fedimint_core::Amount;
+
+    use super::{PendingOutgoingPayments, RiskLimitViolation};
+    use crate::rpc::{FederationRiskUtilization, RiskLimits, RiskStatus};
+
+    fn status(limits: RiskLimits) -> RiskStatus {
+        RiskStatus {
+            limits,
+            pending_exposure: Amount::from_sats(500),
+            federations: BTreeMap::from([(
+                FederationId::dummy(),
+                FederationRiskUtilization {
+                    hourly_outgoing_volume: Amount::from_sats(1000),
+                    daily_outgoing_volume: Amount::from_sats(5000),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn unlimited_by_default() {
+        let status = status(RiskLimits::default());
+        assert_eq!(
+            status.check_outgoing_payment(FederationId::dummy(), Amount::from_sats(1_000_000)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_payments_exceeding_limits() {
+        let federation_id = FederationId::dummy();
+        let status = status(RiskLimits {
+            max_payment_amount: Some(Amount::from_sats(2000)),
+            max_hourly_outgoing_volume: Some(Amount::from_sats(2500)),
+            max_daily_outgoing_volume: Some(Amount::from_sats(6000)),
+            max_pending_exposure: Some(Amount::from_sats(2000)),
+        });
+
+        assert_eq!(
+            status.check_outgoing_payment(federation_id, Amount::from_sats(1000)),
+            Ok(())
+        );
+        assert_eq!(
+            status.check_outgoing_payment(federation_id, Amount::from_sats(3000)),
+            Err(RiskLimitViolation::PaymentTooLarge {
+                amount: Amount::from_sats(3000),
+                max: Amount::from_sats(2000),
+            })
+        );
+        assert_eq!(
+            status.check_payment(Amount::from_sats(1600)),
+            Err(RiskLimitViolation::ExposureExceeded {
+                exposure: Amount::from_sats(2100),
+                max: Amount::from_sats(2000),
+            })
+        );
+
+        let status = RiskStatus {
+            pending_exposure: Amount::ZERO,
+            ..status
+        };
+        assert_eq!(
+            status.check_outgoing_payment(federation_id, Amount::from_sats(1600)),
+            Err(RiskLimitViolation::HourlyVolumeExceeded {
+                federation_id,
+                volume: Amount::from_sats(2600),
+                max: Amount::from_sats(2500),
+            })
+        );
+
+        let status = RiskStatus {
+            federations: BTreeMap::from([(
+                federation_id,
+                FederationRiskUtilization {
+                    hourly_outgoing_volume: Amount::ZERO,
+                    daily_outgoing_volume: Amount::from_sats(5000),
+                },
+            )]),
+            ..status
+        };
+        assert_eq!(
+            status.check_outgoing_payment(federation_id, Amount::from_sats(1500)),
+            Err(RiskLimitViolation::DailyVolumeExceeded {
+                federation_id,
+                volume: Amount::from_sats(6500),
+                max: Amount::from_sats(6000),
+            })
+        );
+        // incoming payments are not subject to the outgoing volume limits
+        assert_eq!(status.check_payment(Amount::from_sats(1500)), Ok(()));
+    }
+
+    #[test]
+    fn pending_payments_are_tracked_until_dropped() {
+        let payments = Arc::new(PendingOutgoingPayments::default());
+        let payment_hash = sha256::Hash::hash(b"payment");
+
+        let guard = payments.insert(payment_hash, FederationId::dummy(), Amount::from_sats(10));
+        assert!(payments.contains(&payment_hash));
+        assert_eq!(
+            payments.amounts_by_federation(),
+            BTreeMap::from([(FederationId::dummy(), Amount::from_sats(10))])
+        );
+
+        drop(guard);
+        assert!(!payments.contains(&payment_hash));
+        assert!(payments.amounts_by_federation().is_empty());
+    }
+}
@@ -1,18 +1,31 @@
 pub mod rpc_client;
 pub mod rpc_server;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::SystemTime;
 
 use bitcoin::address::NetworkUnchecked;
 use bitcoin::{Address, Network};
+use bitcoin_hashes::sha256;
+use fedimint_aead::{decrypt, encrypt, get_encryption_key, random_salt};
 use fedimint_core::config::{ClientConfig, FederationId, JsonClientConfig};
-use fedimint_core::{secp256k1, Amount, BitcoinAmountOrAll};
-use fedimint_ln_common::config::parse_routing_fees;
-use fedimint_ln_common::{route_hints, serde_option_routing_fees};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::invite_code::InviteCode;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{secp256k1, Amount, BitcoinAmountOrAll, NumPeers, NumPeersExt};
+use fedimint_ln_common::config::{parse_routing_fees, FeeToAmount};
+use fedimint_ln_common::{route_hints, serde_option_routing_fees, serde_routing_fees};
+use fedimint_lnv2_client::PaymentFee;
+use hex::ToHex;
 use lightning_invoice::RoutingFees;
 use serde::{Deserialize, Serialize};
 
+use crate::lightning::{LightningMode, PaymentAttemptUpdate};
+
 pub const V1_API_ENDPOINT: &str = "v1";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,6 +95,9 @@ pub struct GatewayInfo {
     pub route_hints: Vec<route_hints::RouteHint>,
     pub gateway_id: secp256k1::PublicKey,
     pub gateway_state: String,
+    /// Why the gateway is degraded, if it is
+    #[serde(default)]
+    pub degraded_reason: Option<String>,
     pub network: Option<Network>,
     // TODO: This is here to allow for backwards compatibility with old versions of this struct. We
     // should be able to remove it once 0.4.0 is released.
@@ -141,6 +157,373 @@ pub struct SetConfigurationPayload {
     pub routing_fees: Option<FederationRoutingFees>,
     pub network: Option<Network>,
     pub per_federation_routing_fees: Option<Vec<(FederationId, FederationRoutingFees)>>,
+    /// Seconds between two checks whether the route hints of the lightning
+    /// node changed, `0` disables the checks
+    #[serde(default)]
+    pub route_hint_refresh_interval_secs: Option<u64>,
+    /// Number of consecutive checks that have to see changed route hints
+    /// before the gateway re-registers with a federation
+    #[serde(default)]
+    pub route_hint_refresh_hysteresis: Option<u32>,
+}
+
+/// Settings of the task that periodically fetches the route hints of the
+/// lightning node and re-registers the gateway with every federation whose
+/// registered route hints are outdated.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct RouteHintRefreshConfig {
+    /// Seconds between two checks, `0` disables the checks
+    pub interval_secs: u64,
+    /// Number of consecutive checks that have to see the same changed route
+    /// hints before re-registering, so that a flapping channel does not cause
+    /// a registration on every check
+    pub hysteresis_checks: u32,
+}
+
+impl Default for RouteHintRefreshConfig {
+    fn default() -> Self {
+        RouteHintRefreshConfig {
+            interval_secs: 60,
+            hysteresis_checks: 2,
+        }
+    }
+}
+
+/// Limits on the payments the gateway makes, checked before paying an
+/// invoice on behalf of a federation client and before paying an intercepted
+/// HTLC to a federation. Unset limits are not enforced, which is the default.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct RiskLimits {
+    /// Maximum amount of a single payment
+    pub max_payment_amount: Option<Amount>,
+    /// Maximum amount paid out on behalf of a single federation within the
+    /// last hour
+    pub max_hourly_outgoing_volume: Option<Amount>,
+    /// Maximum amount paid out on behalf of a single federation within the
+    /// last day
+    pub max_daily_outgoing_volume: Option<Amount>,
+    /// Maximum total amount of outgoing payments and intercepted HTLCs that
+    /// are pending at the same time
+    pub max_pending_exposure: Option<Amount>,
+}
+
+/// Current utilization of the [`RiskLimits`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RiskStatus {
+    pub limits: RiskLimits,
+    /// Total amount of pending outgoing payments and intercepted HTLCs
+    pub pending_exposure: Amount,
+    /// Outgoing volume of every federation that made payments within the last
+    /// day
+    pub federations: BTreeMap<FederationId, FederationRiskUtilization>,
+}
+
+/// Amount paid out on behalf of a federation, including pending payments
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FederationRiskUtilization {
+    pub hourly_outgoing_volume: Amount,
+    pub daily_outgoing_volume: Amount,
+}
+
+impl Default for FederationRiskUtilization {
+    fn default() -> Self {
+        FederationRiskUtilization {
+            hourly_outgoing_volume: Amount::ZERO,
+            daily_outgoing_volume: Amount::ZERO,
+        }
+    }
+}
+
+/// Nostr key the gateway signs zap receipts with, see [`crate::nostr`]
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct NostrConfig {
+    pub secret_key: secp256k1::SecretKey,
+    /// Relays zap receipts are published to in addition to the relays listed
+    /// in the zap request
+    pub relays: Vec<SafeUrl>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetNostrConfigPayload {
+    /// Key to sign zap receipts with, `None` disables zap receipts
+    pub secret_key: Option<secp256k1::SecretKey>,
+    #[serde(default)]
+    pub relays: Vec<SafeUrl>,
+}
+
+/// Nostr configuration of the gateway without the secret key
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NostrConfigInfo {
+    /// Hex encoded x-only public key zap receipts are signed with, `None` if
+    /// zap receipts are disabled
+    pub public_key: Option<String>,
+    pub relays: Vec<SafeUrl>,
+}
+
+impl From<&NostrConfig> for NostrConfigInfo {
+    fn from(config: &NostrConfig) -> Self {
+        let (public_key, _) =
+            secp256k1::KeyPair::from_secret_key(secp256k1::SECP256K1, &config.secret_key)
+                .x_only_public_key();
+
+        NostrConfigInfo {
+            public_key: Some(public_key.serialize().encode_hex()),
+            relays: config.relays.clone(),
+        }
+    }
+}
+
+/// Price and validity of the L402 tokens required to create public invoices,
+/// see [`crate::l402`]
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct L402Config {
+    pub price: Amount,
+    pub validity_secs: u64,
+    /// Key the gateway signs the macaroons of tokens with, rotating it revokes
+    /// all tokens
+    pub root_key: [u8; 32],
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetL402ConfigPayload {
+    /// Price of a token, `None` disables L402 authentication
+    pub price: Option<Amount>,
+    /// Time a token remains valid after it was minted, defaults to a day
+    pub validity_secs: Option<u64>,
+    /// Generates a new root key, revoking all previously minted tokens
+    #[serde(default)]
+    pub rotate_root_key: bool,
+}
+
+/// L402 configuration of the gateway without the root key, `None` if L402
+/// authentication is disabled
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct L402ConfigInfo {
+    pub price: Option<Amount>,
+    pub validity_secs: Option<u64>,
+}
+
+impl From<&L402Config> for L402ConfigInfo {
+    fn from(config: &L402Config) -> Self {
+        L402ConfigInfo {
+            price: Some(config.price),
+            validity_secs: Some(config.validity_secs),
+        }
+    }
+}
+
+/// Declarative gateway configuration. This is the schema of the TOML file
+/// passed to `gatewayd --config` as well as of the `get_config` and
+/// `apply_config` endpoints, so the output of one can be fed into the other.
+///
+/// Unset fields are left unchanged when applied. `mode`,
+/// `additional_lightning_nodes`, `data_dir`, `listen`, `api_addr` and
+/// `low_liquidity_alert_threshold_sats` are only read on startup and can't be
+/// changed through `apply_config`.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct GatewayConfigFile {
+    pub mode: Option<LightningMode>,
+    pub additional_lightning_nodes: Option<Vec<LightningMode>>,
+    pub data_dir: Option<PathBuf>,
+    pub listen: Option<SocketAddr>,
+    pub api_addr: Option<SafeUrl>,
+    pub password: Option<String>,
+    pub network: Option<Network>,
+    pub fees: Option<FederationRoutingFees>,
+    pub num_route_hints: Option<u32>,
+    pub low_liquidity_alert_threshold_sats: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub federations: Vec<FederationConfigOverride>,
+}
+
+/// Routing fees to use for a single federation instead of the gateway-wide
+/// `fees`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FederationConfigOverride {
+    pub federation_id: FederationId,
+    pub fees: FederationRoutingFees,
+}
+
+/// Fees charged for LNv2 direct swaps between two federations served by the
+/// gateway, configured separately from the lightning routing fees.
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct SwapFees {
+    /// Fee the sending federation's client pays for the outgoing contract
+    pub send_fee: PaymentFee,
+    /// Fee deducted from the incoming contract in the receiving federation
+    pub receive_fee: PaymentFee,
+}
+
+impl Default for SwapFees {
+    fn default() -> Self {
+        SwapFees {
+            send_fee: PaymentFee::half_of_one_percent(),
+            receive_fee: PaymentFee::half_of_one_percent(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetSwapFeesPayload {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetSwapFeesPayload {
+    pub federation_id: FederationId,
+    pub fees: SwapFees,
+}
+
+/// How the routing fees of outgoing LNv1 payments of a federation are
+/// determined
+#[derive(Debug, Default, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeMode {
+    /// Clients are charged the static routing fees of the federation, the
+    /// lightning network fees are paid out of them
+    #[default]
+    Static,
+    /// Clients are charged the lightning network fees of the payment plus a
+    /// margin. The gateway announces `margin` plus `max_lightning_fee` as its
+    /// routing fees, so the contract amount clients calculate covers the
+    /// maximum lightning network fee, and pays at most the contract amount
+    /// minus the margin to the lightning network.
+    Passthrough {
+        #[serde(with = "serde_routing_fees")]
+        margin: RoutingFees,
+        #[serde(with = "serde_routing_fees")]
+        max_lightning_fee: RoutingFees,
+    },
+}
+
+impl FeeMode {
+    /// Routing fees clients are charged for a payment, given the static
+    /// routing fees of the federation
+    pub fn routing_fees(&self, static_fees: RoutingFees) -> RoutingFees {
+        match self {
+            FeeMode::Static => static_fees,
+            FeeMode::Passthrough {
+                margin,
+                max_lightning_fee,
+            } => RoutingFees {
+                base_msat: margin.base_msat.saturating_add(max_lightning_fee.base_msat),
+                proportional_millionths: margin
+                    .proportional_millionths
+                    .saturating_add(max_lightning_fee.proportional_millionths),
+            },
+        }
+    }
+
+    /// Part of the routing fee of a payment of `amount` the gateway keeps
+    /// instead of spending it on lightning network fees
+    pub fn margin(&self, amount: &Amount) -> Amount {
+        match self {
+            FeeMode::Static => Amount::ZERO,
+            FeeMode::Passthrough { margin, .. } => margin.to_amount(amount),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetFeeModePayload {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFeeModePayload {
+    pub federation_id: FederationId,
+    pub fee_mode: FeeMode,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetRouteHintPrivacyPayload {
+    pub federation_id: FederationId,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetFederationRegistrationPayload {
+    pub federation_id: FederationId,
+    #[serde(default)]
+    pub lightning_alias: Option<String>,
+    #[serde(default)]
+    pub scid_base: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetPaymentProgressPayload {
+    pub payment_hash: sha256::Hash,
+}
+
+/// HTLC attempts the lightning node reported for an outgoing payment
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct PaymentProgress {
+    pub payment_hash: sha256::Hash,
+    pub attempts: Vec<PaymentAttemptUpdate>,
+    /// Fee paid to the lightning network, known once the payment succeeded
+    pub lightning_fee: Option<Amount>,
+}
+
+/// Restricts which federations the gateway connects to and serves. The
+/// default policy allows every federation.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationPolicy {
+    /// If set, only these federations are allowed
+    pub allowed_federations: Option<BTreeSet<FederationId>>,
+    /// Federations that are never allowed
+    #[serde(default)]
+    pub denied_federations: BTreeSet<FederationId>,
+    /// Minimum number of guardians that have to agree on consensus
+    pub min_guardian_threshold: Option<u64>,
+    /// Module kinds a federation has to offer
+    #[serde(default)]
+    pub required_modules: BTreeSet<ModuleKind>,
+}
+
+impl FederationPolicy {
+    /// Checks whether the federation is allowed by its id alone, which is
+    /// possible before downloading its config
+    pub fn check_federation_id(&self, federation_id: FederationId) -> Result<(), String> {
+        if self.denied_federations.contains(&federation_id) {
+            return Err(format!("Federation {federation_id} is denied"));
+        }
+
+        if let Some(allowed_federations) = &self.allowed_federations {
+            if !allowed_federations.contains(&federation_id) {
+                return Err(format!("Federation {federation_id} is not allowed"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the federation with the given config is allowed
+    pub fn check_config(&self, config: &ClientConfig) -> Result<(), String> {
+        let federation_id = config.global.calculate_federation_id();
+        self.check_federation_id(federation_id)?;
+
+        if let Some(min_guardian_threshold) = self.min_guardian_threshold {
+            let threshold = NumPeers::from(config.global.api_endpoints.len()).threshold() as u64;
+            if threshold < min_guardian_threshold {
+                return Err(format!(
+                    "Federation {federation_id} has a guardian threshold of {threshold}, \
+                     {min_guardian_threshold} is required"
+                ));
+            }
+        }
+
+        for kind in &self.required_modules {
+            if !config.modules.values().any(|module| &module.kind == kind) {
+                return Err(format!(
+                    "Federation {federation_id} is missing the required module {kind}"
+                ));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -163,3 +546,260 @@ pub struct OpenChannelPayload {
 pub struct CloseChannelsWithPeerPayload {
     pub pubkey: secp256k1::PublicKey,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CloseChannelPayload {
+    /// Funding outpoint of the channel
+    pub channel_id: bitcoin::OutPoint,
+    /// Broadcast the latest commitment transaction instead of closing the
+    /// channel cooperatively
+    pub force: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GetChannelPayload {
+    pub short_channel_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegisterPublicReceiverPayload {
+    pub federation_id: FederationId,
+    /// LNv2 static public key of the receiving user
+    pub recipient_static_pk: secp256k1::PublicKey,
+    pub max_invoice_amount: Amount,
+    pub max_invoices_per_hour: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreatePublicInvoicePayload {
+    /// Token returned when the receiver was registered
+    pub token: String,
+    pub amount: Amount,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShutdownPayload {
+    /// How long to wait for in-flight payments to settle or refund, defaults
+    /// to [`crate::DEFAULT_DRAIN_TIMEOUT`]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListPaymentsPayload {
+    pub federation_id: Option<FederationId>,
+    /// Only include payments completed at or after this time
+    pub start_time: Option<SystemTime>,
+    /// Only include payments completed at or before this time
+    pub end_time: Option<SystemTime>,
+    pub limit: usize,
+    /// Completion time of the last payment of the previous page
+    pub start_after: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentDirection {
+    /// A lightning invoice paid on behalf of a federation user
+    Outgoing,
+    /// An intercepted HTLC paid to a federation user
+    Incoming,
+    /// An invoice paid from one federation to another without touching the
+    /// lightning network
+    Swap,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Succeeded,
+    Failed { error: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportConnectionsPayload {
+    /// Password the exported connections are encrypted with
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportDbPayload {
+    /// Password the database archive is encrypted with
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportConnectionsPayload {
+    /// Password the connections were exported with
+    pub password: String,
+    pub connections: EncryptedConnections,
+}
+
+/// A federation the gateway is connected to, with the fees and timelock delta
+/// the gateway uses for it
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FederationConnection {
+    pub invite_code: InviteCode,
+    #[serde(with = "serde_routing_fees")]
+    pub fees: RoutingFees,
+    pub timelock_delta: u64,
+}
+
+/// The federations a gateway is connected to, used to move them to another
+/// gateway
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GatewayConnections {
+    pub federations: Vec<FederationConnection>,
+}
+
+/// [`GatewayConnections`] encrypted with a password, safe to store in a file
+/// and to copy to the machine of another gateway
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct EncryptedConnections {
+    /// Salt used to derive the encryption key from the password
+    pub salt: String,
+    /// Hex encoded ciphertext of the JSON serialized connections
+    pub ciphertext: String,
+}
+
+impl EncryptedConnections {
+    pub fn encrypt(connections: &GatewayConnections, password: &str) -> anyhow::Result<Self> {
+        let salt = random_salt();
+        let key = get_encryption_key(password, &salt)?;
+        let ciphertext = encrypt(serde_json::to_vec(connections)?, &key)?;
+
+        Ok(Self {
+            salt,
+            ciphertext: ciphertext.encode_hex(),
+        })
+    }
+
+    pub fn decrypt(&self, password: &str) -> anyhow::Result<GatewayConnections> {
+        let key = get_encryption_key(password, &self.salt)?;
+        let mut ciphertext = hex::decode(&self.ciphertext)?;
+        let plaintext = decrypt(&mut ciphertext, &key)?;
+
+        Ok(serde_json::from_slice(plaintext)?)
+    }
+}
+
+/// The short channel id alias assigned to a federation, which invoices
+/// created through the gateway use to route payments to the federation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScidAliasInfo {
+    pub federation_id: FederationId,
+    pub scid: u64,
+    /// Whether the gateway is currently connected to the federation
+    pub connected: bool,
+    /// Phantom short channel id invoices use instead of the alias if route
+    /// hint privacy is enabled for the federation
+    pub phantom_scid: Option<u64>,
+    pub registration: FederationRegistration,
+}
+
+/// Lightning alias and SCID base the gateway registers with a federation
+/// instead of the alias of its node and the SCID alias of the federation, so
+/// invoices of different federations can be told apart
+#[derive(Debug, Default, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct FederationRegistration {
+    pub lightning_alias: Option<String>,
+    /// Short channel id with an output index of 0, invoices route through a
+    /// phantom SCID derived from it instead if route hint privacy is enabled
+    /// for the federation
+    pub scid_base: Option<u64>,
+}
+
+/// Routing fees the gateway earned from payments of one federation
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FederationEarnings {
+    pub federation_id: FederationId,
+    /// Fees earned paying HTLCs to the federation
+    pub incoming: Amount,
+    /// Fees earned paying invoices on behalf of clients of the federation
+    pub outgoing: Amount,
+}
+
+/// Routing fees the gateway earned from the federations it is connected to
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GatewayEarnings {
+    pub total_incoming: Amount,
+    pub total_outgoing: Amount,
+    pub federations: Vec<FederationEarnings>,
+}
+
+/// Routing statistics of the lightning node, accumulated by the gateway from
+/// the forwarding and payment history of the node
+#[derive(Debug, Default, Clone, Eq, PartialEq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct NodeRoutingStats {
+    /// Number of HTLCs the node forwarded between two of its channels
+    pub num_forwards: u64,
+    /// Amount that left the node in forwarded HTLCs
+    pub forwarded: Amount,
+    pub forwarding_fees: Amount,
+    /// Number of circular payments that moved liquidity between channels of
+    /// the node
+    pub num_rebalances: u64,
+    pub rebalanced: Amount,
+    /// Fees the node paid for rebalancing
+    pub rebalancing_fees: Amount,
+    /// Unix time up to which the history of the node has been synced
+    pub synced_until_secs: u64,
+}
+
+/// Routing economics of the gateway, combining the fees earned from
+/// federations with the routing of the lightning node itself
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct NodeStats {
+    pub routing: NodeRoutingStats,
+    pub federation_earnings: GatewayEarnings,
+}
+
+/// A finished payment from the gateway's payment history, newest first
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PaymentSummary {
+    pub payment_hash: sha256::Hash,
+    pub federation_id: FederationId,
+    pub direction: PaymentDirection,
+    pub status: PaymentStatus,
+    pub amount: Amount,
+    /// Routing fees earned by the gateway, zero for failed payments
+    pub fees_earned: Amount,
+    /// Fees paid to the lightning network, zero for payments that were not
+    /// routed over lightning
+    pub lightning_fee: Amount,
+    pub started_at: SystemTime,
+    pub completed_at: SystemTime,
+}
+
+/// An intercepted HTLC paying a federation that is not settled or cancelled
+/// yet
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PendingHtlc {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+    pub federation_id: FederationId,
+    pub payment_hash: sha256::Hash,
+    pub amount: Amount,
+    pub started_at: SystemTime,
+    /// Whether the HTLC pays a hold invoice created by the gateway
+    pub hold_invoice: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HtlcResolution {
+    /// Settle the HTLC with the preimage of its payment hash
+    Settle {
+        #[serde(with = "fedimint_core::hex::serde")]
+        preimage: Vec<u8>,
+    },
+    /// Cancel the HTLC, returning the funds to the payer
+    Cancel { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveHtlcPayload {
+    pub incoming_chan_id: u64,
+    pub htlc_id: u64,
+    pub resolution: HtlcResolution,
+}
@@ -3,11 +3,20 @@ use bitcoin::Address;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{Amount, TransactionId};
 use fedimint_ln_common::gateway_endpoint_constants::{
-    BACKUP_ENDPOINT, BALANCE_ENDPOINT, CLOSE_CHANNELS_WITH_PEER_ENDPOINT, CONFIGURATION_ENDPOINT,
-    CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT, GATEWAY_INFO_ENDPOINT,
-    GATEWAY_INFO_POST_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT, LEAVE_FED_ENDPOINT,
-    LIST_ACTIVE_CHANNELS_ENDPOINT, OPEN_CHANNEL_ENDPOINT, RESTORE_ENDPOINT,
-    SET_CONFIGURATION_ENDPOINT, WITHDRAW_ENDPOINT,
+    APPLY_CONFIG_ENDPOINT, BACKUP_ENDPOINT, BALANCE_ENDPOINT, CLOSE_CHANNELS_WITH_PEER_ENDPOINT,
+    CLOSE_CHANNEL_ENDPOINT, CONFIGURATION_ENDPOINT, CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT,
+    EXPORT_CONNECTIONS_ENDPOINT, EXPORT_DB_ENDPOINT, GATEWAY_INFO_ENDPOINT,
+    GATEWAY_INFO_POST_ENDPOINT, GET_CHANNEL_ENDPOINT, GET_CONFIG_ENDPOINT, GET_EARNINGS_ENDPOINT,
+    GET_FEDERATION_POLICY_ENDPOINT, GET_FEE_MODE_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT,
+    GET_L402_CONFIG_ENDPOINT, GET_NOSTR_CONFIG_ENDPOINT, GET_PAYMENT_PROGRESS_ENDPOINT,
+    GET_RISK_STATUS_ENDPOINT, GET_SWAP_FEES_ENDPOINT, IMPORT_CONNECTIONS_ENDPOINT,
+    LEAVE_FED_ENDPOINT, LIST_ACTIVE_CHANNELS_ENDPOINT, LIST_CHANNELS_ENDPOINT,
+    LIST_PAYMENTS_ENDPOINT, LIST_PENDING_HTLCS_ENDPOINT, LIST_SCID_ALIASES_ENDPOINT,
+    NODE_STATS_ENDPOINT, OPEN_CHANNEL_ENDPOINT, REGISTER_PUBLIC_RECEIVER_ENDPOINT,
+    RESOLVE_HTLC_ENDPOINT, RESTORE_ENDPOINT, SET_CONFIGURATION_ENDPOINT,
+    SET_FEDERATION_POLICY_ENDPOINT, SET_FEDERATION_REGISTRATION_ENDPOINT, SET_FEE_MODE_ENDPOINT,
+    SET_L402_CONFIG_ENDPOINT, SET_NOSTR_CONFIG_ENDPOINT, SET_RISK_LIMITS_ENDPOINT,
+    SET_ROUTE_HINT_PRIVACY_ENDPOINT, SET_SWAP_FEES_ENDPOINT, SHUTDOWN_ENDPOINT, WITHDRAW_ENDPOINT,
 };
 use reqwest::{Method, StatusCode};
 use serde::de::DeserializeOwned;
@@ -15,12 +24,22 @@ use serde::Serialize;
 use thiserror::Error;
 
 use super::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConfigPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, FederationInfo, GatewayFedConfig, GatewayInfo,
-    GetFundingAddressPayload, LeaveFedPayload, OpenChannelPayload, RestorePayload,
-    SetConfigurationPayload, WithdrawPayload,
+    BackupPayload, BalancePayload, CloseChannelPayload, CloseChannelsWithPeerPayload,
+    ConfigPayload, ConnectFedPayload, ConnectToPeerPayload, DepositAddressPayload,
+    EncryptedConnections, ExportConnectionsPayload, ExportDbPayload, FederationInfo,
+    FederationPolicy, FeeMode, GatewayConfigFile, GatewayEarnings, GatewayFedConfig, GatewayInfo,
+    GetChannelPayload, GetFeeModePayload, GetFundingAddressPayload, GetPaymentProgressPayload,
+    GetSwapFeesPayload, ImportConnectionsPayload, L402ConfigInfo, LeaveFedPayload,
+    ListPaymentsPayload, NodeStats, NostrConfigInfo, OpenChannelPayload, PaymentProgress,
+    PaymentSummary, PendingHtlc, RegisterPublicReceiverPayload, ResolveHtlcPayload, RestorePayload,
+    RiskLimits, RiskStatus, ScidAliasInfo, SetConfigurationPayload,
+    SetFederationRegistrationPayload, SetFeeModePayload, SetL402ConfigPayload,
+    SetNostrConfigPayload, SetRouteHintPrivacyPayload, SetSwapFeesPayload, ShutdownPayload,
+    SwapFees, WithdrawPayload,
 };
-use crate::lightning::ChannelInfo;
+use crate::db::DrainReport;
+use crate::db_archive::EncryptedDbArchive;
+use crate::lightning::{ChannelDetails, ChannelInfo};
 use crate::CloseChannelsWithPeerResponse;
 
 pub struct GatewayRpcClient {
@@ -190,6 +209,281 @@ impl GatewayRpcClient {
         self.call_get(url).await
     }
 
+    pub async fn list_channels(&self) -> GatewayRpcResult<Vec<ChannelDetails>> {
+        let url = self
+            .base_url
+            .join(LIST_CHANNELS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn get_channel(
+        &self,
+        payload: GetChannelPayload,
+    ) -> GatewayRpcResult<ChannelDetails> {
+        let url = self
+            .base_url
+            .join(GET_CHANNEL_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn close_channel(&self, payload: CloseChannelPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(CLOSE_CHANNEL_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn register_public_receiver(
+        &self,
+        payload: RegisterPublicReceiverPayload,
+    ) -> GatewayRpcResult<String> {
+        let url = self
+            .base_url
+            .join(REGISTER_PUBLIC_RECEIVER_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_payments(
+        &self,
+        payload: ListPaymentsPayload,
+    ) -> GatewayRpcResult<Vec<PaymentSummary>> {
+        let url = self
+            .base_url
+            .join(LIST_PAYMENTS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_pending_htlcs(&self) -> GatewayRpcResult<Vec<PendingHtlc>> {
+        let url = self
+            .base_url
+            .join(LIST_PENDING_HTLCS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn resolve_htlc(&self, payload: ResolveHtlcPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(RESOLVE_HTLC_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn export_connections(
+        &self,
+        payload: ExportConnectionsPayload,
+    ) -> GatewayRpcResult<EncryptedConnections> {
+        let url = self
+            .base_url
+            .join(EXPORT_CONNECTIONS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn export_db(
+        &self,
+        payload: ExportDbPayload,
+    ) -> GatewayRpcResult<EncryptedDbArchive> {
+        let url = self
+            .base_url
+            .join(EXPORT_DB_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn import_connections(
+        &self,
+        payload: ImportConnectionsPayload,
+    ) -> GatewayRpcResult<Vec<FederationInfo>> {
+        let url = self
+            .base_url
+            .join(IMPORT_CONNECTIONS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn list_scid_aliases(&self) -> GatewayRpcResult<Vec<ScidAliasInfo>> {
+        let url = self
+            .base_url
+            .join(LIST_SCID_ALIASES_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn get_earnings(&self) -> GatewayRpcResult<GatewayEarnings> {
+        let url = self
+            .base_url
+            .join(GET_EARNINGS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn node_stats(&self) -> GatewayRpcResult<NodeStats> {
+        let url = self
+            .base_url
+            .join(NODE_STATS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn get_config(&self) -> GatewayRpcResult<GatewayConfigFile> {
+        let url = self
+            .base_url
+            .join(GET_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn apply_config(&self, payload: GatewayConfigFile) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(APPLY_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_federation_policy(&self) -> GatewayRpcResult<FederationPolicy> {
+        let url = self
+            .base_url
+            .join(GET_FEDERATION_POLICY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_federation_policy(&self, payload: FederationPolicy) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_FEDERATION_POLICY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_swap_fees(&self, payload: GetSwapFeesPayload) -> GatewayRpcResult<SwapFees> {
+        let url = self
+            .base_url
+            .join(GET_SWAP_FEES_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_swap_fees(&self, payload: SetSwapFeesPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_SWAP_FEES_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_fee_mode(&self, payload: GetFeeModePayload) -> GatewayRpcResult<FeeMode> {
+        let url = self
+            .base_url
+            .join(GET_FEE_MODE_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_fee_mode(&self, payload: SetFeeModePayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_FEE_MODE_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_route_hint_privacy(
+        &self,
+        payload: SetRouteHintPrivacyPayload,
+    ) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_ROUTE_HINT_PRIVACY_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn set_federation_registration(
+        &self,
+        payload: SetFederationRegistrationPayload,
+    ) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_FEDERATION_REGISTRATION_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_risk_status(&self) -> GatewayRpcResult<RiskStatus> {
+        let url = self
+            .base_url
+            .join(GET_RISK_STATUS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_risk_limits(&self, payload: RiskLimits) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_RISK_LIMITS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_nostr_config(&self) -> GatewayRpcResult<NostrConfigInfo> {
+        let url = self
+            .base_url
+            .join(GET_NOSTR_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_nostr_config(&self, payload: SetNostrConfigPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_NOSTR_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_l402_config(&self) -> GatewayRpcResult<L402ConfigInfo> {
+        let url = self
+            .base_url
+            .join(GET_L402_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_get(url).await
+    }
+
+    pub async fn set_l402_config(&self, payload: SetL402ConfigPayload) -> GatewayRpcResult<()> {
+        let url = self
+            .base_url
+            .join(SET_L402_CONFIG_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn get_payment_progress(
+        &self,
+        payload: GetPaymentProgressPayload,
+    ) -> GatewayRpcResult<Option<PaymentProgress>> {
+        let url = self
+            .base_url
+            .join(GET_PAYMENT_PROGRESS_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
+    pub async fn shutdown(&self, payload: ShutdownPayload) -> GatewayRpcResult<DrainReport> {
+        let url = self
+            .base_url
+            .join(SHUTDOWN_ENDPOINT)
+            .expect("invalid base url");
+        self.call_post(url, payload).await
+    }
+
     async fn call<P: Serialize, T: DeserializeOwned>(
         &self,
         method: Method,
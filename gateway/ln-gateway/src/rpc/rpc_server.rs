@@ -1,4 +1,7 @@
-use axum::extract::Request;
+use std::borrow::Cow;
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, Request};
 use axum::http::{header, StatusCode};
 use axum::middleware::{self, Next};
 use axum::response::IntoResponse;
@@ -9,35 +12,57 @@ use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::config::FederationId;
 use fedimint_core::encoding::Encodable;
 use fedimint_core::task::TaskGroup;
-use fedimint_ln_client::pay::PayInvoicePayload;
+use fedimint_core::time::duration_since_epoch;
+use fedimint_ln_client::pay::{PayInvoicePayload, PaymentData};
 use fedimint_ln_common::gateway_endpoint_constants::{
-    ADDRESS_ENDPOINT, BACKUP_ENDPOINT, BALANCE_ENDPOINT, CLOSE_CHANNELS_WITH_PEER_ENDPOINT,
-    CONFIGURATION_ENDPOINT, CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT,
-    CREATE_INVOICE_V2_ENDPOINT, GATEWAY_INFO_ENDPOINT, GATEWAY_INFO_POST_ENDPOINT,
-    GET_FUNDING_ADDRESS_ENDPOINT, GET_GATEWAY_ID_ENDPOINT, LEAVE_FED_ENDPOINT,
-    LIST_ACTIVE_CHANNELS_ENDPOINT, OPEN_CHANNEL_ENDPOINT, PAYMENT_INFO_V2_ENDPOINT,
-    PAY_INVOICE_ENDPOINT, RESTORE_ENDPOINT, SEND_PAYMENT_V2_ENDPOINT, SET_CONFIGURATION_ENDPOINT,
-    WITHDRAW_ENDPOINT,
+    ADDRESS_ENDPOINT, APPLY_CONFIG_ENDPOINT, BACKUP_ENDPOINT, BALANCE_ENDPOINT,
+    CLOSE_CHANNELS_WITH_PEER_ENDPOINT, CLOSE_CHANNEL_ENDPOINT, CONFIGURATION_ENDPOINT,
+    CONNECT_FED_ENDPOINT, CONNECT_TO_PEER_ENDPOINT, CREATE_INVOICE_V2_ENDPOINT,
+    CREATE_PUBLIC_INVOICE_ENDPOINT, EXPORT_CONNECTIONS_ENDPOINT, EXPORT_DB_ENDPOINT,
+    GATEWAY_EVENTS_ENDPOINT, GATEWAY_INFO_ENDPOINT, GATEWAY_INFO_POST_ENDPOINT,
+    GET_CHANNEL_ENDPOINT, GET_CONFIG_ENDPOINT, GET_EARNINGS_ENDPOINT,
+    GET_FEDERATION_POLICY_ENDPOINT, GET_FEE_MODE_ENDPOINT, GET_FUNDING_ADDRESS_ENDPOINT,
+    GET_GATEWAY_ID_ENDPOINT, GET_L402_CONFIG_ENDPOINT, GET_NOSTR_CONFIG_ENDPOINT,
+    GET_PAYMENT_PROGRESS_ENDPOINT, GET_RISK_STATUS_ENDPOINT, GET_SWAP_FEES_ENDPOINT,
+    IMPORT_CONNECTIONS_ENDPOINT, LEAVE_FED_ENDPOINT, LIST_ACTIVE_CHANNELS_ENDPOINT,
+    LIST_CHANNELS_ENDPOINT, LIST_PAYMENTS_ENDPOINT, LIST_PENDING_HTLCS_ENDPOINT,
+    LIST_SCID_ALIASES_ENDPOINT, NODE_STATS_ENDPOINT, OPEN_CHANNEL_ENDPOINT,
+    PAYMENT_INFO_V2_ENDPOINT, PAY_INVOICE_ENDPOINT, PAY_KEYSEND_ENDPOINT,
+    PUBLIC_INVOICE_CONTRACTS_ENDPOINT, REGISTER_PUBLIC_RECEIVER_ENDPOINT, RESOLVE_HTLC_ENDPOINT,
+    RESTORE_ENDPOINT, SEND_PAYMENT_V2_ENDPOINT, SET_CONFIGURATION_ENDPOINT,
+    SET_FEDERATION_POLICY_ENDPOINT, SET_FEDERATION_REGISTRATION_ENDPOINT, SET_FEE_MODE_ENDPOINT,
+    SET_L402_CONFIG_ENDPOINT, SET_NOSTR_CONFIG_ENDPOINT, SET_RISK_LIMITS_ENDPOINT,
+    SET_ROUTE_HINT_PRIVACY_ENDPOINT, SET_SWAP_FEES_ENDPOINT, SHUTDOWN_ENDPOINT, WITHDRAW_ENDPOINT,
+};
+use fedimint_lnv2_client::{
+    CreateInvoicePayload, PublicInvoiceContractsPayload, SendPaymentPayload,
 };
-use fedimint_lnv2_client::{CreateInvoicePayload, SendPaymentPayload};
 use hex::ToHex;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
 use tower_http::cors::CorsLayer;
-use tracing::{error, info, instrument};
+use tracing::{debug, error, info, instrument};
 
 use super::{
-    BackupPayload, BalancePayload, CloseChannelsWithPeerPayload, ConnectFedPayload,
-    ConnectToPeerPayload, DepositAddressPayload, GetFundingAddressPayload, InfoPayload,
-    LeaveFedPayload, OpenChannelPayload, RestorePayload, SetConfigurationPayload, WithdrawPayload,
-    V1_API_ENDPOINT,
+    BackupPayload, BalancePayload, CloseChannelPayload, CloseChannelsWithPeerPayload,
+    ConnectFedPayload, ConnectToPeerPayload, CreatePublicInvoicePayload, DepositAddressPayload,
+    ExportConnectionsPayload, ExportDbPayload, FederationPolicy, GatewayConfigFile,
+    GetChannelPayload, GetFeeModePayload, GetFundingAddressPayload, GetPaymentProgressPayload,
+    GetSwapFeesPayload, ImportConnectionsPayload, InfoPayload, LeaveFedPayload,
+    ListPaymentsPayload, OpenChannelPayload, RegisterPublicReceiverPayload, ResolveHtlcPayload,
+    RestorePayload, RiskLimits, SetConfigurationPayload, SetFederationRegistrationPayload,
+    SetFeeModePayload, SetL402ConfigPayload, SetNostrConfigPayload, SetRouteHintPrivacyPayload,
+    SetSwapFeesPayload, ShutdownPayload, WithdrawPayload, V1_API_ENDPOINT,
 };
+use crate::events::GatewayEventEnvelope;
 use crate::rpc::ConfigPayload;
-use crate::{Gateway, GatewayError};
+use crate::{l402, Gateway, GatewayError};
 
 /// Creates the webserver's routes and spawns the webserver in a separate task.
 pub async fn run_webserver(gateway: Gateway, task_group: &mut TaskGroup) -> anyhow::Result<()> {
-    let v1_routes = v1_routes(gateway.clone());
+    let v1_routes = v1_routes(gateway.clone(), task_group.clone());
     let api_v1 = Router::new()
         .nest(&format!("/{V1_API_ENDPOINT}"), v1_routes.clone())
         // Backwards compatibility: Continue supporting gateway APIs without versioning
@@ -138,6 +163,46 @@ async fn authenticate(
     Err(StatusCode::UNAUTHORIZED)
 }
 
+/// Middleware to require an L402 token for an incoming request if L402
+/// authentication is enabled, see [`crate::l402`]. Requests without a valid
+/// token are answered with `402 Payment Required` and a new challenge.
+async fn l402_middleware(
+    Extension(gateway): Extension<Gateway>,
+    request: Request,
+    next: Next,
+) -> Result<axum::response::Response, GatewayError> {
+    let Some(config) = gateway.l402_config().await else {
+        return Ok(next.run(request).await);
+    };
+
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(l402::parse_authorization);
+
+    match authorization {
+        Some(Ok((macaroon, preimage))) => {
+            let now = duration_since_epoch().as_secs();
+            match l402::verify_token(&config.root_key, &macaroon, preimage, now) {
+                Ok(()) => return Ok(next.run(request).await),
+                Err(e) => debug!("Rejecting L402 token: {e}"),
+            }
+        }
+        Some(Err(e)) => debug!("Rejecting L402 authorization: {e}"),
+        None => {}
+    }
+
+    let challenge = gateway.create_l402_challenge(&config).await?;
+
+    Ok((
+        StatusCode::PAYMENT_REQUIRED,
+        [(header::WWW_AUTHENTICATE, challenge)],
+        "Payment required",
+    )
+        .into_response())
+}
+
 /// Gateway Webserver Routes. The gateway supports three types of routes
 /// - Always Authenticated: these routes always require a Bearer token. Used by
 ///   gateway administrators.
@@ -146,15 +211,27 @@ async fn authenticate(
 /// to set a password. After setting the password, they become authenticated.
 /// - Un-authenticated: anyone can request these routes. Used by fedimint
 ///   clients.
-fn v1_routes(gateway: Gateway) -> Router {
+fn v1_routes(gateway: Gateway, task_group: TaskGroup) -> Router {
     // Public routes on gateway webserver
     let public_routes = Router::new()
         .route(PAY_INVOICE_ENDPOINT, post(pay_invoice))
+        .route(PAY_KEYSEND_ENDPOINT, post(pay_keysend))
         .route(GET_GATEWAY_ID_ENDPOINT, get(get_gateway_id))
         // These routes are for next generation lightning
         .route(PAYMENT_INFO_V2_ENDPOINT, post(payment_info_v2))
         .route(SEND_PAYMENT_V2_ENDPOINT, post(send_payment_v2))
-        .route(CREATE_INVOICE_V2_ENDPOINT, post(create_invoice_v2));
+        .route(CREATE_INVOICE_V2_ENDPOINT, post(create_invoice_v2))
+        .route(
+            PUBLIC_INVOICE_CONTRACTS_ENDPOINT,
+            post(public_invoice_contracts),
+        );
+
+    // Public routes that require an L402 token if the operator configured a
+    // price for them
+    let l402_routes = Router::new()
+        // Rate-limited per receiver token, see `Gateway::handle_create_public_invoice_msg`
+        .route(CREATE_PUBLIC_INVOICE_ENDPOINT, post(create_public_invoice))
+        .layer(middleware::from_fn(l402_middleware));
 
     // Authenticated, public routes used for gateway administration
     let always_authenticated_routes = Router::new()
@@ -173,6 +250,47 @@ fn v1_routes(gateway: Gateway) -> Router {
             post(close_channels_with_peer),
         )
         .route(LIST_ACTIVE_CHANNELS_ENDPOINT, get(list_active_channels))
+        .route(LIST_CHANNELS_ENDPOINT, get(list_channels))
+        .route(GET_CHANNEL_ENDPOINT, post(get_channel))
+        .route(CLOSE_CHANNEL_ENDPOINT, post(close_channel))
+        .route(LIST_PAYMENTS_ENDPOINT, post(list_payments))
+        .route(LIST_PENDING_HTLCS_ENDPOINT, get(list_pending_htlcs))
+        .route(RESOLVE_HTLC_ENDPOINT, post(resolve_htlc))
+        .route(LIST_SCID_ALIASES_ENDPOINT, get(list_scid_aliases))
+        .route(GET_EARNINGS_ENDPOINT, get(get_earnings))
+        .route(NODE_STATS_ENDPOINT, get(node_stats))
+        .route(GET_CONFIG_ENDPOINT, get(get_config))
+        .route(APPLY_CONFIG_ENDPOINT, post(apply_config))
+        .route(GET_FEDERATION_POLICY_ENDPOINT, get(get_federation_policy))
+        .route(SET_FEDERATION_POLICY_ENDPOINT, post(set_federation_policy))
+        .route(GET_SWAP_FEES_ENDPOINT, post(get_swap_fees))
+        .route(SET_SWAP_FEES_ENDPOINT, post(set_swap_fees))
+        .route(GET_FEE_MODE_ENDPOINT, post(get_fee_mode))
+        .route(SET_FEE_MODE_ENDPOINT, post(set_fee_mode))
+        .route(
+            SET_ROUTE_HINT_PRIVACY_ENDPOINT,
+            post(set_route_hint_privacy),
+        )
+        .route(
+            SET_FEDERATION_REGISTRATION_ENDPOINT,
+            post(set_federation_registration),
+        )
+        .route(GET_RISK_STATUS_ENDPOINT, get(get_risk_status))
+        .route(SET_RISK_LIMITS_ENDPOINT, post(set_risk_limits))
+        .route(GET_NOSTR_CONFIG_ENDPOINT, get(get_nostr_config))
+        .route(SET_NOSTR_CONFIG_ENDPOINT, post(set_nostr_config))
+        .route(GET_L402_CONFIG_ENDPOINT, get(get_l402_config))
+        .route(SET_L402_CONFIG_ENDPOINT, post(set_l402_config))
+        .route(GET_PAYMENT_PROGRESS_ENDPOINT, post(get_payment_progress))
+        .route(EXPORT_CONNECTIONS_ENDPOINT, post(export_connections))
+        .route(IMPORT_CONNECTIONS_ENDPOINT, post(import_connections))
+        .route(EXPORT_DB_ENDPOINT, post(export_db))
+        .route(
+            REGISTER_PUBLIC_RECEIVER_ENDPOINT,
+            post(register_public_receiver),
+        )
+        .route(GATEWAY_EVENTS_ENDPOINT, get(events))
+        .route(SHUTDOWN_ENDPOINT, post(shutdown))
         .layer(middleware::from_fn(auth_middleware));
 
     // Routes that are un-authenticated before gateway configuration, then become
@@ -187,9 +305,11 @@ fn v1_routes(gateway: Gateway) -> Router {
 
     Router::new()
         .merge(public_routes)
+        .merge(l402_routes)
         .merge(always_authenticated_routes)
         .merge(authenticated_after_config_routes)
         .layer(Extension(gateway))
+        .layer(Extension(task_group))
         .layer(CorsLayer::permissive())
 }
 
@@ -250,6 +370,38 @@ async fn balance(
     Ok(Json(json!(amount)))
 }
 
+/// List finished payments from the gateway's payment history
+#[debug_handler]
+#[instrument(skip_all, err, fields(?payload))]
+async fn list_payments(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ListPaymentsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let payments = gateway.handle_list_payments_msg(payload).await?;
+    Ok(Json(json!(payments)))
+}
+
+/// List intercepted HTLCs that are not settled or cancelled yet
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn list_pending_htlcs(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let htlcs = gateway.handle_list_pending_htlcs_msg().await?;
+    Ok(Json(json!(htlcs)))
+}
+
+/// Manually settle or cancel a pending HTLC
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn resolve_htlc(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ResolveHtlcPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_resolve_htlc_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
 /// Generate deposit address
 #[debug_handler]
 #[instrument(skip_all, err, fields(?payload))]
@@ -281,6 +433,19 @@ async fn pay_invoice(
     Ok(Json(json!(preimage.0.encode_hex::<String>())))
 }
 
+/// Send a spontaneous payment on behalf of a federation user
+#[instrument(skip_all, err, fields(?payload))]
+async fn pay_keysend(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PayInvoicePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    if !matches!(payload.payment_data, PaymentData::Keysend(_)) {
+        return Err(anyhow::anyhow!("Payload is not a keysend payment").into());
+    }
+    let preimage = gateway.handle_pay_invoice_msg(payload).await?;
+    Ok(Json(json!(preimage.0.encode_hex::<String>())))
+}
+
 /// Connect a new federation
 #[instrument(skip_all, err, fields(?payload))]
 async fn connect_fed(
@@ -301,6 +466,17 @@ async fn leave_fed(
     Ok(Json(json!(fed)))
 }
 
+/// Drain in-flight payments and shut down the gateway
+#[instrument(skip_all, err, fields(?payload))]
+async fn shutdown(
+    Extension(gateway): Extension<Gateway>,
+    Extension(task_group): Extension<TaskGroup>,
+    Json(payload): Json<ShutdownPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let report = gateway.handle_shutdown_msg(payload, &task_group).await?;
+    Ok(Json(json!(report)))
+}
+
 /// Backup a gateway actor state
 #[instrument(skip_all, err, fields(?payload))]
 async fn backup(
@@ -374,6 +550,237 @@ async fn list_active_channels(
     Ok(Json(json!(channels)))
 }
 
+#[instrument(skip_all, err)]
+async fn list_channels(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let channels = gateway.handle_list_channels_msg().await?;
+    Ok(Json(json!(channels)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn get_channel(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GetChannelPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let channel = gateway.handle_get_channel_msg(payload).await?;
+    Ok(Json(json!(channel)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn close_channel(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<CloseChannelPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_close_channel_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn list_scid_aliases(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let aliases = gateway.handle_list_scid_aliases_msg().await?;
+    Ok(Json(json!(aliases)))
+}
+
+#[instrument(skip_all, err)]
+async fn get_earnings(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let earnings = gateway.handle_get_earnings_msg().await?;
+    Ok(Json(json!(earnings)))
+}
+
+#[instrument(skip_all, err)]
+async fn node_stats(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let stats = gateway.handle_node_stats_msg().await?;
+    Ok(Json(json!(stats)))
+}
+
+#[instrument(skip_all, err)]
+async fn get_config(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let config = gateway.handle_get_config_msg().await?;
+    Ok(Json(json!(config)))
+}
+
+// The payload may contain the gateway password, so it is not logged
+#[instrument(skip_all, err)]
+async fn apply_config(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GatewayConfigFile>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_apply_config_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn get_federation_policy(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let policy = gateway.federation_policy().await;
+    Ok(Json(json!(policy)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_federation_policy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<FederationPolicy>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_federation_policy_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn get_swap_fees(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GetSwapFeesPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let fees = gateway.swap_fees(&payload.federation_id).await;
+    Ok(Json(json!(fees)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_swap_fees(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetSwapFeesPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_swap_fees_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn get_fee_mode(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GetFeeModePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let fee_mode = gateway.fee_mode(&payload.federation_id).await;
+    Ok(Json(json!(fee_mode)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_fee_mode(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFeeModePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_fee_mode_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_route_hint_privacy(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetRouteHintPrivacyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_route_hint_privacy_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_federation_registration(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetFederationRegistrationPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway
+        .handle_set_federation_registration_msg(payload)
+        .await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn get_risk_status(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let status = gateway.risk_status().await;
+    Ok(Json(json!(status)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_risk_limits(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<RiskLimits>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_risk_limits_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn get_nostr_config(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let config = gateway.handle_get_nostr_config_msg().await;
+    Ok(Json(json!(config)))
+}
+
+// The payload is not logged since it contains the nostr secret key
+#[instrument(skip_all, err)]
+async fn set_nostr_config(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetNostrConfigPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_nostr_config_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err)]
+async fn get_l402_config(
+    Extension(gateway): Extension<Gateway>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let config = gateway.handle_get_l402_config_msg().await;
+    Ok(Json(json!(config)))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn set_l402_config(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<SetL402ConfigPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    gateway.handle_set_l402_config_msg(payload).await?;
+    Ok(Json(json!(())))
+}
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn get_payment_progress(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<GetPaymentProgressPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let progress = gateway.payment_progress(&payload.payment_hash);
+    Ok(Json(json!(progress)))
+}
+
+// The payloads contain the export password, so they are not logged
+#[instrument(skip_all, err)]
+async fn export_connections(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ExportConnectionsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let connections = gateway.handle_export_connections_msg(payload).await?;
+    Ok(Json(json!(connections)))
+}
+
+#[instrument(skip_all, err)]
+async fn import_connections(
+    Extension(mut gateway): Extension<Gateway>,
+    Json(payload): Json<ImportConnectionsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let federations = gateway.handle_import_connections_msg(payload).await?;
+    Ok(Json(json!(federations)))
+}
+
+// The payload contains the export password, so it is not logged
+#[instrument(skip_all, err)]
+async fn export_db(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<ExportDbPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let archive = gateway.handle_export_db_msg(payload).await?;
+    Ok(Json(json!(archive)))
+}
+
 #[instrument(skip_all, err)]
 async fn get_gateway_id(
     Extension(gateway): Extension<Gateway>,
@@ -407,3 +814,106 @@ async fn create_invoice_v2(
         .await
         .map_err(|e| e.to_string())))
 }
+
+#[instrument(skip_all, err, fields(?payload))]
+async fn register_public_receiver(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<RegisterPublicReceiverPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let token = gateway.handle_register_public_receiver_msg(payload).await?;
+    Ok(Json(json!(token)))
+}
+
+// The payload contains the receiver's token, so it is not logged
+#[instrument(skip_all, err)]
+async fn create_public_invoice(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<CreatePublicInvoicePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let invoice = gateway.handle_create_public_invoice_msg(payload).await?;
+    Ok(Json(json!(invoice)))
+}
+
+// The payload contains the receiver's token, so it is not logged
+#[instrument(skip_all, err)]
+async fn public_invoice_contracts(
+    Extension(gateway): Extension<Gateway>,
+    Json(payload): Json<PublicInvoiceContractsPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let contracts = gateway.handle_public_invoice_contracts_msg(payload).await?;
+    Ok(Json(json!(contracts)))
+}
+
+/// Websocket close code sent when the requested cursor is no longer buffered
+/// or the subscriber fell too far behind the event stream
+const CURSOR_EXPIRED_CLOSE_CODE: u16 = 4000;
+
+#[derive(Debug, Deserialize)]
+struct EventStreamQuery {
+    /// Cursor of the last event the subscriber has seen
+    cursor: Option<u64>,
+}
+
+/// Upgrades the connection to a websocket streaming `GatewayEventEnvelope`s
+/// as JSON text messages. If a `cursor` is given, buffered events after it are
+/// replayed first.
+async fn events(
+    Extension(gateway): Extension<Gateway>,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| stream_events(gateway, query.cursor, socket))
+}
+
+async fn stream_events(gateway: Gateway, cursor: Option<u64>, mut socket: WebSocket) {
+    let Ok((backlog, mut receiver)) = gateway.events.subscribe(cursor) else {
+        close_cursor_expired(socket).await;
+        return;
+    };
+
+    for envelope in &backlog {
+        if send_event(&mut socket, envelope).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(envelope) => {
+                    if send_event(&mut socket, &envelope).await.is_err() {
+                        return;
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {
+                    close_cursor_expired(socket).await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                // Subscribers are not expected to send anything but pings
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send_event(
+    socket: &mut WebSocket,
+    envelope: &GatewayEventEnvelope,
+) -> Result<(), axum::Error> {
+    let json = serde_json::to_string(envelope).expect("JSON serialization should not fail");
+    socket.send(Message::Text(json)).await
+}
+
+async fn close_cursor_expired(mut socket: WebSocket) {
+    info!("Closing event stream, subscriber cursor expired");
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: CURSOR_EXPIRED_CLOSE_CODE,
+            reason: Cow::Borrowed("cursor expired"),
+        })))
+        .await;
+}
@@ -0,0 +1,212 @@
+//! Short channel ids the gateway registers with federations
+//!
+//! By default invoices of a federation route the payment through the SCID
+//! alias of the federation. Operators can register a SCID base with a
+//! federation instead, which looks like the id of a channel funded by a real
+//! transaction, so that routing policies of the node can match all SCIDs of a
+//! federation by their funding transaction. The SCIDs derived from a base only
+//! differ in the output index, and are used as phantom SCIDs of the federation
+//! if route hint privacy is enabled for it.
+
+use std::collections::BTreeMap;
+
+use fedimint_core::config::FederationId;
+use rand::Rng;
+use thiserror::Error;
+
+/// Bits of a short channel id encoding the output index of the funding
+/// transaction, the remaining bits encode the funding block height and the
+/// index of the transaction in the block
+pub const SCID_OUTPUT_INDEX_MASK: u64 = 0xffff;
+
+/// SCID bases have to encode a funding block height of at least 1, lower SCIDs
+/// are reserved for SCID aliases
+pub const MIN_SCID_BASE: u64 = 1 << 40;
+
+/// Reason a SCID base can't be registered with a federation
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum ScidBaseError {
+    #[error("SCID base {0} has to encode a funding block height of at least 1")]
+    ReservedForAliases(u64),
+    #[error("SCID base {0} has to encode an output index of 0")]
+    NonZeroOutputIndex(u64),
+    #[error("SCID base {scid_base} collides with channel {scid} of the lightning node")]
+    UsedByChannel { scid_base: u64, scid: u64 },
+    #[error("SCID base {scid_base} collides with SCID {scid} of federation {federation_id}")]
+    UsedByFederation {
+        scid_base: u64,
+        scid: u64,
+        federation_id: FederationId,
+    },
+}
+
+/// Returns the SCID base `scid` is derived from
+pub fn scid_base(scid: u64) -> u64 {
+    scid & !SCID_OUTPUT_INDEX_MASK
+}
+
+/// Checks that `scid_base` can be registered with `federation_id`, i.e. that
+/// no SCID derived from it is used by a channel of the lightning node or by
+/// another federation. `federation_scids` contains the SCID aliases, phantom
+/// SCIDs and registered SCID bases of all federations.
+pub fn ensure_scid_base_available(
+    scid_base: u64,
+    federation_id: FederationId,
+    channel_scids: impl IntoIterator<Item = u64>,
+    federation_scids: &BTreeMap<u64, FederationId>,
+) -> Result<(), ScidBaseError> {
+    if scid_base < MIN_SCID_BASE {
+        return Err(ScidBaseError::ReservedForAliases(scid_base));
+    }
+
+    if scid_base & SCID_OUTPUT_INDEX_MASK != 0 {
+        return Err(ScidBaseError::NonZeroOutputIndex(scid_base));
+    }
+
+    if let Some(scid) = channel_scids
+        .into_iter()
+        .find(|scid| self::scid_base(*scid) == scid_base)
+    {
+        return Err(ScidBaseError::UsedByChannel { scid_base, scid });
+    }
+
+    if let Some((scid, other)) = federation_scids
+        .range(scid_base..=scid_base | SCID_OUTPUT_INDEX_MASK)
+        .find(|(_, other)| **other != federation_id)
+    {
+        return Err(ScidBaseError::UsedByFederation {
+            scid_base,
+            scid: *scid,
+            federation_id: *other,
+        });
+    }
+
+    Ok(())
+}
+
+/// Generates a random SCID derived from `scid_base`, which is never the base
+/// itself
+pub fn random_scid_from_base(scid_base: u64) -> u64 {
+    scid_base | rand::thread_rng().gen_range(1..=SCID_OUTPUT_INDEX_MASK)
+}
+
+/// Returns the SCID the gateway registers with a federation, `None` meaning
+/// its SCID alias. If route hint privacy is enabled for the federation its
+/// phantom SCID is used, which is derived from the registered SCID base if
+/// there is one. Otherwise the SCID base itself is used.
+pub fn registered_scid(scid_base: Option<u64>, phantom_scid: Option<u64>) -> Option<u64> {
+    match (scid_base, phantom_scid) {
+        (Some(base), Some(phantom_scid)) if self::scid_base(phantom_scid) != base => Some(base),
+        (_, Some(phantom_scid)) => Some(phantom_scid),
+        (scid_base, None) => scid_base,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::{sha256, Hash};
+    use fedimint_core::config::FederationId;
+
+    use super::{
+        ensure_scid_base_available, random_scid_from_base, registered_scid, scid_base,
+        ScidBaseError, MIN_SCID_BASE,
+    };
+
+    const BASE: u64 = (800_000 << 40) | (42 << 16);
+
+    #[test]
+    fn scid_bases_have_to_look_like_funding_transactions() {
+        let federation_id = FederationId::dummy();
+
+        assert_eq!(
+            ensure_scid_base_available(7, federation_id, [], &BTreeMap::new()),
+            Err(ScidBaseError::ReservedForAliases(7))
+        );
+        assert_eq!(
+            ensure_scid_base_available(BASE | 1, federation_id, [], &BTreeMap::new()),
+            Err(ScidBaseError::NonZeroOutputIndex(BASE | 1))
+        );
+        assert_eq!(
+            ensure_scid_base_available(MIN_SCID_BASE, federation_id, [], &BTreeMap::new()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn scid_bases_must_not_collide_with_channels() {
+        let federation_id = FederationId::dummy();
+
+        assert_eq!(
+            ensure_scid_base_available(BASE, federation_id, [BASE | 1], &BTreeMap::new()),
+            Err(ScidBaseError::UsedByChannel {
+                scid_base: BASE,
+                scid: BASE | 1
+            })
+        );
+        assert_eq!(
+            ensure_scid_base_available(
+                BASE,
+                federation_id,
+                [BASE - 1, BASE + (1 << 16)],
+                &BTreeMap::new()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn scid_bases_must_not_collide_with_other_federations() {
+        let federation_id = FederationId::dummy();
+        let other = FederationId(sha256::Hash::hash(b"other"));
+
+        // The base of another federation or one of its phantom SCIDs
+        for scid in [BASE, BASE | 0xffff] {
+            assert_eq!(
+                ensure_scid_base_available(
+                    BASE,
+                    federation_id,
+                    [],
+                    &BTreeMap::from([(scid, other)])
+                ),
+                Err(ScidBaseError::UsedByFederation {
+                    scid_base: BASE,
+                    scid,
+                    federation_id: other
+                })
+            );
+        }
+
+        // The SCIDs of the federation itself and those outside of the base
+        let federation_scids = BTreeMap::from([
+            (1, other),
+            (BASE, federation_id),
+            (BASE | 3, federation_id),
+            (BASE + (1 << 16), other),
+        ]);
+        assert_eq!(
+            ensure_scid_base_available(BASE, federation_id, [], &federation_scids),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn random_scids_are_derived_from_the_base() {
+        for _ in 0..1000 {
+            let scid = random_scid_from_base(BASE);
+            assert_eq!(scid_base(scid), BASE);
+            assert_ne!(scid, BASE);
+        }
+    }
+
+    #[test]
+    fn phantom_scids_are_registered_if_derived_from_the_base() {
+        assert_eq!(registered_scid(None, None), None);
+        assert_eq!(registered_scid(None, Some(5)), Some(5));
+        assert_eq!(registered_scid(Some(BASE), None), Some(BASE));
+        assert_eq!(registered_scid(Some(BASE), Some(BASE | 7)), Some(BASE | 7));
+        // Phantom SCIDs assigned before the base was registered ignore it
+        assert_eq!(registered_scid(Some(BASE), Some(5)), Some(BASE));
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use fedimint_client::sm::{State, StateTransition};
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
@@ -13,6 +13,7 @@ use thiserror::Error;
 use tracing::{debug, info, warn};
 
 use super::{GatewayClientContext, GatewayClientStateMachines};
+use crate::earnings::settle_pending_earnings;
 use crate::gateway_lnrpc::intercept_htlc_response::{Action, Cancel, Settle};
 use crate::gateway_lnrpc::InterceptHtlcResponse;
 
@@ -164,9 +165,17 @@ impl CompleteHtlcState {
         context: GatewayClientContext,
         common: GatewayCompleteCommon,
     ) -> Vec<StateTransition<GatewayCompleteStateMachine>> {
+        let settled = matches!(self.outcome, HtlcOutcome::Success(..));
         vec![StateTransition::new(
             Self::await_complete_htlc(context, common.clone(), self.outcome.clone()),
-            move |_dbtx, result, _| Box::pin(Self::transition_success(result, common.clone())),
+            move |dbtx, result, _| {
+                Box::pin(Self::transition_success(
+                    dbtx,
+                    result,
+                    settled,
+                    common.clone(),
+                ))
+            },
         )]
     }
 
@@ -177,6 +186,15 @@ impl CompleteHtlcState {
     ) -> Result<(), CompleteHtlcError> {
         // Wait until the lightning node is online to complete the HTLC
         loop {
+            if context
+                .gateway
+                .is_htlc_manually_resolved(common.incoming_chan_id, common.htlc_id)
+                .await
+            {
+                warn!("HTLC was resolved manually, not completing it");
+                return Ok(());
+            }
+
             let htlc_outcome = outcome.clone();
             let lightning_context = context.gateway.get_lightning_context().await;
             match lightning_context {
@@ -196,12 +214,26 @@ impl CompleteHtlcState {
                         },
                     };
 
-                    lightning_context
+                    let result = lightning_context
                         .lnrpc
                         .complete_htlc(htlc)
                         .await
-                        .map_err(|_| CompleteHtlcError::FailedToCompleteHtlc)?;
-                    return Ok(());
+                        .map_err(|_| CompleteHtlcError::FailedToCompleteHtlc);
+
+                    let payment_result = match (&result, outcome) {
+                        (Ok(()), HtlcOutcome::Success(_)) => Ok(()),
+                        (Ok(()), HtlcOutcome::Failure(error)) => Err(error),
+                        (Err(error), _) => Err(error.to_string()),
+                    };
+                    context
+                        .gateway
+                        .finish_incoming_payment(
+                            common.incoming_chan_id,
+                            common.htlc_id,
+                            payment_result,
+                        )
+                        .await;
+                    return result;
                 }
                 Err(e) => {
                     warn!("Trying to complete HTLC but got {e}, will keep retrying...");
@@ -212,9 +244,19 @@ impl CompleteHtlcState {
     }
 
     async fn transition_success(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         result: Result<(), CompleteHtlcError>,
+        settled: bool,
         common: GatewayCompleteCommon,
     ) -> GatewayCompleteStateMachine {
+        // The fee is only earned if the HTLC was settled with the preimage
+        settle_pending_earnings(
+            &mut dbtx.module_tx(),
+            common.operation_id,
+            settled && result.is_ok(),
+        )
+        .await;
+
         match result {
             Ok(_) => GatewayCompleteStateMachine {
                 common,
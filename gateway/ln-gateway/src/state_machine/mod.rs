@@ -52,6 +52,7 @@ use self::pay::{
     GatewayPayCommon, GatewayPayInvoice, GatewayPayStateMachine, GatewayPayStates,
     OutgoingPaymentError,
 };
+use crate::earnings::{dump_earnings, load_earnings, start_pending_earnings, FeeDirection};
 use crate::gateway_lnrpc::InterceptHtlcRequest;
 use crate::state_machine::complete::{
     GatewayCompleteCommon, GatewayCompleteStates, WaitForPreimageState,
@@ -120,10 +121,10 @@ impl ModuleInit for GatewayClientInit {
 
     async fn dump_database(
         &self,
-        _dbtx: &mut DatabaseTransaction<'_>,
-        _prefix_names: Vec<String>,
+        dbtx: &mut DatabaseTransaction<'_>,
+        prefix_names: Vec<String>,
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
-        Box::new(vec![].into_iter())
+        Box::new(dump_earnings(dbtx, &prefix_names).await.into_iter())
     }
 }
 
@@ -233,13 +234,14 @@ impl GatewayClientModule {
     fn to_gateway_registration_info(
         &self,
         route_hints: Vec<RouteHint>,
+        scid: Option<u64>,
         ttl: Duration,
         fees: RoutingFees,
         lightning_context: LightningContext,
     ) -> LightningGatewayAnnouncement {
         LightningGatewayAnnouncement {
             info: LightningGateway {
-                mint_channel_id: self.mint_channel_id,
+                mint_channel_id: scid.unwrap_or(self.mint_channel_id),
                 gateway_redeem_key: self.redeem_key.public_key(),
                 node_pub_key: lightning_context.lightning_public_key,
                 lightning_alias: lightning_context.lightning_alias,
@@ -338,16 +340,24 @@ impl GatewayClientModule {
         Ok((operation_id, client_output))
     }
 
-    /// Register gateway with federation
+    /// Register gateway with federation. If `scid` is set, invoices route the
+    /// payment to the federation through it instead of the SCID alias of the
+    /// federation, e.g. a phantom SCID or a SCID registered by the operator.
     pub async fn register_with_federation(
         &self,
         route_hints: Vec<RouteHint>,
+        scid: Option<u64>,
         time_to_live: Duration,
         fees: RoutingFees,
         lightning_context: LightningContext,
     ) -> anyhow::Result<()> {
-        let registration_info =
-            self.to_gateway_registration_info(route_hints, time_to_live, fees, lightning_context);
+        let registration_info = self.to_gateway_registration_info(
+            route_hints,
+            scid,
+            time_to_live,
+            fees,
+            lightning_context,
+        );
         let gateway_id = registration_info.info.gateway_id;
 
         let federation_id = self
@@ -409,6 +419,12 @@ impl GatewayClientModule {
         Ok(())
     }
 
+    /// Returns the routing fees earned by payments of this federation handled
+    /// by the gateway, per direction
+    pub async fn earnings(&self) -> BTreeMap<FeeDirection, Amount> {
+        load_earnings(&mut self.client_ctx.module_db().begin_transaction_nc().await).await
+    }
+
     /// Attempt fulfill HTLC by buying preimage from the federation
     pub async fn gateway_handle_intercepted_htlc(&self, htlc: Htlc) -> anyhow::Result<OperationId> {
         debug!("Handling intercepted HTLC {htlc:?}");
@@ -416,6 +432,16 @@ impl GatewayClientModule {
             .create_funding_incoming_contract_output_from_htlc(htlc.clone())
             .await?;
 
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        start_pending_earnings(
+            &mut dbtx.to_ref_nc(),
+            operation_id,
+            FeeDirection::Incoming,
+            htlc.incoming_amount_msat.saturating_sub(amount),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
         let output = ClientOutput {
             output: LightningOutput::V0(client_output.output),
             amount,
@@ -530,6 +556,13 @@ impl GatewayClientModule {
             );
         }
 
+        if matches!(pay_invoice_payload.payment_data, PaymentData::Keysend(_)) {
+            ensure!(
+                lightning_context.lnrpc.supports_keysend(),
+                "Keysend payments are not supported by the lightning node"
+            );
+        }
+
         self.client_ctx
             .module_autocommit(
                 |dbtx, _| {
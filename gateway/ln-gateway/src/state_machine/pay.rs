@@ -25,10 +25,14 @@ use tracing::{debug, error, info, warn, Instrument};
 
 use super::{GatewayClientContext, GatewayClientStateMachines, GatewayExtReceiveStates};
 use crate::db::{FederationIdKey, PreimageAuthentication};
+use crate::earnings::{
+    outgoing_fee, settle_pending_earnings, start_pending_earnings, FeeDirection,
+};
 use crate::gateway_lnrpc::{PayInvoiceRequest, PayInvoiceResponse};
 use crate::lightning::LightningRpcError;
+use crate::rpc::FeeMode;
 use crate::state_machine::GatewayClientModule;
-use crate::{GatewayState, RoutingFees};
+use crate::RoutingFees;
 
 #[cfg_attr(doc, aquamarine::aquamarine)]
 /// State machine that executes the Lightning payment on behalf of
@@ -137,6 +141,8 @@ pub enum OutgoingContractError {
     MissingContractData,
     #[error("The invoice is expired. Expiry happened at timestamp: {0}")]
     InvoiceExpired(u64),
+    #[error("The contract's hash does not match the payment hash")]
+    PaymentHashMismatch,
 }
 
 #[derive(
@@ -192,16 +198,51 @@ impl GatewayPayInvoice {
                 context.clone(),
                 common.clone(),
             ),
-            move |_dbtx, result, _old_state| Box::pin(futures::future::ready(result)),
+            move |dbtx, (next_state, routing_fee), _old_state| {
+                Box::pin(Self::transition_bought_preimage(
+                    dbtx,
+                    payload.clone(),
+                    next_state,
+                    routing_fee,
+                ))
+            },
         )]
     }
 
+    /// Remembers the fee charged for the payment once the preimage has been
+    /// bought, minus the `routing_fee` the lightning node paid to the network.
+    /// It is earned when the outgoing contract is claimed.
+    async fn transition_bought_preimage(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        payload: PayInvoicePayload,
+        next_state: GatewayPayStateMachine,
+        routing_fee: Amount,
+    ) -> GatewayPayStateMachine {
+        let contract = match &next_state.state {
+            GatewayPayStates::ClaimOutgoingContract(claim) => Some(&claim.contract),
+            GatewayPayStates::WaitForSwapPreimage(wait) => Some(&wait.contract),
+            _ => None,
+        };
+
+        if let (Some(contract), Some(payment_amount)) = (contract, payload.payment_data.amount()) {
+            start_pending_earnings(
+                &mut dbtx.module_tx(),
+                next_state.common.operation_id,
+                FeeDirection::Outgoing,
+                outgoing_fee(contract.amount, payment_amount, routing_fee),
+            )
+            .await;
+        }
+
+        next_state
+    }
+
     async fn fetch_parameters_and_pay(
         global_context: DynGlobalClientContext,
         pay_invoice_payload: PayInvoicePayload,
         context: GatewayClientContext,
         common: GatewayPayCommon,
-    ) -> GatewayPayStateMachine {
+    ) -> (GatewayPayStateMachine, Amount) {
         match Self::await_get_payment_parameters(
             global_context,
             context.clone(),
@@ -223,7 +264,7 @@ impl GatewayPayInvoice {
             }
             Err(e) => {
                 warn!("Failed to get payment parameters: {e:?}");
-                match e.contract.clone() {
+                let state = match e.contract.clone() {
                     Some(contract) => GatewayPayStateMachine {
                         common,
                         state: GatewayPayStates::CancelContract(Box::new(
@@ -234,7 +275,8 @@ impl GatewayPayInvoice {
                         common,
                         state: GatewayPayStates::OfferDoesNotExist(e.contract_id),
                     },
-                }
+                };
+                (state, Amount::ZERO)
             }
         }
     }
@@ -245,7 +287,7 @@ impl GatewayPayInvoice {
         payment_parameters: PaymentParameters,
         common: GatewayPayCommon,
         payload: PayInvoicePayload,
-    ) -> GatewayPayStateMachine {
+    ) -> (GatewayPayStateMachine, Amount) {
         debug!("Buying preimage contract {contract:?}");
         // Verify that this client is authorized to receive the preimage.
         if let Err(err) = Self::verify_preimage_authentication(
@@ -257,20 +299,22 @@ impl GatewayPayInvoice {
         .await
         {
             warn!("Preimage authentication failed: {err} for contract {contract:?}");
-            return GatewayPayStateMachine {
+            let state = GatewayPayStateMachine {
                 common,
                 state: GatewayPayStates::CancelContract(Box::new(GatewayPayCancelContract {
                     contract,
                     error: err,
                 })),
             };
+            return (state, Amount::ZERO);
         }
 
         if let Some(client) =
             Self::check_swap_to_federation(context.clone(), payment_parameters.payment_data.clone())
                 .await
         {
-            client
+            // Swaps between federations don't pay any lightning routing fees
+            let state = client
                 .with(|client| {
                     Self::buy_preimage_via_direct_swap(
                         client,
@@ -279,7 +323,8 @@ impl GatewayPayInvoice {
                         common.clone(),
                     )
                 })
-                .await
+                .await;
+            (state, Amount::ZERO)
         } else {
             Self::buy_preimage_over_lightning(
                 context,
@@ -347,7 +392,7 @@ impl GatewayPayInvoice {
                     contract_id,
                     contract: Some(outgoing_contract_account.clone()),
                 })?;
-            let routing_fees = config.fees;
+            let fee_mode = context.gateway.fee_mode(&federation_id).await;
 
             let payment_parameters = Self::validate_outgoing_account(
                 &outgoing_contract_account,
@@ -355,7 +400,8 @@ impl GatewayPayInvoice {
                 context.timelock_delta,
                 consensus_block_count.unwrap(),
                 &payment_data,
-                routing_fees,
+                config.fees,
+                &fee_mode,
             )
             .map_err(|e| {
                 warn!("Invalid outgoing contract: {e:?}");
@@ -382,7 +428,7 @@ impl GatewayPayInvoice {
         buy_preimage: PaymentParameters,
         contract: OutgoingContractAccount,
         common: GatewayPayCommon,
-    ) -> GatewayPayStateMachine {
+    ) -> (GatewayPayStateMachine, Amount) {
         debug!("Buying preimage over lightning for contract {contract:?}");
         let payment_data = buy_preimage.payment_data.clone();
 
@@ -396,35 +442,73 @@ impl GatewayPayInvoice {
         let lightning_context = match context.gateway.get_lightning_context().await {
             Ok(lightning_context) => lightning_context,
             Err(error) => {
-                return Self::gateway_pay_cancel_contract(error, contract, common);
+                return (
+                    Self::gateway_pay_cancel_contract(error, contract, common),
+                    Amount::ZERO,
+                );
             }
         };
 
-        let payment_result = match buy_preimage.payment_data {
+        let payment_hash = payment_data.payment_hash();
+        let payment_result = match &buy_preimage.payment_data {
             PaymentData::Invoice(invoice) => {
-                lightning_context
-                    .lnrpc
-                    .pay(PayInvoiceRequest {
-                        invoice: invoice.to_string(),
-                        max_delay,
-                        max_fee_msat: max_fee.msats,
-                        payment_hash: payment_data.payment_hash().to_byte_array().to_vec(),
+                context
+                    .gateway
+                    .pay_with_progress(payment_hash, |updates| {
+                        lightning_context.lnrpc.pay_with_updates(
+                            PayInvoiceRequest {
+                                invoice: invoice.to_string(),
+                                max_delay,
+                                max_fee_msat: max_fee.msats,
+                                payment_hash: payment_hash.to_byte_array().to_vec(),
+                            },
+                            updates,
+                        )
                     })
                     .await
             }
             PaymentData::PrunedInvoice(invoice) => {
+                context
+                    .gateway
+                    .pay_with_progress(payment_hash, |updates| {
+                        lightning_context.lnrpc.pay_private_with_updates(
+                            invoice.clone(),
+                            buy_preimage.max_delay,
+                            max_fee,
+                            updates,
+                        )
+                    })
+                    .await
+            }
+            PaymentData::Keysend(keysend) => {
+                // The contract is only claimed with the preimage the lightning node
+                // revealed by sending the payment, so a backend that chose a
+                // preimage of its own cancels the contract instead of claiming it
+                // with the one supplied by the user
                 lightning_context
                     .lnrpc
-                    .pay_private(invoice, buy_preimage.max_delay, max_fee)
+                    .pay_keysend(keysend.clone(), max_delay, max_fee)
                     .await
+                    .and_then(|response| {
+                        if sha256::Hash::hash(&response.preimage) == keysend.payment_hash() {
+                            Ok(response)
+                        } else {
+                            Err(LightningRpcError::FailedPayment {
+                                failure_reason: "Keysend was sent with a different preimage"
+                                    .to_string(),
+                            })
+                        }
+                    })
             }
         };
 
         match payment_result {
-            Ok(PayInvoiceResponse { preimage, .. }) => {
+            Ok(PayInvoiceResponse {
+                preimage, fee_msat, ..
+            }) => {
                 debug!("Preimage received for contract {contract:?}");
                 let slice: [u8; 32] = preimage.try_into().expect("Failed to parse preimage");
-                GatewayPayStateMachine {
+                let state = GatewayPayStateMachine {
                     common,
                     state: GatewayPayStates::ClaimOutgoingContract(Box::new(
                         GatewayPayClaimOutgoingContract {
@@ -432,9 +516,13 @@ impl GatewayPayInvoice {
                             preimage: Preimage(slice),
                         },
                     )),
-                }
+                };
+                (state, Amount::from_msats(fee_msat))
             }
-            Err(error) => Self::gateway_pay_cancel_contract(error, contract, common),
+            Err(error) => (
+                Self::gateway_pay_cancel_contract(error, contract, common),
+                Amount::ZERO,
+            ),
         }
     }
 
@@ -572,7 +660,8 @@ impl GatewayPayInvoice {
         timelock_delta: u64,
         consensus_block_count: u64,
         payment_data: &PaymentData,
-        routing_fees: RoutingFees,
+        static_fees: RoutingFees,
+        fee_mode: &FeeMode,
     ) -> Result<PaymentParameters, OutgoingContractError> {
         let our_pub_key = secp256k1::PublicKey::from_keypair(&redeem_key);
 
@@ -584,11 +673,17 @@ impl GatewayPayInvoice {
             return Err(OutgoingContractError::NotOurKey);
         }
 
+        if account.contract.hash != payment_data.payment_hash() {
+            return Err(OutgoingContractError::PaymentHashMismatch);
+        }
+
         let payment_amount = payment_data
             .amount()
             .ok_or(OutgoingContractError::InvoiceMissingAmount)?;
 
-        let gateway_fee = routing_fees.to_amount(&payment_amount);
+        let gateway_fee = fee_mode
+            .routing_fees(static_fees)
+            .to_amount(&payment_amount);
         let necessary_contract_amount = payment_amount + gateway_fee;
         if account.amount < necessary_contract_amount {
             return Err(OutgoingContractError::Underfunded(
@@ -610,43 +705,24 @@ impl GatewayPayInvoice {
             ));
         }
 
+        // In passthrough mode everything but the margin of the gateway may be spent on
+        // lightning network fees
         Ok(PaymentParameters {
             max_delay: max_delay.unwrap(),
-            max_send_amount: account.amount,
+            max_send_amount: account.amount - fee_mode.margin(&payment_amount),
             payment_data: payment_data.clone(),
         })
     }
 
-    // Checks if the invoice route hint last hop has source node id matching this
-    // gateways node pubkey and if the short channel id matches one assigned by
-    // this gateway to a connected federation. In this case, the gateway can
-    // avoid paying the invoice over the lightning network and instead perform a
-    // direct swap between the two federations.
+    // Returns the client of the federation the invoice can be paid to by a
+    // direct swap, see [`crate::Gateway::swap_destination`].
     async fn check_swap_to_federation(
         context: GatewayClientContext,
         payment_data: PaymentData,
     ) -> Option<Spanned<ClientHandleArc>> {
-        let rhints = payment_data.route_hints();
-        match rhints.first().and_then(|rh| rh.0.last()) {
-            None => None,
-            Some(hop) => match context.gateway.state.read().await.clone() {
-                GatewayState::Running { lightning_context } => {
-                    if hop.src_node_id != lightning_context.lightning_public_key {
-                        return None;
-                    }
-
-                    let scid_to_feds = context.gateway.scid_to_federation.read().await;
-                    match scid_to_feds.get(&hop.short_channel_id).copied() {
-                        None => None,
-                        Some(federation_id) => {
-                            let clients = context.gateway.clients.read().await;
-                            clients.get(&federation_id).cloned()
-                        }
-                    }
-                }
-                _ => None,
-            },
-        }
+        let federation_id = context.gateway.swap_destination(&payment_data).await?;
+        let clients = context.gateway.clients.read().await;
+        clients.get(&federation_id).cloned()
     }
 }
 
@@ -701,11 +777,12 @@ impl GatewayPayClaimOutgoingContract {
             input: claim_input,
             state_machines: Arc::new(|_, _| vec![]),
             amount: contract.amount,
-            keys: vec![context.redeem_key],
+            keys: vec![context.redeem_key.into()],
         };
 
         let out_points = global_context.claim_input(dbtx, client_input).await.1;
         debug!("Claimed outgoing contract {contract:?} with out points {out_points:?}");
+        settle_pending_earnings(&mut dbtx.module_tx(), common.operation_id, true).await;
         GatewayPayStateMachine {
             common,
             state: GatewayPayStates::Preimage(out_points, preimage),
@@ -880,6 +957,7 @@ impl GatewayPayCancelContract {
         error: OutgoingPaymentError,
     ) -> GatewayPayStateMachine {
         info!("Canceling outgoing contract {contract:?}");
+        settle_pending_earnings(&mut dbtx.module_tx(), common.operation_id, false).await;
         let cancel_signature = context.secp.sign_schnorr(
             &contract.contract.cancellation_message().into(),
             &context.redeem_key,
@@ -2,6 +2,7 @@
 //!
 //! This crate contains integration tests for the gateway API
 //! and business logic.
+use std::collections::BTreeSet;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -23,9 +24,9 @@ use fedimint_dummy_server::DummyInit;
 use fedimint_ln_client::api::LnFederationApi;
 use fedimint_ln_client::pay::{PayInvoicePayload, PaymentData};
 use fedimint_ln_client::{
-    LightningClientInit, LightningClientModule, LightningClientStateMachines,
+    GatewayConnection, LightningClientInit, LightningClientModule, LightningClientStateMachines,
     LightningOperationMeta, LightningOperationMetaVariant, LnPayState, LnReceiveState,
-    MockGatewayConnection, OutgoingLightningPayment, PayType,
+    MockGatewayConnection, OutgoingLightningPayment, PayType, RealGatewayConnection,
 };
 use fedimint_ln_common::config::{FeeToAmount, GatewayFee, LightningGenParams};
 use fedimint_ln_common::contracts::incoming::IncomingContractOffer;
@@ -33,6 +34,7 @@ use fedimint_ln_common::contracts::outgoing::OutgoingContractAccount;
 use fedimint_ln_common::contracts::{EncryptedPreimage, FundedContract, Preimage, PreimageKey};
 use fedimint_ln_common::{LightningGateway, LightningInput, LightningOutput, PrunedInvoice};
 use fedimint_ln_server::LightningInit;
+use fedimint_lnv2_client::PaymentFee;
 use fedimint_logging::LOG_TEST;
 use fedimint_testing::btc::BitcoinTest;
 use fedimint_testing::db::BYTE_33;
@@ -47,8 +49,10 @@ use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description, Ro
 use ln_gateway::rpc::rpc_client::{GatewayRpcClient, GatewayRpcError, GatewayRpcResult};
 use ln_gateway::rpc::rpc_server::hash_password;
 use ln_gateway::rpc::{
-    BalancePayload, ConnectFedPayload, FederationRoutingFees, LeaveFedPayload,
-    SetConfigurationPayload,
+    BalancePayload, ConnectFedPayload, ExportConnectionsPayload, FederationConfigOverride,
+    FederationPolicy, FederationRoutingFees, GatewayConfigFile, GetSwapFeesPayload,
+    ImportConnectionsPayload, LeaveFedPayload, ListPaymentsPayload, PaymentDirection,
+    PaymentStatus, RouteHintRefreshConfig, SetConfigurationPayload, SetSwapFeesPayload, SwapFees,
 };
 use ln_gateway::state_machine::pay::{
     OutgoingContractError, OutgoingPaymentError, OutgoingPaymentErrorType,
@@ -217,6 +221,47 @@ async fn gateway_pay_valid_invoice(
     Ok(())
 }
 
+/// Test helper function for paying a BOLT11 invoice through the public API of
+/// the gateway specified by `gateway_id`, like clients of the federation do.
+/// Returns whether the gateway paid the invoice.
+async fn pay_invoice_through_gateway_api(
+    invoice: Bolt11Invoice,
+    user_client: &ClientHandleArc,
+    gateway_id: &PublicKey,
+) -> anyhow::Result<bool> {
+    let user_lightning_module = &user_client.get_first_module::<LightningClientModule>();
+    let gateway = user_lightning_module
+        .select_gateway(gateway_id)
+        .await
+        .expect("Gateway is registered");
+
+    let OutgoingLightningPayment {
+        payment_type,
+        contract_id,
+        fee: _,
+    } = user_pay_invoice(user_lightning_module, invoice.clone(), gateway_id).await?;
+    let PayType::Lightning(pay_op) = payment_type else {
+        panic!("Expected Lightning payment!");
+    };
+    let mut pay_sub = user_lightning_module
+        .subscribe_ln_pay(pay_op)
+        .await?
+        .into_stream();
+    assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+    assert_matches!(pay_sub.ok().await?, LnPayState::Funded { .. });
+
+    let payload = PayInvoicePayload {
+        federation_id: user_client.federation_id(),
+        contract_id,
+        payment_data: get_payment_data(Some(gateway.clone()), invoice),
+        preimage_auth: Hash::hash(&[0; 32]),
+    };
+    Ok(RealGatewayConnection
+        .pay_invoice(gateway, payload)
+        .await
+        .is_ok())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_pay_valid_invoice() -> anyhow::Result<()> {
     single_federation_test(
@@ -248,6 +293,158 @@ async fn test_gateway_client_pay_valid_invoice() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_lists_paid_invoices() -> anyhow::Result<()> {
+    single_federation_test(
+        |gateway, other_lightning_client, fed, user_client, _| async move {
+            let rpc_client = gateway
+                .get_rpc()
+                .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+            let gateway_id = gateway.gateway.gateway_id;
+            let dummy_module = user_client.get_first_module::<DummyClientModule>();
+            let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+            dummy_module.receive_money(outpoint).await?;
+
+            let started = fedimint_core::time::now();
+            for amount in [sats(100), sats(200)] {
+                let invoice = other_lightning_client.invoice(amount, None)?;
+                assert!(pay_invoice_through_gateway_api(invoice, &user_client, &gateway_id).await?);
+            }
+
+            let rpc_client = &rpc_client;
+            let list_payments = |payload: ListPaymentsPayload| async move {
+                verify_gateway_rpc_success("list_payments", || {
+                    rpc_client.list_payments(payload.clone())
+                })
+                .await
+            };
+            let all = ListPaymentsPayload {
+                federation_id: None,
+                start_time: None,
+                end_time: None,
+                limit: 10,
+                start_after: None,
+            };
+
+            // Payments are listed newest first
+            let payments = list_payments(all.clone()).await;
+            assert_eq!(payments.len(), 2);
+            assert_eq!(
+                payments.iter().map(|p| p.amount).collect::<Vec<_>>(),
+                vec![sats(200), sats(100)]
+            );
+            for payment in &payments {
+                assert_eq!(payment.federation_id, fed.id());
+                assert_eq!(payment.direction, PaymentDirection::Outgoing);
+                assert_eq!(payment.status, PaymentStatus::Succeeded);
+                assert!(started <= payment.started_at);
+                assert!(payment.started_at <= payment.completed_at);
+            }
+
+            // Pages continue after the last payment of the previous page
+            let first_page = list_payments(ListPaymentsPayload {
+                limit: 1,
+                ..all.clone()
+            })
+            .await;
+            let second_page = list_payments(ListPaymentsPayload {
+                limit: 1,
+                start_after: Some(first_page[0].completed_at),
+                ..all.clone()
+            })
+            .await;
+            assert_eq!(first_page, payments[..1]);
+            assert_eq!(second_page, payments[1..]);
+
+            // Payments are filtered by federation and completion time
+            assert!(list_payments(ListPaymentsPayload {
+                federation_id: Some(FederationId::dummy()),
+                ..all.clone()
+            })
+            .await
+            .is_empty());
+            assert_eq!(
+                list_payments(ListPaymentsPayload {
+                    federation_id: Some(fed.id()),
+                    ..all.clone()
+                })
+                .await,
+                payments
+            );
+            assert_eq!(
+                list_payments(ListPaymentsPayload {
+                    end_time: Some(payments[1].completed_at),
+                    ..all.clone()
+                })
+                .await,
+                payments[1..]
+            );
+            assert_eq!(
+                list_payments(ListPaymentsPayload {
+                    start_time: Some(payments[0].completed_at),
+                    ..all.clone()
+                })
+                .await,
+                payments[..1]
+            );
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_lists_failed_payments() -> anyhow::Result<()> {
+    single_federation_test(
+        |gateway, other_lightning_client, fed, user_client, _| async move {
+            let gateway_id = gateway.gateway.gateway_id;
+            let dummy_module = user_client.get_first_module::<DummyClientModule>();
+            let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+            dummy_module.receive_money(outpoint).await?;
+
+            let invoice = other_lightning_client.unpayable_invoice(sats(250), None);
+            assert!(!pay_invoice_through_gateway_api(invoice, &user_client, &gateway_id).await?);
+
+            let payload = ListPaymentsPayload {
+                federation_id: None,
+                start_time: None,
+                end_time: None,
+                limit: 10,
+                start_after: None,
+            };
+
+            // The payment history is only available to the operator
+            let unauthenticated_rpc_client = gateway.get_rpc();
+            verify_gateway_rpc_failure(
+                "list_payments",
+                || unauthenticated_rpc_client.list_payments(payload.clone()),
+                StatusCode::UNAUTHORIZED,
+            )
+            .await;
+
+            let rpc_client = gateway
+                .get_rpc()
+                .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+            let payments = verify_gateway_rpc_success("list_payments", || {
+                rpc_client.list_payments(payload.clone())
+            })
+            .await;
+            assert_matches!(
+                payments.as_slice(),
+                [payment] if payment.federation_id == fed.id()
+                    && payment.direction == PaymentDirection::Outgoing
+                    && matches!(payment.status, PaymentStatus::Failed { .. })
+                    && payment.amount == sats(250)
+                    && payment.fees_earned == Amount::ZERO
+            );
+
+            Ok(())
+        },
+    )
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_can_change_default_routing_fees() -> anyhow::Result<()> {
     single_federation_test(
@@ -269,6 +466,8 @@ async fn test_can_change_default_routing_fees() -> anyhow::Result<()> {
                 routing_fees: Some(federation_fee.clone()),
                 network: None,
                 per_federation_routing_fees: None,
+                route_hint_refresh_interval_secs: None,
+                route_hint_refresh_hysteresis: None,
             };
             verify_gateway_rpc_success("set_configuration", || {
                 rpc_client.set_configuration(set_configuration_payload.clone())
@@ -309,6 +508,67 @@ async fn test_can_change_default_routing_fees() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_earns_fees_of_paid_invoices() -> anyhow::Result<()> {
+    single_federation_test(
+        |gateway, other_lightning_client, fed, user_client, _| async move {
+            let rpc_client = gateway
+                .get_rpc()
+                .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+            let dummy_module = user_client.get_first_module::<DummyClientModule>();
+            let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+            dummy_module.receive_money(outpoint).await?;
+
+            let federation_fee = FederationRoutingFees::from_str("10,10000")?;
+            let set_configuration_payload = SetConfigurationPayload {
+                password: None,
+                num_route_hints: None,
+                routing_fees: Some(federation_fee.clone()),
+                network: None,
+                per_federation_routing_fees: None,
+                route_hint_refresh_interval_secs: None,
+                route_hint_refresh_hysteresis: None,
+            };
+            verify_gateway_rpc_success("set_configuration", || {
+                rpc_client.set_configuration(set_configuration_payload.clone())
+            })
+            .await;
+            reconnect_federation(&rpc_client, &fed).await;
+            let ln_module = user_client.get_first_module::<LightningClientModule>();
+            ln_module.update_gateway_cache().await?;
+
+            let invoice_amount = sats(250);
+            let invoice = other_lightning_client.invoice(invoice_amount, None)?;
+            let gateway_client = gateway.select_client(fed.id()).await;
+            gateway_pay_valid_invoice(
+                invoice,
+                &user_client,
+                &gateway_client,
+                &gateway.gateway.gateway_id,
+            )
+            .await?;
+
+            // The fake lightning node doesn't pay any routing fees, so the whole fee
+            // charged by the gateway is earned
+            let fee: RoutingFees = federation_fee.into();
+            let fee_amount = fee.to_amount(&invoice_amount);
+            let earnings =
+                verify_gateway_rpc_success("get_earnings", || rpc_client.get_earnings()).await;
+            assert_eq!(earnings.total_incoming, Amount::ZERO);
+            assert_eq!(earnings.total_outgoing, fee_amount);
+            assert_matches!(
+                earnings.federations.as_slice(),
+                [federation] if federation.federation_id == fed.id()
+                    && federation.incoming == Amount::ZERO
+                    && federation.outgoing == fee_amount
+            );
+
+            Ok(())
+        },
+    )
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_can_change_federation_routing_fees() -> anyhow::Result<()> {
     single_federation_test(
@@ -330,6 +590,8 @@ async fn test_can_change_federation_routing_fees() -> anyhow::Result<()> {
                 routing_fees: None,
                 network: None,
                 per_federation_routing_fees: Some(vec![(fed.id(), federation_fee.clone())]),
+                route_hint_refresh_interval_secs: None,
+                route_hint_refresh_hysteresis: None,
             };
             verify_gateway_rpc_success("set_configuration", || {
                 rpc_client.set_configuration(set_configuration_payload.clone())
@@ -367,6 +629,60 @@ async fn test_can_change_federation_routing_fees() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_config_round_trips() -> anyhow::Result<()> {
+    single_federation_test(|gateway, _, fed, _, _| async move {
+        let rpc_client = gateway
+            .get_rpc()
+            .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+
+        let config = verify_gateway_rpc_success("get_config", || rpc_client.get_config()).await;
+        assert_eq!(config.password, None);
+        assert_eq!(config.network, Some(DEFAULT_NETWORK));
+        assert!(config
+            .federations
+            .iter()
+            .any(|federation| federation.federation_id == fed.id()));
+
+        // The TOML file format round-trips the same settings
+        let parsed: GatewayConfigFile = toml::from_str(&toml::to_string_pretty(&config)?)?;
+        assert_eq!(parsed, config);
+
+        // Re-applying the current settings is a no-op
+        verify_gateway_rpc_success("apply_config", || rpc_client.apply_config(config.clone()))
+            .await;
+
+        let federation_fee = FederationRoutingFees::from_str("10,10000")?;
+        let mut new_config = config.clone();
+        new_config.num_route_hints = Some(2);
+        new_config.federations = vec![FederationConfigOverride {
+            federation_id: fed.id(),
+            fees: federation_fee,
+        }];
+        verify_gateway_rpc_success("apply_config", || {
+            rpc_client.apply_config(new_config.clone())
+        })
+        .await;
+        assert_eq!(
+            verify_gateway_rpc_success("get_config", || rpc_client.get_config()).await,
+            new_config
+        );
+
+        // Settings that are only read on startup cannot be changed
+        let mut restart_config = new_config.clone();
+        restart_config.listen = Some("127.0.0.1:1".parse()?);
+        verify_gateway_rpc_failure(
+            "apply_config",
+            || rpc_client.apply_config(restart_config.clone()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .await;
+
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_enforces_fees() -> anyhow::Result<()> {
     single_federation_test(
@@ -389,6 +705,8 @@ async fn test_gateway_enforces_fees() -> anyhow::Result<()> {
                 routing_fees: Some(federation_fee),
                 network: None,
                 per_federation_routing_fees: None,
+                route_hint_refresh_interval_secs: None,
+                route_hint_refresh_hysteresis: None,
             };
             verify_gateway_rpc_success("set_configuration", || {
                 rpc_client.set_configuration(set_configuration_payload.clone())
@@ -516,7 +834,7 @@ async fn test_gateway_cannot_claim_invalid_preimage() -> anyhow::Result<()> {
                 input: claim_input,
                 state_machines: Arc::new(|_, _| vec![]),
                 amount: outgoing_contract.amount,
-                keys: vec![gateway_module.redeem_key],
+                keys: vec![gateway_module.redeem_key.into()],
             };
 
             let tx = TransactionBuilder::new().with_input(client_input.into_dyn(gateway_module.id));
@@ -595,6 +913,13 @@ async fn test_gateway_client_pay_unpayable_invoice() -> anyhow::Result<()> {
                         .into_stream();
                     assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
                     assert_matches!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled { .. });
+
+                    // The fee of a canceled payment is never earned
+                    assert!(gateway_client
+                        .get_first_module::<GatewayClientModule>()
+                        .earnings()
+                        .await
+                        .is_empty());
                 }
                 _ => panic!("Expected Lightning payment!"),
             }
@@ -666,6 +991,74 @@ async fn test_gateway_client_intercept_valid_htlc() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_earns_fees_of_intercepted_htlcs() -> anyhow::Result<()> {
+    single_federation_test(|gateway, _, fed, user_client, _| async move {
+        let rpc_client = gateway
+            .get_rpc()
+            .with_password(Some(DEFAULT_GATEWAY_PASSWORD.to_string()));
+        let gateway_id = gateway.gateway.gateway_id;
+        let gateway_client = gateway.select_client(fed.id()).await;
+        let dummy_module = gateway_client.get_first_module::<DummyClientModule>();
+        let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+        dummy_module.receive_money(outpoint).await?;
+
+        let invoice_amount = sats(100);
+        let ln_module = user_client.get_first_module::<LightningClientModule>();
+        let ln_gateway = ln_module.select_gateway(&gateway_id).await;
+        let desc = Description::new("description".to_string())?;
+        let (_invoice_op, invoice, _) = ln_module
+            .create_bolt11_invoice(
+                invoice_amount,
+                Bolt11InvoiceDescription::Direct(&desc),
+                None,
+                "test earn fees of intercepted HTLC",
+                ln_gateway,
+            )
+            .await?;
+
+        // The HTLC pays the gateway more than it has to fund the incoming contract with
+        let fee = sats(2);
+        let htlc = Htlc {
+            payment_hash: *invoice.payment_hash(),
+            incoming_amount_msat: invoice_amount + fee,
+            outgoing_amount_msat: invoice_amount,
+            incoming_expiry: u32::MAX,
+            short_channel_id: Some(1),
+            incoming_chan_id: 2,
+            htlc_id: 1,
+        };
+        let intercept_op = gateway_client
+            .get_first_module::<GatewayClientModule>()
+            .gateway_handle_intercepted_htlc(htlc)
+            .await?;
+        let mut intercept_sub = gateway_client
+            .get_first_module::<GatewayClientModule>()
+            .gateway_subscribe_ln_receive(intercept_op)
+            .await?
+            .into_stream();
+        assert_eq!(intercept_sub.ok().await?, GatewayExtReceiveStates::Funding);
+        assert_matches!(
+            intercept_sub.ok().await?,
+            GatewayExtReceiveStates::Preimage { .. }
+        );
+
+        let earnings =
+            verify_gateway_rpc_success("get_earnings", || rpc_client.get_earnings()).await;
+        assert_eq!(earnings.total_incoming, fee);
+        assert_eq!(earnings.total_outgoing, Amount::ZERO);
+        assert_matches!(
+            earnings.federations.as_slice(),
+            [federation] if federation.federation_id == fed.id()
+                && federation.incoming == fee
+                && federation.outgoing == Amount::ZERO
+        );
+
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_intercept_offer_does_not_exist() -> anyhow::Result<()> {
     single_federation_test(|gateway, _, fed, _, _| async move {
@@ -972,6 +1365,8 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
         routing_fees: None,
         network: None,
         per_federation_routing_fees: None,
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: None,
     };
     verify_gateway_rpc_success("set_configuration", || {
         initial_rpc_client.set_configuration(set_configuration_payload.clone())
@@ -1020,6 +1415,8 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
         routing_fees: Some(federation_fee.clone()),
         network: None,
         per_federation_routing_fees: None,
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: None,
     };
     verify_gateway_rpc_success("set_configuration", || {
         initial_rpc_client_with_password.set_configuration(set_configuration_payload.clone())
@@ -1052,6 +1449,8 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
                                          * network */
         routing_fees: None,
         per_federation_routing_fees: None,
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: None,
     };
     verify_gateway_rpc_success("set_configuration", || {
         new_password_rpc_client.set_configuration(set_configuration_payload.clone())
@@ -1068,6 +1467,8 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
                                           * node's network */
         routing_fees: None,
         per_federation_routing_fees: None,
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: None,
     };
     verify_gateway_rpc_failure(
         "set_configuration",
@@ -1098,6 +1499,8 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
         routing_fees: None,
         network: None,
         per_federation_routing_fees: Some(vec![(fed.id(), federation_routing_fees.clone())]),
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: None,
     };
     verify_gateway_rpc_success("set_configuration", || {
         new_password_rpc_client.set_configuration(set_configuration_payload.clone())
@@ -1116,6 +1519,41 @@ async fn test_gateway_configuration() -> anyhow::Result<()> {
         Some(federation_routing_fees)
     );
 
+    // Verify the route hint refresh settings are persisted and a hysteresis of
+    // zero checks is rejected
+    let set_configuration_payload = SetConfigurationPayload {
+        password: None,
+        num_route_hints: None,
+        routing_fees: None,
+        network: None,
+        per_federation_routing_fees: None,
+        route_hint_refresh_interval_secs: Some(30),
+        route_hint_refresh_hysteresis: Some(3),
+    };
+    verify_gateway_rpc_success("set_configuration", || {
+        new_password_rpc_client.set_configuration(set_configuration_payload.clone())
+    })
+    .await;
+    assert_eq!(
+        gateway.gateway.route_hint_refresh_config().await,
+        RouteHintRefreshConfig {
+            interval_secs: 30,
+            hysteresis_checks: 3,
+        }
+    );
+
+    let set_configuration_payload = SetConfigurationPayload {
+        route_hint_refresh_interval_secs: None,
+        route_hint_refresh_hysteresis: Some(0),
+        ..set_configuration_payload
+    };
+    verify_gateway_rpc_failure(
+        "set_configuration",
+        || new_password_rpc_client.set_configuration(set_configuration_payload.clone()),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+    .await;
+
     Ok(())
 }
 
@@ -1149,6 +1587,124 @@ async fn test_gateway_supports_connecting_multiple_federations() -> anyhow::Resu
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_enforces_federation_policy() -> anyhow::Result<()> {
+    multi_federation_test(|gateway, rpc, fed1, fed2, _| async move {
+        let id2 = fed2.invite_code().federation_id();
+
+        let policy = FederationPolicy {
+            allowed_federations: Some(BTreeSet::from([id2])),
+            ..FederationPolicy::default()
+        };
+        verify_gateway_rpc_success("set_federation_policy", || {
+            rpc.set_federation_policy(policy.clone())
+        })
+        .await;
+        assert_eq!(
+            verify_gateway_rpc_success("get_federation_policy", || rpc.get_federation_policy())
+                .await,
+            policy
+        );
+
+        // The first federation is not on the allowlist
+        let join_fed1 = ConnectFedPayload {
+            invite_code: fed1.invite_code().to_string(),
+        };
+        verify_gateway_rpc_failure(
+            "connect_federation",
+            || rpc.connect_federation(join_fed1.clone()),
+            StatusCode::FORBIDDEN,
+        )
+        .await;
+
+        // The second federation is allowed, but has fewer guardians than required
+        let strict_policy = FederationPolicy {
+            min_guardian_threshold: Some(u64::MAX),
+            ..policy.clone()
+        };
+        verify_gateway_rpc_success("set_federation_policy", || {
+            rpc.set_federation_policy(strict_policy.clone())
+        })
+        .await;
+        let join_fed2 = ConnectFedPayload {
+            invite_code: fed2.invite_code().to_string(),
+        };
+        verify_gateway_rpc_failure(
+            "connect_federation",
+            || rpc.connect_federation(join_fed2.clone()),
+            StatusCode::FORBIDDEN,
+        )
+        .await;
+
+        verify_gateway_rpc_success("set_federation_policy", || {
+            rpc.set_federation_policy(policy.clone())
+        })
+        .await;
+        let info = verify_gateway_rpc_success("connect_federation", || {
+            rpc.connect_federation(join_fed2.clone())
+        })
+        .await;
+        assert_eq!(info.federation_id, id2);
+        assert_eq!(rpc.get_info().await.unwrap().federations.len(), 1);
+
+        drop(gateway); // keep until the end to avoid the gateway shutting down too early
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_configures_swap_fees_per_federation() -> anyhow::Result<()> {
+    multi_federation_test(|gateway, rpc, fed1, fed2, _| async move {
+        let id1 = fed1.invite_code().federation_id();
+        let id2 = fed2.invite_code().federation_id();
+
+        let fees = SwapFees {
+            send_fee: PaymentFee {
+                base: Amount::from_msats(1000),
+                parts_per_million: 100,
+            },
+            receive_fee: PaymentFee {
+                base: Amount::ZERO,
+                parts_per_million: 0,
+            },
+        };
+
+        // Swap fees can only be set for connected federations
+        let set_fees = SetSwapFeesPayload {
+            federation_id: id1,
+            fees: fees.clone(),
+        };
+        verify_gateway_rpc_failure(
+            "set_swap_fees",
+            || rpc.set_swap_fees(set_fees.clone()),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .await;
+
+        connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+
+        verify_gateway_rpc_success("set_swap_fees", || rpc.set_swap_fees(set_fees.clone())).await;
+
+        let fees1 = verify_gateway_rpc_success("get_swap_fees", || {
+            rpc.get_swap_fees(GetSwapFeesPayload { federation_id: id1 })
+        })
+        .await;
+        assert_eq!(fees1, fees);
+
+        // The other federation keeps the default swap fees
+        let fees2 = verify_gateway_rpc_success("get_swap_fees", || {
+            rpc.get_swap_fees(GetSwapFeesPayload { federation_id: id2 })
+        })
+        .await;
+        assert_eq!(fees2, SwapFees::default());
+
+        drop(gateway); // keep until the end to avoid the gateway shutting down too early
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_shows_info_about_all_connected_federations() -> anyhow::Result<()> {
     multi_federation_test(|gateway, rpc, fed1, fed2, _| async move {
@@ -1208,7 +1764,7 @@ async fn test_gateway_can_leave_connected_federations() -> anyhow::Result<()> {
         assert_eq!(fed_info.federation_id, id1);
         assert_eq!(fed_info.channel_id, Some(1));
 
-        // reconnect the first federation
+        // reconnect the first federation, which keeps its SCID alias
         let fed_info = rpc
             .connect_federation(ConnectFedPayload {
                 invite_code: invite1.to_string(),
@@ -1216,7 +1772,7 @@ async fn test_gateway_can_leave_connected_federations() -> anyhow::Result<()> {
             .await
             .unwrap();
         assert_eq!(fed_info.federation_id, id1);
-        assert_eq!(fed_info.channel_id, Some(3));
+        assert_eq!(fed_info.channel_id, Some(1));
 
         // remove second connected federation
         let fed_info = rpc
@@ -1226,6 +1782,16 @@ async fn test_gateway_can_leave_connected_federations() -> anyhow::Result<()> {
         assert_eq!(fed_info.federation_id, id2);
         assert_eq!(fed_info.channel_id, Some(2));
 
+        // the alias of a federation the gateway left is listed but not connected
+        let aliases = rpc.list_scid_aliases().await.unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert!(aliases
+            .iter()
+            .any(|alias| alias.federation_id == id1 && alias.scid == 1 && alias.connected));
+        assert!(aliases
+            .iter()
+            .any(|alias| alias.federation_id == id2 && alias.scid == 2 && !alias.connected));
+
         // reconnect the second federation
         let fed_info = rpc
             .connect_federation(ConnectFedPayload {
@@ -1234,13 +1800,13 @@ async fn test_gateway_can_leave_connected_federations() -> anyhow::Result<()> {
             .await
             .unwrap();
         assert_eq!(fed_info.federation_id, id2);
-        assert_eq!(fed_info.channel_id, Some(4));
+        assert_eq!(fed_info.channel_id, Some(2));
 
         let info = rpc.get_info().await.unwrap();
         assert_eq!(info.federations.len(), 2);
         assert_eq!(
             info.channels.unwrap().keys().cloned().collect::<Vec<u64>>(),
-            vec![3, 4]
+            vec![1, 2]
         );
 
         drop(gateway); // keep until the end to avoid the gateway shutting down too early
@@ -1249,6 +1815,112 @@ async fn test_gateway_can_leave_connected_federations() -> anyhow::Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_round_trips_exported_connections() -> anyhow::Result<()> {
+    multi_federation_test(|gateway, rpc, fed1, fed2, _| async move {
+        let id1 = fed1.invite_code().federation_id();
+        let id2 = fed2.invite_code().federation_id();
+
+        connect_federations(&rpc, &[fed1, fed2]).await.unwrap();
+
+        let federation_fee = FederationRoutingFees::from_str("10,10000")?;
+        verify_gateway_rpc_success("set_configuration", || {
+            rpc.set_configuration(SetConfigurationPayload {
+                password: None,
+                num_route_hints: None,
+                routing_fees: None,
+                network: None,
+                per_federation_routing_fees: Some(vec![(id1, federation_fee.clone())]),
+                route_hint_refresh_interval_secs: None,
+                route_hint_refresh_hysteresis: None,
+            })
+        })
+        .await;
+
+        let connections = verify_gateway_rpc_success("export_connections", || {
+            rpc.export_connections(ExportConnectionsPayload {
+                password: "export".to_string(),
+            })
+        })
+        .await;
+
+        for federation_id in [id1, id2] {
+            verify_gateway_rpc_success("leave_federation", || {
+                rpc.leave_federation(LeaveFedPayload { federation_id })
+            })
+            .await;
+        }
+
+        // Connections can't be imported with a different password
+        assert!(rpc
+            .import_connections(ImportConnectionsPayload {
+                password: "import".to_string(),
+                connections: connections.clone(),
+            })
+            .await
+            .is_err());
+        assert!(rpc.get_info().await.unwrap().federations.is_empty());
+
+        // The import is all or nothing, so no federation is connected if one of them
+        // is not allowed
+        verify_gateway_rpc_success("set_federation_policy", || {
+            rpc.set_federation_policy(FederationPolicy {
+                allowed_federations: Some(BTreeSet::from([id2])),
+                ..FederationPolicy::default()
+            })
+        })
+        .await;
+        verify_gateway_rpc_failure(
+            "import_connections",
+            || {
+                rpc.import_connections(ImportConnectionsPayload {
+                    password: "export".to_string(),
+                    connections: connections.clone(),
+                })
+            },
+            StatusCode::FORBIDDEN,
+        )
+        .await;
+        assert!(rpc.get_info().await.unwrap().federations.is_empty());
+
+        verify_gateway_rpc_success("set_federation_policy", || {
+            rpc.set_federation_policy(FederationPolicy::default())
+        })
+        .await;
+        let imported = verify_gateway_rpc_success("import_connections", || {
+            rpc.import_connections(ImportConnectionsPayload {
+                password: "export".to_string(),
+                connections: connections.clone(),
+            })
+        })
+        .await;
+        assert_eq!(imported.len(), 2);
+
+        // Importing again skips the federations that are already connected
+        let imported = verify_gateway_rpc_success("import_connections", || {
+            rpc.import_connections(ImportConnectionsPayload {
+                password: "export".to_string(),
+                connections: connections.clone(),
+            })
+        })
+        .await;
+        assert!(imported.is_empty());
+
+        let info = rpc.get_info().await.unwrap();
+        assert_eq!(info.federations.len(), 2);
+        // Fees configured for a federation are imported with it
+        assert!(info
+            .federations
+            .iter()
+            .any(|info| info.federation_id == id1
+                && info.routing_fees == Some(federation_fee.clone())));
+
+        drop(gateway); // keep until the end to avoid the gateway shutting down too early
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_shows_balance_for_any_connected_federation() -> anyhow::Result<()> {
     multi_federation_test(|gateway, rpc, fed1, fed2, _| async move {
@@ -1292,6 +1964,8 @@ async fn test_gateway_executes_swaps_between_connected_federations() -> anyhow::
             routing_fees: None,
             network: None,
             per_federation_routing_fees: Some(vec![(id1, fed_routing_fees.clone())]),
+            route_hint_refresh_interval_secs: None,
+            route_hint_refresh_hysteresis: None,
         };
         verify_gateway_rpc_success("set_configuration", || {
             rpc.set_configuration(set_configuration_payload.clone())
@@ -4,7 +4,7 @@ use fedimint_core::core::{ModuleInstanceId, OperationId};
 use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::{impl_db_record, Amount};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
 use strum_macros::EnumIter;
 use tracing::warn;
 
@@ -14,6 +14,8 @@ use crate::states::DummyStateMachine;
 #[derive(Clone, Debug, EnumIter)]
 pub enum DbKeyPrefix {
     ClientFunds = 0x04,
+    ClientAccount = 0x05,
+    ClientAccountFunds = 0x06,
     // Used to verify that 0x50 key can be written to, which used to conflict with
     // `DatabaseVersionKeyV0`
     ClientName = 0x50,
@@ -43,6 +45,43 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::ClientFunds,
 );
 
+/// Name of an additional account of the client, indexed by the child key of
+/// the module secret its key is derived from. The default account `0` is not
+/// stored.
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct DummyClientAccountKey(pub u64);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct DummyClientAccountKeyPrefix;
+
+impl_db_record!(
+    key = DummyClientAccountKey,
+    value = String,
+    db_prefix = DbKeyPrefix::ClientAccount,
+);
+impl_db_lookup!(
+    key = DummyClientAccountKey,
+    query_prefix = DummyClientAccountKeyPrefix
+);
+
+/// Funds of an additional account, the funds of the default account are
+/// stored in [`DummyClientFundsKeyV1`]
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct DummyClientAccountFundsKey(pub u64);
+
+#[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
+pub struct DummyClientAccountFundsKeyPrefix;
+
+impl_db_record!(
+    key = DummyClientAccountFundsKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::ClientAccountFunds,
+);
+impl_db_lookup!(
+    key = DummyClientAccountFundsKey,
+    query_prefix = DummyClientAccountFundsKeyPrefix
+);
+
 #[derive(Debug, Clone, Encodable, Decodable, Eq, PartialEq, Hash)]
 pub struct DummyClientNameKey;
 
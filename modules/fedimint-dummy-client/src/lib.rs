@@ -12,8 +12,12 @@ use std::time::Duration;
 
 use anyhow::{anyhow, format_err, Context as _};
 use common::broken_fed_key_pair;
-use db::{migrate_to_v1, DbKeyPrefix, DummyClientFundsKeyV1, DummyClientNameKey};
+use db::{
+    migrate_to_v1, DbKeyPrefix, DummyClientAccountFundsKey, DummyClientAccountFundsKeyPrefix,
+    DummyClientAccountKey, DummyClientAccountKeyPrefix, DummyClientFundsKeyV1, DummyClientNameKey,
+};
 use fedimint_client::db::{migrate_state, ClientMigrationFn};
+use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
@@ -23,8 +27,9 @@ use fedimint_core::core::{Decoder, OperationId};
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
 };
+use fedimint_core::encoding::Encodable;
 use fedimint_core::module::{
-    ApiVersion, CommonModuleInit, ModuleCommon, ModuleInit, MultiApiVersion,
+    ApiVersion, CommonModuleInit, FeeSchedule, ModuleCommon, ModuleInit, MultiApiVersion,
 };
 use fedimint_core::secp256k1::{KeyPair, PublicKey, Secp256k1};
 use fedimint_core::util::{BoxStream, NextOrPending};
@@ -36,6 +41,7 @@ use fedimint_dummy_common::{
     KIND,
 };
 use futures::{pin_mut, FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
 use states::DummyStateMachine;
 use strum::IntoEnumIterator;
 
@@ -43,19 +49,37 @@ pub mod api;
 pub mod db;
 pub mod states;
 
+/// Index of the account whose funds are used to fund transactions
+pub const DEFAULT_ACCOUNT: u64 = 0;
+
 #[derive(Debug)]
 pub struct DummyClientModule {
     cfg: DummyClientConfig,
+    /// Key of the [`DEFAULT_ACCOUNT`]
     key: KeyPair,
+    /// The keys of additional accounts are derived from it by account index
+    root_secret: DerivableSecret,
     notifier: ModuleNotifier<DummyStateMachine>,
     client_ctx: ClientContext<Self>,
     db: Database,
 }
 
+/// An account of the client, see [`DummyClientModule::create_account`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DummyAccount {
+    pub index: u64,
+    pub name: String,
+    pub public_key: PublicKey,
+    pub balance: Amount,
+}
+
 /// Data needed by the state machine
 #[derive(Debug, Clone)]
 pub struct DummyClientContext {
     pub dummy_decoder: Decoder,
+    /// Sessions to wait for before considering an output final, see
+    /// [`DummyFailureInjection`](fedimint_dummy_common::config::DummyFailureInjection)
+    pub output_delay_sessions: u64,
 }
 
 // TODO: Boiler-plate
@@ -72,6 +96,7 @@ impl ClientModule for DummyClientModule {
     fn context(&self) -> Self::ModuleStateMachineContext {
         DummyClientContext {
             dummy_decoder: self.decoder(),
+            output_delay_sessions: self.cfg.failure_injection.output_delay_sessions,
         }
     }
 
@@ -93,6 +118,7 @@ impl ClientModule for DummyClientModule {
         operation_id: OperationId,
         input_amount: Amount,
         output_amount: Amount,
+        fee_schedule: FeeSchedule,
     ) -> anyhow::Result<(
         Vec<ClientInput<DummyInput, DummyStateMachine>>,
         Vec<ClientOutput<DummyOutput, DummyStateMachine>>,
@@ -101,7 +127,21 @@ impl ClientModule for DummyClientModule {
 
         match input_amount.cmp(&output_amount) {
             Ordering::Less => {
-                let missing_input_amount = output_amount - input_amount;
+                // The input has to pay its own schedule fee, which grows with the
+                // encoding of its amount
+                let missing_amount = output_amount - input_amount;
+                let mut missing_input_amount = missing_amount;
+                loop {
+                    let input = self.client_ctx.make_dyn_input(DummyInput {
+                        amount: missing_input_amount,
+                        account: self.key.public_key(),
+                    });
+                    let fee = fee_schedule.input_fee(input.consensus_encode_to_vec().len());
+                    if missing_amount + fee == missing_input_amount {
+                        break;
+                    }
+                    missing_input_amount = missing_amount + fee;
+                }
 
                 // Check and subtract from our funds
                 let our_funds = get_funds(dbtx).await;
@@ -120,7 +160,7 @@ impl ClientModule for DummyClientModule {
                         account: self.key.public_key(),
                     },
                     amount: missing_input_amount,
-                    keys: vec![self.key],
+                    keys: vec![self.key.into()],
                     state_machines: Arc::new(move |txid, _| {
                         vec![DummyStateMachine::Input(
                             missing_input_amount,
@@ -134,7 +174,18 @@ impl ClientModule for DummyClientModule {
             }
             Ordering::Equal => Ok((Vec::new(), Vec::new())),
             Ordering::Greater => {
-                let missing_output_amount = input_amount - output_amount;
+                // If the excess doesn't cover the schedule fee of the change the
+                // transaction overpays
+                let excess_amount = input_amount - output_amount;
+                let output = self.client_ctx.make_dyn_output(DummyOutput {
+                    amount: excess_amount,
+                    account: self.key.public_key(),
+                });
+                let fee = fee_schedule.output_fee(output.consensus_encode_to_vec().len());
+                let missing_output_amount = excess_amount.saturating_sub(fee);
+                if missing_output_amount == Amount::ZERO {
+                    return Ok((Vec::new(), Vec::new()));
+                }
                 let output = ClientOutput {
                     output: DummyOutput {
                         amount: missing_output_amount,
@@ -215,7 +266,7 @@ impl DummyClientModule {
                 account: account_kp.public_key(),
             },
             amount,
-            keys: vec![account_kp],
+            keys: vec![account_kp.into()],
             state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
         };
 
@@ -281,21 +332,96 @@ impl DummyClientModule {
         Ok(OutPoint { txid, out_idx: 0 })
     }
 
-    /// Wait to receive money at an outpoint
-    pub async fn receive_money(&self, outpoint: OutPoint) -> anyhow::Result<()> {
+    /// Send money from one of our accounts to another user, the fees are paid
+    /// from the [`DEFAULT_ACCOUNT`]
+    pub async fn send_money_from(
+        &self,
+        from: u64,
+        account: PublicKey,
+        amount: Amount,
+    ) -> anyhow::Result<OutPoint> {
+        if from == DEFAULT_ACCOUNT {
+            return self.send_money(account, amount).await;
+        }
+
+        let from_kp = self.account_key_pair(from).await?;
+
+        if self.account_balance(from).await < amount {
+            return Err(format_err!("Insufficient funds in account {from}"));
+        }
+
+        let op_id = OperationId(rand::random());
+
+        let input = ClientInput {
+            input: DummyInput {
+                amount,
+                account: from_kp.public_key(),
+            },
+            amount,
+            keys: vec![from_kp.into()],
+            state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
+        };
+        let output = ClientOutput {
+            output: DummyOutput { amount, account },
+            amount,
+            state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
+        };
+
+        let tx = TransactionBuilder::new()
+            .with_input(self.client_ctx.make_client_input(input))
+            .with_output(self.client_ctx.make_client_output(output));
+
+        let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
+        let (txid, _) = self
+            .client_ctx
+            .finalize_and_submit_transaction(op_id, DummyCommonInit::KIND.as_str(), outpoint, tx)
+            .await?;
+
+        self.client_ctx
+            .transaction_updates(op_id)
+            .await
+            .await_tx_accepted(txid)
+            .await
+            .map_err(|e| anyhow!(e))?;
+
         let mut dbtx = self.db.begin_transaction().await;
+        let funds = dbtx
+            .get_value(&DummyClientAccountFundsKey(from))
+            .await
+            .unwrap_or(Amount::ZERO);
+        dbtx.insert_entry(
+            &DummyClientAccountFundsKey(from),
+            &funds.saturating_sub(amount),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        Ok(OutPoint { txid, out_idx: 0 })
+    }
+
+    /// Wait to receive money at an outpoint paying one of our accounts
+    pub async fn receive_money(&self, outpoint: OutPoint) -> anyhow::Result<()> {
         let DummyOutputOutcome(new_balance, account) = self
             .client_ctx
-            .global_api()
-            .await_output_outcome(outpoint, Duration::from_secs(10), &self.decoder())
+            .await_output_outcome(outpoint, Duration::from_secs(10), |_| true)
             .await?;
 
-        if account != self.key.public_key() {
-            return Err(format_err!("Wrong account id"));
-        }
+        let index = self
+            .accounts()
+            .await
+            .into_iter()
+            .find(|a| a.public_key == account)
+            .ok_or(format_err!("Wrong account id"))?
+            .index;
 
-        dbtx.insert_entry(&DummyClientFundsKeyV1, &new_balance)
-            .await;
+        let mut dbtx = self.db.begin_transaction().await;
+        if index == DEFAULT_ACCOUNT {
+            dbtx.insert_entry(&DummyClientFundsKeyV1, &new_balance)
+                .await;
+        } else {
+            dbtx.insert_entry(&DummyClientAccountFundsKey(index), &new_balance)
+                .await;
+        }
         dbtx.commit_tx().await;
         Ok(())
     }
@@ -304,6 +430,98 @@ impl DummyClientModule {
     pub fn account(&self) -> PublicKey {
         self.key.public_key()
     }
+
+    /// Creates an additional account with its own key and balance, returns
+    /// the index of the account
+    pub async fn create_account(&self, name: String) -> anyhow::Result<u64> {
+        let mut dbtx = self.db.begin_transaction().await;
+
+        let accounts = dbtx
+            .find_by_prefix(&DummyClientAccountKeyPrefix)
+            .await
+            .map(|(key, name)| (key.0, name))
+            .collect::<BTreeMap<u64, String>>()
+            .await;
+
+        if accounts.values().any(|existing| *existing == name) {
+            return Err(format_err!("Account {name} already exists"));
+        }
+
+        let index = accounts.keys().last().copied().unwrap_or(DEFAULT_ACCOUNT) + 1;
+
+        dbtx.insert_new_entry(&DummyClientAccountKey(index), &name)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        Ok(index)
+    }
+
+    /// All accounts of the client, starting with the [`DEFAULT_ACCOUNT`]
+    pub async fn accounts(&self) -> Vec<DummyAccount> {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        let names = dbtx
+            .find_by_prefix(&DummyClientAccountKeyPrefix)
+            .await
+            .map(|(key, name)| (key.0, name))
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut accounts = vec![DummyAccount {
+            index: DEFAULT_ACCOUNT,
+            name: "default".to_string(),
+            public_key: self.key.public_key(),
+            balance: get_funds(&mut dbtx).await,
+        }];
+
+        for (index, name) in names {
+            accounts.push(DummyAccount {
+                index,
+                name,
+                public_key: self.derive_account_key_pair(index).public_key(),
+                balance: get_account_funds(&mut dbtx, index).await,
+            });
+        }
+
+        accounts
+    }
+
+    /// Public key of one of our accounts, which other users send money to
+    pub async fn account_of(&self, index: u64) -> anyhow::Result<PublicKey> {
+        Ok(self.account_key_pair(index).await?.public_key())
+    }
+
+    /// Funds of one of our accounts
+    pub async fn account_balance(&self, index: u64) -> Amount {
+        let mut dbtx = self.db.begin_transaction_nc().await;
+
+        if index == DEFAULT_ACCOUNT {
+            get_funds(&mut dbtx).await
+        } else {
+            get_account_funds(&mut dbtx, index).await
+        }
+    }
+
+    async fn account_key_pair(&self, index: u64) -> anyhow::Result<KeyPair> {
+        if index == DEFAULT_ACCOUNT {
+            return Ok(self.key);
+        }
+
+        self.db
+            .begin_transaction_nc()
+            .await
+            .get_value(&DummyClientAccountKey(index))
+            .await
+            .ok_or(format_err!("Account {index} does not exist"))?;
+
+        Ok(self.derive_account_key_pair(index))
+    }
+
+    fn derive_account_key_pair(&self, index: u64) -> KeyPair {
+        self.root_secret
+            .child_key(ChildId(index))
+            .to_secp_key(&Secp256k1::new())
+    }
 }
 
 async fn get_funds(dbtx: &mut DatabaseTransaction<'_>) -> Amount {
@@ -311,6 +529,12 @@ async fn get_funds(dbtx: &mut DatabaseTransaction<'_>) -> Amount {
     funds.unwrap_or(Amount::ZERO)
 }
 
+async fn get_account_funds(dbtx: &mut DatabaseTransaction<'_>, index: u64) -> Amount {
+    dbtx.get_value(&DummyClientAccountFundsKey(index))
+        .await
+        .unwrap_or(Amount::ZERO)
+}
+
 #[derive(Debug, Clone)]
 pub struct DummyClientInit;
 
@@ -336,6 +560,28 @@ impl ModuleInit for DummyClientInit {
                         items.insert("Dummy Funds".to_string(), Box::new(funds));
                     }
                 }
+                DbKeyPrefix::ClientAccount => {
+                    let accounts = dbtx
+                        .find_by_prefix(&DummyClientAccountKeyPrefix)
+                        .await
+                        .map(|(key, name)| (key.0, name))
+                        .collect::<BTreeMap<u64, String>>()
+                        .await;
+                    if !accounts.is_empty() {
+                        items.insert("Dummy Accounts".to_string(), Box::new(accounts));
+                    }
+                }
+                DbKeyPrefix::ClientAccountFunds => {
+                    let funds = dbtx
+                        .find_by_prefix(&DummyClientAccountFundsKeyPrefix)
+                        .await
+                        .map(|(key, funds)| (key.0, funds))
+                        .collect::<BTreeMap<u64, Amount>>()
+                        .await;
+                    if !funds.is_empty() {
+                        items.insert("Dummy Account Funds".to_string(), Box::new(funds));
+                    }
+                }
                 DbKeyPrefix::ClientName => {
                     if let Some(name) = dbtx.get_value(&DummyClientNameKey).await {
                         items.insert("Dummy Name".to_string(), Box::new(name));
@@ -365,6 +611,7 @@ impl ClientModuleInit for DummyClientInit {
                 .module_root_secret()
                 .clone()
                 .to_secp_key(&Secp256k1::new()),
+            root_secret: args.module_root_secret().clone(),
 
             notifier: args.notifier().clone(),
             client_ctx: args.context(),
@@ -54,6 +54,7 @@ impl State for DummyStateMachine {
                     global_context.clone(),
                     OutPoint { txid, out_idx: 0 },
                     context.dummy_decoder.clone(),
+                    context.output_delay_sessions,
                 ),
                 move |dbtx, res, _state: Self| match res {
                     // output accepted, add funds
@@ -101,6 +102,7 @@ async fn await_dummy_output_outcome(
     global_context: DynGlobalClientContext,
     outpoint: OutPoint,
     module_decoder: Decoder,
+    delay_sessions: u64,
 ) -> Result<(), DummyError> {
     loop {
         match global_context
@@ -113,6 +115,7 @@ async fn await_dummy_output_outcome(
             .await
         {
             Ok(_) => {
+                await_sessions(&global_context, delay_sessions).await;
                 return Ok(());
             }
             Err(e) if e.is_rejected() => {
@@ -127,6 +130,29 @@ async fn await_dummy_output_outcome(
     }
 }
 
+/// Waits until `sessions` more sessions have been completed by the federation
+async fn await_sessions(global_context: &DynGlobalClientContext, sessions: u64) {
+    if sessions == 0 {
+        return;
+    }
+
+    let mut target_session_count = None;
+    loop {
+        match global_context.api().session_count().await {
+            Ok(session_count) => {
+                let target = *target_session_count.get_or_insert(session_count + sessions);
+                if target <= session_count {
+                    return;
+                }
+            }
+            Err(e) => {
+                debug!(error = %e, "Fetching session count failed, retrying");
+            }
+        }
+        sleep(RETRY_DELAY).await;
+    }
+}
+
 // TODO: Boiler-plate
 impl IntoDynInstance for DummyStateMachine {
     type DynType = DynState;
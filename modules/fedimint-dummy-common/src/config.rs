@@ -1,9 +1,10 @@
+use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{plugin_types_trait_impl_config, Amount};
+use fedimint_core::{plugin_types_trait_impl_config, Amount, OutPoint};
 use serde::{Deserialize, Serialize};
 
-use crate::DummyCommonInit;
+use crate::{DummyCommonInit, DummyInput};
 
 /// Parameters necessary to generate this module's configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -20,6 +21,45 @@ pub struct DummyGenParamsLocal;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DummyGenParamsConsensus {
     pub tx_fee: Amount,
+    #[serde(default)]
+    pub failure_injection: DummyFailureInjection,
+}
+
+/// Failures the dummy module injects, so tests can deterministically exercise
+/// error paths of the client executor and the gateway. Disabled by default.
+///
+/// Which items fail is derived from their consensus hash, so all guardians
+/// agree and resubmitting the same input fails again.
+#[derive(
+    Debug, Clone, Default, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct DummyFailureInjection {
+    /// Reject every Nth input on average, `1` rejects every input
+    pub reject_every_nth_input: Option<u64>,
+    /// Number of sessions clients wait for after an output was accepted
+    /// before they consider it final
+    pub output_delay_sessions: u64,
+    /// Reject every Nth output on average, which makes clients refund the
+    /// inputs of the transaction. `1` rejects every output.
+    pub refund_every_nth_output: Option<u64>,
+}
+
+impl DummyFailureInjection {
+    pub fn rejects_input(&self, input: &DummyInput) -> bool {
+        Self::is_selected(self.reject_every_nth_input, input)
+    }
+
+    pub fn refunds_output(&self, out_point: &OutPoint) -> bool {
+        Self::is_selected(self.refund_every_nth_output, out_point)
+    }
+
+    fn is_selected(every_nth: Option<u64>, item: &impl Encodable) -> bool {
+        every_nth.is_some_and(|n| {
+            let hash: sha256::Hash = item.consensus_hash();
+            let prefix = u64::from_be_bytes(hash.to_byte_array()[..8].try_into().expect("8 bytes"));
+            prefix % n.max(1) == 0
+        })
+    }
 }
 
 impl Default for DummyGenParams {
@@ -28,6 +68,7 @@ impl Default for DummyGenParams {
             local: DummyGenParamsLocal,
             consensus: DummyGenParamsConsensus {
                 tx_fee: Amount::ZERO,
+                failure_injection: DummyFailureInjection::default(),
             },
         }
     }
@@ -46,6 +87,7 @@ pub struct DummyConfig {
 pub struct DummyClientConfig {
     /// Accessible to clients
     pub tx_fee: Amount,
+    pub failure_injection: DummyFailureInjection,
 }
 
 /// Locally unencrypted config unique to each member
@@ -57,6 +99,8 @@ pub struct DummyConfigLocal;
 pub struct DummyConfigConsensus {
     /// Will be the same for all peers
     pub tx_fee: Amount,
+    #[serde(default)]
+    pub failure_injection: DummyFailureInjection,
 }
 
 /// Will be encrypted and not shared such as private key material
@@ -54,11 +54,16 @@ pub struct DummyOutputOutcome(pub Amount, pub PublicKey);
 pub enum DummyInputError {
     #[error("Not enough funds")]
     NotEnoughFunds,
+    #[error("Input rejected by failure injection")]
+    InjectedFailure,
 }
 
 /// Errors that might be returned by the server
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Error, Encodable, Decodable)]
-pub enum DummyOutputError {}
+pub enum DummyOutputError {
+    #[error("Output refunded by failure injection")]
+    InjectedRefund,
+}
 
 /// Contains the types defined above
 pub struct DummyModuleTypes;
@@ -135,6 +135,7 @@ impl ServerModuleInit for DummyInit {
                     private: DummyConfigPrivate,
                     consensus: DummyConfigConsensus {
                         tx_fee: params.consensus.tx_fee,
+                        failure_injection: params.consensus.failure_injection.clone(),
                     },
                 };
                 (peer, config.to_erased())
@@ -155,6 +156,7 @@ impl ServerModuleInit for DummyInit {
             private: DummyConfigPrivate,
             consensus: DummyConfigConsensus {
                 tx_fee: params.consensus.tx_fee,
+                failure_injection: params.consensus.failure_injection,
             },
         }
         .to_erased())
@@ -168,6 +170,7 @@ impl ServerModuleInit for DummyInit {
         let config = DummyConfigConsensus::from_erased(config)?;
         Ok(DummyClientConfig {
             tx_fee: config.tx_fee,
+            failure_injection: config.failure_injection,
         })
     }
 
@@ -226,6 +229,10 @@ impl ServerModule for Dummy {
         dbtx: &mut DatabaseTransaction<'c>,
         input: &'b DummyInput,
     ) -> Result<InputMeta, DummyInputError> {
+        if self.cfg.consensus.failure_injection.rejects_input(input) {
+            return Err(DummyInputError::InjectedFailure);
+        }
+
         let current_funds = dbtx
             .get_value(&DummyFundsKeyV1(input.account))
             .await
@@ -268,6 +275,15 @@ impl ServerModule for Dummy {
         output: &'a DummyOutput,
         out_point: OutPoint,
     ) -> Result<TransactionItemAmount, DummyOutputError> {
+        if self
+            .cfg
+            .consensus
+            .failure_injection
+            .refunds_output(&out_point)
+        {
+            return Err(DummyOutputError::InjectedRefund);
+        }
+
         // Add output funds to the user's account
         let current_funds = dbtx.get_value(&DummyFundsKeyV1(output.account)).await;
         let updated_funds = current_funds.unwrap_or(Amount::ZERO) + output.amount;
@@ -1,19 +1,24 @@
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 use anyhow::bail;
+use fedimint_client::backup::Metadata;
+use fedimint_client::events::ClientEvent;
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
+use fedimint_client::WatchOnly;
 use fedimint_core::config::ClientModuleConfig;
 use fedimint_core::core::{IntoDynInstance, ModuleKind, OperationId};
 use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::Database;
 use fedimint_core::module::ModuleConsensusVersion;
-use fedimint_core::secp256k1::Secp256k1;
 use fedimint_core::{sats, Amount, OutPoint};
 use fedimint_dummy_client::states::DummyStateMachine;
 use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
-use fedimint_dummy_common::config::{DummyClientConfig, DummyGenParams};
+use fedimint_dummy_common::config::{DummyClientConfig, DummyFailureInjection, DummyGenParams};
 use fedimint_dummy_common::{broken_fed_key_pair, DummyInput, DummyOutput, KIND};
 use fedimint_dummy_server::DummyInit;
 use fedimint_testing::fixtures::Fixtures;
+use futures::StreamExt;
 
 fn fixtures() -> Fixtures {
     Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default())
@@ -39,6 +44,78 @@ async fn can_print_and_send_money() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn can_send_money_between_accounts() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    let dummy_module = client1.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+
+    let savings = dummy_module.create_account("savings".to_string()).await?;
+    assert!(dummy_module
+        .create_account("savings".to_string())
+        .await
+        .is_err());
+    assert_ne!(
+        dummy_module.account_of(savings).await?,
+        dummy_module.account()
+    );
+
+    let outpoint = dummy_module
+        .send_money(dummy_module.account_of(savings).await?, sats(400))
+        .await?;
+    dummy_module.receive_money(outpoint).await?;
+    assert_eq!(dummy_module.account_balance(savings).await, sats(400));
+    assert_eq!(client1.get_balance().await, sats(600));
+
+    let client2_dummy_module = client2.get_first_module::<DummyClientModule>();
+    let outpoint = dummy_module
+        .send_money_from(savings, client2_dummy_module.account(), sats(150))
+        .await?;
+    client2_dummy_module.receive_money(outpoint).await?;
+    assert_eq!(dummy_module.account_balance(savings).await, sats(250));
+    assert_eq!(client2.get_balance().await, sats(150));
+
+    let accounts = dummy_module.accounts().await;
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[1].name, "savings");
+    assert_eq!(accounts[1].balance, sats(250));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_emits_balance_and_operation_events() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+    let mut events = client.subscribe_events().await;
+
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op_id, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+
+    let mut saw_operation_event = false;
+    loop {
+        match events.next().await.expect("event stream ended") {
+            ClientEvent::OperationStateChanged {
+                op_id: event_op, ..
+            } => {
+                saw_operation_event |= event_op == op_id;
+            }
+            ClientEvent::BalanceChanged { module, delta } => {
+                assert_eq!(module, dummy_module.id);
+                assert_eq!(delta, sats(1000).msats as i64);
+                break;
+            }
+            ClientEvent::RecoveryDone => bail!("No recovery was running"),
+        }
+    }
+    assert!(saw_operation_event);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn client_ignores_unknown_module() {
     let fed = fixtures().new_default_fed().await;
@@ -52,6 +129,7 @@ async fn client_ignores_unknown_module() {
         ModuleConsensusVersion::new(0, 0),
         DummyClientConfig {
             tx_fee: Amount::from_sats(1),
+            failure_injection: DummyFailureInjection::default(),
         },
     )
     .unwrap();
@@ -92,7 +170,7 @@ async fn federation_should_abort_if_balance_sheet_is_negative() -> anyhow::Resul
             account: account_kp.public_key(),
         },
         amount: sats(1000),
-        keys: vec![account_kp],
+        keys: vec![account_kp.into()],
         state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
     };
 
@@ -127,7 +205,7 @@ async fn unbalanced_transactions_get_rejected() -> anyhow::Result<()> {
         state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
     };
     let tx = TransactionBuilder::new().with_output(output.into_dyn(dummy_module.id));
-    let (tx, _) = tx.build(&Secp256k1::new(), rand::thread_rng());
+    let (tx, _) = tx.build(rand::thread_rng()).await?;
     let result = client.api().submit_transaction(tx).await;
     match result {
         Ok(submission_outcome) => {
@@ -146,6 +224,212 @@ async fn unbalanced_transactions_get_rejected() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Transactions of a client in offline mode are queued while the federation is
+/// unreachable and can be submitted later or cancelled to recover their funds
+#[tokio::test(flavor = "multi_thread")]
+async fn queues_transactions_while_federation_is_unreachable() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_offline_mode_client().await;
+    let receiver = fed.new_client().await;
+
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+
+    let receiver_module = receiver.get_first_module::<DummyClientModule>();
+    let send = |amount: Amount| {
+        let output = ClientOutput {
+            output: DummyOutput {
+                amount,
+                account: receiver_module.account(),
+            },
+            amount,
+            state_machines: Arc::new(move |_, _| Vec::<DummyStateMachine>::new()),
+        };
+        TransactionBuilder::new().with_output(output.into_dyn(dummy_module.id))
+    };
+    let outpoint = |txid, _| OutPoint { txid, out_idx: 0 };
+
+    fed.set_reachable(&client, false).await;
+
+    let flushed_op = OperationId::new_random();
+    let (flushed_txid, _) = client
+        .finalize_and_submit_transaction(flushed_op, KIND.as_str(), outpoint, send(sats(250)))
+        .await?;
+    let cancelled_op = OperationId::new_random();
+    let (cancelled_txid, _) = client
+        .finalize_and_submit_transaction(cancelled_op, KIND.as_str(), outpoint, send(sats(100)))
+        .await?;
+
+    assert_eq!(
+        client
+            .outbox()
+            .await
+            .into_iter()
+            .map(|entry| (entry.operation_id, entry.txid))
+            .collect::<BTreeSet<_>>(),
+        BTreeSet::from([(flushed_op, flushed_txid), (cancelled_op, cancelled_txid)])
+    );
+    assert_eq!(client.get_balance().await, sats(650));
+
+    // the cancelled transaction is rejected without being submitted and the
+    // funds of its input are refunded
+    client.cancel_outbox_transaction(cancelled_op).await?;
+    assert!(client
+        .transaction_updates(cancelled_op)
+        .await
+        .await_tx_accepted(cancelled_txid)
+        .await
+        .is_err());
+    client
+        .subscribe_balance_changes()
+        .await
+        .filter(|balance| std::future::ready(*balance == sats(750)))
+        .next()
+        .await;
+    assert!(client
+        .cancel_outbox_transaction(cancelled_op)
+        .await
+        .is_err());
+
+    // flushing submits the transaction even while the federation is
+    // unreachable, its submission is retried until it is reachable again
+    assert_eq!(client.flush_outbox().await?, vec![flushed_op]);
+    assert!(client.outbox().await.is_empty());
+
+    fed.set_reachable(&client, true).await;
+    client
+        .transaction_updates(flushed_op)
+        .await
+        .await_tx_accepted(flushed_txid)
+        .await
+        .map_err(anyhow::Error::msg)?;
+
+    receiver_module
+        .receive_money(OutPoint {
+            txid: flushed_txid,
+            out_idx: 0,
+        })
+        .await?;
+    assert_eq!(receiver.get_balance().await, sats(250));
+    assert_eq!(client.get_balance().await, sats(750));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_only_client_shows_balance_but_cannot_spend() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let other_client = fed.new_client().await;
+
+    let db = Database::from(MemDatabase::new());
+    let client = fed
+        .new_client_with(other_client.get_config().clone(), db.clone(), None)
+        .await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+    Arc::into_inner(client)
+        .expect("only one client handle")
+        .shutdown()
+        .await;
+
+    let watch_only_client = fed.open_watch_only_client(db).await;
+    assert!(watch_only_client.is_watch_only());
+    assert_eq!(watch_only_client.get_balance().await, sats(1000));
+
+    let err = watch_only_client
+        .get_first_module::<DummyClientModule>()
+        .send_money(
+            other_client
+                .get_first_module::<DummyClientModule>()
+                .account(),
+            sats(250),
+        )
+        .await
+        .expect_err("watch-only client must not spend");
+    assert_eq!(err.downcast_ref::<WatchOnly>(), Some(&WatchOnly));
+    assert_eq!(watch_only_client.get_balance().await, sats(1000));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn operation_labels_can_be_set_queried_and_backed_up() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op1, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    dummy_module.receive_money(outpoint).await?;
+    let (op2, outpoint) = dummy_module.print_money(sats(500)).await?;
+    dummy_module.receive_money(outpoint).await?;
+
+    client.set_operation_label(op1, Some("rent".into())).await;
+    client.set_operation_label(op2, Some("rent".into())).await;
+    assert_eq!(
+        client.get_operation_label(op1).await.as_deref(),
+        Some("rent")
+    );
+    assert_eq!(client.get_operations_by_label("rent").await.len(), 2);
+
+    // Relabeling removes the operation from its previous label
+    client.set_operation_label(op2, Some("food".into())).await;
+    assert_eq!(client.get_operations_by_label("rent").await, vec![op1]);
+    assert_eq!(client.get_operations_by_label("food").await, vec![op2]);
+
+    client.set_operation_label(op1, None).await;
+    assert_eq!(client.get_operation_label(op1).await, None);
+    assert!(client.get_operations_by_label("rent").await.is_empty());
+
+    let backup = client.create_backup(Metadata::empty()).await?;
+    assert_eq!(backup.labels, [(op2, "food".to_string())].into());
+
+    Ok(())
+}
+
+fn fixtures_with_failure_injection(failure_injection: DummyFailureInjection) -> Fixtures {
+    let mut params = DummyGenParams::default();
+    params.consensus.failure_injection = failure_injection;
+    Fixtures::new_primary(DummyClientInit, DummyInit, params)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_input_failures_reject_transactions() -> anyhow::Result<()> {
+    let fed = fixtures_with_failure_injection(DummyFailureInjection {
+        reject_every_nth_input: Some(1),
+        ..DummyFailureInjection::default()
+    })
+    .new_default_fed()
+    .await;
+    let client = fed.new_client().await;
+
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    assert!(dummy_module.receive_money(outpoint).await.is_err());
+    assert_eq!(client.get_balance().await, Amount::ZERO);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn injected_refunds_reject_outputs() -> anyhow::Result<()> {
+    let fed = fixtures_with_failure_injection(DummyFailureInjection {
+        refund_every_nth_output: Some(1),
+        ..DummyFailureInjection::default()
+    })
+    .new_default_fed()
+    .await;
+    let client = fed.new_client().await;
+
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (_, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    assert!(dummy_module.receive_money(outpoint).await.is_err());
+    assert_eq!(client.get_balance().await, Amount::ZERO);
+
+    Ok(())
+}
+
 mod fedimint_migration_tests {
     use anyhow::ensure;
     use fedimint_client::module::init::DynClientModuleInit;
@@ -168,8 +452,8 @@ mod fedimint_migration_tests {
     use fedimint_dummy_server::DummyInit;
     use fedimint_logging::TracingSetup;
     use fedimint_testing::db::{
-        snapshot_db_migrations, snapshot_db_migrations_client, validate_migrations_client,
-        validate_migrations_server, BYTE_32, TEST_MODULE_INSTANCE_ID,
+        snapshot_db_migrations, snapshot_db_migrations_client, validate_migration_rollback_client,
+        validate_migrations_client, validate_migrations_server, BYTE_32, TEST_MODULE_INSTANCE_ID,
     };
     use futures::StreamExt;
     use rand::rngs::OsRng;
@@ -365,6 +649,10 @@ mod fedimint_migration_tests {
                         );
                         info!("Validated client funds");
                     }
+                    fedimint_dummy_client::db::DbKeyPrefix::ClientAccount
+                    | fedimint_dummy_client::db::DbKeyPrefix::ClientAccountFunds => {
+                        // Accounts didn't exist in version 0
+                    }
                     fedimint_dummy_client::db::DbKeyPrefix::ClientName => {
                         // No need to validate re-reading of ClientName, it
                         // is only used to validate that the
@@ -405,4 +693,15 @@ mod fedimint_migration_tests {
         })
         .await
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_db_migrations_can_be_checked_and_rolled_back() -> anyhow::Result<()> {
+        let _ = TracingSetup::default().init();
+
+        validate_migration_rollback_client::<DummyClientModule>(
+            DynClientModuleInit::from(DummyClientInit),
+            "dummy-client",
+        )
+        .await
+    }
 }
@@ -1,14 +1,16 @@
+use std::collections::BTreeMap;
 use std::{ffi, iter};
 
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use clap::Parser;
 use fedimint_core::core::OperationId;
 use fedimint_core::Amount;
+use futures::StreamExt;
 use lightning_invoice::{Bolt11InvoiceDescription, Description};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::OutgoingLightningPayment;
+use crate::{LnurlWithdrawState, OutgoingLightningPayment};
 
 #[derive(Parser, Serialize)]
 enum Opts {
@@ -42,6 +44,26 @@ enum Opts {
         #[clap(long, default_value = "false")]
         force_internal: bool,
     },
+    /// Send a spontaneous payment to a lightning node via a gateway
+    Keysend {
+        /// Public key of the receiving lightning node
+        destination: secp256k1::PublicKey,
+        amount: Amount,
+        /// Will return immediately after funding the payment
+        #[clap(long, action)]
+        finish_in_background: bool,
+        #[clap(long)]
+        gateway_id: Option<secp256k1::PublicKey>,
+    },
+    /// Claim an LNURL-withdraw into ecash via a gateway
+    LnurlWithdraw {
+        lnurl: String,
+        /// Amount to withdraw, defaults to the maximum allowed by the service
+        #[clap(long)]
+        amount: Option<Amount>,
+        #[clap(long)]
+        gateway_id: Option<secp256k1::PublicKey>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,7 +115,12 @@ pub(crate) async fn handle_cli_command(
         } => {
             let bolt11 = crate::get_invoice(&payment_info, amount, lnurl_comment).await?;
             info!("Paying invoice: {bolt11}");
-            let ln_gateway = module.get_gateway(gateway_id, force_internal).await?;
+            // Internal invoices are paid without a gateway, so none has to be selected
+            let ln_gateway = if module.is_internal_invoice(&bolt11).await? {
+                None
+            } else {
+                module.get_gateway(gateway_id, force_internal).await?
+            };
 
             let OutgoingLightningPayment {
                 payment_type,
@@ -125,5 +152,78 @@ pub(crate) async fn handle_cli_command(
                     .context("expected a response")?
             }
         }
+        Opts::Keysend {
+            destination,
+            amount,
+            finish_in_background,
+            gateway_id,
+        } => {
+            let ln_gateway = module
+                .get_gateway(gateway_id, false)
+                .await?
+                .context("No gateway available")?;
+
+            let OutgoingLightningPayment {
+                payment_type,
+                contract_id,
+                fee,
+            } = module
+                .pay_keysend(ln_gateway, destination, amount, BTreeMap::new(), ())
+                .await?;
+            let operation_id = payment_type.operation_id();
+            info!(
+                "Gateway fee: {fee}, keysend operation id: {}",
+                operation_id.fmt_short()
+            );
+            let response = module
+                .wait_for_ln_payment(payment_type, contract_id, finish_in_background)
+                .await?;
+            match response {
+                Some(response) => response,
+                None => serde_json::json! {
+                    {
+                        "operation_id": operation_id,
+                        "contract_id": contract_id,
+                        "fee": fee,
+                    }
+                },
+            }
+        }
+        Opts::LnurlWithdraw {
+            lnurl,
+            amount,
+            gateway_id,
+        } => {
+            let ln_gateway = module
+                .get_gateway(gateway_id, false)
+                .await?
+                .context("No gateway available")?;
+
+            let operation_id = module
+                .lnurl_withdraw(&lnurl, amount, ln_gateway, ())
+                .await?;
+            info!("LNURL-withdraw operation id: {}", operation_id.fmt_short());
+
+            let mut updates = module
+                .subscribe_lnurl_withdraw(operation_id)
+                .await?
+                .into_stream();
+            while let Some(update) = updates.next().await {
+                info!("LNURL-withdraw state: {update:?}");
+                match update {
+                    LnurlWithdrawState::Claimed => break,
+                    LnurlWithdrawState::Canceled { reason } => {
+                        bail!("LNURL-withdraw failed: {reason}")
+                    }
+                    _ => {}
+                }
+            }
+
+            serde_json::json! {
+                {
+                    "operation_id": operation_id,
+                }
+            }
+        }
     })
 }
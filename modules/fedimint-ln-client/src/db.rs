@@ -5,12 +5,14 @@ use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{impl_db_lookup, impl_db_record, OutPoint, TransactionId};
+use fedimint_ln_common::contracts::ContractId;
 use fedimint_ln_common::{LightningGateway, LightningGatewayRegistration};
 use lightning_invoice::Bolt11Invoice;
 use secp256k1::{KeyPair, PublicKey};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::gateway_score::GatewayScore;
 use crate::pay::{
     LightningPayCommon, LightningPayFunded, LightningPayRefund, LightningPayStateMachine,
     LightningPayStates, PayInvoicePayload,
@@ -29,6 +31,9 @@ pub enum DbKeyPrefix {
     PaymentResult = 0x29,
     MetaOverridesDeprecated = 0x30,
     LightningGateway = 0x45,
+    GatewayScore = 0x46,
+    PaymentFailover = 0x47,
+    IncomingContractClaim = 0x48,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -91,6 +96,57 @@ impl_db_lookup!(
     query_prefix = LightningGatewayKeyPrefix
 );
 
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct GatewayScoreKey(pub PublicKey);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayScoreKeyPrefix;
+
+impl_db_record!(
+    key = GatewayScoreKey,
+    value = GatewayScore,
+    db_prefix = DbKeyPrefix::GatewayScore,
+);
+impl_db_lookup!(key = GatewayScoreKey, query_prefix = GatewayScoreKeyPrefix);
+
+/// Gateways that already failed to pay the invoice of an outgoing payment
+/// operation, so a re-dispatch does not select them again
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PaymentFailoverKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PaymentFailoverKeyPrefix;
+
+impl_db_record!(
+    key = PaymentFailoverKey,
+    value = Vec<PublicKey>,
+    db_prefix = DbKeyPrefix::PaymentFailover,
+);
+impl_db_lookup!(
+    key = PaymentFailoverKey,
+    query_prefix = PaymentFailoverKeyPrefix
+);
+
+/// Operation claiming an incoming contract through
+/// [`crate::LightningClientModule::claim_funded_incoming_contract`], so
+/// concurrent claims of the same contract return it instead of submitting a
+/// duplicate claim
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct IncomingContractClaimKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct IncomingContractClaimKeyPrefix;
+
+impl_db_record!(
+    key = IncomingContractClaimKey,
+    value = OperationId,
+    db_prefix = DbKeyPrefix::IncomingContractClaim,
+);
+impl_db_lookup!(
+    key = IncomingContractClaimKey,
+    query_prefix = IncomingContractClaimKeyPrefix
+);
+
 /// Migrates `SubmittedOfferV0` to `SubmittedOffer` and `ConfirmedInvoiceV0` to
 /// `ConfirmedInvoice`
 pub(crate) fn get_v1_migrated_state(
@@ -0,0 +1,172 @@
+use std::cmp::Reverse;
+use std::time::Duration;
+
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_ln_common::LightningGateway;
+use futures::StreamExt;
+use lightning_invoice::RoutingFees;
+use secp256k1::PublicKey;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{GatewayScoreKey, LightningGatewayKeyPrefix};
+
+/// Track record of a gateway, updated whenever a payment routed through it
+/// succeeds or fails. Used to rank the gateways of the gateway cache.
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable,
+)]
+pub struct GatewayScore {
+    /// Number of payments the gateway completed
+    pub successes: u64,
+    /// Number of payments the gateway failed to complete
+    pub failures: u64,
+    /// Sum of the time between funding and completion of all successful
+    /// payments, in milliseconds
+    pub total_latency_ms: u64,
+    /// Base routing fee the gateway charged when it was last seen
+    pub fee_base_msat: u32,
+    /// Proportional routing fee the gateway charged when it was last seen
+    pub fee_proportional_millionths: u32,
+}
+
+impl GatewayScore {
+    /// Success rate in parts per million. Gateways start at 50% and converge
+    /// towards their actual success rate as payments are made.
+    pub fn success_rate_ppm(&self) -> u64 {
+        (self.successes + 1) * 1_000_000 / (self.successes + self.failures + 2)
+    }
+
+    /// Average time it took the gateway to complete a payment, if it ever did
+    pub fn average_latency(&self) -> Option<Duration> {
+        self.total_latency_ms
+            .checked_div(self.successes)
+            .map(Duration::from_millis)
+    }
+
+    pub fn fees(&self) -> RoutingFees {
+        RoutingFees {
+            base_msat: self.fee_base_msat,
+            proportional_millionths: self.fee_proportional_millionths,
+        }
+    }
+
+    fn update_fees(&mut self, fees: RoutingFees) {
+        self.fee_base_msat = fees.base_msat;
+        self.fee_proportional_millionths = fees.proportional_millionths;
+    }
+
+    /// Gateways with a higher success rate are preferred, ties are broken by
+    /// lower fees and then by lower latency
+    fn rank(&self) -> impl Ord {
+        (
+            Reverse(self.success_rate_ppm()),
+            self.fee_proportional_millionths,
+            self.fee_base_msat,
+            self.average_latency().unwrap_or(Duration::MAX),
+        )
+    }
+}
+
+/// Loads the score of the gateway, refreshing its fees to the ones the gateway
+/// currently advertises
+async fn load_score(
+    dbtx: &mut DatabaseTransaction<'_>,
+    gateway: &LightningGateway,
+) -> GatewayScore {
+    let mut score = dbtx
+        .get_value(&GatewayScoreKey(gateway.gateway_id))
+        .await
+        .unwrap_or_default();
+    score.update_fees(gateway.fees);
+    score
+}
+
+pub(crate) async fn record_gateway_success(
+    dbtx: &mut DatabaseTransaction<'_>,
+    gateway: &LightningGateway,
+    latency: Duration,
+) {
+    let mut score = load_score(dbtx, gateway).await;
+    score.successes += 1;
+    score.total_latency_ms = score
+        .total_latency_ms
+        .saturating_add(latency.as_millis() as u64);
+    dbtx.insert_entry(&GatewayScoreKey(gateway.gateway_id), &score)
+        .await;
+}
+
+pub(crate) async fn record_gateway_failure(
+    dbtx: &mut DatabaseTransaction<'_>,
+    gateway: &LightningGateway,
+) {
+    let mut score = load_score(dbtx, gateway).await;
+    score.failures += 1;
+    dbtx.insert_entry(&GatewayScoreKey(gateway.gateway_id), &score)
+        .await;
+}
+
+/// Returns the gateways of the gateway cache ordered from best to worst,
+/// skipping the `excluded` ones
+pub(crate) async fn ranked_gateways(
+    dbtx: &mut DatabaseTransaction<'_>,
+    excluded: &[PublicKey],
+) -> Vec<LightningGateway> {
+    let gateways = dbtx
+        .find_by_prefix(&LightningGatewayKeyPrefix)
+        .await
+        .map(|(_, gw)| gw.info)
+        .filter(|gw| futures::future::ready(!excluded.contains(&gw.gateway_id)))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut ranked = Vec::with_capacity(gateways.len());
+    for gateway in gateways {
+        let score = load_score(dbtx, &gateway).await;
+        ranked.push((score, gateway));
+    }
+
+    ranked.sort_by_cached_key(|(score, _)| score.rank());
+    ranked.into_iter().map(|(_, gateway)| gateway).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::GatewayScore;
+
+    fn score(successes: u64, failures: u64, fee_proportional_millionths: u32) -> GatewayScore {
+        GatewayScore {
+            successes,
+            failures,
+            total_latency_ms: successes * 1_000,
+            fee_base_msat: 0,
+            fee_proportional_millionths,
+        }
+    }
+
+    #[test]
+    fn unknown_gateway_starts_at_half_success_rate() {
+        let unknown = GatewayScore::default();
+        assert_eq!(unknown.success_rate_ppm(), 500_000);
+        assert_eq!(unknown.average_latency(), None);
+        assert_eq!(
+            score(2, 0, 0).average_latency(),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn gateways_are_ranked_by_success_rate_then_fees() {
+        let reliable = score(10, 0, 1_000);
+        let unknown = score(0, 0, 0);
+        let unreliable = score(0, 3, 0);
+        let cheap_reliable = score(10, 0, 100);
+
+        let mut scores = [&unreliable, &reliable, &unknown, &cheap_reliable];
+        scores.sort_by_cached_key(|score| score.rank());
+
+        assert_eq!(scores, [&cheap_reliable, &reliable, &unknown, &unreliable]);
+    }
+}
@@ -330,7 +330,7 @@ impl DecryptingPreimageState {
             input: claim_input,
             amount: contract.amount,
             state_machines: Arc::new(|_, _| vec![]),
-            keys: vec![context.redeem_key],
+            keys: vec![context.redeem_key.into()],
         };
 
         let out_points = global_context.claim_input(dbtx, client_input).await.1;
@@ -0,0 +1,338 @@
+use std::time::SystemTime;
+
+use bitcoin::hashes::sha256;
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::config::FederationId;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{Amount, TransactionId};
+use fedimint_ln_common::contracts::outgoing::OutgoingContractData;
+use fedimint_ln_common::contracts::ContractId;
+use fedimint_ln_common::{KeysendPayment, LightningGateway};
+
+use crate::gateway_score::{record_gateway_failure, record_gateway_success};
+use crate::pay::{
+    await_contract_cancelled, await_contract_timeout, claim_outgoing_contract_refund,
+    GatewayPayError, LightningPayCreatedOutgoingLnContract, LightningPayFunded, LightningPayRefund,
+    PayInvoicePayload, PaymentData,
+};
+use crate::LightningClientContext;
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that requests the lightning gateway to send a spontaneous
+/// payment on behalf of a federation client.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///  CreatedOutgoingLnContract -- await transaction failed --> FundingRejected
+///  CreatedOutgoingLnContract -- await transaction acceptance --> Funded
+///  Funded -- await gateway payment success  --> Success
+///  Funded -- await gateway payment failed --> Refundable
+///  Funded -- gateway cancelled contract --> Refund
+///  Funded -- transaction timeout --> Refund
+///  Refundable -- gateway cancelled contract --> Refund
+///  Refundable -- transaction timeout --> Refund
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum LightningKeysendStates {
+    CreatedOutgoingLnContract(LightningKeysendCreatedOutgoingLnContract),
+    FundingRejected,
+    Funded(LightningKeysendFunded),
+    Success(String),
+    Refund(LightningPayRefund),
+    Refundable(LightningKeysendRefundable),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningKeysendCommon {
+    pub operation_id: OperationId,
+    pub federation_id: FederationId,
+    pub contract: OutgoingContractData,
+    pub gateway_fee: Amount,
+    pub preimage_auth: sha256::Hash,
+    pub keysend: KeysendPayment,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningKeysendStateMachine {
+    pub common: LightningKeysendCommon,
+    pub state: LightningKeysendStates,
+}
+
+impl State for LightningKeysendStateMachine {
+    type ModuleContext = LightningClientContext;
+
+    fn transitions(
+        &self,
+        context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            LightningKeysendStates::CreatedOutgoingLnContract(created) => {
+                created.transitions(context, global_context)
+            }
+            LightningKeysendStates::Funded(funded) => {
+                funded.transitions(self.common.clone(), context.clone(), global_context.clone())
+            }
+            LightningKeysendStates::Refundable(refundable) => {
+                refundable.transitions(self.common.clone(), global_context.clone())
+            }
+            LightningKeysendStates::FundingRejected
+            | LightningKeysendStates::Success(_)
+            | LightningKeysendStates::Refund(_) => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningKeysendCreatedOutgoingLnContract {
+    pub funding_txid: TransactionId,
+    pub contract_id: ContractId,
+    pub gateway: LightningGateway,
+}
+
+impl LightningKeysendCreatedOutgoingLnContract {
+    fn transitions(
+        &self,
+        context: &LightningClientContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningKeysendStateMachine>> {
+        let gateway = self.gateway.clone();
+        vec![StateTransition::new(
+            LightningPayCreatedOutgoingLnContract::await_outgoing_contract_funded(
+                context.ln_decoder.clone(),
+                global_context.clone(),
+                self.funding_txid,
+                self.contract_id,
+            ),
+            move |_dbtx, result, old_state| {
+                Box::pin(Self::transition_outgoing_contract_funded(
+                    result,
+                    old_state,
+                    gateway.clone(),
+                ))
+            },
+        )]
+    }
+
+    async fn transition_outgoing_contract_funded(
+        result: Result<u32, GatewayPayError>,
+        old_state: LightningKeysendStateMachine,
+        gateway: LightningGateway,
+    ) -> LightningKeysendStateMachine {
+        let common = old_state.common;
+        match result {
+            Ok(timelock) => {
+                let payload = PayInvoicePayload {
+                    federation_id: common.federation_id,
+                    contract_id: common.contract.contract_account.contract.contract_id(),
+                    payment_data: PaymentData::Keysend(common.keysend.clone()),
+                    preimage_auth: common.preimage_auth,
+                };
+                LightningKeysendStateMachine {
+                    common,
+                    state: LightningKeysendStates::Funded(LightningKeysendFunded {
+                        payload,
+                        gateway,
+                        timelock,
+                        funding_time: fedimint_core::time::now(),
+                    }),
+                }
+            }
+            Err(_) => LightningKeysendStateMachine {
+                common,
+                state: LightningKeysendStates::FundingRejected,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningKeysendFunded {
+    pub payload: PayInvoicePayload,
+    pub gateway: LightningGateway,
+    pub timelock: u32,
+    pub funding_time: SystemTime,
+}
+
+impl LightningKeysendFunded {
+    fn transitions(
+        &self,
+        common: LightningKeysendCommon,
+        context: LightningClientContext,
+        global_context: DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningKeysendStateMachine>> {
+        let success_gateway = self.gateway.clone();
+        let failed_gateway = self.gateway.clone();
+        let cancelled_gateway = self.gateway.clone();
+        let timeout_gateway = self.gateway.clone();
+        let contract_id = self.payload.contract_id;
+        let timelock = self.timelock;
+        let funding_time = self.funding_time;
+        let cancelled_common = common.clone();
+        let cancelled_global_context = global_context.clone();
+        vec![
+            StateTransition::new(
+                LightningPayFunded::gateway_pay_invoice(
+                    self.gateway.clone(),
+                    self.payload.clone(),
+                    context,
+                    funding_time,
+                ),
+                move |dbtx, result, old_state| {
+                    let success_gateway = success_gateway.clone();
+                    let failed_gateway = failed_gateway.clone();
+                    Box::pin(async move {
+                        let state = match result {
+                            Ok(preimage) => {
+                                let latency = fedimint_core::time::now()
+                                    .duration_since(funding_time)
+                                    .unwrap_or_default();
+                                record_gateway_success(
+                                    &mut dbtx.module_tx(),
+                                    &success_gateway,
+                                    latency,
+                                )
+                                .await;
+                                LightningKeysendStates::Success(preimage)
+                            }
+                            // The funds stay locked in the contract until the gateway
+                            // cancels it or the timelock expires
+                            Err(error) => {
+                                record_gateway_failure(&mut dbtx.module_tx(), &failed_gateway)
+                                    .await;
+                                LightningKeysendStates::Refundable(LightningKeysendRefundable {
+                                    contract_id,
+                                    timelock,
+                                    error_reason: error.to_string(),
+                                })
+                            }
+                        };
+                        LightningKeysendStateMachine {
+                            common: old_state.common,
+                            state,
+                        }
+                    })
+                },
+            ),
+            StateTransition::new(
+                await_contract_cancelled(contract_id, global_context.clone()),
+                move |dbtx, (), old_state| {
+                    Box::pin(refund_keysend(
+                        old_state,
+                        cancelled_common.clone(),
+                        Some(cancelled_gateway.clone()),
+                        dbtx,
+                        cancelled_global_context.clone(),
+                        format!("Gateway cancelled contract: {contract_id}"),
+                    ))
+                },
+            ),
+            StateTransition::new(
+                await_contract_timeout(global_context.clone(), timelock),
+                move |dbtx, (), old_state| {
+                    Box::pin(refund_keysend(
+                        old_state,
+                        common.clone(),
+                        Some(timeout_gateway.clone()),
+                        dbtx,
+                        global_context.clone(),
+                        format!("Outgoing contract timed out, BlockHeight: {timelock}"),
+                    ))
+                },
+            ),
+        ]
+    }
+}
+
+/// The gateway rejected the keysend payment, the contract can be refunded once
+/// the gateway cancelled it or its timelock expired
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningKeysendRefundable {
+    pub contract_id: ContractId,
+    pub timelock: u32,
+    pub error_reason: String,
+}
+
+impl LightningKeysendRefundable {
+    fn transitions(
+        &self,
+        common: LightningKeysendCommon,
+        global_context: DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningKeysendStateMachine>> {
+        let contract_id = self.contract_id;
+        let timelock = self.timelock;
+        let cancelled_error = format!(
+            "{}, gateway cancelled contract: {contract_id}",
+            self.error_reason
+        );
+        let timeout_error = format!(
+            "{}, outgoing contract timed out, BlockHeight: {timelock}",
+            self.error_reason
+        );
+        let cancelled_common = common.clone();
+        let cancelled_global_context = global_context.clone();
+        vec![
+            StateTransition::new(
+                await_contract_cancelled(contract_id, global_context.clone()),
+                move |dbtx, (), old_state| {
+                    Box::pin(refund_keysend(
+                        old_state,
+                        cancelled_common.clone(),
+                        None,
+                        dbtx,
+                        cancelled_global_context.clone(),
+                        cancelled_error.clone(),
+                    ))
+                },
+            ),
+            StateTransition::new(
+                await_contract_timeout(global_context.clone(), timelock),
+                move |dbtx, (), old_state| {
+                    Box::pin(refund_keysend(
+                        old_state,
+                        common.clone(),
+                        None,
+                        dbtx,
+                        global_context.clone(),
+                        timeout_error.clone(),
+                    ))
+                },
+            ),
+        ]
+    }
+}
+
+/// Claims the funds of the outgoing contract back after the gateway failed to
+/// send the payment, recording the failure of `failed_gateway` unless it was
+/// already recorded
+async fn refund_keysend(
+    old_state: LightningKeysendStateMachine,
+    common: LightningKeysendCommon,
+    failed_gateway: Option<LightningGateway>,
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: DynGlobalClientContext,
+    error_reason: String,
+) -> LightningKeysendStateMachine {
+    if let Some(failed_gateway) = failed_gateway {
+        record_gateway_failure(&mut dbtx.module_tx(), &failed_gateway).await;
+    }
+    let (txid, out_points) =
+        claim_outgoing_contract_refund(&common.contract, dbtx, &global_context).await;
+
+    LightningKeysendStateMachine {
+        common: old_state.common,
+        state: LightningKeysendStates::Refund(LightningPayRefund {
+            txid,
+            out_points,
+            error_reason,
+        }),
+    }
+}
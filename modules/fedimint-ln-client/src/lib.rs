@@ -22,9 +22,12 @@ pub mod api;
 #[cfg(feature = "cli")]
 pub mod cli;
 pub mod db;
+pub mod gateway_score;
 pub mod incoming;
+pub mod keysend;
 pub mod pay;
 pub mod receive;
+pub mod withdraw;
 
 use std::collections::BTreeMap;
 use std::iter::once;
@@ -39,7 +42,9 @@ use bitcoin::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
 use bitcoin::key::KeyPair;
 use bitcoin::Network;
 use db::{
-    DbKeyPrefix, LightningGatewayKey, LightningGatewayKeyPrefix, PaymentResult, PaymentResultKey,
+    DbKeyPrefix, GatewayScoreKey, GatewayScoreKeyPrefix, IncomingContractClaimKey,
+    IncomingContractClaimKeyPrefix, LightningGatewayKey, LightningGatewayKeyPrefix,
+    PaymentFailoverKey, PaymentFailoverKeyPrefix, PaymentResult, PaymentResultKey,
 };
 use fedimint_api_client::api::DynModuleApi;
 use fedimint_client::db::{migrate_state, ClientMigrationFn};
@@ -47,13 +52,13 @@ use fedimint_client::derivable_secret::ChildId;
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationMeta, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use fedimint_client::{sm_enum_variant_translation, DynGlobalClientContext};
 use fedimint_core::config::FederationId;
-use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, OperationId};
+use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, ModuleKind, OperationId};
 use fedimint_core::db::{DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{
@@ -75,19 +80,18 @@ use fedimint_ln_common::contracts::{
     PreimageKey,
 };
 use fedimint_ln_common::{
-    ContractOutput, LightningCommonInit, LightningGateway, LightningGatewayAnnouncement,
-    LightningGatewayRegistration, LightningInput, LightningModuleTypes, LightningOutput,
-    LightningOutputV0,
+    ContractOutput, KeysendPayment, LightningCommonInit, LightningGateway,
+    LightningGatewayAnnouncement, LightningGatewayRegistration, LightningInput,
+    LightningModuleTypes, LightningOutput, LightningOutputV0,
 };
 use fedimint_logging::LOG_CLIENT_MODULE_LN;
 use futures::{Future, FutureExt, StreamExt};
+use gateway_score::{ranked_gateways, GatewayScore};
 use incoming::IncomingSmError;
 use lightning_invoice::{
     Bolt11Invoice, Currency, InvoiceBuilder, PaymentSecret, RouteHint, RouteHintHop, RoutingFees,
 };
 use pay::PayInvoicePayload;
-use rand::rngs::OsRng;
-use rand::seq::IteratorRandom as _;
 use rand::{CryptoRng, Rng, RngCore};
 use secp256k1::{All, PublicKey, Scalar, Secp256k1, Signing, ThirtyTwoByteHash, Verification};
 use serde::{Deserialize, Serialize};
@@ -99,14 +103,19 @@ use crate::db::PaymentResultPrefix;
 use crate::incoming::{
     FundingOfferState, IncomingSmCommon, IncomingSmStates, IncomingStateMachine,
 };
+use crate::keysend::{
+    LightningKeysendCommon, LightningKeysendCreatedOutgoingLnContract,
+    LightningKeysendStateMachine, LightningKeysendStates,
+};
 use crate::pay::{
     GatewayPayError, LightningPayCommon, LightningPayCreatedOutgoingLnContract,
-    LightningPayStateMachine, LightningPayStates,
+    LightningPayStateMachine, LightningPayStates, PaymentData,
 };
 use crate::receive::{
-    get_incoming_contract, LightningReceiveError, LightningReceiveStateMachine,
+    get_incoming_contract, route_hint_fee, LightningReceiveError, LightningReceiveStateMachine,
     LightningReceiveStates, LightningReceiveSubmittedOffer,
 };
+use crate::withdraw::{LnurlWithdrawCommon, LnurlWithdrawStateMachine, LnurlWithdrawStates};
 
 /// Number of blocks until outgoing lightning contracts times out and user
 /// client can get refund
@@ -162,6 +171,26 @@ impl ReceivingKey {
     }
 }
 
+/// The expected outcome of receiving a payment for an invoice of a given
+/// amount, computed before the invoice is created.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReceivePreview {
+    /// The amount of the invoice, which is what the payer has to send
+    pub invoice_amount: Amount,
+    /// The fee charged by the gateway, incoming payments are routed for free
+    pub gateway_fee: Amount,
+    /// The largest fee the payer pays on top of the invoice amount to reach
+    /// the gateway through one of its route hints, zero for internal payments
+    /// and gateways without route hints
+    pub max_route_hint_fee: Amount,
+    /// The fee charged by the federation to claim the incoming contract
+    pub federation_fee: Amount,
+    /// The amount we receive once the incoming contract has been claimed
+    pub receive_amount: Amount,
+    /// The smallest invoice amount for which we receive anything after fees
+    pub min_invoice_amount: Amount,
+}
+
 /// The high-level state of an pay operation internal to the federation,
 /// started with [`LightningClientModule::pay_bolt11_invoice`].
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -190,12 +219,29 @@ pub enum InternalPayState {
 pub enum LnPayState {
     Created,
     Canceled,
-    Funded { block_height: u32 },
-    WaitingForRefund { error_reason: String },
+    Funded {
+        block_height: u32,
+    },
+    WaitingForRefund {
+        error_reason: String,
+    },
     AwaitingChange,
-    Success { preimage: String },
-    Refunded { gateway_error: GatewayPayError },
-    UnexpectedError { error_message: String },
+    Success {
+        preimage: String,
+    },
+    Refunded {
+        gateway_error: GatewayPayError,
+    },
+    /// The gateway failed to pay the invoice, the payment is retried with
+    /// another gateway
+    Redispatched {
+        failed_gateway_id: PublicKey,
+        gateway_id: PublicKey,
+        error_reason: String,
+    },
+    UnexpectedError {
+        error_message: String,
+    },
 }
 
 /// The high-level state of a reissue operation started with
@@ -211,6 +257,19 @@ pub enum LnReceiveState {
     Claimed,
 }
 
+/// The high-level state of an LNURL-withdraw operation started with
+/// [`LightningClientModule::lnurl_withdraw`].
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LnurlWithdrawState {
+    Created,
+    InvoiceSubmitted { invoice: String },
+    Funded,
+    AwaitingFunds,
+    Claimed,
+    Canceled { reason: String },
+}
+
 fn invoice_has_internal_payment_markers(
     invoice: &Bolt11Invoice,
     markers: (secp256k1::PublicKey, u64),
@@ -251,12 +310,29 @@ pub struct LightningOperationMetaPay {
     pub gateway_id: Option<secp256k1::PublicKey>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct LightningOperationMetaKeysend {
+    pub out_point: OutPoint,
+    pub destination: secp256k1::PublicKey,
+    pub amount: Amount,
+    pub fee: Amount,
+    pub change: Vec<OutPoint>,
+    pub contract_id: ContractId,
+    pub gateway_id: secp256k1::PublicKey,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LightningOperationMeta {
     pub variant: LightningOperationMetaVariant,
     pub extra_meta: serde_json::Value,
 }
 
+impl OperationMeta for LightningOperationMeta {
+    const KIND: ModuleKind = LightningCommonInit::KIND;
+    const VERSION: u16 = 0;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum LightningOperationMetaVariant {
@@ -269,6 +345,12 @@ pub enum LightningOperationMetaVariant {
     Claim {
         out_points: Vec<OutPoint>,
     },
+    Keysend(LightningOperationMetaKeysend),
+    LnurlWithdraw {
+        out_point: OutPoint,
+        invoice: Bolt11Invoice,
+        gateway_id: secp256k1::PublicKey,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -324,6 +406,36 @@ impl ModuleInit for LightningClientInit {
                         "Lightning Gateways"
                     );
                 }
+                DbKeyPrefix::GatewayScore => {
+                    push_db_pair_items!(
+                        dbtx,
+                        GatewayScoreKeyPrefix,
+                        GatewayScoreKey,
+                        GatewayScore,
+                        ln_client_items,
+                        "Gateway Scores"
+                    );
+                }
+                DbKeyPrefix::PaymentFailover => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PaymentFailoverKeyPrefix,
+                        PaymentFailoverKey,
+                        Vec<PublicKey>,
+                        ln_client_items,
+                        "Payment Failovers"
+                    );
+                }
+                DbKeyPrefix::IncomingContractClaim => {
+                    push_db_pair_items!(
+                        dbtx,
+                        IncomingContractClaimKeyPrefix,
+                        IncomingContractClaimKey,
+                        OperationId,
+                        ln_client_items,
+                        "Incoming Contract Claims"
+                    );
+                }
             }
         }
 
@@ -842,6 +954,24 @@ impl LightningClientModule {
         gateways.into_iter().find(|g| g.gateway_id == *gateway_id)
     }
 
+    /// Selects the gateway from the gateway cache with the best track record,
+    /// see [`GatewayScore`]. Payments funded for this gateway are re-dispatched
+    /// to the next-best gateway if it fails to pay the invoice.
+    pub async fn select_best_gateway(&self) -> Option<LightningGateway> {
+        let mut dbtx = self.client_ctx.module_db().begin_transaction_nc().await;
+        ranked_gateways(&mut dbtx, &[]).await.into_iter().next()
+    }
+
+    /// Returns the scores of all gateways this client has paid through
+    pub async fn gateway_scores(&self) -> BTreeMap<PublicKey, GatewayScore> {
+        let mut dbtx = self.client_ctx.module_db().begin_transaction_nc().await;
+        dbtx.find_by_prefix(&GatewayScoreKeyPrefix)
+            .await
+            .map(|(key, score)| (key.0, score))
+            .collect::<BTreeMap<_, _>>()
+            .await
+    }
+
     /// Updates the gateway cache by fetching the latest registered gateways
     /// from the federation.
     ///
@@ -925,6 +1055,26 @@ impl LightningClientModule {
             .await
     }
 
+    /// Returns true if the invoice was created by a client of this federation,
+    /// either without a gateway or with the route hint of one of the cached
+    /// gateways. Such invoices are paid internally, without a gateway and
+    /// without paying fees.
+    pub async fn is_internal_invoice(&self, invoice: &Bolt11Invoice) -> anyhow::Result<bool> {
+        let markers = self.client_ctx.get_internal_payment_markers()?;
+        if invoice_has_internal_payment_markers(invoice, markers) {
+            return Ok(true);
+        }
+
+        let mut dbtx = self.client_ctx.module_db().begin_transaction_nc().await;
+        let gateways = dbtx
+            .find_by_prefix(&LightningGatewayKeyPrefix)
+            .await
+            .map(|(_, gw)| gw.info)
+            .collect::<Vec<_>>()
+            .await;
+        Ok(invoice_routes_back_to_federation(invoice, gateways))
+    }
+
     /// Pays a LN invoice with our available funds using the supplied `gateway`
     /// if one was provided and the invoice is not an internal one. If none is
     /// supplied only internal payments are possible.
@@ -940,7 +1090,6 @@ impl LightningClientModule {
         extra_meta: M,
     ) -> anyhow::Result<OutgoingLightningPayment> {
         let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
-        let maybe_gateway_id = maybe_gateway.as_ref().map(|g| g.gateway_id);
         let prev_payment_result = self
             .get_prev_payment_result(invoice.payment_hash(), &mut dbtx.to_ref_nc())
             .await;
@@ -966,6 +1115,16 @@ impl LightningClientModule {
         let operation_id =
             LightningClientModule::get_payment_operation_id(invoice.payment_hash(), next_index);
 
+        // Concurrent calls paying the same invoice would create the same operation
+        let _lock = self
+            .client_ctx
+            .try_lock_resource(invoice.payment_hash(), operation_id)
+            .map_err(
+                |e| PayBolt11InvoiceError::PreviousPaymentAttemptStillInProgress {
+                    operation_id: e.operation_id,
+                },
+            )?;
+
         let new_payment_result = PaymentResult {
             index: next_index,
             completed_payment: None,
@@ -979,18 +1138,12 @@ impl LightningClientModule {
         )
         .await;
 
-        let markers = self.client_ctx.get_internal_payment_markers()?;
-
-        let mut is_internal_payment = invoice_has_internal_payment_markers(&invoice, markers);
-        if !is_internal_payment {
-            let gateways = dbtx
-                .find_by_prefix(&LightningGatewayKeyPrefix)
-                .await
-                .map(|(_, gw)| gw.info)
-                .collect::<Vec<_>>()
-                .await;
-            is_internal_payment = invoice_routes_back_to_federation(&invoice, gateways);
-        }
+        // Internal payments never involve the gateway, not even for bookkeeping
+        let is_internal_payment = self.is_internal_invoice(&invoice).await?;
+        let maybe_gateway_id = maybe_gateway
+            .as_ref()
+            .filter(|_| !is_internal_payment)
+            .map(|g| g.gateway_id);
 
         let (pay_type, client_output, contract_id) = if is_internal_payment {
             let (output, contract_id) = self
@@ -1024,6 +1177,9 @@ impl LightningClientModule {
         // TODO: return fee from create_outgoing_output or even let user supply
         // it/bounds for it
         let fee = match &client_output.output {
+            // The payee's offer may ask for less than the invoice amount, but internal
+            // payments are exempt from fees either way
+            _ if is_internal_payment => Amount::ZERO,
             LightningOutputV0::Contract(contract) => {
                 let fee_msat = contract
                     .amount
@@ -1081,13 +1237,219 @@ impl LightningClientModule {
         })
     }
 
+    /// Sends a spontaneous payment of `amount` to the lightning node
+    /// `destination` via `gateway`, without requiring an invoice.
+    ///
+    /// The gateway learns the preimage the funds are locked to before sending
+    /// the payment, so it has to be trusted, see [`KeysendPayment`].
+    pub async fn pay_keysend<M: Serialize + MaybeSend + MaybeSync>(
+        &self,
+        gateway: LightningGateway,
+        destination: secp256k1::PublicKey,
+        amount: Amount,
+        tlv_records: BTreeMap<u64, Vec<u8>>,
+        extra_meta: M,
+    ) -> anyhow::Result<OutgoingLightningPayment> {
+        // Do not create the funding transaction if the gateway is not currently
+        // available
+        self.gateway_conn
+            .verify_gateway_availability(&gateway)
+            .await?;
+
+        let consensus_count = self
+            .module_api
+            .fetch_consensus_block_count()
+            .await?
+            .ok_or(format_err!("Cannot get consensus block count"))?;
+        let absolute_timelock = consensus_count + OUTGOING_LN_CONTRACT_TIMELOCK - 1;
+
+        let operation_id = OperationId::new_random();
+        let mut preimage = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut preimage);
+        let keysend = KeysendPayment {
+            destination,
+            amount,
+            preimage: Preimage(preimage),
+            tlv_records,
+        };
+        let payment_hash = keysend.payment_hash();
+
+        let gateway_fee = gateway.fees.to_amount(&amount);
+        let contract_amount = amount + gateway_fee;
+        let user_sk = KeyPair::new(&self.secp, &mut rand::rngs::OsRng);
+        let contract = OutgoingContract {
+            hash: payment_hash,
+            gateway_key: gateway.gateway_redeem_key,
+            timelock: absolute_timelock as u32,
+            user_key: user_sk.public_key(),
+            cancelled: false,
+        };
+        let contract_id = contract.contract_id();
+        let gateway_id = gateway.gateway_id;
+
+        let common = LightningKeysendCommon {
+            operation_id,
+            federation_id: self
+                .client_ctx
+                .get_config()
+                .global
+                .calculate_federation_id(),
+            contract: OutgoingContractData {
+                recovery_key: user_sk,
+                contract_account: OutgoingContractAccount {
+                    amount: contract_amount,
+                    contract: contract.clone(),
+                },
+            },
+            gateway_fee,
+            preimage_auth: self.get_preimage_authentication(&payment_hash),
+            keysend,
+        };
+
+        let output = self.client_ctx.make_client_output(ClientOutput {
+            output: LightningOutput::V0(LightningOutputV0::Contract(ContractOutput {
+                amount: contract_amount,
+                contract: Contract::Outgoing(contract),
+            })),
+            amount: contract_amount,
+            state_machines: Arc::new(move |funding_txid, _| {
+                vec![LightningClientStateMachines::Keysend(
+                    LightningKeysendStateMachine {
+                        common: common.clone(),
+                        state: LightningKeysendStates::CreatedOutgoingLnContract(
+                            LightningKeysendCreatedOutgoingLnContract {
+                                funding_txid,
+                                contract_id,
+                                gateway: gateway.clone(),
+                            },
+                        ),
+                    },
+                )]
+            }),
+        });
+
+        let tx = TransactionBuilder::new().with_output(output);
+        let extra_meta =
+            serde_json::to_value(extra_meta).context("Failed to serialize extra meta")?;
+        let operation_meta_gen = |txid, change| LightningOperationMeta {
+            variant: LightningOperationMetaVariant::Keysend(LightningOperationMetaKeysend {
+                out_point: OutPoint { txid, out_idx: 0 },
+                destination,
+                amount,
+                fee: gateway_fee,
+                change,
+                contract_id,
+                gateway_id,
+            }),
+            extra_meta: extra_meta.clone(),
+        };
+
+        self.client_ctx
+            .finalize_and_submit_transaction(
+                operation_id,
+                LightningCommonInit::KIND.as_str(),
+                operation_meta_gen,
+                tx,
+            )
+            .await?;
+
+        Ok(OutgoingLightningPayment {
+            payment_type: PayType::Lightning(operation_id),
+            contract_id,
+            fee: gateway_fee,
+        })
+    }
+
+    /// Subscribes to a stream of updates about a spontaneous payment started
+    /// with [`LightningClientModule::pay_keysend`]
+    pub async fn subscribe_ln_keysend(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<LnPayState>> {
+        async fn get_next_keysend_state(
+            stream: &mut fedimint_core::util::BoxStream<'_, LightningClientStateMachines>,
+        ) -> Option<LightningKeysendStates> {
+            match stream.next().await {
+                Some(LightningClientStateMachines::Keysend(state)) => Some(state.state),
+                Some(_) => panic!("Operation is not a keysend payment"),
+                None => None,
+            }
+        }
+
+        let operation = self.client_ctx.get_operation(operation_id).await?;
+        let LightningOperationMetaVariant::Keysend(LightningOperationMetaKeysend {
+            change, ..
+        }) = operation.meta_typed::<LightningOperationMeta>()?.variant
+        else {
+            bail!("Operation is not a keysend payment")
+        };
+
+        let client_ctx = self.client_ctx.clone();
+
+        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+            stream! {
+                let self_ref = client_ctx.self_ref();
+                let mut stream = self_ref.notifier.subscribe(operation_id).await;
+
+                loop {
+                    match get_next_keysend_state(&mut stream).await {
+                        Some(LightningKeysendStates::CreatedOutgoingLnContract(_)) => {
+                            yield LnPayState::Created;
+                        }
+                        Some(LightningKeysendStates::FundingRejected) => {
+                            yield LnPayState::Canceled;
+                            return;
+                        }
+                        Some(LightningKeysendStates::Funded(funded)) => {
+                            yield LnPayState::Funded { block_height: funded.timelock };
+                        }
+                        Some(LightningKeysendStates::Success(preimage)) => {
+                            if !change.is_empty() {
+                                yield LnPayState::AwaitingChange;
+                                if let Err(e) = client_ctx.await_primary_module_outputs(operation_id, change.clone()).await {
+                                    yield LnPayState::UnexpectedError { error_message: format!("Error occurred while waiting for the change: {e:?}") };
+                                    return;
+                                }
+                            }
+                            yield LnPayState::Success { preimage };
+                            return;
+                        }
+                        Some(LightningKeysendStates::Refund(refund)) => {
+                            yield LnPayState::WaitingForRefund {
+                                error_reason: refund.error_reason.clone(),
+                            };
+                            match client_ctx.await_primary_module_outputs(operation_id, refund.out_points).await {
+                                Ok(_) => {
+                                    let gateway_error = GatewayPayError::GatewayInternalError { error_code: Some(500), error_message: refund.error_reason };
+                                    yield LnPayState::Refunded { gateway_error };
+                                }
+                                Err(e) => {
+                                    yield LnPayState::UnexpectedError {
+                                        error_message: format!("Error occurred trying to get refund. Refund was not successful: {e:?}"),
+                                    };
+                                }
+                            }
+                            return;
+                        }
+                        // The refund is reported once the contract can be claimed back
+                        Some(LightningKeysendStates::Refundable(_)) => {}
+                        None => {
+                            error!("Unexpected end of keysend state machine");
+                            return;
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
     pub async fn get_ln_pay_details_for(
         &self,
         operation_id: OperationId,
     ) -> anyhow::Result<LightningOperationMetaPay> {
         let operation = self.client_ctx.get_operation(operation_id).await?;
         let LightningOperationMetaVariant::Pay(pay) =
-            operation.meta::<LightningOperationMeta>().variant
+            operation.meta_typed::<LightningOperationMeta>()?.variant
         else {
             anyhow::bail!("Operation is not a lightning payment")
         };
@@ -1144,15 +1506,30 @@ impl LightningClientModule {
             }
         }
 
+        /// Skips the initial states of state machines the payment was
+        /// re-dispatched to, which can be reported before or after the
+        /// re-dispatch itself, and waiting for the refund before a re-dispatch
+        async fn get_next_pay_state_after_creation(
+            stream: &mut fedimint_core::util::BoxStream<'_, LightningClientStateMachines>,
+        ) -> Option<LightningPayStates> {
+            loop {
+                match get_next_pay_state(stream).await {
+                    Some(
+                        LightningPayStates::CreatedOutgoingLnContract(_)
+                        | LightningPayStates::Redispatching(_),
+                    ) => continue,
+                    state => return state,
+                }
+            }
+        }
+
         let operation = self.client_ctx.get_operation(operation_id).await?;
-        let LightningOperationMetaVariant::Pay(LightningOperationMetaPay {
-            out_point: _,
-            invoice: _,
-            change,
-            ..
-        }) = operation.meta::<LightningOperationMeta>().variant
-        else {
-            bail!("Operation is not a lightning payment")
+        let change = match operation.meta_typed::<LightningOperationMeta>()?.variant {
+            LightningOperationMetaVariant::Pay(LightningOperationMetaPay { change, .. }) => change,
+            LightningOperationMetaVariant::Keysend(_) => {
+                return self.subscribe_ln_keysend(operation_id).await;
+            }
+            _ => bail!("Operation is not a lightning payment"),
         };
 
         let client_ctx = self.client_ctx.clone();
@@ -1181,62 +1558,78 @@ impl LightningClientModule {
                     }
                 }
 
-                let state = get_next_pay_state(&mut stream).await;
-                match state {
-                    Some(LightningPayStates::Funded(funded)) => {
-                        yield LnPayState::Funded { block_height: funded.timelock }
-                    }
-                    Some(state) => {
-                        yield LnPayState::UnexpectedError { error_message: format!("Found unexpected state during lightning payment: {state:?}") };
-                        return;
-                    }
-                    _ => {
-                        error!("Unexpected end of lightning pay state machine");
-                        return;
+                loop {
+                    let state = get_next_pay_state_after_creation(&mut stream).await;
+                    match state {
+                        Some(LightningPayStates::Funded(funded)) => {
+                            yield LnPayState::Funded { block_height: funded.timelock }
+                        }
+                        Some(LightningPayStates::FundingRejected) => {
+                            yield LnPayState::Canceled;
+                            return;
+                        }
+                        Some(state) => {
+                            yield LnPayState::UnexpectedError { error_message: format!("Found unexpected state during lightning payment: {state:?}") };
+                            return;
+                        }
+                        _ => {
+                            error!("Unexpected end of lightning pay state machine");
+                            return;
+                        }
                     }
-                }
 
-                let state = get_next_pay_state(&mut stream).await;
-                match state {
-                    Some(LightningPayStates::Success(preimage)) => {
-                        if change.is_empty() {
-                            yield LnPayState::Success { preimage };
-                        } else {
-                            yield LnPayState::AwaitingChange;
-                            match client_ctx.await_primary_module_outputs(operation_id, change.clone()).await {
+                    let state = get_next_pay_state_after_creation(&mut stream).await;
+                    match state {
+                        Some(LightningPayStates::Success(preimage)) => {
+                            if change.is_empty() {
+                                yield LnPayState::Success { preimage };
+                            } else {
+                                yield LnPayState::AwaitingChange;
+                                match client_ctx.await_primary_module_outputs(operation_id, change.clone()).await {
+                                    Ok(_) => {
+                                        yield LnPayState::Success { preimage };
+                                    }
+                                    Err(e) => {
+                                        yield LnPayState::UnexpectedError { error_message: format!("Error occurred while waiting for the change: {e:?}") };
+                                    }
+                                }
+                            }
+                        }
+                        Some(LightningPayStates::Refund(refund)) => {
+                            yield LnPayState::WaitingForRefund {
+                                error_reason: refund.error_reason.clone(),
+                            };
+
+                            match client_ctx.await_primary_module_outputs(operation_id, refund.out_points).await {
                                 Ok(_) => {
-                                    yield LnPayState::Success { preimage };
+                                    let gateway_error = GatewayPayError::GatewayInternalError { error_code: Some(500), error_message: refund.error_reason };
+                                    yield LnPayState::Refunded { gateway_error };
                                 }
                                 Err(e) => {
-                                    yield LnPayState::UnexpectedError { error_message: format!("Error occurred while waiting for the change: {e:?}") };
+                                    yield LnPayState::UnexpectedError {
+                                        error_message: format!("Error occurred trying to get refund. Refund was not successful: {e:?}"),
+                                    };
                                 }
                             }
                         }
-                    }
-                    Some(LightningPayStates::Refund(refund)) => {
-                        yield LnPayState::WaitingForRefund {
-                            error_reason: refund.error_reason.clone(),
-                        };
-
-                        match client_ctx.await_primary_module_outputs(operation_id, refund.out_points).await {
-                            Ok(_) => {
-                                let gateway_error = GatewayPayError::GatewayInternalError { error_code: Some(500), error_message: refund.error_reason };
-                                yield LnPayState::Refunded { gateway_error };
-                            }
-                            Err(e) => {
-                                yield LnPayState::UnexpectedError {
-                                    error_message: format!("Error occurred trying to get refund. Refund was not successful: {e:?}"),
-                                };
-                            }
+                        Some(LightningPayStates::Redispatched(redispatched)) => {
+                            yield LnPayState::Redispatched {
+                                failed_gateway_id: redispatched.failed_gateway_id,
+                                gateway_id: redispatched.gateway_id,
+                                error_reason: redispatched.error_reason,
+                            };
+                            continue;
+                        }
+                        Some(state) => {
+                            yield LnPayState::UnexpectedError { error_message: format!("Found unexpected state during lightning payment: {state:?}") };
+                        }
+                        None => {
+                            error!("Unexpected end of lightning pay state machine");
+                            yield LnPayState::UnexpectedError { error_message: "Unexpected end of lightning pay state machine".to_string() };
                         }
                     }
-                    Some(state) => {
-                        yield LnPayState::UnexpectedError { error_message: format!("Found unexpected state during lightning payment: {state:?}") };
-                    }
-                    None => {
-                        error!("Unexpected end of lightning pay state machine");
-                        yield LnPayState::UnexpectedError { error_message: "Unexpected end of lightning pay state machine".to_string() };
-                    }
+
+                    break;
                 }
             }
         }))
@@ -1280,22 +1673,51 @@ impl LightningClientModule {
 
     /// Claim the funded, unspent incoming contract by submitting a transaction
     /// to the federation and awaiting the primary module's outputs
+    ///
+    /// If the contract is already being claimed or was claimed by this client,
+    /// the operation of that claim is returned instead.
     pub async fn claim_funded_incoming_contract<M: Serialize + Send + Sync>(
         &self,
         key_pair: KeyPair,
         contract_id: ContractId,
         extra_meta: M,
     ) -> anyhow::Result<OperationId> {
+        let operation_id = OperationId::new_random();
+
+        // Claims of the same contract from different code paths, e.g. a rescan while
+        // the contract is being claimed, have to wait for each other
+        let _lock = self
+            .client_ctx
+            .lock_resource(&contract_id, operation_id)
+            .await;
+
+        let prev_claim = {
+            let mut dbtx = self.client_ctx.module_db().begin_transaction_nc().await;
+            dbtx.get_value(&IncomingContractClaimKey(contract_id)).await
+        };
+        if let Some(prev_claim) = prev_claim {
+            if self.client_ctx.has_active_states(prev_claim).await {
+                return Ok(prev_claim);
+            }
+        }
+
         let incoming_contract_account = get_incoming_contract(self.module_api.clone(), contract_id)
             .await?
             .ok_or(anyhow!("No contract account found"))
             .with_context(|| format!("No contract found for {contract_id:?}"))?;
 
+        // The previous claim was accepted, only a rejected claim may be retried
+        if let Some(prev_claim) = prev_claim {
+            if incoming_contract_account.amount == Amount::ZERO {
+                return Ok(prev_claim);
+            }
+        }
+
         let input = incoming_contract_account.claim();
         let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
             input,
             amount: incoming_contract_account.amount,
-            keys: vec![key_pair],
+            keys: vec![key_pair.into()],
             state_machines: Arc::new(|_, _| vec![]),
         };
 
@@ -1306,7 +1728,6 @@ impl LightningClientModule {
             variant: LightningOperationMetaVariant::Claim { out_points },
             extra_meta: extra_meta.clone(),
         };
-        let operation_id = OperationId::new_random();
         self.client_ctx
             .finalize_and_submit_transaction(
                 operation_id,
@@ -1315,9 +1736,43 @@ impl LightningClientModule {
                 tx,
             )
             .await?;
+
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        dbtx.insert_entry(&IncomingContractClaimKey(contract_id), &operation_id)
+            .await;
+        dbtx.commit_tx().await;
+
         Ok(operation_id)
     }
 
+    /// Computes the fees and the amount we would receive for an invoice of
+    /// `invoice_amount` created with the given gateway, as passed to
+    /// [`Self::create_bolt11_invoice`], without creating it. Gateways do not
+    /// charge for incoming payments, but payers routing through the route
+    /// hints of the gateway pay the fees of its channel peers.
+    pub fn preview_receive(
+        &self,
+        invoice_amount: Amount,
+        gateway: Option<&LightningGateway>,
+    ) -> ReceivePreview {
+        let federation_fee = self.cfg.fee_consensus.contract_input;
+        let max_route_hint_fee = gateway
+            .into_iter()
+            .flat_map(|gateway| &gateway.route_hints)
+            .map(|route_hint| route_hint_fee(route_hint, invoice_amount))
+            .max()
+            .unwrap_or(Amount::ZERO);
+
+        ReceivePreview {
+            invoice_amount,
+            gateway_fee: Amount::ZERO,
+            max_route_hint_fee,
+            federation_fee,
+            receive_amount: invoice_amount.saturating_sub(federation_fee),
+            min_invoice_amount: federation_fee + Amount::from_msats(1),
+        }
+    }
+
     /// Receive over LN with a new invoice
     pub async fn create_bolt11_invoice<M: Serialize + Send + Sync>(
         &self,
@@ -1468,7 +1923,7 @@ impl LightningClientModule {
     ) -> anyhow::Result<UpdateStreamOrOutcome<LnReceiveState>> {
         let operation = self.client_ctx.get_operation(operation_id).await?;
         let LightningOperationMetaVariant::Claim { out_points } =
-            operation.meta::<LightningOperationMeta>().variant
+            operation.meta_typed::<LightningOperationMeta>()?.variant
         else {
             bail!("Operation is not a lightning claim")
         };
@@ -1495,7 +1950,7 @@ impl LightningClientModule {
         let operation = self.client_ctx.get_operation(operation_id).await?;
         let LightningOperationMetaVariant::Receive {
             out_point, invoice, ..
-        } = operation.meta::<LightningOperationMeta>().variant
+        } = operation.meta_typed::<LightningOperationMeta>()?.variant
         else {
             bail!("Operation is not a lightning payment")
         };
@@ -1550,6 +2005,168 @@ impl LightningClientModule {
         }))
     }
 
+    /// Claims an LNURL-withdraw into ecash: fetches the withdrawal parameters
+    /// behind `lnurl`, creates an invoice for `amount` (or the maximum the
+    /// service allows) routed via `gateway`, and lets a state machine submit it
+    /// to the service once the federation accepted the offer.
+    ///
+    /// Use [`LightningClientModule::subscribe_lnurl_withdraw`] to follow the
+    /// withdrawal until the funds are claimed.
+    pub async fn lnurl_withdraw<M: Serialize + Send + Sync>(
+        &self,
+        lnurl: &str,
+        amount: Option<Amount>,
+        gateway: LightningGateway,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId> {
+        let lnurl = lnurl::lnurl::LnUrl::from_str(lnurl.trim())?;
+        let async_client = lnurl::AsyncClient::from_client(reqwest::Client::new());
+        let withdrawal = match async_client.make_request(&lnurl.url).await? {
+            lnurl::LnUrlResponse::LnUrlWithdrawResponse(withdrawal) => withdrawal,
+            other => bail!("Unexpected response from lnurl: {other:?}"),
+        };
+
+        let max_amount = Amount::from_msats(withdrawal.max_withdrawable);
+        let min_amount = Amount::from_msats(withdrawal.min_withdrawable.unwrap_or(1));
+        let amount = amount.unwrap_or(max_amount);
+        ensure!(
+            min_amount <= amount && amount <= max_amount,
+            "Amount {amount} is outside of the withdrawable range from {min_amount} to {max_amount}"
+        );
+
+        let gateway_id = gateway.gateway_id;
+        let description = lightning_invoice::Description::new(withdrawal.default_description)?;
+        let receiving_key =
+            ReceivingKey::Personal(KeyPair::new(&self.secp, &mut rand::rngs::OsRng));
+        let (operation_id, invoice, output, _) = self
+            .create_lightning_receive_output(
+                amount,
+                lightning_invoice::Bolt11InvoiceDescription::Direct(&description),
+                receiving_key,
+                rand::rngs::OsRng,
+                None,
+                gateway.node_pub_key,
+                gateway.mint_channel_id,
+                gateway.route_hints,
+                self.cfg.network,
+            )
+            .await?;
+
+        debug!(target: LOG_CLIENT_MODULE_LN, %gateway_id, %amount, "Created invoice for LNURL-withdraw");
+
+        // The receive state machine claims the payment, the withdraw state
+        // machine makes sure the service actually sends it
+        let withdraw_common = LnurlWithdrawCommon {
+            operation_id,
+            invoice: invoice.clone(),
+            callback: withdrawal.callback,
+            k1: withdrawal.k1,
+        };
+        let receive_state_machines = output.state_machines;
+        let output = ClientOutput {
+            state_machines: Arc::new(move |txid, out_idx| {
+                let mut state_machines = receive_state_machines(txid, out_idx);
+                state_machines.push(LightningClientStateMachines::LnurlWithdraw(
+                    LnurlWithdrawStateMachine {
+                        common: withdraw_common.clone(),
+                        state: LnurlWithdrawStates::AwaitingOffer(txid),
+                    },
+                ));
+                state_machines
+            }),
+            ..output
+        };
+
+        let tx = TransactionBuilder::new().with_output(self.client_ctx.make_client_output(output));
+        let extra_meta =
+            serde_json::to_value(extra_meta).context("Failed to serialize extra meta")?;
+        let operation_meta_gen = |txid, _| LightningOperationMeta {
+            variant: LightningOperationMetaVariant::LnurlWithdraw {
+                out_point: OutPoint { txid, out_idx: 0 },
+                invoice: invoice.clone(),
+                gateway_id,
+            },
+            extra_meta: extra_meta.clone(),
+        };
+        self.client_ctx
+            .finalize_and_submit_transaction(
+                operation_id,
+                LightningCommonInit::KIND.as_str(),
+                operation_meta_gen,
+                tx,
+            )
+            .await?;
+
+        Ok(operation_id)
+    }
+
+    /// Subscribes to a stream of updates about an LNURL-withdraw started with
+    /// [`LightningClientModule::lnurl_withdraw`]
+    pub async fn subscribe_lnurl_withdraw(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<LnurlWithdrawState>> {
+        let operation = self.client_ctx.get_operation(operation_id).await?;
+        let LightningOperationMetaVariant::LnurlWithdraw { invoice, .. } =
+            operation.meta_typed::<LightningOperationMeta>()?.variant
+        else {
+            bail!("Operation is not an LNURL-withdraw")
+        };
+
+        let client_ctx = self.client_ctx.clone();
+
+        Ok(operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
+            stream! {
+                let self_ref = client_ctx.self_ref();
+                let mut stream = self_ref.notifier.subscribe(operation_id).await;
+
+                yield LnurlWithdrawState::Created;
+
+                loop {
+                    match stream.next().await {
+                        Some(LightningClientStateMachines::LnurlWithdraw(state)) => match state.state {
+                            LnurlWithdrawStates::InvoiceSubmitted => break,
+                            LnurlWithdrawStates::Failed(reason) => {
+                                yield LnurlWithdrawState::Canceled { reason };
+                                return;
+                            }
+                            LnurlWithdrawStates::AwaitingOffer(_) | LnurlWithdrawStates::SubmittingInvoice => {}
+                        },
+                        Some(_) => {}
+                        None => {
+                            error!("Unexpected end of LNURL-withdraw state machine");
+                            return;
+                        }
+                    }
+                }
+                yield LnurlWithdrawState::InvoiceSubmitted { invoice: invoice.to_string() };
+
+                match self_ref.await_receive_success(operation_id).await {
+                    Ok(is_external) if is_external => {
+                        yield LnurlWithdrawState::Claimed;
+                    }
+                    Ok(_) => {
+                        yield LnurlWithdrawState::Funded;
+
+                        if let Ok(out_points) = self_ref.await_claim_acceptance(operation_id).await {
+                            yield LnurlWithdrawState::AwaitingFunds;
+
+                            if client_ctx.await_primary_module_outputs(operation_id, out_points).await.is_ok() {
+                                yield LnurlWithdrawState::Claimed;
+                                return;
+                            }
+                        }
+
+                        yield LnurlWithdrawState::Canceled { reason: LightningReceiveError::ClaimRejected.to_string() };
+                    }
+                    Err(e) => {
+                        yield LnurlWithdrawState::Canceled { reason: e.to_string() };
+                    }
+                }
+            }
+        }))
+    }
+
     /// Returns a gateway to be used for a lightning operation. If
     /// `force_internal` is true and no `gateway_id` is specified, no
     /// gateway will be selected.
@@ -1570,13 +2187,11 @@ impl LightningClientModule {
                 }
             }
             None if !force_internal => {
-                // Refresh the gateway cache to find a random gateway to select from.
+                // Refresh the gateway cache to find the best gateway to select from.
                 self.update_gateway_cache().await?;
-                let gateways = self.list_gateways().await;
-                let gw = gateways.into_iter().choose(&mut OsRng).map(|gw| gw.info);
-                if let Some(gw) = gw {
+                if let Some(gw) = self.select_best_gateway().await {
                     let gw_id = gw.gateway_id;
-                    info!(%gw_id, "Using random gateway");
+                    info!(%gw_id, "Using best scored gateway");
                     Ok(Some(gw))
                 } else {
                     Err(anyhow!(
@@ -1666,6 +2281,7 @@ impl LightningClientModule {
                         LnPayState::Created
                         | LnPayState::AwaitingChange
                         | LnPayState::WaitingForRefund { .. }
+                        | LnPayState::Redispatched { .. }
                         | LnPayState::Funded { block_height: _ } => {}
                         LnPayState::UnexpectedError { error_message } => {
                             bail!("UnexpectedError: {error_message}")
@@ -1696,6 +2312,8 @@ pub enum LightningClientStateMachines {
     InternalPay(IncomingStateMachine),
     LightningPay(LightningPayStateMachine),
     Receive(LightningReceiveStateMachine),
+    Keysend(LightningKeysendStateMachine),
+    LnurlWithdraw(LnurlWithdrawStateMachine),
 }
 
 impl IntoDynInstance for LightningClientStateMachines {
@@ -1733,6 +2351,18 @@ impl State for LightningClientStateMachines {
                     LightningClientStateMachines::Receive
                 )
             }
+            LightningClientStateMachines::Keysend(keysend_state) => {
+                sm_enum_variant_translation!(
+                    keysend_state.transitions(context, global_context),
+                    LightningClientStateMachines::Keysend
+                )
+            }
+            LightningClientStateMachines::LnurlWithdraw(withdraw_state) => {
+                sm_enum_variant_translation!(
+                    withdraw_state.transitions(context, global_context),
+                    LightningClientStateMachines::LnurlWithdraw
+                )
+            }
         }
     }
 
@@ -1745,6 +2375,10 @@ impl State for LightningClientStateMachines {
                 lightning_pay_state.operation_id()
             }
             LightningClientStateMachines::Receive(receive_state) => receive_state.operation_id(),
+            LightningClientStateMachines::Keysend(keysend_state) => keysend_state.operation_id(),
+            LightningClientStateMachines::LnurlWithdraw(withdraw_state) => {
+                withdraw_state.operation_id()
+            }
         }
     }
 }
@@ -1971,12 +2605,16 @@ impl GatewayConnection for RealGatewayConnection {
         gateway: LightningGateway,
         payload: PayInvoicePayload,
     ) -> Result<String, GatewayPayError> {
+        let endpoint = match payload.payment_data {
+            PaymentData::Keysend(_) => "pay_keysend",
+            PaymentData::Invoice(_) | PaymentData::PrunedInvoice(_) => "pay_invoice",
+        };
         let response = reqwest::Client::new()
             .post(
                 gateway
                     .api
-                    .join("pay_invoice")
-                    .expect("'pay_invoice' contains no invalid characters for a URL")
+                    .join(endpoint)
+                    .expect("endpoint contains no invalid characters for a URL")
                     .as_str(),
             )
             .json(&payload)
@@ -1,9 +1,11 @@
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use anyhow::Context as _;
 use bitcoin::hashes::sha256;
+use bitcoin::key::KeyPair;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
-use fedimint_client::transaction::ClientInput;
+use fedimint_client::transaction::{ClientInput, ClientOutput};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{Decoder, OperationId};
@@ -11,10 +13,16 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
 use fedimint_core::time::duration_since_epoch;
 use fedimint_core::{Amount, OutPoint, TransactionId};
-use fedimint_ln_common::contracts::outgoing::OutgoingContractData;
-use fedimint_ln_common::contracts::{ContractId, IdentifiableContract};
+use fedimint_ln_common::config::FeeToAmount;
+use fedimint_ln_common::contracts::outgoing::{
+    OutgoingContract, OutgoingContractAccount, OutgoingContractData,
+};
+use fedimint_ln_common::contracts::{Contract, ContractId, IdentifiableContract};
 use fedimint_ln_common::route_hints::RouteHint;
-use fedimint_ln_common::{LightningGateway, LightningInput, LightningOutputOutcome, PrunedInvoice};
+use fedimint_ln_common::{
+    ContractOutput, KeysendPayment, LightningGateway, LightningInput, LightningOutput,
+    LightningOutputOutcome, LightningOutputV0, PrunedInvoice,
+};
 use lightning_invoice::Bolt11Invoice;
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
@@ -22,10 +30,19 @@ use thiserror::Error;
 use tracing::{debug, error, warn};
 
 use crate::api::LnFederationApi;
-use crate::{set_payment_result, LightningClientContext, LightningClientStateMachines, PayType};
+use crate::db::PaymentFailoverKey;
+use crate::gateway_score::{ranked_gateways, record_gateway_failure, record_gateway_success};
+use crate::{
+    set_payment_result, LightningClientContext, LightningClientStateMachines, PayType,
+    OUTGOING_LN_CONTRACT_TIMELOCK,
+};
 
 const RETRY_DELAY: Duration = Duration::from_secs(1);
 
+/// Number of times a payment is re-dispatched to another gateway after the
+/// gateway it was funded for failed to pay the invoice
+const MAX_GATEWAY_FAILOVERS: usize = 2;
+
 #[cfg_attr(doc, aquamarine::aquamarine)]
 /// State machine that requests the lightning gateway to pay an invoice on
 /// behalf of a federation client.
@@ -38,6 +55,12 @@ const RETRY_DELAY: Duration = Duration::from_secs(1);
 ///  CreatedOutgoingLnContract -- await transaction acceptance --> Funded
 ///  Funded -- await gateway payment success  --> Success
 ///  Funded -- await gateway payment failed --> Refundable
+///  Funded -- gateway cancelled contract --> Redispatching
+///  Funded -- gateway cancelled contract, no other gateway --> Refund
+///  Redispatching -- refund accepted, new contract funded --> Redispatched
+///  Redispatching -- refund accepted, new contract not funded --> Refund
+///  Redispatching -- refund rejected --> Failure
+///  Redispatched -- new contract with next-best gateway --> CreatedOutgoingLnContract
 ///  Refundable -- gateway issued refunded --> Refund
 ///  Refundable -- transaction timeout --> Refund
 ///  Refund -- await transaction acceptance --> Refunded
@@ -62,6 +85,8 @@ pub enum LightningPayStates {
     )]
     Refunded(Vec<OutPoint>),
     Failure(String),
+    Redispatched(LightningPayRedispatched),
+    Redispatching(LightningPayRedispatching),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
@@ -98,11 +123,15 @@ impl State for LightningPayStateMachine {
             LightningPayStates::Refundable(refundable) => {
                 refundable.transitions(self.common.clone(), global_context.clone())
             }
+            LightningPayStates::Redispatching(redispatching) => {
+                redispatching.transitions(self.common.clone(), global_context.clone())
+            }
             LightningPayStates::Success(_)
             | LightningPayStates::FundingRejected
             | LightningPayStates::Refund(_)
             | LightningPayStates::Refunded(_)
-            | LightningPayStates::Failure(_) => {
+            | LightningPayStates::Failure(_)
+            | LightningPayStates::Redispatched(_) => {
                 vec![]
             }
         }
@@ -147,7 +176,7 @@ impl LightningPayCreatedOutgoingLnContract {
         )]
     }
 
-    async fn await_outgoing_contract_funded(
+    pub(crate) async fn await_outgoing_contract_funded(
         module_decoder: Decoder,
         global_context: DynGlobalClientContext,
         txid: TransactionId,
@@ -276,16 +305,20 @@ impl LightningPayFunded {
         global_context: DynGlobalClientContext,
     ) -> Vec<StateTransition<LightningPayStateMachine>> {
         let gateway = self.gateway.clone();
+        let success_gateway = self.gateway.clone();
+        let cancelled_gateway = self.gateway.clone();
+        let timeout_gateway = self.gateway.clone();
         let payload = self.payload.clone();
         let contract_id = self.payload.contract_id;
         let timelock = self.timelock;
+        let funding_time = self.funding_time;
         let payment_hash = *common.invoice.payment_hash();
         let success_common = common.clone();
         let timeout_common = common.clone();
         let timeout_global_context = global_context.clone();
         vec![
             StateTransition::new(
-                Self::gateway_pay_invoice(gateway, payload, context, self.funding_time),
+                Self::gateway_pay_invoice(gateway, payload, context, funding_time),
                 move |dbtx, result, old_state| {
                     Box::pin(Self::transition_outgoing_contract_execution(
                         result,
@@ -294,15 +327,18 @@ impl LightningPayFunded {
                         dbtx,
                         payment_hash,
                         success_common.clone(),
+                        success_gateway.clone(),
+                        funding_time,
                     ))
                 },
             ),
             StateTransition::new(
                 await_contract_cancelled(contract_id, global_context.clone()),
                 move |dbtx, (), old_state| {
-                    Box::pin(try_refund_outgoing_contract(
+                    Box::pin(try_redispatch_outgoing_contract(
                         old_state,
                         common.clone(),
+                        cancelled_gateway.clone(),
                         dbtx,
                         global_context.clone(),
                         format!("Gateway cancelled contract: {contract_id}"),
@@ -312,19 +348,26 @@ impl LightningPayFunded {
             StateTransition::new(
                 await_contract_timeout(timeout_global_context.clone(), timelock),
                 move |dbtx, (), old_state| {
-                    Box::pin(try_refund_outgoing_contract(
-                        old_state,
-                        timeout_common.clone(),
-                        dbtx,
-                        timeout_global_context.clone(),
-                        format!("Outgoing contract timed out, BlockHeight: {timelock}"),
-                    ))
+                    let timeout_gateway = timeout_gateway.clone();
+                    let timeout_common = timeout_common.clone();
+                    let timeout_global_context = timeout_global_context.clone();
+                    Box::pin(async move {
+                        record_gateway_failure(&mut dbtx.module_tx(), &timeout_gateway).await;
+                        try_refund_outgoing_contract(
+                            old_state,
+                            timeout_common,
+                            dbtx,
+                            timeout_global_context,
+                            format!("Outgoing contract timed out, BlockHeight: {timelock}"),
+                        )
+                        .await
+                    })
                 },
             ),
         ]
     }
 
-    async fn gateway_pay_invoice(
+    pub(crate) async fn gateway_pay_invoice(
         gateway: LightningGateway,
         payload: PayInvoicePayload,
         context: LightningClientContext,
@@ -392,6 +435,7 @@ impl LightningPayFunded {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn transition_outgoing_contract_execution(
         result: Result<String, GatewayPayError>,
         old_state: LightningPayStateMachine,
@@ -399,9 +443,15 @@ impl LightningPayFunded {
         dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
         payment_hash: sha256::Hash,
         common: LightningPayCommon,
+        gateway: LightningGateway,
+        funding_time: SystemTime,
     ) -> LightningPayStateMachine {
         match result {
             Ok(preimage) => {
+                let latency = fedimint_core::time::now()
+                    .duration_since(funding_time)
+                    .unwrap_or_default();
+                record_gateway_success(&mut dbtx.module_tx(), &gateway, latency).await;
                 set_payment_result(
                     &mut dbtx.module_tx(),
                     payment_hash,
@@ -472,7 +522,10 @@ impl LightningPayRefundable {
 }
 
 /// Waits for a contract with `contract_id` to be cancelled by the gateway.
-async fn await_contract_cancelled(contract_id: ContractId, global_context: DynGlobalClientContext) {
+pub(crate) async fn await_contract_cancelled(
+    contract_id: ContractId,
+    global_context: DynGlobalClientContext,
+) {
     loop {
         // If we fail to get the contract from the federation, we need to keep retrying
         // until we successfully do.
@@ -493,7 +546,7 @@ async fn await_contract_cancelled(contract_id: ContractId, global_context: DynGl
 
 /// Waits until a specific block height at which the contract will be able to be
 /// reclaimed.
-async fn await_contract_timeout(global_context: DynGlobalClientContext, timelock: u32) {
+pub(crate) async fn await_contract_timeout(global_context: DynGlobalClientContext, timelock: u32) {
     loop {
         match global_context
             .module_api()
@@ -520,7 +573,160 @@ async fn try_refund_outgoing_contract(
     global_context: DynGlobalClientContext,
     error_reason: String,
 ) -> LightningPayStateMachine {
-    let contract_data = common.contract;
+    let (txid, out_points) =
+        claim_outgoing_contract_refund(&common.contract, dbtx, &global_context).await;
+
+    LightningPayStateMachine {
+        common: old_state.common,
+        state: LightningPayStates::Refund(LightningPayRefund {
+            txid,
+            out_points,
+            error_reason,
+        }),
+    }
+}
+
+/// Claims the refund of a cancelled outgoing contract and re-dispatches the
+/// payment to the next-best gateway of the gateway cache once the refund was
+/// accepted, see [`LightningPayRedispatching`]
+///
+/// If no other gateway is available or the payment was already re-dispatched
+/// [`MAX_GATEWAY_FAILOVERS`] times, the payment is refunded instead.
+async fn try_redispatch_outgoing_contract(
+    old_state: LightningPayStateMachine,
+    common: LightningPayCommon,
+    failed_gateway: LightningGateway,
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: DynGlobalClientContext,
+    error_reason: String,
+) -> LightningPayStateMachine {
+    record_gateway_failure(&mut dbtx.module_tx(), &failed_gateway).await;
+
+    let failover_key = PaymentFailoverKey(common.operation_id);
+    let mut failed_gateways = dbtx
+        .module_tx()
+        .get_value(&failover_key)
+        .await
+        .unwrap_or_default();
+    failed_gateways.push(failed_gateway.gateway_id);
+    dbtx.module_tx()
+        .insert_entry(&failover_key, &failed_gateways)
+        .await;
+
+    let next_gateway = if failed_gateways.len() > MAX_GATEWAY_FAILOVERS {
+        None
+    } else {
+        ranked_gateways(&mut dbtx.module_tx(), &failed_gateways)
+            .await
+            .into_iter()
+            .next()
+    };
+
+    let (txid, out_points) =
+        claim_outgoing_contract_refund(&common.contract, dbtx, &global_context).await;
+
+    let Some(gateway) = next_gateway else {
+        return LightningPayStateMachine {
+            common: old_state.common,
+            state: LightningPayStates::Refund(LightningPayRefund {
+                txid,
+                out_points,
+                error_reason,
+            }),
+        };
+    };
+
+    LightningPayStateMachine {
+        common: old_state.common,
+        state: LightningPayStates::Redispatching(LightningPayRedispatching {
+            refund_txid: txid,
+            refund_out_points: out_points,
+            failed_gateway_id: failed_gateway.gateway_id,
+            gateway,
+            error_reason,
+        }),
+    }
+}
+
+/// Funds a new outgoing contract for the invoice of `common` that can be
+/// claimed by `gateway`. The state machine paying through the new contract
+/// belongs to the same operation.
+///
+/// The contract gets a fresh timelock relative to `consensus_block_count`,
+/// the timelock of the cancelled contract may already be close to expiry.
+async fn fund_redispatched_contract(
+    common: &LightningPayCommon,
+    gateway: LightningGateway,
+    consensus_block_count: u64,
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: &DynGlobalClientContext,
+) -> anyhow::Result<()> {
+    let invoice_amount = Amount::from_msats(
+        common
+            .invoice
+            .amount_milli_satoshis()
+            .context("MissingInvoiceAmount")?,
+    );
+    let gateway_fee = gateway.fees.to_amount(&invoice_amount);
+    let contract_amount = invoice_amount + gateway_fee;
+
+    let timelock = u32::try_from(consensus_block_count + OUTGOING_LN_CONTRACT_TIMELOCK - 1)?;
+    let user_sk = KeyPair::new(secp256k1::SECP256K1, &mut rand::rngs::OsRng);
+    let contract = OutgoingContract {
+        hash: common.contract.contract_account.contract.hash,
+        gateway_key: gateway.gateway_redeem_key,
+        timelock,
+        user_key: user_sk.public_key(),
+        cancelled: false,
+    };
+    let contract_id = contract.contract_id();
+
+    let new_common = LightningPayCommon {
+        contract: OutgoingContractData {
+            recovery_key: user_sk,
+            contract_account: OutgoingContractAccount {
+                amount: contract_amount,
+                contract: contract.clone(),
+            },
+        },
+        gateway_fee,
+        ..common.clone()
+    };
+
+    let output = ClientOutput::<LightningOutput, LightningClientStateMachines> {
+        output: LightningOutput::V0(LightningOutputV0::Contract(ContractOutput {
+            amount: contract_amount,
+            contract: Contract::Outgoing(contract),
+        })),
+        amount: contract_amount,
+        state_machines: Arc::new(move |funding_txid, _| {
+            vec![LightningClientStateMachines::LightningPay(
+                LightningPayStateMachine {
+                    common: new_common.clone(),
+                    state: LightningPayStates::CreatedOutgoingLnContract(
+                        LightningPayCreatedOutgoingLnContract {
+                            funding_txid,
+                            contract_id,
+                            gateway: gateway.clone(),
+                        },
+                    ),
+                },
+            )]
+        }),
+    };
+
+    global_context.fund_output(dbtx, output).await?;
+
+    Ok(())
+}
+
+/// Claims the funds locked in the outgoing contract back into the client's
+/// wallet
+pub(crate) async fn claim_outgoing_contract_refund(
+    contract_data: &OutgoingContractData,
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: &DynGlobalClientContext,
+) -> (TransactionId, Vec<OutPoint>) {
     let (refund_key, refund_input) = (
         contract_data.recovery_key,
         contract_data.contract_account.refund(),
@@ -529,22 +735,13 @@ async fn try_refund_outgoing_contract(
     let refund_client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
         input: refund_input,
         amount: contract_data.contract_account.amount,
-        keys: vec![refund_key],
+        keys: vec![refund_key.into()],
         // The input of the refund tx is managed by this state machine, so no new state machines
         // need to be created
         state_machines: Arc::new(|_, _| vec![]),
     };
 
-    let (txid, out_points) = global_context.claim_input(dbtx, refund_client_input).await;
-
-    LightningPayStateMachine {
-        common: old_state.common,
-        state: LightningPayStates::Refund(LightningPayRefund {
-            txid,
-            out_points,
-            error_reason,
-        }),
-    }
+    global_context.claim_input(dbtx, refund_client_input).await
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
@@ -554,6 +751,130 @@ pub struct LightningPayRefund {
     pub error_reason: String,
 }
 
+/// The gateway cancelled the contract, its funds were refunded and the payment
+/// was handed over to a new state machine paying through the next-best gateway
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningPayRedispatched {
+    pub refund_txid: TransactionId,
+    pub refund_out_points: Vec<OutPoint>,
+    pub failed_gateway_id: secp256k1::PublicKey,
+    pub gateway_id: secp256k1::PublicKey,
+    pub error_reason: String,
+}
+
+/// The gateway cancelled the contract and its refund was submitted. The
+/// payment is re-dispatched to `gateway` only once the refund was accepted, so
+/// the funds of the cancelled contract are never locked in two contracts.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LightningPayRedispatching {
+    pub refund_txid: TransactionId,
+    pub refund_out_points: Vec<OutPoint>,
+    pub failed_gateway_id: secp256k1::PublicKey,
+    pub gateway: LightningGateway,
+    pub error_reason: String,
+}
+
+impl LightningPayRedispatching {
+    fn transitions(
+        &self,
+        common: LightningPayCommon,
+        global_context: DynGlobalClientContext,
+    ) -> Vec<StateTransition<LightningPayStateMachine>> {
+        let redispatching = self.clone();
+        vec![StateTransition::new(
+            Self::await_refund_accepted(self.refund_txid, global_context.clone()),
+            move |dbtx, result, old_state| {
+                Box::pin(Self::transition_refund_accepted(
+                    result,
+                    old_state,
+                    common.clone(),
+                    redispatching.clone(),
+                    dbtx,
+                    global_context.clone(),
+                ))
+            },
+        )]
+    }
+
+    /// Waits for the refund of the cancelled contract to be accepted and
+    /// returns the consensus block count the new contract's timelock is based
+    /// on
+    async fn await_refund_accepted(
+        refund_txid: TransactionId,
+        global_context: DynGlobalClientContext,
+    ) -> Result<u64, String> {
+        global_context.await_tx_accepted(refund_txid).await?;
+
+        loop {
+            match global_context
+                .module_api()
+                .fetch_consensus_block_count()
+                .await
+            {
+                Ok(Some(consensus_block_count)) => return Ok(consensus_block_count),
+                Ok(None) => error!("Consensus block count is not available"),
+                Err(error) => error!("Error fetching consensus block count: {error:?}"),
+            }
+
+            sleep(RETRY_DELAY).await;
+        }
+    }
+
+    async fn transition_refund_accepted(
+        result: Result<u64, String>,
+        old_state: LightningPayStateMachine,
+        common: LightningPayCommon,
+        redispatching: LightningPayRedispatching,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: DynGlobalClientContext,
+    ) -> LightningPayStateMachine {
+        let consensus_block_count = match result {
+            Ok(consensus_block_count) => consensus_block_count,
+            Err(error) => {
+                return LightningPayStateMachine {
+                    common: old_state.common,
+                    state: LightningPayStates::Failure(format!(
+                        "Refund of cancelled contract was rejected: {error}"
+                    )),
+                }
+            }
+        };
+
+        let gateway_id = redispatching.gateway.gateway_id;
+        match fund_redispatched_contract(
+            &common,
+            redispatching.gateway,
+            consensus_block_count,
+            dbtx,
+            &global_context,
+        )
+        .await
+        {
+            Ok(()) => LightningPayStateMachine {
+                common: old_state.common,
+                state: LightningPayStates::Redispatched(LightningPayRedispatched {
+                    refund_txid: redispatching.refund_txid,
+                    refund_out_points: redispatching.refund_out_points,
+                    failed_gateway_id: redispatching.failed_gateway_id,
+                    gateway_id,
+                    error_reason: redispatching.error_reason,
+                }),
+            },
+            Err(error) => {
+                warn!(?error, %gateway_id, "Could not re-dispatch payment to next gateway");
+                LightningPayStateMachine {
+                    common: old_state.common,
+                    state: LightningPayStates::Refund(LightningPayRefund {
+                        txid: redispatching.refund_txid,
+                        out_points: redispatching.refund_out_points,
+                        error_reason: redispatching.error_reason,
+                    }),
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Decodable, Encodable)]
 pub struct PayInvoicePayload {
     pub federation_id: FederationId,
@@ -586,12 +907,13 @@ impl PayInvoicePayload {
 }
 
 /// Data needed to pay an invoice, may be the whole invoice or only the required
-/// parts of it.
+/// parts of it, or a spontaneous payment without any invoice.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Decodable, Encodable)]
 #[serde(rename_all = "snake_case")]
 pub enum PaymentData {
     Invoice(Bolt11Invoice),
     PrunedInvoice(PrunedInvoice),
+    Keysend(KeysendPayment),
 }
 
 impl PaymentData {
@@ -600,7 +922,8 @@ impl PaymentData {
             PaymentData::Invoice(invoice) => {
                 invoice.amount_milli_satoshis().map(Amount::from_msats)
             }
-            PaymentData::PrunedInvoice(PrunedInvoice { amount, .. }) => Some(*amount),
+            PaymentData::PrunedInvoice(PrunedInvoice { amount, .. })
+            | PaymentData::Keysend(KeysendPayment { amount, .. }) => Some(*amount),
         }
     }
 
@@ -610,7 +933,8 @@ impl PaymentData {
                 .payee_pub_key()
                 .copied()
                 .unwrap_or_else(|| invoice.recover_payee_pub_key()),
-            PaymentData::PrunedInvoice(PrunedInvoice { destination, .. }) => *destination,
+            PaymentData::PrunedInvoice(PrunedInvoice { destination, .. })
+            | PaymentData::Keysend(KeysendPayment { destination, .. }) => *destination,
         }
     }
 
@@ -618,6 +942,7 @@ impl PaymentData {
         match self {
             PaymentData::Invoice(invoice) => *invoice.payment_hash(),
             PaymentData::PrunedInvoice(PrunedInvoice { payment_hash, .. }) => *payment_hash,
+            PaymentData::Keysend(keysend) => keysend.payment_hash(),
         }
     }
 
@@ -627,6 +952,7 @@ impl PaymentData {
                 invoice.route_hints().into_iter().map(Into::into).collect()
             }
             PaymentData::PrunedInvoice(PrunedInvoice { route_hints, .. }) => route_hints.clone(),
+            PaymentData::Keysend(_) => vec![],
         }
     }
 
@@ -641,6 +967,8 @@ impl PaymentData {
             PaymentData::PrunedInvoice(PrunedInvoice {
                 expiry_timestamp, ..
             }) => *expiry_timestamp,
+            // Spontaneous payments do not expire
+            PaymentData::Keysend(_) => u64::MAX,
         }
     }
 }
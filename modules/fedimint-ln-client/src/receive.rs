@@ -9,10 +9,11 @@ use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::{IntoDynInstance, ModuleInstanceId, OperationId};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
-use fedimint_core::{OutPoint, TransactionId};
+use fedimint_core::{Amount, OutPoint, TransactionId};
 use fedimint_ln_common::contracts::incoming::IncomingContractAccount;
 use fedimint_ln_common::contracts::{DecryptedPreimage, FundedContract};
 use fedimint_ln_common::federation_endpoint_constants::ACCOUNT_ENDPOINT;
+use fedimint_ln_common::route_hints::RouteHint;
 use fedimint_ln_common::LightningInput;
 use lightning_invoice::Bolt11Invoice;
 use serde::{Deserialize, Serialize};
@@ -287,7 +288,7 @@ impl LightningReceiveConfirmedInvoice {
         let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
             input,
             amount: contract.amount,
-            keys: vec![keypair],
+            keys: vec![keypair.into()],
             // The input of the refund tx is managed by this state machine, so no new state machines
             // need to be created
             state_machines: Arc::new(|_, _| vec![]),
@@ -387,14 +388,55 @@ impl LightningReceiveFunded {
     }
 }
 
+/// Returns the fees the payer pays on top of `amount` to the hops of a route
+/// hint of the gateway, each of which charges on the amount it forwards
+pub(crate) fn route_hint_fee(route_hint: &RouteHint, amount: Amount) -> Amount {
+    route_hint.0.iter().rev().fold(amount, |forwarded, hop| {
+        forwarded
+            + Amount::from_msats(
+                u64::from(hop.base_msat)
+                    + forwarded.msats * u64::from(hop.proportional_millionths) / 1_000_000,
+            )
+    }) - amount
+}
+
 #[cfg(test)]
 mod tests {
     use bitcoin::hashes::{sha256, Hash};
+    use fedimint_ln_common::route_hints::RouteHintHop;
     use lightning_invoice::{Currency, InvoiceBuilder, PaymentSecret};
     use secp256k1::SecretKey;
 
     use super::*;
 
+    #[test]
+    fn route_hint_fees_accumulate_towards_the_payer() {
+        let hop = |base_msat, proportional_millionths| RouteHintHop {
+            src_node_id: secp256k1::PublicKey::from_secret_key(
+                &secp256k1::Secp256k1::new(),
+                &SecretKey::new(&mut rand::thread_rng()),
+            ),
+            short_channel_id: 1,
+            base_msat,
+            proportional_millionths,
+            cltv_expiry_delta: 40,
+            htlc_minimum_msat: None,
+            htlc_maximum_msat: None,
+        };
+        let amount = Amount::from_msats(1_000_000);
+
+        assert_eq!(route_hint_fee(&RouteHint(vec![]), amount), Amount::ZERO);
+        assert_eq!(
+            route_hint_fee(&RouteHint(vec![hop(1_000, 1_000)]), amount),
+            Amount::from_msats(2_000)
+        );
+        // The first hop charges on the amount the second hop forwards plus its fee
+        assert_eq!(
+            route_hint_fee(&RouteHint(vec![hop(0, 10_000), hop(1_000, 0)]), amount),
+            Amount::from_msats(1_000 + 10_010)
+        );
+    }
+
     #[test]
     fn test_invoice_expiration() -> anyhow::Result<()> {
         let now = fedimint_core::time::duration_since_epoch();
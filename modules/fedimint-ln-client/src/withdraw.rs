@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use fedimint_client::sm::{State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::util::{retry, FibonacciBackoff};
+use fedimint_core::TransactionId;
+use lightning_invoice::Bolt11Invoice;
+use tracing::warn;
+
+use crate::LightningClientContext;
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that hands the invoice of an incoming payment to an
+/// LNURL-withdraw service once the federation accepted the offer, so the
+/// service pays it. Receiving the payment itself is handled by the
+/// [`crate::receive::LightningReceiveStateMachine`] of the same operation.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///     AwaitingOffer -- await offer transaction acceptance --> SubmittingInvoice
+///     AwaitingOffer -- await offer transaction rejection --> Failed
+///     SubmittingInvoice -- service accepted invoice --> InvoiceSubmitted
+///     SubmittingInvoice -- service rejected invoice --> Failed
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum LnurlWithdrawStates {
+    AwaitingOffer(TransactionId),
+    SubmittingInvoice,
+    InvoiceSubmitted,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LnurlWithdrawCommon {
+    pub operation_id: OperationId,
+    pub invoice: Bolt11Invoice,
+    /// Callback url of the LNURL-withdraw service the invoice is submitted to
+    pub callback: String,
+    /// Secret identifying the withdrawal to the service
+    pub k1: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct LnurlWithdrawStateMachine {
+    pub common: LnurlWithdrawCommon,
+    pub state: LnurlWithdrawStates,
+}
+
+impl State for LnurlWithdrawStateMachine {
+    type ModuleContext = LightningClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        match &self.state {
+            LnurlWithdrawStates::AwaitingOffer(offer_txid) => {
+                vec![StateTransition::new(
+                    global_context.clone().await_tx_accepted(*offer_txid),
+                    |_dbtx, result, old_state| {
+                        Box::pin(async move {
+                            let state = match result {
+                                Ok(()) => LnurlWithdrawStates::SubmittingInvoice,
+                                Err(_) => LnurlWithdrawStates::Failed(
+                                    "Offer transaction was rejected".to_string(),
+                                ),
+                            };
+                            LnurlWithdrawStateMachine {
+                                common: old_state.common,
+                                state,
+                            }
+                        })
+                    },
+                )]
+            }
+            LnurlWithdrawStates::SubmittingInvoice => {
+                vec![StateTransition::new(
+                    submit_invoice(self.common.clone()),
+                    |_dbtx, result, old_state| {
+                        Box::pin(async move {
+                            let state = match result {
+                                Ok(()) => LnurlWithdrawStates::InvoiceSubmitted,
+                                Err(error) => LnurlWithdrawStates::Failed(error),
+                            };
+                            LnurlWithdrawStateMachine {
+                                common: old_state.common,
+                                state,
+                            }
+                        })
+                    },
+                )]
+            }
+            LnurlWithdrawStates::InvoiceSubmitted | LnurlWithdrawStates::Failed(_) => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+/// Sends the invoice to the callback of the LNURL-withdraw service, retrying
+/// if the service can't be reached. Returns the reason given by the service if
+/// it refuses to pay the invoice.
+async fn submit_invoice(common: LnurlWithdrawCommon) -> Result<(), String> {
+    let separator = if common.callback.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let url = format!(
+        "{}{separator}k1={}&pr={}",
+        common.callback, common.k1, common.invoice
+    );
+
+    let response = retry(
+        "Submitting invoice to LNURL-withdraw service",
+        FibonacciBackoff::default()
+            .with_min_delay(Duration::from_millis(500))
+            .with_max_delay(Duration::from_secs(30))
+            .with_max_times(10),
+        || async {
+            reqwest::Client::new()
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<lnurl::Response>()
+                .await
+                .context("Invalid response from LNURL-withdraw service")
+        },
+    )
+    .await
+    .map_err(|e| {
+        warn!(%e, operation_id = %common.operation_id.fmt_short(), "Failed to submit invoice to LNURL-withdraw service");
+        e.to_string()
+    })?;
+
+    match response {
+        lnurl::Response::Ok { .. } => Ok(()),
+        lnurl::Response::Error { reason } => Err(format!(
+            "LNURL-withdraw service refused the invoice: {reason}"
+        )),
+    }
+}
@@ -166,7 +166,7 @@ impl FeeToAmount for RoutingFees {
             0
         };
 
-        msats(base_fee + margin_fee)
+        msats(base_fee).saturating_add(msats(margin_fee))
     }
 }
 
@@ -1,23 +1,58 @@
 /// Use `_` for word separator
 
 pub const ADDRESS_ENDPOINT: &str = "/address";
+pub const APPLY_CONFIG_ENDPOINT: &str = "/apply_config";
 pub const BACKUP_ENDPOINT: &str = "/backup";
 pub const BALANCE_ENDPOINT: &str = "/balance";
+pub const CLOSE_CHANNEL_ENDPOINT: &str = "/close_channel";
 pub const CONFIGURATION_ENDPOINT: &str = "/config";
 pub const CONNECT_FED_ENDPOINT: &str = "/connect-fed"; // uses `-` for backwards compatibility
 pub const CONNECT_TO_PEER_ENDPOINT: &str = "/connect_to_peer";
 pub const CREATE_INVOICE_V2_ENDPOINT: &str = "/create_invoice";
+pub const CREATE_PUBLIC_INVOICE_ENDPOINT: &str = "/create_public_invoice";
+pub const EXPORT_CONNECTIONS_ENDPOINT: &str = "/export_connections";
+pub const EXPORT_DB_ENDPOINT: &str = "/export_db";
+pub const GATEWAY_EVENTS_ENDPOINT: &str = "/events";
 pub const GATEWAY_INFO_ENDPOINT: &str = "/info";
+pub const GET_CHANNEL_ENDPOINT: &str = "/get_channel";
+pub const GET_CONFIG_ENDPOINT: &str = "/get_config";
+pub const GET_EARNINGS_ENDPOINT: &str = "/get_earnings";
+pub const GET_FEDERATION_POLICY_ENDPOINT: &str = "/get_federation_policy";
+pub const GET_FEE_MODE_ENDPOINT: &str = "/get_fee_mode";
+pub const GET_SWAP_FEES_ENDPOINT: &str = "/get_swap_fees";
 pub const GET_GATEWAY_ID_ENDPOINT: &str = "/id";
 pub const GATEWAY_INFO_POST_ENDPOINT: &str = "/info";
 pub const GET_FUNDING_ADDRESS_ENDPOINT: &str = "/get_funding_address";
+pub const GET_L402_CONFIG_ENDPOINT: &str = "/get_l402_config";
+pub const GET_NOSTR_CONFIG_ENDPOINT: &str = "/get_nostr_config";
+pub const GET_PAYMENT_PROGRESS_ENDPOINT: &str = "/get_payment_progress";
+pub const GET_RISK_STATUS_ENDPOINT: &str = "/get_risk_status";
+pub const IMPORT_CONNECTIONS_ENDPOINT: &str = "/import_connections";
 pub const LEAVE_FED_ENDPOINT: &str = "/leave-fed"; // uses `-` for backwards compatibility
 pub const LIST_ACTIVE_CHANNELS_ENDPOINT: &str = "/list_active_channels";
+pub const LIST_CHANNELS_ENDPOINT: &str = "/list_channels";
+pub const LIST_PAYMENTS_ENDPOINT: &str = "/list_payments";
+pub const LIST_PENDING_HTLCS_ENDPOINT: &str = "/list_pending_htlcs";
+pub const LIST_SCID_ALIASES_ENDPOINT: &str = "/list_scid_aliases";
+pub const NODE_STATS_ENDPOINT: &str = "/node_stats";
 pub const OPEN_CHANNEL_ENDPOINT: &str = "/open_channel";
 pub const CLOSE_CHANNELS_WITH_PEER_ENDPOINT: &str = "/close_channels_with_peer";
 pub const PAYMENT_INFO_V2_ENDPOINT: &str = "/payment_info";
 pub const PAY_INVOICE_ENDPOINT: &str = "/pay_invoice";
+pub const PAY_KEYSEND_ENDPOINT: &str = "/pay_keysend";
+pub const PUBLIC_INVOICE_CONTRACTS_ENDPOINT: &str = "/public_invoice_contracts";
+pub const REGISTER_PUBLIC_RECEIVER_ENDPOINT: &str = "/register_public_receiver";
+pub const RESOLVE_HTLC_ENDPOINT: &str = "/resolve_htlc";
 pub const RESTORE_ENDPOINT: &str = "/restore";
 pub const SEND_PAYMENT_V2_ENDPOINT: &str = "/send_payment";
 pub const SET_CONFIGURATION_ENDPOINT: &str = "/set_configuration";
+pub const SET_FEDERATION_REGISTRATION_ENDPOINT: &str = "/set_federation_registration";
+pub const SET_FEDERATION_POLICY_ENDPOINT: &str = "/set_federation_policy";
+pub const SET_FEE_MODE_ENDPOINT: &str = "/set_fee_mode";
+pub const SET_L402_CONFIG_ENDPOINT: &str = "/set_l402_config";
+pub const SET_NOSTR_CONFIG_ENDPOINT: &str = "/set_nostr_config";
+pub const SET_RISK_LIMITS_ENDPOINT: &str = "/set_risk_limits";
+pub const SET_ROUTE_HINT_PRIVACY_ENDPOINT: &str = "/set_route_hint_privacy";
+pub const SET_SWAP_FEES_ENDPOINT: &str = "/set_swap_fees";
+pub const SHUTDOWN_ENDPOINT: &str = "/shutdown";
 pub const WITHDRAW_ENDPOINT: &str = "/withdraw";
@@ -704,6 +704,27 @@ impl TryFrom<Bolt11Invoice> for PrunedInvoice {
     }
 }
 
+/// A spontaneous payment to a lightning node that does not require an invoice
+///
+/// The outgoing contract funding the payment is locked to the hash of
+/// `preimage`, which is chosen by the payer and revealed to the gateway along
+/// with the payment. The gateway therefore has to be trusted to actually send
+/// the payment before claiming the contract.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Decodable, Encodable)]
+pub struct KeysendPayment {
+    pub destination: secp256k1::PublicKey,
+    pub amount: Amount,
+    pub preimage: Preimage,
+    /// Custom TLV records sent to the recipient along with the payment
+    pub tlv_records: BTreeMap<u64, Vec<u8>>,
+}
+
+impl KeysendPayment {
+    pub fn payment_hash(&self) -> sha256::Hash {
+        bitcoin_hashes::Hash::hash(&self.preimage.0)
+    }
+}
+
 /// Request sent to the federation that requests the removal of a gateway
 /// registration. Each peer is expected to check the `signatures` map for the
 /// signature that validates the gateway authorized the removal of this
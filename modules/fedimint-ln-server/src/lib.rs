@@ -10,7 +10,7 @@
 #![allow(clippy::too_many_lines)]
 
 pub mod db;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
 use anyhow::{bail, Context};
@@ -21,6 +21,7 @@ use fedimint_core::config::{
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::DbKeyPrefixDecl;
 use fedimint_core::db::{
     DatabaseTransaction, DatabaseValue, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
 };
@@ -87,6 +88,20 @@ impl ModuleInit for LightningInit {
     type Common = LightningCommonInit;
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        vec![
+            DbKeyPrefixDecl::of::<ContractKey>(DbKeyPrefix::Contract),
+            DbKeyPrefixDecl::of::<OfferKey>(DbKeyPrefix::Offer),
+            DbKeyPrefixDecl::of::<ProposeDecryptionShareKey>(DbKeyPrefix::ProposeDecryptionShare),
+            DbKeyPrefixDecl::of::<AgreedDecryptionShareKey>(DbKeyPrefix::AgreedDecryptionShare),
+            DbKeyPrefixDecl::of::<ContractUpdateKey>(DbKeyPrefix::ContractUpdate),
+            DbKeyPrefixDecl::of::<LightningGatewayKey>(DbKeyPrefix::LightningGateway),
+            DbKeyPrefixDecl::of::<BlockCountVoteKey>(DbKeyPrefix::BlockCountVote),
+            DbKeyPrefixDecl::of::<EncryptedPreimageIndexKey>(DbKeyPrefix::EncryptedPreimageIndex),
+            DbKeyPrefixDecl::of::<LightningAuditItemKey>(DbKeyPrefix::LightningAuditItem),
+        ]
+    }
+
     async fn dump_database(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -349,6 +364,11 @@ impl ServerModule for Lightning {
     type Common = LightningModuleTypes;
     type Init = LightningInit;
 
+    fn local_db_prefixes(&self) -> BTreeSet<u8> {
+        // our own decryption shares of incoming contracts
+        BTreeSet::from([DbKeyPrefix::ProposeDecryptionShare as u8])
+    }
+
     async fn consensus_proposal(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -10,8 +11,8 @@ use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyInit;
 use fedimint_ln_client::{
     InternalPayState, LightningClientInit, LightningClientModule, LightningOperationMeta,
-    LnPayState, LnReceiveState, MockGatewayConnection, OutgoingLightningPayment, PayType,
-    RealGatewayConnection,
+    LightningOperationMetaPay, LightningOperationMetaVariant, LnPayState, LnReceiveState,
+    MockGatewayConnection, OutgoingLightningPayment, PayType, RealGatewayConnection,
 };
 use fedimint_ln_common::config::LightningGenParams;
 use fedimint_ln_common::ln_operation;
@@ -19,7 +20,7 @@ use fedimint_ln_server::LightningInit;
 use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::Fixtures;
 use fedimint_testing::gateway::{GatewayTest, DEFAULT_GATEWAY_PASSWORD};
-use fedimint_testing::ln::FakeLightningTest;
+use fedimint_testing::ln::{FakeLightningTest, INVALID_KEYSEND_TLV_TYPE};
 use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescription, Description};
 use rand::rngs::OsRng;
 use secp256k1::KeyPair;
@@ -278,6 +279,168 @@ async fn gateway_protects_preimage_for_payment() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Fixtures whose clients send their payments to the gateway under test
+fn fixtures_with_real_gateway_connection() -> Fixtures {
+    let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
+    let ln_params = LightningGenParams::regtest(fixtures.bitcoin_server());
+    fixtures.with_module(
+        LightningClientInit {
+            gateway_conn: Arc::new(RealGatewayConnection),
+        },
+        LightningInit,
+        ln_params,
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pays_keysend_through_gateway() -> anyhow::Result<()> {
+    let fixtures = fixtures_with_real_gateway_connection();
+    let fed = fixtures.new_default_fed().await;
+    let gw = gateway(&fixtures, &fed).await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let ln_module = client.get_first_module::<LightningClientModule>();
+
+    let (op, outpoint) = dummy_module.print_money(sats(10000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    let other_ln = FakeLightningTest::new();
+    let gateway = ln_module
+        .select_gateway(&gw.gateway.gateway_id)
+        .await
+        .expect("Gateway is registered");
+    let OutgoingLightningPayment {
+        payment_type, fee, ..
+    } = ln_module
+        .pay_keysend(
+            gateway,
+            other_ln.gateway_node_pub_key,
+            sats(100),
+            BTreeMap::new(),
+            (),
+        )
+        .await?;
+    let PayType::Lightning(operation_id) = payment_type else {
+        panic!("Expected lightning payment!");
+    };
+
+    let mut sub = ln_module
+        .subscribe_ln_pay(operation_id)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, LnPayState::Created);
+    assert_matches!(sub.ok().await?, LnPayState::Funded { .. });
+    assert_matches!(sub.ok().await?, LnPayState::Success { .. });
+    assert_eq!(client.get_balance().await, sats(10000) - sats(100) - fee);
+
+    drop(gw);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refunds_keysend_the_gateway_failed_to_send() -> anyhow::Result<()> {
+    let fixtures = fixtures_with_real_gateway_connection();
+    let fed = fixtures.new_default_fed().await;
+    let gw = gateway(&fixtures, &fed).await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let ln_module = client.get_first_module::<LightningClientModule>();
+
+    let (op, outpoint) = dummy_module.print_money(sats(10000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    // The gateway's lightning node fails to send the keysend, so the gateway
+    // cancels the contract and the client claims its funds back
+    let other_ln = FakeLightningTest::new();
+    let gateway = ln_module
+        .select_gateway(&gw.gateway.gateway_id)
+        .await
+        .expect("Gateway is registered");
+    let OutgoingLightningPayment { payment_type, .. } = ln_module
+        .pay_keysend(
+            gateway,
+            other_ln.gateway_node_pub_key,
+            sats(100),
+            BTreeMap::from([(INVALID_KEYSEND_TLV_TYPE, vec![])]),
+            (),
+        )
+        .await?;
+    let PayType::Lightning(operation_id) = payment_type else {
+        panic!("Expected lightning payment!");
+    };
+
+    let mut sub = ln_module
+        .subscribe_ln_pay(operation_id)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, LnPayState::Created);
+    assert_matches!(sub.ok().await?, LnPayState::Funded { .. });
+    assert_matches!(sub.ok().await?, LnPayState::WaitingForRefund { .. });
+    assert_matches!(sub.ok().await?, LnPayState::Refunded { .. });
+    assert_eq!(client.get_balance().await, sats(10000));
+
+    drop(gw);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn redispatches_payment_cancelled_by_gateway() -> anyhow::Result<()> {
+    let fixtures = fixtures_with_real_gateway_connection();
+    let fed = fixtures.new_default_fed().await;
+    let gw1 = gateway(&fixtures, &fed).await;
+    let gw2 = gateway(&fixtures, &fed).await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let ln_module = client.get_first_module::<LightningClientModule>();
+
+    let (op, outpoint) = dummy_module.print_money(sats(10000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+    ln_module.update_gateway_cache().await?;
+
+    // Both gateways cancel the contract for an unpayable invoice, so the payment
+    // is re-dispatched to the second gateway once and then refunded
+    let other_ln = FakeLightningTest::new();
+    let invoice = other_ln.unpayable_invoice(sats(100), None);
+    let OutgoingLightningPayment { payment_type, .. } =
+        pay_invoice(&client, invoice, Some(gw1.gateway.gateway_id)).await?;
+    let PayType::Lightning(operation_id) = payment_type else {
+        panic!("Expected lightning payment!");
+    };
+
+    let mut sub = ln_module
+        .subscribe_ln_pay(operation_id)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, LnPayState::Created);
+    let LnPayState::Funded {
+        block_height: first_timelock,
+    } = sub.ok().await?
+    else {
+        panic!("Expected the first contract to be funded");
+    };
+    assert_matches!(
+        sub.ok().await?,
+        LnPayState::Redispatched { failed_gateway_id, gateway_id, .. }
+            if failed_gateway_id == gw1.gateway.gateway_id && gateway_id == gw2.gateway.gateway_id
+    );
+    let LnPayState::Funded {
+        block_height: second_timelock,
+    } = sub.ok().await?
+    else {
+        panic!("Expected the second contract to be funded");
+    };
+    assert!(first_timelock <= second_timelock);
+    assert_matches!(sub.ok().await?, LnPayState::WaitingForRefund { .. });
+    assert_matches!(sub.ok().await?, LnPayState::Refunded { .. });
+
+    // The second contract was only funded after the first one was refunded
+    assert_eq!(client.get_balance().await, sats(10000));
+
+    drop(gw1);
+    drop(gw2);
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn cannot_pay_same_external_invoice_twice() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -380,8 +543,10 @@ async fn makes_internal_payments_within_federation() -> anyhow::Result<()> {
     let OutgoingLightningPayment {
         payment_type,
         contract_id: _,
-        fee: _,
+        fee,
     } = pay_invoice(&client2, invoice, None).await?;
+    // Internal payments skip the gateway, so no routing fee is charged
+    assert_eq!(fee, Amount::ZERO);
     match payment_type {
         PayType::Internal(op_id) => {
             let mut sub2 = client2
@@ -424,8 +589,10 @@ async fn makes_internal_payments_within_federation() -> anyhow::Result<()> {
     let OutgoingLightningPayment {
         payment_type,
         contract_id: _,
-        fee: _,
+        fee,
     } = pay_invoice(&client2, invoice, Some(gw.gateway.gateway_id)).await?;
+    // Internal payments skip the gateway, so no routing fee is charged
+    assert_eq!(fee, Amount::ZERO);
     match payment_type {
         PayType::Internal(op_id) => {
             let mut sub2 = client2
@@ -445,6 +612,80 @@ async fn makes_internal_payments_within_federation() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn internal_payments_skip_the_gateway() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let client2_dummy_module = client2.get_first_module::<DummyClientModule>();
+
+    // Print money for client2
+    let (op, outpoint) = client2_dummy_module.print_money(sats(1000)).await?;
+    client2.await_primary_module_output(op, outpoint).await?;
+
+    let gw = gateway(&fixtures, &fed).await;
+    let ln_module = client2.get_first_module::<LightningClientModule>();
+    let ln_gateway = ln_module.select_gateway(&gw.gateway.gateway_id).await;
+
+    // Invoices of other nodes are paid through the gateway
+    let other_ln = FakeLightningTest::new();
+    let external_invoice = other_ln.invoice(Amount::from_sats(100), None)?;
+    assert!(!ln_module.is_internal_invoice(&external_invoice).await?);
+
+    let desc = Description::new("with-markers".to_string())?;
+    let (op, invoice, _) = client1
+        .get_first_module::<LightningClientModule>()
+        .create_bolt11_invoice(
+            sats(250),
+            Bolt11InvoiceDescription::Direct(&desc),
+            None,
+            (),
+            None,
+        )
+        .await?;
+    let mut sub1 = client1
+        .get_first_module::<LightningClientModule>()
+        .subscribe_ln_receive(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub1.ok().await?, LnReceiveState::Created);
+    assert_matches!(sub1.ok().await?, LnReceiveState::WaitingForPayment { .. });
+    assert!(ln_module.is_internal_invoice(&invoice).await?);
+
+    // Even if a gateway is supplied the payment is settled internally
+    let OutgoingLightningPayment {
+        payment_type,
+        contract_id: _,
+        fee,
+    } = ln_module
+        .pay_bolt11_invoice(ln_gateway, invoice, ())
+        .await?;
+    assert_eq!(fee, Amount::ZERO);
+    let PayType::Internal(op_id) = payment_type else {
+        panic!("Expected internal payment!");
+    };
+
+    let mut sub2 = ln_module.subscribe_internal_pay(op_id).await?.into_stream();
+    assert_eq!(sub2.ok().await?, InternalPayState::Funding);
+    assert_matches!(sub2.ok().await?, InternalPayState::Preimage { .. });
+    assert_eq!(sub1.ok().await?, LnReceiveState::Funded);
+    assert_eq!(sub1.ok().await?, LnReceiveState::AwaitingFunds);
+    assert_eq!(sub1.ok().await?, LnReceiveState::Claimed);
+
+    let operation = ln_operation(&client2, op_id).await?;
+    assert_matches!(
+        operation.meta::<LightningOperationMeta>().variant,
+        LightningOperationMetaVariant::Pay(LightningOperationMetaPay {
+            is_internal_payment: true,
+            gateway_id: None,
+            fee,
+            ..
+        }) if fee == Amount::ZERO
+    );
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn can_receive_for_other_user() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -503,7 +744,13 @@ async fn can_receive_for_other_user() -> anyhow::Result<()> {
     // Create a new client and try to receive the locked payment
     let new_client = fed.new_client().await;
     let new_ln_module = new_client.get_first_module::<LightningClientModule>();
-    let operation_id = new_ln_module.scan_receive_for_user(keypair, ()).await?;
+    // Concurrent scans claim the contract only once
+    let (operation_id, concurrent_operation_id) = tokio::join!(
+        new_ln_module.scan_receive_for_user(keypair, ()),
+        new_ln_module.scan_receive_for_user(keypair, ())
+    );
+    let operation_id = operation_id?;
+    assert_eq!(concurrent_operation_id?, operation_id);
     let mut sub3 = new_ln_module
         .subscribe_ln_claim(operation_id)
         .await?
@@ -511,6 +758,11 @@ async fn can_receive_for_other_user() -> anyhow::Result<()> {
     assert_eq!(sub3.ok().await?, LnReceiveState::AwaitingFunds);
     assert_eq!(sub3.ok().await?, LnReceiveState::Claimed);
     assert_eq!(new_client.get_balance().await, sats(250));
+    // Scanning again after the claim returns the claim as well
+    assert_eq!(
+        new_ln_module.scan_receive_for_user(keypair, ()).await?,
+        operation_id
+    );
 
     // TEST internal payment when there is a registered gateway
     let gw = gateway(&fixtures, &fed).await;
@@ -1386,6 +1638,11 @@ mod fedimint_migration_tests {
                             );
                             info!("Validated LightningGateways");
                         }
+                        fedimint_ln_client::db::DbKeyPrefix::GatewayScore
+                        | fedimint_ln_client::db::DbKeyPrefix::PaymentFailover
+                        | fedimint_ln_client::db::DbKeyPrefix::IncomingContractClaim => {
+                            // Not present in the migration snapshots
+                        }
                     }
                 }
 
@@ -0,0 +1,36 @@
+use fedimint_client::module::init::recovery::RecoveryFromHistoryCommon;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::impl_db_record;
+
+use crate::recovery::LightningRecoveryState;
+
+#[repr(u8)]
+#[derive(Clone, Debug)]
+pub enum DbKeyPrefix {
+    RecoveryState = 0x01,
+    RecoveryFinalized = 0x02,
+}
+
+impl std::fmt::Display for DbKeyPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct RecoveryStateKey;
+
+impl_db_record!(
+    key = RecoveryStateKey,
+    value = (LightningRecoveryState, RecoveryFromHistoryCommon),
+    db_prefix = DbKeyPrefix::RecoveryState,
+);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct RecoveryFinalizedKey;
+
+impl_db_record!(
+    key = RecoveryFinalizedKey,
+    value = bool,
+    db_prefix = DbKeyPrefix::RecoveryFinalized,
+);
@@ -8,7 +8,10 @@
 pub mod api;
 #[cfg(feature = "cli")]
 mod cli;
+mod db;
 mod receive_sm;
+mod recovery;
+mod refund_sm;
 mod send_sm;
 
 use std::sync::Arc;
@@ -16,7 +19,9 @@ use std::sync::Arc;
 use async_stream::stream;
 use bitcoin::hashes::{sha256, Hash};
 use fedimint_api_client::api::DynModuleApi;
-use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
+use fedimint_client::module::init::{
+    ClientModuleInit, ClientModuleInitArgs, ClientModuleRecoverArgs,
+};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
 use fedimint_client::oplog::UpdateStreamOrOutcome;
@@ -45,10 +50,12 @@ use lightning_invoice::Bolt11Invoice;
 use secp256k1::{ecdh, KeyPair, PublicKey, Scalar, SecretKey};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tpe::{derive_agg_decryption_key, AggregateDecryptionKey};
+use tpe::{derive_agg_decryption_key, AggregateDecryptionKey, AggregatePublicKey};
 
 use crate::api::LnFederationApi;
 use crate::receive_sm::{ReceiveSMCommon, ReceiveSMState, ReceiveStateMachine};
+use crate::recovery::LightningRecovery;
+use crate::refund_sm::RefundStateMachine;
 use crate::send_sm::{SendSMCommon, SendSMState, SendStateMachine};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,6 +70,10 @@ pub enum LightningOperationMeta {
     Receive {
         contract: IncomingContract,
     },
+    /// Refund of an outgoing contract found during recovery
+    Refund {
+        contract: OutgoingContract,
+    },
 }
 
 /// Number of blocks until outgoing lightning contracts time out and user
@@ -126,11 +137,45 @@ pub enum ReceiveState {
 pub struct CreateInvoicePayload {
     pub federation_id: FederationId,
     pub contract: IncomingContract,
+    /// The amount of the invoice, zero for an amountless invoice
     pub invoice_amount: Amount,
     pub description: Bolt11InvoiceDescription,
     pub expiry_time: u32,
 }
 
+impl CreateInvoicePayload {
+    /// Returns whether the invoice leaves the amount to the payer, in which
+    /// case the amount is only fixed by the HTLC paying it
+    pub fn is_amountless(&self) -> bool {
+        self.invoice_amount == Amount::ZERO
+    }
+
+    /// Returns whether an HTLC of `amount` pays the invoice. An invoice with
+    /// an amount has to be paid exactly, while an amountless invoice accepts
+    /// any amount that still covers the contract once the gateway deducted its
+    /// `receive_fee`, up to twice that minimum since BOLT 11 payers must not
+    /// overpay by more than that.
+    pub fn accepts_htlc_amount(&self, amount: Amount, receive_fee: &PaymentFee) -> bool {
+        if !self.is_amountless() {
+            return amount == self.invoice_amount;
+        }
+
+        receive_fee
+            .min_amount_before_fee(self.contract.commitment.amount.msats)
+            .is_some_and(|min_amount| {
+                min_amount <= amount && amount.msats <= min_amount.msats.saturating_mul(2)
+            })
+    }
+}
+
+/// Request for the incoming contracts a gateway created for a public receiver,
+/// see [`LightningClientModule::claim_public_invoice_contracts`]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PublicInvoiceContractsPayload {
+    /// Token the gateway returned when the receiver was registered
+    pub token: String,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Decodable, Encodable)]
 pub enum Bolt11InvoiceDescription {
     Direct(String),
@@ -150,6 +195,12 @@ pub struct PaymentInfo {
     pub send_fee_minimum: PaymentFee,
     pub send_fee_default: PaymentFee,
     pub receive_fee: PaymentFee,
+    /// Fee charged on the sending side of a direct swap into another
+    /// federation served by the same gateway
+    pub swap_send_fee: PaymentFee,
+    /// Fee charged on the receiving side of a direct swap from another
+    /// federation served by the same gateway
+    pub swap_receive_fee: PaymentFee,
     pub expiration_delta_default: u64,
     pub expiration_delta_minimum: u64,
 }
@@ -191,6 +242,42 @@ impl PaymentFee {
                 .saturating_mul(self.parts_per_million)
                 .saturating_div(1_000_000)
     }
+
+    /// Returns the smallest amount that leaves at least `msats` once the fee
+    /// has been subtracted, or `None` if the fee consumes any amount.
+    pub fn min_amount_before_fee(&self, msats: u64) -> Option<Amount> {
+        let remaining_ppm = 1_000_000_u64
+            .checked_sub(self.parts_per_million)
+            .filter(|remaining_ppm| *remaining_ppm != 0)?;
+
+        let numerator = u128::from(msats.saturating_add(self.base.msats)) * 1_000_000;
+        let remaining_ppm = u128::from(remaining_ppm);
+        let mut amount = u64::try_from((numerator + remaining_ppm - 1) / remaining_ppm).ok()?;
+
+        // the fee is rounded down, so the estimate above may be slightly too high
+        while amount > 0 && self.subtract_fee(amount - 1).msats >= msats {
+            amount -= 1;
+        }
+
+        Some(Amount::from_msats(amount))
+    }
+}
+
+/// The expected outcome of receiving a payment through a given gateway,
+/// computed before requesting an invoice from it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ReceivePreview {
+    /// The amount of the invoice, which is what the payer has to send
+    pub invoice_amount: Amount,
+    /// The fee deducted by the gateway before it funds the incoming contract
+    pub gateway_fee: Amount,
+    /// The fee charged by the federation to claim the incoming contract
+    pub federation_fee: Amount,
+    /// The amount we receive once the incoming contract has been claimed
+    pub receive_amount: Amount,
+    /// The smallest invoice amount for which we receive anything after fees,
+    /// `None` if the gateway's fee consumes any amount
+    pub min_invoice_amount: Option<Amount>,
 }
 
 #[derive(Debug, Clone)]
@@ -218,6 +305,15 @@ impl ClientModuleInit for LightningClientInit {
             .expect("no version conflicts")
     }
 
+    async fn recover(
+        &self,
+        args: &ClientModuleRecoverArgs<Self>,
+        snapshot: Option<&<Self::Module as ClientModule>::Backup>,
+    ) -> anyhow::Result<()> {
+        args.recover_from_history::<LightningRecovery>(snapshot)
+            .await
+    }
+
     async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
         Ok(LightningClientModule {
             federation_id: *args.federation_id(),
@@ -284,7 +380,10 @@ impl ClientModule for LightningClientModule {
     }
 }
 
-fn generate_ephemeral_tweak(static_pk: PublicKey) -> ([u8; 32], PublicKey) {
+/// Generates an ephemeral tweak for an incoming contract to `static_pk`
+/// together with the ephemeral public key the recipient needs to derive the
+/// same tweak.
+pub fn generate_ephemeral_tweak(static_pk: PublicKey) -> ([u8; 32], PublicKey) {
     let ephemeral_keypair = KeyPair::new(secp256k1::SECP256K1, &mut rand::thread_rng());
 
     let ephemeral_tweak = ecdh::shared_secret_point(&static_pk, &ephemeral_keypair.secret_key())
@@ -294,6 +393,57 @@ fn generate_ephemeral_tweak(static_pk: PublicKey) -> ([u8; 32], PublicKey) {
     (ephemeral_tweak, ephemeral_keypair.public_key())
 }
 
+/// Derives the keys to claim an incoming contract to the static `keypair`,
+/// `None` if the contract was not created for it
+fn recover_contract_keys(
+    keypair: &KeyPair,
+    tpe_agg_pk: &AggregatePublicKey,
+    contract: &IncomingContract,
+) -> Option<(KeyPair, AggregateDecryptionKey)> {
+    let ephemeral_tweak =
+        ecdh::shared_secret_point(&contract.commitment.ephemeral_pk, &keypair.secret_key())
+            .consensus_hash::<sha256::Hash>()
+            .to_byte_array();
+
+    let encryption_seed = ephemeral_tweak
+        .consensus_hash::<sha256::Hash>()
+        .to_byte_array();
+
+    let claim_keypair = keypair
+        .secret_key()
+        .mul_tweak(&Scalar::from_be_bytes(ephemeral_tweak).expect("Within curve order"))
+        .expect("Tweak is valid")
+        .keypair(secp256k1::SECP256K1);
+
+    if claim_keypair.public_key() != contract.commitment.claim_pk {
+        return None; // The claim key is not derived from our pk
+    }
+
+    let agg_decryption_key = derive_agg_decryption_key(tpe_agg_pk, &encryption_seed);
+
+    if !contract.verify_agg_decryption_key(tpe_agg_pk, &agg_decryption_key) {
+        return None; // The decryption key is not derived from our pk
+    }
+
+    contract.decrypt_preimage(&agg_decryption_key)?;
+
+    Some((claim_keypair, agg_decryption_key))
+}
+
+/// Derives the key to refund an outgoing contract we funded with the static
+/// `keypair`, `None` if the contract was not funded by us
+fn recover_refund_keypair(keypair: &KeyPair, contract: &OutgoingContract) -> Option<KeyPair> {
+    let ephemeral_tweak = ecdh::shared_secret_point(&contract.ephemeral_pk, &keypair.secret_key())
+        .consensus_hash::<sha256::Hash>()
+        .to_byte_array();
+
+    let refund_keypair = SecretKey::from_slice(&ephemeral_tweak)
+        .ok()?
+        .keypair(secp256k1::SECP256K1);
+
+    (refund_keypair.public_key() == contract.refund_pk).then_some(refund_keypair)
+}
+
 impl LightningClientModule {
     pub async fn fetch_payment_info(
         &self,
@@ -521,6 +671,33 @@ impl LightningClientModule {
         }))
     }
 
+    /// Computes the fees and the amount we would receive for an invoice of
+    /// `invoice_amount` created by the given gateway without creating it.
+    pub async fn preview_receive(
+        &self,
+        invoice_amount: Amount,
+        gateway_api: SafeUrl,
+    ) -> Result<ReceivePreview, PreviewReceiveError> {
+        let payment_info = self
+            .fetch_payment_info(gateway_api)
+            .await
+            .map_err(PreviewReceiveError::GatewayError)?
+            .ok_or(PreviewReceiveError::UnknownFederation)?;
+
+        let contract_amount = payment_info.receive_fee.subtract_fee(invoice_amount.msats);
+        let federation_fee = self.cfg.fee_consensus.input;
+
+        Ok(ReceivePreview {
+            invoice_amount,
+            gateway_fee: invoice_amount - contract_amount,
+            federation_fee,
+            receive_amount: contract_amount.saturating_sub(federation_fee),
+            min_invoice_amount: payment_info
+                .receive_fee
+                .min_amount_before_fee(federation_fee.msats + 1),
+        })
+    }
+
     pub async fn receive(
         &self,
         gateway_api: SafeUrl,
@@ -563,6 +740,37 @@ impl LightningClientModule {
         Ok((invoice, operation_id))
     }
 
+    /// Creates an amountless invoice, the payer chooses the amount but we
+    /// receive `contract_amount` regardless of how much the gateway's fee
+    /// leaves of it
+    pub async fn receive_amountless(
+        &self,
+        gateway_api: SafeUrl,
+        contract_amount: Amount,
+        expiry_time: u32,
+        description: Bolt11InvoiceDescription,
+        payment_fee_limit: PaymentFee,
+    ) -> Result<(Bolt11Invoice, OperationId), FetchInvoiceError> {
+        let (contract, .., invoice) = self
+            .create_contract_and_fetch_invoice_with_amount(
+                self.keypair.public_key(),
+                gateway_api,
+                Amount::ZERO,
+                Some(contract_amount),
+                expiry_time,
+                description,
+                payment_fee_limit,
+            )
+            .await?;
+
+        let operation_id = self
+            .receive_external_contract(contract)
+            .await
+            .expect("The contract has been generated with our public key");
+
+        Ok((invoice, operation_id))
+    }
+
     pub async fn create_contract_and_fetch_invoice(
         &self,
         recipient_static_pk: PublicKey,
@@ -588,6 +796,31 @@ impl LightningClientModule {
         expiry_time: u32,
         description: Bolt11InvoiceDescription,
         payment_fee_limit: PaymentFee,
+    ) -> Result<(IncomingContract, [u8; 32], Bolt11Invoice), FetchInvoiceError> {
+        self.create_contract_and_fetch_invoice_with_amount(
+            recipient_static_pk,
+            gateway_api,
+            invoice_amount,
+            None,
+            expiry_time,
+            description,
+            payment_fee_limit,
+        )
+        .await
+    }
+
+    /// Creates the contract and fetches its invoice, the contract amount is
+    /// derived from the invoice amount unless the invoice is amountless
+    #[allow(clippy::too_many_arguments)]
+    async fn create_contract_and_fetch_invoice_with_amount(
+        &self,
+        recipient_static_pk: PublicKey,
+        gateway_api: SafeUrl,
+        invoice_amount: Amount,
+        contract_amount: Option<Amount>,
+        expiry_time: u32,
+        description: Bolt11InvoiceDescription,
+        payment_fee_limit: PaymentFee,
     ) -> Result<(IncomingContract, [u8; 32], Bolt11Invoice), FetchInvoiceError> {
         let (ephemeral_tweak, ephemeral_pk) = generate_ephemeral_tweak(recipient_static_pk);
 
@@ -611,7 +844,8 @@ impl LightningClientModule {
             ));
         }
 
-        let contract_amount = payment_info.receive_fee.subtract_fee(invoice_amount.msats);
+        let contract_amount = contract_amount
+            .unwrap_or_else(|| payment_info.receive_fee.subtract_fee(invoice_amount.msats));
 
         let expiration = duration_since_epoch()
             .as_secs()
@@ -653,7 +887,9 @@ impl LightningClientModule {
             return Err(FetchInvoiceError::InvalidInvoicePaymentHash);
         }
 
-        if invoice.amount_milli_satoshis() != Some(invoice_amount.msats) {
+        let expected_amount = Some(invoice_amount.msats).filter(|msats| *msats != 0);
+
+        if invoice.amount_milli_satoshis() != expected_amount {
             return Err(FetchInvoiceError::InvalidInvoiceAmount);
         }
 
@@ -687,6 +923,44 @@ impl LightningClientModule {
             .await
     }
 
+    /// Claims the incoming contracts of invoices the gateway created for us as
+    /// a public receiver registered under `token`. Contracts that are not
+    /// locked to our key are skipped, claiming is idempotent so this can be
+    /// called periodically. Returns the receive operations of the contracts.
+    pub async fn claim_public_invoice_contracts(
+        &self,
+        gateway_api: SafeUrl,
+        token: String,
+    ) -> Result<Vec<OperationId>, GatewayError> {
+        let contracts = reqwest::Client::new()
+            .post(
+                gateway_api
+                    .join("public_invoice_contracts")
+                    .expect("'public_invoice_contracts' contains no invalid characters for a URL")
+                    .as_str(),
+            )
+            .json(&PublicInvoiceContractsPayload { token })
+            .send()
+            .await
+            .map_err(|e| GatewayError::Unreachable(e.to_string()))?
+            .json::<Vec<IncomingContract>>()
+            .await
+            .map_err(|e| GatewayError::InvalidJsonResponse(e.to_string()))?;
+
+        let mut operation_ids = Vec::new();
+        for contract in contracts {
+            if !contract.verify() {
+                continue;
+            }
+
+            if let Some(operation_id) = self.receive_external_contract(contract).await {
+                operation_ids.push(operation_id);
+            }
+        }
+
+        Ok(operation_ids)
+    }
+
     pub async fn receive_external_contract(
         &self,
         contract: IncomingContract,
@@ -724,37 +998,7 @@ impl LightningClientModule {
         &self,
         contract: &IncomingContract,
     ) -> Option<(KeyPair, AggregateDecryptionKey)> {
-        let ephemeral_tweak = ecdh::shared_secret_point(
-            &contract.commitment.ephemeral_pk,
-            &self.keypair.secret_key(),
-        )
-        .consensus_hash::<sha256::Hash>()
-        .to_byte_array();
-
-        let encryption_seed = ephemeral_tweak
-            .consensus_hash::<sha256::Hash>()
-            .to_byte_array();
-
-        let claim_keypair = self
-            .keypair
-            .secret_key()
-            .mul_tweak(&Scalar::from_be_bytes(ephemeral_tweak).expect("Within curve order"))
-            .expect("Tweak is valid")
-            .keypair(secp256k1::SECP256K1);
-
-        if claim_keypair.public_key() != contract.commitment.claim_pk {
-            return None; // The claim key is not derived from our pk
-        }
-
-        let agg_decryption_key = derive_agg_decryption_key(&self.cfg.tpe_agg_pk, &encryption_seed);
-
-        if !contract.verify_agg_decryption_key(&self.cfg.tpe_agg_pk, &agg_decryption_key) {
-            return None; // The decryption key is not derived from our pk
-        }
-
-        contract.decrypt_preimage(&agg_decryption_key)?;
-
-        Some((claim_keypair, agg_decryption_key))
+        recover_contract_keys(&self.keypair, &self.cfg.tpe_agg_pk, contract)
     }
 
     pub async fn subscribe_receive(
@@ -841,11 +1085,20 @@ pub enum FetchInvoiceError {
     InvalidInvoiceAmount,
 }
 
+#[derive(Error, Debug, Clone, Eq, PartialEq)]
+pub enum PreviewReceiveError {
+    #[error("Gateway error: {0}")]
+    GatewayError(GatewayError),
+    #[error("The gateway does not support our federation")]
+    UnknownFederation,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub enum LightningClientStateMachines {
     Send(SendStateMachine),
     Receive(ReceiveStateMachine),
+    Refund(RefundStateMachine),
 }
 
 impl IntoDynInstance for LightningClientStateMachines {
@@ -877,6 +1130,12 @@ impl State for LightningClientStateMachines {
                     LightningClientStateMachines::Receive
                 )
             }
+            LightningClientStateMachines::Refund(state) => {
+                sm_enum_variant_translation!(
+                    state.transitions(context, global_context),
+                    LightningClientStateMachines::Refund
+                )
+            }
         }
     }
 
@@ -884,6 +1143,7 @@ impl State for LightningClientStateMachines {
         match self {
             LightningClientStateMachines::Send(state) => state.operation_id(),
             LightningClientStateMachines::Receive(state) => state.operation_id(),
+            LightningClientStateMachines::Refund(state) => state.operation_id(),
         }
     }
 }
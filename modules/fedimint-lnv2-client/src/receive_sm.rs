@@ -117,7 +117,7 @@ impl ReceiveStateMachine {
                 old_state.common.agg_decryption_key,
             )),
             amount: old_state.common.contract.commitment.amount,
-            keys: vec![old_state.common.claim_keypair],
+            keys: vec![old_state.common.claim_keypair.into()],
             state_machines: Arc::new(|_, _| vec![]),
         };
 
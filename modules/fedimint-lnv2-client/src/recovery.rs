@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use fedimint_client::module::init::recovery::{RecoveryFromHistory, RecoveryFromHistoryCommon};
+use fedimint_client::module::init::ClientModuleRecoverArgs;
+use fedimint_client::module::recovery::NoModuleBackup;
+use fedimint_client::module::{ClientContext, ClientDbTxContext};
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::CommonModuleInit;
+use fedimint_core::{apply, async_trait_maybe_send, OutPoint};
+use fedimint_lnv2_common::contracts::{IncomingContract, OutgoingContract};
+use fedimint_lnv2_common::{
+    ContractId, LightningCommonInit, LightningInput, LightningInputV0, LightningOutput,
+    LightningOutputV0,
+};
+use secp256k1::KeyPair;
+use tpe::AggregatePublicKey;
+use tracing::info;
+
+use crate::db::{RecoveryFinalizedKey, RecoveryStateKey};
+use crate::receive_sm::{ReceiveSMCommon, ReceiveSMState, ReceiveStateMachine};
+use crate::refund_sm::{RefundSMCommon, RefundSMState, RefundStateMachine};
+use crate::{
+    recover_contract_keys, recover_refund_keypair, LightningClientInit, LightningClientModule,
+    LightningClientStateMachines, LightningOperationMeta,
+};
+
+/// Contracts paying to our static key that were funded but not spent yet in
+/// the sessions scanned so far, stored in the database at every checkpoint
+#[derive(Debug, Clone, Default, Eq, PartialEq, Encodable, Decodable)]
+pub struct LightningRecoveryState {
+    incoming: BTreeMap<ContractId, IncomingContract>,
+    outgoing: BTreeMap<ContractId, OutgoingContract>,
+}
+
+/// Recovers the incoming contracts we can still claim and the outgoing
+/// contracts we may have to refund from the federation history, which is
+/// possible since the keys of both are derived from our static key and the
+/// ephemeral key in the contract
+#[derive(Debug, Clone)]
+pub struct LightningRecovery {
+    state: LightningRecoveryState,
+    keypair: KeyPair,
+    tpe_agg_pk: AggregatePublicKey,
+}
+
+impl LightningRecovery {
+    fn from_state(
+        state: LightningRecoveryState,
+        args: &ClientModuleRecoverArgs<LightningClientInit>,
+    ) -> Self {
+        Self {
+            state,
+            keypair: args
+                .module_root_secret()
+                .clone()
+                .to_secp_key(secp256k1::SECP256K1),
+            tpe_agg_pk: args.cfg().tpe_agg_pk,
+        }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl RecoveryFromHistory for LightningRecovery {
+    type Init = LightningClientInit;
+
+    async fn new(
+        args: &ClientModuleRecoverArgs<Self::Init>,
+        _snapshot: Option<&NoModuleBackup>,
+    ) -> anyhow::Result<(Self, u64)> {
+        Ok((Self::from_state(LightningRecoveryState::default(), args), 0))
+    }
+
+    async fn load_dbtx(
+        dbtx: &mut DatabaseTransaction<'_>,
+        args: &ClientModuleRecoverArgs<Self::Init>,
+    ) -> Option<(Self, RecoveryFromHistoryCommon)> {
+        let (state, common) = dbtx.get_value(&RecoveryStateKey).await?;
+
+        Some((Self::from_state(state, args), common))
+    }
+
+    async fn store_dbtx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        common: &RecoveryFromHistoryCommon,
+    ) {
+        dbtx.insert_entry(&RecoveryStateKey, &(self.state.clone(), common.clone()))
+            .await;
+    }
+
+    async fn delete_dbtx(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        dbtx.remove_entry(&RecoveryStateKey).await;
+    }
+
+    async fn load_finalized(dbtx: &mut DatabaseTransaction<'_>) -> Option<bool> {
+        dbtx.get_value(&RecoveryFinalizedKey).await
+    }
+
+    async fn store_finalized(dbtx: &mut DatabaseTransaction<'_>, state: bool) {
+        dbtx.insert_entry(&RecoveryFinalizedKey, &state).await;
+    }
+
+    async fn handle_input(
+        &mut self,
+        _client_ctx: &ClientContext<LightningClientModule>,
+        _idx: usize,
+        input: &LightningInput,
+    ) -> anyhow::Result<()> {
+        match input.maybe_v0_ref() {
+            Some(LightningInputV0::Incoming(contract_id, ..)) => {
+                self.state.incoming.remove(contract_id);
+            }
+            Some(LightningInputV0::Outgoing(contract_id, ..)) => {
+                self.state.outgoing.remove(contract_id);
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    async fn handle_output(
+        &mut self,
+        _client_ctx: &ClientContext<LightningClientModule>,
+        _out_point: OutPoint,
+        output: &LightningOutput,
+    ) -> anyhow::Result<()> {
+        match output.maybe_v0_ref() {
+            Some(LightningOutputV0::Incoming(contract)) => {
+                if recover_contract_keys(&self.keypair, &self.tpe_agg_pk, contract).is_some() {
+                    self.state
+                        .incoming
+                        .insert(contract.contract_id(), contract.clone());
+                }
+            }
+            Some(LightningOutputV0::Outgoing(contract)) => {
+                if recover_refund_keypair(&self.keypair, contract).is_some() {
+                    self.state
+                        .outgoing
+                        .insert(contract.contract_id(), contract.clone());
+                }
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    async fn finalize_dbtx(
+        &self,
+        dbtx: &mut ClientDbTxContext<'_, '_, LightningClientModule>,
+    ) -> anyhow::Result<()> {
+        info!(
+            incoming = self.state.incoming.len(),
+            outgoing = self.state.outgoing.len(),
+            "Finalizing lightning recovery"
+        );
+
+        for contract in self.state.incoming.values() {
+            let (claim_keypair, agg_decryption_key) =
+                recover_contract_keys(&self.keypair, &self.tpe_agg_pk, contract)
+                    .expect("Only contracts with recoverable keys are tracked");
+
+            let operation_id = OperationId::from_encodable(&contract.clone());
+
+            dbtx.add_operation_log_entry(
+                operation_id,
+                LightningCommonInit::KIND.as_str(),
+                LightningOperationMeta::Receive {
+                    contract: contract.clone(),
+                },
+            )
+            .await;
+
+            let client_ctx = dbtx.client_ctx().clone();
+            dbtx.add_state_machines(
+                client_ctx
+                    .map_dyn(vec![LightningClientStateMachines::Receive(
+                        ReceiveStateMachine {
+                            common: ReceiveSMCommon {
+                                operation_id,
+                                contract: contract.clone(),
+                                claim_keypair,
+                                agg_decryption_key,
+                            },
+                            state: ReceiveSMState::Pending,
+                        },
+                    )])
+                    .collect(),
+            )
+            .await?;
+        }
+
+        for contract in self.state.outgoing.values() {
+            let refund_keypair = recover_refund_keypair(&self.keypair, contract)
+                .expect("Only contracts with recoverable keys are tracked");
+
+            let operation_id = OperationId::from_encodable(&contract.clone());
+
+            dbtx.add_operation_log_entry(
+                operation_id,
+                LightningCommonInit::KIND.as_str(),
+                LightningOperationMeta::Refund {
+                    contract: contract.clone(),
+                },
+            )
+            .await;
+
+            let client_ctx = dbtx.client_ctx().clone();
+            dbtx.add_state_machines(
+                client_ctx
+                    .map_dyn(vec![LightningClientStateMachines::Refund(
+                        RefundStateMachine {
+                            common: RefundSMCommon {
+                                operation_id,
+                                contract: contract.clone(),
+                                refund_keypair,
+                            },
+                            state: RefundSMState::Pending,
+                        },
+                    )])
+                    .collect(),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
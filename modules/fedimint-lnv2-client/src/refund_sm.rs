@@ -0,0 +1,108 @@
+use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::OutPoint;
+use fedimint_lnv2_common::contracts::OutgoingContract;
+use fedimint_lnv2_common::LightningClientContext;
+use secp256k1::KeyPair;
+
+use crate::send_sm::{claim_expired_contract_refund, SendStateMachine};
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct RefundStateMachine {
+    pub common: RefundSMCommon,
+    pub state: RefundSMState,
+}
+
+impl RefundStateMachine {
+    pub fn update(&self, state: RefundSMState) -> Self {
+        Self {
+            common: self.common.clone(),
+            state,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct RefundSMCommon {
+    pub operation_id: OperationId,
+    pub contract: OutgoingContract,
+    pub refund_keypair: KeyPair,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub enum RefundSMState {
+    Pending,
+    Success([u8; 32]),
+    Refunding(Vec<OutPoint>),
+}
+
+#[cfg_attr(doc, aquamarine::aquamarine)]
+/// State machine that refunds an outgoing contract found during recovery once
+/// it expired. Unlike [`SendStateMachine`] it does not know the invoice, so it
+/// cannot ask the gateway to pay it, but the gateway may still claim the
+/// contract with the preimage until then.
+///
+/// ```mermaid
+/// graph LR
+/// classDef virtual fill:#fff,stroke-dasharray: 5 5
+///
+///     Pending -- await_preimage returns preimage --> Success
+///     Pending -- await_preimage expires --> Refunding
+/// ```
+impl State for RefundStateMachine {
+    type ModuleContext = LightningClientContext;
+
+    fn transitions(
+        &self,
+        _context: &Self::ModuleContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<Self>> {
+        let gc = global_context.clone();
+
+        match &self.state {
+            RefundSMState::Pending => {
+                vec![StateTransition::new(
+                    SendStateMachine::await_preimage(gc.clone(), self.common.contract.clone()),
+                    move |dbtx, preimage, old_state| {
+                        Box::pin(Self::transition_preimage(
+                            dbtx,
+                            gc.clone(),
+                            old_state,
+                            preimage,
+                        ))
+                    },
+                )]
+            }
+            RefundSMState::Success(..) | RefundSMState::Refunding(..) => vec![],
+        }
+    }
+
+    fn operation_id(&self) -> OperationId {
+        self.common.operation_id
+    }
+}
+
+impl RefundStateMachine {
+    async fn transition_preimage(
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        global_context: DynGlobalClientContext,
+        old_state: RefundStateMachine,
+        preimage: Option<[u8; 32]>,
+    ) -> RefundStateMachine {
+        if let Some(preimage) = preimage {
+            return old_state.update(RefundSMState::Success(preimage));
+        }
+
+        let outpoints = claim_expired_contract_refund(
+            dbtx,
+            global_context,
+            &old_state.common.contract,
+            old_state.common.refund_keypair,
+        )
+        .await;
+
+        old_state.update(RefundSMState::Refunding(outpoints))
+    }
+}
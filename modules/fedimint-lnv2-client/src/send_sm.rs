@@ -250,7 +250,7 @@ impl SendStateMachine {
                         OutgoingWitness::Cancel(signature),
                     )),
                     amount: old_state.common.contract.amount,
-                    keys: vec![old_state.common.refund_keypair],
+                    keys: vec![old_state.common.refund_keypair.into()],
                     // The input of the refund tx is managed by this state machine
                     state_machines: Arc::new(|_, _| vec![]),
                 };
@@ -262,7 +262,7 @@ impl SendStateMachine {
         }
     }
 
-    async fn await_preimage(
+    pub(crate) async fn await_preimage(
         global_context: DynGlobalClientContext,
         contract: OutgoingContract,
     ) -> Option<[u8; 32]> {
@@ -297,19 +297,35 @@ impl SendStateMachine {
             return old_state.update(SendSMState::Success(preimage));
         }
 
-        let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
-            input: LightningInput::V0(LightningInputV0::Outgoing(
-                old_state.common.contract.contract_id(),
-                OutgoingWitness::Refund,
-            )),
-            amount: old_state.common.contract.amount,
-            keys: vec![old_state.common.refund_keypair],
-            // The input of the refund tx is managed by this state machine
-            state_machines: Arc::new(|_, _| vec![]),
-        };
-
-        let outpoints = global_context.claim_input(dbtx, client_input).await.1;
+        let outpoints = claim_expired_contract_refund(
+            dbtx,
+            global_context,
+            &old_state.common.contract,
+            old_state.common.refund_keypair,
+        )
+        .await;
 
         old_state.update(SendSMState::Refunding(outpoints))
     }
 }
+
+/// Claims the funds of an expired outgoing contract back with the refund key
+pub(crate) async fn claim_expired_contract_refund(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    global_context: DynGlobalClientContext,
+    contract: &OutgoingContract,
+    refund_keypair: KeyPair,
+) -> Vec<OutPoint> {
+    let client_input = ClientInput::<LightningInput, LightningClientStateMachines> {
+        input: LightningInput::V0(LightningInputV0::Outgoing(
+            contract.contract_id(),
+            OutgoingWitness::Refund,
+        )),
+        amount: contract.amount,
+        keys: vec![refund_keypair.into()],
+        // The input of the refund tx is managed by the calling state machine
+        state_machines: Arc::new(|_, _| vec![]),
+    };
+
+    global_context.claim_input(dbtx, client_input).await.1
+}
@@ -33,7 +33,20 @@ use crate::contracts::{IncomingContract, OutgoingContract};
 pub const KIND: ModuleKind = ModuleKind::from_static_str("lnv2");
 pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(0, 0);
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    Encodable,
+    Decodable,
+)]
 pub struct ContractId(pub sha256::Hash);
 
 extensible_associated_module_type!(
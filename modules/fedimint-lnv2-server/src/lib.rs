@@ -11,11 +11,13 @@ use std::time::Duration;
 use anyhow::ensure;
 use bls12_381::{G1Projective, Scalar};
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
+use fedimint_core::admin_client::AdminRole;
 use fedimint_core::config::{
     ConfigGenModuleParams, DkgResult, ServerModuleConfig, ServerModuleConsensusConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::DbKeyPrefixDecl;
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
 };
@@ -48,7 +50,7 @@ use fedimint_lnv2_common::{
     LightningOutputOutcome, LightningOutputV0, OutgoingWitness, MODULE_CONSENSUS_VERSION,
 };
 use fedimint_server::config::distributedgen::{evaluate_polynomial_g1, PeerHandleOps};
-use fedimint_server::net::api::check_auth;
+use fedimint_server::net::api::check_role;
 use futures::StreamExt;
 use group::ff::Field;
 use group::Curve;
@@ -70,6 +72,18 @@ impl ModuleInit for LightningInit {
     type Common = LightningCommonInit;
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        vec![
+            DbKeyPrefixDecl::of::<BlockCountVoteKey>(DbKeyPrefix::BlockCountVote),
+            DbKeyPrefixDecl::of::<UnixTimeVoteKey>(DbKeyPrefix::UnixTimeVote),
+            DbKeyPrefixDecl::of::<IncomingContractKey>(DbKeyPrefix::IncomingContract),
+            DbKeyPrefixDecl::of::<OutgoingContractKey>(DbKeyPrefix::OutgoingContract),
+            DbKeyPrefixDecl::of::<OutputOutcomeKey>(DbKeyPrefix::OutputOutcome),
+            DbKeyPrefixDecl::of::<PreimageKey>(DbKeyPrefix::Preimage),
+            DbKeyPrefixDecl::of::<GatewayKey>(DbKeyPrefix::Gateway),
+        ]
+    }
+
     async fn dump_database(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -583,7 +597,7 @@ impl ServerModule for Lightning {
                 ADD_GATEWAY_ENDPOINT,
                 ApiVersion::new(0, 0),
                 async |_module: &Lightning, context, gateway: SafeUrl| -> bool {
-                    check_auth(context)?;
+                    check_role(context, AdminRole::ConfigChange)?;
 
                     let db = context.db();
 
@@ -594,7 +608,7 @@ impl ServerModule for Lightning {
                 REMOVE_GATEWAY_ENDPOINT_V2,
                 ApiVersion::new(0, 0),
                 async |_module: &Lightning, context, gateway: SafeUrl| -> bool {
-                    check_auth(context)?;
+                    check_role(context, AdminRole::ConfigChange)?;
 
                     let db = context.db();
 
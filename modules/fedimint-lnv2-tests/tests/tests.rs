@@ -9,7 +9,8 @@ use fedimint_dummy_client::{DummyClientInit, DummyClientModule};
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyInit;
 use fedimint_lnv2_client::{
-    LightningClientInit, LightningClientModule, ReceiveState, SendPaymentError, SendState,
+    Bolt11InvoiceDescription, LightningClientInit, LightningClientModule, PaymentFee, ReceiveState,
+    SendPaymentError, SendState,
 };
 use fedimint_lnv2_common::config::LightningGenParams;
 use fedimint_lnv2_server::LightningInit;
@@ -17,6 +18,7 @@ use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::Fixtures;
 use fedimint_testing::gateway::{GatewayTest, DEFAULT_GATEWAY_PASSWORD};
 use fedimint_testing::ln::FakeLightningTest;
+use ln_gateway::rpc::{CreatePublicInvoicePayload, RegisterPublicReceiverPayload};
 
 fn fixtures() -> Fixtures {
     let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
@@ -242,6 +244,167 @@ async fn can_make_self_payment_exactly_once() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn preview_receive_accounts_for_all_fees() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let gateway_test = gateway(&fixtures, &fed).await;
+    let gateway_api = gateway_test.gateway.versioned_api.clone();
+
+    let client = fed.new_client().await;
+    let lightning = client.get_first_module::<LightningClientModule>();
+
+    let preview = lightning
+        .preview_receive(Amount::from_sats(100), gateway_api.clone())
+        .await?;
+
+    assert_eq!(preview.invoice_amount, Amount::from_sats(100));
+    assert_eq!(
+        preview.invoice_amount,
+        preview.gateway_fee + preview.federation_fee + preview.receive_amount
+    );
+
+    let min_invoice_amount = preview
+        .min_invoice_amount
+        .expect("The gateway's fee does not consume any amount");
+
+    let preview = lightning
+        .preview_receive(min_invoice_amount, gateway_api.clone())
+        .await?;
+
+    assert_eq!(preview.receive_amount, Amount::from_msats(1));
+
+    let preview = lightning
+        .preview_receive(min_invoice_amount - Amount::from_msats(1), gateway_api)
+        .await?;
+
+    assert_eq!(preview.receive_amount, Amount::ZERO);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn can_create_amountless_invoice_with_custom_expiry() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let gateway_test = gateway(&fixtures, &fed).await;
+    let gateway_api = gateway_test.gateway.versioned_api.clone();
+
+    let client = fed.new_client().await;
+    let lightning = client.get_first_module::<LightningClientModule>();
+
+    let (invoice, _) = lightning
+        .receive_amountless(
+            gateway_api.clone(),
+            Amount::from_sats(100),
+            600,
+            Bolt11InvoiceDescription::Direct(String::new()),
+            PaymentFee::one_percent(),
+        )
+        .await?;
+
+    assert_eq!(invoice.amount_milli_satoshis(), None);
+    assert!(invoice.expiry_time().as_secs() <= 600);
+
+    assert!(lightning
+        .receive_internal(
+            gateway_api,
+            Amount::from_sats(100),
+            0,
+            Bolt11InvoiceDescription::Direct(String::new()),
+            PaymentFee::one_percent(),
+        )
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn public_receiver_claims_paid_invoice() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let gateway_test = gateway(&fixtures, &fed).await;
+    let gateway_api = gateway_test.gateway.versioned_api.clone();
+
+    print_liquidity(&gateway_test, fed.id()).await;
+
+    let client_receive = fed.new_client().await;
+
+    let token = gateway_test
+        .gateway
+        .handle_register_public_receiver_msg(RegisterPublicReceiverPayload {
+            federation_id: fed.id(),
+            recipient_static_pk: client_receive
+                .get_first_module::<LightningClientModule>()
+                .keypair
+                .public_key(),
+            max_invoice_amount: Amount::from_sats(1000),
+            max_invoices_per_hour: 10,
+        })
+        .await?;
+
+    let invoice = gateway_test
+        .gateway
+        .handle_create_public_invoice_msg(CreatePublicInvoicePayload {
+            token: token.clone(),
+            amount: Amount::from_sats(100),
+            description: None,
+        })
+        .await?;
+
+    let client_send = fed.new_client().await;
+
+    let (print_op, print_outpoint) = client_send
+        .get_first_module::<DummyClientModule>()
+        .print_money(Amount::from_sats(10000))
+        .await?;
+
+    client_send
+        .await_primary_module_output(print_op, print_outpoint)
+        .await?;
+
+    let send_op = client_send
+        .get_first_module::<LightningClientModule>()
+        .send(gateway_api.clone(), invoice)
+        .await?;
+
+    let receive_ops = client_receive
+        .get_first_module::<LightningClientModule>()
+        .claim_public_invoice_contracts(gateway_api.clone(), token.clone())
+        .await?;
+
+    assert_eq!(receive_ops.len(), 1);
+
+    verify_payment_success(
+        client_send.clone(),
+        send_op,
+        client_receive.clone(),
+        receive_ops[0],
+    )
+    .await?;
+
+    assert!(client_receive.get_balance().await > Amount::ZERO);
+
+    // Claiming again is idempotent
+    assert_eq!(
+        client_receive
+            .get_first_module::<LightningClientModule>()
+            .claim_public_invoice_contracts(gateway_api.clone(), token)
+            .await?,
+        receive_ops
+    );
+
+    // Contracts are only served to the receiver's token
+    assert!(client_receive
+        .get_first_module::<LightningClientModule>()
+        .claim_public_invoice_contracts(gateway_api, "unknown".to_string())
+        .await
+        .is_err());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn direct_swap() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -3,7 +3,7 @@
 
 pub mod db;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::future;
 
 use async_trait::async_trait;
@@ -16,6 +16,7 @@ use fedimint_core::config::{
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::DbKeyPrefixDecl;
 use fedimint_core::db::{
     Committable, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
     NonCommittable, ServerMigrationFn,
@@ -63,6 +64,14 @@ impl ModuleInit for MetaInit {
     type Common = MetaCommonInit;
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        vec![
+            DbKeyPrefixDecl::of::<MetaDesiredKey>(DbKeyPrefix::Desired),
+            DbKeyPrefixDecl::of::<MetaConsensusKey>(DbKeyPrefix::Consensus),
+            DbKeyPrefixDecl::of::<MetaSubmissionsKey>(DbKeyPrefix::Submissions),
+        ]
+    }
+
     /// Dumps all database items for debugging
     async fn dump_database(
         &self,
@@ -270,6 +279,11 @@ impl ServerModule for Meta {
     type Common = MetaModuleTypes;
     type Init = MetaInit;
 
+    fn local_db_prefixes(&self) -> BTreeSet<u8> {
+        // the values we want to vote for
+        BTreeSet::from([DbKeyPrefix::Desired as u8])
+    }
+
     /// Check the difference between what's desired vs submitted and consensus.
     ///
     /// Returns:
@@ -0,0 +1,12 @@
+use fedimint_api_client::module_api_ext;
+use fedimint_mint_common::endpoint_constants::CHECK_SPENT_NONCES_ENDPOINT;
+use fedimint_mint_common::Nonce;
+
+module_api_ext! {
+    pub trait MintFederationApi {
+        /// Returns for every nonce whether the note it belongs to has already
+        /// been spent
+        async fn check_spent_nonces(nonces: Vec<Nonce>) -> Vec<bool>
+            = CHECK_SPENT_NONCES_ENDPOINT;
+    }
+}
@@ -1,7 +1,9 @@
 use std::cmp::max;
 use std::collections::BTreeMap;
-use std::fmt;
+use std::{cmp, fmt, ops};
 
+use anyhow::ensure;
+use fedimint_api_client::api::DynModuleApi;
 use fedimint_client::module::init::recovery::{RecoveryFromHistory, RecoveryFromHistoryCommon};
 use fedimint_client::module::init::ClientModuleRecoverArgs;
 use fedimint_client::module::{ClientContext, ClientDbTxContext};
@@ -13,23 +15,52 @@ use fedimint_core::{
 };
 use fedimint_derive_secret::DerivableSecret;
 use fedimint_logging::{LOG_CLIENT_MODULE_MINT, LOG_CLIENT_RECOVERY_MINT};
-use fedimint_mint_common::{MintInput, MintOutput, Nonce};
+use fedimint_mint_common::{MintInput, MintOutput, Nonce, MAX_SPENT_NONCES_BATCH_SIZE};
+use futures::StreamExt as _;
 use serde::{Deserialize, Serialize};
 use tbs::{AggregatePublicKey, BlindedMessage, PublicKeyShare};
 use threshold_crypto::G1Affine;
 use tracing::{debug, info, trace, warn};
 
 use super::EcashBackup;
+use crate::api::MintFederationApi;
 use crate::backup::EcashBackupV0;
-use crate::client_db::{NextECashNoteIndexKey, NoteKey, RecoveryFinalizedKey, RecoveryStateKey};
+use crate::client_db::{
+    NextECashNoteIndexKey, NoteKey, RecoveryFinalizedKey, RecoveryNotesKey, RecoveryTierStateKey,
+    RecoveryTierStateKeyPrefix,
+};
 use crate::output::{
     MintOutputCommon, MintOutputStateMachine, MintOutputStatesCreated, NoteIssuanceRequest,
 };
-use crate::{MintClientInit, MintClientModule, MintClientStateMachines, NoteIndex, SpendableNote};
+use crate::{
+    MintClientInit, MintClientModule, MintClientStateMachines, NoteIndex, SpendableNote,
+    CHECK_SPENT_NONCES_API_VERSION,
+};
+
+/// Gap limit every amount tier starts with
+pub const INITIAL_GAP_LIMIT: u64 = 30;
+
+/// Gap limit an amount tier can grow to, see
+/// [`MintRecoveryTierState::observe_note_idx`]
+pub const MAX_GAP_LIMIT: u64 = 1000;
+
+/// Nonces of every amount tier are derived in chunks of this size, which are
+/// processed in parallel on platforms supporting threads
+const NONCE_DERIVATION_CHUNK_SIZE: u64 = 32;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct MintRecovery {
-    state: MintRecoveryState,
+    state: MintRecoveryNotes,
+    /// Progress of every amount tier, stored in a separate database record per
+    /// tier
+    tiers: BTreeMap<Amount, MintRecoveryTierState>,
+    /// Blinded messages of the notes we might have issued next in each tier,
+    /// derived from `tiers` and not persisted
+    ///
+    /// Note: since looking up nonces is going to be the most common operation
+    /// the pool is kept shared (so only one lookup is enough), and
+    /// replenishment is done each time a note is consumed.
+    pending_nonces: BTreeMap<CompressedBlindedMessage, (NoteIssuanceRequest, NoteIndex, Amount)>,
     secret: DerivableSecret,
 }
 
@@ -59,17 +90,39 @@ impl RecoveryFromHistory for MintRecovery {
             (EcashBackupV0::new_empty(), 0)
         };
 
+        // A scan limited to the most recent sessions doesn't see the notes issued
+        // before, so it has to learn from the federation which of them were spent
+        let learn_spent_note_indices = args.scan_depth().is_some();
+        ensure!(
+            !learn_spent_note_indices
+                || args
+                    .module_api_version()
+                    .supports(CHECK_SPENT_NONCES_API_VERSION),
+            "The federation does not support recovering with a limited scan depth"
+        );
+
+        let mut tiers = BTreeMap::new();
+        for amount in config.tbs_pks.tiers() {
+            let backup_note_idx = snapshot
+                .next_note_idx
+                .get(*amount)
+                .copied()
+                .unwrap_or_default();
+
+            let tier = if learn_spent_note_indices {
+                MintRecoveryTierState::with_used_note_idx(
+                    backup_note_idx,
+                    first_note_idx_after_spent_notes(args.module_api(), &secret, *amount).await?,
+                )
+            } else {
+                MintRecoveryTierState::new(backup_note_idx)
+            };
+
+            tiers.insert(*amount, tier);
+        }
+
         Ok((
-            MintRecovery {
-                state: MintRecoveryState::from_backup(
-                    snapshot,
-                    30,
-                    config.tbs_pks.clone(),
-                    config.peer_tbs_pks.clone(),
-                    &secret,
-                ),
-                secret,
-            },
+            MintRecovery::from_parts(MintRecoveryNotes::from_backup(snapshot), tiers, secret),
             starting_session,
         ))
     }
@@ -78,17 +131,19 @@ impl RecoveryFromHistory for MintRecovery {
         dbtx: &mut DatabaseTransaction<'_>,
         args: &ClientModuleRecoverArgs<Self::Init>,
     ) -> Option<(Self, RecoveryFromHistoryCommon)> {
-        dbtx.get_value(&RecoveryStateKey)
+        let (state, common) = dbtx.get_value(&RecoveryNotesKey).await?;
+
+        let tiers = dbtx
+            .find_by_prefix(&RecoveryTierStateKeyPrefix)
             .await
-            .map(|(state, common)| {
-                (
-                    MintRecovery {
-                        state,
-                        secret: args.module_root_secret().clone(),
-                    },
-                    common,
-                )
-            })
+            .map(|(key, tier)| (key.0, tier))
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        Some((
+            MintRecovery::from_parts(state, tiers, args.module_root_secret().clone()),
+            common,
+        ))
     }
 
     async fn store_dbtx(
@@ -96,12 +151,18 @@ impl RecoveryFromHistory for MintRecovery {
         dbtx: &mut DatabaseTransaction<'_>,
         common: &RecoveryFromHistoryCommon,
     ) {
-        dbtx.insert_entry(&RecoveryStateKey, &(self.state.clone(), common.clone()))
+        dbtx.insert_entry(&RecoveryNotesKey, &(self.state.clone(), common.clone()))
             .await;
+
+        for (amount, tier) in &self.tiers {
+            dbtx.insert_entry(&RecoveryTierStateKey(*amount), tier)
+                .await;
+        }
     }
 
     async fn delete_dbtx(&self, dbtx: &mut DatabaseTransaction<'_>) {
-        dbtx.remove_entry(&RecoveryStateKey).await;
+        dbtx.remove_entry(&RecoveryNotesKey).await;
+        dbtx.remove_by_prefix(&RecoveryTierStateKeyPrefix).await;
     }
 
     async fn load_finalized(dbtx: &mut DatabaseTransaction<'_>) -> Option<bool> {
@@ -118,7 +179,7 @@ impl RecoveryFromHistory for MintRecovery {
         _idx: usize,
         input: &MintInput,
     ) -> anyhow::Result<()> {
-        self.state.handle_input(input);
+        self.handle_input(input);
         Ok(())
     }
 
@@ -128,7 +189,7 @@ impl RecoveryFromHistory for MintRecovery {
         out_point: OutPoint,
         output: &MintOutput,
     ) -> anyhow::Result<()> {
-        self.state.handle_output(out_point, output, &self.secret);
+        self.handle_output(out_point, output);
         Ok(())
     }
 
@@ -137,7 +198,7 @@ impl RecoveryFromHistory for MintRecovery {
         &self,
         dbtx: &mut ClientDbTxContext<'_, '_, MintClientModule>,
     ) -> anyhow::Result<()> {
-        let finalized = self.state.clone().finalize();
+        let finalized = self.clone().finalize();
 
         let restored_amount = finalized
             .unconfirmed_notes
@@ -214,6 +275,407 @@ impl RecoveryFromHistory for MintRecovery {
     }
 }
 
+/// Index following the last note of `amount` whose nonce the federation has
+/// seen spent, found by checking the nonces of consecutive note indices until
+/// [`MAX_GAP_LIMIT`] of them in a row are unspent
+///
+/// Issuing notes at an index that was already spent creates notes that can
+/// never be spent, so a recovery that doesn't scan the sessions the notes were
+/// issued in has to start after this index.
+async fn first_note_idx_after_spent_notes(
+    module_api: &DynModuleApi,
+    secret: &DerivableSecret,
+    amount: Amount,
+) -> anyhow::Result<NoteIndex> {
+    let mut next_idx = 0;
+    let mut first_unspent_idx = 0;
+
+    while next_idx < first_unspent_idx + MAX_GAP_LIMIT {
+        let batch = next_idx..next_idx + MAX_SPENT_NONCES_BATCH_SIZE as u64;
+
+        let nonces = batch
+            .clone()
+            .map(|idx| {
+                NoteIssuanceRequest::new(
+                    secp256k1_zkp::SECP256K1,
+                    &MintClientModule::new_note_secret_static(
+                        secret,
+                        amount,
+                        NoteIndex::from_u64(idx),
+                    ),
+                )
+                .0
+                .nonce()
+            })
+            .collect::<Vec<_>>();
+
+        let spent = module_api.check_spent_nonces(nonces).await?;
+
+        ensure!(
+            spent.len() == MAX_SPENT_NONCES_BATCH_SIZE,
+            "Federation returned an invalid number of results"
+        );
+
+        for (idx, is_spent) in batch.clone().zip(spent) {
+            if is_spent {
+                first_unspent_idx = idx + 1;
+            }
+        }
+
+        next_idx = batch.end;
+    }
+
+    debug!(
+        target: LOG_CLIENT_RECOVERY_MINT,
+        %amount,
+        first_unspent_idx,
+        "Found note index following the spent notes"
+    );
+
+    Ok(NoteIndex::from_u64(first_unspent_idx))
+}
+
+impl fmt::Debug for MintRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "MintRecovery(pending_outputs: {}, pending_nonces: {})",
+            self.state.pending_outputs.len(),
+            self.pending_nonces.len()
+        ))
+    }
+}
+
+impl MintRecovery {
+    /// Restores the in-memory pool of pending nonces from the progress of
+    /// every amount tier
+    fn from_parts(
+        state: MintRecoveryNotes,
+        tiers: BTreeMap<Amount, MintRecoveryTierState>,
+        secret: DerivableSecret,
+    ) -> Self {
+        let ranges = tiers
+            .iter()
+            .map(|(amount, tier)| (*amount, tier.pool_range()))
+            .collect::<Vec<_>>();
+
+        debug!(
+            target: LOG_CLIENT_RECOVERY_MINT,
+            count = ranges.iter().map(|(_, range)| range.end - range.start).sum::<u64>(),
+            "Deriving pending nonces"
+        );
+
+        let pending_nonces = derive_pending_nonces(&secret, ranges)
+            .into_iter()
+            // Notes we found already are not pending anymore
+            .filter(|(_, (issuance_request, _, _))| {
+                let nonce = issuance_request.nonce();
+                !state.pending_outputs.contains_key(&nonce)
+                    && !state.spendable_notes.contains_key(&nonce)
+            })
+            .collect();
+
+        Self {
+            state,
+            tiers,
+            pending_nonces,
+            secret,
+        }
+    }
+
+    fn handle_input(&mut self, input: &MintInput) {
+        match input {
+            MintInput::V0(input) => {
+                // We attempt to delete any nonce we see as spent, simple
+                self.state.pending_outputs.remove(&input.note.nonce);
+                self.state.spendable_notes.remove(&input.note.nonce);
+            }
+            MintInput::Default { variant, .. } => {
+                trace!("Ignoring future mint input variant {variant}");
+            }
+        }
+    }
+
+    fn handle_output(&mut self, out_point: OutPoint, output: &MintOutput) {
+        let output = match output {
+            MintOutput::V0(output) => output,
+            MintOutput::Default { variant, .. } => {
+                trace!("Ignoring future mint output variant {variant}");
+                return;
+            }
+        };
+
+        // There is nothing preventing other users from creating valid
+        // transactions mining notes to our own blind nonce, possibly
+        // even racing with us. Including amount in blind nonce
+        // derivation helps us avoid accidentally using a nonce mined
+        // for as smaller amount, but it doesn't eliminate completely
+        // the possibility that we might use a note mined in a different
+        // transaction, that our original one.
+        // While it is harmless to us, as such duplicated blind nonces are
+        // effective as good the as the original ones (same amount), it
+        // breaks the assumption that all our blind nonces in an our
+        // output need to be in the pending pool. It forces us to be
+        // greedy no matter what and take what we can, and just report
+        // anything suspicious.
+
+        let Some((issuance_request, note_idx, pending_amount)) = self
+            .pending_nonces
+            .get(&output.blind_nonce.0.into())
+            .copied()
+        else {
+            return;
+        };
+
+        if pending_amount != output.amount {
+            warn!(
+                output = ?out_point,
+                blind_nonce = ?output.blind_nonce.0,
+                expected_amount = %pending_amount,
+                found_amount = %output.amount,
+                "Transaction output contains blind nonce that looks like ours but is of the wrong amount. Ignoring."
+            );
+        }
+
+        // the moment we see our blind nonce in the epoch history, correctly or
+        // incorrectly used, we know that we must have used
+        // already
+        self.observe_nonce_idx_being_used(pending_amount, note_idx);
+
+        if pending_amount == output.amount {
+            self.pending_nonces.remove(&output.blind_nonce.0.into());
+
+            self.state.pending_outputs.insert(
+                issuance_request.nonce(),
+                (out_point, output.amount, issuance_request),
+            );
+        }
+    }
+
+    /// React to a valid pending nonce being tracked being used in the epoch
+    /// history
+    ///
+    /// Updates the progress of the amount tier, then moves its window of
+    /// pending nonces along, so that it covers the gap limit on both sides of
+    /// the last used note index.
+    fn observe_nonce_idx_being_used(&mut self, amount: Amount, note_idx: NoteIndex) {
+        let tier = self
+            .tiers
+            .get_mut(&amount)
+            .expect("Pending nonces are only derived for known tiers");
+
+        let old_range = tier.pool_range();
+        tier.observe_note_idx(note_idx);
+        let new_range = tier.pool_range();
+
+        if new_range.start > old_range.start {
+            self.pending_nonces.retain(|_, (_, idx, nonce_amount)| {
+                *nonce_amount != amount || new_range.start <= idx.as_u64()
+            });
+        }
+
+        let to_derive = new_range.start.max(old_range.end)..new_range.end;
+        if !to_derive.is_empty() {
+            self.pending_nonces.extend(derive_pending_nonces(
+                &self.secret,
+                vec![(amount, to_derive)],
+            ));
+        }
+    }
+
+    fn finalize(self) -> EcashRecoveryFinalState {
+        EcashRecoveryFinalState {
+            spendable_notes: self.state.spendable_notes.into_values().collect(),
+            unconfirmed_notes: self.state.pending_outputs.into_values().collect(),
+            // next note idx is the last one detected as used + 1
+            next_note_idx: self
+                .tiers
+                .into_iter()
+                .map(|(amount, tier)| (amount, tier.last_mined_nonce_idx.next()))
+                .collect(),
+        }
+    }
+}
+
+/// Derives the issuance requests of the notes with the given indices, keyed by
+/// their blinded message.
+///
+/// The derivation dominates the cost of a recovery for wallets with long
+/// histories, so it is split into chunks that are processed in parallel where
+/// threads are available.
+fn derive_pending_nonces(
+    secret: &DerivableSecret,
+    ranges: Vec<(Amount, ops::Range<u64>)>,
+) -> Vec<(
+    CompressedBlindedMessage,
+    (NoteIssuanceRequest, NoteIndex, Amount),
+)> {
+    let chunks = ranges
+        .into_iter()
+        .flat_map(|(amount, range)| {
+            range
+                .clone()
+                .step_by(NONCE_DERIVATION_CHUNK_SIZE as usize)
+                .map(move |start| {
+                    (
+                        amount,
+                        start..cmp::min(start + NONCE_DERIVATION_CHUNK_SIZE, range.end),
+                    )
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let derive_chunk = |(amount, range): &(Amount, ops::Range<u64>)| {
+        range
+            .clone()
+            .map(|idx| {
+                let note_idx = NoteIndex::from_u64(idx);
+                let (note_issuance_request, blind_nonce) = NoteIssuanceRequest::new(
+                    secp256k1_zkp::SECP256K1,
+                    &MintClientModule::new_note_secret_static(secret, *amount, note_idx),
+                );
+                (
+                    blind_nonce.0.into(),
+                    (note_issuance_request, note_idx, *amount),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    #[cfg(not(target_family = "wasm"))]
+    if 1 < chunks.len() {
+        let threads = std::thread::available_parallelism()
+            .map_or(1, std::num::NonZeroUsize::get)
+            .min(chunks.len());
+
+        return std::thread::scope(|scope| {
+            chunks
+                .chunks(chunks.len().div_ceil(threads))
+                .map(|thread_chunks| {
+                    scope.spawn(|| {
+                        thread_chunks
+                            .iter()
+                            .flat_map(derive_chunk)
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Nonce derivation panicked"))
+                .collect()
+        });
+    }
+
+    chunks.iter().flat_map(derive_chunk).collect()
+}
+
+/// Notes found during the recovery, stored in the database together with
+/// [`RecoveryFromHistoryCommon`] at every checkpoint
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable, Serialize, Deserialize)]
+pub struct MintRecoveryNotes {
+    spendable_notes: BTreeMap<Nonce, (Amount, SpendableNote)>,
+    /// Nonces that we track that are currently spendable.
+    pending_outputs: BTreeMap<Nonce, (OutPoint, Amount, NoteIssuanceRequest)>,
+}
+
+impl MintRecoveryNotes {
+    fn from_backup(backup: EcashBackupV0) -> Self {
+        Self {
+            spendable_notes: backup
+                .spendable_notes
+                .into_iter_items()
+                .map(|(amount, note)| (note.nonce(), (amount, note)))
+                .collect(),
+            pending_outputs: backup
+                .pending_notes
+                .into_iter()
+                .map(|(outpoint, amount, issuance_request)| {
+                    (
+                        issuance_request.nonce(),
+                        (outpoint, amount, issuance_request),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Recovery progress of a single amount tier, stored in the database at every
+/// checkpoint
+///
+/// The pool of pending nonces of the tier covers the note indices within the
+/// gap limit around the last note index that was seen issued, see
+/// [`Self::pool_range`].
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Decodable, Encodable, Serialize, Deserialize)]
+pub struct MintRecoveryTierState {
+    /// Index the pool of pending nonces started at, the notes before it were
+    /// known from the backup already
+    pub first_note_idx: NoteIndex,
+    /// `LastECashNoteIndex` but tracked in flight. Basically max index of any
+    /// note that got a partial sig from the federation (initialled from the
+    /// backup value). TODO: One could imagine a case where the note was
+    /// issued but not get any partial sigs yet. Very unlikely in real life
+    /// scenario, but worth considering.
+    pub last_mined_nonce_idx: NoteIndex,
+    /// The number of nonces we look-ahead when looking for mints
+    pub gap_limit: u64,
+}
+
+impl MintRecoveryTierState {
+    pub fn new(next_note_idx: NoteIndex) -> Self {
+        Self {
+            first_note_idx: next_note_idx,
+            last_mined_nonce_idx: next_note_idx,
+            gap_limit: INITIAL_GAP_LIMIT,
+        }
+    }
+
+    /// Like [`Self::new`], but for a tier whose notes up to `used_note_idx`
+    /// may have been issued in sessions that are not scanned, so the pool of
+    /// pending nonces is centered around it and no note is issued at an index
+    /// up to it again
+    pub fn with_used_note_idx(next_note_idx: NoteIndex, used_note_idx: NoteIndex) -> Self {
+        Self {
+            first_note_idx: next_note_idx,
+            last_mined_nonce_idx: max(next_note_idx, used_note_idx),
+            gap_limit: INITIAL_GAP_LIMIT,
+        }
+    }
+
+    /// Indices of the notes that are in the pool of pending nonces. Notes more
+    /// than the gap limit behind the last used one are unlikely to be issued
+    /// anymore and are dropped from the pool.
+    pub fn pool_range(&self) -> ops::Range<u64> {
+        let last_mined = self.last_mined_nonce_idx.as_u64();
+
+        cmp::max(
+            self.first_note_idx.as_u64(),
+            last_mined.saturating_sub(self.gap_limit),
+        )..last_mined + self.gap_limit
+    }
+
+    /// Records that the note with `note_idx` was issued
+    ///
+    /// Wallets that skip many note indices, e.g. because their transactions
+    /// got rejected, risk that the next issued note is beyond the gap limit.
+    /// If a note is found in the far half of the window, the gap limit of the
+    /// tier is doubled.
+    pub fn observe_note_idx(&mut self, note_idx: NoteIndex) {
+        let last_mined = self.last_mined_nonce_idx.as_u64();
+
+        if last_mined + self.gap_limit / 2 < note_idx.as_u64() {
+            self.gap_limit = cmp::min(self.gap_limit * 2, MAX_GAP_LIMIT);
+            debug!(
+                target: LOG_CLIENT_RECOVERY_MINT,
+                %note_idx,
+                gap_limit = self.gap_limit,
+                "Increased gap limit of amount tier"
+            );
+        }
+
+        self.last_mined_nonce_idx = max(self.last_mined_nonce_idx, note_idx);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct EcashRecoveryFinalState {
     pub spendable_notes: TieredMulti<SpendableNote>,
@@ -244,13 +706,9 @@ impl From<CompressedBlindedMessage> for BlindedMessage {
     }
 }
 
-/// The state machine used for fast-forwarding backup from point when it was
-/// taken to the present time by following epoch history items from the time the
-/// snapshot was taken.
-///
-/// The caller is responsible for creating it, and then feeding it in order all
-/// valid consensus items from the epoch history between time taken (or even
-/// somewhat before it) and present time.
+/// Recovery state of database version 0, which stored the pool of pending
+/// nonces and the progress of all tiers in a single record. Only kept to
+/// migrate recoveries that were in progress, see [`Self::into_v1`].
 #[derive(Clone, Eq, PartialEq, Decodable, Encodable, Serialize, Deserialize)]
 pub struct MintRecoveryState {
     spendable_notes: BTreeMap<Nonce, (Amount, SpendableNote)>,
@@ -363,127 +821,97 @@ impl MintRecoveryState {
         note_idx_ref.advance();
     }
 
-    pub fn handle_input(&mut self, input: &MintInput) {
-        match input {
-            MintInput::V0(input) => {
-                // We attempt to delete any nonce we see as spent, simple
-                self.pending_outputs.remove(&input.note.nonce);
-                self.spendable_notes.remove(&input.note.nonce);
-            }
-            MintInput::Default { variant, .. } => {
-                trace!("Ignoring future mint input variant {variant}");
-            }
-        }
+    /// Converts the state into the records of database version 1
+    pub fn into_v1(self) -> (MintRecoveryNotes, BTreeMap<Amount, MintRecoveryTierState>) {
+        let tiers = self
+            .tbs_pks
+            .tiers()
+            .map(|amount| {
+                let last_mined_nonce_idx = self
+                    .last_mined_nonce_idx
+                    .get(*amount)
+                    .copied()
+                    .unwrap_or_default();
+                let first_note_idx = self
+                    .pending_nonces
+                    .values()
+                    .filter(|(_, _, nonce_amount)| nonce_amount == amount)
+                    .map(|(_, note_idx, _)| *note_idx)
+                    .min()
+                    .map_or(last_mined_nonce_idx, |idx| {
+                        cmp::min(idx, last_mined_nonce_idx)
+                    });
+
+                (
+                    *amount,
+                    MintRecoveryTierState {
+                        first_note_idx,
+                        last_mined_nonce_idx,
+                        gap_limit: self.gap_limit,
+                    },
+                )
+            })
+            .collect();
+
+        (
+            MintRecoveryNotes {
+                spendable_notes: self.spendable_notes,
+                pending_outputs: self.pending_outputs,
+            },
+            tiers,
+        )
     }
+}
 
-    pub fn handle_output(
-        &mut self,
-        out_point: OutPoint,
-        output: &MintOutput,
-        secret: &DerivableSecret,
-    ) {
-        let output = match output {
-            MintOutput::V0(output) => output,
-            MintOutput::Default { variant, .. } => {
-                trace!("Ignoring future mint output variant {variant}");
-                return;
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use super::{MintRecoveryTierState, INITIAL_GAP_LIMIT, MAX_GAP_LIMIT};
+    use crate::NoteIndex;
 
-        // There is nothing preventing other users from creating valid
-        // transactions mining notes to our own blind nonce, possibly
-        // even racing with us. Including amount in blind nonce
-        // derivation helps us avoid accidentally using a nonce mined
-        // for as smaller amount, but it doesn't eliminate completely
-        // the possibility that we might use a note mined in a different
-        // transaction, that our original one.
-        // While it is harmless to us, as such duplicated blind nonces are
-        // effective as good the as the original ones (same amount), it
-        // breaks the assumption that all our blind nonces in an our
-        // output need to be in the pending pool. It forces us to be
-        // greedy no matter what and take what we can, and just report
-        // anything suspicious.
+    #[test]
+    fn tier_window_follows_issued_notes() {
+        let mut tier = MintRecoveryTierState::new(NoteIndex::from_u64(5));
+        assert_eq!(tier.pool_range(), 5..5 + INITIAL_GAP_LIMIT);
 
-        if let Some((issuance_request, note_idx, pending_amount)) = self
-            .pending_nonces
-            .get(&output.blind_nonce.0.into())
-            .copied()
-        {
-            // the moment we see our blind nonce in the epoch history, correctly or
-            // incorrectly used, we know that we must have used
-            // already
-            self.observe_nonce_idx_being_used(pending_amount, note_idx, secret);
-
-            if pending_amount == output.amount {
-                assert!(self
-                    .pending_nonces
-                    .remove(&output.blind_nonce.0.into())
-                    .is_some());
+        tier.observe_note_idx(NoteIndex::from_u64(10));
+        assert_eq!(tier.gap_limit, INITIAL_GAP_LIMIT);
+        assert_eq!(tier.pool_range(), 5..10 + INITIAL_GAP_LIMIT);
 
-                self.pending_outputs.insert(
-                    issuance_request.nonce(),
-                    (out_point, output.amount, issuance_request),
-                );
-            } else {
-                // put it back, incorrect amount
-                self.pending_nonces.insert(
-                    output.blind_nonce.0.into(),
-                    (issuance_request, note_idx, pending_amount),
-                );
+        tier.observe_note_idx(NoteIndex::from_u64(100));
+        assert_eq!(tier.pool_range(), 70..130);
 
-                warn!(
-                    output = ?out_point,
-                    blind_nonce = ?output.blind_nonce.0,
-                    expected_amount = %pending_amount,
-                    found_amount = %output.amount,
-                    "Transaction output contains blind nonce that looks like ours but is of the wrong amount. Ignoring."
-                );
-            }
-        }
+        // notes issued out of order don't move the window back
+        tier.observe_note_idx(NoteIndex::from_u64(90));
+        assert_eq!(tier.pool_range(), 70..130);
     }
 
-    /// React to a valid pending nonce being tracked being used in the epoch
-    /// history
-    ///
-    /// (Possibly) increment the `self.last_mined_nonce_idx`, then replenish the
-    /// pending pool to always maintain at least `gap_limit` of pending
-    /// nonces in each amount tier.
-    fn observe_nonce_idx_being_used(
-        &mut self,
-        amount: Amount,
-        note_idx: NoteIndex,
-        secret: &DerivableSecret,
-    ) {
-        *self.last_mined_nonce_idx.entry(amount).or_default() = max(
-            self.last_mined_nonce_idx
-                .get(amount)
-                .copied()
-                .unwrap_or_default(),
-            note_idx,
-        );
+    #[test]
+    fn tier_gap_limit_grows_on_large_jumps() {
+        let mut tier = MintRecoveryTierState::new(NoteIndex::from_u64(0));
 
-        while self.next_pending_note_idx.get_mut_or_default(amount).0
-            < self.gap_limit
-                + self
-                    .last_mined_nonce_idx
-                    .get(amount)
-                    .expect("must be there already")
-                    .0
-        {
-            self.add_next_pending_nonce_in_pending_pool(amount, secret);
+        tier.observe_note_idx(NoteIndex::from_u64(INITIAL_GAP_LIMIT - 1));
+        assert_eq!(tier.gap_limit, INITIAL_GAP_LIMIT * 2);
+
+        for _ in 0..10 {
+            let next = tier.last_mined_nonce_idx.as_u64() + tier.gap_limit - 1;
+            tier.observe_note_idx(NoteIndex::from_u64(next));
         }
+        assert_eq!(tier.gap_limit, MAX_GAP_LIMIT);
     }
 
-    pub fn finalize(self) -> EcashRecoveryFinalState {
-        EcashRecoveryFinalState {
-            spendable_notes: self.spendable_notes.into_values().collect(),
-            unconfirmed_notes: self.pending_outputs.into_values().collect(),
-            // next note idx is the last one detected as used + 1
-            next_note_idx: self
-                .last_mined_nonce_idx
-                .iter()
-                .map(|(amount, value)| (amount, value.next()))
-                .collect(),
-        }
+    #[test]
+    fn tier_with_used_notes_never_reissues_them() {
+        let tier = MintRecoveryTierState::with_used_note_idx(
+            NoteIndex::from_u64(0),
+            NoteIndex::from_u64(100),
+        );
+        assert_eq!(tier.pool_range(), 70..130);
+
+        // the backup is ahead of the used notes
+        let tier = MintRecoveryTierState::with_used_note_idx(
+            NoteIndex::from_u64(20),
+            NoteIndex::from_u64(10),
+        );
+        assert_eq!(tier.pool_range(), 20..20 + INITIAL_GAP_LIMIT);
     }
 }
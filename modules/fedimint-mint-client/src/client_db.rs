@@ -1,12 +1,13 @@
 use fedimint_client::module::init::recovery::RecoveryFromHistoryCommon;
 use fedimint_core::core::OperationId;
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
 use fedimint_mint_common::Nonce;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
-use crate::backup::recovery::MintRecoveryState;
+use crate::backup::recovery::{MintRecoveryNotes, MintRecoveryState, MintRecoveryTierState};
 use crate::SpendableNoteUndecoded;
 
 #[repr(u8)]
@@ -17,6 +18,8 @@ pub enum DbKeyPrefix {
     CancelledOOBSpend = 0x2b,
     RecoveryState = 0x2c,
     RecoveryFinalized = 0x2d,
+    RecoveryNotes = 0x2e,
+    RecoveryTierState = 0x2f,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -57,6 +60,8 @@ impl_db_lookup!(
     query_prefix = NextECashNoteIndexKeyPrefix
 );
 
+/// Recovery state of database version 0, migrated to [`RecoveryNotesKey`] and
+/// [`RecoveryTierStateKey`]
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
 pub struct RecoveryStateKey;
 
@@ -68,6 +73,33 @@ impl_db_record!(
     value = (MintRecoveryState, RecoveryFromHistoryCommon),
     db_prefix = DbKeyPrefix::RecoveryState,
 );
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct RecoveryNotesKey;
+
+impl_db_record!(
+    key = RecoveryNotesKey,
+    value = (MintRecoveryNotes, RecoveryFromHistoryCommon),
+    db_prefix = DbKeyPrefix::RecoveryNotes,
+);
+
+/// Recovery progress of an amount tier, stored separately so a checkpoint
+/// doesn't have to persist the pool of pending nonces
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct RecoveryTierStateKey(pub Amount);
+
+#[derive(Debug, Clone, Encodable, Decodable)]
+pub struct RecoveryTierStateKeyPrefix;
+
+impl_db_record!(
+    key = RecoveryTierStateKey,
+    value = MintRecoveryTierState,
+    db_prefix = DbKeyPrefix::RecoveryTierState,
+);
+impl_db_lookup!(
+    key = RecoveryTierStateKey,
+    query_prefix = RecoveryTierStateKeyPrefix
+);
+
 #[derive(Debug, Clone, Encodable, Decodable, Serialize)]
 pub struct RecoveryFinalizedKey;
 
@@ -96,3 +128,21 @@ impl_db_lookup!(
     key = CancelledOOBSpendKey,
     query_prefix = CancelledOOBSpendKeyPrefix,
 );
+
+/// Migrates a recovery that is in progress to per-tier checkpoints
+pub async fn migrate_to_v1(
+    dbtx: &mut DatabaseTransaction<'_>,
+) -> anyhow::Result<Option<(Vec<(Vec<u8>, OperationId)>, Vec<(Vec<u8>, OperationId)>)>> {
+    if let Some((state, common)) = dbtx.remove_entry(&RecoveryStateKey).await {
+        let (notes, tiers) = state.into_v1();
+
+        dbtx.insert_new_entry(&RecoveryNotesKey, &(notes, common))
+            .await;
+        for (amount, tier) in tiers {
+            dbtx.insert_new_entry(&RecoveryTierStateKey(amount), &tier)
+                .await;
+        }
+    }
+
+    Ok(None)
+}
@@ -11,6 +11,8 @@
 #![allow(clippy::return_self_not_must_use)]
 #![allow(clippy::unused_async)]
 
+/// Federation API calls specific to the mint module
+pub mod api;
 // Backup and restore logic
 pub mod backup;
 /// Database keys used throughout the mint client module
@@ -22,13 +24,13 @@ mod oob;
 /// State machines for mint outputs
 pub mod output;
 
-use std::cmp::{min, Ordering};
+use std::cmp::{max, min, Ordering};
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::io::Read;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{ffi, fmt};
 
 use anyhow::{anyhow, bail, ensure, Context as _};
@@ -37,17 +39,20 @@ use backup::recovery::MintRecovery;
 use base64::Engine as _;
 use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine as BitcoinHashEngine};
 use client_db::{DbKeyPrefix, NoteKeyPrefix};
+use fedimint_api_client::api::DynModuleApi;
+use fedimint_client::db::ClientMigrationFn;
+use fedimint_client::error::ClientError;
 use fedimint_client::module::init::{
     ClientModuleInit, ClientModuleInitArgs, ClientModuleRecoverArgs,
 };
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::oplog::{OperationLogEntry, OperationMeta, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
 use fedimint_client::{sm_enum_variant_translation, DynGlobalClientContext};
 use fedimint_core::config::{FederationId, FederationIdPrefix};
-use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, OperationId};
+use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, ModuleKind, OperationId};
 use fedimint_core::db::{
     AutocommitError, Database, DatabaseTransaction, DatabaseVersion,
     IDatabaseTransactionOpsCoreTyped,
@@ -56,7 +61,7 @@ use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::invite_code::InviteCode;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::{
-    ApiVersion, CommonModuleInit, ModuleCommon, ModuleInit, MultiApiVersion,
+    ApiVersion, CommonModuleInit, FeeSchedule, ModuleCommon, ModuleInit, MultiApiVersion,
 };
 use fedimint_core::util::{BoxFuture, BoxStream, NextOrPending, SafeUrl};
 use fedimint_core::{
@@ -68,7 +73,7 @@ use fedimint_logging::LOG_CLIENT_MODULE_MINT;
 pub use fedimint_mint_common as common;
 use fedimint_mint_common::config::MintClientConfig;
 pub use fedimint_mint_common::*;
-use futures::{pin_mut, StreamExt};
+use futures::{pin_mut, FutureExt, StreamExt};
 use hex::ToHex;
 use secp256k1_zkp::{All, KeyPair, Secp256k1};
 use serde::{Deserialize, Serialize};
@@ -77,6 +82,7 @@ use tbs::AggregatePublicKey;
 use thiserror::Error;
 use tracing::{debug, warn};
 
+use crate::api::MintFederationApi;
 use crate::backup::EcashBackup;
 use crate::client_db::{
     CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, NextECashNoteIndexKey,
@@ -95,6 +101,10 @@ const MINT_E_CASH_TYPE_CHILD_ID: ChildId = ChildId(0);
 
 pub const LOG_TARGET: &str = "client::module::mint";
 
+/// Module API version that introduced the endpoint for checking whether notes
+/// have been spent
+const CHECK_SPENT_NONCES_API_VERSION: ApiVersion = ApiVersion::new(0, 1);
+
 /// An encapsulation of [`FederationId`] and e-cash notes in the form of
 /// [`TieredMulti<SpendableNote>`] for the purpose of spending e-cash
 /// out-of-band. Also used for validating and reissuing such out-of-band notes.
@@ -118,6 +128,9 @@ enum OOBNotesData {
         federation_id: FederationId,
     },
     ApiSecret(String),
+    /// Unix timestamp (seconds) after which the sender will try to reclaim the
+    /// notes, see [`MintClientModule::export_notes`]
+    Expiry(u64),
     #[encodable_default]
     Default {
         variant: u64,
@@ -207,6 +220,9 @@ impl OOBNotes {
                     );
                 }
                 OOBNotesData::ApiSecret(_) => { /* already covered inside `Invite` */ }
+                OOBNotesData::Expiry(expiry) => {
+                    notes_map.insert("expiry".to_string(), serde_json::to_value(expiry)?);
+                }
                 OOBNotesData::Default { variant, bytes } => {
                     notes_map.insert(
                         format!("default_{variant}"),
@@ -240,6 +256,35 @@ impl OOBNotes {
         })
     }
 
+    /// Attach an expiry time after which the sender will try to reclaim the
+    /// notes. Older clients ignore it.
+    pub fn with_expiry(mut self, expiry: SystemTime) -> Self {
+        let expiry_secs = expiry
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.0
+            .retain(|data| !matches!(data, OOBNotesData::Expiry(_)));
+        self.0.push(OOBNotesData::Expiry(expiry_secs));
+        self
+    }
+
+    /// Time after which the sender will try to reclaim the notes, if known
+    pub fn expiry(&self) -> Option<SystemTime> {
+        self.0.iter().find_map(|data| match data {
+            OOBNotesData::Expiry(expiry_secs) => {
+                Some(UNIX_EPOCH + Duration::from_secs(*expiry_secs))
+            }
+            _ => None,
+        })
+    }
+
+    /// Returns `true` if the notes carry an expiry that lies in the past
+    pub fn is_expired(&self) -> bool {
+        self.expiry()
+            .is_some_and(|expiry| expiry <= fedimint_core::time::now())
+    }
+
     fn api_secret(&self) -> Option<String> {
         self.0.iter().find_map(|data| {
             let OOBNotesData::ApiSecret(api_secret) = data else {
@@ -424,6 +469,11 @@ pub struct MintOperationMeta {
     pub extra_meta: serde_json::Value,
 }
 
+impl OperationMeta for MintOperationMeta {
+    const KIND: ModuleKind = MintCommonInit::KIND;
+    const VERSION: u16 = 0;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum MintOperationMetaVariant {
@@ -444,6 +494,9 @@ pub enum MintOperationMetaVariant {
     SpendOOB {
         requested_amount: Amount,
         oob_notes: OOBNotes,
+        // Introduced with `export_notes`:
+        #[serde(default)]
+        expires_at: Option<SystemTime>,
     },
 }
 
@@ -452,7 +505,7 @@ pub struct MintClientInit;
 
 impl ModuleInit for MintClientInit {
     type Common = MintCommonInit;
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
 
     async fn dump_database(
         &self,
@@ -497,7 +550,10 @@ impl ModuleInit for MintClientInit {
                         "CancelledOOBSpendKey"
                     );
                 }
-                DbKeyPrefix::RecoveryState | DbKeyPrefix::RecoveryFinalized => {}
+                DbKeyPrefix::RecoveryState
+                | DbKeyPrefix::RecoveryFinalized
+                | DbKeyPrefix::RecoveryNotes
+                | DbKeyPrefix::RecoveryTierState => {}
             }
         }
 
@@ -522,6 +578,7 @@ impl ClientModuleInit for MintClientInit {
             secp: Secp256k1::new(),
             notifier: args.notifier().clone(),
             client_ctx: args.context(),
+            module_api: args.module_api().clone(),
         })
     }
 
@@ -532,6 +589,14 @@ impl ClientModuleInit for MintClientInit {
     ) -> anyhow::Result<()> {
         args.recover_from_history::<MintRecovery>(snapshot).await
     }
+
+    fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ClientMigrationFn> {
+        let mut migrations: BTreeMap<DatabaseVersion, ClientMigrationFn> = BTreeMap::new();
+        migrations.insert(DatabaseVersion(0), move |dbtx, _, _| {
+            client_db::migrate_to_v1(dbtx).boxed()
+        });
+        migrations
+    }
 }
 
 /// The `MintClientModule` is responsible for handling e-cash minting
@@ -567,6 +632,7 @@ pub struct MintClientModule {
     secp: Secp256k1<All>,
     notifier: ModuleNotifier<MintClientStateMachines>,
     client_ctx: ClientContext<Self>,
+    module_api: DynModuleApi,
 }
 
 // TODO: wrap in Arc
@@ -701,6 +767,7 @@ impl ClientModule for MintClientModule {
         operation_id: OperationId,
         input: Amount,
         output: Amount,
+        fee_schedule: FeeSchedule,
     ) -> anyhow::Result<(
         Vec<ClientInput<MintInput, MintClientStateMachines>>,
         Vec<ClientOutput<MintOutput, MintClientStateMachines>>,
@@ -720,18 +787,69 @@ impl ClientModule for MintClientModule {
 
         inputs.append(&mut consolidated_inputs);
 
-        let selected_input_amount = inputs.iter().map(|input| input.amount).sum();
+        // The selected notes may not cover the schedule fees of their own inputs,
+        // in which case we select more until they do or we run out of notes
+        let (selected_input_amount, selected_input_fee) = loop {
+            let selected_input_amount: Amount = inputs.iter().map(|input| input.amount).sum();
 
-        let selected_input_fee = self
-            .cfg
-            .fee_consensus
-            .note_spend_abs
-            .mul_u64(inputs.len() as u64);
+            let selected_input_fee = self
+                .cfg
+                .fee_consensus
+                .note_spend_abs
+                .mul_u64(inputs.len() as u64)
+                + inputs
+                    .iter()
+                    .map(|input| {
+                        fee_schedule.input_fee(
+                            self.client_ctx
+                                .make_dyn_input(input.input.clone())
+                                .consensus_encode_to_vec()
+                                .len(),
+                        )
+                    })
+                    .sum::<Amount>();
+
+            let missing_input =
+                (output + selected_input_fee).saturating_sub(input + selected_input_amount);
+
+            if missing_input == Amount::ZERO {
+                break (selected_input_amount, selected_input_fee);
+            }
+
+            inputs.append(
+                &mut self
+                    .create_sufficient_input(dbtx, operation_id, missing_input)
+                    .await?,
+            );
+        };
 
         let missing_output = (input + selected_input_amount) - (output + selected_input_fee);
 
+        let change = if fee_schedule == FeeSchedule::default() {
+            missing_output
+        } else {
+            let tier_counts = self.get_notes_tier_counts(dbtx).await;
+            let blind_nonce = BlindNonce(tbs::blind_message(
+                tbs::Message::from_bytes(&[]),
+                tbs::BlindingKey::random(),
+            ));
+
+            change_after_schedule_fees(
+                missing_output,
+                |amount| represent_amount(amount, &tier_counts, &self.cfg.tbs_pks, 2),
+                |amount| {
+                    fee_schedule.output_fee(
+                        self.client_ctx
+                            .make_dyn_output(MintOutput::new_v0(amount, blind_nonce))
+                            .consensus_encode_to_vec()
+                            .len(),
+                    )
+                },
+            )
+        };
+
         let outputs = self
-            .create_exact_output(dbtx, operation_id, 2, missing_output)
+            .create_exact_output(dbtx, operation_id, 2, change)
             .await;
 
         Ok((inputs, outputs))
@@ -786,6 +904,29 @@ impl ClientModule for MintClientModule {
         }
         Ok(())
     }
+
+    /// Cancels an out-of-band spend whose notes weren't claimed yet, see
+    /// [`MintClientModule::cancel_export`], and waits for the refund so the
+    /// cancellation is recorded as the outcome of the operation.
+    async fn cancel(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        self.cancel_export(operation_id).await?;
+
+        let final_state = self
+            .subscribe_spend_notes(operation_id)
+            .await?
+            .into_stream()
+            .collect::<Vec<_>>()
+            .await
+            .pop();
+
+        match final_state {
+            Some(SpendOOBState::UserCanceledSuccess | SpendOOBState::Refunded) => Ok(()),
+            Some(SpendOOBState::UserCanceledFailure | SpendOOBState::Success) => {
+                bail!("Notes were claimed by the recipient before the spend could be cancelled")
+            }
+            state => bail!("Unexpected final state of cancelled spend: {state:?}"),
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug, Clone)]
@@ -794,6 +935,8 @@ pub enum ReissueExternalNotesError {
     WrongFederationId,
     #[error("We already reissued these notes")]
     AlreadyReissued,
+    #[error("The notes have expired and may have been reclaimed by the sender")]
+    Expired,
 }
 
 impl MintClientModule {
@@ -962,7 +1105,10 @@ impl MintClientModule {
 
                 match state.state {
                     MintOutputStates::Succeeded(succeeded) => Some(Ok(succeeded.amount)),
-                    MintOutputStates::Aborted(_) => Some(Err(anyhow!("Transaction was rejected"))),
+                    MintOutputStates::Aborted(_) => Some(Err(ClientError::TxRejected {
+                        reason: "Transaction was rejected".to_string(),
+                    }
+                    .into())),
                     MintOutputStates::Failed(failed) => Some(Err(anyhow!(
                         "Failed to finalize transaction: {}",
                         failed.error
@@ -1090,7 +1236,7 @@ impl MintClientModule {
 
             inputs.push(ClientInput {
                 input: MintInput::new_v0(amount, note),
-                keys: vec![spendable_note.spend_key],
+                keys: vec![spendable_note.spend_key.into()],
                 amount,
                 state_machines: sm_gen,
             });
@@ -1338,7 +1484,7 @@ impl MintClientModule {
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<ReissueExternalNotesState>> {
         let operation = self.mint_operation(operation_id).await?;
-        let (txid, out_points) = match operation.meta::<MintOperationMeta>().variant {
+        let (txid, out_points) = match operation.meta_typed::<MintOperationMeta>()?.variant {
             MintOperationMetaVariant::Reissuance {
                 legacy_out_point,
                 txid,
@@ -1430,6 +1576,55 @@ impl MintClientModule {
         include_invite: bool,
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, OOBNotes)> {
+        self.spend_notes_inner(
+            notes_selector,
+            requested_amount,
+            try_cancel_after,
+            include_invite,
+            false,
+            extra_meta,
+        )
+        .await
+    }
+
+    /// Exports notes of *at least* `min_amount` as an [`OOBNotes`] string that
+    /// can be handed to a recipient offline. Unlike
+    /// [`MintClientModule::spend_notes`] the notes carry their expiry, so
+    /// the recipient can refuse them once the sender may have reclaimed them
+    /// (see [`MintClientModule::import_notes`]).
+    ///
+    /// After `expires_in` has passed the client reclaims unclaimed notes
+    /// automatically. Before that, [`MintClientModule::cancel_export`] reclaims
+    /// them on demand. Whether the recipient claimed the notes can be observed
+    /// using [`MintClientModule::subscribe_spend_notes`].
+    pub async fn export_notes<M: Serialize + Send>(
+        &self,
+        min_amount: Amount,
+        expires_in: Duration,
+        include_invite: bool,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OOBNotes)> {
+        self.spend_notes_inner(
+            &SelectNotesWithAtleastAmount,
+            min_amount,
+            expires_in,
+            include_invite,
+            true,
+            extra_meta,
+        )
+        .await
+    }
+
+    async fn spend_notes_inner<M: Serialize + Send>(
+        &self,
+        notes_selector: &impl NotesSelector,
+        requested_amount: Amount,
+        try_cancel_after: Duration,
+        include_invite: bool,
+        with_expiry: bool,
+        extra_meta: M,
+    ) -> anyhow::Result<(OperationId, OOBNotes)> {
+        let expires_at = with_expiry.then(|| fedimint_core::time::now() + try_cancel_after);
         let federation_id_prefix = self.federation_id.to_prefix();
         let extra_meta = serde_json::to_value(extra_meta)
             .expect("MintClientModule::spend_notes extra_meta is serializable");
@@ -1453,6 +1648,10 @@ impl MintClientModule {
                         } else {
                             OOBNotes::new(federation_id_prefix, notes)
                         };
+                        let oob_notes = match expires_at {
+                            Some(expires_at) => oob_notes.with_expiry(expires_at),
+                            None => oob_notes,
+                        };
 
                         dbtx.add_state_machines(self.client_ctx.map_dyn(states).collect())
                             .await?;
@@ -1463,6 +1662,7 @@ impl MintClientModule {
                                 variant: MintOperationMetaVariant::SpendOOB {
                                     requested_amount,
                                     oob_notes: oob_notes.clone(),
+                                    expires_at,
                                 },
                                 amount: oob_notes.total_amount(),
                                 extra_meta,
@@ -1484,6 +1684,38 @@ impl MintClientModule {
             })
     }
 
+    /// Imports notes exported with [`MintClientModule::export_notes`] (or
+    /// [`MintClientModule::spend_notes`]) by reissuing them into our wallet.
+    ///
+    /// Each set of notes can only be claimed once: the claim is recorded in the
+    /// operation log under an id derived from the notes, so importing the same
+    /// string again fails with [`ReissueExternalNotesError::AlreadyReissued`]
+    /// before anything is submitted. Expired notes are rejected with
+    /// [`ReissueExternalNotesError::Expired`] since the sender may already
+    /// have reclaimed them. The progress can be observed using
+    /// [`MintClientModule::subscribe_reissue_external_notes`].
+    pub async fn import_notes<M: Serialize + Send>(
+        &self,
+        oob_notes: OOBNotes,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId> {
+        if oob_notes.is_expired() {
+            bail!(ReissueExternalNotesError::Expired);
+        }
+
+        let operation_id = OperationId(
+            oob_notes
+                .notes()
+                .consensus_hash::<sha256t::Hash<OOBReissueTag>>()
+                .to_byte_array(),
+        );
+        if self.client_ctx.operation_exists(operation_id).await {
+            bail!(ReissueExternalNotesError::AlreadyReissued);
+        }
+
+        self.reissue_external_notes(oob_notes, extra_meta).await
+    }
+
     /// Validate the given notes and return the total amount of the notes.
     /// Validation checks that:
     /// - the federation ID is correct
@@ -1518,6 +1750,51 @@ impl MintClientModule {
         Ok(notes.total_amount())
     }
 
+    /// Validates the notes like [`MintClientModule::validate_notes`] and
+    /// additionally asks the federation whether any of them have already been
+    /// spent. This lets a receiver reject worthless notes before accepting
+    /// them, but the sender can still double-spend the notes until they are
+    /// reissued.
+    pub async fn check_notes_spendable(&self, oob_notes: &OOBNotes) -> anyhow::Result<Amount> {
+        ensure!(
+            self.client_ctx
+                .module_api_version()
+                .supports(CHECK_SPENT_NONCES_API_VERSION),
+            "The federation does not support checking whether notes have been spent"
+        );
+
+        let amount = self.validate_notes(oob_notes)?;
+
+        let nonces = oob_notes
+            .notes()
+            .iter_items()
+            .map(|(_, snote)| Nonce(snote.spend_key.public_key()))
+            .collect::<Vec<_>>();
+
+        let mut spent = Vec::new();
+        for (chunk_idx, chunk) in nonces.chunks(MAX_SPENT_NONCES_BATCH_SIZE).enumerate() {
+            let chunk_spent = self.module_api.check_spent_nonces(chunk.to_vec()).await?;
+
+            if chunk_spent.len() != chunk.len() {
+                bail!("Federation returned an invalid number of results");
+            }
+
+            spent.extend(
+                chunk_spent
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(_, is_spent)| *is_spent)
+                    .map(|(idx, _)| chunk_idx * MAX_SPENT_NONCES_BATCH_SIZE + idx),
+            );
+        }
+
+        if !spent.is_empty() {
+            bail!("Notes {spent:?} have already been spent");
+        }
+
+        Ok(amount)
+    }
+
     /// Try to cancel a spend operation started with
     /// [`MintClientModule::spend_notes`]. If the e-cash notes have already been
     /// spent this operation will fail which can be observed using
@@ -1531,6 +1808,26 @@ impl MintClientModule {
         }
     }
 
+    /// Cancels an export started with [`MintClientModule::export_notes`] that
+    /// the recipient hasn't claimed yet, returning the notes to our wallet.
+    /// The outcome can be observed using
+    /// [`MintClientModule::subscribe_spend_notes`].
+    pub async fn cancel_export(&self, operation_id: OperationId) -> anyhow::Result<()> {
+        let operation = self.mint_operation(operation_id).await?;
+        if !matches!(
+            operation.meta_typed::<MintOperationMeta>()?.variant,
+            MintOperationMetaVariant::SpendOOB { .. }
+        ) {
+            bail!("Operation is not a out-of-band spend");
+        }
+        if operation.outcome::<SpendOOBState>().is_some() {
+            bail!("Export has already been claimed or reclaimed");
+        }
+
+        self.try_cancel_spend_notes(operation_id).await;
+        Ok(())
+    }
+
     /// Subscribe to updates on the progress of a raw e-cash spend operation
     /// started with [`MintClientModule::spend_notes`].
     pub async fn subscribe_spend_notes(
@@ -1539,7 +1836,7 @@ impl MintClientModule {
     ) -> anyhow::Result<UpdateStreamOrOutcome<SpendOOBState>> {
         let operation = self.mint_operation(operation_id).await?;
         if !matches!(
-            operation.meta::<MintOperationMeta>().variant,
+            operation.meta_typed::<MintOperationMeta>()?.variant,
             MintOperationMetaVariant::SpendOOB { .. }
         ) {
             bail!("Operation is not a out-of-band spend");
@@ -1664,7 +1961,11 @@ impl<Note: Send> NotesSelector<Note> for SelectNotesWithAtleastAmount {
         requested_amount: Amount,
         fee_per_note_input: Amount,
     ) -> anyhow::Result<TieredMulti<Note>> {
-        Ok(select_notes_from_stream(stream, requested_amount, fee_per_note_input).await?)
+        Ok(
+            select_notes_from_stream(stream, requested_amount, fee_per_note_input)
+                .await
+                .map_err(ClientError::from)?,
+        )
     }
 }
 
@@ -1682,7 +1983,9 @@ impl<Note: Send> NotesSelector<Note> for SelectNotesWithExactAmount {
         requested_amount: Amount,
         note_fee: Amount,
     ) -> anyhow::Result<TieredMulti<Note>> {
-        let notes = select_notes_from_stream(stream, requested_amount, note_fee).await?;
+        let notes = select_notes_from_stream(stream, requested_amount, note_fee)
+            .await
+            .map_err(ClientError::from)?;
 
         if notes.total_amount() != requested_amount {
             bail!(
@@ -1805,6 +2108,15 @@ impl std::fmt::Display for InsufficientBalanceError {
     }
 }
 
+impl From<InsufficientBalanceError> for ClientError {
+    fn from(error: InsufficientBalanceError) -> Self {
+        ClientError::InsufficientFunds {
+            requested: error.requested_amount,
+            available: error.total_amount,
+        }
+    }
+}
+
 /// Old and no longer used, will be deleted in the future
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 enum MintRestoreStates {
@@ -2007,10 +2319,8 @@ impl NoteIndex {
         self.0
     }
 
-    // Private. If it turns out it is useful outside,
-    // we can relax and convert to `From<u64>`
-    // Actually used in tests RN, so cargo complains in non-test builds.
-    #[allow(unused)]
+    // If it turns out it is useful outside, we can relax and convert to
+    // `From<u64>`
     pub fn from_u64(v: u64) -> Self {
         Self(v)
     }
@@ -2086,10 +2396,52 @@ pub fn represent_amount<K>(
     denominations
 }
 
+/// Change that doesn't exceed `available` together with the schedule fees of
+/// the notes it is split into. Since these fees depend on the split, an exact
+/// match may not exist and the transaction then overpays by the difference,
+/// see [`FeeSchedule`].
+fn change_after_schedule_fees(
+    available: Amount,
+    denominations: impl Fn(Amount) -> TieredCounts,
+    note_fee: impl Fn(Amount) -> Amount,
+) -> Amount {
+    let fees = |change: Amount| -> Amount {
+        denominations(change)
+            .iter()
+            .map(|(amount, count)| note_fee(amount).mul_u64(count as u64))
+            .sum()
+    };
+
+    // Reserving the highest fee seen so far lets the change only shrink, so this
+    // terminates at the latest once there is no change left to pay fees for
+    let mut reserved_fee = Amount::ZERO;
+    let mut change = available;
+
+    loop {
+        let fee = fees(change);
+
+        if fee <= reserved_fee {
+            break;
+        }
+
+        reserved_fee = max(reserved_fee, fee);
+        change = available.saturating_sub(reserved_fee);
+    }
+
+    // A split with lower fees may leave room for a bit more change
+    let larger_change = available.saturating_sub(fees(change));
+    if larger_change + fees(larger_change) <= available {
+        return larger_change;
+    }
+
+    change
+}
+
 #[cfg(test)]
 mod tests {
     use std::fmt::Display;
     use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
 
     use bitcoin_hashes::Hash;
     use fedimint_core::config::FederationId;
@@ -2102,10 +2454,39 @@ mod tests {
     use serde_json::json;
 
     use crate::{
-        represent_amount, select_notes_from_stream, MintOperationMetaVariant, OOBNotes,
-        OOBNotesData, SpendableNote, SpendableNoteUndecoded,
+        change_after_schedule_fees, represent_amount, select_notes_from_stream,
+        MintOperationMetaVariant, OOBNotes, OOBNotesData, SpendableNote, SpendableNoteUndecoded,
     };
 
+    #[test]
+    fn change_after_schedule_fees_never_exceeds_available_amount() {
+        let tiers: Tiered<()> = (0..20)
+            .map(|exp| (Amount::from_msats(1 << exp), ()))
+            .collect();
+        let denominations = |amount| represent_amount(amount, &TieredCounts::default(), &tiers, 2);
+        let note_fee = Amount::from_msats(3);
+        let fees = |change: Amount| -> Amount {
+            denominations(change)
+                .iter()
+                .map(|(_, count)| note_fee.mul_u64(count as u64))
+                .sum()
+        };
+
+        for available in (0..5_000).map(Amount::from_msats) {
+            assert_eq!(
+                change_after_schedule_fees(available, denominations, |_| Amount::ZERO),
+                available
+            );
+
+            let change = change_after_schedule_fees(available, denominations, |_| note_fee);
+            assert!(change + fees(change) <= available);
+            // Only dust is left to the fees
+            if available >= Amount::from_msats(100) {
+                assert_ne!(change, Amount::ZERO);
+            }
+        }
+    }
+
     #[test]
     fn represent_amount_targets_denomination_sets() {
         fn tiers(tiers: Vec<u64>) -> Tiered<()> {
@@ -2302,6 +2683,15 @@ mod tests {
             assert_eq!(oob_notes.federation_invite(), Some(invite.clone()));
         });
 
+        // Can decode notes with expiry, which is truncated to whole seconds
+        let expiry = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let notes_expiry = OOBNotes::new(federation_id_prefix_1, notes.clone()).with_expiry(expiry);
+        test_roundtrip_serialize_str(notes_expiry, |oob_notes| {
+            assert_eq!(oob_notes.notes(), &notes);
+            assert_eq!(oob_notes.expiry(), Some(expiry));
+            assert!(oob_notes.is_expired());
+        });
+
         // Can decode notes without federation id prefix, so we can optionally remove it
         // in the future
         let notes_no_prefix = OOBNotes(vec![
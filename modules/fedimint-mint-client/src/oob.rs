@@ -175,7 +175,7 @@ async fn try_cancel_oob_spend(
 ) -> TransactionId {
     let input = ClientInput {
         input: MintInput::new_v0(amount, spendable_note.note()),
-        keys: vec![spendable_note.spend_key],
+        keys: vec![spendable_note.spend_key.into()],
         amount,
         state_machines: Arc::new(move |txid, input_idx| {
             vec![MintClientStateMachines::Input(MintInputStateMachine {
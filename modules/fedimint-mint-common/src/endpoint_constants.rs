@@ -1,3 +1,4 @@
 pub const AWAIT_OUTPUT_OUTCOME_ENDPOINT: &str = "await_output_outcome";
 pub const BACKUP_ENDPOINT: &str = "backup";
+pub const CHECK_SPENT_NONCES_ENDPOINT: &str = "check_spent_nonces";
 pub const RECOVER_ENDPOINT: &str = "recover";
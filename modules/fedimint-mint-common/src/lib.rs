@@ -29,6 +29,10 @@ pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVers
 /// By default, the maximum notes per denomination when change-making for users
 pub const DEFAULT_MAX_NOTES_PER_DENOMINATION: u16 = 3;
 
+/// Maximum number of nonces that can be checked in a single request to the
+/// spent nonces endpoint
+pub const MAX_SPENT_NONCES_BATCH_SIZE: usize = 1000;
+
 /// The mint module currently doesn't define any consensus items and generally
 /// throws an error on encountering one. To allow old clients to still decode
 /// blocks in the future, should we decide to add consensus items, this has to
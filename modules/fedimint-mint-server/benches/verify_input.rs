@@ -0,0 +1,103 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use fedimint_core::config::{ConfigGenModuleParams, ServerModuleConfig};
+use fedimint_core::module::ServerModuleInit;
+use fedimint_core::{secp256k1, Amount, PeerId, ServerModule};
+use fedimint_mint_common::config::{
+    FeeConsensus, MintConfig, MintGenParams, MintGenParamsConsensus,
+};
+use fedimint_mint_common::{MintInput, Nonce, Note};
+use fedimint_mint_server::{Mint, MintInit};
+use rayon::prelude::*;
+
+const PEERS: u16 = 4;
+
+fn build_configs() -> Vec<ServerModuleConfig> {
+    let peers = (0..PEERS).map(PeerId::from).collect::<Vec<_>>();
+
+    MintInit
+        .trusted_dealer_gen(
+            &peers,
+            &ConfigGenModuleParams::from_typed(MintGenParams {
+                local: Default::default(),
+                consensus: MintGenParamsConsensus::new(2, FeeConsensus::default()),
+            })
+            .unwrap(),
+        )
+        .into_values()
+        .collect()
+}
+
+fn issue_note(server_cfgs: &[ServerModuleConfig], denomination: Amount) -> Note {
+    let note_key = secp256k1::KeyPair::new(secp256k1::SECP256K1, &mut rand::thread_rng());
+    let nonce = Nonce(note_key.public_key());
+    let blinding_key = tbs::BlindingKey::random();
+    let blind_msg = tbs::blind_message(nonce.to_message(), blinding_key);
+
+    let bsig_shares = (1_u64..)
+        .zip(server_cfgs.iter().map(|cfg| {
+            let sks = *cfg
+                .to_typed::<MintConfig>()
+                .unwrap()
+                .private
+                .tbs_sks
+                .get(denomination)
+                .unwrap();
+            tbs::sign_blinded_msg(blind_msg, sks)
+        }))
+        .take(server_cfgs.len() - ((server_cfgs.len() - 1) / 3))
+        .collect();
+
+    let blind_signature = tbs::aggregate_signature_shares(&bsig_shares);
+
+    Note {
+        nonce,
+        signature: tbs::unblind_signature(blinding_key, blind_signature),
+    }
+}
+
+/// Compares verifying the notes of a batch of transactions one after another
+/// with verifying them in parallel, like the consensus engine does before it
+/// processes the transactions in order
+fn bench_verify_input(c: &mut Criterion) {
+    let server_cfgs = build_configs();
+    let mint = Mint::new(server_cfgs[0].to_typed().unwrap());
+    let denomination = Amount::from_msats(1024);
+
+    let mut group = c.benchmark_group("Mint input verification");
+
+    for num_inputs in [1, 10, 100] {
+        let inputs = (0..num_inputs)
+            .map(|_| MintInput::new_v0(denomination, issue_note(&server_cfgs, denomination)))
+            .collect::<Vec<_>>();
+
+        group.bench_with_input(
+            BenchmarkId::new("sequential", num_inputs),
+            &inputs,
+            |b, inputs| {
+                b.iter(|| {
+                    for input in inputs {
+                        mint.verify_input(black_box(input)).unwrap();
+                    }
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("parallel", num_inputs),
+            &inputs,
+            |b, inputs| {
+                b.iter(|| {
+                    inputs
+                        .par_iter()
+                        .try_for_each(|input| mint.verify_input(black_box(input)))
+                        .unwrap();
+                })
+            },
+        );
+    }
+}
+
+criterion_group!(benches, bench_verify_input);
+criterion_main!(benches);
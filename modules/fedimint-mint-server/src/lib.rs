@@ -17,6 +17,7 @@ use fedimint_core::config::{
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::DbKeyPrefixDecl;
 use fedimint_core::db::{DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
@@ -35,12 +36,14 @@ use fedimint_mint_common::config::{
     MintClientConfig, MintConfig, MintConfigConsensus, MintConfigLocal, MintConfigPrivate,
     MintGenParams,
 };
-use fedimint_mint_common::endpoint_constants::{BACKUP_ENDPOINT, RECOVER_ENDPOINT};
+use fedimint_mint_common::endpoint_constants::{
+    BACKUP_ENDPOINT, CHECK_SPENT_NONCES_ENDPOINT, RECOVER_ENDPOINT,
+};
 pub use fedimint_mint_common::{BackupRequest, SignedBackupRequest};
 use fedimint_mint_common::{
     MintCommonInit, MintConsensusItem, MintInput, MintInputError, MintModuleTypes, MintOutput,
-    MintOutputError, MintOutputOutcome, DEFAULT_MAX_NOTES_PER_DENOMINATION,
-    MODULE_CONSENSUS_VERSION,
+    MintOutputError, MintOutputOutcome, Nonce, DEFAULT_MAX_NOTES_PER_DENOMINATION,
+    MAX_SPENT_NONCES_BATCH_SIZE, MODULE_CONSENSUS_VERSION,
 };
 use fedimint_server::config::distributedgen::{evaluate_polynomial_g2, scalar, PeerHandleOps};
 use futures::StreamExt;
@@ -74,6 +77,15 @@ impl ModuleInit for MintInit {
     type Common = MintCommonInit;
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        vec![
+            DbKeyPrefixDecl::of::<NonceKey>(DbKeyPrefix::NoteNonce),
+            DbKeyPrefixDecl::of::<MintOutputOutcomeKey>(DbKeyPrefix::OutputOutcome),
+            DbKeyPrefixDecl::of::<MintAuditItemKey>(DbKeyPrefix::MintAuditItem),
+            DbKeyPrefixDecl::of::<EcashBackupKey>(DbKeyPrefix::EcashBackup),
+        ]
+    }
+
     async fn dump_database(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
@@ -140,7 +152,7 @@ impl ServerModuleInit for MintInit {
                 MODULE_CONSENSUS_VERSION.major,
                 MODULE_CONSENSUS_VERSION.minor,
             ),
-            &[(0, 0)],
+            &[(0, 1)],
         )
     }
 
@@ -378,15 +390,6 @@ impl ServerModule for Mint {
     ) -> Result<InputMeta, MintInputError> {
         let input = input.ensure_v0_ref()?;
 
-        let amount_key = self
-            .pub_key
-            .get(&input.amount)
-            .ok_or(MintInputError::InvalidAmountTier(input.amount))?;
-
-        if !input.note.verify(*amount_key) {
-            return Err(MintInputError::InvalidSignature);
-        }
-
         debug!(target: LOG_MODULE_MINT, nonce=%(input.note.nonce), "Marking note as spent");
         if dbtx
             .insert_entry(&NonceKey(input.note.nonce), &())
@@ -410,6 +413,21 @@ impl ServerModule for Mint {
         })
     }
 
+    fn verify_input(&self, input: &MintInput) -> Result<(), MintInputError> {
+        let input = input.ensure_v0_ref()?;
+
+        let amount_key = self
+            .pub_key
+            .get(&input.amount)
+            .ok_or(MintInputError::InvalidAmountTier(input.amount))?;
+
+        if !input.note.verify(*amount_key) {
+            return Err(MintInputError::InvalidSignature);
+        }
+
+        Ok(())
+    }
+
     async fn process_output<'a, 'b>(
         &'a self,
         dbtx: &mut DatabaseTransaction<'b>,
@@ -515,6 +533,15 @@ impl ServerModule for Mint {
                         .handle_recover_request(&mut context.dbtx().into_nc(), id).await)
                 }
             },
+            api_endpoint! {
+                CHECK_SPENT_NONCES_ENDPOINT,
+                ApiVersion::new(0, 1),
+                async |module: &Mint, context, nonces: Vec<Nonce>| -> Vec<bool> {
+                    module
+                        .handle_check_spent_nonces_request(&mut context.dbtx().into_nc(), nonces)
+                        .await
+                }
+            },
         ]
     }
 }
@@ -557,6 +584,26 @@ impl Mint {
     ) -> Option<ECashUserBackupSnapshot> {
         dbtx.get_value(&EcashBackupKey(id)).await
     }
+
+    /// Returns for every nonce whether the note it belongs to has been spent
+    async fn handle_check_spent_nonces_request(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        nonces: Vec<Nonce>,
+    ) -> Result<Vec<bool>, ApiError> {
+        if MAX_SPENT_NONCES_BATCH_SIZE < nonces.len() {
+            return Err(ApiError::bad_request(format!(
+                "At most {MAX_SPENT_NONCES_BATCH_SIZE} nonces can be checked at once"
+            )));
+        }
+
+        let mut spent = Vec::with_capacity(nonces.len());
+        for nonce in nonces {
+            spent.push(dbtx.get_value(&NonceKey(nonce)).await.is_some());
+        }
+
+        Ok(spent)
+    }
 }
 
 fn calculate_mint_issued_ecash_metrics(
@@ -673,12 +720,18 @@ impl Mint {
 mod test {
     use assert_matches::assert_matches;
     use fedimint_core::config::{ClientModuleConfig, ConfigGenModuleParams, ServerModuleConfig};
+    use fedimint_core::core::server::DynServerModule;
+    use fedimint_core::core::DynInput;
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
+    use fedimint_core::epoch::ConsensusItem;
+    use fedimint_core::module::registry::ServerModuleRegistry;
     use fedimint_core::module::{ModuleConsensusVersion, ServerModuleInit};
+    use fedimint_core::transaction::{Transaction, TransactionSignature};
     use fedimint_core::{secp256k1, Amount, PeerId, ServerModule};
     use fedimint_mint_common::config::FeeConsensus;
     use fedimint_mint_common::{MintInput, Nonce, Note};
+    use fedimint_server::consensus::transaction::verify_transactions;
     use tbs::blind_message;
 
     use crate::common::config::MintGenParamsConsensus;
@@ -788,6 +841,8 @@ mod test {
 
         // Double spend in same session is detected
         let mut dbtx = db.begin_transaction().await;
+        mint.verify_input(&input)
+            .expect("Note is signed by the mint");
         mint.process_input(&mut dbtx.to_ref_with_prefix_module_id(42).into_nc(), &input)
             .await
             .expect("Spend of valid e-cash works");
@@ -797,4 +852,45 @@ mod test {
             Err(_)
         );
     }
+
+    #[test_log::test(tokio::test)]
+    async fn test_verify_transactions_in_parallel() {
+        let (mint_server_cfg, _) = build_configs();
+        let mint = Mint::new(mint_server_cfg[0].to_typed().unwrap());
+        let denomination = Amount::from_msats(1024);
+        let modules =
+            ServerModuleRegistry::from_iter([(0, MintInit::kind(), DynServerModule::from(mint))]);
+
+        let transaction = |note: Note| {
+            ConsensusItem::Transaction(Transaction {
+                inputs: vec![DynInput::from_typed(
+                    0,
+                    MintInput::new_v0(denomination, note),
+                )],
+                outputs: vec![],
+                nonce: [0; 8],
+                signatures: TransactionSignature::NaiveMultisig(vec![]),
+            })
+        };
+
+        let mut items = (0..10)
+            .map(|_| transaction(issue_note(&mint_server_cfg, denomination).1))
+            .collect::<Vec<_>>();
+
+        // A note signed by the mint of another federation is rejected
+        let (other_mint_server_cfg, _) = build_configs();
+        items.push(transaction(
+            issue_note(&other_mint_server_cfg, denomination).1,
+        ));
+
+        // Items that are no transactions are never verified
+        items.push(ConsensusItem::Default {
+            variant: 42,
+            bytes: vec![],
+        });
+
+        let mut expected = vec![true; 10];
+        expected.extend([false, false]);
+        assert_eq!(verify_transactions(&modules, &items).await, expected);
+    }
 }
@@ -2,6 +2,7 @@ use std::io::Cursor;
 use std::time::Duration;
 
 use fedimint_client::backup::{ClientBackup, Metadata};
+use fedimint_client::Client;
 use fedimint_core::config::EmptyGenParams;
 use fedimint_core::task::sleep_in_test;
 use fedimint_core::util::NextOrPending;
@@ -84,6 +85,73 @@ async fn sends_ecash_out_of_band() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn exported_notes_can_only_be_imported_once() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let client1_dummy_module = client1.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = client1_dummy_module.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let client1_mint = client1.get_first_module::<MintClientModule>();
+    let client2_mint = client2.get_first_module::<MintClientModule>();
+    let (op, notes) = client1_mint
+        .export_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+    assert!(notes.expiry().is_some());
+    assert!(!notes.is_expired());
+    let sub1 = &mut client1_mint.subscribe_spend_notes(op).await?.into_stream();
+    assert_eq!(sub1.ok().await?, SpendOOBState::Created);
+
+    let import_op = client2_mint.import_notes(notes.clone(), ()).await?;
+    let mut sub2 = client2_mint
+        .subscribe_reissue_external_notes(import_op)
+        .await?
+        .into_stream();
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Done);
+
+    // The claim is recorded, so a second import is rejected without a round trip
+    assert!(client2_mint.import_notes(notes, ()).await.is_err());
+    assert_eq!(sub1.ok().await?, SpendOOBState::Success);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_notes_spendable_detects_spent_notes() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let client1_dummy_module = client1.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = client1_dummy_module.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let client1_mint = client1.get_first_module::<MintClientModule>();
+    let client2_mint = client2.get_first_module::<MintClientModule>();
+    let (_, notes) = client1_mint
+        .spend_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+
+    assert_eq!(
+        client2_mint.check_notes_spendable(&notes).await?,
+        notes.total_amount()
+    );
+
+    let op = client2_mint
+        .reissue_external_notes(notes.clone(), ())
+        .await?;
+    let mut sub = client2_mint
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    assert!(client2_mint.check_notes_spendable(&notes).await.is_err());
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[ignore] // TODO: flaky https://github.com/fedimint/fedimint/issues/4508
 async fn sends_ecash_oob_highly_parallel() -> anyhow::Result<()> {
@@ -225,6 +293,74 @@ async fn ecash_backup_can_recover_metadata() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn recovers_without_backup() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let client1_dummy_module = client1.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = client1_dummy_module.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    // Spend some notes so the history contains used note indices
+    let client1_mint = client1.get_first_module::<MintClientModule>();
+    let client2_mint = client2.get_first_module::<MintClientModule>();
+    let (op, notes) = client1_mint
+        .spend_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+    let sub1 = &mut client1_mint.subscribe_spend_notes(op).await?.into_stream();
+    assert_eq!(sub1.ok().await?, SpendOOBState::Created);
+    let op = client2_mint.reissue_external_notes(notes, ()).await?;
+    let mut sub2 = client2_mint
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Done);
+    assert_eq!(sub1.ok().await?, SpendOOBState::Success);
+
+    let client_secret = Client::load_or_generate_client_secret(client1.db()).await?;
+
+    info!(target: LOG_TEST, "### RECOVER WITH FULL SCAN");
+    let recovered = fed.recover_client_without_backup(client_secret, None).await;
+    recovered.wait_for_all_recoveries().await?;
+    assert_eq!(recovered.get_balance().await, client1.get_balance().await);
+
+    // The scan window doesn't contain the spent notes, but the recovered client
+    // must still not reissue notes at their indices
+    info!(target: LOG_TEST, "### RECOVER WITH LIMITED SCAN DEPTH");
+    let recovered = fed
+        .recover_client_without_backup(client_secret, Some(0))
+        .await;
+    recovered.wait_for_all_recoveries().await?;
+    let (op, outpoint) = recovered
+        .get_first_module::<DummyClientModule>()
+        .print_money(sats(1000))
+        .await?;
+    recovered.await_primary_module_output(op, outpoint).await?;
+
+    let recovered_mint = recovered.get_first_module::<MintClientModule>();
+    let (op, notes) = recovered_mint
+        .spend_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+    let sub1 = &mut recovered_mint
+        .subscribe_spend_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub1.ok().await?, SpendOOBState::Created);
+    let op = client2_mint.reissue_external_notes(notes, ()).await?;
+    let mut sub2 = client2_mint
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Done);
+    assert_eq!(sub1.ok().await?, SpendOOBState::Success);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn sends_ecash_out_of_band_cancel() -> anyhow::Result<()> {
     // Print notes for client1
@@ -262,6 +398,36 @@ async fn sends_ecash_out_of_band_cancel() -> anyhow::Result<()> {
     panic!("Did not receive refund in time");
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_operation_cancels_ecash_out_of_band_spend() -> anyhow::Result<()> {
+    let fed = fixtures().new_default_fed().await;
+    let client = fed.new_client().await;
+    let dummy_module = client.get_first_module::<DummyClientModule>();
+    let (op, outpoint) = dummy_module.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    let mint_module = client.get_first_module::<MintClientModule>();
+    let (op, _) = mint_module
+        .spend_notes(sats(750), TIMEOUT, false, ())
+        .await?;
+
+    client.cancel_operation(op).await?;
+    assert_eq!(
+        client
+            .operation_log()
+            .get_operation(op)
+            .await
+            .expect("Operation exists")
+            .outcome::<SpendOOBState>(),
+        Some(SpendOOBState::UserCanceledSuccess)
+    );
+
+    // A finished operation can't be cancelled again
+    assert!(client.cancel_operation(op).await.is_err());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn error_zero_value_oob_spend() -> anyhow::Result<()> {
     // Print notes for client1
@@ -329,7 +495,8 @@ mod fedimint_migration_tests {
     use fedimint_mint_client::backup::{EcashBackup, EcashBackupV0};
     use fedimint_mint_client::client_db::{
         CancelledOOBSpendKey, CancelledOOBSpendKeyPrefix, NextECashNoteIndexKey,
-        NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix, RecoveryStateKey,
+        NextECashNoteIndexKeyPrefix, NoteKey, NoteKeyPrefix, RecoveryFinalizedKey,
+        RecoveryNotesKey, RecoveryStateKey, RecoveryTierStateKeyPrefix,
     };
     use fedimint_mint_client::output::NoteIssuanceRequest;
     use fedimint_mint_client::{MintClientInit, MintClientModule, NoteIndex, SpendableNote};
@@ -648,13 +815,33 @@ mod fedimint_migration_tests {
                         fedimint_mint_client::client_db::DbKeyPrefix::RecoveryState => {
                             let restore_state = dbtx.get_value(&RecoveryStateKey).await;
                             ensure!(
-                                restore_state.is_some(),
-                                "validate_migrations was not able to read any RecoveryState"
+                                restore_state.is_none(),
+                                "validate_migrations found a RecoveryState that was not migrated"
                             );
                             info!("Validated RecoveryState");
                         }
+                        fedimint_mint_client::client_db::DbKeyPrefix::RecoveryNotes => {
+                            let recovery_notes = dbtx.get_value(&RecoveryNotesKey).await;
+                            ensure!(
+                                recovery_notes.is_some(),
+                                "validate_migrations was not able to read any RecoveryNotes"
+                            );
+                            info!("Validated RecoveryNotes");
+                        }
+                        fedimint_mint_client::client_db::DbKeyPrefix::RecoveryTierState => {
+                            let tiers = dbtx
+                                .find_by_prefix(&RecoveryTierStateKeyPrefix)
+                                .await
+                                .collect::<Vec<_>>()
+                                .await;
+                            ensure!(
+                                !tiers.is_empty(),
+                                "validate_migrations was not able to read any RecoveryTierStates"
+                            );
+                            info!("Validated RecoveryTierState");
+                        }
                         fedimint_mint_client::client_db::DbKeyPrefix::RecoveryFinalized => {
-                            let recovery_finalized = dbtx.get_value(&RecoveryStateKey).await;
+                            let recovery_finalized = dbtx.get_value(&RecoveryFinalizedKey).await;
                             ensure!(
                                 recovery_finalized.is_some(),
                                 "validate_migrations was not able to read any RecoveryFinalized"
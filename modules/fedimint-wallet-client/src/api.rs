@@ -1,9 +1,12 @@
-use bitcoin::Address;
+use bitcoin::{Address, ScriptBuf};
 use fedimint_api_client::api::{FederationApiExt, FederationResult, IModuleFederationApi};
-use fedimint_core::module::ApiRequestErased;
+use fedimint_core::module::{ApiRequestErased, ModuleConsensusVersion};
 use fedimint_core::task::{MaybeSend, MaybeSync};
-use fedimint_core::{apply, async_trait_maybe_send};
-use fedimint_wallet_common::endpoint_constants::{BLOCK_COUNT_ENDPOINT, PEG_OUT_FEES_ENDPOINT};
+use fedimint_core::{apply, async_trait_maybe_send, OutPoint};
+use fedimint_wallet_common::endpoint_constants::{
+    AWAIT_PEG_OUT_OUTPOINT_ENDPOINT, BLOCK_COUNT_ENDPOINT, MODULE_CONSENSUS_VERSION_ENDPOINT,
+    PEG_OUT_FEES_ENDPOINT, PEG_OUT_SCRIPT_FEES_ENDPOINT,
+};
 use fedimint_wallet_common::PegOutFees;
 
 #[apply(async_trait_maybe_send!)]
@@ -14,6 +17,21 @@ pub trait WalletFederationApi {
         address: &Address,
         amount: bitcoin::Amount,
     ) -> FederationResult<Option<PegOutFees>>;
+    /// Like [`Self::fetch_peg_out_fees`] for a peg-out to an arbitrary script
+    async fn fetch_peg_out_script_fees(
+        &self,
+        script_pubkey: &ScriptBuf,
+        amount: bitcoin::Amount,
+    ) -> FederationResult<Option<PegOutFees>>;
+    /// Waits until the peg-out created by `out_point` was paid on-chain,
+    /// returns the bitcoin output paying it if it was part of a batch
+    async fn await_peg_out_outpoint(
+        &self,
+        out_point: OutPoint,
+    ) -> FederationResult<Option<bitcoin::OutPoint>>;
+    /// Module consensus version a threshold of guardians agreed on, not
+    /// available from guardians that predate taproot peg-ins
+    async fn fetch_module_consensus_version(&self) -> FederationResult<ModuleConsensusVersion>;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -40,4 +58,35 @@ where
         )
         .await
     }
+
+    async fn fetch_peg_out_script_fees(
+        &self,
+        script_pubkey: &ScriptBuf,
+        amount: bitcoin::Amount,
+    ) -> FederationResult<Option<PegOutFees>> {
+        self.request_current_consensus(
+            PEG_OUT_SCRIPT_FEES_ENDPOINT.to_string(),
+            ApiRequestErased::new((script_pubkey, amount.to_sat())),
+        )
+        .await
+    }
+
+    async fn await_peg_out_outpoint(
+        &self,
+        out_point: OutPoint,
+    ) -> FederationResult<Option<bitcoin::OutPoint>> {
+        self.request_current_consensus(
+            AWAIT_PEG_OUT_OUTPOINT_ENDPOINT.to_string(),
+            ApiRequestErased::new(out_point),
+        )
+        .await
+    }
+
+    async fn fetch_module_consensus_version(&self) -> FederationResult<ModuleConsensusVersion> {
+        self.request_current_consensus(
+            MODULE_CONSENSUS_VERSION_ENDPOINT.to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+    }
 }
@@ -0,0 +1,296 @@
+use std::fmt;
+
+use anyhow::{ensure, format_err};
+use bitcoin::consensus::{deserialize, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::merkle_tree::PartialMerkleTree;
+use bitcoin::{ScriptBuf, Transaction, TxMerkleNode, Txid};
+use electrum_client::ElectrumApi;
+use fedimint_core::runtime::block_in_place;
+use fedimint_core::txoproof::TxOutProof;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send, Feerate};
+use tracing::info;
+
+use super::IChainSource;
+
+/// Chain source querying an Electrum server, which indexes the history of all
+/// scripts, so scripts don't have to be watched in advance
+pub struct ElectrumChainSource(electrum_client::Client);
+
+impl ElectrumChainSource {
+    pub fn new(url: &SafeUrl) -> anyhow::Result<Self> {
+        Ok(Self(electrum_client::Client::new(url.as_str())?))
+    }
+
+    /// Returns the number of transactions in the block at `height`, which
+    /// Electrum servers only reveal by failing to look up transactions past
+    /// the end of the block. A block whose merkle branches have `depth` hashes
+    /// has between `2^(depth - 1) + 1` and `2^depth` transactions.
+    fn block_tx_count(&self, height: usize, depth: usize) -> usize {
+        if depth == 0 {
+            return 1;
+        }
+
+        // Positions known to hold a transaction and known to be past the end
+        let (mut present, mut absent) = (1 << (depth - 1), 1 << depth);
+        while absent - present > 1 {
+            let pos = present + (absent - present) / 2;
+            if self.0.txid_from_pos(height, pos).is_ok() {
+                present = pos;
+            } else {
+                absent = pos;
+            }
+        }
+
+        present + 1
+    }
+}
+
+impl fmt::Debug for ElectrumChainSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ElectrumChainSource")
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IChainSource for ElectrumChainSource {
+    async fn watch_script_history(&self, _script: &ScriptBuf) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>> {
+        block_in_place(|| {
+            self.0
+                .script_get_history(script)?
+                .iter()
+                .map(|entry| Ok(self.0.transaction_get(&entry.tx_hash)?))
+                .collect()
+        })
+    }
+
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>> {
+        let Some(transaction) = self.get_transaction(txid).await? else {
+            return Ok(None);
+        };
+
+        let output = transaction
+            .output
+            .first()
+            .ok_or(format_err!("Transaction must contain at least one output"))?;
+
+        // Unconfirmed transactions have a height of 0 or -1
+        Ok(
+            block_in_place(|| self.0.script_get_history(&output.script_pubkey))?
+                .into_iter()
+                .find(|entry| entry.tx_hash == *txid && 0 < entry.height)
+                .and_then(|entry| u64::try_from(entry.height).ok()),
+        )
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        Ok(block_in_place(|| self.0.transaction_get(txid))
+            .map_err(|error| info!(?error, "Unable to get raw transaction"))
+            .ok())
+    }
+
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof> {
+        let height = self
+            .get_tx_block_height(&txid)
+            .await?
+            .ok_or(format_err!("Transaction is not confirmed"))?;
+        let height = usize::try_from(height)?;
+
+        let merkle = block_in_place(|| self.0.transaction_get_merkle(&txid, height))?;
+        let branch = merkle
+            .merkle
+            .iter()
+            .map(|hash| {
+                // Electrum servers return hashes in the byte order they are displayed in
+                let mut bytes = *hash;
+                bytes.reverse();
+                TxMerkleNode::from_byte_array(bytes)
+            })
+            .collect::<Vec<_>>();
+        let tx_count = block_in_place(|| self.block_tx_count(height, branch.len()));
+        let merkle_proof = partial_merkle_tree(txid, merkle.pos, tx_count, &branch)?;
+        let block_header = block_in_place(|| self.0.block_header(height))?;
+
+        // A failed lookup while counting the transactions of the block results in a
+        // proof for a different tree
+        let root = merkle_proof
+            .extract_matches(&mut vec![], &mut vec![])
+            .map_err(|e| format_err!("Invalid merkle proof: {e}"))?;
+        ensure!(
+            root == block_header.merkle_root,
+            "Merkle proof doesn't match the block header"
+        );
+
+        Ok(TxOutProof {
+            block_header,
+            merkle_proof,
+        })
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) {
+        if let Err(error) = block_in_place(|| self.0.transaction_broadcast(&transaction)) {
+            info!(?error, "Error broadcasting transaction");
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn get_fee_rate(&self, confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+        let estimate = block_in_place(|| self.0.estimate_fee(confirmation_target.into()))?;
+        let min_fee = block_in_place(|| self.0.relay_fee())?;
+
+        // Both are denominated in BTC per kvB
+        Ok(Some(Feerate {
+            sats_per_kvb: (estimate.max(min_fee) * 100_000_000f64).ceil() as u64,
+        }))
+    }
+}
+
+/// Builds the partial merkle tree proving that the transaction `txid` at
+/// position `pos` is part of a block with `tx_count` transactions from the
+/// merkle branch of the transaction, which lists the hashes it is paired with
+/// on the way up to the merkle root.
+fn partial_merkle_tree(
+    txid: Txid,
+    pos: usize,
+    tx_count: usize,
+    branch: &[TxMerkleNode],
+) -> anyhow::Result<PartialMerkleTree> {
+    fn width(tx_count: usize, height: usize) -> usize {
+        (tx_count + (1 << height) - 1) >> height
+    }
+
+    // Nodes are visited depth first, only the nodes on the path of the transaction
+    // are descended into, all others are represented by their hash
+    fn traverse(
+        height: usize,
+        node: usize,
+        path: &Path,
+        bits: &mut Vec<bool>,
+        hashes: &mut Vec<TxMerkleNode>,
+    ) {
+        let on_path = path.pos >> height == node;
+        bits.push(on_path);
+
+        if !on_path {
+            hashes.push(path.branch[height]);
+        } else if height == 0 {
+            hashes.push(TxMerkleNode::from_raw_hash(path.txid.to_raw_hash()));
+        } else {
+            traverse(height - 1, node * 2, path, bits, hashes);
+            if node * 2 + 1 < width(path.tx_count, height - 1) {
+                traverse(height - 1, node * 2 + 1, path, bits, hashes);
+            }
+        }
+    }
+
+    struct Path<'a> {
+        txid: Txid,
+        pos: usize,
+        tx_count: usize,
+        branch: &'a [TxMerkleNode],
+    }
+
+    let mut tree_height = 0;
+    while width(tx_count, tree_height) > 1 {
+        tree_height += 1;
+    }
+    ensure!(
+        pos < tx_count && tree_height == branch.len(),
+        "Merkle branch doesn't match a block with {tx_count} transactions"
+    );
+
+    let mut bits = vec![];
+    let mut hashes = vec![];
+    traverse(
+        tree_height,
+        0,
+        &Path {
+            txid,
+            pos,
+            tx_count,
+            branch,
+        },
+        &mut bits,
+        &mut hashes,
+    );
+
+    // Serialized as a merkleblock message without the block header
+    let mut flags = vec![0u8; (bits.len() + 7) / 8];
+    for (i, bit) in bits.into_iter().enumerate() {
+        flags[i / 8] |= u8::from(bit) << (i % 8);
+    }
+    let mut encoded = vec![];
+    u32::try_from(tx_count)?.consensus_encode(&mut encoded)?;
+    hashes.consensus_encode(&mut encoded)?;
+    flags.consensus_encode(&mut encoded)?;
+
+    Ok(deserialize(&encoded)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::{sha256d, Hash};
+    use bitcoin::merkle_tree::PartialMerkleTree;
+    use bitcoin::{TxMerkleNode, Txid};
+
+    use super::partial_merkle_tree;
+
+    /// Returns the merkle branch of the transaction at `pos`
+    fn merkle_branch(txids: &[Txid], mut pos: usize) -> Vec<TxMerkleNode> {
+        let mut level = txids
+            .iter()
+            .map(|txid| txid.to_raw_hash())
+            .collect::<Vec<_>>();
+        let mut branch = vec![];
+
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().expect("Level is not empty"));
+            }
+            branch.push(TxMerkleNode::from_raw_hash(level[pos ^ 1]));
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    sha256d::Hash::hash(
+                        &[pair[0].as_byte_array(), pair[1].as_byte_array()].concat(),
+                    )
+                })
+                .collect();
+            pos /= 2;
+        }
+
+        branch
+    }
+
+    #[test]
+    fn partial_merkle_tree_from_branch_matches_bitcoin_core() {
+        for tx_count in 1..=33u8 {
+            let txids = (0..tx_count).map(|i| Txid::hash(&[i])).collect::<Vec<_>>();
+
+            for pos in 0..txids.len() {
+                let matches = (0..txids.len()).map(|i| i == pos).collect::<Vec<_>>();
+
+                assert_eq!(
+                    partial_merkle_tree(txids[pos], pos, txids.len(), &merkle_branch(&txids, pos))
+                        .expect("Failed to build partial merkle tree"),
+                    PartialMerkleTree::from_txids(&txids, &matches),
+                    "{pos} of {tx_count} transactions"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn partial_merkle_tree_requires_matching_branch_length() {
+        let txids = (0..5u8).map(|i| Txid::hash(&[i])).collect::<Vec<_>>();
+        let branch = merkle_branch(&txids, 0);
+
+        assert!(partial_merkle_tree(txids[0], 0, 9, &branch).is_err());
+        assert!(partial_merkle_tree(txids[0], 5, 5, &branch).is_err());
+    }
+}
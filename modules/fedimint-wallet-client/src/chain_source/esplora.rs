@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use anyhow::format_err;
+use bitcoin::{ScriptBuf, Transaction, Txid};
+use fedimint_core::txoproof::TxOutProof;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send, Feerate};
+use tracing::info;
+
+use super::IChainSource;
+
+/// Chain source querying an Esplora HTTP API, which indexes the history of
+/// all scripts, so scripts don't have to be watched in advance
+#[derive(Debug)]
+pub struct EsploraChainSource(esplora_client::AsyncClient);
+
+impl EsploraChainSource {
+    pub fn new(url: &SafeUrl) -> anyhow::Result<Self> {
+        // URL needs to have any trailing path including '/' removed
+        let without_trailing = url.as_str().trim_end_matches('/');
+
+        Ok(Self(
+            esplora_client::Builder::new(without_trailing).build_async()?,
+        ))
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IChainSource for EsploraChainSource {
+    async fn watch_script_history(&self, _script: &ScriptBuf) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>> {
+        Ok(self
+            .0
+            .scripthash_txs(script, None)
+            .await?
+            .into_iter()
+            .map(|tx| tx.to_tx())
+            .collect())
+    }
+
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .0
+            .get_tx_status(txid)
+            .await?
+            .block_height
+            .map(u64::from))
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        Ok(self.0.get_tx(txid).await?)
+    }
+
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof> {
+        let proof = self
+            .0
+            .get_merkle_block(&txid)
+            .await?
+            .ok_or(format_err!("No merkle proof found"))?;
+
+        Ok(TxOutProof {
+            block_header: proof.header,
+            merkle_proof: proof.txn,
+        })
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) {
+        if let Err(error) = self.0.broadcast(&transaction).await {
+            info!(?error, "Error broadcasting transaction");
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    async fn get_fee_rate(&self, confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+        let fee_estimates: HashMap<String, f64> = self.0.get_fee_estimates().await?;
+        let fee_rate_vb =
+            esplora_client::convert_fee_rate(confirmation_target.into(), fee_estimates)?;
+
+        Ok(Some(Feerate {
+            sats_per_kvb: (fee_rate_vb * 1_000f32).ceil() as u64,
+        }))
+    }
+}
@@ -0,0 +1,175 @@
+//! On-chain backends the wallet client can use to follow peg-in transactions.
+//!
+//! Light clients don't have to run a full node: a bitcoind node, an Electrum
+//! server, an Esplora API or any other backend registered with
+//! [`fedimint_bitcoind`] can be selected through [`ChainSourceConfig`] when
+//! constructing the [`crate::WalletClientInit`].
+
+#[cfg(not(target_family = "wasm"))]
+mod electrum;
+mod esplora;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use bitcoin::{ScriptBuf, Transaction, Txid};
+use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
+use fedimint_core::envs::BitcoinRpcConfig;
+use fedimint_core::task::TaskHandle;
+use fedimint_core::txoproof::TxOutProof;
+use fedimint_core::util::SafeUrl;
+use fedimint_core::{apply, async_trait_maybe_send, dyn_newtype_define, Feerate};
+use serde::{Deserialize, Serialize};
+
+/// The on-chain data the wallet client needs to track peg-ins
+#[apply(async_trait_maybe_send!)]
+pub trait IChainSource: Debug {
+    /// Starts watching the script, needed by backends that only index
+    /// scripts they have been told about in advance
+    async fn watch_script_history(&self, script: &ScriptBuf) -> anyhow::Result<()>;
+
+    /// Returns all transactions paying to the script
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>>;
+
+    /// Returns the height of the block the transaction was included in, or
+    /// `None` if it's unconfirmed
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>>;
+
+    /// Returns the transaction if it's known to the backend
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>>;
+
+    /// Returns a proof that the transaction was included in a block
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof>;
+
+    /// Broadcasts the transaction, failures are only logged by the backend
+    async fn submit_transaction(&self, transaction: Transaction);
+
+    /// Returns the estimated fee rate to confirm within the given number of
+    /// blocks
+    async fn get_fee_rate(&self, confirmation_target: u16) -> anyhow::Result<Option<Feerate>>;
+}
+
+dyn_newtype_define! {
+    #[derive(Clone)]
+    pub DynChainSource(Arc<IChainSource>)
+}
+
+/// Selects the backend the wallet client uses to follow peg-ins
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainSourceConfig {
+    /// A bitcoind node, which has to be able to import watch-only scripts
+    Bitcoind(SafeUrl),
+    /// An Electrum server
+    Electrum(SafeUrl),
+    /// An Esplora HTTP API
+    Esplora(SafeUrl),
+    /// Any other backend registered with
+    /// [`fedimint_bitcoind::register_bitcoind`]
+    Other(BitcoinRpcConfig),
+}
+
+impl ChainSourceConfig {
+    pub fn rpc_config(&self) -> BitcoinRpcConfig {
+        let (kind, url) = match self {
+            ChainSourceConfig::Bitcoind(url) => ("bitcoind", url),
+            ChainSourceConfig::Electrum(url) => ("electrum", url),
+            ChainSourceConfig::Esplora(url) => ("esplora", url),
+            ChainSourceConfig::Other(rpc_config) => return rpc_config.clone(),
+        };
+
+        BitcoinRpcConfig {
+            kind: kind.to_string(),
+            url: url.clone(),
+        }
+    }
+
+    /// Connects to the configured backend
+    pub fn build(&self, handle: TaskHandle) -> anyhow::Result<DynChainSource> {
+        match self {
+            #[cfg(not(target_family = "wasm"))]
+            ChainSourceConfig::Electrum(url) => Ok(electrum::ElectrumChainSource::new(url)?.into()),
+            #[cfg(target_family = "wasm")]
+            ChainSourceConfig::Electrum(_) => {
+                anyhow::bail!("Electrum is not supported on wasm")
+            }
+            ChainSourceConfig::Esplora(url) => Ok(esplora::EsploraChainSource::new(url)?.into()),
+            ChainSourceConfig::Bitcoind(_) | ChainSourceConfig::Other(_) => {
+                let rpc = create_bitcoind(&self.rpc_config(), handle)?;
+                Ok(BitcoindRpcChainSource(rpc).into())
+            }
+        }
+    }
+}
+
+impl From<BitcoinRpcConfig> for ChainSourceConfig {
+    fn from(rpc_config: BitcoinRpcConfig) -> Self {
+        match rpc_config.kind.as_str() {
+            "bitcoind" => ChainSourceConfig::Bitcoind(rpc_config.url),
+            "electrum" => ChainSourceConfig::Electrum(rpc_config.url),
+            "esplora" => ChainSourceConfig::Esplora(rpc_config.url),
+            _ => ChainSourceConfig::Other(rpc_config),
+        }
+    }
+}
+
+/// Chain source backed by one of the bitcoin RPC clients of
+/// [`fedimint_bitcoind`], used for bitcoind nodes and custom backends
+#[derive(Debug)]
+pub struct BitcoindRpcChainSource(pub DynBitcoindRpc);
+
+#[apply(async_trait_maybe_send!)]
+impl IChainSource for BitcoindRpcChainSource {
+    async fn watch_script_history(&self, script: &ScriptBuf) -> anyhow::Result<()> {
+        self.0.watch_script_history(script).await
+    }
+
+    async fn get_script_history(&self, script: &ScriptBuf) -> anyhow::Result<Vec<Transaction>> {
+        self.0.get_script_history(script).await
+    }
+
+    async fn get_tx_block_height(&self, txid: &Txid) -> anyhow::Result<Option<u64>> {
+        self.0.get_tx_block_height(txid).await
+    }
+
+    async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+        self.0.get_transaction(txid).await
+    }
+
+    async fn get_txout_proof(&self, txid: Txid) -> anyhow::Result<TxOutProof> {
+        self.0.get_txout_proof(txid).await
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) {
+        self.0.submit_transaction(transaction).await;
+    }
+
+    async fn get_fee_rate(&self, confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+        self.0.get_fee_rate(confirmation_target).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::envs::BitcoinRpcConfig;
+    use fedimint_core::util::SafeUrl;
+
+    use super::ChainSourceConfig;
+
+    #[test]
+    fn chain_source_config_round_trips_through_rpc_config() {
+        let url = SafeUrl::parse("http://127.0.0.1:50002").expect("Invalid url");
+
+        for config in [
+            ChainSourceConfig::Bitcoind(url.clone()),
+            ChainSourceConfig::Electrum(url.clone()),
+            ChainSourceConfig::Esplora(url.clone()),
+            ChainSourceConfig::Other(BitcoinRpcConfig {
+                kind: "custom".to_string(),
+                url: url.clone(),
+            }),
+        ] {
+            assert_eq!(ChainSourceConfig::from(config.rpc_config()), config);
+        }
+    }
+}
@@ -1,11 +1,16 @@
+use std::time::SystemTime;
+
+use bitcoin::{Transaction, Txid};
+use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::impl_db_record;
+use fedimint_core::{impl_db_lookup, impl_db_record};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     NextPegInTweakIndex = 0x2c,
+    PayjoinSession = 0x2d,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -22,3 +27,49 @@ impl_db_record!(
     value = u64,
     db_prefix = DbKeyPrefix::NextPegInTweakIndex,
 );
+
+/// Payjoin proposals we made for a deposit, see
+/// [`crate::WalletClientModule::process_payjoin_request`]
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PayjoinSessionKey(pub OperationId);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PayjoinSessionKeyPrefix;
+
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct PayjoinSession {
+    /// The fully signed transaction of the sender without our inputs, which
+    /// we broadcast if the sender never broadcasts the payjoin transaction
+    pub original_tx: Transaction,
+    /// Id of the payjoin transaction, it doesn't change when the inputs are
+    /// signed
+    pub proposal_txid: Txid,
+    /// Time after which we broadcast the original transaction if neither
+    /// transaction was broadcast by the sender
+    pub fallback_at: SystemTime,
+    pub state: PayjoinSessionState,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub enum PayjoinSessionState {
+    /// The proposal was returned to the sender, waiting for either transaction
+    /// to be broadcast
+    Proposed,
+    /// The sender broadcast the payjoin transaction
+    ProposalBroadcast,
+    /// The sender broadcast its original transaction instead
+    OriginalBroadcast,
+    /// Neither transaction was broadcast in time, so we broadcast the original
+    /// transaction ourselves
+    FallbackBroadcast,
+}
+
+impl_db_record!(
+    key = PayjoinSessionKey,
+    value = PayjoinSession,
+    db_prefix = DbKeyPrefix::PayjoinSession,
+);
+impl_db_lookup!(
+    key = PayjoinSessionKey,
+    query_prefix = PayjoinSessionKeyPrefix
+);
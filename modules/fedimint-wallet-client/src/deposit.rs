@@ -2,6 +2,7 @@ use std::cmp;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use anyhow::Context as _;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, State, StateTransition};
 use fedimint_client::transaction::ClientInput;
 use fedimint_client::DynGlobalClientContext;
@@ -9,18 +10,23 @@ use fedimint_core::core::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
 use fedimint_core::txoproof::TxOutProof;
-use fedimint_core::{Amount, OutPoint, TransactionId};
+use fedimint_core::{Amount, Feerate, OutPoint, TransactionId};
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::txoproof::PegInProof;
-use fedimint_wallet_common::WalletInput;
+use fedimint_wallet_common::{taproot_peg_in_descriptor, WalletInput, CONFIRMATION_TARGET};
 use secp256k1::KeyPair;
 use tracing::{debug, instrument, trace, warn};
 
 use crate::api::WalletFederationApi;
-use crate::{WalletClientContext, WalletClientStates};
+use crate::chain_source::DynChainSource;
+use crate::{BitcoinTransactionData, StuckDepositData, WalletClientContext, WalletClientStates};
 
 const TRANSACTION_STATUS_FETCH_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Virtual size of a transaction spending a single P2WPKH output to a single
+/// output, used to estimate the fee needed to bump a stuck deposit
+const CPFP_CHILD_VSIZE: u64 = 110;
+
 // FIXME: deal with RBF
 // FIXME: deal with multiple deposits
 #[cfg_attr(doc, aquamarine::aquamarine)]
@@ -98,17 +104,28 @@ async fn await_created_btc_transaction_submitted(
     context: WalletClientContext,
     tweak: KeyPair,
 ) -> (bitcoin::Transaction, u32) {
-    let script = context
-        .wallet_descriptor
-        .tweak(&tweak.public_key(), &context.secp)
-        .script_pubkey();
-    loop {
-        match context.rpc.watch_script_history(&script).await {
-            Ok(_) => break,
-            Err(e) => warn!("Error while awaiting btc tx submitting: {e}"),
+    // The deposit address is derived from the taproot descriptor once the
+    // federation activated it, so we look for the deposit at both addresses
+    let scripts = [
+        context.wallet_descriptor.clone(),
+        taproot_peg_in_descriptor(&context.wallet_descriptor),
+    ]
+    .map(|descriptor| {
+        descriptor
+            .tweak(&tweak.public_key(), &context.secp)
+            .script_pubkey()
+    });
+
+    for script in &scripts {
+        loop {
+            match context.chain_source.watch_script_history(script).await {
+                Ok(_) => break,
+                Err(e) => warn!("Error while awaiting btc tx submitting: {e}"),
+            }
+            sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
         }
-        sleep(TRANSACTION_STATUS_FETCH_INTERVAL).await;
     }
+
     for attempt in 0u32.. {
         sleep(cmp::min(
             TRANSACTION_STATUS_FETCH_INTERVAL * attempt,
@@ -116,34 +133,36 @@ async fn await_created_btc_transaction_submitted(
         ))
         .await;
 
-        match context.rpc.get_script_history(&script).await {
-            Ok(received) => {
-                // TODO: fix
-                if received.len() > 1 {
-                    warn!("More than one transaction was sent to deposit address, only considering the first one");
+        for script in &scripts {
+            match context.chain_source.get_script_history(script).await {
+                Ok(received) => {
+                    // TODO: fix
+                    if received.len() > 1 {
+                        warn!("More than one transaction was sent to deposit address, only considering the first one");
+                    }
+
+                    if let Some(transaction) = received.into_iter().next() {
+                        let out_idx = transaction
+                            .output
+                            .iter()
+                            .enumerate()
+                            .find_map(|(idx, output)| {
+                                if output.script_pubkey == *script {
+                                    Some(idx as u32)
+                                } else {
+                                    None
+                                }
+                            })
+                            .expect("TODO: handle invalid tx returned by API");
+
+                        return (transaction, out_idx);
+                    }
+
+                    trace!("No transactions received yet for script {script:?}");
                 }
-
-                if let Some(transaction) = received.into_iter().next() {
-                    let out_idx = transaction
-                        .output
-                        .iter()
-                        .enumerate()
-                        .find_map(|(idx, output)| {
-                            if output.script_pubkey == script {
-                                Some(idx as u32)
-                            } else {
-                                None
-                            }
-                        })
-                        .expect("TODO: handle invalid tx returned by API");
-
-                    return (transaction, out_idx);
+                Err(e) => {
+                    warn!("Error fetching transaction history for {script:?}: {e}");
                 }
-
-                trace!("No transactions received yet for script {script:?}");
-            }
-            Err(e) => {
-                warn!("Error fetching transaction history for {script:?}: {e}");
             }
         }
     }
@@ -216,7 +235,7 @@ async fn await_btc_transaction_confirmed(
         debug!(consensus_block_count, "Fetched consensus block count");
 
         let confirmation_block_count = match context
-            .rpc
+            .chain_source
             .get_tx_block_height(&waiting_state.btc_transaction.txid())
             .await
         {
@@ -244,7 +263,7 @@ async fn await_btc_transaction_confirmed(
 
         // Get txout proof
         let txout_proof = match context
-            .rpc
+            .chain_source
             .get_txout_proof(waiting_state.btc_transaction.txid())
             .await
         {
@@ -287,7 +306,7 @@ async fn transition_btc_tx_confirmed(
 
     let client_input = ClientInput::<WalletInput, WalletClientStates> {
         input: wallet_input,
-        keys: vec![awaiting_confirmation_state.tweak_key],
+        keys: vec![awaiting_confirmation_state.tweak_key.into()],
         amount,
         state_machines: Arc::new(|_, _| vec![]),
     };
@@ -303,6 +322,79 @@ async fn transition_btc_tx_confirmed(
     }
 }
 
+/// Returns fee data about the deposit transaction if it is unconfirmed and pays
+/// a lower fee rate than currently estimated to confirm within
+/// [`CONFIRMATION_TARGET`] blocks.
+///
+/// Returns `None` if the fee can't be determined because the backend doesn't
+/// know the transactions spent by the deposit transaction.
+pub(crate) async fn fetch_stuck_deposit(
+    chain_source: &DynChainSource,
+    tx_data: &BitcoinTransactionData,
+) -> anyhow::Result<Option<StuckDepositData>> {
+    let btc_transaction = &tx_data.btc_transaction;
+    let txid = btc_transaction.txid();
+
+    if chain_source.get_tx_block_height(&txid).await?.is_some() {
+        return Ok(None);
+    }
+
+    let mut input_value = 0;
+    for input in &btc_transaction.input {
+        let Some(spent_transaction) = chain_source
+            .get_transaction(&input.previous_output.txid)
+            .await?
+        else {
+            return Ok(None);
+        };
+        input_value += spent_transaction
+            .output
+            .get(input.previous_output.vout as usize)
+            .context("Deposit transaction spends a non-existent output")?
+            .value;
+    }
+
+    let output_value = btc_transaction
+        .output
+        .iter()
+        .map(|output| output.value)
+        .sum::<u64>();
+    let fee = input_value
+        .checked_sub(output_value)
+        .context("Deposit transaction spends more than its inputs")?;
+
+    let vsize = btc_transaction.vsize() as u64;
+    let fee_rate = Feerate {
+        sats_per_kvb: fee * 1000 / vsize,
+    };
+
+    let Some(target_fee_rate) = chain_source.get_fee_rate(CONFIRMATION_TARGET).await? else {
+        return Ok(None);
+    };
+
+    if fee_rate.sats_per_kvb >= target_fee_rate.sats_per_kvb {
+        return Ok(None);
+    }
+
+    // The child has to pay for the missing fee of its parent as well as for
+    // itself for the package to reach the target fee rate
+    let package_fee = (vsize + CPFP_CHILD_VSIZE) * target_fee_rate.sats_per_kvb / 1000;
+
+    let cpfp_outpoints = (0..btc_transaction.output.len() as u32)
+        .filter(|vout| *vout != tx_data.out_idx)
+        .map(|vout| bitcoin::OutPoint { txid, vout })
+        .collect();
+
+    Ok(Some(StuckDepositData {
+        tx_data: tx_data.clone(),
+        fee: bitcoin::Amount::from_sat(fee),
+        fee_rate,
+        target_fee_rate,
+        cpfp_outpoints,
+        cpfp_fee: bitcoin::Amount::from_sat(package_fee.saturating_sub(fee)),
+    }))
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub enum DepositStates {
     Created(CreatedDepositState),
@@ -339,3 +431,237 @@ pub struct ClaimingDepositState {
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct TimedOutDepositState {}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        Address, Network, ScriptBuf, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    };
+    use fedimint_core::txoproof::TxOutProof;
+    use fedimint_core::{apply, async_trait_maybe_send, Feerate};
+
+    use super::{fetch_stuck_deposit, CPFP_CHILD_VSIZE};
+    use crate::chain_source::{DynChainSource, IChainSource};
+    use crate::BitcoinTransactionData;
+
+    #[derive(Debug)]
+    struct FakeChainSource {
+        transactions: Vec<Transaction>,
+        confirmed: bool,
+        fee_rate: Feerate,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IChainSource for FakeChainSource {
+        async fn watch_script_history(&self, _script: &ScriptBuf) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        async fn get_script_history(
+            &self,
+            _script: &ScriptBuf,
+        ) -> anyhow::Result<Vec<Transaction>> {
+            Ok(vec![])
+        }
+
+        async fn get_tx_block_height(&self, _txid: &Txid) -> anyhow::Result<Option<u64>> {
+            Ok(self.confirmed.then_some(100))
+        }
+
+        async fn get_transaction(&self, txid: &Txid) -> anyhow::Result<Option<Transaction>> {
+            Ok(self
+                .transactions
+                .iter()
+                .find(|tx| tx.txid() == *txid)
+                .cloned())
+        }
+
+        async fn get_txout_proof(&self, _txid: Txid) -> anyhow::Result<TxOutProof> {
+            unimplemented!()
+        }
+
+        async fn submit_transaction(&self, _transaction: Transaction) {}
+
+        async fn get_fee_rate(&self, _confirmation_target: u16) -> anyhow::Result<Option<Feerate>> {
+            Ok(Some(self.fee_rate))
+        }
+    }
+
+    fn script() -> ScriptBuf {
+        ScriptBuf::new_v0_p2wpkh(&WPubkeyHash::all_zeros())
+    }
+
+    fn transaction(inputs: &[bitcoin::OutPoint], outputs: &[u64]) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    script_sig: ScriptBuf::new(),
+                    sequence: bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|value| TxOut {
+                    value: *value,
+                    script_pubkey: script(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns a funding transaction and a deposit transaction spending it
+    /// that pays a fee of 100 sats, with the deposit at index 0 and the change
+    /// at index 1
+    fn deposit() -> (Transaction, BitcoinTransactionData) {
+        let funding = transaction(&[], &[100_000]);
+        let deposit = transaction(
+            &[bitcoin::OutPoint {
+                txid: funding.txid(),
+                vout: 0,
+            }],
+            &[50_000, 49_900],
+        );
+
+        (
+            funding,
+            BitcoinTransactionData {
+                btc_transaction: deposit,
+                out_idx: 0,
+            },
+        )
+    }
+
+    fn chain_source(
+        transactions: Vec<Transaction>,
+        confirmed: bool,
+        sats_per_kvb: u64,
+    ) -> DynChainSource {
+        FakeChainSource {
+            transactions,
+            confirmed,
+            fee_rate: Feerate { sats_per_kvb },
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn deposits_paying_less_than_the_target_fee_rate_are_stuck() {
+        let (funding, tx_data) = deposit();
+        let txid = tx_data.btc_transaction.txid();
+        let vsize = tx_data.btc_transaction.vsize() as u64;
+
+        let stuck = fetch_stuck_deposit(&chain_source(vec![funding], false, 10_000), &tx_data)
+            .await
+            .expect("Failed to fetch fee data")
+            .expect("Deposit is stuck");
+
+        assert_eq!(stuck.fee, bitcoin::Amount::from_sat(100));
+        assert_eq!(stuck.fee_rate.sats_per_kvb, 100 * 1000 / vsize);
+        assert_eq!(stuck.target_fee_rate.sats_per_kvb, 10_000);
+        assert_eq!(
+            stuck.cpfp_outpoints,
+            vec![bitcoin::OutPoint { txid, vout: 1 }]
+        );
+        assert_eq!(
+            stuck.cpfp_fee,
+            bitcoin::Amount::from_sat((vsize + CPFP_CHILD_VSIZE) * 10 - 100)
+        );
+
+        // The change output pays the fee of the child spending it
+        let destination = Address::from_script(&script(), Network::Regtest).expect("Valid script");
+        let psbt = stuck
+            .cpfp_psbt(stuck.cpfp_outpoints[0], &destination)
+            .expect("Failed to build the child transaction");
+        assert_eq!(
+            psbt.unsigned_tx.input[0].previous_output,
+            stuck.cpfp_outpoints[0]
+        );
+        assert_eq!(
+            psbt.unsigned_tx.output[0].value,
+            49_900 - stuck.cpfp_fee.to_sat()
+        );
+        assert_eq!(
+            psbt.inputs[0].witness_utxo,
+            Some(tx_data.btc_transaction.output[1].clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn child_transactions_can_only_spend_outputs_paying_the_fee() {
+        let (funding, tx_data) = deposit();
+        let txid = tx_data.btc_transaction.txid();
+        let destination = Address::from_script(&script(), Network::Regtest).expect("Valid script");
+
+        let stuck = fetch_stuck_deposit(
+            &chain_source(vec![funding.clone()], false, 10_000),
+            &tx_data,
+        )
+        .await
+        .expect("Failed to fetch fee data")
+        .expect("Deposit is stuck");
+        // The deposit output belongs to the federation
+        assert!(stuck
+            .cpfp_psbt(bitcoin::OutPoint { txid, vout: 0 }, &destination)
+            .is_err());
+
+        // The change can't pay a fee exceeding its value
+        let stuck = fetch_stuck_deposit(&chain_source(vec![funding], false, 1_000_000), &tx_data)
+            .await
+            .expect("Failed to fetch fee data")
+            .expect("Deposit is stuck");
+        assert!(stuck
+            .cpfp_psbt(bitcoin::OutPoint { txid, vout: 1 }, &destination)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn deposits_are_not_stuck_unless_fee_data_shows_it() {
+        let (funding, tx_data) = deposit();
+
+        // Paying the target fee rate
+        assert_eq!(
+            fetch_stuck_deposit(&chain_source(vec![funding.clone()], false, 100), &tx_data)
+                .await
+                .expect("Failed to fetch fee data"),
+            None
+        );
+        // Already confirmed
+        assert_eq!(
+            fetch_stuck_deposit(&chain_source(vec![funding.clone()], true, 10_000), &tx_data)
+                .await
+                .expect("Failed to fetch fee data"),
+            None
+        );
+        // The spent transaction is unknown to the backend
+        assert_eq!(
+            fetch_stuck_deposit(&chain_source(vec![], false, 10_000), &tx_data)
+                .await
+                .expect("Failed to fetch fee data"),
+            None
+        );
+
+        // A transaction spending more than its inputs is invalid
+        let overspending = transaction(
+            &[bitcoin::OutPoint {
+                txid: funding.txid(),
+                vout: 0,
+            }],
+            &[100_001],
+        );
+        assert!(fetch_stuck_deposit(
+            &chain_source(vec![funding], false, 10_000),
+            &BitcoinTransactionData {
+                btc_transaction: overspending,
+                out_idx: 0,
+            }
+        )
+        .await
+        .is_err());
+    }
+}
@@ -9,33 +9,36 @@
 #![allow(clippy::unused_async)]
 
 pub mod api;
+pub mod chain_source;
 
 pub mod client_db;
 mod deposit;
+pub mod payjoin;
 mod withdraw;
 
 use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{anyhow, bail, ensure, Context as AnyhowContext};
 use async_stream::stream;
+use bitcoin::absolute::LockTime;
 use bitcoin::address::NetworkUnchecked;
-use bitcoin::{Address, Network};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::{Address, Network, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
 use client_db::DbKeyPrefix;
 use fedimint_api_client::api::DynModuleApi;
-use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
 use fedimint_client::module::init::{ClientModuleInit, ClientModuleInitArgs};
 use fedimint_client::module::recovery::NoModuleBackup;
 use fedimint_client::module::{ClientContext, ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationMeta, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{Context, DynState, ModuleNotifier, State, StateTransition};
 use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
 use fedimint_client::{sm_enum_variant_translation, DynGlobalClientContext};
 use fedimint_core::bitcoin_migration::checked_address_to_unchecked_address;
-use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, OperationId};
+use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId, ModuleKind, OperationId};
 use fedimint_core::db::{
     AutocommitError, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
 };
@@ -44,8 +47,9 @@ use fedimint_core::envs::BitcoinRpcConfig;
 use fedimint_core::module::{
     ApiVersion, CommonModuleInit, ModuleCommon, ModuleInit, MultiApiVersion,
 };
+use fedimint_core::runtime::timeout;
 use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
-use fedimint_core::{apply, async_trait_maybe_send, Amount, OutPoint};
+use fedimint_core::{apply, async_trait_maybe_send, push_db_pair_items, Amount, Feerate, OutPoint};
 use fedimint_wallet_common::config::{FeeConsensus, WalletClientConfig};
 use fedimint_wallet_common::tweakable::Tweakable;
 pub use fedimint_wallet_common::*;
@@ -54,14 +58,30 @@ use rand::{thread_rng, Rng};
 use secp256k1::{All, Secp256k1};
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
+use tracing::{debug, warn};
 
 use crate::api::WalletFederationApi;
-use crate::client_db::NextPegInTweakIndexKey;
-use crate::deposit::{CreatedDepositState, DepositStateMachine, DepositStates};
-use crate::withdraw::{CreatedWithdrawState, WithdrawStateMachine, WithdrawStates};
+use crate::chain_source::{ChainSourceConfig, DynChainSource};
+use crate::client_db::{
+    NextPegInTweakIndexKey, PayjoinSession, PayjoinSessionKey, PayjoinSessionKeyPrefix,
+};
+use crate::deposit::{
+    fetch_stuck_deposit, CreatedDepositState, DepositStateMachine, DepositStates,
+};
+use crate::payjoin::{await_payjoin_broadcast, pending_payjoin_sessions};
+use crate::withdraw::{
+    CreatedScriptWithdrawState, CreatedWithdrawState, WithdrawStateMachine, WithdrawStates,
+};
 
 const WALLET_TWEAK_CHILD_ID: ChildId = ChildId(0);
 
+/// Interval at which the fee of an unconfirmed deposit is compared against the
+/// current fee estimate
+const STUCK_DEPOSIT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Module API version that introduced peg-out batching
+const PEG_OUT_BATCH_API_VERSION: ApiVersion = ApiVersion::new(0, 1);
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct BitcoinTransactionData {
     /// The bitcoin transaction is saved as soon as we see it so the transaction
@@ -71,10 +91,85 @@ pub struct BitcoinTransactionData {
     pub out_idx: u32,
 }
 
+/// Fee data of an unconfirmed deposit transaction that pays less than the fee
+/// rate currently needed to confirm within [`CONFIRMATION_TARGET`] blocks.
+///
+/// The deposit output is locked to the federation's descriptor and can't be
+/// spent by the client, so the fee can only be bumped by the depositor
+/// spending one of the other outputs of the deposit transaction in a
+/// child-pays-for-parent transaction, see [`Self::cpfp_psbt`].
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct StuckDepositData {
+    pub tx_data: BitcoinTransactionData,
+    /// Fee paid by the deposit transaction
+    pub fee: bitcoin::Amount,
+    pub fee_rate: Feerate,
+    pub target_fee_rate: Feerate,
+    /// Outputs of the deposit transaction other than the deposit itself, one
+    /// of them is usually the change of the depositor's wallet
+    pub cpfp_outpoints: Vec<bitcoin::OutPoint>,
+    /// Fee a child transaction of one input and one output has to pay for the
+    /// deposit to confirm at the target fee rate
+    pub cpfp_fee: bitcoin::Amount,
+}
+
+impl StuckDepositData {
+    /// Builds an unsigned child transaction sending `outpoint`, one of
+    /// [`Self::cpfp_outpoints`], to `destination` while paying
+    /// [`Self::cpfp_fee`]. It has to be signed by the wallet that owns
+    /// `outpoint`.
+    pub fn cpfp_psbt(
+        &self,
+        outpoint: bitcoin::OutPoint,
+        destination: &Address,
+    ) -> anyhow::Result<PartiallySignedTransaction> {
+        ensure!(
+            self.cpfp_outpoints.contains(&outpoint),
+            "Outpoint is not a spendable output of the deposit transaction"
+        );
+
+        let spent_output = self.tx_data.btc_transaction.output[outpoint.vout as usize].clone();
+        let script_pubkey = destination.script_pubkey();
+        let value = spent_output
+            .value
+            .checked_sub(self.cpfp_fee.to_sat())
+            .filter(|value| *value >= script_pubkey.dust_value().to_sat())
+            .context("Output is too small to pay the fee")?;
+
+        let transaction = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey,
+            }],
+        };
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(transaction)?;
+        if spent_output.script_pubkey.is_witness_program() {
+            psbt.inputs[0].witness_utxo = Some(spent_output);
+        } else {
+            psbt.inputs[0].non_witness_utxo = Some(self.tx_data.btc_transaction.clone());
+        }
+
+        Ok(psbt)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum DepositState {
     WaitingForTransaction,
     WaitingForConfirmation(BitcoinTransactionData),
+    /// The deposit transaction is unconfirmed and pays a fee rate too low to
+    /// confirm in a timely manner. Emitted again whenever the fee data
+    /// changes, followed by [`DepositState::Confirmed`] once it confirms.
+    Stuck(StuckDepositData),
     Confirmed(BitcoinTransactionData),
     Claimed(BitcoinTransactionData),
     Failed(String),
@@ -83,6 +178,9 @@ pub enum DepositState {
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum WithdrawState {
     Created,
+    /// The peg-out was paid by a batch transaction together with other
+    /// peg-outs, always followed by [`WithdrawState::Succeeded`]
+    Batched(bitcoin::OutPoint),
     Succeeded(bitcoin::Txid),
     Failed(String),
     // TODO: track refund
@@ -114,13 +212,15 @@ where
     }
 }
 
+/// Initializer of the wallet client, which by default follows peg-ins using the
+/// bitcoin backend configured by the federation
 #[derive(Debug, Clone, Default)]
 // TODO: should probably move to DB
-pub struct WalletClientInit(pub Option<BitcoinRpcConfig>);
+pub struct WalletClientInit(pub Option<ChainSourceConfig>);
 
 impl WalletClientInit {
-    pub fn new(rpc: BitcoinRpcConfig) -> Self {
-        Self(Some(rpc))
+    pub fn new(chain_source: impl Into<ChainSourceConfig>) -> Self {
+        Self(Some(chain_source.into()))
     }
 }
 
@@ -147,6 +247,16 @@ impl ModuleInit for WalletClientInit {
                             .insert("NextPegInTweakIndex".to_string(), Box::new(index));
                     }
                 }
+                DbKeyPrefix::PayjoinSession => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PayjoinSessionKeyPrefix,
+                        PayjoinSessionKey,
+                        PayjoinSession,
+                        wallet_client_items,
+                        "Payjoin Sessions"
+                    );
+                }
             }
         }
 
@@ -164,10 +274,10 @@ impl ClientModuleInit for WalletClientInit {
     }
 
     async fn init(&self, args: &ClientModuleInitArgs<Self>) -> anyhow::Result<Self::Module> {
-        let rpc_config = self
+        let chain_source = self
             .0
             .clone()
-            .unwrap_or(WalletClientModule::get_rpc_config(args.cfg()));
+            .unwrap_or_else(|| WalletClientModule::get_rpc_config(args.cfg()).into());
 
         // FIXME: reactivate key derivation once we implement recovery
         let random_root_secret = {
@@ -175,14 +285,26 @@ impl ClientModuleInit for WalletClientInit {
             DerivableSecret::new_root(&key, &salt)
         };
 
+        let chain_source = chain_source.build(TaskGroup::new().make_handle())?;
+
+        for operation_id in pending_payjoin_sessions(args.db()).await {
+            let db = args.db().clone();
+            let chain_source = chain_source.clone();
+            args.task_group()
+                .spawn_cancellable("await payjoin broadcast", async move {
+                    await_payjoin_broadcast(db, chain_source, operation_id).await;
+                });
+        }
+
         Ok(WalletClientModule {
             cfg: args.cfg().clone(),
             module_root_secret: random_root_secret,
             module_api: args.module_api().clone(),
             notifier: args.notifier().clone(),
-            rpc: create_bitcoind(&rpc_config, TaskGroup::new().make_handle())?,
+            chain_source,
             secp: Default::default(),
             client_ctx: args.context(),
+            task_group: args.task_group().clone(),
         })
     }
 }
@@ -193,6 +315,11 @@ pub struct WalletOperationMeta {
     pub extra_meta: serde_json::Value,
 }
 
+impl OperationMeta for WalletOperationMeta {
+    const KIND: ModuleKind = WalletCommonInit::KIND;
+    const VERSION: u16 = 0;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum WalletOperationMetaVariant {
@@ -212,6 +339,14 @@ pub enum WalletOperationMetaVariant {
         rbf: Rbf,
         change: Vec<OutPoint>,
     },
+
+    WithdrawToScript {
+        script_pubkey: ScriptBuf,
+        #[serde(with = "bitcoin::amount::serde::as_sat")]
+        amount: bitcoin::Amount,
+        fee: PegOutFees,
+        change: Vec<OutPoint>,
+    },
 }
 
 #[derive(Debug)]
@@ -220,9 +355,10 @@ pub struct WalletClientModule {
     module_root_secret: DerivableSecret,
     module_api: DynModuleApi,
     notifier: ModuleNotifier<WalletClientStates>,
-    rpc: DynBitcoindRpc,
+    chain_source: DynChainSource,
     secp: Secp256k1<All>,
     client_ctx: ClientContext<Self>,
+    task_group: TaskGroup,
 }
 
 impl ClientModule for WalletClientModule {
@@ -234,10 +370,14 @@ impl ClientModule for WalletClientModule {
 
     fn context(&self) -> Self::ModuleStateMachineContext {
         WalletClientContext {
-            rpc: self.rpc.clone(),
+            chain_source: self.chain_source.clone(),
             wallet_descriptor: self.cfg.peg_in_descriptor.clone(),
             wallet_decoder: self.decoder(),
             secp: Default::default(),
+            supports_peg_out_batches: self
+                .client_ctx
+                .module_api_version()
+                .supports(PEG_OUT_BATCH_API_VERSION),
         }
     }
 
@@ -252,10 +392,12 @@ impl ClientModule for WalletClientModule {
 
 #[derive(Debug, Clone)]
 pub struct WalletClientContext {
-    rpc: DynBitcoindRpc,
+    chain_source: DynChainSource,
     wallet_descriptor: PegInDescriptor,
     wallet_decoder: Decoder,
     secp: Secp256k1<All>,
+    /// Whether the federation can report the output paying a batched peg-out
+    supports_peg_out_batches: bool,
 }
 
 impl Context for WalletClientContext {}
@@ -283,9 +425,27 @@ impl WalletClientModule {
         self.cfg.fee_consensus
     }
 
+    /// The descriptor new deposits should be sent to: the taproot descriptor
+    /// once the federation activated it, the segwit v0 descriptor from the
+    /// config otherwise. The deposit state machine watches both, so a deposit
+    /// is claimed no matter which one was used.
+    async fn deposit_descriptor(&self) -> PegInDescriptor {
+        match self.module_api.fetch_module_consensus_version().await {
+            Ok(version) if version >= TAPROOT_MODULE_CONSENSUS_VERSION => {
+                taproot_peg_in_descriptor(&self.cfg.peg_in_descriptor)
+            }
+            Ok(_) => self.cfg.peg_in_descriptor.clone(),
+            Err(e) => {
+                debug!(%e, "Federation did not report its module consensus version, using segwit deposit address");
+                self.cfg.peg_in_descriptor.clone()
+            }
+        }
+    }
+
     pub async fn get_deposit_address_inner(
         &self,
         valid_until: SystemTime,
+        peg_in_descriptor: &PegInDescriptor,
         dbtx: &mut DatabaseTransaction<'_>,
     ) -> (OperationId, WalletClientStates, Address) {
         let secret_tweak_key = self
@@ -297,9 +457,7 @@ impl WalletClientModule {
         let public_tweak_key = secret_tweak_key.public_key();
         let operation_id = OperationId(public_tweak_key.x_only_public_key().0.serialize()); // TODO: make hash?
 
-        let address = self
-            .cfg
-            .peg_in_descriptor
+        let address = peg_in_descriptor
             .tweak(&public_tweak_key, secp256k1::SECP256K1)
             .address(self.cfg.network)
             .unwrap();
@@ -363,6 +521,48 @@ impl WalletClientModule {
         })
     }
 
+    /// Like [`Self::get_withdraw_fees`] for a peg-out to an arbitrary script,
+    /// see [`Self::withdraw_to_script`]
+    pub async fn get_withdraw_script_fees(
+        &self,
+        script_pubkey: &ScriptBuf,
+        amount: bitcoin::Amount,
+    ) -> anyhow::Result<PegOutFees> {
+        self.module_api
+            .fetch_peg_out_script_fees(script_pubkey, amount)
+            .await?
+            .context("Federation didn't return peg-out fees")
+    }
+
+    pub fn create_withdraw_script_output(
+        &self,
+        operation_id: OperationId,
+        peg_out: PegOutScript,
+    ) -> anyhow::Result<ClientOutput<WalletOutput, WalletClientStates>> {
+        peg_out.validate()?;
+
+        let script_pubkey = peg_out.script_pubkey.clone();
+        let output = WalletOutput::new_v0_peg_out_script(peg_out);
+
+        let amount = output.maybe_v0_ref().expect("v0 output").amount().into();
+
+        let sm_gen = move |txid, out_idx| {
+            vec![WalletClientStates::Withdraw(WithdrawStateMachine {
+                operation_id,
+                state: WithdrawStates::CreatedScript(CreatedScriptWithdrawState {
+                    fm_outpoint: OutPoint { txid, out_idx },
+                    script_pubkey: script_pubkey.clone(),
+                }),
+            })]
+        };
+
+        Ok(ClientOutput::<WalletOutput, WalletClientStates> {
+            output,
+            amount,
+            state_machines: Arc::new(sm_gen),
+        })
+    }
+
     pub fn create_rbf_withdraw_output(
         &self,
         operation_id: OperationId,
@@ -394,19 +594,25 @@ impl WalletClientModule {
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, Address)> {
         let extra_meta = serde_json::to_value(extra_meta).expect("extra meta is serializable");
+        let peg_in_descriptor = self.deposit_descriptor().await;
 
         let (operation_id, address) = self
             .client_ctx
             .module_autocommit(
                 |dbtx, _| {
                     let extra_meta_inner = extra_meta.clone();
+                    let peg_in_descriptor = &peg_in_descriptor;
                     Box::pin(async move {
                         let (operation_id, sm, address) = self
-                            .get_deposit_address_inner(valid_until, &mut dbtx.module_dbtx())
+                            .get_deposit_address_inner(
+                                valid_until,
+                                peg_in_descriptor,
+                                &mut dbtx.module_dbtx(),
+                            )
                             .await;
 
                         // Begin watching the script address
-                        self.rpc
+                        self.chain_source
                             .watch_script_history(&address.script_pubkey())
                             .await?;
 
@@ -457,7 +663,7 @@ impl WalletClientModule {
             bail!("Operation is not a wallet operation");
         }
 
-        let operation_meta = operation_log_entry.meta::<WalletOperationMeta>();
+        let operation_meta = operation_log_entry.meta_typed::<WalletOperationMeta>()?;
 
         if !matches!(
             operation_meta.variant,
@@ -470,6 +676,7 @@ impl WalletClientModule {
         let tx_subscriber = self.client_ctx.transaction_updates(operation_id).await;
 
         let client_ctx = self.client_ctx.clone();
+        let chain_source = self.chain_source.clone();
         Ok(
             operation_log_entry.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
                 stream! {
@@ -500,12 +707,24 @@ impl WalletClientModule {
                         None => return,
                     };
 
-                    let claiming = match next_deposit_state(&mut operation_stream).await {
-                        Some(DepositStates::Claiming(claiming)) => claiming,
-                        Some(s) => {
-                            panic!("Unexpected state {s:?}")
-                        },
-                        None => return,
+                    let mut stuck_deposit = None;
+                    let claiming = loop {
+                        let next_state = next_deposit_state(&mut operation_stream);
+                        match timeout(STUCK_DEPOSIT_CHECK_INTERVAL, next_state).await {
+                            Ok(Some(DepositStates::Claiming(claiming))) => break claiming,
+                            Ok(Some(s)) => {
+                                panic!("Unexpected state {s:?}")
+                            },
+                            Ok(None) => return,
+                            Err(_) => match fetch_stuck_deposit(&chain_source, &tx_data).await {
+                                Ok(Some(stuck)) if stuck_deposit.as_ref() != Some(&stuck) => {
+                                    stuck_deposit = Some(stuck.clone());
+                                    yield DepositState::Stuck(stuck);
+                                },
+                                Ok(_) => {},
+                                Err(e) => warn!("Failed to check deposit transaction fee: {e:?}"),
+                            },
+                        }
                     };
                     yield DepositState::Confirmed(tx_data.clone());
 
@@ -567,6 +786,61 @@ impl WalletClientModule {
         }
     }
 
+    /// Whether the federation accepts peg-outs to scripts, which requires all
+    /// guardians to have signaled support for them
+    pub async fn is_script_peg_out_active(&self) -> anyhow::Result<bool> {
+        self.client_ctx
+            .is_consensus_feature_active(&script_peg_out_feature())
+            .await
+    }
+
+    /// Attempt to withdraw to an arbitrary output script instead of an
+    /// address, e.g. directly into a multisig or timelocked script. The fees
+    /// can be fetched using [`Self::get_withdraw_script_fees`], see
+    /// [`Self::withdraw`].
+    ///
+    /// Fails if the federation doesn't accept peg-outs to scripts yet, see
+    /// [`Self::is_script_peg_out_active`].
+    pub async fn withdraw_to_script<M: Serialize + MaybeSend + MaybeSync>(
+        &self,
+        peg_out: PegOutScript,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId> {
+        ensure!(
+            self.is_script_peg_out_active().await?,
+            WalletOutputError::ScriptPegOutNotActive
+        );
+
+        let operation_id = OperationId(thread_rng().gen());
+
+        let script_pubkey = peg_out.script_pubkey.clone();
+        let (amount, fee) = (peg_out.amount, peg_out.fees);
+
+        let withdraw_output = self.create_withdraw_script_output(operation_id, peg_out)?;
+        let tx_builder = TransactionBuilder::new()
+            .with_output(self.client_ctx.make_client_output(withdraw_output));
+
+        let extra_meta = serde_json::to_value(extra_meta).expect("Failed to serialize extra meta");
+        self.client_ctx
+            .finalize_and_submit_transaction(
+                operation_id,
+                WalletCommonInit::KIND.as_str(),
+                move |_, change| WalletOperationMeta {
+                    variant: WalletOperationMetaVariant::WithdrawToScript {
+                        script_pubkey: script_pubkey.clone(),
+                        amount,
+                        fee,
+                        change,
+                    },
+                    extra_meta: extra_meta.clone(),
+                },
+                tx_builder,
+            )
+            .await?;
+
+        Ok(operation_id)
+    }
+
     /// Attempt to increase the fee of a onchain withdraw transaction using
     /// replace by fee (RBF).
     /// This can prevent transactions from getting stuck
@@ -615,10 +889,11 @@ impl WalletClientModule {
             bail!("Operation is not a wallet operation");
         }
 
-        let operation_meta = operation.meta::<WalletOperationMeta>();
+        let operation_meta = operation.meta_typed::<WalletOperationMeta>()?;
 
         let (WalletOperationMetaVariant::Withdraw { change, .. }
-        | WalletOperationMetaVariant::RbfWithdraw { change, .. }) = operation_meta.variant
+        | WalletOperationMetaVariant::RbfWithdraw { change, .. }
+        | WalletOperationMetaVariant::WithdrawToScript { change, .. }) = operation_meta.variant
         else {
             bail!("Operation is not a withdraw operation");
         };
@@ -630,7 +905,7 @@ impl WalletClientModule {
             operation.outcome_or_updates(&self.client_ctx.global_db(), operation_id, move || {
                 stream! {
                     match next_withdraw_state(&mut operation_stream).await {
-                        Some(WithdrawStates::Created(_)) => {
+                        Some(WithdrawStates::Created(_) | WithdrawStates::CreatedScript(_)) => {
                             yield WithdrawState::Created;
                         },
                         Some(s) => {
@@ -655,6 +930,10 @@ impl WalletClientModule {
                         Some(WithdrawStates::Success(inner)) => {
                             yield WithdrawState::Succeeded(inner.txid);
                         },
+                        Some(WithdrawStates::Batched(inner)) => {
+                            yield WithdrawState::Batched(inner.outpoint);
+                            yield WithdrawState::Succeeded(inner.outpoint.txid);
+                        },
                         Some(s) => {
                             panic!("Unexpected state {s:?}")
                         },
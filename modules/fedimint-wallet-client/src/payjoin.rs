@@ -0,0 +1,334 @@
+//! Receiving peg-ins as payjoin transactions (BIP 78)
+//!
+//! In a payjoin the receiver adds inputs of its own to the transaction of the
+//! sender, which breaks the common input ownership heuristic chain analysis
+//! relies on. The client doesn't run an HTTP server: the application serves
+//! the payjoin endpoint advertised in the `pj` parameter of the BIP 21 URI of
+//! a deposit address and passes the original PSBT of the sender to
+//! [`WalletClientModule::process_payjoin_request`]. The value of the inputs
+//! the application contributes is added to the deposit, so it is pegged in
+//! together with the payment of the sender.
+//!
+//! If the sender never broadcasts the payjoin transaction, the client
+//! broadcasts the original transaction of the sender after
+//! [`PAYJOIN_FALLBACK_DELAY`], so that the deposit is received either way.
+
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context};
+use bitcoin::psbt::{self, PartiallySignedTransaction};
+use bitcoin::{ScriptBuf, Transaction, TxIn, TxOut, Witness};
+use fedimint_core::core::OperationId;
+use fedimint_core::db::{Database, IDatabaseTransactionOpsCoreTyped};
+use fedimint_core::task::sleep;
+use futures::StreamExt;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::chain_source::DynChainSource;
+use crate::client_db::{
+    PayjoinSession, PayjoinSessionKey, PayjoinSessionKeyPrefix, PayjoinSessionState,
+};
+use crate::{WalletClientModule, WalletOperationMeta, WalletOperationMetaVariant};
+
+/// Time after which the original transaction of the sender is broadcast if the
+/// sender didn't broadcast the payjoin transaction
+pub const PAYJOIN_FALLBACK_DELAY: Duration = Duration::from_secs(2 * 60);
+
+/// Interval in which the chain source is polled for the transactions of a
+/// payjoin session once the fallback delay passed
+const PAYJOIN_BROADCAST_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// An output of the receiver's on-chain wallet to add to the payjoin
+/// transaction
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PayjoinContribution {
+    pub outpoint: bitcoin::OutPoint,
+    /// The output being spent, its value minus the fee for the additional input
+    /// is added to the deposit
+    pub txout: TxOut,
+    /// Virtual size of the input once it is signed, e.g. 68 vbytes for P2WPKH
+    pub input_vsize: u64,
+}
+
+impl WalletClientModule {
+    /// Handles the payjoin request of a sender paying the address of the
+    /// deposit `operation_id`, see [`Self::get_deposit_address`].
+    ///
+    /// Returns the payjoin proposal, which contains the inputs of
+    /// `contributions` unsigned. The application has to sign and finalize them
+    /// with the wallet owning them before responding to the sender with the
+    /// proposal. Only one proposal is made per deposit, so that senders can't
+    /// probe the wallet of the receiver.
+    pub async fn process_payjoin_request(
+        &self,
+        operation_id: OperationId,
+        original: PartiallySignedTransaction,
+        contributions: Vec<PayjoinContribution>,
+    ) -> anyhow::Result<PartiallySignedTransaction> {
+        let operation = self
+            .client_ctx
+            .get_operation(operation_id)
+            .await
+            .context("Operation not found")?;
+
+        let WalletOperationMetaVariant::Deposit {
+            address,
+            expires_at,
+        } = operation.meta_typed::<WalletOperationMeta>()?.variant
+        else {
+            bail!("Operation is not a deposit operation");
+        };
+
+        ensure!(
+            fedimint_core::time::now() < expires_at,
+            "Deposit address expired"
+        );
+
+        let deposit_script = address.assume_checked().script_pubkey();
+
+        ensure!(
+            self.chain_source
+                .get_script_history(&deposit_script)
+                .await?
+                .is_empty(),
+            "Deposit address already received a transaction"
+        );
+
+        let (proposal, original_tx) =
+            build_payjoin_proposal(original, &deposit_script, &contributions)?;
+        let proposal_txid = proposal.unsigned_tx.txid();
+
+        let session = PayjoinSession {
+            original_tx,
+            proposal_txid,
+            fallback_at: fedimint_core::time::now() + PAYJOIN_FALLBACK_DELAY,
+            state: PayjoinSessionState::Proposed,
+        };
+
+        let mut dbtx = self.client_ctx.module_db().begin_transaction().await;
+        if dbtx
+            .insert_entry(&PayjoinSessionKey(operation_id), &session)
+            .await
+            .is_some()
+        {
+            bail!("A payjoin was already proposed for this deposit");
+        }
+        dbtx.commit_tx_result().await?;
+
+        info!(%operation_id, %proposal_txid, "Proposed payjoin for deposit");
+
+        let db = self.client_ctx.module_db().clone();
+        let chain_source = self.chain_source.clone();
+        self.task_group
+            .spawn_cancellable("await payjoin broadcast", async move {
+                await_payjoin_broadcast(db, chain_source, operation_id).await;
+            });
+
+        Ok(proposal)
+    }
+
+    /// Returns the payjoin sessions of all deposits a payjoin was proposed for
+    pub async fn list_payjoin_sessions(&self) -> Vec<(OperationId, PayjoinSession)> {
+        self.client_ctx
+            .module_db()
+            .begin_transaction_nc()
+            .await
+            .find_by_prefix(&PayjoinSessionKeyPrefix)
+            .await
+            .map(|(key, session)| (key.0, session))
+            .collect()
+            .await
+    }
+}
+
+/// Checks the original PSBT of the sender and adds `contributions` to it.
+/// Returns the unsigned payjoin proposal and the signed original transaction.
+fn build_payjoin_proposal(
+    original: PartiallySignedTransaction,
+    deposit_script: &ScriptBuf,
+    contributions: &[PayjoinContribution],
+) -> anyhow::Result<(PartiallySignedTransaction, Transaction)> {
+    ensure!(!original.inputs.is_empty(), "Original PSBT has no inputs");
+
+    let mut input_value = 0;
+    for (psbt_input, txin) in original.inputs.iter().zip(&original.unsigned_tx.input) {
+        ensure!(
+            psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some(),
+            "Inputs of the original PSBT have to be finalized"
+        );
+
+        let spent_output = match (&psbt_input.witness_utxo, &psbt_input.non_witness_utxo) {
+            (Some(txout), _) => txout.clone(),
+            (None, Some(tx)) => tx
+                .output
+                .get(txin.previous_output.vout as usize)
+                .context("Previous transaction of input is missing the spent output")?
+                .clone(),
+            (None, None) => bail!("Inputs of the original PSBT have to include the spent output"),
+        };
+        input_value += spent_output.value;
+    }
+
+    let original_tx = original.clone().extract_tx();
+    let output_value = original_tx
+        .output
+        .iter()
+        .map(|txout| txout.value)
+        .sum::<u64>();
+    let fee = input_value
+        .checked_sub(output_value)
+        .context("Original transaction spends more than its inputs")?;
+    let fee_rate = fee / original_tx.vsize() as u64;
+
+    let mut deposit_outputs = original_tx
+        .output
+        .iter()
+        .enumerate()
+        .filter(|(_, txout)| txout.script_pubkey == *deposit_script);
+    let (deposit_idx, _) = deposit_outputs
+        .next()
+        .context("Original transaction doesn't pay the deposit address")?;
+    ensure!(
+        deposit_outputs.next().is_none(),
+        "Original transaction pays the deposit address more than once"
+    );
+
+    let mut proposal = original;
+
+    // Per BIP 78 the sender re-adds the data of its inputs and outputs to the
+    // proposal before signing it
+    for psbt_input in &mut proposal.inputs {
+        *psbt_input = psbt::Input::default();
+    }
+    for psbt_output in &mut proposal.outputs {
+        *psbt_output = psbt::Output::default();
+    }
+
+    let sequence = proposal.unsigned_tx.input[0].sequence;
+
+    for contribution in contributions {
+        ensure!(
+            !proposal
+                .unsigned_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == contribution.outpoint),
+            "Contributed output {} is already spent by the transaction",
+            contribution.outpoint
+        );
+
+        let value = contribution
+            .txout
+            .value
+            .checked_sub(fee_rate * contribution.input_vsize)
+            .context("Contributed output is too small to pay for its input")?;
+        proposal.unsigned_tx.output[deposit_idx].value += value;
+
+        // Inputs are inserted at random positions, so that the receiver's inputs
+        // can't be told apart by their position
+        let idx = thread_rng().gen_range(0..=proposal.unsigned_tx.input.len());
+        proposal.unsigned_tx.input.insert(
+            idx,
+            TxIn {
+                previous_output: contribution.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
+            },
+        );
+        proposal.inputs.insert(
+            idx,
+            psbt::Input {
+                witness_utxo: Some(contribution.txout.clone()),
+                ..Default::default()
+            },
+        );
+    }
+
+    Ok((proposal, original_tx))
+}
+
+/// Waits until the sender broadcast either the payjoin or the original
+/// transaction of the session and broadcasts the original transaction itself
+/// once [`PayjoinSession::fallback_at`] passed
+pub(crate) async fn await_payjoin_broadcast(
+    db: Database,
+    chain_source: DynChainSource,
+    operation_id: OperationId,
+) {
+    let Some(session) = db
+        .begin_transaction_nc()
+        .await
+        .get_value(&PayjoinSessionKey(operation_id))
+        .await
+    else {
+        return;
+    };
+
+    if session.state != PayjoinSessionState::Proposed {
+        return;
+    }
+
+    if let Ok(delay) = session
+        .fallback_at
+        .duration_since(fedimint_core::time::now())
+    {
+        sleep(delay).await;
+    }
+
+    let original_txid = session.original_tx.txid();
+
+    let state = loop {
+        match (
+            chain_source.get_transaction(&session.proposal_txid).await,
+            chain_source.get_transaction(&original_txid).await,
+        ) {
+            (Ok(Some(_)), _) => break PayjoinSessionState::ProposalBroadcast,
+            (_, Ok(Some(_))) => break PayjoinSessionState::OriginalBroadcast,
+            (Ok(None), Ok(None)) => {
+                warn!(%operation_id, %original_txid, "Payjoin was not broadcast by the sender, broadcasting original transaction");
+                chain_source
+                    .submit_transaction(session.original_tx.clone())
+                    .await;
+                break PayjoinSessionState::FallbackBroadcast;
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                debug!(%operation_id, %e, "Failed to fetch payjoin transactions");
+            }
+        }
+
+        sleep(PAYJOIN_BROADCAST_CHECK_INTERVAL).await;
+    };
+
+    let mut dbtx = db.begin_transaction().await;
+    dbtx.insert_entry(
+        &PayjoinSessionKey(operation_id),
+        &PayjoinSession {
+            state: state.clone(),
+            ..session
+        },
+    )
+    .await;
+    if let Err(e) = dbtx.commit_tx_result().await {
+        warn!(%operation_id, %e, "Failed to update payjoin session");
+        return;
+    }
+
+    info!(%operation_id, ?state, "Payjoin session finished");
+}
+
+/// Returns the payjoin sessions that are waiting for a transaction to be
+/// broadcast, used to resume them when the client is started
+pub(crate) async fn pending_payjoin_sessions(db: &Database) -> Vec<OperationId> {
+    db.begin_transaction_nc()
+        .await
+        .find_by_prefix(&PayjoinSessionKeyPrefix)
+        .await
+        .filter_map(|(key, session)| {
+            std::future::ready((session.state == PayjoinSessionState::Proposed).then_some(key.0))
+        })
+        .collect()
+        .await
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use bitcoin::Txid;
+use bitcoin::{ScriptBuf, Txid};
 use fedimint_client::sm::{State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::OperationId;
@@ -8,8 +8,10 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
 use fedimint_core::OutPoint;
 use fedimint_wallet_common::WalletOutputOutcome;
+use serde::{Deserialize, Serialize};
 use tracing::debug;
 
+use crate::api::WalletFederationApi;
 use crate::WalletClientContext;
 
 const RETRY_DELAY: Duration = Duration::from_secs(1);
@@ -18,7 +20,10 @@ const RETRY_DELAY: Duration = Duration::from_secs(1);
 #[aquamarine::aquamarine]
 /// graph LR
 ///     Created --> Success
+///     Created --> Batched
 ///     Created --> Aborted
+///     CreatedScript --> Success
+///     CreatedScript --> Aborted
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct WithdrawStateMachine {
     pub(crate) operation_id: OperationId,
@@ -40,12 +45,25 @@ impl State for WithdrawStateMachine {
                         global_context.clone(),
                         context.clone(),
                         self.operation_id,
-                        created.clone(),
+                        created.fm_outpoint,
                     ),
                     |_dbtx, res, old_state| Box::pin(transition_withdraw_processed(res, old_state)),
                 )]
             }
-            WithdrawStates::Success(_) | WithdrawStates::Aborted(_) => {
+            WithdrawStates::CreatedScript(created) => {
+                vec![StateTransition::new(
+                    await_withdraw_processed(
+                        global_context.clone(),
+                        context.clone(),
+                        self.operation_id,
+                        created.fm_outpoint,
+                    ),
+                    |_dbtx, res, old_state| Box::pin(transition_withdraw_processed(res, old_state)),
+                )]
+            }
+            WithdrawStates::Success(_)
+            | WithdrawStates::Batched(_)
+            | WithdrawStates::Aborted(_) => {
                 vec![]
             }
         }
@@ -60,17 +78,39 @@ async fn await_withdraw_processed(
     global_context: DynGlobalClientContext,
     context: WalletClientContext,
     operation_id: OperationId,
-    created: CreatedWithdrawState,
-) -> Result<Txid, String> {
-    global_context
-        .await_tx_accepted(created.fm_outpoint.txid)
-        .await?;
+    fm_outpoint: OutPoint,
+) -> Result<WithdrawProcessed, String> {
+    global_context.await_tx_accepted(fm_outpoint.txid).await?;
+
+    // Batched peg-outs only get an outcome once the batch transaction was
+    // created at the end of the federation's batching window
+    while context.supports_peg_out_batches {
+        match global_context
+            .module_api()
+            .await_peg_out_outpoint(fm_outpoint)
+            .await
+        {
+            Ok(Some(outpoint)) => return Ok(WithdrawProcessed::Batched(outpoint)),
+            Ok(None) => break,
+            Err(e) => {
+                e.report_if_important();
+                debug!(
+                    error = %e,
+                    operation_id = %operation_id.fmt_short(),
+                    delay_secs =  RETRY_DELAY.as_secs_f64(),
+                    "Waiting before retry",
+                );
+
+                sleep(RETRY_DELAY).await;
+            }
+        }
+    }
 
     loop {
         match global_context
             .api()
             .await_output_outcome::<WalletOutputOutcome>(
-                created.fm_outpoint,
+                fm_outpoint,
                 Duration::MAX,
                 &context.wallet_decoder,
             )
@@ -79,7 +119,7 @@ async fn await_withdraw_processed(
             Ok(outcome) => {
                 return outcome
                     .ensure_v0_ref()
-                    .map(|outcome| outcome.0)
+                    .map(|outcome| WithdrawProcessed::Single(outcome.0))
                     .map_err(|e| e.to_string())
             }
             Err(e) => {
@@ -101,18 +141,35 @@ async fn await_withdraw_processed(
     }
 }
 
+/// How the peg-out was paid on-chain
+#[derive(Debug, Serialize, Deserialize)]
+enum WithdrawProcessed {
+    /// By a transaction paying only this peg-out
+    Single(Txid),
+    /// By the given output of a batch transaction
+    Batched(bitcoin::OutPoint),
+}
+
 async fn transition_withdraw_processed(
-    res: Result<Txid, String>,
+    res: Result<WithdrawProcessed, String>,
     old_state: WithdrawStateMachine,
 ) -> WithdrawStateMachine {
     assert!(
-        matches!(old_state.state, WithdrawStates::Created(_)),
-        "Unexpected old state: got {:?}, expected Created",
+        matches!(
+            old_state.state,
+            WithdrawStates::Created(_) | WithdrawStates::CreatedScript(_)
+        ),
+        "Unexpected old state: got {:?}, expected Created or CreatedScript",
         old_state.state
     );
 
     let new_state = match res {
-        Ok(txid) => WithdrawStates::Success(SuccessWithdrawState { txid }),
+        Ok(WithdrawProcessed::Single(txid)) => {
+            WithdrawStates::Success(SuccessWithdrawState { txid })
+        }
+        Ok(WithdrawProcessed::Batched(outpoint)) => {
+            WithdrawStates::Batched(BatchedWithdrawState { outpoint })
+        }
         Err(error) => WithdrawStates::Aborted(AbortedWithdrawState { error }),
     };
 
@@ -127,6 +184,9 @@ pub enum WithdrawStates {
     Created(CreatedWithdrawState),
     Success(SuccessWithdrawState),
     Aborted(AbortedWithdrawState),
+    Batched(BatchedWithdrawState),
+    /// A peg-out to an arbitrary script, which is never batched
+    CreatedScript(CreatedScriptWithdrawState),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
@@ -134,11 +194,25 @@ pub struct CreatedWithdrawState {
     pub(crate) fm_outpoint: OutPoint,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct CreatedScriptWithdrawState {
+    pub(crate) fm_outpoint: OutPoint,
+    /// Script the peg-out pays to
+    pub(crate) script_pubkey: ScriptBuf,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct SuccessWithdrawState {
     pub(crate) txid: Txid,
 }
 
+/// The peg-out was paid by a transaction batching multiple peg-outs
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
+pub struct BatchedWithdrawState {
+    /// Output of the batch transaction paying the peg-out
+    pub(crate) outpoint: bitcoin::OutPoint,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Decodable, Encodable)]
 pub struct AbortedWithdrawState {
     pub(crate) error: String,
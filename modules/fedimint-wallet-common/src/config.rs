@@ -2,9 +2,10 @@ use std::collections::BTreeMap;
 
 use bitcoin::Network;
 use fedimint_core::core::ModuleKind;
-use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::envs::BitcoinRpcConfig;
 use fedimint_core::module::__reexports::serde_json;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::util::SafeUrl;
 use fedimint_core::{plugin_types_trait_impl_config, Feerate, PeerId};
 use miniscript::descriptor::{Wpkh, Wsh};
@@ -41,6 +42,7 @@ impl WalletGenParams {
                     .expect("Failed to parse default esplora server"),
                 },
                 fee_consensus: Default::default(),
+                peg_out_batch_window: 0,
             },
         }
     }
@@ -62,6 +64,9 @@ pub struct WalletGenParamsConsensus {
     ///
     /// Deposit fees in particular are a protection against dust attacks.
     pub fee_consensus: FeeConsensus,
+    /// See [`WalletConfigConsensus::peg_out_batch_window`].
+    #[serde(default)]
+    pub peg_out_batch_window: u32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -83,7 +88,7 @@ pub struct WalletConfigPrivate {
     pub peg_in_key: SecretKey,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Encodable, Decodable)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WalletConfigConsensus {
     /// Bitcoin network (e.g. testnet, bitcoin)
     pub network: Network,
@@ -107,6 +112,69 @@ pub struct WalletConfigConsensus {
     /// **This is only used by the client, the RPC used by the server is defined
     /// in [`WalletConfigLocal`].**
     pub client_default_bitcoin_rpc: BitcoinRpcConfig,
+    /// Number of consensus blocks over which peg-outs are accumulated before
+    /// being paid out in a single on-chain transaction. `0` disables batching
+    /// and every peg-out gets its own transaction.
+    ///
+    /// Only encoded if non-zero, so the consensus configs of federations
+    /// created before batching existed still decode and keep their hash.
+    #[serde(default)]
+    pub peg_out_batch_window: u32,
+}
+
+impl Encodable for WalletConfigConsensus {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut len = 0;
+        len += self.network.consensus_encode(writer)?;
+        len += self.peg_in_descriptor.consensus_encode(writer)?;
+        len += self.peer_peg_in_keys.consensus_encode(writer)?;
+        len += self.finality_delay.consensus_encode(writer)?;
+        len += self.default_fee.consensus_encode(writer)?;
+        len += self.fee_consensus.consensus_encode(writer)?;
+        len += self.client_default_bitcoin_rpc.consensus_encode(writer)?;
+        if self.peg_out_batch_window != 0 {
+            len += self.peg_out_batch_window.consensus_encode(writer)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for WalletConfigConsensus {
+    fn consensus_decode_from_finite_reader<R: std::io::Read>(
+        r: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let network = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let peg_in_descriptor = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let peer_peg_in_keys = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let finality_delay = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let default_fee = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let fee_consensus = Decodable::consensus_decode_from_finite_reader(r, modules)?;
+        let client_default_bitcoin_rpc =
+            Decodable::consensus_decode_from_finite_reader(r, modules)?;
+
+        // The consensus config is always decoded from its own byte vector, so any
+        // remaining bytes belong to the fields added later
+        let mut extension = vec![];
+        r.read_to_end(&mut extension)
+            .map_err(DecodeError::from_err)?;
+        let peg_out_batch_window = if extension.is_empty() {
+            0
+        } else {
+            Decodable::consensus_decode_from_finite_reader(&mut &extension[..], modules)?
+        };
+
+        Ok(Self {
+            network,
+            peg_in_descriptor,
+            peer_peg_in_keys,
+            finality_delay,
+            default_fee,
+            fee_consensus,
+            client_default_bitcoin_rpc,
+            peg_out_batch_window,
+        })
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
@@ -161,6 +229,7 @@ impl WalletConfig {
         bitcoin_rpc: BitcoinRpcConfig,
         client_default_bitcoin_rpc: BitcoinRpcConfig,
         fee_consensus: FeeConsensus,
+        peg_out_batch_window: u32,
     ) -> Self {
         let peg_in_descriptor = if pubkeys.len() == 1 {
             PegInDescriptor::Wpkh(
@@ -189,6 +258,7 @@ impl WalletConfig {
                 default_fee: Feerate { sats_per_kvb: 1000 },
                 fee_consensus,
                 client_default_bitcoin_rpc,
+                peg_out_batch_window,
             },
         }
     }
@@ -222,3 +292,97 @@ plugin_types_trait_impl_config!(
     WalletConfigConsensus,
     WalletClientConfig
 );
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin::hashes::sha256;
+    use bitcoin::Network;
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::envs::BitcoinRpcConfig;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::util::SafeUrl;
+    use fedimint_core::{Feerate, PeerId};
+    use secp256k1::SecretKey;
+
+    use super::{FeeConsensus, WalletConfig, WalletConfigConsensus};
+    use crate::keys::CompressedPublicKey;
+    use crate::PegInDescriptor;
+
+    /// Layout of [`WalletConfigConsensus`] before peg-out batching was added
+    #[derive(Encodable)]
+    struct WalletConfigConsensusV0 {
+        network: Network,
+        peg_in_descriptor: PegInDescriptor,
+        peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey>,
+        finality_delay: u32,
+        default_fee: Feerate,
+        fee_consensus: FeeConsensus,
+        client_default_bitcoin_rpc: BitcoinRpcConfig,
+    }
+
+    fn wallet_config(peg_out_batch_window: u32) -> WalletConfig {
+        let sk = SecretKey::from_slice(&[42; 32]).expect("valid key");
+        let pk = CompressedPublicKey::new(sk.public_key(&secp256k1::Secp256k1::new()));
+        let rpc = BitcoinRpcConfig {
+            kind: "esplora".to_string(),
+            url: SafeUrl::parse("http://127.0.0.1:50002/").expect("valid url"),
+        };
+
+        WalletConfig::new(
+            BTreeMap::from([(PeerId::from(0), pk)]),
+            sk,
+            1,
+            Network::Regtest,
+            10,
+            rpc.clone(),
+            rpc,
+            FeeConsensus::default(),
+            peg_out_batch_window,
+        )
+    }
+
+    #[test]
+    fn decodes_consensus_config_from_before_batching() {
+        let consensus = wallet_config(0).consensus;
+        let old = WalletConfigConsensusV0 {
+            network: consensus.network,
+            peg_in_descriptor: consensus.peg_in_descriptor.clone(),
+            peer_peg_in_keys: consensus.peer_peg_in_keys.clone(),
+            finality_delay: consensus.finality_delay,
+            default_fee: consensus.default_fee.clone(),
+            fee_consensus: consensus.fee_consensus,
+            client_default_bitcoin_rpc: consensus.client_default_bitcoin_rpc.clone(),
+        };
+
+        let decoded = WalletConfigConsensus::consensus_decode_vec(
+            old.consensus_encode_to_vec(),
+            &ModuleDecoderRegistry::default(),
+        )
+        .expect("pre-batching config decodes");
+
+        assert_eq!(decoded.peg_out_batch_window, 0);
+        assert_eq!(
+            decoded.consensus_hash::<sha256::Hash>(),
+            old.consensus_hash::<sha256::Hash>()
+        );
+    }
+
+    #[test]
+    fn consensus_config_with_batch_window_round_trips() {
+        let consensus = wallet_config(6).consensus;
+
+        let decoded = WalletConfigConsensus::consensus_decode_vec(
+            consensus.consensus_encode_to_vec(),
+            &ModuleDecoderRegistry::default(),
+        )
+        .expect("config decodes");
+
+        assert_eq!(decoded.peg_out_batch_window, 6);
+        assert_eq!(
+            decoded.consensus_encode_to_vec(),
+            consensus.consensus_encode_to_vec()
+        );
+    }
+}
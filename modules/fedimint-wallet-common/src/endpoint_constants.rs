@@ -1,3 +1,6 @@
 pub const BLOCK_COUNT_ENDPOINT: &str = "block_count";
 pub const PEG_OUT_FEES_ENDPOINT: &str = "peg_out_fees";
+pub const PEG_OUT_SCRIPT_FEES_ENDPOINT: &str = "peg_out_script_fees";
 pub const BLOCK_COUNT_LOCAL_ENDPOINT: &str = "block_count_local";
+pub const AWAIT_PEG_OUT_OUTPOINT_ENDPOINT: &str = "await_peg_out_outpoint";
+pub const MODULE_CONSENSUS_VERSION_ENDPOINT: &str = "module_consensus_version";
@@ -7,17 +7,23 @@
 #![allow(clippy::return_self_not_must_use)]
 
 use std::hash::Hasher;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use bitcoin::address::NetworkUnchecked;
 use bitcoin::psbt::raw::ProprietaryKey;
-use bitcoin::{Address, Amount, BlockHash, Network, Txid};
+use bitcoin::{Address, Amount, BlockHash, Network, ScriptBuf, Txid};
 use config::WalletClientConfig;
 use fedimint_core::core::{Decoder, ModuleInstanceId, ModuleKind};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::module::{CommonModuleInit, ModuleCommon, ModuleConsensusVersion};
+use fedimint_core::module::{
+    CommonModuleInit, ModuleCommon, ModuleConsensusFeature, ModuleConsensusVersion,
+};
 use fedimint_core::{extensible_associated_module_type, plugin_types_trait_impl_common, Feerate};
 use impl_tools::autoimpl;
-use miniscript::Descriptor;
+use miniscript::descriptor::{TapTree, WshInner};
+use miniscript::miniscript::decode::Terminal;
+use miniscript::{Descriptor, Miniscript};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::error;
@@ -33,7 +39,30 @@ pub mod tweakable;
 pub mod txoproof;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("wallet");
-pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 0);
+pub const MODULE_CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion::new(2, 1);
+
+/// Module consensus version from which on deposits and change go to the
+/// [`taproot_peg_in_descriptor`] once a threshold of guardians voted for it
+pub const TAPROOT_MODULE_CONSENSUS_VERSION: ModuleConsensusVersion =
+    ModuleConsensusVersion::new(2, 1);
+
+/// Consensus feature from which on [`WalletOutputV0::PegOutScript`] is
+/// accepted, once all guardians signaled it
+pub fn script_peg_out_feature() -> ModuleConsensusFeature {
+    ModuleConsensusFeature::new("script-peg-out")
+}
+
+/// Consensus feature from which on outputs pay a fee while peg-outs are
+/// waiting for signatures, once all guardians signaled it
+pub fn peg_out_backlog_fee_feature() -> ModuleConsensusFeature {
+    ModuleConsensusFeature::new("peg-out-backlog-fee")
+}
+
+/// The `H` point of BIP 341 that nobody knows the discrete logarithm of. Used
+/// as internal key of the taproot peg-in descriptor so that it can only be
+/// spent through its multisig leaf, tweaking it keeps it unspendable.
+const TAPROOT_UNSPENDABLE_INTERNAL_KEY: &str =
+    "0250929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
 
 pub const CONFIRMATION_TARGET: u16 = 10;
 
@@ -41,12 +70,49 @@ pub type PartialSig = Vec<u8>;
 
 pub type PegInDescriptor = Descriptor<CompressedPublicKey>;
 
+/// Derives the taproot equivalent of a segwit v0 peg-in descriptor: a
+/// `tr(H,multi_a(k,...))` descriptor with an unspendable internal key and the
+/// same keys and threshold as the `wsh(sortedmulti(k,...))` (or `wpkh`)
+/// descriptor of the federation.
+///
+/// The taproot descriptor isn't part of the config, since it can be derived by
+/// guardians and clients of federations created before taproot support alike.
+pub fn taproot_peg_in_descriptor(descriptor: &PegInDescriptor) -> PegInDescriptor {
+    let (threshold, mut keys) = match descriptor {
+        Descriptor::Wpkh(wpkh) => (1, vec![*wpkh.as_inner()]),
+        Descriptor::Wsh(wsh) => match wsh.as_inner() {
+            WshInner::SortedMulti(multi) => (multi.k, multi.pks.clone()),
+            WshInner::Ms(_) => panic!("Peg-in descriptors are always sortedmulti"),
+        },
+        Descriptor::Tr(_) => return descriptor.clone(),
+        _ => panic!("Unsupported peg-in descriptor {descriptor}"),
+    };
+
+    // multi_a is not sorted, so we need to sort the keys ourselves for every
+    // guardian to derive the same script
+    keys.sort_by_key(|key| key.key.serialize());
+
+    let leaf = Miniscript::from_ast(Terminal::MultiA(threshold, keys))
+        .expect("multi_a of compressed keys is a valid tapscript");
+    let internal_key = CompressedPublicKey::from_str(TAPROOT_UNSPENDABLE_INTERNAL_KEY)
+        .expect("H is a valid point");
+
+    Descriptor::new_tr(internal_key, Some(TapTree::Leaf(Arc::new(leaf))))
+        .expect("Taproot descriptor with a single leaf is valid")
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub enum WalletConsensusItem {
     BlockCount(u32), /* FIXME: use block hash instead, but needs more complicated
                       * * verification logic */
     Feerate(Feerate),
     PegOutSignature(PegOutSignatureItem),
+    /// Highest module consensus version the guardian supports, see
+    /// [`TAPROOT_MODULE_CONSENSUS_VERSION`]
+    ModuleConsensusVersion(ModuleConsensusVersion),
+    /// Signatures for peg-out txs that spend taproot UTXOs, which can't be
+    /// represented by [`PegOutSignatureItem`]
+    PegOutSignatureV1(PegOutSignatureItemV1),
     #[encodable_default]
     Default {
         variant: u64,
@@ -70,6 +136,20 @@ impl std::fmt::Display for WalletConsensusItem {
             WalletConsensusItem::PegOutSignature(sig) => {
                 write!(f, "Wallet PegOut signature for Bitcoin TxId {}", sig.txid)
             }
+            WalletConsensusItem::ModuleConsensusVersion(version) => {
+                write!(
+                    f,
+                    "Wallet Module Consensus Version {}.{}",
+                    version.major, version.minor
+                )
+            }
+            WalletConsensusItem::PegOutSignatureV1(sig) => {
+                write!(
+                    f,
+                    "Wallet PegOut signature v1 for Bitcoin TxId {}",
+                    sig.txid
+                )
+            }
             WalletConsensusItem::Default { variant, .. } => {
                 write!(f, "Unknown Wallet CI variant={variant}")
             }
@@ -83,6 +163,29 @@ pub struct PegOutSignatureItem {
     pub signature: Vec<secp256k1::ecdsa::Signature>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Encodable, Decodable)]
+pub struct PegOutSignatureItemV1 {
+    pub txid: Txid,
+    pub signature: Vec<PegOutInputSignature>,
+}
+
+/// Signature of a guardian for a single input of a peg-out tx, depending on
+/// whether the input spends a segwit v0 or a taproot UTXO
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Encodable, Decodable)]
+pub enum PegOutInputSignature {
+    Ecdsa(secp256k1::ecdsa::Signature),
+    Schnorr(secp256k1::schnorr::Signature),
+}
+
+impl std::hash::Hash for PegOutInputSignature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            PegOutInputSignature::Ecdsa(sig) => sig.serialize_der().hash(state),
+            PegOutInputSignature::Schnorr(sig) => sig[..].hash(state),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Encodable, Decodable)]
 pub struct SpendableUTXO {
     #[serde(with = "::fedimint_core::encoding::as_hex")]
@@ -118,6 +221,77 @@ pub struct PegOut {
     pub fees: PegOutFees,
 }
 
+/// Peg-out to an arbitrary output script instead of an address, e.g. directly
+/// into a multisig or timelocked script.
+///
+/// Like a partially specified PSBT output the peg-out may carry the redeem or
+/// witness script `script_pubkey` commits to, which lets the federation check
+/// that the output is spendable by the script the user expects.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Encodable, Decodable)]
+pub struct PegOutScript {
+    pub script_pubkey: ScriptBuf,
+    /// Redeem script of a P2SH `script_pubkey`
+    pub redeem_script: Option<ScriptBuf>,
+    /// Witness script of a P2WSH or P2SH-wrapped P2WSH `script_pubkey`
+    pub witness_script: Option<ScriptBuf>,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub amount: bitcoin::Amount,
+    pub fees: PegOutFees,
+}
+
+impl PegOutScript {
+    /// Creates a peg-out to `script_pubkey` that carries the scripts of a
+    /// partially specified PSBT output
+    pub fn from_psbt_output(
+        script_pubkey: ScriptBuf,
+        psbt_output: &bitcoin::psbt::Output,
+        amount: bitcoin::Amount,
+        fees: PegOutFees,
+    ) -> Self {
+        PegOutScript {
+            script_pubkey,
+            redeem_script: psbt_output.redeem_script.clone(),
+            witness_script: psbt_output.witness_script.clone(),
+            amount,
+            fees,
+        }
+    }
+
+    /// Checks that `script_pubkey` is a standard output type the federation's
+    /// transaction will be relayed with and that it commits to the redeem and
+    /// witness scripts
+    pub fn validate(&self) -> Result<(), WalletOutputError> {
+        let script = &self.script_pubkey;
+        if !(script.is_p2pkh()
+            || script.is_p2sh()
+            || script.is_v0_p2wpkh()
+            || script.is_v0_p2wsh()
+            || script.is_v1_p2tr())
+        {
+            return Err(WalletOutputError::NonStandardPegOutScript);
+        }
+
+        // The script the output commits to, possibly through a P2SH redeem script
+        let committed = match &self.redeem_script {
+            Some(redeem_script) => {
+                if *script != redeem_script.to_p2sh() {
+                    return Err(WalletOutputError::PegOutScriptMismatch);
+                }
+                redeem_script
+            }
+            None => script,
+        };
+
+        if let Some(witness_script) = &self.witness_script {
+            if *committed != witness_script.to_v0_p2wsh() {
+                return Err(WalletOutputError::PegOutScriptMismatch);
+            }
+        }
+
+        Ok(())
+    }
+}
+
 extensible_associated_module_type!(
     WalletOutputOutcome,
     WalletOutputOutcomeV0,
@@ -195,6 +369,9 @@ impl WalletOutput {
             fees,
         }))
     }
+    pub fn new_v0_peg_out_script(peg_out: PegOutScript) -> WalletOutput {
+        WalletOutput::V0(WalletOutputV0::PegOutScript(peg_out))
+    }
     pub fn new_v0_rbf(fees: PegOutFees, txid: Txid) -> WalletOutput {
         WalletOutput::V0(WalletOutputV0::Rbf(Rbf { fees, txid }))
     }
@@ -204,6 +381,8 @@ impl WalletOutput {
 pub enum WalletOutputV0 {
     PegOut(PegOut),
     Rbf(Rbf),
+    /// Only accepted once [`script_peg_out_feature`] is active
+    PegOutScript(PegOutScript),
 }
 
 /// Allows a user to bump the fees of a `PendingTransaction`
@@ -220,6 +399,15 @@ impl WalletOutputV0 {
         match self {
             WalletOutputV0::PegOut(pegout) => pegout.amount + pegout.fees.amount(),
             WalletOutputV0::Rbf(rbf) => rbf.fees.amount(),
+            WalletOutputV0::PegOutScript(pegout) => pegout.amount + pegout.fees.amount(),
+        }
+    }
+
+    /// Consensus feature that has to be active for the output to be accepted
+    pub fn consensus_feature(&self) -> Option<ModuleConsensusFeature> {
+        match self {
+            WalletOutputV0::PegOut(_) | WalletOutputV0::Rbf(_) => None,
+            WalletOutputV0::PegOutScript(_) => Some(script_peg_out_feature()),
         }
     }
 }
@@ -236,6 +424,13 @@ impl std::fmt::Display for WalletOutputV0 {
                 )
             }
             WalletOutputV0::Rbf(rbf) => write!(f, "Wallet RBF {:?} to {}", rbf.fees, rbf.txid),
+            WalletOutputV0::PegOutScript(pegout) => {
+                write!(
+                    f,
+                    "Wallet PegOut {} to script {}",
+                    pegout.amount, pegout.script_pubkey
+                )
+            }
         }
     }
 }
@@ -316,6 +511,14 @@ pub enum WalletOutputError {
     BelowMinRelayFee,
     #[error("The wallet output version is not supported by this federation")]
     UnknownOutputVariant(#[from] UnknownWalletOutputVariantError),
+    #[error("Batched peg-out transactions cannot be fee bumped")]
+    RbfBatchedTransaction,
+    #[error("Peg-out script is not a standard output type")]
+    NonStandardPegOutScript,
+    #[error("Peg-out script doesn't commit to the given redeem or witness script")]
+    PegOutScriptMismatch,
+    #[error("The federation doesn't accept peg-outs to scripts yet")]
+    ScriptPegOutNotActive,
 }
 
 #[derive(Debug, Error)]
@@ -336,4 +539,6 @@ pub enum ProcessPegOutSigError {
     MissingOrMalformedChangeTweak,
     #[error("Error finalizing PSBT {0:?}")]
     ErrorFinalizingPsbt(Vec<miniscript::psbt::Error>),
+    #[error("Signature for input {0} is of the wrong kind")]
+    WrongSignatureKind(usize),
 }
@@ -1,10 +1,14 @@
 use bitcoin::{BlockHash, Txid};
+use fedimint_core::db::{DatabaseTransaction, IDatabaseTransactionOpsCoreTyped};
 use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::ModuleConsensusVersion;
 use fedimint_core::{impl_db_lookup, impl_db_record, PeerId};
+use futures::StreamExt;
 use secp256k1::ecdsa::Signature;
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::common::{PegOut, PegOutInputSignature};
 use crate::{PendingTransaction, SpendableUTXO, UnsignedTransaction, WalletOutputOutcome};
 
 #[repr(u8)]
@@ -19,6 +23,10 @@ pub enum DbKeyPrefix {
     PegOutTxSigCi = 0x36,
     PegOutBitcoinOutPoint = 0x37,
     PegOutNonce = 0x38,
+    PendingPegOut = 0x39,
+    PegOutBatch = 0x3a,
+    TaprootUtxo = 0x3b,
+    ConsensusVersionVote = 0x3c,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -53,6 +61,23 @@ impl_db_record!(
 );
 impl_db_lookup!(key = UTXOKey, query_prefix = UTXOPrefixKey);
 
+/// Marks UTXOs locked to the taproot peg-in descriptor, all other UTXOs are
+/// locked to the segwit v0 descriptor the federation was created with. The
+/// marker is kept after the UTXO is spent, since RBF txs have to spend it
+/// again.
+#[derive(Clone, Debug, Eq, PartialEq, Encodable, Decodable, Serialize)]
+pub struct TaprootUTXOKey(pub bitcoin::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct TaprootUTXOPrefixKey;
+
+impl_db_record!(
+    key = TaprootUTXOKey,
+    value = (),
+    db_prefix = DbKeyPrefix::TaprootUtxo,
+);
+impl_db_lookup!(key = TaprootUTXOKey, query_prefix = TaprootUTXOPrefixKey);
+
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
 pub struct UnsignedTransactionKey(pub Txid);
 
@@ -85,6 +110,22 @@ impl_db_lookup!(
     query_prefix = PendingTransactionPrefixKey
 );
 
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutTxSignatureCIV0(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutTxSignatureCIPrefixV0;
+
+impl_db_record!(
+    key = PegOutTxSignatureCIV0,
+    value = Vec<Signature>,
+    db_prefix = DbKeyPrefix::PegOutTxSigCi,
+);
+impl_db_lookup!(
+    key = PegOutTxSignatureCIV0,
+    query_prefix = PegOutTxSignatureCIPrefixV0
+);
+
 #[derive(Clone, Debug, Encodable, Decodable, Serialize)]
 pub struct PegOutTxSignatureCI(pub Txid);
 
@@ -93,7 +134,7 @@ pub struct PegOutTxSignatureCIPrefix;
 
 impl_db_record!(
     key = PegOutTxSignatureCI,
-    value = Vec<Signature>,
+    value = Vec<PegOutInputSignature>,
     db_prefix = DbKeyPrefix::PegOutTxSigCi,
 );
 impl_db_lookup!(
@@ -111,6 +152,7 @@ impl_db_record!(
     key = PegOutBitcoinTransaction,
     value = WalletOutputOutcome,
     db_prefix = DbKeyPrefix::PegOutBitcoinOutPoint,
+    notify_on_modify = true,
 );
 
 impl_db_lookup!(
@@ -146,6 +188,23 @@ impl_db_record!(
 
 impl_db_lookup!(key = FeeRateVoteKey, query_prefix = FeeRateVotePrefix);
 
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusVersionVoteKey(pub PeerId);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct ConsensusVersionVotePrefix;
+
+impl_db_record!(
+    key = ConsensusVersionVoteKey,
+    value = ModuleConsensusVersion,
+    db_prefix = DbKeyPrefix::ConsensusVersionVote
+);
+
+impl_db_lookup!(
+    key = ConsensusVersionVoteKey,
+    query_prefix = ConsensusVersionVotePrefix
+);
+
 #[derive(Clone, Debug, Encodable, Decodable)]
 pub struct PegOutNonceKey;
 
@@ -154,3 +213,60 @@ impl_db_record!(
     value = u64,
     db_prefix = DbKeyPrefix::PegOutNonce
 );
+
+/// Peg-out accepted by consensus that is waiting for the end of the current
+/// batching window to be included in an on-chain transaction
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PendingPegOutKey(pub fedimint_core::OutPoint);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PendingPegOutPrefix;
+
+impl_db_record!(
+    key = PendingPegOutKey,
+    value = PegOut,
+    db_prefix = DbKeyPrefix::PendingPegOut,
+);
+
+impl_db_lookup!(key = PendingPegOutKey, query_prefix = PendingPegOutPrefix);
+
+/// Peg-outs paid by a batch transaction, the index of each out point is the
+/// index of the bitcoin output paying its recipient
+#[derive(Clone, Debug, Encodable, Decodable, Serialize)]
+pub struct PegOutBatchKey(pub Txid);
+
+#[derive(Clone, Debug, Encodable, Decodable)]
+pub struct PegOutBatchPrefix;
+
+impl_db_record!(
+    key = PegOutBatchKey,
+    value = Vec<fedimint_core::OutPoint>,
+    db_prefix = DbKeyPrefix::PegOutBatch,
+);
+
+impl_db_lookup!(key = PegOutBatchKey, query_prefix = PegOutBatchPrefix);
+
+/// Our own peg-out signatures used to be ECDSA only, taproot inputs need
+/// Schnorr signatures
+pub async fn migrate_to_v1(dbtx: &mut DatabaseTransaction<'_>) -> Result<(), anyhow::Error> {
+    let v0_entries = dbtx
+        .find_by_prefix(&PegOutTxSignatureCIPrefixV0)
+        .await
+        .collect::<Vec<(PegOutTxSignatureCIV0, Vec<Signature>)>>()
+        .await;
+
+    dbtx.remove_by_prefix(&PegOutTxSignatureCIPrefixV0).await;
+
+    for (v0_key, v0_sigs) in v0_entries {
+        dbtx.insert_new_entry(
+            &PegOutTxSignatureCI(v0_key.0),
+            &v0_sigs
+                .into_iter()
+                .map(PegOutInputSignature::Ecdsa)
+                .collect::<Vec<_>>(),
+        )
+        .await;
+    }
+
+    Ok(())
+}
@@ -24,13 +24,16 @@ use bitcoin::hashes::{sha256, Hash as BitcoinHash, HashEngine, Hmac, HmacEngine}
 use bitcoin::policy::DEFAULT_MIN_RELAY_TX_FEE;
 use bitcoin::psbt::{Input, PartiallySignedTransaction};
 use bitcoin::secp256k1::{All, Secp256k1, Verification};
-use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash};
 use bitcoin::{Address, BlockHash, Network, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid};
 use common::config::WalletConfigConsensus;
 use common::{
-    proprietary_tweak_key, PegOutFees, PegOutSignatureItem, ProcessPegOutSigError, SpendableUTXO,
+    peg_out_backlog_fee_feature, proprietary_tweak_key, script_peg_out_feature,
+    taproot_peg_in_descriptor, PegInDescriptor, PegOut, PegOutFees, PegOutInputSignature,
+    PegOutSignatureItem, PegOutSignatureItemV1, ProcessPegOutSigError, SpendableUTXO,
     WalletCommonInit, WalletConsensusItem, WalletCreationError, WalletInput, WalletModuleTypes,
-    WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET,
+    WalletOutput, WalletOutputOutcome, CONFIRMATION_TARGET, TAPROOT_MODULE_CONSENSUS_VERSION,
 };
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
 use fedimint_core::config::{
@@ -38,15 +41,18 @@ use fedimint_core::config::{
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::key_space::DbKeyPrefixDecl;
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseVersion, IDatabaseTransactionOpsCoreTyped,
+    ServerMigrationFn,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::envs::is_running_in_test_env;
 use fedimint_core::module::audit::Audit;
 use fedimint_core::module::{
-    api_endpoint, ApiEndpoint, ApiVersion, CoreConsensusVersion, InputMeta, ModuleConsensusVersion,
-    ModuleInit, PeerHandle, ServerModuleInit, ServerModuleInitArgs, SupportedModuleApiVersions,
+    api_endpoint, ApiEndpoint, ApiEndpointContext, ApiVersion, CoreConsensusVersion, FeeSchedule,
+    InputMeta, ModuleConsensusFeature, ModuleConsensusVersion, ModuleHealth, ModuleInit,
+    PeerHandle, ServerModuleInit, ServerModuleInitArgs, SupportedModuleApiVersions,
     TransactionItemAmount, CORE_CONSENSUS_VERSION,
 };
 use fedimint_core::server::DynServerModule;
@@ -64,14 +70,15 @@ use fedimint_server::config::distributedgen::PeerHandleOps;
 pub use fedimint_wallet_common as common;
 use fedimint_wallet_common::config::{WalletClientConfig, WalletConfig, WalletGenParams};
 use fedimint_wallet_common::endpoint_constants::{
-    BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT, PEG_OUT_FEES_ENDPOINT,
+    AWAIT_PEG_OUT_OUTPOINT_ENDPOINT, BLOCK_COUNT_ENDPOINT, BLOCK_COUNT_LOCAL_ENDPOINT,
+    MODULE_CONSENSUS_VERSION_ENDPOINT, PEG_OUT_FEES_ENDPOINT, PEG_OUT_SCRIPT_FEES_ENDPOINT,
 };
 use fedimint_wallet_common::keys::CompressedPublicKey;
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::{
     Rbf, WalletInputError, WalletOutputError, WalletOutputV0, MODULE_CONSENSUS_VERSION,
 };
-use futures::StreamExt;
+use futures::{FutureExt, StreamExt};
 use hex::ToHex;
 use metrics::{
     WALLET_INOUT_FEES_SATS, WALLET_INOUT_SATS, WALLET_PEGIN_FEES_SATS, WALLET_PEGIN_SATS,
@@ -80,29 +87,74 @@ use metrics::{
 use miniscript::psbt::PsbtExt;
 use miniscript::{translate_hash_fail, Descriptor, TranslatePk};
 use rand::rngs::OsRng;
-use secp256k1::{Message, Scalar};
+use secp256k1::{KeyPair, Message, Scalar};
 use serde::Serialize;
 use strum::IntoEnumIterator;
 use tokio::sync::watch;
 use tracing::{debug, info, instrument, trace, warn};
 
 use crate::db::{
-    BlockCountVoteKey, BlockCountVotePrefix, BlockHashKey, BlockHashKeyPrefix, DbKeyPrefix,
-    FeeRateVoteKey, FeeRateVotePrefix, PegOutBitcoinTransaction, PegOutBitcoinTransactionPrefix,
-    PegOutNonceKey, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix, PendingTransactionKey,
-    PendingTransactionPrefixKey, UTXOKey, UTXOPrefixKey, UnsignedTransactionKey,
-    UnsignedTransactionPrefixKey,
+    migrate_to_v1, BlockCountVoteKey, BlockCountVotePrefix, BlockHashKey, BlockHashKeyPrefix,
+    ConsensusVersionVoteKey, ConsensusVersionVotePrefix, DbKeyPrefix, FeeRateVoteKey,
+    FeeRateVotePrefix, PegOutBatchKey, PegOutBatchPrefix, PegOutBitcoinTransaction,
+    PegOutBitcoinTransactionPrefix, PegOutNonceKey, PegOutTxSignatureCI, PegOutTxSignatureCIPrefix,
+    PegOutTxSignatureCIV0, PendingPegOutKey, PendingPegOutPrefix, PendingTransactionKey,
+    PendingTransactionPrefixKey, TaprootUTXOKey, TaprootUTXOPrefixKey, UTXOKey, UTXOPrefixKey,
+    UnsignedTransactionKey, UnsignedTransactionPrefixKey,
 };
 use crate::metrics::WALLET_BLOCK_COUNT;
 
 mod metrics;
 
+/// Number of blocks the bitcoin backend and the consensus block count may
+/// differ by before the module is reported as degraded
+const MAX_HEALTHY_BLOCK_LAG: u64 = 6;
+
+/// Number of peg-out transactions that may wait for signatures before outputs
+/// pay [`PEG_OUT_BACKLOG_FEE`]
+const PEG_OUT_BACKLOG_FREE_TRANSACTIONS: usize = 10;
+
+/// Fee every output pays per peg-out transaction waiting for signatures beyond
+/// [`PEG_OUT_BACKLOG_FREE_TRANSACTIONS`], to slow down peg-outs while the
+/// guardians fall behind signing them
+const PEG_OUT_BACKLOG_FEE: fedimint_core::Amount = fedimint_core::Amount::from_sats(100);
+
+/// Fee schedule while `unsigned_transactions` peg-out transactions are waiting
+/// for signatures
+fn peg_out_backlog_fee_schedule(unsigned_transactions: usize) -> FeeSchedule {
+    let backlog = unsigned_transactions.saturating_sub(PEG_OUT_BACKLOG_FREE_TRANSACTIONS);
+
+    FeeSchedule {
+        output_base: PEG_OUT_BACKLOG_FEE.mul_u64(backlog as u64),
+        ..FeeSchedule::default()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WalletInit;
 
 impl ModuleInit for WalletInit {
     type Common = WalletCommonInit;
-    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+    const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
+
+    fn db_key_prefixes(&self) -> Vec<DbKeyPrefixDecl> {
+        vec![
+            DbKeyPrefixDecl::of::<BlockHashKey>(DbKeyPrefix::BlockHash),
+            DbKeyPrefixDecl::of::<UTXOKey>(DbKeyPrefix::Utxo),
+            DbKeyPrefixDecl::of::<BlockCountVoteKey>(DbKeyPrefix::BlockCountVote),
+            DbKeyPrefixDecl::of::<FeeRateVoteKey>(DbKeyPrefix::FeeRateVote),
+            DbKeyPrefixDecl::of::<UnsignedTransactionKey>(DbKeyPrefix::UnsignedTransaction),
+            DbKeyPrefixDecl::of::<PendingTransactionKey>(DbKeyPrefix::PendingTransaction),
+            DbKeyPrefixDecl::of::<PegOutTxSignatureCIV0>(DbKeyPrefix::PegOutTxSigCi),
+            DbKeyPrefixDecl::of::<PegOutTxSignatureCI>(DbKeyPrefix::PegOutTxSigCi),
+            DbKeyPrefixDecl::of::<PegOutBitcoinTransaction>(DbKeyPrefix::PegOutBitcoinOutPoint),
+            DbKeyPrefixDecl::of::<PegOutNonceKey>(DbKeyPrefix::PegOutNonce),
+            DbKeyPrefixDecl::of::<PendingPegOutKey>(DbKeyPrefix::PendingPegOut),
+            DbKeyPrefixDecl::of::<PegOutBatchKey>(DbKeyPrefix::PegOutBatch),
+            DbKeyPrefixDecl::of::<TaprootUTXOKey>(DbKeyPrefix::TaprootUtxo),
+            DbKeyPrefixDecl::of::<ConsensusVersionVoteKey>(DbKeyPrefix::ConsensusVersionVote),
+        ]
+    }
 
     async fn dump_database(
         &self,
@@ -133,7 +185,7 @@ impl ModuleInit for WalletInit {
                         dbtx,
                         PegOutTxSignatureCIPrefix,
                         PegOutTxSignatureCI,
-                        Vec<secp256k1::ecdsa::Signature>,
+                        Vec<PegOutInputSignature>,
                         wallet,
                         "Peg Out Transaction Signatures"
                     );
@@ -195,6 +247,45 @@ impl ModuleInit for WalletInit {
                         "Fee Rate Votes"
                     );
                 }
+                DbKeyPrefix::PendingPegOut => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PendingPegOutPrefix,
+                        PendingPegOutKey,
+                        PegOut,
+                        wallet,
+                        "Pending Peg Outs"
+                    );
+                }
+                DbKeyPrefix::PegOutBatch => {
+                    push_db_pair_items!(
+                        dbtx,
+                        PegOutBatchPrefix,
+                        PegOutBatchKey,
+                        Vec<OutPoint>,
+                        wallet,
+                        "Peg Out Batches"
+                    );
+                }
+                DbKeyPrefix::TaprootUtxo => {
+                    push_db_key_items!(
+                        dbtx,
+                        TaprootUTXOPrefixKey,
+                        TaprootUTXOKey,
+                        wallet,
+                        "Taproot UTXOs"
+                    );
+                }
+                DbKeyPrefix::ConsensusVersionVote => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ConsensusVersionVotePrefix,
+                        ConsensusVersionVoteKey,
+                        ModuleConsensusVersion,
+                        wallet,
+                        "Consensus Version Votes"
+                    );
+                }
             }
         }
 
@@ -217,7 +308,7 @@ impl ServerModuleInit for WalletInit {
                 MODULE_CONSENSUS_VERSION.major,
                 MODULE_CONSENSUS_VERSION.minor,
             ),
-            &[(0, 0)],
+            &[(0, 3)],
         )
     }
 
@@ -274,6 +365,7 @@ impl ServerModuleInit for WalletInit {
                     params.local.bitcoin_rpc.clone(),
                     params.consensus.client_default_bitcoin_rpc.clone(),
                     params.consensus.fee_consensus,
+                    params.consensus.peg_out_batch_window,
                 );
                 (*id, cfg)
             })
@@ -310,6 +402,7 @@ impl ServerModuleInit for WalletInit {
             params.local.bitcoin_rpc.clone(),
             params.consensus.client_default_bitcoin_rpc.clone(),
             params.consensus.fee_consensus,
+            params.consensus.peg_out_batch_window,
         );
 
         Ok(wallet_cfg.to_erased())
@@ -345,6 +438,12 @@ impl ServerModuleInit for WalletInit {
             default_bitcoin_rpc: config.client_default_bitcoin_rpc,
         })
     }
+
+    fn get_database_migrations(&self) -> BTreeMap<DatabaseVersion, ServerMigrationFn> {
+        let mut migrations: BTreeMap<DatabaseVersion, ServerMigrationFn> = BTreeMap::new();
+        migrations.insert(DatabaseVersion(0), move |dbtx| migrate_to_v1(dbtx).boxed());
+        migrations
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -359,15 +458,20 @@ impl ServerModule for Wallet {
         let mut items = dbtx
             .find_by_prefix(&PegOutTxSignatureCIPrefix)
             .await
-            .map(|(key, val)| {
-                WalletConsensusItem::PegOutSignature(PegOutSignatureItem {
-                    txid: key.0,
-                    signature: val,
-                })
-            })
+            .map(|(key, val)| peg_out_signature_item(key.0, val))
             .collect::<Vec<WalletConsensusItem>>()
             .await;
 
+        let our_version_vote = dbtx
+            .get_value(&ConsensusVersionVoteKey(self.our_peer_id))
+            .await;
+
+        if our_version_vote.map_or(true, |vote| vote < MODULE_CONSENSUS_VERSION) {
+            items.push(WalletConsensusItem::ModuleConsensusVersion(
+                MODULE_CONSENSUS_VERSION,
+            ));
+        }
+
         // If we are unable to get a block count from the node we skip adding a block
         // count vote to consensus items.
         //
@@ -458,6 +562,15 @@ impl ServerModule for Wallet {
                             "Not syncing up to consensus block count because we are at block 0"
                         );
                     }
+
+                    let batch_window = self.cfg.consensus.peg_out_batch_window;
+
+                    if batch_window > 0
+                        && old_consensus_block_count / batch_window
+                            != new_consensus_block_count / batch_window
+                    {
+                        self.process_peg_out_batch(dbtx).await;
+                    }
                 }
             }
             WalletConsensusItem::Feerate(feerate) => {
@@ -466,34 +579,43 @@ impl ServerModule for Wallet {
                 }
             }
             WalletConsensusItem::PegOutSignature(peg_out_signature) => {
-                let txid = peg_out_signature.txid;
+                let signature = peg_out_signature
+                    .signature
+                    .into_iter()
+                    .map(PegOutInputSignature::Ecdsa)
+                    .collect::<Vec<_>>();
+
+                self.process_peg_out_signature(dbtx, peer, peg_out_signature.txid, &signature)
+                    .await?;
+            }
+            WalletConsensusItem::PegOutSignatureV1(peg_out_signature) => {
+                // Guardians that don't support taproot can't decode this item, so
+                // it must not change our state before they upgraded
+                ensure!(
+                    self.is_taproot_active(dbtx).await,
+                    "Taproot peg-outs are not active yet"
+                );
 
-                if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
-                    bail!("Already received a threshold of valid signatures");
-                }
+                self.process_peg_out_signature(
+                    dbtx,
+                    peer,
+                    peg_out_signature.txid,
+                    &peg_out_signature.signature,
+                )
+                .await?;
+            }
+            WalletConsensusItem::ModuleConsensusVersion(version) => {
+                let current_vote = dbtx.get_value(&ConsensusVersionVoteKey(peer)).await;
 
-                let mut unsigned = dbtx
-                    .get_value(&UnsignedTransactionKey(txid))
-                    .await
-                    .context("Unsigned transaction does not exist")?;
+                ensure!(
+                    current_vote.map_or(true, |vote| vote < version),
+                    "Module consensus version vote is redundant"
+                );
 
-                self.sign_peg_out_psbt(&mut unsigned.psbt, peer, &peg_out_signature)
-                    .context("Peg out signature is invalid")?;
+                debug!(?peer, ?version, "Received module consensus version vote");
 
-                dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+                dbtx.insert_entry(&ConsensusVersionVoteKey(peer), &version)
                     .await;
-
-                if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
-                    // We were able to finalize the transaction, so we will delete the
-                    // PSBT and instead keep the extracted tx for periodic transmission
-                    // as well as to accept the change into our wallet eventually once
-                    // it confirms.
-                    dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
-                        .await;
-
-                    dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
-                    dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
-                }
             }
             WalletConsensusItem::Default { variant, .. } => {
                 bail!("Received wallet consensus item with unknown variant {variant}");
@@ -516,9 +638,19 @@ impl ServerModule for Wallet {
             ));
         }
 
-        input.verify(&self.secp, &self.cfg.consensus.peg_in_descriptor)?;
+        let is_taproot = match input.verify(&self.secp, &self.cfg.consensus.peg_in_descriptor) {
+            Ok(()) => false,
+            Err(error) => {
+                if !self.is_taproot_active(dbtx).await {
+                    return Err(error.into());
+                }
+
+                input.verify(&self.secp, &self.taproot_peg_in_descriptor)?;
+                true
+            }
+        };
 
-        debug!(outpoint = %input.outpoint(), "Claiming peg-in");
+        debug!(outpoint = %input.outpoint(), is_taproot, "Claiming peg-in");
 
         if dbtx
             .insert_entry(
@@ -533,6 +665,12 @@ impl ServerModule for Wallet {
         {
             return Err(WalletInputError::PegInAlreadyClaimed);
         }
+
+        if is_taproot {
+            dbtx.insert_new_entry(&TaprootUTXOKey(input.outpoint()), &())
+                .await;
+        }
+
         let amount = fedimint_core::Amount::from_sats(input.tx_output().value);
         let fee = self.cfg.consensus.fee_consensus.peg_in_abs;
         calculate_pegin_metrics(dbtx, amount, fee);
@@ -550,65 +688,23 @@ impl ServerModule for Wallet {
     ) -> Result<TransactionItemAmount, WalletOutputError> {
         let output = output.ensure_v0_ref()?;
 
-        let change_tweak = self.consensus_nonce(dbtx).await;
-
-        let mut tx = self.create_peg_out_tx(dbtx, output, &change_tweak).await?;
-
-        let fee_rate = self.consensus_fee_rate(dbtx).await;
-
-        StatelessWallet::validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)?;
-
-        self.offline_wallet().sign_psbt(&mut tx.psbt);
-
-        let txid = tx.psbt.unsigned_tx.txid();
-
-        info!(
-            %txid,
-            "Signing peg out",
-        );
-
-        let sigs = tx
-            .psbt
-            .inputs
-            .iter_mut()
-            .map(|input| {
-                assert_eq!(
-                    input.partial_sigs.len(),
-                    1,
-                    "There was already more than one (our) or no signatures in input"
-                );
-
-                // TODO: don't put sig into PSBT in the first place
-                // We actually take out our own signature so everyone finalizes the tx in the
-                // same epoch.
-                let sig = std::mem::take(&mut input.partial_sigs)
-                    .into_values()
-                    .next()
-                    .expect("asserted previously");
-
-                // We drop SIGHASH_ALL, because we always use that and it is only present in the
-                // PSBT for compatibility with other tools.
-                secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
-                    .expect("we serialized it ourselves that way")
-            })
-            .collect::<Vec<_>>();
+        match output {
+            WalletOutputV0::PegOut(peg_out) if self.cfg.consensus.peg_out_batch_window > 0 => {
+                self.queue_peg_out(dbtx, output, peg_out, out_point).await?;
+            }
+            // Only processed once the script peg-out feature is active, see
+            // `output_consensus_feature`
+            WalletOutputV0::PegOutScript(peg_out) => {
+                peg_out.validate()?;
 
-        // Delete used UTXOs
-        for input in &tx.psbt.unsigned_tx.input {
-            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+                // Only peg-outs to addresses are batched
+                self.process_peg_out_tx(dbtx, output, out_point).await?;
+            }
+            _ => {
+                self.process_peg_out_tx(dbtx, output, out_point).await?;
+            }
         }
 
-        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
-            .await;
-
-        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
-            .await;
-
-        dbtx.insert_new_entry(
-            &PegOutBitcoinTransaction(out_point),
-            &WalletOutputOutcome::new_v0(txid),
-        )
-        .await;
         let amount: fedimint_core::Amount = output.amount().into();
         let fee = self.cfg.consensus.fee_consensus.peg_out_abs;
         calculate_pegout_metrics(dbtx, amount, fee);
@@ -645,6 +741,11 @@ impl ServerModule for Wallet {
                 },
             )
             .await;
+        audit
+            .add_items(dbtx, module_instance_id, &PendingPegOutPrefix, |_, v| {
+                (v.amount + v.fees.amount()).to_sat() as i64 * -1000
+            })
+            .await;
         audit
             .add_items(
                 dbtx,
@@ -658,6 +759,68 @@ impl ServerModule for Wallet {
             .await;
     }
 
+    async fn health_check(&self, dbtx: &mut DatabaseTransaction<'_>) -> Option<ModuleHealth> {
+        let block_count = match self.btc_rpc.get_block_count().await {
+            Ok(block_count) => block_count,
+            Err(e) => {
+                return Some(ModuleHealth::Unhealthy(format!(
+                    "Bitcoin backend is unreachable: {e}"
+                )))
+            }
+        };
+
+        // Compare the block count we would vote for with the consensus block count
+        let block_count_vote =
+            block_count.saturating_sub(u64::from(self.cfg.consensus.finality_delay));
+        let consensus_block_count = u64::from(self.consensus_block_count(dbtx).await);
+
+        let health = if consensus_block_count.saturating_sub(block_count_vote)
+            > MAX_HEALTHY_BLOCK_LAG
+        {
+            ModuleHealth::Degraded(format!(
+                "Bitcoin backend is {} blocks behind the federation",
+                consensus_block_count - block_count_vote
+            ))
+        } else if block_count_vote.saturating_sub(consensus_block_count) > MAX_HEALTHY_BLOCK_LAG {
+            ModuleHealth::Degraded(format!(
+                "Consensus block count is {} blocks behind the bitcoin backend",
+                block_count_vote - consensus_block_count
+            ))
+        } else {
+            ModuleHealth::Healthy
+        };
+
+        Some(health)
+    }
+
+    async fn fee_schedule(&self, dbtx: &mut DatabaseTransaction<'_>) -> FeeSchedule {
+        let unsigned_transactions = dbtx
+            .find_by_prefix(&UnsignedTransactionPrefixKey)
+            .await
+            .count()
+            .await;
+
+        peg_out_backlog_fee_schedule(unsigned_transactions)
+    }
+
+    fn fee_schedule_consensus_feature(&self) -> Option<ModuleConsensusFeature> {
+        Some(peg_out_backlog_fee_feature())
+    }
+
+    fn supported_consensus_features(&self) -> BTreeSet<ModuleConsensusFeature> {
+        BTreeSet::from([script_peg_out_feature(), peg_out_backlog_fee_feature()])
+    }
+
+    fn local_db_prefixes(&self) -> BTreeSet<u8> {
+        // our own signatures of peg-out transactions
+        BTreeSet::from([DbKeyPrefix::PegOutTxSigCi as u8])
+    }
+
+    fn output_consensus_feature(&self, output: &WalletOutput) -> Option<ModuleConsensusFeature> {
+        // Unknown variants are rejected by `process_output` anyway
+        output.maybe_v0_ref()?.consensus_feature()
+    }
+
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
         vec![
             api_endpoint! {
@@ -679,29 +842,41 @@ impl ServerModule for Wallet {
                 ApiVersion::new(0, 0),
                 async |module: &Wallet, context, params: (Address<NetworkUnchecked>, u64)| -> Option<PegOutFees> {
                     let (address, sats) = params;
-                    let feerate = module.consensus_fee_rate(&mut context.dbtx().into_nc()).await;
-
-                    // Since we are only calculating the tx size we can use an arbitrary dummy nonce.
-                    let dummy_tweak = [0; 33];
-
-                    let tx = module.offline_wallet().create_tx(
-                        bitcoin::Amount::from_sat(sats),
-                        address.assume_checked().script_pubkey(),
-                        vec![],
-                        module.available_utxos(&mut context.dbtx().into_nc()).await,
-                        feerate,
-                        &dummy_tweak,
-                        None
-                    );
-
-                    match tx {
-                        Err(error) => {
-                            // Usually from not enough spendable UTXOs
-                            warn!("Error returning peg-out fees {error}");
-                            Ok(None)
-                        }
-                        Ok(tx) => Ok(Some(tx.fees))
-                    }
+                    Ok(module
+                        .peg_out_fees(
+                            &mut context.dbtx().into_nc(),
+                            address.assume_checked().script_pubkey(),
+                            bitcoin::Amount::from_sat(sats),
+                        )
+                        .await)
+                }
+            },
+            api_endpoint! {
+                PEG_OUT_SCRIPT_FEES_ENDPOINT,
+                ApiVersion::new(0, 3),
+                async |module: &Wallet, context, params: (ScriptBuf, u64)| -> Option<PegOutFees> {
+                    let (script_pubkey, sats) = params;
+                    Ok(module
+                        .peg_out_fees(
+                            &mut context.dbtx().into_nc(),
+                            script_pubkey,
+                            bitcoin::Amount::from_sat(sats),
+                        )
+                        .await)
+                }
+            },
+            api_endpoint! {
+                AWAIT_PEG_OUT_OUTPOINT_ENDPOINT,
+                ApiVersion::new(0, 1),
+                async |module: &Wallet, context, out_point: OutPoint| -> Option<bitcoin::OutPoint> {
+                    Ok(module.await_peg_out_outpoint(context, out_point).await)
+                }
+            },
+            api_endpoint! {
+                MODULE_CONSENSUS_VERSION_ENDPOINT,
+                ApiVersion::new(0, 2),
+                async |module: &Wallet, context, _params: ()| -> ModuleConsensusVersion {
+                    Ok(module.consensus_module_consensus_version(&mut context.dbtx().into_nc()).await)
                 }
             },
         ]
@@ -745,6 +920,9 @@ fn calculate_pegout_metrics(
 #[derive(Debug)]
 pub struct Wallet {
     cfg: WalletConfig,
+    /// Derived from the segwit v0 peg-in descriptor of the config, see
+    /// [`taproot_peg_in_descriptor`]
+    taproot_peg_in_descriptor: PegInDescriptor,
     secp: Secp256k1<All>,
     btc_rpc: DynBitcoindRpc,
     /// The result of last successful get_block_count
@@ -793,6 +971,7 @@ impl Wallet {
         }
 
         let wallet = Wallet {
+            taproot_peg_in_descriptor: taproot_peg_in_descriptor(&cfg.consensus.peg_in_descriptor),
             cfg,
             secp: Default::default(),
             block_count_local: Default::default(),
@@ -805,12 +984,51 @@ impl Wallet {
         Ok(wallet)
     }
 
+    /// Verifies and attaches the signatures of a peer to an unsigned peg-out tx
+    /// and finalizes it once we have a threshold of signatures
+    async fn process_peg_out_signature(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        peer: PeerId,
+        txid: Txid,
+        signature: &[PegOutInputSignature],
+    ) -> anyhow::Result<()> {
+        if dbtx.get_value(&PendingTransactionKey(txid)).await.is_some() {
+            bail!("Already received a threshold of valid signatures");
+        }
+
+        let mut unsigned = dbtx
+            .get_value(&UnsignedTransactionKey(txid))
+            .await
+            .context("Unsigned transaction does not exist")?;
+
+        self.sign_peg_out_psbt(&mut unsigned.psbt, peer, signature)
+            .context("Peg out signature is invalid")?;
+
+        dbtx.insert_entry(&UnsignedTransactionKey(txid), &unsigned)
+            .await;
+
+        if let Ok(pending_tx) = self.finalize_peg_out_psbt(unsigned) {
+            // We were able to finalize the transaction, so we will delete the
+            // PSBT and instead keep the extracted tx for periodic transmission
+            // as well as to accept the change into our wallet eventually once
+            // it confirms.
+            dbtx.insert_new_entry(&PendingTransactionKey(txid), &pending_tx)
+                .await;
+
+            dbtx.remove_entry(&PegOutTxSignatureCI(txid)).await;
+            dbtx.remove_entry(&UnsignedTransactionKey(txid)).await;
+        }
+
+        Ok(())
+    }
+
     /// Try to attach signatures to a pending peg-out tx.
     fn sign_peg_out_psbt(
         &self,
         psbt: &mut PartiallySignedTransaction,
         peer: PeerId,
-        signature: &PegOutSignatureItem,
+        signature: &[PegOutInputSignature],
     ) -> Result<(), ProcessPegOutSigError> {
         let peer_key = self
             .cfg
@@ -819,53 +1037,89 @@ impl Wallet {
             .get(&peer)
             .expect("always called with valid peer id");
 
-        if psbt.inputs.len() != signature.signature.len() {
+        if psbt.inputs.len() != signature.len() {
             return Err(ProcessPegOutSigError::WrongSignatureCount(
                 psbt.inputs.len(),
-                signature.signature.len(),
+                signature.len(),
             ));
         }
 
+        let prevouts = psbt_prevouts(psbt);
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
-        for (idx, (input, signature)) in psbt
-            .inputs
-            .iter_mut()
-            .zip(signature.signature.iter())
-            .enumerate()
-        {
-            let tx_hash = tx_hasher
-                .segwit_signature_hash(
-                    idx,
-                    input
-                        .witness_script
-                        .as_ref()
-                        .expect("Missing witness script"),
-                    input.witness_utxo.as_ref().expect("Missing UTXO").value,
-                    EcdsaSighashType::All,
-                )
-                .map_err(|_| ProcessPegOutSigError::SighashError)?;
-
+        for (idx, (input, signature)) in psbt.inputs.iter_mut().zip(signature.iter()).enumerate() {
             let tweak = input
                 .proprietary
                 .get(&proprietary_tweak_key())
                 .expect("we saved it with a tweak");
 
             let tweaked_peer_key = peer_key.tweak(tweak, &self.secp);
-            self.secp
-                .verify_ecdsa(
-                    &Message::from_slice(&tx_hash[..]).unwrap(),
-                    signature,
-                    &tweaked_peer_key.key,
-                )
-                .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
-
-            if input
-                .partial_sigs
-                .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
-                .is_some()
-            {
-                // Should never happen since peers only sign a PSBT once
-                return Err(ProcessPegOutSigError::DuplicateSignature);
+
+            match (tap_leaf_hash(input), signature) {
+                (Some(leaf_hash), PegOutInputSignature::Schnorr(signature)) => {
+                    let tx_hash = tx_hasher
+                        .taproot_script_spend_signature_hash(
+                            idx,
+                            &Prevouts::All(&prevouts),
+                            leaf_hash,
+                            TapSighashType::Default,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    let tweaked_peer_key = tweaked_peer_key.key.x_only_public_key().0;
+                    self.secp
+                        .verify_schnorr(
+                            signature,
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            &tweaked_peer_key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    if input
+                        .tap_script_sigs
+                        .insert(
+                            (tweaked_peer_key, leaf_hash),
+                            bitcoin::taproot::Signature {
+                                sig: *signature,
+                                hash_ty: TapSighashType::Default,
+                            },
+                        )
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
+                (None, PegOutInputSignature::Ecdsa(signature)) => {
+                    let tx_hash = tx_hasher
+                        .segwit_signature_hash(
+                            idx,
+                            input
+                                .witness_script
+                                .as_ref()
+                                .expect("Missing witness script"),
+                            input.witness_utxo.as_ref().expect("Missing UTXO").value,
+                            EcdsaSighashType::All,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::SighashError)?;
+
+                    self.secp
+                        .verify_ecdsa(
+                            &Message::from_slice(&tx_hash[..]).unwrap(),
+                            signature,
+                            &tweaked_peer_key.key,
+                        )
+                        .map_err(|_| ProcessPegOutSigError::InvalidSignature)?;
+
+                    if input
+                        .partial_sigs
+                        .insert(tweaked_peer_key.into(), EcdsaSig::sighash_all(*signature))
+                        .is_some()
+                    {
+                        // Should never happen since peers only sign a PSBT once
+                        return Err(ProcessPegOutSigError::DuplicateSignature);
+                    }
+                }
+                _ => return Err(ProcessPegOutSigError::WrongSignatureKind(idx)),
             }
         }
         Ok(())
@@ -958,6 +1212,38 @@ impl Wallet {
         rates[peer_count / 2]
     }
 
+    /// The highest module consensus version supported by a threshold of
+    /// guardians. Guardians that haven't voted yet are assumed to run the
+    /// version preceding the vote.
+    pub async fn consensus_module_consensus_version(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> ModuleConsensusVersion {
+        let peer_count = self.cfg.consensus.peer_peg_in_keys.total();
+
+        let mut versions = dbtx
+            .find_by_prefix(&ConsensusVersionVotePrefix)
+            .await
+            .map(|(.., version)| version)
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(versions.len() <= peer_count);
+
+        while versions.len() < peer_count {
+            versions.push(ModuleConsensusVersion::new(2, 0));
+        }
+
+        versions.sort_unstable();
+
+        versions[self.cfg.consensus.peer_peg_in_keys.max_evil()]
+    }
+
+    /// Whether deposits and change may go to the taproot peg-in descriptor
+    async fn is_taproot_active(&self, dbtx: &mut DatabaseTransaction<'_>) -> bool {
+        self.consensus_module_consensus_version(dbtx).await >= TAPROOT_MODULE_CONSENSUS_VERSION
+    }
+
     pub async fn consensus_nonce(&self, dbtx: &mut DatabaseTransaction<'_>) -> [u8; 33] {
         let nonce_idx = dbtx.get_value(&PegOutNonceKey).await.unwrap_or(0);
         dbtx.insert_entry(&PegOutNonceKey, &(nonce_idx + 1)).await;
@@ -1051,25 +1337,42 @@ impl Wallet {
     ) {
         self.remove_rbf_transactions(dbtx, pending_tx).await;
 
+        // The inputs can't be spent by an RBF tx anymore
+        for (utxo_key, _) in &pending_tx.selected_utxos {
+            dbtx.remove_entry(&TaprootUTXOKey(utxo_key.0)).await;
+        }
+
         let script_pk = self
             .cfg
             .consensus
             .peg_in_descriptor
             .tweak(&pending_tx.tweak, &self.secp)
             .script_pubkey();
+        let taproot_script_pk = self
+            .taproot_peg_in_descriptor
+            .tweak(&pending_tx.tweak, &self.secp)
+            .script_pubkey();
         for (idx, output) in pending_tx.tx.output.iter().enumerate() {
-            if output.script_pubkey == script_pk {
+            let is_taproot = output.script_pubkey == taproot_script_pk;
+
+            if output.script_pubkey == script_pk || is_taproot {
+                let outpoint = bitcoin::OutPoint {
+                    txid: pending_tx.tx.txid(),
+                    vout: idx as u32,
+                };
+
                 dbtx.insert_entry(
-                    &UTXOKey(bitcoin::OutPoint {
-                        txid: pending_tx.tx.txid(),
-                        vout: idx as u32,
-                    }),
+                    &UTXOKey(outpoint),
                     &SpendableUTXO {
                         tweak: pending_tx.tweak,
                         amount: bitcoin::Amount::from_sat(output.value),
                     },
                 )
                 .await;
+
+                if is_taproot {
+                    dbtx.insert_entry(&TaprootUTXOKey(outpoint), &()).await;
+                }
             }
         }
     }
@@ -1120,35 +1423,336 @@ impl Wallet {
         dbtx.get_value(&BlockHashKey(block_hash)).await.is_some()
     }
 
-    async fn create_peg_out_tx(
+    /// Validates a peg-out and queues it to be paid by the batch transaction
+    /// created at the end of the current batching window
+    async fn queue_peg_out(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
         output: &WalletOutputV0,
-        change_tweak: &[u8; 33],
-    ) -> Result<UnsignedTransaction, WalletOutputError> {
-        match output {
-            WalletOutputV0::PegOut(peg_out) => self.offline_wallet().create_tx(
-                peg_out.amount,
-                peg_out.recipient.clone().assume_checked().script_pubkey(),
-                vec![],
-                self.available_utxos(dbtx).await,
-                peg_out.fees.fee_rate,
-                change_tweak,
-                None,
-            ),
-            WalletOutputV0::Rbf(rbf) => {
-                let tx = dbtx
-                    .get_value(&PendingTransactionKey(rbf.txid))
-                    .await
-                    .ok_or(WalletOutputError::RbfTransactionIdNotFound)?;
+        peg_out: &PegOut,
+        out_point: OutPoint,
+    ) -> Result<(), WalletOutputError> {
+        // Peg-outs are charged the fees of a standalone transaction, which always
+        // covers their share of the batch transaction. Since the tx is never
+        // signed we can use an arbitrary dummy tweak for the change output.
+        let dummy_tweak = [0; 33];
+        let tx = self.create_peg_out_tx(dbtx, output, &dummy_tweak).await?;
 
-                self.offline_wallet().create_tx(
-                    tx.peg_out_amount,
-                    tx.destination,
-                    tx.selected_utxos,
-                    self.available_utxos(dbtx).await,
-                    tx.fees.fee_rate,
-                    change_tweak,
+        let fee_rate = self.consensus_fee_rate(dbtx).await;
+
+        StatelessWallet::validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)?;
+
+        let queued = self
+            .pending_peg_outs(dbtx)
+            .await
+            .into_iter()
+            .map(|(_, queued)| queued.amount + queued.fees.amount())
+            .sum::<bitcoin::Amount>();
+
+        if self.get_wallet_value(dbtx).await < queued + output.amount() {
+            return Err(WalletOutputError::NotEnoughSpendableUTXO);
+        }
+
+        dbtx.insert_new_entry(&PendingPegOutKey(out_point), peg_out)
+            .await;
+
+        Ok(())
+    }
+
+    async fn pending_peg_outs(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+    ) -> Vec<(PendingPegOutKey, PegOut)> {
+        dbtx.find_by_prefix(&PendingPegOutPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// Pays all queued peg-outs in a single transaction. If the wallet is
+    /// unable to fund the batch the peg-outs stay queued until the next window.
+    async fn process_peg_out_batch(&self, dbtx: &mut DatabaseTransaction<'_>) {
+        let pending = self.pending_peg_outs(dbtx).await;
+
+        // Every queued peg-out paid at least this fee rate for a standalone tx, so
+        // the batch tx can't cost more than the fees collected for it
+        let Some(fee_rate) = pending
+            .iter()
+            .map(|(_, peg_out)| peg_out.fees.fee_rate)
+            .min()
+        else {
+            return;
+        };
+
+        let recipients = pending
+            .iter()
+            .map(|(_, peg_out)| {
+                (
+                    peg_out.recipient.clone().assume_checked().script_pubkey(),
+                    peg_out.amount,
+                )
+            })
+            .collect();
+
+        let change_tweak = self.consensus_nonce(dbtx).await;
+
+        let wallet = self.offline_wallet(dbtx).await;
+        let tx = match wallet.create_batch_tx(
+            recipients,
+            vec![],
+            self.available_utxos(dbtx).await,
+            fee_rate,
+            &change_tweak,
+            None,
+        ) {
+            Ok(tx) => tx,
+            Err(error) => {
+                warn!(
+                    target: LOG_MODULE_WALLET,
+                    %error,
+                    peg_outs = pending.len(),
+                    "Unable to create peg-out batch, retrying in the next window"
+                );
+                return;
+            }
+        };
+
+        let txid = self.submit_peg_out_tx(dbtx, tx).await;
+
+        info!(
+            target: LOG_MODULE_WALLET,
+            %txid,
+            peg_outs = pending.len(),
+            "Created peg-out batch",
+        );
+
+        let mut out_points = Vec::with_capacity(pending.len());
+
+        for (key, _) in pending {
+            dbtx.remove_entry(&key).await;
+
+            dbtx.insert_new_entry(
+                &PegOutBitcoinTransaction(key.0),
+                &WalletOutputOutcome::new_v0(txid),
+            )
+            .await;
+
+            out_points.push(key.0);
+        }
+
+        dbtx.insert_new_entry(&PegOutBatchKey(txid), &out_points)
+            .await;
+    }
+
+    /// Signs a peg-out tx, marks its inputs as spent and queues our signatures
+    /// to be shared with our peers
+    ///
+    /// The peg-in descriptors are a `wsh(sortedmulti)` of plain ECDSA keys and
+    /// a taproot `multi_a` leaf checking individual Schnorr signatures, so
+    /// signing needs no nonce exchange that could be done ahead of time: every
+    /// peer signs while processing the output and the tx is finalized in the
+    /// first consensus round in which a threshold of `PegOutSignature` items is
+    /// processed. Processing the output makes every peer propose its
+    /// signatures right away rather than at its next periodic proposal, so
+    /// this is the round right after the peg-out was accepted.
+    async fn submit_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        mut tx: UnsignedTransaction,
+    ) -> Txid {
+        self.offline_wallet(dbtx).await.sign_psbt(&mut tx.psbt);
+
+        let txid = tx.psbt.unsigned_tx.txid();
+
+        info!(
+            %txid,
+            "Signing peg out",
+        );
+
+        let sigs = tx
+            .psbt
+            .inputs
+            .iter_mut()
+            .map(|input| {
+                assert_eq!(
+                    input.partial_sigs.len() + input.tap_script_sigs.len(),
+                    1,
+                    "There was already more than one (our) or no signatures in input"
+                );
+
+                // TODO: don't put sig into PSBT in the first place
+                // We actually take out our own signature so everyone finalizes the tx in the
+                // same epoch.
+                if let Some(sig) = std::mem::take(&mut input.tap_script_sigs)
+                    .into_values()
+                    .next()
+                {
+                    return PegOutInputSignature::Schnorr(sig.sig);
+                }
+
+                let sig = std::mem::take(&mut input.partial_sigs)
+                    .into_values()
+                    .next()
+                    .expect("asserted previously");
+
+                // We drop SIGHASH_ALL, because we always use that and it is only present in the
+                // PSBT for compatibility with other tools.
+                PegOutInputSignature::Ecdsa(
+                    secp256k1::ecdsa::Signature::from_der(&sig.to_vec()[..sig.to_vec().len() - 1])
+                        .expect("we serialized it ourselves that way"),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Delete used UTXOs
+        for input in &tx.psbt.unsigned_tx.input {
+            dbtx.remove_entry(&UTXOKey(input.previous_output)).await;
+        }
+
+        dbtx.insert_new_entry(&UnsignedTransactionKey(txid), &tx)
+            .await;
+
+        dbtx.insert_new_entry(&PegOutTxSignatureCI(txid), &sigs)
+            .await;
+
+        txid
+    }
+
+    /// Waits until the peg-out created by `out_point` was paid on-chain and
+    /// returns the bitcoin output paying it if it was part of a batch
+    async fn await_peg_out_outpoint(
+        &self,
+        context: &mut ApiEndpointContext<'_>,
+        out_point: OutPoint,
+    ) -> Option<bitcoin::OutPoint> {
+        // not using a variable here leads to a !Send error
+        let future = context.wait_key_exists(PegOutBitcoinTransaction(out_point));
+        let txid = future.await.maybe_v0_ref()?.0;
+
+        // The batch is written together with the outcome, so we need a fresh
+        // transaction to see it
+        let vout = context
+            .db()
+            .begin_transaction_nc()
+            .await
+            .get_value(&PegOutBatchKey(txid))
+            .await?
+            .iter()
+            .position(|batched| *batched == out_point)?;
+
+        Some(bitcoin::OutPoint {
+            txid,
+            vout: vout as u32,
+        })
+    }
+
+    /// Creates, validates and submits the transaction paying a peg-out or fee
+    /// bump right away, without waiting for a batch
+    async fn process_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        output: &WalletOutputV0,
+        out_point: OutPoint,
+    ) -> Result<(), WalletOutputError> {
+        let change_tweak = self.consensus_nonce(dbtx).await;
+
+        let tx = self.create_peg_out_tx(dbtx, output, &change_tweak).await?;
+
+        let fee_rate = self.consensus_fee_rate(dbtx).await;
+
+        StatelessWallet::validate_tx(&tx, output, fee_rate, self.cfg.consensus.network)?;
+
+        let txid = self.submit_peg_out_tx(dbtx, tx).await;
+
+        dbtx.insert_new_entry(
+            &PegOutBitcoinTransaction(out_point),
+            &WalletOutputOutcome::new_v0(txid),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    /// Fees a peg-out of `amount` to `script_pubkey` would have to pay at the
+    /// current consensus fee rate, `None` if the wallet can't fund it
+    async fn peg_out_fees(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        script_pubkey: ScriptBuf,
+        amount: bitcoin::Amount,
+    ) -> Option<PegOutFees> {
+        let feerate = self.consensus_fee_rate(dbtx).await;
+
+        // Since we are only calculating the tx size we can use an arbitrary dummy
+        // nonce.
+        let dummy_tweak = [0; 33];
+
+        let wallet = self.offline_wallet(dbtx).await;
+        let tx = wallet.create_tx(
+            amount,
+            script_pubkey,
+            vec![],
+            self.available_utxos(dbtx).await,
+            feerate,
+            &dummy_tweak,
+            None,
+        );
+
+        match tx {
+            Err(error) => {
+                // Usually from not enough spendable UTXOs
+                warn!("Error returning peg-out fees {error}");
+                None
+            }
+            Ok(tx) => Some(tx.fees),
+        }
+    }
+
+    async fn create_peg_out_tx(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        output: &WalletOutputV0,
+        change_tweak: &[u8; 33],
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        let wallet = self.offline_wallet(dbtx).await;
+
+        match output {
+            WalletOutputV0::PegOut(peg_out) => wallet.create_tx(
+                peg_out.amount,
+                peg_out.recipient.clone().assume_checked().script_pubkey(),
+                vec![],
+                self.available_utxos(dbtx).await,
+                peg_out.fees.fee_rate,
+                change_tweak,
+                None,
+            ),
+            WalletOutputV0::PegOutScript(peg_out) => wallet.create_tx(
+                peg_out.amount,
+                peg_out.script_pubkey.clone(),
+                vec![],
+                self.available_utxos(dbtx).await,
+                peg_out.fees.fee_rate,
+                change_tweak,
+                None,
+            ),
+            WalletOutputV0::Rbf(rbf) => {
+                let tx = dbtx
+                    .get_value(&PendingTransactionKey(rbf.txid))
+                    .await
+                    .ok_or(WalletOutputError::RbfTransactionIdNotFound)?;
+
+                // The rebuilt tx would only pay the first recipient of the batch
+                if dbtx.get_value(&PegOutBatchKey(rbf.txid)).await.is_some() {
+                    return Err(WalletOutputError::RbfBatchedTransaction);
+                }
+
+                wallet.create_tx(
+                    tx.peg_out_amount,
+                    tx.destination,
+                    tx.selected_utxos,
+                    self.available_utxos(dbtx).await,
+                    tx.fees.fee_rate,
+                    change_tweak,
                     Some(rbf.clone()),
                 )
             }
@@ -1175,9 +1779,17 @@ impl Wallet {
         bitcoin::Amount::from_sat(sat_sum)
     }
 
-    fn offline_wallet(&self) -> StatelessWallet {
+    async fn offline_wallet(&self, dbtx: &mut DatabaseTransaction<'_>) -> StatelessWallet {
         StatelessWallet {
             descriptor: &self.cfg.consensus.peg_in_descriptor,
+            taproot_descriptor: &self.taproot_peg_in_descriptor,
+            taproot_utxos: dbtx
+                .find_by_prefix(&TaprootUTXOPrefixKey)
+                .await
+                .map(|(key, ())| key.0)
+                .collect::<BTreeSet<_>>()
+                .await,
+            taproot_change: self.is_taproot_active(dbtx).await,
             secret_key: &self.cfg.private.peg_in_key,
             secp: &self.secp,
         }
@@ -1309,13 +1921,72 @@ pub async fn broadcast_pending_tx(mut dbtx: DatabaseTransaction<'_>, rpc: &DynBi
     }
 }
 
+/// Shares our peg-out signatures with the item that guardians without taproot
+/// support understand if the tx has no taproot inputs
+fn peg_out_signature_item(txid: Txid, signature: Vec<PegOutInputSignature>) -> WalletConsensusItem {
+    let ecdsa_signature = signature
+        .iter()
+        .map(|signature| match signature {
+            PegOutInputSignature::Ecdsa(signature) => Some(*signature),
+            PegOutInputSignature::Schnorr(_) => None,
+        })
+        .collect::<Option<Vec<_>>>();
+
+    match ecdsa_signature {
+        Some(signature) => {
+            WalletConsensusItem::PegOutSignature(PegOutSignatureItem { txid, signature })
+        }
+        None => WalletConsensusItem::PegOutSignatureV1(PegOutSignatureItemV1 { txid, signature }),
+    }
+}
+
+/// The outputs spent by a PSBT, needed to compute taproot sighashes
+fn psbt_prevouts(psbt: &PartiallySignedTransaction) -> Vec<TxOut> {
+    psbt.inputs
+        .iter()
+        .map(|input| input.witness_utxo.clone().expect("Missing UTXO"))
+        .collect()
+}
+
+/// Hash of the multisig leaf spent by a PSBT input, `None` if the input spends
+/// a segwit v0 UTXO
+fn tap_leaf_hash(input: &Input) -> Option<TapLeafHash> {
+    input
+        .tap_scripts
+        .values()
+        .next()
+        .map(|(script, version)| TapLeafHash::from_script(script, *version))
+}
+
 struct StatelessWallet<'a> {
+    /// Segwit v0 descriptor the federation was created with
     descriptor: &'a Descriptor<CompressedPublicKey>,
+    taproot_descriptor: &'a Descriptor<CompressedPublicKey>,
+    /// UTXOs locked to the taproot descriptor, all others are segwit v0
+    taproot_utxos: BTreeSet<bitcoin::OutPoint>,
+    /// Whether change goes to the taproot descriptor
+    taproot_change: bool,
     secret_key: &'a secp256k1::SecretKey,
     secp: &'a secp256k1::Secp256k1<secp256k1::All>,
 }
 
 impl<'a> StatelessWallet<'a> {
+    fn utxo_descriptor(&self, utxo_key: &UTXOKey) -> &Descriptor<CompressedPublicKey> {
+        if self.taproot_utxos.contains(&utxo_key.0) {
+            self.taproot_descriptor
+        } else {
+            self.descriptor
+        }
+    }
+
+    fn change_descriptor(&self) -> &Descriptor<CompressedPublicKey> {
+        if self.taproot_change {
+            self.taproot_descriptor
+        } else {
+            self.descriptor
+        }
+    }
+
     /// Given a tx created from an `WalletOutput`, validate there will be no
     /// issues submitting the transaction to the Bitcoin network
     fn validate_tx(
@@ -1350,6 +2021,7 @@ impl<'a> StatelessWallet<'a> {
         // BIP-0125 requires 1 sat/vb for RBF by default (same as normal txs)
         let fees = match output {
             WalletOutputV0::PegOut(pegout) => pegout.fees,
+            WalletOutputV0::PegOutScript(pegout) => pegout.fees,
             WalletOutputV0::Rbf(rbf) => rbf.fees,
         };
         if fees.fee_rate.sats_per_kvb < u64::from(DEFAULT_MIN_RELAY_TX_FEE) {
@@ -1381,12 +2053,48 @@ impl<'a> StatelessWallet<'a> {
         &self,
         peg_out_amount: bitcoin::Amount,
         destination: ScriptBuf,
+        included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
+        fee_rate: Feerate,
+        change_tweak: &[u8; 33],
+        rbf: Option<Rbf>,
+    ) -> Result<UnsignedTransaction, WalletOutputError> {
+        self.create_batch_tx(
+            vec![(destination, peg_out_amount)],
+            included_utxos,
+            remaining_utxos,
+            fee_rate,
+            change_tweak,
+            rbf,
+        )
+    }
+
+    /// Attempts to create a tx paying multiple recipients at once. Recipients
+    /// are paid in the given order starting at output index 0, the change
+    /// output is always last.
+    ///
+    /// The `destination` and `peg_out_amount` of the returned tx refer to the
+    /// first recipient and the sum of all recipients respectively.
+    fn create_batch_tx(
+        &self,
+        recipients: Vec<(ScriptBuf, bitcoin::Amount)>,
         mut included_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut remaining_utxos: Vec<(UTXOKey, SpendableUTXO)>,
         mut fee_rate: Feerate,
         change_tweak: &[u8; 33],
         rbf: Option<Rbf>,
     ) -> Result<UnsignedTransaction, WalletOutputError> {
+        assert!(
+            !recipients.is_empty(),
+            "a peg-out needs at least one recipient"
+        );
+
+        let peg_out_amount = recipients
+            .iter()
+            .map(|(_, amount)| *amount)
+            .sum::<bitcoin::Amount>();
+        let destination = recipients[0].0.clone();
+
         // Add the rbf fees to the existing tx fees
         if let Some(rbf) = &rbf {
             fee_rate.sats_per_kvb += rbf.fees.fee_rate.sats_per_kvb;
@@ -1400,7 +2108,10 @@ impl<'a> StatelessWallet<'a> {
         // and the maximum weight per added input which we will add every time
         // we select an input.
         let change_script = self.derive_script(change_tweak);
-        let out_weight = (destination.len() * 4 + 1 + 32
+        let out_weight = (recipients
+            .iter()
+            .map(|(script, _)| script.len() * 4 + 1 + 32)
+            .sum::<usize>()
             // Add change script weight, it's very likely to be needed if not we just overpay in fees
             + 1 // script len varint, 1 byte for all addresses we accept
             + change_script.len() * 4 // script len
@@ -1412,17 +2123,24 @@ impl<'a> StatelessWallet<'a> {
             16; // lock time
                 // https://github.com/fedimint/fedimint/issues/4590
         #[allow(deprecated)]
-        let max_input_weight = (self
-            .descriptor
-            .max_satisfaction_weight()
-            .expect("is satisfyable") +
-            128 + // TxOutHash
-            16 + // TxOutIndex
-            16) as u64; // sequence
-
-        // Ensure deterministic ordering of UTXOs for all peers
-        included_utxos.sort_by_key(|(_, utxo)| utxo.amount);
-        remaining_utxos.sort_by_key(|(_, utxo)| utxo.amount);
+        let max_input_weight = |utxo_key: &UTXOKey| {
+            (self
+                .utxo_descriptor(utxo_key)
+                .max_satisfaction_weight()
+                .expect("is satisfyable") +
+                128 + // TxOutHash
+                16 + // TxOutIndex
+                16) as u64 // sequence
+        };
+
+        // Ensure deterministic ordering of UTXOs for all peers. Segwit v0 UTXOs are
+        // selected first so that the funds of the federation move to taproot
+        // change outputs over time.
+        let selection_order = |utxo_key: &UTXOKey, utxo: &SpendableUTXO| {
+            (!self.taproot_utxos.contains(&utxo_key.0), utxo.amount)
+        };
+        included_utxos.sort_by_key(|(utxo_key, utxo)| selection_order(utxo_key, utxo));
+        remaining_utxos.sort_by_key(|(utxo_key, utxo)| selection_order(utxo_key, utxo));
         included_utxos.extend(remaining_utxos);
 
         // Finally we initialize our accumulator for selected input amounts
@@ -1434,7 +2152,7 @@ impl<'a> StatelessWallet<'a> {
             match included_utxos.pop() {
                 Some((utxo_key, utxo)) => {
                     total_selected_value += utxo.amount;
-                    total_weight += max_input_weight;
+                    total_weight += max_input_weight(&utxo_key);
                     fees = fee_rate.calculate_fee(total_weight);
                     selected_utxos.push((utxo_key, utxo));
                 }
@@ -1445,16 +2163,17 @@ impl<'a> StatelessWallet<'a> {
         // We always pay ourselves change back to ensure that we don't lose anything due
         // to dust
         let change = total_selected_value - fees - peg_out_amount;
-        let output: Vec<TxOut> = vec![
-            TxOut {
-                value: peg_out_amount.to_sat(),
-                script_pubkey: destination.clone(),
-            },
-            TxOut {
+        let output: Vec<TxOut> = recipients
+            .iter()
+            .map(|(script, amount)| TxOut {
+                value: amount.to_sat(),
+                script_pubkey: script.clone(),
+            })
+            .chain(std::iter::once(TxOut {
                 value: change.to_sat(),
                 script_pubkey: change_script,
-            },
-        ];
+            }))
+            .collect();
         let mut change_out = bitcoin::psbt::Output::default();
         change_out
             .proprietary
@@ -1462,6 +2181,7 @@ impl<'a> StatelessWallet<'a> {
 
         info!(
             inputs = selected_utxos.len(),
+            recipients = recipients.len(),
             input_sats = total_selected_value.to_sat(),
             peg_out_sats = peg_out_amount.to_sat(),
             ?total_weight,
@@ -1497,26 +2217,56 @@ impl<'a> StatelessWallet<'a> {
             unknown: Default::default(),
             inputs: selected_utxos
                 .iter()
-                .map(|(_utxo_key, utxo)| {
-                    let script_pubkey = self
-                        .descriptor
-                        .tweak(&utxo.tweak, self.secp)
-                        .script_pubkey();
+                .map(|(utxo_key, utxo)| {
+                    let descriptor = self.utxo_descriptor(utxo_key).tweak(&utxo.tweak, self.secp);
+
+                    // Taproot inputs are spent through the multisig leaf, which the
+                    // finalizer needs together with the control block proving it is
+                    // committed to by the output key
+                    let (witness_script, tap_internal_key, tap_merkle_root, tap_scripts) =
+                        match &descriptor {
+                            Descriptor::Tr(tr) => {
+                                let spend_info = tr.spend_info();
+                                let tap_scripts = tr
+                                    .iter_scripts()
+                                    .map(|(_, leaf)| {
+                                        let script = (leaf.encode(), LeafVersion::TapScript);
+                                        let control_block = spend_info
+                                            .control_block(&script)
+                                            .expect("Leaf is part of the tree");
+                                        (control_block, script)
+                                    })
+                                    .collect();
+
+                                (
+                                    None,
+                                    Some(spend_info.internal_key()),
+                                    spend_info.merkle_root(),
+                                    tap_scripts,
+                                )
+                            }
+                            _ => (
+                                Some(
+                                    descriptor
+                                        .script_code()
+                                        .expect("Failed to tweak descriptor"),
+                                ),
+                                None,
+                                None,
+                                BTreeMap::new(),
+                            ),
+                        };
+
                     Input {
                         non_witness_utxo: None,
                         witness_utxo: Some(TxOut {
                             value: utxo.amount.to_sat(),
-                            script_pubkey,
+                            script_pubkey: descriptor.script_pubkey(),
                         }),
                         partial_sigs: Default::default(),
                         sighash_type: None,
                         redeem_script: None,
-                        witness_script: Some(
-                            self.descriptor
-                                .tweak(&utxo.tweak, self.secp)
-                                .script_code()
-                                .expect("Failed to tweak descriptor"),
-                        ),
+                        witness_script,
                         bip32_derivation: Default::default(),
                         final_script_sig: None,
                         final_script_witness: None,
@@ -1529,15 +2279,19 @@ impl<'a> StatelessWallet<'a> {
                             .collect(),
                         tap_key_sig: Default::default(),
                         tap_script_sigs: Default::default(),
-                        tap_scripts: Default::default(),
+                        tap_scripts,
                         tap_key_origins: Default::default(),
-                        tap_internal_key: Default::default(),
-                        tap_merkle_root: Default::default(),
+                        tap_internal_key,
+                        tap_merkle_root,
                         unknown: Default::default(),
                     }
                 })
                 .collect(),
-            outputs: vec![Default::default(), change_out],
+            outputs: recipients
+                .iter()
+                .map(|_| Default::default())
+                .chain(std::iter::once(change_out))
+                .collect(),
         };
 
         Ok(UnsignedTransaction {
@@ -1556,6 +2310,7 @@ impl<'a> StatelessWallet<'a> {
     }
 
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) {
+        let prevouts = psbt_prevouts(psbt);
         let mut tx_hasher = SighashCache::new(&psbt.unsigned_tx);
 
         for (idx, (psbt_input, _tx_input)) in psbt
@@ -1573,6 +2328,32 @@ impl<'a> StatelessWallet<'a> {
                 self.secret_key.tweak(tweak, self.secp)
             };
 
+            if let Some(leaf_hash) = tap_leaf_hash(psbt_input) {
+                let tx_hash = tx_hasher
+                    .taproot_script_spend_signature_hash(
+                        idx,
+                        &Prevouts::All(&prevouts),
+                        leaf_hash,
+                        TapSighashType::Default,
+                    )
+                    .expect("Failed to create taproot sighash");
+
+                let keypair = KeyPair::from_secret_key(self.secp, &tweaked_secret);
+                let signature = self.secp.sign_schnorr_no_aux_rand(
+                    &Message::from_slice(&tx_hash[..]).unwrap(),
+                    &keypair,
+                );
+
+                psbt_input.tap_script_sigs.insert(
+                    (keypair.x_only_public_key().0, leaf_hash),
+                    bitcoin::taproot::Signature {
+                        sig: signature,
+                        hash_ty: TapSighashType::Default,
+                    },
+                );
+                continue;
+            }
+
             let tx_hash = tx_hasher
                 .segwit_signature_hash(
                     idx,
@@ -1634,7 +2415,7 @@ impl<'a> StatelessWallet<'a> {
         }
 
         let descriptor = self
-            .descriptor
+            .change_descriptor()
             .translate_pk(&mut CompressedPublicKeyTranslator {
                 tweak,
                 secp: self.secp,
@@ -1716,19 +2497,38 @@ impl Serialize for UnsignedTransaction {
 #[cfg(test)]
 mod tests {
 
+    use std::collections::BTreeSet;
     use std::str::FromStr;
 
     use bitcoin::Network::{Bitcoin, Testnet};
-    use bitcoin::{Address, Amount, Network, OutPoint, Txid};
+    use bitcoin::{Address, Amount, Network, OutPoint, ScriptBuf, Txid};
     use fedimint_core::{BitcoinHash, Feerate};
-    use fedimint_wallet_common::{PegOut, PegOutFees, Rbf, WalletOutputV0};
+    use fedimint_wallet_common::{
+        script_peg_out_feature, taproot_peg_in_descriptor, PegOut, PegOutFees, PegOutScript, Rbf,
+        WalletOutputV0,
+    };
     use miniscript::descriptor::Wsh;
 
     use crate::common::PegInDescriptor;
     use crate::{
-        CompressedPublicKey, OsRng, SpendableUTXO, StatelessWallet, UTXOKey, WalletOutputError,
+        peg_out_backlog_fee_schedule, CompressedPublicKey, OsRng, SpendableUTXO, StatelessWallet,
+        Tweakable, UTXOKey, WalletOutputError, PEG_OUT_BACKLOG_FEE,
+        PEG_OUT_BACKLOG_FREE_TRANSACTIONS,
     };
 
+    #[test]
+    fn outputs_only_pay_for_peg_out_backlog_beyond_free_transactions() {
+        assert_eq!(peg_out_backlog_fee_schedule(0), Default::default());
+        assert_eq!(
+            peg_out_backlog_fee_schedule(PEG_OUT_BACKLOG_FREE_TRANSACTIONS),
+            Default::default()
+        );
+
+        let fee_schedule = peg_out_backlog_fee_schedule(PEG_OUT_BACKLOG_FREE_TRANSACTIONS + 3);
+        assert_eq!(fee_schedule.output_fee(100), PEG_OUT_BACKLOG_FEE.mul_u64(3));
+        assert_eq!(fee_schedule.input_fee(100), fedimint_core::Amount::ZERO);
+    }
+
     #[test]
     fn create_tx_should_validate_amounts() {
         let secp = secp256k1::Secp256k1::new();
@@ -1744,10 +2544,15 @@ mod tests {
             .unwrap(),
         );
 
+        let taproot_descriptor = taproot_peg_in_descriptor(&descriptor);
+
         let (secret_key, _) = secp.generate_keypair(&mut OsRng);
 
         let wallet = StatelessWallet {
             descriptor: &descriptor,
+            taproot_descriptor: &taproot_descriptor,
+            taproot_utxos: BTreeSet::new(),
+            taproot_change: false,
             secret_key: &secret_key,
             secp: &secp,
         };
@@ -1829,6 +2634,178 @@ mod tests {
         assert_eq!(res, Err(WalletOutputError::WrongNetwork(Testnet, Bitcoin)));
     }
 
+    #[test]
+    fn create_tx_should_spend_segwit_before_taproot_utxos() {
+        let secp = secp256k1::Secp256k1::new();
+
+        let descriptor = PegInDescriptor::Wsh(
+            Wsh::new_sortedmulti(
+                3,
+                (0..4)
+                    .map(|_| secp.generate_keypair(&mut OsRng))
+                    .map(|(_, key)| CompressedPublicKey { key })
+                    .collect(),
+            )
+            .unwrap(),
+        );
+        let taproot_descriptor = taproot_peg_in_descriptor(&descriptor);
+        assert!(taproot_descriptor
+            .tweak(&[0; 33], &secp)
+            .script_pubkey()
+            .is_v1_p2tr());
+
+        let (secret_key, _) = secp.generate_keypair(&mut OsRng);
+
+        let segwit_utxo = UTXOKey(OutPoint::new(Txid::all_zeros(), 0));
+        let taproot_utxo = UTXOKey(OutPoint::new(Txid::all_zeros(), 1));
+
+        let wallet = StatelessWallet {
+            descriptor: &descriptor,
+            taproot_descriptor: &taproot_descriptor,
+            taproot_utxos: [taproot_utxo.0].into_iter().collect(),
+            taproot_change: true,
+            secret_key: &secret_key,
+            secp: &secp,
+        };
+
+        let spendable = |sats| SpendableUTXO {
+            tweak: [0; 33],
+            amount: Amount::from_sat(sats),
+        };
+
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let fee = Feerate { sats_per_kvb: 1000 };
+
+        // the larger taproot UTXO is only selected once the segwit UTXO is spent
+        let tx = wallet
+            .create_tx(
+                Amount::from_sat(1000),
+                recipient.clone().assume_checked().script_pubkey(),
+                vec![],
+                vec![
+                    (segwit_utxo.clone(), spendable(3000)),
+                    (taproot_utxo.clone(), spendable(10_000)),
+                ],
+                fee,
+                &[0; 33],
+                None,
+            )
+            .expect("is ok");
+
+        assert_eq!(tx.selected_utxos, vec![(segwit_utxo, spendable(3000))]);
+        assert!(tx.psbt.inputs[0].tap_scripts.is_empty());
+        assert!(tx.psbt.unsigned_tx.output[1].script_pubkey.is_v1_p2tr());
+
+        // taproot inputs are spent through the multisig leaf
+        let tx = wallet
+            .create_tx(
+                Amount::from_sat(5000),
+                recipient.assume_checked().script_pubkey(),
+                vec![],
+                vec![(taproot_utxo, spendable(10_000))],
+                fee,
+                &[0; 33],
+                None,
+            )
+            .expect("is ok");
+
+        assert_eq!(tx.psbt.inputs[0].tap_scripts.len(), 1);
+        assert!(tx.psbt.inputs[0].witness_script.is_none());
+    }
+
+    #[test]
+    fn peg_out_script_should_commit_to_scripts() {
+        // OP_1 and OP_2, which are enough to check the commitments
+        let witness_script = ScriptBuf::from(vec![0x51]);
+        let other_script = ScriptBuf::from(vec![0x52]);
+
+        let peg_out = |script_pubkey: ScriptBuf,
+                       redeem_script: Option<ScriptBuf>,
+                       witness_script: Option<ScriptBuf>| PegOutScript {
+            script_pubkey,
+            redeem_script,
+            witness_script,
+            amount: Amount::from_sat(1000),
+            fees: PegOutFees::new(1000, 875),
+        };
+
+        assert_eq!(
+            peg_out(
+                witness_script.to_v0_p2wsh(),
+                None,
+                Some(witness_script.clone())
+            )
+            .validate(),
+            Ok(())
+        );
+        assert_eq!(
+            peg_out(
+                other_script.to_v0_p2wsh(),
+                None,
+                Some(witness_script.clone())
+            )
+            .validate(),
+            Err(WalletOutputError::PegOutScriptMismatch)
+        );
+
+        // P2SH-wrapped P2WSH
+        let redeem_script = witness_script.to_v0_p2wsh();
+        assert_eq!(
+            peg_out(
+                redeem_script.to_p2sh(),
+                Some(redeem_script.clone()),
+                Some(witness_script.clone())
+            )
+            .validate(),
+            Ok(())
+        );
+        assert_eq!(
+            peg_out(
+                other_script.to_p2sh(),
+                Some(redeem_script),
+                Some(witness_script.clone())
+            )
+            .validate(),
+            Err(WalletOutputError::PegOutScriptMismatch)
+        );
+
+        // Bare scripts and unspendable outputs are not relayed
+        assert_eq!(
+            peg_out(witness_script, None, None).validate(),
+            Err(WalletOutputError::NonStandardPegOutScript)
+        );
+        assert_eq!(
+            peg_out(ScriptBuf::from(vec![0x6a, 0x00]), None, None).validate(),
+            Err(WalletOutputError::NonStandardPegOutScript)
+        );
+    }
+
+    #[test]
+    fn only_peg_outs_to_scripts_require_a_consensus_feature() {
+        let recipient = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf").unwrap();
+        let witness_script = ScriptBuf::from(vec![0x51]);
+
+        let peg_out = WalletOutputV0::PegOut(PegOut {
+            recipient,
+            amount: Amount::from_sat(1000),
+            fees: PegOutFees::new(1000, 875),
+        });
+        let peg_out_script = WalletOutputV0::PegOutScript(PegOutScript {
+            script_pubkey: witness_script.to_v0_p2wsh(),
+            redeem_script: None,
+            witness_script: Some(witness_script),
+            amount: Amount::from_sat(1000),
+            fees: PegOutFees::new(1000, 875),
+        });
+
+        assert_eq!(peg_out.consensus_feature(), None);
+        assert_eq!(rbf(1000, 875).consensus_feature(), None);
+        assert_eq!(
+            peg_out_script.consensus_feature(),
+            Some(script_peg_out_feature())
+        );
+    }
+
     fn rbf(sats_per_kvb: u64, total_weight: u64) -> WalletOutputV0 {
         WalletOutputV0::Rbf(Rbf {
             fees: PegOutFees::new(sats_per_kvb, total_weight),
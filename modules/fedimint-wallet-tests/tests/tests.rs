@@ -22,7 +22,7 @@ use fedimint_wallet_client::{DepositState, WalletClientInit, WalletClientModule,
 use fedimint_wallet_common::config::{WalletConfig, WalletGenParams};
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::txoproof::PegInProof;
-use fedimint_wallet_common::{PegOutFees, Rbf};
+use fedimint_wallet_common::{PegOutFees, PegOutScript, Rbf};
 use fedimint_wallet_server::WalletInit;
 use futures::stream::StreamExt;
 use tracing::info;
@@ -34,6 +34,14 @@ fn fixtures() -> Fixtures {
     fixtures.with_module(wallet_client, WalletInit, wallet_params)
 }
 
+fn fixtures_with_peg_out_batch_window(peg_out_batch_window: u32) -> Fixtures {
+    let fixtures = Fixtures::new_primary(DummyClientInit, DummyInit, DummyGenParams::default());
+    let mut wallet_params = WalletGenParams::regtest(fixtures.bitcoin_server());
+    wallet_params.consensus.peg_out_batch_window = peg_out_batch_window;
+    let wallet_client = WalletClientInit::new(fixtures.bitcoin_client());
+    fixtures.with_module(wallet_client, WalletInit, wallet_params)
+}
+
 fn bsats(satoshi: u64) -> bitcoin::Amount {
     bitcoin::Amount::from_sat(satoshi)
 }
@@ -205,6 +213,120 @@ async fn on_chain_peg_in_and_peg_out_happy_case() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn peg_out_to_script_once_feature_is_active() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    info!("Starting test peg_out_to_script_once_feature_is_active");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    let mut balance_sub = peg_in(&client, bitcoin.as_ref(), finality_delay).await?;
+
+    // All guardians run the same code, so they activate the feature after
+    // signaling it
+    let wallet_module = client.get_first_module::<WalletClientModule>();
+    while !wallet_module.is_script_peg_out_active().await? {
+        sleep_in_test(
+            "waiting for the script peg-out feature to be activated",
+            Duration::from_millis(100),
+        )
+        .await;
+    }
+
+    let address = bitcoin.get_new_address().await;
+    let script_pubkey = address.script_pubkey();
+    let peg_out = bsats(PEG_OUT_AMOUNT_SATS);
+    let fees = wallet_module
+        .get_withdraw_script_fees(&script_pubkey, peg_out)
+        .await?;
+    let op = wallet_module
+        .withdraw_to_script(
+            PegOutScript {
+                script_pubkey,
+                redeem_script: None,
+                witness_script: None,
+                amount: peg_out,
+                fees,
+            },
+            (),
+        )
+        .await?;
+
+    let balance_after_peg_out =
+        sats(PEG_IN_AMOUNT_SATS - PEG_OUT_AMOUNT_SATS - fees.amount().to_sat());
+    assert_eq!(client.get_balance().await, balance_after_peg_out);
+    assert_eq!(balance_sub.ok().await?, balance_after_peg_out);
+
+    let sub = wallet_module.subscribe_withdraw_updates(op).await?;
+    let mut sub = sub.into_stream();
+    assert_eq!(sub.ok().await?, WithdrawState::Created);
+    assert_matches!(sub.ok().await?, WithdrawState::Succeeded(_));
+
+    let received = bitcoin.mine_block_and_get_received(&address).await;
+    assert_eq!(received, peg_out.into());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn peg_outs_are_batched() -> anyhow::Result<()> {
+    let peg_out_batch_window = 2;
+    let fixtures = fixtures_with_peg_out_batch_window(peg_out_batch_window);
+    let fed = fixtures.new_default_fed().await;
+    let client = fed.new_client().await;
+    let bitcoin = fixtures.bitcoin();
+    let bitcoin = bitcoin.lock_exclusive().await;
+    let dyn_bitcoin_rpc = fixtures.dyn_bitcoin_rpc();
+    info!("Starting test peg_outs_are_batched");
+
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    await_consensus_to_catch_up(&client, 1).await?;
+
+    peg_in(&client, bitcoin.as_ref(), finality_delay).await?;
+
+    let wallet_module = client.get_first_module::<WalletClientModule>();
+    let peg_out = bsats(PEG_OUT_AMOUNT_SATS);
+    let mut subs = vec![];
+    for _ in 0..2 {
+        let address = checked_address_to_unchecked_address(&bitcoin.get_new_address().await);
+        let fees = wallet_module
+            .get_withdraw_fees(address.clone(), peg_out)
+            .await?;
+        let op = wallet_module.withdraw(address, peg_out, fees, ()).await?;
+        let mut sub = wallet_module
+            .subscribe_withdraw_updates(op)
+            .await?
+            .into_stream();
+        assert_eq!(sub.ok().await?, WithdrawState::Created);
+        subs.push(sub);
+    }
+
+    // Crossing the end of the window pays both peg-outs in one transaction
+    bitcoin.mine_blocks(peg_out_batch_window.into()).await;
+    let block_count = dyn_bitcoin_rpc.get_block_count().await?;
+    await_consensus_to_catch_up(&client, block_count - finality_delay).await?;
+
+    let mut outpoints = vec![];
+    for mut sub in subs {
+        let WithdrawState::Batched(outpoint) = sub.ok().await? else {
+            panic!("Peg-out was not batched");
+        };
+        assert_eq!(sub.ok().await?, WithdrawState::Succeeded(outpoint.txid));
+        outpoints.push(outpoint);
+    }
+
+    assert_eq!(outpoints[0].txid, outpoints[1].txid);
+    assert_ne!(outpoints[0].vout, outpoints[1].vout);
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn peg_out_fail_refund() -> anyhow::Result<()> {
     let fixtures = fixtures();
@@ -574,6 +696,7 @@ fn build_wallet_server_configs(
                 finality_delay: 10,
                 client_default_bitcoin_rpc: bitcoin_rpc.clone(),
                 fee_consensus: Default::default(),
+                peg_out_batch_window: 0,
             },
         })?,
     );
@@ -617,8 +740,8 @@ mod fedimint_migration_tests {
     use fedimint_wallet_server::db::{
         BlockCountVoteKey, BlockCountVotePrefix, BlockHashKey, BlockHashKeyPrefix, DbKeyPrefix,
         FeeRateVoteKey, FeeRateVotePrefix, PegOutBitcoinTransaction,
-        PegOutBitcoinTransactionPrefix, PegOutNonceKey, PegOutTxSignatureCI,
-        PegOutTxSignatureCIPrefix, PendingTransactionKey, PendingTransactionPrefixKey, UTXOKey,
+        PegOutBitcoinTransactionPrefix, PegOutNonceKey, PegOutTxSignatureCIPrefix,
+        PegOutTxSignatureCIV0, PendingTransactionKey, PendingTransactionPrefixKey, UTXOKey,
         UTXOPrefixKey, UnsignedTransactionKey, UnsignedTransactionPrefixKey,
     };
     use fedimint_wallet_server::{PendingTransaction, UnsignedTransaction};
@@ -772,7 +895,7 @@ mod fedimint_migration_tests {
         let secp = secp256k1::Secp256k1::new();
         let signature = secp.sign_ecdsa(&Message::from_slice(&BYTE_32).unwrap(), &sk);
         dbtx.insert_new_entry(
-            &PegOutTxSignatureCI(Txid::from_byte_array(BYTE_32)),
+            &PegOutTxSignatureCIV0(Txid::from_byte_array(BYTE_32)),
             &vec![signature],
         )
         .await;
@@ -937,6 +1060,11 @@ mod fedimint_migration_tests {
                             );
                             info!("Validated FeeRateVote");
                         }
+                        // Not present in the snapshot
+                        DbKeyPrefix::PendingPegOut
+                        | DbKeyPrefix::PegOutBatch
+                        | DbKeyPrefix::TaprootUtxo
+                        | DbKeyPrefix::ConsensusVersionVote => {}
                     }
                 }
                 Ok(())
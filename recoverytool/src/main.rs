@@ -37,9 +37,9 @@ use fedimint_wallet_server::common::config::WalletConfig;
 use fedimint_wallet_server::common::keys::CompressedPublicKey;
 use fedimint_wallet_server::common::tweakable::Tweakable;
 use fedimint_wallet_server::common::{
-    PegInDescriptor, SpendableUTXO, WalletCommonInit, WalletInput,
+    taproot_peg_in_descriptor, PegInDescriptor, SpendableUTXO, WalletCommonInit, WalletInput,
 };
-use fedimint_wallet_server::db::{UTXOKey, UTXOPrefixKey};
+use fedimint_wallet_server::db::{TaprootUTXOKey, TaprootUTXOPrefixKey, UTXOKey, UTXOPrefixKey};
 use fedimint_wallet_server::{nonce_from_idx, Wallet};
 use futures::stream::StreamExt;
 use hex::FromHex;
@@ -169,13 +169,26 @@ async fn main() -> anyhow::Result<()> {
                 db.with_prefix_module_id(LEGACY_HARDCODED_INSTANCE_ID_WALLET)
             };
 
-            let utxos: Vec<ImportableWallet> = db
-                .begin_transaction()
+            let mut dbtx = db.begin_transaction().await;
+
+            let taproot_utxos = dbtx
+                .find_by_prefix(&TaprootUTXOPrefixKey)
                 .await
+                .map(|(TaprootUTXOKey(outpoint), ())| outpoint)
+                .collect::<BTreeSet<_>>()
+                .await;
+            let taproot_descriptor = taproot_peg_in_descriptor(&base_descriptor);
+
+            let utxos: Vec<ImportableWallet> = dbtx
                 .find_by_prefix(&UTXOPrefixKey)
                 .await
                 .map(|(UTXOKey(outpoint), SpendableUTXO { tweak, amount })| {
-                    let descriptor = tweak_descriptor(&base_descriptor, &base_key, &tweak, network);
+                    let utxo_descriptor = if taproot_utxos.contains(&outpoint) {
+                        &taproot_descriptor
+                    } else {
+                        &base_descriptor
+                    };
+                    let descriptor = tweak_descriptor(utxo_descriptor, &base_key, &tweak, network);
 
                     ImportableWallet {
                         outpoint,
@@ -229,7 +242,11 @@ async fn main() -> anyhow::Result<()> {
                             .into_iter()
                             .filter_map(|item| match item.item {
                                 ConsensusItem::Transaction(tx) => Some(tx),
-                                ConsensusItem::Module(_) | ConsensusItem::Default { .. } => None,
+                                ConsensusItem::Module(_)
+                                | ConsensusItem::ModuleAddition(_)
+                                | ConsensusItem::ModuleFeatures(_)
+                                | ConsensusItem::Upgrade(_)
+                                | ConsensusItem::Default { .. } => None,
                             })
                             .collect();
 